@@ -12,4 +12,18 @@ fn main() {
         None,                 // customizations
     )
     .unwrap_or_else(|e| panic!("Failed to compile gRPC definitions, the error is: {}", e));
+
+    // The transfer service between storage nodes is built with tonic, the
+    // generated code is included from `OUT_DIR`.
+    tonic_build::compile_protos("./src/storage/transfer/proto/transfer.proto")
+        .unwrap_or_else(|e| panic!("Failed to compile transfer gRPC definitions: {e}"));
+    tonic_build::compile_protos("./src/async_fuse/memfs/kv_engine/raft_impl/proto/raft.proto")
+        .unwrap_or_else(|e| panic!("Failed to compile raft gRPC definitions: {e}"));
+    tonic_build::compile_protos("./src/volume/proto/volume.proto")
+        .unwrap_or_else(|e| panic!("Failed to compile volume gRPC definitions: {e}"));
+
+    // WinFsp is loaded when the volume is mounted on Windows, so the binary
+    // starts without it.
+    #[cfg(windows)]
+    winfsp::build::winfsp_link_delayload();
 }
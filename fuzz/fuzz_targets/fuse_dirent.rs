@@ -0,0 +1,74 @@
+//! Fill a directory reply with arbitrary entries, and check the encoded
+//! entries are aligned, fit in the buffer and decode to the entries added.
+
+#![no_main]
+
+use std::ffi::OsStr;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+
+use datenlord::async_fuse::fuse::fuse_reply::ReplyDirectory;
+use datenlord::async_fuse::fuse::protocol::{FuseDirEnt, FuseOutHeader};
+use libfuzzer_sys::fuzz_target;
+use nix::sys::stat::SFlag;
+
+/// The kinds of the entries.
+const KINDS: [SFlag; 7] = [
+    SFlag::S_IFREG,
+    SFlag::S_IFDIR,
+    SFlag::S_IFLNK,
+    SFlag::S_IFIFO,
+    SFlag::S_IFSOCK,
+    SFlag::S_IFCHR,
+    SFlag::S_IFBLK,
+];
+
+/// Read a `u32` at `offset` of `bytes`.
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Read a `u64` at `offset` of `bytes`.
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_ne_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fuzz_target!(|input: (u16, Vec<(u64, i64, u8, Vec<u8>)>)| {
+    let (size, entries) = input;
+    let size = usize::from(size);
+    let mut sink = Vec::new();
+    let mut reply = ReplyDirectory::new(1, &mut sink, size);
+    let mut added = vec![];
+    // Add the entries as the readdir does, until the buffer is full
+    for (ino, offset, kind, name) in entries {
+        let kind = KINDS[usize::from(kind) % KINDS.len()];
+        if reply.add(ino, offset, kind, OsStr::from_bytes(&name)) {
+            break;
+        }
+        added.push((ino, offset, name));
+    }
+    futures::executor::block_on(reply.ok()).unwrap();
+
+    let header_len = mem::size_of::<FuseOutHeader>();
+    assert_eq!(read_u32(&sink, 0) as usize, sink.len());
+    let data = &sink[header_len..];
+    assert!(data.len() <= size);
+    assert_eq!(data.len() % mem::size_of::<u64>(), 0);
+
+    let dirent_len = mem::size_of::<FuseDirEnt>();
+    let mut pos = 0;
+    for (ino, offset, name) in added {
+        assert_eq!(read_u64(data, pos), ino);
+        assert_eq!(read_u64(data, pos + 8), u64::from_ne_bytes(offset.to_ne_bytes()));
+        let namelen = read_u32(data, pos + 16) as usize;
+        assert_eq!(namelen, name.len());
+        let name_start = pos + dirent_len;
+        assert_eq!(&data[name_start..name_start + namelen], name.as_slice());
+        let entsize = (dirent_len + namelen + 7) & !7;
+        assert!(data[name_start + namelen..pos + entsize]
+            .iter()
+            .all(|&b| b == 0));
+        pos += entsize;
+    }
+    assert_eq!(pos, data.len());
+});
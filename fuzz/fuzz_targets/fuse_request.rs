@@ -0,0 +1,28 @@
+//! Feed arbitrary bytes to the decoder of the FUSE requests, which must reject
+//! a malformed request instead of panicking or reading out of bounds.
+
+#![no_main]
+
+use aligned_utils::bytes::AlignedBytes;
+use datenlord::async_fuse::fuse::context::ProtoVersion;
+use datenlord::async_fuse::fuse::fuse_request::Request;
+use libfuzzer_sys::fuzz_target;
+
+/// The protocol versions the requests are decoded with.
+const PROTO_VERSIONS: [ProtoVersion; 3] = [
+    ProtoVersion { major: 7, minor: 8 },
+    ProtoVersion { major: 7, minor: 23 },
+    ProtoVersion { major: 7, minor: 31 },
+];
+
+fuzz_target!(|data: &[u8]| {
+    // The session reads the requests into a page aligned buffer
+    let mut buffer = AlignedBytes::new_zeroed(data.len(), 4096);
+    buffer.copy_from_slice(data);
+    for proto_version in PROTO_VERSIONS {
+        if let Ok(request) = Request::new(&buffer, proto_version) {
+            assert!(request.len() as usize <= data.len());
+            let _ = request.to_string();
+        }
+    }
+});
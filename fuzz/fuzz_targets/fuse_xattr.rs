@@ -0,0 +1,37 @@
+//! Reply arbitrary xattr values to requests of arbitrary sizes, and check the
+//! reply is the size, the value or `ERANGE` as the kernel expects.
+
+#![no_main]
+
+use std::mem;
+
+use datenlord::async_fuse::fuse::fuse_reply::ReplyXAttr;
+use datenlord::async_fuse::fuse::protocol::FuseOutHeader;
+use libfuzzer_sys::fuzz_target;
+use nix::errno::Errno;
+
+fuzz_target!(|input: (u32, Vec<u8>)| {
+    let (size, value) = input;
+    let mut sink = Vec::new();
+    let reply = ReplyXAttr::new(1, &mut sink);
+    let written = futures::executor::block_on(reply.value(value.clone(), size)).unwrap();
+    assert_eq!(written, sink.len());
+
+    let header_len = mem::size_of::<FuseOutHeader>();
+    let len = u32::from_ne_bytes(sink[0..4].try_into().unwrap()) as usize;
+    let error = i32::from_ne_bytes(sink[4..8].try_into().unwrap());
+    assert_eq!(len, sink.len());
+    let body = &sink[header_len..];
+    if size == 0 {
+        // `fuse_getxattr_out` holds the size and a padding
+        assert_eq!(error, 0);
+        assert_eq!(body.len(), 8);
+        assert_eq!(u32::from_ne_bytes(body[0..4].try_into().unwrap()) as usize, value.len());
+    } else if value.len() > size as usize {
+        assert_eq!(error, -(Errno::ERANGE as i32));
+        assert!(body.is_empty());
+    } else {
+        assert_eq!(error, 0);
+        assert_eq!(body, value.as_slice());
+    }
+});
@@ -48,7 +48,6 @@ pub enum DeserializeError {
     NumOverflow,
 
     /// The value of the target type is invalid
-    #[allow(dead_code)]
     #[error("InvalidValue")]
     InvalidValue,
 
@@ -122,7 +121,6 @@ impl<'b> Deserializer<'b> {
     }
 
     /// Fetch specified amount of bytes
-    #[allow(dead_code)]
     pub fn fetch_bytes(&mut self, amt: usize) -> Result<&'b [u8], DeserializeError> {
         check_size(self.bytes.len(), amt)?;
         unsafe { Ok(self.pop_bytes_unchecked(amt)) }
@@ -214,8 +212,10 @@ impl<'b> Deserializer<'b> {
             bytes_with_nul.get_unchecked(..len)
         };
 
-        Ok(std::str::from_utf8(bytes_without_nul)
-            .unwrap_or_else(|e| panic!("failed to convert to utf8 string, error is {e:?}")))
+        std::str::from_utf8(bytes_without_nul).map_err(|e| {
+            trace!("failed to convert to utf8 string, error is {e:?}");
+            DeserializeError::InvalidValue
+        })
     }
 
     /// Returns `TooMuchData` if the bytes is not completely consumed
@@ -5,11 +5,16 @@ use async_trait::async_trait;
 
 use super::fuse_reply::{
     ReplyAttr, ReplyBMap, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry,
-    ReplyLock, ReplyOpen, ReplyStatFs, ReplyWrite, ReplyXAttr,
+    ReplyLSeek, ReplyLock, ReplyOpen, ReplyStatFs, ReplyWrite, ReplyXAttr,
 };
 use super::fuse_request::Request;
+#[cfg(feature = "abi-7-16")]
+use super::protocol::FuseForgetOne;
 use super::protocol::INum;
-use crate::async_fuse::memfs::{CreateParam, FileLockParam, RenameParam, SetAttrParam};
+use crate::async_fuse::memfs::{
+    CopyRangeParam, CreateParam, FileLockParam, RenameParam, SetAttrParam,
+};
+use crate::common::error::DatenLordResult;
 
 /// FUSE filesystem trait
 #[async_trait]
@@ -23,6 +28,10 @@ pub trait FileSystem {
     /// Interrupt another FUSE request
     async fn interrupt(&self, req: &Request<'_>, unique: u64);
 
+    /// Check whether the request is authorized before it's dispatched, the
+    /// error is replied instead if not
+    async fn authorize(&self, req: &Request<'_>) -> DatenLordResult<()>;
+
     /// Look up a directory entry by name and get its attributes.
     async fn lookup(
         &self,
@@ -35,6 +44,10 @@ pub trait FileSystem {
     /// Forget about an inode
     async fn forget(&self, req: &Request<'_>, nlookup: u64);
 
+    /// Forget about multiple inodes
+    #[cfg(feature = "abi-7-16")]
+    async fn batch_forget(&self, req: &Request<'_>, nodes: &[FuseForgetOne]);
+
     /// Get file attributes.
     async fn getattr(&self, req: &Request<'_>, reply: ReplyAttr<'_>) -> nix::Result<usize>;
 
@@ -57,13 +70,15 @@ pub trait FileSystem {
         reply: ReplyEntry<'_>,
     ) -> nix::Result<usize>;
 
-    /// Create a directory
+    /// Create a directory, `umask` is applied to `mode` by the file system if
+    /// it's not 0.
     async fn mkdir(
         &self,
         req: &Request<'_>,
         parent: INum,
         name: &str,
         mode: u32,
+        umask: u32,
         reply: ReplyEntry<'_>,
     ) -> nix::Result<usize>;
 
@@ -127,12 +142,16 @@ pub trait FileSystem {
     ) -> nix::Result<usize>;
 
     /// Write data
+    ///
+    /// `data` is borrowed from the buffer the request is read into, which is
+    /// kept until the request is replied, so it's not copied into an
+    /// intermediate `Vec`. It's still copied once into the cached blocks.
     async fn write(
         &self,
         req: &Request<'_>,
         fh: u64,
         offset: i64,
-        data: Vec<u8>,
+        data: &[u8],
         flags: u32,
         reply: ReplyWrite<'_>,
     ) -> nix::Result<usize>;
@@ -284,4 +303,33 @@ pub trait FileSystem {
         _idx: u64,
         reply: ReplyBMap<'_>,
     ) -> nix::Result<usize>;
+
+    /// Allocate or deallocate a range of a file
+    async fn fallocate(
+        &self,
+        req: &Request<'_>,
+        fh: u64,
+        offset: u64,
+        length: u64,
+        mode: u32,
+        reply: ReplyEmpty<'_>,
+    ) -> nix::Result<usize>;
+
+    /// Find the next data or hole in a file
+    async fn lseek(
+        &self,
+        req: &Request<'_>,
+        fh: u64,
+        offset: u64,
+        whence: u32,
+        reply: ReplyLSeek<'_>,
+    ) -> nix::Result<usize>;
+
+    /// Copy a range of data from an opened file to another
+    async fn copy_file_range(
+        &self,
+        req: &Request<'_>,
+        param: CopyRangeParam,
+        reply: ReplyWrite<'_>,
+    ) -> nix::Result<usize>;
 }
@@ -4,11 +4,10 @@
 #![allow(clippy::unused_async)]
 
 use std::convert::AsRef;
-#[cfg(feature = "abi-7-18")]
+#[cfg(feature = "abi-7-12")]
 use std::ffi::CString;
 use std::ffi::OsStr;
 use std::fmt::Debug;
-use std::fs::File;
 use std::io::{IoSlice, Write};
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::io::RawFd;
@@ -23,10 +22,16 @@ use tracing::debug;
 use super::abi_marker;
 use super::protocol::{
     FuseAttr, FuseAttrOut, FuseBMapOut, FuseDirEnt, FuseEntryOut, FuseFileLock, FuseGetXAttrOut,
-    FuseInitOut, FuseKStatFs, FuseLockOut, FuseOpenOut, FuseOutHeader, FuseStatFsOut, FuseWriteOut,
+    FuseInitOut, FuseKStatFs, FuseLSeekOut, FuseLockOut, FuseOpenOut, FuseOutHeader, FuseStatFsOut,
+    FuseWriteOut,
 };
 #[cfg(feature = "abi-7-18")]
 use super::protocol::{FuseNotifyCode::FUSE_NOTIFY_DELETE, FuseNotifyDeleteOut};
+#[cfg(feature = "abi-7-12")]
+use super::protocol::{
+    FuseNotifyCode::{FUSE_NOTIFY_INVAL_ENTRY, FUSE_NOTIFY_INVAL_INODE},
+    FuseNotifyInvalEntryOut, FuseNotifyInvalINodeOut,
+};
 
 /// This trait describes a type that can be converted to `Vec<IoSlice>`
 pub trait AsIoSliceList {
@@ -157,19 +162,32 @@ impl AsIoSlice for Vec<u8> {
     }
 }
 
+/// Where the replies are written to, the FUSE device, or the buffer of a
+/// request of virtio-fs copied into the virtqueue after the request is done.
+pub trait ReplySink: Write + Send {}
+
+impl<T: Write + Send> ReplySink for T {}
+
 /// FUSE raw response
-#[derive(Debug)]
 struct ReplyRaw<'a> {
     /// The FUSE request unique ID
     unique: u64,
-    /// The FUSE device fd
-    file: &'a mut File,
+    /// The FUSE device fd, or another sink of the replies
+    sink: &'a mut dyn ReplySink,
+}
+
+impl Debug for ReplyRaw<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReplyRaw")
+            .field("unique", &self.unique)
+            .finish_non_exhaustive()
+    }
 }
 
 impl<'a> ReplyRaw<'a> {
     /// Create `ReplyRaw`
-    fn new(unique: u64, file: &'a mut File) -> Self {
-        Self { unique, file }
+    fn new(unique: u64, sink: &'a mut dyn ReplySink) -> Self {
+        Self { unique, sink }
     }
 
     /// Send raw message to FUSE kernel
@@ -200,7 +218,7 @@ impl<'a> ReplyRaw<'a> {
         };
 
         let wsize = self
-            .file
+            .sink
             .write_vectored(io_slices)
             .map_err(|e| Errno::try_from(e).unwrap_or(Errno::EIO))?;
 
@@ -261,9 +279,9 @@ macro_rules! impl_fuse_reply_new_for{
         $(impl<'a> $t<'a> {
             /// New fuse reply
             #[must_use]
-            pub fn new(unique: u64, file: &'a mut File) -> Self {
+            pub fn new(unique: u64, sink: &'a mut dyn ReplySink) -> Self {
                 Self {
-                    reply: ReplyRaw::new(unique, file),
+                    reply: ReplyRaw::new(unique, sink),
                 }
             }
         })+
@@ -278,6 +296,7 @@ impl_fuse_reply_new_for! {
     ReplyEmpty,
     ReplyEntry,
     ReplyInit,
+    ReplyLSeek,
     ReplyLock,
     ReplyOpen,
     ReplyStatFs,
@@ -315,6 +334,7 @@ impl_fuse_reply_error_for! {
     ReplyEmpty,
     ReplyEntry,
     ReplyInit,
+    ReplyLSeek,
     ReplyLock,
     ReplyOpen,
     ReplyStatFs,
@@ -357,12 +377,17 @@ impl_as_ioslice_for! {
     FuseBMapOut,
     FuseEntryOut,
     FuseInitOut,
+    FuseLSeekOut,
     FuseLockOut,
     FuseOpenOut,
     FuseStatFsOut,
     FuseWriteOut,
     FuseGetXAttrOut,
 }
+#[cfg(feature = "abi-7-12")]
+impl_as_ioslice_for! {
+    FuseNotifyInvalINodeOut,
+}
 #[cfg(feature = "abi-7-18")]
 impl_as_ioslice_for! {
     FuseNotifyDeleteOut,
@@ -621,6 +646,20 @@ impl ReplyBMap<'_> {
     }
 }
 
+/// FUSE lseek response
+#[derive(Debug)]
+pub struct ReplyLSeek<'a> {
+    /// The inner raw reply
+    reply: ReplyRaw<'a>,
+}
+
+impl ReplyLSeek<'_> {
+    /// Reply to a request with the resulting offset
+    pub async fn offset(self, offset: u64) -> nix::Result<usize> {
+        self.reply.send(FuseLSeekOut { offset }).await
+    }
+}
+
 /// FUSE directory response
 #[derive(Debug)]
 pub struct ReplyDirectory<'a> {
@@ -633,9 +672,9 @@ pub struct ReplyDirectory<'a> {
 impl<'a> ReplyDirectory<'a> {
     /// Creates a new `ReplyDirectory` with a specified buffer size.
     #[must_use]
-    pub fn new(unique: u64, file: &'a mut File, size: usize) -> Self {
+    pub fn new(unique: u64, sink: &'a mut dyn ReplySink, size: usize) -> Self {
         Self {
-            reply: ReplyRaw::new(unique, file),
+            reply: ReplyRaw::new(unique, sink),
             data: Vec::with_capacity(size),
         }
     }
@@ -646,10 +685,15 @@ impl<'a> ReplyDirectory<'a> {
     /// readdir calls
     pub fn add<T: AsRef<OsStr>>(&mut self, ino: u64, offset: i64, kind: SFlag, name: T) -> bool {
         let name_bytes = name.as_ref().as_bytes();
+        // A name whose length overflows `namelen` never fits in the buffer
+        let Ok(namelen) = u32::try_from(name_bytes.len()) else {
+            return true;
+        };
         let dirent = FuseDirEnt {
             ino,
-            off: offset.cast(),
-            namelen: name_bytes.len().cast(),
+            // The offset is transparent to the kernel, so keep its bits as is
+            off: u64::from_ne_bytes(offset.to_ne_bytes()),
+            namelen,
             typ: crate::async_fuse::util::mode_from_kind_and_perm(kind, 0).overflow_shr(12),
         };
         let entlen = dirent.size_with_name();
@@ -660,7 +704,7 @@ impl<'a> ReplyDirectory<'a> {
         let entsize = super::super::util::round_up(entlen, mem::size_of::<u64>()); // 64bit align
 
         let padlen = entsize.overflow_sub(entlen);
-        if self.data.len().overflow_add(entsize) > self.data.capacity() {
+        if entsize > self.data.capacity().saturating_sub(self.data.len()) {
             return true;
         }
 
@@ -715,19 +759,35 @@ pub struct ReplyXAttr<'a> {
 
 impl ReplyXAttr<'_> {
     /// Reply to a request with the size of the xattr.
-    #[allow(dead_code)]
     pub async fn size(self, size: u32) -> nix::Result<usize> {
         self.reply.send(FuseGetXAttrOut { size, padding: 0 }).await
     }
 
     /// Reply to a request with the data in the xattr.
-    #[allow(dead_code)]
-    pub async fn data(self, bytes: FuseGetXAttrOut) -> nix::Result<usize> {
+    pub async fn data(
+        self,
+        bytes: impl AsIoSliceList + Send + Sync + 'static,
+    ) -> nix::Result<usize> {
         self.reply.send(bytes).await
     }
+
+    /// Reply to a request with `value`, or its size if `size` is `0`, or
+    /// `ERANGE` if it doesn't fit in `size`, or `E2BIG` if its size overflows.
+    pub async fn value(self, value: Vec<u8>, size: u32) -> nix::Result<usize> {
+        let Ok(len) = u32::try_from(value.len()) else {
+            return self.reply.send_error_code(Errno::E2BIG).await;
+        };
+        if size == 0 {
+            self.size(len).await
+        } else if len > size {
+            self.reply.send_error_code(Errno::ERANGE).await
+        } else {
+            self.data(value).await
+        }
+    }
 }
 
-#[cfg(feature = "abi-7-18")]
+#[cfg(feature = "abi-7-12")]
 impl AsIoSlice for CString {
     fn as_io_slice(&self) -> IoSlice {
         IoSlice::new(self.as_bytes_with_nul())
@@ -746,6 +806,71 @@ impl AsIoSlice for CString {
         false
     }
 }
+/// Fuse inode invalidation notification
+#[cfg(feature = "abi-7-12")]
+#[derive(Debug)]
+pub struct FuseInvalINodeNotification<'a> {
+    /// The inner raw reply
+    reply: ReplyRaw<'a>,
+}
+
+#[cfg(feature = "abi-7-12")]
+impl<'a> FuseInvalINodeNotification<'a> {
+    /// Create `FuseInvalINodeNotification`
+    #[must_use]
+    pub fn new(sink: &'a mut dyn ReplySink) -> Self {
+        Self {
+            reply: ReplyRaw::new(0, sink),
+        }
+    }
+
+    /// Notify kernel to invalidate the cached attributes and the cached data
+    /// of `ino` in `[off, off + len)`, a negative `off` invalidates the
+    /// attributes only, and a `len` of `0` invalidates to the end of file.
+    pub async fn notify(self, ino: u64, off: i64, len: i64) -> nix::Result<usize> {
+        let notify_inval_inode = FuseNotifyInvalINodeOut { ino, off, len };
+        #[allow(clippy::as_conversions)] // allow this for enum
+        self.reply
+            .send_raw_message(FUSE_NOTIFY_INVAL_INODE as i32, notify_inval_inode)
+    }
+}
+
+/// Fuse entry invalidation notification
+#[cfg(feature = "abi-7-12")]
+#[derive(Debug)]
+pub struct FuseInvalEntryNotification<'a> {
+    /// The inner raw reply
+    reply: ReplyRaw<'a>,
+}
+
+#[cfg(feature = "abi-7-12")]
+impl<'a> FuseInvalEntryNotification<'a> {
+    /// Create `FuseInvalEntryNotification`
+    #[must_use]
+    pub fn new(sink: &'a mut dyn ReplySink) -> Self {
+        Self {
+            reply: ReplyRaw::new(0, sink),
+        }
+    }
+
+    /// Notify kernel to invalidate the cached entry of `name` in directory
+    /// `parent`, the inode of which is forgotten once it's no longer used.
+    pub async fn notify(self, parent: u64, name: &str) -> nix::Result<usize> {
+        let notify_inval_entry = FuseNotifyInvalEntryOut {
+            parent,
+            namelen: name.len().cast(),
+            padding: 0,
+        };
+        let file_name = CString::new(name)
+            .unwrap_or_else(|e| panic!("failed to create CString for {name}, error is {e:?}"));
+        #[allow(clippy::as_conversions)] // allow this for enum
+        self.reply.send_raw_message(
+            FUSE_NOTIFY_INVAL_ENTRY as i32,
+            (notify_inval_entry, file_name),
+        )
+    }
+}
+
 /// Fuse delete notification
 #[cfg(feature = "abi-7-18")]
 #[derive(Debug)]
@@ -758,9 +883,9 @@ pub struct FuseDeleteNotification<'a> {
 impl<'a> FuseDeleteNotification<'a> {
     /// Create `FuseDeleteNotification`
     #[must_use]
-    pub fn new(file: &'a mut File) -> Self {
+    pub fn new(sink: &'a mut dyn ReplySink) -> Self {
         Self {
-            reply: ReplyRaw::new(0, file),
+            reply: ReplyRaw::new(0, sink),
         }
     }
 
@@ -789,13 +914,13 @@ mod test {
     use aligned_utils::bytes::AlignedBytes;
     use anyhow::Context;
     use nix::fcntl::{self, OFlag};
-    use nix::sys::stat::Mode;
+    use nix::sys::stat::{Mode, SFlag};
     use nix::unistd;
     use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
     use super::super::de::Deserializer;
-    use super::super::protocol::{FuseAttr, FuseAttrOut, FuseOutHeader};
-    use super::ReplyAttr;
+    use super::super::protocol::{FuseAttr, FuseAttrOut, FuseDirEnt, FuseOutHeader};
+    use super::{ReplyAttr, ReplyDirectory, ReplyXAttr};
 
     #[test]
     fn test_slice() {
@@ -854,14 +979,20 @@ mod test {
             atime: a_time,
             mtime: m_time,
             ctime: c_time,
+            #[cfg(target_os = "macos")]
+            crtime: c_time,
             atimensec: a_timensec,
             mtimensec: m_timensec,
             ctimensec: c_timensec,
+            #[cfg(target_os = "macos")]
+            crtimensec: c_timensec,
             mode,
             nlink,
             uid,
             gid: g_id,
             rdev,
+            #[cfg(target_os = "macos")]
+            flags: 0,
             #[cfg(feature = "abi-7-9")]
             blksize,
             #[cfg(feature = "abi-7-9")]
@@ -896,4 +1027,54 @@ mod test {
         debug_assert_eq!(fao.attr.ctime, c_time);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_reply_directory() -> anyhow::Result<()> {
+        let mut sink = Vec::new();
+        let mut reply = ReplyDirectory::new(1, &mut sink, 64);
+        // The offset is transparent, even if it's negative
+        assert!(!reply.add(2, -1, SFlag::S_IFREG, "a"));
+        // The entry of 24 + 40 bytes doesn't fit in the rest 32 bytes
+        assert!(reply.add(3, 2, SFlag::S_IFDIR, "b".repeat(40)));
+        reply.ok().await?;
+
+        let mut aligned_bytes = AlignedBytes::new_zeroed(sink.len(), 4096);
+        aligned_bytes.copy_from_slice(&sink);
+        let mut de = Deserializer::new(&aligned_bytes);
+        let foh: &FuseOutHeader = de.fetch_ref().context("failed to fetch FuseOutHeader")?;
+        assert_eq!(foh.len, 48);
+        let dirent: &FuseDirEnt = de.fetch_ref().context("failed to fetch FuseDirEnt")?;
+        assert_eq!(dirent.off, u64::MAX);
+        assert_eq!(dirent.namelen, 1);
+        assert_eq!(de.fetch_all_bytes(), b"a\0\0\0\0\0\0\0");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reply_xattr_value() -> anyhow::Result<()> {
+        let mut sink = Vec::new();
+        ReplyXAttr::new(1, &mut sink)
+            .value(b"abc".to_vec(), 0)
+            .await?;
+        // The header and `fuse_getxattr_out`
+        assert_eq!(sink.len(), 24);
+        assert_eq!(sink.get(16..20), Some(3_u32.to_ne_bytes().as_slice()));
+
+        let mut sink = Vec::new();
+        ReplyXAttr::new(1, &mut sink)
+            .value(b"abc".to_vec(), 2)
+            .await?;
+        assert_eq!(sink.len(), 16);
+        assert_eq!(
+            sink.get(4..8),
+            Some((-libc::ERANGE).to_ne_bytes().as_slice())
+        );
+
+        let mut sink = Vec::new();
+        ReplyXAttr::new(1, &mut sink)
+            .value(b"abc".to_vec(), 3)
+            .await?;
+        assert_eq!(sink.get(16..), Some(b"abc".as_slice()));
+        Ok(())
+    }
 }
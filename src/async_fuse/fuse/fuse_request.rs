@@ -7,6 +7,8 @@ use tracing::debug;
 
 use super::context::ProtoVersion;
 use super::de::{DeserializeError, Deserializer};
+#[cfg(target_os = "macos")]
+use super::protocol::FuseExchangeIn;
 #[cfg(feature = "abi-7-19")]
 use super::protocol::FuseFAllocateIn;
 #[cfg(feature = "abi-7-23")]
@@ -283,6 +285,25 @@ pub enum Operation<'a> {
         /// The FUSE copy file range request
         arg: &'a FuseCopyFileRangeIn,
     },
+    /// FUSE_SETVOLNAME = 61, macOS only
+    #[cfg(target_os = "macos")]
+    SetVolName {
+        /// The new name of the volume
+        name: &'a str,
+    },
+    /// FUSE_GETXTIMES = 62, macOS only
+    #[cfg(target_os = "macos")]
+    GetXTimes,
+    /// FUSE_EXCHANGE = 63, macOS only
+    #[cfg(target_os = "macos")]
+    Exchange {
+        /// The FUSE exchange request
+        arg: &'a FuseExchangeIn,
+        /// The old file name
+        oldname: &'a str,
+        /// The new file name
+        newname: &'a str,
+    },
     /// CUSE_INIT = 4096
     #[cfg(feature = "abi-7-11")]
     CuseInit {
@@ -354,6 +375,12 @@ impl<'a> Operation<'a> {
             46 => FuseOpCode::FUSE_LSEEK,
             // #[cfg(feature = "abi-7-28")]
             47 => FuseOpCode::FUSE_COPY_FILE_RANGE,
+            #[cfg(target_os = "macos")]
+            61 => FuseOpCode::FUSE_SETVOLNAME,
+            #[cfg(target_os = "macos")]
+            62 => FuseOpCode::FUSE_GETXTIMES,
+            #[cfg(target_os = "macos")]
+            63 => FuseOpCode::FUSE_EXCHANGE,
             #[cfg(feature = "abi-7-11")]
             4096 => FuseOpCode::CUSE_INIT,
 
@@ -405,10 +432,13 @@ impl<'a> Operation<'a> {
             FuseOpCode::FUSE_READ => Operation::Read {
                 arg: data.fetch_ref()?,
             },
-            FuseOpCode::FUSE_WRITE => Operation::Write {
-                arg: data.fetch_ref()?,
-                data: data.fetch_all_bytes(),
-            },
+            FuseOpCode::FUSE_WRITE => {
+                let arg: &FuseWriteIn = data.fetch_ref()?;
+                Operation::Write {
+                    arg,
+                    data: data.fetch_bytes(arg.size.cast())?,
+                }
+            }
             FuseOpCode::FUSE_STATFS => Operation::StatFs,
             FuseOpCode::FUSE_RELEASE => Operation::Release {
                 arg: data.fetch_ref()?,
@@ -416,11 +446,14 @@ impl<'a> Operation<'a> {
             FuseOpCode::FUSE_FSYNC => Operation::FSync {
                 arg: data.fetch_ref()?,
             },
-            FuseOpCode::FUSE_SETXATTR => Operation::SetXAttr {
-                arg: data.fetch_ref()?,
-                name: data.fetch_str()?,
-                value: data.fetch_all_bytes(),
-            },
+            FuseOpCode::FUSE_SETXATTR => {
+                let arg: &FuseSetXAttrIn = data.fetch_ref()?;
+                Operation::SetXAttr {
+                    arg,
+                    name: data.fetch_str()?,
+                    value: data.fetch_bytes(arg.size.cast())?,
+                }
+            }
             FuseOpCode::FUSE_GETXATTR => Operation::GetXAttr {
                 arg: data.fetch_ref()?,
                 name: data.fetch_str()?,
@@ -512,6 +545,18 @@ impl<'a> Operation<'a> {
             FuseOpCode::FUSE_COPY_FILE_RANGE => Operation::CopyFileRange {
                 arg: data.fetch_ref()?,
             },
+            #[cfg(target_os = "macos")]
+            FuseOpCode::FUSE_SETVOLNAME => Operation::SetVolName {
+                name: data.fetch_str()?,
+            },
+            #[cfg(target_os = "macos")]
+            FuseOpCode::FUSE_GETXTIMES => Operation::GetXTimes,
+            #[cfg(target_os = "macos")]
+            FuseOpCode::FUSE_EXCHANGE => Operation::Exchange {
+                arg: data.fetch_ref()?,
+                oldname: data.fetch_str()?,
+                newname: data.fetch_str()?,
+            },
             #[cfg(feature = "abi-7-11")]
             FuseOpCode::CUSE_INIT => Operation::CuseInit {
                 arg: data.fetch_ref()?,
@@ -680,6 +725,21 @@ impl fmt::Display for Operation<'_> {
                 "COPYFILERANGE src fh={}, dst fh={}, flags={:#?}",
                 arg.fh_in, arg.fh_out, arg.flags,
             ),
+            #[cfg(target_os = "macos")]
+            Operation::SetVolName { name } => write!(f, "SETVOLNAME name={name:?}"),
+            #[cfg(target_os = "macos")]
+            Operation::GetXTimes => write!(f, "GETXTIMES"),
+            #[cfg(target_os = "macos")]
+            Operation::Exchange {
+                arg,
+                oldname,
+                newname,
+            } => write!(
+                f,
+                "EXCHANGE olddir={:#018x}, oldname={:?}, newdir={:#018x}, newname={:?}, \
+                    options={:#x}",
+                arg.olddir, oldname, arg.newdir, newname, arg.options,
+            ),
             #[cfg(feature = "abi-7-11")]
             Operation::CuseInit { arg } => write!(
                 f,
@@ -716,13 +776,15 @@ impl<'a> Request<'a> {
         let mut de = Deserializer::new(bytes);
         // Parse header
         let header = de.fetch_ref::<FuseInHeader>()?;
-        // Check data size
-        debug_assert!(
-            data_len >= header.len.cast(), // TODO: why not daten_len == header.len?
-            "failed to assert {} >= {}",
-            data_len,
-            header.len,
-        );
+        // Check data size, a request shorter than its header claims is
+        // truncated
+        if data_len < header.len.cast() {
+            debug!(
+                "request is truncated: bytes.len() = {}, header.len = {}",
+                data_len, header.len,
+            );
+            return Err(DeserializeError::NotEnough);
+        }
         // Parse/check operation arguments
         let operation = Operation::parse(header.opcode, &mut de, proto_version).map_err(|e| {
             if let DeserializeError::UnknownOpCode { code, .. } = e {
@@ -817,8 +879,6 @@ impl<'a> Request<'a> {
 
 #[cfg(test)]
 mod test {
-    use tracing::debug;
-
     use super::super::de::DeserializeError;
     use super::*;
 
@@ -903,17 +963,16 @@ mod test {
     }
 
     #[test]
-    #[should_panic(expected = "failed to assert 48 >= 56")]
     fn short_read() {
         let idx = 48;
-        let req = Request::new(
-            INIT_REQUEST
-                .get(..idx)
-                .unwrap_or_else(|| panic!("failed to get first {idx} elements from INIT_REQUEST")),
-            PROTO_VERSION,
-        )
-        .unwrap_or_else(|err| panic!("failed to build FUSE request, the error is: {err}"));
-        debug!("short read request={:?}", req);
+        let bytes = INIT_REQUEST
+            .get(..idx)
+            .unwrap_or_else(|| panic!("failed to get first {idx} elements from INIT_REQUEST"));
+
+        #[allow(clippy::expect_used)]
+        let err =
+            Request::new(bytes, PROTO_VERSION).expect_err("Unexpected request parsing result");
+        assert_eq!(err, DeserializeError::NotEnough);
     }
 
     fn check_header(req: &Request<'_>) {
@@ -1363,7 +1422,7 @@ mod test {
         opcode: 16;
         u64: 0x10,         // fh
         u64: 0x0a,         // offset
-        u32: 0x10,         // size
+        u32: 8,            // size
         u32: 0,            // write_flags
         str: b"foo, bar",  // data
     }
@@ -1375,7 +1434,7 @@ mod test {
         opcode: 16;
         u64: 0x10,         // fh
         u64: 0x0a,         // offset
-        u32: 0x10,         // size
+        u32: 8,            // size
         u32: 0b11,         // write_flags
         u64: 0x1234,       // lock_owner
         u32: 2,            // flags
@@ -1398,7 +1457,7 @@ mod test {
             Operation::Write { arg, data } => {
                 assert_eq!(arg.fh, 0x10);
                 assert_eq!(arg.offset, 0x0a);
-                assert_eq!(arg.size, 0x10);
+                assert_eq!(arg.size, 8);
                 #[cfg(not(feature = "abi-7-9"))]
                 assert_eq!(arg.write_flags, 0);
                 #[cfg(feature = "abi-7-9")]
@@ -1413,6 +1472,42 @@ mod test {
         }
     }
 
+    #[cfg(not(feature = "abi-7-9"))]
+    define_payload! {
+        SHORT_WRITE_REQUEST;
+        len: 72;
+        opcode: 16;
+        u64: 0x10,         // fh
+        u64: 0x0a,         // offset
+        u32: 0x10,         // size
+        u32: 0,            // write_flags
+        str: b"foo, bar",  // data
+    }
+
+    #[cfg(feature = "abi-7-9")]
+    define_payload! {
+        SHORT_WRITE_REQUEST;
+        len: 88;
+        opcode: 16;
+        u64: 0x10,         // fh
+        u64: 0x0a,         // offset
+        u32: 0x10,         // size
+        u32: 0b11,         // write_flags
+        u64: 0x1234,       // lock_owner
+        u32: 2,            // flags
+        u32: 0,            // padding
+        str: b"foo, bar",  // data
+    }
+
+    #[test]
+    fn short_write() {
+        // The data is shorter than the size of the write.
+        #[allow(clippy::expect_used)]
+        let err = Request::new(&SHORT_WRITE_REQUEST[..], PROTO_VERSION)
+            .expect_err("Unexpected request parsing result");
+        assert_eq!(err, DeserializeError::NotEnough);
+    }
+
     define_payload! {
         STATFS_REQUEST;
         len: 40;
@@ -1493,6 +1588,7 @@ mod test {
         }
     }
 
+    #[cfg(not(target_os = "macos"))]
     define_payload! {
         SETXATTR_REQUEST;
         len: 64;
@@ -1503,6 +1599,19 @@ mod test {
         str: b"foo, bar",   // value
     }
 
+    #[cfg(target_os = "macos")]
+    define_payload! {
+        SETXATTR_REQUEST;
+        len: 72;
+        opcode: 21;
+        u32: 8,             // size
+        u32: 0,             // flags
+        u32: 0,             // position
+        u32: 0,             // padding
+        str: b"foo.bar\0",  // name
+        str: b"foo, bar",   // value
+    }
+
     #[test]
     fn setxattr() {
         let req = Request::new(&SETXATTR_REQUEST[..], PROTO_VERSION)
@@ -1523,6 +1632,7 @@ mod test {
         }
     }
 
+    #[cfg(not(target_os = "macos"))]
     define_payload! {
         GETXATTR_REQUEST;
         len: 56;
@@ -1532,6 +1642,18 @@ mod test {
         str: b"foo.bar\0",  // name
     }
 
+    #[cfg(target_os = "macos")]
+    define_payload! {
+        GETXATTR_REQUEST;
+        len: 64;
+        opcode: 22;
+        u32: 0x80,          // size
+        u32: 0,             // padding
+        u32: 0,             // position
+        u32: 0,             // padding2
+        str: b"foo.bar\0",  // name
+    }
+
     #[test]
     fn getxattr() {
         let req = Request::new(&GETXATTR_REQUEST[..], PROTO_VERSION)
@@ -2420,6 +2542,43 @@ mod test {
         }
     }
 
+    #[cfg(target_os = "macos")]
+    define_payload! {
+        EXCHANGE_REQUEST;
+        len: 80;
+        opcode: 63;
+        u64: 1,             // olddir
+        u64: 2,             // newdir
+        u64: 0,             // options
+        str: b"foo.txt\0",  // oldname
+        str: b"bar.txt\0",  // newname
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn exchange() {
+        let req = Request::new(&EXCHANGE_REQUEST[..], PROTO_VERSION)
+            .unwrap_or_else(|err| panic!("failed to build FUSE request, the error is: {err}"));
+        assert_eq!(EXCHANGE_REQUEST.len(), req.len().cast::<usize>());
+        assert_eq!(req.header.opcode, 63);
+        check_header(&req);
+
+        #[allow(clippy::wildcard_enum_match_arm)]
+        match *req.operation() {
+            Operation::Exchange {
+                arg,
+                oldname,
+                newname,
+            } => {
+                assert_eq!(arg.olddir, 1);
+                assert_eq!(arg.newdir, 2);
+                assert_eq!(oldname, "foo.txt");
+                assert_eq!(newname, "bar.txt");
+            }
+            _ => panic!("unexpected request operation"),
+        }
+    }
+
     #[cfg(feature = "abi-7-11")]
     define_payload! {
         CUSE_INIT_REQUEST;
@@ -0,0 +1,841 @@
+//! The capture and the replay of the FUSE requests and replies, a tcpdump of
+//! the FUSE session.
+//!
+//! Once a trace is started by `--fuse-trace`, every request read from the
+//! FUSE device and every reply written to it are recorded into a compact
+//! binary trace in the order they're read and written. The first `SNAP_LEN`
+//! bytes of a message are captured, which hold the header and the arguments
+//! of every request but the data of the writes, and the payloads beyond are
+//! truncated.
+//!
+//! The fusedump role prints a trace, or replays it against the volume opened
+//! in the process. The requests are dispatched one by one to the file system
+//! as they're by a session, with the truncated payloads filled with zeros,
+//! the errors replied are compared with the recorded ones, and the latency of
+//! every operation is measured. So a failure seen on a mount is reproduced
+//! without the kernel, and a regression of the performance is measured on a
+//! real workload.
+//!
+//! The i-numbers and the file handles of a replay differ from the recorded
+//! ones, they're mapped to the replayed ones by the replies of the lookups,
+//! the creations and the opens. So a trace is replayed against an empty
+//! volume, or one restored to the state where the trace started. The
+//! messages are kept in the native byte order, so a trace is replayed on a
+//! machine of the same byte order.
+//!
+//! A trace starts with `TRACE_MAGIC` and the version of the format, followed
+//! by the records, each of which is the nanoseconds since the start of the
+//! trace, the kind, the length of the message and the length captured, all in
+//! little-endian, then the bytes captured.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::fs::File;
+use std::io::{self, BufReader, IoSlice, Read, Write};
+use std::mem;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use aligned_utils::bytes::AlignedBytes;
+use anyhow::{anyhow, Context};
+use clippy_utilities::{Cast, OverflowArithmetic};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tracing::{debug, error};
+
+use super::context::ProtoVersion;
+use super::file_system::FileSystem;
+use super::fuse_reply::ReplySink;
+use super::fuse_request::{Operation, Request};
+use super::io_stats::{op_name, OpLatency};
+#[cfg(feature = "abi-7-21")]
+use super::protocol::FuseDirEnt;
+#[cfg(feature = "abi-7-16")]
+use super::protocol::{FuseBatchForgetIn, FuseForgetOne};
+use super::protocol::{FuseEntryOut, FuseInHeader, FuseOutHeader};
+use super::session::{self, FUSE_REQUEST_DEADLINE, PAGE_SIZE};
+use crate::bench::format_latency;
+use crate::storage::transfer::deadline;
+
+/// The FUSE requests and replies recorded of this process.
+pub static FUSE_TRACE: Lazy<Recorder> = Lazy::new(Recorder::default);
+
+/// The magic at the start of a trace.
+const TRACE_MAGIC: &[u8; 8] = b"FUSEDUMP";
+/// The version of the format of a trace.
+const TRACE_VERSION: u32 = 1;
+/// The bytes captured of a message at most.
+pub const SNAP_LEN: usize = 4096;
+/// The length of the header of a record.
+const RECORD_HEADER_LEN: usize = 20;
+/// The offset of the error in the header of a reply.
+const ERROR_OFFSET: usize = 4;
+/// The offset of the unique ID in the header of a request or a reply.
+const UNIQUE_OFFSET: usize = 8;
+/// The offset of the i-number in the header of a request.
+const NODEID_OFFSET: usize = 16;
+/// The mismatches of a replay described at most.
+const MAX_MISMATCHES_SHOWN: usize = 20;
+
+/// The kind of a record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    /// A request read from the FUSE device
+    Request,
+    /// A reply written to the FUSE device
+    Reply,
+}
+
+impl RecordKind {
+    /// The code of the kind in a trace.
+    const fn code(self) -> u32 {
+        match self {
+            Self::Request => 1,
+            Self::Reply => 2,
+        }
+    }
+
+    /// The kind of `code`, `None` if it's unknown.
+    const fn from_code(code: u32) -> Option<Self> {
+        match code {
+            1 => Some(Self::Request),
+            2 => Some(Self::Reply),
+            _ => None,
+        }
+    }
+}
+
+/// A request or a reply recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// The kind of the message
+    pub kind: RecordKind,
+    /// The time since the start of the trace
+    pub time: Duration,
+    /// The length of the message
+    pub len: u32,
+    /// The first `SNAP_LEN` bytes of the message
+    pub bytes: Vec<u8>,
+}
+
+impl Record {
+    /// The unique ID of the request, or of the request replied.
+    #[must_use]
+    pub fn unique(&self) -> Option<u64> {
+        field(&self.bytes, UNIQUE_OFFSET).map(u64::from_ne_bytes)
+    }
+
+    /// The error of a reply, `0` or a negative errno.
+    #[must_use]
+    pub fn error(&self) -> Option<i32> {
+        field(&self.bytes, ERROR_OFFSET).map(i32::from_ne_bytes)
+    }
+
+    /// Whether the payload of the message is truncated.
+    #[must_use]
+    pub fn is_truncated(&self) -> bool {
+        self.bytes.len() < self.len.cast()
+    }
+
+    /// The message with the truncated payload filled with zeros, aligned as
+    /// the buffers of a session.
+    fn restore(&self) -> AlignedBytes {
+        let len = self.bytes.len().max(self.len.cast());
+        let mut bytes = AlignedBytes::new_zeroed(len, PAGE_SIZE);
+        if let Some(captured) = bytes.get_mut(..self.bytes.len()) {
+            captured.copy_from_slice(&self.bytes);
+        }
+        bytes
+    }
+
+    /// Encode the record in a trace.
+    fn encode(&self) -> Vec<u8> {
+        let nanos: u64 = self.time.as_nanos().try_into().unwrap_or(u64::MAX);
+        let captured: u32 = self.bytes.len().cast();
+        let mut encoded = Vec::with_capacity(RECORD_HEADER_LEN.overflow_add(self.bytes.len()));
+        encoded.extend_from_slice(&nanos.to_le_bytes());
+        encoded.extend_from_slice(&self.kind.code().to_le_bytes());
+        encoded.extend_from_slice(&self.len.to_le_bytes());
+        encoded.extend_from_slice(&captured.to_le_bytes());
+        encoded.extend_from_slice(&self.bytes);
+        encoded
+    }
+}
+
+/// The `N` bytes of `bytes` at `offset`, `None` if they're beyond it.
+fn field<const N: usize>(bytes: &[u8], offset: usize) -> Option<[u8; N]> {
+    bytes.get(offset..offset.checked_add(N)?)?.try_into().ok()
+}
+
+/// The body of a reply following its header.
+fn body(reply: &[u8]) -> &[u8] {
+    reply
+        .get(mem::size_of::<FuseOutHeader>()..)
+        .unwrap_or_default()
+}
+
+/// The recorder of the FUSE requests and replies into a trace.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    /// Whether the messages are recorded
+    enabled: AtomicBool,
+    /// The file of the trace and the start of it, `None` if it's not started
+    trace: Mutex<Option<(File, Instant)>>,
+}
+
+impl Recorder {
+    /// Record the requests and the replies from now on into a new trace at
+    /// `path`.
+    pub fn start(&self, path: &Path) -> anyhow::Result<()> {
+        let mut file = File::create(path)
+            .with_context(|| format!("failed to create the FUSE trace {}", path.display()))?;
+        file.write_all(TRACE_MAGIC)?;
+        file.write_all(&TRACE_VERSION.to_le_bytes())?;
+        *self.trace.lock() = Some((file, Instant::now()));
+        self.enabled.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Whether the messages are recorded.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Record a message of `kind` made up of `parts`, if the trace is
+    /// started. The trace is stopped once it fails to be written.
+    pub fn record<B: Deref<Target = [u8]>>(&self, kind: RecordKind, parts: &[B]) {
+        if !self.is_enabled() {
+            return;
+        }
+        let len = parts
+            .iter()
+            .fold(0_usize, |len, part| len.overflow_add(part.len()));
+        let mut bytes = Vec::with_capacity(len.min(SNAP_LEN));
+        for part in parts {
+            let part: &[u8] = part;
+            let room = SNAP_LEN.saturating_sub(bytes.len());
+            bytes.extend_from_slice(part.get(..room).unwrap_or(part));
+        }
+
+        let mut trace = self.trace.lock();
+        let Some((ref mut file, start)) = *trace else {
+            return;
+        };
+        let record = Record {
+            kind,
+            time: start.elapsed(),
+            len: len.cast(),
+            bytes,
+        };
+        // A record is written at once, so the records of the concurrent
+        // requests are not interleaved.
+        let result = file.write_all(&record.encode());
+        if let Err(e) = result {
+            error!("failed to record into the FUSE trace, it's stopped: {e}");
+            *trace = None;
+            self.enabled.store(false, Ordering::Release);
+        }
+    }
+}
+
+/// A sink of the replies, which records them into `FUSE_TRACE` before they're
+/// written to the inner sink.
+pub struct TracedSink<'a> {
+    /// The inner sink
+    sink: &'a mut dyn ReplySink,
+}
+
+impl Debug for TracedSink<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TracedSink").finish_non_exhaustive()
+    }
+}
+
+impl<'a> TracedSink<'a> {
+    /// Record the replies written to `sink`.
+    pub fn new(sink: &'a mut dyn ReplySink) -> Self {
+        Self { sink }
+    }
+}
+
+impl Write for TracedSink<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        FUSE_TRACE.record(RecordKind::Reply, &[buf]);
+        self.sink.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        FUSE_TRACE.record(RecordKind::Reply, bufs);
+        self.sink.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
+
+/// The reader of the records of a trace.
+#[derive(Debug)]
+pub struct TraceReader {
+    /// The file of the trace
+    reader: BufReader<File>,
+}
+
+impl TraceReader {
+    /// Open the trace at `path`.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("failed to open the FUSE trace {}", path.display()))?;
+        let mut trace = Self {
+            reader: BufReader::new(file),
+        };
+        let magic: [u8; 8] = trace
+            .read_array()
+            .context("failed to read the header of the FUSE trace")?;
+        anyhow::ensure!(
+            &magic == TRACE_MAGIC,
+            "{} is not a FUSE trace",
+            path.display()
+        );
+        let version = u32::from_le_bytes(trace.read_array()?);
+        anyhow::ensure!(
+            version == TRACE_VERSION,
+            "the FUSE trace of version {version} is not supported"
+        );
+        Ok(trace)
+    }
+
+    /// Read the next record, `None` at the end of the trace.
+    pub fn next_record(&mut self) -> anyhow::Result<Option<Record>> {
+        match self.read_record() {
+            Ok(record) => Ok(Some(record)),
+            // The end of the trace, or a record partially written when the
+            // mount is killed
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(anyhow!(e).context("failed to read the FUSE trace")),
+        }
+    }
+
+    /// Read `N` bytes of the trace.
+    fn read_array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Read a record.
+    fn read_record(&mut self) -> io::Result<Record> {
+        let time = Duration::from_nanos(u64::from_le_bytes(self.read_array()?));
+        let code = u32::from_le_bytes(self.read_array()?);
+        let kind = RecordKind::from_code(code).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the record kind {code} is unknown"),
+            )
+        })?;
+        let len = u32::from_le_bytes(self.read_array()?);
+        let captured: usize = u32::from_le_bytes(self.read_array()?).cast();
+        if captured > SNAP_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the record of {captured} bytes is beyond {SNAP_LEN}"),
+            ));
+        }
+        let mut bytes = vec![0; captured];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(Record {
+            kind,
+            time,
+            len,
+            bytes,
+        })
+    }
+}
+
+/// Print the records of the trace at `path` to `out`, one per line.
+pub fn print_trace(path: &Path, out: &mut impl Write) -> anyhow::Result<()> {
+    let mut trace = TraceReader::open(path)?;
+    let mut proto_version = ProtoVersion::UNSPECIFIED;
+    while let Some(record) = trace.next_record()? {
+        let time = format!(
+            "{}.{:06}",
+            record.time.as_secs(),
+            record.time.subsec_micros()
+        );
+        let unique = record.unique().unwrap_or_default();
+        match record.kind {
+            RecordKind::Request => {
+                let bytes = record.restore();
+                match Request::new(&bytes, proto_version) {
+                    Ok(req) => {
+                        if let Operation::Init { arg } = *req.operation() {
+                            proto_version = ProtoVersion {
+                                major: arg.major,
+                                minor: arg.minor,
+                            };
+                        }
+                        write!(out, "{time} > {req}")?;
+                    }
+                    Err(e) => write!(out, "{time} > fuse={unique} undecodable: {e}")?,
+                }
+            }
+            RecordKind::Reply => write!(
+                out,
+                "{time} < fuse={unique} error={} len={}",
+                record.error().unwrap_or_default(),
+                record.len,
+            )?,
+        }
+        if record.is_truncated() {
+            write!(
+                out,
+                " (truncated to {} of {} bytes)",
+                record.bytes.len(),
+                record.len
+            )?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// The report of a replay.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// The requests replayed
+    pub requests: u64,
+    /// The requests skipped as they cannot be decoded
+    pub undecodable: u64,
+    /// The replies whose errors differ from the recorded ones
+    pub mismatches: u64,
+    /// The first `MAX_MISMATCHES_SHOWN` mismatches
+    pub first_mismatches: Vec<String>,
+    /// The name of an operation -> its latency
+    pub ops: BTreeMap<String, OpLatency>,
+}
+
+impl ReplayReport {
+    /// Count a request of `op` replayed in `latency`.
+    fn add(&mut self, op: &str, latency: Duration) {
+        self.requests = self.requests.overflow_add(1);
+        self.ops.entry(op.to_owned()).or_default().add(latency);
+    }
+
+    /// Count a reply mismatched, described by `mismatch`.
+    fn mismatch(&mut self, mismatch: String) {
+        debug!("the replayed reply mismatches: {mismatch}");
+        self.mismatches = self.mismatches.overflow_add(1);
+        if self.first_mismatches.len() < MAX_MISMATCHES_SHOWN {
+            self.first_mismatches.push(mismatch);
+        }
+    }
+}
+
+impl Display for ReplayReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let elapsed: Duration = self.ops.values().map(|stats| stats.total).sum();
+        writeln!(
+            f,
+            "{} requests replayed in {}, {} undecodable, {} mismatched",
+            self.requests,
+            format_latency(elapsed),
+            self.undecodable,
+            self.mismatches,
+        )?;
+        write!(f, "{:<16} {:>8} {:>8} {:>8}", "OP", "COUNT", "MEAN", "MAX")?;
+        for (op, stats) in &self.ops {
+            write!(
+                f,
+                "\n{:<16} {:>8} {:>8} {:>8}",
+                op,
+                stats.count,
+                format_latency(stats.mean()),
+                format_latency(stats.max),
+            )?;
+        }
+        for mismatch in &self.first_mismatches {
+            write!(f, "\n{mismatch}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The IDs a reply assigns, which are mapped from the recorded ones to the
+/// replayed ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Assigned {
+    /// None
+    Nothing,
+    /// The i-number of an entry
+    Entry,
+    /// The i-number of an entry and the handle of it opened, by `CREATE`
+    EntryOpened,
+    /// The handle of a file or a directory opened
+    Opened,
+    /// The i-numbers of the entries of a directory read with their attributes
+    #[cfg(feature = "abi-7-21")]
+    Entries,
+}
+
+/// The IDs assigned by the reply to `op`.
+#[allow(clippy::wildcard_enum_match_arm)]
+fn assigned_by(op: &Operation<'_>) -> Assigned {
+    match *op {
+        Operation::Lookup { .. }
+        | Operation::MkNod { .. }
+        | Operation::MkDir { .. }
+        | Operation::SymLink { .. }
+        | Operation::Link { .. } => Assigned::Entry,
+        Operation::Create { .. } => Assigned::EntryOpened,
+        Operation::Open { .. } | Operation::OpenDir { .. } => Assigned::Opened,
+        #[cfg(feature = "abi-7-21")]
+        Operation::ReadDirPlus { .. } => Assigned::Entries,
+        _ => Assigned::Nothing,
+    }
+}
+
+/// An ID in the arguments of a request assigned by the file system, at the
+/// offset from the start of the arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IdField {
+    /// An i-number
+    Inode(usize),
+    /// A file handle
+    Handle(usize),
+}
+
+/// The IDs in the arguments of `op`, besides the i-number in the header.
+#[allow(clippy::wildcard_enum_match_arm)]
+fn id_fields(op: &Operation<'_>) -> Vec<IdField> {
+    match *op {
+        // `newdir` and `oldnodeid` lead the arguments
+        Operation::Rename { .. } | Operation::Link { .. } => vec![IdField::Inode(0)],
+        #[cfg(feature = "abi-7-23")]
+        Operation::Rename2 { .. } => vec![IdField::Inode(0)],
+        // The handle leads the arguments
+        Operation::Read { .. }
+        | Operation::Write { .. }
+        | Operation::Release { .. }
+        | Operation::ReleaseDir { .. }
+        | Operation::ReadDir { .. }
+        | Operation::Flush { .. }
+        | Operation::FSync { .. }
+        | Operation::FSyncDir { .. }
+        | Operation::GetLk { .. }
+        | Operation::SetLk { .. }
+        | Operation::SetLkW { .. }
+        | Operation::LSeek { .. } => vec![IdField::Handle(0)],
+        #[cfg(feature = "abi-7-19")]
+        Operation::FAllocate { .. } => vec![IdField::Handle(0)],
+        #[cfg(feature = "abi-7-21")]
+        Operation::ReadDirPlus { .. } => vec![IdField::Handle(0)],
+        // `fh_in`, `off_in`, `nodeid_out` and `fh_out`
+        Operation::CopyFileRange { .. } => {
+            vec![IdField::Handle(0), IdField::Inode(16), IdField::Handle(24)]
+        }
+        #[cfg(feature = "abi-7-16")]
+        Operation::BatchForget { nodes, .. } => (0..nodes.len())
+            .map(|i| {
+                IdField::Inode(
+                    mem::size_of::<FuseBatchForgetIn>()
+                        .overflow_add(i.overflow_mul(mem::size_of::<FuseForgetOne>())),
+                )
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Replace the ID at `offset` of `bytes` by the one it's mapped to in `ids`.
+fn remap(bytes: &mut [u8], offset: usize, ids: &HashMap<u64, u64>) {
+    let Some(id) = field(bytes, offset).map(u64::from_ne_bytes) else {
+        return;
+    };
+    if let Some(&mapped) = ids.get(&id) {
+        if let Some(dst) = bytes.get_mut(offset..offset.overflow_add(mem::size_of::<u64>())) {
+            dst.copy_from_slice(&mapped.to_ne_bytes());
+        }
+    }
+}
+
+/// Map the ID at `offset` of the recorded body to the one of the replayed.
+fn map_id(ids: &mut HashMap<u64, u64>, recorded: &[u8], replayed: &[u8], offset: usize) {
+    let recorded = field(recorded, offset).map(u64::from_ne_bytes);
+    let replayed = field(replayed, offset).map(u64::from_ne_bytes);
+    if let (Some(recorded), Some(replayed)) = (recorded, replayed) {
+        ids.insert(recorded, replayed);
+    }
+}
+
+/// The i-numbers of the entries of a `READDIRPLUS` reply, `0` for the ones
+/// not looked up.
+#[cfg(feature = "abi-7-21")]
+fn dir_entries_plus(body: &[u8]) -> Vec<u64> {
+    let entry_len = mem::size_of::<FuseEntryOut>();
+    let dirent_len = mem::size_of::<FuseDirEnt>();
+    let mut nodeids = vec![];
+    let mut offset = 0_usize;
+    // `namelen` follows `ino` and `off` of the dirent
+    while let (Some(nodeid), Some(namelen)) = (
+        field(body, offset).map(u64::from_ne_bytes),
+        field(body, offset.overflow_add(entry_len).overflow_add(16)).map(u32::from_ne_bytes),
+    ) {
+        nodeids.push(nodeid);
+        let len = entry_len
+            .overflow_add(dirent_len)
+            .overflow_add(namelen.cast());
+        // The entries are aligned to 8 bytes
+        offset = offset.overflow_add(len.overflow_add(7) & !7);
+    }
+    nodeids
+}
+
+/// A request replayed, whose recorded reply is not read yet.
+#[derive(Debug)]
+struct Replayed {
+    /// The name of the operation
+    op: String,
+    /// The IDs assigned by the reply
+    assigned: Assigned,
+    /// The reply replayed
+    reply: Vec<u8>,
+}
+
+/// The state of a replay.
+struct Replayer {
+    /// The file system replayed against
+    fs: Arc<dyn FileSystem + Send + Sync>,
+    /// The FUSE protocol version of the trace
+    proto_version: ProtoVersion,
+    /// The recorded i-numbers -> the replayed ones
+    inodes: HashMap<u64, u64>,
+    /// The recorded file handles -> the replayed ones
+    handles: HashMap<u64, u64>,
+    /// The unique ID -> the request replayed whose recorded reply is not
+    /// read yet
+    pending: HashMap<u64, Replayed>,
+    /// The report
+    report: ReplayReport,
+}
+
+impl Replayer {
+    /// Replay the request of `record`.
+    async fn replay_request(&mut self, record: &Record) -> anyhow::Result<()> {
+        let mut bytes = record.restore();
+        let (op, assigned, fields) = match Request::new(&bytes, self.proto_version) {
+            Ok(req) => (
+                op_name(req.operation()),
+                assigned_by(req.operation()),
+                id_fields(req.operation()),
+            ),
+            Err(e) => {
+                debug!("skip the undecodable request: {e}");
+                self.report.undecodable = self.report.undecodable.overflow_add(1);
+                return Ok(());
+            }
+        };
+        remap(&mut bytes, NODEID_OFFSET, &self.inodes);
+        let args = mem::size_of::<FuseInHeader>();
+        for id_field in fields {
+            match id_field {
+                IdField::Inode(offset) => {
+                    remap(&mut bytes, args.overflow_add(offset), &self.inodes);
+                }
+                IdField::Handle(offset) => {
+                    remap(&mut bytes, args.overflow_add(offset), &self.handles);
+                }
+            }
+        }
+
+        let req = Request::new(&bytes, self.proto_version)
+            .map_err(|e| anyhow!("failed to decode the remapped request: {e}"))?;
+        let mut reply = Vec::new();
+        let start = Instant::now();
+        if let Operation::Init { arg } = *req.operation() {
+            self.proto_version =
+                session::init_session(arg, &req, &*self.fs, &mut reply, false).await?;
+        } else {
+            let deadline = tokio::time::Instant::now() + FUSE_REQUEST_DEADLINE;
+            deadline::scope(
+                deadline,
+                session::dispatch(&req, &mut reply, Arc::clone(&self.fs)),
+            )
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "failed to replay {req}: {}",
+                    crate::async_fuse::util::format_nix_error(e)
+                )
+            })?;
+        }
+        self.report.add(&op, start.elapsed());
+        // The requests never replied, such as `FORGET`, are not pending
+        if !reply.is_empty() {
+            self.pending.insert(
+                req.unique(),
+                Replayed {
+                    op,
+                    assigned,
+                    reply,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Check the replayed reply against the recorded reply of `record`, and
+    /// map the IDs it assigns.
+    fn check_reply(&mut self, record: &Record) {
+        let Some(unique) = record.unique() else {
+            return;
+        };
+        // The replies to the requests skipped are not pending
+        let Some(replayed) = self.pending.remove(&unique) else {
+            return;
+        };
+        let recorded_error = record.error().unwrap_or_default();
+        let replayed_error = field(&replayed.reply, ERROR_OFFSET)
+            .map(i32::from_ne_bytes)
+            .unwrap_or_default();
+        if recorded_error != replayed_error {
+            self.report.mismatch(format!(
+                "fuse={unique} {}: recorded error {recorded_error}, replayed {replayed_error}",
+                replayed.op,
+            ));
+            return;
+        }
+        if recorded_error != 0 {
+            return;
+        }
+
+        let (recorded, replayed_body) = (body(&record.bytes), body(&replayed.reply));
+        match replayed.assigned {
+            Assigned::Nothing => {}
+            // `nodeid` leads the `fuse_entry_out`
+            Assigned::Entry => map_id(&mut self.inodes, recorded, replayed_body, 0),
+            // `fh` leads the `fuse_open_out`
+            Assigned::Opened => map_id(&mut self.handles, recorded, replayed_body, 0),
+            Assigned::EntryOpened => {
+                map_id(&mut self.inodes, recorded, replayed_body, 0);
+                let open_offset = mem::size_of::<FuseEntryOut>();
+                map_id(&mut self.handles, recorded, replayed_body, open_offset);
+            }
+            // The entries are replied in the same order, unless the
+            // directories differ, which the later requests mismatch
+            #[cfg(feature = "abi-7-21")]
+            Assigned::Entries => {
+                for (recorded, replayed) in dir_entries_plus(recorded)
+                    .into_iter()
+                    .zip(dir_entries_plus(replayed_body))
+                {
+                    if recorded != 0 {
+                        self.inodes.insert(recorded, replayed);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Replay the requests of the trace at `path` against `fs` one by one,
+/// returns the latency of the operations and the replies mismatched.
+pub async fn replay(
+    path: &Path,
+    fs: Arc<dyn FileSystem + Send + Sync>,
+) -> anyhow::Result<ReplayReport> {
+    let mut trace = TraceReader::open(path)?;
+    let mut replayer = Replayer {
+        fs,
+        proto_version: ProtoVersion::UNSPECIFIED,
+        inodes: HashMap::new(),
+        handles: HashMap::new(),
+        pending: HashMap::new(),
+        report: ReplayReport::default(),
+    };
+    while let Some(record) = trace.next_record()? {
+        match record.kind {
+            RecordKind::Request => replayer.replay_request(&record).await?,
+            RecordKind::Reply => replayer.check_reply(&record),
+        }
+    }
+    Ok(replayer.report)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::io::Write;
+    use std::path::Path;
+
+    use super::{remap, RecordKind, Recorder, TraceReader, NODEID_OFFSET, SNAP_LEN};
+
+    /// The directory of the traces of the tests.
+    const TRACE_TEST_DIR: &str = "/tmp/datenlord_fusedump_unit_test";
+
+    #[test]
+    fn test_trace_round_trip() {
+        fs::create_dir_all(TRACE_TEST_DIR).unwrap();
+        let path = Path::new(TRACE_TEST_DIR).join("round_trip");
+        let recorder = Recorder::default();
+        // Nothing is recorded before the trace is started
+        recorder.record(RecordKind::Request, &[[0_u8; 8].as_slice()]);
+        recorder.start(&path).unwrap();
+        let request: Vec<u8> = (0..40).collect();
+        recorder.record(RecordKind::Request, &[request.as_slice()]);
+        let payload = vec![7_u8; SNAP_LEN];
+        recorder.record(
+            RecordKind::Reply,
+            &[[1_u8; 16].as_slice(), payload.as_slice()],
+        );
+        // A record partially written ends the trace
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&[0; 6])
+            .unwrap();
+
+        let mut trace = TraceReader::open(&path).unwrap();
+        let record = trace.next_record().unwrap().unwrap();
+        assert_eq!(record.kind, RecordKind::Request);
+        assert_eq!(record.bytes, request);
+        assert_eq!(
+            record.unique(),
+            Some(u64::from_ne_bytes([8, 9, 10, 11, 12, 13, 14, 15]))
+        );
+        assert!(!record.is_truncated());
+
+        let record = trace.next_record().unwrap().unwrap();
+        assert_eq!(record.kind, RecordKind::Reply);
+        assert_eq!(record.len, 16 + 4096);
+        assert_eq!(record.bytes.len(), SNAP_LEN);
+        assert_eq!(record.bytes[..16], [1; 16]);
+        assert!(record.is_truncated());
+        let restored = record.restore();
+        assert_eq!(restored.len(), 16 + 4096);
+        assert!(restored[SNAP_LEN..].iter().all(|&b| b == 0));
+        assert!(trace.next_record().unwrap().is_none());
+
+        fs::write(&path, b"NOTATRACE").unwrap();
+        assert!(TraceReader::open(&path).is_err());
+    }
+
+    #[test]
+    fn test_remap() {
+        let mut bytes = vec![0_u8; 24];
+        bytes[NODEID_OFFSET..].copy_from_slice(&1000_u64.to_ne_bytes());
+        let ids = HashMap::from([(1000, 2), (3, 4)]);
+        remap(&mut bytes, NODEID_OFFSET, &ids);
+        assert_eq!(bytes[NODEID_OFFSET..], 2_u64.to_ne_bytes());
+        // The IDs not mapped and the ones beyond the bytes are kept
+        remap(&mut bytes, 0, &ids);
+        assert_eq!(bytes[..8], [0; 8]);
+        remap(&mut bytes, 20, &ids);
+        assert_eq!(bytes[NODEID_OFFSET..], 2_u64.to_ne_bytes());
+    }
+}
@@ -0,0 +1,400 @@
+//! The IO of the FUSE requests attributed to the processes issuing them.
+//!
+//! The header of a FUSE request carries the pid of the caller, so the requests
+//! and the bytes read and written are counted by the process, and by its
+//! cgroup, which is the pod or the container of the process on a node of
+//! Kubernetes. They're served on the control socket to the top role, so the
+//! operators see which pod generates the IO of a shared node.
+//!
+//! The requests of the whole mount are always counted, and are checkpointed
+//! for billing, see [`billing`](crate::async_fuse::memfs::billing), while the
+//! ones by the processes are only counted once the control socket is served.
+//! The command and the cgroup of a process are read from `/proc` on its first
+//! request, and a process is forgotten once it exits, while the IO of its
+//! cgroup is kept. The requests issued by the kernel itself, such as the pages
+//! written back, carry no pid and are counted by pid 0. The latency of every
+//! operation is measured as well once the control socket is served, which
+//! the stats role shows.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use std::{fs, mem};
+
+use clippy_utilities::{Cast, OverflowArithmetic};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use super::fuse_request::{Operation, Request};
+use super::protocol::FuseOutHeader;
+
+/// The IO of the FUSE requests of this process.
+pub static IO_STATS: Lazy<IoStats> = Lazy::new(IoStats::default);
+
+/// The processes remembered at most, the ones exited are forgotten beyond it.
+const MAX_PROCESSES: usize = 4096;
+
+/// The IO counted of a process or a cgroup.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IoCounters {
+    /// The requests
+    pub ops: u64,
+    /// The bytes read
+    pub read_bytes: u64,
+    /// The bytes written
+    pub write_bytes: u64,
+}
+
+impl IoCounters {
+    /// Count a request reading `read` bytes and writing `written` bytes.
+    fn add(&mut self, read: u64, written: u64) {
+        self.ops = self.ops.wrapping_add(1);
+        self.read_bytes = self.read_bytes.wrapping_add(read);
+        self.write_bytes = self.write_bytes.wrapping_add(written);
+    }
+
+    /// The bytes read and written.
+    #[must_use]
+    pub const fn bytes(&self) -> u64 {
+        self.read_bytes.wrapping_add(self.write_bytes)
+    }
+
+    /// The IO counted since `before` was.
+    #[must_use]
+    pub const fn since(&self, before: &Self) -> Self {
+        Self {
+            ops: self.ops.wrapping_sub(before.ops),
+            read_bytes: self.read_bytes.wrapping_sub(before.read_bytes),
+            write_bytes: self.write_bytes.wrapping_sub(before.write_bytes),
+        }
+    }
+
+    /// Add the IO of `other`.
+    pub fn merge(&mut self, other: &Self) {
+        self.ops = self.ops.wrapping_add(other.ops);
+        self.read_bytes = self.read_bytes.wrapping_add(other.read_bytes);
+        self.write_bytes = self.write_bytes.wrapping_add(other.write_bytes);
+    }
+}
+
+/// The latency of an operation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpLatency {
+    /// The requests
+    pub count: u64,
+    /// The time spent in the requests
+    pub total: Duration,
+    /// The max latency
+    pub max: Duration,
+}
+
+impl OpLatency {
+    /// Count a request taking `latency`.
+    pub fn add(&mut self, latency: Duration) {
+        self.count = self.count.overflow_add(1);
+        self.total = self.total.saturating_add(latency);
+        self.max = self.max.max(latency);
+    }
+
+    /// The mean latency, zero if there's no request.
+    #[must_use]
+    pub fn mean(&self) -> Duration {
+        self.total
+            .checked_div(self.count.cast())
+            .unwrap_or_default()
+    }
+}
+
+/// The name of `op`, the first word of its description, such as `LOOKUP`.
+pub fn op_name(op: &Operation<'_>) -> String {
+    op.to_string()
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_owned()
+}
+
+/// The IO of a process.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessIo {
+    /// The pid of the process
+    pub pid: u32,
+    /// The command of the process, empty if it's unknown
+    pub comm: String,
+    /// The cgroup of the process, empty if it's unknown
+    pub cgroup: String,
+    /// The IO of the process
+    pub io: IoCounters,
+}
+
+impl ProcessIo {
+    /// Read the command and the cgroup of process `pid` from `/proc`.
+    fn read(pid: u32) -> Self {
+        let (comm, cgroup) = if pid == 0 {
+            (String::new(), String::new())
+        } else {
+            let comm = fs::read_to_string(format!("/proc/{pid}/comm"))
+                .map(|comm| comm.trim_end().to_owned())
+                .unwrap_or_default();
+            let cgroup = fs::read_to_string(format!("/proc/{pid}/cgroup"))
+                .map(|cgroups| parse_cgroup(&cgroups))
+                .unwrap_or_default();
+            (comm, cgroup)
+        };
+        Self {
+            pid,
+            comm,
+            cgroup,
+            io: IoCounters::default(),
+        }
+    }
+
+    /// Whether the process is still running.
+    fn is_alive(&self) -> bool {
+        self.pid == 0 || Path::new(&format!("/proc/{}", self.pid)).exists()
+    }
+}
+
+/// The IO of a cgroup.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CgroupIo {
+    /// The path of the cgroup, empty for the processes of no cgroup known
+    pub cgroup: String,
+    /// The IO of the processes in the cgroup
+    pub io: IoCounters,
+}
+
+/// The IO counted since the mount, the most bytes first.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IoSnapshot {
+    /// The processes running
+    pub processes: Vec<ProcessIo>,
+    /// The cgroups, including the IO of the processes exited
+    pub cgroups: Vec<CgroupIo>,
+}
+
+/// The processes and the cgroups counted.
+#[derive(Debug, Default)]
+struct Counted {
+    /// Pid -> the IO of the process
+    processes: HashMap<u32, ProcessIo>,
+    /// The path of a cgroup -> its IO
+    cgroups: HashMap<String, IoCounters>,
+}
+
+impl Counted {
+    /// Forget the processes exited.
+    fn prune(&mut self) {
+        self.processes.retain(|_, process| process.is_alive());
+    }
+}
+
+/// The IO of the FUSE requests by the processes and the cgroups.
+#[derive(Debug, Default)]
+pub struct IoStats {
+    /// Whether the requests are counted by the processes
+    enabled: AtomicBool,
+    /// The IO of all the requests
+    total: Mutex<IoCounters>,
+    /// The processes and the cgroups counted
+    counted: Mutex<Counted>,
+    /// The name of an operation -> its latency
+    latency: Mutex<BTreeMap<String, OpLatency>>,
+}
+
+impl IoStats {
+    /// Count the requests from now on.
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// Count request `req`, whose reply is `replied` bytes long and is
+    /// replied in `latency`.
+    pub fn record(&self, req: &Request<'_>, replied: usize, latency: Duration) {
+        let read = if let Operation::Read { .. } = *req.operation() {
+            replied.saturating_sub(mem::size_of::<FuseOutHeader>())
+        } else {
+            0
+        };
+        let written = if let Operation::Write { data, .. } = *req.operation() {
+            data.len()
+        } else {
+            0
+        };
+        self.total.lock().add(read.cast(), written.cast());
+        if self.enabled.load(Ordering::Relaxed) {
+            self.add(req.pid(), read.cast(), written.cast());
+            self.add_latency(&op_name(req.operation()), latency);
+        }
+    }
+
+    /// Count a request of `op` replied in `latency`.
+    fn add_latency(&self, op: &str, latency: Duration) {
+        let mut ops = self.latency.lock();
+        if let Some(stats) = ops.get_mut(op) {
+            stats.add(latency);
+        } else {
+            let mut stats = OpLatency::default();
+            stats.add(latency);
+            ops.insert(op.to_owned(), stats);
+        }
+    }
+
+    /// The latency of the operations since the control socket is served, by
+    /// their names.
+    pub fn latency(&self) -> BTreeMap<String, OpLatency> {
+        self.latency.lock().clone()
+    }
+
+    /// The IO of all the requests since the mount.
+    pub fn total(&self) -> IoCounters {
+        *self.total.lock()
+    }
+
+    /// Count a request of process `pid` reading `read` bytes and writing
+    /// `written` bytes.
+    fn add(&self, pid: u32, read: u64, written: u64) {
+        let mut counted = self.counted.lock();
+        if !counted.processes.contains_key(&pid) {
+            // `/proc` is read without the lock.
+            drop(counted);
+            let process = ProcessIo::read(pid);
+            counted = self.counted.lock();
+            if counted.processes.len() >= MAX_PROCESSES {
+                counted.prune();
+            }
+            counted.processes.entry(pid).or_insert(process);
+        }
+
+        let counted = &mut *counted;
+        let Some(process) = counted.processes.get_mut(&pid) else {
+            unreachable!("process {pid} is just inserted");
+        };
+        process.io.add(read, written);
+        match counted.cgroups.get_mut(&process.cgroup) {
+            Some(io) => io.add(read, written),
+            None => {
+                let mut io = IoCounters::default();
+                io.add(read, written);
+                counted.cgroups.insert(process.cgroup.clone(), io);
+            }
+        }
+    }
+
+    /// The IO counted, the processes exited are forgotten.
+    pub fn snapshot(&self) -> IoSnapshot {
+        let mut counted = self.counted.lock();
+        counted.prune();
+        let mut processes: Vec<ProcessIo> = counted.processes.values().cloned().collect();
+        let mut cgroups: Vec<CgroupIo> = counted
+            .cgroups
+            .iter()
+            .map(|(cgroup, io)| CgroupIo {
+                cgroup: cgroup.clone(),
+                io: *io,
+            })
+            .collect();
+        drop(counted);
+        processes.sort_by_key(|process| Reverse((process.io.bytes(), process.io.ops)));
+        cgroups.sort_by_key(|cgroup| Reverse((cgroup.io.bytes(), cgroup.io.ops)));
+        IoSnapshot { processes, cgroups }
+    }
+}
+
+/// Parse the cgroup of a process from its `/proc/<pid>/cgroup`, the one of
+/// cgroup v2, or the one of the memory controller of cgroup v1.
+fn parse_cgroup(cgroups: &str) -> String {
+    let mut found = None;
+    for line in cgroups.lines() {
+        let mut fields = line.splitn(3, ':');
+        let (Some(id), Some(controllers), Some(path)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        if id == "0" && controllers.is_empty() {
+            return path.to_owned();
+        }
+        if found.is_none() || controllers.split(',').any(|c| c == "memory") {
+            found = Some(path);
+        }
+    }
+    found.unwrap_or_default().to_owned()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{parse_cgroup, IoCounters, IoStats};
+
+    #[test]
+    fn test_parse_cgroup() {
+        assert_eq!(
+            parse_cgroup("0::/kubepods.slice/kubepods-pod1234.slice/cri-containerd-abc.scope\n"),
+            "/kubepods.slice/kubepods-pod1234.slice/cri-containerd-abc.scope"
+        );
+        assert_eq!(
+            parse_cgroup(
+                "12:cpu,cpuacct:/kubepods/pod1234/abc\n\
+                 5:memory:/kubepods/burstable/pod1234/abc\n\
+                 1:name=systemd:/kubepods/pod1234/abc\n"
+            ),
+            "/kubepods/burstable/pod1234/abc"
+        );
+        assert_eq!(parse_cgroup(""), "");
+    }
+
+    #[test]
+    fn test_io_stats() {
+        let stats = IoStats::default();
+        let pid = std::process::id();
+        stats.add(pid, 4096, 0);
+        stats.add(pid, 0, 100);
+        stats.add(0, 0, 8192);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.processes.len(), 2);
+        let kernel = snapshot.processes.first().unwrap();
+        assert_eq!(kernel.pid, 0);
+        assert_eq!(
+            kernel.io,
+            IoCounters {
+                ops: 1,
+                read_bytes: 0,
+                write_bytes: 8192,
+            }
+        );
+        let this = snapshot.processes.get(1).unwrap();
+        assert_eq!(this.pid, pid);
+        assert_eq!(
+            this.io,
+            IoCounters {
+                ops: 2,
+                read_bytes: 4096,
+                write_bytes: 100,
+            }
+        );
+        let ops: u64 = snapshot.cgroups.iter().map(|cgroup| cgroup.io.ops).sum();
+        assert_eq!(ops, 3);
+    }
+
+    #[test]
+    fn test_latency() {
+        let stats = IoStats::default();
+        stats.add_latency("READ", Duration::from_millis(2));
+        stats.add_latency("READ", Duration::from_millis(4));
+        stats.add_latency("LOOKUP", Duration::from_millis(1));
+
+        let latency = stats.latency();
+        assert_eq!(latency.keys().collect::<Vec<_>>(), ["LOOKUP", "READ"]);
+        let read = latency.get("READ").unwrap();
+        assert_eq!(read.count, 2);
+        assert_eq!(read.mean(), Duration::from_millis(3));
+        assert_eq!(read.max, Duration::from_millis(4));
+    }
+}
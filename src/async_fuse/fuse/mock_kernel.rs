@@ -0,0 +1,285 @@
+//! A mock of the kernel side of the FUSE protocol, which tests the session,
+//! the dispatch and the replies end to end without mounting, so without
+//! `CAP_SYS_ADMIN`.
+//!
+//! The FUSE device is emulated by a `SOCK_SEQPACKET` socket pair, which keeps
+//! the boundaries of the messages as the device does: a request is read by a
+//! single read, and a reply is written by a single write. The session end is
+//! served by the buffer pool and the readers of a session, the kernel end
+//! issues the requests and checks the replies are well formed.
+
+use std::fmt::{self, Debug};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::mem;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+use aligned_utils::bytes::AlignedBytes;
+use anyhow::{anyhow, ensure, Context};
+use clippy_utilities::{Cast, OverflowArithmetic};
+use datenlord::common::task_manager::{TaskName, TASK_MANAGER};
+use nix::sys::socket::{self, AddressFamily, SockFlag, SockType};
+
+use super::abi_marker::{self, FuseAbiData};
+use super::de::Deserializer;
+use super::file_system::FileSystem;
+use super::protocol::{
+    FuseInHeader, FuseInitIn, FuseInitOut, FuseOpCode, FuseOutHeader, FUSE_KERNEL_MINOR_VERSION,
+    FUSE_KERNEL_VERSION,
+};
+use super::session;
+
+/// The timeout of a reply, a request panicking the session is never replied.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+/// The max size of a reply, larger than the max read of the session.
+const MAX_REPLY_SIZE: usize = 256 * 1024;
+/// The max readahead claimed by the mock kernel.
+const MAX_READAHEAD: u32 = 128 * 1024;
+
+/// A reply of the session.
+pub struct Reply {
+    /// The error, `0` or a negative errno
+    pub error: i32,
+    /// The bytes of the reply, including the header
+    bytes: AlignedBytes,
+}
+
+impl Debug for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reply")
+            .field("error", &self.error)
+            .field("body", &self.body())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Reply {
+    /// The bytes following the header.
+    #[must_use]
+    pub fn body(&self) -> &[u8] {
+        self.bytes
+            .get(mem::size_of::<FuseOutHeader>()..)
+            .unwrap_or_default()
+    }
+
+    /// The bytes of the whole reply.
+    #[must_use]
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Decode the body as a `T`, and `tail` bytes following it.
+    pub fn decode<T: FuseAbiData>(&self, tail: usize) -> anyhow::Result<(&T, &[u8])> {
+        ensure!(self.error == 0, "the request failed with {}", self.error);
+        // The header is 8-byte aligned, so is the body
+        let mut de = Deserializer::new(self.body());
+        let out = de
+            .fetch_ref::<T>()
+            .map_err(|e| anyhow!("failed to decode the reply: {e}"))?;
+        let rest = de.fetch_all_bytes();
+        ensure!(
+            rest.len() == tail,
+            "the reply has {} bytes after the {}, not {tail}",
+            rest.len(),
+            std::any::type_name::<T>(),
+        );
+        Ok((out, rest))
+    }
+}
+
+/// The kernel end of a mock FUSE device.
+#[derive(Debug)]
+pub struct MockKernel {
+    /// The kernel end of the socket pair
+    device: UnixStream,
+    /// The unique ID of the next request
+    next_unique: u64,
+}
+
+impl MockKernel {
+    /// Serve `fs` on a mock FUSE device as a session does, and initialize the
+    /// session by `FUSE_INIT` as the kernel does.
+    pub async fn start(fs: Arc<dyn FileSystem + Send + Sync>) -> anyhow::Result<Self> {
+        let (kernel, _) = Self::start_with(fs, 0, false).await?;
+        Ok(kernel)
+    }
+
+    /// Serve `fs` as `start` does, with the capabilities of `flags` offered by
+    /// the `FUSE_INIT`, and the writeback cache claimed by the session if
+    /// `writeback_cache` is true. Returns the flags claimed by the session as
+    /// well.
+    pub async fn start_with(
+        fs: Arc<dyn FileSystem + Send + Sync>,
+        flags: u32,
+        writeback_cache: bool,
+    ) -> anyhow::Result<(Self, u32)> {
+        let (kernel_end, session_end) = socket::socketpair(
+            AddressFamily::Unix,
+            SockType::SeqPacket,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )
+        .context("failed to create the mock FUSE device")?;
+        let device = UnixStream::from(kernel_end);
+        device.set_read_timeout(Some(REPLY_TIMEOUT))?;
+        let session_end = File::from(session_end);
+        let (pool_sender, pool_receiver) =
+            session::new_buffer_pool(|| Ok(session_end.try_clone()?))?;
+
+        let mut kernel = Self {
+            device,
+            next_unique: 1,
+        };
+        let init = FuseInitIn {
+            major: FUSE_KERNEL_VERSION,
+            minor: FUSE_KERNEL_MINOR_VERSION,
+            max_readahead: MAX_READAHEAD,
+            flags,
+        };
+        let unique = kernel.send(FuseOpCode::FUSE_INIT, 0, &[abi_marker::as_abi_bytes(&init)])?;
+        let proto_version =
+            session::init_from_pool(&pool_sender, &pool_receiver, &*fs, writeback_cache)
+                .await?
+                .ok_or_else(|| anyhow!("the session doesn't read the FUSE_INIT"))?;
+        let reply = kernel.receive(unique).await?;
+        let (out, _) = reply.decode::<FuseInitOut>(0)?;
+        ensure!(
+            out.major == FUSE_KERNEL_VERSION && out.minor == FUSE_KERNEL_MINOR_VERSION,
+            "the session replies version {}.{}",
+            out.major,
+            out.minor,
+        );
+        ensure!(
+            out.flags & !flags == 0,
+            "the session claims the flags not offered"
+        );
+        ensure!(
+            out.max_readahead == MAX_READAHEAD,
+            "the max readahead is changed"
+        );
+        let claimed = out.flags;
+
+        let spawn_handle = TASK_MANAGER
+            .get_gc_handle(TaskName::FuseRequest)
+            .await
+            .unwrap_or_else(|| unreachable!("`FuseRequest` must be GC task."));
+        session::spawn_fuse_readers(
+            &pool_sender,
+            &pool_receiver,
+            &spawn_handle,
+            proto_version,
+            &fs,
+        );
+        Ok((kernel, claimed))
+    }
+
+    /// Send a request of `opcode` on `nodeid` with the arguments `args` as
+    /// the root, returns its unique ID.
+    #[allow(clippy::as_conversions)] // allow this for enum
+    fn send(&mut self, opcode: FuseOpCode, nodeid: u64, args: &[&[u8]]) -> anyhow::Result<u64> {
+        let unique = self.next_unique;
+        self.next_unique = self.next_unique.overflow_add(1);
+        let len = args
+            .iter()
+            .fold(mem::size_of::<FuseInHeader>(), |len, arg| {
+                len.overflow_add(arg.len())
+            });
+        let header = FuseInHeader {
+            len: len.cast(),
+            opcode: opcode as u32,
+            unique,
+            nodeid,
+            uid: 0,
+            gid: 0,
+            pid: std::process::id(),
+            padding: 0,
+        };
+        let mut request = Vec::with_capacity(len);
+        request.extend_from_slice(abi_marker::as_abi_bytes(&header));
+        for arg in args {
+            request.extend_from_slice(arg);
+        }
+        let written = self.device.write(&request)?;
+        ensure!(written == len, "the request is sent partially");
+        Ok(unique)
+    }
+
+    /// Receive the reply to the request `unique`, and check its header.
+    async fn receive(&self, unique: u64) -> anyhow::Result<Reply> {
+        let mut device = self.device.try_clone()?;
+        let bytes = tokio::task::spawn_blocking(move || {
+            let mut buf = vec![0; MAX_REPLY_SIZE];
+            let size = device
+                .read(&mut buf)
+                .context("the request is not replied")?;
+            buf.truncate(size);
+            Ok::<_, anyhow::Error>(buf)
+        })
+        .await??;
+
+        let mut aligned = AlignedBytes::new_zeroed(bytes.len(), mem::align_of::<u64>());
+        aligned.copy_from_slice(&bytes);
+        let (len, error, replied) = {
+            let header = Deserializer::new(&aligned)
+                .fetch_ref::<FuseOutHeader>()
+                .map_err(|e| anyhow!("failed to decode the reply header: {e}"))?;
+            (header.len.cast::<usize>(), header.error, header.unique)
+        };
+        ensure!(
+            len == bytes.len(),
+            "the reply of {} bytes claims {len}",
+            bytes.len()
+        );
+        ensure!(
+            replied == unique,
+            "the reply to {replied} is received for {unique}"
+        );
+        ensure!(error <= 0, "the error {error} is positive");
+        ensure!(
+            error == 0 || len == mem::size_of::<FuseOutHeader>(),
+            "the error reply has a body"
+        );
+        Ok(Reply {
+            error,
+            bytes: aligned,
+        })
+    }
+
+    /// Issue a request of `opcode` on `nodeid` with the arguments `args`, and
+    /// receive its reply.
+    pub async fn request(
+        &mut self,
+        opcode: FuseOpCode,
+        nodeid: u64,
+        args: &[&[u8]],
+    ) -> anyhow::Result<Reply> {
+        let unique = self.send(opcode, nodeid, args)?;
+        self.receive(unique).await
+    }
+
+    /// Issue a request, such as `FUSE_FORGET`, which is never replied.
+    pub fn notify(
+        &mut self,
+        opcode: FuseOpCode,
+        nodeid: u64,
+        args: &[&[u8]],
+    ) -> anyhow::Result<()> {
+        self.send(opcode, nodeid, args).map(|_| ())
+    }
+}
+
+/// The bytes of `arg`, the argument of a request.
+#[must_use]
+pub fn arg_bytes<T: FuseAbiData>(arg: &T) -> &[u8] {
+    abi_marker::as_abi_bytes(arg)
+}
+
+/// The nul-terminated bytes of `name`, the argument of a request.
+#[must_use]
+pub fn name_bytes(name: &str) -> Vec<u8> {
+    let mut bytes = name.as_bytes().to_vec();
+    bytes.push(0);
+    bytes
+}
@@ -12,8 +12,16 @@ pub mod file_system;
 pub mod channel;
 pub mod fuse_reply;
 pub mod fuse_request;
+pub mod fusedump;
+pub mod io_stats;
+#[cfg(test)]
+pub mod mock_kernel;
 pub mod mount;
 // ioctl_read!() macro involves inter arithmetic
 #[allow(clippy::arithmetic_side_effects)]
 pub mod protocol;
+pub mod request_log;
 pub mod session;
+pub mod slow_log;
+#[cfg(target_os = "linux")]
+pub mod virtiofs;
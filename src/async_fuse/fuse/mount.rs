@@ -1,13 +1,24 @@
 //! The implementation of FUSE mount and un-mount
+//!
+//! Linux mounts by mount(2) as root, or by `fusermount` otherwise, macOS by
+//! the mount helper of macFUSE, and FreeBSD by nmount(2), which requires the
+//! sysctl `vfs.usermount` if it's not mounted by root.
 
+#[cfg(target_os = "linux")]
 use std::fs;
 use std::os::unix::io::RawFd;
 use std::path::Path;
 
 use anyhow::Context;
+#[cfg(not(target_os = "macos"))]
 use nix::fcntl::{self, OFlag};
-use nix::sys::stat::{self, Mode};
-use tracing::{debug, info};
+#[cfg(target_os = "linux")]
+use nix::sys::stat;
+#[cfg(not(target_os = "macos"))]
+use nix::sys::stat::Mode;
+use tracing::debug;
+#[cfg(target_os = "linux")]
+use tracing::info;
 
 // Linux mount flags, check the following link for details
 // <https://github.com/torvalds/linux/blob/master/include/uapi/linux/mount.h#L11>
@@ -54,33 +65,54 @@ pub async fn umount(short_path: &Path) -> anyhow::Result<()> {
     .await?
 }
 
-/// Linux mount
+/// Linux mount, the file system is mounted read-only if `read_only` is set,
+/// and all the nodes are labeled by `selinux_context` if it's given, by the
+/// `context=` mount option
 #[cfg(target_os = "linux")]
-pub async fn mount(mount_point: &Path) -> anyhow::Result<RawFd> {
+pub async fn mount(
+    mount_point: &Path,
+    read_only: bool,
+    selinux_context: Option<&str>,
+) -> anyhow::Result<RawFd> {
     use nix::unistd;
 
     if unistd::geteuid().is_root() {
         // Direct umount
-        direct_mount(mount_point).await
+        direct_mount(mount_point, read_only, selinux_context).await
     } else {
         // Use fusermount to mount
-        fuser_mount(mount_point).await
+        fuser_mount(mount_point, read_only, selinux_context).await
     }
 }
 
 /// Linux fusermount
 #[cfg(target_os = "linux")]
-async fn fuser_mount(mount_point: &Path) -> anyhow::Result<RawFd> {
-    use std::io::IoSliceMut;
+async fn fuser_mount(
+    mount_point: &Path,
+    read_only: bool,
+    selinux_context: Option<&str>,
+) -> anyhow::Result<RawFd> {
     use std::os::fd::AsRawFd;
     use std::process::Command;
 
-    use nix::cmsg_space;
-    use nix::sys::socket::{
-        self, AddressFamily, ControlMessageOwned, MsgFlags, SockFlag, SockType,
-    };
+    use nix::sys::socket::{self, AddressFamily, SockFlag, SockType};
 
     let mount_path = mount_point.to_path_buf();
+    // fusermount option allow_other only allowed if user_allow_other is set in
+    // /etc/fuse.conf
+    // rw,async,noatime,noexec,auto_unmount,allow_other
+    let mut options = "nosuid,nodev,allow_other,default_permissions".to_owned();
+    if read_only {
+        options.push_str(",ro");
+    }
+    if let Some(context) = selinux_context {
+        // fusermount splits the options by the commas, even the quoted ones.
+        if context.contains(',') {
+            anyhow::bail!("SELinux context {context} with categories can only be mounted by root");
+        }
+        options.push_str(",context=");
+        options.push_str(context);
+    }
 
     let (local, remote) = tokio::task::spawn_blocking(|| {
         socket::socketpair(
@@ -96,9 +128,7 @@ async fn fuser_mount(mount_point: &Path) -> anyhow::Result<RawFd> {
     let mount_handle = tokio::task::spawn_blocking(move || {
         Command::new("fusermount")
             .arg("-o")
-            // fusermount option allow_other only allowed if user_allow_other is set in
-            // /etc/fuse.conf
-            .arg("nosuid,nodev,allow_other,default_permissions") // rw,async,noatime,noexec,auto_unmount,allow_other
+            .arg(options)
             .arg(mount_path.as_os_str())
             .env("_FUSE_COMMFD", remote.as_raw_fd().to_string())
             .output()
@@ -116,6 +146,19 @@ async fn fuser_mount(mount_point: &Path) -> anyhow::Result<RawFd> {
         mount_point,
     );
 
+    receive_fd(local).await
+}
+
+/// Receive the fd of the FUSE device from the mount helper, `fusermount` or
+/// `mount_macfuse`, through the socket `sock`.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+async fn receive_fd(sock: std::os::fd::OwnedFd) -> anyhow::Result<RawFd> {
+    use std::io::IoSliceMut;
+    use std::os::fd::AsRawFd;
+
+    use nix::cmsg_space;
+    use nix::sys::socket::{self, ControlMessageOwned, MsgFlags};
+
     tokio::task::spawn_blocking(move || {
         let mut buf = [0_u8; 5];
         let mut iov = [IoSliceMut::new(&mut buf[..])];
@@ -123,12 +166,12 @@ async fn fuser_mount(mount_point: &Path) -> anyhow::Result<RawFd> {
         #[allow(clippy::arithmetic_side_effects)]
         let mut cmsgspace = cmsg_space!([RawFd; 1]);
         let msg: socket::RecvMsg<'_, '_, ()> = socket::recvmsg(
-            local.as_raw_fd(),
+            sock.as_raw_fd(),
             &mut iov,
             Some(&mut cmsgspace),
             MsgFlags::empty(),
         )
-        .context("failed to receive from fusermount")?;
+        .context("failed to receive from the mount helper")?;
 
         let mount_fd = if let Some(cmsg) = msg.cmsgs().next() {
             if let ControlMessageOwned::ScmRights(fds) = cmsg {
@@ -149,7 +192,11 @@ async fn fuser_mount(mount_point: &Path) -> anyhow::Result<RawFd> {
 
 /// Linux directly mount
 #[cfg(target_os = "linux")]
-async fn direct_mount(mount_point: &Path) -> anyhow::Result<RawFd> {
+async fn direct_mount(
+    mount_point: &Path,
+    read_only: bool,
+    selinux_context: Option<&str>,
+) -> anyhow::Result<RawFd> {
     use nix::mount::MsFlags;
     use nix::sys::stat::SFlag;
     use nix::unistd;
@@ -178,6 +225,16 @@ async fn direct_mount(mount_point: &Path) -> anyhow::Result<RawFd> {
         unistd::getuid().as_raw(),
         unistd::getgid().as_raw(),
     );
+    // Quoted for the commas of the categories
+    let opts = match selinux_context {
+        Some(context) => format!("{opts},context=\"{context}\""),
+        None => opts,
+    };
+
+    let mut flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV;
+    if read_only {
+        flags |= MsFlags::MS_RDONLY;
+    }
 
     debug!("direct mount opts={:?}", &opts);
     tokio::task::spawn_blocking(move || {
@@ -185,7 +242,7 @@ async fn direct_mount(mount_point: &Path) -> anyhow::Result<RawFd> {
             Some(fsname),
             &target_path,
             Some(fstype),
-            MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            flags,
             Some(opts.as_str()),
         )
     })
@@ -194,3 +251,123 @@ async fn direct_mount(mount_point: &Path) -> anyhow::Result<RawFd> {
 
     Ok(dev_fd)
 }
+
+/// The mount helper of macFUSE
+#[cfg(target_os = "macos")]
+const MACFUSE_MOUNT_HELPER: &str =
+    "/Library/Filesystems/macfuse.fs/Contents/Resources/mount_macfuse";
+
+/// macOS and FreeBSD un-mount
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+pub async fn umount(short_path: &Path) -> anyhow::Result<()> {
+    use nix::mount::MntFlags;
+
+    let mount_path = short_path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        nix::mount::unmount(&mount_path, MntFlags::empty())
+            .context(format!("failed to un-mount FUSE, path={mount_path:?}"))
+    })
+    .await?
+}
+
+/// macOS mount by the mount helper of macFUSE, the file system is mounted
+/// read-only if `read_only` is set, there's no `selinux_context` on macOS
+#[cfg(target_os = "macos")]
+pub async fn mount(
+    mount_point: &Path,
+    read_only: bool,
+    selinux_context: Option<&str>,
+) -> anyhow::Result<RawFd> {
+    use std::os::fd::AsRawFd;
+    use std::process::Command;
+
+    use nix::sys::socket::{self, AddressFamily, SockFlag, SockType};
+
+    if let Some(context) = selinux_context {
+        anyhow::bail!("SELinux context {context} is only supported on Linux");
+    }
+
+    let mount_path = mount_point.to_path_buf();
+    let mut options = "nosuid,nodev,default_permissions,noappledouble".to_owned();
+    if read_only {
+        options.push_str(",rdonly");
+    }
+
+    let (local, remote) = tokio::task::spawn_blocking(|| {
+        socket::socketpair(
+            AddressFamily::Unix,
+            SockType::Stream,
+            None,
+            SockFlag::empty(),
+        )
+    })
+    .await?
+    .context("failed to create socket pair")?;
+
+    // The helper sends the fd of the device before mounting, and the mount
+    // completes after the `FUSE_INIT` request is replied, so the helper is not
+    // waited for here.
+    let mut helper = Command::new(MACFUSE_MOUNT_HELPER)
+        .arg("-o")
+        .arg(options)
+        .arg(mount_path.as_os_str())
+        .env("_FUSE_COMMFD", remote.as_raw_fd().to_string())
+        .env("_FUSE_COMMVERS", "2")
+        .env("_FUSE_CALL_BY_LIB", "1")
+        .spawn()
+        .context("mount_macfuse command failed to start")?;
+    let fd = receive_fd(local).await?;
+    tokio::task::spawn_blocking(move || match helper.wait() {
+        Ok(status) if status.success() => {
+            debug!("mount_macfuse path={mount_path:?} to FUSE device successfully!");
+        }
+        Ok(status) => tracing::error!("mount_macfuse failed to mount {mount_path:?}: {status}"),
+        Err(e) => tracing::error!("failed to wait for mount_macfuse: {e}"),
+    });
+    Ok(fd)
+}
+
+/// FreeBSD mount by nmount(2), the file system is mounted read-only if
+/// `read_only` is set, there's no `selinux_context` on FreeBSD
+#[cfg(target_os = "freebsd")]
+pub async fn mount(
+    mount_point: &Path,
+    read_only: bool,
+    selinux_context: Option<&str>,
+) -> anyhow::Result<RawFd> {
+    use nix::mount::{MntFlags, Nmount};
+
+    if let Some(context) = selinux_context {
+        anyhow::bail!("SELinux context {context} is only supported on Linux");
+    }
+
+    let devpath = Path::new("/dev/fuse");
+    let dev_fd =
+        tokio::task::spawn_blocking(move || fcntl::open(devpath, OFlag::O_RDWR, Mode::empty()))
+            .await?
+            .context("failed to open fuse device")?;
+    let mount_path = mount_point.to_path_buf();
+
+    let mut flags = MntFlags::MNT_NOSUID;
+    if read_only {
+        flags |= MntFlags::MNT_RDONLY;
+    }
+
+    tokio::task::spawn_blocking(move || {
+        let fd = dev_fd.to_string();
+        Nmount::new()
+            .str_opt_owned("fstype", "fusefs")
+            .str_opt_owned("fspath", &mount_path)
+            .str_opt_owned("from", "/dev/fuse")
+            .str_opt_owned("fd", fd.as_str())
+            .null_opt_owned("default_permissions")
+            .null_opt_owned("allow_other")
+            .nmount(flags)
+            .map_err(|e| e.error())
+    })
+    .await?
+    .context(format!("failed to nmount {mount_point:?}"))?;
+    debug!("nmount path={mount_point:?} to FUSE device successfully!");
+
+    Ok(dev_fd)
+}
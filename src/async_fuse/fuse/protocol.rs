@@ -99,12 +99,18 @@ pub struct FuseAttr {
     pub mtime: u64,
     /// Meta-data changed time seconds
     pub ctime: u64,
+    /// Creation time seconds
+    #[cfg(target_os = "macos")]
+    pub crtime: u64,
     /// Access time nano-seconds
     pub atimensec: u32,
     /// Content modified time nano-seconds
     pub mtimensec: u32,
     /// Meta-data changed time nano-seconds
     pub ctimensec: u32,
+    /// Creation time nano-seconds
+    #[cfg(target_os = "macos")]
+    pub crtimensec: u32,
     /// File mode
     pub mode: u32,
     /// Link numbers
@@ -115,6 +121,9 @@ pub struct FuseAttr {
     pub gid: u32,
     /// The device ID that this file (inode) represents if special file
     pub rdev: u32,
+    /// The flags of the file, see chflags(2)
+    #[cfg(target_os = "macos")]
+    pub flags: u32,
     /// Block size
     #[cfg(feature = "abi-7-9")]
     pub blksize: u32,
@@ -341,6 +350,25 @@ pub mod init_flags {
     /// request
     #[cfg(feature = "abi-7-30")]
     pub const FUSE_EXPLICIT_INVAL_DATA: u32 = 1 << 25_i32;
+
+    /// `FUSE_ALLOCATE`: macOS only, the file system supports preallocation
+    #[cfg(target_os = "macos")]
+    pub const FUSE_ALLOCATE: u32 = 1 << 27_i32;
+    /// `FUSE_EXCHANGE_DATA`: macOS only, the file system supports
+    /// exchangedata(2)
+    #[cfg(target_os = "macos")]
+    pub const FUSE_EXCHANGE_DATA: u32 = 1 << 28_i32;
+    /// `FUSE_CASE_INSENSITIVE`: macOS only, the file names are case
+    /// insensitive
+    #[cfg(target_os = "macos")]
+    pub const FUSE_CASE_INSENSITIVE: u32 = 1 << 29_i32;
+    /// `FUSE_VOL_RENAME`: macOS only, the volume can be renamed
+    #[cfg(target_os = "macos")]
+    pub const FUSE_VOL_RENAME: u32 = 1 << 30_i32;
+    /// `FUSE_XTIMES`: macOS only, the file system replies the backup and the
+    /// creation times
+    #[cfg(target_os = "macos")]
+    pub const FUSE_XTIMES: u32 = 1 << 31_i32;
 }
 
 pub use init_flags::*;
@@ -542,6 +570,15 @@ pub enum FuseOpCode {
     /// Copy a range of data from an opened file to another
     // #[cfg(feature = "abi-7-28")]
     FUSE_COPY_FILE_RANGE = 47,
+    /// Rename the volume, macOS only
+    #[cfg(target_os = "macos")]
+    FUSE_SETVOLNAME = 61,
+    /// Get the backup and the creation times, macOS only
+    #[cfg(target_os = "macos")]
+    FUSE_GETXTIMES = 62,
+    /// Exchange the data of two files, macOS only
+    #[cfg(target_os = "macos")]
+    FUSE_EXCHANGE = 63,
     /// CUSE specific operations
     #[cfg(feature = "abi-7-11")]
     CUSE_INIT = 4096,
@@ -957,6 +994,12 @@ pub struct FuseSetXAttrIn {
     pub size: u32,
     /// The flags that specifies the meanings of this operation
     pub flags: u32,
+    /// The offset in the extended attribute value, macOS only
+    #[cfg(target_os = "macos")]
+    pub position: u32,
+    /// Alignment padding
+    #[cfg(target_os = "macos")]
+    pub padding: u32,
 }
 
 /// FUSE get extended attribute request input `fuse_getxattr_in`
@@ -967,6 +1010,25 @@ pub struct FuseGetXAttrIn {
     pub size: u32,
     /// Alignment padding
     pub padding: u32,
+    /// The offset in the extended attribute value, macOS only
+    #[cfg(target_os = "macos")]
+    pub position: u32,
+    /// Alignment padding
+    #[cfg(target_os = "macos")]
+    pub padding2: u32,
+}
+
+/// FUSE exchange request input `fuse_exchange_in`, macOS only
+#[cfg(target_os = "macos")]
+#[derive(Debug)]
+#[repr(C)]
+pub struct FuseExchangeIn {
+    /// The directory of the old file
+    pub olddir: u64,
+    /// The directory of the new file
+    pub newdir: u64,
+    /// The options of exchangedata(2)
+    pub options: u64,
 }
 
 /// FUSE get extended attribute response `fuse_getxattr_out`
@@ -1480,3 +1542,460 @@ pub struct FuseCopyFileRangeIn {
     /// The flags passed along with the `copy_file_range()` syscall
     pub flags: u64,
 }
+
+/// The golden bytes of the replies, under every combination of the ABI
+/// features. Every field of a reply is filled with a distinct byte, so a
+/// field moved, resized or packed differently changes the bytes, which are
+/// the same on any endianness.
+#[cfg(test)]
+mod test {
+    use std::any;
+
+    use super::super::abi_marker::{self, FuseAbiData};
+    use super::*;
+
+    /// The golden bytes of the fields of a struct, a field of `size` bytes
+    /// filled with `byte` is `byte * size`.
+    macro_rules! golden {
+        ($($(#[$attr:meta])* $byte:literal * $size:literal,)*) => {{
+            let mut bytes = Vec::<u8>::new();
+            $(
+                $(#[$attr])*
+                bytes.extend_from_slice(&[$byte; $size]);
+            )*
+            bytes
+        }};
+    }
+
+    /// A `u64` of which every byte is `byte`.
+    const fn u64_of(byte: u8) -> u64 {
+        u64::from_ne_bytes([byte; 8])
+    }
+
+    /// An `i64` of which every byte is `byte`.
+    #[cfg(feature = "abi-7-12")]
+    const fn i64_of(byte: u8) -> i64 {
+        i64::from_ne_bytes([byte; 8])
+    }
+
+    /// A `u32` of which every byte is `byte`.
+    const fn u32_of(byte: u8) -> u32 {
+        u32::from_ne_bytes([byte; 4])
+    }
+
+    /// An `i32` of which every byte is `byte`.
+    const fn i32_of(byte: u8) -> i32 {
+        i32::from_ne_bytes([byte; 4])
+    }
+
+    /// A `u16` of which every byte is `byte`.
+    #[cfg(feature = "abi-7-13")]
+    const fn u16_of(byte: u8) -> u16 {
+        u16::from_ne_bytes([byte; 2])
+    }
+
+    /// Check the bytes replied of `out` are `expected`.
+    fn check<T: FuseAbiData>(out: &T, expected: &[u8]) {
+        assert_eq!(
+            abi_marker::as_abi_bytes(out),
+            expected,
+            "the layout of {} is changed",
+            any::type_name::<T>(),
+        );
+    }
+
+    /// A `FuseAttr` of the bytes `0x11` and up.
+    fn attr() -> FuseAttr {
+        FuseAttr {
+            ino: u64_of(0x11),
+            size: u64_of(0x12),
+            blocks: u64_of(0x13),
+            atime: u64_of(0x14),
+            mtime: u64_of(0x15),
+            ctime: u64_of(0x16),
+            #[cfg(target_os = "macos")]
+            crtime: u64_of(0x17),
+            atimensec: u32_of(0x18),
+            mtimensec: u32_of(0x19),
+            ctimensec: u32_of(0x1a),
+            #[cfg(target_os = "macos")]
+            crtimensec: u32_of(0x1b),
+            mode: u32_of(0x1c),
+            nlink: u32_of(0x1d),
+            uid: u32_of(0x1e),
+            gid: u32_of(0x1f),
+            rdev: u32_of(0x20),
+            #[cfg(target_os = "macos")]
+            flags: u32_of(0x21),
+            #[cfg(feature = "abi-7-9")]
+            blksize: u32_of(0x22),
+            #[cfg(feature = "abi-7-9")]
+            padding: u32_of(0x23),
+        }
+    }
+
+    /// The golden bytes of `attr()`.
+    fn attr_golden() -> Vec<u8> {
+        golden![
+            0x11 * 8, // ino
+            0x12 * 8, // size
+            0x13 * 8, // blocks
+            0x14 * 8, // atime
+            0x15 * 8, // mtime
+            0x16 * 8, // ctime
+            #[cfg(target_os = "macos")]
+            0x17 * 8, // crtime
+            0x18 * 4, // atimensec
+            0x19 * 4, // mtimensec
+            0x1a * 4, // ctimensec
+            #[cfg(target_os = "macos")]
+            0x1b * 4, // crtimensec
+            0x1c * 4, // mode
+            0x1d * 4, // nlink
+            0x1e * 4, // uid
+            0x1f * 4, // gid
+            0x20 * 4, // rdev
+            #[cfg(target_os = "macos")]
+            0x21 * 4, // flags
+            #[cfg(feature = "abi-7-9")]
+            0x22 * 4, // blksize
+            #[cfg(feature = "abi-7-9")]
+            0x23 * 4, // padding
+        ]
+    }
+
+    /// A `FuseEntryOut` of the bytes `0x01` and up.
+    fn entry_out() -> FuseEntryOut {
+        FuseEntryOut {
+            nodeid: u64_of(1),
+            generation: u64_of(2),
+            entry_valid: u64_of(3),
+            attr_valid: u64_of(4),
+            entry_valid_nsec: u32_of(5),
+            attr_valid_nsec: u32_of(6),
+            attr: attr(),
+        }
+    }
+
+    /// The golden bytes of `entry_out()`.
+    fn entry_out_golden() -> Vec<u8> {
+        let head = golden![
+            1 * 8, // nodeid
+            2 * 8, // generation
+            3 * 8, // entry_valid
+            4 * 8, // attr_valid
+            5 * 4, // entry_valid_nsec
+            6 * 4, // attr_valid_nsec
+        ];
+        [head, attr_golden()].concat()
+    }
+
+    /// A `FuseDirEnt` of the bytes `0x31` and up.
+    fn dirent() -> FuseDirEnt {
+        FuseDirEnt {
+            ino: u64_of(0x31),
+            off: u64_of(0x32),
+            namelen: u32_of(0x33),
+            typ: u32_of(0x34),
+        }
+    }
+
+    /// The golden bytes of `dirent()`.
+    fn dirent_golden() -> Vec<u8> {
+        golden![
+            0x31 * 8, // ino
+            0x32 * 8, // off
+            0x33 * 4, // namelen
+            0x34 * 4, // typ
+        ]
+    }
+
+    #[test]
+    fn test_out_header() {
+        let out = FuseOutHeader {
+            len: u32_of(1),
+            error: i32_of(2),
+            unique: u64_of(3),
+        };
+        check(&out, &golden![1 * 4, 2 * 4, 3 * 8,]);
+    }
+
+    #[test]
+    fn test_attr() {
+        // `fuse_attr` is 80 bytes before 7.9, and 88 bytes since then
+        #[cfg(all(not(target_os = "macos"), not(feature = "abi-7-9")))]
+        assert_eq!(attr_golden().len(), 80);
+        #[cfg(all(not(target_os = "macos"), feature = "abi-7-9"))]
+        assert_eq!(attr_golden().len(), 88);
+        check(&attr(), &attr_golden());
+    }
+
+    #[test]
+    fn test_entry_out() {
+        check(&entry_out(), &entry_out_golden());
+    }
+
+    #[test]
+    fn test_attr_out() {
+        let out = FuseAttrOut {
+            attr_valid: u64_of(1),
+            attr_valid_nsec: u32_of(2),
+            dummy: u32_of(3),
+            attr: attr(),
+        };
+        let head = golden![
+            1 * 8, // attr_valid
+            2 * 4, // attr_valid_nsec
+            3 * 4, // dummy
+        ];
+        check(&out, &[head, attr_golden()].concat());
+    }
+
+    #[test]
+    fn test_open_out() {
+        let out = FuseOpenOut {
+            fh: u64_of(1),
+            open_flags: u32_of(2),
+            padding: u32_of(3),
+        };
+        check(&out, &golden![1 * 8, 2 * 4, 3 * 4,]);
+    }
+
+    #[test]
+    fn test_write_out() {
+        let out = FuseWriteOut {
+            size: u32_of(1),
+            padding: u32_of(2),
+        };
+        check(&out, &golden![1 * 4, 2 * 4,]);
+    }
+
+    #[test]
+    fn test_statfs_out() {
+        let out = FuseStatFsOut {
+            st: FuseKStatFs {
+                blocks: u64_of(1),
+                bfree: u64_of(2),
+                bavail: u64_of(3),
+                files: u64_of(4),
+                ffree: u64_of(5),
+                bsize: u32_of(6),
+                namelen: u32_of(7),
+                frsize: u32_of(8),
+                padding: u32_of(9),
+                spare: [u32_of(10); 6],
+            },
+        };
+        let expected = golden![
+            1 * 8,   // blocks
+            2 * 8,   // bfree
+            3 * 8,   // bavail
+            4 * 8,   // files
+            5 * 8,   // ffree
+            6 * 4,   // bsize
+            7 * 4,   // namelen
+            8 * 4,   // frsize
+            9 * 4,   // padding
+            10 * 24, // spare
+        ];
+        check(&out, &expected);
+    }
+
+    #[test]
+    fn test_getxattr_out() {
+        let out = FuseGetXAttrOut {
+            size: u32_of(1),
+            padding: u32_of(2),
+        };
+        check(&out, &golden![1 * 4, 2 * 4,]);
+    }
+
+    #[test]
+    fn test_lock_out() {
+        let out = FuseLockOut {
+            lk: FuseFileLock {
+                start: u64_of(1),
+                end: u64_of(2),
+                typ: u32_of(3),
+                pid: u32_of(4),
+            },
+        };
+        check(&out, &golden![1 * 8, 2 * 8, 3 * 4, 4 * 4,]);
+    }
+
+    #[test]
+    fn test_init_out() {
+        let out = FuseInitOut {
+            major: u32_of(1),
+            minor: u32_of(2),
+            max_readahead: u32_of(3),
+            flags: u32_of(4),
+            #[cfg(not(feature = "abi-7-13"))]
+            unused: u32_of(5),
+            #[cfg(feature = "abi-7-13")]
+            max_background: u16_of(6),
+            #[cfg(feature = "abi-7-13")]
+            congestion_threshold: u16_of(7),
+            max_write: u32_of(8),
+            #[cfg(feature = "abi-7-23")]
+            time_gran: u32_of(9),
+            #[cfg(all(feature = "abi-7-23", not(feature = "abi-7-28")))]
+            unused: [u32_of(10); 9],
+            #[cfg(feature = "abi-7-28")]
+            max_pages: u16_of(11),
+            #[cfg(feature = "abi-7-28")]
+            padding: u16_of(12),
+            #[cfg(feature = "abi-7-28")]
+            unused: [u32_of(13); 8],
+        };
+        let expected = golden![
+            1 * 4, // major
+            2 * 4, // minor
+            3 * 4, // max_readahead
+            4 * 4, // flags
+            #[cfg(not(feature = "abi-7-13"))]
+            5 * 4, // unused
+            #[cfg(feature = "abi-7-13")]
+            6 * 2, // max_background
+            #[cfg(feature = "abi-7-13")]
+            7 * 2, // congestion_threshold
+            8 * 4, // max_write
+            #[cfg(feature = "abi-7-23")]
+            9 * 4, // time_gran
+            #[cfg(all(feature = "abi-7-23", not(feature = "abi-7-28")))]
+            10 * 36, // unused
+            #[cfg(feature = "abi-7-28")]
+            11 * 2, // max_pages
+            #[cfg(feature = "abi-7-28")]
+            12 * 2, // padding
+            #[cfg(feature = "abi-7-28")]
+            13 * 32, // unused
+        ];
+        // `fuse_init_out` is 24 bytes before 7.23, and 64 bytes since then
+        #[cfg(not(feature = "abi-7-23"))]
+        assert_eq!(expected.len(), 24);
+        #[cfg(feature = "abi-7-23")]
+        assert_eq!(expected.len(), 64);
+        check(&out, &expected);
+    }
+
+    #[test]
+    fn test_bmap_out() {
+        check(&FuseBMapOut { block: u64_of(1) }, &golden![1 * 8,]);
+    }
+
+    #[test]
+    fn test_lseek_out() {
+        check(&FuseLSeekOut { offset: u64_of(1) }, &golden![1 * 8,]);
+    }
+
+    #[test]
+    fn test_dirent() {
+        check(&dirent(), &dirent_golden());
+    }
+
+    #[cfg(feature = "abi-7-11")]
+    #[test]
+    fn test_abi_7_11_out() {
+        let out = FuseIoCtlOut {
+            result: i32_of(1),
+            flags: u32_of(2),
+            in_iovs: u32_of(3),
+            out_iovs: u32_of(4),
+        };
+        check(&out, &golden![1 * 4, 2 * 4, 3 * 4, 4 * 4,]);
+
+        let out = FusePollOut {
+            revents: u32_of(1),
+            padding: u32_of(2),
+        };
+        check(&out, &golden![1 * 4, 2 * 4,]);
+
+        check(&FuseNotifyPollWakeUpOut { kh: u64_of(1) }, &golden![1 * 8,]);
+
+        let out = CuseInitOut {
+            major: u32_of(1),
+            minor: u32_of(2),
+            unused: u32_of(3),
+            flags: u32_of(4),
+            max_read: u32_of(5),
+            max_write: u32_of(6),
+            dev_major: u32_of(7),
+            dev_minor: u32_of(8),
+            spare: [u32_of(9); 10],
+        };
+        let expected = golden![
+            1 * 4,  // major
+            2 * 4,  // minor
+            3 * 4,  // unused
+            4 * 4,  // flags
+            5 * 4,  // max_read
+            6 * 4,  // max_write
+            7 * 4,  // dev_major
+            8 * 4,  // dev_minor
+            9 * 40, // spare
+        ];
+        check(&out, &expected);
+    }
+
+    #[cfg(feature = "abi-7-12")]
+    #[test]
+    fn test_abi_7_12_out() {
+        let out = FuseNotifyInvalINodeOut {
+            ino: u64_of(1),
+            off: i64_of(2),
+            len: i64_of(3),
+        };
+        check(&out, &golden![1 * 8, 2 * 8, 3 * 8,]);
+
+        let out = FuseNotifyInvalEntryOut {
+            parent: u64_of(1),
+            namelen: u32_of(2),
+            padding: u32_of(3),
+        };
+        check(&out, &golden![1 * 8, 2 * 4, 3 * 4,]);
+    }
+
+    #[cfg(feature = "abi-7-15")]
+    #[test]
+    fn test_abi_7_15_out() {
+        let out = FuseNotifyStoreOut {
+            nodeid: u64_of(1),
+            offset: u64_of(2),
+            size: u32_of(3),
+            padding: u32_of(4),
+        };
+        check(&out, &golden![1 * 8, 2 * 8, 3 * 4, 4 * 4,]);
+
+        let out = FuseNotifyRetrieveOut {
+            notify_unique: u64_of(1),
+            nodeid: u64_of(2),
+            offset: u64_of(3),
+            size: u32_of(4),
+            padding: u32_of(5),
+        };
+        check(&out, &golden![1 * 8, 2 * 8, 3 * 8, 4 * 4, 5 * 4,]);
+    }
+
+    #[cfg(feature = "abi-7-18")]
+    #[test]
+    fn test_abi_7_18_out() {
+        let out = FuseNotifyDeleteOut {
+            parent: u64_of(1),
+            child: u64_of(2),
+            namelen: u32_of(3),
+            padding: u32_of(4),
+        };
+        check(&out, &golden![1 * 8, 2 * 8, 3 * 4, 4 * 4,]);
+    }
+
+    #[cfg(feature = "abi-7-21")]
+    #[test]
+    fn test_abi_7_21_out() {
+        let out = FuseDirEntPlus {
+            entry_out: entry_out(),
+            dirent: dirent(),
+        };
+        check(&out, &[entry_out_golden(), dirent_golden()].concat());
+    }
+}
@@ -0,0 +1,126 @@
+//! The structured log of the FUSE requests.
+//!
+//! Every request is served in a span carrying the volume, the opcode, the
+//! unique id and the i-number of it, so the records logged while it's served
+//! are correlated by these fields, which are the fields of the JSON records
+//! given `--log-format json`. A request is logged once it's replied, with the
+//! bytes replied and its latency, while only one in `--log-sample-rate`
+//! requests of the frequent operations, such as `READ` and `WRITE`, is, so a
+//! busy mount doesn't flood the log.
+
+use std::num::NonZeroU64;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use once_cell::sync::{Lazy, OnceCell};
+use tracing::{info, info_span, Span};
+
+use super::fuse_request::{Operation, Request};
+use super::io_stats::op_name;
+
+/// The log of the FUSE requests of this process.
+pub static REQUEST_LOG: Lazy<RequestLog> = Lazy::new(RequestLog::default);
+
+/// The log of the FUSE requests.
+#[derive(Debug)]
+pub struct RequestLog {
+    /// The volume served, by its mount point
+    volume: OnceCell<String>,
+    /// One in how many requests of the frequent operations is logged
+    sample_rate: AtomicU64,
+    /// The requests of the frequent operations served
+    frequent: AtomicU64,
+}
+
+impl Default for RequestLog {
+    fn default() -> Self {
+        Self {
+            volume: OnceCell::new(),
+            sample_rate: AtomicU64::new(1),
+            frequent: AtomicU64::new(0),
+        }
+    }
+}
+
+impl RequestLog {
+    /// Set the volume served to `volume`, which is set once.
+    pub fn set_volume(&self, volume: &str) {
+        if self.volume.set(volume.to_owned()).is_err() {
+            info!("The volume of the request log is set already, {volume} is ignored.");
+        }
+    }
+
+    /// Log one in `rate` requests of the frequent operations.
+    pub fn set_sample_rate(&self, rate: NonZeroU64) {
+        self.sample_rate.store(rate.get(), Ordering::Relaxed);
+    }
+
+    /// The span `req` is served in.
+    pub fn span(&self, req: &Request<'_>) -> Span {
+        info_span!(
+            "request",
+            volume = self.volume.get().map_or("", String::as_str),
+            opcode = %op_name(req.operation()),
+            unique = req.unique(),
+            ino = req.nodeid(),
+        )
+    }
+
+    /// Log `req` served in `span` replied by `replied` bytes in `latency`, if
+    /// it's sampled.
+    pub fn replied(&self, span: &Span, req: &Request<'_>, replied: usize, latency: Duration) {
+        if self.is_sampled(req.operation()) {
+            let latency_us: u64 = latency.as_micros().try_into().unwrap_or(u64::MAX);
+            info!(parent: span, replied, latency_us, "request replied");
+        }
+    }
+
+    /// Whether a request of `op` is logged once it's replied.
+    fn is_sampled(&self, op: &Operation<'_>) -> bool {
+        if !is_frequent(op) {
+            return true;
+        }
+        let rate = self.sample_rate.load(Ordering::Relaxed);
+        self.frequent
+            .fetch_add(1, Ordering::Relaxed)
+            .wrapping_rem(rate)
+            == 0
+    }
+}
+
+/// Whether `op` is one of the operations issued the most, whose requests are
+/// sampled.
+#[allow(clippy::wildcard_enum_match_arm)]
+fn is_frequent(op: &Operation<'_>) -> bool {
+    match *op {
+        Operation::Lookup { .. }
+        | Operation::Forget { .. }
+        | Operation::GetAttr
+        | Operation::Read { .. }
+        | Operation::Write { .. } => true,
+        #[cfg(feature = "abi-7-16")]
+        Operation::BatchForget { .. } => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::num::NonZeroU64;
+
+    use super::super::fuse_request::Operation;
+    use super::RequestLog;
+
+    #[test]
+    fn test_sampling() {
+        let log = RequestLog::default();
+        log.set_sample_rate(NonZeroU64::new(3).unwrap());
+        let sampled = (0..9_i32)
+            .filter(|_| log.is_sampled(&Operation::GetAttr))
+            .count();
+        assert_eq!(sampled, 3);
+        // The other operations are always logged
+        assert!((0..3_i32).all(|_| log.is_sampled(&Operation::StatFs)));
+    }
+}
@@ -11,61 +11,83 @@ use std::time::{Duration, UNIX_EPOCH};
 
 use aligned_utils::bytes::AlignedBytes;
 use anyhow::{anyhow, Context};
-use clippy_utilities::Cast;
+use clippy_utilities::{Cast, OverflowArithmetic};
 use crossbeam_channel::{Receiver, Sender};
 use crossbeam_utils::atomic::AtomicCell;
+use datenlord::common::memory_budget::MemoryReservation;
 use datenlord::common::task_manager::{GcHandle, TaskName, TASK_MANAGER};
 use nix::errno::Errno;
 use nix::sys::stat::SFlag;
 use nix::unistd;
 use tokio::runtime::Handle;
+use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, error, info, instrument};
+use tracing::{debug, error, info, Instrument};
 
 use super::context::ProtoVersion;
 use super::file_system::FileSystem;
 use super::fuse_reply::{
     ReplyAttr, ReplyBMap, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry,
-    ReplyInit, ReplyLock, ReplyOpen, ReplyStatFs, ReplyWrite, ReplyXAttr,
+    ReplyInit, ReplyLSeek, ReplyLock, ReplyOpen, ReplySink, ReplyStatFs, ReplyWrite, ReplyXAttr,
 };
 use super::fuse_request::{Operation, Request};
+use super::fusedump::{RecordKind, TracedSink, FUSE_TRACE};
+use super::io_stats::IO_STATS;
 use super::mount;
 #[cfg(feature = "abi-7-23")]
 use super::protocol::FATTR_CTIME;
 #[cfg(feature = "abi-7-9")]
 use super::protocol::FATTR_LOCKOWNER; // {FATTR_ATIME_NOW, FATTR_MTIME_NOW};
+#[cfg(not(target_os = "macos"))]
+use super::protocol::FUSE_POSIX_LOCKS;
+#[cfg(feature = "abi-7-23")]
+use super::protocol::FUSE_WRITEBACK_CACHE;
 use super::protocol::{
     FuseInitIn, FuseInitOut, FuseSetXAttrIn, FATTR_ATIME, FATTR_FH, FATTR_GID, FATTR_MODE,
     FATTR_MTIME, FATTR_SIZE, FATTR_UID, FUSE_ASYNC_READ, FUSE_KERNEL_MINOR_VERSION,
     FUSE_KERNEL_VERSION, FUSE_RELEASE_FLUSH,
 };
+#[cfg(feature = "abi-7-26")]
+use super::protocol::{FUSE_DONT_MASK, FUSE_POSIX_ACL};
+use super::request_log::REQUEST_LOG;
+use super::slow_log::{self, SLOW_LOG};
 use crate::async_fuse::fuse::de::DeserializeError;
 use crate::async_fuse::memfs::{
-    CreateParam, FileLockParam, MemFs, MetaData, RenameParam, SetAttrParam,
+    CopyRangeParam, CreateParam, FileLockParam, MemFs, MetaData, RenameParam, SetAttrParam,
 };
+use crate::storage::transfer::deadline;
 
-/// We generally support async reads
-#[cfg(target_os = "linux")]
+/// We generally support async reads, and POSIX locks shared among the nodes
+#[cfg(not(target_os = "macos"))]
+const INIT_FLAGS: u32 = FUSE_ASYNC_READ | FUSE_POSIX_LOCKS;
+/// macFUSE keeps the POSIX locks in the kernel, and the capabilities of macOS,
+/// such as `FUSE_XTIMES`, are not claimed, so their requests are not sent.
+#[cfg(target_os = "macos")]
 const INIT_FLAGS: u32 = FUSE_ASYNC_READ;
 // TODO: Add FUSE_EXPORT_SUPPORT and FUSE_BIG_WRITES (requires ABI 7.10)
 
 /// The max size of write requests from the kernel. The absolute minimum is 4k,
 /// FUSE recommends at least 128k, max 16M. The FUSE default is  128k on Linux.
-#[cfg(target_os = "linux")]
 const MAX_WRITE_SIZE: u32 = 128 * 1024;
 
+// macFUSE speaks the OSXFUSE protocol, whose newest version is 7.19.
+#[cfg(all(target_os = "macos", feature = "abi-7-20"))]
+compile_error!("macFUSE supports the FUSE ABI up to 7.19, disable the features abi-7-20 and above");
+
 /// Size of the buffer for reading a request from the kernel. Since the kernel
 /// may send up to `MAX_WRITE_SIZE` bytes in a write request, we use that value
 /// plus some extra space.
 const BUFFER_SIZE: u32 = MAX_WRITE_SIZE + 512;
 
 /// We use `PAGE_SIZE` (4 KiB) as the alignment of the buffer.
-const PAGE_SIZE: usize = 4096;
+pub(super) const PAGE_SIZE: usize = 4096;
 /// Max background pending requests under processing, at least to be 4,
 /// otherwise deadlock
 const MAX_BACKGROUND: u16 = 10; // TODO: set to larger value when release
 /// The max number of FUSE device reader threads.
 const MAX_FUSE_READER: usize = 2; // TODO: make it custom
+/// The deadline of the transfers between nodes issued by a FUSE request.
+pub(super) const FUSE_REQUEST_DEADLINE: Duration = Duration::from_secs(60);
 
 /// The implementation of fuse fd clone.
 /// This module is just for avoiding the `missing_docs` of `ioctl_read` macro.
@@ -73,10 +95,16 @@ const MAX_FUSE_READER: usize = 2; // TODO: make it custom
 mod _fuse_fd_clone {
     use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
 
+    #[cfg(target_os = "linux")]
     use clippy_utilities::Cast;
-    use nix::fcntl::{self, FcntlArg, FdFlag, OFlag};
+    #[cfg(target_os = "linux")]
+    use nix::fcntl::OFlag;
+    use nix::fcntl::{self, FcntlArg, FdFlag};
+    #[cfg(target_os = "linux")]
     use nix::ioctl_read;
+    #[cfg(target_os = "linux")]
     use nix::sys::stat::Mode;
+    #[cfg(target_os = "linux")]
     ioctl_read!(fuse_fd_clone_impl, 229, 0, u32);
 
     /// Clones a FUSE session fd into a FUSE worker fd.
@@ -85,6 +113,7 @@ mod _fuse_fd_clone {
     /// Behavior is undefined if any of the following conditions are violated:
     ///
     /// - `session_fd` must be a valid file descriptor to an open FUSE device.
+    #[cfg(target_os = "linux")]
     #[allow(clippy::unnecessary_safety_comment)]
     pub unsafe fn fuse_fd_clone(session_fd: RawFd) -> nix::Result<RawFd> {
         let devname = "/dev/fuse";
@@ -102,6 +131,24 @@ mod _fuse_fd_clone {
         Ok(cloned_fd.into_raw_fd()) // use `into_raw_fd` to transfer the
                                     // ownership of the fd
     }
+
+    /// Clones a FUSE session fd into a FUSE worker fd. FreeBSD and macFUSE
+    /// cannot clone a session, the requests are read from the duplicates of
+    /// its fd instead.
+    ///
+    /// # Safety
+    /// Behavior is undefined if any of the following conditions are violated:
+    ///
+    /// - `session_fd` must be a valid file descriptor to an open FUSE device.
+    #[cfg(not(target_os = "linux"))]
+    #[allow(clippy::unnecessary_safety_comment)]
+    pub unsafe fn fuse_fd_clone(session_fd: RawFd) -> nix::Result<RawFd> {
+        let cloned_fd = nix::unistd::dup(session_fd)?;
+        // SAFETY: the `cloned_fd` is just duplicated
+        let cloned_fd = OwnedFd::from_raw_fd(cloned_fd);
+        fcntl::fcntl(cloned_fd.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        Ok(cloned_fd.into_raw_fd())
+    }
 }
 
 use _fuse_fd_clone::fuse_fd_clone;
@@ -123,6 +170,12 @@ fn fuse_device_reader(
         };
 
         let size = match file.read(&mut buffer) {
+            // The FUSE device never reads empty, the other end of a mock
+            // device is closed
+            Ok(0) => {
+                info!("the FUSE device is closed, quit the run loop");
+                return;
+            }
             Ok(size) => size,
             Err(e) => {
                 let errno = e.raw_os_error().map(Errno::from_raw);
@@ -169,11 +222,13 @@ fn fuse_device_reader(
                 }
             }
         };
+        let received = Instant::now();
 
         let spawn_result = runtime_handle.block_on(fuse_request_spawn_handle.spawn(|_| {
             process_fuse_request(
                 buffer,
                 size,
+                received,
                 file,
                 Arc::clone(&fs),
                 buffer_tx.clone(),
@@ -187,10 +242,11 @@ fn fuse_device_reader(
     }
 }
 
-/// Process one FUSE request
+/// Process one FUSE request read from the device at `received`
 async fn process_fuse_request(
     byte_buffer: AlignedBytes,
     read_size: usize,
+    received: Instant,
     mut file: File,
     fs: Arc<dyn FileSystem + Send + Sync + 'static>,
     sender: Sender<(File, AlignedBytes)>,
@@ -199,6 +255,7 @@ async fn process_fuse_request(
     let bytes = byte_buffer
         .get(..read_size)
         .unwrap_or_else(|| panic!("failed to read {read_size} bytes from the buffer",));
+    FUSE_TRACE.record(RecordKind::Request, &[bytes]);
     let fuse_req = match Request::new(bytes, proto_version) {
         // Dispatch request
         Ok(r) => r,
@@ -209,7 +266,7 @@ async fn process_fuse_request(
                 let unique = unique.unwrap_or_else(|| {
                     unreachable!("A `unique` must be filled in by deserializer.")
                 });
-                ReplyEmpty::new(unique, &mut file)
+                ReplyEmpty::new(unique, &mut TracedSink::new(&mut file))
                     .error_code(Errno::ENOSYS)
                     .await
                     .unwrap_or_else(|reply_err| {
@@ -225,8 +282,22 @@ async fn process_fuse_request(
             panic!("failed to build FUSE request, the error is: {e}");
         }
     };
-    debug!("received FUSE req={}", fuse_req);
-    let res = dispatch(&fuse_req, &mut file, fs).await;
+    let span = REQUEST_LOG.span(&fuse_req);
+    debug!(parent: &span, len = fuse_req.len(), "request received");
+    let start = Instant::now();
+    let deadline = start + FUSE_REQUEST_DEADLINE;
+    let (res, decisions) = slow_log::scope(deadline::scope(
+        deadline,
+        dispatch(&fuse_req, &mut TracedSink::new(&mut file), fs).instrument(span.clone()),
+    ))
+    .await;
+    if let Ok(replied) = res {
+        let latency = start.elapsed();
+        IO_STATS.record(&fuse_req, replied, latency);
+        REQUEST_LOG.replied(&span, &fuse_req, replied, latency);
+        let queue_wait = start.saturating_duration_since(received);
+        SLOW_LOG.record(&fuse_req, queue_wait, latency, decisions);
+    }
     if let Err(e) = res {
         panic!(
             "failed to process req={:?}, the error is: {}",
@@ -249,10 +320,15 @@ pub struct Session<F: FileSystem + Send + Sync + 'static> {
     proto_version: AtomicCell<ProtoVersion>,
     /// Mount path (relative)
     mount_path: PathBuf,
+    /// Whether the writeback cache of the kernel is claimed
+    writeback_cache: bool,
     /// The underlying FUSE file system
     filesystem: Arc<F>,
     /// A handle to spawn FUSE Resuest tasks
     fuse_request_spawn_handle: GcHandle,
+    /// The memory of the request buffers reserved from the budget of the
+    /// file system, the payloads of in-flight requests are kept in them
+    _buffer_reservation: Option<MemoryReservation>,
 }
 
 /// FUSE device fd
@@ -281,11 +357,16 @@ impl<F: FileSystem + Send + Sync + 'static> Drop for Session<F> {
     }
 }
 
-/// Create FUSE session
+/// Create FUSE session, the writes are cached by the kernel and written back
+/// later if `writeback_cache` is true. All the nodes are labeled by
+/// `selinux_context` if it's given, see [`mount::mount`].
 #[allow(clippy::clone_on_ref_ptr)] // allow this clone to transform trait to sub-trait
 pub async fn new_session_of_memfs<M>(
     mount_path: &Path,
     fs: MemFs<M>,
+    read_only: bool,
+    selinux_context: Option<&str>,
+    writeback_cache: bool,
 ) -> anyhow::Result<Session<MemFs<M>>>
 where
     M: MetaData + Send + Sync + 'static,
@@ -297,22 +378,37 @@ where
     );
 
     // Must create filesystem before mount
-    let fuse_fd = mount::mount(mount_path)
+    let fuse_fd = mount::mount(mount_path, read_only, selinux_context)
         .await
         .context("failed to mount fuse device")?;
 
+    let notifier = unsafe {
+        // SAFETY: The fuse session fd is just mounted.
+        let notifier_fd = fuse_fd_clone(fuse_fd)?;
+        // SAFETY: The notifier fd is just cloned.
+        File::from_raw_fd(notifier_fd)
+    };
+    fs.set_notifier(notifier).await;
+
     let fuse_request_spawn_handle = TASK_MANAGER
         .get_gc_handle(TaskName::FuseRequest)
         .await
         .unwrap_or_else(|| unreachable!("`FuseRequest` must be GC task."));
 
+    let buffers_size = usize::from(MAX_BACKGROUND).overflow_mul(BUFFER_SIZE.cast());
+    let buffer_reservation = fs
+        .memory_budget()
+        .map(|budget| budget.reserve(buffers_size));
+
     let fsarc = Arc::new(fs);
     Ok(Session {
         fuse_fd: Arc::new(FuseFd(fuse_fd)),
         proto_version: AtomicCell::new(ProtoVersion::UNSPECIFIED),
         mount_path: mount_path.to_owned(),
+        writeback_cache,
         fuse_request_spawn_handle,
         filesystem: fsarc,
+        _buffer_reservation: buffer_reservation,
     })
 }
 
@@ -332,18 +428,14 @@ impl<F: FileSystem + Send + Sync + 'static> Session<F> {
             .await
             .context("failed to setup buffer pool")?;
 
-        for _ in 0..MAX_FUSE_READER {
-            let pool_tx = pool_sender.clone();
-            let pool_rx = pool_receiver.clone();
-            let gc_handle = self.fuse_request_spawn_handle.clone();
-            let handle = Handle::current();
-            let fs = Arc::clone(&self.filesystem);
-            let protocol_version = self.proto_version.load();
-            // The `JoinHandle` is ignored
-            thread::spawn(move || {
-                fuse_device_reader(pool_tx, pool_rx, gc_handle, handle, protocol_version, fs);
-            });
-        }
+        let fs: Arc<dyn FileSystem + Send + Sync> = Arc::<F>::clone(&self.filesystem);
+        spawn_fuse_readers(
+            &pool_sender,
+            &pool_receiver,
+            &self.fuse_request_spawn_handle,
+            self.proto_version.load(),
+            &fs,
+        );
 
         drop(pool_receiver);
 
@@ -357,157 +449,250 @@ impl<F: FileSystem + Send + Sync + 'static> Session<F> {
     async fn setup_buffer_pool(
         &self,
     ) -> anyhow::Result<(Sender<(File, AlignedBytes)>, Receiver<(File, AlignedBytes)>)> {
-        let (pool_sender, pool_receiver) =
-            crossbeam_channel::bounded::<(File, AlignedBytes)>(MAX_BACKGROUND.into());
-
-        for _ in 0..MAX_BACKGROUND {
-            let buf = AlignedBytes::new_zeroed(BUFFER_SIZE.cast(), PAGE_SIZE);
-            let session_fd = self.dev_fd();
-
+        let session_fd = self.dev_fd();
+        let (pool_sender, pool_receiver) = new_buffer_pool(|| {
             let file = unsafe {
                 // SAFETY: We assume that the fuse session fd is valid.
                 let worker_fd = fuse_fd_clone(session_fd)?;
                 // SAFETY: The worker fd is just cloned.
                 File::from_raw_fd(worker_fd)
             };
+            Ok(file)
+        })?;
 
-            let res = pool_sender.send((file, buf));
-            if let Err(e) = res {
-                panic!(
-                    "failed to insert buffer to buffer pool when initializing, the error is: {e}",
-                );
-            }
+        if let Some(proto_version) = init_from_pool(
+            &pool_sender,
+            &pool_receiver,
+            &*self.filesystem,
+            self.writeback_cache,
+        )
+        .await?
+        {
+            // Store the kernel FUSE major and minor version
+            self.proto_version.store(proto_version);
         }
 
-        let (mut file, mut byte_buf) = pool_receiver.recv()?;
-        let (read_result, mut file, byte_buf) = tokio::task::spawn_blocking(move || {
-            let res = file.read(&mut byte_buf);
-            (res, file, byte_buf)
-        })
-        .await?;
-        if let Ok(read_size) = read_result {
-            debug!("read successfully {} byte data from FUSE device", read_size);
-            let bytes = byte_buf.get(..read_size).unwrap_or_else(|| {
-                panic!(
-                    "read_size is greater than buffer size: read_size = {}, buffer size = {}",
-                    read_size,
-                    byte_buf.len()
-                )
-            });
-            if let Ok(req) = Request::new(bytes, self.proto_version.load()) {
-                if let Operation::Init { arg } = *req.operation() {
-                    let filesystem = Arc::clone(&self.filesystem);
-                    self.init(arg, &req, &*filesystem, &mut file).await?;
-                }
-            }
+        Ok((pool_sender, pool_receiver))
+    }
+}
+
+/// Create the buffer pool of a session, every buffer is paired with a clone
+/// of the FUSE device made by `clone_device`.
+pub(super) fn new_buffer_pool(
+    mut clone_device: impl FnMut() -> anyhow::Result<File>,
+) -> anyhow::Result<(Sender<(File, AlignedBytes)>, Receiver<(File, AlignedBytes)>)> {
+    let (pool_sender, pool_receiver) =
+        crossbeam_channel::bounded::<(File, AlignedBytes)>(MAX_BACKGROUND.into());
+
+    for _ in 0..MAX_BACKGROUND {
+        let buf = AlignedBytes::new_zeroed(BUFFER_SIZE.cast(), PAGE_SIZE);
+        let file = clone_device()?;
+
+        let res = pool_sender.send((file, buf));
+        if let Err(e) = res {
+            panic!("failed to insert buffer to buffer pool when initializing, the error is: {e}",);
         }
-        pool_sender
-            .send((file, byte_buf))
-            .context("failed to put buffer back to buffer pool after FUSE init")?;
+    }
 
-        Ok((pool_sender, pool_receiver))
+    Ok((pool_sender, pool_receiver))
+}
+
+/// Read the `FUSE_INIT` request by a buffer of the pool and reply to it,
+/// returns the FUSE protocol version of the kernel, or `None` if the request
+/// read is not a `FUSE_INIT`.
+pub(super) async fn init_from_pool(
+    pool_sender: &Sender<(File, AlignedBytes)>,
+    pool_receiver: &Receiver<(File, AlignedBytes)>,
+    fs: &(dyn FileSystem + Send + Sync + 'static),
+    writeback_cache: bool,
+) -> anyhow::Result<Option<ProtoVersion>> {
+    let mut proto_version = None;
+    let (mut file, mut byte_buf) = pool_receiver.recv()?;
+    let (read_result, mut file, byte_buf) = tokio::task::spawn_blocking(move || {
+        let res = file.read(&mut byte_buf);
+        (res, file, byte_buf)
+    })
+    .await?;
+    if let Ok(read_size) = read_result {
+        debug!("read successfully {} byte data from FUSE device", read_size);
+        let bytes = byte_buf.get(..read_size).unwrap_or_else(|| {
+            panic!(
+                "read_size is greater than buffer size: read_size = {}, buffer size = {}",
+                read_size,
+                byte_buf.len()
+            )
+        });
+        FUSE_TRACE.record(RecordKind::Request, &[bytes]);
+        if let Ok(req) = Request::new(bytes, ProtoVersion::UNSPECIFIED) {
+            if let Operation::Init { arg } = *req.operation() {
+                let sink = &mut TracedSink::new(&mut file);
+                proto_version = Some(init_session(arg, &req, fs, sink, writeback_cache).await?);
+            }
+        }
     }
+    pool_sender
+        .send((file, byte_buf))
+        .context("failed to put buffer back to buffer pool after FUSE init")?;
 
-    /// Initialize FUSE session
-    #[allow(single_use_lifetimes)] // false positive
-    async fn init<'a>(
-        &self,
-        arg: &'_ FuseInitIn,
-        req: &'_ Request<'a>,
-        fs: &'_ (dyn FileSystem + Send + Sync + 'static),
-        file: &mut File,
-    ) -> anyhow::Result<()> {
-        debug!("Init args={:?}", arg);
-        // TODO: rewrite init based on do_init() in fuse_lowlevel.c
-        // https://github.com/libfuse/libfuse/blob/master/lib/fuse_lowlevel.c#L1892
-        let reply = ReplyInit::new(req.unique(), file);
-        // We don't support ABI versions before 7.8
-        if arg.major < 7 || (arg.major == 7 && arg.minor < 8) {
-            error!("Unsupported FUSE ABI version={}.{}", arg.major, arg.minor);
-            reply.error_code(Errno::EPROTO).await?;
-            return Err(anyhow!("FUSE ABI version too low"));
-        }
-        // Call filesystem init method and give it a chance to return an error
-        let filesystem = fs;
-        let init_res = filesystem.init(req).await;
-        if let Err(err) = init_res {
-            reply.error_code(Errno::ENOSYS).await?;
-            return Err(anyhow!("user defined init failed, the error is: {}", err,));
-        }
-        let flags = arg.flags & INIT_FLAGS; // TODO: handle init flags properly
-        #[cfg(not(feature = "abi-7-13"))]
-        let unused = 0_u32;
-        #[cfg(feature = "abi-7-13")]
-        let congestion_threshold = 10_u16; // TODO: set congestion threshold
-        #[cfg(feature = "abi-7-23")]
-        let time_gran = 1_u32; // TODO: set time_gran
-        #[cfg(all(feature = "abi-7-23", not(feature = "abi-7-28")))]
-        let unused = [0_u32; 9];
-        #[cfg(feature = "abi-7-28")]
-        let max_pages = 0_u16; // TODO: max_pages = (max_write - 1) / getpagesize() + 1;
-        #[cfg(feature = "abi-7-28")]
-        let padding = 0_u16;
-        #[cfg(feature = "abi-7-28")]
-        let unused = [0_u32; 8];
-        // Reply with our desired version and settings. If the kernel supports a
-        // larger major version, it'll re-send a matching init message. If it
-        // supports only lower major versions, we replied with an error above.
-        reply
-            .init(FuseInitOut {
-                major: FUSE_KERNEL_VERSION,
-                minor: FUSE_KERNEL_MINOR_VERSION, /* Do not change minor version, otherwise
-                                                   * unknown panic */
-                max_readahead: arg.max_readahead, // accept FUSE kernel module max_readahead
-                flags,                            /* TODO: use features given in INIT_FLAGS and
-                                                   * reported as capable */
-                #[cfg(not(feature = "abi-7-13"))]
-                unused,
-                #[cfg(feature = "abi-7-13")]
-                max_background: MAX_BACKGROUND,
-                #[cfg(feature = "abi-7-13")]
-                congestion_threshold,
-                max_write: MAX_WRITE_SIZE,
-                #[cfg(feature = "abi-7-23")]
-                time_gran,
-                #[cfg(all(feature = "abi-7-23", not(feature = "abi-7-28")))]
-                unused,
-                #[cfg(feature = "abi-7-28")]
-                max_pages,
-                #[cfg(feature = "abi-7-28")]
-                padding,
-                #[cfg(feature = "abi-7-28")]
-                unused,
-            })
-            .await?;
-        debug!(
-            "INIT response: ABI version={}.{}, flags={:#x}, max readahead={}, max write={}",
-            FUSE_KERNEL_VERSION,
-            FUSE_KERNEL_MINOR_VERSION,
-            flags,
-            arg.max_readahead,
-            MAX_WRITE_SIZE,
-        );
+    Ok(proto_version)
+}
 
-        // Store the kernel FUSE major and minor version
-        self.proto_version.store(ProtoVersion {
-            major: arg.major,
-            minor: arg.minor,
+/// Spawn the threads reading the requests from the FUSE device by the buffers
+/// of the pool, the requests are processed by the tasks spawned by
+/// `spawn_handle`.
+pub(super) fn spawn_fuse_readers(
+    pool_sender: &Sender<(File, AlignedBytes)>,
+    pool_receiver: &Receiver<(File, AlignedBytes)>,
+    spawn_handle: &GcHandle,
+    proto_version: ProtoVersion,
+    fs: &Arc<dyn FileSystem + Send + Sync>,
+) {
+    for _ in 0..MAX_FUSE_READER {
+        let pool_tx = pool_sender.clone();
+        let pool_rx = pool_receiver.clone();
+        let gc_handle = spawn_handle.clone();
+        let handle = Handle::current();
+        let fs = Arc::clone(fs);
+        // The `JoinHandle` is ignored
+        thread::spawn(move || {
+            fuse_device_reader(pool_tx, pool_rx, gc_handle, handle, proto_version, fs);
         });
+    }
+}
 
-        Ok(())
+/// The capabilities claimed by the reply to a `FUSE_INIT` offering `offered`,
+/// the writeback cache is claimed as well if `writeback_cache` is true.
+fn init_flags(offered: u32, writeback_cache: bool) -> u32 {
+    let mut flags = INIT_FLAGS;
+    // The dirty pages of the kernel, written by buffered writes or by shared
+    // mappings, are written back before the flush of a close, the fsync of
+    // an `msync()`, and the invalidation of a recalled lease.
+    #[cfg(feature = "abi-7-23")]
+    if writeback_cache {
+        flags |= FUSE_WRITEBACK_CACHE;
+    }
+    #[cfg(not(feature = "abi-7-23"))]
+    if writeback_cache {
+        tracing::warn!("The writeback cache requires ABI 7.23, it's not claimed.");
+    }
+    // The kernel checks the permissions against the POSIX ACLs, which are
+    // kept as extended attributes, and leaves the umask to the file system,
+    // as it's not applied under a directory with a default ACL.
+    #[cfg(feature = "abi-7-26")]
+    {
+        flags |= FUSE_POSIX_ACL | FUSE_DONT_MASK;
     }
+    offered & flags // TODO: handle init flags properly
+}
+
+/// Reply to the `FUSE_INIT` request of the kernel, returns the FUSE protocol
+/// version of the kernel. The writeback cache is claimed as well if
+/// `writeback_cache` is true.
+#[allow(single_use_lifetimes)] // false positive
+pub(super) async fn init_session<'a>(
+    arg: &'_ FuseInitIn,
+    req: &'_ Request<'a>,
+    fs: &'_ (dyn FileSystem + Send + Sync + 'static),
+    sink: &mut dyn ReplySink,
+    writeback_cache: bool,
+) -> anyhow::Result<ProtoVersion> {
+    debug!("Init args={:?}", arg);
+    // TODO: rewrite init based on do_init() in fuse_lowlevel.c
+    // https://github.com/libfuse/libfuse/blob/master/lib/fuse_lowlevel.c#L1892
+    let reply = ReplyInit::new(req.unique(), sink);
+    // We don't support ABI versions before 7.8
+    if arg.major < 7 || (arg.major == 7 && arg.minor < 8) {
+        error!("Unsupported FUSE ABI version={}.{}", arg.major, arg.minor);
+        reply.error_code(Errno::EPROTO).await?;
+        return Err(anyhow!("FUSE ABI version too low"));
+    }
+    // Call filesystem init method and give it a chance to return an error
+    let filesystem = fs;
+    let init_res = filesystem.init(req).await;
+    if let Err(err) = init_res {
+        reply.error_code(Errno::ENOSYS).await?;
+        return Err(anyhow!("user defined init failed, the error is: {}", err,));
+    }
+    let flags = init_flags(arg.flags, writeback_cache);
+    #[cfg(not(feature = "abi-7-13"))]
+    let unused = 0_u32;
+    #[cfg(feature = "abi-7-13")]
+    let congestion_threshold = 10_u16; // TODO: set congestion threshold
+    #[cfg(feature = "abi-7-23")]
+    let time_gran = 1_u32; // TODO: set time_gran
+    #[cfg(all(feature = "abi-7-23", not(feature = "abi-7-28")))]
+    let unused = [0_u32; 9];
+    #[cfg(feature = "abi-7-28")]
+    let max_pages = 0_u16; // TODO: max_pages = (max_write - 1) / getpagesize() + 1;
+    #[cfg(feature = "abi-7-28")]
+    let padding = 0_u16;
+    #[cfg(feature = "abi-7-28")]
+    let unused = [0_u32; 8];
+    // macFUSE takes the minor version replied as the one of the session
+    // without negotiating it, so the version of the kernel is replied if it's
+    // older.
+    #[cfg(target_os = "macos")]
+    let minor = arg.minor.min(FUSE_KERNEL_MINOR_VERSION);
+    #[cfg(not(target_os = "macos"))]
+    let minor = FUSE_KERNEL_MINOR_VERSION;
+    // Reply with our desired version and settings. If the kernel supports a
+    // larger major version, it'll re-send a matching init message. If it
+    // supports only lower major versions, we replied with an error above.
+    reply
+        .init(FuseInitOut {
+            major: FUSE_KERNEL_VERSION,
+            // Do not change minor version on Linux, otherwise unknown panic
+            minor,
+            max_readahead: arg.max_readahead, // accept FUSE kernel module max_readahead
+            flags,                            /* TODO: use features given in INIT_FLAGS and
+                                               * reported as capable */
+            #[cfg(not(feature = "abi-7-13"))]
+            unused,
+            #[cfg(feature = "abi-7-13")]
+            max_background: MAX_BACKGROUND,
+            #[cfg(feature = "abi-7-13")]
+            congestion_threshold,
+            max_write: MAX_WRITE_SIZE,
+            #[cfg(feature = "abi-7-23")]
+            time_gran,
+            #[cfg(all(feature = "abi-7-23", not(feature = "abi-7-28")))]
+            unused,
+            #[cfg(feature = "abi-7-28")]
+            max_pages,
+            #[cfg(feature = "abi-7-28")]
+            padding,
+            #[cfg(feature = "abi-7-28")]
+            unused,
+        })
+        .await?;
+    debug!(
+        "INIT response: ABI version={}.{}, flags={:#x}, max readahead={}, max write={}",
+        FUSE_KERNEL_VERSION, minor, flags, arg.max_readahead, MAX_WRITE_SIZE,
+    );
+
+    Ok(ProtoVersion {
+        major: arg.major,
+        minor: arg.minor,
+    })
 }
 
 /// Dispatch request to the filesystem
 /// This calls the appropriate filesystem operation method for the
 /// request and sends back the returned reply to the kernel
+///
+/// The request is logged in the span of `REQUEST_LOG` entered by the caller.
 #[allow(clippy::too_many_lines)]
-#[instrument(name="request",skip(req, file, fs), fields(fuse_id =req.unique(),ino=req.nodeid(), len=req.len()),ret)]
-async fn dispatch<'a>(
+pub(super) async fn dispatch<'a>(
     req: &'a Request<'a>,
-    file: &mut File,
+    file: &mut dyn ReplySink,
     fs: Arc<dyn FileSystem + Send + Sync + 'static>,
 ) -> nix::Result<usize> {
+    if let Err(e) = fs.authorize(req).await {
+        debug!("request is not authorized: {e}");
+        let reply = ReplyEmpty::new(req.unique(), file);
+        return reply.error(e).await;
+    }
+
     let result = match *req.operation() {
         // Filesystem initialization
         Operation::Init { .. } => panic!("FUSE should have already initialized"),
@@ -623,6 +808,10 @@ async fn dispatch<'a>(
                 rdev: arg.rdev,
                 uid: req.uid(),
                 gid: req.gid(),
+                #[cfg(feature = "abi-7-12")]
+                umask: arg.umask,
+                #[cfg(not(feature = "abi-7-12"))]
+                umask: 0,
                 node_type: SFlag::S_IFREG,
                 link: None,
             };
@@ -630,8 +819,13 @@ async fn dispatch<'a>(
             fs.mknod(req, param, reply).await
         }
         Operation::MkDir { arg, name } => {
+            #[cfg(feature = "abi-7-12")]
+            let umask = arg.umask;
+            #[cfg(not(feature = "abi-7-12"))]
+            let umask = 0;
             let reply = ReplyEntry::new(req.unique(), file);
-            fs.mkdir(req, req.nodeid(), name, arg.mode, reply).await
+            fs.mkdir(req, req.nodeid(), name, arg.mode, umask, reply)
+                .await
         }
         Operation::Unlink { name } => {
             let reply = ReplyEmpty::new(req.unique(), file);
@@ -675,18 +869,11 @@ async fn dispatch<'a>(
                 .await
         }
         Operation::Write { arg, data } => {
-            info!("operation:write: {:?}", arg);
+            debug!(fh = arg.fh, offset = arg.offset, size = arg.size, "write");
             assert_eq!(data.len(), arg.size.cast::<usize>());
             let reply = ReplyWrite::new(req.unique(), file);
-            fs.write(
-                req,
-                arg.fh,
-                arg.offset.cast(),
-                data.to_vec(), // TODO: consider zero copy
-                arg.write_flags,
-                reply,
-            )
-            .await
+            fs.write(req, arg.fh, arg.offset.cast(), data, arg.write_flags, reply)
+                .await
         }
         Operation::Flush { arg } => {
             let reply = ReplyEmpty::new(req.unique(), file);
@@ -726,12 +913,19 @@ async fn dispatch<'a>(
         }
         Operation::SetXAttr { arg, name, value } => {
             /// Set the position of an extended attribute
-            /// zero for Linux
-            #[cfg(target_os = "linux")]
+            /// zero for Linux and FreeBSD
+            #[cfg(not(target_os = "macos"))]
             #[inline]
             const fn get_position(_arg: &FuseSetXAttrIn) -> u32 {
                 0
             }
+            /// Set the position of an extended attribute, which is the
+            /// offset of the resource fork on macOS
+            #[cfg(target_os = "macos")]
+            #[inline]
+            const fn get_position(arg: &FuseSetXAttrIn) -> u32 {
+                arg.position
+            }
             assert!(value.len() == arg.size.cast::<usize>());
             let reply = ReplyEmpty::new(req.unique(), file);
             fs.setxattr(req, name, value, arg.flags, get_position(arg), reply)
@@ -805,6 +999,9 @@ async fn dispatch<'a>(
 
         #[cfg(feature = "abi-7-11")]
         Operation::IoCtl { arg, data } => {
+            // `FICLONE` and `FICLONERANGE` never reach here, as the kernel
+            // rejects them for FUSE, then `cp` falls back to
+            // `copy_file_range(2)`, which clones the blocks.
             error!("IoCtl not implemented, arg={:?}, data={:?}", arg, data);
             not_implement_helper(req, file).await
         }
@@ -819,17 +1016,15 @@ async fn dispatch<'a>(
             not_implement_helper(req, file).await
         }
         #[cfg(feature = "abi-7-16")]
-        Operation::BatchForget { arg, nodes } => {
-            error!(
-                "BatchForget not implemented, arg={:?}, nodes={:?}",
-                arg, nodes
-            );
-            not_implement_helper(req, file).await
+        Operation::BatchForget { nodes, .. } => {
+            fs.batch_forget(req, nodes).await; // No reply
+            Ok(0)
         }
         #[cfg(feature = "abi-7-19")]
         Operation::FAllocate { arg } => {
-            error!("FAllocate not implemented, arg={:?}", arg);
-            not_implement_helper(req, file).await
+            let reply = ReplyEmpty::new(req.unique(), file);
+            fs.fallocate(req, arg.fh, arg.offset, arg.length, arg.mode, reply)
+                .await
         }
         #[cfg(feature = "abi-7-21")]
         Operation::ReadDirPlus { arg } => {
@@ -854,12 +1049,26 @@ async fn dispatch<'a>(
         }
         // #[cfg(feature = "abi-7-24")]
         Operation::LSeek { arg } => {
-            error!("LSeek not implemented, arg={:?}", arg);
-            not_implement_helper(req, file).await
+            let reply = ReplyLSeek::new(req.unique(), file);
+            fs.lseek(req, arg.fh, arg.offset, arg.whence, reply).await
         }
         // #[cfg(feature = "abi-7-28")]
         Operation::CopyFileRange { arg } => {
-            error!("ReadDirPlusCopyFileRange not implemented, arg={:?}", arg);
+            let reply = ReplyWrite::new(req.unique(), file);
+            let param = CopyRangeParam {
+                fh_in: arg.fh_in,
+                offset_in: arg.off_in,
+                ino_out: arg.nodeid_out,
+                fh_out: arg.fh_out,
+                offset_out: arg.off_out,
+                len: arg.len,
+                flags: arg.flags,
+            };
+            fs.copy_file_range(req, param, reply).await
+        }
+        // The capabilities of these requests are not claimed by `INIT_FLAGS`.
+        #[cfg(target_os = "macos")]
+        Operation::SetVolName { .. } | Operation::GetXTimes | Operation::Exchange { .. } => {
             not_implement_helper(req, file).await
         }
         #[cfg(feature = "abi-7-11")]
@@ -872,7 +1081,30 @@ async fn dispatch<'a>(
 }
 
 /// Replies ENOSYS
-async fn not_implement_helper(req: &Request<'_>, file: &mut File) -> nix::Result<usize> {
+async fn not_implement_helper(req: &Request<'_>, file: &mut dyn ReplySink) -> nix::Result<usize> {
     let reply = ReplyEmpty::new(req.unique(), file);
     reply.error_code(Errno::ENOSYS).await
 }
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "abi-7-23")]
+    use super::FUSE_WRITEBACK_CACHE;
+    use super::{init_flags, INIT_FLAGS};
+
+    #[test]
+    fn test_init_flags() {
+        // Nothing is claimed beyond what the kernel offers.
+        assert_eq!(init_flags(0, true), 0);
+        assert_eq!(init_flags(INIT_FLAGS, false), INIT_FLAGS);
+
+        // The writeback cache is claimed only if it's offered and enabled.
+        #[cfg(feature = "abi-7-23")]
+        {
+            let offered = INIT_FLAGS | FUSE_WRITEBACK_CACHE;
+            assert_eq!(init_flags(INIT_FLAGS, true), INIT_FLAGS);
+            assert_eq!(init_flags(offered, false), INIT_FLAGS);
+            assert_eq!(init_flags(offered, true), offered);
+        }
+    }
+}
@@ -0,0 +1,323 @@
+//! The log of the slow FUSE requests.
+//!
+//! A request served longer than `--slow-log-threshold` is kept with its
+//! operation and the decoded arguments of it, the time it waits to be served
+//! after it's read from the device and the time it's served in, and the
+//! decisions the caches and the backend take while serving it, such as a
+//! block missed in the memory cache and read from the backend. The last
+//! `--slow-log-capacity` ones are kept in a ring buffer, which is dumped on
+//! the control socket by the slowlog role, see `control`.
+//!
+//! The decisions are noted in the task serving a request, so the ones taken
+//! by the background tasks, such as writing back the blocks, are not.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::fuse_request::Request;
+use super::io_stats::op_name;
+use super::protocol::INum;
+
+/// The most decisions kept for a request.
+const MAX_DECISIONS: usize = 64;
+
+/// The slow requests of this process.
+pub static SLOW_LOG: Lazy<SlowLog> = Lazy::new(SlowLog::default);
+
+tokio::task_local! {
+    /// The decisions taken by the request of the current task
+    static DECISIONS: RefCell<Decisions>;
+}
+
+/// A decision of the caches or the backend taken to serve a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Decision {
+    /// The block is read from the memory cache
+    CacheHit {
+        /// The i-number of the file
+        ino: INum,
+        /// The index of the block
+        block: usize,
+    },
+    /// The block is not in the memory cache, and is read from the backend
+    CacheMiss {
+        /// The i-number of the file
+        ino: INum,
+        /// The index of the block
+        block: usize,
+    },
+    /// The part of the block cached is completed by the backend
+    BlockCompleted {
+        /// The i-number of the file
+        ino: INum,
+        /// The index of the block
+        block: usize,
+    },
+    /// The block written is stored to the backend before the reply
+    WriteThrough {
+        /// The i-number of the file
+        ino: INum,
+        /// The index of the block
+        block: usize,
+    },
+    /// The block written is queued to be written back
+    WriteBack {
+        /// The i-number of the file
+        ino: INum,
+        /// The index of the block
+        block: usize,
+    },
+    /// The block is evicted from the memory cache to the backend
+    Eviction {
+        /// The i-number of the file
+        ino: INum,
+        /// The index of the block
+        block: usize,
+    },
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::CacheHit { ino, block } => write!(f, "cache hit of block {ino}:{block}"),
+            Self::CacheMiss { ino, block } => {
+                write!(
+                    f,
+                    "cache miss of block {ino}:{block}, read from the backend"
+                )
+            }
+            Self::BlockCompleted { ino, block } => {
+                write!(f, "partial block {ino}:{block} completed from the backend")
+            }
+            Self::WriteThrough { ino, block } => {
+                write!(f, "block {ino}:{block} written through to the backend")
+            }
+            Self::WriteBack { ino, block } => {
+                write!(f, "block {ino}:{block} queued to be written back")
+            }
+            Self::Eviction { ino, block } => {
+                write!(f, "block {ino}:{block} evicted to the backend")
+            }
+        }
+    }
+}
+
+/// The decisions taken by a request.
+#[derive(Debug, Default)]
+pub struct Decisions {
+    /// The decisions kept, the first `MAX_DECISIONS` ones
+    taken: Vec<Decision>,
+    /// The decisions not kept
+    dropped: usize,
+}
+
+/// Run `fut` serving a request, returns its output and the decisions noted
+/// by it.
+pub async fn scope<F: Future>(fut: F) -> (F::Output, Decisions) {
+    DECISIONS
+        .scope(RefCell::default(), async move {
+            let output = fut.await;
+            (output, DECISIONS.with(RefCell::take))
+        })
+        .await
+}
+
+/// Note `decision` taken by the request of the current task, it's ignored if
+/// the task is not serving a request.
+pub fn note(decision: Decision) {
+    // Not in the scope of a request.
+    let _: Result<(), _> = DECISIONS.try_with(|decisions| {
+        let mut decisions = decisions.borrow_mut();
+        if decisions.taken.len() < MAX_DECISIONS {
+            decisions.taken.push(decision);
+        } else {
+            decisions.dropped = decisions.dropped.saturating_add(1);
+        }
+    });
+}
+
+/// A request served longer than the threshold.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlowRequest {
+    /// When the request is read from the device
+    pub received: SystemTime,
+    /// The unique id of the request
+    pub unique: u64,
+    /// The i-number the request is on
+    pub ino: INum,
+    /// The process issuing the request
+    pub pid: u32,
+    /// The operation with its decoded arguments, such as
+    /// `READ fh=1, offset=0, size=4096, ...`
+    pub operation: String,
+    /// The time the request waits to be served after it's read
+    pub queue_wait: Duration,
+    /// The time the request is served in
+    pub service: Duration,
+    /// The decisions taken to serve the request
+    pub decisions: Vec<Decision>,
+    /// The decisions taken beyond the ones kept
+    pub decisions_dropped: usize,
+}
+
+/// The ring buffer of the slow requests.
+#[derive(Debug)]
+pub struct SlowLog {
+    /// The latency a request is kept beyond in microseconds, 0 if none is
+    threshold_us: AtomicU64,
+    /// The most requests kept
+    capacity: AtomicUsize,
+    /// The requests kept, the latest last
+    requests: Mutex<VecDeque<SlowRequest>>,
+}
+
+impl Default for SlowLog {
+    fn default() -> Self {
+        Self {
+            threshold_us: AtomicU64::new(0),
+            capacity: AtomicUsize::new(128),
+            requests: Mutex::new(VecDeque::new()),
+        }
+    }
+}
+
+impl SlowLog {
+    /// Keep the requests served longer than `threshold`, none is kept if it's
+    /// `None`.
+    pub fn set_threshold(&self, threshold: Option<Duration>) {
+        let threshold_us = threshold.map_or(0, |threshold| {
+            threshold.as_micros().try_into().unwrap_or(u64::MAX).max(1)
+        });
+        self.threshold_us.store(threshold_us, Ordering::Relaxed);
+    }
+
+    /// Keep the last `capacity` slow requests.
+    pub fn set_capacity(&self, capacity: usize) {
+        self.capacity.store(capacity, Ordering::Relaxed);
+        let mut requests = self.requests.lock();
+        while requests.len() > capacity {
+            requests.pop_front();
+        }
+    }
+
+    /// Keep `req` if it's slow, which waits `queue_wait` to be served and is
+    /// served in `service` by `decisions`.
+    pub fn record(
+        &self,
+        req: &Request<'_>,
+        queue_wait: Duration,
+        service: Duration,
+        decisions: Decisions,
+    ) {
+        let latency = queue_wait.saturating_add(service);
+        if !self.is_slow(latency) {
+            return;
+        }
+
+        let latency_us: u64 = latency.as_micros().try_into().unwrap_or(u64::MAX);
+        warn!(
+            unique = req.unique(),
+            opcode = %op_name(req.operation()),
+            ino = req.nodeid(),
+            latency_us,
+            "slow request"
+        );
+        self.keep(SlowRequest {
+            received: SystemTime::now()
+                .checked_sub(latency)
+                .unwrap_or(SystemTime::UNIX_EPOCH),
+            unique: req.unique(),
+            ino: req.nodeid(),
+            pid: req.pid(),
+            operation: req.operation().to_string(),
+            queue_wait,
+            service,
+            decisions: decisions.taken,
+            decisions_dropped: decisions.dropped,
+        });
+    }
+
+    /// Whether a request served in `latency` is slow.
+    fn is_slow(&self, latency: Duration) -> bool {
+        let threshold_us = self.threshold_us.load(Ordering::Relaxed);
+        threshold_us != 0 && latency >= Duration::from_micros(threshold_us)
+    }
+
+    /// Keep `request`, the earliest one is dropped if it's full.
+    fn keep(&self, request: SlowRequest) {
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        let mut requests = self.requests.lock();
+        requests.push_back(request);
+        while requests.len() > capacity {
+            requests.pop_front();
+        }
+    }
+
+    /// The slow requests kept, the earliest first.
+    pub fn dump(&self) -> Vec<SlowRequest> {
+        self.requests.lock().iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use clippy_utilities::OverflowArithmetic;
+
+    use super::{note, scope, Decision, SlowLog, SlowRequest, MAX_DECISIONS};
+
+    #[tokio::test]
+    async fn test_decisions() {
+        let ((), decisions) = scope(async {
+            for block in 0..MAX_DECISIONS.overflow_add(2) {
+                note(Decision::CacheHit { ino: 2, block });
+            }
+        })
+        .await;
+        assert_eq!(decisions.taken.len(), MAX_DECISIONS);
+        assert_eq!(decisions.dropped, 2);
+        // Not noted out of the scope of a request
+        note(Decision::CacheMiss { ino: 2, block: 0 });
+    }
+
+    #[test]
+    fn test_ring_buffer() {
+        let log = SlowLog::default();
+        assert!(!log.is_slow(Duration::from_secs(60)));
+        log.set_threshold(Some(Duration::from_millis(10)));
+        assert!(!log.is_slow(Duration::from_millis(9)));
+        assert!(log.is_slow(Duration::from_millis(10)));
+
+        let slow = |unique: u64| SlowRequest {
+            received: SystemTime::UNIX_EPOCH,
+            unique,
+            ino: 2,
+            pid: 42,
+            operation: "GETATTR".to_owned(),
+            queue_wait: Duration::from_millis(4),
+            service: Duration::from_millis(8),
+            decisions: vec![Decision::CacheMiss { ino: 2, block: 0 }],
+            decisions_dropped: 0,
+        };
+        for unique in 1..=4 {
+            log.keep(slow(unique));
+        }
+        log.set_capacity(2);
+        let uniques: Vec<u64> = log.dump().iter().map(|request| request.unique).collect();
+        assert_eq!(uniques, [3, 4]);
+        log.keep(slow(5));
+        let uniques: Vec<u64> = log.dump().iter().map(|request| request.unique).collect();
+        assert_eq!(uniques, [4, 5]);
+    }
+}
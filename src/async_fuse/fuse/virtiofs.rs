@@ -0,0 +1,288 @@
+//! The vhost-user-fs transport, which serves the file system to a VM as a
+//! virtio-fs device, the guest mounts it by its own virtio-fs driver without
+//! a FUSE daemon in the guest.
+//!
+//! The VMM, such as QEMU or the one of Kata Containers, connects to the
+//! vhost-user socket and shares the memory of the guest. The requests in the
+//! virtqueues are the FUSE requests of the guest kernel, they are decoded,
+//! dispatched and replied by the same handlers as the ones read from the FUSE
+//! device. The first queue is the high priority one, where the guest sends
+//! `FORGET` and `INTERRUPT`, the others carry the rest of the requests.
+//!
+//! Neither the DAX window nor the notification queue is supported, so the
+//! guest is not notified of the changes made by the other nodes, the
+//! attributes it caches expire by their TTL.
+
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use aligned_utils::bytes::AlignedBytes;
+use anyhow::{anyhow, Context};
+use clippy_utilities::Cast;
+use crossbeam_utils::atomic::AtomicCell;
+use nix::errno::Errno;
+use parking_lot::RwLock;
+use tokio::runtime::Handle;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, Instrument};
+use vhost::vhost_user::message::{VhostUserProtocolFeatures, VhostUserVirtioFeatures};
+use vhost::vhost_user::Listener;
+use vhost_user_backend::{VhostUserBackend, VhostUserDaemon, VringMutex, VringT};
+use virtio_bindings::virtio_config::VIRTIO_F_VERSION_1;
+use virtio_bindings::virtio_ring::VIRTIO_RING_F_INDIRECT_DESC;
+use virtio_queue::QueueT;
+use vm_memory::{GuestAddressSpace, GuestMemoryAtomic, GuestMemoryMmap};
+use vmm_sys_util::epoll::EventSet;
+use vmm_sys_util::eventfd::{EventFd, EFD_NONBLOCK};
+
+use super::context::ProtoVersion;
+use super::de::DeserializeError;
+use super::file_system::FileSystem;
+use super::fuse_reply::ReplyEmpty;
+use super::fuse_request::{Operation, Request};
+use super::request_log::REQUEST_LOG;
+use super::session::{self, FUSE_REQUEST_DEADLINE, PAGE_SIZE};
+use super::slow_log::{self, SLOW_LOG};
+use crate::storage::transfer::deadline;
+
+/// The number of the request queues, besides the high priority queue.
+const NUM_REQUEST_QUEUES: usize = 1;
+/// The max number of the descriptors of a virtqueue.
+const QUEUE_SIZE: usize = 1024;
+
+/// The backend of the virtio-fs device, it's driven by the threads of
+/// `VhostUserDaemon`.
+struct VirtioFsBackend {
+    /// The underlying FUSE file system
+    fs: Arc<dyn FileSystem + Send + Sync>,
+    /// The runtime the requests are processed in
+    runtime: Handle,
+    /// The FUSE protocol version of the guest kernel
+    proto_version: AtomicCell<ProtoVersion>,
+    /// The memory of the guest, set by the VMM
+    mem: RwLock<Option<GuestMemoryAtomic<GuestMemoryMmap>>>,
+    /// Stops the threads processing the virtqueues
+    exit_event: EventFd,
+}
+
+impl VirtioFsBackend {
+    /// Create `VirtioFsBackend`
+    fn new(fs: Arc<dyn FileSystem + Send + Sync>, runtime: Handle) -> io::Result<Self> {
+        Ok(Self {
+            fs,
+            runtime,
+            proto_version: AtomicCell::new(ProtoVersion::UNSPECIFIED),
+            mem: RwLock::new(None),
+            exit_event: EventFd::new(EFD_NONBLOCK)?,
+        })
+    }
+
+    /// Process the requests available in `vring`, and notify the guest of
+    /// the ones done.
+    fn process_queue(&self, vring: &VringMutex) -> io::Result<()> {
+        let mem = self
+            .mem
+            .read()
+            .as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no guest memory is set"))?
+            .memory();
+        let mut used = false;
+        loop {
+            let chain = vring
+                .get_mut()
+                .get_queue_mut()
+                .pop_descriptor_chain(mem.clone());
+            let Some(chain) = chain else {
+                break;
+            };
+            let head_index = chain.head_index();
+            let mut reader = chain
+                .clone()
+                .reader(&mem)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let mut writer = chain
+                .writer(&mem)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+            // The request is decoded from an aligned buffer as the one read
+            // from the FUSE device.
+            let mut buffer = AlignedBytes::new_zeroed(reader.available_bytes(), PAGE_SIZE);
+            reader.read_exact(&mut buffer)?;
+            let reply = self.runtime.block_on(self.process_request(&buffer));
+            writer.write_all(&reply)?;
+
+            vring
+                .add_used(head_index, writer.bytes_written().cast())
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            used = true;
+        }
+        if used {
+            vring.signal_used_queue()?;
+        }
+        Ok(())
+    }
+
+    /// Process one FUSE request, returns the reply, which is empty if the
+    /// request is not replied.
+    async fn process_request(&self, bytes: &[u8]) -> Vec<u8> {
+        let mut reply = Vec::new();
+        let req = match Request::new(bytes, self.proto_version.load()) {
+            Ok(req) => req,
+            Err(DeserializeError::UnknownOpCode { code, unique }) => {
+                error!("Unknown operation code found: {code}");
+                let unique = unique.unwrap_or_else(|| {
+                    unreachable!("A `unique` must be filled in by deserializer.")
+                });
+                if let Err(e) = ReplyEmpty::new(unique, &mut reply)
+                    .error_code(Errno::ENOSYS)
+                    .await
+                {
+                    error!("Failed to reply an error code: {e}.");
+                }
+                return reply;
+            }
+            Err(e) => {
+                // The guest is waiting for the reply, which cannot be built
+                // without the `unique` of the request.
+                error!("failed to build FUSE request, the error is: {e}");
+                return reply;
+            }
+        };
+        let span = REQUEST_LOG.span(&req);
+        debug!(parent: &span, len = req.len(), "virtio-fs request received");
+
+        if let Operation::Init { arg } = *req.operation() {
+            // The caches of the guest can't be invalidated by the recalls
+            // without the notification queue, so its writeback cache is not
+            // claimed.
+            match session::init_session(arg, &req, &*self.fs, &mut reply, false).await {
+                Ok(proto_version) => self.proto_version.store(proto_version),
+                Err(e) => error!("failed to initialize the virtio-fs session: {e}"),
+            }
+            return reply;
+        }
+
+        let start = Instant::now();
+        let deadline = start + FUSE_REQUEST_DEADLINE;
+        let (res, decisions) = slow_log::scope(deadline::scope(
+            deadline,
+            session::dispatch(&req, &mut reply, Arc::clone(&self.fs)).instrument(span.clone()),
+        ))
+        .await;
+        if let Ok(replied) = res {
+            let latency = start.elapsed();
+            REQUEST_LOG.replied(&span, &req, replied, latency);
+            // Served as soon as it's popped from the queue
+            SLOW_LOG.record(&req, Duration::ZERO, latency, decisions);
+        }
+        if let Err(e) = res {
+            error!(
+                "failed to process req={:?}, the error is: {}",
+                req,
+                crate::async_fuse::util::format_nix_error(e),
+            );
+        }
+        reply
+    }
+}
+
+impl VhostUserBackend for VirtioFsBackend {
+    type Bitmap = ();
+    type Vring = VringMutex;
+
+    fn num_queues(&self) -> usize {
+        NUM_REQUEST_QUEUES + 1
+    }
+
+    fn max_queue_size(&self) -> usize {
+        QUEUE_SIZE
+    }
+
+    fn features(&self) -> u64 {
+        (1 << VIRTIO_F_VERSION_1)
+            | (1 << VIRTIO_RING_F_INDIRECT_DESC)
+            | VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits()
+    }
+
+    fn protocol_features(&self) -> VhostUserProtocolFeatures {
+        VhostUserProtocolFeatures::MQ
+    }
+
+    fn set_event_idx(&self, _enabled: bool) {
+        // `VIRTIO_RING_F_EVENT_IDX` is not offered.
+    }
+
+    fn update_memory(&self, mem: GuestMemoryAtomic<GuestMemoryMmap>) -> io::Result<()> {
+        *self.mem.write() = Some(mem);
+        Ok(())
+    }
+
+    fn exit_event(&self, _thread_index: usize) -> Option<EventFd> {
+        self.exit_event.try_clone().ok()
+    }
+
+    fn handle_event(
+        &self,
+        device_event: u16,
+        evset: EventSet,
+        vrings: &[VringMutex],
+        _thread_id: usize,
+    ) -> io::Result<()> {
+        if evset != EventSet::IN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unexpected events {evset:?} of queue {device_event}"),
+            ));
+        }
+        let vring = vrings.get(usize::from(device_event)).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown queue {device_event}"),
+            )
+        })?;
+        self.process_queue(vring)
+    }
+}
+
+/// Serve `fs` as a virtio-fs device to the VMM connecting to the vhost-user
+/// socket `socket`, until the VMM disconnects or `token` is cancelled.
+#[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+pub async fn run_virtiofs(
+    socket: &Path,
+    fs: Arc<dyn FileSystem + Send + Sync>,
+    token: CancellationToken,
+) -> anyhow::Result<()> {
+    let backend = Arc::new(VirtioFsBackend::new(fs, Handle::current())?);
+    let listener = Listener::new(socket, true)
+        .with_context(|| format!("failed to listen on the vhost-user socket {socket:?}"))?;
+    let mut daemon = VhostUserDaemon::new(
+        "datenlord-virtiofs".to_owned(),
+        Arc::clone(&backend),
+        GuestMemoryAtomic::new(GuestMemoryMmap::new()),
+    )
+    .map_err(|e| anyhow!("failed to create the vhost-user daemon: {e}"))?;
+    info!("virtio-fs waits for the VMM on {socket:?}.");
+
+    // The daemon blocks its thread until the VMM disconnects.
+    let serving = tokio::task::spawn_blocking(move || {
+        daemon
+            .start(listener)
+            .and_then(|()| daemon.wait())
+            .map_err(|e| anyhow!("the vhost-user daemon failed: {e}"))
+    });
+    tokio::select! {
+        () = token.cancelled() => {
+            // Stop processing the requests, the thread serving the VMM exits
+            // with the process.
+            backend.exit_event.write(1)?;
+        }
+        res = serving => {
+            res??;
+        }
+    }
+    info!("virtio-fs session exits.");
+    Ok(())
+}
@@ -0,0 +1,337 @@
+//! The authentication of tenants and the authorization of their requests.
+//!
+//! A mount serving a tenant presents the token of the tenant, which is the
+//! name of the tenant and its MAC keyed by the auth key of the cluster, so
+//! only the holders of the key issue tokens. The policy of the cluster grants
+//! each tenant the directories it reads and writes, and the ones it only
+//! reads, by their paths in the file system, which are resolved once the
+//! volume is mounted and then followed across renames.
+//!
+//! The requests of the mount are authorized before they're dispatched. A node
+//! is granted by the nearest directory of the rules above it, a read-only one
+//! refuses the changes with `EROFS`, and the nodes granted by no rule are
+//! refused with `EACCES`, except that the directories above the granted ones
+//! are looked up on the way to them, but not listed.
+//!
+//! The classes of operations turned off on a mount by its `OpMask` are
+//! refused before the tenant is checked, with the same errno whichever node
+//! they access.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Debug, Formatter};
+
+use clippy_utilities::Cast;
+use datenlord::config::{OpClass, OpMask};
+use nix::errno::Errno;
+use nix::sys::stat::SFlag;
+use serde::Deserialize;
+
+use crate::async_fuse::fuse::fuse_request::{Operation, Request};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::async_fuse::util::build_error_result_from_errno;
+use crate::common::error::{Context, DatenLordResult};
+
+/// The context of deriving the key of the tokens from the auth key file.
+const TOKEN_KEY_CONTEXT: &str = "datenlord 2024 tenant token";
+
+/// The key of the tenant tokens.
+pub struct AuthKey([u8; 32]);
+
+impl Debug for AuthKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("AuthKey(..)")
+    }
+}
+
+impl AuthKey {
+    /// Derive the key from the contents of the auth key file.
+    #[must_use]
+    pub fn derive(material: &[u8]) -> Self {
+        Self(blake3::derive_key(TOKEN_KEY_CONTEXT, material))
+    }
+
+    /// Load the key from the auth key file at `path`.
+    pub async fn load(path: &str) -> DatenLordResult<Self> {
+        let material = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("failed to read auth key file {path}"))?;
+        Ok(Self::derive(&material))
+    }
+
+    /// The MAC of the token of `tenant`.
+    fn token_mac(&self, tenant: &str) -> blake3::Hash {
+        blake3::keyed_hash(&self.0, tenant.as_bytes())
+    }
+
+    /// Issue the token of `tenant`.
+    #[must_use]
+    pub fn issue_token(&self, tenant: &str) -> String {
+        format!("{tenant}.{}", self.token_mac(tenant).to_hex())
+    }
+
+    /// Returns the tenant of `token`, `None` if it's not issued by the key.
+    #[must_use]
+    pub fn verify_token<'a>(&self, token: &'a str) -> Option<&'a str> {
+        let (tenant, mac) = token.rsplit_once('.')?;
+        let mac = blake3::Hash::from_hex(mac).ok()?;
+        // The comparison of hashes takes constant time.
+        (self.token_mac(tenant) == mac).then_some(tenant)
+    }
+}
+
+/// The rules of a tenant in the policy.
+#[derive(Debug, Default, Deserialize)]
+pub struct TenantRules {
+    /// The paths of the directories read and written by the tenant
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// The paths of the directories only read by the tenant
+    #[serde(default)]
+    pub read_only: Vec<String>,
+}
+
+/// The authorization policy, in JSON such as
+/// `{"tenants": {"team-a": {"paths": ["/team-a"], "read_only": ["/shared"]}}}`.
+#[derive(Debug, Default, Deserialize)]
+pub struct AuthPolicy {
+    /// Tenant name -> the rules of the tenant
+    pub tenants: HashMap<String, TenantRules>,
+}
+
+impl AuthPolicy {
+    /// Load the policy from the file at `path`.
+    pub async fn load(path: &str) -> DatenLordResult<Self> {
+        let content = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("failed to read auth policy file {path}"))?;
+        serde_json::from_slice(&content)
+            .with_context(|| format!("failed to parse auth policy file {path}"))
+    }
+}
+
+/// The access needed by a request to a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Need {
+    /// Look up the node or get its attributes
+    Traverse,
+    /// Read the node
+    Read,
+    /// Change the node
+    Write,
+}
+
+/// The nodes accessed by a FUSE request and the access needed to them.
+#[must_use]
+pub fn needs(req: &Request<'_>) -> Vec<(INum, Need)> {
+    let ino = req.nodeid();
+    match *req.operation() {
+        Operation::Init { .. }
+        | Operation::Destroy
+        | Operation::Interrupt { .. }
+        | Operation::Forget { .. }
+        | Operation::Release { .. }
+        | Operation::ReleaseDir { .. } => vec![],
+        #[cfg(feature = "abi-7-11")]
+        Operation::IoCtl { .. } | Operation::Poll { .. } | Operation::CuseInit { .. } => vec![],
+        #[cfg(feature = "abi-7-15")]
+        Operation::NotifyReply { .. } => vec![],
+        #[cfg(feature = "abi-7-16")]
+        Operation::BatchForget { .. } => vec![],
+        #[cfg(feature = "abi-7-21")]
+        Operation::ReadDirPlus { .. } => vec![],
+        #[cfg(target_os = "macos")]
+        Operation::SetVolName { .. } | Operation::GetXTimes | Operation::Exchange { .. } => {
+            vec![]
+        }
+
+        Operation::Lookup { .. }
+        | Operation::GetAttr
+        | Operation::Access { .. }
+        | Operation::StatFs => vec![(ino, Need::Traverse)],
+
+        Operation::ReadLink
+        | Operation::Read { .. }
+        | Operation::Flush { .. }
+        | Operation::FSync { .. }
+        | Operation::OpenDir { .. }
+        | Operation::ReadDir { .. }
+        | Operation::FSyncDir { .. }
+        | Operation::GetXAttr { .. }
+        | Operation::ListXAttr { .. }
+        | Operation::GetLk { .. }
+        | Operation::SetLk { .. }
+        | Operation::SetLkW { .. }
+        | Operation::BMap { .. }
+        | Operation::LSeek { .. } => vec![(ino, Need::Read)],
+
+        Operation::Open { arg } => {
+            let write_flags = libc::O_WRONLY | libc::O_RDWR | libc::O_TRUNC;
+            if arg.flags.cast::<i32>() & write_flags == 0 {
+                vec![(ino, Need::Read)]
+            } else {
+                vec![(ino, Need::Write)]
+            }
+        }
+
+        Operation::SetAttr { .. }
+        | Operation::MkNod { .. }
+        | Operation::MkDir { .. }
+        | Operation::Unlink { .. }
+        | Operation::RmDir { .. }
+        | Operation::SymLink { .. }
+        | Operation::Write { .. }
+        | Operation::SetXAttr { .. }
+        | Operation::RemoveXAttr { .. }
+        | Operation::Create { .. } => vec![(ino, Need::Write)],
+        #[cfg(feature = "abi-7-19")]
+        Operation::FAllocate { .. } => vec![(ino, Need::Write)],
+
+        // A node linked elsewhere is still granted by its first parent, so
+        // it's linked only if it's writable.
+        Operation::Link { arg, .. } => vec![(ino, Need::Write), (arg.oldnodeid, Need::Write)],
+        Operation::Rename { arg, .. } => vec![(ino, Need::Write), (arg.newdir, Need::Write)],
+        #[cfg(feature = "abi-7-23")]
+        Operation::Rename2 { arg, .. } => vec![(ino, Need::Write), (arg.newdir, Need::Write)],
+        Operation::CopyFileRange { arg } => vec![(ino, Need::Read), (arg.nodeid_out, Need::Write)],
+    }
+}
+
+/// The errno refusing a FUSE request if its operation is in a class turned
+/// off by `mask`, `None` if it's not.
+#[must_use]
+pub fn masked(req: &Request<'_>, mask: OpMask) -> Option<Errno> {
+    if mask == OpMask::default() {
+        return None;
+    }
+    let operation = req.operation();
+    let xattr = matches!(
+        *operation,
+        Operation::GetXAttr { .. }
+            | Operation::ListXAttr { .. }
+            | Operation::SetXAttr { .. }
+            | Operation::RemoveXAttr { .. }
+    );
+    let device = matches!(
+        *operation,
+        Operation::MkNod { arg, .. } if matches!(
+            SFlag::from_bits_truncate(arg.mode & SFlag::S_IFMT.bits()),
+            SFlag::S_IFCHR | SFlag::S_IFBLK
+        )
+    );
+    let lock = matches!(
+        *operation,
+        Operation::GetLk { .. } | Operation::SetLk { .. } | Operation::SetLkW { .. }
+    );
+    let write = needs(req).iter().any(|&(_, need)| need == Need::Write);
+    // The classes of the operation are refused in this order, so a change of
+    // the xattrs on a read-only mount without them is not supported rather
+    // than read-only.
+    [
+        (OpClass::Xattr, xattr, Errno::EOPNOTSUPP),
+        (OpClass::Device, device, Errno::EPERM),
+        (OpClass::Lock, lock, Errno::ENOLCK),
+        (OpClass::Write, write, Errno::EROFS),
+    ]
+    .into_iter()
+    .find_map(|(class, of_class, errno)| (of_class && mask.denies(class)).then_some(errno))
+}
+
+/// The authorizer of the requests of a tenant.
+#[derive(Debug)]
+pub struct Authorizer {
+    /// The name of the tenant
+    tenant: String,
+    /// The directories granted -> whether they're only read
+    grants: HashMap<INum, bool>,
+    /// The directories above the granted ones
+    ancestors: HashSet<INum>,
+}
+
+impl Authorizer {
+    /// Create an `Authorizer` of `tenant` granting nothing.
+    #[must_use]
+    pub fn new(tenant: &str) -> Self {
+        Self {
+            tenant: tenant.to_owned(),
+            grants: HashMap::new(),
+            ancestors: HashSet::new(),
+        }
+    }
+
+    /// The name of the tenant.
+    #[must_use]
+    pub fn tenant(&self) -> &str {
+        &self.tenant
+    }
+
+    /// Grant the directory whose path from the root is `path`, the inodes
+    /// from the root to it. A directory granted read-only as well is only
+    /// read.
+    pub fn grant(&mut self, path: &[INum], read_only: bool) {
+        let Some((&dir, ancestors)) = path.split_last() else {
+            return;
+        };
+        let only_read = self.grants.entry(dir).or_insert(read_only);
+        *only_read = *only_read || read_only;
+        self.ancestors.extend(ancestors);
+    }
+
+    /// Check `need` of the node whose inodes up to the root are `chain`,
+    /// starting from the node itself.
+    pub fn check(&self, chain: &[INum], need: Need) -> DatenLordResult<()> {
+        let ino = chain.first().copied().unwrap_or_default();
+        match chain.iter().find_map(|ino| self.grants.get(ino)) {
+            Some(&false) => Ok(()),
+            Some(&true) if need != Need::Write => Ok(()),
+            Some(&true) => build_error_result_from_errno(
+                Errno::EROFS,
+                format!("ino={ino} is read-only for tenant {}", self.tenant),
+            ),
+            None if need == Need::Traverse && self.ancestors.contains(&ino) => Ok(()),
+            None => build_error_result_from_errno(
+                Errno::EACCES,
+                format!("ino={ino} is not granted to tenant {}", self.tenant),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuthKey, Authorizer, Need};
+
+    #[test]
+    fn test_tenant_token() {
+        let key = AuthKey::derive(b"secret");
+        let token = key.issue_token("team.a");
+        assert_eq!(key.verify_token(&token), Some("team.a"));
+
+        // The tenant of a token can't be changed.
+        let forged = token.replacen("team.a", "team.b", 1);
+        assert_eq!(key.verify_token(&forged), None);
+        assert_eq!(key.verify_token("team-a"), None);
+        // A token is only verified by its own key.
+        assert_eq!(AuthKey::derive(b"other").verify_token(&token), None);
+    }
+
+    #[test]
+    fn test_authorizer() {
+        // / (1) -> tenants (2) -> a (3) -> shared (4)
+        let mut authorizer = Authorizer::new("a");
+        authorizer.grant(&[1, 2, 3], false);
+        authorizer.grant(&[1, 2, 3, 4], true);
+
+        assert!(authorizer.check(&[5, 3, 2, 1], Need::Write).is_ok());
+        assert!(authorizer.check(&[3, 2, 1], Need::Write).is_ok());
+        // The nearest grant decides.
+        assert!(authorizer.check(&[6, 4, 3, 2, 1], Need::Read).is_ok());
+        assert!(authorizer.check(&[6, 4, 3, 2, 1], Need::Write).is_err());
+        // The directories above are only traversed.
+        assert!(authorizer.check(&[2, 1], Need::Traverse).is_ok());
+        assert!(authorizer.check(&[1], Need::Traverse).is_ok());
+        assert!(authorizer.check(&[1], Need::Read).is_err());
+        // The other nodes are not granted.
+        assert!(authorizer.check(&[7, 2, 1], Need::Traverse).is_err());
+        assert!(authorizer.check(&[8, 1], Need::Read).is_err());
+    }
+}
@@ -0,0 +1,276 @@
+//! The usage of a volume persisted for billing.
+//!
+//! The requests and the bytes read and written by the FUSE requests of a node,
+//! see `IO_STATS`, are checkpointed periodically into the kv engine with the
+//! metadata. Like the usage of the volume, see [`usage`](super::usage), every
+//! node adds what it counted since its last checkpoint to its own record, so
+//! there is no hot key shared by all the nodes, and the records keep counting
+//! over the restarts of the nodes. The IO counted since the last checkpoint is
+//! lost if a node crashes, a node shut down checkpoints it before exiting.
+//!
+//! The storage is charged in byte-seconds. At every checkpoint, the bytes in
+//! the volume are charged for the time since the storage was charged last, in
+//! a transaction on the record of the volume, so the time is charged once
+//! however many nodes checkpoint. The usage role exports the usage of the
+//! volume in JSON, which the tenants are charged by.
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use clippy_utilities::Cast;
+use serde::{Deserialize, Serialize};
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use super::kv_engine::{KVEngine, KVEngineType, KeyType, ValueType};
+use super::usage::VolumeUsage;
+use crate::async_fuse::fuse::io_stats::{IoCounters, IO_STATS};
+use crate::common::error::{Context, DatenLordResult};
+
+/// The limit of retrying a transaction.
+const TXN_RETRY_LIMIT: u32 = 10;
+
+/// The seconds of a day.
+const SECS_PER_DAY: u128 = 86400;
+
+/// The IO checkpointed by a node.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NodeIo {
+    /// The id of the node
+    pub node_id: String,
+    /// The IO of the node
+    pub io: IoCounters,
+}
+
+/// The storage charged to a volume.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageTime {
+    /// The bytes stored multiplied by the seconds they're stored
+    pub byte_seconds: u128,
+    /// When the storage is charged until
+    pub charged_until: SystemTime,
+}
+
+impl StorageTime {
+    /// Start charging the storage at `now`.
+    fn new(now: SystemTime) -> Self {
+        Self {
+            byte_seconds: 0,
+            charged_until: now,
+        }
+    }
+
+    /// Charge `bytes` stored for the whole seconds until `now`, the rest is
+    /// charged next time. Nothing is charged if the clock of this node is
+    /// behind.
+    fn charge(&mut self, bytes: u64, now: SystemTime) {
+        let Ok(elapsed) = now.duration_since(self.charged_until) else {
+            return;
+        };
+        let secs = elapsed.as_secs();
+        self.byte_seconds = self
+            .byte_seconds
+            .saturating_add(u128::from(bytes).saturating_mul(u128::from(secs)));
+        self.charged_until = self
+            .charged_until
+            .checked_add(Duration::from_secs(secs))
+            .unwrap_or(now);
+    }
+}
+
+/// The usage of a volume exported for billing.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BillingReport {
+    /// When the usage is exported
+    pub at: SystemTime,
+    /// The IO of all the nodes checkpointed
+    pub io: IoCounters,
+    /// The IO checkpointed by every node
+    pub nodes: Vec<NodeIo>,
+    /// The bytes in the volume now
+    pub bytes: u64,
+    /// The storage charged until now, in byte-seconds
+    pub byte_seconds: u128,
+    /// The storage charged until now, in byte-days
+    pub byte_days: u128,
+}
+
+/// Get the bytes in the volume.
+async fn volume_bytes(kv_engine: &KVEngineType) -> DatenLordResult<u64> {
+    let records = kv_engine
+        .range(&KeyType::AllVolumeUsages)
+        .await
+        .add_context("failed to get the usage of the volume")?;
+    let usage = VolumeUsage::sum(records.into_iter().map(ValueType::into_volume_usage));
+    Ok(usage.bytes.max(0).cast())
+}
+
+/// Add `io` counted by `node_id` since its last checkpoint to its record, and
+/// charge the storage of the volume until `now`.
+pub async fn checkpoint(
+    kv_engine: &KVEngineType,
+    node_id: &str,
+    io: &IoCounters,
+    now: SystemTime,
+) -> DatenLordResult<()> {
+    let bytes = volume_bytes(kv_engine).await?;
+    let io_key = KeyType::NodeIo(node_id.to_owned());
+    let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+        let mut txn = kv_engine.new_meta_txn().await;
+        let mut record = txn.get(&io_key).await?.map_or_else(
+            || NodeIo {
+                node_id: node_id.to_owned(),
+                io: IoCounters::default(),
+            },
+            ValueType::into_node_io,
+        );
+        record.io.merge(io);
+        txn.set(&io_key, &ValueType::NodeIo(record));
+
+        let storage = match txn.get(&KeyType::VolumeStorageTime).await? {
+            Some(value) => {
+                let mut storage = value.into_storage_time();
+                storage.charge(bytes, now);
+                storage
+            }
+            None => StorageTime::new(now),
+        };
+        txn.set(
+            &KeyType::VolumeStorageTime,
+            &ValueType::StorageTime(storage),
+        );
+        (txn.commit().await, ())
+    });
+    res.add_context("failed to checkpoint the usage of the volume")
+}
+
+/// Export the usage of the volume at `now`, the storage is charged until
+/// `now` in the report.
+pub async fn report(kv_engine: &KVEngineType, now: SystemTime) -> DatenLordResult<BillingReport> {
+    let mut nodes: Vec<NodeIo> = kv_engine
+        .range(&KeyType::AllNodeIos)
+        .await
+        .add_context("failed to get the IO of the nodes")?
+        .into_iter()
+        .map(ValueType::into_node_io)
+        .collect();
+    nodes.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+    let io = nodes.iter().fold(IoCounters::default(), |mut io, node| {
+        io.merge(&node.io);
+        io
+    });
+
+    let bytes = volume_bytes(kv_engine).await?;
+    let byte_seconds = kv_engine
+        .get(&KeyType::VolumeStorageTime)
+        .await
+        .add_context("failed to get the storage charged to the volume")?
+        .map_or(0, |value| {
+            let mut storage = value.into_storage_time();
+            storage.charge(bytes, now);
+            storage.byte_seconds
+        });
+
+    Ok(BillingReport {
+        at: now,
+        io,
+        nodes,
+        bytes,
+        byte_seconds,
+        byte_days: byte_seconds.wrapping_div(SECS_PER_DAY),
+    })
+}
+
+/// Checkpoint the IO of `node_id` every `interval`, and once more when `token`
+/// is cancelled, after the requests are served.
+#[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+pub async fn run_checkpointer(
+    kv_engine: Arc<KVEngineType>,
+    node_id: String,
+    interval: Duration,
+    token: CancellationToken,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    let mut checkpointed = IoCounters::default();
+    loop {
+        let cancelled = select! {
+            _ = ticker.tick() => false,
+            () = token.cancelled() => true,
+        };
+        let total = IO_STATS.total();
+        let io = total.since(&checkpointed);
+        match checkpoint(&kv_engine, &node_id, &io, SystemTime::now()).await {
+            Ok(()) => checkpointed = total,
+            Err(e) => error!("Failed to checkpoint the usage of the volume: {e}"),
+        }
+        if cancelled {
+            info!("Usage checkpointer exits.");
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{checkpoint, report, StorageTime};
+    use crate::async_fuse::fuse::io_stats::IoCounters;
+    use crate::async_fuse::memfs::kv_engine::{KVEngine, KeyType, ValueType};
+    use crate::async_fuse::memfs::usage::VolumeUsage;
+    use crate::async_fuse::test::test_util::open_kv_engine;
+
+    #[test]
+    fn test_charge_storage() {
+        let start = SystemTime::UNIX_EPOCH;
+        let mut storage = StorageTime::new(start);
+        storage.charge(100, start.checked_add(Duration::from_millis(2500)).unwrap());
+        assert_eq!(storage.byte_seconds, 200);
+        // The rest of a second is charged next time.
+        storage.charge(100, start.checked_add(Duration::from_secs(3)).unwrap());
+        assert_eq!(storage.byte_seconds, 300);
+        // Nothing is charged by a clock behind.
+        storage.charge(100, start);
+        assert_eq!(storage.byte_seconds, 300);
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_and_report() {
+        let kv_engine = open_kv_engine();
+        kv_engine
+            .set(
+                &KeyType::VolumeUsage("node1".to_owned()),
+                &ValueType::VolumeUsage(VolumeUsage::new(1000, 1)),
+                None,
+            )
+            .await
+            .unwrap();
+
+        let start = SystemTime::UNIX_EPOCH;
+        let io = IoCounters {
+            ops: 3,
+            read_bytes: 4096,
+            write_bytes: 100,
+        };
+        checkpoint(&kv_engine, "node1", &io, start).await.unwrap();
+        checkpoint(&kv_engine, "node2", &io, start).await.unwrap();
+        let day = start.checked_add(Duration::from_secs(86400)).unwrap();
+        checkpoint(&kv_engine, "node1", &io, day).await.unwrap();
+
+        let two_days = start.checked_add(Duration::from_secs(172_800)).unwrap();
+        let usage = report(&kv_engine, two_days).await.unwrap();
+        assert_eq!(usage.nodes.len(), 2);
+        assert_eq!(
+            usage.io,
+            IoCounters {
+                ops: 9,
+                read_bytes: 12288,
+                write_bytes: 300,
+            }
+        );
+        assert_eq!(usage.bytes, 1000);
+        assert_eq!(usage.byte_days, 2000);
+    }
+}
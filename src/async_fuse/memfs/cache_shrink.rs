@@ -0,0 +1,61 @@
+//! The shrinking of the metadata caches under memory pressure.
+//!
+//! The data cache is evicted by the writes waiting for the memory budget, but
+//! the directory entries and the nodes cached by this node and by the kernel
+//! grow with the files touched, and nothing shrinks them while they're idle.
+//! Once the budget is exhausted, the clean metadata cached here is dropped in
+//! batches, and the kernel is asked to drop the same entries, so it forgets
+//! the inodes no longer used, whose references are released by the
+//! `BATCH_FORGET`s following.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use datenlord::common::memory_budget::MemoryBudget;
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+
+use super::coherence::Coherence;
+use super::metadata::MetaData;
+
+/// The interval between checking the memory budget.
+const SHRINK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The max number of the directory entries dropped by a check.
+const SHRINK_BATCH: usize = 1024;
+
+/// Drop a batch of the caches of `metadata` and of the kernel, notified by
+/// `coherence`, whenever `budget` is found exhausted, until `token` is
+/// cancelled.
+#[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+pub(super) async fn run_cache_shrinker<M: MetaData + Send + Sync + 'static>(
+    metadata: Arc<M>,
+    coherence: Arc<Coherence>,
+    budget: Arc<MemoryBudget>,
+    token: CancellationToken,
+) {
+    let mut ticker = tokio::time::interval(SHRINK_CHECK_INTERVAL);
+
+    loop {
+        select! {
+            _ = ticker.tick() => {}
+            () = token.cancelled() => {
+                info!("Cache shrinker exits.");
+                return;
+            }
+        }
+
+        if !budget.is_exhausted() {
+            continue;
+        }
+        let dropped = metadata.shrink_caches(SHRINK_BATCH);
+        debug!(
+            "{} directory entries are dropped under memory pressure.",
+            dropped.len()
+        );
+        for (parent, name) in dropped {
+            coherence.invalidate_kernel_entry(parent, &name).await;
+        }
+    }
+}
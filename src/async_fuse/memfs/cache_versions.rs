@@ -0,0 +1,41 @@
+//! The `FileVersions` upon the metadata in the kv engine.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use super::kv_engine::{KVEngine, KVEngineType, KeyType};
+use super::serial::serial_to_file_attr;
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::disk_cache::{FileVersion, FileVersions};
+use crate::storage::error::StorageResult;
+
+/// A `FileVersions` looking up the attributes of files in the metadata of the
+/// volume, a file is of a new version once its mtime or its size changes.
+#[derive(Debug)]
+pub struct KvFileVersions {
+    /// The kv engine
+    kv_engine: Arc<KVEngineType>,
+}
+
+impl KvFileVersions {
+    /// Create a `KvFileVersions`.
+    #[must_use]
+    pub fn new(kv_engine: Arc<KVEngineType>) -> Self {
+        Self { kv_engine }
+    }
+}
+
+#[async_trait]
+impl FileVersions for KvFileVersions {
+    async fn file_version(&self, ino: INum) -> StorageResult<Option<FileVersion>> {
+        let node = self.kv_engine.get(&KeyType::INum2Node(ino)).await?;
+        Ok(node.map(|node| {
+            let attr = serial_to_file_attr(&node.into_serial_node().attr);
+            FileVersion {
+                mtime: attr.mtime,
+                size: attr.size,
+            }
+        }))
+    }
+}
@@ -0,0 +1,119 @@
+//! The `ChecksumIndex` persisted in the kv engine.
+
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+
+use super::kv_engine::{KVEngine, KVEngineType, KeyType, ValueType};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::common::error::DatenLordResult;
+use crate::storage::checksum::{BlockChecksum, ChecksumIndex};
+use crate::storage::error::{StorageError, StorageResult};
+
+/// The limit of retrying a transaction.
+const TXN_RETRY_LIMIT: u32 = 10;
+
+/// A `ChecksumIndex` persisted in the kv engine, the checksums are shared
+/// among all nodes of the volume.
+#[derive(Debug)]
+pub struct KvChecksumIndex {
+    /// The kv engine
+    kv_engine: Arc<KVEngineType>,
+}
+
+impl KvChecksumIndex {
+    /// Create a `KvChecksumIndex`.
+    #[must_use]
+    pub fn new(kv_engine: Arc<KVEngineType>) -> Self {
+        Self { kv_engine }
+    }
+
+    /// Remove the checksums of blocks in a transaction.
+    async fn remove_checksums_impl(
+        &self,
+        ino: INum,
+        block_ids: Range<usize>,
+    ) -> DatenLordResult<()> {
+        let block_ids: Vec<usize> = self
+            .kv_engine
+            .range(&KeyType::FileBlockChecksums(ino))
+            .await?
+            .into_iter()
+            .map(|value| value.into_block_checksum().block_id)
+            .filter(|block_id| block_ids.contains(block_id))
+            .collect();
+        if block_ids.is_empty() {
+            return Ok(());
+        }
+
+        let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            for &block_id in &block_ids {
+                txn.delete(&KeyType::BlockChecksum(ino, block_id));
+            }
+            (txn.commit().await, ())
+        });
+        res
+    }
+}
+
+#[async_trait]
+impl ChecksumIndex for KvChecksumIndex {
+    async fn get_checksum(
+        &self,
+        ino: INum,
+        block_id: usize,
+    ) -> StorageResult<Option<BlockChecksum>> {
+        let value = self
+            .kv_engine
+            .get(&KeyType::BlockChecksum(ino, block_id))
+            .await;
+        value
+            .map_err(StorageError::from)
+            .map(|value| value.map(ValueType::into_block_checksum))
+    }
+
+    async fn set_checksum(&self, checksum: BlockChecksum) -> StorageResult<()> {
+        let key = KeyType::BlockChecksum(checksum.ino, checksum.block_id);
+        let value = ValueType::BlockChecksum(checksum);
+        self.kv_engine.set(&key, &value, None).await?;
+        Ok(())
+    }
+
+    async fn remove_checksums(&self, ino: INum, block_ids: Range<usize>) -> StorageResult<()> {
+        self.remove_checksums_impl(ino, block_ids)
+            .await
+            .map_err(StorageError::from)
+    }
+
+    async fn list_checksums(&self) -> StorageResult<Vec<BlockChecksum>> {
+        let values = self.kv_engine.range(&KeyType::AllBlockChecksums).await;
+        values.map_err(StorageError::from).map(|values| {
+            values
+                .into_iter()
+                .map(ValueType::into_block_checksum)
+                .collect()
+        })
+    }
+
+    async fn scrub_requested(&self) -> StorageResult<Option<SystemTime>> {
+        let value = self.kv_engine.get(&KeyType::ScrubRequest).await;
+        value
+            .map_err(StorageError::from)
+            .map(|value| value.map(ValueType::into_scrub_request))
+    }
+}
+
+/// Request a scrub pass of every node scrubbing the volume, which is started
+/// on the next poll of its scrubber.
+///
+/// Returns the time of the request.
+pub async fn request_scrub(kv_engine: &KVEngineType) -> DatenLordResult<SystemTime> {
+    let now = SystemTime::now();
+    kv_engine
+        .set(&KeyType::ScrubRequest, &ValueType::ScrubRequest(now), None)
+        .await?;
+    Ok(now)
+}
@@ -0,0 +1,404 @@
+//! The chunk index of files.
+//!
+//! File data is stored as fixed-size chunks (blocks) in the storage layers,
+//! the chunk index records which chunks of a file have been written, as a set
+//! of extents of chunk ids. A file written sequentially only has one extent,
+//! therefore the index stays small even for large files.
+//!
+//! The chunks not in the index are holes of a file, they read as zeros and
+//! are not counted in `st_blocks`.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use clippy_utilities::OverflowArithmetic;
+use datenlord::config::{CompressionType, ConsistencyModel, ErasureShards, StoragePolicy};
+use serde::{Deserialize, Serialize};
+
+/// The unit of `st_blocks` in bytes.
+pub(crate) const STAT_BLOCK_SIZE: u64 = 512;
+
+/// The persisted information of a volume.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub struct VolumeInfo {
+    /// The size of chunks of all files in this volume, it's fixed once the
+    /// volume is created.
+    pub chunk_size: u64,
+    /// The compression of blocks in the backend, it's fixed once the volume
+    /// is created. Volumes created before compression was supported are not
+    /// compressed.
+    #[serde(default)]
+    pub compression: CompressionType,
+    /// Whether blocks are deduplicated in the backend, it's fixed once the
+    /// volume is created.
+    #[serde(default)]
+    pub dedup: bool,
+    /// Whether small blocks are packed, it's fixed once the volume is
+    /// created, as packed blocks are not found in the backend.
+    #[serde(default)]
+    pub packing: bool,
+    /// Whether checksums of blocks are recorded and verified, it's fixed once
+    /// the volume is created, as blocks written without checksums cannot be
+    /// verified.
+    #[serde(default)]
+    pub checksum: bool,
+    /// Whether blocks are encrypted in the backend, it's fixed once the
+    /// volume is created. The data keys are persisted separately.
+    #[serde(default)]
+    pub encryption: bool,
+    /// Whether the versions of blocks are kept for snapshots, it's fixed once
+    /// the volume is created, as blocks written without versions cannot be
+    /// seen by snapshots.
+    #[serde(default)]
+    pub snapshot: bool,
+    /// Whether blocks are stored in the hot and cold tiers, it's fixed once
+    /// the volume is created, as the blocks written without tiers are not
+    /// found in the tiers.
+    #[serde(default)]
+    pub tiering: bool,
+    /// The number of replicas of blocks, it's fixed once the volume is
+    /// created. Blocks are stored in the backend instead of replicas if it's
+    /// 0.
+    #[serde(default)]
+    pub replication: u32,
+    /// The layout of blocks on the replica nodes, it's fixed once the volume
+    /// is created. Volumes created before erasure coding was supported are
+    /// replicated.
+    #[serde(default)]
+    pub storage_policy: StoragePolicy,
+    /// The shards of erasure coded blocks, it's fixed once the volume is
+    /// created. It's ignored if the blocks are replicated.
+    #[serde(default)]
+    pub erasure_shards: ErasureShards,
+    /// The consistency among the nodes, it's fixed once the volume is
+    /// created, as the leases of the strict nodes are not taken by the
+    /// close-to-open ones. Volumes created before it was configurable are
+    /// strict.
+    #[serde(default)]
+    pub consistency: ConsistencyModel,
+}
+
+impl VolumeInfo {
+    /// The info of a volume created before the volume info is persisted,
+    /// whose blocks are of `chunk_size` and stored in the backend as they
+    /// are, as none of the features fixed at the creation was there.
+    #[must_use]
+    pub fn legacy(chunk_size: u64) -> Self {
+        Self {
+            chunk_size,
+            compression: CompressionType::default(),
+            dedup: false,
+            packing: false,
+            checksum: false,
+            encryption: false,
+            snapshot: false,
+            tiering: false,
+            replication: 0,
+            storage_policy: StoragePolicy::default(),
+            erasure_shards: ErasureShards::default(),
+            consistency: ConsistencyModel::default(),
+        }
+    }
+}
+
+/// The chunk index of a file.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct ChunkIndex {
+    /// The size of chunks
+    chunk_size: u64,
+    /// The extents of written chunks, maps the start chunk id to the end
+    /// (excluded) chunk id. Extents never overlap or adjoin each other.
+    extents: BTreeMap<u64, u64>,
+}
+
+impl ChunkIndex {
+    /// Create an empty chunk index.
+    #[must_use]
+    pub fn new(chunk_size: u64) -> Self {
+        debug_assert!(chunk_size > 0, "Chunk size must not be zero.");
+        Self {
+            chunk_size,
+            extents: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the chunk size.
+    #[must_use]
+    pub fn chunk_size(&self) -> u64 {
+        self.chunk_size
+    }
+
+    /// Returns the range of chunk ids covered by the byte range `[offset,
+    /// offset + len)`.
+    #[must_use]
+    pub fn chunk_range(&self, offset: u64, len: u64) -> Range<u64> {
+        let start = offset.overflow_div(self.chunk_size);
+        if len == 0 {
+            return start..start;
+        }
+        let end = offset
+            .overflow_add(len)
+            .overflow_sub(1)
+            .overflow_div(self.chunk_size)
+            .overflow_add(1);
+        start..end
+    }
+
+    /// Record that the byte range `[offset, offset + len)` has been written.
+    pub fn record_write(&mut self, offset: u64, len: u64) {
+        let range = self.chunk_range(offset, len);
+        self.insert(range);
+    }
+
+    /// Insert a range of chunk ids, merging with overlapped or adjoined
+    /// extents.
+    pub fn insert(&mut self, range: Range<u64>) {
+        if range.is_empty() {
+            return;
+        }
+
+        let mut start = range.start;
+        let mut end = range.end;
+
+        // Merge with the extent starts before (or at) `start`.
+        if let Some((&prev_start, &prev_end)) = self.extents.range(..=start).next_back() {
+            if prev_end >= start {
+                start = prev_start;
+                end = end.max(prev_end);
+            }
+        }
+
+        // Merge with all extents starts in `[start, end]`.
+        let overlapped: Vec<(u64, u64)> = self
+            .extents
+            .range(start..=end)
+            .map(|(&s, &e)| (s, e))
+            .collect();
+        for (s, e) in overlapped {
+            self.extents.remove(&s);
+            end = end.max(e);
+        }
+
+        self.extents.insert(start, end);
+    }
+
+    /// Checks if a chunk has been written.
+    #[must_use]
+    pub fn contains(&self, chunk_id: u64) -> bool {
+        self.extents
+            .range(..=chunk_id)
+            .next_back()
+            .map_or(false, |(_, &end)| chunk_id < end)
+    }
+
+    /// Remove all chunks beyond a file size of `size` bytes.
+    pub fn truncate(&mut self, size: u64) {
+        let end_chunk = self.chunk_range(0, size).end;
+        let removed: Vec<u64> = self.extents.range(end_chunk..).map(|(&s, _)| s).collect();
+        for s in removed {
+            self.extents.remove(&s);
+        }
+        if let Some((_, last_end)) = self.extents.iter_mut().next_back() {
+            if *last_end > end_chunk {
+                *last_end = end_chunk;
+            }
+        }
+    }
+
+    /// Remove a range of chunk ids, splitting the extents overlapped with it.
+    pub fn remove(&mut self, range: Range<u64>) {
+        if range.is_empty() {
+            return;
+        }
+
+        // Split the extent starts before `range.start`.
+        if let Some((&prev_start, &prev_end)) = self.extents.range(..range.start).next_back() {
+            if prev_end > range.start {
+                self.extents.insert(prev_start, range.start);
+                if prev_end > range.end {
+                    self.extents.insert(range.end, prev_end);
+                }
+            }
+        }
+
+        // Remove or split the extents start in `range`.
+        let overlapped: Vec<(u64, u64)> = self
+            .extents
+            .range(range.clone())
+            .map(|(&s, &e)| (s, e))
+            .collect();
+        for (s, e) in overlapped {
+            self.extents.remove(&s);
+            if e > range.end {
+                self.extents.insert(range.end, e);
+            }
+        }
+    }
+
+    /// Deallocate the chunks fully covered by the byte range `[offset,
+    /// offset + len)`, as `fallocate(2)` punches a hole. The partially
+    /// covered chunks at both ends stay allocated, they are filled with zeros
+    /// instead.
+    pub fn punch_hole(&mut self, offset: u64, len: u64) {
+        let start = offset
+            .overflow_add(self.chunk_size.overflow_sub(1))
+            .overflow_div(self.chunk_size);
+        let end = offset.overflow_add(len).overflow_div(self.chunk_size);
+        if start < end {
+            self.remove(start..end);
+        }
+    }
+
+    /// Returns the offset of the first data byte at or after `offset`, as
+    /// `SEEK_DATA` of `lseek(2)`. Returns `None` if there is no data after
+    /// `offset` in a file of `size` bytes.
+    #[must_use]
+    pub fn seek_data(&self, offset: u64, size: u64) -> Option<u64> {
+        if offset >= size {
+            return None;
+        }
+        let chunk_id = offset.overflow_div(self.chunk_size);
+        if self.contains(chunk_id) {
+            return Some(offset);
+        }
+        self.extents
+            .range(chunk_id..)
+            .next()
+            .map(|(&start, _)| start.overflow_mul(self.chunk_size))
+            .filter(|&data| data < size)
+    }
+
+    /// Returns the offset of the first hole byte at or after `offset`, as
+    /// `SEEK_HOLE` of `lseek(2)`. The end of a file of `size` bytes is an
+    /// implicit hole. Returns `None` if `offset` is beyond the end of file.
+    #[must_use]
+    pub fn seek_hole(&self, offset: u64, size: u64) -> Option<u64> {
+        if offset >= size {
+            return None;
+        }
+        let chunk_id = offset.overflow_div(self.chunk_size);
+        let hole = self
+            .extents
+            .range(..=chunk_id)
+            .next_back()
+            .filter(|&(_, &end)| chunk_id < end)
+            .map_or(offset, |(_, &end)| end.overflow_mul(self.chunk_size));
+        Some(hole.min(size))
+    }
+
+    /// Returns the number of 512-byte units allocated, i.e. the `st_blocks`
+    /// of the file.
+    #[must_use]
+    pub fn st_blocks(&self) -> u64 {
+        let bytes = self.chunk_count().overflow_mul(self.chunk_size);
+        bytes
+            .overflow_add(STAT_BLOCK_SIZE.overflow_sub(1))
+            .overflow_div(STAT_BLOCK_SIZE)
+    }
+
+    /// Returns the number of written chunks.
+    #[must_use]
+    pub fn chunk_count(&self) -> u64 {
+        self.extents.iter().map(|(&s, &e)| e.overflow_sub(s)).sum()
+    }
+
+    /// Returns the extents of written chunks, in ascending order.
+    pub fn extents(&self) -> impl Iterator<Item = Range<u64>> + '_ {
+        self.extents.iter().map(|(&s, &e)| s..e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChunkIndex;
+
+    const CHUNK_SIZE: u64 = 4;
+
+    #[test]
+    fn test_chunk_range() {
+        let index = ChunkIndex::new(CHUNK_SIZE);
+        assert_eq!(index.chunk_range(0, 4), 0..1);
+        assert_eq!(index.chunk_range(3, 2), 0..2);
+        assert_eq!(index.chunk_range(4, 9), 1..4);
+        assert!(index.chunk_range(5, 0).is_empty());
+    }
+
+    #[test]
+    fn test_insert_and_merge() {
+        let mut index = ChunkIndex::new(CHUNK_SIZE);
+        index.insert(0..2);
+        index.insert(5..6);
+        assert_eq!(index.extents().collect::<Vec<_>>(), vec![0..2, 5..6]);
+
+        // Adjoined extents are merged.
+        index.insert(2..3);
+        assert_eq!(index.extents().collect::<Vec<_>>(), vec![0..3, 5..6]);
+
+        // An extent covers several extents.
+        index.insert(1..8);
+        assert_eq!(index.extents().collect::<Vec<_>>(), vec![0..8]);
+        assert_eq!(index.chunk_count(), 8);
+    }
+
+    #[test]
+    fn test_record_write_and_contains() {
+        let mut index = ChunkIndex::new(CHUNK_SIZE);
+        index.record_write(6, 4);
+        assert!(!index.contains(0));
+        assert!(index.contains(1));
+        assert!(index.contains(2));
+        assert!(!index.contains(3));
+    }
+
+    #[test]
+    fn test_truncate() {
+        let mut index = ChunkIndex::new(CHUNK_SIZE);
+        index.insert(0..3);
+        index.insert(5..8);
+
+        index.truncate(22);
+        assert_eq!(index.extents().collect::<Vec<_>>(), vec![0..3, 5..6]);
+
+        index.truncate(5);
+        assert_eq!(index.extents().collect::<Vec<_>>(), vec![0..2]);
+
+        index.truncate(0);
+        assert_eq!(index.chunk_count(), 0);
+    }
+
+    #[test]
+    fn test_punch_hole() {
+        let mut index = ChunkIndex::new(CHUNK_SIZE);
+        index.insert(0..8);
+
+        // Only chunk 2 is fully covered.
+        index.punch_hole(6, 7);
+        assert_eq!(index.extents().collect::<Vec<_>>(), vec![0..2, 3..8]);
+
+        index.punch_hole(12, 20);
+        assert_eq!(index.extents().collect::<Vec<_>>(), vec![0..2]);
+        assert_eq!(index.st_blocks(), 1);
+    }
+
+    #[test]
+    fn test_seek_data_and_hole() {
+        let mut index = ChunkIndex::new(CHUNK_SIZE);
+        index.insert(1..3);
+        index.insert(5..6);
+        let size = 30;
+
+        assert_eq!(index.seek_data(0, size), Some(4));
+        assert_eq!(index.seek_data(9, size), Some(9));
+        assert_eq!(index.seek_data(13, size), Some(20));
+        assert_eq!(index.seek_data(24, size), None);
+
+        assert_eq!(index.seek_hole(0, size), Some(0));
+        assert_eq!(index.seek_hole(5, size), Some(12));
+        assert_eq!(index.seek_hole(21, size), Some(24));
+        assert_eq!(index.seek_hole(29, size), Some(29));
+        assert_eq!(index.seek_hole(30, size), None);
+
+        // The end of file is a hole.
+        assert_eq!(index.seek_hole(21, 22), Some(22));
+    }
+}
@@ -0,0 +1,319 @@
+//! The nodes sharing the namespace of a volume.
+//!
+//! The namespace, which is the nodes, the directory entries and the chunk
+//! indexes of files, is stored in the kv engine, and every change is committed
+//! in a transaction, so the nodes mounting a volume with etcd see one
+//! consistent namespace.
+//!
+//! Every node registers itself under its node id with a lease, which is renewed
+//! periodically, so the registration expires shortly after the node is gone.
+//! The attributes of open files are cached by every node, they're refreshed by
+//! watching the nodes changed by other nodes, so that a file written elsewhere
+//! is reloaded with its new `mtime` instead of being served from a stale
+//! cache. The changes made while the watch is reconnecting are missed, they
+//! are seen once the file is reopened.
+//!
+//! The registrations are the heartbeats of the nodes as well, the failure
+//! detector of the replica nodes lists them by [`KvMembership`], and the nodes
+//! sharing their caches are listed by their registrations with the endpoints
+//! serving the caches.
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use datenlord::config::PeerCacheConfig;
+use futures::StreamExt;
+use nix::sys::stat::SFlag;
+use serde::{Deserialize, Serialize};
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use super::kv_engine::{KVEngine, KVEngineType, KeyType, SetOption, ValueType, WatchEvent};
+use super::serial::serial_to_file_attr;
+use super::MetaData;
+use crate::common::error::DatenLordResult;
+use crate::storage::error::StorageResult;
+use crate::storage::replication::Membership;
+
+/// The TTL of the lease of a registration in seconds.
+const NODE_LEASE_TTL_SEC: i64 = 10;
+
+/// The interval between renewing the lease, which is a third of the TTL, so a
+/// lost renewal doesn't expire the registration.
+const NODE_LEASE_RENEW_INTERVAL: Duration = Duration::from_secs(3);
+
+/// The interval between the health checks of the nodes, which is the interval
+/// between renewing the leases, so a node is seen at every check.
+pub const HEALTH_CHECK_INTERVAL: Duration = NODE_LEASE_RENEW_INTERVAL;
+
+/// The interval between the retries to watch the kv engine.
+const WATCH_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The registration of a node mounting the volume.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NodeRegistration {
+    /// The id of the node
+    pub node_id: String,
+    /// The mount point on the node
+    pub mount_point: String,
+    /// The time the node is registered
+    pub registered: SystemTime,
+    /// The endpoint of the transfer service serving the caches of the node,
+    /// `None` if they're not shared
+    #[serde(default)]
+    pub cache_endpoint: Option<String>,
+    /// The zone of the node, such as its rack, `None` if it's not set
+    #[serde(default)]
+    pub zone: Option<String>,
+}
+
+/// Register a node with a new lease, returns the lease.
+async fn register(
+    kv_engine: &Arc<KVEngineType>,
+    registration: &NodeRegistration,
+) -> DatenLordResult<i64> {
+    let lease = kv_engine.lease_grant(NODE_LEASE_TTL_SEC).await?;
+    kv_engine
+        .set(
+            &KeyType::NodeRegistration(registration.node_id.clone()),
+            &ValueType::NodeRegistration(registration.clone()),
+            Some(SetOption::new().with_lease(lease)),
+        )
+        .await?;
+    Ok(lease)
+}
+
+/// List the nodes mounting the volume, sorted by their ids.
+pub async fn list_nodes(kv_engine: &Arc<KVEngineType>) -> DatenLordResult<Vec<NodeRegistration>> {
+    let mut nodes: Vec<NodeRegistration> = kv_engine
+        .range(&KeyType::AllNodeRegistrations)
+        .await?
+        .into_iter()
+        .map(ValueType::into_node_registration)
+        .collect();
+    nodes.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+    Ok(nodes)
+}
+
+/// The `Membership` of the nodes registered in the kv engine.
+#[derive(Debug)]
+pub struct KvMembership {
+    /// The kv engine
+    kv_engine: Arc<KVEngineType>,
+}
+
+impl KvMembership {
+    /// Create a `KvMembership`.
+    #[must_use]
+    pub fn new(kv_engine: Arc<KVEngineType>) -> Self {
+        Self { kv_engine }
+    }
+}
+
+#[async_trait]
+impl Membership for KvMembership {
+    async fn list_members(&self) -> StorageResult<Vec<String>> {
+        let nodes = list_nodes(&self.kv_engine).await?;
+        Ok(nodes.into_iter().map(|node| node.node_id).collect())
+    }
+}
+
+/// Keep node `node_id` registered, with the caches shared by `peer_cache`,
+/// until `token` is cancelled, then the node is deregistered. It's registered
+/// again if its lease is expired.
+#[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+pub async fn run_registration(
+    kv_engine: Arc<KVEngineType>,
+    node_id: String,
+    mount_point: String,
+    peer_cache: Option<PeerCacheConfig>,
+    token: CancellationToken,
+) {
+    let (cache_endpoint, zone) =
+        peer_cache.map_or((None, None), |config| (Some(config.endpoint), config.zone));
+    let registration = NodeRegistration {
+        node_id,
+        mount_point,
+        registered: SystemTime::now(),
+        cache_endpoint,
+        zone,
+    };
+    // The first tick completes immediately, the node is registered at once.
+    let mut ticker = tokio::time::interval(NODE_LEASE_RENEW_INTERVAL);
+    let mut lease = None;
+
+    loop {
+        select! {
+            _ = ticker.tick() => {
+                if let Some(id) = lease {
+                    if let Err(e) = kv_engine.lease_keep_alive(id).await {
+                        warn!("Failed to renew the lease of node {}: {e}", registration.node_id);
+                        lease = None;
+                    }
+                    continue;
+                }
+                match register(&kv_engine, &registration).await {
+                    Ok(id) => {
+                        lease = Some(id);
+                        let nodes = list_nodes(&kv_engine).await.map_or(0, |nodes| nodes.len());
+                        info!(
+                            "Node {} is registered, {nodes} nodes are mounting the volume.",
+                            registration.node_id
+                        );
+                    }
+                    Err(e) => error!("Failed to register node {}: {e}", registration.node_id),
+                }
+            }
+            () = token.cancelled() => {
+                let key = KeyType::NodeRegistration(registration.node_id.clone());
+                if let Err(e) = kv_engine.delete(&key, None).await {
+                    error!("Failed to deregister node {}: {e}", registration.node_id);
+                }
+                info!("Node {} is deregistered.", registration.node_id);
+                return;
+            }
+        }
+    }
+}
+
+/// Watch the nodes changed by other nodes, refresh the open files in
+/// `metadata` with them, and drop the cached entries of the changed
+/// directories, until `token` is cancelled.
+#[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+pub async fn run_invalidator<M: MetaData + Send + Sync>(
+    metadata: Arc<M>,
+    kv_engine: Arc<KVEngineType>,
+    token: CancellationToken,
+) {
+    loop {
+        match kv_engine.watch(&KeyType::AllINum2Nodes).await {
+            Ok(mut stream) => loop {
+                select! {
+                    events = stream.next() => match events {
+                        Some(Ok(events)) => {
+                            for event in events {
+                                if let WatchEvent::Put(ValueType::Node(node)) = event {
+                                    let attr = serial_to_file_attr(&node.attr);
+                                    if attr.kind == SFlag::S_IFDIR {
+                                        metadata.invalidate_dir(attr.ino);
+                                    }
+                                    metadata.refresh_open_file(attr);
+                                }
+                            }
+                        }
+                        Some(Err(e)) => {
+                            warn!("The watch of nodes failed: {e}");
+                            break;
+                        }
+                        None => {
+                            warn!("The watch of nodes is closed.");
+                            break;
+                        }
+                    },
+                    () = token.cancelled() => {
+                        info!("Invalidator exits.");
+                        return;
+                    }
+                }
+            },
+            Err(e) => error!("Failed to watch nodes: {e}"),
+        }
+
+        select! {
+            () = tokio::time::sleep(WATCH_RETRY_INTERVAL) => {}
+            () = token.cancelled() => {
+                info!("Invalidator exits.");
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    use nix::sys::stat::SFlag;
+    use tokio_util::sync::CancellationToken;
+
+    use super::{list_nodes, run_registration};
+    use crate::async_fuse::fuse::protocol::FUSE_ROOT_ID;
+    use crate::async_fuse::memfs::metadata::ReqContext;
+    use crate::async_fuse::memfs::node::Node;
+    use crate::async_fuse::memfs::{CreateParam, MetaData, MetaDataOptions, S3MetaData};
+    use crate::async_fuse::test::test_util::open_kv_engine;
+
+    #[tokio::test]
+    async fn test_registration() {
+        let kv_engine = open_kv_engine();
+        let token = CancellationToken::new();
+        let handle = tokio::spawn(run_registration(
+            Arc::clone(&kv_engine),
+            "node1".to_owned(),
+            "/mnt".to_owned(),
+            None,
+            token.clone(),
+        ));
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let nodes = list_nodes(&kv_engine).await.unwrap();
+        assert_eq!(nodes.len(), 1);
+        let node = nodes.first().unwrap();
+        assert_eq!(
+            (node.node_id.as_str(), node.mount_point.as_str()),
+            ("node1", "/mnt")
+        );
+
+        token.cancel();
+        handle.await.unwrap();
+        assert!(list_nodes(&kv_engine).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_open_file() {
+        let kv_engine = open_kv_engine();
+        let meta = S3MetaData::new(
+            Arc::clone(&kv_engine),
+            "node1",
+            4096,
+            MetaDataOptions::default(),
+        )
+        .await
+        .unwrap();
+        let param = CreateParam {
+            parent: FUSE_ROOT_ID,
+            name: "file".to_owned(),
+            mode: 0o644,
+            rdev: 0,
+            uid: 0,
+            gid: 0,
+            umask: 0,
+            node_type: SFlag::S_IFREG,
+            link: None,
+        };
+        let (_, attr, _) = meta.mknod(param).await.unwrap();
+        let ino = attr.ino;
+        meta.open(ReqContext { uid: 0, gid: 0 }, ino, 0)
+            .await
+            .unwrap();
+
+        // The attributes not newer than the cached ones, such as the ones
+        // written by this node, are ignored.
+        let node = meta.get_node_from_kv_engine(ino).await.unwrap().unwrap();
+        let mut attr = node.get_attr();
+        attr.size = 4096;
+        meta.refresh_open_file(attr);
+        assert_eq!(meta.mtime_and_size(ino).0, 0);
+
+        let mtime = SystemTime::now()
+            .checked_add(Duration::from_secs(1))
+            .unwrap();
+        attr.mtime = mtime;
+        meta.refresh_open_file(attr);
+        assert_eq!(meta.mtime_and_size(ino), (4096, mtime));
+    }
+}
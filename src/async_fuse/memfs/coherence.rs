@@ -0,0 +1,704 @@
+//! The cache coherence of files among the nodes of a volume.
+//!
+//! A node caches the data and the attributes of a file only while it holds a
+//! lease of the file. The leases are kept in the kv engine, a file is leased
+//! for reading to any number of nodes, or for writing to a single node.
+//!
+//! A node asking for a conflicting lease recalls the lease from its holders,
+//! and waits until they release it. A holder releases the lease by flushing
+//! the dirty data of the file, invalidating its caches and the caches of the
+//! kernel, so the node proceeding afterward sees every write of the holder.
+//! A holder not releasing the lease in `RECALL_TIMEOUT`, which is likely gone,
+//! is evicted from the lease.
+//!
+//! The leases are the delegations of the files as the ones of NFSv4. While
+//! holding a read delegation, a node serves the opens and the attribute
+//! queries of the file from its caches without the kv engine. A write
+//! delegation keeps the POSIX locks of the file locally as well, they're
+//! written back when the delegation is recalled.
+//!
+//! A volume of the close-to-open consistency takes no lease, its files are
+//! only flushed on close and revalidated on open, like NFS, so the accesses
+//! are not serialized among the nodes, and the changes of a node are seen by
+//! the others once they open the file after it's closed.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use datenlord::config::ConsistencyModel;
+use futures::StreamExt;
+use parking_lot::Mutex as SyncMutex;
+use serde::{Deserialize, Serialize};
+use tokio::select;
+use tokio::sync::{Mutex, OwnedRwLockReadGuard};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+use super::fs_util::FileAttr;
+use super::inode_lock::{InodeGuard, InodeLocks};
+use super::kv_engine::{KVEngine, KVEngineType, KeyType, MetaTxn, ValueType, WatchStream};
+use super::metadata::MetaData;
+use super::posix_lock::PosixLocks;
+use super::serial::serial_to_file_attr;
+use super::StorageType;
+#[cfg(feature = "abi-7-12")]
+use crate::async_fuse::fuse::fuse_reply::{FuseInvalEntryNotification, FuseInvalINodeNotification};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::common::error::DatenLordResult;
+
+/// The limit of retrying a transaction.
+const TXN_RETRY_LIMIT: u32 = 10;
+
+/// The interval between checking whether the recalled holders have released
+/// the lease.
+const RECALL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The time to wait for the recalled holders before evicting them.
+const RECALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The interval between checking the recalls of this node, in case the kv
+/// engine can't be watched.
+const RECALL_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The mode of a lease.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaseMode {
+    /// Shared with the other readers
+    Read,
+    /// Exclusive
+    Write,
+}
+
+impl LeaseMode {
+    /// Whether a lease of `self` allows the accesses of `mode`.
+    fn covers(self, mode: LeaseMode) -> bool {
+        self == LeaseMode::Write || mode == LeaseMode::Read
+    }
+
+    /// Whether a lease of `self` conflicts with a lease of `other` held by
+    /// another node.
+    fn conflicts(self, other: LeaseMode) -> bool {
+        self == LeaseMode::Write || other == LeaseMode::Write
+    }
+}
+
+/// The nodes holding the lease of a file.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct InodeLease {
+    /// Node id -> the mode of its lease
+    pub holders: BTreeMap<String, LeaseMode>,
+}
+
+impl InodeLease {
+    /// The holders other than `node_id` conflicting with a lease of `mode`.
+    fn conflicts(&self, node_id: &str, mode: LeaseMode) -> Vec<String> {
+        self.holders
+            .iter()
+            .filter(|&(holder, &held)| holder != node_id && mode.conflicts(held))
+            .map(|(holder, _)| holder.clone())
+            .collect()
+    }
+}
+
+/// A request to a node to release the lease of a file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LeaseRecall {
+    /// The i-number of the file
+    pub ino: INum,
+    /// The node asking for the lease
+    pub requester: String,
+}
+
+/// The leases of files held by a node.
+#[derive(Debug)]
+pub struct Coherence {
+    /// The id of this node
+    node_id: String,
+    /// The consistency among the nodes, the leases are only taken if it's
+    /// strict
+    consistency: ConsistencyModel,
+    /// The kv engine
+    kv_engine: Arc<KVEngineType>,
+    /// The leases held by this node
+    held: SyncMutex<HashMap<INum, LeaseMode>>,
+    /// The locks of the files being accessed under a lease, a lease is only
+    /// acquired or released with the exclusive lock
+    in_flight: InodeLocks,
+    /// The FUSE device to notify the kernel, it's set once the file system is
+    /// mounted
+    notifier: Mutex<Option<File>>,
+}
+
+impl Coherence {
+    /// Create a `Coherence` of node `node_id` holding no lease, which keeps
+    /// the caches coherent by `consistency`.
+    #[must_use]
+    pub fn new(kv_engine: Arc<KVEngineType>, node_id: &str, consistency: ConsistencyModel) -> Self {
+        Self {
+            node_id: node_id.to_owned(),
+            consistency,
+            kv_engine,
+            held: SyncMutex::new(HashMap::new()),
+            in_flight: InodeLocks::new(),
+            notifier: Mutex::new(None),
+        }
+    }
+
+    /// Whether the caches are kept coherent by the leases, rather than
+    /// revalidated on open.
+    pub fn is_strict(&self) -> bool {
+        self.consistency == ConsistencyModel::Strict
+    }
+
+    /// Set the FUSE device to notify the kernel.
+    pub async fn set_notifier(&self, file: File) {
+        *self.notifier.lock().await = Some(file);
+    }
+
+    /// The leases held by this node.
+    pub fn held_leases(&self) -> HashMap<INum, LeaseMode> {
+        self.held.lock().clone()
+    }
+
+    /// Whether this node holds a lease of `ino` allowing the accesses of
+    /// `mode`.
+    fn holds(&self, ino: INum, mode: LeaseMode) -> bool {
+        self.held
+            .lock()
+            .get(&ino)
+            .map_or(false, |held| held.covers(mode))
+    }
+
+    /// Try to add this node to the holders of `ino` in a transaction.
+    /// Returns the attributes of the file if the lease is acquired, or the
+    /// conflicting holders, which are recalled unless they are in `recalled`.
+    /// The conflicting holders are evicted instead if `evict` is true.
+    async fn try_acquire(
+        &self,
+        ino: INum,
+        mode: LeaseMode,
+        recalled: &HashSet<String>,
+        evict: bool,
+    ) -> DatenLordResult<Result<Option<FileAttr>, Vec<String>>> {
+        let key = KeyType::InodeLease(ino);
+        let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            let mut lease = txn
+                .get(&key)
+                .await?
+                .map_or_else(InodeLease::default, ValueType::into_inode_lease);
+            let conflicts = lease.conflicts(&self.node_id, mode);
+            if conflicts.is_empty() || evict {
+                for holder in &conflicts {
+                    lease.holders.remove(holder);
+                    txn.delete(&KeyType::LeaseRecall(holder.clone(), ino));
+                }
+                lease.holders.insert(self.node_id.clone(), mode);
+                txn.set(&key, &ValueType::InodeLease(lease));
+                let attr = txn
+                    .get(&KeyType::INum2Node(ino))
+                    .await?
+                    .map(|node| serial_to_file_attr(&node.into_serial_node().attr));
+                (txn.commit().await, Ok((attr, conflicts)))
+            } else {
+                let recall = ValueType::LeaseRecall(LeaseRecall {
+                    ino,
+                    requester: self.node_id.clone(),
+                });
+                let new_conflicts = conflicts
+                    .iter()
+                    .filter(|holder| !recalled.contains(*holder));
+                for holder in new_conflicts {
+                    txn.set(&KeyType::LeaseRecall(holder.clone(), ino), &recall);
+                }
+                (txn.commit().await, Err(conflicts))
+            }
+        });
+
+        Ok(res?.map(|(attr, evicted)| {
+            if !evicted.is_empty() {
+                warn!("Nodes {evicted:?} are evicted from the lease of ino={ino}.");
+            }
+            self.held.lock().insert(ino, mode);
+            attr
+        }))
+    }
+
+    /// Acquire a lease of `ino` for the accesses of `mode`, the conflicting
+    /// holders are recalled, and are evicted if they don't release the lease
+    /// in time. Returns the attributes of the file.
+    async fn acquire(&self, ino: INum, mode: LeaseMode) -> DatenLordResult<Option<FileAttr>> {
+        let deadline = Instant::now() + RECALL_TIMEOUT;
+        let mut recalled = HashSet::new();
+        loop {
+            let evict = Instant::now() >= deadline;
+            let res = {
+                // Serialize against the recalls of this node, and the lock
+                // isn't held while waiting for the other nodes.
+                let _guard = self.in_flight.write(ino).await;
+                if self.holds(ino, mode) {
+                    return Ok(None);
+                }
+                self.try_acquire(ino, mode, &recalled, evict).await?
+            };
+            match res {
+                Ok(attr) => return Ok(attr),
+                Err(conflicts) => {
+                    debug!("Waiting for nodes {conflicts:?} to release the lease of ino={ino}.");
+                    recalled.extend(conflicts);
+                    tokio::time::sleep(RECALL_POLL_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    /// Hold a lease of `ino` for the accesses of `mode` until the returned
+    /// guard is dropped, the lease is not released in the meantime. Returns
+    /// the attributes of the file as well if the lease is newly acquired, the
+    /// cached attributes may be stale if so.
+    pub async fn guard(
+        &self,
+        ino: INum,
+        mode: LeaseMode,
+    ) -> DatenLordResult<(InodeGuard<'_, OwnedRwLockReadGuard<()>>, Option<FileAttr>)> {
+        // No lease is taken under the close-to-open consistency.
+        if !self.is_strict() {
+            return Ok((self.in_flight.read(ino).await, None));
+        }
+        let mut attr = None;
+        loop {
+            if !self.holds(ino, mode) {
+                attr = self.acquire(ino, mode).await?.or(attr);
+            }
+            let guard = self.in_flight.read(ino).await;
+            // The lease may be recalled before the guard is taken.
+            if self.holds(ino, mode) {
+                return Ok((guard, attr));
+            }
+        }
+    }
+
+    /// Remove this node from the holders of `ino`, and remove the recall.
+    async fn release(&self, ino: INum) -> DatenLordResult<()> {
+        let key = KeyType::InodeLease(ino);
+        let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            if let Some(lease) = txn.get(&key).await? {
+                let mut lease = lease.into_inode_lease();
+                lease.holders.remove(&self.node_id);
+                if lease.holders.is_empty() {
+                    txn.delete(&key);
+                } else {
+                    txn.set(&key, &ValueType::InodeLease(lease));
+                }
+            }
+            txn.delete(&KeyType::LeaseRecall(self.node_id.clone(), ino));
+            (txn.commit().await, ())
+        });
+        res
+    }
+
+    /// Whether the caches of the kernel are invalidated once the leases are
+    /// recalled.
+    pub async fn invalidates_kernel_cache(&self) -> bool {
+        cfg!(feature = "abi-7-12") && self.notifier.lock().await.is_some()
+    }
+
+    /// Notify the kernel to invalidate the caches of `ino`.
+    async fn invalidate_kernel_cache(&self, ino: INum) {
+        #[cfg(feature = "abi-7-12")]
+        if let Some(ref mut file) = *self.notifier.lock().await {
+            if let Err(e) = FuseInvalINodeNotification::new(file)
+                .notify(ino, 0, 0)
+                .await
+            {
+                // The kernel doesn't cache the file.
+                debug!("Failed to invalidate the kernel cache of ino={ino}: {e}");
+            }
+        }
+        #[cfg(not(feature = "abi-7-12"))]
+        debug!("The kernel cache of ino={ino} is not invalidated without abi-7-12.");
+    }
+
+    /// Notify the kernel to invalidate the entry of `name` in directory
+    /// `parent`, the inode of which is forgotten once it's no longer used.
+    pub async fn invalidate_kernel_entry(&self, parent: INum, name: &str) {
+        #[cfg(feature = "abi-7-12")]
+        if let Some(ref mut file) = *self.notifier.lock().await {
+            if let Err(e) = FuseInvalEntryNotification::new(file)
+                .notify(parent, name)
+                .await
+            {
+                // The kernel doesn't cache the entry.
+                debug!("Failed to invalidate the kernel entry of {name:?} in ino={parent}: {e}");
+            }
+        }
+        #[cfg(not(feature = "abi-7-12"))]
+        debug!("The kernel entry of {name:?} in ino={parent} is not invalidated without abi-7-12.");
+    }
+
+    /// Release the leases recalled from this node, `release_caches` flushes
+    /// and invalidates the caches of a file before its lease is released.
+    /// Returns the number of released leases.
+    pub async fn handle_recalls<F, Fut>(&self, release_caches: F) -> DatenLordResult<usize>
+    where
+        F: Fn(INum) -> Fut + Send + Sync,
+        Fut: Future<Output = DatenLordResult<()>> + Send,
+    {
+        let recalls = self
+            .kv_engine
+            .range(&KeyType::NodeLeaseRecalls(self.node_id.clone()))
+            .await?;
+        let mut released = 0_usize;
+        for recall in recalls.into_iter().map(ValueType::into_lease_recall) {
+            let ino = recall.ino;
+            let held = self.release_with(ino, &release_caches).await?;
+            if held.is_some() {
+                released += 1;
+            }
+            debug!(
+                "The lease {held:?} of ino={ino} is released to node {}.",
+                recall.requester
+            );
+        }
+        Ok(released)
+    }
+
+    /// Release the lease of `ino` once `release_caches` flushes and
+    /// invalidates the caches of the file. Returns the mode of the lease, or
+    /// `None` if it's not held.
+    async fn release_with<F, Fut>(
+        &self,
+        ino: INum,
+        release_caches: &F,
+    ) -> DatenLordResult<Option<LeaseMode>>
+    where
+        F: Fn(INum) -> Fut + Send + Sync,
+        Fut: Future<Output = DatenLordResult<()>> + Send,
+    {
+        // The dirty pages of the kernel, such as the ones of the shared
+        // mappings, are written back by the invalidation under the lease, so
+        // it's not serialized against the accesses, whose writes it waits for.
+        if self.held.lock().contains_key(&ino) {
+            self.invalidate_kernel_cache(ino).await;
+        }
+        let held = {
+            let _guard = self.in_flight.write(ino).await;
+            let held = self.held.lock().get(&ino).copied();
+            if held.is_some() {
+                release_caches(ino).await?;
+                self.held.lock().remove(&ino);
+            }
+            self.release(ino).await?;
+            held
+        };
+        // The pages read meanwhile are dropped once the lease is released, a
+        // page written again reacquires it to be written back.
+        if held.is_some() {
+            self.invalidate_kernel_cache(ino).await;
+        }
+        Ok(held)
+    }
+
+    /// Release all leases held by this node like the recalled ones, with the
+    /// caches of the files flushed and invalidated by `release_caches`, when
+    /// the node is drained. Returns the number of released leases.
+    pub async fn release_all_with<F, Fut>(&self, release_caches: F) -> DatenLordResult<usize>
+    where
+        F: Fn(INum) -> Fut + Send + Sync,
+        Fut: Future<Output = DatenLordResult<()>> + Send,
+    {
+        let inos: Vec<INum> = self.held.lock().keys().copied().collect();
+        let mut released = 0_usize;
+        for ino in inos {
+            if self.release_with(ino, &release_caches).await?.is_some() {
+                released += 1;
+            }
+        }
+        Ok(released)
+    }
+
+    /// Release all leases held by this node, without flushing the caches.
+    pub async fn release_all(&self) -> DatenLordResult<()> {
+        let inos: Vec<INum> = self.held.lock().drain().map(|(ino, _)| ino).collect();
+        for ino in inos {
+            self.release(ino).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Watch the recalls of this node, `None` if the kv engine can't be watched.
+async fn watch_recalls(coherence: &Coherence) -> Option<WatchStream> {
+    match coherence
+        .kv_engine
+        .watch(&KeyType::NodeLeaseRecalls(coherence.node_id.clone()))
+        .await
+    {
+        Ok(stream) => Some(stream),
+        Err(e) => {
+            warn!("Failed to watch the lease recalls: {e}");
+            None
+        }
+    }
+}
+
+/// Release the leases recalled from this node, with the caches of `storage`
+/// flushed and invalidated, the locks of `posix_locks` written back and the
+/// files of `metadata` no longer delegated, until `token` is cancelled, then
+/// all leases of this node are released.
+#[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+pub async fn run_recall_handler<M: MetaData + Send + Sync + 'static>(
+    coherence: Arc<Coherence>,
+    storage: StorageType,
+    posix_locks: Arc<PosixLocks>,
+    metadata: Arc<M>,
+    token: CancellationToken,
+) {
+    let mut ticker = tokio::time::interval(RECALL_CHECK_INTERVAL);
+    let mut stream = None;
+
+    loop {
+        if stream.is_none() {
+            stream = watch_recalls(&coherence).await;
+        }
+        select! {
+            _ = ticker.tick() => {}
+            events = async {
+                match stream.as_mut() {
+                    Some(stream) => stream.next().await,
+                    None => futures::future::pending().await,
+                }
+            } => {
+                if !matches!(events, Some(Ok(_))) {
+                    warn!("The watch of lease recalls is closed or failed.");
+                    stream = None;
+                }
+            }
+            () = token.cancelled() => {
+                if let Err(e) = posix_locks.recall_all().await {
+                    error!("Failed to write back the delegated locks: {e}");
+                }
+                if let Err(e) = coherence.release_all().await {
+                    error!("Failed to release the leases: {e}");
+                }
+                info!("Recall handler exits.");
+                return;
+            }
+        }
+
+        let res = coherence
+            .handle_recalls(|ino| {
+                let storage = Arc::clone(&storage);
+                let posix_locks = Arc::clone(&posix_locks);
+                let metadata = Arc::clone(&metadata);
+                async move {
+                    storage.flush(ino).await?;
+                    storage.invalidate(ino).await?;
+                    posix_locks.recall(ino).await?;
+                    metadata.undelegate_open_file(ino);
+                    Ok(())
+                }
+            })
+            .await;
+        if let Err(e) = res {
+            error!("Failed to release the recalled leases: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    #[cfg(feature = "abi-7-12")]
+    use std::fs::File;
+    #[cfg(feature = "abi-7-12")]
+    use std::io::{ErrorKind, Read};
+    #[cfg(feature = "abi-7-12")]
+    use std::os::unix::net::UnixStream;
+    use std::sync::Arc;
+
+    use datenlord::config::ConsistencyModel;
+    #[cfg(feature = "abi-7-12")]
+    use nix::sys::socket::{self, AddressFamily, SockFlag, SockType};
+    use parking_lot::Mutex;
+
+    use super::{Coherence, LeaseMode, RECALL_POLL_INTERVAL};
+    #[cfg(feature = "abi-7-12")]
+    use crate::async_fuse::fuse::protocol::{FuseNotifyCode, INum};
+    use crate::async_fuse::memfs::kv_engine::{KVEngine, KeyType};
+    use crate::async_fuse::test::test_util::open_kv_engine;
+
+    /// The inode invalidated by the next notification read from the kernel
+    /// end of a mock FUSE device, `None` if no notification is sent.
+    #[cfg(feature = "abi-7-12")]
+    #[allow(clippy::as_conversions)] // allow this for enum
+    fn invalidated_ino(mut kernel_end: &UnixStream) -> Option<INum> {
+        let mut buf = [0_u8; 64];
+        let size = match kernel_end.read(&mut buf) {
+            Ok(size) => size,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return None,
+            Err(e) => panic!("failed to read the mock FUSE device: {e}"),
+        };
+        // The `fuse_out_header` carries the code of the notification in its
+        // error, followed by the `fuse_notify_inval_inode_out`.
+        assert_eq!(size, 40);
+        let code = i32::from_ne_bytes(buf.get(4..8).unwrap().try_into().unwrap());
+        assert_eq!(code, FuseNotifyCode::FUSE_NOTIFY_INVAL_INODE as i32);
+        Some(u64::from_ne_bytes(
+            buf.get(16..24).unwrap().try_into().unwrap(),
+        ))
+    }
+
+    #[test]
+    fn test_lease_mode() {
+        assert!(LeaseMode::Write.covers(LeaseMode::Read));
+        assert!(!LeaseMode::Read.covers(LeaseMode::Write));
+        assert!(!LeaseMode::Read.conflicts(LeaseMode::Read));
+        assert!(LeaseMode::Read.conflicts(LeaseMode::Write));
+        assert!(LeaseMode::Write.conflicts(LeaseMode::Read));
+    }
+
+    #[tokio::test]
+    async fn test_recall() {
+        let kv_engine = open_kv_engine();
+        let node1 = Arc::new(Coherence::new(
+            Arc::clone(&kv_engine),
+            "node1",
+            ConsistencyModel::Strict,
+        ));
+        let node2 = Arc::new(Coherence::new(
+            Arc::clone(&kv_engine),
+            "node2",
+            ConsistencyModel::Strict,
+        ));
+
+        // Readers share the lease.
+        drop(node1.guard(2, LeaseMode::Read).await.unwrap());
+        drop(node2.guard(2, LeaseMode::Read).await.unwrap());
+        assert_eq!(node1.handle_recalls(|_| async { Ok(()) }).await.unwrap(), 0);
+
+        // The writer waits for node1 to release the lease.
+        let writer = {
+            let node2 = Arc::clone(&node2);
+            tokio::spawn(async move {
+                let _guard = node2.guard(2, LeaseMode::Write).await.unwrap();
+            })
+        };
+        let released = Arc::new(Mutex::new(vec![]));
+        while !writer.is_finished() {
+            let released = Arc::clone(&released);
+            node1
+                .handle_recalls(move |ino| {
+                    released.lock().push(ino);
+                    async { Ok(()) }
+                })
+                .await
+                .unwrap();
+            tokio::time::sleep(RECALL_POLL_INTERVAL).await;
+        }
+        writer.await.unwrap();
+        assert_eq!(*released.lock(), vec![2]);
+        assert!(node1.held.lock().is_empty());
+        assert!(node2.holds(2, LeaseMode::Write));
+
+        // Released on exit.
+        node2.release_all().await.unwrap();
+        assert!(kv_engine
+            .get(&KeyType::InodeLease(2))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_close_to_open() {
+        let kv_engine = open_kv_engine();
+        let node1 = Coherence::new(
+            Arc::clone(&kv_engine),
+            "node1",
+            ConsistencyModel::CloseToOpen,
+        );
+        let node2 = Coherence::new(
+            Arc::clone(&kv_engine),
+            "node2",
+            ConsistencyModel::CloseToOpen,
+        );
+
+        // The writers don't wait for each other without the leases.
+        let (_guard1, attr) = node1.guard(2, LeaseMode::Write).await.unwrap();
+        assert!(attr.is_none());
+        let _guard2 = node2.guard(2, LeaseMode::Write).await.unwrap();
+        assert!(!node1.holds(2, LeaseMode::Read));
+        assert!(kv_engine
+            .get(&KeyType::InodeLease(2))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[cfg(feature = "abi-7-12")]
+    #[tokio::test]
+    async fn test_recall_invalidation() {
+        let kv_engine = open_kv_engine();
+        let node1 = Coherence::new(Arc::clone(&kv_engine), "node1", ConsistencyModel::Strict);
+        let node2 = Arc::new(Coherence::new(
+            Arc::clone(&kv_engine),
+            "node2",
+            ConsistencyModel::Strict,
+        ));
+        let (kernel_end, session_end) = socket::socketpair(
+            AddressFamily::Unix,
+            SockType::SeqPacket,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )
+        .unwrap();
+        let kernel_end = UnixStream::from(kernel_end);
+        kernel_end.set_nonblocking(true).unwrap();
+        node1.set_notifier(File::from(session_end)).await;
+        assert!(node1.invalidates_kernel_cache().await);
+
+        drop(node1.guard(2, LeaseMode::Read).await.unwrap());
+        let writer = {
+            let node2 = Arc::clone(&node2);
+            tokio::spawn(async move {
+                let _guard = node2.guard(2, LeaseMode::Write).await.unwrap();
+            })
+        };
+        let released = Arc::new(Mutex::new(vec![]));
+        while !writer.is_finished() {
+            node1
+                .handle_recalls(|ino| {
+                    // The kernel is notified before the caches of the node
+                    // are released, while the lease is still held.
+                    assert_eq!(invalidated_ino(&kernel_end), Some(ino));
+                    released.lock().push(ino);
+                    let kv_engine = Arc::clone(&kv_engine);
+                    async move {
+                        let lease = kv_engine
+                            .get(&KeyType::InodeLease(ino))
+                            .await?
+                            .unwrap()
+                            .into_inode_lease();
+                        assert!(lease.holders.contains_key("node1"));
+                        Ok(())
+                    }
+                })
+                .await
+                .unwrap();
+            tokio::time::sleep(RECALL_POLL_INTERVAL).await;
+        }
+        writer.await.unwrap();
+        assert_eq!(*released.lock(), vec![2]);
+
+        // The pages read meanwhile are dropped once the lease is released.
+        assert_eq!(invalidated_ino(&kernel_end), Some(2));
+        assert_eq!(invalidated_ino(&kernel_end), None);
+        node2.release_all().await.unwrap();
+    }
+}
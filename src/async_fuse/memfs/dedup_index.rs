@@ -0,0 +1,146 @@
+//! The `DedupIndex` persisted in the kv engine.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use super::kv_engine::{KVEngine, KVEngineType, KeyType, MetaTxn, ValueType};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::common::error::DatenLordResult;
+use crate::storage::dedup::{BlockRecipe, ChunkHash, DedupIndex, RefDeltas};
+use crate::storage::error::{StorageError, StorageResult};
+
+/// The limit of retrying a transaction.
+const TXN_RETRY_LIMIT: u32 = 10;
+
+/// Apply `deltas` to the refcounts in `txn`, returns the chunks no longer
+/// referenced.
+pub(super) async fn apply_deltas(
+    txn: &mut (dyn MetaTxn + Send),
+    deltas: &RefDeltas,
+) -> DatenLordResult<Vec<ChunkHash>> {
+    let mut orphans = vec![];
+    for (hash, delta) in deltas.iter() {
+        let key = KeyType::ChunkRefCount(hash);
+        let refcount = txn
+            .get(&key)
+            .await?
+            .map_or(0, ValueType::into_chunk_ref_count)
+            .saturating_add_signed(delta);
+        if refcount == 0 {
+            txn.delete(&key);
+            orphans.push(hash);
+        } else {
+            txn.set(&key, &ValueType::ChunkRefCount(refcount));
+        }
+    }
+    Ok(orphans)
+}
+
+/// A `DedupIndex` persisted in the kv engine, the recipes and refcounts are
+/// shared among all nodes of the volume.
+#[derive(Debug)]
+pub struct KvDedupIndex {
+    /// The kv engine
+    kv_engine: Arc<KVEngineType>,
+}
+
+impl KvDedupIndex {
+    /// Create a `KvDedupIndex`.
+    #[must_use]
+    pub fn new(kv_engine: Arc<KVEngineType>) -> Self {
+        Self { kv_engine }
+    }
+
+    /// Set the recipe of a block in a transaction.
+    async fn set_recipe_impl(
+        &self,
+        ino: INum,
+        recipe: &BlockRecipe,
+    ) -> DatenLordResult<Vec<ChunkHash>> {
+        let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            let key = KeyType::BlockRecipe(ino, recipe.block_id);
+            let mut deltas = RefDeltas::default();
+            deltas.add(recipe);
+            if let Some(old) = txn.get(&key).await? {
+                deltas.sub(&old.into_block_recipe());
+            }
+            let orphans = apply_deltas(txn.as_mut(), &deltas).await?;
+            txn.set(&key, &ValueType::BlockRecipe(recipe.clone()));
+            (txn.commit().await, orphans)
+        });
+        res
+    }
+
+    /// Remove the recipes of blocks in a transaction.
+    async fn remove_recipes_impl(
+        &self,
+        ino: INum,
+        block_ids: Range<usize>,
+    ) -> DatenLordResult<Vec<ChunkHash>> {
+        let block_ids: Vec<usize> = self
+            .kv_engine
+            .range(&KeyType::FileBlockRecipes(ino))
+            .await?
+            .into_iter()
+            .map(|value| value.into_block_recipe().block_id)
+            .filter(|block_id| block_ids.contains(block_id))
+            .collect();
+        if block_ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            let mut deltas = RefDeltas::default();
+            for &block_id in &block_ids {
+                let key = KeyType::BlockRecipe(ino, block_id);
+                if let Some(old) = txn.get(&key).await? {
+                    deltas.sub(&old.into_block_recipe());
+                    txn.delete(&key);
+                }
+            }
+            let orphans = apply_deltas(txn.as_mut(), &deltas).await?;
+            (txn.commit().await, orphans)
+        });
+        res
+    }
+}
+
+#[async_trait]
+impl DedupIndex for KvDedupIndex {
+    async fn get_recipe(&self, ino: INum, block_id: usize) -> StorageResult<Option<BlockRecipe>> {
+        let value = self
+            .kv_engine
+            .get(&KeyType::BlockRecipe(ino, block_id))
+            .await;
+        value
+            .map_err(StorageError::from)
+            .map(|value| value.map(ValueType::into_block_recipe))
+    }
+
+    async fn contains_chunk(&self, hash: ChunkHash) -> StorageResult<bool> {
+        let value = self.kv_engine.get(&KeyType::ChunkRefCount(hash)).await;
+        value
+            .map_err(StorageError::from)
+            .map(|value| value.is_some())
+    }
+
+    async fn set_recipe(&self, ino: INum, recipe: BlockRecipe) -> StorageResult<Vec<ChunkHash>> {
+        self.set_recipe_impl(ino, &recipe)
+            .await
+            .map_err(StorageError::from)
+    }
+
+    async fn remove_recipes(
+        &self,
+        ino: INum,
+        block_ids: Range<usize>,
+    ) -> StorageResult<Vec<ChunkHash>> {
+        self.remove_recipes_impl(ino, block_ids)
+            .await
+            .map_err(StorageError::from)
+    }
+}
@@ -0,0 +1,322 @@
+//! The cache of directory entries.
+//!
+//! Looking up names is the hottest metadata operation, as every component of
+//! every path opened is looked up, so the entries found are cached in memory
+//! instead of being read from the kv engine every time. The cache is sharded
+//! by the directories, and each shard is bounded by the bytes of its entries,
+//! which are evicted by the CLOCK algorithm, so a lookup only takes the shared
+//! lock of its shard.
+//!
+//! The entries of a directory are dropped once it's changed, either by this
+//! node or by others, whose changes are watched in the kv engine. As the
+//! lookups are not serialized with the changes, each shard has a sequence
+//! increased by every invalidation like a seqlock, and an entry read before
+//! an invalidation is not cached after it.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use clippy_utilities::{Cast, OverflowArithmetic};
+use datenlord::metrics::CACHE_METRICS;
+use hashlink::LinkedHashMap;
+use parking_lot::RwLock;
+
+use crate::async_fuse::fuse::protocol::INum;
+
+/// The number of the shards of the cache.
+const DENTRY_CACHE_SHARDS: usize = 16;
+
+/// The bytes taken by an entry besides its name.
+const DENTRY_OVERHEAD: usize = 64;
+
+/// The name of the eviction policy in the metrics.
+const DENTRY_CACHE_POLICY: &str = "clock";
+
+/// A cached directory entry.
+#[derive(Debug)]
+struct Dentry {
+    /// The name of the entry, which is compared as different names may have
+    /// the same hash
+    name: Box<str>,
+    /// The i-number of the child
+    ino: INum,
+    /// Whether the entry is looked up since it's inserted or given the
+    /// second chance
+    referenced: AtomicBool,
+}
+
+impl Dentry {
+    /// The bytes taken by the entry.
+    fn size(&self) -> usize {
+        self.name.len().overflow_add(DENTRY_OVERHEAD)
+    }
+}
+
+/// The inner state of a shard.
+#[derive(Debug, Default)]
+struct ShardInner {
+    /// The entries by their directories and the hashes of their names, in
+    /// the order they're inserted or given the second chance
+    entries: LinkedHashMap<(INum, u64), Dentry>,
+    /// The hashes of the names of the cached entries in each directory
+    dirs: HashMap<INum, HashSet<u64>>,
+    /// The bytes taken by the entries
+    size: usize,
+}
+
+impl ShardInner {
+    /// Remove the entry of `key`.
+    fn remove(&mut self, key: (INum, u64)) {
+        if let Some(dentry) = self.entries.remove(&key) {
+            self.size = self.size.overflow_sub(dentry.size());
+            self.forget_hash(key);
+        }
+    }
+
+    /// Remove the hash of `key` from its directory.
+    fn forget_hash(&mut self, (parent, hash): (INum, u64)) {
+        if let Some(hashes) = self.dirs.get_mut(&parent) {
+            hashes.remove(&hash);
+            if hashes.is_empty() {
+                self.dirs.remove(&parent);
+            }
+        }
+    }
+
+    /// Evict the entries not referenced since their last chance, until the
+    /// bytes taken are within `capacity`.
+    ///
+    /// Returns the number of the evicted entries.
+    fn evict(&mut self, capacity: usize) -> usize {
+        let mut evicted: usize = 0;
+        while self.size > capacity {
+            let Some((key, mut dentry)) = self.entries.pop_front() else {
+                break;
+            };
+            if std::mem::take(dentry.referenced.get_mut()) {
+                // The second chance
+                self.entries.insert(key, dentry);
+            } else {
+                self.size = self.size.overflow_sub(dentry.size());
+                self.forget_hash(key);
+                evicted = evicted.overflow_add(1);
+            }
+        }
+        evicted
+    }
+}
+
+/// A shard of the cache.
+#[derive(Debug, Default)]
+struct Shard {
+    /// Increased by every invalidation, while the write lock is held
+    seq: AtomicU64,
+    /// The inner state
+    inner: RwLock<ShardInner>,
+}
+
+/// The cache of directory entries, from the names in directories to the
+/// i-numbers of the children.
+#[derive(Debug)]
+pub(super) struct DentryCache {
+    /// The shards by the directories
+    shards: Vec<Shard>,
+    /// The max bytes taken by the entries of a shard
+    shard_capacity: usize,
+}
+
+impl DentryCache {
+    /// Create a `DentryCache` whose entries take at most `capacity` bytes.
+    pub(super) fn new(capacity: usize) -> Self {
+        Self {
+            shards: (0..DENTRY_CACHE_SHARDS).map(|_| Shard::default()).collect(),
+            shard_capacity: capacity.overflow_div(DENTRY_CACHE_SHARDS),
+        }
+    }
+
+    /// The shard of the entries of directory `parent`.
+    fn shard(&self, parent: INum) -> &Shard {
+        let index = parent.cast::<usize>().wrapping_rem(DENTRY_CACHE_SHARDS);
+        self.shards
+            .get(index)
+            .unwrap_or_else(|| unreachable!("The index is less than the number of shards."))
+    }
+
+    /// The hash of `name`.
+    fn hash_name(name: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        name.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// The bytes taken by the cached entries.
+    pub(super) fn size(&self) -> usize {
+        self.shards
+            .iter()
+            .fold(0, |size, shard| size.overflow_add(shard.inner.read().size))
+    }
+
+    /// Get the i-number of `name` in directory `parent`.
+    pub(super) fn lookup(&self, parent: INum, name: &str) -> Option<INum> {
+        let inner = self.shard(parent).inner.read();
+        let ino = inner
+            .entries
+            .get(&(parent, Self::hash_name(name)))
+            .filter(|dentry| &*dentry.name == name)
+            .map(|dentry| {
+                dentry.referenced.store(true, Ordering::Relaxed);
+                dentry.ino
+            });
+        if ino.is_some() {
+            CACHE_METRICS.cache_hit_count_inc("dentry", DENTRY_CACHE_POLICY);
+        } else {
+            CACHE_METRICS.cache_miss_count_inc("dentry", DENTRY_CACHE_POLICY);
+        }
+        ino
+    }
+
+    /// The sequence of the shard of directory `parent`, which is passed to
+    /// `insert()` after the entry is read.
+    pub(super) fn seq(&self, parent: INum) -> u64 {
+        self.shard(parent).seq.load(Ordering::Acquire)
+    }
+
+    /// Cache `name` in directory `parent` read since `seq`, it's not cached if
+    /// the shard is invalidated meanwhile.
+    pub(super) fn insert(&self, seq: u64, parent: INum, name: &str, ino: INum) {
+        let shard = self.shard(parent);
+        let mut inner = shard.inner.write();
+        if shard.seq.load(Ordering::Acquire) != seq {
+            return;
+        }
+
+        let hash = Self::hash_name(name);
+        // A name with the same hash is replaced
+        inner.remove((parent, hash));
+        let dentry = Dentry {
+            name: name.into(),
+            ino,
+            referenced: AtomicBool::new(false),
+        };
+        inner.size = inner.size.overflow_add(dentry.size());
+        inner.entries.insert((parent, hash), dentry);
+        inner.dirs.entry(parent).or_default().insert(hash);
+
+        let evicted = inner.evict(self.shard_capacity);
+        if evicted > 0 {
+            CACHE_METRICS.cache_eviction_count_inc_by("dentry", DENTRY_CACHE_POLICY, evicted);
+        }
+    }
+
+    /// Drop the entry of `name` in directory `parent` once it's removed or
+    /// renamed.
+    pub(super) fn invalidate(&self, parent: INum, name: &str) {
+        let shard = self.shard(parent);
+        let mut inner = shard.inner.write();
+        shard.seq.fetch_add(1, Ordering::Release);
+        inner.remove((parent, Self::hash_name(name)));
+    }
+
+    /// Drop at most `max` entries under memory pressure, the oldest ones of
+    /// every shard first.
+    ///
+    /// Returns the directories and the names of the dropped entries.
+    pub(super) fn shrink(&self, max: usize) -> Vec<(INum, String)> {
+        let per_shard = max.overflow_div(DENTRY_CACHE_SHARDS).max(1);
+        let mut dropped = vec![];
+        for shard in &self.shards {
+            let mut inner = shard.inner.write();
+            for _ in 0..per_shard {
+                if dropped.len() >= max {
+                    return dropped;
+                }
+                let Some((key, dentry)) = inner.entries.pop_front() else {
+                    break;
+                };
+                inner.size = inner.size.overflow_sub(dentry.size());
+                inner.forget_hash(key);
+                dropped.push((key.0, String::from(dentry.name)));
+            }
+        }
+        dropped
+    }
+
+    /// Drop the entries of directory `parent` once it's changed.
+    pub(super) fn invalidate_dir(&self, parent: INum) {
+        let shard = self.shard(parent);
+        let mut inner = shard.inner.write();
+        shard.seq.fetch_add(1, Ordering::Release);
+        for hash in inner.dirs.remove(&parent).unwrap_or_default() {
+            if let Some(dentry) = inner.entries.remove(&(parent, hash)) {
+                inner.size = inner.size.overflow_sub(dentry.size());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DentryCache, DENTRY_CACHE_SHARDS, DENTRY_OVERHEAD};
+
+    #[test]
+    fn test_lookup_and_invalidate() {
+        let cache = DentryCache::new(1 << 20);
+        cache.insert(cache.seq(1), 1, "a", 2);
+        cache.insert(cache.seq(1), 1, "b", 3);
+        cache.insert(cache.seq(4), 4, "a", 5);
+        assert_eq!(cache.lookup(1, "a"), Some(2));
+        assert_eq!(cache.lookup(4, "a"), Some(5));
+        assert_eq!(cache.lookup(1, "c"), None);
+
+        cache.invalidate(1, "a");
+        assert_eq!(cache.lookup(1, "a"), None);
+        assert_eq!(cache.lookup(1, "b"), Some(3));
+
+        cache.invalidate_dir(1);
+        assert_eq!(cache.lookup(1, "b"), None);
+        assert_eq!(cache.lookup(4, "a"), Some(5));
+
+        // An entry read before an invalidation is not cached.
+        let seq = cache.seq(1);
+        cache.invalidate(1, "b");
+        cache.insert(seq, 1, "b", 3);
+        assert_eq!(cache.lookup(1, "b"), None);
+    }
+
+    #[test]
+    fn test_eviction() {
+        // Every shard holds two entries of one-byte names.
+        let cache = DentryCache::new(2080);
+        assert_eq!(2080, DENTRY_CACHE_SHARDS * 2 * (DENTRY_OVERHEAD + 1));
+        cache.insert(cache.seq(1), 1, "a", 2);
+        cache.insert(cache.seq(1), 1, "b", 3);
+        // The referenced entry is given the second chance.
+        assert_eq!(cache.lookup(1, "a"), Some(2));
+        cache.insert(cache.seq(1), 1, "c", 4);
+        assert_eq!(cache.lookup(1, "a"), Some(2));
+        assert_eq!(cache.lookup(1, "b"), None);
+        assert_eq!(cache.lookup(1, "c"), Some(4));
+        assert_eq!(cache.size(), 2 * (DENTRY_OVERHEAD + 1));
+    }
+
+    #[test]
+    fn test_shrink() {
+        let cache = DentryCache::new(1 << 20);
+        cache.insert(cache.seq(1), 1, "a", 2);
+        cache.insert(cache.seq(1), 1, "b", 3);
+        cache.insert(cache.seq(2), 2, "c", 4);
+
+        // The oldest entry of every shard is dropped first.
+        let mut dropped = cache.shrink(DENTRY_CACHE_SHARDS);
+        dropped.sort();
+        assert_eq!(dropped, vec![(1, "a".to_owned()), (2, "c".to_owned())]);
+        assert_eq!(cache.lookup(1, "a"), None);
+        assert_eq!(cache.lookup(1, "b"), Some(3));
+        assert_eq!(cache.size(), DENTRY_OVERHEAD + 1);
+
+        assert_eq!(cache.shrink(1), vec![(1, "b".to_owned())]);
+        assert!(cache.shrink(1).is_empty());
+    }
+}
@@ -0,0 +1,204 @@
+//! The drain of a node before it's shut down.
+//!
+//! A node draining refuses the writes with `EROFS`, flushes all the dirty
+//! data of its caches to the backend, writes the delegated POSIX locks back to
+//! the kv engine, and releases the leases of its files with their caches
+//! flushed and the delegated opens recorded, so nothing acknowledged is lost
+//! and the other nodes don't wait for the recalls once the node is gone. The
+//! writes accepted before the node is fenced are flushed by a second pass.
+//!
+//! The drain is requested by the drain role on the control socket, e.g. by
+//! the `preStop` hook of the node DaemonSet, which is replied by its progress
+//! until the node is drained, so a drain of Kubernetes waits for it.
+
+use std::sync::Arc;
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::select;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use super::coherence::Coherence;
+use super::metadata::MetaData;
+use super::posix_lock::PosixLocks;
+use super::StorageType;
+use crate::common::error::DatenLordResult;
+
+/// The drain of this process.
+pub static DRAIN: Lazy<Drain> = Lazy::new(Drain::default);
+
+/// The stages of a drain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DrainStage {
+    /// The node is not draining
+    Running,
+    /// The dirty data is being flushed to the backend
+    Flushing,
+    /// The delegated locks are being written back to the kv engine
+    WritingBackLocks,
+    /// The leases are being released
+    ReleasingLeases,
+    /// The writes accepted before the node is fenced are being flushed
+    FlushingStragglers,
+    /// Everything is handed off, the node can be shut down
+    Drained,
+    /// The drain failed for the error
+    Failed(String),
+}
+
+/// The progress of a drain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DrainProgress {
+    /// The stage reached
+    pub stage: DrainStage,
+    /// The files whose delegated locks are written back
+    pub locks_written_back: usize,
+    /// The leases released
+    pub leases_released: usize,
+}
+
+impl DrainProgress {
+    /// Whether the drain is finished, drained or failed.
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        matches!(self.stage, DrainStage::Drained | DrainStage::Failed(_))
+    }
+}
+
+/// The drain of a node, which is started once and never stopped.
+#[derive(Debug)]
+pub struct Drain {
+    /// The progress of the drain
+    progress: watch::Sender<DrainProgress>,
+}
+
+impl Default for Drain {
+    fn default() -> Self {
+        let (progress, _) = watch::channel(DrainProgress {
+            stage: DrainStage::Running,
+            locks_written_back: 0,
+            leases_released: 0,
+        });
+        Self { progress }
+    }
+}
+
+impl Drain {
+    /// Whether the node is draining or drained, when the writes are refused.
+    #[must_use]
+    pub fn is_draining(&self) -> bool {
+        self.progress.borrow().stage != DrainStage::Running
+    }
+
+    /// Start the drain if it's not started, and watch its progress.
+    #[must_use]
+    pub fn start(&self) -> watch::Receiver<DrainProgress> {
+        self.progress.send_if_modified(|progress| {
+            if progress.stage == DrainStage::Running {
+                info!("The node starts draining.");
+                progress.stage = DrainStage::Flushing;
+                true
+            } else {
+                false
+            }
+        });
+        self.progress.subscribe()
+    }
+
+    /// Update the progress of the drain.
+    fn report(&self, update: impl FnOnce(&mut DrainProgress)) {
+        self.progress.send_modify(update);
+    }
+}
+
+/// Hand off the dirty data of `storage`, the locks of `posix_locks` and the
+/// leases of `coherence`, with the opens of `metadata` recorded.
+async fn drain<M: MetaData + Send + Sync + 'static>(
+    storage: &StorageType,
+    coherence: &Coherence,
+    posix_locks: &PosixLocks,
+    metadata: &Arc<M>,
+) -> DatenLordResult<()> {
+    storage.flush_all().await?;
+
+    DRAIN.report(|progress| progress.stage = DrainStage::WritingBackLocks);
+    let locks = posix_locks.recall_all().await?;
+
+    DRAIN.report(|progress| {
+        progress.stage = DrainStage::ReleasingLeases;
+        progress.locks_written_back = locks;
+    });
+    let leases = coherence
+        .release_all_with(|ino| {
+            let storage = Arc::clone(storage);
+            let metadata = Arc::clone(metadata);
+            async move {
+                storage.flush(ino).await?;
+                storage.invalidate(ino).await?;
+                metadata.undelegate_open_file(ino);
+                Ok(())
+            }
+        })
+        .await?;
+
+    DRAIN.report(|progress| {
+        progress.stage = DrainStage::FlushingStragglers;
+        progress.leases_released = leases;
+    });
+    storage.flush_all().await
+}
+
+/// Drain the node once it's requested, until `token` is cancelled.
+#[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+pub async fn run_drainer<M: MetaData + Send + Sync + 'static>(
+    storage: StorageType,
+    coherence: Arc<Coherence>,
+    posix_locks: Arc<PosixLocks>,
+    metadata: Arc<M>,
+    token: CancellationToken,
+) {
+    let mut progress = DRAIN.progress.subscribe();
+    select! {
+        res = progress.wait_for(|progress| progress.stage != DrainStage::Running) => {
+            if res.is_err() {
+                return;
+            }
+        }
+        () = token.cancelled() => return,
+    }
+
+    match drain(&storage, &coherence, &posix_locks, &metadata).await {
+        Ok(()) => {
+            DRAIN.report(|progress| progress.stage = DrainStage::Drained);
+            info!("The node is drained.");
+        }
+        Err(e) => {
+            error!("Failed to drain the node: {e}");
+            DRAIN.report(|progress| progress.stage = DrainStage::Failed(e.to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Drain, DrainStage};
+
+    #[test]
+    fn test_drain_start() {
+        let drain = Drain::default();
+        assert!(!drain.is_draining());
+
+        let progress = drain.start();
+        assert!(drain.is_draining());
+        assert_eq!(progress.borrow().stage, DrainStage::Flushing);
+        assert!(!progress.borrow().is_finished());
+
+        // A drain is started only once.
+        drain.report(|progress| progress.stage = DrainStage::Drained);
+        let progress = drain.start();
+        assert_eq!(progress.borrow().stage, DrainStage::Drained);
+        assert!(progress.borrow().is_finished());
+    }
+}
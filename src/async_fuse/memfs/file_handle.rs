@@ -0,0 +1,213 @@
+//! The handles of opened files.
+//!
+//! Every open of a file allocates a handle, whose index in the slab is the
+//! `fh` passed by the kernel to the following operations, until the handle is
+//! released. The state of an open is kept by its handle, such as the flags it's
+//! opened with, the read-ahead of the reads via it, and the ranges written via
+//! it but not flushed yet. The slots of the released handles are reused.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use clippy_utilities::{Cast, OverflowArithmetic};
+use nix::fcntl::OFlag;
+use parking_lot::Mutex;
+
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::ReadAheadState;
+
+/// The handle of an opened file.
+#[derive(Debug)]
+pub(super) struct FileHandle {
+    /// The i-number of the file
+    ino: INum,
+    /// The flags the file is opened with
+    flags: OFlag,
+    /// The read-ahead of the reads via the handle
+    read_ahead: Mutex<ReadAheadState>,
+    /// The ranges written via the handle but not flushed yet, sorted and not
+    /// overlapping
+    dirty: Mutex<Vec<Range<u64>>>,
+}
+
+impl FileHandle {
+    /// Whether the file is opened for writing.
+    pub(super) fn is_writable(&self) -> bool {
+        self.flags.intersects(OFlag::O_WRONLY | OFlag::O_RDWR)
+    }
+
+    /// The read-ahead of the reads via the handle.
+    pub(super) fn read_ahead(&self) -> &Mutex<ReadAheadState> {
+        &self.read_ahead
+    }
+
+    /// Record a write of `len` bytes at `offset` via the handle.
+    pub(super) fn mark_dirty(&self, offset: u64, len: u64) {
+        if len == 0 {
+            return;
+        }
+        let mut dirty = self.dirty.lock();
+        let mut range = offset..offset.overflow_add(len);
+        // Merge the ranges overlapping or adjacent to the written one
+        dirty.retain(|r| {
+            if r.end < range.start || r.start > range.end {
+                true
+            } else {
+                range.start = range.start.min(r.start);
+                range.end = range.end.max(r.end);
+                false
+            }
+        });
+        let index = dirty.partition_point(|r| r.start < range.start);
+        dirty.insert(index, range);
+    }
+
+    /// Whether anything is written via the handle but not flushed yet.
+    pub(super) fn is_dirty(&self) -> bool {
+        !self.dirty.lock().is_empty()
+    }
+
+    /// Forget the ranges written once they're flushed.
+    pub(super) fn clear_dirty(&self) {
+        self.dirty.lock().clear();
+    }
+}
+
+/// The inner state of `FileHandles`.
+#[derive(Debug, Default)]
+struct FileHandlesInner {
+    /// The slots of the handles by their `fh`
+    slots: Vec<Option<Arc<FileHandle>>>,
+    /// The slots of the released handles
+    free: Vec<usize>,
+}
+
+/// The slab of the handles of opened files.
+#[derive(Debug, Default)]
+pub(super) struct FileHandles {
+    /// The inner state
+    inner: Mutex<FileHandlesInner>,
+}
+
+impl FileHandles {
+    /// Create an empty `FileHandles`.
+    pub(super) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allocate a handle of `ino` opened with `flags`, returns its `fh`.
+    pub(super) fn open(&self, ino: INum, flags: u32) -> u64 {
+        let handle = Arc::new(FileHandle {
+            ino,
+            flags: OFlag::from_bits_truncate(flags.cast()),
+            read_ahead: Mutex::new(ReadAheadState::default()),
+            dirty: Mutex::new(vec![]),
+        });
+        let mut inner = self.inner.lock();
+        let index = if let Some(index) = inner.free.pop() {
+            if let Some(slot) = inner.slots.get_mut(index) {
+                *slot = Some(handle);
+            }
+            index
+        } else {
+            inner.slots.push(Some(handle));
+            inner.slots.len().overflow_sub(1)
+        };
+        index.cast()
+    }
+
+    /// Get the handle of `fh` opened for `ino`.
+    pub(super) fn get(&self, ino: INum, fh: u64) -> Option<Arc<FileHandle>> {
+        let inner = self.inner.lock();
+        inner
+            .slots
+            .get(fh.cast::<usize>())
+            .and_then(Option::as_ref)
+            .filter(|handle| handle.ino == ino)
+            .map(Arc::clone)
+    }
+
+    /// Release the handle of `fh` opened for `ino`, its slot is reused by the
+    /// following opens.
+    pub(super) fn release(&self, ino: INum, fh: u64) -> Option<Arc<FileHandle>> {
+        let mut inner = self.inner.lock();
+        let index: usize = fh.cast();
+        let slot = inner.slots.get_mut(index)?;
+        if slot.as_ref().map_or(true, |handle| handle.ino != ino) {
+            return None;
+        }
+        let handle = slot.take();
+        inner.free.push(index);
+        handle
+    }
+
+    /// The i-numbers of the handles opened, and whether they're opened for
+    /// writing.
+    pub(super) fn opened(&self) -> Vec<(INum, bool)> {
+        let inner = self.inner.lock();
+        inner
+            .slots
+            .iter()
+            .flatten()
+            .map(|handle| (handle.ino, handle.is_writable()))
+            .collect()
+    }
+
+    /// Forget the ranges written via the handles of `ino` once the file is
+    /// flushed.
+    pub(super) fn clear_dirty(&self, ino: INum) {
+        let inner = self.inner.lock();
+        for handle in inner.slots.iter().flatten() {
+            if handle.ino == ino {
+                handle.clear_dirty();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use clippy_utilities::Cast;
+
+    use super::FileHandles;
+
+    #[test]
+    fn test_file_handles() {
+        let handles = FileHandles::new();
+        let fh1 = handles.open(2, libc::O_RDONLY.cast());
+        let fh2 = handles.open(3, libc::O_RDWR.cast());
+        assert_ne!(fh1, fh2);
+        assert!(!handles.get(2, fh1).unwrap().is_writable());
+        assert!(handles.get(3, fh2).unwrap().is_writable());
+        // A handle is not found for another file.
+        assert!(handles.get(3, fh1).is_none());
+
+        assert_eq!(handles.opened(), [(2, false), (3, true)]);
+
+        // The slot of a released handle is reused.
+        assert!(handles.release(2, fh1).is_some());
+        assert!(handles.get(2, fh1).is_none());
+        assert!(handles.release(2, fh1).is_none());
+        assert_eq!(handles.open(4, 0), fh1);
+    }
+
+    #[test]
+    fn test_dirty_ranges() {
+        let handles = FileHandles::new();
+        let fh = handles.open(2, libc::O_WRONLY.cast());
+        let handle = handles.get(2, fh).unwrap();
+        assert!(!handle.is_dirty());
+
+        handle.mark_dirty(10, 5);
+        handle.mark_dirty(0, 4);
+        handle.mark_dirty(4, 6);
+        handle.mark_dirty(30, 0);
+        assert_eq!(*handle.dirty.lock(), vec![0..15]);
+        handle.mark_dirty(20, 5);
+        assert_eq!(*handle.dirty.lock(), vec![0..15, 20..25]);
+
+        handles.clear_dirty(2);
+        assert!(!handle.is_dirty());
+    }
+}
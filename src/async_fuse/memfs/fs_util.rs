@@ -293,6 +293,8 @@ pub fn parse_mode(mode: u32) -> Mode {
 
     #[cfg(target_os = "linux")]
     let file_mode = Mode::from_bits_truncate(mode);
+    #[cfg(any(target_os = "macos", target_os = "freebsd"))]
+    let file_mode = Mode::from_bits_truncate(mode.cast());
     debug!("parse_mode() read mode={:?}", file_mode);
     file_mode
 }
@@ -301,6 +303,8 @@ pub fn parse_mode(mode: u32) -> Mode {
 pub fn parse_mode_bits(mode: u32) -> u16 {
     #[cfg(target_os = "linux")]
     let bits = parse_mode(mode).bits().cast();
+    #[cfg(any(target_os = "macos", target_os = "freebsd"))]
+    let bits = parse_mode(mode).bits();
 
     bits
 }
@@ -336,14 +340,20 @@ pub fn convert_to_fuse_attr(attr: FileAttr) -> FuseAttr {
         atime: a_time_secs,
         mtime: m_time_secs,
         ctime: c_time_secs,
+        #[cfg(target_os = "macos")]
+        crtime: c_time_secs,
         atimensec: a_time_nanos,
         mtimensec: m_time_nanos,
         ctimensec: c_time_nanos,
+        #[cfg(target_os = "macos")]
+        crtimensec: c_time_nanos,
         mode: crate::async_fuse::util::mode_from_kind_and_perm(attr.kind, attr.perm),
         nlink: attr.nlink,
         uid: attr.uid,
         gid: attr.gid,
         rdev: attr.rdev,
+        #[cfg(target_os = "macos")]
+        flags: 0,
         #[cfg(feature = "abi-7-9")]
         blksize: 0, // TODO: find a proper way to set block size
         #[cfg(feature = "abi-7-9")]
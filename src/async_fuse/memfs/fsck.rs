@@ -0,0 +1,807 @@
+//! The offline check of the metadata of a volume.
+//!
+//! The check runs while no node mounts the volume. Opening the kv engine
+//! replays its journal, so a local metadata store is recovered to the last
+//! committed transaction before it's checked. The values in the kv engine
+//! don't carry their keys, so every allocated inode number is scanned:
+//!
+//! - Dir entries referring to missing inodes are removed.
+//! - The files in the trash are kept, as if they're referred to by their trash
+//!   entries, and trash entries referring to missing inodes are removed.
+//! - Dir entries reaching a directory twice, which are cycles or hard links of
+//!   directories, are removed.
+//! - Inodes not reachable from the root are removed if they are pending
+//!   deletion, otherwise they are quarantined in `/lost+found`.
+//! - The `nlink` of an inode is set to the number of dir entries referring to
+//!   it.
+//! - The block metadata of missing inodes is removed. The blocks in the
+//!   backend are not touched, they are listed in the report for the operator.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+
+use clippy_utilities::OverflowArithmetic;
+use nix::sys::stat::SFlag;
+use tracing::{info, warn};
+
+use super::direntry::{DirEntry, FileType};
+use super::fs_util::FileAttr;
+use super::id_alloc::IdType;
+use super::id_alloc_used::INumAllocator;
+use super::kv_engine::{KVEngine, KVEngineType, KeyType, ValueType};
+use super::serial::{file_attr_to_serial, serial_to_file_attr, SerialNode, SerialNodeData};
+use super::{KvDedupIndex, KvPackIndex};
+use crate::async_fuse::fuse::protocol::{INum, FUSE_ROOT_ID};
+use crate::common::error::{DatenLordError, DatenLordResult};
+use crate::storage::dedup::DedupIndex;
+use crate::storage::error::StorageResult;
+use crate::storage::pack::PackIndex;
+
+/// The name of the directory under the root, where unreachable inodes are
+/// quarantined.
+pub const LOST_FOUND: &str = "lost+found";
+
+/// The first inode number allocated by the `INumAllocator`, it's used if no
+/// inode number has been allocated.
+const FIRST_ALLOCATED_INUM: INum = 2;
+
+/// An inconsistency found by the check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// A dir entry refers to a missing inode
+    DanglingEntry {
+        /// The directory of the entry
+        parent: INum,
+        /// The name of the entry
+        name: String,
+        /// The missing inode
+        ino: INum,
+    },
+    /// A dir entry reaches a directory which has been reached, it forms a
+    /// cycle or a hard link of the directory
+    DirectoryCycle {
+        /// The directory of the entry
+        parent: INum,
+        /// The name of the entry
+        name: String,
+        /// The directory reached again
+        ino: INum,
+    },
+    /// A trash entry refers to a missing inode
+    DanglingTrashEntry {
+        /// The missing inode
+        ino: INum,
+    },
+    /// An inode is not reachable from the root
+    OrphanNode {
+        /// The inode
+        ino: INum,
+        /// Whether the inode is pending deletion
+        deferred_deletion: bool,
+    },
+    /// The `nlink` of an inode mismatches the dir entries referring to it
+    NlinkMismatch {
+        /// The inode
+        ino: INum,
+        /// The `nlink` in the metadata
+        nlink: u32,
+        /// The number of dir entries referring to the inode
+        expected: u32,
+    },
+    /// The blocks of a missing inode
+    OrphanBlocks {
+        /// The missing inode
+        ino: INum,
+        /// The number of chunks in its chunk index
+        chunks: u64,
+    },
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            Problem::DanglingEntry {
+                parent,
+                ref name,
+                ino,
+            } => write!(
+                f,
+                "entry {name:?} of directory {parent} refers to missing inode {ino}"
+            ),
+            Problem::DirectoryCycle {
+                parent,
+                ref name,
+                ino,
+            } => write!(
+                f,
+                "entry {name:?} of directory {parent} reaches directory {ino} again"
+            ),
+            Problem::DanglingTrashEntry { ino } => {
+                write!(f, "trash entry refers to missing inode {ino}")
+            }
+            Problem::OrphanNode {
+                ino,
+                deferred_deletion,
+            } => {
+                write!(f, "inode {ino} is not reachable from the root")?;
+                if deferred_deletion {
+                    write!(f, ", and is pending deletion")?;
+                }
+                Ok(())
+            }
+            Problem::NlinkMismatch {
+                ino,
+                nlink,
+                expected,
+            } => write!(
+                f,
+                "inode {ino} has nlink {nlink}, but {expected} entries refer to it"
+            ),
+            Problem::OrphanBlocks { ino, chunks } => write!(
+                f,
+                "missing inode {ino} still has {chunks} chunks in the backend"
+            ),
+        }
+    }
+}
+
+/// The report of the check.
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    /// The number of inodes checked
+    pub inodes: usize,
+    /// The inconsistencies found
+    pub problems: Vec<Problem>,
+    /// Whether the inconsistencies have been repaired
+    pub repaired: bool,
+}
+
+impl FsckReport {
+    /// Whether no inconsistency is found.
+    #[must_use]
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl Display for FsckReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "checked {} inodes, found {} problems",
+            self.inodes,
+            self.problems.len()
+        )?;
+        if self.repaired && !self.is_clean() {
+            write!(f, ", all repaired")?;
+        }
+        for problem in &self.problems {
+            write!(f, "\n  {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Convert the result of storage into the result of the kv engine.
+fn from_storage_result<T>(result: StorageResult<T>) -> DatenLordResult<T> {
+    result.map_err(|e| DatenLordError::InternalErr {
+        source: e.into(),
+        context: vec![],
+    })
+}
+
+/// Check the metadata in `kv_engine`, and repair the inconsistencies if
+/// `repair` is set.
+///
+/// Without `repair`, the report describes what repairing would do, and
+/// nothing is written to the kv engine.
+pub async fn check(kv_engine: Arc<KVEngineType>, repair: bool) -> DatenLordResult<FsckReport> {
+    let next_ino = kv_engine
+        .get(&KeyType::IdAllocatorValue(IdType::INum))
+        .await?
+        .map_or(
+            FIRST_ALLOCATED_INUM,
+            ValueType::into_next_id_allocate_range_begin,
+        );
+
+    let mut checker = Checker {
+        kv_engine,
+        repair,
+        nodes: BTreeMap::new(),
+        dirty: BTreeSet::new(),
+        reached: BTreeSet::new(),
+        refs: BTreeMap::new(),
+        problems: vec![],
+        lost_found: None,
+    };
+    checker.load_nodes(next_ino).await?;
+    let inodes = checker.nodes.len();
+    if inodes == 0 {
+        info!("the volume has no inode, nothing to check");
+        return Ok(FsckReport::default());
+    }
+    if !checker.nodes.contains_key(&FUSE_ROOT_ID) {
+        return Err(DatenLordError::InconsistentFS {
+            context: vec![format!(
+                "the root inode is missing, while {inodes} other inodes exist"
+            )],
+        });
+    }
+
+    checker.reached.insert(FUSE_ROOT_ID);
+    checker.walk(FUSE_ROOT_ID).await?;
+    checker.walk_trash().await?;
+    checker.check_orphan_nodes().await?;
+    checker.check_nlink();
+    checker.persist_dirty_nodes().await?;
+    checker.check_orphan_blocks(next_ino).await?;
+
+    Ok(FsckReport {
+        inodes,
+        problems: checker.problems,
+        repaired: repair,
+    })
+}
+
+/// The state of a check.
+#[derive(Debug)]
+struct Checker {
+    /// The kv engine
+    kv_engine: Arc<KVEngineType>,
+    /// Whether to repair the inconsistencies
+    repair: bool,
+    /// The existing inodes
+    nodes: BTreeMap<INum, SerialNode>,
+    /// The inodes modified by repairing, to be written back
+    dirty: BTreeSet<INum>,
+    /// The inodes reachable from the root
+    reached: BTreeSet<INum>,
+    /// The number of dir entries referring to each inode
+    refs: BTreeMap<INum, u32>,
+    /// The inconsistencies found
+    problems: Vec<Problem>,
+    /// The inode of `/lost+found`, it's created on the first quarantine
+    lost_found: Option<INum>,
+}
+
+impl Checker {
+    /// Load all inodes allocated before `next_ino`.
+    async fn load_nodes(&mut self, next_ino: INum) -> DatenLordResult<()> {
+        for ino in FUSE_ROOT_ID..next_ino {
+            if let Some(value) = self.kv_engine.get(&KeyType::INum2Node(ino)).await? {
+                self.nodes.insert(ino, value.into_serial_node());
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the inode is a directory.
+    fn is_dir(&self, ino: INum) -> bool {
+        self.nodes
+            .get(&ino)
+            .map_or(false, |node| node.data == SerialNodeData::Directory)
+    }
+
+    /// Get the entries of a directory, sorted by name, so the check is
+    /// deterministic.
+    async fn get_entries(&self, parent: INum) -> DatenLordResult<Vec<DirEntry>> {
+        let mut entries: Vec<DirEntry> = self
+            .kv_engine
+            .range(&KeyType::DirEntryKey((parent, String::new())))
+            .await?
+            .into_iter()
+            .map(ValueType::into_dir_entry)
+            .collect();
+        entries.sort_by(|a, b| a.name().cmp(b.name()));
+        Ok(entries)
+    }
+
+    /// Walk the tree under `dir`, which has been reached, and count the dir
+    /// entries referring to each inode.
+    async fn walk(&mut self, dir: INum) -> DatenLordResult<()> {
+        let mut queue = VecDeque::from([dir]);
+        while let Some(parent) = queue.pop_front() {
+            for entry in self.get_entries(parent).await? {
+                let ino = entry.ino();
+                let name = entry.name().to_owned();
+                if !self.nodes.contains_key(&ino) {
+                    self.remove_entry(Problem::DanglingEntry { parent, name, ino })
+                        .await?;
+                    continue;
+                }
+                let is_dir = self.is_dir(ino);
+                if is_dir && self.reached.contains(&ino) {
+                    self.remove_entry(Problem::DirectoryCycle { parent, name, ino })
+                        .await?;
+                    continue;
+                }
+
+                let refs = self.refs.entry(ino).or_default();
+                *refs = refs.overflow_add(1);
+                if self.reached.insert(ino) && is_dir {
+                    queue.push_back(ino);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reach the files in the trash, each of them is referred to by its trash
+    /// entry.
+    async fn walk_trash(&mut self) -> DatenLordResult<()> {
+        let entries = self.kv_engine.range(&KeyType::AllTrash).await?;
+        for entry in entries.into_iter().map(ValueType::into_trash_entry) {
+            let ino = entry.ino;
+            if !self.nodes.contains_key(&ino) {
+                let problem = Problem::DanglingTrashEntry { ino };
+                if self.repair {
+                    self.kv_engine.delete(&KeyType::Trash(ino), None).await?;
+                }
+                warn!("{problem}");
+                self.problems.push(problem);
+                continue;
+            }
+            let refs = self.refs.entry(ino).or_default();
+            *refs = refs.overflow_add(1);
+            self.reached.insert(ino);
+        }
+        Ok(())
+    }
+
+    /// Record a problem of a dir entry, and remove the entry.
+    async fn remove_entry(&mut self, problem: Problem) -> DatenLordResult<()> {
+        if let Problem::DanglingEntry {
+            parent, ref name, ..
+        }
+        | Problem::DirectoryCycle {
+            parent, ref name, ..
+        } = problem
+        {
+            if self.repair {
+                let key = KeyType::DirEntryKey((parent, name.clone()));
+                self.kv_engine.delete(&key, None).await?;
+            }
+        }
+        warn!("{problem}");
+        self.problems.push(problem);
+        Ok(())
+    }
+
+    /// Remove or quarantine the inodes not reachable from the root.
+    async fn check_orphan_nodes(&mut self) -> DatenLordResult<()> {
+        let orphans: Vec<INum> = self
+            .nodes
+            .keys()
+            .filter(|ino| !self.reached.contains(ino))
+            .copied()
+            .collect();
+        for ino in orphans {
+            // It's reached by walking a quarantined directory.
+            if self.reached.contains(&ino) {
+                continue;
+            }
+            let deferred_deletion = self
+                .nodes
+                .get(&ino)
+                .map_or(false, |node| node.deferred_deletion);
+            let problem = Problem::OrphanNode {
+                ino,
+                deferred_deletion,
+            };
+            warn!("{problem}");
+            self.problems.push(problem);
+
+            if deferred_deletion {
+                self.remove_node(ino).await?;
+            } else {
+                self.quarantine(ino).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove an inode pending deletion, its blocks are removed as orphan
+    /// blocks afterwards.
+    async fn remove_node(&mut self, ino: INum) -> DatenLordResult<()> {
+        let is_dir = self.is_dir(ino);
+        self.nodes.remove(&ino);
+        self.dirty.remove(&ino);
+        if !self.repair {
+            return Ok(());
+        }
+
+        if is_dir {
+            for entry in self.get_entries(ino).await? {
+                let key = KeyType::DirEntryKey((ino, entry.name().to_owned()));
+                self.kv_engine.delete(&key, None).await?;
+            }
+        }
+        self.kv_engine
+            .delete(&KeyType::INum2Node(ino), None)
+            .await?;
+        Ok(())
+    }
+
+    /// Quarantine an unreachable inode in `/lost+found`, with the name of
+    /// `#<ino>`.
+    async fn quarantine(&mut self, ino: INum) -> DatenLordResult<()> {
+        if self.repair {
+            let lost_found = self.lost_found().await?;
+            let name = format!("#{ino}");
+            let Some(node) = self.nodes.get_mut(&ino) else {
+                unreachable!("the orphan inode {ino} must exist");
+            };
+            node.parent = lost_found;
+            node.name = name.clone();
+            let file_type = match node.data {
+                SerialNodeData::Directory => FileType::Dir,
+                SerialNodeData::File => FileType::File,
+                SerialNodeData::SymLink(_) => FileType::Symlink,
+            };
+            self.dirty.insert(ino);
+
+            self.kv_engine
+                .set(
+                    &KeyType::DirEntryKey((lost_found, name.clone())),
+                    &ValueType::DirEntry(DirEntry::new(ino, name, file_type)),
+                    None,
+                )
+                .await?;
+        }
+
+        let refs = self.refs.entry(ino).or_default();
+        *refs = refs.overflow_add(1);
+        self.reached.insert(ino);
+        if self.is_dir(ino) {
+            self.walk(ino).await?;
+        }
+        Ok(())
+    }
+
+    /// Get the inode of `/lost+found`, create it if it does not exist.
+    async fn lost_found(&mut self) -> DatenLordResult<INum> {
+        if let Some(ino) = self.lost_found {
+            return Ok(ino);
+        }
+
+        let key = KeyType::DirEntryKey((FUSE_ROOT_ID, LOST_FOUND.to_owned()));
+        if let Some(value) = self.kv_engine.get(&key).await? {
+            let ino = value.into_dir_entry().ino();
+            if !self.is_dir(ino) {
+                return Err(DatenLordError::InconsistentFS {
+                    context: vec![format!("/{LOST_FOUND} is not a directory")],
+                });
+            }
+            self.lost_found = Some(ino);
+            return Ok(ino);
+        }
+
+        let ino = INumAllocator::new(Arc::clone(&self.kv_engine))
+            .alloc_inum_for_fnode()
+            .await?;
+        let attr = FileAttr {
+            ino,
+            kind: SFlag::S_IFDIR,
+            perm: 0o700,
+            nlink: 1,
+            ..FileAttr::now()
+        };
+        let node = SerialNode {
+            parent: FUSE_ROOT_ID,
+            name: LOST_FOUND.to_owned(),
+            attr: file_attr_to_serial(&attr),
+            data: SerialNodeData::Directory,
+            lookup_count: 0,
+            deferred_deletion: false,
+            inline_data: None,
+        };
+        self.kv_engine
+            .set(
+                &KeyType::INum2Node(ino),
+                &ValueType::Node(node.clone()),
+                None,
+            )
+            .await?;
+        self.kv_engine
+            .set(
+                &key,
+                &ValueType::DirEntry(DirEntry::new(ino, LOST_FOUND.to_owned(), FileType::Dir)),
+                None,
+            )
+            .await?;
+        info!("created /{LOST_FOUND} with inode {ino}");
+
+        self.nodes.insert(ino, node);
+        self.refs.insert(ino, 1);
+        self.reached.insert(ino);
+        self.lost_found = Some(ino);
+        Ok(ino)
+    }
+
+    /// Check the `nlink` of inodes except the root, whose `nlink` is not
+    /// maintained.
+    fn check_nlink(&mut self) {
+        for (&ino, node) in &mut self.nodes {
+            if ino == FUSE_ROOT_ID {
+                continue;
+            }
+            let expected = self.refs.get(&ino).copied().unwrap_or(0);
+            let mut attr = serial_to_file_attr(&node.attr);
+            if attr.nlink == expected {
+                continue;
+            }
+
+            let problem = Problem::NlinkMismatch {
+                ino,
+                nlink: attr.nlink,
+                expected,
+            };
+            warn!("{problem}");
+            self.problems.push(problem);
+            attr.nlink = expected;
+            node.attr = file_attr_to_serial(&attr);
+            self.dirty.insert(ino);
+        }
+    }
+
+    /// Write the repaired inodes back.
+    async fn persist_dirty_nodes(&mut self) -> DatenLordResult<()> {
+        if !self.repair {
+            return Ok(());
+        }
+        for ino in std::mem::take(&mut self.dirty) {
+            if let Some(node) = self.nodes.get(&ino) {
+                self.kv_engine
+                    .set(
+                        &KeyType::INum2Node(ino),
+                        &ValueType::Node(node.clone()),
+                        None,
+                    )
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Find the block metadata of missing inodes allocated before
+    /// `next_ino`, and remove it.
+    async fn check_orphan_blocks(&mut self, next_ino: INum) -> DatenLordResult<()> {
+        let mut checksums: BTreeSet<INum> = BTreeSet::new();
+        for value in self.kv_engine.range(&KeyType::AllBlockChecksums).await? {
+            checksums.insert(value.into_block_checksum().ino);
+        }
+        let dedup_index = KvDedupIndex::new(Arc::clone(&self.kv_engine));
+        let pack_index = KvPackIndex::new(Arc::clone(&self.kv_engine));
+
+        for ino in FUSE_ROOT_ID..next_ino {
+            if self.nodes.contains_key(&ino) {
+                continue;
+            }
+            let chunk_index = self.kv_engine.get(&KeyType::ChunkIndex(ino)).await?;
+            let has_recipes = !self
+                .kv_engine
+                .range(&KeyType::FileBlockRecipes(ino))
+                .await?
+                .is_empty();
+            let has_packed = !self
+                .kv_engine
+                .range(&KeyType::FilePackedBlocks(ino))
+                .await?
+                .is_empty();
+            if chunk_index.is_none() && !has_recipes && !has_packed && !checksums.contains(&ino) {
+                continue;
+            }
+
+            let chunks = chunk_index.map_or(0, |index| index.into_chunk_index().chunk_count());
+            let problem = Problem::OrphanBlocks { ino, chunks };
+            warn!("{problem}");
+            self.problems.push(problem);
+            if !self.repair {
+                continue;
+            }
+
+            self.kv_engine
+                .delete(&KeyType::ChunkIndex(ino), None)
+                .await?;
+            let unreferenced =
+                from_storage_result(dedup_index.remove_recipes(ino, 0..usize::MAX).await)?;
+            if !unreferenced.is_empty() {
+                warn!(
+                    "{} chunks of missing inode {ino} are no longer referenced",
+                    unreferenced.len()
+                );
+            }
+            let unreferenced =
+                from_storage_result(pack_index.remove_packed(ino, 0..usize::MAX).await)?;
+            if !unreferenced.is_empty() {
+                warn!(
+                    "{} containers of missing inode {ino} are no longer referenced",
+                    unreferenced.len()
+                );
+            }
+            for value in self
+                .kv_engine
+                .range(&KeyType::FileBlockChecksums(ino))
+                .await?
+            {
+                let block_id = value.into_block_checksum().block_id;
+                self.kv_engine
+                    .delete(&KeyType::BlockChecksum(ino, block_id), None)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+    use crate::async_fuse::memfs::chunk_index::ChunkIndex;
+    use crate::async_fuse::test::test_util::open_kv_engine;
+
+    /// Set a node in the kv engine.
+    async fn set_node(
+        kv_engine: &KVEngineType,
+        ino: INum,
+        parent: INum,
+        name: &str,
+        data: SerialNodeData,
+        nlink: u32,
+    ) {
+        let kind = if data == SerialNodeData::Directory {
+            SFlag::S_IFDIR
+        } else {
+            SFlag::S_IFREG
+        };
+        let attr = FileAttr {
+            ino,
+            kind,
+            nlink,
+            ..FileAttr::now()
+        };
+        let node = SerialNode {
+            parent,
+            name: name.to_owned(),
+            attr: file_attr_to_serial(&attr),
+            data,
+            lookup_count: 0,
+            deferred_deletion: false,
+            inline_data: None,
+        };
+        kv_engine
+            .set(&KeyType::INum2Node(ino), &ValueType::Node(node), None)
+            .await
+            .unwrap();
+    }
+
+    /// Set a dir entry in the kv engine.
+    async fn set_entry(
+        kv_engine: &KVEngineType,
+        parent: INum,
+        name: &str,
+        ino: INum,
+        file_type: FileType,
+    ) {
+        kv_engine
+            .set(
+                &KeyType::DirEntryKey((parent, name.to_owned())),
+                &ValueType::DirEntry(DirEntry::new(ino, name.to_owned(), file_type)),
+                None,
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_check_and_repair() {
+        let kv_engine = open_kv_engine();
+        kv_engine
+            .set(
+                &KeyType::IdAllocatorValue(IdType::INum),
+                &ValueType::NextIdAllocateRangeBegin(10),
+                None,
+            )
+            .await
+            .unwrap();
+
+        // / -> a (2) -> b (3) -> a (2), a cycle
+        // / -> file (4), with a wrong nlink
+        // / -> missing (8), dangling
+        // orphan (5), not reachable
+        // missing inode 9 still has a chunk index
+        set_node(
+            &kv_engine,
+            FUSE_ROOT_ID,
+            FUSE_ROOT_ID,
+            "/",
+            SerialNodeData::Directory,
+            0,
+        )
+        .await;
+        set_node(
+            &kv_engine,
+            2,
+            FUSE_ROOT_ID,
+            "a",
+            SerialNodeData::Directory,
+            1,
+        )
+        .await;
+        set_node(&kv_engine, 3, 2, "b", SerialNodeData::Directory, 1).await;
+        set_node(&kv_engine, 4, FUSE_ROOT_ID, "file", SerialNodeData::File, 3).await;
+        set_node(&kv_engine, 5, 7, "orphan", SerialNodeData::File, 1).await;
+        set_entry(&kv_engine, FUSE_ROOT_ID, "a", 2, FileType::Dir).await;
+        set_entry(&kv_engine, 2, "b", 3, FileType::Dir).await;
+        set_entry(&kv_engine, 3, "a", 2, FileType::Dir).await;
+        set_entry(&kv_engine, FUSE_ROOT_ID, "file", 4, FileType::File).await;
+        set_entry(&kv_engine, FUSE_ROOT_ID, "missing", 8, FileType::File).await;
+        let mut chunk_index = ChunkIndex::new(4);
+        chunk_index.record_write(0, 8);
+        kv_engine
+            .set(
+                &KeyType::ChunkIndex(9),
+                &ValueType::ChunkIndex(chunk_index),
+                None,
+            )
+            .await
+            .unwrap();
+
+        let report = check(Arc::clone(&kv_engine), false).await.unwrap();
+        let expected = vec![
+            Problem::DanglingEntry {
+                parent: FUSE_ROOT_ID,
+                name: "missing".to_owned(),
+                ino: 8,
+            },
+            Problem::DirectoryCycle {
+                parent: 3,
+                name: "a".to_owned(),
+                ino: 2,
+            },
+            Problem::OrphanNode {
+                ino: 5,
+                deferred_deletion: false,
+                inline_data: None,
+            },
+            Problem::NlinkMismatch {
+                ino: 4,
+                nlink: 3,
+                expected: 1,
+            },
+            Problem::OrphanBlocks { ino: 9, chunks: 2 },
+        ];
+        assert_eq!(report.inodes, 5);
+        assert_eq!(report.problems, expected);
+
+        // Nothing is written by the check above, so repairing finds the same
+        // problems.
+        let report = check(Arc::clone(&kv_engine), true).await.unwrap();
+        assert_eq!(report.problems, expected);
+        assert!(report.repaired);
+
+        let report = check(Arc::clone(&kv_engine), false).await.unwrap();
+        assert!(report.is_clean(), "{report}");
+        let lost_found = kv_engine
+            .get(&KeyType::DirEntryKey((FUSE_ROOT_ID, LOST_FOUND.to_owned())))
+            .await
+            .unwrap()
+            .unwrap()
+            .into_dir_entry()
+            .ino();
+        let quarantined = kv_engine
+            .get(&KeyType::DirEntryKey((lost_found, "#5".to_owned())))
+            .await
+            .unwrap()
+            .unwrap()
+            .into_dir_entry();
+        assert_eq!(quarantined.ino(), 5);
+        assert!(kv_engine
+            .get(&KeyType::ChunkIndex(9))
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
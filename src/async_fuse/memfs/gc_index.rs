@@ -0,0 +1,86 @@
+//! The `GcIndex` upon the metadata in the kv engine.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use clippy_utilities::Cast;
+
+use super::kv_engine::{KVEngine, KVEngineType, KeyType, ValueType};
+use crate::common::error::DatenLordResult;
+use crate::storage::block_store::INITIAL_BLOCK_VERSION;
+use crate::storage::error::{StorageError, StorageResult};
+use crate::storage::gc::{GcIndex, ObjectKey};
+use crate::storage::BlockKey;
+
+/// A `GcIndex` checking the references of objects in the metadata of the
+/// volume.
+#[derive(Debug)]
+pub struct KvGcIndex {
+    /// The kv engine
+    kv_engine: Arc<KVEngineType>,
+    /// Whether the versions of blocks are kept for snapshots, the blocks are
+    /// referenced by their version records instead of the chunk indexes then
+    snapshot: bool,
+}
+
+impl KvGcIndex {
+    /// Create a `KvGcIndex`.
+    #[must_use]
+    pub fn new(kv_engine: Arc<KVEngineType>, snapshot: bool) -> Self {
+        Self {
+            kv_engine,
+            snapshot,
+        }
+    }
+
+    /// Returns whether a block is referenced by a file or a snapshot.
+    async fn is_block_referenced(&self, key: BlockKey) -> DatenLordResult<bool> {
+        if self.snapshot {
+            let versions = self
+                .kv_engine
+                .get(&KeyType::BlockVersions(key.ino, key.block_id))
+                .await?
+                .map(ValueType::into_block_versions);
+            return Ok(versions.map_or(false, |versions| {
+                versions
+                    .versions
+                    .iter()
+                    .any(|version| version.present && version.epoch == key.version)
+            }));
+        }
+
+        if key.version != INITIAL_BLOCK_VERSION
+            || self
+                .kv_engine
+                .get(&KeyType::INum2Node(key.ino))
+                .await?
+                .is_none()
+        {
+            return Ok(false);
+        }
+        let chunk_index = self
+            .kv_engine
+            .get(&KeyType::ChunkIndex(key.ino))
+            .await?
+            .map(ValueType::into_chunk_index);
+        Ok(chunk_index.map_or(false, |index| index.contains(key.block_id.cast())))
+    }
+}
+
+#[async_trait]
+impl GcIndex for KvGcIndex {
+    async fn is_referenced(&self, key: ObjectKey) -> StorageResult<bool> {
+        match key {
+            ObjectKey::Block(key) => self
+                .is_block_referenced(key)
+                .await
+                .map_err(StorageError::from),
+            ObjectKey::Chunk(hash) => {
+                let refcount = self.kv_engine.get(&KeyType::ChunkRefCount(hash)).await;
+                refcount
+                    .map_err(StorageError::from)
+                    .map(|refcount| refcount.is_some())
+            }
+        }
+    }
+}
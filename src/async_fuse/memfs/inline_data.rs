@@ -0,0 +1,86 @@
+//! The content of small files inlined in their nodes.
+//!
+//! A file not larger than the inline threshold keeps a copy of its content in
+//! its node in the kv engine, so the content is loaded with the node when the
+//! file is opened, and the reads of it are served without the storage. The
+//! copy is updated in the same transaction as the attributes of the file, or
+//! dropped once the file grows beyond the threshold, or once it's changed by
+//! the storage only, such as a copy of a range. A dropped copy is not known
+//! until the file is truncated to empty.
+
+use std::ops::Range;
+
+use clippy_utilities::Cast;
+
+/// Update the inlined content of a file resized from `old_size` to `new_size`
+/// bytes, the content is resized and then changed by `change`.
+///
+/// Returns the new content to inline, `None` if it's not inlined, as the file
+/// is larger than `threshold`, or its old content is not known.
+pub(super) fn update(
+    old: Option<&[u8]>,
+    old_size: u64,
+    new_size: u64,
+    threshold: Option<u64>,
+    change: impl FnOnce(&mut Vec<u8>),
+) -> Option<Vec<u8>> {
+    if threshold.map_or(true, |threshold| new_size > threshold) {
+        return None;
+    }
+    let mut data = match old {
+        Some(data) if data.len().cast::<u64>() == old_size => data.to_vec(),
+        // An empty file has nothing to inline.
+        None if old_size == 0 => vec![],
+        Some(_) | None => return None,
+    };
+    data.resize(new_size.cast(), 0);
+    change(&mut data);
+    Some(data)
+}
+
+/// Write `written` at `offset` of the content resized by `update()`.
+pub(super) fn write(data: &mut [u8], offset: u64, written: &[u8]) {
+    let start: usize = offset.cast();
+    if let Some(dest) = data.get_mut(start..start.saturating_add(written.len())) {
+        dest.copy_from_slice(written);
+    }
+}
+
+/// Fill `range` of the content resized by `update()` with zeros.
+pub(super) fn punch(data: &mut [u8], range: Range<u64>) {
+    if let Some(dest) = data.get_mut(range.start.cast()..range.end.cast()) {
+        dest.fill(0);
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::{punch, update, write};
+
+    #[test]
+    fn test_update() {
+        // An empty file is inlined by its first write.
+        let data = update(None, 0, 5, Some(8), |data| write(data, 0, b"hello")).unwrap();
+        assert_eq!(data, b"hello");
+
+        // A write beyond the end leaves a hole.
+        let data = update(Some(&data), 5, 8, Some(8), |data| write(data, 6, b"ab")).unwrap();
+        assert_eq!(data, b"hello\0ab");
+
+        let data = update(Some(&data), 8, 8, Some(8), |data| punch(data, 1..3)).unwrap();
+        assert_eq!(data, b"h\0\0lo\0ab");
+
+        // Truncating is resizing only.
+        let data = update(Some(&data), 8, 2, Some(8), |_| {}).unwrap();
+        assert_eq!(data, b"h\0");
+
+        // A file larger than the threshold is not inlined.
+        assert!(update(Some(&data), 2, 9, Some(8), |_| {}).is_none());
+        // Nor is a file whose content is not known.
+        assert!(update(None, 2, 4, Some(8), |_| {}).is_none());
+        assert!(update(Some(b"h"), 2, 4, Some(8), |_| {}).is_none());
+        // Nor is any file if inlining is disabled.
+        assert!(update(None, 0, 0, None, |_| {}).is_none());
+    }
+}
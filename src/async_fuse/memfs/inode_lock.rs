@@ -0,0 +1,357 @@
+//! Per-inode locks serializing the size changes of a file against its writes,
+//! and the overlapping writes against each other.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use clippy_utilities::OverflowArithmetic;
+use parking_lot::Mutex;
+use tokio::sync::{Notify, OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
+
+use crate::async_fuse::fuse::protocol::INum;
+
+/// The byte ranges of a file locked by its writes, in the order they're
+/// requested.
+#[derive(Debug, Default)]
+struct RangeQueue {
+    /// The ranges requested and their tickets, a range is held once no range
+    /// requested before it overlaps it
+    ranges: Vec<(u64, Range<u64>)>,
+    /// The ticket of the next range requested
+    next_ticket: u64,
+}
+
+impl RangeQueue {
+    /// Whether the range of `ticket` is overlapped by no range requested
+    /// before it.
+    fn is_ready(&self, ticket: u64) -> bool {
+        let Some(&(_, ref range)) = self.ranges.iter().find(|&&(t, _)| t == ticket) else {
+            return true;
+        };
+        !self
+            .ranges
+            .iter()
+            .take_while(|&&(t, _)| t != ticket)
+            .any(|&(_, ref r)| r.start < range.end && range.start < r.end)
+    }
+}
+
+/// The lock of an inode.
+#[derive(Debug, Default)]
+struct InodeLock {
+    /// Shared by the writes, and exclusive for the size changes
+    rw: Arc<RwLock<()>>,
+    /// The byte ranges locked by the writes
+    ranges: Mutex<RangeQueue>,
+    /// Notified once a range is unlocked
+    unlocked: Notify,
+}
+
+/// The locks of inodes.
+///
+/// Writes of a file hold the shared lock and may run concurrently, while
+/// truncating or punching a hole holds the exclusive lock, so no write is in
+/// flight when the blocks past the new end of file are removed. The writes
+/// lock their byte ranges besides, so the ones of disjoint regions run in
+/// parallel, while the overlapping ones are ordered as they arrive. A lock is
+/// dropped from the map once no one holds it.
+///
+/// The storage writes whole blocks, by merging a partial write into the block
+/// and storing the block, so the ranges are rounded out to the blocks they
+/// touch, and two writes of the same block never interleave.
+#[derive(Debug)]
+pub struct InodeLocks {
+    /// The locks of inodes being accessed
+    locks: Mutex<HashMap<INum, Arc<InodeLock>>>,
+    /// The size of blocks the ranges are rounded out to
+    block_size: u64,
+}
+
+impl Default for InodeLocks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The guard of an inode lock, the lock is released on drop.
+#[derive(Debug)]
+pub struct InodeGuard<'a, G> {
+    /// The guard of the lock, it's only taken on drop
+    guard: Option<G>,
+    /// The lock
+    lock: Arc<InodeLock>,
+    /// The inode number
+    ino: INum,
+    /// The locks it belongs to
+    locks: &'a InodeLocks,
+}
+
+/// The guard of a byte range locked by a write, the range and the shared lock
+/// of the inode are released on drop.
+#[derive(Debug)]
+pub struct RangeGuard<'a> {
+    /// The ticket of the range
+    ticket: u64,
+    /// The guard of the shared lock of the inode
+    inode_guard: InodeGuard<'a, OwnedRwLockReadGuard<()>>,
+}
+
+impl InodeLocks {
+    /// Create an empty `InodeLocks`, which locks the exact byte ranges.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_block_size(1)
+    }
+
+    /// Create an empty `InodeLocks`, which locks the blocks of `block_size`
+    /// covering the byte ranges, `block_size` is not 0 as it's checked by the
+    /// config.
+    #[must_use]
+    pub fn with_block_size(block_size: u64) -> Self {
+        assert!(block_size > 0, "the block size of inode locks is 0");
+        Self {
+            locks: Mutex::new(HashMap::new()),
+            block_size,
+        }
+    }
+
+    /// Get the lock of an inode, insert one if it does not exist.
+    fn get_lock(&self, ino: INum) -> Arc<InodeLock> {
+        Arc::clone(self.locks.lock().entry(ino).or_default())
+    }
+
+    /// Remove the lock of an inode if no one else holds it.
+    fn release(&self, ino: INum, lock: &Arc<InodeLock>) {
+        let mut locks = self.locks.lock();
+        // One in the map, and the one of the caller.
+        if Arc::strong_count(lock) == 2 {
+            locks.remove(&ino);
+        }
+    }
+
+    /// Acquire the shared lock of an inode, for writing the file.
+    pub async fn read(&self, ino: INum) -> InodeGuard<'_, OwnedRwLockReadGuard<()>> {
+        let lock = self.get_lock(ino);
+        let guard = Arc::clone(&lock.rw).read_owned().await;
+        InodeGuard {
+            guard: Some(guard),
+            lock,
+            ino,
+            locks: self,
+        }
+    }
+
+    /// Acquire the exclusive lock of an inode, for changing the size of the
+    /// file.
+    pub async fn write(&self, ino: INum) -> InodeGuard<'_, OwnedRwLockWriteGuard<()>> {
+        let lock = self.get_lock(ino);
+        let guard = Arc::clone(&lock.rw).write_owned().await;
+        InodeGuard {
+            guard: Some(guard),
+            lock,
+            ino,
+            locks: self,
+        }
+    }
+
+    /// Acquire the shared lock of an inode and the blocks covering the byte
+    /// range `[offset, offset + len)` of it, for writing the range. It waits
+    /// for the ranges overlapping it and requested before it.
+    pub async fn write_range(&self, ino: INum, offset: u64, len: u64) -> RangeGuard<'_> {
+        let start = offset
+            .overflow_div(self.block_size)
+            .overflow_mul(self.block_size);
+        let end = offset
+            .overflow_add(len)
+            .div_ceil(self.block_size)
+            .overflow_mul(self.block_size);
+        let inode_guard = self.read(ino).await;
+        let lock = &inode_guard.lock;
+        let ticket = {
+            let mut ranges = lock.ranges.lock();
+            let ticket = ranges.next_ticket;
+            ranges.next_ticket = ticket.wrapping_add(1);
+            ranges.ranges.push((ticket, start..end));
+            ticket
+        };
+        loop {
+            // Registered before the check, so an unlock meanwhile is not
+            // missed.
+            let unlocked = lock.unlocked.notified();
+            if lock.ranges.lock().is_ready(ticket) {
+                break;
+            }
+            unlocked.await;
+        }
+        RangeGuard {
+            ticket,
+            inode_guard,
+        }
+    }
+}
+
+impl<G> Drop for InodeGuard<'_, G> {
+    fn drop(&mut self) {
+        drop(self.guard.take());
+        self.locks.release(self.ino, &self.lock);
+    }
+}
+
+impl Drop for RangeGuard<'_> {
+    fn drop(&mut self) {
+        let lock = &self.inode_guard.lock;
+        lock.ranges
+            .lock()
+            .ranges
+            .retain(|&(ticket, _)| ticket != self.ticket);
+        lock.unlocked.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    use clippy_utilities::Cast;
+    use parking_lot::Mutex;
+
+    use super::InodeLocks;
+    use crate::storage::policy::LruPolicy;
+    use crate::storage::{
+        BlockCoordinate, MemoryCacheBuilder, MemoryStorage, Storage, StorageManager,
+    };
+
+    #[tokio::test]
+    async fn test_write_waits_for_reads() {
+        let locks = Arc::new(InodeLocks::new());
+
+        let read_guard = locks.read(1).await;
+        let other_read_guard = locks.read(1).await;
+        // Locks of different inodes are independent.
+        let _other_inode_guard = locks.write(2).await;
+
+        let writer = {
+            let locks = Arc::clone(&locks);
+            tokio::spawn(async move {
+                let _guard = locks.write(1).await;
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!writer.is_finished());
+
+        drop(read_guard);
+        drop(other_read_guard);
+        tokio::time::timeout(Duration::from_secs(1), writer)
+            .await
+            .unwrap_or_else(|_| panic!("The writer is blocked."))
+            .unwrap_or_else(|e| panic!("The writer panics: {e}"));
+    }
+
+    #[tokio::test]
+    async fn test_lock_removed() {
+        let locks = InodeLocks::new();
+        {
+            let _guard = locks.write(1).await;
+            assert_eq!(locks.locks.lock().len(), 1);
+        }
+        {
+            let _guard = locks.write_range(1, 0, 10).await;
+            assert_eq!(locks.locks.lock().len(), 1);
+        }
+        assert!(locks.locks.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_range_locks() {
+        let locks = Arc::new(InodeLocks::new());
+
+        let guard = locks.write_range(1, 0, 10).await;
+        // A disjoint range is not blocked.
+        let _disjoint_guard = locks.write_range(1, 20, 10).await;
+
+        let overlapping = {
+            let locks = Arc::clone(&locks);
+            tokio::spawn(async move {
+                let _guard = locks.write_range(1, 5, 10).await;
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!overlapping.is_finished());
+
+        drop(guard);
+        tokio::time::timeout(Duration::from_secs(1), overlapping)
+            .await
+            .unwrap_or_else(|_| panic!("The overlapping write is blocked."))
+            .unwrap_or_else(|e| panic!("The overlapping write panics: {e}"));
+    }
+
+    #[tokio::test]
+    async fn test_block_locks() {
+        let locks = Arc::new(InodeLocks::with_block_size(16));
+
+        let guard = locks.write_range(1, 0, 4).await;
+        // A range in another block is not blocked.
+        let _other_block_guard = locks.write_range(1, 16, 4).await;
+
+        let same_block = {
+            let locks = Arc::clone(&locks);
+            tokio::spawn(async move {
+                let _guard = locks.write_range(1, 8, 4).await;
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!same_block.is_finished());
+
+        drop(guard);
+        tokio::time::timeout(Duration::from_secs(1), same_block)
+            .await
+            .unwrap_or_else(|_| panic!("The write of the same block is blocked."))
+            .unwrap_or_else(|e| panic!("The write of the same block panics: {e}"));
+    }
+
+    #[tokio::test]
+    async fn test_disjoint_writes_in_one_block() {
+        const BLOCK_SIZE: usize = 16;
+
+        // The backend is slow, so both writes miss the cache and merge
+        // themselves into the block loaded from the backend at once, unless
+        // they're serialized.
+        let backend = Arc::new(MemoryStorage::new(BLOCK_SIZE, Duration::from_millis(20)));
+        let policy = LruPolicy::<BlockCoordinate>::new(16);
+        let cache = MemoryCacheBuilder::new(policy, Arc::clone(&backend), BLOCK_SIZE)
+            .write_through(true)
+            .build()
+            .await;
+        let storage = Arc::new(StorageManager::new(cache, BLOCK_SIZE));
+        let locks = Arc::new(InodeLocks::with_block_size(BLOCK_SIZE.cast()));
+        let mtime = Arc::new(Mutex::new(SystemTime::now()));
+
+        let writers = [(0, b"abcd"), (8, b"efgh")].map(|(offset, data)| {
+            let (storage, locks, mtime) =
+                (Arc::clone(&storage), Arc::clone(&locks), Arc::clone(&mtime));
+            tokio::spawn(async move {
+                let _guard = locks.write_range(1, offset, 4).await;
+                let old_mtime = *mtime.lock();
+                let new_mtime = storage
+                    .store(1, offset.cast(), data, old_mtime)
+                    .await
+                    .unwrap_or_else(|e| panic!("Failed to write: {e}"));
+                *mtime.lock() = new_mtime;
+            })
+        });
+        for writer in writers {
+            writer
+                .await
+                .unwrap_or_else(|e| panic!("The writer panics: {e}"));
+        }
+
+        let block = backend
+            .load(1, 0)
+            .await
+            .unwrap_or_else(|e| panic!("Failed to load: {e}"))
+            .unwrap_or_else(|| panic!("The block is not written."));
+        assert_eq!(block.as_slice(), b"abcd\0\0\0\0efgh\0\0\0\0");
+    }
+}
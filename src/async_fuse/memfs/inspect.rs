@@ -0,0 +1,187 @@
+//! The state of the mount inspected by the operators.
+//!
+//! The file system mounted registers its data cache, the handles opened by
+//! the kernel and the leases it holds, whose state is taken on demand on the
+//! control socket, see `control`. The stats role shows the summary of them
+//! with the latency of the operations, and the inspect role shows them by the
+//! files, such as the bytes of a file pending to be written back, so the
+//! operators see what a node holds when it stalls or before it's drained.
+//!
+//! Only the weak references are registered, nothing is inspected once the
+//! file system is dropped.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Weak};
+
+use clippy_utilities::{Cast, OverflowArithmetic};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use super::coherence::{Coherence, LeaseMode};
+use super::file_handle::FileHandles;
+use super::{BackendStorageType, StorageType};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::policy::BoxedPolicy;
+use crate::storage::{BlockCoordinate, MemoryCache, StorageManager};
+
+/// The state of the file system mounted by this process.
+pub static INSPECTOR: Lazy<Inspector> = Lazy::new(Inspector::default);
+
+/// The occupancy of the data cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheOccupancy {
+    /// The blocks cached
+    pub blocks: usize,
+    /// The capacity of the cache in blocks
+    pub capacity: usize,
+    /// The block size in bytes
+    pub block_size: usize,
+    /// The bytes cached and pending to be written back
+    pub dirty_bytes: u64,
+}
+
+impl CacheOccupancy {
+    /// The bytes of the blocks cached.
+    #[must_use]
+    pub fn used_bytes(&self) -> u64 {
+        self.blocks.overflow_mul(self.block_size).cast()
+    }
+
+    /// The capacity of the cache in bytes.
+    #[must_use]
+    pub fn capacity_bytes(&self) -> u64 {
+        self.capacity.overflow_mul(self.block_size).cast()
+    }
+}
+
+/// The state of a file held by the mount.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InodeState {
+    /// The i-number of the file
+    pub ino: INum,
+    /// The bytes of the file cached and pending to be written back
+    pub dirty_bytes: u64,
+    /// The handles of the file opened by the kernel
+    pub handles: usize,
+    /// The handles opened for writing
+    pub writable_handles: usize,
+    /// The lease of the file held by this node, `None` if it's not held
+    pub lease: Option<LeaseMode>,
+}
+
+impl InodeState {
+    /// The state of file `ino` holding nothing.
+    fn new(ino: INum) -> Self {
+        Self {
+            ino,
+            dirty_bytes: 0,
+            handles: 0,
+            writable_handles: 0,
+            lease: None,
+        }
+    }
+}
+
+/// The state of the mount at a moment.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Inspection {
+    /// The occupancy of the data cache
+    pub cache: CacheOccupancy,
+    /// The files holding dirty bytes, handles or leases, the most dirty bytes
+    /// first
+    pub inodes: Vec<InodeState>,
+}
+
+/// The storage manager referenced by `StorageType`.
+pub(super) type StorageManagerType =
+    StorageManager<Arc<MemoryCache<BoxedPolicy<BlockCoordinate>, BackendStorageType>>>;
+
+/// The state registered by the file system mounted.
+#[derive(Debug)]
+struct Registered {
+    /// The storage of the file system
+    storage: Weak<StorageManagerType>,
+    /// The leases held by the file system
+    coherence: Weak<Coherence>,
+    /// The handles opened by the kernel
+    file_handles: Weak<FileHandles>,
+}
+
+/// The state of the file system mounted, inspected on demand.
+#[derive(Debug, Default)]
+pub struct Inspector {
+    /// The state registered, `None` if no file system is mounted
+    registered: Mutex<Option<Registered>>,
+}
+
+impl Inspector {
+    /// Register the state of a file system, which replaces the one registered
+    /// before.
+    pub(super) fn register(
+        &self,
+        storage: &StorageType,
+        coherence: &Arc<Coherence>,
+        file_handles: &Arc<FileHandles>,
+    ) {
+        *self.registered.lock() = Some(Registered {
+            storage: Arc::downgrade(storage),
+            coherence: Arc::downgrade(coherence),
+            file_handles: Arc::downgrade(file_handles),
+        });
+    }
+
+    /// Take the state of the file system registered, `None` if there's none
+    /// or it's dropped.
+    pub async fn inspect(&self) -> Option<Inspection> {
+        let (storage, coherence, file_handles) = {
+            let registered = self.registered.lock();
+            let registered = registered.as_ref()?;
+            (
+                registered.storage.upgrade()?,
+                registered.coherence.upgrade()?,
+                registered.file_handles.upgrade()?,
+            )
+        };
+
+        let cache = storage.storage();
+        let (blocks, capacity) = cache.occupancy();
+        let mut inodes = BTreeMap::new();
+        let mut dirty_bytes: u64 = 0;
+        for (ino, bytes) in cache.dirty_bytes().await {
+            let bytes: u64 = bytes.cast();
+            dirty_bytes = dirty_bytes.overflow_add(bytes);
+            inodes
+                .entry(ino)
+                .or_insert_with(|| InodeState::new(ino))
+                .dirty_bytes = bytes;
+        }
+        for (ino, writable) in file_handles.opened() {
+            let state = inodes.entry(ino).or_insert_with(|| InodeState::new(ino));
+            state.handles = state.handles.overflow_add(1);
+            if writable {
+                state.writable_handles = state.writable_handles.overflow_add(1);
+            }
+        }
+        for (ino, mode) in coherence.held_leases() {
+            inodes
+                .entry(ino)
+                .or_insert_with(|| InodeState::new(ino))
+                .lease = Some(mode);
+        }
+
+        let mut inodes: Vec<InodeState> = inodes.into_values().collect();
+        // Stable, so the files of the same dirty bytes are in the i-numbers
+        inodes.sort_by_key(|state| Reverse(state.dirty_bytes));
+        Some(Inspection {
+            cache: CacheOccupancy {
+                blocks,
+                capacity,
+                block_size: cache.block_size(),
+                dirty_bytes,
+            },
+            inodes,
+        })
+    }
+}
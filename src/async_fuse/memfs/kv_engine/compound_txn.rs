@@ -0,0 +1,124 @@
+//! A transaction running several metadata operations together.
+//!
+//! A transaction of the kv engines reads a key at most once, and never reads
+//! or writes a key again after it's written, as every metadata operation
+//! touches the keys it needs once. A compound operation, such as creating a
+//! node and changing its owner, or renaming over an existing entry and
+//! setting the attributes of the node moved, runs the operations one after
+//! another, and the later ones read the keys written by the former ones.
+//! Committing them one by one lets the other nodes see the states in
+//! between, so `CompoundTxn` wraps a transaction of the engine instead: the
+//! keys read are cached, the ones written are buffered and read back by the
+//! operations following, and they're written to the inner transaction on
+//! commit, so the operations are committed or retried together.
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+
+use super::local_impl::{decode_value, encode_value};
+use super::{KeyType, MetaTxn, ValueType};
+use crate::common::error::DatenLordResult;
+
+/// A `MetaTxn` whose keys are read and written any number of times.
+pub struct CompoundTxn {
+    /// The transaction of the kv engine
+    inner: Box<dyn MetaTxn + Send>,
+    /// The encoded values of the keys read or written, `None` if the key
+    /// doesn't exist or is deleted
+    values: HashMap<String, Option<Vec<u8>>>,
+    /// The keys written, which are written to the inner transaction on commit
+    written: HashMap<String, KeyType>,
+}
+
+impl CompoundTxn {
+    /// Wrap transaction `inner` of the kv engine.
+    #[must_use]
+    pub fn new(inner: Box<dyn MetaTxn + Send>) -> Self {
+        Self {
+            inner,
+            values: HashMap::new(),
+            written: HashMap::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl MetaTxn for CompoundTxn {
+    async fn get(&mut self, key: &KeyType) -> DatenLordResult<Option<ValueType>> {
+        let string_key = key.to_string_key();
+        if let Some(value) = self.values.get(&string_key) {
+            return value.as_deref().map(decode_value).transpose();
+        }
+        let value = self.inner.get(key).await?;
+        self.values
+            .insert(string_key, value.as_ref().map(encode_value));
+        Ok(value)
+    }
+
+    fn set(&mut self, key: &KeyType, value: &ValueType) {
+        let string_key = key.to_string_key();
+        self.values
+            .insert(string_key.clone(), Some(encode_value(value)));
+        self.written.insert(string_key, key.clone());
+    }
+
+    fn delete(&mut self, key: &KeyType) {
+        let string_key = key.to_string_key();
+        self.values.insert(string_key.clone(), None);
+        self.written.insert(string_key, key.clone());
+    }
+
+    async fn commit(&mut self) -> DatenLordResult<bool> {
+        for (string_key, key) in self.written.drain() {
+            match self.values.get(&string_key) {
+                Some(&Some(ref value)) => self.inner.set(&key, &decode_value(value)?),
+                Some(&None) | None => self.inner.delete(&key),
+            }
+        }
+        self.inner.commit().await
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::CompoundTxn;
+    use crate::async_fuse::memfs::kv_engine::local_impl::LocalKVEngine;
+    use crate::async_fuse::memfs::kv_engine::{KVEngine, KeyType, MetaTxn, ValueType};
+
+    #[tokio::test]
+    async fn test_compound_txn() {
+        let dir = Path::new("/tmp/datenlord_local_kv").join("compound");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        let engine = LocalKVEngine::open(&dir).unwrap();
+        let key = KeyType::String("key".to_owned());
+        let removed = KeyType::String("removed".to_owned());
+        let value = ValueType::String("value".to_owned());
+        engine.set(&removed, &value, None).await.unwrap();
+
+        let mut txn = CompoundTxn::new(engine.new_meta_txn().await);
+        assert!(txn.get(&key).await.unwrap().is_none());
+        txn.set(&key, &ValueType::String("first".to_owned()));
+        // The keys written are read back, and written again.
+        assert_eq!(
+            txn.get(&key).await.unwrap(),
+            Some(ValueType::String("first".to_owned()))
+        );
+        txn.set(&key, &value);
+        assert_eq!(txn.get(&removed).await.unwrap(), Some(value.clone()));
+        txn.delete(&removed);
+        assert!(txn.get(&removed).await.unwrap().is_none());
+        // Nothing is visible before the commit.
+        assert!(engine.get(&key).await.unwrap().is_none());
+
+        assert!(txn.commit().await.unwrap());
+        assert_eq!(engine.get(&key).await.unwrap(), Some(value));
+        assert!(engine.get(&removed).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,149 @@
+//! The `KVEngineType`, which dispatches to the etcd engine, the local engine
+//! or the raft engine.
+//!
+//! The operations are served in the spans of their keys, and the transactions
+//! are committed in the spans of the numbers of the keys read and written, so
+//! they're traced as the children of the FUSE requests issuing them, see
+//! `telemetry`.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tracing::instrument;
+
+use super::etcd_impl::EtcdKVEngine;
+use super::local_impl::{LocalKVEngine, LOCAL_ENDPOINT_PREFIX};
+use super::raft_impl::{RaftKVEngine, RAFT_ENDPOINT_PREFIX};
+use super::{
+    DeleteOption, KVEngine, KeyType, LockKeyType, MetaTxn, SetOption, ValueType, WatchStream,
+};
+use crate::common::error::DatenLordResult;
+
+/// The `KVEngineType` is used to provide support for metadata.
+/// We use this enum to avoid generic type.
+///
+/// An endpoint of `file://<dir>` selects the local engine persisting the
+/// metadata in `<dir>`, an endpoint of `raft://` selects the raft engine
+/// replicating the metadata among the nodes, otherwise the endpoints are of
+/// etcd.
+#[derive(Debug, Clone)]
+pub enum KVEngineType {
+    /// The etcd engine, the metadata is shared among nodes
+    Etcd(Box<EtcdKVEngine>),
+    /// The local engine, for single-node deployments
+    Local(LocalKVEngine),
+    /// The raft engine, the metadata is replicated among nodes without etcd
+    Raft(Box<RaftKVEngine>),
+}
+
+#[async_trait]
+impl KVEngine for KVEngineType {
+    async fn new(end_points: Vec<String>) -> DatenLordResult<Self> {
+        let has_prefix = |prefix: &str| {
+            end_points
+                .first()
+                .map_or(false, |end_point| end_point.starts_with(prefix))
+        };
+        if has_prefix(LOCAL_ENDPOINT_PREFIX) {
+            Ok(Self::Local(LocalKVEngine::new(end_points).await?))
+        } else if has_prefix(RAFT_ENDPOINT_PREFIX) {
+            Ok(Self::Raft(Box::new(RaftKVEngine::new(end_points).await?)))
+        } else {
+            Ok(Self::Etcd(Box::new(EtcdKVEngine::new(end_points).await?)))
+        }
+    }
+
+    async fn new_meta_txn(&self) -> Box<dyn MetaTxn + Send> {
+        match *self {
+            Self::Etcd(ref engine) => engine.new_meta_txn().await,
+            Self::Local(ref engine) => engine.new_meta_txn().await,
+            Self::Raft(ref engine) => engine.new_meta_txn().await,
+        }
+    }
+
+    #[instrument(name = "kv_lock", skip_all, fields(key = ?key))]
+    async fn lock(&self, key: &LockKeyType, timeout: Duration) -> DatenLordResult<Vec<u8>> {
+        match *self {
+            Self::Etcd(ref engine) => engine.lock(key, timeout).await,
+            Self::Local(ref engine) => engine.lock(key, timeout).await,
+            Self::Raft(ref engine) => engine.lock(key, timeout).await,
+        }
+    }
+
+    async fn unlock(&self, key: Vec<u8>) -> DatenLordResult<()> {
+        match *self {
+            Self::Etcd(ref engine) => engine.unlock(key).await,
+            Self::Local(ref engine) => engine.unlock(key).await,
+            Self::Raft(ref engine) => engine.unlock(key).await,
+        }
+    }
+
+    #[instrument(name = "kv_get", skip_all, fields(key = ?key))]
+    async fn get(&self, key: &KeyType) -> DatenLordResult<Option<ValueType>> {
+        match *self {
+            Self::Etcd(ref engine) => engine.get(key).await,
+            Self::Local(ref engine) => engine.get(key).await,
+            Self::Raft(ref engine) => engine.get(key).await,
+        }
+    }
+
+    #[instrument(name = "kv_set", skip_all, fields(key = ?key))]
+    async fn set(
+        &self,
+        key: &KeyType,
+        value: &ValueType,
+        option: Option<SetOption>,
+    ) -> DatenLordResult<Option<ValueType>> {
+        match *self {
+            Self::Etcd(ref engine) => engine.set(key, value, option).await,
+            Self::Local(ref engine) => engine.set(key, value, option).await,
+            Self::Raft(ref engine) => engine.set(key, value, option).await,
+        }
+    }
+
+    #[instrument(name = "kv_delete", skip_all, fields(key = ?key))]
+    async fn delete(
+        &self,
+        key: &KeyType,
+        option: Option<DeleteOption>,
+    ) -> DatenLordResult<Option<ValueType>> {
+        match *self {
+            Self::Etcd(ref engine) => engine.delete(key, option).await,
+            Self::Local(ref engine) => engine.delete(key, option).await,
+            Self::Raft(ref engine) => engine.delete(key, option).await,
+        }
+    }
+
+    async fn lease_grant(&self, ttl: i64) -> DatenLordResult<i64> {
+        match *self {
+            Self::Etcd(ref engine) => engine.lease_grant(ttl).await,
+            Self::Local(ref engine) => engine.lease_grant(ttl).await,
+            Self::Raft(ref engine) => engine.lease_grant(ttl).await,
+        }
+    }
+
+    async fn lease_keep_alive(&self, lease: i64) -> DatenLordResult<()> {
+        match *self {
+            Self::Etcd(ref engine) => engine.lease_keep_alive(lease).await,
+            Self::Local(ref engine) => engine.lease_keep_alive(lease).await,
+            Self::Raft(ref engine) => engine.lease_keep_alive(lease).await,
+        }
+    }
+
+    async fn watch(&self, prefix: &KeyType) -> DatenLordResult<WatchStream> {
+        match *self {
+            Self::Etcd(ref engine) => engine.watch(prefix).await,
+            Self::Local(ref engine) => engine.watch(prefix).await,
+            Self::Raft(ref engine) => engine.watch(prefix).await,
+        }
+    }
+
+    #[instrument(name = "kv_range", skip_all, fields(prefix = ?prefix))]
+    async fn range(&self, prefix: &KeyType) -> DatenLordResult<Vec<ValueType>> {
+        match *self {
+            Self::Etcd(ref engine) => engine.range(prefix).await,
+            Self::Local(ref engine) => engine.range(prefix).await,
+            Self::Raft(ref engine) => engine.range(prefix).await,
+        }
+    }
+}
@@ -5,14 +5,18 @@ use std::time::Duration;
 use async_trait::async_trait;
 use datenlord::metrics::KV_METRICS;
 use etcd_client::{
-    Compare, CompareOp, DeleteOptions, GetOptions, LockOptions, PutOptions, Txn, TxnOp,
+    Compare, CompareOp, DeleteOptions, Event, EventType, GetOptions, LockOptions, PutOptions, Txn,
+    TxnOp, WatchOptions, Watcher,
 };
+use futures::StreamExt;
+use tracing::instrument;
 
 use super::{
     check_ttl, conv_u64_sec_2_i64, fmt, DeleteOption, KVEngine, KeyType, KvVersion, LockKeyType,
-    MetaTxn, SetOption, ValueType,
+    MetaTxn, SetOption, ValueType, WatchEvent, WatchStream,
 };
-use crate::common::error::{Context, DatenLordResult};
+use crate::common::async_fuse_error::KVEngineError;
+use crate::common::error::{Context, DatenLordError, DatenLordResult};
 
 #[derive(Clone)]
 /// Wrap the etcd client to support the `KVEngine` trait.
@@ -60,6 +64,21 @@ impl EtcdKVEngine {
     }
 }
 
+/// Convert an event of etcd into a `WatchEvent`, the keys not of `ValueType`,
+/// such as locks, are skipped.
+fn convert_event(event: &Event) -> Option<WatchEvent> {
+    let kv = event.kv()?;
+    match event.event_type() {
+        EventType::Put => serde_json::from_slice::<ValueType>(kv.value())
+            .ok()
+            .map(WatchEvent::Put),
+        EventType::Delete => kv
+            .key_str()
+            .ok()
+            .map(|key| WatchEvent::Delete(key.to_owned())),
+    }
+}
+
 #[async_trait]
 impl KVEngine for EtcdKVEngine {
     async fn new(end_points: Vec<String>) -> DatenLordResult<Self> {
@@ -82,6 +101,49 @@ impl KVEngine for EtcdKVEngine {
             .id())
     }
 
+    async fn lease_keep_alive(&self, lease: i64) -> DatenLordResult<()> {
+        let mut client = self.client.clone();
+        let (mut keeper, mut stream) = client
+            .lease_keep_alive(lease)
+            .await
+            .with_context(|| format!("failed to keep alive lease={lease}"))?;
+        keeper
+            .keep_alive()
+            .await
+            .with_context(|| format!("failed to keep alive lease={lease}"))?;
+        let resp = stream
+            .message()
+            .await
+            .with_context(|| format!("failed to keep alive lease={lease}"))?;
+        // The TTL of an expired lease is 0.
+        if resp.map_or(true, |resp| resp.ttl() <= 0) {
+            return Err(DatenLordError::KVEngineErr {
+                source: KVEngineError::LeaseExpired(lease),
+                context: vec![format!("failed to keep alive lease={lease}")],
+            });
+        }
+        Ok(())
+    }
+
+    async fn watch(&self, prefix: &KeyType) -> DatenLordResult<WatchStream> {
+        let mut client = self.client.clone();
+        let (watcher, stream) = client
+            .watch(
+                prefix.to_string_key(),
+                Some(WatchOptions::new().with_prefix()),
+            )
+            .await
+            .with_context(|| format!("failed to watch etcd, prefix={prefix:?}"))?;
+        let events = stream.map(move |resp| {
+            // The watch is cancelled once the watcher is dropped, so it's kept
+            // along with the stream.
+            let _: &Watcher = &watcher;
+            let resp = resp.with_context(|| "failed to receive from etcd watch".to_owned())?;
+            Ok(resp.events().iter().filter_map(convert_event).collect())
+        });
+        Ok(events.boxed())
+    }
+
     /// Distribute lock - lock
     /// - `timeout_sec` should be >=1s
     /// - `timeout_sec` should be >=1s
@@ -292,6 +354,11 @@ impl MetaTxn for EtcdTxn {
         self.buffer.insert(key, None);
     }
 
+    #[instrument(
+        name = "kv_txn",
+        skip_all,
+        fields(reads = self.version_map.len(), writes = self.buffer.len())
+    )]
     async fn commit(&mut self) -> DatenLordResult<bool> {
         let _timer = KV_METRICS.start_kv_operation_timer("txn");
 
@@ -0,0 +1,160 @@
+//! The `KVEngine` injecting faults into an inner engine, with the
+//! `fault-injection` feature.
+//!
+//! The writes to the metadata are atomic, so a partial write persists nothing
+//! and fails with `EIO`. The writes of a transaction are faulted on commit.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use super::{
+    DeleteOption, KVEngine, KeyType, LockKeyType, MetaTxn, SetOption, ValueType, WatchStream,
+};
+use crate::common::error::{DatenLordError, DatenLordResult};
+use crate::common::fault::{Fault, FaultSchedule};
+
+/// Draw the fault of the next operation from `schedule`, sleeps for a delay,
+/// or returns the error failing the operation.
+async fn inject(schedule: &FaultSchedule, is_write: bool) -> DatenLordResult<()> {
+    let fault = match schedule.draw(is_write) {
+        None => return Ok(()),
+        Some(Fault::Latency(latency)) => {
+            tokio::time::sleep(latency).await;
+            return Ok(());
+        }
+        Some(fault) => fault,
+    };
+    Err(DatenLordError::IoErr {
+        source: fault
+            .io_error()
+            .unwrap_or_else(|| unreachable!("a delay doesn't fail the operation")),
+        context: vec![format!("{fault:?} is injected into the metadata engine")],
+    })
+}
+
+/// A `KVEngine` which injects the faults drawn from a `FaultSchedule` into
+/// the operations on the inner engine.
+#[derive(Debug)]
+pub struct FaultyKVEngine<E> {
+    /// The inner engine
+    inner: E,
+    /// The schedule of faults
+    schedule: Arc<FaultSchedule>,
+}
+
+impl<E> FaultyKVEngine<E> {
+    /// Wrap `inner` to inject the faults of `schedule`.
+    #[must_use]
+    pub fn with_schedule(inner: E, schedule: Arc<FaultSchedule>) -> Self {
+        Self { inner, schedule }
+    }
+
+    /// The inner engine.
+    #[must_use]
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+}
+
+#[async_trait]
+impl<E: KVEngine> KVEngine for FaultyKVEngine<E> {
+    /// Create the inner engine with a schedule injecting no fault.
+    async fn new(end_points: Vec<String>) -> DatenLordResult<Self> {
+        Ok(Self::with_schedule(
+            E::new(end_points).await?,
+            Arc::new(FaultSchedule::new(0)),
+        ))
+    }
+
+    async fn new_meta_txn(&self) -> Box<dyn MetaTxn + Send> {
+        Box::new(FaultyMetaTxn {
+            inner: self.inner.new_meta_txn().await,
+            schedule: Arc::clone(&self.schedule),
+        })
+    }
+
+    async fn lock(&self, key: &LockKeyType, timeout: Duration) -> DatenLordResult<Vec<u8>> {
+        inject(&self.schedule, false).await?;
+        self.inner.lock(key, timeout).await
+    }
+
+    async fn unlock(&self, key: Vec<u8>) -> DatenLordResult<()> {
+        inject(&self.schedule, false).await?;
+        self.inner.unlock(key).await
+    }
+
+    async fn get(&self, key: &KeyType) -> DatenLordResult<Option<ValueType>> {
+        inject(&self.schedule, false).await?;
+        self.inner.get(key).await
+    }
+
+    async fn set(
+        &self,
+        key: &KeyType,
+        value: &ValueType,
+        option: Option<SetOption>,
+    ) -> DatenLordResult<Option<ValueType>> {
+        inject(&self.schedule, true).await?;
+        self.inner.set(key, value, option).await
+    }
+
+    async fn delete(
+        &self,
+        key: &KeyType,
+        option: Option<DeleteOption>,
+    ) -> DatenLordResult<Option<ValueType>> {
+        inject(&self.schedule, false).await?;
+        self.inner.delete(key, option).await
+    }
+
+    async fn lease_grant(&self, ttl: i64) -> DatenLordResult<i64> {
+        inject(&self.schedule, false).await?;
+        self.inner.lease_grant(ttl).await
+    }
+
+    async fn lease_keep_alive(&self, lease: i64) -> DatenLordResult<()> {
+        inject(&self.schedule, false).await?;
+        self.inner.lease_keep_alive(lease).await
+    }
+
+    async fn watch(&self, prefix: &KeyType) -> DatenLordResult<WatchStream> {
+        inject(&self.schedule, false).await?;
+        self.inner.watch(prefix).await
+    }
+
+    async fn range(&self, prefix: &KeyType) -> DatenLordResult<Vec<ValueType>> {
+        inject(&self.schedule, false).await?;
+        self.inner.range(prefix).await
+    }
+}
+
+/// A `MetaTxn` of a `FaultyKVEngine`, whose reads and commit are faulted.
+struct FaultyMetaTxn {
+    /// The transaction of the inner engine
+    inner: Box<dyn MetaTxn + Send>,
+    /// The schedule of faults
+    schedule: Arc<FaultSchedule>,
+}
+
+#[async_trait]
+impl MetaTxn for FaultyMetaTxn {
+    async fn get(&mut self, key: &KeyType) -> DatenLordResult<Option<ValueType>> {
+        inject(&self.schedule, false).await?;
+        self.inner.get(key).await
+    }
+
+    fn set(&mut self, key: &KeyType, value: &ValueType) {
+        self.inner.set(key, value);
+    }
+
+    fn delete(&mut self, key: &KeyType) {
+        self.inner.delete(key);
+    }
+
+    async fn commit(&mut self) -> DatenLordResult<bool> {
+        inject(&self.schedule, true).await?;
+        self.inner.commit().await
+    }
+}
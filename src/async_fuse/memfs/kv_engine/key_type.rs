@@ -2,16 +2,20 @@ use std::fmt::{self, Display, Formatter, Write};
 
 use crate::async_fuse::fuse::protocol::INum;
 use crate::async_fuse::memfs::id_alloc::IdType;
+use crate::storage::dedup::ChunkHash;
 
 /// The `KeyType` is used to locate the value in the distributed K/V storage.
 /// Every key is prefixed with a string to indicate the type of the value.
 /// If you want to add a new type of value, you need to add a new variant to the
 /// enum. And you need to add a new match arm to the `get_key` function , make
 /// sure the key is unique.
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum KeyType {
     /// INum -> SerailNode
     INum2Node(INum),
+    /// The prefix of all `INum2Node`s, only used for watching, it covers the
+    /// `IdAllocatorValue`s as well
+    AllINum2Nodes,
     /// (paretn_id,child_name) -> DirEntry
     DirEntryKey((INum, String)),
     /// IdAllocator value key
@@ -19,6 +23,117 @@ pub enum KeyType {
     /// Node list
     /// The corresponding value type is ValueType::RawData
     FileNodeList(INum),
+    /// INum -> ChunkIndex
+    ChunkIndex(INum),
+    /// The information of the volume
+    VolumeInfo,
+    /// The wrapped data keys of the volume
+    VolumeDataKeys,
+    /// (INum, block id) -> BlockRecipe
+    BlockRecipe(INum, usize),
+    /// The prefix of all `BlockRecipe`s of a file, only used for range get
+    FileBlockRecipes(INum),
+    /// ChunkHash -> the refcount of the chunk
+    ChunkRefCount(ChunkHash),
+    /// (INum, block id) -> BlockChecksum
+    BlockChecksum(INum, usize),
+    /// The prefix of all `BlockChecksum`s of a file, only used for range get
+    FileBlockChecksums(INum),
+    /// The prefix of all `BlockChecksum`s, only used for range get
+    AllBlockChecksums,
+    /// (INum, block id) -> BlockVersions
+    BlockVersions(INum, usize),
+    /// The prefix of all `BlockVersions` of a file, only used for range get
+    FileBlockVersions(INum),
+    /// The prefix of all `BlockVersions`, only used for range get
+    AllBlockVersions,
+    /// The current snapshot epoch of the volume
+    SnapshotEpoch,
+    /// Snapshot name -> SnapshotInfo
+    SnapshotInfo(String),
+    /// The prefix of all `SnapshotInfo`s, only used for range get
+    AllSnapshots,
+    /// (snapshot id, the key of the record) -> SnapshotRecord
+    SnapshotRecord(u64, String),
+    /// The prefix of all `SnapshotRecord`s of a snapshot, only used for range
+    /// get
+    SnapshotRecords(u64),
+    /// The i-number of a quota root -> Quota
+    Quota(INum),
+    /// The prefix of all `Quota`s, only used for range get
+    AllQuotas,
+    /// Node id -> the VolumeUsage charged by the node, the baseline is of an
+    /// empty node id
+    VolumeUsage(String),
+    /// The prefix of all `VolumeUsage`s, only used for range get
+    AllVolumeUsages,
+    /// Node id -> the NodeIo checkpointed by the node
+    NodeIo(String),
+    /// The prefix of all `NodeIo`s, only used for range get
+    AllNodeIos,
+    /// The StorageTime charged to the volume
+    VolumeStorageTime,
+    /// The i-number of a removed file -> TrashEntry
+    Trash(INum),
+    /// The prefix of all `TrashEntry`s, only used for range get
+    AllTrash,
+    /// (i-number, block id) -> BlockTier
+    BlockTier(INum, usize),
+    /// The prefix of `BlockTier`s of a file, only used for range get
+    FileBlockTiers(INum),
+    /// The prefix of all `BlockTier`s, only used for range get
+    AllBlockTiers,
+    /// i-number -> the Tier the file or directory is pinned to
+    TierPin(INum),
+    /// i-number -> Scratch, the directory whose removed descendants skip the
+    /// trash
+    Scratch(INum),
+    /// i-number -> the access PosixAcl of the file or directory
+    AccessAcl(INum),
+    /// i-number -> the default PosixAcl of the directory
+    DefaultAcl(INum),
+    /// i-number -> the SELinux label of the file or directory
+    SecurityLabel(INum),
+    /// Node id -> NodeRegistration, which expires with the lease of the node
+    NodeRegistration(String),
+    /// The prefix of all `NodeRegistration`s, only used for range get
+    AllNodeRegistrations,
+    /// i-number -> InodeLease, the nodes holding the lease of the file
+    InodeLease(INum),
+    /// (node id, i-number) -> LeaseRecall, the lease of the file to be
+    /// released by the node
+    LeaseRecall(String, INum),
+    /// The prefix of all `LeaseRecall`s of a node, only used for range get
+    /// and watching
+    NodeLeaseRecalls(String),
+    /// i-number -> FileLocks, the POSIX locks of the file
+    FileLocks(INum),
+    /// i-number -> FileOpeners, the nodes opening the file
+    FileOpeners(INum),
+    /// (i-number, block id) -> BlockReplicas
+    BlockReplicas(INum, usize),
+    /// The prefix of `BlockReplicas` of a file, only used for range get
+    FileBlockReplicas(INum),
+    /// The prefix of all `BlockReplicas`, only used for range get
+    AllBlockReplicas,
+    /// (i-number, block id) -> BlockShards
+    BlockShards(INum, usize),
+    /// The prefix of `BlockShards` of a file, only used for range get
+    FileBlockShards(INum),
+    /// (i-number, block id) -> BlockHolders, the nodes caching the block
+    BlockHolders(INum, usize),
+    /// The prefix of `BlockHolders` of a file, only used for range get
+    FileBlockHolders(INum),
+    /// Volume name -> VolumeSpec, a named volume of the volume manager
+    VolumeSpec(String),
+    /// The prefix of all `VolumeSpec`s, only used for range get
+    AllVolumeSpecs,
+    /// (i-number, block id) -> PackedBlock
+    PackedBlock(INum, usize),
+    /// The prefix of `PackedBlock`s of a file, only used for range get
+    FilePackedBlocks(INum),
+    /// The time a scrub pass is last requested by the volume manager
+    ScrubRequest,
     /// Just a string key for testing the KVEngine.
     #[cfg(test)]
     String(String),
@@ -41,11 +156,83 @@ impl Display for KeyType {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match *self {
             KeyType::INum2Node(ref inum) => write!(f, "INum2Node({inum})"),
+            KeyType::AllINum2Nodes => write!(f, "AllINum2Nodes"),
             KeyType::DirEntryKey((ref parent_id, ref child_name)) => {
                 write!(f, "DirEntryKey(({parent_id}, {child_name}))")
             }
             KeyType::IdAllocatorValue(ref id_type) => write!(f, "IdAllocatorValue({id_type})"),
             KeyType::FileNodeList(ref inum) => write!(f, "FileNodeList({inum})"),
+            KeyType::ChunkIndex(ref inum) => write!(f, "ChunkIndex({inum})"),
+            KeyType::VolumeInfo => write!(f, "VolumeInfo"),
+            KeyType::VolumeDataKeys => write!(f, "VolumeDataKeys"),
+            KeyType::BlockRecipe(ref inum, ref block_id) => {
+                write!(f, "BlockRecipe({inum}, {block_id})")
+            }
+            KeyType::FileBlockRecipes(ref inum) => write!(f, "FileBlockRecipes({inum})"),
+            KeyType::ChunkRefCount(ref hash) => write!(f, "ChunkRefCount({hash})"),
+            KeyType::BlockChecksum(ref inum, ref block_id) => {
+                write!(f, "BlockChecksum({inum}, {block_id})")
+            }
+            KeyType::FileBlockChecksums(ref inum) => write!(f, "FileBlockChecksums({inum})"),
+            KeyType::AllBlockChecksums => write!(f, "AllBlockChecksums"),
+            KeyType::BlockVersions(ref inum, ref block_id) => {
+                write!(f, "BlockVersions({inum}, {block_id})")
+            }
+            KeyType::FileBlockVersions(ref inum) => write!(f, "FileBlockVersions({inum})"),
+            KeyType::AllBlockVersions => write!(f, "AllBlockVersions"),
+            KeyType::SnapshotEpoch => write!(f, "SnapshotEpoch"),
+            KeyType::SnapshotInfo(ref name) => write!(f, "SnapshotInfo({name})"),
+            KeyType::AllSnapshots => write!(f, "AllSnapshots"),
+            KeyType::SnapshotRecord(ref id, ref key) => write!(f, "SnapshotRecord({id}, {key})"),
+            KeyType::SnapshotRecords(ref id) => write!(f, "SnapshotRecords({id})"),
+            KeyType::Quota(ref inum) => write!(f, "Quota({inum})"),
+            KeyType::AllQuotas => write!(f, "AllQuotas"),
+            KeyType::VolumeUsage(ref node_id) => write!(f, "VolumeUsage({node_id})"),
+            KeyType::AllVolumeUsages => write!(f, "AllVolumeUsages"),
+            KeyType::NodeIo(ref node_id) => write!(f, "NodeIo({node_id})"),
+            KeyType::AllNodeIos => write!(f, "AllNodeIos"),
+            KeyType::VolumeStorageTime => write!(f, "VolumeStorageTime"),
+            KeyType::Trash(ref inum) => write!(f, "Trash({inum})"),
+            KeyType::AllTrash => write!(f, "AllTrash"),
+            KeyType::BlockTier(ref inum, ref block_id) => {
+                write!(f, "BlockTier({inum}, {block_id})")
+            }
+            KeyType::FileBlockTiers(ref inum) => write!(f, "FileBlockTiers({inum})"),
+            KeyType::AllBlockTiers => write!(f, "AllBlockTiers"),
+            KeyType::TierPin(ref inum) => write!(f, "TierPin({inum})"),
+            KeyType::Scratch(ref inum) => write!(f, "Scratch({inum})"),
+            KeyType::AccessAcl(ref inum) => write!(f, "AccessAcl({inum})"),
+            KeyType::DefaultAcl(ref inum) => write!(f, "DefaultAcl({inum})"),
+            KeyType::SecurityLabel(ref inum) => write!(f, "SecurityLabel({inum})"),
+            KeyType::NodeRegistration(ref node_id) => write!(f, "NodeRegistration({node_id})"),
+            KeyType::AllNodeRegistrations => write!(f, "AllNodeRegistrations"),
+            KeyType::InodeLease(ref inum) => write!(f, "InodeLease({inum})"),
+            KeyType::LeaseRecall(ref node_id, ref inum) => {
+                write!(f, "LeaseRecall({node_id}, {inum})")
+            }
+            KeyType::NodeLeaseRecalls(ref node_id) => write!(f, "NodeLeaseRecalls({node_id})"),
+            KeyType::FileLocks(ref inum) => write!(f, "FileLocks({inum})"),
+            KeyType::FileOpeners(ref inum) => write!(f, "FileOpeners({inum})"),
+            KeyType::BlockReplicas(ref inum, ref block_id) => {
+                write!(f, "BlockReplicas({inum}, {block_id})")
+            }
+            KeyType::FileBlockReplicas(ref inum) => write!(f, "FileBlockReplicas({inum})"),
+            KeyType::AllBlockReplicas => write!(f, "AllBlockReplicas"),
+            KeyType::BlockShards(ref inum, ref block_id) => {
+                write!(f, "BlockShards({inum}, {block_id})")
+            }
+            KeyType::FileBlockShards(ref inum) => write!(f, "FileBlockShards({inum})"),
+            KeyType::BlockHolders(ref inum, ref block_id) => {
+                write!(f, "BlockHolders({inum}, {block_id})")
+            }
+            KeyType::FileBlockHolders(ref inum) => write!(f, "FileBlockHolders({inum})"),
+            KeyType::VolumeSpec(ref name) => write!(f, "VolumeSpec({name})"),
+            KeyType::AllVolumeSpecs => write!(f, "AllVolumeSpecs"),
+            KeyType::PackedBlock(ref inum, ref block_id) => {
+                write!(f, "PackedBlock({inum}, {block_id})")
+            }
+            KeyType::FilePackedBlocks(ref inum) => write!(f, "FilePackedBlocks({inum})"),
+            KeyType::ScrubRequest => write!(f, "ScrubRequest"),
             #[cfg(test)]
             KeyType::String(ref s) => write!(f, "String({s})"),
         }
@@ -84,12 +271,50 @@ impl KeyType {
     #[must_use]
     pub fn prefix(&self) -> &str {
         match *self {
-            KeyType::INum2Node(_) => "I",
+            KeyType::INum2Node(_) | KeyType::AllINum2Nodes => "I",
             KeyType::DirEntryKey(_) => "D",
             #[cfg(test)]
             KeyType::String(_) => "TEST_",
             KeyType::IdAllocatorValue(_) => "IdAlloc",
             KeyType::FileNodeList(_) => "FileNodeList",
+            KeyType::ChunkIndex(_) => "C",
+            KeyType::VolumeInfo => "VolumeInfo",
+            KeyType::VolumeDataKeys => "VolumeDataKeys",
+            KeyType::BlockRecipe(..) | KeyType::FileBlockRecipes(_) => "R",
+            KeyType::ChunkRefCount(_) => "H",
+            KeyType::BlockChecksum(..)
+            | KeyType::FileBlockChecksums(_)
+            | KeyType::AllBlockChecksums => "K",
+            KeyType::BlockVersions(..)
+            | KeyType::FileBlockVersions(_)
+            | KeyType::AllBlockVersions => "B",
+            KeyType::SnapshotEpoch => "SnapshotEpoch",
+            KeyType::SnapshotInfo(_) | KeyType::AllSnapshots => "SnapshotInfo",
+            KeyType::SnapshotRecord(..) | KeyType::SnapshotRecords(_) => "SnapshotRecord",
+            KeyType::Quota(_) | KeyType::AllQuotas => "Q",
+            KeyType::VolumeUsage(_) | KeyType::AllVolumeUsages => "VolumeUsage",
+            KeyType::NodeIo(_) | KeyType::AllNodeIos => "NodeIo",
+            KeyType::VolumeStorageTime => "VolumeStorageTime",
+            KeyType::Trash(_) | KeyType::AllTrash => "Trash",
+            KeyType::BlockTier(..) | KeyType::FileBlockTiers(_) | KeyType::AllBlockTiers => "Tier",
+            KeyType::TierPin(_) => "P",
+            KeyType::Scratch(_) => "Scratch",
+            KeyType::AccessAcl(_) => "AclAccess",
+            KeyType::DefaultAcl(_) => "AclDefault",
+            KeyType::SecurityLabel(_) => "SecurityLabel",
+            KeyType::NodeRegistration(_) | KeyType::AllNodeRegistrations => "NodeRegistration",
+            KeyType::InodeLease(_) => "L",
+            KeyType::LeaseRecall(..) | KeyType::NodeLeaseRecalls(_) => "LeaseRecall",
+            KeyType::FileLocks(_) => "PosixLock",
+            KeyType::FileOpeners(_) => "Opener",
+            KeyType::BlockReplicas(..)
+            | KeyType::FileBlockReplicas(_)
+            | KeyType::AllBlockReplicas => "Replica",
+            KeyType::BlockShards(..) | KeyType::FileBlockShards(_) => "Shard",
+            KeyType::BlockHolders(..) | KeyType::FileBlockHolders(_) => "Holder",
+            KeyType::VolumeSpec(_) | KeyType::AllVolumeSpecs => "VolumeSpec",
+            KeyType::PackedBlock(..) | KeyType::FilePackedBlocks(_) => "Pack",
+            KeyType::ScrubRequest => "ScrubRequest",
         }
     }
 
@@ -109,9 +334,85 @@ impl KeyType {
             KeyType::IdAllocatorValue(ref id_type) => {
                 write!(f, "{id_type}").unwrap();
             }
-            KeyType::FileNodeList(ref inum) => {
+            KeyType::FileNodeList(ref inum)
+            | KeyType::ChunkIndex(ref inum)
+            | KeyType::Quota(ref inum)
+            | KeyType::Trash(ref inum)
+            | KeyType::TierPin(ref inum)
+            | KeyType::Scratch(ref inum)
+            | KeyType::AccessAcl(ref inum)
+            | KeyType::DefaultAcl(ref inum)
+            | KeyType::SecurityLabel(ref inum)
+            | KeyType::InodeLease(ref inum)
+            | KeyType::FileLocks(ref inum)
+            | KeyType::FileOpeners(ref inum) => {
                 write!(f, "{inum}").unwrap();
             }
+            KeyType::VolumeInfo
+            | KeyType::VolumeDataKeys
+            | KeyType::VolumeStorageTime
+            | KeyType::ScrubRequest => {
+                // No additional data is appended for the records of the
+                // volume
+            }
+            KeyType::BlockRecipe(ref inum, ref block_id)
+            | KeyType::BlockChecksum(ref inum, ref block_id)
+            | KeyType::BlockVersions(ref inum, ref block_id)
+            | KeyType::BlockTier(ref inum, ref block_id)
+            | KeyType::BlockReplicas(ref inum, ref block_id)
+            | KeyType::BlockShards(ref inum, ref block_id)
+            | KeyType::BlockHolders(ref inum, ref block_id)
+            | KeyType::PackedBlock(ref inum, ref block_id) => {
+                write!(f, "{inum}_{block_id}").unwrap();
+            }
+            KeyType::FileBlockRecipes(ref inum)
+            | KeyType::FileBlockChecksums(ref inum)
+            | KeyType::FileBlockVersions(ref inum)
+            | KeyType::FileBlockTiers(ref inum)
+            | KeyType::FileBlockReplicas(ref inum)
+            | KeyType::FileBlockShards(ref inum)
+            | KeyType::FileBlockHolders(ref inum)
+            | KeyType::FilePackedBlocks(ref inum) => {
+                write!(f, "{inum}_").unwrap();
+            }
+            KeyType::AllBlockChecksums
+            | KeyType::AllBlockVersions
+            | KeyType::SnapshotEpoch
+            | KeyType::AllSnapshots
+            | KeyType::AllQuotas
+            | KeyType::AllVolumeUsages
+            | KeyType::AllNodeIos
+            | KeyType::AllTrash
+            | KeyType::AllBlockTiers
+            | KeyType::AllINum2Nodes
+            | KeyType::AllNodeRegistrations
+            | KeyType::AllBlockReplicas
+            | KeyType::AllVolumeSpecs => {
+                // No additional data is appended for the prefixes of all
+                // records and the snapshot epoch
+            }
+            KeyType::SnapshotInfo(ref name)
+            | KeyType::VolumeUsage(ref name)
+            | KeyType::NodeIo(ref name)
+            | KeyType::NodeRegistration(ref name)
+            | KeyType::VolumeSpec(ref name) => {
+                write!(f, "{name}").unwrap();
+            }
+            KeyType::SnapshotRecord(ref id, ref key) => {
+                write!(f, "{id}_{key}").unwrap();
+            }
+            KeyType::SnapshotRecords(ref id) => {
+                write!(f, "{id}_").unwrap();
+            }
+            KeyType::LeaseRecall(ref node_id, ref inum) => {
+                write!(f, "{node_id}_{inum}").unwrap();
+            }
+            KeyType::NodeLeaseRecalls(ref node_id) => {
+                write!(f, "{node_id}_").unwrap();
+            }
+            KeyType::ChunkRefCount(ref hash) => {
+                write!(f, "{hash}").unwrap();
+            }
         }
     }
 }
@@ -190,6 +491,322 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_chunkindex_key() {
+        let key = KeyType::ChunkIndex(789);
+        assert_eq!(key.to_string_key(), "C789", "ChunkIndex key mismatch");
+    }
+
+    #[test]
+    fn test_volumeinfo_key() {
+        let key = KeyType::VolumeInfo;
+        assert_eq!(key.to_string_key(), "VolumeInfo", "VolumeInfo key mismatch");
+    }
+
+    #[test]
+    fn test_volumedatakeys_key() {
+        let key = KeyType::VolumeDataKeys;
+        assert_eq!(
+            key.to_string_key(),
+            "VolumeDataKeys",
+            "VolumeDataKeys key mismatch"
+        );
+    }
+
+    #[test]
+    fn test_blockrecipe_key() {
+        let key = KeyType::BlockRecipe(789, 3);
+        assert_eq!(key.to_string_key(), "R789_3", "BlockRecipe key mismatch");
+        let key = KeyType::FileBlockRecipes(789);
+        assert_eq!(
+            key.to_string_key(),
+            "R789_",
+            "FileBlockRecipes key mismatch"
+        );
+    }
+
+    #[test]
+    fn test_blockchecksum_key() {
+        let key = KeyType::BlockChecksum(789, 3);
+        assert_eq!(key.to_string_key(), "K789_3", "BlockChecksum key mismatch");
+        let key = KeyType::FileBlockChecksums(789);
+        assert_eq!(
+            key.to_string_key(),
+            "K789_",
+            "FileBlockChecksums key mismatch"
+        );
+        let key = KeyType::AllBlockChecksums;
+        assert_eq!(key.to_string_key(), "K", "AllBlockChecksums key mismatch");
+    }
+
+    #[test]
+    fn test_blockversions_key() {
+        let key = KeyType::BlockVersions(789, 3);
+        assert_eq!(key.to_string_key(), "B789_3", "BlockVersions key mismatch");
+        let key = KeyType::FileBlockVersions(789);
+        assert_eq!(
+            key.to_string_key(),
+            "B789_",
+            "FileBlockVersions key mismatch"
+        );
+        let key = KeyType::AllBlockVersions;
+        assert_eq!(key.to_string_key(), "B", "AllBlockVersions key mismatch");
+    }
+
+    #[test]
+    fn test_snapshot_key() {
+        let key = KeyType::SnapshotEpoch;
+        assert_eq!(
+            key.to_string_key(),
+            "SnapshotEpoch",
+            "SnapshotEpoch key mismatch"
+        );
+        let key = KeyType::SnapshotInfo("daily".to_owned());
+        assert_eq!(
+            key.to_string_key(),
+            "SnapshotInfodaily",
+            "SnapshotInfo key mismatch"
+        );
+        let key = KeyType::AllSnapshots;
+        assert_eq!(
+            key.to_string_key(),
+            "SnapshotInfo",
+            "AllSnapshots key mismatch"
+        );
+        let key = KeyType::SnapshotRecord(3, "I123".to_owned());
+        assert_eq!(
+            key.to_string_key(),
+            "SnapshotRecord3_I123",
+            "SnapshotRecord key mismatch"
+        );
+        let key = KeyType::SnapshotRecords(3);
+        assert_eq!(
+            key.to_string_key(),
+            "SnapshotRecord3_",
+            "SnapshotRecords key mismatch"
+        );
+    }
+
+    #[test]
+    fn test_quota_key() {
+        let key = KeyType::Quota(123);
+        assert_eq!(key.to_string_key(), "Q123", "Quota key mismatch");
+        let key = KeyType::AllQuotas;
+        assert_eq!(key.to_string_key(), "Q", "AllQuotas key mismatch");
+    }
+
+    #[test]
+    fn test_volume_usage_key() {
+        let key = KeyType::VolumeUsage("node1".to_owned());
+        assert_eq!(
+            key.to_string_key(),
+            "VolumeUsagenode1",
+            "VolumeUsage key mismatch"
+        );
+        let key = KeyType::AllVolumeUsages;
+        assert_eq!(
+            key.to_string_key(),
+            "VolumeUsage",
+            "AllVolumeUsages key mismatch"
+        );
+    }
+
+    #[test]
+    fn test_node_io_key() {
+        let key = KeyType::NodeIo("node1".to_owned());
+        assert_eq!(key.to_string_key(), "NodeIonode1", "NodeIo key mismatch");
+        let key = KeyType::AllNodeIos;
+        assert_eq!(key.to_string_key(), "NodeIo", "AllNodeIos key mismatch");
+        let key = KeyType::VolumeStorageTime;
+        assert_eq!(
+            key.to_string_key(),
+            "VolumeStorageTime",
+            "VolumeStorageTime key mismatch"
+        );
+    }
+
+    #[test]
+    fn test_trash_key() {
+        let key = KeyType::Trash(123);
+        assert_eq!(key.to_string_key(), "Trash123", "Trash key mismatch");
+        let key = KeyType::AllTrash;
+        assert_eq!(key.to_string_key(), "Trash", "AllTrash key mismatch");
+    }
+
+    #[test]
+    fn test_tier_key() {
+        let key = KeyType::BlockTier(123, 4);
+        assert_eq!(key.to_string_key(), "Tier123_4", "BlockTier key mismatch");
+        let key = KeyType::FileBlockTiers(123);
+        assert_eq!(
+            key.to_string_key(),
+            "Tier123_",
+            "FileBlockTiers key mismatch"
+        );
+        let key = KeyType::AllBlockTiers;
+        assert_eq!(key.to_string_key(), "Tier", "AllBlockTiers key mismatch");
+        let key = KeyType::TierPin(123);
+        assert_eq!(key.to_string_key(), "P123", "TierPin key mismatch");
+    }
+
+    #[test]
+    fn test_scratch_key() {
+        let key = KeyType::Scratch(123);
+        assert_eq!(key.to_string_key(), "Scratch123", "Scratch key mismatch");
+    }
+
+    #[test]
+    fn test_acl_key() {
+        let key = KeyType::AccessAcl(123);
+        assert_eq!(
+            key.to_string_key(),
+            "AclAccess123",
+            "AccessAcl key mismatch"
+        );
+        let key = KeyType::DefaultAcl(123);
+        assert_eq!(
+            key.to_string_key(),
+            "AclDefault123",
+            "DefaultAcl key mismatch"
+        );
+    }
+
+    #[test]
+    fn test_security_label_key() {
+        let key = KeyType::SecurityLabel(123);
+        assert_eq!(
+            key.to_string_key(),
+            "SecurityLabel123",
+            "SecurityLabel key mismatch"
+        );
+    }
+
+    #[test]
+    fn test_node_registration_key() {
+        let key = KeyType::NodeRegistration("node1".to_owned());
+        assert_eq!(
+            key.to_string_key(),
+            "NodeRegistrationnode1",
+            "NodeRegistration key mismatch"
+        );
+        let key = KeyType::AllNodeRegistrations;
+        assert_eq!(
+            key.to_string_key(),
+            "NodeRegistration",
+            "AllNodeRegistrations key mismatch"
+        );
+        let key = KeyType::AllINum2Nodes;
+        assert_eq!(key.to_string_key(), "I", "AllINum2Nodes key mismatch");
+    }
+
+    #[test]
+    fn test_file_locks_key() {
+        let key = KeyType::FileLocks(123);
+        assert_eq!(
+            key.to_string_key(),
+            "PosixLock123",
+            "FileLocks key mismatch"
+        );
+    }
+
+    #[test]
+    fn test_file_openers_key() {
+        let key = KeyType::FileOpeners(123);
+        assert_eq!(key.to_string_key(), "Opener123", "FileOpeners key mismatch");
+    }
+
+    #[test]
+    fn test_replica_key() {
+        let key = KeyType::BlockReplicas(123, 4);
+        assert_eq!(
+            key.to_string_key(),
+            "Replica123_4",
+            "BlockReplicas key mismatch"
+        );
+        let key = KeyType::FileBlockReplicas(123);
+        assert_eq!(
+            key.to_string_key(),
+            "Replica123_",
+            "FileBlockReplicas key mismatch"
+        );
+        let key = KeyType::AllBlockReplicas;
+        assert_eq!(
+            key.to_string_key(),
+            "Replica",
+            "AllBlockReplicas key mismatch"
+        );
+    }
+
+    #[test]
+    fn test_shard_key() {
+        let key = KeyType::BlockShards(123, 4);
+        assert_eq!(
+            key.to_string_key(),
+            "Shard123_4",
+            "BlockShards key mismatch"
+        );
+        let key = KeyType::FileBlockShards(123);
+        assert_eq!(
+            key.to_string_key(),
+            "Shard123_",
+            "FileBlockShards key mismatch"
+        );
+    }
+
+    #[test]
+    fn test_holder_key() {
+        let key = KeyType::BlockHolders(123, 4);
+        assert_eq!(
+            key.to_string_key(),
+            "Holder123_4",
+            "BlockHolders key mismatch"
+        );
+        let key = KeyType::FileBlockHolders(123);
+        assert_eq!(
+            key.to_string_key(),
+            "Holder123_",
+            "FileBlockHolders key mismatch"
+        );
+    }
+
+    #[test]
+    fn test_volume_spec_key() {
+        let key = KeyType::VolumeSpec("pvc-1".to_owned());
+        assert_eq!(
+            key.to_string_key(),
+            "VolumeSpecpvc-1",
+            "VolumeSpec key mismatch"
+        );
+        let key = KeyType::AllVolumeSpecs;
+        assert_eq!(
+            key.to_string_key(),
+            "VolumeSpec",
+            "AllVolumeSpecs key mismatch"
+        );
+    }
+
+    #[test]
+    fn test_packed_block_key() {
+        let key = KeyType::PackedBlock(123, 4);
+        assert_eq!(key.to_string_key(), "Pack123_4", "PackedBlock key mismatch");
+        let key = KeyType::FilePackedBlocks(123);
+        assert_eq!(
+            key.to_string_key(),
+            "Pack123_",
+            "FilePackedBlocks key mismatch"
+        );
+    }
+
+    #[test]
+    fn test_scrub_request_key() {
+        let key = KeyType::ScrubRequest;
+        assert_eq!(
+            key.to_string_key(),
+            "ScrubRequest",
+            "ScrubRequest key mismatch"
+        );
+    }
+
     #[cfg(test)]
     #[test]
     fn test_string_key() {
@@ -0,0 +1,625 @@
+//! A local `KVEngine` persisting the metadata in a directory, for single-node
+//! deployments without etcd.
+//!
+//! The whole key space is kept in memory. Every committed write is appended
+//! to a write-ahead journal and synced before it's acknowledged, and the
+//! journal is compacted into a snapshot once it grows too large. On open, the
+//! snapshot is loaded and the journal is replayed, a torn record at the tail
+//! of the journal is left by a crash in the middle of a write, which was never
+//! acknowledged, so it's discarded.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Debug;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use clippy_utilities::{Cast, OverflowArithmetic};
+use datenlord::metrics::KV_METRICS;
+use futures::stream::{self, StreamExt};
+use parking_lot::Mutex;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::Notify;
+use tracing::{info, instrument, warn};
+
+use super::{
+    DeleteOption, KVEngine, KeyType, KvVersion, LockKeyType, MetaTxn, SetOption, ValueType,
+    WatchStream,
+};
+use crate::common::error::{Context, DatenLordResult};
+
+/// The prefix of endpoints of the local engine, followed by the directory.
+pub const LOCAL_ENDPOINT_PREFIX: &str = "file://";
+/// The name of the snapshot file
+const SNAPSHOT_FILE: &str = "snapshot";
+/// The name of the snapshot file being written
+const SNAPSHOT_TMP_FILE: &str = "snapshot.tmp";
+/// The name of the journal file
+const JOURNAL_FILE: &str = "journal";
+/// The journal is compacted into the snapshot once it's larger than this.
+const COMPACT_THRESHOLD: u64 = 64 * 1024 * 1024;
+/// The length of a record header, the length and the checksum of the payload
+const RECORD_HEADER_LEN: usize = 8;
+
+/// A batch of writes, a `None` value deletes the key.
+pub(super) type WriteBatch = Vec<(Vec<u8>, Option<Vec<u8>>)>;
+
+/// A value with its version.
+#[derive(Debug)]
+struct Entry {
+    /// The serialized value
+    value: Vec<u8>,
+    /// The number of modifications since the key is created
+    version: KvVersion,
+}
+
+/// The persisted key space.
+#[derive(Debug)]
+struct Store {
+    /// The directory of the snapshot and the journal
+    dir: PathBuf,
+    /// All the keys and values
+    entries: BTreeMap<Vec<u8>, Entry>,
+    /// The journal opened for appending
+    journal: File,
+    /// The length of the journal in bytes
+    journal_len: u64,
+}
+
+impl Store {
+    /// Open the store in `dir`, recover it from the snapshot and the journal.
+    fn open(dir: &Path) -> DatenLordResult<Self> {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create the metadata directory {dir:?}"))?;
+
+        let mut entries = BTreeMap::new();
+        let snapshot_path = dir.join(SNAPSHOT_FILE);
+        if snapshot_path.exists() {
+            let bytes = fs::read(&snapshot_path)
+                .with_context(|| format!("failed to read the snapshot {snapshot_path:?}"))?;
+            let snapshot: Vec<(Vec<u8>, Vec<u8>)> = bincode::deserialize(&bytes)
+                .with_context(|| format!("failed to decode the snapshot {snapshot_path:?}"))?;
+            for (key, value) in snapshot {
+                entries.insert(key, Entry { value, version: 1 });
+            }
+        }
+
+        let journal_path = dir.join(JOURNAL_FILE);
+        let mut journal = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&journal_path)
+            .with_context(|| format!("failed to open the journal {journal_path:?}"))?;
+        let mut bytes = vec![];
+        journal
+            .read_to_end(&mut bytes)
+            .with_context(|| format!("failed to read the journal {journal_path:?}"))?;
+
+        let mut store = Self {
+            dir: dir.to_owned(),
+            entries,
+            journal,
+            journal_len: 0,
+        };
+        let mut replayed = 0_usize;
+        while let Some((batch, len)) =
+            decode_record::<WriteBatch>(bytes.get(store.journal_len.cast()..))
+        {
+            store.apply(batch);
+            store.journal_len = store.journal_len.overflow_add(len.cast());
+            replayed = replayed.overflow_add(1);
+        }
+
+        if store.journal_len < bytes.len().cast() {
+            warn!(
+                "discard a torn record at offset {} of the journal {journal_path:?}",
+                store.journal_len
+            );
+            store
+                .journal
+                .set_len(store.journal_len)
+                .with_context(|| "failed to truncate the torn record of the journal".to_owned())?;
+            store.journal.sync_all()?;
+        }
+
+        info!(
+            "metadata is recovered from {dir:?}, {} keys, {replayed} records replayed",
+            store.entries.len()
+        );
+        Ok(store)
+    }
+
+    /// Returns the version of a key, or `0` if it does not exist.
+    fn version(&self, key: &[u8]) -> KvVersion {
+        self.entries.get(key).map_or(0, |entry| entry.version)
+    }
+
+    /// Apply a batch to the memory.
+    fn apply(&mut self, batch: WriteBatch) {
+        for (key, value) in batch {
+            match value {
+                Some(value) => {
+                    let version = self.version(&key).overflow_add(1);
+                    self.entries.insert(key, Entry { value, version });
+                }
+                None => {
+                    self.entries.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// Write a batch, it's durable once this method returns.
+    fn write(&mut self, batch: WriteBatch) -> DatenLordResult<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let record = encode_record(&batch)?;
+        self.journal
+            .write_all(&record)
+            .with_context(|| "failed to append to the journal".to_owned())?;
+        self.journal
+            .sync_data()
+            .with_context(|| "failed to sync the journal".to_owned())?;
+        self.journal_len = self.journal_len.overflow_add(record.len().cast());
+        self.apply(batch);
+
+        if self.journal_len > COMPACT_THRESHOLD {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Write all the keys into a new snapshot, and clear the journal.
+    ///
+    /// The snapshot is replaced atomically by renaming. If it crashes before
+    /// the journal is cleared, the journal is replayed upon the new snapshot,
+    /// which ends up with the same state.
+    fn compact(&mut self) -> DatenLordResult<()> {
+        let snapshot: Vec<(&Vec<u8>, &Vec<u8>)> = self
+            .entries
+            .iter()
+            .map(|(key, entry)| (key, &entry.value))
+            .collect();
+        let bytes = bincode::serialize(&snapshot)
+            .with_context(|| "failed to encode the snapshot".to_owned())?;
+
+        let tmp_path = self.dir.join(SNAPSHOT_TMP_FILE);
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, self.dir.join(SNAPSHOT_FILE))?;
+        File::open(&self.dir)?.sync_all()?;
+
+        self.journal.set_len(0)?;
+        self.journal.sync_all()?;
+        self.journal_len = 0;
+        info!(
+            "the journal is compacted into a snapshot of {} keys",
+            self.entries.len()
+        );
+        Ok(())
+    }
+}
+
+/// Encode a value into a journal record.
+pub(super) fn encode_record<T: Serialize>(value: &T) -> DatenLordResult<Vec<u8>> {
+    let payload = bincode::serialize(value)
+        .with_context(|| "failed to encode a record of the journal".to_owned())?;
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN.overflow_add(payload.len()));
+    record.extend_from_slice(&payload.len().cast::<u32>().to_le_bytes());
+    record.extend_from_slice(&crc32c::crc32c(&payload).to_le_bytes());
+    record.extend_from_slice(&payload);
+    Ok(record)
+}
+
+/// Decode the first record of `bytes`, returns the value and the length of
+/// the record. Returns `None` if the record is incomplete or corrupted.
+pub(super) fn decode_record<T: DeserializeOwned>(bytes: Option<&[u8]>) -> Option<(T, usize)> {
+    let bytes = bytes?;
+    let len = u32::from_le_bytes(bytes.get(0..4)?.try_into().ok()?);
+    let checksum = u32::from_le_bytes(bytes.get(4..RECORD_HEADER_LEN)?.try_into().ok()?);
+    let record_len = RECORD_HEADER_LEN.overflow_add(len.cast());
+    let payload = bytes.get(RECORD_HEADER_LEN..record_len)?;
+    if crc32c::crc32c(payload) != checksum {
+        return None;
+    }
+    let value = bincode::deserialize(payload).ok()?;
+    Some((value, record_len))
+}
+
+/// Decode a serialized value.
+pub(super) fn decode_value(value: &[u8]) -> DatenLordResult<ValueType> {
+    serde_json::from_slice::<ValueType>(value).with_context(|| {
+        "failed to deserialize value from bytes, KVEngine's value supposed to be `ValueType`"
+            .to_owned()
+    })
+}
+
+/// Encode a value.
+pub(super) fn encode_value(value: &ValueType) -> Vec<u8> {
+    // Because the ValueType derives the serde::Serialize
+    // This unwrap will not panic.
+    serde_json::to_vec(value)
+        .unwrap_or_else(|value| panic!("failed to serialize value to json,value = {value:?}"))
+}
+
+/// The local `KVEngine`, the metadata is persisted in a local directory.
+#[derive(Clone)]
+pub struct LocalKVEngine {
+    /// The persisted key space
+    store: Arc<Mutex<Store>>,
+    /// The keys locked
+    locks: Arc<Mutex<HashSet<Vec<u8>>>>,
+    /// Notified when a lock is released
+    lock_released: Arc<Notify>,
+    /// The id of the next lease
+    next_lease: Arc<AtomicI64>,
+}
+
+impl Debug for LocalKVEngine {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LocalKVEngine").finish()
+    }
+}
+
+impl LocalKVEngine {
+    /// Open the engine in `dir`, it's created if not exists.
+    pub fn open(dir: impl AsRef<Path>) -> DatenLordResult<Self> {
+        let store = Store::open(dir.as_ref())?;
+        Ok(Self {
+            store: Arc::new(Mutex::new(store)),
+            locks: Arc::new(Mutex::new(HashSet::new())),
+            lock_released: Arc::new(Notify::new()),
+            next_lease: Arc::new(AtomicI64::new(1)),
+        })
+    }
+}
+
+#[async_trait]
+impl KVEngine for LocalKVEngine {
+    async fn new(end_points: Vec<String>) -> DatenLordResult<Self> {
+        let dir = end_points.first().map_or("", |end_point| {
+            end_point
+                .strip_prefix(LOCAL_ENDPOINT_PREFIX)
+                .unwrap_or(end_point)
+        });
+        Self::open(dir)
+    }
+
+    async fn new_meta_txn(&self) -> Box<dyn MetaTxn + Send> {
+        Box::new(LocalTxn::new(Arc::clone(&self.store)))
+    }
+
+    /// Leases are not supported by the local engine, the keys never expire.
+    async fn lease_grant(&self, _ttl: i64) -> DatenLordResult<i64> {
+        Ok(self.next_lease.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Leases never expire in the local engine.
+    async fn lease_keep_alive(&self, _lease: i64) -> DatenLordResult<()> {
+        Ok(())
+    }
+
+    /// The local engine is changed by this node only, so nothing is watched.
+    async fn watch(&self, _prefix: &KeyType) -> DatenLordResult<WatchStream> {
+        Ok(stream::pending().boxed())
+    }
+
+    /// Lock a key in the process, the `timeout` is ignored, as the lock is
+    /// gone along with the process.
+    async fn lock(&self, key: &LockKeyType, _timeout: Duration) -> DatenLordResult<Vec<u8>> {
+        let _timer = KV_METRICS.start_kv_lock_timer();
+        let key = key.to_string_key().into_bytes();
+        loop {
+            let released = self.lock_released.notified();
+            if self.locks.lock().insert(key.clone()) {
+                return Ok(key);
+            }
+            released.await;
+        }
+    }
+
+    async fn unlock(&self, key: Vec<u8>) -> DatenLordResult<()> {
+        self.locks.lock().remove(&key);
+        self.lock_released.notify_waiters();
+        Ok(())
+    }
+
+    async fn get(&self, key: &KeyType) -> DatenLordResult<Option<ValueType>> {
+        let _timer = KV_METRICS.start_kv_operation_timer("get");
+        let key = key.to_string_key().into_bytes();
+        let store = self.store.lock();
+        store
+            .entries
+            .get(&key)
+            .map(|entry| decode_value(&entry.value))
+            .transpose()
+    }
+
+    async fn set(
+        &self,
+        key: &KeyType,
+        value: &ValueType,
+        option: Option<SetOption>,
+    ) -> DatenLordResult<Option<ValueType>> {
+        let _timer = KV_METRICS.start_kv_operation_timer("set");
+        let key = key.to_string_key().into_bytes();
+        let mut store = self.store.lock();
+        let prev = if option.map_or(false, |option| option.prev_kv) {
+            store
+                .entries
+                .get(&key)
+                .map(|entry| decode_value(&entry.value))
+                .transpose()?
+        } else {
+            None
+        };
+        store.write(vec![(key, Some(encode_value(value)))])?;
+        Ok(prev)
+    }
+
+    async fn delete(
+        &self,
+        key: &KeyType,
+        option: Option<DeleteOption>,
+    ) -> DatenLordResult<Option<ValueType>> {
+        let _timer = KV_METRICS.start_kv_operation_timer("delete");
+        let key = key.to_string_key().into_bytes();
+        let (prev_kv, range_end) =
+            option.map_or((false, None), |option| (option.prev_kv, option.range_end));
+
+        let mut store = self.store.lock();
+        let keys: Vec<Vec<u8>> = match range_end {
+            Some(range_end) => store
+                .entries
+                .range(key..range_end)
+                .map(|(k, _)| k.clone())
+                .collect(),
+            None => vec![key],
+        };
+        let prev = if prev_kv {
+            keys.first()
+                .and_then(|first| store.entries.get(first))
+                .map(|entry| decode_value(&entry.value))
+                .transpose()?
+        } else {
+            None
+        };
+        store.write(keys.into_iter().map(|k| (k, None)).collect())?;
+        Ok(prev)
+    }
+
+    async fn range(&self, prefix: &KeyType) -> DatenLordResult<Vec<ValueType>> {
+        let _timer = KV_METRICS.start_kv_operation_timer("range");
+        let prefix = prefix.to_string_key().into_bytes();
+        let store = self.store.lock();
+        store
+            .entries
+            .range(prefix.clone()..)
+            .take_while(|&(key, _)| key.starts_with(&prefix))
+            .map(|(_, entry)| decode_value(&entry.value))
+            .collect()
+    }
+}
+
+/// The transaction of the local engine, it's optimistic like the one of etcd.
+/// Write operations are buffered until commit is called, and the commit fails
+/// if any key read has been modified.
+struct LocalTxn {
+    /// The persisted key space
+    store: Arc<Mutex<Store>>,
+    /// The key is the key in bytes, the value is the version of the key.
+    version_map: HashMap<Vec<u8>, KvVersion>,
+    /// Store the write operations in the buffer.
+    buffer: HashMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl LocalTxn {
+    /// Create a new local transaction.
+    fn new(store: Arc<Mutex<Store>>) -> Self {
+        Self {
+            store,
+            version_map: HashMap::new(),
+            buffer: HashMap::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl MetaTxn for LocalTxn {
+    async fn get(&mut self, key_arg: &KeyType) -> DatenLordResult<Option<ValueType>> {
+        let _timer = KV_METRICS.start_kv_operation_timer("get");
+        let key = key_arg.to_string_key().into_bytes();
+        assert!(
+            self.buffer.get(&key).is_none(),
+            "get the key={key_arg:?} after write in the same transaction"
+        );
+        assert!(
+            self.version_map.get(&key).is_none(),
+            "get the key={key_arg:?} twice in the same transaction"
+        );
+
+        let store = self.store.lock();
+        let (value, version) = match store.entries.get(&key) {
+            Some(entry) => (Some(decode_value(&entry.value)?), entry.version),
+            None => (None, 0),
+        };
+        drop(store);
+        self.version_map.insert(key, version);
+        Ok(value)
+    }
+
+    fn set(&mut self, key: &KeyType, value: &ValueType) {
+        let key = key.to_string_key().into_bytes();
+        // Set same key twice in the same transaction is not allowed.
+        debug_assert!(
+            self.buffer.get(&key).is_none(),
+            "set the key={key:?} twice in the same transaction"
+        );
+        self.buffer.insert(key, Some(encode_value(value)));
+    }
+
+    fn delete(&mut self, key: &KeyType) {
+        let key = key.to_string_key().into_bytes();
+        self.buffer.insert(key, None);
+    }
+
+    #[instrument(
+        name = "kv_txn",
+        skip_all,
+        fields(reads = self.version_map.len(), writes = self.buffer.len())
+    )]
+    async fn commit(&mut self) -> DatenLordResult<bool> {
+        let _timer = KV_METRICS.start_kv_operation_timer("txn");
+
+        if self.version_map.is_empty() && self.buffer.is_empty() {
+            return Ok(true);
+        }
+
+        let mut store = self.store.lock();
+        let conflicted = self
+            .version_map
+            .iter()
+            .any(|(key, &version)| store.version(key) != version);
+        if conflicted {
+            return Ok(false);
+        }
+        store.write(self.buffer.drain().collect())?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    use super::*;
+    use crate::async_fuse::memfs::direntry::{DirEntry, FileType};
+
+    /// Open an engine in an empty directory.
+    fn open_empty(name: &str) -> (PathBuf, LocalKVEngine) {
+        let dir = Path::new("/tmp/datenlord_local_kv").join(name);
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        let engine = LocalKVEngine::open(&dir).unwrap();
+        (dir, engine)
+    }
+
+    #[tokio::test]
+    async fn test_recover_after_restart() {
+        let (dir, engine) = open_empty("recover");
+        let key = KeyType::String("key".to_owned());
+        let removed = KeyType::String("removed".to_owned());
+        let value = ValueType::String("value".to_owned());
+
+        engine.set(&key, &value, None).await.unwrap();
+        engine.set(&removed, &value, None).await.unwrap();
+        let mut txn = engine.new_meta_txn().await;
+        txn.delete(&removed);
+        assert!(txn.commit().await.unwrap());
+        drop(engine);
+
+        let engine = LocalKVEngine::open(&dir).unwrap();
+        assert_eq!(engine.get(&key).await.unwrap(), Some(value));
+        assert!(engine.get(&removed).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_discard_torn_record() {
+        let (dir, engine) = open_empty("torn");
+        let key = KeyType::String("key".to_owned());
+        let value = ValueType::String("value".to_owned());
+        engine.set(&key, &value, None).await.unwrap();
+        drop(engine);
+
+        // A record is partially written when it crashes.
+        let mut journal = OpenOptions::new()
+            .append(true)
+            .open(dir.join(JOURNAL_FILE))
+            .unwrap();
+        journal.write_all(&[42, 0, 0, 0, 1, 2]).unwrap();
+        drop(journal);
+
+        let engine = LocalKVEngine::open(&dir).unwrap();
+        assert_eq!(engine.get(&key).await.unwrap(), Some(value.clone()));
+
+        // The journal is still appendable after the torn record is discarded.
+        let other = KeyType::String("other".to_owned());
+        engine.set(&other, &value, None).await.unwrap();
+        drop(engine);
+        let engine = LocalKVEngine::open(&dir).unwrap();
+        assert_eq!(engine.get(&other).await.unwrap(), Some(value));
+    }
+
+    #[tokio::test]
+    async fn test_compact() {
+        let (dir, engine) = open_empty("compact");
+        let key = KeyType::String("key".to_owned());
+        engine
+            .set(&key, &ValueType::String("old".to_owned()), None)
+            .await
+            .unwrap();
+        engine.store.lock().compact().unwrap();
+        engine
+            .set(&key, &ValueType::String("new".to_owned()), None)
+            .await
+            .unwrap();
+        drop(engine);
+
+        let engine = LocalKVEngine::open(&dir).unwrap();
+        assert_eq!(
+            engine.get(&key).await.unwrap(),
+            Some(ValueType::String("new".to_owned()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_commit_conflict() {
+        let (_, engine) = open_empty("conflict");
+        let key = KeyType::String("key".to_owned());
+
+        let mut txn = engine.new_meta_txn().await;
+        assert!(txn.get(&key).await.unwrap().is_none());
+        engine
+            .set(&key, &ValueType::String("value".to_owned()), None)
+            .await
+            .unwrap();
+        txn.set(&key, &ValueType::String("stale".to_owned()));
+        assert!(!txn.commit().await.unwrap());
+        assert_eq!(
+            engine.get(&key).await.unwrap(),
+            Some(ValueType::String("value".to_owned()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_range_get() {
+        let (_, engine) = open_empty("range");
+        for (parent, name) in [(1, "a"), (1, "b"), (2, "a")] {
+            let key = KeyType::DirEntryKey((parent, name.to_owned()));
+            let value = ValueType::DirEntry(DirEntry::new(parent, name.to_owned(), FileType::Dir));
+            engine.set(&key, &value, None).await.unwrap();
+        }
+
+        let result = engine
+            .range(&KeyType::DirEntryKey((1, String::new())))
+            .await
+            .unwrap();
+        assert_eq!(result.len(), 2);
+        for value in result {
+            assert_eq!(value.into_dir_entry().ino(), 1);
+        }
+    }
+}
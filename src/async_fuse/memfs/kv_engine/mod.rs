@@ -3,24 +3,36 @@ use std::fmt;
 use std::time::Duration;
 
 use async_trait::async_trait;
+use futures::stream::BoxStream;
 
 use crate::common::async_fuse_error::KVEngineError;
 use crate::common::error::{DatenLordError, DatenLordResult};
 
-/// The `KVEngineType` is used to provide support for metadata.
-/// We use this alias to avoid generic type.
-pub type KVEngineType = etcd_impl::EtcdKVEngine;
-
+/// The `CompoundTxn` running several metadata operations in one transaction
+pub mod compound_txn;
+/// The `KVEngineType` dispatching to the etcd, the local or the raft engine
+pub mod engine_type;
 /// The etcd implementation of `KVEngine` and `MetaTxn`
 pub mod etcd_impl;
+/// The `FaultyKVEngine` injecting faults into another engine in tests
+#[cfg(feature = "fault-injection")]
+pub mod fault;
 /// The `kv_utils` is used to provide some common functions for `KVEngine`
 pub mod kv_utils;
+/// The local implementation of `KVEngine` and `MetaTxn`, persisting the
+/// metadata with a write-ahead journal
+pub mod local_impl;
+/// The raft implementation of `KVEngine` and `MetaTxn`, replicating the
+/// metadata among the nodes without etcd
+pub mod raft_impl;
 
 /// The key type api
 pub mod key_type;
 /// The value type api
 pub mod value_type;
 
+pub use compound_txn::CompoundTxn;
+pub use engine_type::KVEngineType;
 pub use key_type::{KeyType, LockKeyType};
 pub use value_type::ValueType;
 /// The Txn is used to provide support for metadata.
@@ -39,6 +51,19 @@ pub trait MetaTxn: Send {
     async fn commit(&mut self) -> DatenLordResult<bool>;
 }
 
+/// A change of a watched key.
+#[derive(Debug)]
+pub enum WatchEvent {
+    /// The key is set to the value
+    Put(ValueType),
+    /// The key is deleted
+    Delete(String),
+}
+
+/// The changes of watched keys, every item is a batch of changes committed
+/// together.
+pub type WatchStream = BoxStream<'static, DatenLordResult<Vec<WatchEvent>>>;
+
 /// The option of 'set' operation
 /// Currently support 'lease' and `prev_kv`
 /// `lease` is used to set the lease of the key
@@ -53,21 +78,19 @@ pub struct SetOption {
 }
 
 impl SetOption {
-    #[allow(dead_code)]
     #[must_use]
     /// Create a new `SetOption`
     /// Default lease is None, `prev_kv` is false
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
             lease: None,
             prev_kv: false,
         }
     }
 
-    #[allow(dead_code)]
     #[must_use]
     /// Set the lease of the key
-    fn with_lease(mut self, lease: i64) -> Self {
+    pub(crate) fn with_lease(mut self, lease: i64) -> Self {
         self.lease = Some(lease);
         self
     }
@@ -225,6 +248,12 @@ pub trait KVEngine: Send + Sync + Debug + Sized {
     /// Lease grant
     async fn lease_grant(&self, ttl: i64) -> DatenLordResult<i64>;
 
+    /// Renew a lease once, it fails if the lease is already expired.
+    async fn lease_keep_alive(&self, lease: i64) -> DatenLordResult<()>;
+
+    /// Watch the changes of keys start with prefix, made after the call.
+    async fn watch(&self, prefix: &KeyType) -> DatenLordResult<WatchStream>;
+
     /// Range get, return all key-value pairs start with prefix
     async fn range(&self, prefix: &KeyType) -> DatenLordResult<Vec<ValueType>>;
 }
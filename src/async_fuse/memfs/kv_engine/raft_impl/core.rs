@@ -0,0 +1,774 @@
+//! The consensus of a raft group, which is driven by ticks and messages, and
+//! leaves the timers and the transport to the engine.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
+
+use clippy_utilities::{Cast, OverflowArithmetic};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use super::storage::{HardState, RaftStorage};
+use super::{no_leader, raft_error};
+use crate::common::error::DatenLordResult;
+
+/// The id of a member of the raft group.
+pub type RaftId = u64;
+/// The members of the raft group, the values are their endpoints.
+pub type Members = BTreeMap<RaftId, String>;
+
+/// The minimum number of ticks without hearing from the leader before a
+/// follower campaigns, the timeout is randomized in `[ELECTION_TICKS,
+/// 2 * ELECTION_TICKS)`.
+pub const ELECTION_TICKS: u32 = 10;
+/// The number of ticks between the heartbeats of the leader.
+const HEARTBEAT_TICKS: u32 = 2;
+/// The maximum number of entries sent in a message.
+const MAX_APPEND_ENTRIES: u64 = 256;
+
+/// The payload of a log entry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EntryPayload {
+    /// Appended by a new leader to commit the entries of previous terms
+    Noop,
+    /// A command of the state machine
+    Command(Vec<u8>),
+    /// The new members of the group, effective once it's appended
+    Members(Members),
+}
+
+/// An entry of the replicated log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// The index of the entry in the log, starting from 1
+    pub index: u64,
+    /// The term the entry is appended in
+    pub term: u64,
+    /// The payload
+    pub payload: EntryPayload,
+}
+
+/// A snapshot of the state machine, replacing the log up to `index`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The index of the last entry in the snapshot
+    pub index: u64,
+    /// The term of the last entry in the snapshot
+    pub term: u64,
+    /// The members as of the last entry
+    pub members: Members,
+    /// The serialized state machine
+    pub data: Vec<u8>,
+}
+
+/// A message between the members.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Message {
+    /// The sender
+    pub from: RaftId,
+    /// The receiver
+    pub to: RaftId,
+    /// The term of the sender
+    pub term: u64,
+    /// The body
+    pub body: MessageBody,
+}
+
+/// The body of a message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MessageBody {
+    /// A candidate asks for the vote
+    RequestVote {
+        /// The index of the last entry of the candidate
+        last_index: u64,
+        /// The term of the last entry of the candidate
+        last_term: u64,
+    },
+    /// The reply to `RequestVote`
+    Vote {
+        /// Whether the vote is granted
+        granted: bool,
+    },
+    /// The leader replicates entries, or sends a heartbeat without entries
+    Append {
+        /// The index of the entry preceding `entries`
+        prev_index: u64,
+        /// The term of the entry preceding `entries`
+        prev_term: u64,
+        /// The entries to append
+        entries: Vec<Entry>,
+        /// The commit index of the leader
+        commit: u64,
+    },
+    /// The reply to `Append` and `InstallSnapshot`
+    AppendReply {
+        /// Whether the entries are appended
+        success: bool,
+        /// The index of the last entry matching the leader if it succeeds,
+        /// otherwise the last index of the follower as a hint
+        last_index: u64,
+    },
+    /// The leader sends a snapshot to a follower lagging behind its log
+    InstallSnapshot {
+        /// The snapshot
+        snapshot: Snapshot,
+    },
+}
+
+/// The role of a member.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    /// Following the leader
+    Follower,
+    /// Campaigning for the leader
+    Candidate,
+    /// Leading the group
+    Leader,
+}
+
+/// The replication progress of a follower, tracked by the leader.
+#[derive(Debug, Clone, Copy)]
+struct Progress {
+    /// The index of the next entry to send
+    next: u64,
+    /// The index of the last entry known to match
+    matched: u64,
+}
+
+/// The state of a member of the raft group.
+///
+/// Every change of the hard state and the log is persisted before it returns,
+/// so the messages taken afterwards are safe to send. The committed entries
+/// and the installed snapshot are taken by the engine to apply.
+#[derive(Debug)]
+pub struct RaftCore {
+    /// The id of this member
+    id: RaftId,
+    /// The persisted state
+    storage: RaftStorage,
+    /// The role
+    role: Role,
+    /// The leader of the current term, if known
+    leader: Option<RaftId>,
+    /// The members of the latest config in the log
+    members: Members,
+    /// The index of the last committed entry
+    commit: u64,
+    /// The index of the last entry taken to apply
+    applied: u64,
+    /// The ticks since the last message from the leader, or since the
+    /// campaign
+    election_elapsed: u32,
+    /// The randomized election timeout in ticks
+    election_timeout: u32,
+    /// The ticks since the last heartbeat of the leader
+    heartbeat_elapsed: u32,
+    /// The members voting for this candidate
+    votes: BTreeSet<RaftId>,
+    /// The progress of the followers
+    progress: BTreeMap<RaftId, Progress>,
+    /// The followers replied in the current election timeout, the leader
+    /// steps down if they're fewer than a quorum
+    recent_active: BTreeSet<RaftId>,
+    /// The messages to send
+    messages: Vec<Message>,
+    /// The snapshot installed from the leader, to restore the state machine
+    pending_snapshot: Option<Snapshot>,
+}
+
+impl RaftCore {
+    /// Create a member from the persisted state. If nothing is persisted, the
+    /// group is bootstrapped with `initial` if this member is one of them,
+    /// otherwise this member waits to be added by the leader.
+    pub fn new(id: RaftId, mut storage: RaftStorage, initial: &Members) -> DatenLordResult<Self> {
+        if storage.last_index() == 0 && storage.members().is_empty() && initial.contains_key(&id) {
+            info!("bootstrap the raft group with members {initial:?}");
+            storage.install_snapshot(Snapshot {
+                members: initial.clone(),
+                ..Snapshot::default()
+            })?;
+        }
+        let snapshot_index = storage.snapshot().index;
+        let members = storage.members();
+        let mut core = Self {
+            id,
+            storage,
+            role: Role::Follower,
+            leader: None,
+            members,
+            commit: snapshot_index,
+            applied: snapshot_index,
+            election_elapsed: 0,
+            election_timeout: ELECTION_TICKS,
+            heartbeat_elapsed: 0,
+            votes: BTreeSet::new(),
+            progress: BTreeMap::new(),
+            recent_active: BTreeSet::new(),
+            messages: vec![],
+            pending_snapshot: None,
+        };
+        core.reset_election_timeout();
+        Ok(core)
+    }
+
+    /// The current term.
+    pub fn term(&self) -> u64 {
+        self.storage.hard_state().term
+    }
+
+    /// The leader of the current term, if known.
+    pub fn leader(&self) -> Option<RaftId> {
+        self.leader
+    }
+
+    /// Whether this member is the leader.
+    pub fn is_leader(&self) -> bool {
+        self.role == Role::Leader
+    }
+
+    /// The members of the latest config in the log.
+    pub fn members(&self) -> &Members {
+        &self.members
+    }
+
+    /// The snapshot replacing the log up to its index.
+    pub fn snapshot(&self) -> &Snapshot {
+        self.storage.snapshot()
+    }
+
+    /// The commit index for a read to see all the writes acknowledged before
+    /// it, `None` if this member is not the leader, or it hasn't committed an
+    /// entry in its term, when the entries of previous terms may be not
+    /// committed yet.
+    ///
+    /// The leader steps down once it doesn't hear from a quorum in an election
+    /// timeout, so a deposed leader serves stale reads at most that long.
+    pub fn read_index(&self) -> Option<u64> {
+        (self.is_leader() && self.storage.term(self.commit) == Some(self.term()))
+            .then_some(self.commit)
+    }
+
+    /// Take the messages to send.
+    pub fn take_messages(&mut self) -> Vec<Message> {
+        mem::take(&mut self.messages)
+    }
+
+    /// Take the snapshot installed from the leader, the state machine should
+    /// be restored from it before applying the committed entries.
+    pub fn take_snapshot(&mut self) -> Option<Snapshot> {
+        self.pending_snapshot.take()
+    }
+
+    /// Take the committed entries to apply.
+    pub fn take_committed(&mut self) -> Vec<Entry> {
+        if self.applied >= self.commit {
+            return vec![];
+        }
+        let entries = self
+            .storage
+            .entries(self.applied.overflow_add(1), self.commit);
+        self.applied = self.commit;
+        entries
+    }
+
+    /// Replace the applied entries with a snapshot of the state machine.
+    pub fn compact(&mut self, data: Vec<u8>) -> DatenLordResult<()> {
+        let index = self.applied;
+        let term = self.storage.term(index).unwrap_or(0);
+        let members = self.storage.members_at(index);
+        self.storage.install_snapshot(Snapshot {
+            index,
+            term,
+            members,
+            data,
+        })?;
+        info!("the raft log is compacted up to {index}");
+        Ok(())
+    }
+
+    /// Advance the clock by a tick.
+    pub fn tick(&mut self) -> DatenLordResult<()> {
+        self.election_elapsed = self.election_elapsed.overflow_add(1);
+        if self.is_leader() {
+            if self.election_elapsed >= self.election_timeout {
+                self.check_quorum()?;
+            }
+            self.heartbeat_elapsed = self.heartbeat_elapsed.overflow_add(1);
+            if self.is_leader() && self.heartbeat_elapsed >= HEARTBEAT_TICKS {
+                self.heartbeat_elapsed = 0;
+                self.broadcast_append();
+            }
+        } else if self.election_elapsed >= self.election_timeout
+            && self.members.contains_key(&self.id)
+        {
+            self.campaign()?;
+        }
+        Ok(())
+    }
+
+    /// Propose an entry, returns its index. Fails if this member is not the
+    /// leader, or the members are changed while a previous change is not
+    /// committed yet.
+    pub fn propose(&mut self, payload: EntryPayload) -> DatenLordResult<u64> {
+        if !self.is_leader() {
+            return Err(no_leader());
+        }
+        if let EntryPayload::Members(ref members) = payload {
+            if self.storage.members_index() > self.commit {
+                return Err(raft_error(
+                    "a previous change of members is not committed yet".to_owned(),
+                ));
+            }
+            info!("propose to change the members to {members:?}");
+        }
+
+        let index = self.storage.last_index().overflow_add(1);
+        self.storage.append(vec![Entry {
+            index,
+            term: self.term(),
+            payload,
+        }])?;
+        self.update_members();
+        self.advance_commit();
+        self.broadcast_append();
+        Ok(index)
+    }
+
+    /// Handle a message from another member.
+    pub fn step(&mut self, msg: Message) -> DatenLordResult<()> {
+        let term = self.term();
+        if msg.term > term {
+            if matches!(msg.body, MessageBody::RequestVote { .. })
+                && self.leader.is_some()
+                && self.election_elapsed < ELECTION_TICKS
+            {
+                // The leader is alive, a member removed or partitioned away
+                // should not disrupt it.
+                debug!(
+                    "ignore the vote request of {} in term {}",
+                    msg.from, msg.term
+                );
+                return Ok(());
+            }
+            let leader = match msg.body {
+                MessageBody::Append { .. } | MessageBody::InstallSnapshot { .. } => Some(msg.from),
+                MessageBody::RequestVote { .. }
+                | MessageBody::Vote { .. }
+                | MessageBody::AppendReply { .. } => None,
+            };
+            self.become_follower(msg.term, leader)?;
+        } else if msg.term < term {
+            // Tell the stale leader or candidate the new term.
+            match msg.body {
+                MessageBody::Append { .. } | MessageBody::InstallSnapshot { .. } => {
+                    self.send(
+                        msg.from,
+                        MessageBody::AppendReply {
+                            success: false,
+                            last_index: self.storage.last_index(),
+                        },
+                    );
+                }
+                MessageBody::RequestVote { .. } => {
+                    self.send(msg.from, MessageBody::Vote { granted: false });
+                }
+                MessageBody::Vote { .. } | MessageBody::AppendReply { .. } => {}
+            }
+            return Ok(());
+        }
+
+        match msg.body {
+            MessageBody::RequestVote {
+                last_index,
+                last_term,
+            } => self.handle_request_vote(msg.from, last_index, last_term)?,
+            MessageBody::Vote { granted } => self.handle_vote(msg.from, granted)?,
+            MessageBody::Append {
+                prev_index,
+                prev_term,
+                entries,
+                commit,
+            } => self.handle_append(msg.from, prev_index, prev_term, entries, commit)?,
+            MessageBody::AppendReply {
+                success,
+                last_index,
+            } => self.handle_append_reply(msg.from, success, last_index),
+            MessageBody::InstallSnapshot { snapshot } => {
+                self.handle_install_snapshot(msg.from, snapshot)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Queue a message to another member.
+    fn send(&mut self, to: RaftId, body: MessageBody) {
+        self.messages.push(Message {
+            from: self.id,
+            to,
+            term: self.term(),
+            body,
+        });
+    }
+
+    /// Randomize the election timeout, so the members rarely campaign at the
+    /// same time.
+    fn reset_election_timeout(&mut self) {
+        self.election_elapsed = 0;
+        self.election_timeout =
+            rand::thread_rng().gen_range(ELECTION_TICKS..ELECTION_TICKS.overflow_mul(2));
+    }
+
+    /// The number of members making a quorum.
+    fn quorum(&self) -> usize {
+        self.members.len().overflow_div(2).overflow_add(1)
+    }
+
+    /// Reload the members from the log, and track the progress of the new
+    /// members if this member is the leader.
+    fn update_members(&mut self) {
+        self.members = self.storage.members();
+        if self.is_leader() {
+            let next = self.storage.last_index().overflow_add(1);
+            let (id, members) = (self.id, &self.members);
+            self.progress
+                .retain(|follower, _| members.contains_key(follower));
+            for &follower in members.keys().filter(|&&member| member != id) {
+                self.progress
+                    .entry(follower)
+                    .or_insert(Progress { next, matched: 0 });
+            }
+        }
+    }
+
+    /// Step down to a follower in `term`.
+    fn become_follower(&mut self, term: u64, leader: Option<RaftId>) -> DatenLordResult<()> {
+        if term != self.term() {
+            self.storage.set_hard_state(HardState {
+                term,
+                voted_for: None,
+            })?;
+        }
+        if self.is_leader() {
+            info!("member {} steps down in term {term}", self.id);
+        }
+        self.role = Role::Follower;
+        self.leader = leader;
+        self.progress.clear();
+        self.votes.clear();
+        self.reset_election_timeout();
+        Ok(())
+    }
+
+    /// Campaign for the leader in a new term.
+    fn campaign(&mut self) -> DatenLordResult<()> {
+        let term = self.term().overflow_add(1);
+        self.storage.set_hard_state(HardState {
+            term,
+            voted_for: Some(self.id),
+        })?;
+        self.role = Role::Candidate;
+        self.leader = None;
+        self.votes = BTreeSet::from([self.id]);
+        self.reset_election_timeout();
+        info!("member {} campaigns in term {term}", self.id);
+
+        if self.votes.len() >= self.quorum() {
+            return self.become_leader();
+        }
+        let last_index = self.storage.last_index();
+        let last_term = self.storage.term(last_index).unwrap_or(0);
+        let others: Vec<RaftId> = self
+            .members
+            .keys()
+            .copied()
+            .filter(|&member| member != self.id)
+            .collect();
+        for member in others {
+            self.send(
+                member,
+                MessageBody::RequestVote {
+                    last_index,
+                    last_term,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Become the leader, and commit the entries of previous terms by a noop
+    /// entry.
+    fn become_leader(&mut self) -> DatenLordResult<()> {
+        info!(
+            "member {} becomes the leader in term {}",
+            self.id,
+            self.term()
+        );
+        self.role = Role::Leader;
+        self.leader = Some(self.id);
+        self.votes.clear();
+        self.recent_active.clear();
+        self.progress.clear();
+        self.heartbeat_elapsed = 0;
+        self.election_elapsed = 0;
+        self.update_members();
+        self.propose(EntryPayload::Noop)?;
+        Ok(())
+    }
+
+    /// Step down if the leader didn't hear from a quorum in the last election
+    /// timeout.
+    fn check_quorum(&mut self) -> DatenLordResult<()> {
+        let mut active = mem::take(&mut self.recent_active);
+        active.insert(self.id);
+        let active_members = self
+            .members
+            .keys()
+            .filter(|&member| active.contains(member))
+            .count();
+        self.election_elapsed = 0;
+        if active_members < self.quorum() {
+            info!(
+                "member {} loses the quorum, only {active:?} are active",
+                self.id
+            );
+            self.become_follower(self.term(), None)?;
+        }
+        Ok(())
+    }
+
+    /// Send the entries a follower lacks, or the snapshot if they're
+    /// compacted.
+    fn send_append(&mut self, to: RaftId) {
+        let Some(progress) = self.progress.get(&to).copied() else {
+            return;
+        };
+        let prev_index = progress.next.overflow_sub(1);
+        let body = match self.storage.term(prev_index) {
+            Some(prev_term) => {
+                let last = self
+                    .storage
+                    .last_index()
+                    .min(prev_index.overflow_add(MAX_APPEND_ENTRIES));
+                MessageBody::Append {
+                    prev_index,
+                    prev_term,
+                    entries: self.storage.entries(progress.next, last),
+                    commit: self.commit,
+                }
+            }
+            None => {
+                let snapshot = self.storage.snapshot().clone();
+                debug!("send the snapshot at {} to member {to}", snapshot.index);
+                if let Some(progress) = self.progress.get_mut(&to) {
+                    progress.next = snapshot.index.overflow_add(1);
+                }
+                MessageBody::InstallSnapshot { snapshot }
+            }
+        };
+        self.send(to, body);
+    }
+
+    /// Send the entries or heartbeats to all followers.
+    fn broadcast_append(&mut self) {
+        if !self.is_leader() {
+            return;
+        }
+        let followers: Vec<RaftId> = self.progress.keys().copied().collect();
+        for follower in followers {
+            self.send_append(follower);
+        }
+    }
+
+    /// Commit the latest entry of the current term replicated on a quorum.
+    fn advance_commit(&mut self) {
+        let mut matched: Vec<u64> = self
+            .members
+            .keys()
+            .map(|member| {
+                if *member == self.id {
+                    self.storage.last_index()
+                } else {
+                    self.progress
+                        .get(member)
+                        .map_or(0, |progress| progress.matched)
+                }
+            })
+            .collect();
+        if matched.is_empty() {
+            return;
+        }
+        matched.sort_unstable_by(|a, b| b.cmp(a));
+        let Some(&index) = matched.get(self.quorum().overflow_sub(1)) else {
+            return;
+        };
+        // The entries of previous terms are committed along with an entry of
+        // the current term only.
+        if index > self.commit && self.storage.term(index) == Some(self.term()) {
+            self.commit = index;
+        }
+
+        if !self.members.contains_key(&self.id) && self.storage.members_index() <= self.commit {
+            // The removal of this member is committed, the rest of the group
+            // elects a new leader.
+            info!("member {} is removed from the group", self.id);
+            self.role = Role::Follower;
+            self.leader = None;
+            self.progress.clear();
+        }
+    }
+
+    /// Handle the vote request of a candidate.
+    fn handle_request_vote(
+        &mut self,
+        candidate: RaftId,
+        last_index: u64,
+        last_term: u64,
+    ) -> DatenLordResult<()> {
+        let hard_state = self.storage.hard_state().clone();
+        let our_last_index = self.storage.last_index();
+        let our_last_term = self.storage.term(our_last_index).unwrap_or(0);
+        let up_to_date = (last_term, last_index) >= (our_last_term, our_last_index);
+        let granted = up_to_date
+            && hard_state
+                .voted_for
+                .map_or(true, |voted_for| voted_for == candidate);
+        if granted {
+            self.storage.set_hard_state(HardState {
+                term: hard_state.term,
+                voted_for: Some(candidate),
+            })?;
+            self.election_elapsed = 0;
+        }
+        self.send(candidate, MessageBody::Vote { granted });
+        Ok(())
+    }
+
+    /// Handle a vote for this candidate.
+    fn handle_vote(&mut self, voter: RaftId, granted: bool) -> DatenLordResult<()> {
+        if self.role != Role::Candidate || !granted || !self.members.contains_key(&voter) {
+            return Ok(());
+        }
+        self.votes.insert(voter);
+        if self.votes.len() >= self.quorum() {
+            self.become_leader()?;
+        }
+        Ok(())
+    }
+
+    /// Handle the entries from the leader.
+    fn handle_append(
+        &mut self,
+        leader: RaftId,
+        mut prev_index: u64,
+        mut prev_term: u64,
+        mut entries: Vec<Entry>,
+        commit: u64,
+    ) -> DatenLordResult<()> {
+        if self.role != Role::Follower || self.leader != Some(leader) {
+            self.become_follower(self.term(), Some(leader))?;
+        }
+        self.election_elapsed = 0;
+
+        // The entries in the snapshot are committed, so they match the leader.
+        let snapshot = self.storage.snapshot();
+        if prev_index < snapshot.index {
+            let (snapshot_index, snapshot_term) = (snapshot.index, snapshot.term);
+            entries.retain(|entry| entry.index > snapshot_index);
+            prev_index = snapshot_index;
+            prev_term = snapshot_term;
+        }
+
+        if self.storage.term(prev_index) != Some(prev_term) {
+            self.send(
+                leader,
+                MessageBody::AppendReply {
+                    success: false,
+                    last_index: self.storage.last_index().min(prev_index.saturating_sub(1)),
+                },
+            );
+            return Ok(());
+        }
+
+        let last_new = prev_index.overflow_add(entries.len().cast());
+        let conflict = entries
+            .iter()
+            .position(|entry| self.storage.term(entry.index) != Some(entry.term));
+        if let Some(conflict) = conflict {
+            let new_entries = entries.split_off(conflict);
+            self.storage.append(new_entries)?;
+            self.update_members();
+        }
+        let commit = commit.min(last_new);
+        if commit > self.commit {
+            self.commit = commit;
+        }
+        self.send(
+            leader,
+            MessageBody::AppendReply {
+                success: true,
+                last_index: last_new,
+            },
+        );
+        Ok(())
+    }
+
+    /// Handle the reply of a follower.
+    fn handle_append_reply(&mut self, follower: RaftId, success: bool, last_index: u64) {
+        if !self.is_leader() {
+            return;
+        }
+        self.recent_active.insert(follower);
+        let last = self.storage.last_index();
+        let Some(progress) = self.progress.get_mut(&follower) else {
+            return;
+        };
+        if success {
+            progress.matched = progress.matched.max(last_index);
+            progress.next = progress.next.max(progress.matched.overflow_add(1));
+            let lagging = progress.next <= last;
+            self.advance_commit();
+            if lagging {
+                self.send_append(follower);
+            }
+        } else {
+            // Go back to the last index of the follower, one entry at least.
+            progress.next = progress
+                .next
+                .saturating_sub(1)
+                .min(last_index.overflow_add(1))
+                .max(progress.matched.overflow_add(1));
+            self.send_append(follower);
+        }
+    }
+
+    /// Handle the snapshot from the leader.
+    fn handle_install_snapshot(
+        &mut self,
+        leader: RaftId,
+        snapshot: Snapshot,
+    ) -> DatenLordResult<()> {
+        if self.role != Role::Follower || self.leader != Some(leader) {
+            self.become_follower(self.term(), Some(leader))?;
+        }
+        self.election_elapsed = 0;
+
+        let index = snapshot.index;
+        if index > self.commit {
+            info!("install the snapshot at {index} from member {leader}");
+            self.storage.install_snapshot(snapshot.clone())?;
+            self.commit = index;
+            self.applied = index;
+            self.pending_snapshot = Some(snapshot);
+            self.update_members();
+        }
+        self.send(
+            leader,
+            MessageBody::AppendReply {
+                success: true,
+                last_index: index,
+            },
+        );
+        Ok(())
+    }
+}
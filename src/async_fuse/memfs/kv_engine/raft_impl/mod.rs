@@ -0,0 +1,686 @@
+//! A `KVEngine` replicating the metadata among the daemons by raft, for
+//! deployments without etcd.
+//!
+//! Every daemon is a member of the raft group, and keeps the whole key space
+//! in memory, which is rebuilt from the snapshot and the log persisted in a
+//! local directory. A write is proposed to the leader, forwarded by a
+//! follower if necessary, and acknowledged once it's committed by a quorum
+//! and applied. A read waits until the member applies the commit index of the
+//! leader, so it sees all the writes acknowledged before it. The log is
+//! compacted into a snapshot once it grows too large, and a member lagging
+//! behind the compacted log is sent the snapshot.
+//!
+//! The engine is selected by the endpoints
+//! `raft://<id>@<addr>/<dir>,<id>=<endpoint>,...`, where `<addr>` is the
+//! address this member serves the group at, which should be reachable by the
+//! others, `<dir>` is the directory of the persisted state, and the rest are
+//! the members of the group, such as `1=http://10.0.0.1:7900`. A group is
+//! bootstrapped by the members listed along with themselves, and a member not
+//! listed asks the group to add it on the first start. A member is removed by
+//! the `ChangeMembers` request to the leader.
+//!
+//! Leases are tracked by the leader, a new leader renews all of them, as the
+//! deadlines are not replicated. The members communicate in plaintext.
+
+mod core;
+mod node;
+mod state_machine;
+mod storage;
+mod transport;
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use clippy_utilities::Cast;
+use datenlord::metrics::KV_METRICS;
+use futures::stream::{self, StreamExt};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time;
+use tokio_util::sync::{CancellationToken, DropGuard};
+use tracing::instrument;
+
+use self::core::{Members, RaftCore, RaftId};
+use self::node::{RaftNode, TICK_INTERVAL};
+use self::state_machine::{Change, Command, CommandResult, Op};
+use self::storage::RaftStorage;
+use super::local_impl::{decode_value, encode_value};
+use super::{
+    DeleteOption, KVEngine, KeyType, KvVersion, LockKeyType, MetaTxn, SetOption, ValueType,
+    WatchEvent, WatchStream,
+};
+use crate::common::async_fuse_error::KVEngineError;
+use crate::common::error::{DatenLordError, DatenLordResult};
+
+/// The prefix of endpoints of the raft engine.
+pub const RAFT_ENDPOINT_PREFIX: &str = "raft://";
+
+/// The error of a request to a member which is not the leader, or when the
+/// leader is unknown.
+fn no_leader() -> DatenLordError {
+    DatenLordError::KVEngineErr {
+        source: KVEngineError::NoLeader,
+        context: vec![],
+    }
+}
+
+/// Whether the error is `no_leader`, the request is retried once the leader
+/// is elected.
+fn is_no_leader(error: &DatenLordError) -> bool {
+    matches!(
+        *error,
+        DatenLordError::KVEngineErr {
+            source: KVEngineError::NoLeader,
+            ..
+        }
+    )
+}
+
+/// An error of the raft group.
+fn raft_error(message: String) -> DatenLordError {
+    DatenLordError::KVEngineErr {
+        source: KVEngineError::Raft(message),
+        context: vec![],
+    }
+}
+
+/// The error of an invalid endpoint.
+fn invalid_end_point(end_point: &str, reason: &str) -> DatenLordError {
+    DatenLordError::ArgumentInvalid {
+        context: vec![format!("raft endpoint {end_point} is invalid: {reason}")],
+    }
+}
+
+/// The config of a member parsed from the endpoints.
+#[derive(Debug, PartialEq, Eq)]
+struct RaftEndPoints {
+    /// The id of this member
+    id: RaftId,
+    /// The address this member serves at
+    addr: SocketAddr,
+    /// The directory of the persisted state
+    dir: PathBuf,
+    /// The members of the group
+    members: Members,
+}
+
+impl RaftEndPoints {
+    /// Parse `raft://<id>@<addr>/<dir>,<id>=<endpoint>,...`.
+    fn parse(end_points: &[String]) -> DatenLordResult<Self> {
+        let (first, rest) = end_points
+            .split_first()
+            .ok_or_else(|| invalid_end_point("", "the endpoints are empty"))?;
+        let local = first
+            .strip_prefix(RAFT_ENDPOINT_PREFIX)
+            .ok_or_else(|| invalid_end_point(first, "the scheme is not raft://"))?;
+        let (id, local) = local
+            .split_once('@')
+            .ok_or_else(|| invalid_end_point(first, "the member id is missing"))?;
+        let id = RaftId::from_str(id)
+            .map_err(|e| invalid_end_point(first, &format!("invalid member id: {e}")))?;
+        let slash = local
+            .find('/')
+            .ok_or_else(|| invalid_end_point(first, "the directory is missing"))?;
+        let (addr, dir) = local.split_at(slash);
+        let addr = SocketAddr::from_str(addr)
+            .map_err(|e| invalid_end_point(first, &format!("invalid address: {e}")))?;
+
+        let mut members = Members::new();
+        for member in rest {
+            let (member_id, endpoint) = member
+                .split_once('=')
+                .ok_or_else(|| invalid_end_point(member, "expect <id>=<endpoint>"))?;
+            let member_id = RaftId::from_str(member_id)
+                .map_err(|e| invalid_end_point(member, &format!("invalid member id: {e}")))?;
+            members.insert(member_id, endpoint.to_owned());
+        }
+        Ok(Self {
+            id,
+            addr,
+            dir: PathBuf::from(dir),
+            members,
+        })
+    }
+}
+
+/// The raft `KVEngine`, the metadata is replicated among the members.
+#[derive(Clone)]
+pub struct RaftKVEngine {
+    /// The member
+    node: Arc<RaftNode>,
+    /// Stops the background tasks once all clones of the engine are dropped
+    _guard: Arc<DropGuard>,
+}
+
+impl Debug for RaftKVEngine {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RaftKVEngine").finish()
+    }
+}
+
+impl RaftKVEngine {
+    /// Start the member `id` serving on `listener`, with the state persisted
+    /// in `dir`. If it's not one of `members` and hasn't been added, it asks
+    /// `members` to add it.
+    async fn start(
+        id: RaftId,
+        listener: TcpListener,
+        dir: &Path,
+        members: &Members,
+    ) -> DatenLordResult<Self> {
+        let storage = RaftStorage::open(dir)?;
+        let joining = storage.members().is_empty() && !members.contains_key(&id);
+        let core = RaftCore::new(id, storage, members)?;
+        let node = Arc::new(RaftNode::new(core)?);
+        let addr = listener.local_addr()?;
+
+        let token = CancellationToken::new();
+        tokio::spawn(transport::serve(listener, Arc::clone(&node), token.clone()));
+        tokio::spawn(Arc::clone(&node).run(token.clone()));
+        let engine = Self {
+            node,
+            _guard: Arc::new(token.drop_guard()),
+        };
+
+        if joining {
+            engine
+                .node
+                .join(id, &format!("http://{addr}"), members)
+                .await?;
+        }
+        Ok(engine)
+    }
+
+    /// Propose a transaction, returns whether it's applied and the previous
+    /// value of the first key written.
+    async fn propose_txn(&self, ops: Vec<Op>) -> DatenLordResult<(bool, Option<Vec<u8>>)> {
+        match self.node.propose(&Command::Txn(ops)).await? {
+            CommandResult::Applied(prev) => Ok((true, prev)),
+            CommandResult::Conflicted => Ok((false, None)),
+            CommandResult::LeaseNotFound(lease) => Err(DatenLordError::KVEngineErr {
+                source: KVEngineError::LeaseExpired(lease),
+                context: vec![],
+            }),
+            result @ (CommandResult::LeaseGranted(_)
+            | CommandResult::LeaseRevoked
+            | CommandResult::MembersChanged) => Err(raft_error(format!(
+                "unexpected result {result:?} of a transaction"
+            ))),
+        }
+    }
+}
+
+/// Convert a change of a key into a watch event.
+fn into_watch_event(change: Change) -> DatenLordResult<WatchEvent> {
+    match change {
+        Change::Put(_, value) => Ok(WatchEvent::Put(decode_value(&value)?)),
+        Change::Delete(key) => Ok(WatchEvent::Delete(
+            String::from_utf8_lossy(&key).into_owned(),
+        )),
+    }
+}
+
+#[async_trait]
+impl KVEngine for RaftKVEngine {
+    async fn new(end_points: Vec<String>) -> DatenLordResult<Self> {
+        let end_points = RaftEndPoints::parse(&end_points)?;
+        let listener = TcpListener::bind(end_points.addr).await?;
+        Self::start(
+            end_points.id,
+            listener,
+            &end_points.dir,
+            &end_points.members,
+        )
+        .await
+    }
+
+    async fn new_meta_txn(&self) -> Box<dyn MetaTxn + Send> {
+        Box::new(RaftTxn::new(Arc::clone(&self.node)))
+    }
+
+    async fn lease_grant(&self, ttl: i64) -> DatenLordResult<i64> {
+        match self.node.propose(&Command::LeaseGrant { ttl }).await? {
+            CommandResult::LeaseGranted(lease) => Ok(lease),
+            result @ (CommandResult::Applied(_)
+            | CommandResult::Conflicted
+            | CommandResult::LeaseRevoked
+            | CommandResult::LeaseNotFound(_)
+            | CommandResult::MembersChanged) => Err(raft_error(format!(
+                "unexpected result {result:?} of granting a lease"
+            ))),
+        }
+    }
+
+    async fn lease_keep_alive(&self, lease: i64) -> DatenLordResult<()> {
+        if self.node.keep_alive(lease).await? {
+            Ok(())
+        } else {
+            Err(DatenLordError::KVEngineErr {
+                source: KVEngineError::LeaseExpired(lease),
+                context: vec![],
+            })
+        }
+    }
+
+    async fn watch(&self, prefix: &KeyType) -> DatenLordResult<WatchStream> {
+        let prefix = prefix.to_string_key().into_bytes();
+        let changes = self.node.subscribe();
+        let events = stream::unfold(changes, move |mut changes| {
+            let prefix = prefix.clone();
+            async move {
+                loop {
+                    let batch = match changes.recv().await {
+                        Ok(batch) => batch,
+                        Err(RecvError::Lagged(count)) => {
+                            let error =
+                                raft_error(format!("the watcher lags behind {count} changes"));
+                            return Some((Err(error), changes));
+                        }
+                        Err(RecvError::Closed) => return None,
+                    };
+                    let events: DatenLordResult<Vec<WatchEvent>> = batch
+                        .into_iter()
+                        .filter(|change| match *change {
+                            Change::Put(ref key, _) | Change::Delete(ref key) => {
+                                key.starts_with(&prefix)
+                            }
+                        })
+                        .map(into_watch_event)
+                        .collect();
+                    if events.as_ref().map_or(true, |events| !events.is_empty()) {
+                        return Some((events, changes));
+                    }
+                }
+            }
+        });
+        Ok(events.boxed())
+    }
+
+    /// Lock a key by a lease of `timeout`, the lock is released once the
+    /// lease expires, like the lock of etcd.
+    async fn lock(&self, key: &LockKeyType, timeout: Duration) -> DatenLordResult<Vec<u8>> {
+        let _timer = KV_METRICS.start_kv_lock_timer();
+        let lease = self.lease_grant(timeout.as_secs().max(1).cast()).await?;
+        let key = key.to_string_key().into_bytes();
+        loop {
+            let ops = vec![
+                Op::Check {
+                    key: key.clone(),
+                    version: 0,
+                },
+                Op::Put {
+                    key: key.clone(),
+                    value: vec![],
+                    lease: Some(lease),
+                },
+            ];
+            if self.propose_txn(ops).await?.0 {
+                return Ok(key);
+            }
+            time::sleep(TICK_INTERVAL).await;
+            self.lease_keep_alive(lease).await?;
+        }
+    }
+
+    async fn unlock(&self, key: Vec<u8>) -> DatenLordResult<()> {
+        self.propose_txn(vec![Op::Delete { key }]).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &KeyType) -> DatenLordResult<Option<ValueType>> {
+        let _timer = KV_METRICS.start_kv_operation_timer("get");
+        let key = key.to_string_key().into_bytes();
+        self.node.read_barrier().await?;
+        let machine = self.node.machine().read();
+        machine
+            .get(&key)
+            .map(|(value, _)| decode_value(value))
+            .transpose()
+    }
+
+    async fn set(
+        &self,
+        key: &KeyType,
+        value: &ValueType,
+        option: Option<SetOption>,
+    ) -> DatenLordResult<Option<ValueType>> {
+        let _timer = KV_METRICS.start_kv_operation_timer("set");
+        let (lease, prev_kv) =
+            option.map_or((None, false), |option| (option.lease, option.prev_kv));
+        let op = Op::Put {
+            key: key.to_string_key().into_bytes(),
+            value: encode_value(value),
+            lease,
+        };
+        let (_, prev) = self.propose_txn(vec![op]).await?;
+        match prev {
+            Some(ref prev) if prev_kv => Ok(Some(decode_value(prev)?)),
+            Some(_) | None => Ok(None),
+        }
+    }
+
+    async fn delete(
+        &self,
+        key: &KeyType,
+        option: Option<DeleteOption>,
+    ) -> DatenLordResult<Option<ValueType>> {
+        let _timer = KV_METRICS.start_kv_operation_timer("delete");
+        let key = key.to_string_key().into_bytes();
+        let (prev_kv, range_end) =
+            option.map_or((false, None), |option| (option.prev_kv, option.range_end));
+        let op = match range_end {
+            Some(end) => Op::DeleteRange { start: key, end },
+            None => Op::Delete { key },
+        };
+        let (_, prev) = self.propose_txn(vec![op]).await?;
+        match prev {
+            Some(ref prev) if prev_kv => Ok(Some(decode_value(prev)?)),
+            Some(_) | None => Ok(None),
+        }
+    }
+
+    async fn range(&self, prefix: &KeyType) -> DatenLordResult<Vec<ValueType>> {
+        let _timer = KV_METRICS.start_kv_operation_timer("range");
+        let prefix = prefix.to_string_key().into_bytes();
+        self.node.read_barrier().await?;
+        let machine = self.node.machine().read();
+        machine.range(&prefix).map(decode_value).collect()
+    }
+}
+
+/// The transaction of the raft engine, it's optimistic like the one of etcd.
+/// Write operations are buffered until commit is called, and the commit fails
+/// if any key read has been modified.
+struct RaftTxn {
+    /// The member
+    node: Arc<RaftNode>,
+    /// Whether the member has applied the writes acknowledged before the
+    /// transaction
+    synced: bool,
+    /// The key is the key in bytes, the value is the version of the key.
+    version_map: HashMap<Vec<u8>, KvVersion>,
+    /// Store the write operations in the buffer.
+    buffer: HashMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl RaftTxn {
+    /// Create a new raft transaction.
+    fn new(node: Arc<RaftNode>) -> Self {
+        Self {
+            node,
+            synced: false,
+            version_map: HashMap::new(),
+            buffer: HashMap::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl MetaTxn for RaftTxn {
+    async fn get(&mut self, key_arg: &KeyType) -> DatenLordResult<Option<ValueType>> {
+        let _timer = KV_METRICS.start_kv_operation_timer("get");
+        let key = key_arg.to_string_key().into_bytes();
+        assert!(
+            self.buffer.get(&key).is_none(),
+            "get the key={key_arg:?} after write in the same transaction"
+        );
+        assert!(
+            self.version_map.get(&key).is_none(),
+            "get the key={key_arg:?} twice in the same transaction"
+        );
+
+        if !self.synced {
+            self.node.read_barrier().await?;
+            self.synced = true;
+        }
+        let machine = self.node.machine().read();
+        let (value, version) = match machine.get(&key) {
+            Some((value, version)) => (Some(decode_value(value)?), version),
+            None => (None, 0),
+        };
+        drop(machine);
+        self.version_map.insert(key, version);
+        Ok(value)
+    }
+
+    fn set(&mut self, key: &KeyType, value: &ValueType) {
+        let key = key.to_string_key().into_bytes();
+        // Set same key twice in the same transaction is not allowed.
+        debug_assert!(
+            self.buffer.get(&key).is_none(),
+            "set the key={key:?} twice in the same transaction"
+        );
+        self.buffer.insert(key, Some(encode_value(value)));
+    }
+
+    fn delete(&mut self, key: &KeyType) {
+        let key = key.to_string_key().into_bytes();
+        self.buffer.insert(key, None);
+    }
+
+    #[instrument(
+        name = "kv_txn",
+        skip_all,
+        fields(reads = self.version_map.len(), writes = self.buffer.len())
+    )]
+    async fn commit(&mut self) -> DatenLordResult<bool> {
+        let _timer = KV_METRICS.start_kv_operation_timer("txn");
+
+        if self.version_map.is_empty() && self.buffer.is_empty() {
+            return Ok(true);
+        }
+
+        let checks = self
+            .version_map
+            .drain()
+            .map(|(key, version)| Op::Check { key, version });
+        let writes = self.buffer.drain().map(|(key, value)| match value {
+            Some(value) => Op::Put {
+                key,
+                value,
+                lease: None,
+            },
+            None => Op::Delete { key },
+        });
+        let ops = checks.chain(writes).collect();
+
+        let result = self.node.propose(&Command::Txn(ops)).await?;
+        match result {
+            CommandResult::Applied(_) => Ok(true),
+            CommandResult::Conflicted => Ok(false),
+            CommandResult::LeaseGranted(_)
+            | CommandResult::LeaseRevoked
+            | CommandResult::LeaseNotFound(_)
+            | CommandResult::MembersChanged => Err(raft_error(format!(
+                "unexpected result {result:?} of a transaction"
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+
+    /// An empty directory for the state of a member.
+    fn empty_dir(name: &str) -> PathBuf {
+        let dir = Path::new("/tmp/datenlord_raft_kv").join(name);
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        dir
+    }
+
+    /// Bind the listeners of `count` members on loopback, returns them along
+    /// with the members.
+    async fn bind(count: RaftId) -> (Vec<TcpListener>, Members) {
+        let mut listeners = vec![];
+        let mut members = Members::new();
+        for id in 1..=count {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            members.insert(id, format!("http://{}", listener.local_addr().unwrap()));
+            listeners.push(listener);
+        }
+        (listeners, members)
+    }
+
+    /// Start a group of a single member.
+    async fn start_single(dir: &Path) -> RaftKVEngine {
+        let (mut listeners, members) = bind(1).await;
+        RaftKVEngine::start(1, listeners.pop().unwrap(), dir, &members)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_end_points() {
+        let end_points = vec![
+            "raft://1@127.0.0.1:7900/var/lib/datenlord/meta".to_owned(),
+            "1=http://127.0.0.1:7900".to_owned(),
+            "2=http://127.0.0.2:7900".to_owned(),
+        ];
+        assert_eq!(
+            RaftEndPoints::parse(&end_points).unwrap(),
+            RaftEndPoints {
+                id: 1,
+                addr: SocketAddr::from(([127, 0, 0, 1], 7900)),
+                dir: PathBuf::from("/var/lib/datenlord/meta"),
+                members: Members::from([
+                    (1, "http://127.0.0.1:7900".to_owned()),
+                    (2, "http://127.0.0.2:7900".to_owned()),
+                ]),
+            }
+        );
+
+        for invalid in [
+            vec!["raft://127.0.0.1:7900/meta".to_owned()],
+            vec!["raft://1@127.0.0.1:7900".to_owned()],
+            vec!["file:///meta".to_owned()],
+            vec![
+                "raft://1@127.0.0.1:7900/meta".to_owned(),
+                "http://127.0.0.1:7900".to_owned(),
+            ],
+        ] {
+            assert!(RaftEndPoints::parse(&invalid).is_err(), "{invalid:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recover_after_restart() {
+        let dir = empty_dir("restart");
+        let engine = start_single(&dir).await;
+        let key = KeyType::String("key".to_owned());
+        let removed = KeyType::String("removed".to_owned());
+        let value = ValueType::String("value".to_owned());
+
+        engine.set(&key, &value, None).await.unwrap();
+        engine.set(&removed, &value, None).await.unwrap();
+        let mut txn = engine.new_meta_txn().await;
+        txn.delete(&removed);
+        assert!(txn.commit().await.unwrap());
+        drop(engine);
+        // Wait for the background tasks to exit.
+        time::sleep(TICK_INTERVAL * 2).await;
+
+        let engine = start_single(&dir).await;
+        assert_eq!(engine.get(&key).await.unwrap(), Some(value));
+        assert!(engine.get(&removed).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_commit_conflict() {
+        let engine = start_single(&empty_dir("conflict")).await;
+        let key = KeyType::String("key".to_owned());
+
+        let mut txn = engine.new_meta_txn().await;
+        assert!(txn.get(&key).await.unwrap().is_none());
+        engine
+            .set(&key, &ValueType::String("value".to_owned()), None)
+            .await
+            .unwrap();
+        txn.set(&key, &ValueType::String("stale".to_owned()));
+        assert!(!txn.commit().await.unwrap());
+        assert_eq!(
+            engine.get(&key).await.unwrap(),
+            Some(ValueType::String("value".to_owned()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch_lease_expiry() {
+        let engine = start_single(&empty_dir("lease")).await;
+        let value = ValueType::String("value".to_owned());
+        let mut events = engine
+            .watch(&KeyType::String("dir/".to_owned()))
+            .await
+            .unwrap();
+
+        let lease = engine.lease_grant(1).await.unwrap();
+        engine
+            .set(&KeyType::String("other".to_owned()), &value, None)
+            .await
+            .unwrap();
+        let option = SetOption {
+            lease: Some(lease),
+            prev_kv: false,
+        };
+        engine
+            .set(&KeyType::String("dir/a".to_owned()), &value, Some(option))
+            .await
+            .unwrap();
+
+        let batch = events.next().await.unwrap().unwrap();
+        assert_eq!(batch.len(), 1);
+        assert!(matches!(batch.first(), Some(&WatchEvent::Put(ref put)) if *put == value));
+
+        // The key is deleted once the lease expires.
+        let batch = events.next().await.unwrap().unwrap();
+        assert_eq!(batch.len(), 1);
+        assert!(
+            matches!(batch.first(), Some(&WatchEvent::Delete(ref key)) if key.ends_with("dir/a"))
+        );
+        assert!(engine
+            .get(&KeyType::String("dir/a".to_owned()))
+            .await
+            .unwrap()
+            .is_none());
+        assert!(engine.lease_keep_alive(lease).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_replicate_among_members() {
+        let (listeners, members) = bind(3).await;
+        let mut engines = vec![];
+        for (id, listener) in (1..).zip(listeners) {
+            let dir = empty_dir(&format!("member_{id}"));
+            let engine = RaftKVEngine::start(id, listener, &dir, &members)
+                .await
+                .unwrap();
+            engines.push(engine);
+        }
+        let key = KeyType::String("key".to_owned());
+        let value = ValueType::String("value".to_owned());
+
+        let (first, rest) = engines.split_first().unwrap();
+        first.set(&key, &value, None).await.unwrap();
+        for engine in rest {
+            assert_eq!(engine.get(&key).await.unwrap(), Some(value.clone()));
+        }
+
+        // A member not listed joins the group, and catches up.
+        let (mut listeners, _) = bind(1).await;
+        let listener = listeners.pop().unwrap();
+        let joined = RaftKVEngine::start(4, listener, &empty_dir("member_4"), &members)
+            .await
+            .unwrap();
+        assert_eq!(joined.get(&key).await.unwrap(), Some(value));
+    }
+}
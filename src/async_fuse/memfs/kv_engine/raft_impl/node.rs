@@ -0,0 +1,456 @@
+//! A member of the raft group, driving the consensus by the clock and the
+//! transport, and applying the committed commands to the state machine.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clippy_utilities::{Cast, OverflowArithmetic};
+use futures::Future;
+use parking_lot::{Mutex, RwLock};
+use tokio::sync::{broadcast, oneshot, watch};
+use tokio::time::{self, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+use super::core::{EntryPayload, Members, Message, RaftCore, RaftId};
+use super::state_machine::{Change, Command, CommandResult, KvStateMachine};
+use super::transport::RaftPeer;
+use super::{is_no_leader, no_leader, raft_error};
+use crate::common::error::{Context, DatenLordResult};
+
+/// The interval between ticks of the consensus.
+pub const TICK_INTERVAL: Duration = Duration::from_millis(100);
+/// The time to wait for a leader, before a request fails.
+const LEADER_TIMEOUT: Duration = Duration::from_secs(10);
+/// The time to wait for a proposal to be applied.
+const PROPOSE_TIMEOUT: Duration = Duration::from_secs(10);
+/// The log is compacted into a snapshot once it has more applied entries than
+/// this.
+const SNAPSHOT_ENTRIES: u64 = 10_000;
+
+/// The messages to send, along with the endpoints of the receivers.
+type Outbox = Vec<(String, Message)>;
+
+/// Where the leader is.
+#[derive(Debug)]
+pub enum Leader {
+    /// This member is the leader
+    Local,
+    /// Another member is the leader
+    Remote(RaftPeer),
+}
+
+/// A member of the raft group.
+#[derive(Debug)]
+pub struct RaftNode {
+    /// The consensus
+    core: Mutex<RaftCore>,
+    /// The key space, always locked after `core` if both are locked
+    machine: RwLock<KvStateMachine>,
+    /// The proposals waiting to be applied, indexed by the indexes of their
+    /// entries, along with their terms
+    proposals: Mutex<HashMap<u64, (u64, oneshot::Sender<CommandResult>)>>,
+    /// The index of the last entry applied
+    applied: watch::Sender<u64>,
+    /// The clients of the other members, indexed by their endpoints
+    peers: Mutex<HashMap<String, RaftPeer>>,
+    /// The deadlines of the leases, tracked by the leader only
+    lease_deadlines: Mutex<HashMap<i64, Instant>>,
+}
+
+impl RaftNode {
+    /// Create a member, the state machine is restored from the snapshot.
+    pub fn new(core: RaftCore) -> DatenLordResult<Self> {
+        let mut machine = KvStateMachine::default();
+        machine.restore(&core.snapshot().data)?;
+        let applied = core.snapshot().index;
+        Ok(Self {
+            core: Mutex::new(core),
+            machine: RwLock::new(machine),
+            proposals: Mutex::new(HashMap::new()),
+            applied: watch::channel(applied).0,
+            peers: Mutex::new(HashMap::new()),
+            lease_deadlines: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The key space.
+    pub fn machine(&self) -> &RwLock<KvStateMachine> {
+        &self.machine
+    }
+
+    /// Subscribe the changes of the key space.
+    pub fn subscribe(&self) -> broadcast::Receiver<Vec<Change>> {
+        self.machine.read().subscribe()
+    }
+
+    /// Drive the consensus by the clock until `token` is cancelled.
+    #[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+    pub async fn run(self: Arc<Self>, token: CancellationToken) {
+        let mut interval = time::interval(TICK_INTERVAL);
+        loop {
+            tokio::select! {
+                () = token.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            if let Err(e) = self.tick() {
+                error!("Failed to tick the raft group: {e}");
+            }
+        }
+        info!("Raft member exits.");
+    }
+
+    /// Advance the clock, and revoke the expired leases if this member is the
+    /// leader.
+    fn tick(&self) -> DatenLordResult<()> {
+        let outbox = {
+            let mut core = self.core.lock();
+            core.tick()?;
+            for lease in self.expired_leases(&core) {
+                info!("Lease {lease} is expired.");
+                let payload =
+                    EntryPayload::Command(encode_command(&Command::LeaseRevoke { lease })?);
+                if let Err(e) = core.propose(payload) {
+                    warn!("Failed to revoke the expired lease {lease}: {e}");
+                }
+            }
+            self.advance(&mut core)?
+        };
+        self.send(outbox);
+        Ok(())
+    }
+
+    /// Returns the leases expired. The deadlines are tracked by the leader
+    /// only, a new leader renews all leases, as the deadlines are not
+    /// replicated.
+    fn expired_leases(&self, core: &RaftCore) -> Vec<i64> {
+        let mut deadlines = self.lease_deadlines.lock();
+        if !core.is_leader() {
+            deadlines.clear();
+            return vec![];
+        }
+        let now = Instant::now();
+        let machine = self.machine.read();
+        let leases = machine.leases();
+        deadlines.retain(|lease, _| leases.contains_key(lease));
+        for (&lease, info) in leases {
+            deadlines
+                .entry(lease)
+                .or_insert_with(|| now + ttl_duration(info.ttl));
+        }
+        let expired: Vec<i64> = deadlines
+            .iter()
+            .filter(|&(_, deadline)| *deadline <= now)
+            .map(|(&lease, _)| lease)
+            .collect();
+        for lease in &expired {
+            deadlines.remove(lease);
+        }
+        expired
+    }
+
+    /// Handle a message from another member.
+    pub fn step(&self, message: Message) -> DatenLordResult<()> {
+        let outbox = {
+            let mut core = self.core.lock();
+            core.step(message)?;
+            self.advance(&mut core)?
+        };
+        self.send(outbox);
+        Ok(())
+    }
+
+    /// Apply the committed entries, resolve the proposals of them, and take
+    /// the messages to send.
+    fn advance(&self, core: &mut RaftCore) -> DatenLordResult<Outbox> {
+        if let Some(snapshot) = core.take_snapshot() {
+            self.machine.write().restore(&snapshot.data)?;
+            self.applied.send_replace(snapshot.index);
+        }
+
+        let entries = core.take_committed();
+        if let Some(last) = entries.last().map(|entry| entry.index) {
+            let mut machine = self.machine.write();
+            let mut proposals = self.proposals.lock();
+            for entry in entries {
+                let result = match entry.payload {
+                    EntryPayload::Noop => None,
+                    EntryPayload::Command(ref command) => {
+                        let command: Command = bincode::deserialize(command)
+                            .with_context(|| "failed to decode a raft command".to_owned())?;
+                        Some(machine.apply(command))
+                    }
+                    EntryPayload::Members(_) => Some(CommandResult::MembersChanged),
+                };
+                // The proposal is dropped if the entry is replaced by another
+                // leader.
+                if let Some((term, tx)) = proposals.remove(&entry.index) {
+                    match result {
+                        Some(result) if term == entry.term => {
+                            let _: Result<(), CommandResult> = tx.send(result);
+                        }
+                        Some(_) | None => {}
+                    }
+                }
+            }
+            self.applied.send_replace(last);
+
+            if last.overflow_sub(core.snapshot().index) >= SNAPSHOT_ENTRIES {
+                core.compact(machine.snapshot()?)?;
+            }
+        }
+
+        let members = core.members().clone();
+        let messages = core.take_messages();
+        Ok(messages
+            .into_iter()
+            .filter_map(|message| {
+                let endpoint = members.get(&message.to)?.clone();
+                Some((endpoint, message))
+            })
+            .collect())
+    }
+
+    /// Get the client of the member serving at `endpoint`.
+    fn peer(&self, endpoint: &str) -> DatenLordResult<RaftPeer> {
+        let mut peers = self.peers.lock();
+        if let Some(peer) = peers.get(endpoint) {
+            return Ok(peer.clone());
+        }
+        let peer = RaftPeer::connect(endpoint)?;
+        peers.insert(endpoint.to_owned(), peer.clone());
+        Ok(peer)
+    }
+
+    /// Send the messages in the background, the lost ones are recovered by
+    /// the retries of the consensus.
+    fn send(&self, outbox: Outbox) {
+        for (endpoint, message) in outbox {
+            let peer = match self.peer(&endpoint) {
+                Ok(peer) => peer,
+                Err(e) => {
+                    warn!("Failed to connect the raft member at {endpoint}: {e}");
+                    continue;
+                }
+            };
+            tokio::spawn(async move {
+                if let Err(e) = peer.step(&message).await {
+                    debug!("Failed to send a raft message to {endpoint}: {e}");
+                }
+            });
+        }
+    }
+
+    /// Find the leader.
+    fn leader(&self) -> DatenLordResult<Leader> {
+        let core = self.core.lock();
+        if core.is_leader() {
+            return Ok(Leader::Local);
+        }
+        let endpoint = core
+            .leader()
+            .and_then(|leader| core.members().get(&leader))
+            .cloned()
+            .ok_or_else(no_leader)?;
+        drop(core);
+        Ok(Leader::Remote(self.peer(&endpoint)?))
+    }
+
+    /// Call the leader, it's retried until the leader is elected.
+    async fn on_leader<T, F, Fut>(&self, call: F) -> DatenLordResult<T>
+    where
+        F: Fn(Leader) -> Fut,
+        Fut: Future<Output = DatenLordResult<T>>,
+    {
+        let deadline = Instant::now() + LEADER_TIMEOUT;
+        loop {
+            let result = match self.leader() {
+                Ok(leader) => call(leader).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Err(ref e) if is_no_leader(e) && Instant::now() < deadline => {
+                    time::sleep(TICK_INTERVAL).await;
+                }
+                Ok(_) | Err(_) => return result,
+            }
+        }
+    }
+
+    /// Propose an entry on this member, returns the receiver of its result
+    /// and the messages to send.
+    fn propose_locked(
+        &self,
+        core: &mut RaftCore,
+        payload: EntryPayload,
+    ) -> DatenLordResult<(oneshot::Receiver<CommandResult>, Outbox)> {
+        let term = core.term();
+        let index = core.propose(payload)?;
+        let (tx, rx) = oneshot::channel();
+        self.proposals.lock().insert(index, (term, tx));
+        Ok((rx, self.advance(core)?))
+    }
+
+    /// Wait for the result of a proposal.
+    async fn wait_proposal(rx: oneshot::Receiver<CommandResult>) -> DatenLordResult<CommandResult> {
+        match time::timeout(PROPOSE_TIMEOUT, rx).await {
+            Ok(Ok(result)) => Ok(result),
+            // The entry is replaced by another leader, so it's not applied.
+            Ok(Err(_)) => Err(no_leader()),
+            Err(_) => Err(raft_error("the proposal is timed out".to_owned())),
+        }
+    }
+
+    /// Propose a command on this member, which should be the leader.
+    pub async fn propose_local(&self, command: &Command) -> DatenLordResult<CommandResult> {
+        let payload = EntryPayload::Command(encode_command(command)?);
+        let (rx, outbox) = self.propose_locked(&mut self.core.lock(), payload)?;
+        self.send(outbox);
+        Self::wait_proposal(rx).await
+    }
+
+    /// Propose a command to the leader.
+    pub async fn propose(&self, command: &Command) -> DatenLordResult<CommandResult> {
+        self.on_leader(|leader| async move {
+            match leader {
+                Leader::Local => self.propose_local(command).await,
+                Leader::Remote(peer) => peer.propose(command).await,
+            }
+        })
+        .await
+    }
+
+    /// Get the commit index on this member, which should be the leader.
+    pub fn read_index_local(&self) -> DatenLordResult<u64> {
+        self.core.lock().read_index().ok_or_else(no_leader)
+    }
+
+    /// Wait until this member applies all the writes acknowledged, so a read
+    /// afterwards sees them.
+    pub async fn read_barrier(&self) -> DatenLordResult<()> {
+        let index = self
+            .on_leader(|leader| async move {
+                match leader {
+                    Leader::Local => self.read_index_local(),
+                    Leader::Remote(peer) => peer.read_index().await,
+                }
+            })
+            .await?;
+        let mut applied = self.applied.subscribe();
+        match time::timeout(
+            LEADER_TIMEOUT,
+            applied.wait_for(|&applied| applied >= index),
+        )
+        .await
+        {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(_)) | Err(_) => Err(raft_error(format!(
+                "the member fails to apply up to {index} in time"
+            ))),
+        }
+    }
+
+    /// Renew a lease on this member, which should be the leader. Returns
+    /// `false` if the lease doesn't exist.
+    pub fn keep_alive_local(&self, lease: i64) -> DatenLordResult<bool> {
+        let core = self.core.lock();
+        if !core.is_leader() {
+            return Err(no_leader());
+        }
+        let Some(ttl) = self
+            .machine
+            .read()
+            .leases()
+            .get(&lease)
+            .map(|info| info.ttl)
+        else {
+            return Ok(false);
+        };
+        drop(core);
+        self.lease_deadlines
+            .lock()
+            .insert(lease, Instant::now() + ttl_duration(ttl));
+        Ok(true)
+    }
+
+    /// Renew a lease on the leader. Returns `false` if the lease doesn't
+    /// exist.
+    pub async fn keep_alive(&self, lease: i64) -> DatenLordResult<bool> {
+        self.on_leader(|leader| async move {
+            match leader {
+                Leader::Local => self.keep_alive_local(lease),
+                Leader::Remote(peer) => peer.keep_alive(lease).await,
+            }
+        })
+        .await
+    }
+
+    /// Add the member `id` serving at `endpoint`, or remove it if `endpoint`
+    /// is `None`, on this member, which should be the leader. It returns once
+    /// the change is committed.
+    pub async fn change_members_local(
+        &self,
+        id: RaftId,
+        endpoint: Option<String>,
+    ) -> DatenLordResult<()> {
+        let (rx, outbox) = {
+            let mut core = self.core.lock();
+            if !core.is_leader() {
+                return Err(no_leader());
+            }
+            let mut members = core.members().clone();
+            let unchanged = match endpoint {
+                Some(endpoint) => members.insert(id, endpoint.clone()) == Some(endpoint),
+                None => members.remove(&id).is_none(),
+            };
+            if unchanged {
+                return Ok(());
+            }
+            if members.is_empty() {
+                return Err(raft_error("the last member can't be removed".to_owned()));
+            }
+            self.propose_locked(&mut core, EntryPayload::Members(members))?
+        };
+        self.send(outbox);
+        Self::wait_proposal(rx).await?;
+        Ok(())
+    }
+
+    /// Ask the members to add this member serving at `endpoint`, it's
+    /// retried until the leader adds it.
+    pub async fn join(&self, id: RaftId, endpoint: &str, members: &Members) -> DatenLordResult<()> {
+        let deadline = Instant::now() + LEADER_TIMEOUT;
+        loop {
+            for (&member, member_endpoint) in members {
+                let result = match self.peer(member_endpoint) {
+                    Ok(peer) => peer.change_members(id, Some(endpoint.to_owned())).await,
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(()) => {
+                        info!("Member {id} joins the raft group by member {member}.");
+                        return Ok(());
+                    }
+                    Err(e) => debug!("Failed to join the raft group by member {member}: {e}"),
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err(raft_error(format!(
+                    "member {id} fails to join the raft group of {members:?}"
+                )));
+            }
+            time::sleep(TICK_INTERVAL).await;
+        }
+    }
+}
+
+/// Encode a command into the payload of an entry.
+fn encode_command(command: &Command) -> DatenLordResult<Vec<u8>> {
+    bincode::serialize(command).with_context(|| "failed to encode a raft command".to_owned())
+}
+
+/// The duration of the time to live of a lease in seconds.
+fn ttl_duration(ttl: i64) -> Duration {
+    Duration::from_secs(ttl.max(1).cast())
+}
@@ -0,0 +1,345 @@
+//! The key space replicated by the raft log.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use clippy_utilities::OverflowArithmetic;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::async_fuse::memfs::kv_engine::KvVersion;
+use crate::common::error::{Context, DatenLordResult};
+
+/// The capacity of the channel of changes, a watcher lagging behind more
+/// batches than this fails.
+const CHANGE_CHANNEL_CAPACITY: usize = 1024;
+
+/// An operation of a transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Op {
+    /// Check the version of a key, the transaction is not applied if the key
+    /// is modified
+    Check {
+        /// The key
+        key: Vec<u8>,
+        /// The version read, `0` if the key doesn't exist
+        version: KvVersion,
+    },
+    /// Set a key, attached to a lease if any
+    Put {
+        /// The key
+        key: Vec<u8>,
+        /// The serialized value
+        value: Vec<u8>,
+        /// The lease of the key
+        lease: Option<i64>,
+    },
+    /// Delete a key
+    Delete {
+        /// The key
+        key: Vec<u8>,
+    },
+    /// Delete the keys in `[start, end)`
+    DeleteRange {
+        /// The first key
+        start: Vec<u8>,
+        /// The end of the keys, excluded
+        end: Vec<u8>,
+    },
+}
+
+/// A command of the state machine, applied in the same order on every member.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Command {
+    /// Apply the writes of a transaction if none of the keys checked is
+    /// modified, like the commit of a transaction of etcd
+    Txn(Vec<Op>),
+    /// Grant a lease
+    LeaseGrant {
+        /// The time to live in seconds
+        ttl: i64,
+    },
+    /// Revoke a lease, and delete the keys attached to it
+    LeaseRevoke {
+        /// The lease
+        lease: i64,
+    },
+}
+
+/// The result of a command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CommandResult {
+    /// The writes of the transaction are applied, along with the previous
+    /// value of the first key written
+    Applied(Option<Vec<u8>>),
+    /// The transaction is not applied, as a key checked is modified
+    Conflicted,
+    /// The lease granted
+    LeaseGranted(i64),
+    /// The lease is revoked
+    LeaseRevoked,
+    /// The lease of a key written doesn't exist
+    LeaseNotFound(i64),
+    /// The members are changed
+    MembersChanged,
+}
+
+/// A change of a key, sent to the watchers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// The key is set to the value
+    Put(Vec<u8>, Vec<u8>),
+    /// The key is deleted
+    Delete(Vec<u8>),
+}
+
+/// A value with its version.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct KeyValue {
+    /// The serialized value
+    value: Vec<u8>,
+    /// The number of modifications since the key is created
+    version: KvVersion,
+    /// The lease of the key
+    lease: Option<i64>,
+}
+
+/// A lease, its keys are deleted once it's revoked.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lease {
+    /// The time to live in seconds
+    pub ttl: i64,
+    /// The keys attached
+    keys: BTreeSet<Vec<u8>>,
+}
+
+/// The state in a snapshot.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct KvState {
+    /// All the keys and values
+    entries: BTreeMap<Vec<u8>, KeyValue>,
+    /// The leases
+    leases: BTreeMap<i64, Lease>,
+    /// The id of the next lease
+    next_lease: i64,
+}
+
+/// The key space replicated by the raft log, the changes are sent to the
+/// watchers as they're applied.
+#[derive(Debug)]
+pub struct KvStateMachine {
+    /// The state
+    state: KvState,
+    /// The changes of keys, every item is a batch of changes of a command
+    changes: broadcast::Sender<Vec<Change>>,
+}
+
+impl Default for KvStateMachine {
+    fn default() -> Self {
+        Self {
+            state: KvState::default(),
+            changes: broadcast::channel(CHANGE_CHANNEL_CAPACITY).0,
+        }
+    }
+}
+
+impl KvStateMachine {
+    /// Get the value and the version of a key.
+    pub fn get(&self, key: &[u8]) -> Option<(&[u8], KvVersion)> {
+        self.state
+            .entries
+            .get(key)
+            .map(|kv| (kv.value.as_slice(), kv.version))
+    }
+
+    /// Get the values of the keys with `prefix`.
+    pub fn range<'a>(&'a self, prefix: &'a [u8]) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.state
+            .entries
+            .range(prefix.to_vec()..)
+            .take_while(move |&(key, _)| key.starts_with(prefix))
+            .map(|(_, kv)| kv.value.as_slice())
+    }
+
+    /// The version of a key, or `0` if it does not exist.
+    fn version(&self, key: &[u8]) -> KvVersion {
+        self.state.entries.get(key).map_or(0, |kv| kv.version)
+    }
+
+    /// The leases.
+    pub fn leases(&self) -> &BTreeMap<i64, Lease> {
+        &self.state.leases
+    }
+
+    /// Subscribe the changes applied from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Vec<Change>> {
+        self.changes.subscribe()
+    }
+
+    /// Send the changes to the watchers.
+    fn notify(&self, changes: Vec<Change>) {
+        if !changes.is_empty() {
+            // It fails only if there's no watcher.
+            let _: usize = self.changes.send(changes).unwrap_or(0);
+        }
+    }
+
+    /// Apply a command.
+    pub fn apply(&mut self, command: Command) -> CommandResult {
+        match command {
+            Command::Txn(ops) => self.apply_txn(ops),
+            Command::LeaseGrant { ttl } => {
+                let lease = self.state.next_lease.max(1);
+                self.state.next_lease = lease.overflow_add(1);
+                self.state.leases.insert(
+                    lease,
+                    Lease {
+                        ttl,
+                        keys: BTreeSet::new(),
+                    },
+                );
+                CommandResult::LeaseGranted(lease)
+            }
+            Command::LeaseRevoke { lease } => {
+                if let Some(lease) = self.state.leases.remove(&lease) {
+                    let mut changes = vec![];
+                    for key in lease.keys {
+                        self.state.entries.remove(&key);
+                        changes.push(Change::Delete(key));
+                    }
+                    self.notify(changes);
+                }
+                CommandResult::LeaseRevoked
+            }
+        }
+    }
+
+    /// Apply the writes of a transaction if none of the keys checked is
+    /// modified.
+    fn apply_txn(&mut self, ops: Vec<Op>) -> CommandResult {
+        for op in &ops {
+            match *op {
+                Op::Check { ref key, version } if self.version(key) != version => {
+                    return CommandResult::Conflicted;
+                }
+                Op::Put {
+                    lease: Some(lease), ..
+                } if !self.state.leases.contains_key(&lease) => {
+                    return CommandResult::LeaseNotFound(lease);
+                }
+                Op::Check { .. } | Op::Put { .. } | Op::Delete { .. } | Op::DeleteRange { .. } => {}
+            }
+        }
+
+        let mut prev = None;
+        let mut changes = vec![];
+        let mut first_write = true;
+        for op in ops {
+            let removed = match op {
+                Op::Check { .. } => continue,
+                Op::Put { key, value, lease } => {
+                    let version = self.version(&key).overflow_add(1);
+                    if let Some(lease) = lease {
+                        if let Some(lease) = self.state.leases.get_mut(&lease) {
+                            lease.keys.insert(key.clone());
+                        }
+                    }
+                    changes.push(Change::Put(key.clone(), value.clone()));
+                    let old = self.state.entries.insert(
+                        key,
+                        KeyValue {
+                            value,
+                            version,
+                            lease,
+                        },
+                    );
+                    vec![old]
+                }
+                Op::Delete { key } => {
+                    let old = self.state.entries.remove(&key);
+                    if old.is_some() {
+                        changes.push(Change::Delete(key));
+                    }
+                    vec![old]
+                }
+                Op::DeleteRange { start, end } => {
+                    // The range is empty rather than invalid if it's reversed.
+                    let keys: Vec<Vec<u8>> = if start < end {
+                        self.state
+                            .entries
+                            .range(start..end)
+                            .map(|(key, _)| key.clone())
+                            .collect()
+                    } else {
+                        vec![]
+                    };
+                    keys.into_iter()
+                        .map(|key| {
+                            let old = self.state.entries.remove(&key);
+                            changes.push(Change::Delete(key));
+                            old
+                        })
+                        .collect()
+                }
+            };
+
+            for old in removed.iter().flatten() {
+                self.detach_lease(old);
+            }
+            if first_write {
+                first_write = false;
+                prev = removed.into_iter().next().flatten().map(|kv| kv.value);
+            }
+        }
+        self.notify(changes);
+        CommandResult::Applied(prev)
+    }
+
+    /// Detach a key overwritten or deleted from its lease.
+    fn detach_lease(&mut self, old: &KeyValue) {
+        let Some(lease) = old.lease else {
+            return;
+        };
+        let Some(lease) = self.state.leases.get_mut(&lease) else {
+            return;
+        };
+        lease.keys.retain(|key| {
+            self.state
+                .entries
+                .get(key)
+                .map_or(false, |kv| kv.lease == old.lease)
+        });
+    }
+
+    /// Serialize the state into a snapshot.
+    pub fn snapshot(&self) -> DatenLordResult<Vec<u8>> {
+        bincode::serialize(&self.state)
+            .with_context(|| "failed to encode the state machine".to_owned())
+    }
+
+    /// Restore the state from a snapshot, the differences are sent to the
+    /// watchers as changes.
+    pub fn restore(&mut self, data: &[u8]) -> DatenLordResult<()> {
+        let state: KvState = if data.is_empty() {
+            KvState::default()
+        } else {
+            bincode::deserialize(data)
+                .with_context(|| "failed to decode the state machine".to_owned())?
+        };
+
+        let mut changes: Vec<Change> = self
+            .state
+            .entries
+            .keys()
+            .filter(|&key| !state.entries.contains_key(key))
+            .map(|key| Change::Delete(key.clone()))
+            .collect();
+        for (key, kv) in &state.entries {
+            if self.state.entries.get(key) != Some(kv) {
+                changes.push(Change::Put(key.clone(), kv.value.clone()));
+            }
+        }
+        self.state = state;
+        self.notify(changes);
+        Ok(())
+    }
+}
@@ -0,0 +1,269 @@
+//! The persisted state of a raft member: the hard state, the log and the
+//! snapshot.
+//!
+//! The log is a journal of batches of entries, like the journal of the local
+//! engine. A batch starting at an index already in the log replaces the
+//! entries from there on, so the conflicting entries are truncated by
+//! appending as well. The journal is rewritten only when the log is
+//! compacted into a snapshot.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use clippy_utilities::{Cast, OverflowArithmetic};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use super::core::{Entry, EntryPayload, Members, RaftId, Snapshot};
+use crate::async_fuse::memfs::kv_engine::local_impl::{decode_record, encode_record};
+use crate::common::error::{Context, DatenLordResult};
+
+/// The name of the hard state file
+const HARD_STATE_FILE: &str = "hard_state";
+/// The name of the snapshot file
+const SNAPSHOT_FILE: &str = "snapshot";
+/// The name of the log file
+const LOG_FILE: &str = "log";
+/// The suffix of a file being written, which is renamed once it's synced
+const TMP_SUFFIX: &str = ".tmp";
+
+/// The state of a member persisted before it replies to any message.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HardState {
+    /// The latest term the member has seen
+    pub term: u64,
+    /// The candidate voted for in `term`
+    pub voted_for: Option<RaftId>,
+}
+
+/// The persisted state of a raft member.
+#[derive(Debug)]
+pub struct RaftStorage {
+    /// The directory of the files
+    dir: PathBuf,
+    /// The hard state
+    hard_state: HardState,
+    /// The snapshot, replacing the log up to its index
+    snapshot: Snapshot,
+    /// The entries after the snapshot
+    entries: Vec<Entry>,
+    /// The log opened for appending
+    log: File,
+}
+
+/// Write a file atomically by renaming.
+fn write_atomically(dir: &Path, name: &str, bytes: &[u8]) -> DatenLordResult<()> {
+    let tmp_path = dir.join(format!("{name}{TMP_SUFFIX}"));
+    let mut file = File::create(&tmp_path)
+        .with_context(|| format!("failed to create the raft file {tmp_path:?}"))?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, dir.join(name))?;
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Read and decode a file written by `write_atomically`, returns `None` if
+/// it doesn't exist.
+fn read_file<T: DeserializeOwned>(path: &Path) -> DatenLordResult<Option<T>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let bytes = fs::read(path).with_context(|| format!("failed to read the raft file {path:?}"))?;
+    let value = bincode::deserialize(&bytes)
+        .with_context(|| format!("failed to decode the raft file {path:?}"))?;
+    Ok(Some(value))
+}
+
+/// Open the log for appending.
+fn open_log(path: &Path) -> DatenLordResult<File> {
+    OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+        .with_context(|| format!("failed to open the raft log {path:?}"))
+}
+
+impl RaftStorage {
+    /// Open the storage in `dir`, it's created if not exists.
+    pub fn open(dir: &Path) -> DatenLordResult<Self> {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create the raft directory {dir:?}"))?;
+        let hard_state = read_file(&dir.join(HARD_STATE_FILE))?.unwrap_or_default();
+        let snapshot: Snapshot = read_file(&dir.join(SNAPSHOT_FILE))?.unwrap_or_default();
+
+        let log_path = dir.join(LOG_FILE);
+        let mut log = open_log(&log_path)?;
+        let mut bytes = vec![];
+        log.read_to_end(&mut bytes)
+            .with_context(|| format!("failed to read the raft log {log_path:?}"))?;
+
+        let mut storage = Self {
+            dir: dir.to_owned(),
+            hard_state,
+            snapshot,
+            entries: vec![],
+            log,
+        };
+        let mut offset = 0_usize;
+        while let Some((batch, len)) = decode_record::<Vec<Entry>>(bytes.get(offset..)) {
+            storage.append_in_memory(batch);
+            offset = offset.overflow_add(len);
+        }
+        if offset < bytes.len() {
+            // The record was not acknowledged, as it's synced before replying.
+            warn!("discard a torn record at offset {offset} of the raft log {log_path:?}");
+            storage
+                .log
+                .set_len(offset.cast())
+                .with_context(|| "failed to truncate the torn record of the raft log".to_owned())?;
+            storage.log.sync_all()?;
+        }
+
+        info!(
+            "raft state is recovered from {dir:?}, term {}, snapshot at {}, last index {}",
+            storage.hard_state.term,
+            storage.snapshot.index,
+            storage.last_index()
+        );
+        Ok(storage)
+    }
+
+    /// The hard state.
+    pub fn hard_state(&self) -> &HardState {
+        &self.hard_state
+    }
+
+    /// Persist the hard state.
+    pub fn set_hard_state(&mut self, hard_state: HardState) -> DatenLordResult<()> {
+        let bytes = bincode::serialize(&hard_state)
+            .with_context(|| "failed to encode the raft hard state".to_owned())?;
+        write_atomically(&self.dir, HARD_STATE_FILE, &bytes)?;
+        self.hard_state = hard_state;
+        Ok(())
+    }
+
+    /// The snapshot.
+    pub fn snapshot(&self) -> &Snapshot {
+        &self.snapshot
+    }
+
+    /// The index of the last entry, including the ones in the snapshot.
+    pub fn last_index(&self) -> u64 {
+        self.snapshot.index.overflow_add(self.entries.len().cast())
+    }
+
+    /// Get an entry after the snapshot.
+    fn entry(&self, index: u64) -> Option<&Entry> {
+        let offset = index.checked_sub(self.snapshot.index.overflow_add(1))?;
+        self.entries.get(offset.cast::<usize>())
+    }
+
+    /// The term of an entry, `None` if it's compacted or not in the log.
+    pub fn term(&self, index: u64) -> Option<u64> {
+        if index == self.snapshot.index {
+            return Some(self.snapshot.term);
+        }
+        self.entry(index).map(|entry| entry.term)
+    }
+
+    /// The entries in `[from, to]` after the snapshot.
+    pub fn entries(&self, from: u64, to: u64) -> Vec<Entry> {
+        let first = self.snapshot.index.overflow_add(1);
+        (from.max(first)..=to)
+            .map_while(|index| self.entry(index).cloned())
+            .collect()
+    }
+
+    /// The index of the latest config, in the log or in the snapshot.
+    pub fn members_index(&self) -> u64 {
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| matches!(entry.payload, EntryPayload::Members(_)))
+            .map_or(self.snapshot.index, |entry| entry.index)
+    }
+
+    /// The members of the latest config at `index`.
+    pub fn members_at(&self, index: u64) -> Members {
+        self.entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.index <= index)
+            .find_map(|entry| match entry.payload {
+                EntryPayload::Members(ref members) => Some(members.clone()),
+                EntryPayload::Noop | EntryPayload::Command(_) => None,
+            })
+            .unwrap_or_else(|| self.snapshot.members.clone())
+    }
+
+    /// The members of the latest config.
+    pub fn members(&self) -> Members {
+        self.members_at(self.last_index())
+    }
+
+    /// Append contiguous entries, replacing the entries from the first of
+    /// them. It's durable once this method returns.
+    pub fn append(&mut self, entries: Vec<Entry>) -> DatenLordResult<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let record = encode_record(&entries)?;
+        self.log
+            .write_all(&record)
+            .with_context(|| "failed to append to the raft log".to_owned())?;
+        self.log
+            .sync_data()
+            .with_context(|| "failed to sync the raft log".to_owned())?;
+        self.append_in_memory(entries);
+        Ok(())
+    }
+
+    /// Append entries to the memory, replacing the entries from the first of
+    /// them, the entries in the snapshot are skipped.
+    fn append_in_memory(&mut self, entries: Vec<Entry>) {
+        for entry in entries {
+            if entry.index <= self.snapshot.index {
+                continue;
+            }
+            let offset: usize = entry
+                .index
+                .overflow_sub(self.snapshot.index.overflow_add(1))
+                .cast();
+            self.entries.truncate(offset);
+            self.entries.push(entry);
+        }
+    }
+
+    /// Replace the log up to the index of `snapshot`. The entries after it are
+    /// kept if the log matches the snapshot, otherwise the whole log is
+    /// discarded.
+    ///
+    /// The snapshot is persisted before the log is rewritten, if it crashes in
+    /// between, the entries in the snapshot are skipped on replay.
+    pub fn install_snapshot(&mut self, snapshot: Snapshot) -> DatenLordResult<()> {
+        let kept = if self.term(snapshot.index) == Some(snapshot.term) {
+            self.entries(snapshot.index.overflow_add(1), self.last_index())
+        } else {
+            vec![]
+        };
+
+        let bytes = bincode::serialize(&snapshot)
+            .with_context(|| "failed to encode the raft snapshot".to_owned())?;
+        write_atomically(&self.dir, SNAPSHOT_FILE, &bytes)?;
+        let log = if kept.is_empty() {
+            vec![]
+        } else {
+            encode_record(&kept)?
+        };
+        write_atomically(&self.dir, LOG_FILE, &log)?;
+        self.log = open_log(&self.dir.join(LOG_FILE))?;
+
+        self.snapshot = snapshot;
+        self.entries = kept;
+        Ok(())
+    }
+}
@@ -0,0 +1,260 @@
+//! The gRPC transport between the members of the raft group.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tonic::transport::Channel;
+use tonic::{Code, Request, Response, Status};
+use tracing::{error, info};
+
+use self::proto::raft_client::RaftClient;
+use self::proto::raft_server::{Raft, RaftServer};
+use self::proto::{
+    ChangeMembersRequest, ChangeMembersResponse, KeepAliveRequest, KeepAliveResponse,
+    ProposeRequest, ProposeResponse, ReadIndexRequest, ReadIndexResponse, StepRequest,
+    StepResponse,
+};
+use super::core::{Message, RaftId};
+use super::node::RaftNode;
+use super::state_machine::{Command, CommandResult};
+use super::{is_no_leader, no_leader, raft_error};
+use crate::common::error::{Context, DatenLordError, DatenLordResult};
+use crate::telemetry;
+use crate::tls::{self, TlsChannel};
+
+/// The generated code of the raft service
+#[allow(
+    missing_docs,
+    unreachable_pub,
+    unused_qualifications,
+    trivial_casts,
+    clippy::all,
+    clippy::restriction,
+    clippy::pedantic,
+    clippy::nursery,
+    clippy::cargo
+)]
+mod proto {
+    tonic::include_proto!("datenlord.raft.v1");
+}
+
+/// The timeout of a request to another member.
+const RPC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Convert an error of the node into the status of the request, a member
+/// which is not the leader is unavailable, so the caller retries another
+/// one.
+fn error_to_status(error: &DatenLordError) -> Status {
+    if is_no_leader(error) {
+        Status::unavailable(error.to_string())
+    } else {
+        Status::internal(error.to_string())
+    }
+}
+
+/// Convert a failed request into an error, an unavailable member is treated
+/// as no leader, so the caller retries.
+fn status_to_error(status: &Status) -> DatenLordError {
+    if status.code() == Code::Unavailable {
+        no_leader()
+    } else {
+        raft_error(format!("{:?}: {}", status.code(), status.message()))
+    }
+}
+
+/// Decode a bincode message in a request.
+fn decode<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<T, Status> {
+    bincode::deserialize(bytes).map_err(|e| Status::invalid_argument(e.to_string()))
+}
+
+/// Encode a bincode message in a response.
+fn encode<T: serde::Serialize>(value: &T) -> Result<Vec<u8>, Status> {
+    bincode::serialize(value).map_err(|e| Status::internal(e.to_string()))
+}
+
+/// The service of a member.
+#[derive(Debug)]
+struct RaftService {
+    /// The member
+    node: Arc<RaftNode>,
+}
+
+#[tonic::async_trait]
+impl Raft for RaftService {
+    async fn step(&self, request: Request<StepRequest>) -> Result<Response<StepResponse>, Status> {
+        let message: Message = decode(&request.into_inner().message)?;
+        self.node.step(message).map_err(|e| error_to_status(&e))?;
+        Ok(Response::new(StepResponse {}))
+    }
+
+    async fn propose(
+        &self,
+        request: Request<ProposeRequest>,
+    ) -> Result<Response<ProposeResponse>, Status> {
+        let command: Command = decode(&request.into_inner().command)?;
+        let result = self
+            .node
+            .propose_local(&command)
+            .await
+            .map_err(|e| error_to_status(&e))?;
+        Ok(Response::new(ProposeResponse {
+            result: encode(&result)?,
+        }))
+    }
+
+    async fn read_index(
+        &self,
+        _request: Request<ReadIndexRequest>,
+    ) -> Result<Response<ReadIndexResponse>, Status> {
+        let index = self
+            .node
+            .read_index_local()
+            .map_err(|e| error_to_status(&e))?;
+        Ok(Response::new(ReadIndexResponse { index }))
+    }
+
+    async fn keep_alive(
+        &self,
+        request: Request<KeepAliveRequest>,
+    ) -> Result<Response<KeepAliveResponse>, Status> {
+        let found = self
+            .node
+            .keep_alive_local(request.into_inner().lease)
+            .map_err(|e| error_to_status(&e))?;
+        Ok(Response::new(KeepAliveResponse { found }))
+    }
+
+    async fn change_members(
+        &self,
+        request: Request<ChangeMembersRequest>,
+    ) -> Result<Response<ChangeMembersResponse>, Status> {
+        let ChangeMembersRequest {
+            id,
+            endpoint,
+            remove,
+        } = request.into_inner();
+        let endpoint = (!remove).then_some(endpoint);
+        self.node
+            .change_members_local(id, endpoint)
+            .await
+            .map_err(|e| error_to_status(&e))?;
+        Ok(Response::new(ChangeMembersResponse {}))
+    }
+}
+
+/// Serve the member on `listener` until `token` is cancelled, over the TLS of
+/// the cluster if it's set.
+pub async fn serve(listener: TcpListener, node: Arc<RaftNode>, token: CancellationToken) {
+    let addr = listener.local_addr().ok();
+    info!("Raft service listens on {addr:?}.");
+    let result = tls::serve(listener, tls::cluster_tls(), token, |server| {
+        server.add_service(RaftServer::new(RaftService {
+            node: Arc::clone(&node),
+        }))
+    })
+    .await;
+    if let Err(e) = result {
+        error!("Raft service failed: {e}");
+    }
+    info!("Raft service exits.");
+}
+
+/// The client of another member.
+#[derive(Debug, Clone)]
+pub struct RaftPeer {
+    /// The channel to the service
+    channel: Arc<TlsChannel>,
+}
+
+impl RaftPeer {
+    /// Create a client of the member serving at `endpoint`, such as
+    /// `http://10.0.0.2:7900`, over the TLS of the cluster if it's set. The
+    /// member is connected on the first request.
+    pub fn connect(endpoint: &str) -> DatenLordResult<Self> {
+        let channel = TlsChannel::new(endpoint, tls::cluster_tls())
+            .map_err(|e| raft_error(format!("invalid raft endpoint {endpoint}: {e}")))?;
+        Ok(Self {
+            channel: Arc::new(channel),
+        })
+    }
+
+    /// The client of the service.
+    fn client(&self) -> RaftClient<Channel> {
+        RaftClient::new(self.channel.get())
+    }
+
+    /// Create a request of `message` with the timeout and the trace of the
+    /// current span.
+    fn request<T>(message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        request.set_timeout(RPC_TIMEOUT);
+        telemetry::inject(&mut request);
+        request
+    }
+
+    /// Deliver a message.
+    pub async fn step(&self, message: &Message) -> DatenLordResult<()> {
+        let message = bincode::serialize(message)
+            .with_context(|| "failed to encode a raft message".to_owned())?;
+        self.client()
+            .step(Self::request(StepRequest { message }))
+            .await
+            .map_err(|status| status_to_error(&status))?;
+        Ok(())
+    }
+
+    /// Propose a command to the member, which should be the leader.
+    pub async fn propose(&self, command: &Command) -> DatenLordResult<CommandResult> {
+        let command = bincode::serialize(command)
+            .with_context(|| "failed to encode a raft command".to_owned())?;
+        let response = self
+            .client()
+            .propose(Self::request(ProposeRequest { command }))
+            .await
+            .map_err(|status| status_to_error(&status))?;
+        bincode::deserialize(&response.into_inner().result)
+            .with_context(|| "failed to decode the result of a raft command".to_owned())
+    }
+
+    /// Get the commit index of the member, which should be the leader.
+    pub async fn read_index(&self) -> DatenLordResult<u64> {
+        let response = self
+            .client()
+            .read_index(Self::request(ReadIndexRequest {}))
+            .await
+            .map_err(|status| status_to_error(&status))?;
+        Ok(response.into_inner().index)
+    }
+
+    /// Renew a lease on the member, which should be the leader. Returns
+    /// `false` if the lease doesn't exist.
+    pub async fn keep_alive(&self, lease: i64) -> DatenLordResult<bool> {
+        let response = self
+            .client()
+            .keep_alive(Self::request(KeepAliveRequest { lease }))
+            .await
+            .map_err(|status| status_to_error(&status))?;
+        Ok(response.into_inner().found)
+    }
+
+    /// Add the member `id` serving at `endpoint`, or remove it if `endpoint`
+    /// is `None`, by the member, which should be the leader.
+    pub async fn change_members(
+        &self,
+        id: RaftId,
+        endpoint: Option<String>,
+    ) -> DatenLordResult<()> {
+        let request = ChangeMembersRequest {
+            id,
+            remove: endpoint.is_none(),
+            endpoint: endpoint.unwrap_or_default(),
+        };
+        self.client()
+            .change_members(Self::request(request))
+            .await
+            .map_err(|status| status_to_error(&status))?;
+        Ok(())
+    }
+}
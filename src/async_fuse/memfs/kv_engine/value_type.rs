@@ -1,9 +1,32 @@
+use std::time::SystemTime;
+
 use serde::{Deserialize, Serialize};
 
+use crate::async_fuse::memfs::billing::{NodeIo, StorageTime};
+use crate::async_fuse::memfs::chunk_index::{ChunkIndex, VolumeInfo};
+use crate::async_fuse::memfs::cluster::NodeRegistration;
+use crate::async_fuse::memfs::coherence::{InodeLease, LeaseRecall};
 use crate::async_fuse::memfs::direntry::DirEntry;
+use crate::async_fuse::memfs::open_state::FileOpeners;
+use crate::async_fuse::memfs::posix_acl::PosixAcl;
+use crate::async_fuse::memfs::posix_lock::FileLocks;
+use crate::async_fuse::memfs::quota::Quota;
 use crate::async_fuse::memfs::s3_node::S3Node;
 use crate::async_fuse::memfs::serial::SerialNode;
+use crate::async_fuse::memfs::snapshot::{SnapshotInfo, SnapshotRecord};
+use crate::async_fuse::memfs::trash::TrashEntry;
+use crate::async_fuse::memfs::usage::VolumeUsage;
+use crate::async_fuse::memfs::volume::VolumeSpec;
 use crate::async_fuse::memfs::S3MetaData;
+use crate::storage::checksum::BlockChecksum;
+use crate::storage::dedup::BlockRecipe;
+use crate::storage::encryption::WrappedDataKey;
+use crate::storage::erasure::BlockShards;
+use crate::storage::locality::BlockHolders;
+use crate::storage::pack::PackedBlock;
+use crate::storage::replication::BlockReplicas;
+use crate::storage::snapshot::BlockVersions;
+use crate::storage::tiering::{BlockTier, Tier};
 
 /// The `ValueType` is used to provide support for metadata.
 ///
@@ -21,6 +44,68 @@ pub enum ValueType {
     Raw(Vec<u8>),
     /// String value
     String(String),
+    /// The chunk index of a file
+    ChunkIndex(ChunkIndex),
+    /// The information of the volume
+    VolumeInfo(VolumeInfo),
+    /// The recipe of a deduplicated block
+    BlockRecipe(BlockRecipe),
+    /// The refcount of a chunk
+    ChunkRefCount(u64),
+    /// The checksum of a block
+    BlockChecksum(BlockChecksum),
+    /// The wrapped data keys of a volume
+    DataKeys(Vec<WrappedDataKey>),
+    /// The versions of a block kept for snapshots
+    BlockVersions(BlockVersions),
+    /// The current snapshot epoch of a volume
+    SnapshotEpoch(u64),
+    /// The information of a snapshot
+    SnapshotInfo(SnapshotInfo),
+    /// A metadata record in a snapshot
+    SnapshotRecord(SnapshotRecord),
+    /// The quota of a directory subtree
+    Quota(Quota),
+    /// The usage of a volume charged by a node
+    VolumeUsage(VolumeUsage),
+    /// The IO checkpointed by a node
+    NodeIo(NodeIo),
+    /// The storage charged to a volume
+    StorageTime(StorageTime),
+    /// A removed file in the trash
+    TrashEntry(TrashEntry),
+    /// The tier of a block
+    BlockTier(BlockTier),
+    /// The tier a file or directory is pinned to
+    TierPin(Tier),
+    /// The mark of a scratch directory
+    Scratch,
+    /// The access or default ACL of a file or directory
+    PosixAcl(PosixAcl),
+    /// The SELinux label of a file or directory
+    SecurityLabel(Vec<u8>),
+    /// The registration of a node mounting the volume
+    NodeRegistration(NodeRegistration),
+    /// The nodes holding the lease of a file
+    InodeLease(InodeLease),
+    /// A request to release the lease of a file
+    LeaseRecall(LeaseRecall),
+    /// The POSIX locks of a file
+    FileLocks(FileLocks),
+    /// The nodes opening a file
+    FileOpeners(FileOpeners),
+    /// The nodes holding the replicas of a block
+    BlockReplicas(BlockReplicas),
+    /// The nodes holding the shards of a block
+    BlockShards(BlockShards),
+    /// The nodes holding a block in their caches
+    BlockHolders(BlockHolders),
+    /// The spec of a named volume
+    VolumeSpec(VolumeSpec),
+    /// The record of a packed block
+    PackedBlock(PackedBlock),
+    /// The time a scrub pass is requested
+    ScrubRequest(SystemTime),
 }
 
 impl ValueType {
@@ -37,6 +122,18 @@ impl ValueType {
         }
     }
 
+    /// Turn the `ValueType` into `SerialNode`.
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::Node`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_serial_node(self) -> SerialNode {
+        match self {
+            ValueType::Node(node) => node,
+            _ => panic!("expect ValueType::Node but get {self:?}"),
+        }
+    }
+
     /// Turn the `ValueType` into `NextIdAllocateRangeBegin`.
     /// # Panics
     /// Panics if `ValueType` is not `ValueType::NextIdAllocateRangeBegin`.
@@ -84,4 +181,364 @@ impl ValueType {
             _ => panic!("expect ValueType::DirEntry but get {self:?}"),
         }
     }
+
+    /// Turn the `ValueType` into `ChunkIndex`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::ChunkIndex`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_chunk_index(self) -> ChunkIndex {
+        match self {
+            ValueType::ChunkIndex(index) => index,
+            _ => panic!("expect ValueType::ChunkIndex but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `VolumeInfo`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::VolumeInfo`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_volume_info(self) -> VolumeInfo {
+        match self {
+            ValueType::VolumeInfo(info) => info,
+            _ => panic!("expect ValueType::VolumeInfo but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `BlockRecipe`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::BlockRecipe`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_block_recipe(self) -> BlockRecipe {
+        match self {
+            ValueType::BlockRecipe(recipe) => recipe,
+            _ => panic!("expect ValueType::BlockRecipe but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into the refcount of a chunk
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::ChunkRefCount`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_chunk_ref_count(self) -> u64 {
+        match self {
+            ValueType::ChunkRefCount(refcount) => refcount,
+            _ => panic!("expect ValueType::ChunkRefCount but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `BlockChecksum`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::BlockChecksum`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_block_checksum(self) -> BlockChecksum {
+        match self {
+            ValueType::BlockChecksum(checksum) => checksum,
+            _ => panic!("expect ValueType::BlockChecksum but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into wrapped data keys
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::DataKeys`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_data_keys(self) -> Vec<WrappedDataKey> {
+        match self {
+            ValueType::DataKeys(keys) => keys,
+            _ => panic!("expect ValueType::DataKeys but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `BlockVersions`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::BlockVersions`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_block_versions(self) -> BlockVersions {
+        match self {
+            ValueType::BlockVersions(versions) => versions,
+            _ => panic!("expect ValueType::BlockVersions but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into the snapshot epoch
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::SnapshotEpoch`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_snapshot_epoch(self) -> u64 {
+        match self {
+            ValueType::SnapshotEpoch(epoch) => epoch,
+            _ => panic!("expect ValueType::SnapshotEpoch but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `SnapshotInfo`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::SnapshotInfo`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_snapshot_info(self) -> SnapshotInfo {
+        match self {
+            ValueType::SnapshotInfo(info) => info,
+            _ => panic!("expect ValueType::SnapshotInfo but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `SnapshotRecord`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::SnapshotRecord`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_snapshot_record(self) -> SnapshotRecord {
+        match self {
+            ValueType::SnapshotRecord(record) => record,
+            _ => panic!("expect ValueType::SnapshotRecord but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `Quota`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::Quota`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_quota(self) -> Quota {
+        match self {
+            ValueType::Quota(quota) => quota,
+            _ => panic!("expect ValueType::Quota but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `VolumeUsage`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::VolumeUsage`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_volume_usage(self) -> VolumeUsage {
+        match self {
+            ValueType::VolumeUsage(usage) => usage,
+            _ => panic!("expect ValueType::VolumeUsage but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `NodeIo`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::NodeIo`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_node_io(self) -> NodeIo {
+        match self {
+            ValueType::NodeIo(io) => io,
+            _ => panic!("expect ValueType::NodeIo but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `StorageTime`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::StorageTime`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_storage_time(self) -> StorageTime {
+        match self {
+            ValueType::StorageTime(storage) => storage,
+            _ => panic!("expect ValueType::StorageTime but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `TrashEntry`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::TrashEntry`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_trash_entry(self) -> TrashEntry {
+        match self {
+            ValueType::TrashEntry(entry) => entry,
+            _ => panic!("expect ValueType::TrashEntry but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `BlockTier`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::BlockTier`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_block_tier(self) -> BlockTier {
+        match self {
+            ValueType::BlockTier(record) => record,
+            _ => panic!("expect ValueType::BlockTier but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `Tier`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::TierPin`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_tier_pin(self) -> Tier {
+        match self {
+            ValueType::TierPin(tier) => tier,
+            _ => panic!("expect ValueType::TierPin but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `PosixAcl`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::PosixAcl`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_posix_acl(self) -> PosixAcl {
+        match self {
+            ValueType::PosixAcl(acl) => acl,
+            _ => panic!("expect ValueType::PosixAcl but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into the SELinux label
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::SecurityLabel`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_security_label(self) -> Vec<u8> {
+        match self {
+            ValueType::SecurityLabel(label) => label,
+            _ => panic!("expect ValueType::SecurityLabel but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `NodeRegistration`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::NodeRegistration`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_node_registration(self) -> NodeRegistration {
+        match self {
+            ValueType::NodeRegistration(registration) => registration,
+            _ => panic!("expect ValueType::NodeRegistration but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `InodeLease`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::InodeLease`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_inode_lease(self) -> InodeLease {
+        match self {
+            ValueType::InodeLease(lease) => lease,
+            _ => panic!("expect ValueType::InodeLease but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `LeaseRecall`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::LeaseRecall`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_lease_recall(self) -> LeaseRecall {
+        match self {
+            ValueType::LeaseRecall(recall) => recall,
+            _ => panic!("expect ValueType::LeaseRecall but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `FileLocks`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::FileLocks`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_file_locks(self) -> FileLocks {
+        match self {
+            ValueType::FileLocks(locks) => locks,
+            _ => panic!("expect ValueType::FileLocks but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `FileOpeners`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::FileOpeners`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_file_openers(self) -> FileOpeners {
+        match self {
+            ValueType::FileOpeners(openers) => openers,
+            _ => panic!("expect ValueType::FileOpeners but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `BlockReplicas`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::BlockReplicas`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_block_replicas(self) -> BlockReplicas {
+        match self {
+            ValueType::BlockReplicas(record) => record,
+            _ => panic!("expect ValueType::BlockReplicas but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `BlockShards`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::BlockShards`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_block_shards(self) -> BlockShards {
+        match self {
+            ValueType::BlockShards(record) => record,
+            _ => panic!("expect ValueType::BlockShards but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `BlockHolders`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::BlockHolders`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_block_holders(self) -> BlockHolders {
+        match self {
+            ValueType::BlockHolders(record) => record,
+            _ => panic!("expect ValueType::BlockHolders but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `VolumeSpec`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::VolumeSpec`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_volume_spec(self) -> VolumeSpec {
+        match self {
+            ValueType::VolumeSpec(spec) => spec,
+            _ => panic!("expect ValueType::VolumeSpec but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into `PackedBlock`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::PackedBlock`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_packed_block(self) -> PackedBlock {
+        match self {
+            ValueType::PackedBlock(record) => record,
+            _ => panic!("expect ValueType::PackedBlock but get {self:?}"),
+        }
+    }
+
+    /// Turn the `ValueType` into the time of a `ScrubRequest`
+    /// # Panics
+    /// Panics if `ValueType` is not `ValueType::ScrubRequest`.
+    #[allow(clippy::wildcard_enum_match_arm)] // Allow wildcard because there should be only one enum branch matches one specific type.
+    #[must_use]
+    pub fn into_scrub_request(self) -> SystemTime {
+        match self {
+            ValueType::ScrubRequest(requested) => requested,
+            _ => panic!("expect ValueType::ScrubRequest but get {self:?}"),
+        }
+    }
 }
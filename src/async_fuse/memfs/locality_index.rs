@@ -0,0 +1,128 @@
+//! The `LocalityIndex` persisted in the kv engine.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use super::cluster::list_nodes;
+use super::kv_engine::{KVEngine, KVEngineType, KeyType, MetaTxn, ValueType};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::common::error::DatenLordResult;
+use crate::storage::error::{StorageError, StorageResult};
+use crate::storage::locality::{BlockHolders, LocalityIndex, Peer};
+
+/// The limit of retrying a transaction.
+const TXN_RETRY_LIMIT: u32 = 10;
+
+/// A `LocalityIndex` persisted in the kv engine, the holders of blocks are
+/// shared among all nodes of the volume, and the nodes serving their caches
+/// are the ones registered with a cache endpoint.
+#[derive(Debug)]
+pub struct KvLocalityIndex {
+    /// The kv engine
+    kv_engine: Arc<KVEngineType>,
+}
+
+impl KvLocalityIndex {
+    /// Create a `KvLocalityIndex`.
+    #[must_use]
+    pub fn new(kv_engine: Arc<KVEngineType>) -> Self {
+        Self { kv_engine }
+    }
+
+    /// Record a holder of a block. The holders are hints, so a holder
+    /// recorded by another node meanwhile may be lost.
+    async fn add_holder_impl(
+        &self,
+        ino: INum,
+        block_id: usize,
+        node_id: &str,
+    ) -> DatenLordResult<()> {
+        let key = KeyType::BlockHolders(ino, block_id);
+        let mut record = self.kv_engine.get(&key).await?.map_or_else(
+            || BlockHolders {
+                ino,
+                block_id,
+                nodes: vec![],
+            },
+            ValueType::into_block_holders,
+        );
+        if record.add(node_id) {
+            self.kv_engine
+                .set(&key, &ValueType::BlockHolders(record), None)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Remove the holders of blocks in a transaction.
+    async fn remove_holders_impl(&self, ino: INum, block_ids: Range<usize>) -> DatenLordResult<()> {
+        if block_ids.len() == 1 {
+            self.kv_engine
+                .delete(&KeyType::BlockHolders(ino, block_ids.start), None)
+                .await?;
+            return Ok(());
+        }
+
+        let block_ids: Vec<usize> = self
+            .kv_engine
+            .range(&KeyType::FileBlockHolders(ino))
+            .await?
+            .into_iter()
+            .map(|value| value.into_block_holders().block_id)
+            .filter(|block_id| block_ids.contains(block_id))
+            .collect();
+        if block_ids.is_empty() {
+            return Ok(());
+        }
+
+        let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            for &block_id in &block_ids {
+                txn.delete(&KeyType::BlockHolders(ino, block_id));
+            }
+            (txn.commit().await, ())
+        });
+        res
+    }
+}
+
+#[async_trait]
+impl LocalityIndex for KvLocalityIndex {
+    async fn get_holders(&self, ino: INum, block_id: usize) -> StorageResult<Vec<String>> {
+        let value = self
+            .kv_engine
+            .get(&KeyType::BlockHolders(ino, block_id))
+            .await;
+        value
+            .map_err(StorageError::from)
+            .map(|value| value.map_or_else(Vec::new, |value| value.into_block_holders().nodes))
+    }
+
+    async fn add_holder(&self, ino: INum, block_id: usize, node_id: &str) -> StorageResult<()> {
+        self.add_holder_impl(ino, block_id, node_id)
+            .await
+            .map_err(StorageError::from)
+    }
+
+    async fn remove_holders(&self, ino: INum, block_ids: Range<usize>) -> StorageResult<()> {
+        self.remove_holders_impl(ino, block_ids)
+            .await
+            .map_err(StorageError::from)
+    }
+
+    async fn list_peers(&self) -> StorageResult<Vec<Peer>> {
+        let nodes = list_nodes(&self.kv_engine).await?;
+        Ok(nodes
+            .into_iter()
+            .filter_map(|node| {
+                node.cache_endpoint.map(|endpoint| Peer {
+                    node_id: node.node_id,
+                    endpoint,
+                    zone: node.zone,
+                })
+            })
+            .collect())
+    }
+}
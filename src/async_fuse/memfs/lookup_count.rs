@@ -0,0 +1,188 @@
+//! The lookup counts of the nodes referenced by the kernel.
+//!
+//! Every entry replied to the kernel, by a lookup or a creation, is a
+//! reference of the node until the kernel forgets it, and a node removed
+//! while it's referenced, such as a file unlinked while it's opened, is kept
+//! until its last reference is forgotten. The counts of the kernel are kept
+//! in memory, while the kv engine only counts the nodes of the volume
+//! referencing a node, so a lookup only updates the kv engine when it takes
+//! the first reference of this node, and a forget when it drops the last one.
+//!
+//! The count of a node is locked while its reference in the kv engine is
+//! taken or dropped, so a lookup racing with the first one is counted only
+//! after the reference is taken, and not at all if it fails.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+
+use clippy_utilities::OverflowArithmetic;
+use parking_lot::Mutex;
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+use crate::async_fuse::fuse::protocol::INum;
+use crate::common::error::DatenLordResult;
+
+/// The lookup counts of the nodes referenced by the kernel.
+#[derive(Debug, Default)]
+pub(super) struct LookupCounts {
+    /// The counts of the referenced nodes, a node is removed once its count
+    /// reaches zero
+    counts: Mutex<HashMap<INum, Arc<AsyncMutex<u64>>>>,
+}
+
+impl LookupCounts {
+    /// Create an empty `LookupCounts`.
+    pub(super) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lock the count of `ino`, which is zero if the node is not referenced.
+    async fn lock(&self, ino: INum) -> OwnedMutexGuard<u64> {
+        loop {
+            let count = Arc::clone(self.counts.lock().entry(ino).or_default());
+            let guard = Arc::clone(&count).lock_owned().await;
+            // The count is removed once it reaches zero while it's waited, the
+            // node is counted again by a new one.
+            if self
+                .counts
+                .lock()
+                .get(&ino)
+                .is_some_and(|cur| Arc::ptr_eq(cur, &count))
+            {
+                return guard;
+            }
+        }
+    }
+
+    /// Remove the count of `ino` locked by `count` if it reaches zero.
+    fn remove_unreferenced(&self, ino: INum, count: &OwnedMutexGuard<u64>) {
+        if **count == 0 {
+            self.counts.lock().remove(&ino);
+        }
+    }
+
+    /// Count a reference of `ino` replied to the kernel, `reference` takes the
+    /// reference of the node in the kv engine if it's the first one.
+    ///
+    /// The reference is not counted if `reference` fails.
+    pub(super) async fn inc(
+        &self,
+        ino: INum,
+        reference: impl Future<Output = DatenLordResult<()>>,
+    ) -> DatenLordResult<()> {
+        let mut count = self.lock(ino).await;
+        let res = if *count == 0 { reference.await } else { Ok(()) };
+        if res.is_ok() {
+            *count = count.overflow_add(1);
+        }
+        self.remove_unreferenced(ino, &count);
+        res
+    }
+
+    /// Count a reference of `ino` replied to the kernel, which is already
+    /// referenced in the kv engine, such as a node just created.
+    pub(super) async fn inc_referenced(&self, ino: INum) {
+        let mut count = self.lock(ino).await;
+        *count = count.overflow_add(1);
+    }
+
+    /// Drop `nlookup` references of `ino` forgotten by the kernel, `forget`
+    /// drops the reference of the node in the kv engine if the last one is
+    /// dropped.
+    pub(super) async fn dec(
+        &self,
+        ino: INum,
+        nlookup: u64,
+        forget: impl Future<Output = DatenLordResult<()>>,
+    ) -> DatenLordResult<()> {
+        let mut count = self.lock(ino).await;
+        let res = if *count > 0 && *count <= nlookup {
+            forget.await
+        } else {
+            Ok(())
+        };
+        *count = count.saturating_sub(nlookup);
+        self.remove_unreferenced(ino, &count);
+        res
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use nix::errno::Errno;
+
+    use super::LookupCounts;
+    use crate::async_fuse::util::build_error_result_from_errno;
+    use crate::common::error::DatenLordResult;
+
+    /// Take or drop a reference in the kv engine, counted by `calls`, it fails
+    /// if `fail` is true.
+    async fn update_kv(calls: &AtomicU64, fail: bool) -> DatenLordResult<()> {
+        // Let the racing lookups run in the meantime.
+        tokio::task::yield_now().await;
+        calls.fetch_add(1, Ordering::SeqCst);
+        if fail {
+            build_error_result_from_errno(Errno::EIO, "failed to update kv".to_owned())
+        } else {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lookup_counts() {
+        let counts = LookupCounts::new();
+        let references = AtomicU64::new(0);
+        let forgets = AtomicU64::new(0);
+
+        counts.inc(2, update_kv(&references, false)).await.unwrap();
+        counts.inc(2, update_kv(&references, false)).await.unwrap();
+        counts.inc(3, update_kv(&references, false)).await.unwrap();
+        assert_eq!(references.load(Ordering::SeqCst), 2);
+
+        counts.dec(2, 1, update_kv(&forgets, false)).await.unwrap();
+        assert_eq!(forgets.load(Ordering::SeqCst), 0);
+        counts.dec(2, 1, update_kv(&forgets, false)).await.unwrap();
+        assert_eq!(forgets.load(Ordering::SeqCst), 1);
+        // A node not referenced is never dropped again.
+        counts.dec(2, 1, update_kv(&forgets, false)).await.unwrap();
+        assert_eq!(forgets.load(Ordering::SeqCst), 1);
+        // The kernel may forget more than it's known, when the node was
+        // looked up before this node restarts.
+        counts.dec(3, 2, update_kv(&forgets, false)).await.unwrap();
+        assert_eq!(forgets.load(Ordering::SeqCst), 2);
+
+        // A node is referenced again after it's forgotten.
+        counts.inc(2, update_kv(&references, false)).await.unwrap();
+        assert_eq!(references.load(Ordering::SeqCst), 3);
+
+        // A created node is already referenced.
+        counts.inc_referenced(4).await;
+        counts.inc(4, update_kv(&references, false)).await.unwrap();
+        assert_eq!(references.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_failed_first_reference() {
+        let counts = LookupCounts::new();
+        let references = AtomicU64::new(0);
+        let forgets = AtomicU64::new(0);
+
+        // A lookup racing with a failed first reference is not counted on it,
+        // it takes the reference itself.
+        let (failed, racing) = tokio::join!(
+            counts.inc(2, update_kv(&references, true)),
+            counts.inc(2, update_kv(&references, false)),
+        );
+        failed.unwrap_err();
+        racing.unwrap();
+        assert_eq!(references.load(Ordering::SeqCst), 2);
+
+        // The reference taken by the racing lookup is the only one.
+        counts.dec(2, 1, update_kv(&forgets, false)).await.unwrap();
+        assert_eq!(forgets.load(Ordering::SeqCst), 1);
+    }
+}
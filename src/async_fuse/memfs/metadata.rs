@@ -1,15 +1,22 @@
+use std::ops::Range;
 use std::os::unix::io::RawFd;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
 use async_trait::async_trait;
+use datenlord::common::memory_budget::MemoryConsumer;
 
+use super::direntry::DirEntry;
+use super::fs_util::FileAttr;
 use super::kv_engine::KVEngineType;
 use super::node::Node;
+use super::posix_acl::{AclKind, PosixAcl};
+use super::quota::{Quota, QuotaAttr};
 use super::{CreateParam, RenameParam, SetAttrParam, StorageType};
 use crate::async_fuse::fuse::fuse_reply::{ReplyDirectory, StatFsParam};
 use crate::async_fuse::fuse::protocol::{FuseAttr, INum};
 use crate::common::error::DatenLordResult;
+use crate::storage::tiering::Tier;
 
 pub(crate) mod error {
     //! A module containing helper functions to build errors.
@@ -43,15 +50,82 @@ pub struct ReqContext {
     pub gid: u32,
 }
 
+/// An operation of a metadata transaction, see `MetaData::transact()`.
+#[derive(Debug)]
+pub enum MetaOp {
+    /// Create a node like `mknod()`
+    Create(CreateParam),
+    /// Set the attributes of a node other than the size, which is of the node
+    /// created by the last `Create` before if `ino` is `None`
+    SetAttr {
+        /// The i-number of the node
+        ino: Option<INum>,
+        /// The attributes to set
+        param: SetAttrParam,
+    },
+    /// Rename an entry like `rename()`
+    Rename(RenameParam),
+    /// Set an extended attribute of a node like `setxattr()`, which is of the
+    /// node created by the last `Create` before if `ino` is `None`. Only the
+    /// POSIX ACLs, whose ids are the ones of the volume, and the SELinux
+    /// label are set this way.
+    SetXattr {
+        /// The i-number of the node
+        ino: Option<INum>,
+        /// The name of the extended attribute
+        name: String,
+        /// The value of the extended attribute
+        value: Vec<u8>,
+    },
+}
+
+/// The result of an operation of a metadata transaction.
+#[derive(Debug)]
+pub enum MetaOpResult {
+    /// The attributes of the node created
+    Created(FuseAttr),
+    /// The attributes of the node set
+    AttrSet(FuseAttr),
+    /// The i-number of the file replaced and removed, if any
+    Renamed(Option<INum>),
+    /// The i-number of the node whose extended attribute is set
+    XattrSet(INum),
+}
+
+/// The options of `MetaData`, each of which is disabled if it's `None`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MetaDataOptions {
+    /// The size of the volume in bytes, which is unlimited if it's `None`
+    pub capacity: Option<u64>,
+    /// How long removed files are kept in the trash
+    pub trash_retention: Option<Duration>,
+    /// The max size of the files whose content is inlined in their nodes
+    pub inline_threshold: Option<u64>,
+    /// The max number of the children prefetched when a directory is listed
+    pub stat_ahead_limit: Option<usize>,
+    /// How long the names missing in directories are cached
+    pub negative_cache_ttl: Option<Duration>,
+    /// The max bytes of the cached directory entries
+    pub dentry_cache_capacity: Option<usize>,
+}
+
 /// MetaData of fs
+///
+/// The metadata cached in memory is accounted by the memory budget of the
+/// daemon, as a `MemoryConsumer`.
 #[async_trait]
-pub trait MetaData {
+pub trait MetaData: MemoryConsumer {
     /// Node type
     type N: Node + Send + Sync + 'static;
 
-    /// Create `MetaData`
-    #[allow(clippy::too_many_arguments)]
-    async fn new(kv_engine: Arc<KVEngineType>, node_id: &str) -> DatenLordResult<Arc<Self>>;
+    /// Create `MetaData`, `chunk_size` is the size of chunks of files in the
+    /// volume, the rest is set by `options`.
+    async fn new(
+        kv_engine: Arc<KVEngineType>,
+        node_id: &str,
+        chunk_size: u64,
+        options: MetaDataOptions,
+    ) -> DatenLordResult<Arc<Self>>;
 
     /// Helper function to create node
     async fn mknod(&self, param: CreateParam) -> DatenLordResult<(Duration, FuseAttr, u64)>;
@@ -65,22 +139,166 @@ pub trait MetaData {
     ) -> DatenLordResult<(Duration, FuseAttr, u64)>;
 
     /// Rename helper to exchange on disk
-    async fn rename(&self, context: ReqContext, param: RenameParam) -> DatenLordResult<()>;
+    /// # Return
+    /// Return the ino of the file replaced and removed
+    async fn rename(
+        &self,
+        context: ReqContext,
+        param: RenameParam,
+    ) -> DatenLordResult<Option<INum>>;
+
+    /// Run `ops` one after another in one transaction, so they're committed
+    /// together, and no other node sees the states in between. Returns the
+    /// results of the operations in order.
+    async fn transact(
+        &self,
+        context: ReqContext,
+        ops: &[MetaOp],
+    ) -> DatenLordResult<Vec<MetaOpResult>>;
 
-    /// Helper function to write data
+    /// Helper function to write data, `offset` and `len` are the range of the
+    /// written data, which are recorded in the chunk index of the file.
+    /// `data` is the written data, which updates the content inlined in the
+    /// node, or `None` if it's written by the storage only.
     async fn write_helper(
         &self,
         ino: u64,
         new_mtime: SystemTime,
         new_size: u64,
+        offset: u64,
+        len: u64,
+        data: Option<&[u8]>,
+    ) -> DatenLordResult<()>;
+
+    /// Helper function to fallocate, the file is resized to `new_size`, and
+    /// the byte range `punched` is deallocated from the chunk index of the
+    /// file, if any.
+    async fn fallocate_helper(
+        &self,
+        ino: u64,
+        new_mtime: SystemTime,
+        new_size: u64,
+        punched: Option<Range<u64>>,
+    ) -> DatenLordResult<()>;
+
+    /// Check whether writing `len` bytes at `offset` of file `ino` is allowed
+    /// by the quotas covering the file, before the data is stored.
+    async fn check_quota(&self, ino: u64, offset: u64, len: u64) -> DatenLordResult<()>;
+
+    /// Get the quota of directory `ino`, if any.
+    async fn get_quota(&self, ino: u64) -> DatenLordResult<Option<Quota>>;
+
+    /// Set a limit of the quota of directory `ino`. The quota is created with
+    /// the usage of the subtree if it does not exist, and is removed once it's
+    /// unlimited.
+    async fn set_quota(
+        &self,
+        context: ReqContext,
+        ino: u64,
+        attr: QuotaAttr,
+        limit: u64,
+    ) -> DatenLordResult<()>;
+
+    /// Get the tier file `ino` is pinned to by its extended attribute, if any.
+    async fn get_tier_pin(&self, ino: u64) -> DatenLordResult<Option<Tier>>;
+
+    /// Pin file `ino` and its descendants to `tier`, or unpin it if it's
+    /// `None`. Only the owner of the file or the super user pins it.
+    async fn set_tier_pin(
+        &self,
+        context: ReqContext,
+        ino: u64,
+        tier: Option<Tier>,
     ) -> DatenLordResult<()>;
 
+    /// Returns whether directory `ino` is a scratch directory.
+    async fn is_scratch(&self, ino: u64) -> DatenLordResult<bool>;
+
+    /// Mark directory `ino` as a scratch directory, whose removed descendants
+    /// skip the trash, or unmark it. Only the owner of the directory or the
+    /// super user marks it.
+    async fn set_scratch(
+        &self,
+        context: ReqContext,
+        ino: u64,
+        scratch: bool,
+    ) -> DatenLordResult<()>;
+
+    /// Get the ACL of `kind` of `ino`, if any.
+    async fn get_posix_acl(&self, ino: u64, kind: AclKind) -> DatenLordResult<Option<PosixAcl>>;
+
+    /// Set the ACL of `kind` of `ino`, or remove it if it's `None`. Setting
+    /// the access ACL sets the permission bits of the mode as well, and only
+    /// a directory has the default ACL. Only the owner of the file or the
+    /// super user sets it.
+    async fn set_posix_acl(
+        &self,
+        context: ReqContext,
+        ino: u64,
+        kind: AclKind,
+        acl: Option<PosixAcl>,
+    ) -> DatenLordResult<()>;
+
+    /// Get the SELinux label of `ino`, if any.
+    async fn get_security_label(&self, ino: u64) -> DatenLordResult<Option<Vec<u8>>>;
+
+    /// Set the SELinux label of `ino`, or remove it if it's `None`. The
+    /// relabeling is checked by the kernel, only the owner of the file or the
+    /// super user sets it here besides.
+    async fn set_security_label(
+        &self,
+        context: ReqContext,
+        ino: u64,
+        label: Option<Vec<u8>>,
+    ) -> DatenLordResult<()>;
+
+    /// Get the i-number of the parent directory of `ino`.
+    async fn get_parent_ino(&self, ino: u64) -> DatenLordResult<INum>;
+
+    /// Helper function to lseek, returns the offset of the next data
+    /// (`SEEK_DATA`) or hole (`SEEK_HOLE`) at or after `offset`.
+    async fn lseek_helper(&self, ino: u64, offset: u64, whence: u32) -> DatenLordResult<u64>;
+
     /// Helper function to get a open file's size and mtime
     /// # Return
     /// Return a tuple of (file_size, modified_time)
     /// It will not change the file's atime
     fn mtime_and_size(&self, ino: u64) -> (u64, SystemTime);
 
+    /// The content of an open file inlined in its node when it's opened,
+    /// `None` if it's not inlined or it's changed since then.
+    fn inline_data(&self, ino: u64) -> Option<Arc<[u8]>>;
+
+    /// Refresh the cached attributes of an open file with `attr` changed by
+    /// another node, if they're newer. It does nothing if the file is not open.
+    fn refresh_open_file(&self, attr: FileAttr);
+
+    /// Revalidate the cached attributes of file `ino` with the ones in the kv
+    /// engine when it's opened under the close-to-open consistency. Returns
+    /// whether the data cached for it is still valid, which is false if the
+    /// file is not open.
+    async fn revalidate_open_file(&self, ino: INum) -> DatenLordResult<bool>;
+
+    /// Drop the cached entries of directory `ino` once it's changed, by this
+    /// node or another one.
+    fn invalidate_dir(&self, ino: INum);
+
+    /// Keep the file of `attr` cached while this node holds a delegation of
+    /// it, so its opens and attribute queries are served without the kv
+    /// engine. The cached attributes are refreshed with `attr` if it's newer.
+    fn delegate_open_file(&self, attr: FileAttr);
+
+    /// Stop keeping the file `ino` cached for its delegation, which is
+    /// recalled. It's still cached while it's open.
+    fn undelegate_open_file(&self, ino: INum);
+
+    /// Drop the clean metadata cached in memory under memory pressure, at
+    /// most `max` directory entries of them.
+    ///
+    /// Returns the directories and the names of the dropped entries, which
+    /// the kernel is asked to drop as well.
+    fn shrink_caches(&self, max: usize) -> Vec<(INum, String)>;
+
     /// Set fuse fd into `MetaData`
     async fn set_fuse_fd(&self, fuse_fd: RawFd);
 
@@ -103,13 +321,24 @@ pub trait MetaData {
         name: &str,
     ) -> DatenLordResult<Option<INum>>;
 
+    /// Remove the files which have been in the trash for `retention`
+    /// # Return
+    /// Return the inos of the removed regular files, whose blocks are to be
+    /// removed
+    async fn purge_trash(&self, retention: Duration) -> DatenLordResult<Vec<INum>>;
+
     /// Get attribute of i-node by ino
     async fn getattr(&self, ino: u64) -> DatenLordResult<(Duration, FuseAttr)>;
 
     /// Open a file or directory by ino and flags
     async fn open(&self, context: ReqContext, ino: u64, flags: u32) -> DatenLordResult<RawFd>;
 
-    /// Forget a i-node by ino
+    /// Take a reference of `ino`, once it's referenced by the kernel of this
+    /// node.
+    async fn reference(&self, ino: u64) -> DatenLordResult<()>;
+
+    /// Forget a i-node by ino, a removed i-node is deleted by its last
+    /// reference
     /// # Return
     /// Return true if the file is removed
     /// Return false if the file is not removed
@@ -142,7 +371,12 @@ pub trait MetaData {
         reply: &mut ReplyDirectory,
     ) -> DatenLordResult<()>;
 
-    /// Helper function to release
+    /// List the entries of directory `ino`
+    async fn list_dir(&self, context: ReqContext, ino: u64) -> DatenLordResult<Vec<DirEntry>>;
+
+    /// Helper function to release, a removed file is deleted by the last
+    /// release among the nodes if it's no longer referenced. Return true if
+    /// the file is deleted.
     async fn release(
         &self,
         ino: u64,
@@ -150,5 +384,5 @@ pub trait MetaData {
         flags: u32,
         lock_owner: u64,
         flush: bool,
-    ) -> DatenLordResult<()>;
+    ) -> DatenLordResult<bool>;
 }
@@ -1,54 +1,175 @@
 //! The implementation of user space file system
+/// The authentication and authorization of tenants
+pub mod auth;
+/// The chunk index of files
+pub mod chunk_index;
 mod fs_util;
 pub mod id_alloc;
 mod id_alloc_used;
+/// The content of small files inlined in their nodes
+mod inline_data;
+/// Per-inode locks
+mod inode_lock;
+/// The lookup counts of the nodes referenced by the kernel
+mod lookup_count;
 /// The KV engine module
 #[macro_use]
 pub mod kv_engine;
+/// The usage of volumes persisted for billing
+pub mod billing;
+/// The shrinking of the metadata caches under memory pressure
+mod cache_shrink;
+/// The versions of files in the kv engine, for the disk cache
+mod cache_versions;
+/// The checksum index persisted in the kv engine
+mod checksum_index;
+/// The nodes sharing the namespace of the volume
+mod cluster;
+/// The cache coherence of files among the nodes
+pub mod coherence;
+/// The dedup index persisted in the kv engine
+mod dedup_index;
+/// The cache of directory entries
+mod dentry_cache;
 /// Dir entry module
 pub mod direntry;
+/// The drain of the node before it's shut down
+pub mod drain;
+/// The handles of opened files
+mod file_handle;
+/// The offline check of the metadata
+pub mod fsck;
+/// The GC index upon the metadata in the kv engine
+mod gc_index;
+/// The state of the mount inspected by the operators
+pub mod inspect;
+/// The locality index persisted in the kv engine
+mod locality_index;
 /// fs metadata module
 mod metadata;
+/// The cache of the names missing in directories
+mod negative_cache;
 mod node;
 /// Opened files
 mod open_file;
+/// The opens of files among the nodes
+mod open_state;
+/// The pack index persisted in the kv engine
+mod pack_index;
+/// The pins of files in the local cache
+pub mod pin;
+/// The POSIX ACLs of files
+mod posix_acl;
+/// The POSIX locks of files among the nodes
+mod posix_lock;
+/// Directory quotas
+pub mod quota;
+/// The volumes published read-only on this node
+pub mod read_only;
+/// The replica index persisted in the kv engine
+mod replica_index;
 /// fs metadata with S3 backend module
 mod s3_metadata;
 mod s3_node;
+/// The SELinux labels of files
+mod security_label;
+/// The shard index persisted in the kv engine
+mod shard_index;
+/// Snapshots of the volume
+pub mod snapshot;
+/// The snapshot index persisted in the kv engine
+mod snapshot_index;
+/// The stat-ahead of listed directories
+mod stat_ahead;
+/// The tier index persisted in the kv engine
+mod tier_index;
+/// The trash of removed files
+pub mod trash;
+/// The usage accounting of volumes
+pub mod usage;
+/// The file system operations independent of the transports
+mod vfs;
+/// Named volumes of the volume manager
+pub mod volume;
+/// The data keys of encrypted volumes
+mod volume_keys;
+/// The warm-up of the caches with datasets
+pub mod warmup;
 
 /// Serializable types module
 pub mod serial;
+use std::collections::HashMap;
+use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::SystemTime;
 
 use async_trait::async_trait;
+pub use cache_versions::KvFileVersions;
+pub use checksum_index::{request_scrub, KvChecksumIndex};
 use clippy_utilities::{Cast, OverflowArithmetic};
-use datenlord::config::StorageConfig;
+pub use cluster::{list_nodes, KvMembership, NodeRegistration, HEALTH_CHECK_INTERVAL};
+use datenlord::common::memory_budget::MemoryBudget;
+use datenlord::common::task_manager::{TaskName, TASK_MANAGER};
+use datenlord::config::{IdMap, OpMask, Squash, StorageConfig};
 use datenlord::metrics::FILESYSTEM_METRICS;
-pub use metadata::MetaData;
+pub use dedup_index::KvDedupIndex;
+pub use gc_index::KvGcIndex;
+use libc::{FALLOC_FL_KEEP_SIZE, FALLOC_FL_PUNCH_HOLE};
+pub use locality_index::KvLocalityIndex;
+pub use metadata::{MetaData, MetaDataOptions, MetaOp, MetaOpResult, ReqContext};
 use nix::errno::Errno;
 use nix::sys::stat::SFlag;
-pub use s3_metadata::S3MetaData;
+pub use pack_index::KvPackIndex;
+use parking_lot::Mutex;
+pub use replica_index::KvReplicaIndex;
+pub use s3_metadata::{load_or_init_volume_info, S3MetaData};
 use serde::{Deserialize, Serialize};
+pub use shard_index::KvShardIndex;
+pub use snapshot_index::KvSnapshotIndex;
+pub use tier_index::{KvTierIndex, TIER_XATTR};
 use tracing::{debug, error, info, instrument, warn};
-
+pub use trash::SCRATCH_XATTR;
+pub use vfs::ROOT_CONTEXT;
+pub use volume_keys::load_data_keys;
+
+use self::auth::{Authorizer, Need, TenantRules};
+use self::coherence::{Coherence, LeaseMode};
+use self::drain::DRAIN;
+use self::file_handle::FileHandles;
+use self::inode_lock::InodeLocks;
+use self::inspect::INSPECTOR;
 use self::kv_engine::KVEngineType;
+use self::lookup_count::LookupCounts;
+use self::pin::PINS;
+use self::posix_acl::{AclKind, PosixAcl};
+use self::posix_lock::PosixLocks;
+use self::quota::QuotaAttr;
+use self::read_only::READ_ONLY_VOLUMES;
+use self::security_label::SELINUX_XATTR;
 use crate::async_fuse::fuse::file_system::FileSystem;
 use crate::async_fuse::fuse::fuse_reply::{
     ReplyAttr, ReplyBMap, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry,
-    ReplyLock, ReplyOpen, ReplyStatFs, ReplyWrite, ReplyXAttr,
+    ReplyLSeek, ReplyLock, ReplyOpen, ReplyStatFs, ReplyWrite, ReplyXAttr,
 };
 use crate::async_fuse::fuse::fuse_request::Request;
-use crate::async_fuse::fuse::protocol::{INum, FUSE_ROOT_ID};
-use crate::async_fuse::memfs::metadata::ReqContext;
+#[cfg(feature = "abi-7-16")]
+use crate::async_fuse::fuse::protocol::FuseForgetOne;
+use crate::async_fuse::fuse::protocol::{FuseAttr, INum, FOPEN_KEEP_CACHE, FUSE_ROOT_ID};
 use crate::async_fuse::util::build_error_result_from_errno;
 use crate::common::error::{Context, DatenLordResult};
-use crate::storage::policy::LruPolicy;
-use crate::storage::{Backend, Block, BlockCoordinate, MemoryCache, StorageManager};
+use crate::storage::policy::BoxedPolicy;
+use crate::storage::tiering::Tier;
+use crate::storage::{BlockCoordinate, MemoryCache, Storage, StorageManager};
+
+/// The type of storage layers below the memory cache, it's the backend, or the
+/// dedup storage, the snapshot storage or the tiers upon the backend,
+/// optionally with the checksum storage and a disk cache upon them.
+pub type BackendStorageType = Arc<dyn Storage + Send + Sync>;
 
 /// The type of storage
-pub type StorageType = Arc<StorageManager<Arc<MemoryCache<LruPolicy<BlockCoordinate>, Backend>>>>;
+pub type StorageType =
+    Arc<StorageManager<Arc<MemoryCache<BoxedPolicy<BlockCoordinate>, BackendStorageType>>>>;
 
 /// In-memory file system
 #[derive(Debug)]
@@ -57,6 +178,31 @@ pub struct MemFs<M: MetaData + Send + Sync + 'static> {
     metadata: Arc<M>,
     /// Storage manager
     storage: StorageType,
+    /// The locks serializing size changes against writes of files
+    inode_locks: InodeLocks,
+    /// The leases of files keeping the caches coherent among the nodes
+    coherence: Arc<Coherence>,
+    /// The POSIX locks of files shared with the other nodes
+    posix_locks: Arc<PosixLocks>,
+    /// The handles of the files opened by the kernel
+    file_handles: Arc<FileHandles>,
+    /// The references of the nodes taken by the kernel
+    lookup_counts: LookupCounts,
+    /// The references of the nodes created by `create_stateless()`, which
+    /// are dropped when the nodes are removed by `remove_stateless()`
+    stateless_refs: Mutex<HashMap<INum, u64>>,
+    /// The memory budget the metadata cache and the buffers of requests draw
+    /// from, `None` if there's no budget
+    memory_budget: Option<Arc<MemoryBudget>>,
+    /// The mapping of the uids and gids between the mount and the volume
+    id_map: IdMap,
+    /// The squashing of the callers of the FUSE requests
+    squash: Squash,
+    /// The classes of the FUSE operations turned off on the mount
+    op_mask: OpMask,
+    /// The authorizer of the FUSE requests of the tenant of the mount, `None`
+    /// if the mount serves no tenant
+    authorizer: Option<Authorizer>,
 }
 
 /// Set attribute parameters
@@ -101,6 +247,9 @@ pub struct CreateParam {
     pub uid: u32,
     /// Group ID
     pub gid: u32,
+    /// The umask of the creating process, applied to the mode if the parent
+    /// has no default ACL, or 0 if it's applied by the caller
+    pub umask: u32,
     /// Type
     pub node_type: SFlag,
     /// For symlink
@@ -139,6 +288,25 @@ pub struct FileLockParam {
     pub pid: u32,
 }
 
+/// Copy file range parameters
+#[derive(Debug)]
+pub struct CopyRangeParam {
+    /// File handler of the source file
+    pub fh_in: u64,
+    /// Start offset in the source file
+    pub offset_in: u64,
+    /// The i-number of the destination file
+    pub ino_out: INum,
+    /// File handler of the destination file
+    pub fh_out: u64,
+    /// Start offset in the destination file
+    pub offset_out: u64,
+    /// The maximum length to copy
+    pub len: u64,
+    /// The flags of `copy_file_range(2)`
+    pub flags: u64,
+}
+
 /// MAX NAME LEN
 const MAX_NAME_LEN: usize = 255;
 
@@ -181,8 +349,250 @@ impl<M: MetaData + Send + Sync + 'static> MemFs<M> {
             "mount_point: ${}$, capacity: ${}$, node_id: {}, storage_config: {:?}",
             mount_point, capacity, node_id, storage_config
         );
-        let metadata = M::new(kv_engine, node_id).await?;
-        Ok(Self { metadata, storage })
+        let metadata = M::new(
+            Arc::clone(&kv_engine),
+            node_id,
+            storage_config.block_size.cast(),
+            MetaDataOptions {
+                capacity: storage_config.capacity,
+                trash_retention: storage_config.trash_retention,
+                inline_threshold: storage_config.inline_data_threshold,
+                stat_ahead_limit: storage_config.stat_ahead_limit,
+                negative_cache_ttl: storage_config.negative_cache_ttl,
+                dentry_cache_capacity: storage_config.dentry_cache_capacity,
+            },
+        )
+        .await?;
+        {
+            let kv_engine = Arc::clone(&kv_engine);
+            let node_id = node_id.to_owned();
+            let mount_point = mount_point.to_owned();
+            let peer_cache = storage_config.peer_cache_config.clone();
+            TASK_MANAGER
+                .spawn(TaskName::Cluster, |token| {
+                    cluster::run_registration(kv_engine, node_id, mount_point, peer_cache, token)
+                })
+                .await?;
+        }
+        {
+            let metadata = Arc::clone(&metadata);
+            let kv_engine = Arc::clone(&kv_engine);
+            TASK_MANAGER
+                .spawn(TaskName::Cluster, |token| {
+                    cluster::run_invalidator(metadata, kv_engine, token)
+                })
+                .await?;
+        }
+        let coherence = Arc::new(Coherence::new(
+            Arc::clone(&kv_engine),
+            node_id,
+            storage_config.consistency,
+        ));
+        // The locks are only delegated with the leases of the strict
+        // consistency.
+        let posix_locks = Arc::new(PosixLocks::new(
+            kv_engine,
+            node_id,
+            coherence.is_strict().then(|| Arc::clone(&coherence)),
+        ));
+        if coherence.is_strict() {
+            let coherence = Arc::clone(&coherence);
+            let storage = Arc::clone(&storage);
+            let posix_locks = Arc::clone(&posix_locks);
+            let metadata = Arc::clone(&metadata);
+            TASK_MANAGER
+                .spawn(TaskName::Coherence, |token| {
+                    coherence::run_recall_handler(coherence, storage, posix_locks, metadata, token)
+                })
+                .await?;
+        }
+        {
+            let storage = Arc::clone(&storage);
+            let coherence = Arc::clone(&coherence);
+            let posix_locks = Arc::clone(&posix_locks);
+            let metadata = Arc::clone(&metadata);
+            TASK_MANAGER
+                .spawn(TaskName::Drain, |token| {
+                    drain::run_drainer(storage, coherence, posix_locks, metadata, token)
+                })
+                .await?;
+        }
+        {
+            let metadata = Arc::clone(&metadata);
+            let storage = Arc::clone(&storage);
+            let coherence = Arc::clone(&coherence);
+            let block_size = storage_config.block_size;
+            let mount_point = mount_point.to_owned();
+            TASK_MANAGER
+                .spawn(TaskName::WarmUp, |token| {
+                    warmup::run_warmer(metadata, storage, coherence, block_size, mount_point, token)
+                })
+                .await?;
+        }
+        if let Some(retention) = storage_config.trash_retention {
+            let metadata = Arc::clone(&metadata);
+            let storage = Arc::clone(&storage);
+            TASK_MANAGER
+                .spawn(TaskName::TrashGc, |token| {
+                    trash::run_trash_gc(metadata, storage, retention, token)
+                })
+                .await?;
+        }
+        let file_handles = Arc::new(FileHandles::new());
+        INSPECTOR.register(&storage, &coherence, &file_handles);
+        PINS.register(
+            &storage,
+            mount_point,
+            storage_config.pin_limit,
+            storage_config.block_size,
+        );
+        Ok(Self {
+            metadata,
+            storage,
+            inode_locks: InodeLocks::with_block_size(storage_config.block_size.cast()),
+            coherence,
+            posix_locks,
+            file_handles,
+            lookup_counts: LookupCounts::new(),
+            stateless_refs: Mutex::new(HashMap::new()),
+            memory_budget: None,
+            id_map: IdMap::default(),
+            squash: Squash::default(),
+            op_mask: OpMask::default(),
+            authorizer: None,
+        })
+    }
+
+    /// Draw the metadata cache from `budget`, which is shared with the data
+    /// cache, and the buffers of the requests served are reserved from it as
+    /// well.
+    ///
+    /// The metadata cached by this node and by the kernel is shrunk in the
+    /// background whenever the budget is exhausted.
+    pub async fn with_memory_budget(mut self, budget: Arc<MemoryBudget>) -> anyhow::Result<Self> {
+        budget.register(&self.metadata);
+        {
+            let metadata = Arc::clone(&self.metadata);
+            let coherence = Arc::clone(&self.coherence);
+            let budget = Arc::clone(&budget);
+            TASK_MANAGER
+                .spawn(TaskName::CacheShrink, |token| {
+                    cache_shrink::run_cache_shrinker(metadata, coherence, budget, token)
+                })
+                .await?;
+        }
+        self.memory_budget = Some(budget);
+        Ok(self)
+    }
+
+    /// The memory budget of the file system, `None` if there's no budget.
+    pub fn memory_budget(&self) -> Option<&Arc<MemoryBudget>> {
+        self.memory_budget.as_ref()
+    }
+
+    /// Map the uids and gids of the FUSE requests into the volume by
+    /// `id_map`, and the owners replied back to the mount.
+    ///
+    /// The gateways run as root and don't map their clients, the mapping is
+    /// done by the FUSE session only.
+    #[must_use]
+    pub fn with_id_map(mut self, id_map: IdMap) -> Self {
+        self.id_map = id_map;
+        self
+    }
+
+    /// Run the FUSE requests of the callers squashed by `squash` as its
+    /// anonymous user, which owns the nodes they create as well.
+    #[must_use]
+    pub fn with_squash(mut self, squash: Squash) -> Self {
+        self.squash = squash;
+        self
+    }
+
+    /// Refuse the FUSE requests of the classes turned off by `op_mask`.
+    #[must_use]
+    pub fn with_op_mask(mut self, op_mask: OpMask) -> Self {
+        self.op_mask = op_mask;
+        self
+    }
+
+    /// Serve the FUSE requests of `tenant` only, authorized by its `rules`,
+    /// whose directories must exist.
+    pub async fn with_tenant(mut self, tenant: &str, rules: &TenantRules) -> DatenLordResult<Self> {
+        let mut authorizer = Authorizer::new(tenant);
+        for (path, read_only) in rules
+            .paths
+            .iter()
+            .map(|path| (path, false))
+            .chain(rules.read_only.iter().map(|path| (path, true)))
+        {
+            let mut inos = vec![FUSE_ROOT_ID];
+            for name in path.split('/').filter(|name| !name.is_empty()) {
+                let parent = inos.last().copied().unwrap_or(FUSE_ROOT_ID);
+                let (_, attr, _) = self
+                    .lookup_entry(ROOT_CONTEXT, parent, name)
+                    .await
+                    .with_context(|| format!("failed to resolve {path} of tenant {tenant}"))?;
+                inos.push(attr.ino);
+            }
+            authorizer.grant(&inos, read_only);
+        }
+        self.authorizer = Some(authorizer);
+        Ok(self)
+    }
+
+    /// The context of a FUSE request, the anonymous user if the caller is
+    /// squashed, or its uid and gid mapped into the volume.
+    fn req_context(&self, req: &Request<'_>) -> ReqContext {
+        if let Some((uid, gid)) = self.squash.squash(req.uid()) {
+            return ReqContext { uid, gid };
+        }
+        ReqContext {
+            uid: self.id_map.uid_to_volume(req.uid()),
+            gid: self.id_map.gid_to_volume(req.gid()),
+        }
+    }
+
+    /// Map the owner of `attr` replied to the kernel to the mount.
+    fn attr_to_mount(&self, mut attr: FuseAttr) -> FuseAttr {
+        attr.uid = self.id_map.uid_to_mount(attr.uid);
+        attr.gid = self.id_map.gid_to_mount(attr.gid);
+        attr
+    }
+
+    /// Set the FUSE device to notify the kernel, once the file system is
+    /// mounted.
+    pub async fn set_notifier(&self, file: File) {
+        self.coherence.set_notifier(file).await;
+    }
+
+    /// Fail with `EROFS` if `ino` is below the directory of a volume only
+    /// published read-only on this node.
+    async fn check_writable(&self, ino: INum) -> DatenLordResult<()> {
+        if READ_ONLY_VOLUMES.is_empty() {
+            return Ok(());
+        }
+        let mut cur = ino;
+        loop {
+            if READ_ONLY_VOLUMES.contains(cur) {
+                return build_error_result_from_errno(
+                    Errno::EROFS,
+                    format!("ino={ino} is in a volume published read-only"),
+                );
+            }
+            if cur == FUSE_ROOT_ID {
+                return Ok(());
+            }
+            cur = self.metadata.get_parent_ino(cur).await?;
+        }
+    }
+
+    /// Drop `nlookup` references of `ino` forgotten by the kernel, the
+    /// reference of this node in the kv engine is dropped with the last one.
+    async fn forget_lookups(&self, ino: INum, nlookup: u64) -> DatenLordResult<()> {
+        self.lookup_counts
+            .dec(ino, nlookup, self.forget_entry(ino, 1))
+            .await
     }
 }
 
@@ -213,17 +623,19 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
     ) -> nix::Result<usize> {
         let _timer = FILESYSTEM_METRICS.start_storage_operation_timer("lookup");
         debug!("lookup(parent={}, name={:?}, req={:?})", parent, name, req,);
-        // check the dir_name is valid
-        if let Err(e) = check_name_length(name) {
-            return reply.error(e).await;
-        }
-        let context = ReqContext {
-            uid: req.uid(),
-            gid: req.gid(),
-        };
-        let lookup_res = self.metadata.lookup_helper(context, parent, name).await;
-        match lookup_res {
-            Ok((ttl, fuse_attr, generation)) => reply.entry(ttl, fuse_attr, generation).await,
+        let context = self.req_context(req);
+        match self.lookup_entry(context, parent, name).await {
+            Ok((ttl, fuse_attr, generation)) => {
+                // The first reference of this node is taken in the kv engine,
+                // so the node is kept if it's removed by others
+                let reference = self.metadata.reference(fuse_attr.ino);
+                if let Err(e) = self.lookup_counts.inc(fuse_attr.ino, reference).await {
+                    return reply.error(e).await;
+                }
+                reply
+                    .entry(ttl, self.attr_to_mount(fuse_attr), generation)
+                    .await
+            }
             Err(e) => reply.error(e).await,
         }
     }
@@ -233,13 +645,13 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
         let _timer = FILESYSTEM_METRICS.start_storage_operation_timer("getattr");
         let ino = req.nodeid();
         debug!("getattr(ino={}, req={:?})", ino, req);
-        match self.metadata.getattr(ino).await {
+        match self.get_attr(ino).await {
             Ok((ttl, fuse_attr)) => {
                 debug!(
                     "getattr() successfully got the attr={:?} of ino={}",
                     fuse_attr, ino,
                 );
-                reply.attr(ttl, fuse_attr).await
+                reply.attr(ttl, self.attr_to_mount(fuse_attr)).await
             }
             Err(err) => {
                 // In the previous version ,this panic will never happen.
@@ -268,18 +680,45 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
         let ino = req.nodeid();
         debug!("open(ino={}, flags={}, req={:?})", ino, flags, req);
 
-        let context = ReqContext {
-            uid: req.uid(),
-            gid: req.gid(),
-        };
+        let context = self.req_context(req);
 
+        // The read delegation recalls the writer of another node, so the file
+        // is opened with its last writes, and later opens are served locally.
+        let (_lease, newly_delegated) = match self.coherence.guard(ino, LeaseMode::Read).await {
+            Ok((lease, attr)) => {
+                let newly_delegated = attr.is_some();
+                if let Some(attr) = attr {
+                    self.metadata.delegate_open_file(attr);
+                }
+                (lease, newly_delegated)
+            }
+            Err(e) => return reply.error(e).await,
+        };
+        // The pages cached by the kernel, which the mappings of the file share
+        // as well, are kept while the delegation is held, as a recall
+        // invalidates them. They're dropped once the delegation is acquired
+        // again, so a file is at least opened with what's written and closed
+        // by the other nodes. They're kept under the close-to-open
+        // consistency if the file is not changed since they're cached.
+        let keep_cache = if self.coherence.is_strict() {
+            !newly_delegated && self.coherence.invalidates_kernel_cache().await
+        } else {
+            match self.metadata.revalidate_open_file(ino).await {
+                Ok(valid) => valid,
+                Err(e) => return reply.error(e).await,
+            }
+        };
+        let open_flags = if keep_cache { FOPEN_KEEP_CACHE } else { 0 };
         match self.metadata.open(context, ino, flags).await {
-            Ok(new_fd) => {
+            Ok(_) => {
+                // The state of the open is kept by its handle until it's
+                // released
+                let fh = self.file_handles.open(ino, flags);
                 debug!(
-                    "open() successfully duplicated the file handler of ino={} , fd={}, flags={:?}",
-                    ino, new_fd, flags,
+                    "open() successfully allocated the file handle of ino={} , fh={}, flags={:?}",
+                    ino, fh, flags,
                 );
-                reply.opened(new_fd, flags).await
+                reply.opened(fh.cast(), open_flags).await
             }
             Err(e) => {
                 debug!("open() failed, the error is: {:?}", e);
@@ -299,15 +738,19 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
     #[instrument(skip(self))]
     async fn forget(&self, req: &Request<'_>, nlookup: u64) {
         let _timer = FILESYSTEM_METRICS.start_storage_operation_timer("forget");
-        let ino = req.nodeid();
-        let deleted = self
-            .metadata
-            .forget(ino, nlookup)
+        self.forget_lookups(req.nodeid(), nlookup)
             .await
             .unwrap_or_else(|e| panic!("{e}"));
-        if deleted {
-            self.storage
-                .remove(ino)
+    }
+
+    /// Forget about multiple inodes.
+    /// Like `forget()` for each of the nodes.
+    #[cfg(feature = "abi-7-16")]
+    #[instrument(skip(self))]
+    async fn batch_forget(&self, req: &Request<'_>, nodes: &[FuseForgetOne]) {
+        let _timer = FILESYSTEM_METRICS.start_storage_operation_timer("batch_forget");
+        for node in nodes {
+            self.forget_lookups(node.nodeid, node.nlookup)
                 .await
                 .unwrap_or_else(|e| panic!("{e}"));
         }
@@ -317,7 +760,7 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
     async fn setattr(
         &self,
         req: &Request<'_>,
-        param: SetAttrParam,
+        mut param: SetAttrParam,
         reply: ReplyAttr<'_>,
     ) -> nix::Result<usize> {
         let _timer = FILESYSTEM_METRICS.start_storage_operation_timer("setattr");
@@ -331,16 +774,12 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
         if 0 == valid {
             warn!("setattr() encountered valid=0, the req={:?}", req);
         };
-        let context = ReqContext {
-            uid: req.uid(),
-            gid: req.gid(),
-        };
-        let set_res = self
-            .metadata
-            .setattr_helper(context, ino, &param, &self.storage)
-            .await;
-        match set_res {
-            Ok((ttl, fuse_attr)) => reply.attr(ttl, fuse_attr).await,
+        let context = self.req_context(req);
+        // The owner set is stored in the volume.
+        param.u_id = param.u_id.map(|uid| self.id_map.uid_to_volume(uid));
+        param.g_id = param.g_id.map(|gid| self.id_map.gid_to_volume(gid));
+        match self.set_attr(context, ino, &param).await {
+            Ok((ttl, fuse_attr)) => reply.attr(ttl, self.attr_to_mount(fuse_attr)).await,
             Err(e) => reply.error(e).await,
         }
     }
@@ -351,14 +790,23 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
     async fn mknod(
         &self,
         req: &Request<'_>,
-        param: CreateParam,
+        mut param: CreateParam,
         reply: ReplyEntry<'_>,
     ) -> nix::Result<usize> {
         let _timer = FILESYSTEM_METRICS.start_storage_operation_timer("mknod");
         debug!("mknod param = {:?}, req = {:?}", param, req);
-        let mknod_res = self.metadata.mknod(param).await;
-        match mknod_res {
-            Ok((ttl, fuse_attr, generation)) => reply.entry(ttl, fuse_attr, generation).await,
+        // The node is owned by the caller in the volume.
+        let context = self.req_context(req);
+        param.uid = context.uid;
+        param.gid = context.gid;
+        match self.create_node(param).await {
+            Ok((ttl, fuse_attr, generation)) => {
+                // The node is created referenced in the kv engine
+                self.lookup_counts.inc_referenced(fuse_attr.ino).await;
+                reply
+                    .entry(ttl, self.attr_to_mount(fuse_attr), generation)
+                    .await
+            }
             Err(e) => {
                 info!("mknod() failed , the error is: {:?}", e);
                 reply.error(e).await
@@ -373,32 +821,39 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
         parent: INum,
         name: &str,
         mode: u32,
+        umask: u32,
         reply: ReplyEntry<'_>,
     ) -> nix::Result<usize> {
         let _timer = FILESYSTEM_METRICS.start_storage_operation_timer("mkdir");
         debug!(
-            "mkdir(parent={}, name={:?}, mode={}, req={:?})",
-            parent, name, mode, req,
+            "mkdir(parent={}, name={:?}, mode={}, umask={}, req={:?})",
+            parent, name, mode, umask, req,
         );
+        let context = self.req_context(req);
         let param = CreateParam {
             parent,
             name: name.to_owned(),
             mode,
             rdev: 0,
-            uid: req.uid(),
-            gid: req.gid(),
+            uid: context.uid,
+            gid: context.gid,
+            umask,
             node_type: SFlag::S_IFDIR,
             link: None,
         };
         let mkdir_res = self
-            .metadata
-            .mknod(param)
+            .create_node(param)
             .await
             .add_context(format!(
                 "mkdir() failed to create a directory name={name:?} and mode={mode:?} under parent ino={parent}",
             ));
         match mkdir_res {
-            Ok((ttl, fuse_attr, generation)) => reply.entry(ttl, fuse_attr, generation).await,
+            Ok((ttl, fuse_attr, generation)) => {
+                self.lookup_counts.inc_referenced(fuse_attr.ino).await;
+                reply
+                    .entry(ttl, self.attr_to_mount(fuse_attr), generation)
+                    .await
+            }
             Err(e) => {
                 debug!(
                     "mkdir() failed to create a directory name={:?} and mode={:?} under parent ino={}, \
@@ -424,28 +879,9 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
     ) -> nix::Result<usize> {
         let _timer = FILESYSTEM_METRICS.start_storage_operation_timer("unlink");
         debug!("unlink(parent={}, name={:?}, req={:?}", parent, name, req,);
-        // check the dir_name is valid
-        if let Err(e) = check_name_length(name) {
-            return reply.error(e).await;
-        }
-
-        let context = ReqContext {
-            uid: req.uid(),
-            gid: req.gid(),
-        };
-
-        match self.metadata.unlink(context, parent, name).await {
-            Ok(result) => {
-                if let Some(ino) = result {
-                    match self.storage.remove(ino).await {
-                        Ok(()) => {}
-                        Err(e) => {
-                            return reply.error(e).await;
-                        }
-                    }
-                }
-                reply.ok().await
-            }
+        let context = self.req_context(req);
+        match self.remove_entry(context, parent, name).await {
+            Ok(()) => reply.ok().await,
             Err(e) => reply.error(e).await,
         }
     }
@@ -460,27 +896,16 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
         reply: ReplyEmpty<'_>,
     ) -> nix::Result<usize> {
         let _timer = FILESYSTEM_METRICS.start_storage_operation_timer("rmdir");
-        // check the dir_name is valid
-        if let Err(e) = check_name_length(dir_name) {
-            return reply.error(e).await;
-        }
-
-        let context = ReqContext {
-            uid: req.uid(),
-            gid: req.gid(),
-        };
+        let context = self.req_context(req);
 
         let rmdir_res = self
-            .metadata
-            .unlink(context, parent, dir_name)
+            .remove_entry(context, parent, dir_name)
             .await
             .add_context(format!(
             "rmdir() failed to remove sub-directory name={dir_name:?} under parent ino={parent}",
         ));
         match rmdir_res {
-            // We don't store dir information in the persistent storage, so we don't need to remove
-            // it
-            Ok(_) => reply.ok().await,
+            Ok(()) => reply.ok().await,
             Err(e) => reply.error(e).await,
         }
     }
@@ -506,11 +931,8 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
         reply: ReplyEmpty<'_>,
     ) -> nix::Result<usize> {
         let _timer = FILESYSTEM_METRICS.start_storage_operation_timer("rename");
-        let context = ReqContext {
-            uid: req.uid(),
-            gid: req.gid(),
-        };
-        match self.metadata.rename(context, param).await {
+        let context = self.req_context(req);
+        match self.rename_entry(context, param).await {
             Ok(()) => reply.ok().await,
             Err(e) => {
                 debug!("rename() failed, the error is: {:?}", e);
@@ -530,40 +952,21 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
     async fn read(
         &self,
         req: &Request<'_>,
-        _fh: u64,
+        fh: u64,
         offset: i64,
         size: u32,
         reply: ReplyData<'_>,
     ) -> nix::Result<usize> {
         let _timer = FILESYSTEM_METRICS.start_storage_operation_timer("read");
         let ino = req.nodeid();
-        let offset: u64 = offset.cast();
-
-        let (file_size, mtime) = match self.metadata.read_helper(ino).await {
-            Ok((file_size, mtime)) => (file_size, mtime),
-            Err(e) => {
-                return reply.error(e).await;
-            }
+        let Some(handle) = self.file_handles.get(ino, fh) else {
+            return reply.error_code(Errno::EBADF).await;
         };
-
-        if offset >= file_size {
-            return reply.data(Vec::<Block>::new()).await;
-        }
-
-        // Ensure `offset + size` is le than the size of the file
-        let read_size: u64 = if offset.overflow_add(size.cast::<u64>()) > file_size {
-            file_size.overflow_sub(offset.cast::<u64>())
-        } else {
-            size.cast()
-        };
-
-        let result = self
-            .storage
-            .load(ino, offset.cast(), read_size.cast(), mtime)
-            .await;
-        // Check the load result
-        match result {
-            Ok(content) => reply.data(content).await,
+        match self
+            .read_opened(ino, Some(&handle), offset.cast(), size.into())
+            .await
+        {
+            Ok((content, _)) => reply.data(content).await,
             Err(e) => reply.error(e).await,
         }
     }
@@ -579,34 +982,23 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
     async fn write(
         &self,
         req: &Request<'_>,
-        _fh: u64,
+        fh: u64,
         offset: i64,
-        data: Vec<u8>,
+        data: &[u8],
         _flags: u32,
         reply: ReplyWrite<'_>,
     ) -> nix::Result<usize> {
         let _timer = FILESYSTEM_METRICS.start_storage_operation_timer("write");
         let ino = req.nodeid();
-        let data_len: u64 = data.len().cast();
-
-        let (old_size, old_mtime) = self.metadata.mtime_and_size(ino);
-        let new_mtime = self
-            .storage
-            .store(ino, offset.cast(), &data, old_mtime)
-            .await;
-
-        let new_mtime = match new_mtime {
-            Ok(new_mtime) => new_mtime,
-            Err(e) => {
-                return reply.error(e).await;
-            }
+        let handle = match self.file_handles.get(ino, fh) {
+            Some(handle) if handle.is_writable() => handle,
+            Some(_) | None => return reply.error_code(Errno::EBADF).await,
         };
-
-        let new_size = old_size.max(offset.cast::<u64>().overflow_add(data_len));
-
-        let write_result = self.metadata.write_helper(ino, new_mtime, new_size).await;
-        match write_result {
-            Ok(()) => reply.written(data_len.cast()).await,
+        match self.write_opened(ino, offset.cast(), data).await {
+            Ok(()) => {
+                handle.mark_dirty(offset.cast(), data.len().cast());
+                reply.written(data.len().cast()).await
+            }
             Err(e) => reply.error(e).await,
         }
     }
@@ -642,7 +1034,12 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
         // called multiple times for an open file, self must not really
         // close the file. This is important if used on a network
         // filesystem like NFS which flush the data/metadata on close()
-        match self.storage.flush(ino).await {
+        if let Err(e) = self.storage.flush(ino).await {
+            return reply.error(e).await;
+        }
+        self.file_handles.clear_dirty(ino);
+        // Closing a file releases the POSIX locks of the lock owner on it.
+        match self.posix_locks.release_owner(ino, lock_owner).await {
             Ok(()) => reply.ok().await,
             Err(e) => reply.error(e).await,
         }
@@ -669,19 +1066,24 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
     ) -> nix::Result<usize> {
         let _timer = FILESYSTEM_METRICS.start_storage_operation_timer("release");
         let ino = req.nodeid();
-        if flush {
+        // The handle is torn down with its state, what's written via it is
+        // flushed as the kernel may not flush it.
+        let dirty = self
+            .file_handles
+            .release(ino, fh)
+            .is_some_and(|handle| handle.is_dirty());
+        if flush || dirty {
             match self.storage.flush(ino).await {
-                Ok(()) => {}
+                Ok(()) => self.file_handles.clear_dirty(ino),
                 Err(e) => {
                     return reply.error(e).await;
                 }
             }
         }
-        self.metadata
-            .release(ino, fh, flags, lock_owner, flush)
-            .await
-            .unwrap_or_else(|e| panic!("{e}"));
-        reply.ok().await
+        match self.release_file(ino, fh, flags, lock_owner, flush).await {
+            Ok(()) => reply.ok().await,
+            Err(e) => reply.error(e).await,
+        }
     }
 
     /// Synchronize file contents.
@@ -698,7 +1100,10 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
         let _timer = FILESYSTEM_METRICS.start_storage_operation_timer("fsync");
         let ino = req.nodeid();
         match self.storage.flush(ino).await {
-            Ok(()) => reply.ok().await,
+            Ok(()) => {
+                self.file_handles.clear_dirty(ino);
+                reply.ok().await
+            }
             Err(e) => reply.error(e).await,
         }
     }
@@ -720,10 +1125,7 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
         let _timer = FILESYSTEM_METRICS.start_storage_operation_timer("opendir");
         let ino = req.nodeid();
         debug!("opendir(ino={}, flags={}, req={:?})", ino, flags, req,);
-        let context = ReqContext {
-            uid: req.uid(),
-            gid: req.gid(),
-        };
+        let context = self.req_context(req);
         let o_flags = fs_util::parse_oflag(flags);
         match self.metadata.opendir(context, ino, flags).await {
             Ok(new_fd) => {
@@ -764,10 +1166,7 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
             ino, fh, offset, req,
         );
 
-        let context = ReqContext {
-            uid: req.uid(),
-            gid: req.gid(),
-        };
+        let context = self.req_context(req);
         match self
             .metadata
             .readdir(context, ino, fh, offset, &mut reply)
@@ -835,10 +1234,7 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
             req.nodeid()
         };
         debug!("statfs(ino={}, req={:?})", ino, req);
-        let context = ReqContext {
-            uid: req.uid(),
-            gid: req.gid(),
-        };
+        let context = self.req_context(req);
         match self.metadata.statfs(context, ino).await {
             Ok(statvfs) => {
                 debug!(
@@ -863,12 +1259,7 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
         let ino = req.nodeid();
         debug!("readlink(ino={}, req={:?})", ino, req,);
         reply
-            .data(
-                self.metadata
-                    .readlink(ino)
-                    .await
-                    .unwrap_or_else(|e| panic!("{e}")),
-            )
+            .data(self.read_link(ino).await.unwrap_or_else(|e| panic!("{e}")))
             .await
     }
 
@@ -886,25 +1277,30 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
             "symlink(parent={}, name={:?}, target_path={:?}, req={:?})",
             parent, name, target_path, req
         );
+        let context = self.req_context(req);
         let param = CreateParam {
             parent,
             name: name.to_owned(),
             mode: 0o777,
             rdev: 0,
-            uid: req.uid(),
-            gid: req.gid(),
+            uid: context.uid,
+            gid: context.gid,
+            umask: 0,
             node_type: SFlag::S_IFLNK,
             link: Some(target_path.to_owned()),
         };
-        let symlink_res = self.metadata.mknod(
-            param
-        )
+        let symlink_res = self.create_node(param)
         .await
         .add_context(format!(
             "symlink() failed to create a symlink name={name:?} to target path={target_path:?} under parent ino={parent}",
         ));
         match symlink_res {
-            Ok((ttl, fuse_attr, generation)) => reply.entry(ttl, fuse_attr, generation).await,
+            Ok((ttl, fuse_attr, generation)) => {
+                self.lookup_counts.inc_referenced(fuse_attr.ino).await;
+                reply
+                    .entry(ttl, self.attr_to_mount(fuse_attr), generation)
+                    .await
+            }
             Err(e) => {
                 debug!(
                     "symlink() failed to create a symlink name={:?} to target path={:?} under parent ino={}, \
@@ -931,6 +1327,41 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
         );
     }
 
+    /// Check the class of the request by the operation mask of the mount,
+    /// the nodes it changes by the volumes published read-only and by the
+    /// drain of the node, and its accesses by the authorizer of the tenant of
+    /// the mount.
+    async fn authorize(&self, req: &Request<'_>) -> DatenLordResult<()> {
+        if let Some(errno) = auth::masked(req, self.op_mask) {
+            return build_error_result_from_errno(
+                errno,
+                format!("the operation is turned off on the mount: {errno}"),
+            );
+        }
+        for (ino, need) in auth::needs(req) {
+            if need == Need::Write {
+                if DRAIN.is_draining() {
+                    return build_error_result_from_errno(
+                        Errno::EROFS,
+                        "the node is draining".to_owned(),
+                    );
+                }
+                self.check_writable(ino).await?;
+            }
+            let Some(ref authorizer) = self.authorizer else {
+                continue;
+            };
+            let mut chain = vec![ino];
+            let mut cur = ino;
+            while cur != FUSE_ROOT_ID {
+                cur = self.metadata.get_parent_ino(cur).await?;
+                chain.push(cur);
+            }
+            authorizer.check(&chain, need)?;
+        }
+        Ok(())
+    }
+
     /// Create a hard link.
     async fn link(
         &self,
@@ -943,16 +1374,87 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
     }
 
     /// Set an extended attribute.
+    /// Only the POSIX ACLs, see [`AclKind`], the SELinux label, see
+    /// [`SELINUX_XATTR`], the limits of quotas, see [`QuotaAttr`], the tier
+    /// pin, see [`TIER_XATTR`], and the scratch mark, see [`SCRATCH_XATTR`],
+    /// are supported.
     async fn setxattr(
         &self,
-        _req: &Request<'_>,
-        _name: &str,
-        _value: &[u8],
+        req: &Request<'_>,
+        name: &str,
+        value: &[u8],
         _flags: u32,
         _position: u32,
         reply: ReplyEmpty<'_>,
     ) -> nix::Result<usize> {
-        reply.error_code(Errno::ENOSYS).await
+        let context = self.req_context(req);
+        if let Some(kind) = AclKind::from_name(name) {
+            let mut acl = match PosixAcl::from_xattr(value) {
+                Ok(acl) => acl,
+                Err(e) => return reply.error(e).await,
+            };
+            acl.map_ids(
+                |uid| self.id_map.uid_to_volume(uid),
+                |gid| self.id_map.gid_to_volume(gid),
+            );
+            return match self
+                .metadata
+                .set_posix_acl(context, req.nodeid(), kind, Some(acl))
+                .await
+            {
+                Ok(()) => reply.ok().await,
+                Err(e) => reply.error(e).await,
+            };
+        }
+        if name == SELINUX_XATTR {
+            return match self
+                .metadata
+                .set_security_label(context, req.nodeid(), Some(value.to_vec()))
+                .await
+            {
+                Ok(()) => reply.ok().await,
+                Err(e) => reply.error(e).await,
+            };
+        }
+        if name == TIER_XATTR {
+            let Some(tier) = std::str::from_utf8(value)
+                .ok()
+                .and_then(|value| value.trim().parse::<Tier>().ok())
+            else {
+                return reply.error_code(Errno::EINVAL).await;
+            };
+            return match self
+                .metadata
+                .set_tier_pin(context, req.nodeid(), Some(tier))
+                .await
+            {
+                Ok(()) => reply.ok().await,
+                Err(e) => reply.error(e).await,
+            };
+        }
+        if name == SCRATCH_XATTR {
+            return match self.metadata.set_scratch(context, req.nodeid(), true).await {
+                Ok(()) => reply.ok().await,
+                Err(e) => reply.error(e).await,
+            };
+        }
+        let Some(attr) = QuotaAttr::from_name(name) else {
+            return reply.error_code(Errno::EOPNOTSUPP).await;
+        };
+        let Some(limit) = std::str::from_utf8(value)
+            .ok()
+            .and_then(|value| value.trim().parse::<u64>().ok())
+        else {
+            return reply.error_code(Errno::EINVAL).await;
+        };
+        match self
+            .metadata
+            .set_quota(context, req.nodeid(), attr, limit)
+            .await
+        {
+            Ok(()) => reply.ok().await,
+            Err(e) => reply.error(e).await,
+        }
     }
 
     /// Get an extended attribute.
@@ -961,12 +1463,57 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
     /// `reply.data()`, or `reply.error(ERANGE)` if it doesn't.
     async fn getxattr(
         &self,
-        _req: &Request<'_>,
-        _name: &str,
-        _size: u32,
+        req: &Request<'_>,
+        name: &str,
+        size: u32,
         reply: ReplyXAttr<'_>,
     ) -> nix::Result<usize> {
-        reply.error_code(Errno::ENOSYS).await
+        if let Some(kind) = AclKind::from_name(name) {
+            let value = match self.metadata.get_posix_acl(req.nodeid(), kind).await {
+                Ok(Some(mut acl)) => {
+                    acl.map_ids(
+                        |uid| self.id_map.uid_to_mount(uid),
+                        |gid| self.id_map.gid_to_mount(gid),
+                    );
+                    acl.to_xattr()
+                }
+                Ok(None) => return reply.error_code(Errno::ENODATA).await,
+                Err(e) => return reply.error(e).await,
+            };
+            return reply.value(value, size).await;
+        }
+        if name == SELINUX_XATTR {
+            let value = match self.metadata.get_security_label(req.nodeid()).await {
+                Ok(Some(label)) => label,
+                Ok(None) => return reply.error_code(Errno::ENODATA).await,
+                Err(e) => return reply.error(e).await,
+            };
+            return reply.value(value, size).await;
+        }
+        if name == TIER_XATTR {
+            let value = match self.metadata.get_tier_pin(req.nodeid()).await {
+                Ok(Some(tier)) => tier.to_string().into_bytes(),
+                Ok(None) => return reply.error_code(Errno::ENODATA).await,
+                Err(e) => return reply.error(e).await,
+            };
+            return reply.value(value, size).await;
+        }
+        if name == SCRATCH_XATTR {
+            return match self.metadata.is_scratch(req.nodeid()).await {
+                Ok(true) => reply.value(b"1".to_vec(), size).await,
+                Ok(false) => reply.error_code(Errno::ENODATA).await,
+                Err(e) => reply.error(e).await,
+            };
+        }
+        let Some(attr) = QuotaAttr::from_name(name) else {
+            return reply.error_code(Errno::ENODATA).await;
+        };
+        let value = match self.metadata.get_quota(req.nodeid()).await {
+            Ok(Some(quota)) => quota.get(attr).to_string().into_bytes(),
+            Ok(None) => return reply.error_code(Errno::ENODATA).await,
+            Err(e) => return reply.error(e).await,
+        };
+        reply.value(value, size).await
     }
 
     /// List extended attribute names.
@@ -975,21 +1522,122 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
     /// `reply.data()`, or `reply.error(ERANGE)` if it doesn't.
     async fn listxattr(
         &self,
-        _req: &Request<'_>,
-        _size: u32,
+        req: &Request<'_>,
+        size: u32,
         reply: ReplyXAttr<'_>,
     ) -> nix::Result<usize> {
-        reply.error_code(Errno::ENOSYS).await
+        let ino = req.nodeid();
+        let mut names = match self.metadata.get_quota(ino).await {
+            Ok(Some(_)) => QuotaAttr::list(),
+            Ok(None) => vec![],
+            Err(e) => return reply.error(e).await,
+        };
+        for kind in [AclKind::Access, AclKind::Default] {
+            match self.metadata.get_posix_acl(ino, kind).await {
+                Ok(Some(_)) => {
+                    names.extend_from_slice(kind.name().as_bytes());
+                    names.push(0);
+                }
+                Ok(None) => {}
+                Err(e) => return reply.error(e).await,
+            }
+        }
+        match self.metadata.get_security_label(ino).await {
+            Ok(Some(_)) => {
+                names.extend_from_slice(SELINUX_XATTR.as_bytes());
+                names.push(0);
+            }
+            Ok(None) => {}
+            Err(e) => return reply.error(e).await,
+        }
+        match self.metadata.get_tier_pin(ino).await {
+            Ok(Some(_)) => {
+                names.extend_from_slice(TIER_XATTR.as_bytes());
+                names.push(0);
+            }
+            Ok(None) => {}
+            Err(e) => return reply.error(e).await,
+        }
+        match self.metadata.is_scratch(ino).await {
+            Ok(true) => {
+                names.extend_from_slice(SCRATCH_XATTR.as_bytes());
+                names.push(0);
+            }
+            Ok(false) => {}
+            Err(e) => return reply.error(e).await,
+        }
+        reply.value(names, size).await
     }
 
     /// Remove an extended attribute.
+    /// Removing a limit of a quota is the same as setting it to `0`, and
+    /// removing the tier pin leaves the tier to the policy, removing the
+    /// scratch mark moves removed files into the trash again. Removing the
+    /// access ACL keeps the mode.
     async fn removexattr(
         &self,
-        _req: &Request<'_>,
-        _name: &str,
+        req: &Request<'_>,
+        name: &str,
         reply: ReplyEmpty<'_>,
     ) -> nix::Result<usize> {
-        reply.error_code(Errno::ENOSYS).await
+        let ino = req.nodeid();
+        let context = self.req_context(req);
+        if let Some(kind) = AclKind::from_name(name) {
+            match self.metadata.get_posix_acl(ino, kind).await {
+                Ok(Some(_)) => {}
+                Ok(None) => return reply.error_code(Errno::ENODATA).await,
+                Err(e) => return reply.error(e).await,
+            }
+            return match self.metadata.set_posix_acl(context, ino, kind, None).await {
+                Ok(()) => reply.ok().await,
+                Err(e) => reply.error(e).await,
+            };
+        }
+        if name == SELINUX_XATTR {
+            match self.metadata.get_security_label(ino).await {
+                Ok(Some(_)) => {}
+                Ok(None) => return reply.error_code(Errno::ENODATA).await,
+                Err(e) => return reply.error(e).await,
+            }
+            return match self.metadata.set_security_label(context, ino, None).await {
+                Ok(()) => reply.ok().await,
+                Err(e) => reply.error(e).await,
+            };
+        }
+        if name == TIER_XATTR {
+            match self.metadata.get_tier_pin(ino).await {
+                Ok(Some(_)) => {}
+                Ok(None) => return reply.error_code(Errno::ENODATA).await,
+                Err(e) => return reply.error(e).await,
+            }
+            return match self.metadata.set_tier_pin(context, ino, None).await {
+                Ok(()) => reply.ok().await,
+                Err(e) => reply.error(e).await,
+            };
+        }
+        if name == SCRATCH_XATTR {
+            match self.metadata.is_scratch(ino).await {
+                Ok(true) => {}
+                Ok(false) => return reply.error_code(Errno::ENODATA).await,
+                Err(e) => return reply.error(e).await,
+            }
+            return match self.metadata.set_scratch(context, ino, false).await {
+                Ok(()) => reply.ok().await,
+                Err(e) => reply.error(e).await,
+            };
+        }
+        let Some(attr) = QuotaAttr::from_name(name) else {
+            return reply.error_code(Errno::ENODATA).await;
+        };
+        match self.metadata.get_quota(ino).await {
+            Ok(Some(_)) => {}
+            Ok(None) => return reply.error_code(Errno::ENODATA).await,
+            Err(e) => return reply.error(e).await,
+        }
+        match self.metadata.set_quota(context, ino, attr, 0).await {
+            Ok(()) => reply.ok().await,
+            Err(e) => reply.error(e).await,
+        }
     }
 
     /// Check file access permissions.
@@ -1029,13 +1677,30 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
     }
 
     /// Test for a POSIX file lock.
+    /// The locks are shared with the other nodes mounting the volume. The
+    /// first conflicting lock is returned, or a lock of `F_UNLCK` if the lock
+    /// could be taken.
+    #[instrument(skip(self), err, ret)]
     async fn getlk(
         &self,
-        _req: &Request<'_>,
-        _lk_param: FileLockParam,
+        req: &Request<'_>,
+        lk_param: FileLockParam,
         reply: ReplyLock<'_>,
     ) -> nix::Result<usize> {
-        reply.error_code(Errno::ENOSYS).await
+        let _timer = FILESYSTEM_METRICS.start_storage_operation_timer("getlk");
+        match self.posix_locks.test(req.nodeid(), &lk_param).await {
+            Ok(Some(lock)) => {
+                reply
+                    .locked(lock.start, lock.end, lock.typ.to_raw(), lock.pid)
+                    .await
+            }
+            Ok(None) => {
+                reply
+                    .locked(lk_param.start, lk_param.end, libc::F_UNLCK.cast(), 0)
+                    .await
+            }
+            Err(e) => reply.error(e).await,
+        }
     }
 
     /// Acquire, modify or release a POSIX file lock.
@@ -1046,14 +1711,21 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
     /// if the locking methods are not implemented, the kernel will still
     /// allow file locking to work locally. Hence these are only interesting
     /// for network filesystems and similar.
+    /// The locks are shared with the other nodes mounting the volume, so the
+    /// writers of a volume mounted on several nodes lock each other out.
+    #[instrument(skip(self), err, ret)]
     async fn setlk(
         &self,
-        _req: &Request<'_>,
-        _lk_param: FileLockParam,
-        _sleep: bool,
+        req: &Request<'_>,
+        lk_param: FileLockParam,
+        sleep: bool,
         reply: ReplyEmpty<'_>,
     ) -> nix::Result<usize> {
-        reply.error_code(Errno::ENOSYS).await
+        let _timer = FILESYSTEM_METRICS.start_storage_operation_timer("setlk");
+        match self.posix_locks.set(req.nodeid(), &lk_param, sleep).await {
+            Ok(()) => reply.ok().await,
+            Err(e) => reply.error(e).await,
+        }
     }
 
     /// Map block index within file to block index within device.
@@ -1068,6 +1740,203 @@ impl<M: MetaData + Send + Sync + 'static> FileSystem for MemFs<M> {
     ) -> nix::Result<usize> {
         reply.error_code(Errno::ENOSYS).await
     }
+
+    /// Allocate or deallocate a range of a file.
+    /// Extending a file allocates nothing, the new range is a hole. Punching
+    /// a hole requires `FALLOC_FL_KEEP_SIZE`, the range is filled with zeros,
+    /// and the chunks fully covered by it are deallocated. Other modes are
+    /// not supported.
+    #[instrument(skip(self), err, ret)]
+    async fn fallocate(
+        &self,
+        req: &Request<'_>,
+        _fh: u64,
+        offset: u64,
+        length: u64,
+        mode: u32,
+        reply: ReplyEmpty<'_>,
+    ) -> nix::Result<usize> {
+        let _timer = FILESYSTEM_METRICS.start_storage_operation_timer("fallocate");
+        let ino = req.nodeid();
+        let keep_size = mode & FALLOC_FL_KEEP_SIZE.cast::<u32>() != 0;
+        let punch_hole = mode & FALLOC_FL_PUNCH_HOLE.cast::<u32>() != 0;
+        let supported = (FALLOC_FL_KEEP_SIZE | FALLOC_FL_PUNCH_HOLE).cast::<u32>();
+        if mode & !supported != 0 || (punch_hole && !keep_size) {
+            return reply.error_code(Errno::EOPNOTSUPP).await;
+        }
+
+        let _guard = self.inode_locks.write(ino).await;
+        let (old_size, old_mtime) = self.metadata.mtime_and_size(ino);
+        let end = offset.overflow_add(length);
+
+        let result = if punch_hole {
+            // Nothing to punch beyond the end of file.
+            let end = end.min(old_size);
+            if offset >= end {
+                return reply.ok().await;
+            }
+            let new_mtime = self
+                .storage
+                .punch_hole(
+                    ino,
+                    offset.cast(),
+                    end.overflow_sub(offset).cast(),
+                    old_mtime,
+                )
+                .await;
+            let new_mtime = match new_mtime {
+                Ok(new_mtime) => new_mtime,
+                Err(e) => {
+                    return reply.error(e).await;
+                }
+            };
+            self.metadata
+                .fallocate_helper(ino, new_mtime, old_size, Some(offset..end))
+                .await
+        } else {
+            if keep_size || end <= old_size {
+                return reply.ok().await;
+            }
+            let new_mtime = self
+                .storage
+                .truncate(ino, old_size.cast(), end.cast(), old_mtime)
+                .await;
+            let new_mtime = match new_mtime {
+                Ok(new_mtime) => new_mtime,
+                Err(e) => {
+                    return reply.error(e).await;
+                }
+            };
+            self.metadata
+                .fallocate_helper(ino, new_mtime, end, None)
+                .await
+        };
+
+        match result {
+            Ok(()) => reply.ok().await,
+            Err(e) => reply.error(e).await,
+        }
+    }
+
+    /// Find the next data or hole in a file, at or after `offset`.
+    /// Only `SEEK_DATA` and `SEEK_HOLE` are sent by the kernel.
+    #[instrument(skip(self), err, ret)]
+    async fn lseek(
+        &self,
+        req: &Request<'_>,
+        _fh: u64,
+        offset: u64,
+        whence: u32,
+        reply: ReplyLSeek<'_>,
+    ) -> nix::Result<usize> {
+        let _timer = FILESYSTEM_METRICS.start_storage_operation_timer("lseek");
+        match self
+            .metadata
+            .lseek_helper(req.nodeid(), offset, whence)
+            .await
+        {
+            Ok(found) => reply.offset(found).await,
+            Err(e) => reply.error(e).await,
+        }
+    }
+
+    /// Copy a range of data from an opened file to another, as
+    /// `copy_file_range(2)` does. The whole blocks in the range are cloned
+    /// if the storage supports it, so the copy shares the data with the
+    /// source without transferring it, until either one is overwritten.
+    #[instrument(skip(self), err, ret)]
+    async fn copy_file_range(
+        &self,
+        req: &Request<'_>,
+        param: CopyRangeParam,
+        reply: ReplyWrite<'_>,
+    ) -> nix::Result<usize> {
+        /// The maximum size copied by a request, as the size in the reply is
+        /// a `u32`. The caller copies the rest with further requests.
+        const MAX_COPY_SIZE: u64 = 1 << 30;
+
+        let _timer = FILESYSTEM_METRICS.start_storage_operation_timer("copy_file_range");
+        let ino_in = req.nodeid();
+        let ino_out = param.ino_out;
+        if param.flags != 0 {
+            return reply.error_code(Errno::EINVAL).await;
+        }
+
+        // Lock in the order of inode numbers, so that two copies in opposite
+        // directions never deadlock. The range copied to is locked against
+        // the writes overlapping it.
+        let dst_len = param.len.min(MAX_COPY_SIZE);
+        let (_src_guard, _dst_guard) = if ino_in < ino_out {
+            let src_guard = self.inode_locks.read(ino_in).await;
+            let dst_guard = self
+                .inode_locks
+                .write_range(ino_out, param.offset_out, dst_len)
+                .await;
+            (Some(src_guard), dst_guard)
+        } else {
+            let dst_guard = self
+                .inode_locks
+                .write_range(ino_out, param.offset_out, dst_len)
+                .await;
+            let src_guard = if ino_in == ino_out {
+                None
+            } else {
+                Some(self.inode_locks.read(ino_in).await)
+            };
+            (src_guard, dst_guard)
+        };
+
+        let (src_size, src_mtime) = match self.metadata.read_helper(ino_in).await {
+            Ok((src_size, src_mtime)) => (src_size, src_mtime),
+            Err(e) => {
+                return reply.error(e).await;
+            }
+        };
+        if param.offset_in >= src_size {
+            return reply.written(0).await;
+        }
+        let len = param
+            .len
+            .min(src_size.overflow_sub(param.offset_in))
+            .min(MAX_COPY_SIZE);
+        if let Err(e) = self
+            .metadata
+            .check_quota(ino_out, param.offset_out, len)
+            .await
+        {
+            return reply.error(e).await;
+        }
+
+        let (old_size, old_mtime) = self.metadata.mtime_and_size(ino_out);
+        let new_mtime = self
+            .storage
+            .copy_range(
+                ino_in,
+                param.offset_in.cast(),
+                src_mtime,
+                ino_out,
+                param.offset_out.cast(),
+                len.cast(),
+                old_mtime,
+            )
+            .await;
+        let new_mtime = match new_mtime {
+            Ok(new_mtime) => new_mtime,
+            Err(e) => {
+                return reply.error(e).await;
+            }
+        };
+
+        let new_size = old_size.max(param.offset_out.overflow_add(len));
+        let write_result = self
+            .metadata
+            .write_helper(ino_out, new_mtime, new_size, param.offset_out, len, None)
+            .await;
+        match write_result {
+            Ok(()) => reply.written(len.cast()).await,
+            Err(e) => reply.error(e).await,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,128 @@
+//! The cache of the names missing in directories.
+//!
+//! Some workloads look up many names that don't exist, such as searching the
+//! `PATH` for a command, or probing the paths of a module to import, and each
+//! miss is a round trip to the kv engine, while the kernel only caches it for
+//! the lookups of the same path. The misses are cached here for a while
+//! instead, and dropped once the names are created by this node. A name
+//! created by other nodes is missed until its miss expires.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::async_fuse::fuse::protocol::INum;
+
+/// The max number of the misses cached, the expired ones are purged once
+/// it's reached, and then all of them if none is expired.
+const NEGATIVE_CACHE_CAPACITY: usize = 65536;
+
+/// The inner state of `NegativeCache`.
+#[derive(Debug, Default)]
+struct NegativeCacheInner {
+    /// The missing names in directories, and when they expire
+    misses: HashMap<(INum, String), Instant>,
+    /// Increased by every creation, so a miss overlapping a creation is not
+    /// cached, as the name may have been created after it's looked up.
+    generation: u64,
+}
+
+/// The cache of the names missing in directories.
+#[derive(Debug)]
+pub(super) struct NegativeCache {
+    /// How long a miss is cached
+    ttl: Duration,
+    /// The inner state
+    inner: Mutex<NegativeCacheInner>,
+}
+
+impl NegativeCache {
+    /// Create a `NegativeCache` caching the misses for `ttl`.
+    pub(super) fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            inner: Mutex::new(NegativeCacheInner::default()),
+        }
+    }
+
+    /// The current generation, which is passed to `insert()` after the name
+    /// is found missing.
+    pub(super) fn generation(&self) -> u64 {
+        self.inner.lock().generation
+    }
+
+    /// Cache that `name` is missing in directory `parent` since `generation`,
+    /// it's not cached if any name is created meanwhile.
+    pub(super) fn insert(&self, generation: u64, parent: INum, name: &str) {
+        let mut inner = self.inner.lock();
+        if inner.generation != generation {
+            return;
+        }
+        let now = Instant::now();
+        if inner.misses.len() >= NEGATIVE_CACHE_CAPACITY {
+            inner.misses.retain(|_, &mut expires| expires > now);
+            if inner.misses.len() >= NEGATIVE_CACHE_CAPACITY {
+                inner.misses.clear();
+            }
+        }
+        inner
+            .misses
+            .insert((parent, name.to_owned()), now + self.ttl);
+    }
+
+    /// Whether `name` is cached missing in directory `parent`.
+    pub(super) fn contains(&self, parent: INum, name: &str) -> bool {
+        let inner = self.inner.lock();
+        // A tuple key can't be looked up by a borrowed name, so the name is
+        // copied.
+        inner
+            .misses
+            .get(&(parent, name.to_owned()))
+            .is_some_and(|&expires| expires > Instant::now())
+    }
+
+    /// Drop all the cached misses under memory pressure.
+    pub(super) fn clear(&self) {
+        self.inner.lock().misses.clear();
+    }
+
+    /// Drop the miss of `name` in directory `parent` once it's created.
+    pub(super) fn invalidate(&self, parent: INum, name: &str) {
+        let mut inner = self.inner.lock();
+        inner.generation = inner.generation.wrapping_add(1);
+        inner.misses.remove(&(parent, name.to_owned()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::NegativeCache;
+
+    #[test]
+    fn test_negative_cache() {
+        let cache = NegativeCache::new(Duration::from_secs(60));
+        let generation = cache.generation();
+        cache.insert(generation, 1, "a");
+        assert!(cache.contains(1, "a"));
+        assert!(!cache.contains(1, "b"));
+        assert!(!cache.contains(2, "a"));
+
+        // A created name is no longer missing.
+        cache.invalidate(1, "a");
+        assert!(!cache.contains(1, "a"));
+
+        // A miss overlapping a creation is not cached.
+        let generation = cache.generation();
+        cache.invalidate(1, "b");
+        cache.insert(generation, 1, "a");
+        assert!(!cache.contains(1, "a"));
+
+        // A miss expires.
+        let cache = NegativeCache::new(Duration::ZERO);
+        cache.insert(cache.generation(), 1, "a");
+        assert!(!cache.contains(1, "a"));
+    }
+}
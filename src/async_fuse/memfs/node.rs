@@ -12,7 +12,6 @@ use parking_lot::RwLock;
 use super::fs_util::FileAttr;
 use super::kv_engine::MetaTxn;
 use super::CreateParam;
-use crate::async_fuse::fuse::fuse_reply::StatFsParam;
 use crate::async_fuse::fuse::protocol::INum;
 use crate::common::error::DatenLordResult;
 
@@ -86,12 +85,12 @@ pub trait Node: Sized {
     ) -> DatenLordResult<Self>;
     /// Get symlink target path
     fn get_symlink_target(&self) -> &Path;
-    /// Get fs stat
-    async fn statefs(&self) -> DatenLordResult<StatFsParam>;
     /// Mark as deferred deletion
     fn mark_deferred_deletion(&self);
     /// If node is marked as deferred deletion
     fn is_deferred_deletion(&self) -> bool;
+    /// Clear the mark of deferred deletion, when it's restored from the trash
+    fn clear_deferred_deletion(&self);
 
     /// Create child node
     async fn create_child_node<T: MetaTxn + ?Sized>(
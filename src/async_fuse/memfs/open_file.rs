@@ -15,8 +15,26 @@ use crate::async_fuse::fuse::protocol::INum;
 pub struct RawOpenFile {
     /// The file attributes.
     pub attr: FileAttr,
+    /// The content of a small file inlined in its node when it's opened, it's
+    /// dropped once the file is changed.
+    pub inline_data: Option<Arc<[u8]>>,
     /// The number of times this file is currently opened.
     open_cnt: u32,
+    /// Whether this file is kept for the delegation of this node, even if
+    /// it's not opened.
+    delegated: bool,
+}
+
+impl RawOpenFile {
+    /// Creates a file not opened yet with its attributes.
+    fn new(attr: FileAttr) -> Self {
+        Self {
+            attr,
+            inline_data: None,
+            open_cnt: 0,
+            delegated: false,
+        }
+    }
 }
 
 /// A thread-safe reference counted wrapper around `RawOpenFile`.
@@ -66,6 +84,15 @@ impl OpenFiles {
         open_file
     }
 
+    /// Whether a file is opened, rather than kept for the delegation only.
+    pub fn is_open(&self, inum: INum) -> bool {
+        let inner = self.inner.lock();
+        inner
+            .open_files
+            .get(&inum)
+            .is_some_and(|open_file| open_file.read().open_cnt > 0)
+    }
+
     /// Retrieves an `OpenFile` by its inode number.
     ///
     /// Panics if the file is not found.
@@ -74,15 +101,17 @@ impl OpenFiles {
             .unwrap_or_else(|| panic!("Couldn't find the open file with inum={inum}"))
     }
 
-    /// Opens a file, adding it to the collection
+    /// Opens a file, adding it to the collection with the content inlined in
+    /// its node if there's any.
     ///
     /// Returns a reference to the newly opened file.
-    pub fn open(&self, inum: INum, attr: FileAttr) -> OpenFile {
+    pub fn open(&self, inum: INum, attr: FileAttr, inline_data: Option<&[u8]>) -> OpenFile {
         let mut inner = self.inner.lock();
-        let open_file = inner
-            .open_files
-            .entry(inum)
-            .or_insert_with(|| Arc::new(RwLock::new(RawOpenFile { attr, open_cnt: 0 })));
+        let open_file = inner.open_files.entry(inum).or_insert_with(|| {
+            let mut open_file = RawOpenFile::new(attr);
+            open_file.inline_data = inline_data.map(Arc::from);
+            Arc::new(RwLock::new(open_file))
+        });
         {
             let mut open_file = open_file.write();
             open_file.open_cnt += 1;
@@ -90,6 +119,36 @@ impl OpenFiles {
         Arc::clone(open_file)
     }
 
+    /// Keeps a file in the collection for the delegation of this node, until
+    /// `undelegate()` is called.
+    pub fn delegate(&self, inum: INum, attr: FileAttr) {
+        let mut inner = self.inner.lock();
+        let open_file = inner
+            .open_files
+            .entry(inum)
+            .or_insert_with(|| Arc::new(RwLock::new(RawOpenFile::new(attr))));
+        open_file.write().delegated = true;
+    }
+
+    /// Stops keeping a file for the delegation of this node.
+    ///
+    /// Removes the file from the collection if it's not opened. Returns the
+    /// `OpenFile` if it was removed, or `None` if it remains open.
+    pub fn undelegate(&self, inum: INum) -> Option<OpenFile> {
+        let mut inner = self.inner.lock();
+        let open_count = {
+            let open_file = inner.open_files.get_mut(&inum)?;
+            let mut open_file = open_file.write();
+            open_file.delegated = false;
+            open_file.open_cnt
+        };
+        if open_count == 0 {
+            inner.open_files.remove(&inum)
+        } else {
+            None
+        }
+    }
+
     /// Tries to open a file if it is already in the collection.
     ///
     /// Increments the open count if the file is found. Returns `Some(OpenFile)`
@@ -107,12 +166,12 @@ impl OpenFiles {
     /// Closes an open file, identified by its inode number.
     ///
     /// Decrements the open count and removes the file from the collection
-    /// if its open count reaches zero. Returns the `OpenFile` if it was
-    /// removed, or `None` if it remains open.
+    /// if its open count reaches zero, unless it's kept for the delegation.
+    /// Returns the `OpenFile` if it was removed, or `None` if it remains.
     #[allow(clippy::unwrap_in_result)]
     pub fn close(&self, inum: INum) -> Option<OpenFile> {
         let mut inner = self.inner.lock();
-        let open_count = {
+        let (open_count, delegated) = {
             let open_file = inner
                 .open_files
                 .get_mut(&inum)
@@ -120,9 +179,9 @@ impl OpenFiles {
             let mut open_file = open_file.write();
             debug_assert!(open_file.open_cnt > 0);
             open_file.open_cnt -= 1;
-            open_file.open_cnt
+            (open_file.open_cnt, open_file.delegated)
         };
-        if open_count == 0 {
+        if open_count == 0 && !delegated {
             inner.open_files.remove(&inum)
         } else {
             None
@@ -0,0 +1,117 @@
+//! The opens of files among the nodes of a volume.
+//!
+//! A file unlinked while it's open is kept until it's closed, so the classic
+//! pattern of unlinking a temporary file and keeping it open works on a
+//! shared volume as well. The kernel of a node keeps the files opened on it
+//! referenced, but the gateways and the nodes of other mounts don't, so the
+//! nodes opening a file are recorded with it in the kv engine, and a removed
+//! file is deleted by the release of its last open among the nodes.
+//!
+//! A node is recorded by its first open of a file and dropped by its last
+//! release. The opens of a node no longer registered, which has been
+//! unmounted or is likely gone, don't keep a file, and the current node
+//! knows its own opens without the record.
+
+use serde::{Deserialize, Serialize};
+
+use super::kv_engine::{KeyType, MetaTxn, ValueType};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::common::error::DatenLordResult;
+
+/// The nodes opening a file.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileOpeners {
+    /// The ids of the nodes, sorted
+    pub nodes: Vec<String>,
+}
+
+impl FileOpeners {
+    /// Add node `node_id`.
+    fn add(&mut self, node_id: &str) {
+        if let Err(index) = self
+            .nodes
+            .binary_search_by(|node| node.as_str().cmp(node_id))
+        {
+            self.nodes.insert(index, node_id.to_owned());
+        }
+    }
+
+    /// Remove node `node_id`.
+    fn remove(&mut self, node_id: &str) {
+        self.nodes.retain(|node| node != node_id);
+    }
+}
+
+/// Load the nodes opening `ino`.
+async fn load<T: MetaTxn + ?Sized>(txn: &mut T, ino: INum) -> DatenLordResult<FileOpeners> {
+    Ok(txn
+        .get(&KeyType::FileOpeners(ino))
+        .await?
+        .map_or_else(FileOpeners::default, ValueType::into_file_openers))
+}
+
+/// Record that node `node_id` opens `ino`.
+pub(super) async fn record_open<T: MetaTxn + ?Sized>(
+    txn: &mut T,
+    ino: INum,
+    node_id: &str,
+) -> DatenLordResult<()> {
+    let mut openers = load(txn, ino).await?;
+    openers.add(node_id);
+    txn.set(&KeyType::FileOpeners(ino), &ValueType::FileOpeners(openers));
+    Ok(())
+}
+
+/// Record that node `node_id` no longer opens `ino`.
+pub(super) async fn record_release<T: MetaTxn + ?Sized>(
+    txn: &mut T,
+    ino: INum,
+    node_id: &str,
+) -> DatenLordResult<()> {
+    let mut openers = load(txn, ino).await?;
+    openers.remove(node_id);
+    if openers.nodes.is_empty() {
+        txn.delete(&KeyType::FileOpeners(ino));
+    } else {
+        txn.set(&KeyType::FileOpeners(ino), &ValueType::FileOpeners(openers));
+    }
+    Ok(())
+}
+
+/// Whether `ino` is opened by any node still registered other than node
+/// `node_id`, which is the current one.
+pub(super) async fn is_opened_by_others<T: MetaTxn + ?Sized>(
+    txn: &mut T,
+    ino: INum,
+    node_id: &str,
+) -> DatenLordResult<bool> {
+    let openers = load(txn, ino).await?;
+    for node in openers.nodes.iter().filter(|node| *node != node_id) {
+        if txn
+            .get(&KeyType::NodeRegistration(node.clone()))
+            .await?
+            .is_some()
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileOpeners;
+
+    #[test]
+    fn test_file_openers() {
+        let mut openers = FileOpeners::default();
+        openers.add("node2");
+        openers.add("node1");
+        openers.add("node2");
+        assert_eq!(openers.nodes, vec!["node1", "node2"]);
+
+        openers.remove("node2");
+        openers.remove("node3");
+        assert_eq!(openers.nodes, vec!["node1"]);
+    }
+}
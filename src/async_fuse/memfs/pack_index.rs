@@ -0,0 +1,124 @@
+//! The `PackIndex` persisted in the kv engine.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use super::dedup_index::apply_deltas;
+use super::kv_engine::{KVEngine, KVEngineType, KeyType, MetaTxn, ValueType};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::common::error::DatenLordResult;
+use crate::storage::dedup::{ChunkHash, RefDeltas};
+use crate::storage::error::{StorageError, StorageResult};
+use crate::storage::pack::{PackIndex, PackedBlock};
+
+/// The limit of retrying a transaction.
+const TXN_RETRY_LIMIT: u32 = 10;
+
+/// A `PackIndex` persisted in the kv engine, the refcounts of containers
+/// share the ones of chunks, so the GC sees containers as chunks.
+#[derive(Debug)]
+pub struct KvPackIndex {
+    /// The kv engine
+    kv_engine: Arc<KVEngineType>,
+}
+
+impl KvPackIndex {
+    /// Create a `KvPackIndex`.
+    #[must_use]
+    pub fn new(kv_engine: Arc<KVEngineType>) -> Self {
+        Self { kv_engine }
+    }
+
+    /// Set the records of packed blocks in a transaction.
+    async fn set_packed_impl(
+        &self,
+        blocks: &[(INum, PackedBlock)],
+    ) -> DatenLordResult<Vec<ChunkHash>> {
+        let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            let mut deltas = RefDeltas::default();
+            for &(ino, ref block) in blocks {
+                let key = KeyType::PackedBlock(ino, block.block_id);
+                if let Some(hash) = block.container() {
+                    deltas.add_chunk(hash);
+                }
+                if let Some(old) = txn.get(&key).await? {
+                    if let Some(hash) = old.into_packed_block().container() {
+                        deltas.sub_chunk(hash);
+                    }
+                }
+                txn.set(&key, &ValueType::PackedBlock(block.clone()));
+            }
+            let orphans = apply_deltas(txn.as_mut(), &deltas).await?;
+            (txn.commit().await, orphans)
+        });
+        res
+    }
+
+    /// Remove the records of packed blocks in a transaction.
+    async fn remove_packed_impl(
+        &self,
+        ino: INum,
+        block_ids: Range<usize>,
+    ) -> DatenLordResult<Vec<ChunkHash>> {
+        let block_ids: Vec<usize> = self
+            .kv_engine
+            .range(&KeyType::FilePackedBlocks(ino))
+            .await?
+            .into_iter()
+            .map(|value| value.into_packed_block().block_id)
+            .filter(|block_id| block_ids.contains(block_id))
+            .collect();
+        if block_ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            let mut deltas = RefDeltas::default();
+            for &block_id in &block_ids {
+                let key = KeyType::PackedBlock(ino, block_id);
+                if let Some(old) = txn.get(&key).await? {
+                    if let Some(hash) = old.into_packed_block().container() {
+                        deltas.sub_chunk(hash);
+                    }
+                    txn.delete(&key);
+                }
+            }
+            let orphans = apply_deltas(txn.as_mut(), &deltas).await?;
+            (txn.commit().await, orphans)
+        });
+        res
+    }
+}
+
+#[async_trait]
+impl PackIndex for KvPackIndex {
+    async fn get_packed(&self, ino: INum, block_id: usize) -> StorageResult<Option<PackedBlock>> {
+        let value = self
+            .kv_engine
+            .get(&KeyType::PackedBlock(ino, block_id))
+            .await;
+        value
+            .map_err(StorageError::from)
+            .map(|value| value.map(ValueType::into_packed_block))
+    }
+
+    async fn set_packed(&self, blocks: Vec<(INum, PackedBlock)>) -> StorageResult<Vec<ChunkHash>> {
+        self.set_packed_impl(&blocks)
+            .await
+            .map_err(StorageError::from)
+    }
+
+    async fn remove_packed(
+        &self,
+        ino: INum,
+        block_ids: Range<usize>,
+    ) -> StorageResult<Vec<ChunkHash>> {
+        self.remove_packed_impl(ino, block_ids)
+            .await
+            .map_err(StorageError::from)
+    }
+}
@@ -0,0 +1,359 @@
+//! The pins of files in the local cache.
+//!
+//! A pinned file is kept in the lowest local cache of the node, the disk
+//! cache if it's enabled or the memory cache otherwise, as its blocks are
+//! never evicted from it until the file is unpinned. A path is pinned by the
+//! pin role on the control socket, which pins the files of the path, and all
+//! the files below it if it's a directory, and loads them as a warm-up, see
+//! `WARMUP`. The files are pinned by the sizes they have then, so the ones
+//! added below a directory later are pinned by pinning the directory again.
+//!
+//! A file pinned by several paths is pinned until all of them are unpinned.
+//! The pinned blocks take `--storage-pin-limit` percent of the cache at most,
+//! and a pin beyond it fails as a whole, so the cache is always left to the
+//! other files. The files removed stay pinned until their paths are unpinned.
+
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Weak};
+
+use clippy_utilities::{Cast, OverflowArithmetic};
+use nix::errno::Errno;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use super::inspect::StorageManagerType;
+use super::warmup::components;
+use super::StorageType;
+use crate::async_fuse::fuse::protocol::INum;
+use crate::async_fuse::util::build_error_result_from_errno;
+use crate::common::error::DatenLordResult;
+use crate::storage::Storage;
+
+/// The pins of this process.
+pub static PINS: Lazy<Pins> = Lazy::new(Pins::default);
+
+/// A path pinned.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pin {
+    /// The path below the root of the volume
+    pub path: String,
+    /// The files pinned by the path
+    pub files: usize,
+    /// The blocks of the files
+    pub blocks: usize,
+    /// The bytes of the files
+    pub bytes: u64,
+}
+
+/// The paths pinned in the mount.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PinList {
+    /// The paths pinned, in their order
+    pub pins: Vec<Pin>,
+    /// The blocks pinned, each file counted once
+    pub blocks: usize,
+    /// The blocks pinned at most
+    pub limit: usize,
+    /// The size of the blocks
+    pub block_size: usize,
+}
+
+/// The files pinned by a path.
+#[derive(Debug, Clone, Default)]
+struct PinnedPath {
+    /// The files pinned
+    files: Vec<INum>,
+    /// The blocks of the files
+    blocks: usize,
+    /// The bytes of the files
+    bytes: u64,
+}
+
+/// A file pinned.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct PinnedFile {
+    /// The number of the paths pinning the file
+    refs: usize,
+    /// The blocks pinned from the start of the file
+    blocks: usize,
+}
+
+/// The paths pinned and the files pinned by them.
+#[derive(Debug, Clone, Default)]
+struct Pinned {
+    /// The path below the root of the volume -> the files pinned by it
+    paths: BTreeMap<String, PinnedPath>,
+    /// The files pinned
+    files: HashMap<INum, PinnedFile>,
+}
+
+impl Pinned {
+    /// The blocks pinned, each file counted once.
+    fn blocks(&self) -> usize {
+        self.files.values().map(|file| file.blocks).sum()
+    }
+
+    /// Pin `files` of their sizes by `path`, which replaces the files pinned
+    /// by it before.
+    fn insert(&mut self, path: String, files: &[(INum, u64)], block_size: usize) {
+        self.remove(&path);
+        let mut pinned = PinnedPath::default();
+        for &(ino, size) in files {
+            let blocks: usize = size.div_ceil(block_size.cast()).cast();
+            let file = self.files.entry(ino).or_default();
+            file.refs = file.refs.overflow_add(1);
+            file.blocks = file.blocks.max(blocks);
+            pinned.files.push(ino);
+            pinned.blocks = pinned.blocks.overflow_add(blocks);
+            pinned.bytes = pinned.bytes.overflow_add(size);
+        }
+        self.paths.insert(path, pinned);
+    }
+
+    /// Unpin `path`, returns the files pinned by it, `None` if it's not
+    /// pinned.
+    fn remove(&mut self, path: &str) -> Option<PinnedPath> {
+        let pinned = self.paths.remove(path)?;
+        for &ino in &pinned.files {
+            if let Entry::Occupied(mut file) = self.files.entry(ino) {
+                let refs = file.get().refs.overflow_sub(1);
+                if refs == 0 {
+                    file.remove();
+                } else {
+                    file.get_mut().refs = refs;
+                }
+            }
+        }
+        Some(pinned)
+    }
+
+    /// The files whose pinned blocks differ in `to`, with the blocks pinned
+    /// here and in `to`.
+    fn changes(&self, to: &Self) -> Vec<(INum, usize, usize)> {
+        let blocks =
+            |pinned: &Self, ino: INum| pinned.files.get(&ino).map_or(0, |file| file.blocks);
+        self.files
+            .keys()
+            .chain(to.files.keys().filter(|&ino| !self.files.contains_key(ino)))
+            .map(|&ino| (ino, blocks(self, ino), blocks(to, ino)))
+            .filter(|&(_, from, to)| from != to)
+            .collect()
+    }
+}
+
+/// The state registered by the file system mounted.
+#[derive(Debug)]
+struct Registered {
+    /// The storage of the file system
+    storage: Weak<StorageManagerType>,
+    /// The mount point, stripped from the paths under it
+    mount_point: String,
+    /// The max percentage of the cache taken by the pinned blocks, `None` if
+    /// pinning is disabled
+    limit: Option<u32>,
+    /// The size of the blocks
+    block_size: usize,
+    /// The paths pinned
+    pinned: Pinned,
+}
+
+impl Registered {
+    /// The path below the root of the volume of `path`, which is relative to
+    /// the root, or under the mount point.
+    fn normalize(&self, path: &str) -> DatenLordResult<String> {
+        Ok(format!(
+            "/{}",
+            components(path, &self.mount_point)?.join("/")
+        ))
+    }
+
+    /// The blocks pinned at most in the cache of `storage`.
+    fn limit_blocks(&self, storage: &StorageManagerType) -> usize {
+        let percent: usize = self.limit.unwrap_or(0).cast();
+        storage
+            .storage()
+            .pin_capacity()
+            .overflow_mul(percent)
+            .overflow_div(100)
+    }
+
+    /// Apply the pins of `pinned` to the cache of `storage`.
+    fn apply(&mut self, storage: &StorageManagerType, pinned: Pinned) {
+        let cache = storage.storage();
+        for (ino, from, to) in self.pinned.changes(&pinned) {
+            if to > from {
+                cache.pin(ino, from..to);
+            } else {
+                cache.unpin(ino, to..from);
+            }
+        }
+        self.pinned = pinned;
+    }
+}
+
+/// The pins of the file system mounted.
+#[derive(Debug, Default)]
+pub struct Pins {
+    /// The state registered, `None` if no file system is mounted
+    registered: Mutex<Option<Registered>>,
+}
+
+impl Pins {
+    /// Register the file system mounted at `mount_point` with `storage` of
+    /// blocks of `block_size`, whose pins take `limit` percent of the cache
+    /// at most. It replaces the one registered before, with its pins.
+    pub(super) fn register(
+        &self,
+        storage: &StorageType,
+        mount_point: &str,
+        limit: Option<u32>,
+        block_size: usize,
+    ) {
+        *self.registered.lock() = Some(Registered {
+            storage: Arc::downgrade(storage),
+            mount_point: mount_point.to_owned(),
+            limit,
+            block_size,
+            pinned: Pinned::default(),
+        });
+    }
+
+    /// Pin the files listed of every path with their sizes, which replace the
+    /// files pinned by the path before. None of them is pinned if the blocks
+    /// pinned would exceed the limit.
+    pub(super) fn pin(&self, paths: Vec<(String, Vec<(INum, u64)>)>) -> DatenLordResult<()> {
+        let mut registered = self.registered.lock();
+        let (registered, storage) = mounted(&mut registered)?;
+        if registered.limit.is_none() {
+            return build_error_result_from_errno(
+                Errno::EINVAL,
+                "pinning is disabled by the storage pin limit".to_owned(),
+            );
+        }
+
+        let mut pinned = registered.pinned.clone();
+        for (path, files) in paths {
+            let path = registered.normalize(&path)?;
+            pinned.insert(path, &files, registered.block_size);
+        }
+        let (blocks, limit) = (pinned.blocks(), registered.limit_blocks(&storage));
+        if blocks > limit {
+            return build_error_result_from_errno(
+                Errno::ENOSPC,
+                format!("{blocks} blocks to pin exceed the limit of {limit} blocks"),
+            );
+        }
+        registered.apply(&storage, pinned);
+        Ok(())
+    }
+
+    /// Unpin `paths`, returns the pins removed. None of them is unpinned if
+    /// any is not pinned.
+    pub fn unpin(&self, paths: &[String]) -> DatenLordResult<Vec<Pin>> {
+        let mut registered = self.registered.lock();
+        let (registered, storage) = mounted(&mut registered)?;
+
+        let mut pinned = registered.pinned.clone();
+        let mut removed = Vec::with_capacity(paths.len());
+        for path in paths {
+            let path = registered.normalize(path)?;
+            let Some(files) = pinned.remove(&path) else {
+                return build_error_result_from_errno(
+                    Errno::ENOENT,
+                    format!("{path} is not pinned"),
+                );
+            };
+            removed.push(pin_of(path, &files));
+        }
+        registered.apply(&storage, pinned);
+        Ok(removed)
+    }
+
+    /// List the paths pinned, `None` if no file system is mounted.
+    #[must_use]
+    pub fn list(&self) -> Option<PinList> {
+        let registered = self.registered.lock();
+        let registered = registered.as_ref()?;
+        let storage = registered.storage.upgrade()?;
+        Some(PinList {
+            pins: registered
+                .pinned
+                .paths
+                .iter()
+                .map(|(path, files)| pin_of(path.clone(), files))
+                .collect(),
+            blocks: registered.pinned.blocks(),
+            limit: registered.limit_blocks(&storage),
+            block_size: registered.block_size,
+        })
+    }
+}
+
+/// The state registered in `registered` with its storage, or an error if no
+/// file system is mounted.
+fn mounted(
+    registered: &mut Option<Registered>,
+) -> DatenLordResult<(&mut Registered, Arc<StorageManagerType>)> {
+    let storage = registered
+        .as_ref()
+        .and_then(|registered| registered.storage.upgrade());
+    match (registered.as_mut(), storage) {
+        (Some(registered), Some(storage)) => Ok((registered, storage)),
+        (None, _) | (_, None) => {
+            build_error_result_from_errno(Errno::ENOTCONN, "no file system is mounted".to_owned())
+        }
+    }
+}
+
+/// The pin of `path` pinning `files`.
+fn pin_of(path: String, files: &PinnedPath) -> Pin {
+    Pin {
+        path,
+        files: files.files.len(),
+        blocks: files.blocks,
+        bytes: files.bytes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pinned;
+
+    #[test]
+    fn test_pinned() {
+        let mut pinned = Pinned::default();
+        pinned.insert("/data".to_owned(), &[(2, 10), (3, 4096)], 4096);
+        pinned.insert("/data/a".to_owned(), &[(2, 8192)], 4096);
+        // The blocks of a file pinned by both paths are counted once.
+        assert_eq!(pinned.blocks(), 3);
+        let pins: Vec<(&str, usize)> = pinned
+            .paths
+            .iter()
+            .map(|(path, files)| (path.as_str(), files.blocks))
+            .collect();
+        assert_eq!(pins, [("/data", 2), ("/data/a", 2)]);
+
+        // Pinning a path again replaces its files.
+        let mut repinned = pinned.clone();
+        repinned.insert("/data".to_owned(), &[(3, 4096), (4, 1)], 4096);
+        let mut changes = pinned.changes(&repinned);
+        changes.sort_unstable();
+        assert_eq!(changes, [(4, 0, 1)]);
+
+        // A file is pinned until all the paths pinning it are unpinned.
+        let mut unpinned = repinned.clone();
+        assert_eq!(
+            unpinned.remove("/data").map(|files| files.bytes),
+            Some(4097)
+        );
+        let mut changes = repinned.changes(&unpinned);
+        changes.sort_unstable();
+        assert_eq!(changes, [(3, 1, 0), (4, 1, 0)]);
+        assert_eq!(unpinned.blocks(), 2);
+        assert!(unpinned.remove("/data").is_none());
+        assert!(unpinned.remove("/data/a").is_some());
+        assert!(unpinned.files.is_empty());
+    }
+}
@@ -0,0 +1,638 @@
+//! The POSIX ACLs of files and directories.
+//!
+//! The ACLs are set and got by the kernel as the extended attributes
+//! [`ACL_ACCESS_XATTR`] and [`ACL_DEFAULT_XATTR`], in the format of
+//! `posix_acl_xattr`. With `FUSE_POSIX_ACL`, the kernel checks the permissions
+//! against the access ACLs itself, and the ACLs are kept in the kv engine and
+//! in sync with the modes here: the permission bits of a mode are the ones of
+//! the owner, of the mask or the owning group if there's no mask, and of the
+//! others in the access ACL, and changing the mode changes them in the ACL.
+//!
+//! A node created in a directory with a default ACL gets an access ACL from
+//! it, masked by the mode requested, and a directory inherits the default ACL
+//! besides. The umask is not applied to such a node.
+
+use clippy_utilities::{Cast, OverflowArithmetic};
+use nix::errno::Errno;
+use nix::sys::stat::SFlag;
+use serde::{Deserialize, Serialize};
+
+use super::fs_util::FileAttr;
+use super::kv_engine::{KeyType, MetaTxn, ValueType};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::async_fuse::util::build_error_result_from_errno;
+use crate::common::error::{Context, DatenLordResult};
+use crate::function_name;
+
+/// The extended attribute of the access ACL.
+pub const ACL_ACCESS_XATTR: &str = "system.posix_acl_access";
+
+/// The extended attribute of the default ACL of a directory.
+pub const ACL_DEFAULT_XATTR: &str = "system.posix_acl_default";
+
+/// The version of `posix_acl_xattr`.
+const ACL_XATTR_VERSION: u32 = 2;
+/// The size of the header of `posix_acl_xattr`.
+const ACL_XATTR_HEADER_SIZE: usize = 4;
+/// The size of an entry of `posix_acl_xattr`.
+const ACL_XATTR_ENTRY_SIZE: usize = 8;
+
+/// The tag of the entry of the owner.
+const ACL_USER_OBJ: u16 = 0x01;
+/// The tag of the entries of named users.
+const ACL_USER: u16 = 0x02;
+/// The tag of the entry of the owning group.
+const ACL_GROUP_OBJ: u16 = 0x04;
+/// The tag of the entries of named groups.
+const ACL_GROUP: u16 = 0x08;
+/// The tag of the entry of the mask.
+const ACL_MASK: u16 = 0x10;
+/// The tag of the entry of others.
+const ACL_OTHER: u16 = 0x20;
+
+/// The id of the entries other than the named users and groups.
+const ACL_UNDEFINED_ID: u32 = u32::MAX;
+
+/// The permission bits of an entry.
+const ACL_PERM_BITS: u16 = 0o7;
+/// The permission bits of a mode of the owner, the group and others.
+const MODE_PERM_BITS: u16 = 0o777;
+
+/// The kind of an ACL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AclKind {
+    /// The ACL checked when the node is accessed
+    Access,
+    /// The ACL inherited by the nodes created in a directory
+    Default,
+}
+
+impl AclKind {
+    /// The key of the ACL of this kind of `ino`.
+    #[must_use]
+    pub fn key(self, ino: INum) -> KeyType {
+        match self {
+            Self::Access => KeyType::AccessAcl(ino),
+            Self::Default => KeyType::DefaultAcl(ino),
+        }
+    }
+
+    /// The kind of the ACL of an extended attribute, `None` if it's not one of
+    /// an ACL.
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            ACL_ACCESS_XATTR => Some(Self::Access),
+            ACL_DEFAULT_XATTR => Some(Self::Default),
+            _ => None,
+        }
+    }
+
+    /// The extended attribute of the ACL.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Access => ACL_ACCESS_XATTR,
+            Self::Default => ACL_DEFAULT_XATTR,
+        }
+    }
+}
+
+/// An entry of an ACL.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AclEntry {
+    /// The tag
+    pub tag: u16,
+    /// The permission bits, of read, write and execute
+    pub perm: u16,
+    /// The uid or gid of a named user or group, or `ACL_UNDEFINED_ID`
+    pub id: u32,
+}
+
+/// A POSIX ACL, whose entries are sorted by their tags and ids.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PosixAcl {
+    /// The entries
+    entries: Vec<AclEntry>,
+}
+
+impl PosixAcl {
+    /// Decode and check an ACL from the value of its extended attribute.
+    pub fn from_xattr(value: &[u8]) -> DatenLordResult<Self> {
+        let invalid = |reason: &str| {
+            build_error_result_from_errno(Errno::EINVAL, format!("invalid POSIX ACL: {reason}"))
+        };
+        let header = value
+            .get(..ACL_XATTR_HEADER_SIZE)
+            .and_then(|header| <[u8; ACL_XATTR_HEADER_SIZE]>::try_from(header).ok());
+        let (Some(header), Some(body)) = (header, value.get(ACL_XATTR_HEADER_SIZE..)) else {
+            return invalid("no header");
+        };
+        if u32::from_le_bytes(header) != ACL_XATTR_VERSION {
+            return invalid("unknown version");
+        }
+        let chunks = body.chunks_exact(ACL_XATTR_ENTRY_SIZE);
+        if !chunks.remainder().is_empty() {
+            return invalid("truncated entry");
+        }
+        let mut entries: Vec<_> = chunks
+            .filter_map(|chunk| <[u8; ACL_XATTR_ENTRY_SIZE]>::try_from(chunk).ok())
+            .map(|[t0, t1, p0, p1, i0, i1, i2, i3]| AclEntry {
+                tag: u16::from_le_bytes([t0, t1]),
+                perm: u16::from_le_bytes([p0, p1]),
+                id: u32::from_le_bytes([i0, i1, i2, i3]),
+            })
+            .collect();
+        entries.sort_by_key(|entry| (entry.tag, entry.id));
+
+        let count = |tag: u16| entries.iter().filter(|entry| entry.tag == tag).count();
+        for entry in &entries {
+            match entry.tag {
+                ACL_USER_OBJ | ACL_GROUP_OBJ | ACL_MASK | ACL_OTHER => {
+                    if count(entry.tag) != 1 {
+                        return invalid("duplicated entry");
+                    }
+                }
+                ACL_USER | ACL_GROUP => {
+                    if entry.id == ACL_UNDEFINED_ID {
+                        return invalid("named entry without id");
+                    }
+                }
+                _ => return invalid("unknown tag"),
+            }
+            if entry.perm & !ACL_PERM_BITS != 0 {
+                return invalid("unknown permission");
+            }
+        }
+        if entries
+            .iter()
+            .zip(entries.iter().skip(1))
+            .any(|(a, b)| a.tag == b.tag && a.id == b.id)
+        {
+            return invalid("duplicated entry");
+        }
+        if count(ACL_USER_OBJ) != 1 || count(ACL_GROUP_OBJ) != 1 || count(ACL_OTHER) != 1 {
+            return invalid("missing entry");
+        }
+        if count(ACL_MASK) == 0 && (count(ACL_USER) != 0 || count(ACL_GROUP) != 0) {
+            return invalid("missing mask");
+        }
+        Ok(Self { entries })
+    }
+
+    /// Encode the ACL as the value of its extended attribute.
+    #[must_use]
+    pub fn to_xattr(&self) -> Vec<u8> {
+        let mut value = Vec::with_capacity(
+            ACL_XATTR_ENTRY_SIZE
+                .overflow_mul(self.entries.len())
+                .overflow_add(ACL_XATTR_HEADER_SIZE),
+        );
+        value.extend_from_slice(&ACL_XATTR_VERSION.to_le_bytes());
+        for entry in &self.entries {
+            value.extend_from_slice(&entry.tag.to_le_bytes());
+            value.extend_from_slice(&entry.perm.to_le_bytes());
+            value.extend_from_slice(&entry.id.to_le_bytes());
+        }
+        value
+    }
+
+    /// Map the ids of the named users by `uid` and the ones of the named
+    /// groups by `gid`. Of the entries mapped to the same id, such as the
+    /// overflow id, the first is kept.
+    pub fn map_ids(&mut self, uid: impl Fn(u32) -> u32, gid: impl Fn(u32) -> u32) {
+        for entry in &mut self.entries {
+            match entry.tag {
+                ACL_USER => entry.id = uid(entry.id),
+                ACL_GROUP => entry.id = gid(entry.id),
+                _ => {}
+            }
+        }
+        self.entries.sort_by_key(|entry| (entry.tag, entry.id));
+        self.entries.dedup_by_key(|entry| (entry.tag, entry.id));
+    }
+
+    /// Whether the ACL is the same as the permission bits of the mode, which
+    /// has the entries of the owner, the owning group and others only.
+    #[must_use]
+    pub fn is_minimal(&self) -> bool {
+        self.entries.len() == 3
+    }
+
+    /// The permission bits of the entry of `tag`, which is at most once.
+    fn perm_of(&self, tag: u16) -> Option<u16> {
+        self.entries
+            .iter()
+            .find(|entry| entry.tag == tag)
+            .map(|entry| entry.perm)
+    }
+
+    /// Change the permission bits of the entry of `tag`, which is at most
+    /// once, by `f`.
+    fn update_perm(&mut self, tag: u16, f: impl FnOnce(u16) -> u16) {
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.tag == tag) {
+            entry.perm = f(entry.perm);
+        }
+    }
+
+    /// The tag of the entry of the group class, the mask if there is one, or
+    /// the owning group.
+    fn group_class(&self) -> u16 {
+        if self.perm_of(ACL_MASK).is_some() {
+            ACL_MASK
+        } else {
+            ACL_GROUP_OBJ
+        }
+    }
+
+    /// The permission bits of the mode by the ACL.
+    #[must_use]
+    pub fn mode_perm(&self) -> u16 {
+        let perm = |tag| self.perm_of(tag).unwrap_or(0);
+        (perm(ACL_USER_OBJ) << 6) | (perm(self.group_class()) << 3) | perm(ACL_OTHER)
+    }
+
+    /// Set the permission bits of the mode to the ACL, as `chmod(2)` does.
+    pub fn chmod(&mut self, mode_perm: u16) {
+        let group_class = self.group_class();
+        self.update_perm(ACL_USER_OBJ, |_| (mode_perm >> 6) & ACL_PERM_BITS);
+        self.update_perm(group_class, |_| (mode_perm >> 3) & ACL_PERM_BITS);
+        self.update_perm(ACL_OTHER, |_| mode_perm & ACL_PERM_BITS);
+    }
+
+    /// The permission bits `perm` of a mode, whose ones of the owner, the
+    /// group class and others are replaced by the ACL.
+    #[must_use]
+    pub fn apply_to_perm(&self, perm: u16) -> u16 {
+        (perm & !MODE_PERM_BITS) | self.mode_perm()
+    }
+
+    /// The access ACL of a node created with the permission bits `mode_perm`
+    /// in a directory of this default ACL. The entries of the owner, the group
+    /// class and others are masked by the mode.
+    #[must_use]
+    pub fn inherit(&self, mode_perm: u16) -> Self {
+        let mut acl = self.clone();
+        let group_class = acl.group_class();
+        acl.update_perm(ACL_USER_OBJ, |perm| perm & (mode_perm >> 6));
+        acl.update_perm(group_class, |perm| perm & (mode_perm >> 3));
+        acl.update_perm(ACL_OTHER, |perm| perm & mode_perm);
+        acl
+    }
+}
+
+/// Set the ACLs and the permission bits of `attr` of node `ino` created in
+/// directory `parent` in `txn`. The node inherits the default ACL of the
+/// parent if there's one, or `umask` is applied.
+pub(super) async fn inherit_in_txn<T: MetaTxn + ?Sized>(
+    txn: &mut T,
+    parent: INum,
+    ino: INum,
+    umask: u32,
+    attr: &mut FileAttr,
+) -> DatenLordResult<()> {
+    let default_acl = txn
+        .get(&KeyType::DefaultAcl(parent))
+        .await
+        .add_context(format!(
+            "{}() failed to get default ACL of ino={parent} from kv engine",
+            function_name!()
+        ))?;
+    let Some(default_acl) = default_acl.map(ValueType::into_posix_acl) else {
+        attr.perm &= !(umask & 0o777).cast::<u16>();
+        return Ok(());
+    };
+    let access_acl = default_acl.inherit(attr.perm);
+    attr.perm = access_acl.apply_to_perm(attr.perm);
+    if !access_acl.is_minimal() {
+        txn.set(&KeyType::AccessAcl(ino), &ValueType::PosixAcl(access_acl));
+    }
+    if attr.kind == SFlag::S_IFDIR {
+        txn.set(&KeyType::DefaultAcl(ino), &ValueType::PosixAcl(default_acl));
+    }
+    Ok(())
+}
+
+/// Set the permission bits `perm` of the mode of `ino` to its access ACL in
+/// `txn`, if it has one.
+pub(super) async fn chmod_in_txn<T: MetaTxn + ?Sized>(
+    txn: &mut T,
+    ino: INum,
+    perm: u16,
+) -> DatenLordResult<()> {
+    let key = KeyType::AccessAcl(ino);
+    let acl = txn.get(&key).await.add_context(format!(
+        "{}() failed to get access ACL of ino={ino} from kv engine",
+        function_name!()
+    ))?;
+    if let Some(mut acl) = acl.map(ValueType::into_posix_acl) {
+        acl.chmod(perm);
+        txn.set(&key, &ValueType::PosixAcl(acl));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::sync::Arc;
+
+    use nix::sys::stat::SFlag;
+
+    use super::{
+        AclKind, PosixAcl, ACL_GROUP, ACL_GROUP_OBJ, ACL_MASK, ACL_OTHER, ACL_USER, ACL_USER_OBJ,
+    };
+    use crate::async_fuse::fuse::protocol::{INum, FUSE_ROOT_ID};
+    use crate::async_fuse::memfs::kv_engine::{KVEngine, KeyType};
+    use crate::async_fuse::memfs::metadata::ReqContext;
+    use crate::async_fuse::memfs::security_label::SELINUX_XATTR;
+    use crate::async_fuse::memfs::{
+        CreateParam, MetaData, MetaDataOptions, MetaOp, MetaOpResult, S3MetaData,
+    };
+    use crate::async_fuse::test::test_util::open_kv_engine;
+
+    /// Encode the entries of (tag, perm, id) as an extended attribute.
+    fn xattr(entries: &[(u16, u16, u32)]) -> Vec<u8> {
+        let mut value = 2_u32.to_le_bytes().to_vec();
+        for &(tag, perm, id) in entries {
+            value.extend_from_slice(&tag.to_le_bytes());
+            value.extend_from_slice(&perm.to_le_bytes());
+            value.extend_from_slice(&id.to_le_bytes());
+        }
+        value
+    }
+
+    #[test]
+    fn test_acl_xattr() {
+        let value = xattr(&[
+            (ACL_USER_OBJ, 7, u32::MAX),
+            (ACL_USER, 6, 1000),
+            (ACL_GROUP_OBJ, 5, u32::MAX),
+            (ACL_GROUP, 4, 100),
+            (ACL_MASK, 6, u32::MAX),
+            (ACL_OTHER, 0, u32::MAX),
+        ]);
+        let acl = PosixAcl::from_xattr(&value).unwrap();
+        assert_eq!(acl.to_xattr(), value);
+        assert!(!acl.is_minimal());
+        // The group class is the mask.
+        assert_eq!(acl.mode_perm(), 0o760);
+
+        let minimal = xattr(&[
+            (ACL_USER_OBJ, 6, u32::MAX),
+            (ACL_GROUP_OBJ, 4, u32::MAX),
+            (ACL_OTHER, 4, u32::MAX),
+        ]);
+        let acl = PosixAcl::from_xattr(&minimal).unwrap();
+        assert!(acl.is_minimal());
+        assert_eq!(acl.mode_perm(), 0o644);
+    }
+
+    #[test]
+    fn test_invalid_acl() {
+        // A named user without a mask.
+        let value = xattr(&[
+            (ACL_USER_OBJ, 7, u32::MAX),
+            (ACL_USER, 6, 1000),
+            (ACL_GROUP_OBJ, 5, u32::MAX),
+            (ACL_OTHER, 0, u32::MAX),
+        ]);
+        assert!(PosixAcl::from_xattr(&value).is_err());
+        // No entry of others.
+        let value = xattr(&[(ACL_USER_OBJ, 7, u32::MAX), (ACL_GROUP_OBJ, 5, u32::MAX)]);
+        assert!(PosixAcl::from_xattr(&value).is_err());
+        // A truncated entry.
+        let mut value = xattr(&[
+            (ACL_USER_OBJ, 7, u32::MAX),
+            (ACL_GROUP_OBJ, 5, u32::MAX),
+            (ACL_OTHER, 0, u32::MAX),
+        ]);
+        value.pop();
+        assert!(PosixAcl::from_xattr(&value).is_err());
+        assert!(PosixAcl::from_xattr(&[]).is_err());
+    }
+
+    #[test]
+    fn test_acl_chmod_and_inherit() {
+        let default_acl = PosixAcl::from_xattr(&xattr(&[
+            (ACL_USER_OBJ, 7, u32::MAX),
+            (ACL_USER, 7, 1000),
+            (ACL_GROUP_OBJ, 7, u32::MAX),
+            (ACL_MASK, 7, u32::MAX),
+            (ACL_OTHER, 5, u32::MAX),
+        ]))
+        .unwrap();
+        // The mode requested masks the owner, the mask and others, but not
+        // the named user.
+        let mut acl = default_acl.inherit(0o640);
+        // The setuid bit is kept.
+        assert_eq!(acl.apply_to_perm(0o4777), 0o4640);
+        assert_eq!(
+            acl.to_xattr(),
+            xattr(&[
+                (ACL_USER_OBJ, 6, u32::MAX),
+                (ACL_USER, 7, 1000),
+                (ACL_GROUP_OBJ, 7, u32::MAX),
+                (ACL_MASK, 4, u32::MAX),
+                (ACL_OTHER, 0, u32::MAX),
+            ])
+        );
+
+        acl.chmod(0o751);
+        assert_eq!(acl.mode_perm(), 0o751);
+        assert_eq!(
+            acl.to_xattr(),
+            xattr(&[
+                (ACL_USER_OBJ, 7, u32::MAX),
+                (ACL_USER, 7, 1000),
+                (ACL_GROUP_OBJ, 7, u32::MAX),
+                (ACL_MASK, 5, u32::MAX),
+                (ACL_OTHER, 1, u32::MAX),
+            ])
+        );
+    }
+
+    /// Create a node named `name` under directory `parent` with the umask
+    /// `0o022`, returns its i-number and mode.
+    async fn create(
+        meta: &S3MetaData,
+        parent: INum,
+        name: &str,
+        mode: u32,
+        node_type: SFlag,
+    ) -> (INum, u32) {
+        let param = CreateParam {
+            parent,
+            name: name.to_owned(),
+            mode,
+            rdev: 0,
+            uid: 0,
+            gid: 0,
+            umask: 0o022,
+            node_type,
+            link: None,
+        };
+        let (_, attr, _) = meta.mknod(param).await.unwrap();
+        (attr.ino, attr.mode & 0o7777)
+    }
+
+    #[tokio::test]
+    async fn test_acl_inheritance() {
+        let kv_engine = open_kv_engine();
+        let meta = S3MetaData::new(
+            Arc::clone(&kv_engine),
+            "node1",
+            4096,
+            MetaDataOptions::default(),
+        )
+        .await
+        .unwrap();
+        let context = ReqContext { uid: 0, gid: 0 };
+
+        // The umask is applied without a default ACL.
+        let (dir, mode) = create(&meta, FUSE_ROOT_ID, "dir", 0o777, SFlag::S_IFDIR).await;
+        assert_eq!(mode, 0o755);
+        let (file, _) = create(&meta, FUSE_ROOT_ID, "file", 0o666, SFlag::S_IFREG).await;
+        let default_acl = PosixAcl::from_xattr(&xattr(&[
+            (ACL_USER_OBJ, 7, u32::MAX),
+            (ACL_USER, 7, 1000),
+            (ACL_GROUP_OBJ, 5, u32::MAX),
+            (ACL_MASK, 7, u32::MAX),
+            (ACL_OTHER, 5, u32::MAX),
+        ]))
+        .unwrap();
+        meta.set_posix_acl(ReqContext { uid: 1, gid: 1 }, dir, AclKind::Default, None)
+            .await
+            .unwrap_err();
+        meta.set_posix_acl(
+            context.clone(),
+            file,
+            AclKind::Default,
+            Some(default_acl.clone()),
+        )
+        .await
+        .unwrap_err();
+        meta.set_posix_acl(
+            context.clone(),
+            dir,
+            AclKind::Default,
+            Some(default_acl.clone()),
+        )
+        .await
+        .unwrap();
+
+        // A file gets the access ACL masked by its mode, and the umask is not
+        // applied, while a directory inherits the default ACL besides.
+        let (child, mode) = create(&meta, dir, "child", 0o666, SFlag::S_IFREG).await;
+        assert_eq!(mode, 0o664);
+        let access_acl = meta
+            .get_posix_acl(child, AclKind::Access)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(access_acl, default_acl.inherit(0o666));
+        assert!(meta
+            .get_posix_acl(child, AclKind::Default)
+            .await
+            .unwrap()
+            .is_none());
+        let (sub_dir, mode) = create(&meta, dir, "sub", 0o777, SFlag::S_IFDIR).await;
+        assert_eq!(mode, 0o775);
+        assert_eq!(
+            meta.get_posix_acl(sub_dir, AclKind::Default).await.unwrap(),
+            Some(default_acl)
+        );
+
+        // Setting the access ACL sets the mode.
+        let mut acl = access_acl;
+        acl.chmod(0o600);
+        meta.set_posix_acl(context.clone(), child, AclKind::Access, Some(acl))
+            .await
+            .unwrap();
+        let (_, attr) = meta.getattr(child).await.unwrap();
+        assert_eq!(attr.mode & 0o777, 0o600);
+
+        // The ACLs are removed with the node.
+        meta.unlink(context, dir, "sub").await.unwrap();
+        assert!(kv_engine
+            .get(&KeyType::DefaultAcl(sub_dir))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_acl_in_transaction() {
+        let kv_engine = open_kv_engine();
+        let meta = S3MetaData::new(
+            Arc::clone(&kv_engine),
+            "node1",
+            4096,
+            MetaDataOptions::default(),
+        )
+        .await
+        .unwrap();
+        let context = ReqContext { uid: 0, gid: 0 };
+        let param = |name: &str| CreateParam {
+            parent: FUSE_ROOT_ID,
+            name: name.to_owned(),
+            mode: 0o644,
+            rdev: 0,
+            uid: 0,
+            gid: 0,
+            umask: 0o022,
+            node_type: SFlag::S_IFREG,
+            link: None,
+        };
+        let access_acl = xattr(&[
+            (ACL_USER_OBJ, 6, u32::MAX),
+            (ACL_USER, 6, 1000),
+            (ACL_GROUP_OBJ, 4, u32::MAX),
+            (ACL_MASK, 6, u32::MAX),
+            (ACL_OTHER, 4, u32::MAX),
+        ]);
+        let label = b"system_u:object_r:container_file_t:s0".to_vec();
+
+        // A file is created with its ACL and label in one transaction.
+        let ops = [
+            MetaOp::Create(param("file")),
+            MetaOp::SetXattr {
+                ino: None,
+                name: AclKind::Access.name().to_owned(),
+                value: access_acl.clone(),
+            },
+            MetaOp::SetXattr {
+                ino: None,
+                name: SELINUX_XATTR.to_owned(),
+                value: label.clone(),
+            },
+        ];
+        let results = meta.transact(context.clone(), &ops).await.unwrap();
+        let Some(&MetaOpResult::Created(ref attr)) = results.first() else {
+            panic!("the first result should be of the creation");
+        };
+        let file = attr.ino;
+        assert_eq!(
+            meta.get_posix_acl(file, AclKind::Access).await.unwrap(),
+            Some(PosixAcl::from_xattr(&access_acl).unwrap())
+        );
+        assert_eq!(meta.get_security_label(file).await.unwrap(), Some(label));
+        let (_, attr) = meta.getattr(file).await.unwrap();
+        assert_eq!(attr.mode & 0o777, 0o664);
+
+        // Nothing is created if the ACL fails to be set, as a file has no
+        // default ACL.
+        let ops = [
+            MetaOp::Create(param("bad")),
+            MetaOp::SetXattr {
+                ino: None,
+                name: AclKind::Default.name().to_owned(),
+                value: access_acl,
+            },
+        ];
+        meta.transact(context.clone(), &ops).await.unwrap_err();
+        meta.lookup_helper(context, FUSE_ROOT_ID, "bad")
+            .await
+            .unwrap_err();
+    }
+}
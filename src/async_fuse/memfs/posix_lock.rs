@@ -0,0 +1,653 @@
+//! The POSIX record locks of files among the nodes of a volume.
+//!
+//! The kernel keeps `fcntl(2)` record locks local to a node, so the locks are
+//! kept in the kv engine instead, and the processes writing a shared volume
+//! from several nodes lock each other out. A lock is held by a lock owner of
+//! a node, the conflicts are checked and the locks are changed in a
+//! transaction. A lock owner's locks of a file are released when it closes
+//! the file.
+//!
+//! The locks of a node no longer registered, which has been unmounted or is
+//! likely gone, don't conflict with others, and are dropped once they're in
+//! the way.
+//!
+//! A node holding the write delegation of a file, which is the write lease of
+//! `Coherence`, keeps the locks of the file locally, and writes them back to
+//! the kv engine when the delegation is recalled. The other nodes testing or
+//! taking a lock of the file recall the delegation first.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use clippy_utilities::{Cast, OverflowArithmetic};
+use nix::errno::Errno;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::OwnedRwLockReadGuard;
+use tracing::{debug, warn};
+
+use super::coherence::{Coherence, LeaseMode};
+use super::inode_lock::InodeGuard;
+use super::kv_engine::{KVEngine, KVEngineType, KeyType, MetaTxn, ValueType};
+use super::FileLockParam;
+use crate::async_fuse::fuse::protocol::INum;
+use crate::async_fuse::util::build_error_result_from_errno;
+use crate::common::error::DatenLordResult;
+
+/// The limit of retrying a transaction.
+const TXN_RETRY_LIMIT: u32 = 10;
+
+/// The interval between the attempts to take a lock held by others.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The type of a lock.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockType {
+    /// Shared with the other readers
+    Read,
+    /// Exclusive
+    Write,
+}
+
+impl LockType {
+    /// Parse the type of a lock from `typ` of `struct flock`, `None` if it's
+    /// `F_UNLCK`.
+    fn from_raw(typ: u32) -> DatenLordResult<Option<Self>> {
+        match typ.cast::<i32>() {
+            libc::F_RDLCK => Ok(Some(LockType::Read)),
+            libc::F_WRLCK => Ok(Some(LockType::Write)),
+            libc::F_UNLCK => Ok(None),
+            _ => build_error_result_from_errno(Errno::EINVAL, format!("unknown lock type {typ}")),
+        }
+    }
+
+    /// The `typ` of `struct flock` of the type.
+    #[must_use]
+    pub fn to_raw(self) -> u32 {
+        match self {
+            LockType::Read => libc::F_RDLCK.cast(),
+            LockType::Write => libc::F_WRLCK.cast(),
+        }
+    }
+}
+
+/// A lock of a range of a file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PosixLock {
+    /// The node of the lock owner
+    pub node_id: String,
+    /// The lock owner
+    pub owner: u64,
+    /// The first byte locked
+    pub start: u64,
+    /// The last byte locked, inclusive
+    pub end: u64,
+    /// The type of the lock
+    pub typ: LockType,
+    /// The process holding the lock, only reported to `getlk()`
+    pub pid: u32,
+}
+
+impl PosixLock {
+    /// Whether the lock is held by `owner` of node `node_id`.
+    fn is_held_by(&self, node_id: &str, owner: u64) -> bool {
+        self.node_id == node_id && self.owner == owner
+    }
+
+    /// Whether the lock overlaps with the range from `start` to `end`.
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.start <= end && start <= self.end
+    }
+}
+
+/// The locks of a file.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileLocks {
+    /// The locks, in the order of being taken
+    pub locks: Vec<PosixLock>,
+}
+
+impl FileLocks {
+    /// The first lock conflicting with a lock of `typ` from `start` to `end`
+    /// of `owner` of node `node_id`.
+    fn conflict(
+        &self,
+        node_id: &str,
+        owner: u64,
+        start: u64,
+        end: u64,
+        typ: LockType,
+    ) -> Option<&PosixLock> {
+        self.locks.iter().find(|lock| {
+            !lock.is_held_by(node_id, owner)
+                && lock.overlaps(start, end)
+                && (typ == LockType::Write || lock.typ == LockType::Write)
+        })
+    }
+
+    /// Release the range from `start` to `end` of the locks of `owner` of
+    /// node `node_id`, a lock covering the range on both sides is split.
+    fn unlock(&mut self, node_id: &str, owner: u64, start: u64, end: u64) {
+        let mut locks = Vec::with_capacity(self.locks.len());
+        for lock in self.locks.drain(..) {
+            if !lock.is_held_by(node_id, owner) || !lock.overlaps(start, end) {
+                locks.push(lock);
+                continue;
+            }
+            if lock.start < start {
+                locks.push(PosixLock {
+                    end: start.overflow_sub(1),
+                    ..lock.clone()
+                });
+            }
+            if lock.end > end {
+                locks.push(PosixLock {
+                    start: end.overflow_add(1),
+                    ..lock
+                });
+            }
+        }
+        self.locks = locks;
+    }
+
+    /// Change the range of `param` of the locks of `owner` of node `node_id`
+    /// to a lock of `typ`, or release it if `typ` is `None`.
+    fn change(&mut self, node_id: &str, param: &FileLockParam, typ: Option<LockType>) {
+        self.unlock(node_id, param.lock_owner, param.start, param.end);
+        if let Some(typ) = typ {
+            self.locks.push(PosixLock {
+                node_id: node_id.to_owned(),
+                owner: param.lock_owner,
+                start: param.start,
+                end: param.end,
+                typ,
+                pid: param.pid,
+            });
+        }
+    }
+
+    /// Whether `owner` of node `node_id` holds any lock.
+    fn is_held_by(&self, node_id: &str, owner: u64) -> bool {
+        self.locks
+            .iter()
+            .any(|lock| lock.is_held_by(node_id, owner))
+    }
+}
+
+/// The POSIX locks of files taken by a node.
+#[derive(Debug)]
+pub struct PosixLocks {
+    /// The id of this node
+    node_id: String,
+    /// The kv engine
+    kv_engine: Arc<KVEngineType>,
+    /// The files and the lock owners of this node holding locks of them, so
+    /// closing a file without locks doesn't touch the kv engine
+    held: Mutex<HashSet<(INum, u64)>>,
+    /// The leases of files, the locks are kept in the kv engine without it
+    coherence: Option<Arc<Coherence>>,
+    /// The locks of the files delegated to this node, which are not written
+    /// to the kv engine until the delegation is recalled
+    delegated: Mutex<HashMap<INum, FileLocks>>,
+}
+
+impl PosixLocks {
+    /// Create a `PosixLocks` of node `node_id` holding no lock. The locks of
+    /// a file are kept locally while `coherence` holds its write lease.
+    #[must_use]
+    pub fn new(
+        kv_engine: Arc<KVEngineType>,
+        node_id: &str,
+        coherence: Option<Arc<Coherence>>,
+    ) -> Self {
+        Self {
+            node_id: node_id.to_owned(),
+            kv_engine,
+            held: Mutex::new(HashSet::new()),
+            coherence,
+            delegated: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether node `node_id` still mounts the volume.
+    async fn is_alive<T: MetaTxn + ?Sized>(
+        &self,
+        txn: &mut T,
+        node_id: &str,
+    ) -> DatenLordResult<bool> {
+        if node_id == self.node_id {
+            return Ok(true);
+        }
+        let registration = txn
+            .get(&KeyType::NodeRegistration(node_id.to_owned()))
+            .await?;
+        Ok(registration.is_some())
+    }
+
+    /// The first lock in `locks` conflicting with a lock of `typ` described
+    /// by `param` of this node. The conflicting locks of the nodes no longer
+    /// registered are dropped.
+    async fn resolve_conflict<T: MetaTxn + ?Sized>(
+        &self,
+        txn: &mut T,
+        ino: INum,
+        locks: &mut FileLocks,
+        param: &FileLockParam,
+        typ: LockType,
+    ) -> DatenLordResult<Option<PosixLock>> {
+        loop {
+            let Some(conflict) = locks
+                .conflict(&self.node_id, param.lock_owner, param.start, param.end, typ)
+                .cloned()
+            else {
+                return Ok(None);
+            };
+            if self.is_alive(txn, &conflict.node_id).await? {
+                return Ok(Some(conflict));
+            }
+            warn!(
+                "The locks of ino={ino} held by node {} no longer registered are dropped.",
+                conflict.node_id
+            );
+            locks.locks.retain(|lock| lock.node_id != conflict.node_id);
+        }
+    }
+
+    /// Test for a lock of `ino` described by `param`. Returns the first
+    /// conflicting lock, `None` if the lock could be taken.
+    pub async fn test(
+        &self,
+        ino: INum,
+        param: &FileLockParam,
+    ) -> DatenLordResult<Option<PosixLock>> {
+        let Some(typ) = LockType::from_raw(param.typ)? else {
+            return Ok(None);
+        };
+        // Recall the write delegation from another node.
+        let _lease = match self.coherence {
+            Some(ref coherence) => Some(coherence.guard(ino, LeaseMode::Read).await?.0),
+            None => None,
+        };
+        if let Some(locks) = self.delegated.lock().get(&ino) {
+            return Ok(locks
+                .conflict(&self.node_id, param.lock_owner, param.start, param.end, typ)
+                .cloned());
+        }
+        let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            let mut locks = txn
+                .get(&KeyType::FileLocks(ino))
+                .await?
+                .map_or_else(FileLocks::default, ValueType::into_file_locks);
+            let conflict = self
+                .resolve_conflict(txn.as_mut(), ino, &mut locks, param, typ)
+                .await?;
+            (txn.commit().await, conflict)
+        });
+        res
+    }
+
+    /// Load the locks of `ino` from the kv engine, without the locks of the
+    /// nodes no longer registered.
+    async fn load(&self, ino: INum) -> DatenLordResult<FileLocks> {
+        let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            let mut locks = txn
+                .get(&KeyType::FileLocks(ino))
+                .await?
+                .map_or_else(FileLocks::default, ValueType::into_file_locks);
+            let mut dead = HashSet::new();
+            for lock in &locks.locks {
+                if !dead.contains(&lock.node_id)
+                    && !self.is_alive(txn.as_mut(), &lock.node_id).await?
+                {
+                    dead.insert(lock.node_id.clone());
+                }
+            }
+            if !dead.is_empty() {
+                warn!(
+                    "The locks of ino={ino} held by nodes {dead:?} no longer registered are dropped."
+                );
+                locks.locks.retain(|lock| !dead.contains(&lock.node_id));
+            }
+            (txn.commit().await, locks)
+        });
+        res
+    }
+
+    /// Hold the write delegation of `ino` until the returned guard is
+    /// dropped, the locks of `ino` are kept locally in the meantime. Returns
+    /// `None` without `coherence`.
+    async fn delegate(
+        &self,
+        ino: INum,
+    ) -> DatenLordResult<Option<InodeGuard<'_, OwnedRwLockReadGuard<()>>>> {
+        let Some(ref coherence) = self.coherence else {
+            return Ok(None);
+        };
+        let (guard, _) = coherence.guard(ino, LeaseMode::Write).await?;
+        if !self.delegated.lock().contains_key(&ino) {
+            let locks = self.load(ino).await?;
+            // Another request of the file may have loaded the locks already.
+            self.delegated.lock().entry(ino).or_insert(locks);
+        }
+        Ok(Some(guard))
+    }
+
+    /// Take, change or release a lock of `ino` described by `param` in a
+    /// transaction. Returns the first conflicting lock if the lock is held by
+    /// others, or whether `param.lock_owner` holds any lock of `ino`.
+    async fn try_set_in_txn(
+        &self,
+        ino: INum,
+        param: &FileLockParam,
+        typ: Option<LockType>,
+    ) -> DatenLordResult<(Option<PosixLock>, bool)> {
+        let key = KeyType::FileLocks(ino);
+        let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            let mut locks = txn
+                .get(&key)
+                .await?
+                .map_or_else(FileLocks::default, ValueType::into_file_locks);
+            let conflict = match typ {
+                Some(typ) => {
+                    self.resolve_conflict(txn.as_mut(), ino, &mut locks, param, typ)
+                        .await?
+                }
+                None => None,
+            };
+            if conflict.is_none() {
+                locks.change(&self.node_id, param, typ);
+                if locks.locks.is_empty() {
+                    txn.delete(&key);
+                } else {
+                    txn.set(&key, &ValueType::FileLocks(locks.clone()));
+                }
+            }
+            let is_held = locks.is_held_by(&self.node_id, param.lock_owner);
+            (txn.commit().await, (conflict, is_held))
+        });
+        res
+    }
+
+    /// Try to take, change or release a lock of `ino` described by `param`,
+    /// locally if `ino` is delegated to this node, or in a transaction.
+    /// Returns the first conflicting lock if the lock is held by others.
+    async fn try_set(
+        &self,
+        ino: INum,
+        param: &FileLockParam,
+        typ: Option<LockType>,
+    ) -> DatenLordResult<Option<PosixLock>> {
+        let owner = param.lock_owner;
+        let local = self.delegated.lock().get_mut(&ino).map(|locks| {
+            let conflict = typ.and_then(|typ| {
+                locks
+                    .conflict(&self.node_id, owner, param.start, param.end, typ)
+                    .cloned()
+            });
+            if conflict.is_none() {
+                locks.change(&self.node_id, param, typ);
+            }
+            (conflict, locks.is_held_by(&self.node_id, owner))
+        });
+        let (conflict, is_held) = match local {
+            Some(res) => res,
+            None => self.try_set_in_txn(ino, param, typ).await?,
+        };
+
+        if conflict.is_none() {
+            let mut held = self.held.lock();
+            if is_held {
+                held.insert((ino, owner));
+            } else {
+                held.remove(&(ino, owner));
+            }
+        }
+        Ok(conflict)
+    }
+
+    /// Take, change or release a lock of `ino` described by `param`. If the
+    /// lock is held by others, waits for it if `sleep` is true, or fails with
+    /// `EAGAIN`.
+    pub async fn set(&self, ino: INum, param: &FileLockParam, sleep: bool) -> DatenLordResult<()> {
+        let typ = LockType::from_raw(param.typ)?;
+        loop {
+            let conflict = {
+                let _lease = self.delegate(ino).await?;
+                self.try_set(ino, param, typ).await?
+            };
+            let Some(conflict) = conflict else {
+                return Ok(());
+            };
+            if !sleep {
+                return build_error_result_from_errno(
+                    Errno::EAGAIN,
+                    format!(
+                        "the lock of ino={ino} is held by owner {} of node {}",
+                        conflict.owner, conflict.node_id
+                    ),
+                );
+            }
+            debug!(
+                "Waiting for owner {} of node {} to release the lock of ino={ino}.",
+                conflict.owner, conflict.node_id
+            );
+            tokio::time::sleep(LOCK_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Release all locks of `ino` of `owner`, when `owner` closes the file.
+    pub async fn release_owner(&self, ino: INum, owner: u64) -> DatenLordResult<()> {
+        if !self.held.lock().contains(&(ino, owner)) {
+            return Ok(());
+        }
+        let param = FileLockParam {
+            fh: 0,
+            lock_owner: owner,
+            start: 0,
+            end: u64::MAX,
+            typ: libc::F_UNLCK.cast(),
+            pid: 0,
+        };
+        let _lease = self.delegate(ino).await?;
+        self.try_set(ino, &param, None).await.map(|_| ())
+    }
+
+    /// Write the locks of `ino` back to the kv engine, when its delegation is
+    /// recalled from this node.
+    pub async fn recall(&self, ino: INum) -> DatenLordResult<()> {
+        let Some(locks) = self.delegated.lock().get(&ino).cloned() else {
+            return Ok(());
+        };
+        let key = KeyType::FileLocks(ino);
+        if locks.locks.is_empty() {
+            self.kv_engine.delete(&key, None).await?;
+        } else {
+            self.kv_engine
+                .set(&key, &ValueType::FileLocks(locks), None)
+                .await?;
+        }
+        // The locks are kept locally until they're written back.
+        self.delegated.lock().remove(&ino);
+        Ok(())
+    }
+
+    /// Write the locks of all files delegated to this node back to the kv
+    /// engine, before the leases of this node are released. Returns the
+    /// number of files whose locks are written back.
+    pub async fn recall_all(&self) -> DatenLordResult<usize> {
+        let inos: Vec<INum> = self.delegated.lock().keys().copied().collect();
+        for &ino in &inos {
+            self.recall(ino).await?;
+        }
+        Ok(inos.len())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::SystemTime;
+
+    use clippy_utilities::Cast;
+    use datenlord::config::ConsistencyModel;
+
+    use super::{LockType, PosixLocks};
+    use crate::async_fuse::memfs::cluster::NodeRegistration;
+    use crate::async_fuse::memfs::coherence::Coherence;
+    use crate::async_fuse::memfs::kv_engine::{KVEngine, KVEngineType, KeyType, ValueType};
+    use crate::async_fuse::memfs::FileLockParam;
+    use crate::async_fuse::test::test_util::open_kv_engine;
+
+    /// Register node `node_id` without a lease.
+    async fn register(kv_engine: &Arc<KVEngineType>, node_id: &str) {
+        let registration = NodeRegistration {
+            node_id: node_id.to_owned(),
+            mount_point: "/mnt".to_owned(),
+            registered: SystemTime::now(),
+            cache_endpoint: None,
+            zone: None,
+        };
+        kv_engine
+            .set(
+                &KeyType::NodeRegistration(node_id.to_owned()),
+                &ValueType::NodeRegistration(registration),
+                None,
+            )
+            .await
+            .unwrap();
+    }
+
+    /// The parameter of a lock of `owner` from `start` to `end`.
+    fn param(owner: u64, start: u64, end: u64, typ: i32) -> FileLockParam {
+        FileLockParam {
+            fh: 0,
+            lock_owner: owner,
+            start,
+            end,
+            typ: typ.cast(),
+            pid: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_posix_locks() {
+        let kv_engine = open_kv_engine();
+        register(&kv_engine, "node1").await;
+        register(&kv_engine, "node2").await;
+        let node1 = PosixLocks::new(Arc::clone(&kv_engine), "node1", None);
+        let node2 = PosixLocks::new(Arc::clone(&kv_engine), "node2", None);
+
+        // Readers share the range, a writer is locked out.
+        node1
+            .set(2, &param(1, 0, 99, libc::F_RDLCK), false)
+            .await
+            .unwrap();
+        node2
+            .set(2, &param(1, 50, 149, libc::F_RDLCK), false)
+            .await
+            .unwrap();
+        assert!(node2
+            .set(2, &param(1, 0, 9, libc::F_WRLCK), false)
+            .await
+            .is_err());
+        let conflict = node2
+            .test(2, &param(1, 0, 9, libc::F_WRLCK))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(conflict.node_id, "node1");
+        assert_eq!(conflict.typ, LockType::Read);
+
+        // Unlocking the middle of the range splits the lock.
+        node1
+            .set(2, &param(1, 10, 19, libc::F_UNLCK), false)
+            .await
+            .unwrap();
+        assert!(node2
+            .test(2, &param(1, 10, 19, libc::F_WRLCK))
+            .await
+            .unwrap()
+            .is_none());
+        assert!(node2
+            .test(2, &param(1, 0, 9, libc::F_WRLCK))
+            .await
+            .unwrap()
+            .is_some());
+
+        // Closing the file releases the locks of the owner.
+        node1.release_owner(2, 1).await.unwrap();
+        node2
+            .set(2, &param(1, 0, 9, libc::F_WRLCK), false)
+            .await
+            .unwrap();
+
+        // The locks of an unregistered node are dropped.
+        kv_engine
+            .delete(&KeyType::NodeRegistration("node2".to_owned()), None)
+            .await
+            .unwrap();
+        node1
+            .set(2, &param(1, 0, 199, libc::F_WRLCK), false)
+            .await
+            .unwrap();
+
+        node1.release_owner(2, 1).await.unwrap();
+        assert!(kv_engine
+            .get(&KeyType::FileLocks(2))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_delegated_locks() {
+        let kv_engine = open_kv_engine();
+        register(&kv_engine, "node1").await;
+        let coherence = Arc::new(Coherence::new(
+            Arc::clone(&kv_engine),
+            "node1",
+            ConsistencyModel::Strict,
+        ));
+        let node1 = PosixLocks::new(Arc::clone(&kv_engine), "node1", Some(coherence));
+
+        // The locks are kept locally under the delegation.
+        node1
+            .set(2, &param(1, 0, 99, libc::F_WRLCK), false)
+            .await
+            .unwrap();
+        assert!(node1
+            .set(2, &param(2, 50, 59, libc::F_RDLCK), false)
+            .await
+            .is_err());
+        assert!(kv_engine
+            .get(&KeyType::FileLocks(2))
+            .await
+            .unwrap()
+            .is_none());
+
+        // They're written back when the delegation is recalled.
+        node1.recall(2).await.unwrap();
+        let locks = kv_engine
+            .get(&KeyType::FileLocks(2))
+            .await
+            .unwrap()
+            .unwrap()
+            .into_file_locks();
+        assert_eq!(locks.locks.len(), 1);
+        assert_eq!(locks.locks.first().unwrap().owner, 1);
+
+        // And loaded again under a new delegation.
+        node1.release_owner(2, 1).await.unwrap();
+        node1.recall(2).await.unwrap();
+        assert!(kv_engine
+            .get(&KeyType::FileLocks(2))
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,248 @@
+//! Directory quotas.
+//!
+//! A quota limits the bytes and the inodes in the subtree of a directory,
+//! which is the root of the quota, while the root itself is not counted.
+//! Quotas nest, a change in a subtree is charged to the quotas of all the
+//! directories on its path, and growth is refused with `EDQUOT` if any of them
+//! would be exceeded. Bytes are counted by the allocated chunks, i.e. the
+//! `st_blocks` of files, so holes take no quota.
+//!
+//! Quotas are managed with the extended attributes of the root directory, see
+//! [`QuotaAttr`]. Only the super user sets the limits, a limit of `0` means
+//! unlimited, and a quota is dropped once both of its limits are removed.
+//! Renaming across quotas fails with `EXDEV`, so that `mv` falls back to
+//! copying, which is charged as usual.
+
+use std::ops::Range;
+
+use clippy_utilities::{Cast, OverflowArithmetic};
+use nix::errno::Errno;
+use serde::{Deserialize, Serialize};
+
+use super::chunk_index::STAT_BLOCK_SIZE;
+use crate::async_fuse::fuse::protocol::INum;
+use crate::async_fuse::util::build_error_result_from_errno;
+use crate::common::error::DatenLordResult;
+
+/// The bytes taken by `blocks` 512-byte units, as in `st_blocks`.
+#[must_use]
+pub fn blocks_to_bytes(blocks: u64) -> u64 {
+    blocks.overflow_mul(STAT_BLOCK_SIZE)
+}
+
+/// The change of bytes when the `st_blocks` of a file changes.
+#[must_use]
+pub fn bytes_delta(old_blocks: u64, new_blocks: u64) -> i64 {
+    blocks_to_bytes(new_blocks)
+        .cast::<i64>()
+        .overflow_sub(blocks_to_bytes(old_blocks).cast::<i64>())
+}
+
+/// Apply `delta` to `used`, the result is `None` if it grows beyond `max`,
+/// which is unlimited if it's `0`.
+fn apply(used: u64, delta: i64, max: u64) -> Option<u64> {
+    let delta_abs = delta.unsigned_abs();
+    if delta < 0 {
+        return Some(used.saturating_sub(delta_abs));
+    }
+    let new = used.overflow_add(delta_abs);
+    (max == 0 || delta == 0 || new <= max).then_some(new)
+}
+
+/// The quota of a directory subtree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quota {
+    /// The i-number of the root directory
+    pub ino: INum,
+    /// The limit of bytes, `0` for unlimited
+    pub max_bytes: u64,
+    /// The limit of inodes, `0` for unlimited
+    pub max_inodes: u64,
+    /// The bytes in the subtree
+    pub used_bytes: u64,
+    /// The inodes in the subtree
+    pub used_inodes: u64,
+}
+
+impl Quota {
+    /// Create an unlimited quota of directory `ino` with the current usage of
+    /// its subtree.
+    #[must_use]
+    pub fn new(ino: INum, used_bytes: u64, used_inodes: u64) -> Self {
+        Self {
+            ino,
+            max_bytes: 0,
+            max_inodes: 0,
+            used_bytes,
+            used_inodes,
+        }
+    }
+
+    /// Returns whether the quota has no limit.
+    #[must_use]
+    pub fn is_unlimited(&self) -> bool {
+        self.max_bytes == 0 && self.max_inodes == 0
+    }
+
+    /// Check whether the change of usage is allowed, growth exceeding a limit
+    /// is refused with `EDQUOT`.
+    pub fn check(&self, bytes: i64, inodes: i64) -> DatenLordResult<()> {
+        if apply(self.used_bytes, bytes, self.max_bytes).is_none()
+            || apply(self.used_inodes, inodes, self.max_inodes).is_none()
+        {
+            return build_error_result_from_errno(
+                Errno::EDQUOT,
+                format!(
+                    "quota of directory ino={} is exceeded, bytes={}/{} (+{bytes}), \
+                        inodes={}/{} (+{inodes})",
+                    self.ino, self.used_bytes, self.max_bytes, self.used_inodes, self.max_inodes,
+                ),
+            );
+        }
+        Ok(())
+    }
+
+    /// Charge a change of usage regardless of the limits. It's for the changes
+    /// already checked, or made anyway, such as removing.
+    pub fn charge(&mut self, bytes: i64, inodes: i64) {
+        self.used_bytes = apply(self.used_bytes, bytes, 0).unwrap_or(self.used_bytes);
+        self.used_inodes = apply(self.used_inodes, inodes, 0).unwrap_or(self.used_inodes);
+    }
+
+    /// Get the value of an attribute.
+    #[must_use]
+    pub fn get(&self, attr: QuotaAttr) -> u64 {
+        match attr {
+            QuotaAttr::MaxBytes => self.max_bytes,
+            QuotaAttr::MaxInodes => self.max_inodes,
+            QuotaAttr::UsedBytes => self.used_bytes,
+            QuotaAttr::UsedInodes => self.used_inodes,
+        }
+    }
+
+    /// Set a limit, `0` removes it. The usages are not settable.
+    pub fn set_limit(&mut self, attr: QuotaAttr, limit: u64) -> DatenLordResult<()> {
+        match attr {
+            QuotaAttr::MaxBytes => self.max_bytes = limit,
+            QuotaAttr::MaxInodes => self.max_inodes = limit,
+            QuotaAttr::UsedBytes | QuotaAttr::UsedInodes => {
+                return build_error_result_from_errno(
+                    Errno::EPERM,
+                    format!("{} is read-only", attr.name()),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The free and the total amounts of the bytes and the inodes as
+    /// `free..total`, if they're limited, which are reported by `statfs(2)`.
+    #[must_use]
+    pub fn capacity(&self) -> (Option<Range<u64>>, Option<Range<u64>>) {
+        let limited = |used: u64, max: u64| (max != 0).then(|| max.saturating_sub(used)..max);
+        (
+            limited(self.used_bytes, self.max_bytes),
+            limited(self.used_inodes, self.max_inodes),
+        )
+    }
+}
+
+/// The extended attributes of a quota root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaAttr {
+    /// The limit of bytes, settable
+    MaxBytes,
+    /// The limit of inodes, settable
+    MaxInodes,
+    /// The bytes in the subtree, read-only
+    UsedBytes,
+    /// The inodes in the subtree, read-only
+    UsedInodes,
+}
+
+impl QuotaAttr {
+    /// All the attributes.
+    pub const ALL: [Self; 4] = [
+        Self::MaxBytes,
+        Self::MaxInodes,
+        Self::UsedBytes,
+        Self::UsedInodes,
+    ];
+
+    /// The name of the extended attribute, the value is a decimal number.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::MaxBytes => "user.datenlord.quota.max_bytes",
+            Self::MaxInodes => "user.datenlord.quota.max_inodes",
+            Self::UsedBytes => "user.datenlord.quota.used_bytes",
+            Self::UsedInodes => "user.datenlord.quota.used_inodes",
+        }
+    }
+
+    /// Parse the name of an extended attribute.
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|attr| attr.name() == name)
+    }
+
+    /// The names of all the attributes, as listed by `listxattr(2)`.
+    #[must_use]
+    pub fn list() -> Vec<u8> {
+        Self::ALL
+            .iter()
+            .flat_map(|attr| attr.name().bytes().chain(std::iter::once(0)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use nix::errno::Errno;
+
+    use super::{bytes_delta, Quota, QuotaAttr};
+    use crate::common::error::DatenLordError;
+
+    #[test]
+    fn test_charge_and_check() {
+        let mut quota = Quota::new(2, 1024, 1);
+        assert!(quota.is_unlimited());
+        quota.check(1 << 40, 1 << 20).unwrap();
+
+        quota.set_limit(QuotaAttr::MaxBytes, 4096).unwrap();
+        quota.set_limit(QuotaAttr::MaxInodes, 2).unwrap();
+        quota.check(3072, 1).unwrap();
+        let err = quota.check(3073, 0).unwrap_err();
+        assert!(matches!(
+            err,
+            DatenLordError::InternalErr { ref source, .. }
+                if source.root_cause().downcast_ref::<nix::Error>() == Some(&Errno::EDQUOT)
+        ));
+        quota.check(0, 2).unwrap_err();
+
+        // Shrinking is always allowed, even if the usage is beyond the limit.
+        quota.charge(8192, 0);
+        quota.check(-512, 0).unwrap();
+        quota.check(0, 0).unwrap();
+        quota.charge(-(1 << 20), -5);
+        assert_eq!((quota.used_bytes, quota.used_inodes), (0, 0));
+
+        assert_eq!(quota.capacity(), (Some(4096..4096), Some(2..2)));
+        quota.set_limit(QuotaAttr::MaxInodes, 0).unwrap();
+        assert_eq!(quota.capacity(), (Some(4096..4096), None));
+        quota.set_limit(QuotaAttr::UsedBytes, 0).unwrap_err();
+    }
+
+    #[test]
+    fn test_attr_names() {
+        for attr in QuotaAttr::ALL {
+            assert_eq!(QuotaAttr::from_name(attr.name()), Some(attr));
+        }
+        assert_eq!(QuotaAttr::from_name("user.datenlord.quota"), None);
+        let list = QuotaAttr::list();
+        assert_eq!(list.iter().filter(|&&byte| byte == 0).count(), 4);
+        assert!(list.starts_with(b"user.datenlord.quota.max_bytes\0"));
+        assert_eq!(bytes_delta(8, 2), -3072);
+    }
+}
@@ -0,0 +1,112 @@
+//! The volumes published read-only on this node.
+//!
+//! The CSI node service publishes a volume by bind mounting its directory in
+//! the file system mounted on the node, and the bind mount of a read-only
+//! publish is read-only. The directories of the volumes only published
+//! read-only on the node are registered here as well, so the requests changing
+//! the nodes below them are refused with `EROFS` as they're dispatched, like
+//! the ones of a read-only mount, even if they're reached through a writable
+//! mount.
+//!
+//! A volume published read-write on the node as well is not read-only, the
+//! file system can't tell which mount a request comes through.
+
+use std::collections::{HashMap, HashSet};
+
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+
+use crate::async_fuse::fuse::protocol::INum;
+
+/// The volumes published read-only on this node.
+pub static READ_ONLY_VOLUMES: Lazy<ReadOnlyVolumes> = Lazy::new(ReadOnlyVolumes::default);
+
+/// The publishes of the volumes on this node.
+#[derive(Debug, Default)]
+struct Publishes {
+    /// Target path -> the i-number of the directory of the volume, and
+    /// whether it's published read-only
+    targets: HashMap<String, (INum, bool)>,
+    /// The directories only published read-only
+    read_only: HashSet<INum>,
+}
+
+impl Publishes {
+    /// Refresh whether directory `dir` is only published read-only.
+    fn refresh(&mut self, dir: INum) {
+        let mut published = self
+            .targets
+            .values()
+            .filter(|&&(ino, _)| ino == dir)
+            .peekable();
+        if published.peek().is_some() && published.all(|&(_, read_only)| read_only) {
+            self.read_only.insert(dir);
+        } else {
+            self.read_only.remove(&dir);
+        }
+    }
+}
+
+/// The volumes published on this node, and the ones only published read-only.
+#[derive(Debug, Default)]
+pub struct ReadOnlyVolumes {
+    /// The publishes
+    publishes: RwLock<Publishes>,
+}
+
+impl ReadOnlyVolumes {
+    /// Record the publish of directory `dir` at `target`.
+    pub fn publish(&self, target: &str, dir: INum, read_only: bool) {
+        let mut publishes = self.publishes.write();
+        if let Some((old, _)) = publishes
+            .targets
+            .insert(target.to_owned(), (dir, read_only))
+        {
+            publishes.refresh(old);
+        }
+        publishes.refresh(dir);
+    }
+
+    /// Remove the publish at `target`.
+    pub fn unpublish(&self, target: &str) {
+        let mut publishes = self.publishes.write();
+        if let Some((dir, _)) = publishes.targets.remove(target) {
+            publishes.refresh(dir);
+        }
+    }
+
+    /// Whether no directory is only published read-only.
+    pub fn is_empty(&self) -> bool {
+        self.publishes.read().read_only.is_empty()
+    }
+
+    /// Whether directory `dir` is only published read-only.
+    pub fn contains(&self, dir: INum) -> bool {
+        self.publishes.read().read_only.contains(&dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReadOnlyVolumes;
+
+    #[test]
+    fn test_read_only_volumes() {
+        let volumes = ReadOnlyVolumes::default();
+        volumes.publish("/pods/a/vol", 2, true);
+        volumes.publish("/pods/b/vol", 2, true);
+        volumes.publish("/pods/c/vol", 3, false);
+        assert!(volumes.contains(2));
+        assert!(!volumes.contains(3));
+
+        // A read-write publish on the node lifts it.
+        volumes.publish("/pods/d/vol", 2, false);
+        assert!(!volumes.contains(2));
+        volumes.unpublish("/pods/d/vol");
+        assert!(volumes.contains(2));
+
+        volumes.unpublish("/pods/a/vol");
+        volumes.unpublish("/pods/b/vol");
+        assert!(volumes.is_empty());
+    }
+}
@@ -0,0 +1,127 @@
+//! The `ReplicaIndex` persisted in the kv engine.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use super::kv_engine::{KVEngine, KVEngineType, KeyType, MetaTxn, ValueType};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::common::error::DatenLordResult;
+use crate::storage::error::{StorageError, StorageResult};
+use crate::storage::replication::{BlockReplicas, ReplicaIndex};
+
+/// The limit of retrying a transaction.
+const TXN_RETRY_LIMIT: u32 = 10;
+
+/// A `ReplicaIndex` persisted in the kv engine, the replicas of blocks are
+/// shared among all nodes of the volume.
+#[derive(Debug)]
+pub struct KvReplicaIndex {
+    /// The kv engine
+    kv_engine: Arc<KVEngineType>,
+}
+
+impl KvReplicaIndex {
+    /// Create a `KvReplicaIndex`.
+    #[must_use]
+    pub fn new(kv_engine: Arc<KVEngineType>) -> Self {
+        Self { kv_engine }
+    }
+
+    /// Remove the replicas of blocks in a transaction.
+    async fn remove_replicas_impl(
+        &self,
+        ino: INum,
+        block_ids: Range<usize>,
+    ) -> DatenLordResult<()> {
+        let block_ids: Vec<usize> = self
+            .kv_engine
+            .range(&KeyType::FileBlockReplicas(ino))
+            .await?
+            .into_iter()
+            .map(|value| value.into_block_replicas().block_id)
+            .filter(|block_id| block_ids.contains(block_id))
+            .collect();
+        if block_ids.is_empty() {
+            return Ok(());
+        }
+
+        let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            for &block_id in &block_ids {
+                txn.delete(&KeyType::BlockReplicas(ino, block_id));
+            }
+            (txn.commit().await, ())
+        });
+        res
+    }
+
+    /// Set the replicas of a block in a transaction, if the current ones are
+    /// `expected`.
+    async fn compare_and_set_replicas_impl(
+        &self,
+        expected: &BlockReplicas,
+        record: BlockReplicas,
+    ) -> DatenLordResult<bool> {
+        let key = KeyType::BlockReplicas(record.ino, record.block_id);
+        let mut txn = self.kv_engine.new_meta_txn().await;
+        let current = txn.get(&key).await?.map(ValueType::into_block_replicas);
+        if current.as_ref() != Some(expected) {
+            return Ok(false);
+        }
+        txn.set(&key, &ValueType::BlockReplicas(record));
+        // The commit fails if the replicas are set by others meanwhile.
+        txn.commit().await
+    }
+}
+
+#[async_trait]
+impl ReplicaIndex for KvReplicaIndex {
+    async fn get_replicas(
+        &self,
+        ino: INum,
+        block_id: usize,
+    ) -> StorageResult<Option<BlockReplicas>> {
+        let value = self
+            .kv_engine
+            .get(&KeyType::BlockReplicas(ino, block_id))
+            .await;
+        value
+            .map_err(StorageError::from)
+            .map(|value| value.map(ValueType::into_block_replicas))
+    }
+
+    async fn set_replicas(&self, record: BlockReplicas) -> StorageResult<()> {
+        let key = KeyType::BlockReplicas(record.ino, record.block_id);
+        let value = ValueType::BlockReplicas(record);
+        self.kv_engine.set(&key, &value, None).await?;
+        Ok(())
+    }
+
+    async fn compare_and_set_replicas(
+        &self,
+        expected: &BlockReplicas,
+        record: BlockReplicas,
+    ) -> StorageResult<bool> {
+        self.compare_and_set_replicas_impl(expected, record)
+            .await
+            .map_err(StorageError::from)
+    }
+
+    async fn remove_replicas(&self, ino: INum, block_ids: Range<usize>) -> StorageResult<()> {
+        self.remove_replicas_impl(ino, block_ids)
+            .await
+            .map_err(StorageError::from)
+    }
+
+    async fn list_replicas(&self) -> StorageResult<Vec<BlockReplicas>> {
+        let values = self.kv_engine.range(&KeyType::AllBlockReplicas).await;
+        values.map_err(StorageError::from).map(|values| {
+            values
+                .into_iter()
+                .map(ValueType::into_block_replicas)
+                .collect()
+        })
+    }
+}
@@ -1,4 +1,6 @@
+use std::collections::BTreeMap;
 use std::fmt::Debug;
+use std::ops::Range;
 use std::os::unix::ffi::OsStringExt;
 use std::os::unix::io::RawFd;
 use std::sync::atomic::{AtomicU32, Ordering};
@@ -7,26 +9,40 @@ use std::time::{Duration, SystemTime};
 
 use async_trait::async_trait;
 use clippy_utilities::{Cast, OverflowArithmetic};
+use datenlord::common::memory_budget::MemoryConsumer;
 use datenlord::metrics::FILESYSTEM_METRICS;
-use libc::{RENAME_EXCHANGE, RENAME_NOREPLACE};
+use futures::stream::{self, StreamExt};
+use libc::{RENAME_EXCHANGE, RENAME_NOREPLACE, SEEK_DATA, SEEK_HOLE};
 use nix::errno::Errno;
 use nix::fcntl::OFlag;
 use nix::sys::stat::SFlag;
 use tokio::sync::Mutex;
 use tracing::{debug, info, instrument};
 
-use super::fs_util::{self, NEED_CHECK_PERM};
+use super::chunk_index::{ChunkIndex, VolumeInfo};
+use super::dentry_cache::DentryCache;
+use super::fs_util::{self, FileAttr, NEED_CHECK_PERM};
 use super::id_alloc_used::INumAllocator;
-use super::kv_engine::{KVEngine, KVEngineType, MetaTxn, ValueType};
-use super::metadata::{error, MetaData, ReqContext};
+use super::kv_engine::{CompoundTxn, KVEngine, KVEngineType, MetaTxn, ValueType, RETRY_TXN_BREAK};
+use super::metadata::{error, MetaData, MetaDataOptions, MetaOp, MetaOpResult, ReqContext};
+use super::negative_cache::NegativeCache;
 use super::node::Node;
 use super::open_file::OpenFiles;
+use super::posix_acl::{self, AclKind, PosixAcl};
+use super::quota::{self, Quota, QuotaAttr};
 use super::s3_node::{S3Node, GLOBAL_S3_FD_CNT};
-use super::{check_type_supported, CreateParam, RenameParam, SetAttrParam, StorageType};
+use super::security_label::SELINUX_XATTR;
+use super::stat_ahead::{PrefetchedNode, StatAhead, STAT_AHEAD_CONCURRENCY};
+use super::trash::{self, TrashEntry};
+use super::usage::VolumeUsage;
+use super::{
+    check_type_supported, inline_data, open_state, CreateParam, RenameParam, SetAttrParam,
+    StorageType,
+};
 use crate::async_fuse::fuse::fuse_reply::{ReplyDirectory, StatFsParam};
 use crate::async_fuse::fuse::protocol::{FuseAttr, INum, FUSE_ROOT_ID};
 use crate::async_fuse::memfs::check_name_length;
-use crate::async_fuse::memfs::direntry::DirEntry;
+use crate::async_fuse::memfs::direntry::{DirEntry, FileType};
 use crate::async_fuse::memfs::kv_engine::KeyType;
 use crate::async_fuse::util::build_error_result_from_errno;
 use crate::common::error::{
@@ -34,6 +50,7 @@ use crate::common::error::{
     DatenLordResult,
 };
 use crate::function_name;
+use crate::storage::tiering::Tier;
 
 /// A helper function to build [`DatenLordError::InconsistentFS`] with default
 /// context and get the function name automatic.
@@ -66,6 +83,23 @@ pub struct S3MetaData {
     inum_allocator: INumAllocator<KVEngineType>,
     /// opend files
     open_files: OpenFiles,
+    /// The size of chunks of files
+    chunk_size: u64,
+    /// The capacity of the volume in bytes, `None` if it's unlimited
+    capacity: Option<u64>,
+    /// How long removed files are kept in the trash, `None` if the trash is
+    /// disabled
+    trash_retention: Option<Duration>,
+    /// The max size of the files whose content is inlined in their nodes,
+    /// `None` if inlining is disabled
+    inline_threshold: Option<u64>,
+    /// The nodes prefetched by listing directories, `None` if the stat-ahead
+    /// is disabled
+    stat_ahead: Option<StatAhead>,
+    /// The names missing in directories, `None` if they're not cached
+    negative_cache: Option<NegativeCache>,
+    /// The cached directory entries, `None` if they're not cached
+    dentry_cache: Option<DentryCache>,
 }
 
 #[async_trait]
@@ -80,14 +114,46 @@ impl MetaData for S3MetaData {
         _flags: u32,
         _lock_owner: u64,
         _flush: bool,
-    ) -> DatenLordResult<()> {
-        if self.open_files.close(ino).is_some() {
-            // open_count reaches 0, flush the metadata to kv
-            info!("release() ino={} fh={} file is closed", ino, fh);
-        } else {
+    ) -> DatenLordResult<bool> {
+        self.open_files.close(ino);
+        if self.open_files.is_open(ino) {
             info!("release() ino={} fh={} file is still open", ino, fh);
+            return Ok(false);
         }
-        Ok(())
+        // This node no longer opens the file, which is deleted if it's
+        // removed and not referenced or opened by any node.
+        info!("release() ino={} fh={} file is closed", ino, fh);
+        let (res, retry) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            open_state::record_release(txn.as_mut(), ino, &self.node_id).await?;
+            let mut is_deleted = false;
+            if let Some(inode) = self.try_get_inode_from_txn(txn.as_mut(), ino).await? {
+                is_deleted = inode.get_lookup_count() == 0
+                    && inode.is_deferred_deletion()
+                    && txn
+                        .get(&KeyType::Trash(ino))
+                        .await
+                        .add_context(format!(
+                            "{}() failed to get trash entry of ino={ino} from kv engine",
+                            function_name!()
+                        ))?
+                        .is_none()
+                    && !self.is_opened(txn.as_mut(), ino).await?;
+            }
+            if is_deleted {
+                txn.delete(&KeyType::INum2Node(ino));
+                txn.delete(&KeyType::ChunkIndex(ino));
+                txn.delete(&KeyType::TierPin(ino));
+                txn.delete(&KeyType::AccessAcl(ino));
+                txn.delete(&KeyType::DefaultAcl(ino));
+                txn.delete(&KeyType::SecurityLabel(ino));
+                txn.delete(&KeyType::InodeLease(ino));
+                txn.delete(&KeyType::FileOpeners(ino));
+            }
+            (txn.commit().await, is_deleted)
+        });
+        FILESYSTEM_METRICS.observe_storage_operation_throughput(retry, "release");
+        res
     }
 
     #[instrument(skip(self), err, ret)]
@@ -99,13 +165,13 @@ impl MetaData for S3MetaData {
         offset: i64,
         reply: &mut ReplyDirectory,
     ) -> DatenLordResult<()> {
-        let inode = self
-            .get_node_from_kv_engine(ino)
-            .await?
-            .ok_or_else(|| build_inconsistent_fs!(ino))?;
-        inode.get_attr().check_perm(context.uid, context.gid, 5)?;
-
-        let dir_entries = self.get_all_dir_entry(ino).await?;
+        let dir_entries = self.list_dir(context, ino).await?;
+        // The children are prefetched once the listing starts
+        if offset == 0 {
+            if let Some(ref stat_ahead) = self.stat_ahead {
+                self.prefetch_children(stat_ahead, ino, &dir_entries).await;
+            }
+        }
         for (i, dir_etnry) in dir_entries.iter().enumerate().skip(offset.cast()) {
             reply.add(
                 dir_etnry.ino(),
@@ -126,6 +192,15 @@ impl MetaData for S3MetaData {
         Ok(())
     }
 
+    async fn list_dir(&self, context: ReqContext, ino: u64) -> DatenLordResult<Vec<DirEntry>> {
+        let inode = self
+            .get_node_from_kv_engine(ino)
+            .await?
+            .ok_or_else(|| build_inconsistent_fs!(ino))?;
+        inode.get_attr().check_perm(context.uid, context.gid, 5)?;
+        self.get_all_dir_entry(ino).await
+    }
+
     #[instrument(skip(self), err, ret)]
     async fn opendir(&self, context: ReqContext, ino: u64, flags: u32) -> DatenLordResult<RawFd> {
         match self.get_node_from_kv_engine(ino).await? {
@@ -159,7 +234,18 @@ impl MetaData for S3MetaData {
             .await?
             .ok_or_else(|| build_inconsistent_fs!(ino))?;
         node.get_attr().check_perm(context.uid, context.gid, 5)?;
-        node.statefs().await
+
+        let records = self
+            .kv_engine
+            .range(&KeyType::AllVolumeUsages)
+            .await
+            .add_context(format!(
+                "{}() failed to get the usage of the volume from kv engine",
+                function_name!()
+            ))?;
+        let usage = VolumeUsage::sum(records.into_iter().map(ValueType::into_volume_usage));
+        let quota_roots = self.quota_roots(ino).await?;
+        Ok(usage.statfs(self.capacity, quota_roots.first()))
     }
 
     #[instrument(skip(self))]
@@ -223,31 +309,48 @@ impl MetaData for S3MetaData {
             _ => 6,
         };
 
-        // First find in `open_files`
+        // The first open of this node is recorded, so the file is kept for it
+        // once it's removed by other nodes.
+        let is_first_open = !self.open_files.is_open(ino);
+
+        // First find in `open_files`, the file may be kept for the delegation
+        // without being opened
         if let Some(open_file) = self.open_files.try_open(ino) {
-            let open_file = open_file.read();
             open_file
+                .read()
                 .attr
                 .check_perm(context.uid, context.gid, access_mode)?;
+            if is_first_open {
+                self.record_open(ino).await?;
+            }
             return Ok(GLOBAL_S3_FD_CNT.fetch_add(1, Ordering::SeqCst).cast());
         }
 
-        // The file doesn't open by any process, so we need to open it
-        match self.get_node_from_kv_engine(ino).await? {
-            None => {
-                return build_error_result_from_errno(
-                    Errno::ENOENT,
-                    format!("open() failed to find ino={ino}"),
-                );
-            }
-            Some(node) => {
-                let attr = node.get_attr();
-                attr.check_perm(context.uid, context.gid, access_mode)?;
-                // Add the file to `open_files`
-                self.open_files.open(ino, attr);
-                return Ok(GLOBAL_S3_FD_CNT.fetch_add(1, Ordering::SeqCst).cast());
-            }
-        }
+        // The file doesn't open by any process, so we need to open it, from
+        // the node prefetched if there's any
+        let node = match self.prefetched_node(ino) {
+            Some(node) => node,
+            None => match self.get_node_from_kv_engine(ino).await? {
+                None => {
+                    return build_error_result_from_errno(
+                        Errno::ENOENT,
+                        format!("open() failed to find ino={ino}"),
+                    );
+                }
+                Some(node) => PrefetchedNode {
+                    attr: node.get_attr(),
+                    inline_data: node.inline_data().map(Arc::from),
+                },
+            },
+        };
+        node.attr
+            .check_perm(context.uid, context.gid, access_mode)?;
+        self.record_open(ino).await?;
+        // Add the file to `open_files`, with its content loaded with the node
+        // if it's inlined.
+        self.open_files
+            .open(ino, node.attr, node.inline_data.as_deref());
+        Ok(GLOBAL_S3_FD_CNT.fetch_add(1, Ordering::SeqCst).cast())
     }
 
     #[instrument(skip(self), err, ret)]
@@ -259,6 +362,12 @@ impl MetaData for S3MetaData {
             return Ok((Duration::new(MY_TTL_SEC, 0), attr));
         }
 
+        // If the file is prefetched, return the attr prefetched
+        if let Some(node) = self.prefetched_node(ino) {
+            let attr = fs_util::convert_to_fuse_attr(node.attr);
+            return Ok((Duration::new(MY_TTL_SEC, 0), attr));
+        }
+
         // If the file is not open, return the attr in kv engine
         let inode = self
             .get_node_from_kv_engine(ino)
@@ -279,6 +388,91 @@ impl MetaData for S3MetaData {
         (file_size, mtime)
     }
 
+    fn inline_data(&self, ino: u64) -> Option<Arc<[u8]>> {
+        let open_file = self.open_files.try_get(ino)?;
+        let open_file = open_file.read();
+        open_file
+            .inline_data
+            .as_ref()
+            .filter(|data| data.len().cast::<u64>() == open_file.attr.size)
+            .map(Arc::clone)
+    }
+
+    fn refresh_open_file(&self, attr: FileAttr) {
+        let Some(open_file) = self.open_files.try_get(attr.ino) else {
+            return;
+        };
+        let mut open_file = open_file.write();
+        // The changes made by this node are cached already, only the newer
+        // ones from other nodes are taken, and the data cache is invalidated
+        // by the new `mtime`.
+        if attr.mtime > open_file.attr.mtime || attr.ctime > open_file.attr.ctime {
+            open_file.attr = attr;
+            open_file.inline_data = None;
+        }
+    }
+
+    #[instrument(skip(self), err, ret)]
+    async fn revalidate_open_file(&self, ino: INum) -> DatenLordResult<bool> {
+        // The data cached for a file not open may be stale, as it's not
+        // revalidated since it's closed.
+        let Some(open_file) = self.open_files.try_get(ino) else {
+            return Ok(false);
+        };
+        let attr = self
+            .get_node_from_kv_engine(ino)
+            .await?
+            .ok_or_else(|| build_inconsistent_fs!(ino))?
+            .get_attr();
+        let cached_mtime = open_file.read().attr.mtime;
+        self.refresh_open_file(attr);
+        Ok(attr.mtime <= cached_mtime)
+    }
+
+    fn invalidate_dir(&self, ino: INum) {
+        if let Some(ref dentry_cache) = self.dentry_cache {
+            dentry_cache.invalidate_dir(ino);
+        }
+        self.invalidate_prefetched(ino);
+    }
+
+    fn delegate_open_file(&self, attr: FileAttr) {
+        self.refresh_open_file(attr);
+        self.open_files.delegate(attr.ino, attr);
+    }
+
+    fn undelegate_open_file(&self, ino: INum) {
+        self.open_files.undelegate(ino);
+    }
+
+    fn shrink_caches(&self, max: usize) -> Vec<(INum, String)> {
+        if let Some(ref stat_ahead) = self.stat_ahead {
+            stat_ahead.clear();
+        }
+        if let Some(ref negative_cache) = self.negative_cache {
+            negative_cache.clear();
+        }
+        self.dentry_cache
+            .as_ref()
+            .map_or_else(Vec::new, |dentry_cache| dentry_cache.shrink(max))
+    }
+
+    #[instrument(skip(self), err, ret)]
+    async fn reference(&self, ino: u64) -> DatenLordResult<()> {
+        let (res, retry) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            let inode = self.get_inode_from_txn(txn.as_mut(), ino).await?;
+            inode.lookup_attr();
+            txn.set(
+                &KeyType::INum2Node(ino),
+                &ValueType::Node(inode.to_serial_node()),
+            );
+            (txn.commit().await, ())
+        });
+        FILESYSTEM_METRICS.observe_storage_operation_throughput(retry, "reference");
+        res
+    }
+
     #[instrument(skip(self))]
     async fn forget(&self, ino: u64, nlookup: u64) -> DatenLordResult<bool> {
         let (res, retry) = retry_txn!(TXN_RETRY_LIMIT, {
@@ -286,14 +480,30 @@ impl MetaData for S3MetaData {
             let mut result = false;
             let inode = self.get_inode_from_txn(txn.as_mut(), ino).await?;
             inode.dec_lookup_count_by(nlookup);
-            let is_deleted = inode.get_lookup_count() == 0;
+            // Only a removed file is deleted, a file in the trash is kept
+            // until it's restored or expired, and an opened file until it's
+            // released
+            let is_deleted = inode.get_lookup_count() == 0
+                && inode.is_deferred_deletion()
+                && txn
+                    .get(&KeyType::Trash(ino))
+                    .await
+                    .add_context(format!(
+                        "{}() failed to get trash entry of ino={ino} from kv engine",
+                        function_name!()
+                    ))?
+                    .is_none()
+                && !self.is_opened(txn.as_mut(), ino).await?;
             if is_deleted {
-                // FIXME: rename should also rename the node's name and reset the parent ino
-                txn.delete(&KeyType::DirEntryKey((
-                    inode.get_parent_ino(),
-                    inode.get_name().to_owned(),
-                )));
+                // Its entry is removed once it's unlinked
                 txn.delete(&KeyType::INum2Node(ino));
+                txn.delete(&KeyType::ChunkIndex(ino));
+                txn.delete(&KeyType::TierPin(ino));
+                txn.delete(&KeyType::AccessAcl(ino));
+                txn.delete(&KeyType::DefaultAcl(ino));
+                txn.delete(&KeyType::SecurityLabel(ino));
+                txn.delete(&KeyType::InodeLease(ino));
+                txn.delete(&KeyType::FileOpeners(ino));
                 result = true;
             } else {
                 txn.set(
@@ -316,53 +526,94 @@ impl MetaData for S3MetaData {
         storage: &StorageType,
     ) -> DatenLordResult<(Duration, FuseAttr)> {
         let ttl = Duration::new(MY_TTL_SEC, 0);
+        // The cached blocks of the file are valid against this mtime, it's
+        // kept across retries, as the storage is truncated in every attempt.
+        let mut cache_mtime = self
+            .open_files
+            .try_get(ino)
+            .map(|open_file| open_file.read().attr.mtime);
         let (res, retry) = retry_txn!(TXN_RETRY_LIMIT, {
             let mut txn = self.kv_engine.new_meta_txn().await;
             let mut inode = self.get_inode_from_txn(txn.as_mut(), ino).await?;
             let remote_attr = inode.get_attr();
-            let dirty_attr_for_reply =
-                match remote_attr.setattr_precheck(param, context.uid, context.gid)? {
-                    Some(dirty_attr) => {
-                        if remote_attr.size != dirty_attr.size {
-                            inode.update_mtime_ctime_to_now();
-                            storage
-                                .truncate(
-                                    ino,
-                                    remote_attr.size.cast(),
-                                    dirty_attr.size.cast(),
-                                    inode.get_attr().mtime,
-                                )
-                                .await?;
-                            if param.fh.is_some() {
-                                // The file is open, update the attr in `open_files`
-                                let raw_open_file = self.open_files.get(ino);
-                                let mut open_file = raw_open_file.write();
-                                open_file.attr.size = dirty_attr.size;
-                                open_file.attr.mtime = inode.get_attr().mtime;
-                                open_file.attr.ctime = inode.get_attr().ctime;
-                            }
-                        }
-                        inode.set_attr(dirty_attr);
-                        dirty_attr
-                    }
-                    None => {
-                        // setattr did not change any attribute.
-                        return Ok((ttl, fs_util::convert_to_fuse_attr(remote_attr)));
-                    }
-                };
+            let Some(mut dirty_attr) =
+                remote_attr.setattr_precheck(param, context.uid, context.gid)?
+            else {
+                // setattr did not change any attribute.
+                return Ok((ttl, fs_util::convert_to_fuse_attr(remote_attr)));
+            };
+
+            if remote_attr.size != dirty_attr.size {
+                // Truncating down removes the chunks past the new end of file,
+                // while truncating up leaves a hole, which is not in the index.
+                let mut chunk_index = self.get_chunk_index_from_txn(txn.as_mut(), ino).await?;
+                let old_blocks = chunk_index.st_blocks();
+                chunk_index.truncate(dirty_attr.size);
+                dirty_attr.blocks = chunk_index.st_blocks();
+                // A removed file is no longer charged
+                if !inode.is_deferred_deletion() {
+                    let bytes = quota::bytes_delta(old_blocks, dirty_attr.blocks);
+                    self.charge_usage(txn.as_mut(), ino, bytes, 0, false)
+                        .await?;
+                }
+                txn.set(
+                    &KeyType::ChunkIndex(ino),
+                    &ValueType::ChunkIndex(chunk_index),
+                );
+
+                // The blocks past the new end of file are removed from caches
+                // and the backend, and the last block is filled with zeros.
+                let new_mtime = storage
+                    .truncate(
+                        ino,
+                        remote_attr.size.cast(),
+                        dirty_attr.size.cast(),
+                        cache_mtime.unwrap_or(remote_attr.mtime),
+                    )
+                    .await?;
+                cache_mtime = Some(new_mtime);
+                dirty_attr.mtime = new_mtime;
+                dirty_attr.ctime = new_mtime;
+
+                let inline_data = inline_data::update(
+                    inode.inline_data(),
+                    remote_attr.size,
+                    dirty_attr.size,
+                    self.inline_threshold,
+                    |_| {},
+                );
+                inode.set_inline_data(inline_data);
+            }
+            if dirty_attr.perm != remote_attr.perm {
+                posix_acl::chmod_in_txn(txn.as_mut(), ino, dirty_attr.perm).await?;
+            }
+            inode.set_attr(dirty_attr);
 
             txn.set(
                 &KeyType::INum2Node(ino),
                 &ValueType::Node(inode.to_serial_node()),
             );
 
-            (
-                txn.commit().await,
-                (ttl, fs_util::convert_to_fuse_attr(dirty_attr_for_reply)),
-            )
+            (txn.commit().await, dirty_attr)
         });
         FILESYSTEM_METRICS.observe_storage_operation_throughput(retry, "setattr");
-        res
+        let attr = res?;
+        self.invalidate_prefetched(ino);
+
+        // Keep the size of the open file consistent, no matter whether it's
+        // truncated via the handle or not. Writes are blocked by the caller
+        // meanwhile.
+        if param.size.is_some() {
+            if let Some(open_file) = self.open_files.try_get(ino) {
+                let mut open_file = open_file.write();
+                open_file.attr.size = attr.size;
+                open_file.attr.blocks = attr.blocks;
+                open_file.attr.mtime = attr.mtime;
+                open_file.attr.ctime = attr.ctime;
+                open_file.inline_data = None;
+            }
+        }
+        Ok((ttl, fs_util::convert_to_fuse_attr(attr)))
     }
 
     #[instrument(skip(self), err, ret)]
@@ -418,14 +669,49 @@ impl MetaData for S3MetaData {
                 }
             }
 
+            // The child leaves the volume and the subtrees of the quotas, and a
+            // directory is no longer a quota root.
+            let child_bytes = quota::blocks_to_bytes(child_node.get_attr().blocks);
+            self.charge_usage(txn.as_mut(), parent, -child_bytes.cast::<i64>(), -1, false)
+                .await?;
+            if let SFlag::S_IFDIR = child_node.get_type() {
+                txn.delete(&KeyType::Quota(child_ino));
+                txn.delete(&KeyType::Scratch(child_ino));
+            }
+
             // Ready to unlink
-            let deferred_deletion = child_node.get_lookup_count() > 0;
+            let deferred_deletion = child_node.get_lookup_count() > 0
+                || self.is_opened(txn.as_mut(), child_ino).await?;
+            // A file is moved into the trash instead of being removed, if the
+            // trash is enabled, its path is recorded before it's unlinked.
+            let is_dir = matches!(child_node.get_type(), SFlag::S_IFDIR);
+            let trash_entry = if self.trash_retention.is_some()
+                && !is_dir
+                && !self.in_scratch(txn.as_mut(), parent).await?
+            {
+                let path = self.node_path(txn.as_mut(), parent, name).await?;
+                Some(TrashEntry::new(child_ino, path))
+            } else {
+                None
+            };
             // Deferred deletion is for inode ,not for dir entry
             // So we will remove the dir entry immediately
             txn.delete(&KeyType::DirEntryKey((parent, name.into())));
             parent_node.update_mtime_ctime_to_now();
 
-            if deferred_deletion {
+            if let Some(trash_entry) = trash_entry {
+                // The node is removed like a deferred deletion, but `forget()`
+                // keeps it while it's in the trash.
+                child_node.mark_deferred_deletion();
+                txn.set(
+                    &KeyType::INum2Node(child_ino),
+                    &ValueType::Node(child_node.to_serial_node()),
+                );
+                txn.set(
+                    &KeyType::Trash(child_ino),
+                    &ValueType::TrashEntry(trash_entry),
+                );
+            } else if deferred_deletion {
                 // `forget()` will remove the inode
                 child_node.mark_deferred_deletion();
                 txn.set(
@@ -435,8 +721,15 @@ impl MetaData for S3MetaData {
             } else {
                 if let SFlag::S_IFREG = child_node.get_type() {
                     result = Some(child_ino);
+                    txn.delete(&KeyType::ChunkIndex(child_ino));
                 }
                 txn.delete(&KeyType::INum2Node(child_ino));
+                txn.delete(&KeyType::TierPin(child_ino));
+                txn.delete(&KeyType::AccessAcl(child_ino));
+                txn.delete(&KeyType::DefaultAcl(child_ino));
+                txn.delete(&KeyType::SecurityLabel(child_ino));
+                txn.delete(&KeyType::InodeLease(child_ino));
+                txn.delete(&KeyType::FileOpeners(child_ino));
             }
             txn.set(
                 &KeyType::INum2Node(parent),
@@ -446,10 +739,19 @@ impl MetaData for S3MetaData {
         });
 
         FILESYSTEM_METRICS.observe_storage_operation_throughput(retry, "unlink");
+        self.invalidate_prefetched(parent);
+        if let Some(ref dentry_cache) = self.dentry_cache {
+            dentry_cache.invalidate(parent, name);
+        }
         res
     }
 
-    async fn new(kv_engine: Arc<KVEngineType>, node_id: &str) -> DatenLordResult<Arc<Self>> {
+    async fn new(
+        kv_engine: Arc<KVEngineType>,
+        node_id: &str,
+        chunk_size: u64,
+        options: MetaDataOptions,
+    ) -> DatenLordResult<Arc<Self>> {
         let meta = Arc::new(Self {
             cur_fd: AtomicU32::new(4),
             node_id: Arc::<str>::from(node_id.to_owned()),
@@ -457,6 +759,13 @@ impl MetaData for S3MetaData {
             inum_allocator: INumAllocator::new(Arc::clone(&kv_engine)),
             kv_engine,
             open_files: OpenFiles::new(),
+            chunk_size,
+            capacity: options.capacity,
+            trash_retention: options.trash_retention,
+            inline_threshold: options.inline_threshold,
+            stat_ahead: options.stat_ahead_limit.map(StatAhead::new),
+            negative_cache: options.negative_cache_ttl.map(NegativeCache::new),
+            dentry_cache: options.dentry_cache_capacity.map(DentryCache::new),
         });
 
         let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
@@ -487,6 +796,29 @@ impl MetaData for S3MetaData {
         });
         res?;
 
+        // The usage of the volume is counted once when the accounting starts,
+        // the volume may be created before it's supported.
+        let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = meta.kv_engine.new_meta_txn().await;
+            let baseline_key = KeyType::VolumeUsage(String::new());
+            let baseline = txn.get(&baseline_key).await.add_context(format!(
+                "{}() failed to get the usage of the volume from kv engine",
+                function_name!()
+            ))?;
+            if baseline.is_some() {
+                (RETRY_TXN_BREAK, ())
+            } else {
+                let (bytes, inodes) = meta.subtree_usage(FUSE_ROOT_ID).await?;
+                info!("[init] the volume has {bytes} bytes in {inodes} inodes");
+                txn.set(
+                    &baseline_key,
+                    &ValueType::VolumeUsage(VolumeUsage::new(bytes, inodes)),
+                );
+                (txn.commit().await, ())
+            }
+        });
+        res?;
+
         Ok(meta)
     }
 
@@ -500,50 +832,20 @@ impl MetaData for S3MetaData {
     // Create a file, but do not open it. They are two separate steps.
     // If the file does not exist, first create it with
     // the specified mode, and then open it.
-    #[allow(clippy::too_many_lines)]
     async fn mknod(&self, param: CreateParam) -> DatenLordResult<(Duration, FuseAttr, u64)> {
         check_name_length(&param.name)?;
         check_type_supported(&param.node_type)?;
         let parent_ino = param.parent;
         let (res, retry) = retry_txn!(TXN_RETRY_LIMIT, {
             let mut txn = self.kv_engine.new_meta_txn().await;
-            let mut parent_node = self.get_inode_from_txn(txn.as_mut(), parent_ino).await?;
-
-            if self
-                .try_get_dir_entry(txn.as_mut(), parent_ino, &param.name)
-                .await?
-                .is_some()
-            {
-                return build_error_result_from_errno(
-                    Errno::EEXIST,
-                    format!(
-                        "failed to create file name={:?} under parent ino={} and name={:?}",
-                        param.name,
-                        parent_ino,
-                        parent_node.get_name(),
-                    ),
-                );
-            }
-
-            let new_num = self.alloc_inum().await?;
-
-            let new_node = parent_node
-                .create_child_node(&param, new_num, txn.as_mut())
-                .await?;
-            let fuse_attr = fs_util::convert_to_fuse_attr(new_node.get_attr());
-            let ttl = Duration::new(MY_TTL_SEC, 0);
-            txn.set(
-                &KeyType::INum2Node(new_num),
-                &ValueType::Node(new_node.to_serial_node()),
-            );
-            txn.set(
-                &KeyType::INum2Node(parent_ino),
-                &ValueType::Node(parent_node.to_serial_node()),
-            );
-            (txn.commit().await, (ttl, fuse_attr))
+            let attr = self.create_in_txn(txn.as_mut(), &param).await?;
+            (txn.commit().await, attr)
         });
         FILESYSTEM_METRICS.observe_storage_operation_throughput(retry, "mknod");
-        let (ttl, fuse_attr) = res?;
+        let fuse_attr = fs_util::convert_to_fuse_attr(res?);
+        let ttl = Duration::new(MY_TTL_SEC, 0);
+        self.invalidate_prefetched(parent_ino);
+        self.invalidate_missing(parent_ino, &param.name);
 
         Ok((ttl, fuse_attr, MY_GENERATION))
     }
@@ -557,6 +859,54 @@ impl MetaData for S3MetaData {
         parent: INum,
         child_name: &str,
     ) -> DatenLordResult<(Duration, FuseAttr, u64)> {
+        let build_enoent = || {
+            build_error_result_from_errno(
+                Errno::ENOENT,
+                format!("failed to find child name={child_name:?} under parent ino={parent} "),
+            )
+        };
+        // The permission of the parent is checked against the kv engine
+        if !NEED_CHECK_PERM {
+            if let Some(node) = self
+                .stat_ahead
+                .as_ref()
+                .and_then(|stat_ahead| stat_ahead.lookup(parent, child_name))
+            {
+                let ttl = Duration::new(MY_TTL_SEC, 0);
+                let fuse_attr = fs_util::convert_to_fuse_attr(node.attr);
+                return Ok((ttl, fuse_attr, MY_GENERATION));
+            }
+            if self
+                .negative_cache
+                .as_ref()
+                .is_some_and(|negative_cache| negative_cache.contains(parent, child_name))
+            {
+                return build_enoent();
+            }
+            if let Some(ref dentry_cache) = self.dentry_cache {
+                if let Some(child_ino) = dentry_cache.lookup(parent, child_name) {
+                    // The child may be removed by another node, whose change
+                    // is not watched yet.
+                    match self.get_node_from_kv_engine(child_ino).await? {
+                        Some(child_node) => {
+                            let ttl = Duration::new(MY_TTL_SEC, 0);
+                            let fuse_attr = fs_util::convert_to_fuse_attr(child_node.get_attr());
+                            return Ok((ttl, fuse_attr, MY_GENERATION));
+                        }
+                        None => dentry_cache.invalidate(parent, child_name),
+                    }
+                }
+            }
+        }
+        let generation = self
+            .negative_cache
+            .as_ref()
+            .map_or(0, NegativeCache::generation);
+        let seq = self
+            .dentry_cache
+            .as_ref()
+            .map_or(0, |dentry_cache| dentry_cache.seq(parent));
+
         let (res, retry) = retry_txn!(TXN_RETRY_LIMIT, {
             let mut txn = self.kv_engine.new_meta_txn().await;
             if NEED_CHECK_PERM {
@@ -570,10 +920,10 @@ impl MetaData for S3MetaData {
                 .try_get_dir_entry(txn.as_mut(), parent, child_name)
                 .await?
             else {
-                return build_error_result_from_errno(
-                    Errno::ENOENT,
-                    format!("failed to find child name={child_name:?} under parent ino={parent} "),
-                );
+                if let Some(ref negative_cache) = self.negative_cache {
+                    negative_cache.insert(generation, parent, child_name);
+                }
+                return build_enoent();
             };
 
             let child_ino = child_entry.ino();
@@ -590,226 +940,1268 @@ impl MetaData for S3MetaData {
         });
 
         FILESYSTEM_METRICS.observe_storage_operation_throughput(retry, "lookup");
+        if let Some(ref dentry_cache) = self.dentry_cache {
+            if let Ok((_, ref fuse_attr, _)) = res {
+                dentry_cache.insert(seq, parent, child_name, fuse_attr.ino);
+            }
+        }
         res
     }
 
-    #[allow(clippy::too_many_lines)] // TODO: refactor it into smaller functions
     #[instrument(skip(self), err, ret)]
-    async fn rename(&self, context: ReqContext, param: RenameParam) -> DatenLordResult<()> {
-        let old_parent = param.old_parent;
-        let old_name = param.old_name.as_str();
-        let new_parent = param.new_parent;
-        let new_name = param.new_name.as_str();
-        let flags = param.flags;
-        // TODO: replace the new_node should delete its related data
-        // TODO: we should also rename the node's name and parent_ino, not only the dir
-        // entry
-        let exchange = match flags {
-            0 | RENAME_NOREPLACE => false,
-            RENAME_EXCHANGE => true,
-            _ => {
-                return build_error_result_from_errno(
-                    Errno::EINVAL,
-                    format!("rename(): flags={flags} is not supported"),
-                )
+    async fn rename(
+        &self,
+        context: ReqContext,
+        param: RenameParam,
+    ) -> DatenLordResult<Option<INum>> {
+        let (res, retry) = retry_txn!(TXN_RETRY_LIMIT, {
+            // The parents are read by both the check and the rename.
+            let mut txn = CompoundTxn::new(self.kv_engine.new_meta_txn().await);
+            if let Some(exchange) = self.rename_precheck(&mut txn, &param).await? {
+                let removed = self
+                    .rename_in_txn(&mut txn, &context, &param, exchange)
+                    .await?;
+                (txn.commit().await, removed)
+            } else {
+                (RETRY_TXN_BREAK, None)
             }
-        };
+        });
 
-        if old_parent == new_parent && old_name == new_name {
-            return Ok(());
-        }
+        FILESYSTEM_METRICS.observe_storage_operation_throughput(retry, "rename");
+        self.invalidate_renamed(&param);
+        res
+    }
 
-        let build_enoent = |name: &str, parent: INum| {
-            build_error_result_from_errno(
-                Errno::ENOENT,
-                format!(
-                    "exchange_pre_check() failed to find child entry of name={name:?} \
-                        under parent directory ino={parent}"
-                ),
-            )
-        };
+    #[instrument(skip(self), err, ret)]
+    async fn transact(
+        &self,
+        context: ReqContext,
+        ops: &[MetaOp],
+    ) -> DatenLordResult<Vec<MetaOpResult>> {
+        for op in ops {
+            if let MetaOp::Create(ref param) = *op {
+                check_name_length(&param.name)?;
+                check_type_supported(&param.node_type)?;
+            }
+        }
 
         let (res, retry) = retry_txn!(TXN_RETRY_LIMIT, {
-            let mut txn = self.kv_engine.new_meta_txn().await;
-            let old_parent_node = Arc::new(Mutex::new(
-                self.get_inode_from_txn(txn.as_mut(), old_parent).await?,
-            ));
-            let old_entry = {
-                let old_parent_node = old_parent_node.lock().await;
-                match self
-                    .try_get_dir_entry(txn.as_mut(), old_parent, old_name)
-                    .await?
-                {
-                    None => {
-                        return build_enoent(old_name, old_parent);
+            let mut txn = CompoundTxn::new(self.kv_engine.new_meta_txn().await);
+            let mut results = Vec::with_capacity(ops.len());
+            let mut created = None;
+            for op in ops {
+                let result = match *op {
+                    MetaOp::Create(ref param) => {
+                        let attr = self.create_in_txn(&mut txn, param).await?;
+                        created = Some(attr.ino);
+                        MetaOpResult::Created(fs_util::convert_to_fuse_attr(attr))
                     }
-                    Some(old_entry) => {
-                        self.check_sticky_bit(&context, &old_parent_node, &old_entry, txn.as_mut())
-                            .await?;
-                        old_entry
+                    MetaOp::SetAttr { ino, ref param } => {
+                        let Some(ino) = ino.or(created) else {
+                            return build_error_result_from_errno(
+                                Errno::EINVAL,
+                                "no node is created before setting its attributes".to_owned(),
+                            );
+                        };
+                        let attr = self.set_attr_in_txn(&mut txn, &context, ino, param).await?;
+                        MetaOpResult::AttrSet(fs_util::convert_to_fuse_attr(attr))
                     }
-                }
-            };
-
-            let new_parent_node = if old_parent == new_parent {
-                Arc::clone(&old_parent_node)
-            } else {
-                Arc::new(Mutex::new(
-                    self.get_inode_from_txn(txn.as_mut(), new_parent).await?,
-                ))
-            };
-
-            let new_entry = self
-                .try_get_dir_entry(txn.as_mut(), new_parent, new_name)
-                .await?;
-            match new_entry {
-                None => {
-                    // new_name does not exist under new_parent
-                    if exchange {
-                        // exchange is true, new name must exist
-                        return build_enoent(new_name, new_parent);
+                    MetaOp::Rename(ref param) => {
+                        // The quotas are read in the transaction, so the
+                        // directories created by the former operations are in
+                        // the quotas of their parents.
+                        let removed = match self.rename_precheck(&mut txn, param).await? {
+                            Some(exchange) => {
+                                self.rename_in_txn(&mut txn, &context, param, exchange)
+                                    .await?
+                            }
+                            None => None,
+                        };
+                        MetaOpResult::Renamed(removed)
                     }
-                    // exchange is false, so we can do rename directly
-                    // Remove from old_parent and insert into new_parent
-                    txn.delete(&KeyType::DirEntryKey((old_parent, old_name.into())));
-                    txn.set(
-                        &KeyType::DirEntryKey((new_parent, new_name.into())),
-                        &ValueType::DirEntry(DirEntry::new(
-                            old_entry.ino(),
-                            new_name.into(),
-                            old_entry.file_type(),
-                        )),
-                    );
-                }
-                Some(new_entry) => {
-                    // new_name exists under new_parent
-                    if exchange {
-                        // old_name -> new_entry
-                        // new_name -> old_entry
-                        txn.set(
-                            &KeyType::DirEntryKey((old_parent, old_name.into())),
-                            &ValueType::DirEntry(DirEntry::new(
-                                new_entry.ino(),
-                                old_name.into(),
-                                new_entry.file_type(),
-                            )),
-                        );
-                        txn.set(
-                            &KeyType::DirEntryKey((new_parent, new_name.into())),
-                            &ValueType::DirEntry(DirEntry::new(
-                                old_entry.ino(),
-                                new_name.into(),
-                                old_entry.file_type(),
-                            )),
-                        );
-                    } else {
-                        // exchange is false, replace or no_replace
-                        if flags & RENAME_NOREPLACE != 0 {
+                    MetaOp::SetXattr {
+                        ino,
+                        ref name,
+                        ref value,
+                    } => {
+                        let Some(ino) = ino.or(created) else {
                             return build_error_result_from_errno(
-                                Errno::EEXIST,
-                                format!(
-                                    "rename(): failed to rename() \
-                                        because new_name={new_name:?} already exists",
-                                ),
+                                Errno::EINVAL,
+                                "no node is created before setting its extended attribute"
+                                    .to_owned(),
                             );
-                        }
-                        let delete_key = KeyType::DirEntryKey((old_parent, old_name.into()));
-                        txn.delete(&delete_key);
-                        txn.set(
-                            &KeyType::DirEntryKey((new_parent, new_name.into())),
-                            &ValueType::DirEntry(DirEntry::new(
-                                old_entry.ino(),
-                                new_name.into(),
-                                old_entry.file_type(),
-                            )),
+                        };
+                        self.set_xattr_in_txn(&mut txn, &context, ino, name, value)
+                            .await?;
+                        MetaOpResult::XattrSet(ino)
+                    }
+                };
+                results.push(result);
+            }
+            (txn.commit().await, results)
+        });
+        FILESYSTEM_METRICS.observe_storage_operation_throughput(retry, "transact");
+        let results = res?;
+
+        for op in ops {
+            match *op {
+                MetaOp::Create(ref param) => {
+                    self.invalidate_prefetched(param.parent);
+                    self.invalidate_missing(param.parent, &param.name);
+                }
+                MetaOp::SetAttr { .. } | MetaOp::SetXattr { .. } => {}
+                MetaOp::Rename(ref param) => self.invalidate_renamed(param),
+            }
+        }
+        for result in &results {
+            match *result {
+                MetaOpResult::AttrSet(ref attr) => self.invalidate_prefetched(attr.ino),
+                MetaOpResult::XattrSet(ino) => self.invalidate_prefetched(ino),
+                MetaOpResult::Created(_) | MetaOpResult::Renamed(_) => {}
+            }
+        }
+        Ok(results)
+    }
+
+    /// Helper function to write data
+    async fn write_helper(
+        &self,
+        ino: u64,
+        new_mtime: SystemTime,
+        new_size: u64,
+        offset: u64,
+        len: u64,
+        data: Option<&[u8]>,
+    ) -> DatenLordResult<()> {
+        // Update the `mtime` and `size` of the file, the content inlined is
+        // read from the storage since then. The writes of disjoint ranges run
+        // in parallel, so neither of them is set back by a write finished
+        // later.
+        {
+            let raw_open_file = self.open_files.get(ino);
+            let mut open_file = raw_open_file.write();
+            open_file.attr.mtime = open_file.attr.mtime.max(new_mtime);
+            open_file.attr.size = open_file.attr.size.max(new_size);
+            open_file.inline_data = None;
+        }
+
+        let (res, retry) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            let mut chunk_index = self.get_chunk_index_from_txn(txn.as_mut(), ino).await?;
+            let old_blocks = chunk_index.st_blocks();
+            chunk_index.record_write(offset, len);
+            let blocks = chunk_index.st_blocks();
+            txn.set(
+                &KeyType::ChunkIndex(ino),
+                &ValueType::ChunkIndex(chunk_index),
+            );
+
+            // The quotas are checked by `check_quota()` before the data is
+            // stored, so the usage is charged anyway. A removed file is no
+            // longer charged.
+            let mut node = self.get_inode_from_txn(txn.as_mut(), ino).await?;
+            if !node.is_deferred_deletion() {
+                let bytes = quota::bytes_delta(old_blocks, blocks);
+                self.charge_usage(txn.as_mut(), ino, bytes, 0, false)
+                    .await?;
+            }
+            let mut attr = node.get_attr();
+            let new_size = attr.size.max(new_size);
+            let inline_data = data.and_then(|data| {
+                inline_data::update(
+                    node.inline_data(),
+                    attr.size,
+                    new_size,
+                    self.inline_threshold,
+                    |content| inline_data::write(content, offset, data),
+                )
+            });
+            node.set_inline_data(inline_data);
+            attr.mtime = attr.mtime.max(new_mtime);
+            attr.size = new_size;
+            attr.blocks = blocks;
+            node.set_attr(attr);
+            txn.set(
+                &KeyType::INum2Node(ino),
+                &ValueType::Node(node.to_serial_node()),
+            );
+            (txn.commit().await, blocks)
+        });
+
+        FILESYSTEM_METRICS.observe_storage_operation_throughput(retry, "write");
+        let blocks = res?;
+        self.invalidate_prefetched(ino);
+        self.open_files.get(ino).write().attr.blocks = blocks;
+        Ok(())
+    }
+
+    #[instrument(skip(self), err, ret)]
+    async fn fallocate_helper(
+        &self,
+        ino: u64,
+        new_mtime: SystemTime,
+        new_size: u64,
+        punched: Option<Range<u64>>,
+    ) -> DatenLordResult<()> {
+        let (res, retry) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            let mut chunk_index = self.get_chunk_index_from_txn(txn.as_mut(), ino).await?;
+            let old_blocks = chunk_index.st_blocks();
+            if let Some(ref punched) = punched {
+                chunk_index.punch_hole(punched.start, punched.end.overflow_sub(punched.start));
+            }
+            let blocks = chunk_index.st_blocks();
+            txn.set(
+                &KeyType::ChunkIndex(ino),
+                &ValueType::ChunkIndex(chunk_index),
+            );
+
+            let mut node = self.get_inode_from_txn(txn.as_mut(), ino).await?;
+            if !node.is_deferred_deletion() {
+                let bytes = quota::bytes_delta(old_blocks, blocks);
+                self.charge_usage(txn.as_mut(), ino, bytes, 0, false)
+                    .await?;
+            }
+            let mut attr = node.get_attr();
+            let inline_data = inline_data::update(
+                node.inline_data(),
+                attr.size,
+                new_size,
+                self.inline_threshold,
+                |content| {
+                    if let Some(ref punched) = punched {
+                        inline_data::punch(content, punched.clone());
+                    }
+                },
+            );
+            node.set_inline_data(inline_data);
+            attr.mtime = new_mtime;
+            attr.ctime = new_mtime;
+            attr.size = new_size;
+            attr.blocks = blocks;
+            node.set_attr(attr);
+            txn.set(
+                &KeyType::INum2Node(ino),
+                &ValueType::Node(node.to_serial_node()),
+            );
+            (txn.commit().await, attr)
+        });
+
+        FILESYSTEM_METRICS.observe_storage_operation_throughput(retry, "fallocate");
+        let attr = res?;
+        self.invalidate_prefetched(ino);
+        if let Some(open_file) = self.open_files.try_get(ino) {
+            let mut open_file = open_file.write();
+            open_file.attr.size = attr.size;
+            open_file.attr.blocks = attr.blocks;
+            open_file.attr.mtime = attr.mtime;
+            open_file.attr.ctime = attr.ctime;
+            open_file.inline_data = None;
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self), err, ret)]
+    async fn lseek_helper(&self, ino: u64, offset: u64, whence: u32) -> DatenLordResult<u64> {
+        let size = if let Some(open_file) = self.open_files.try_get(ino) {
+            open_file.read().attr.size
+        } else {
+            self.get_node_from_kv_engine(ino)
+                .await?
+                .ok_or_else(|| build_inconsistent_fs!(ino))?
+                .get_attr()
+                .size
+        };
+
+        let chunk_index = self
+            .kv_engine
+            .get(&KeyType::ChunkIndex(ino))
+            .await
+            .add_context(format!(
+                "{}() failed to get chunk index of ino={ino} from kv engine",
+                function_name!()
+            ))?
+            .map_or_else(
+                || ChunkIndex::new(self.chunk_size),
+                ValueType::into_chunk_index,
+            );
+
+        let found = match whence.cast::<i32>() {
+            SEEK_DATA => chunk_index.seek_data(offset, size),
+            SEEK_HOLE => chunk_index.seek_hole(offset, size),
+            _ => {
+                return build_error_result_from_errno(
+                    Errno::EINVAL,
+                    format!("lseek() got unsupported whence={whence}"),
+                );
+            }
+        };
+        match found {
+            Some(found) => Ok(found),
+            None => build_error_result_from_errno(
+                Errno::ENXIO,
+                format!("lseek() found no more data or hole after offset={offset}"),
+            ),
+        }
+    }
+
+    #[instrument(skip(self), err, ret)]
+    async fn check_quota(&self, ino: u64, offset: u64, len: u64) -> DatenLordResult<()> {
+        let roots = self.quota_roots(ino).await?;
+        if roots.is_empty() {
+            return Ok(());
+        }
+
+        let mut chunk_index = self
+            .kv_engine
+            .get(&KeyType::ChunkIndex(ino))
+            .await
+            .add_context(format!(
+                "{}() failed to get chunk index of ino={ino} from kv engine",
+                function_name!()
+            ))?
+            .map_or_else(
+                || ChunkIndex::new(self.chunk_size),
+                ValueType::into_chunk_index,
+            );
+        let old_blocks = chunk_index.st_blocks();
+        chunk_index.record_write(offset, len);
+        let bytes = quota::bytes_delta(old_blocks, chunk_index.st_blocks());
+        roots.iter().try_for_each(|root| root.check(bytes, 0))
+    }
+
+    #[instrument(skip(self), err, ret)]
+    async fn get_quota(&self, ino: u64) -> DatenLordResult<Option<Quota>> {
+        let quota = self
+            .kv_engine
+            .get(&KeyType::Quota(ino))
+            .await
+            .add_context(format!(
+                "{}() failed to get quota of ino={ino} from kv engine",
+                function_name!()
+            ))?;
+        Ok(quota.map(ValueType::into_quota))
+    }
+
+    #[instrument(skip(self), err, ret)]
+    async fn set_quota(
+        &self,
+        context: ReqContext,
+        ino: u64,
+        attr: QuotaAttr,
+        limit: u64,
+    ) -> DatenLordResult<()> {
+        if context.uid != 0 {
+            return build_error_result_from_errno(
+                Errno::EPERM,
+                format!("set_quota() of ino={ino} is only allowed for the super user"),
+            );
+        }
+        self.get_node_from_kv_engine(ino)
+            .await?
+            .ok_or_else(|| build_inconsistent_fs!(ino))?
+            .check_is_dir()?;
+
+        let (res, retry) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            let key = KeyType::Quota(ino);
+            let current = txn.get(&key).await.add_context(format!(
+                "{}() failed to get quota of ino={ino} from kv engine",
+                function_name!()
+            ))?;
+            let mut quota = if let Some(current) = current {
+                current.into_quota()
+            } else {
+                let (used_bytes, used_inodes) = self.subtree_usage(ino).await?;
+                Quota::new(ino, used_bytes, used_inodes)
+            };
+            quota.set_limit(attr, limit)?;
+            if quota.is_unlimited() {
+                txn.delete(&key);
+            } else {
+                txn.set(&key, &ValueType::Quota(quota));
+            }
+            (txn.commit().await, ())
+        });
+        FILESYSTEM_METRICS.observe_storage_operation_throughput(retry, "set_quota");
+        res
+    }
+
+    #[instrument(skip(self), err, ret)]
+    async fn get_tier_pin(&self, ino: u64) -> DatenLordResult<Option<Tier>> {
+        let pin = self
+            .kv_engine
+            .get(&KeyType::TierPin(ino))
+            .await
+            .add_context(format!(
+                "{}() failed to get tier pin of ino={ino} from kv engine",
+                function_name!()
+            ))?;
+        Ok(pin.map(ValueType::into_tier_pin))
+    }
+
+    #[instrument(skip(self), err, ret)]
+    async fn set_tier_pin(
+        &self,
+        context: ReqContext,
+        ino: u64,
+        tier: Option<Tier>,
+    ) -> DatenLordResult<()> {
+        let node = self
+            .get_node_from_kv_engine(ino)
+            .await?
+            .ok_or_else(|| build_inconsistent_fs!(ino))?;
+        if context.uid != 0 && context.uid != node.get_attr().uid {
+            return build_error_result_from_errno(
+                Errno::EPERM,
+                format!("set_tier_pin() of ino={ino} is only allowed for the owner"),
+            );
+        }
+
+        let key = KeyType::TierPin(ino);
+        match tier {
+            Some(tier) => {
+                self.kv_engine
+                    .set(&key, &ValueType::TierPin(tier), None)
+                    .await
+            }
+            None => self.kv_engine.delete(&key, None).await,
+        }
+        .add_context(format!(
+            "{}() failed to set tier pin of ino={ino} to kv engine",
+            function_name!()
+        ))?;
+        Ok(())
+    }
+
+    #[instrument(skip(self), err, ret)]
+    async fn is_scratch(&self, ino: u64) -> DatenLordResult<bool> {
+        let mark = self
+            .kv_engine
+            .get(&KeyType::Scratch(ino))
+            .await
+            .add_context(format!(
+                "{}() failed to get scratch mark of ino={ino} from kv engine",
+                function_name!()
+            ))?;
+        Ok(mark.is_some())
+    }
+
+    #[instrument(skip(self), err, ret)]
+    async fn set_scratch(
+        &self,
+        context: ReqContext,
+        ino: u64,
+        scratch: bool,
+    ) -> DatenLordResult<()> {
+        let node = self
+            .get_node_from_kv_engine(ino)
+            .await?
+            .ok_or_else(|| build_inconsistent_fs!(ino))?;
+        node.check_is_dir()?;
+        if context.uid != 0 && context.uid != node.get_attr().uid {
+            return build_error_result_from_errno(
+                Errno::EPERM,
+                format!("set_scratch() of ino={ino} is only allowed for the owner"),
+            );
+        }
+
+        let key = KeyType::Scratch(ino);
+        if scratch {
+            self.kv_engine.set(&key, &ValueType::Scratch, None).await
+        } else {
+            self.kv_engine.delete(&key, None).await
+        }
+        .add_context(format!(
+            "{}() failed to set scratch mark of ino={ino} to kv engine",
+            function_name!()
+        ))?;
+        Ok(())
+    }
+
+    #[instrument(skip(self), err, ret)]
+    async fn get_posix_acl(&self, ino: u64, kind: AclKind) -> DatenLordResult<Option<PosixAcl>> {
+        let acl = self
+            .kv_engine
+            .get(&kind.key(ino))
+            .await
+            .add_context(format!(
+                "{}() failed to get {kind:?} ACL of ino={ino} from kv engine",
+                function_name!()
+            ))?;
+        Ok(acl.map(ValueType::into_posix_acl))
+    }
+
+    #[instrument(skip(self), err, ret)]
+    async fn set_posix_acl(
+        &self,
+        context: ReqContext,
+        ino: u64,
+        kind: AclKind,
+        acl: Option<PosixAcl>,
+    ) -> DatenLordResult<()> {
+        let (res, retry) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            self.set_posix_acl_in_txn(txn.as_mut(), &context, ino, kind, acl.as_ref())
+                .await?;
+            (txn.commit().await, ())
+        });
+        FILESYSTEM_METRICS.observe_storage_operation_throughput(retry, "set_posix_acl");
+        res?;
+        self.invalidate_prefetched(ino);
+        Ok(())
+    }
+
+    #[instrument(skip(self), err, ret)]
+    async fn get_security_label(&self, ino: u64) -> DatenLordResult<Option<Vec<u8>>> {
+        let label = self
+            .kv_engine
+            .get(&KeyType::SecurityLabel(ino))
+            .await
+            .add_context(format!(
+                "{}() failed to get SELinux label of ino={ino} from kv engine",
+                function_name!()
+            ))?;
+        Ok(label.map(ValueType::into_security_label))
+    }
+
+    #[instrument(skip(self), err, ret)]
+    async fn set_security_label(
+        &self,
+        context: ReqContext,
+        ino: u64,
+        label: Option<Vec<u8>>,
+    ) -> DatenLordResult<()> {
+        let (res, retry) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            self.set_security_label_in_txn(txn.as_mut(), &context, ino, label.as_deref())
+                .await?;
+            (txn.commit().await, ())
+        });
+        FILESYSTEM_METRICS.observe_storage_operation_throughput(retry, "set_security_label");
+        res
+    }
+
+    #[instrument(skip(self), err, ret)]
+    async fn get_parent_ino(&self, ino: u64) -> DatenLordResult<INum> {
+        let node = self
+            .get_node_from_kv_engine(ino)
+            .await?
+            .ok_or_else(|| build_inconsistent_fs!(ino))?;
+        Ok(node.get_parent_ino())
+    }
+
+    #[instrument(skip(self), err, ret)]
+    async fn purge_trash(&self, retention: Duration) -> DatenLordResult<Vec<INum>> {
+        let now = SystemTime::now();
+        let mut removed = vec![];
+        for entry in trash::list_trash(&self.kv_engine).await? {
+            if !entry.is_expired(now, retention) {
+                continue;
+            }
+            let ino = entry.ino;
+            let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+                let mut txn = self.kv_engine.new_meta_txn().await;
+                let trash_entry = txn.get(&KeyType::Trash(ino)).await.add_context(format!(
+                    "{}() failed to get trash entry of ino={ino} from kv engine",
+                    function_name!()
+                ))?;
+                // The file is restored meanwhile
+                if trash_entry.is_none() {
+                    (RETRY_TXN_BREAK, false)
+                } else {
+                    txn.delete(&KeyType::Trash(ino));
+                    // A file still looked up is removed by `forget()` later, and
+                    // a file still opened by `release()`
+                    let mut is_removed = false;
+                    if let Some(node) = self.try_get_inode_from_txn(txn.as_mut(), ino).await? {
+                        if node.get_lookup_count() == 0
+                            && !self.is_opened(txn.as_mut(), ino).await?
+                        {
+                            is_removed = matches!(node.get_type(), SFlag::S_IFREG);
+                            txn.delete(&KeyType::INum2Node(ino));
+                            txn.delete(&KeyType::ChunkIndex(ino));
+                            txn.delete(&KeyType::TierPin(ino));
+                            txn.delete(&KeyType::AccessAcl(ino));
+                            txn.delete(&KeyType::DefaultAcl(ino));
+                            txn.delete(&KeyType::SecurityLabel(ino));
+                            txn.delete(&KeyType::InodeLease(ino));
+                            txn.delete(&KeyType::FileOpeners(ino));
+                        }
+                    }
+                    (txn.commit().await, is_removed)
+                }
+            });
+            if res? {
+                removed.push(ino);
+            }
+        }
+        Ok(removed)
+    }
+}
+
+impl MemoryConsumer for S3MetaData {
+    fn used_memory(&self) -> usize {
+        self.dentry_cache.as_ref().map_or(0, DentryCache::size)
+    }
+}
+
+impl S3MetaData {
+    /// Restore file `ino` in the trash to `path`, or to where it's removed
+    /// from if it's `None`, the missing parent directories are created.
+    ///
+    /// Returns the path the file is restored to.
+    #[instrument(skip(self), err, ret)]
+    pub async fn restore_from_trash(
+        &self,
+        ino: INum,
+        path: Option<&str>,
+    ) -> DatenLordResult<String> {
+        let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            let trash_entry = txn.get(&KeyType::Trash(ino)).await.add_context(format!(
+                "{}() failed to get trash entry of ino={ino} from kv engine",
+                function_name!()
+            ))?;
+            let Some(trash_entry) = trash_entry else {
+                return build_error_result_from_errno(
+                    Errno::ENOENT,
+                    format!("file ino={ino} is not in the trash"),
+                );
+            };
+            let target = path.map_or(trash_entry.into_trash_entry().path, str::to_owned);
+            let (dir_names, name) = trash::split_path(&target)?;
+            let mut node = self.get_inode_from_txn(txn.as_mut(), ino).await?;
+            let attr = node.get_attr();
+
+            // The created directories and the file are charged to the quotas
+            // of the deepest existing directory.
+            let mut existing = FUSE_ROOT_ID;
+            let mut created = 0_i64;
+            let mut parent_node = self.get_inode_from_txn(txn.as_mut(), FUSE_ROOT_ID).await?;
+            for dir_name in dir_names {
+                let parent = parent_node.get_ino();
+                let dir_node = match self
+                    .try_get_dir_entry(txn.as_mut(), parent, dir_name)
+                    .await?
+                {
+                    Some(dir_entry) => {
+                        if dir_entry.file_type() != FileType::Dir {
+                            return build_error_result_from_errno(
+                                Errno::ENOTDIR,
+                                format!("{dir_name:?} in {target:?} is not a directory"),
+                            );
+                        }
+                        existing = dir_entry.ino();
+                        self.get_inode_from_txn(txn.as_mut(), existing).await?
+                    }
+                    None => {
+                        let param = CreateParam {
+                            parent,
+                            name: dir_name.to_owned(),
+                            mode: 0o755,
+                            rdev: 0,
+                            uid: attr.uid,
+                            gid: attr.gid,
+                            umask: 0,
+                            node_type: SFlag::S_IFDIR,
+                            link: None,
+                        };
+                        let dir_ino = self.alloc_inum().await?;
+                        let dir_node = parent_node
+                            .create_child_node(&param, dir_ino, txn.as_mut())
+                            .await?;
+                        txn.set(
+                            &KeyType::INum2Node(parent),
+                            &ValueType::Node(parent_node.to_serial_node()),
                         );
+                        created = created.overflow_add(1);
+                        dir_node
                     }
+                };
+                parent_node = dir_node;
+            }
+
+            let parent = parent_node.get_ino();
+            if self
+                .try_get_dir_entry(txn.as_mut(), parent, name)
+                .await?
+                .is_some()
+            {
+                return build_error_result_from_errno(
+                    Errno::EEXIST,
+                    format!("failed to restore file ino={ino} to {target:?}"),
+                );
+            }
+            let bytes = quota::blocks_to_bytes(attr.blocks);
+            self.charge_usage(
+                txn.as_mut(),
+                existing,
+                bytes.cast(),
+                created.overflow_add(1),
+                true,
+            )
+            .await?;
+
+            txn.set(
+                &KeyType::DirEntryKey((parent, name.to_owned())),
+                &ValueType::DirEntry(DirEntry::new(
+                    ino,
+                    name.to_owned(),
+                    FileType::try_from(node.get_type())?,
+                )),
+            );
+            parent_node.update_mtime_ctime_to_now();
+            node.clear_deferred_deletion();
+            let _: INum = node.set_parent_ino(parent);
+            node.set_name(name);
+            txn.set(
+                &KeyType::INum2Node(ino),
+                &ValueType::Node(node.to_serial_node()),
+            );
+            txn.set(
+                &KeyType::INum2Node(parent),
+                &ValueType::Node(parent_node.to_serial_node()),
+            );
+            txn.delete(&KeyType::Trash(ino));
+            (txn.commit().await, target)
+        });
+        res
+    }
+
+    /// Remove `name` under directory `parent` with its subtree, as `rm -r`
+    /// does. The blocks of the removed files are left to the garbage
+    /// collection of the backend.
+    pub async fn remove_tree(
+        &self,
+        context: ReqContext,
+        parent: INum,
+        name: &str,
+    ) -> DatenLordResult<()> {
+        let key = KeyType::DirEntryKey((parent, name.to_owned()));
+        let Some(entry) = self.kv_engine.get(&key).await? else {
+            return build_error_result_from_errno(
+                Errno::ENOENT,
+                format!("{name:?} is not found under directory ino={parent}"),
+            );
+        };
+        let entry = entry.into_dir_entry();
+        if entry.file_type() != FileType::Dir {
+            let _: Option<INum> = self.unlink(context, parent, name).await?;
+            return Ok(());
+        }
+
+        // The directories are removed once they're empty, i.e. in the reverse
+        // order they're found.
+        let mut dirs = vec![(parent, name.to_owned(), entry.ino())];
+        let mut next: usize = 0;
+        while let Some(&(_, _, dir)) = dirs.get(next) {
+            next = next.overflow_add(1);
+            for child in self.get_all_dir_entry(dir).await? {
+                if child.file_type() == FileType::Dir {
+                    dirs.push((dir, child.name().to_owned(), child.ino()));
+                } else {
+                    let _: Option<INum> = self.unlink(context.clone(), dir, child.name()).await?;
+                }
+            }
+        }
+        for (dir_parent, dir_name, _) in dirs.into_iter().rev() {
+            let _: Option<INum> = self.unlink(context.clone(), dir_parent, &dir_name).await?;
+        }
+        Ok(())
+    }
+
+    /// Prefetch the nodes of the children of directory `ino` listed in
+    /// `dir_entries`. The failures are only logged, as the children are
+    /// fetched again when they're queried.
+    async fn prefetch_children(&self, stat_ahead: &StatAhead, ino: INum, dir_entries: &[DirEntry]) {
+        let generation = stat_ahead.generation();
+        let children = stream::iter(dir_entries.iter().take(stat_ahead.limit()))
+            .map(|entry| async move {
+                let node = self.get_node_from_kv_engine(entry.ino()).await;
+                (entry.name(), node)
+            })
+            .buffer_unordered(STAT_AHEAD_CONCURRENCY)
+            .filter_map(|(name, node)| async move {
+                match node {
+                    Ok(Some(node)) => Some((
+                        name.to_owned(),
+                        PrefetchedNode {
+                            attr: node.get_attr(),
+                            inline_data: node.inline_data().map(Arc::from),
+                        },
+                    )),
+                    // The child is removed meanwhile
+                    Ok(None) => None,
+                    Err(e) => {
+                        debug!("failed to prefetch child name={name:?} of ino={ino}: {e}");
+                        None
+                    }
+                }
+            })
+            .collect()
+            .await;
+        stat_ahead.fill(generation, ino, children);
+    }
+
+    /// Get the prefetched node of `ino`, `None` if it's not prefetched or the
+    /// stat-ahead is disabled.
+    fn prefetched_node(&self, ino: INum) -> Option<PrefetchedNode> {
+        self.stat_ahead
+            .as_ref()
+            .and_then(|stat_ahead| stat_ahead.get(ino))
+    }
+
+    /// Drop the prefetched node of `ino` changed by this node, and the
+    /// children of it if it's a directory.
+    fn invalidate_prefetched(&self, ino: INum) {
+        if let Some(ref stat_ahead) = self.stat_ahead {
+            stat_ahead.invalidate(ino);
+        }
+    }
+
+    /// Drop the miss of `name` in directory `parent` created by this node.
+    fn invalidate_missing(&self, parent: INum, name: &str) {
+        if let Some(ref negative_cache) = self.negative_cache {
+            negative_cache.invalidate(parent, name);
+        }
+    }
+
+    #[allow(clippy::unwrap_used)]
+    /// Get a node from kv engine by inum
+    pub async fn get_node_from_kv_engine(&self, inum: INum) -> DatenLordResult<Option<S3Node>> {
+        let inum_key = KeyType::INum2Node(inum);
+        let raw_data = self.kv_engine.get(&inum_key).await.add_context(format!(
+            "{}() failed to get node of ino={inum} from kv engine",
+            function_name!()
+        ))?;
+
+        // deserialize node
+        Ok(raw_data.map(|value| value.into_s3_node(self)))
+    }
+
+    /// Allocate a new uinque inum for new node
+    async fn alloc_inum(&self) -> DatenLordResult<INum> {
+        let result = self.inum_allocator.alloc_inum_for_fnode().await;
+        debug!("alloc_inum_for_fnode() result={result:?}");
+        result
+    }
+
+    /// Create a node in `txn`, returns its attributes.
+    async fn create_in_txn<T: MetaTxn + ?Sized>(
+        &self,
+        txn: &mut T,
+        param: &CreateParam,
+    ) -> DatenLordResult<FileAttr> {
+        let parent_ino = param.parent;
+        let mut parent_node = self.get_inode_from_txn(txn, parent_ino).await?;
+
+        if self
+            .try_get_dir_entry(txn, parent_ino, &param.name)
+            .await?
+            .is_some()
+        {
+            return build_error_result_from_errno(
+                Errno::EEXIST,
+                format!(
+                    "failed to create file name={:?} under parent ino={} and name={:?}",
+                    param.name,
+                    parent_ino,
+                    parent_node.get_name(),
+                ),
+            );
+        }
+
+        let new_num = self.alloc_inum().await?;
+
+        let mut new_node = parent_node.create_child_node(param, new_num, txn).await?;
+        // A symlink has neither an ACL nor the umask applied.
+        if param.node_type != SFlag::S_IFLNK {
+            let mut attr = new_node.get_attr();
+            posix_acl::inherit_in_txn(txn, parent_ino, new_num, param.umask, &mut attr).await?;
+            new_node.set_attr(attr);
+        }
+        let new_bytes = quota::blocks_to_bytes(new_node.get_attr().blocks);
+        self.charge_usage(txn, parent_ino, new_bytes.cast(), 1, true)
+            .await?;
+        txn.set(
+            &KeyType::INum2Node(new_num),
+            &ValueType::Node(new_node.to_serial_node()),
+        );
+        txn.set(
+            &KeyType::INum2Node(parent_ino),
+            &ValueType::Node(parent_node.to_serial_node()),
+        );
+        Ok(new_node.get_attr())
+    }
+
+    /// Set the attributes of `ino` other than the size in `txn`, returns its
+    /// attributes.
+    async fn set_attr_in_txn<T: MetaTxn + ?Sized>(
+        &self,
+        txn: &mut T,
+        context: &ReqContext,
+        ino: INum,
+        param: &SetAttrParam,
+    ) -> DatenLordResult<FileAttr> {
+        if param.size.is_some() {
+            return build_error_result_from_errno(
+                Errno::EINVAL,
+                format!("the size of ino={ino} is not set in a transaction"),
+            );
+        }
+        let mut inode = self.get_inode_from_txn(txn, ino).await?;
+        let remote_attr = inode.get_attr();
+        let Some(dirty_attr) = remote_attr.setattr_precheck(param, context.uid, context.gid)?
+        else {
+            return Ok(remote_attr);
+        };
+        if dirty_attr.perm != remote_attr.perm {
+            posix_acl::chmod_in_txn(txn, ino, dirty_attr.perm).await?;
+        }
+        inode.set_attr(dirty_attr);
+        txn.set(
+            &KeyType::INum2Node(ino),
+            &ValueType::Node(inode.to_serial_node()),
+        );
+        Ok(dirty_attr)
+    }
+
+    /// Set the ACL of `kind` of `ino` in `txn`, or remove it if it's `None`.
+    async fn set_posix_acl_in_txn<T: MetaTxn + ?Sized>(
+        &self,
+        txn: &mut T,
+        context: &ReqContext,
+        ino: INum,
+        kind: AclKind,
+        acl: Option<&PosixAcl>,
+    ) -> DatenLordResult<()> {
+        let mut inode = self.get_inode_from_txn(txn, ino).await?;
+        let mut attr = inode.get_attr();
+        if context.uid != 0 && context.uid != attr.uid {
+            return build_error_result_from_errno(
+                Errno::EPERM,
+                format!("set_posix_acl() of ino={ino} is only allowed for the owner"),
+            );
+        }
+        if kind == AclKind::Default && attr.kind != SFlag::S_IFDIR {
+            return build_error_result_from_errno(
+                Errno::EACCES,
+                format!("set_posix_acl() found ino={ino} is not a directory"),
+            );
+        }
+
+        let key = kind.key(ino);
+        match acl {
+            // The access ACL of the mode only is kept as the mode
+            Some(acl) if kind == AclKind::Default || !acl.is_minimal() => {
+                txn.set(&key, &ValueType::PosixAcl(acl.clone()));
+            }
+            Some(_) | None => txn.delete(&key),
+        }
+        if let (AclKind::Access, Some(acl)) = (kind, acl) {
+            attr.perm = acl.apply_to_perm(attr.perm);
+            attr.ctime = SystemTime::now();
+            inode.set_attr(attr);
+            txn.set(
+                &KeyType::INum2Node(ino),
+                &ValueType::Node(inode.to_serial_node()),
+            );
+        }
+        Ok(())
+    }
+
+    /// Set the SELinux label of `ino` in `txn`, or remove it if it's `None`.
+    async fn set_security_label_in_txn<T: MetaTxn + ?Sized>(
+        &self,
+        txn: &mut T,
+        context: &ReqContext,
+        ino: INum,
+        label: Option<&[u8]>,
+    ) -> DatenLordResult<()> {
+        let inode = self.get_inode_from_txn(txn, ino).await?;
+        if context.uid != 0 && context.uid != inode.get_attr().uid {
+            return build_error_result_from_errno(
+                Errno::EPERM,
+                format!("set_security_label() of ino={ino} is only allowed for the owner"),
+            );
+        }
+
+        let key = KeyType::SecurityLabel(ino);
+        match label {
+            Some(label) => txn.set(&key, &ValueType::SecurityLabel(label.to_vec())),
+            None => txn.delete(&key),
+        }
+        Ok(())
+    }
+
+    /// Set the extended attribute `name` of `ino` to `value` in `txn`, only
+    /// the POSIX ACLs and the SELinux label are supported.
+    async fn set_xattr_in_txn<T: MetaTxn + ?Sized>(
+        &self,
+        txn: &mut T,
+        context: &ReqContext,
+        ino: INum,
+        name: &str,
+        value: &[u8],
+    ) -> DatenLordResult<()> {
+        if let Some(kind) = AclKind::from_name(name) {
+            let acl = PosixAcl::from_xattr(value)?;
+            return self
+                .set_posix_acl_in_txn(txn, context, ino, kind, Some(&acl))
+                .await;
+        }
+        if name == SELINUX_XATTR {
+            return self
+                .set_security_label_in_txn(txn, context, ino, Some(value))
+                .await;
+        }
+        build_error_result_from_errno(
+            Errno::EOPNOTSUPP,
+            format!("the extended attribute {name:?} of ino={ino} is not set in a transaction"),
+        )
+    }
+
+    /// Check a rename against the quotas read in `txn`, as they may change
+    /// meanwhile, returns whether the entries are exchanged, or `None` if the
+    /// entry is renamed to itself.
+    async fn rename_precheck<T: MetaTxn + ?Sized>(
+        &self,
+        txn: &mut T,
+        param: &RenameParam,
+    ) -> DatenLordResult<Option<bool>> {
+        let old_parent = param.old_parent;
+        let old_name = param.old_name.as_str();
+        let new_parent = param.new_parent;
+        let new_name = param.new_name.as_str();
+        let flags = param.flags;
+        let exchange = match flags {
+            0 | RENAME_NOREPLACE => false,
+            RENAME_EXCHANGE => true,
+            _ => {
+                return build_error_result_from_errno(
+                    Errno::EINVAL,
+                    format!("rename(): flags={flags} is not supported"),
+                )
+            }
+        };
+
+        if old_parent == new_parent && old_name == new_name {
+            return Ok(None);
+        }
+
+        // Moving across quotas is left to the caller, which copies and then
+        // removes, so that the usage is charged as usual.
+        if old_parent != new_parent {
+            let old_roots = self.quota_roots_in_txn(txn, old_parent).await?;
+            let new_roots = self.quota_roots_in_txn(txn, new_parent).await?;
+            if old_roots != new_roots {
+                return build_error_result_from_errno(
+                    Errno::EXDEV,
+                    format!(
+                        "rename(): failed to move name={old_name:?} from parent ino={old_parent} \
+                            to parent ino={new_parent} across quotas",
+                    ),
+                );
+            }
+        }
+
+        Ok(Some(exchange))
+    }
+
+    /// Rename an entry in `txn`, returns the i-number of the file replaced
+    /// and removed, if any.
+    #[allow(clippy::too_many_lines)] // TODO: refactor it into smaller functions
+    async fn rename_in_txn<T: MetaTxn + ?Sized>(
+        &self,
+        txn: &mut T,
+        context: &ReqContext,
+        param: &RenameParam,
+        exchange: bool,
+    ) -> DatenLordResult<Option<INum>> {
+        let old_parent = param.old_parent;
+        let old_name = param.old_name.as_str();
+        let new_parent = param.new_parent;
+        let new_name = param.new_name.as_str();
+        let flags = param.flags;
+        let build_enoent = |name: &str, parent: INum| {
+            build_error_result_from_errno(
+                Errno::ENOENT,
+                format!(
+                    "exchange_pre_check() failed to find child entry of name={name:?} \
+                        under parent directory ino={parent}"
+                ),
+            )
+        };
+
+        let mut removed = None;
+        let old_parent_node = Arc::new(Mutex::new(self.get_inode_from_txn(txn, old_parent).await?));
+        let old_entry = {
+            let old_parent_node = old_parent_node.lock().await;
+            match self.try_get_dir_entry(txn, old_parent, old_name).await? {
+                None => {
+                    return build_enoent(old_name, old_parent);
+                }
+                Some(old_entry) => {
+                    self.check_sticky_bit(context, &old_parent_node, &old_entry, txn)
+                        .await?;
+                    old_entry
                 }
-            };
-            {
-                old_parent_node.lock().await.update_mtime_ctime_to_now();
-            }
-            {
-                new_parent_node.lock().await.update_mtime_ctime_to_now();
             }
+        };
 
-            if old_parent == new_parent {
-                txn.set(
-                    &KeyType::INum2Node(old_parent),
-                    &ValueType::Node(old_parent_node.lock().await.to_serial_node()),
-                );
-            } else {
-                txn.set(
-                    &KeyType::INum2Node(old_parent),
-                    &ValueType::Node(old_parent_node.lock().await.to_serial_node()),
-                );
+        let new_parent_node = if old_parent == new_parent {
+            Arc::clone(&old_parent_node)
+        } else {
+            Arc::new(Mutex::new(self.get_inode_from_txn(txn, new_parent).await?))
+        };
+
+        let new_entry = self.try_get_dir_entry(txn, new_parent, new_name).await?;
+        match new_entry {
+            None => {
+                // new_name does not exist under new_parent
+                if exchange {
+                    // exchange is true, new name must exist
+                    return build_enoent(new_name, new_parent);
+                }
+                // exchange is false, so we can do rename directly
+                // Remove from old_parent and insert into new_parent
+                txn.delete(&KeyType::DirEntryKey((old_parent, old_name.into())));
                 txn.set(
-                    &KeyType::INum2Node(new_parent),
-                    &ValueType::Node(new_parent_node.lock().await.to_serial_node()),
+                    &KeyType::DirEntryKey((new_parent, new_name.into())),
+                    &ValueType::DirEntry(DirEntry::new(
+                        old_entry.ino(),
+                        new_name.into(),
+                        old_entry.file_type(),
+                    )),
                 );
+                self.move_node(txn, old_entry.ino(), new_parent, new_name)
+                    .await?;
             }
+            Some(new_entry) => {
+                // new_name exists under new_parent
+                if exchange {
+                    // old_name -> new_entry
+                    // new_name -> old_entry
+                    txn.set(
+                        &KeyType::DirEntryKey((old_parent, old_name.into())),
+                        &ValueType::DirEntry(DirEntry::new(
+                            new_entry.ino(),
+                            old_name.into(),
+                            new_entry.file_type(),
+                        )),
+                    );
+                    txn.set(
+                        &KeyType::DirEntryKey((new_parent, new_name.into())),
+                        &ValueType::DirEntry(DirEntry::new(
+                            old_entry.ino(),
+                            new_name.into(),
+                            old_entry.file_type(),
+                        )),
+                    );
+                    self.move_node(txn, new_entry.ino(), old_parent, old_name)
+                        .await?;
+                    self.move_node(txn, old_entry.ino(), new_parent, new_name)
+                        .await?;
+                } else {
+                    // exchange is false, replace or no_replace
+                    if flags & RENAME_NOREPLACE != 0 {
+                        return build_error_result_from_errno(
+                            Errno::EEXIST,
+                            format!(
+                                "rename(): failed to rename() \
+                                    because new_name={new_name:?} already exists",
+                            ),
+                        );
+                    }
+                    let delete_key = KeyType::DirEntryKey((old_parent, old_name.into()));
+                    txn.delete(&delete_key);
+                    txn.set(
+                        &KeyType::DirEntryKey((new_parent, new_name.into())),
+                        &ValueType::DirEntry(DirEntry::new(
+                            old_entry.ino(),
+                            new_name.into(),
+                            old_entry.file_type(),
+                        )),
+                    );
+                    self.move_node(txn, old_entry.ino(), new_parent, new_name)
+                        .await?;
 
-            (txn.commit().await, ())
-        });
-
-        FILESYSTEM_METRICS.observe_storage_operation_throughput(retry, "rename");
-        res
-    }
+                    // The replaced node leaves the volume and the subtrees of
+                    // the quotas.
+                    let replaced = self.get_inode_from_txn(txn, new_entry.ino()).await?;
+                    let replaced_bytes = quota::blocks_to_bytes(replaced.get_attr().blocks);
+                    self.charge_usage(txn, new_parent, -replaced_bytes.cast::<i64>(), -1, false)
+                        .await?;
+                    if let SFlag::S_IFDIR = replaced.get_type() {
+                        txn.delete(&KeyType::Quota(new_entry.ino()));
+                    }
 
-    /// Helper function to write data
-    async fn write_helper(
-        &self,
-        ino: u64,
-        new_mtime: SystemTime,
-        new_size: u64,
-    ) -> DatenLordResult<()> {
-        // Update the `mtime` and `size` of the file
+                    // The replaced node is removed like an unlinked one, it's
+                    // kept until it's forgotten if it's still referenced, or
+                    // until it's released if it's still opened.
+                    let replaced_ino = new_entry.ino();
+                    if replaced.get_lookup_count() > 0 || self.is_opened(txn, replaced_ino).await? {
+                        replaced.mark_deferred_deletion();
+                        txn.set(
+                            &KeyType::INum2Node(replaced_ino),
+                            &ValueType::Node(replaced.to_serial_node()),
+                        );
+                    } else {
+                        if let SFlag::S_IFREG = replaced.get_type() {
+                            removed = Some(replaced_ino);
+                            txn.delete(&KeyType::ChunkIndex(replaced_ino));
+                        }
+                        txn.delete(&KeyType::INum2Node(replaced_ino));
+                        txn.delete(&KeyType::TierPin(replaced_ino));
+                        txn.delete(&KeyType::AccessAcl(replaced_ino));
+                        txn.delete(&KeyType::DefaultAcl(replaced_ino));
+                        txn.delete(&KeyType::SecurityLabel(replaced_ino));
+                        txn.delete(&KeyType::InodeLease(replaced_ino));
+                        txn.delete(&KeyType::FileOpeners(replaced_ino));
+                    }
+                }
+            }
+        };
         {
-            let raw_open_file = self.open_files.get(ino);
-            let mut open_file = raw_open_file.write();
-            open_file.attr.mtime = new_mtime;
-            open_file.attr.size = new_size;
+            old_parent_node.lock().await.update_mtime_ctime_to_now();
+        }
+        {
+            new_parent_node.lock().await.update_mtime_ctime_to_now();
         }
 
-        let (res, retry) = retry_txn!(TXN_RETRY_LIMIT, {
-            let mut txn = self.kv_engine.new_meta_txn().await;
-            let mut node = self.get_inode_from_txn(txn.as_mut(), ino).await?;
-            let mut attr = node.get_attr();
-            attr.mtime = new_mtime;
-            attr.size = new_size;
-            node.set_attr(attr);
+        if old_parent == new_parent {
             txn.set(
-                &KeyType::INum2Node(ino),
-                &ValueType::Node(node.to_serial_node()),
+                &KeyType::INum2Node(old_parent),
+                &ValueType::Node(old_parent_node.lock().await.to_serial_node()),
             );
-            (txn.commit().await, ())
-        });
-
-        FILESYSTEM_METRICS.observe_storage_operation_throughput(retry, "write");
-        res?;
-        Ok(())
-    }
-}
-
-impl S3MetaData {
-    #[allow(clippy::unwrap_used)]
-    /// Get a node from kv engine by inum
-    pub async fn get_node_from_kv_engine(&self, inum: INum) -> DatenLordResult<Option<S3Node>> {
-        let inum_key = KeyType::INum2Node(inum);
-        let raw_data = self.kv_engine.get(&inum_key).await.add_context(format!(
-            "{}() failed to get node of ino={inum} from kv engine",
-            function_name!()
-        ))?;
+        } else {
+            txn.set(
+                &KeyType::INum2Node(old_parent),
+                &ValueType::Node(old_parent_node.lock().await.to_serial_node()),
+            );
+            txn.set(
+                &KeyType::INum2Node(new_parent),
+                &ValueType::Node(new_parent_node.lock().await.to_serial_node()),
+            );
+        }
 
-        // deserialize node
-        Ok(raw_data.map(|value| value.into_s3_node(self)))
+        Ok(removed)
     }
 
-    /// Allocate a new uinque inum for new node
-    async fn alloc_inum(&self) -> DatenLordResult<INum> {
-        let result = self.inum_allocator.alloc_inum_for_fnode().await;
-        debug!("alloc_inum_for_fnode() result={result:?}");
-        result
+    /// Invalidate the caches of the entries renamed.
+    fn invalidate_renamed(&self, param: &RenameParam) {
+        let old_parent = param.old_parent;
+        let new_parent = param.new_parent;
+        self.invalidate_prefetched(old_parent);
+        self.invalidate_prefetched(new_parent);
+        if let Some(ref dentry_cache) = self.dentry_cache {
+            dentry_cache.invalidate(old_parent, &param.old_name);
+            dentry_cache.invalidate(new_parent, &param.new_name);
+        }
+        // The old name is moved to the new one, or they're exchanged, so the
+        // old one can't be missing before.
+        self.invalidate_missing(new_parent, &param.new_name);
     }
 
     /// If sticky bit is set, only the owner of the directory, the owner of the
@@ -877,6 +2269,26 @@ impl S3MetaData {
             .into_s3_node(self))
     }
 
+    /// Helper function to get the chunk index of a file from `MetaTxn`,
+    /// returns an empty index if the file has no chunk written.
+    async fn get_chunk_index_from_txn<T: MetaTxn + ?Sized>(
+        &self,
+        txn: &mut T,
+        ino: INum,
+    ) -> DatenLordResult<ChunkIndex> {
+        let index = txn
+            .get(&KeyType::ChunkIndex(ino))
+            .await
+            .add_context(format!(
+                "{}() failed to get chunk index of ino={ino} from kv engine",
+                function_name!()
+            ))?;
+        Ok(index.map_or_else(
+            || ChunkIndex::new(self.chunk_size),
+            ValueType::into_chunk_index,
+        ))
+    }
+
     /// Helper function to get dir entry from `MetaTxn`
     async fn try_get_dir_entry<T: MetaTxn + ?Sized>(
         &self,
@@ -898,6 +2310,230 @@ impl S3MetaData {
         }
     }
 
+    /// Get the quotas of `ino` and its ancestors, the nearest first, which are
+    /// charged for the changes below `ino`. A removed node is in no quota.
+    async fn quota_roots(&self, ino: INum) -> DatenLordResult<Vec<Quota>> {
+        let quotas = self
+            .kv_engine
+            .range(&KeyType::AllQuotas)
+            .await
+            .add_context(format!(
+                "{}() failed to get quotas from kv engine",
+                function_name!()
+            ))?;
+        if quotas.is_empty() {
+            return Ok(vec![]);
+        }
+        let quotas: BTreeMap<INum, Quota> = quotas
+            .into_iter()
+            .map(|value| {
+                let quota = value.into_quota();
+                (quota.ino, quota)
+            })
+            .collect();
+
+        let mut roots = vec![];
+        let mut cur = ino;
+        loop {
+            if let Some(&quota) = quotas.get(&cur) {
+                roots.push(quota);
+            }
+            if cur == FUSE_ROOT_ID {
+                break;
+            }
+            let Some(node) = self.get_node_from_kv_engine(cur).await? else {
+                return Ok(vec![]);
+            };
+            if node.is_deferred_deletion() {
+                return Ok(vec![]);
+            }
+            cur = node.get_parent_ino();
+        }
+        Ok(roots)
+    }
+
+    /// Get the i-numbers of the quotas of `ino` and its ancestors in `txn`, the
+    /// nearest first, so the transaction conflicts with a quota set or a node
+    /// moved meanwhile. A removed node is in no quota.
+    async fn quota_roots_in_txn<T: MetaTxn + ?Sized>(
+        &self,
+        txn: &mut T,
+        ino: INum,
+    ) -> DatenLordResult<Vec<INum>> {
+        let mut roots = vec![];
+        let mut cur = ino;
+        loop {
+            let quota = txn.get(&KeyType::Quota(cur)).await.add_context(format!(
+                "{}() failed to get quota of ino={cur} from kv engine",
+                function_name!()
+            ))?;
+            if quota.is_some() {
+                roots.push(cur);
+            }
+            if cur == FUSE_ROOT_ID {
+                break;
+            }
+            let Some(node) = self.try_get_inode_from_txn(txn, cur).await? else {
+                return Ok(vec![]);
+            };
+            if node.is_deferred_deletion() {
+                return Ok(vec![]);
+            }
+            cur = node.get_parent_ino();
+        }
+        Ok(roots)
+    }
+
+    /// Charge a change of usage below `ino` to the volume and the quotas
+    /// covering it in `txn`, growth beyond any limit of the quotas is refused
+    /// if `enforce` is set.
+    async fn charge_usage<T: MetaTxn + ?Sized>(
+        &self,
+        txn: &mut T,
+        ino: INum,
+        bytes: i64,
+        inodes: i64,
+        enforce: bool,
+    ) -> DatenLordResult<()> {
+        if bytes == 0 && inodes == 0 {
+            return Ok(());
+        }
+
+        let usage_key = KeyType::VolumeUsage(self.node_id.to_string());
+        let usage = txn.get(&usage_key).await.add_context(format!(
+            "{}() failed to get the usage of node id={} from kv engine",
+            function_name!(),
+            self.node_id,
+        ))?;
+        let mut usage = usage.map_or_else(VolumeUsage::default, ValueType::into_volume_usage);
+        usage.charge(bytes, inodes);
+        txn.set(&usage_key, &ValueType::VolumeUsage(usage));
+
+        for root in self.quota_roots(ino).await? {
+            let key = KeyType::Quota(root.ino);
+            let quota = txn.get(&key).await.add_context(format!(
+                "{}() failed to get quota of ino={} from kv engine",
+                function_name!(),
+                root.ino,
+            ))?;
+            // The quota is removed meanwhile
+            let Some(quota) = quota else {
+                continue;
+            };
+            let mut quota = quota.into_quota();
+            if enforce {
+                quota.check(bytes, inodes)?;
+            }
+            quota.charge(bytes, inodes);
+            txn.set(&key, &ValueType::Quota(quota));
+        }
+        Ok(())
+    }
+
+    /// Count the bytes and the inodes in the subtree of directory `ino`, the
+    /// directory itself is not counted.
+    async fn subtree_usage(&self, ino: INum) -> DatenLordResult<(u64, u64)> {
+        let mut bytes = 0_u64;
+        let mut inodes = 0_u64;
+        let mut dirs = vec![ino];
+        while let Some(dir) = dirs.pop() {
+            for entry in self.get_all_dir_entry(dir).await? {
+                let Some(node) = self.get_node_from_kv_engine(entry.ino()).await? else {
+                    continue;
+                };
+                bytes = bytes.overflow_add(quota::blocks_to_bytes(node.get_attr().blocks));
+                inodes = inodes.overflow_add(1);
+                if let SFlag::S_IFDIR = node.get_type() {
+                    dirs.push(entry.ino());
+                }
+            }
+        }
+        Ok((bytes, inodes))
+    }
+
+    /// Record the new parent and name of a renamed node in `txn`.
+    async fn move_node<T: MetaTxn + ?Sized>(
+        &self,
+        txn: &mut T,
+        ino: INum,
+        new_parent: INum,
+        new_name: &str,
+    ) -> DatenLordResult<()> {
+        let mut node = self.get_inode_from_txn(txn, ino).await?;
+        let _: INum = node.set_parent_ino(new_parent);
+        node.set_name(new_name);
+        txn.set(
+            &KeyType::INum2Node(ino),
+            &ValueType::Node(node.to_serial_node()),
+        );
+        Ok(())
+    }
+
+    /// Returns whether directory `parent` or any of its ancestors is a scratch
+    /// directory, read from `txn`.
+    async fn in_scratch<T: MetaTxn + ?Sized>(
+        &self,
+        txn: &mut T,
+        parent: INum,
+    ) -> DatenLordResult<bool> {
+        let mut cur = parent;
+        loop {
+            let mark = txn.get(&KeyType::Scratch(cur)).await.add_context(format!(
+                "{}() failed to get scratch mark of ino={cur} from kv engine",
+                function_name!()
+            ))?;
+            if mark.is_some() {
+                return Ok(true);
+            }
+            if cur == FUSE_ROOT_ID {
+                return Ok(false);
+            }
+            cur = self.get_inode_from_txn(txn, cur).await?.get_parent_ino();
+        }
+    }
+
+    /// Record in the kv engine that this node opens file `ino`.
+    async fn record_open(&self, ino: INum) -> DatenLordResult<()> {
+        let (res, retry) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            open_state::record_open(txn.as_mut(), ino, &self.node_id).await?;
+            (txn.commit().await, ())
+        });
+        FILESYSTEM_METRICS.observe_storage_operation_throughput(retry, "open");
+        res
+    }
+
+    /// Returns whether file `ino` is opened by this node, or by any other
+    /// node recorded in `txn`.
+    async fn is_opened<T: MetaTxn + ?Sized>(
+        &self,
+        txn: &mut T,
+        ino: INum,
+    ) -> DatenLordResult<bool> {
+        if self.open_files.is_open(ino) {
+            return Ok(true);
+        }
+        open_state::is_opened_by_others(txn, ino, &self.node_id).await
+    }
+
+    /// Get the absolute path of `name` under directory `parent` from `txn`.
+    async fn node_path<T: MetaTxn + ?Sized>(
+        &self,
+        txn: &mut T,
+        parent: INum,
+        name: &str,
+    ) -> DatenLordResult<String> {
+        let mut names = vec![name.to_owned()];
+        let mut cur = parent;
+        while cur != FUSE_ROOT_ID {
+            let node = self.get_inode_from_txn(txn, cur).await?;
+            names.push(node.get_name().to_owned());
+            cur = node.get_parent_ino();
+        }
+        names.reverse();
+        Ok(format!("/{}", names.join("/")))
+    }
+
     /// Helper function to get all dir etnry in a directory from `KVEngine`
     async fn get_all_dir_entry(&self, parent: INum) -> DatenLordResult<Vec<DirEntry>> {
         let key = KeyType::DirEntryKey((parent, String::new()));
@@ -909,3 +2545,181 @@ impl S3MetaData {
         Ok(values)
     }
 }
+
+/// Load the volume info from the kv engine, the volume info is initialized
+/// with `default` if the volume is newly created, or with `legacy` if the
+/// volume was created before the volume info is persisted, which has its
+/// root already.
+///
+/// The volume info is fixed once the volume is created, so the persisted one
+/// always takes precedence over `default`.
+pub async fn load_or_init_volume_info(
+    kv_engine: &Arc<KVEngineType>,
+    default: VolumeInfo,
+    legacy: VolumeInfo,
+) -> DatenLordResult<VolumeInfo> {
+    let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+        let mut txn = kv_engine.new_meta_txn().await;
+        let volume_info = txn.get(&KeyType::VolumeInfo).await.add_context(format!(
+            "{}() failed to get volume info from kv engine",
+            function_name!()
+        ))?;
+        if let Some(volume_info) = volume_info {
+            (RETRY_TXN_BREAK, volume_info.into_volume_info())
+        } else {
+            let root = txn
+                .get(&KeyType::INum2Node(FUSE_ROOT_ID))
+                .await
+                .add_context(format!(
+                    "{}() failed to get the root from kv engine",
+                    function_name!()
+                ))?;
+            let volume_info = if root.is_some() {
+                info!("the volume is created before its info is persisted, it's {legacy:?}");
+                legacy
+            } else {
+                default
+            };
+            txn.set(&KeyType::VolumeInfo, &ValueType::VolumeInfo(volume_info));
+            (txn.commit().await, volume_info)
+        }
+    });
+    let volume_info = res?;
+    if volume_info != default {
+        info!(
+            "the volume is created with {:?}, the configured {:?} is ignored",
+            volume_info, default
+        );
+    }
+    Ok(volume_info)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::sync::Arc;
+
+    use nix::sys::stat::SFlag;
+
+    use super::{load_or_init_volume_info, S3MetaData};
+    use crate::async_fuse::fuse::protocol::{INum, FUSE_ROOT_ID};
+    use crate::async_fuse::memfs::chunk_index::VolumeInfo;
+    use crate::async_fuse::memfs::kv_engine::{CompoundTxn, KVEngine, MetaTxn};
+    use crate::async_fuse::memfs::quota::QuotaAttr;
+    use crate::async_fuse::memfs::{
+        CreateParam, MetaData, MetaDataOptions, RenameParam, ReqContext,
+    };
+    use crate::async_fuse::test::test_util::open_kv_engine;
+
+    /// The parameter creating a node named `name` under directory `parent`.
+    fn create_param(parent: INum, name: &str, node_type: SFlag) -> CreateParam {
+        CreateParam {
+            parent,
+            name: name.to_owned(),
+            mode: 0o755,
+            rdev: 0,
+            uid: 0,
+            gid: 0,
+            umask: 0o022,
+            node_type,
+            link: None,
+        }
+    }
+
+    /// The parameter moving `name` from directory `old_parent` to
+    /// `new_parent`.
+    fn move_param(old_parent: INum, new_parent: INum, name: &str) -> RenameParam {
+        RenameParam {
+            old_parent,
+            old_name: name.to_owned(),
+            new_parent,
+            new_name: name.to_owned(),
+            flags: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rename_in_quota() {
+        let kv_engine = open_kv_engine();
+        let meta = S3MetaData::new(
+            Arc::clone(&kv_engine),
+            "node1",
+            4096,
+            MetaDataOptions::default(),
+        )
+        .await
+        .unwrap();
+        let context = ReqContext { uid: 0, gid: 0 };
+        let (_, quota_dir, _) = meta
+            .mknod(create_param(FUSE_ROOT_ID, "quota", SFlag::S_IFDIR))
+            .await
+            .unwrap();
+        let quota_dir = quota_dir.ino;
+        meta.set_quota(context.clone(), quota_dir, QuotaAttr::MaxBytes, 0x10_0000)
+            .await
+            .unwrap();
+        meta.mknod(create_param(quota_dir, "file", SFlag::S_IFREG))
+            .await
+            .unwrap();
+
+        // Moving out of the quota fails.
+        meta.rename(context.clone(), move_param(quota_dir, FUSE_ROOT_ID, "file"))
+            .await
+            .unwrap_err();
+
+        // A directory created in the same transaction is in the quota of its
+        // parent, so the file is moved into it.
+        let mut txn = CompoundTxn::new(kv_engine.new_meta_txn().await);
+        let dir = meta
+            .create_in_txn(&mut txn, &create_param(quota_dir, "dir", SFlag::S_IFDIR))
+            .await
+            .unwrap()
+            .ino;
+        let param = move_param(quota_dir, dir, "file");
+        let exchange = meta.rename_precheck(&mut txn, &param).await.unwrap();
+        assert_eq!(exchange, Some(false));
+        meta.rename_in_txn(&mut txn, &context, &param, false)
+            .await
+            .unwrap();
+        assert!(txn.commit().await.unwrap());
+        meta.lookup_helper(context, dir, "file").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_volume_info_upgrade() {
+        let default = VolumeInfo {
+            checksum: true,
+            ..VolumeInfo::legacy(0x40_0000)
+        };
+        let legacy = VolumeInfo::legacy(0x8_0000);
+
+        // A new volume is created with the configured info.
+        let kv_engine = open_kv_engine();
+        let volume_info = load_or_init_volume_info(&kv_engine, default, legacy)
+            .await
+            .unwrap();
+        assert_eq!(volume_info, default);
+
+        // A volume with files but without its info is created before the info
+        // is persisted, so it's stamped with the legacy info.
+        let kv_engine = open_kv_engine();
+        S3MetaData::new(
+            Arc::clone(&kv_engine),
+            "node1",
+            0x8_0000,
+            MetaDataOptions::default(),
+        )
+        .await
+        .unwrap();
+        let volume_info = load_or_init_volume_info(&kv_engine, default, legacy)
+            .await
+            .unwrap();
+        assert_eq!(volume_info, legacy);
+
+        // The stamped info is kept on the next mount.
+        let volume_info = load_or_init_volume_info(&kv_engine, default, default)
+            .await
+            .unwrap();
+        assert_eq!(volume_info, legacy);
+    }
+}
@@ -22,7 +22,6 @@ use super::node::Node;
 use super::s3_metadata::S3MetaData;
 use super::serial::{file_attr_to_serial, serial_to_file_attr, SerialNode, SerialNodeData};
 use super::CreateParam;
-use crate::async_fuse::fuse::fuse_reply::StatFsParam;
 use crate::async_fuse::fuse::protocol::{INum, FUSE_ROOT_ID};
 use crate::async_fuse::util::build_error_result_from_errno;
 use crate::common::error::DatenLordResult;
@@ -68,6 +67,8 @@ pub struct S3Node {
     lookup_count: AtomicI64,
     /// If S3Node has been marked as deferred deletion
     deferred_deletion: AtomicBool,
+    /// The content of a small file inlined in the node
+    inline_data: Option<Vec<u8>>,
     /// KVEngine
     kv_engine: Arc<KVEngineType>,
     /// K8s node id
@@ -93,6 +94,7 @@ impl S3Node {
             // lookup count set to 1 by creation
             lookup_count: AtomicI64::new(1),
             deferred_deletion: AtomicBool::new(false),
+            inline_data: None,
             kv_engine: Arc::clone(kv_engine),
             k8s_node_id: Arc::clone(k8s_node_id),
         }
@@ -114,6 +116,7 @@ impl S3Node {
             data: dir_data,
             lookup_count: AtomicI64::new(serial_node.lookup_count),
             deferred_deletion: AtomicBool::new(serial_node.deferred_deletion),
+            inline_data: serial_node.inline_data,
             kv_engine: Arc::clone(&meta.kv_engine),
             k8s_node_id: Arc::clone(&meta.node_id),
         }
@@ -128,6 +131,7 @@ impl S3Node {
             data: self.data.serial(),
             lookup_count: self.lookup_count.load(Ordering::SeqCst),
             deferred_deletion: self.deferred_deletion.load(Ordering::SeqCst),
+            inline_data: self.inline_data.clone(),
         }
     }
 
@@ -158,6 +162,7 @@ impl S3Node {
             // lookup count set to 0 for sync
             lookup_count: AtomicI64::new(0),
             deferred_deletion: AtomicBool::new(false),
+            inline_data: None,
             kv_engine: Arc::clone(&parent.kv_engine),
             k8s_node_id: Arc::clone(&parent.k8s_node_id),
         }
@@ -179,6 +184,17 @@ impl S3Node {
         self.set_attr(attr);
     }
 
+    /// The content of the file inlined in the node, `None` if it's not
+    /// inlined.
+    pub fn inline_data(&self) -> Option<&[u8]> {
+        self.inline_data.as_deref()
+    }
+
+    /// Set the content of the file inlined in the node.
+    pub fn set_inline_data(&mut self, data: Option<Vec<u8>>) {
+        self.inline_data = data;
+    }
+
     /// Increase node lookup count
     fn inc_lookup_count(&self) -> i64 {
         self.lookup_count.fetch_add(1, Ordering::AcqRel)
@@ -357,6 +373,11 @@ impl Node for S3Node {
         self.deferred_deletion.load(Ordering::SeqCst)
     }
 
+    /// Clear the mark of deferred deletion
+    fn clear_deferred_deletion(&self) {
+        self.deferred_deletion.store(false, Ordering::SeqCst);
+    }
+
     /// Duplicate fd
     async fn dup_fd(&self, _oflags: OFlag) -> DatenLordResult<RawFd> {
         Ok(self.new_fd().cast())
@@ -515,21 +536,6 @@ impl Node for S3Node {
         }
     }
 
-    /// Fake data for statefs
-    /// TODO: handle some important data from S3 storage
-    async fn statefs(&self) -> DatenLordResult<StatFsParam> {
-        Ok(StatFsParam {
-            blocks: 10_000_000_000,
-            bfree: 10_000_000_000,
-            bavail: 10_000_000_000,
-            files: 0, // TODO: file count is  temporarily zero
-            f_free: 1_000_000,
-            bsize: 4096, // TODO: consider use customized block size
-            namelen: 1024,
-            frsize: 4096,
-        })
-    }
-
     /// Create child node
     /// TODO: refactor the create_child_xxx() functions
     /// to reduce the duplicated code
@@ -0,0 +1,13 @@
+//! The SELinux labels of files and directories.
+//!
+//! The label of a node is the extended attribute [`SELINUX_XATTR`], which is
+//! set and got by the kernel on the hosts enforcing SELinux, and kept in the
+//! kv engine, so the nodes mounting the volume see the same labels. The
+//! relabeling is checked by the policy of the kernel.
+//!
+//! A mount given a fixed context, like the `context=` mount option, is
+//! labeled by the kernel instead, which neither gets nor sets the labels of
+//! the nodes then, such as the volumes of the pods on OpenShift.
+
+/// The extended attribute of the SELinux label.
+pub const SELINUX_XATTR: &str = "security.selinux";
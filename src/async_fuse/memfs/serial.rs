@@ -59,7 +59,7 @@ pub enum SerialSFlag {
 /// In order to derive Serialize and Deserialize,
 /// Replace the `BTreeMap`<String, `DirEntry`>' with `HashMap`<String,
 /// `SerialDirEntry`>'
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub enum SerialNodeData {
     /// Directory data
     Directory,
@@ -82,7 +82,7 @@ impl SerialNodeData {
 }
 /// TODO: We should discuss the design about persist
 /// Serializable 'Node'
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub struct SerialNode {
     /// Parent node i-number
     pub(crate) parent: u64,
@@ -96,6 +96,10 @@ pub struct SerialNode {
     pub(crate) lookup_count: i64,
     /// If S3Node has been marked as deferred deletion
     pub(crate) deferred_deletion: bool,
+    /// The content of a small file inlined in the node, `None` if it's not
+    /// inlined
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) inline_data: Option<Vec<u8>>,
 }
 
 /// Convert `SFlag` to `SerialSFlag`
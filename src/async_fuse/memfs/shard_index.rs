@@ -0,0 +1,81 @@
+//! The `ShardIndex` persisted in the kv engine.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use super::kv_engine::{KVEngine, KVEngineType, KeyType, MetaTxn, ValueType};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::common::error::DatenLordResult;
+use crate::storage::erasure::{BlockShards, ShardIndex};
+use crate::storage::error::{StorageError, StorageResult};
+
+/// The limit of retrying a transaction.
+const TXN_RETRY_LIMIT: u32 = 10;
+
+/// A `ShardIndex` persisted in the kv engine, the shards of blocks are
+/// shared among all nodes of the volume.
+#[derive(Debug)]
+pub struct KvShardIndex {
+    /// The kv engine
+    kv_engine: Arc<KVEngineType>,
+}
+
+impl KvShardIndex {
+    /// Create a `KvShardIndex`.
+    #[must_use]
+    pub fn new(kv_engine: Arc<KVEngineType>) -> Self {
+        Self { kv_engine }
+    }
+
+    /// Remove the shards of blocks in a transaction.
+    async fn remove_shards_impl(&self, ino: INum, block_ids: Range<usize>) -> DatenLordResult<()> {
+        let block_ids: Vec<usize> = self
+            .kv_engine
+            .range(&KeyType::FileBlockShards(ino))
+            .await?
+            .into_iter()
+            .map(|value| value.into_block_shards().block_id)
+            .filter(|block_id| block_ids.contains(block_id))
+            .collect();
+        if block_ids.is_empty() {
+            return Ok(());
+        }
+
+        let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            for &block_id in &block_ids {
+                txn.delete(&KeyType::BlockShards(ino, block_id));
+            }
+            (txn.commit().await, ())
+        });
+        res
+    }
+}
+
+#[async_trait]
+impl ShardIndex for KvShardIndex {
+    async fn get_shards(&self, ino: INum, block_id: usize) -> StorageResult<Option<BlockShards>> {
+        let value = self
+            .kv_engine
+            .get(&KeyType::BlockShards(ino, block_id))
+            .await;
+        value
+            .map_err(StorageError::from)
+            .map(|value| value.map(ValueType::into_block_shards))
+    }
+
+    async fn set_shards(&self, record: BlockShards) -> StorageResult<()> {
+        let key = KeyType::BlockShards(record.ino, record.block_id);
+        let value = ValueType::BlockShards(record);
+        self.kv_engine.set(&key, &value, None).await?;
+        Ok(())
+    }
+
+    async fn remove_shards(&self, ino: INum, block_ids: Range<usize>) -> StorageResult<()> {
+        self.remove_shards_impl(ino, block_ids)
+            .await
+            .map_err(StorageError::from)
+    }
+}
@@ -0,0 +1,404 @@
+//! Snapshots of a volume.
+//!
+//! Creating a snapshot freezes the current epoch of the volume in one
+//! transaction, so the blocks written afterwards are copied on write by the
+//! [`SnapshotStorage`](crate::storage::SnapshotStorage), then copies the
+//! metadata of the volume into records of the snapshot. The snapshot is
+//! marked ready once all records are copied, only ready snapshots can be
+//! mounted.
+//!
+//! The metadata is copied after the epoch is frozen, so a snapshot is
+//! consistent only if the volume is flushed and not modified while it's
+//! created, which is what freezing the file system before a CSI snapshot
+//! does.
+//!
+//! A snapshot is mounted read-only by restoring its records into a private
+//! kv engine, and reading the blocks of its epoch.
+
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use clippy_utilities::OverflowArithmetic;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use super::id_alloc::IdType;
+use super::kv_engine::{KVEngine, KVEngineType, KeyType, ValueType, RETRY_TXN_BREAK};
+use crate::async_fuse::fuse::protocol::{INum, FUSE_ROOT_ID};
+use crate::common::error::{DatenLordError, DatenLordResult};
+use crate::storage::snapshot::INITIAL_EPOCH;
+
+/// The limit of retrying a transaction.
+const TXN_RETRY_LIMIT: u32 = 10;
+
+/// The first inode number allocated by the `INumAllocator`, it's used if no
+/// inode number has been allocated.
+const FIRST_ALLOCATED_INUM: INum = 2;
+
+/// The information of a snapshot.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotInfo {
+    /// The id of the snapshot, which is the epoch frozen by it
+    pub id: u64,
+    /// The name of the snapshot
+    pub name: String,
+    /// The time the snapshot is created
+    pub created: SystemTime,
+    /// Whether all metadata has been copied into the snapshot
+    pub ready: bool,
+}
+
+/// The key of a metadata record in a snapshot.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotKey {
+    /// The node of an inode
+    Node(INum),
+    /// A dir entry of a directory
+    DirEntry(INum, String),
+    /// The chunk index of a file
+    ChunkIndex(INum),
+    /// The next inode number to allocate
+    NextINum,
+}
+
+impl SnapshotKey {
+    /// Get the key of the record in the live volume.
+    #[must_use]
+    pub fn key_type(&self) -> KeyType {
+        match *self {
+            SnapshotKey::Node(ino) => KeyType::INum2Node(ino),
+            SnapshotKey::DirEntry(parent, ref name) => KeyType::DirEntryKey((parent, name.clone())),
+            SnapshotKey::ChunkIndex(ino) => KeyType::ChunkIndex(ino),
+            SnapshotKey::NextINum => KeyType::IdAllocatorValue(IdType::INum),
+        }
+    }
+}
+
+/// A metadata record in a snapshot.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct SnapshotRecord {
+    /// The key of the record in the live volume
+    pub key: SnapshotKey,
+    /// The value of the record
+    pub value: Box<ValueType>,
+}
+
+impl SnapshotRecord {
+    /// Get the key of the record in the snapshot of `id`.
+    fn key_in(&self, id: u64) -> KeyType {
+        KeyType::SnapshotRecord(id, self.key.key_type().to_string_key())
+    }
+}
+
+/// Create a snapshot of the volume named `name`.
+pub async fn create_snapshot(
+    kv_engine: &Arc<KVEngineType>,
+    name: &str,
+) -> DatenLordResult<SnapshotInfo> {
+    if name.is_empty() {
+        return Err(DatenLordError::ArgumentInvalid {
+            context: vec!["the name of a snapshot cannot be empty".to_owned()],
+        });
+    }
+    // The blocks of a volume without versions would be overwritten in place.
+    let snapshot_enabled = kv_engine
+        .get(&KeyType::VolumeInfo)
+        .await?
+        .map_or(false, |value| value.into_volume_info().snapshot);
+    if !snapshot_enabled {
+        return Err(DatenLordError::ArgumentInvalid {
+            context: vec!["the volume is not created with snapshot enabled".to_owned()],
+        });
+    }
+
+    let info_key = KeyType::SnapshotInfo(name.to_owned());
+    let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+        let mut txn = kv_engine.new_meta_txn().await;
+        if txn.get(&info_key).await?.is_some() {
+            (RETRY_TXN_BREAK, None)
+        } else {
+            let epoch = txn
+                .get(&KeyType::SnapshotEpoch)
+                .await?
+                .map_or(INITIAL_EPOCH, ValueType::into_snapshot_epoch);
+            let info = SnapshotInfo {
+                id: epoch,
+                name: name.to_owned(),
+                created: SystemTime::now(),
+                ready: false,
+            };
+            txn.set(&info_key, &ValueType::SnapshotInfo(info.clone()));
+            txn.set(
+                &KeyType::SnapshotEpoch,
+                &ValueType::SnapshotEpoch(epoch.overflow_add(1)),
+            );
+            (txn.commit().await, Some(info))
+        }
+    });
+    let mut info = res?.ok_or_else(|| DatenLordError::SnapshotAlreadyExist {
+        snapshot_id: name.to_owned(),
+        context: vec![],
+    })?;
+
+    let records = copy_metadata(kv_engine, info.id).await?;
+    info.ready = true;
+    kv_engine
+        .set(&info_key, &ValueType::SnapshotInfo(info.clone()), None)
+        .await?;
+    info!(
+        "created snapshot {} with id {}, {records} metadata records are copied",
+        info.name, info.id
+    );
+    Ok(info)
+}
+
+/// Copy the metadata of the volume into the records of the snapshot of `id`.
+///
+/// Returns the number of records.
+async fn copy_metadata(kv_engine: &Arc<KVEngineType>, id: u64) -> DatenLordResult<usize> {
+    let mut records = vec![];
+    // The values in the kv engine don't carry their keys, so every allocated
+    // inode number is scanned.
+    let next_ino = match kv_engine
+        .get(&KeyType::IdAllocatorValue(IdType::INum))
+        .await?
+    {
+        Some(value) => {
+            let next_ino = value.into_next_id_allocate_range_begin();
+            records.push((
+                SnapshotKey::NextINum,
+                ValueType::NextIdAllocateRangeBegin(next_ino),
+            ));
+            next_ino
+        }
+        None => FIRST_ALLOCATED_INUM,
+    };
+
+    let mut count: usize = 0;
+    for ino in FUSE_ROOT_ID..next_ino {
+        if let Some(node) = kv_engine.get(&KeyType::INum2Node(ino)).await? {
+            records.push((SnapshotKey::Node(ino), node));
+        }
+        let entries = kv_engine
+            .range(&KeyType::DirEntryKey((ino, String::new())))
+            .await?;
+        for entry in entries.into_iter().map(ValueType::into_dir_entry) {
+            let key = SnapshotKey::DirEntry(ino, entry.name().to_owned());
+            records.push((key, ValueType::DirEntry(entry)));
+        }
+        if let Some(chunk_index) = kv_engine.get(&KeyType::ChunkIndex(ino)).await? {
+            records.push((SnapshotKey::ChunkIndex(ino), chunk_index));
+        }
+
+        for (key, value) in records.drain(..) {
+            let record = SnapshotRecord {
+                key,
+                value: Box::new(value),
+            };
+            kv_engine
+                .set(&record.key_in(id), &ValueType::SnapshotRecord(record), None)
+                .await?;
+            count = count.overflow_add(1);
+        }
+    }
+    Ok(count)
+}
+
+/// Delete the snapshot named `name`.
+///
+/// The blocks only seen by the snapshot are not deleted, they are collected
+/// by [`SnapshotStorage::collect_garbage`](crate::storage::SnapshotStorage::collect_garbage)
+/// afterwards.
+pub async fn delete_snapshot(
+    kv_engine: &Arc<KVEngineType>,
+    name: &str,
+) -> DatenLordResult<SnapshotInfo> {
+    let mut info =
+        get_snapshot(kv_engine, name)
+            .await?
+            .ok_or_else(|| DatenLordError::SnapshotNotFound {
+                snapshot_id: name.to_owned(),
+                context: vec![],
+            })?;
+
+    // Mark the snapshot not ready first, so a partially deleted snapshot is
+    // never mounted.
+    let info_key = KeyType::SnapshotInfo(name.to_owned());
+    info.ready = false;
+    kv_engine
+        .set(&info_key, &ValueType::SnapshotInfo(info.clone()), None)
+        .await?;
+    for value in kv_engine.range(&KeyType::SnapshotRecords(info.id)).await? {
+        let record = value.into_snapshot_record();
+        kv_engine.delete(&record.key_in(info.id), None).await?;
+    }
+    kv_engine.delete(&info_key, None).await?;
+    info!("deleted snapshot {} with id {}", info.name, info.id);
+    Ok(info)
+}
+
+/// Get the snapshot named `name`.
+pub async fn get_snapshot(
+    kv_engine: &Arc<KVEngineType>,
+    name: &str,
+) -> DatenLordResult<Option<SnapshotInfo>> {
+    let value = kv_engine
+        .get(&KeyType::SnapshotInfo(name.to_owned()))
+        .await?;
+    Ok(value.map(ValueType::into_snapshot_info))
+}
+
+/// List all snapshots of the volume, in the order of creation.
+pub async fn list_snapshots(kv_engine: &Arc<KVEngineType>) -> DatenLordResult<Vec<SnapshotInfo>> {
+    let mut snapshots: Vec<SnapshotInfo> = kv_engine
+        .range(&KeyType::AllSnapshots)
+        .await?
+        .into_iter()
+        .map(ValueType::into_snapshot_info)
+        .collect();
+    snapshots.sort_by_key(|info| info.id);
+    Ok(snapshots)
+}
+
+/// Restore the metadata of the snapshot `info` of the volume in `source` into
+/// `target`, where the snapshot can be mounted as a volume.
+///
+/// The volume information and the data keys are shared with the live volume.
+pub async fn restore_snapshot(
+    source: &Arc<KVEngineType>,
+    info: &SnapshotInfo,
+    target: &Arc<KVEngineType>,
+) -> DatenLordResult<()> {
+    if !info.ready {
+        return Err(DatenLordError::SnapshotNotReady {
+            snapshot_id: info.name.clone(),
+            context: vec![],
+        });
+    }
+
+    for key in [KeyType::VolumeInfo, KeyType::VolumeDataKeys] {
+        if let Some(value) = source.get(&key).await? {
+            target.set(&key, &value, None).await?;
+        }
+    }
+    for value in source.range(&KeyType::SnapshotRecords(info.id)).await? {
+        let record = value.into_snapshot_record();
+        target
+            .set(&record.key.key_type(), &record.value, None)
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use datenlord::config::{CompressionType, ConsistencyModel, ErasureShards, StoragePolicy};
+    use nix::sys::stat::SFlag;
+
+    use super::*;
+    use crate::async_fuse::memfs::chunk_index::VolumeInfo;
+    use crate::async_fuse::memfs::direntry::{DirEntry, FileType};
+    use crate::async_fuse::memfs::fs_util::FileAttr;
+    use crate::async_fuse::memfs::serial::{file_attr_to_serial, SerialNode, SerialNodeData};
+    use crate::async_fuse::test::test_util::open_kv_engine;
+
+    /// Set a file named `name` under the root in the kv engine.
+    async fn set_file(kv_engine: &KVEngineType, ino: INum, name: &str) {
+        let attr = FileAttr {
+            ino,
+            kind: SFlag::S_IFREG,
+            nlink: 1,
+            ..FileAttr::now()
+        };
+        let node = SerialNode {
+            parent: FUSE_ROOT_ID,
+            name: name.to_owned(),
+            attr: file_attr_to_serial(&attr),
+            data: SerialNodeData::File,
+            lookup_count: 0,
+            deferred_deletion: false,
+            inline_data: None,
+        };
+        kv_engine
+            .set(&KeyType::INum2Node(ino), &ValueType::Node(node), None)
+            .await
+            .unwrap();
+        kv_engine
+            .set(
+                &KeyType::DirEntryKey((FUSE_ROOT_ID, name.to_owned())),
+                &ValueType::DirEntry(DirEntry::new(ino, name.to_owned(), FileType::File)),
+                None,
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_create_restore_delete() {
+        let kv_engine = open_kv_engine();
+        let volume_info = VolumeInfo {
+            chunk_size: 4096,
+            compression: CompressionType::None,
+            dedup: false,
+            packing: false,
+            checksum: false,
+            encryption: false,
+            snapshot: true,
+            tiering: false,
+            replication: 0,
+            storage_policy: StoragePolicy::Replicate,
+            erasure_shards: ErasureShards::default(),
+            consistency: ConsistencyModel::Strict,
+        };
+        kv_engine
+            .set(
+                &KeyType::VolumeInfo,
+                &ValueType::VolumeInfo(volume_info),
+                None,
+            )
+            .await
+            .unwrap();
+        kv_engine
+            .set(
+                &KeyType::IdAllocatorValue(IdType::INum),
+                &ValueType::NextIdAllocateRangeBegin(4),
+                None,
+            )
+            .await
+            .unwrap();
+        set_file(&kv_engine, 2, "a").await;
+
+        let info = create_snapshot(&kv_engine, "first").await.unwrap();
+        assert_eq!(info.id, INITIAL_EPOCH);
+        assert!(info.ready);
+        assert!(create_snapshot(&kv_engine, "first").await.is_err());
+
+        // The changes after the snapshot are not seen by it.
+        set_file(&kv_engine, 3, "b").await;
+        let second = create_snapshot(&kv_engine, "second").await.unwrap();
+        assert_eq!(second.id, INITIAL_EPOCH.overflow_add(1));
+
+        let target = open_kv_engine();
+        restore_snapshot(&kv_engine, &info, &target).await.unwrap();
+        let entries = target
+            .range(&KeyType::DirEntryKey((FUSE_ROOT_ID, String::new())))
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(target.get(&KeyType::INum2Node(2)).await.unwrap().is_some());
+        assert!(target.get(&KeyType::INum2Node(3)).await.unwrap().is_none());
+        let restored_info = target.get(&KeyType::VolumeInfo).await.unwrap().unwrap();
+        assert_eq!(restored_info.into_volume_info(), volume_info);
+
+        delete_snapshot(&kv_engine, "first").await.unwrap();
+        assert!(get_snapshot(&kv_engine, "first").await.unwrap().is_none());
+        assert!(kv_engine
+            .range(&KeyType::SnapshotRecords(info.id))
+            .await
+            .unwrap()
+            .is_empty());
+        let snapshots = list_snapshots(&kv_engine).await.unwrap();
+        assert_eq!(snapshots, vec![second]);
+    }
+}
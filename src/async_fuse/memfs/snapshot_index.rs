@@ -0,0 +1,96 @@
+//! The `SnapshotIndex` persisted in the kv engine.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use super::kv_engine::{KVEngine, KVEngineType, KeyType, ValueType};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::common::error::DatenLordResult;
+use crate::storage::error::{StorageError, StorageResult};
+use crate::storage::snapshot::{BlockVersions, SnapshotIndex, SnapshotState, INITIAL_EPOCH};
+
+/// A `SnapshotIndex` persisted in the kv engine, the versions and snapshots
+/// are shared among all nodes of the volume.
+#[derive(Debug)]
+pub struct KvSnapshotIndex {
+    /// The kv engine
+    kv_engine: Arc<KVEngineType>,
+}
+
+impl KvSnapshotIndex {
+    /// Create a `KvSnapshotIndex`.
+    #[must_use]
+    pub fn new(kv_engine: Arc<KVEngineType>) -> Self {
+        Self { kv_engine }
+    }
+
+    /// Get the snapshot state from the kv engine.
+    async fn state_impl(&self) -> DatenLordResult<SnapshotState> {
+        let epoch = self
+            .kv_engine
+            .get(&KeyType::SnapshotEpoch)
+            .await?
+            .map_or(INITIAL_EPOCH, ValueType::into_snapshot_epoch);
+        let snapshots = self
+            .kv_engine
+            .range(&KeyType::AllSnapshots)
+            .await?
+            .into_iter()
+            .map(|value| value.into_snapshot_info().id)
+            .collect();
+        Ok(SnapshotState { epoch, snapshots })
+    }
+
+    /// List the versions with the key prefix.
+    async fn list_versions_impl(&self, prefix: &KeyType) -> StorageResult<Vec<BlockVersions>> {
+        let values = self.kv_engine.range(prefix).await;
+        values.map_err(StorageError::from).map(|values| {
+            values
+                .into_iter()
+                .map(ValueType::into_block_versions)
+                .collect()
+        })
+    }
+}
+
+#[async_trait]
+impl SnapshotIndex for KvSnapshotIndex {
+    async fn state(&self) -> StorageResult<SnapshotState> {
+        self.state_impl().await.map_err(StorageError::from)
+    }
+
+    async fn get_versions(
+        &self,
+        ino: INum,
+        block_id: usize,
+    ) -> StorageResult<Option<BlockVersions>> {
+        let value = self
+            .kv_engine
+            .get(&KeyType::BlockVersions(ino, block_id))
+            .await;
+        value
+            .map_err(StorageError::from)
+            .map(|value| value.map(ValueType::into_block_versions))
+    }
+
+    async fn set_versions(&self, versions: BlockVersions) -> StorageResult<()> {
+        let key = KeyType::BlockVersions(versions.ino, versions.block_id);
+        if versions.is_empty() {
+            self.kv_engine.delete(&key, None).await?;
+        } else {
+            let value = ValueType::BlockVersions(versions);
+            self.kv_engine.set(&key, &value, None).await?;
+        }
+        Ok(())
+    }
+
+    async fn list_file_versions(&self, ino: INum) -> StorageResult<Vec<BlockVersions>> {
+        self.list_versions_impl(&KeyType::FileBlockVersions(ino))
+            .await
+    }
+
+    async fn list_versions(&self) -> StorageResult<Vec<BlockVersions>> {
+        self.list_versions_impl(&KeyType::AllBlockVersions).await
+    }
+}
@@ -0,0 +1,195 @@
+//! The stat-ahead of directories.
+//!
+//! Listing a directory is usually followed by querying every child of it, as
+//! `ls -l`, `find` and package managers do, and each query is a round trip to
+//! the kv engine. Instead, the nodes of the children are fetched at once when
+//! the directory is listed, and kept shortly to serve the lookups, the
+//! attribute queries and the opens following, the small files are opened
+//! with the content inlined in their nodes as well.
+//!
+//! A prefetched node is dropped once it or its directory is changed by this
+//! node, and expires after `STAT_AHEAD_TTL` anyway, which bounds how long the
+//! changes made by other nodes are missed.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use super::fs_util::FileAttr;
+use crate::async_fuse::fuse::protocol::INum;
+
+/// How long a prefetched node is kept.
+pub(super) const STAT_AHEAD_TTL: Duration = Duration::from_secs(1);
+
+/// The max number of the nodes fetched concurrently by a stat-ahead.
+pub(super) const STAT_AHEAD_CONCURRENCY: usize = 32;
+
+/// A prefetched node.
+#[derive(Debug, Clone)]
+pub(super) struct PrefetchedNode {
+    /// The attributes of the node
+    pub(super) attr: FileAttr,
+    /// The content of a small file inlined in the node
+    pub(super) inline_data: Option<Arc<[u8]>>,
+}
+
+/// The inner state of `StatAhead`.
+#[derive(Debug, Default)]
+struct StatAheadInner {
+    /// The prefetched nodes and when they expire
+    nodes: HashMap<INum, (PrefetchedNode, Instant)>,
+    /// The children of the listed directories by their names, and when they
+    /// expire
+    dirs: HashMap<INum, (HashMap<String, INum>, Instant)>,
+    /// Increased by every change, so a stat-ahead overlapping a change is
+    /// discarded, as it may have fetched the nodes before the change.
+    generation: u64,
+}
+
+/// The nodes prefetched by listing directories.
+#[derive(Debug)]
+pub(super) struct StatAhead {
+    /// The max number of the children prefetched of a directory
+    limit: usize,
+    /// The inner state
+    inner: Mutex<StatAheadInner>,
+}
+
+impl StatAhead {
+    /// Create a `StatAhead` prefetching at most `limit` children of a
+    /// directory.
+    pub(super) fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            inner: Mutex::new(StatAheadInner::default()),
+        }
+    }
+
+    /// The max number of the children prefetched of a directory.
+    pub(super) fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// The current generation, which is passed to `fill()` after the nodes
+    /// are fetched.
+    pub(super) fn generation(&self) -> u64 {
+        self.inner.lock().generation
+    }
+
+    /// Keep the nodes of the children of `dir` fetched since `generation`,
+    /// they're discarded if anything is changed meanwhile.
+    pub(super) fn fill(&self, generation: u64, dir: INum, children: Vec<(String, PrefetchedNode)>) {
+        let mut inner = self.inner.lock();
+        if inner.generation != generation {
+            return;
+        }
+        let now = Instant::now();
+        inner.nodes.retain(|_, &mut (_, expires)| expires > now);
+        inner.dirs.retain(|_, &mut (_, expires)| expires > now);
+
+        let expires = now + STAT_AHEAD_TTL;
+        let mut names = HashMap::with_capacity(children.len());
+        for (name, node) in children {
+            let ino = node.attr.ino;
+            names.insert(name, ino);
+            inner.nodes.insert(ino, (node, expires));
+        }
+        inner.dirs.insert(dir, (names, expires));
+    }
+
+    /// Get the prefetched node of `name` in directory `parent`.
+    pub(super) fn lookup(&self, parent: INum, name: &str) -> Option<PrefetchedNode> {
+        let ino = {
+            let inner = self.inner.lock();
+            let &(ref names, expires) = inner.dirs.get(&parent)?;
+            if expires <= Instant::now() {
+                return None;
+            }
+            *names.get(name)?
+        };
+        self.get(ino)
+    }
+
+    /// Get the prefetched node of `ino`.
+    pub(super) fn get(&self, ino: INum) -> Option<PrefetchedNode> {
+        let inner = self.inner.lock();
+        let &(ref node, expires) = inner.nodes.get(&ino)?;
+        (expires > Instant::now()).then(|| node.clone())
+    }
+
+    /// Drop all the prefetched nodes under memory pressure.
+    pub(super) fn clear(&self) {
+        let mut inner = self.inner.lock();
+        inner.nodes.clear();
+        inner.dirs.clear();
+    }
+
+    /// Drop the prefetched node of `ino` once it's changed, and the children
+    /// of it if it's a directory.
+    pub(super) fn invalidate(&self, ino: INum) {
+        let mut inner = self.inner.lock();
+        inner.generation = inner.generation.wrapping_add(1);
+        inner.nodes.remove(&ino);
+        if let Some((names, _)) = inner.dirs.remove(&ino) {
+            for child in names.values() {
+                inner.nodes.remove(child);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{PrefetchedNode, StatAhead};
+    use crate::async_fuse::memfs::fs_util::FileAttr;
+
+    /// A prefetched node of `ino`.
+    fn node_of(ino: u64) -> PrefetchedNode {
+        PrefetchedNode {
+            attr: FileAttr {
+                ino,
+                ..FileAttr::now()
+            },
+            inline_data: Some(Arc::from(&b"data"[..])),
+        }
+    }
+
+    #[test]
+    fn test_stat_ahead() {
+        let stat_ahead = StatAhead::new(16);
+        let generation = stat_ahead.generation();
+        stat_ahead.fill(
+            generation,
+            1,
+            vec![("a".to_owned(), node_of(2)), ("b".to_owned(), node_of(3))],
+        );
+        assert_eq!(stat_ahead.lookup(1, "a").unwrap().attr.ino, 2);
+        assert_eq!(
+            stat_ahead.get(3).unwrap().inline_data.unwrap().as_ref(),
+            b"data"
+        );
+        assert!(stat_ahead.lookup(1, "c").is_none());
+        assert!(stat_ahead.lookup(2, "a").is_none());
+
+        // A changed node is dropped.
+        stat_ahead.invalidate(2);
+        assert!(stat_ahead.lookup(1, "a").is_none());
+        assert!(stat_ahead.get(3).is_some());
+
+        // So are the children of a changed directory.
+        stat_ahead.invalidate(1);
+        assert!(stat_ahead.lookup(1, "b").is_none());
+        assert!(stat_ahead.get(3).is_none());
+
+        // A stat-ahead overlapping a change is discarded.
+        let generation = stat_ahead.generation();
+        stat_ahead.invalidate(4);
+        stat_ahead.fill(generation, 1, vec![("a".to_owned(), node_of(2))]);
+        assert!(stat_ahead.lookup(1, "a").is_none());
+    }
+}
@@ -0,0 +1,161 @@
+//! The `TierIndex` persisted in the kv engine.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use datenlord::config::TierRule;
+
+use super::kv_engine::{KVEngine, KVEngineType, KeyType, MetaTxn, ValueType};
+use crate::async_fuse::fuse::protocol::{INum, FUSE_ROOT_ID};
+use crate::common::error::DatenLordResult;
+use crate::storage::error::{StorageError, StorageResult};
+use crate::storage::tiering::{BlockTier, Tier, TierIndex};
+
+/// The extended attribute pinning a file or a directory to a tier, the value
+/// is `hot` or `cold`.
+pub const TIER_XATTR: &str = "user.datenlord.tier";
+
+/// The limit of retrying a transaction.
+const TXN_RETRY_LIMIT: u32 = 10;
+
+/// Find the tier of the longest rule matching `path`.
+fn match_rules(rules: &[TierRule], path: &str) -> Option<Tier> {
+    rules
+        .iter()
+        .filter(|rule| {
+            path.strip_prefix(rule.path.as_str())
+                .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+        })
+        .max_by_key(|rule| rule.path.len())
+        .map(|rule| rule.tier)
+}
+
+/// A `TierIndex` persisted in the kv engine, the tiers of blocks are shared
+/// among all nodes of the volume.
+#[derive(Debug)]
+pub struct KvTierIndex {
+    /// The kv engine
+    kv_engine: Arc<KVEngineType>,
+    /// The rules pinning the files under paths to tiers
+    rules: Vec<TierRule>,
+}
+
+impl KvTierIndex {
+    /// Create a `KvTierIndex`.
+    #[must_use]
+    pub fn new(kv_engine: Arc<KVEngineType>, rules: Vec<TierRule>) -> Self {
+        Self { kv_engine, rules }
+    }
+
+    /// Remove the tiers of blocks in a transaction.
+    async fn remove_tiers_impl(&self, ino: INum, block_ids: Range<usize>) -> DatenLordResult<()> {
+        let block_ids: Vec<usize> = self
+            .kv_engine
+            .range(&KeyType::FileBlockTiers(ino))
+            .await?
+            .into_iter()
+            .map(|value| value.into_block_tier().block_id)
+            .filter(|block_id| block_ids.contains(block_id))
+            .collect();
+        if block_ids.is_empty() {
+            return Ok(());
+        }
+
+        let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            for &block_id in &block_ids {
+                txn.delete(&KeyType::BlockTier(ino, block_id));
+            }
+            (txn.commit().await, ())
+        });
+        res
+    }
+
+    /// Find the tier pinned by the extended attribute of the file or its
+    /// nearest ancestor, or by the rules with the path of the file.
+    async fn pinned_tier_impl(&self, ino: INum) -> DatenLordResult<Option<Tier>> {
+        let mut names = vec![];
+        let mut cur = ino;
+        loop {
+            if let Some(pin) = self.kv_engine.get(&KeyType::TierPin(cur)).await? {
+                return Ok(Some(pin.into_tier_pin()));
+            }
+            if cur == FUSE_ROOT_ID {
+                break;
+            }
+            let Some(node) = self.kv_engine.get(&KeyType::INum2Node(cur)).await? else {
+                // The file is removed.
+                return Ok(None);
+            };
+            let node = node.into_serial_node();
+            names.push(node.name);
+            cur = node.parent;
+        }
+
+        names.reverse();
+        Ok(match_rules(&self.rules, &format!("/{}", names.join("/"))))
+    }
+}
+
+#[async_trait]
+impl TierIndex for KvTierIndex {
+    async fn get_tier(&self, ino: INum, block_id: usize) -> StorageResult<Option<BlockTier>> {
+        let value = self.kv_engine.get(&KeyType::BlockTier(ino, block_id)).await;
+        value
+            .map_err(StorageError::from)
+            .map(|value| value.map(ValueType::into_block_tier))
+    }
+
+    async fn set_tier(&self, record: BlockTier) -> StorageResult<()> {
+        let key = KeyType::BlockTier(record.ino, record.block_id);
+        let value = ValueType::BlockTier(record);
+        self.kv_engine.set(&key, &value, None).await?;
+        Ok(())
+    }
+
+    async fn remove_tiers(&self, ino: INum, block_ids: Range<usize>) -> StorageResult<()> {
+        self.remove_tiers_impl(ino, block_ids)
+            .await
+            .map_err(StorageError::from)
+    }
+
+    async fn list_tiers(&self) -> StorageResult<Vec<BlockTier>> {
+        let values = self.kv_engine.range(&KeyType::AllBlockTiers).await;
+        values
+            .map_err(StorageError::from)
+            .map(|values| values.into_iter().map(ValueType::into_block_tier).collect())
+    }
+
+    async fn pinned_tier(&self, ino: INum) -> StorageResult<Option<Tier>> {
+        self.pinned_tier_impl(ino).await.map_err(StorageError::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use datenlord::config::TierRule;
+
+    use super::match_rules;
+    use crate::storage::tiering::Tier;
+
+    #[test]
+    fn test_match_rules() {
+        let rules = vec![
+            TierRule {
+                path: "/data".to_owned(),
+                tier: Tier::Cold,
+            },
+            TierRule {
+                path: "/data/hot".to_owned(),
+                tier: Tier::Hot,
+            },
+        ];
+        assert_eq!(match_rules(&rules, "/data/a"), Some(Tier::Cold));
+        assert_eq!(match_rules(&rules, "/data"), Some(Tier::Cold));
+        assert_eq!(match_rules(&rules, "/data/hot/a"), Some(Tier::Hot));
+        assert_eq!(match_rules(&rules, "/data/hotter"), Some(Tier::Cold));
+        assert_eq!(match_rules(&rules, "/database"), None);
+        assert_eq!(match_rules(&[], "/data"), None);
+    }
+}
@@ -0,0 +1,317 @@
+//! The trash of removed files.
+//!
+//! When the trash is enabled, unlinking a file moves it into the trash, a
+//! hidden namespace of the metadata out of the directory tree, instead of
+//! removing it. Only files and symlinks are moved, directories are removed
+//! once they're empty as before, and they're recreated when a file below them
+//! is restored. The chunks of a file in the trash are kept, but it's no longer
+//! charged to the volume or any quota.
+//!
+//! The files in the trash are listed and restored with the trash role, a file
+//! is restored to the path it's removed from, or to another one. The mounting
+//! nodes remove the files which have been in the trash longer than the
+//! retention in the background. Files replaced by `rename(2)` are removed
+//! immediately.
+//!
+//! Files below a scratch directory, marked by [`SCRATCH_XATTR`], skip the
+//! trash, so the space of scratch data like CSI ephemeral volumes is
+//! reclaimed as soon as it's removed.
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use nix::errno::Errno;
+use serde::{Deserialize, Serialize};
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use super::kv_engine::{KVEngine, KVEngineType, KeyType, ValueType};
+use super::{check_name_length, MetaData, MetaDataOptions, S3MetaData, StorageType};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::async_fuse::util::build_error_result_from_errno;
+use crate::common::error::DatenLordResult;
+
+/// The extended attribute marking a directory as a scratch directory, its
+/// value is ignored.
+pub const SCRATCH_XATTR: &str = "user.datenlord.scratch";
+
+/// The longest interval between the passes removing the expired files.
+const TRASH_GC_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// A removed file in the trash.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TrashEntry {
+    /// The i-number of the file
+    pub ino: INum,
+    /// The absolute path the file is removed from
+    pub path: String,
+    /// The time the file is removed
+    pub deleted: SystemTime,
+}
+
+impl TrashEntry {
+    /// Create an entry of file `ino` removed from `path` just now.
+    #[must_use]
+    pub fn new(ino: INum, path: String) -> Self {
+        Self {
+            ino,
+            path,
+            deleted: SystemTime::now(),
+        }
+    }
+
+    /// Returns whether the file has been in the trash for `retention` at
+    /// `now`.
+    #[must_use]
+    pub fn is_expired(&self, now: SystemTime, retention: Duration) -> bool {
+        now.duration_since(self.deleted)
+            .map_or(false, |age| age >= retention)
+    }
+}
+
+/// Split an absolute path of a file into the names of its parent directories
+/// and its own name.
+pub fn split_path(path: &str) -> DatenLordResult<(Vec<&str>, &str)> {
+    let mut names: Vec<&str> = path.split('/').filter(|name| !name.is_empty()).collect();
+    let name = match names.pop() {
+        Some(name) if path.starts_with('/') => name,
+        _ => {
+            return build_error_result_from_errno(
+                Errno::EINVAL,
+                format!("{path:?} is not an absolute path of a file"),
+            );
+        }
+    };
+    for &dir_name in names.iter().chain(std::iter::once(&name)) {
+        if dir_name == "." || dir_name == ".." {
+            return build_error_result_from_errno(
+                Errno::EINVAL,
+                format!("{path:?} is not a normalized path"),
+            );
+        }
+        check_name_length(dir_name)?;
+    }
+    Ok((names, name))
+}
+
+/// List the files in the trash, in the order they're removed.
+pub async fn list_trash(kv_engine: &Arc<KVEngineType>) -> DatenLordResult<Vec<TrashEntry>> {
+    let mut entries: Vec<TrashEntry> = kv_engine
+        .range(&KeyType::AllTrash)
+        .await?
+        .into_iter()
+        .map(ValueType::into_trash_entry)
+        .collect();
+    entries.sort_by_key(|entry| entry.deleted);
+    Ok(entries)
+}
+
+/// Restore file `ino` in the trash to `path`, or to where it's removed from if
+/// it's `None`, on behalf of node `node_id`.
+///
+/// Returns the path the file is restored to.
+pub async fn restore(
+    kv_engine: Arc<KVEngineType>,
+    node_id: &str,
+    ino: INum,
+    path: Option<&str>,
+) -> DatenLordResult<String> {
+    // No chunk of files is accessed by restoring, so the chunk size is not
+    // needed, and the trash of the metadata is not enabled.
+    let meta = S3MetaData::new(kv_engine, node_id, 0, MetaDataOptions::default()).await?;
+    meta.restore_from_trash(ino, path).await
+}
+
+/// Remove the files which have been in the trash for `retention` periodically,
+/// until `token` is cancelled.
+pub async fn run_trash_gc<M: MetaData + Send + Sync>(
+    metadata: Arc<M>,
+    storage: StorageType,
+    retention: Duration,
+    token: CancellationToken,
+) {
+    let mut ticker = tokio::time::interval(retention.min(TRASH_GC_INTERVAL));
+    // The first tick completes immediately, skip it to collect after an
+    // interval.
+    ticker.tick().await;
+
+    loop {
+        select! {
+            _ = ticker.tick() => {
+                match metadata.purge_trash(retention).await {
+                    Ok(removed) => {
+                        for &ino in &removed {
+                            if let Err(e) = storage.remove(ino).await {
+                                error!("Failed to remove the blocks of ino={ino}: {e}");
+                            }
+                        }
+                        info!("Trash GC pass finished, {} files removed.", removed.len());
+                    }
+                    Err(e) => error!("Trash GC pass failed: {e}"),
+                }
+            }
+            () = token.cancelled() => {
+                info!("Trash GC exits.");
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    use nix::sys::stat::SFlag;
+
+    use super::{list_trash, split_path, TrashEntry};
+    use crate::async_fuse::fuse::protocol::{INum, FUSE_ROOT_ID};
+    use crate::async_fuse::memfs::kv_engine::{KVEngine, KeyType};
+    use crate::async_fuse::memfs::metadata::ReqContext;
+    use crate::async_fuse::memfs::{CreateParam, MetaData, MetaDataOptions, S3MetaData};
+    use crate::async_fuse::test::test_util::open_kv_engine;
+
+    /// Create a node named `name` under directory `parent`.
+    async fn create(meta: &S3MetaData, parent: INum, name: &str, node_type: SFlag) -> INum {
+        let param = CreateParam {
+            parent,
+            name: name.to_owned(),
+            mode: 0o755,
+            rdev: 0,
+            uid: 0,
+            gid: 0,
+            umask: 0,
+            node_type,
+            link: None,
+        };
+        let (_, attr, _) = meta.mknod(param).await.unwrap();
+        attr.ino
+    }
+
+    #[test]
+    fn test_expiry_and_paths() {
+        let entry = TrashEntry::new(2, "/a".to_owned());
+        let later = entry.deleted.checked_add(Duration::from_secs(60)).unwrap();
+        assert!(entry.is_expired(later, Duration::from_secs(60)));
+        assert!(!entry.is_expired(later, Duration::from_secs(61)));
+        assert!(!entry.is_expired(SystemTime::UNIX_EPOCH, Duration::ZERO));
+
+        assert_eq!(split_path("/a//b/c").unwrap(), (vec!["a", "b"], "c"));
+        assert_eq!(split_path("/c").unwrap(), (vec![], "c"));
+        split_path("a/b").unwrap_err();
+        split_path("/").unwrap_err();
+        split_path("/a/../b").unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_trash_and_restore() {
+        let kv_engine = open_kv_engine();
+        let retention = Some(Duration::from_secs(3600));
+        let meta = S3MetaData::new(
+            Arc::clone(&kv_engine),
+            "node1",
+            4096,
+            MetaDataOptions {
+                trash_retention: retention,
+                ..MetaDataOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+        let context = ReqContext { uid: 0, gid: 0 };
+
+        let dir = create(&meta, FUSE_ROOT_ID, "dir", SFlag::S_IFDIR).await;
+        let file = create(&meta, dir, "file", SFlag::S_IFREG).await;
+        // The file is moved into the trash, so its chunks are kept.
+        let removed = meta.unlink(context.clone(), dir, "file").await.unwrap();
+        assert_eq!(removed, None);
+        meta.unlink(context.clone(), FUSE_ROOT_ID, "dir")
+            .await
+            .unwrap();
+        let entries = list_trash(&kv_engine).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry = entries.first().unwrap();
+        assert_eq!((entry.ino, entry.path.as_str()), (file, "/dir/file"));
+        assert!(meta
+            .purge_trash(retention.unwrap())
+            .await
+            .unwrap()
+            .is_empty());
+
+        // The removed directory is recreated.
+        let path = meta.restore_from_trash(file, None).await.unwrap();
+        assert_eq!(path, "/dir/file");
+        let (_, dir_attr, _) = meta
+            .lookup_helper(context.clone(), FUSE_ROOT_ID, "dir")
+            .await
+            .unwrap();
+        let (_, attr, _) = meta
+            .lookup_helper(context.clone(), dir_attr.ino, "file")
+            .await
+            .unwrap();
+        assert_eq!(attr.ino, file);
+        assert!(list_trash(&kv_engine).await.unwrap().is_empty());
+        meta.restore_from_trash(file, None).await.unwrap_err();
+
+        // A file expired in the trash is removed with its chunks, once it's
+        // forgotten by the kernel.
+        meta.unlink(context, dir_attr.ino, "file").await.unwrap();
+        meta.restore_from_trash(file, Some("/dir"))
+            .await
+            .unwrap_err();
+        assert!(!meta.forget(file, 1).await.unwrap());
+        assert_eq!(meta.purge_trash(Duration::ZERO).await.unwrap(), vec![file]);
+        assert!(list_trash(&kv_engine).await.unwrap().is_empty());
+        assert!(meta.get_node_from_kv_engine(file).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_scratch_skips_trash() {
+        let kv_engine = open_kv_engine();
+        let retention = Some(Duration::from_secs(3600));
+        let meta = S3MetaData::new(
+            Arc::clone(&kv_engine),
+            "node1",
+            4096,
+            MetaDataOptions {
+                trash_retention: retention,
+                ..MetaDataOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+        let context = ReqContext { uid: 0, gid: 0 };
+
+        let scratch = create(&meta, FUSE_ROOT_ID, "scratch", SFlag::S_IFDIR).await;
+        let dir = create(&meta, scratch, "dir", SFlag::S_IFDIR).await;
+        let file = create(&meta, dir, "file", SFlag::S_IFREG).await;
+        meta.set_scratch(ReqContext { uid: 1, gid: 1 }, scratch, true)
+            .await
+            .unwrap_err();
+        meta.set_scratch(context.clone(), file, true)
+            .await
+            .unwrap_err();
+        meta.set_scratch(context.clone(), scratch, true)
+            .await
+            .unwrap();
+        assert!(meta.is_scratch(scratch).await.unwrap());
+        assert!(!meta.is_scratch(dir).await.unwrap());
+
+        // A file below a scratch directory is removed with its chunks.
+        let removed = meta.unlink(context.clone(), dir, "file").await.unwrap();
+        assert_eq!(removed, Some(file));
+        assert!(list_trash(&kv_engine).await.unwrap().is_empty());
+
+        // The mark is removed with the directory.
+        meta.unlink(context.clone(), scratch, "dir").await.unwrap();
+        meta.unlink(context, FUSE_ROOT_ID, "scratch").await.unwrap();
+        assert!(kv_engine
+            .get(&KeyType::Scratch(scratch))
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,157 @@
+//! The usage accounting of volumes.
+//!
+//! The bytes and the inodes in a volume are counted the same way as quotas,
+//! see [`quota`](super::quota), and are updated in the same transactions as
+//! the metadata. To avoid a hot key shared by all the nodes, every node
+//! charges its own record, which is a delta and can be negative, as a node
+//! may remove what another node created. The usage of the volume is the sum
+//! of the records, including the baseline counted when the accounting starts
+//! on a volume.
+
+use clippy_utilities::{Cast, OverflowArithmetic};
+use serde::{Deserialize, Serialize};
+
+use super::quota::Quota;
+use super::MAX_NAME_LEN;
+use crate::async_fuse::fuse::fuse_reply::StatFsParam;
+
+/// The block size reported by `statfs(2)`.
+const STATFS_BLOCK_SIZE: u64 = 4096;
+/// The free blocks reported for a volume without capacity, as the backend
+/// has no limit.
+const UNLIMITED_FREE_BLOCKS: u64 = 10_000_000_000;
+/// The free inodes reported, as inodes are not limited by the volume.
+const UNLIMITED_FREE_INODES: u64 = 1_000_000_000;
+
+/// A record of the usage of a volume.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct VolumeUsage {
+    /// The bytes of the allocated chunks
+    pub bytes: i64,
+    /// The number of inodes
+    pub inodes: i64,
+}
+
+impl VolumeUsage {
+    /// Create a record of the usage counted by a scan.
+    #[must_use]
+    pub fn new(bytes: u64, inodes: u64) -> Self {
+        Self {
+            bytes: bytes.cast(),
+            inodes: inodes.cast(),
+        }
+    }
+
+    /// Charge a change of usage.
+    pub fn charge(&mut self, bytes: i64, inodes: i64) {
+        self.bytes = self.bytes.overflow_add(bytes);
+        self.inodes = self.inodes.overflow_add(inodes);
+    }
+
+    /// Sum up the records of all the nodes.
+    #[must_use]
+    pub fn sum(records: impl IntoIterator<Item = Self>) -> Self {
+        records
+            .into_iter()
+            .fold(Self::default(), |mut sum, record| {
+                sum.charge(record.bytes, record.inodes);
+                sum
+            })
+    }
+
+    /// Build the result of `statfs(2)` of a volume of `capacity` bytes, which
+    /// is unlimited if it's `None`. The `quota` covering the queried directory
+    /// limits the result further, if any.
+    #[must_use]
+    pub fn statfs(&self, capacity: Option<u64>, quota: Option<&Quota>) -> StatFsParam {
+        let used_blocks = self
+            .bytes
+            .max(0)
+            .cast::<u64>()
+            .overflow_add(STATFS_BLOCK_SIZE.overflow_sub(1))
+            .overflow_div(STATFS_BLOCK_SIZE);
+        let used_inodes = self.inodes.max(0).cast::<u64>();
+
+        let (mut blocks, mut bfree) = match capacity {
+            Some(capacity) => {
+                let blocks = capacity.overflow_div(STATFS_BLOCK_SIZE);
+                (blocks, blocks.saturating_sub(used_blocks))
+            }
+            None => (
+                used_blocks.overflow_add(UNLIMITED_FREE_BLOCKS),
+                UNLIMITED_FREE_BLOCKS,
+            ),
+        };
+        let mut files = used_inodes.overflow_add(UNLIMITED_FREE_INODES);
+        let mut f_free = UNLIMITED_FREE_INODES;
+
+        // A quota is reported as if its subtree is a file system of the
+        // limited size, but no more space is free than in the volume.
+        if let Some(quota) = quota {
+            let (quota_bytes, quota_inodes) = quota.capacity();
+            if let Some(quota_bytes) = quota_bytes {
+                blocks = quota_bytes.end.overflow_div(STATFS_BLOCK_SIZE);
+                bfree = bfree.min(quota_bytes.start.overflow_div(STATFS_BLOCK_SIZE));
+            }
+            if let Some(quota_inodes) = quota_inodes {
+                files = quota_inodes.end;
+                f_free = f_free.min(quota_inodes.start);
+            }
+        }
+
+        StatFsParam {
+            blocks,
+            bfree,
+            bavail: bfree,
+            files,
+            f_free,
+            bsize: STATFS_BLOCK_SIZE.cast(),
+            namelen: MAX_NAME_LEN.cast(),
+            frsize: STATFS_BLOCK_SIZE.cast(),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use clippy_utilities::OverflowArithmetic;
+
+    use super::{VolumeUsage, UNLIMITED_FREE_BLOCKS, UNLIMITED_FREE_INODES};
+    use crate::async_fuse::memfs::quota::{Quota, QuotaAttr};
+
+    #[test]
+    fn test_sum() {
+        let mut record = VolumeUsage::default();
+        record.charge(-4096, -1);
+        let usage = VolumeUsage::sum([VolumeUsage::new(8192, 3), record]);
+        assert_eq!(usage, VolumeUsage::new(4096, 2));
+    }
+
+    #[test]
+    fn test_statfs() {
+        let usage = VolumeUsage::new(4097, 2);
+
+        let param = usage.statfs(None, None);
+        assert_eq!(param.blocks, UNLIMITED_FREE_BLOCKS.overflow_add(2));
+        assert_eq!(param.bfree, UNLIMITED_FREE_BLOCKS);
+        assert_eq!(param.files, UNLIMITED_FREE_INODES.overflow_add(2));
+
+        let param = usage.statfs(Some(40960), None);
+        assert_eq!((param.blocks, param.bfree, param.bavail), (10, 8, 8));
+
+        // The quota is limited by the free space of the volume.
+        let mut quota = Quota::new(2, 4096, 1);
+        quota.set_limit(QuotaAttr::MaxBytes, 409_600).unwrap();
+        quota.set_limit(QuotaAttr::MaxInodes, 10).unwrap();
+        let param = usage.statfs(Some(40960), Some(&quota));
+        assert_eq!((param.blocks, param.bfree), (100, 8));
+        assert_eq!((param.files, param.f_free), (10, 9));
+
+        // A negative usage left by a broken record is reported as empty.
+        let mut usage = VolumeUsage::default();
+        usage.charge(-4096, -1);
+        let param = usage.statfs(Some(40960), None);
+        assert_eq!((param.bfree, param.files), (10, UNLIMITED_FREE_INODES));
+    }
+}
@@ -0,0 +1,379 @@
+//! The file system operations independent of the transports, which are the
+//! FUSE session and the gateways re-exporting the file system over the
+//! network protocols.
+//!
+//! An operation runs in the `ReqContext` of its caller, the FUSE session
+//! passes the credentials of the request, and the gateways run as root, see
+//! [`ROOT_CONTEXT`], because they authenticate their clients by themselves.
+//! The transports only convert the requests and the replies.
+//!
+//! A node created is referenced once like one looked up by the kernel. The
+//! FUSE session drops the references by `forget_entry()` when the kernel
+//! forgets the node, while the gateways, whose clients never forget a node,
+//! create and remove the nodes by `create_stateless()` and
+//! `remove_stateless()`, which drop the references when the nodes are
+//! removed.
+
+use std::time::Duration;
+
+use clippy_utilities::{Cast, OverflowArithmetic};
+use nix::fcntl::OFlag;
+
+use super::coherence::LeaseMode;
+use super::direntry::DirEntry;
+use super::file_handle::FileHandle;
+use super::metadata::{MetaData, MetaOp, MetaOpResult, ReqContext};
+use super::{check_name_length, CreateParam, MemFs, RenameParam, SetAttrParam};
+use crate::async_fuse::fuse::fuse_reply::StatFsParam;
+use crate::async_fuse::fuse::protocol::{FuseAttr, INum};
+use crate::common::error::DatenLordResult;
+use crate::storage::Block;
+
+/// The context of the operations run as root.
+pub const ROOT_CONTEXT: ReqContext = ReqContext { uid: 0, gid: 0 };
+
+impl<M: MetaData + Send + Sync + 'static> MemFs<M> {
+    /// Look up `name` in directory `parent`, returns the TTL, the attributes
+    /// and the generation of the node. No reference is taken, the references
+    /// of the kernel are counted by the FUSE session.
+    pub async fn lookup_entry(
+        &self,
+        context: ReqContext,
+        parent: INum,
+        name: &str,
+    ) -> DatenLordResult<(Duration, FuseAttr, u64)> {
+        check_name_length(name)?;
+        self.metadata.lookup_helper(context, parent, name).await
+    }
+
+    /// Drop `nlookup` references of `ino` taken in the kv engine, by the
+    /// creations or by the kernel of this node, a removed node is deleted
+    /// with its data by its last reference.
+    pub async fn forget_entry(&self, ino: INum, nlookup: u64) -> DatenLordResult<()> {
+        if self.metadata.forget(ino, nlookup).await? {
+            self.storage.remove(ino).await?;
+        }
+        Ok(())
+    }
+
+    /// Release an open of `ino`, a removed file is deleted with its data by
+    /// its last release among the nodes.
+    pub async fn release_file(
+        &self,
+        ino: INum,
+        fh: u64,
+        flags: u32,
+        lock_owner: u64,
+        flush: bool,
+    ) -> DatenLordResult<()> {
+        if self
+            .metadata
+            .release(ino, fh, flags, lock_owner, flush)
+            .await?
+        {
+            self.storage.remove(ino).await?;
+        }
+        Ok(())
+    }
+
+    /// Get the i-number of the parent directory of `ino`.
+    pub async fn get_parent(&self, ino: INum) -> DatenLordResult<INum> {
+        self.metadata.get_parent_ino(ino).await
+    }
+
+    /// Get the TTL and the attributes of `ino`.
+    pub async fn get_attr(&self, ino: INum) -> DatenLordResult<(Duration, FuseAttr)> {
+        self.metadata.getattr(ino).await
+    }
+
+    /// Set the attributes of `ino`, returns its TTL and attributes.
+    pub async fn set_attr(
+        &self,
+        context: ReqContext,
+        ino: INum,
+        param: &SetAttrParam,
+    ) -> DatenLordResult<(Duration, FuseAttr)> {
+        self.check_writable(ino).await?;
+        // Changing the size is serialized against writes of the file.
+        let _guard = match param.size {
+            Some(_) => Some(self.inode_locks.write(ino).await),
+            None => None,
+        };
+        self.metadata
+            .setattr_helper(context, ino, param, &self.storage)
+            .await
+    }
+
+    /// Read at most `len` bytes of `ino` from `offset`, and whether the end of
+    /// the file is reached. The file is opened for the read only.
+    pub async fn read_file(
+        &self,
+        context: ReqContext,
+        ino: INum,
+        offset: u64,
+        len: u64,
+    ) -> DatenLordResult<(Vec<u8>, bool)> {
+        if !self.coherence.is_strict() {
+            self.metadata.revalidate_open_file(ino).await?;
+        }
+        let fh = self
+            .metadata
+            .open(context, ino, OFlag::O_RDONLY.bits().cast())
+            .await?;
+        let result = self.read_opened(ino, None, offset, len).await;
+        self.release_file(ino, fh.cast(), 0, 0, false).await?;
+        let (blocks, eof) = result?;
+        let data = blocks
+            .iter()
+            .flat_map(|block| block.as_slice().iter().copied())
+            .collect();
+        Ok((data, eof))
+    }
+
+    /// Read at most `len` bytes of `ino` from `offset`, and whether the end of
+    /// the file is reached. The file is opened by the caller, as `handle` if
+    /// it's opened by the kernel, whose reads are read ahead.
+    pub(super) async fn read_opened(
+        &self,
+        ino: INum,
+        handle: Option<&FileHandle>,
+        offset: u64,
+        len: u64,
+    ) -> DatenLordResult<(Vec<Block>, bool)> {
+        let (_lease, attr) = self.coherence.guard(ino, LeaseMode::Read).await?;
+        // Reload the attributes written by the previous holder, and keep
+        // them cached while the delegation is held.
+        if let Some(attr) = attr {
+            self.metadata.delegate_open_file(attr);
+        }
+        let (file_size, mtime) = self.metadata.read_helper(ino).await?;
+        if offset >= file_size {
+            return Ok((vec![], true));
+        }
+        // Ensure `offset + len` is not beyond the end of the file
+        let len = len.min(file_size.overflow_sub(offset));
+        let eof = offset.overflow_add(len) >= file_size;
+        // The content of a small file loaded with its node is served without
+        // the storage.
+        if let Some(data) = self.metadata.inline_data(ino) {
+            let (start, end): (usize, usize) = (offset.cast(), offset.overflow_add(len).cast());
+            if let Some(data) = data.get(start..end).filter(|data| !data.is_empty()) {
+                return Ok((vec![Block::from_slice(data.len(), data)], eof));
+            }
+        }
+        let blocks = match handle {
+            Some(handle) => {
+                self.storage
+                    .read(
+                        ino,
+                        handle.read_ahead(),
+                        offset.cast(),
+                        len.cast(),
+                        file_size.cast(),
+                        mtime,
+                    )
+                    .await?
+            }
+            None => {
+                self.storage
+                    .load(ino, offset.cast(), len.cast(), mtime)
+                    .await?
+            }
+        };
+        Ok((blocks, eof))
+    }
+
+    /// Write `data` to `ino` at `offset`, the data is flushed before the
+    /// attributes of the file are returned. The file is opened for the write
+    /// only.
+    pub async fn write_file(
+        &self,
+        context: ReqContext,
+        ino: INum,
+        offset: u64,
+        data: &[u8],
+    ) -> DatenLordResult<FuseAttr> {
+        self.check_writable(ino).await?;
+        if !self.coherence.is_strict() {
+            self.metadata.revalidate_open_file(ino).await?;
+        }
+        let fh = self
+            .metadata
+            .open(context, ino, OFlag::O_WRONLY.bits().cast())
+            .await?;
+        let result = match self.write_opened(ino, offset, data).await {
+            Ok(()) => self.storage.flush(ino).await,
+            Err(e) => Err(e),
+        };
+        self.release_file(ino, fh.cast(), 0, 0, true).await?;
+        result?;
+        let (_, attr) = self.get_attr(ino).await?;
+        Ok(attr)
+    }
+
+    /// Write `data` to `ino` opened by the caller at `offset`, the data is
+    /// flushed by the caller.
+    pub(super) async fn write_opened(
+        &self,
+        ino: INum,
+        offset: u64,
+        data: &[u8],
+    ) -> DatenLordResult<()> {
+        let data_len: u64 = data.len().cast();
+        let (_lease, attr) = self.coherence.guard(ino, LeaseMode::Write).await?;
+        // Reload the attributes written by the previous holder, and keep
+        // them cached while the delegation is held.
+        if let Some(attr) = attr {
+            self.metadata.delegate_open_file(attr);
+        }
+        let _guard = self.inode_locks.write_range(ino, offset, data_len).await;
+        self.metadata.check_quota(ino, offset, data_len).await?;
+        let (old_size, old_mtime) = self.metadata.mtime_and_size(ino);
+        let new_mtime = self
+            .storage
+            .store(ino, offset.cast(), data, old_mtime)
+            .await?;
+        let new_size = old_size.max(offset.overflow_add(data_len));
+        self.metadata
+            .write_helper(ino, new_mtime, new_size, offset, data_len, Some(data))
+            .await
+    }
+
+    /// Create a file, a directory or a symbolic link, the node created is
+    /// referenced once. Returns the TTL, the attributes and the generation of
+    /// the node.
+    pub async fn create_node(
+        &self,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FuseAttr, u64)> {
+        self.check_writable(param.parent).await?;
+        check_name_length(&param.name)?;
+        self.metadata.mknod(param).await
+    }
+
+    /// Create a node like `create_node()` for the callers never forgetting
+    /// it, the reference it's created with is dropped by
+    /// `remove_stateless()`.
+    pub async fn create_stateless(&self, param: CreateParam) -> DatenLordResult<FuseAttr> {
+        let (_, attr, _) = self.create_node(param).await?;
+        let mut refs = self.stateless_refs.lock();
+        let count = refs.entry(attr.ino).or_insert(0);
+        *count = count.overflow_add(1);
+        Ok(attr)
+    }
+
+    /// Remove `name` from directory `parent`. The data of a file still
+    /// referenced is deleted by `forget_entry()`.
+    pub async fn remove_entry(
+        &self,
+        context: ReqContext,
+        parent: INum,
+        name: &str,
+    ) -> DatenLordResult<()> {
+        self.check_writable(parent).await?;
+        check_name_length(name)?;
+        if let Some(ino) = self.metadata.unlink(context, parent, name).await? {
+            self.storage.remove(ino).await?;
+        }
+        Ok(())
+    }
+
+    /// Remove `name` from directory `parent` as root, the references taken
+    /// by `create_stateless()` are dropped.
+    pub async fn remove_stateless(&self, parent: INum, name: &str) -> DatenLordResult<()> {
+        let (_, attr, _) = self.lookup_entry(ROOT_CONTEXT, parent, name).await?;
+        self.remove_entry(ROOT_CONTEXT, parent, name).await?;
+        let nlookup = self.stateless_refs.lock().remove(&attr.ino);
+        if let Some(nlookup) = nlookup {
+            self.forget_entry(attr.ino, nlookup).await?;
+        }
+        Ok(())
+    }
+
+    /// Rename an entry. The data of a file replaced is deleted, unless it's
+    /// still referenced, then it's deleted by `forget_entry()`.
+    pub async fn rename_entry(
+        &self,
+        context: ReqContext,
+        param: RenameParam,
+    ) -> DatenLordResult<()> {
+        self.check_writable(param.old_parent).await?;
+        self.check_writable(param.new_parent).await?;
+        check_name_length(&param.new_name)?;
+        if let Some(ino) = self.metadata.rename(context, param).await? {
+            self.storage.remove(ino).await?;
+        }
+        Ok(())
+    }
+
+    /// Run the metadata operations `ops` in one transaction, so the other
+    /// nodes see none of them or all of them. The data of the files replaced
+    /// is deleted like `rename_entry()`.
+    pub async fn transact(
+        &self,
+        context: ReqContext,
+        ops: &[MetaOp],
+    ) -> DatenLordResult<Vec<MetaOpResult>> {
+        for op in ops {
+            match *op {
+                MetaOp::Create(ref param) => self.check_writable(param.parent).await?,
+                MetaOp::SetAttr { ino, .. } | MetaOp::SetXattr { ino, .. } => {
+                    if let Some(ino) = ino {
+                        self.check_writable(ino).await?;
+                    }
+                }
+                MetaOp::Rename(ref param) => {
+                    self.check_writable(param.old_parent).await?;
+                    self.check_writable(param.new_parent).await?;
+                }
+            }
+        }
+        let results = self.metadata.transact(context, ops).await?;
+        for result in &results {
+            if let MetaOpResult::Renamed(Some(ino)) = *result {
+                self.storage.remove(ino).await?;
+            }
+        }
+        Ok(results)
+    }
+
+    /// Create a node like `create_stateless()`, and set its attributes other
+    /// than the size in the same transaction, so it's never seen with the
+    /// attributes it's created with.
+    pub async fn create_stateless_with_attr(
+        &self,
+        param: CreateParam,
+        attr: SetAttrParam,
+    ) -> DatenLordResult<FuseAttr> {
+        let ops = [
+            MetaOp::Create(param),
+            MetaOp::SetAttr {
+                ino: None,
+                param: attr,
+            },
+        ];
+        let mut results = self.transact(ROOT_CONTEXT, &ops).await?;
+        let Some(MetaOpResult::AttrSet(attr)) = results.pop() else {
+            unreachable!("The attributes are set by the last operation.");
+        };
+        let mut refs = self.stateless_refs.lock();
+        let count = refs.entry(attr.ino).or_insert(0);
+        *count = count.overflow_add(1);
+        Ok(attr)
+    }
+
+    /// List the entries of directory `ino`.
+    pub async fn list_dir(&self, context: ReqContext, ino: INum) -> DatenLordResult<Vec<DirEntry>> {
+        self.metadata.list_dir(context, ino).await
+    }
+
+    /// Read the target of symbolic link `ino`.
+    pub async fn read_link(&self, ino: INum) -> DatenLordResult<Vec<u8>> {
+        self.metadata.readlink(ino).await
+    }
+
+    /// Get the statistics of the file system of `ino`.
+    pub async fn stat_fs(&self, context: ReqContext, ino: INum) -> DatenLordResult<StatFsParam> {
+        self.metadata.statfs(context, ino).await
+    }
+}
@@ -0,0 +1,618 @@
+//! Named volumes of the volume manager.
+//!
+//! A named volume is a directory at the root of the file system, named by the
+//! id of the volume, so it's found at `<mount path>/<id>` on every mounting
+//! node, which is where the CSI driver publishes volumes from. The capacity
+//! of a volume is the byte limit of the quota of its directory, so growth
+//! beyond it fails with `EDQUOT` and `statfs(2)` in the volume reports it, see
+//! [`quota`](super::quota). The storage policy of a volume pins its directory
+//! to a tier, the replicated hot tier or the erasure coded cold one, which
+//! takes effect when the file system is mounted with the tiered storage
+//! policy.
+//!
+//! The specs of the volumes are kept in the kv engine with the metadata. A
+//! volume is mounted with its secret, which is returned once when the volume
+//! is created, only the hash of the secret is kept.
+//!
+//! A volume is also mounted with an access token issued by the manager, which
+//! grants read-only or read-write access to the volume alone. The CSI
+//! controller hands out the tokens, so a node only mounts the volumes it's
+//! given the tokens of. A token is the access it grants and the MAC of the
+//! access and the id of the volume, keyed by a key of the volume which never
+//! leaves the manager.
+//!
+//! A volume is created with its tuning parameters, such as the block size, the
+//! cache size, the compression and the number of replicas, which are validated
+//! and kept in the spec, each of them is `None` for the default of the file
+//! system.
+
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use datenlord::config::{CompressionType, StoragePolicy};
+use nix::sys::stat::SFlag;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::kv_engine::{KVEngine, KVEngineType, KeyType, ValueType, RETRY_TXN_BREAK};
+use super::metadata::ReqContext;
+use super::quota::{Quota, QuotaAttr};
+use super::{CreateParam, MetaData, MetaDataOptions, S3MetaData};
+use crate::async_fuse::fuse::protocol::{INum, FUSE_ROOT_ID};
+use crate::common::error::{DatenLordError, DatenLordResult};
+use crate::storage::tiering::Tier;
+
+/// The limit of retrying a transaction.
+const TXN_RETRY_LIMIT: u32 = 10;
+
+/// The volume manager acts as the super user.
+const ROOT_CONTEXT: ReqContext = ReqContext { uid: 0, gid: 0 };
+
+/// The minimal block size of a volume, 4 KiB.
+const MIN_BLOCK_SIZE: u64 = 0x1000;
+
+/// The maximal block size of a volume, 64 MiB.
+const MAX_BLOCK_SIZE: u64 = 0x400_0000;
+
+/// The maximal number of replicas of a volume.
+const MAX_REPLICAS: u32 = 16;
+
+/// The tuning parameters of a volume, `None` for the default of the file
+/// system.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VolumeParams {
+    /// The size of blocks in bytes, a power of two
+    pub block_size: Option<u64>,
+    /// The capacity of the memory cache in bytes
+    pub cache_size: Option<u64>,
+    /// The compression of blocks
+    pub compression: Option<CompressionType>,
+    /// The number of replicas of blocks
+    pub replicas: Option<u32>,
+}
+
+impl VolumeParams {
+    /// Check the parameters are in range.
+    pub fn validate(&self) -> DatenLordResult<()> {
+        if let Some(block_size) = self.block_size {
+            let in_range = (MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size);
+            if !block_size.is_power_of_two() || !in_range {
+                return Err(DatenLordError::ArgumentInvalid {
+                    context: vec![format!(
+                        "block size {block_size} is not a power of two \
+                            between {MIN_BLOCK_SIZE} and {MAX_BLOCK_SIZE}"
+                    )],
+                });
+            }
+        }
+        if let Some(cache_size) = self.cache_size {
+            let block_size = self.block_size.unwrap_or(MIN_BLOCK_SIZE);
+            if cache_size < block_size {
+                return Err(DatenLordError::ArgumentInvalid {
+                    context: vec![format!(
+                        "cache size {cache_size} is less than a block of {block_size} bytes"
+                    )],
+                });
+            }
+        }
+        if let Some(replicas) = self.replicas {
+            if !(1..=MAX_REPLICAS).contains(&replicas) {
+                return Err(DatenLordError::ArgumentInvalid {
+                    context: vec![format!(
+                        "{replicas} replicas are not between 1 and {MAX_REPLICAS}"
+                    )],
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The spec of a named volume.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct VolumeSpec {
+    /// The name of the volume
+    pub name: String,
+    /// The id of the volume, which names its directory
+    pub id: String,
+    /// The i-number of the directory of the volume
+    pub ino: INum,
+    /// The capacity in bytes, `0` for unlimited
+    pub capacity: u64,
+    /// The storage policy
+    pub policy: StoragePolicy,
+    /// The tuning parameters, the volumes created before them have the
+    /// defaults
+    #[serde(default)]
+    pub params: VolumeParams,
+    /// The time the volume is created
+    pub created: SystemTime,
+    /// The hash of the secret to mount the volume
+    secret_hash: [u8; 32],
+    /// The key of the MACs of the access tokens, the volumes created before
+    /// the tokens have none, and are still mounted read-write without one
+    #[serde(default)]
+    token_key: Option<[u8; 32]>,
+}
+
+/// The access to a volume granted by a token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// The volume is only mounted read-only
+    ReadOnly,
+    /// The volume is mounted read-only or read-write
+    ReadWrite,
+}
+
+impl Access {
+    /// The name of the access in the tokens.
+    const fn name(self) -> &'static str {
+        match self {
+            Access::ReadOnly => "ro",
+            Access::ReadWrite => "rw",
+        }
+    }
+}
+
+impl Display for Access {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl VolumeSpec {
+    /// Returns whether `secret` is the one to mount the volume.
+    #[must_use]
+    pub fn authorize(&self, secret: &str) -> bool {
+        // The comparison of hashes takes constant time.
+        blake3::Hash::from(self.secret_hash) == blake3::hash(secret.as_bytes())
+    }
+
+    /// The MAC of a token granting `access`, `None` if the volume has no key.
+    fn token_mac(&self, access: Access) -> Option<blake3::Hash> {
+        let key = self.token_key.as_ref()?;
+        let message = format!("{}/{access}", self.id);
+        Some(blake3::keyed_hash(key, message.as_bytes()))
+    }
+
+    /// Issue a token granting `access` to the volume.
+    pub fn issue_token(&self, access: Access) -> DatenLordResult<String> {
+        let mac = self
+            .token_mac(access)
+            .ok_or_else(|| DatenLordError::ArgumentInvalid {
+                context: vec![format!(
+                    "volume {} is created without access tokens",
+                    self.name
+                )],
+            })?;
+        Ok(format!("{access}.{}", mac.to_hex()))
+    }
+
+    /// Returns the access granted by `token`, `None` if it's not a token of
+    /// the volume.
+    #[must_use]
+    pub fn verify_token(&self, token: &str) -> Option<Access> {
+        if self.token_key.is_none() {
+            return Some(Access::ReadWrite);
+        }
+        let (access, mac) = token.split_once('.')?;
+        let access = [Access::ReadOnly, Access::ReadWrite]
+            .into_iter()
+            .find(|candidate| candidate.name() == access)?;
+        let mac = blake3::Hash::from_hex(mac).ok()?;
+        // The comparison of hashes takes constant time.
+        (self.token_mac(access)? == mac).then_some(access)
+    }
+}
+
+/// The tier the directory of a volume is pinned to by the storage policy.
+fn pinned_tier(policy: StoragePolicy) -> Option<Tier> {
+    match policy {
+        StoragePolicy::Replicate => Some(Tier::Hot),
+        StoragePolicy::Erasure => Some(Tier::Cold),
+        StoragePolicy::Tiered => None,
+    }
+}
+
+/// The error of a volume which doesn't exist.
+fn not_found(name: &str) -> DatenLordError {
+    DatenLordError::VolumeNotFound {
+        volume_id: name.to_owned(),
+        context: vec![],
+    }
+}
+
+/// The manager of the named volumes.
+#[derive(Debug)]
+pub struct VolumeManager {
+    /// The kv engine keeping the specs
+    kv_engine: Arc<KVEngineType>,
+    /// The metadata of the file system
+    meta: Arc<S3MetaData>,
+}
+
+impl VolumeManager {
+    /// Create the manager of the volumes in the file system of `kv_engine`,
+    /// on behalf of node `node_id`.
+    pub async fn new(kv_engine: Arc<KVEngineType>, node_id: &str) -> DatenLordResult<Self> {
+        // No chunk of files is accessed by the manager, so the chunk size is
+        // not needed, and the removed files skip the trash.
+        let meta = S3MetaData::new(
+            Arc::clone(&kv_engine),
+            node_id,
+            0,
+            MetaDataOptions::default(),
+        )
+        .await?;
+        Ok(Self { kv_engine, meta })
+    }
+
+    /// Create a volume named `name` with the tuning parameters `params`,
+    /// which is mounted with `secret`, or a generated one if it's `None`.
+    ///
+    /// Returns the spec and the secret of the volume.
+    pub async fn create(
+        &self,
+        name: &str,
+        capacity: u64,
+        policy: StoragePolicy,
+        params: VolumeParams,
+        secret: Option<String>,
+    ) -> DatenLordResult<(VolumeSpec, String)> {
+        if name.is_empty() {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["the name of a volume cannot be empty".to_owned()],
+            });
+        }
+        params.validate()?;
+        let key = KeyType::VolumeSpec(name.to_owned());
+        if self.kv_engine.get(&key).await?.is_some() {
+            return Err(DatenLordError::VolumeAlreadyExist {
+                volume_id: name.to_owned(),
+                context: vec![],
+            });
+        }
+
+        let id = Uuid::new_v4().to_string();
+        let param = CreateParam {
+            parent: FUSE_ROOT_ID,
+            name: id.clone(),
+            mode: 0o755,
+            rdev: 0,
+            uid: 0,
+            gid: 0,
+            umask: 0,
+            node_type: SFlag::S_IFDIR,
+            link: None,
+        };
+        let (_, attr, _) = self.meta.mknod(param).await?;
+        let secret = secret.unwrap_or_else(|| Uuid::new_v4().simple().to_string());
+        let mut token_key = [0_u8; 32];
+        OsRng.fill_bytes(&mut token_key);
+        let spec = VolumeSpec {
+            name: name.to_owned(),
+            id,
+            ino: attr.ino,
+            capacity,
+            policy,
+            params,
+            created: SystemTime::now(),
+            secret_hash: *blake3::hash(secret.as_bytes()).as_bytes(),
+            token_key: Some(token_key),
+        };
+
+        let res = match self.init_dir(&spec).await {
+            Ok(()) => self.insert(&key, &spec).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            // The directory is not recorded by any spec, and would be leaked.
+            if let Err(remove_err) = self
+                .meta
+                .remove_tree(ROOT_CONTEXT, FUSE_ROOT_ID, &spec.id)
+                .await
+            {
+                warn!(
+                    "failed to remove directory {} of volume {name}: {remove_err}",
+                    spec.id
+                );
+            }
+            return Err(e);
+        }
+        info!(
+            "created volume {} with id {} and capacity {capacity}",
+            spec.name, spec.id
+        );
+        Ok((spec, secret))
+    }
+
+    /// Limit the directory of a new volume by its spec.
+    async fn init_dir(&self, spec: &VolumeSpec) -> DatenLordResult<()> {
+        if spec.capacity != 0 {
+            self.meta
+                .set_quota(ROOT_CONTEXT, spec.ino, QuotaAttr::MaxBytes, spec.capacity)
+                .await?;
+        }
+        if let Some(tier) = pinned_tier(spec.policy) {
+            self.meta
+                .set_tier_pin(ROOT_CONTEXT, spec.ino, Some(tier))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Record `spec` at `key` unless another volume of the name is created.
+    async fn insert(&self, key: &KeyType, spec: &VolumeSpec) -> DatenLordResult<()> {
+        let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+            let mut txn = self.kv_engine.new_meta_txn().await;
+            if txn.get(key).await?.is_some() {
+                (RETRY_TXN_BREAK, false)
+            } else {
+                txn.set(key, &ValueType::VolumeSpec(spec.clone()));
+                (txn.commit().await, true)
+            }
+        });
+        if res? {
+            Ok(())
+        } else {
+            Err(DatenLordError::VolumeAlreadyExist {
+                volume_id: spec.name.clone(),
+                context: vec![],
+            })
+        }
+    }
+
+    /// Delete volume `name` with its files.
+    ///
+    /// Returns the spec of the deleted volume.
+    pub async fn delete(&self, name: &str) -> DatenLordResult<VolumeSpec> {
+        let spec = self.get(name).await?;
+        // The directory may have been removed through a mount.
+        if self.meta.get_node_from_kv_engine(spec.ino).await?.is_some() {
+            self.meta
+                .remove_tree(ROOT_CONTEXT, FUSE_ROOT_ID, &spec.id)
+                .await?;
+        }
+        self.kv_engine
+            .delete(&KeyType::VolumeSpec(name.to_owned()), None)
+            .await?;
+        info!("deleted volume {} with id {}", spec.name, spec.id);
+        Ok(spec)
+    }
+
+    /// Change the capacity of volume `name`, `0` for unlimited. A volume
+    /// cannot shrink below the bytes it has used.
+    ///
+    /// Returns the updated spec.
+    pub async fn resize(&self, name: &str, capacity: u64) -> DatenLordResult<VolumeSpec> {
+        let mut spec = self.get(name).await?;
+        if capacity != 0 {
+            if let Some(quota) = self.meta.get_quota(spec.ino).await? {
+                if quota.used_bytes > capacity {
+                    return Err(DatenLordError::ArgumentOutOfRange {
+                        context: vec![format!(
+                            "volume {name} has used {} bytes, beyond the capacity {capacity}",
+                            quota.used_bytes
+                        )],
+                    });
+                }
+            }
+        }
+        self.meta
+            .set_quota(ROOT_CONTEXT, spec.ino, QuotaAttr::MaxBytes, capacity)
+            .await?;
+
+        spec.capacity = capacity;
+        self.kv_engine
+            .set(
+                &KeyType::VolumeSpec(name.to_owned()),
+                &ValueType::VolumeSpec(spec.clone()),
+                None,
+            )
+            .await?;
+        info!("resized volume {} to capacity {capacity}", spec.name);
+        Ok(spec)
+    }
+
+    /// Get the spec of volume `name`.
+    pub async fn get(&self, name: &str) -> DatenLordResult<VolumeSpec> {
+        self.kv_engine
+            .get(&KeyType::VolumeSpec(name.to_owned()))
+            .await?
+            .map(ValueType::into_volume_spec)
+            .ok_or_else(|| not_found(name))
+    }
+
+    /// List the specs of all volumes, in the order of their names.
+    pub async fn list(&self) -> DatenLordResult<Vec<VolumeSpec>> {
+        let mut specs: Vec<VolumeSpec> = self
+            .kv_engine
+            .range(&KeyType::AllVolumeSpecs)
+            .await?
+            .into_iter()
+            .map(ValueType::into_volume_spec)
+            .collect();
+        specs.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(specs)
+    }
+
+    /// Get the spec of volume `name` with the quota of its directory, the
+    /// quota is `None` if the volume is unlimited, whose usage is not
+    /// tracked.
+    pub async fn quota(&self, name: &str) -> DatenLordResult<(VolumeSpec, Option<Quota>)> {
+        let spec = self.get(name).await?;
+        let quota = self.meta.get_quota(spec.ino).await?;
+        Ok((spec, quota))
+    }
+
+    /// The kv engine of the file system the volumes are in.
+    #[must_use]
+    pub fn kv_engine(&self) -> &Arc<KVEngineType> {
+        &self.kv_engine
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use datenlord::config::{CompressionType, StoragePolicy};
+    use nix::sys::stat::SFlag;
+
+    use super::{Access, VolumeManager, VolumeParams};
+    use crate::async_fuse::fuse::protocol::INum;
+    use crate::async_fuse::memfs::{CreateParam, MetaData};
+    use crate::async_fuse::test::test_util::open_kv_engine;
+    use crate::common::error::DatenLordError;
+    use crate::storage::tiering::Tier;
+
+    /// Create a node named `name` under directory `parent`.
+    async fn create(manager: &VolumeManager, parent: INum, name: &str, node_type: SFlag) -> INum {
+        let param = CreateParam {
+            parent,
+            name: name.to_owned(),
+            mode: 0o755,
+            rdev: 0,
+            uid: 0,
+            gid: 0,
+            umask: 0,
+            node_type,
+            link: None,
+        };
+        let (_, attr, _) = manager.meta.mknod(param).await.unwrap();
+        attr.ino
+    }
+
+    #[tokio::test]
+    async fn test_volume_manager() {
+        let kv_engine = open_kv_engine();
+        let manager = VolumeManager::new(kv_engine, "node1").await.unwrap();
+
+        // The directory of the volume is limited and pinned by the spec.
+        let params = VolumeParams {
+            block_size: Some(1 << 16),
+            compression: Some(CompressionType::Zstd),
+            ..VolumeParams::default()
+        };
+        let (spec, secret) = manager
+            .create("data", 1 << 20, StoragePolicy::Erasure, params, None)
+            .await
+            .unwrap();
+        assert_eq!(spec.params, params);
+        assert!(spec.authorize(&secret));
+        assert!(!spec.authorize("guess"));
+        let token = spec.issue_token(Access::ReadOnly).unwrap();
+        assert_eq!(spec.verify_token(&token), Some(Access::ReadOnly));
+        let token = spec.issue_token(Access::ReadWrite).unwrap();
+        assert_eq!(spec.verify_token(&token), Some(Access::ReadWrite));
+        // The access of a token can't be raised.
+        let raised = spec
+            .issue_token(Access::ReadOnly)
+            .unwrap()
+            .replacen("ro", "rw", 1);
+        assert_eq!(spec.verify_token(&raised), None);
+        assert_eq!(spec.verify_token("rw.guess"), None);
+        let quota = manager.meta.get_quota(spec.ino).await.unwrap().unwrap();
+        assert_eq!(quota.max_bytes, 1 << 20);
+        let pin = manager.meta.get_tier_pin(spec.ino).await.unwrap();
+        assert_eq!(pin, Some(Tier::Cold));
+        let err = manager
+            .create("data", 0, StoragePolicy::Tiered, params, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DatenLordError::VolumeAlreadyExist { .. }));
+
+        let (other, secret) = manager
+            .create(
+                "backup",
+                0,
+                StoragePolicy::Tiered,
+                VolumeParams::default(),
+                Some("secret".to_owned()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(secret, "secret");
+        assert!(other.authorize("secret"));
+        // A token only grants the access to its own volume.
+        assert_eq!(other.verify_token(&token), None);
+        assert!(manager.meta.get_quota(other.ino).await.unwrap().is_none());
+        assert!(manager
+            .meta
+            .get_tier_pin(other.ino)
+            .await
+            .unwrap()
+            .is_none());
+        let names: Vec<String> = manager
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|spec| spec.name)
+            .collect();
+        assert_eq!(names, vec!["backup", "data"]);
+
+        let resized = manager.resize("data", 2 << 20).await.unwrap();
+        assert_eq!(resized.capacity, 2 << 20);
+        assert_eq!(manager.get("data").await.unwrap(), resized);
+        let quota = manager.meta.get_quota(spec.ino).await.unwrap().unwrap();
+        assert_eq!(quota.max_bytes, 2 << 20);
+        // The quota is dropped once the volume is unlimited.
+        manager.resize("data", 0).await.unwrap();
+        assert!(manager.meta.get_quota(spec.ino).await.unwrap().is_none());
+
+        // Deleting a volume removes its files.
+        let dir = create(&manager, spec.ino, "dir", SFlag::S_IFDIR).await;
+        let file = create(&manager, dir, "file", SFlag::S_IFREG).await;
+        let deleted = manager.delete("data").await.unwrap();
+        assert_eq!(deleted.id, spec.id);
+        for ino in [spec.ino, dir, file] {
+            assert!(manager
+                .meta
+                .get_node_from_kv_engine(ino)
+                .await
+                .unwrap()
+                .is_none());
+        }
+        let err = manager.get("data").await.unwrap_err();
+        assert!(matches!(err, DatenLordError::VolumeNotFound { .. }));
+        let err = manager.delete("data").await.unwrap_err();
+        assert!(matches!(err, DatenLordError::VolumeNotFound { .. }));
+        assert_eq!(manager.list().await.unwrap(), vec![other]);
+    }
+
+    #[test]
+    fn test_volume_params() {
+        let params = VolumeParams {
+            block_size: Some(1 << 20),
+            cache_size: Some(1 << 30),
+            compression: Some(CompressionType::Lz4),
+            replicas: Some(3),
+        };
+        assert!(params.validate().is_ok());
+        assert!(VolumeParams::default().validate().is_ok());
+        for invalid in [
+            VolumeParams {
+                block_size: Some(3000),
+                ..params
+            },
+            VolumeParams {
+                block_size: Some(1 << 30),
+                ..params
+            },
+            VolumeParams {
+                cache_size: Some(1 << 10),
+                ..params
+            },
+            VolumeParams {
+                replicas: Some(0),
+                ..params
+            },
+        ] {
+            assert!(invalid.validate().is_err(), "{invalid:?} is valid");
+        }
+    }
+}
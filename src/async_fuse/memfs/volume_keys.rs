@@ -0,0 +1,92 @@
+//! The data keys of an encrypted volume, persisted in the kv engine.
+
+use std::sync::Arc;
+
+use tracing::info;
+
+use super::kv_engine::{KVEngine, KVEngineType, KeyType, ValueType, RETRY_TXN_BREAK};
+use crate::common::error::{DatenLordError, DatenLordResult};
+use crate::storage::encryption::{DataKey, MasterKey, WrappedDataKey};
+use crate::storage::error::StorageResult;
+
+/// The limit of retrying a transaction.
+const TXN_RETRY_LIMIT: u32 = 10;
+
+/// Convert the result of storage into the result of the kv engine.
+fn into_datenlord_result<T>(result: StorageResult<T>) -> DatenLordResult<T> {
+    result.map_err(|e| DatenLordError::from(anyhow::Error::from(e)))
+}
+
+/// Unwrap a data key with the master key, or the old master key.
+///
+/// Returns the key, and whether it's wrapped by the old master key.
+fn unwrap_key(
+    wrapped: &WrappedDataKey,
+    master_key: &MasterKey,
+    old_master_key: Option<&MasterKey>,
+) -> DatenLordResult<(DataKey, bool)> {
+    match (master_key.unwrap_key(wrapped), old_master_key) {
+        (Ok(key), _) => Ok((key, false)),
+        (Err(_), Some(old_master_key)) => {
+            into_datenlord_result(old_master_key.unwrap_key(wrapped)).map(|key| (key, true))
+        }
+        (Err(e), None) => into_datenlord_result(Err(e)),
+    }
+}
+
+/// Load the data keys of the volume, and generate the first one if the volume
+/// is newly created.
+///
+/// The data keys wrapped by `old_master_key` are re-wrapped by `master_key`,
+/// for rotating the master key. If `rotate` is set, a new data key is
+/// generated for the following writes, and the older ones are kept to read
+/// existing blocks.
+pub async fn load_data_keys(
+    kv_engine: &Arc<KVEngineType>,
+    master_key: &MasterKey,
+    old_master_key: Option<&MasterKey>,
+    rotate: bool,
+) -> DatenLordResult<Vec<DataKey>> {
+    let (res, _) = retry_txn!(TXN_RETRY_LIMIT, {
+        let mut txn = kv_engine.new_meta_txn().await;
+        let wrapped_keys = txn
+            .get(&KeyType::VolumeDataKeys)
+            .await?
+            .map(ValueType::into_data_keys)
+            .unwrap_or_default();
+
+        let mut keys = Vec::with_capacity(wrapped_keys.len());
+        let mut rewrap = false;
+        for wrapped in &wrapped_keys {
+            let (key, is_old) = unwrap_key(wrapped, master_key, old_master_key)?;
+            rewrap |= is_old;
+            keys.push(key);
+        }
+
+        let generate = keys.is_empty() || rotate;
+        if generate {
+            let id = keys.iter().map(|key| key.id().wrapping_add(1)).max();
+            keys.push(DataKey::generate(id.unwrap_or_default()));
+        }
+
+        if generate || rewrap {
+            let wrapped_keys = keys
+                .iter()
+                .map(|key| into_datenlord_result(master_key.wrap_key(key)))
+                .collect::<DatenLordResult<Vec<_>>>()?;
+            txn.set(&KeyType::VolumeDataKeys, &ValueType::DataKeys(wrapped_keys));
+            (txn.commit().await, keys)
+        } else {
+            (RETRY_TXN_BREAK, keys)
+        }
+    });
+
+    let keys = res?;
+    if let Some(current) = keys.iter().map(DataKey::id).max() {
+        info!(
+            "the volume is encrypted with {} data key(s), the current one is {current}",
+            keys.len()
+        );
+    }
+    Ok(keys)
+}
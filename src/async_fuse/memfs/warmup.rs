@@ -0,0 +1,508 @@
+//! The warm-up of the caches with a dataset.
+//!
+//! A training job reading a dataset pays for fetching every block from the
+//! backend in its first epoch. A warm-up loads the files listed, and all the
+//! files below the directories listed, into the caches of the node ahead of
+//! the job. The files are loaded through the same path as the reads of them,
+//! under the leases of the files and validated by their modified times, so
+//! the blocks loaded are hit by the reads. The loads are paced under the
+//! bandwidth limit if one is given, so a warm-up doesn't starve the jobs
+//! already running on the node.
+//!
+//! A warm-up is requested by the warmup role on the control socket, which is
+//! replied by its progress until the files are loaded. The warm-ups run one at
+//! a time in the order they're requested, and a warm-up goes on if its client
+//! is gone. The files failing to load, such as the ones removed meanwhile, are
+//! skipped and counted. A warm-up pinning the files, requested by the pin
+//! role, pins them before they're loaded, see `PINS`.
+
+use std::collections::HashSet;
+use std::num::NonZeroU64;
+use std::slice;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clippy_utilities::{Cast, OverflowArithmetic};
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::select;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{self, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use super::coherence::{Coherence, LeaseMode};
+use super::direntry::FileType;
+use super::metadata::MetaData;
+use super::pin::PINS;
+use super::{StorageType, ROOT_CONTEXT};
+use crate::async_fuse::fuse::protocol::{FuseAttr, INum, FUSE_ROOT_ID};
+use crate::async_fuse::util::build_error_result_from_errno;
+use crate::common::error::{Context, DatenLordResult};
+
+/// The warm-ups of this process.
+pub static WARMUP: Lazy<WarmUp> = Lazy::new(WarmUp::default);
+
+/// The number of blocks loaded at once.
+const LOAD_BLOCKS: usize = 16;
+
+/// The nanoseconds in a second.
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// A warm-up requested.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WarmUpRequest {
+    /// The files and the directories to load, relative to the root of the
+    /// volume or under the mount point
+    pub paths: Vec<String>,
+    /// The bytes per second loaded at most, `None` if it's not limited
+    pub bandwidth: Option<NonZeroU64>,
+    /// Whether the files are pinned in the cache before they're loaded, see
+    /// `PINS`
+    pub pin: bool,
+}
+
+/// The stages of a warm-up.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WarmUpStage {
+    /// The warm-up waits for the ones requested before it
+    #[default]
+    Queued,
+    /// The files below the directories are being listed, and pinned if
+    /// they're to pin
+    Listing,
+    /// The files are being loaded
+    Loading,
+    /// All the files are loaded, or skipped if they fail to load
+    Done,
+    /// The warm-up failed for the error
+    Failed(String),
+}
+
+/// The progress of a warm-up.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WarmUpProgress {
+    /// The stage reached
+    pub stage: WarmUpStage,
+    /// The files to load
+    pub files: usize,
+    /// The files loaded
+    pub files_loaded: usize,
+    /// The files skipped as they fail to load
+    pub files_failed: usize,
+    /// The bytes of the files to load
+    pub bytes: u64,
+    /// The bytes loaded
+    pub bytes_loaded: u64,
+}
+
+impl WarmUpProgress {
+    /// Whether the warm-up is finished, done or failed.
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        matches!(self.stage, WarmUpStage::Done | WarmUpStage::Failed(_))
+    }
+}
+
+/// A warm-up queued to the warmer.
+#[derive(Debug)]
+struct Job {
+    /// The warm-up requested
+    request: WarmUpRequest,
+    /// The progress of the warm-up
+    progress: watch::Sender<WarmUpProgress>,
+}
+
+/// The queue of the warm-ups to the warmer of the mount.
+#[derive(Debug, Default)]
+pub struct WarmUp {
+    /// The sender of the warm-ups to the warmer, `None` if no file system is
+    /// mounted
+    jobs: Mutex<Option<mpsc::UnboundedSender<Job>>>,
+}
+
+impl WarmUp {
+    /// Register the warmer of the mount, which receives the warm-ups.
+    fn register(&self) -> mpsc::UnboundedReceiver<Job> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        *self.jobs.lock() = Some(sender);
+        receiver
+    }
+
+    /// Queue the warm-up of `request`, and watch its progress. Returns `None`
+    /// if no file system is mounted.
+    #[must_use]
+    pub fn start(&self, request: WarmUpRequest) -> Option<watch::Receiver<WarmUpProgress>> {
+        let (progress, receiver) = watch::channel(WarmUpProgress::default());
+        self.jobs
+            .lock()
+            .as_ref()?
+            .send(Job { request, progress })
+            .ok()?;
+        Some(receiver)
+    }
+}
+
+/// The pacing of the loads under a bandwidth limit.
+#[derive(Debug)]
+struct Throttle {
+    /// The bytes per second loaded at most, `None` if it's not limited
+    bandwidth: Option<NonZeroU64>,
+    /// The start of the loads
+    start: Instant,
+    /// The bytes loaded since the start
+    loaded: u64,
+}
+
+impl Throttle {
+    /// Create a `Throttle` starting now.
+    fn new(bandwidth: Option<NonZeroU64>) -> Self {
+        Self {
+            bandwidth,
+            start: Instant::now(),
+            loaded: 0,
+        }
+    }
+
+    /// Account `bytes` loaded, returns the instant the next load waits for,
+    /// `None` if it's not limited.
+    fn account(&mut self, bytes: u64) -> Option<Instant> {
+        let bandwidth = self.bandwidth?;
+        self.loaded = self.loaded.overflow_add(bytes);
+        let nanos = u128::from(self.loaded)
+            .saturating_mul(NANOS_PER_SEC)
+            .checked_div(u128::from(bandwidth.get()))?;
+        self.start
+            .checked_add(Duration::from_nanos(nanos.try_into().unwrap_or(u64::MAX)))
+    }
+
+    /// Wait after `bytes` loaded until the loads are under the limit, or
+    /// `token` is cancelled.
+    #[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+    async fn pace(&mut self, bytes: u64, token: &CancellationToken) {
+        let Some(until) = self.account(bytes) else {
+            return;
+        };
+        select! {
+            () = time::sleep_until(until) => {}
+            () = token.cancelled() => {}
+        }
+    }
+}
+
+/// The names of the components of `path` below the root of the volume, which
+/// is relative to the root, or under `mount_point`.
+pub(super) fn components<'a>(path: &'a str, mount_point: &str) -> DatenLordResult<Vec<&'a str>> {
+    let mount_point = mount_point.trim_end_matches('/');
+    let relative = path
+        .strip_prefix(mount_point)
+        .filter(|rest| !mount_point.is_empty() && (rest.is_empty() || rest.starts_with('/')))
+        .unwrap_or(path);
+    let names: Vec<&str> = relative
+        .split('/')
+        .filter(|&name| !name.is_empty() && name != ".")
+        .collect();
+    if names.contains(&"..") {
+        return build_error_result_from_errno(
+            Errno::EINVAL,
+            format!("{path} to warm up is not below the root of the volume"),
+        );
+    }
+    Ok(names)
+}
+
+/// The type of the node of `attr`.
+fn file_type(attr: &FuseAttr) -> DatenLordResult<FileType> {
+    FileType::try_from(SFlag::from_bits_truncate(attr.mode & SFlag::S_IFMT.bits()))
+}
+
+/// The loader of the files of the warm-ups into the caches.
+#[derive(Debug)]
+struct Warmer<M: MetaData + Send + Sync + 'static> {
+    /// Fs metadata
+    metadata: Arc<M>,
+    /// Storage manager
+    storage: StorageType,
+    /// The leases of files keeping the caches coherent among the nodes
+    coherence: Arc<Coherence>,
+    /// The size of the blocks
+    block_size: usize,
+    /// The mount point, stripped from the paths under it
+    mount_point: String,
+}
+
+impl<M: MetaData + Send + Sync + 'static> Warmer<M> {
+    /// Resolve `path` to the attributes of its node.
+    async fn resolve(&self, path: &str) -> DatenLordResult<FuseAttr> {
+        let (_, mut attr) = self.metadata.getattr(FUSE_ROOT_ID).await?;
+        for name in components(path, &self.mount_point)? {
+            let (_, child, _) = self
+                .metadata
+                .lookup_helper(ROOT_CONTEXT, attr.ino, name)
+                .await
+                .with_context(|| format!("failed to resolve {path} to warm up"))?;
+            attr = child;
+        }
+        Ok(attr)
+    }
+
+    /// List the files of `paths` and below the directories of them, with
+    /// their sizes. The symbolic links are not followed.
+    async fn list(&self, paths: &[String]) -> DatenLordResult<Vec<(INum, u64)>> {
+        let mut seen = HashSet::new();
+        let mut files = vec![];
+        let mut dirs = vec![];
+        for path in paths {
+            let attr = self.resolve(path).await?;
+            match file_type(&attr)? {
+                FileType::Dir => dirs.push(attr.ino),
+                FileType::File if seen.insert(attr.ino) => files.push((attr.ino, attr.size)),
+                FileType::File | FileType::Symlink => {}
+            }
+        }
+        while let Some(dir) = dirs.pop() {
+            if !seen.insert(dir) {
+                continue;
+            }
+            for entry in self.metadata.list_dir(ROOT_CONTEXT, dir).await? {
+                match entry.file_type() {
+                    FileType::Dir => dirs.push(entry.ino()),
+                    FileType::File if seen.insert(entry.ino()) => {
+                        let (_, attr) = self.metadata.getattr(entry.ino()).await?;
+                        files.push((attr.ino, attr.size));
+                    }
+                    FileType::File | FileType::Symlink => {}
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// Pin the files of `paths` and below the directories of them, returns
+    /// the files pinned with their sizes.
+    async fn pin(&self, paths: &[String]) -> DatenLordResult<Vec<(INum, u64)>> {
+        let mut pins = Vec::with_capacity(paths.len());
+        for path in paths {
+            pins.push((path.clone(), self.list(slice::from_ref(path)).await?));
+        }
+        let mut seen = HashSet::new();
+        let files = pins
+            .iter()
+            .flat_map(|pin| &pin.1)
+            .filter(|&&(ino, _)| seen.insert(ino))
+            .copied()
+            .collect();
+        PINS.pin(pins)?;
+        Ok(files)
+    }
+
+    /// Load `ino` into the caches as it's read, the loads are reported to
+    /// `progress`.
+    async fn load_file(
+        &self,
+        ino: INum,
+        throttle: &mut Throttle,
+        progress: &watch::Sender<WarmUpProgress>,
+        token: &CancellationToken,
+    ) -> DatenLordResult<()> {
+        if !self.coherence.is_strict() {
+            self.metadata.revalidate_open_file(ino).await?;
+        }
+        let fh = self
+            .metadata
+            .open(ROOT_CONTEXT, ino, OFlag::O_RDONLY.bits().cast())
+            .await?;
+        let result = self.load_opened(ino, throttle, progress, token).await;
+        if self.metadata.release(ino, fh.cast(), 0, 0, false).await? {
+            self.storage.remove(ino).await?;
+        }
+        result
+    }
+
+    /// Load `ino` opened by the caller, a few blocks at a time, each under
+    /// the lease of the file as a read.
+    async fn load_opened(
+        &self,
+        ino: INum,
+        throttle: &mut Throttle,
+        progress: &watch::Sender<WarmUpProgress>,
+        token: &CancellationToken,
+    ) -> DatenLordResult<()> {
+        // The content of a small file loaded with its node is served without
+        // the storage.
+        if self.metadata.inline_data(ino).is_some() {
+            let (file_size, _) = self.metadata.read_helper(ino).await?;
+            progress.send_modify(|progress| {
+                progress.bytes_loaded = progress.bytes_loaded.overflow_add(file_size);
+            });
+            return Ok(());
+        }
+        let chunk: u64 = self.block_size.overflow_mul(LOAD_BLOCKS).cast();
+        let mut offset = 0;
+        while !token.is_cancelled() {
+            let len = {
+                let (_lease, attr) = self.coherence.guard(ino, LeaseMode::Read).await?;
+                if let Some(attr) = attr {
+                    self.metadata.delegate_open_file(attr);
+                }
+                let (file_size, mtime) = self.metadata.read_helper(ino).await?;
+                if offset >= file_size {
+                    return Ok(());
+                }
+                let len = chunk.min(file_size.overflow_sub(offset));
+                self.storage
+                    .load(ino, offset.cast(), len.cast(), mtime)
+                    .await?;
+                len
+            };
+            progress.send_modify(|progress| {
+                progress.bytes_loaded = progress.bytes_loaded.overflow_add(len);
+            });
+            offset = offset.overflow_add(len);
+            throttle.pace(len, token).await;
+        }
+        build_error_result_from_errno(Errno::ECANCELED, "the mount is shutting down".to_owned())
+    }
+
+    /// Run the warm-up of `job`.
+    async fn warm_up(&self, job: &Job, token: &CancellationToken) -> DatenLordResult<()> {
+        job.progress
+            .send_modify(|progress| progress.stage = WarmUpStage::Listing);
+        let files = if job.request.pin {
+            self.pin(&job.request.paths).await?
+        } else {
+            self.list(&job.request.paths).await?
+        };
+        job.progress.send_modify(|progress| {
+            progress.stage = WarmUpStage::Loading;
+            progress.files = files.len();
+            progress.bytes = files.iter().map(|&(_, size)| size).sum();
+        });
+
+        let mut throttle = Throttle::new(job.request.bandwidth);
+        for (ino, _) in files {
+            if let Err(e) = self
+                .load_file(ino, &mut throttle, &job.progress, token)
+                .await
+            {
+                if token.is_cancelled() {
+                    return Err(e);
+                }
+                warn!("Failed to warm up ino={ino}, it's skipped: {e}");
+                job.progress.send_modify(|progress| {
+                    progress.files_failed = progress.files_failed.overflow_add(1);
+                });
+                continue;
+            }
+            job.progress.send_modify(|progress| {
+                progress.files_loaded = progress.files_loaded.overflow_add(1);
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Run the warm-ups requested one at a time, loading the files of `metadata`
+/// into the caches of `storage` of blocks of `block_size`, until `token` is
+/// cancelled. The paths under `mount_point` are resolved below it.
+#[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+pub async fn run_warmer<M: MetaData + Send + Sync + 'static>(
+    metadata: Arc<M>,
+    storage: StorageType,
+    coherence: Arc<Coherence>,
+    block_size: usize,
+    mount_point: String,
+    token: CancellationToken,
+) {
+    let mut jobs = WARMUP.register();
+    let warmer = Warmer {
+        metadata,
+        storage,
+        coherence,
+        block_size,
+        mount_point,
+    };
+    loop {
+        let job = select! {
+            job = jobs.recv() => match job {
+                Some(job) => job,
+                None => return,
+            },
+            () = token.cancelled() => return,
+        };
+        info!("Warming up {:?}.", job.request.paths);
+        match warmer.warm_up(&job, &token).await {
+            Ok(()) => {
+                job.progress
+                    .send_modify(|progress| progress.stage = WarmUpStage::Done);
+                let progress = job.progress.borrow();
+                info!(
+                    "Warmed up {:?}, {} files of {} bytes loaded, {} files skipped.",
+                    job.request.paths,
+                    progress.files_loaded,
+                    progress.bytes_loaded,
+                    progress.files_failed
+                );
+            }
+            Err(e) => {
+                warn!("Failed to warm up {:?}: {e}", job.request.paths);
+                job.progress
+                    .send_modify(|progress| progress.stage = WarmUpStage::Failed(e.to_string()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU64;
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+    use tokio_util::sync::CancellationToken;
+
+    use super::{components, Throttle};
+
+    #[test]
+    fn test_components() {
+        assert_eq!(components("data/train", "/mnt").unwrap(), ["data", "train"]);
+        assert_eq!(
+            components("/data/./train/", "/mnt").unwrap(),
+            ["data", "train"]
+        );
+        assert_eq!(
+            components("/mnt/data/train", "/mnt/").unwrap(),
+            ["data", "train"]
+        );
+        assert!(components("/mnt", "/mnt").unwrap().is_empty());
+        // Not under the mount point, only sharing its prefix.
+        assert_eq!(components("/mnt2/data", "/mnt").unwrap(), ["mnt2", "data"]);
+        assert!(components("/mnt/data/../..", "/mnt").is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_throttle() {
+        let token = CancellationToken::new();
+        let start = Instant::now();
+        let mut throttle = Throttle::new(None);
+        throttle.pace(1 << 30, &token).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // 4 MiB at 1 MiB per second.
+        let mut throttle = Throttle::new(NonZeroU64::new(1 << 20));
+        for _ in 0..4_i32 {
+            throttle.pace(1 << 20, &token).await;
+        }
+        assert_eq!(start.elapsed(), Duration::from_secs(4));
+
+        // The pacing is cut short once the token is cancelled.
+        let start = Instant::now();
+        token.cancel();
+        throttle.pace(1 << 30, &token).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+}
@@ -1,32 +1,270 @@
 //! FUSE async implementation
 
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 use std::sync::Arc;
 
-use clippy_utilities::OverflowArithmetic;
+use anyhow::anyhow;
+use clippy_utilities::{Cast, OverflowArithmetic};
+use datenlord::common::memory_budget::MemoryBudget;
+use datenlord::common::task_manager::{TaskName, TASK_MANAGER};
+use datenlord::config::{DiskCacheConfig, ErasureShards, OpClass, StorageConfig, StoragePolicy};
 use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
 
+use self::memfs::auth::{AuthKey, AuthPolicy};
+use self::memfs::chunk_index::VolumeInfo;
+use self::memfs::kv_engine::local_impl::LocalKVEngine;
 use self::memfs::kv_engine::KVEngineType;
+use self::memfs::snapshot::{self, SnapshotInfo};
+use self::memfs::{
+    BackendStorageType, KvChecksumIndex, KvDedupIndex, KvFileVersions, KvGcIndex, KvLocalityIndex,
+    KvMembership, KvPackIndex, KvReplicaIndex, KvShardIndex, KvSnapshotIndex, KvTierIndex,
+    HEALTH_CHECK_INTERVAL,
+};
+use crate::async_fuse::fuse::request_log::REQUEST_LOG;
 use crate::async_fuse::fuse::session;
-use crate::storage::policy::LruPolicy;
-use crate::storage::{BackendBuilder, BlockCoordinate, MemoryCacheBuilder, StorageManager};
+#[cfg(target_os = "linux")]
+use crate::async_fuse::fuse::virtiofs;
+use crate::common::error::DatenLordError;
+use crate::health::{CACHE_COMPONENT, HEALTH, MOUNT_COMPONENT};
+use crate::reload::RELOAD;
+use crate::storage::block_store::{BreakerBlockStore, LocalBlockStore};
+use crate::storage::checksum::ReplicaRepair;
+use crate::storage::condition::{self, BACKEND_PROBE_INTERVAL};
+use crate::storage::encryption::{BlockCipher, MasterKey};
+use crate::storage::erasure::ErasureBlockStore;
+use crate::storage::gc::GarbageCollector;
+use crate::storage::locality::{LocalCache, PeerCacheStorage};
+use crate::storage::policy::new_policy;
+use crate::storage::replication::{ReplicaStore, ReplicatedBlockStore};
+use crate::storage::tiering::{TierPolicy, TieredBlockStore};
+use crate::storage::transfer::{run_transfer_server, RemoteBlockStore, TransferService};
+use crate::storage::{
+    BackendBuilder, BlockCoordinate, BlockStoreBackend, ChecksumStorage, DedupStorage, DiskCache,
+    MemoryCacheBuilder, PackStorage, SnapshotStorage, StorageManager,
+};
+use crate::tls::Tls;
 use crate::AsyncFuseArgs;
 
 pub mod fuse;
 pub mod memfs;
+#[cfg(target_os = "linux")]
 pub mod proactor;
 pub mod util;
 
-/// Start async-fuse
-#[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select`
+/// Start async-fuse, the volume is mounted by FUSE, or served to a VM by
+/// virtio-fs if `args.virtiofs_socket` is set.
 pub async fn start_async_fuse(
     kv_engine: Arc<KVEngineType>,
     args: AsyncFuseArgs,
     token: CancellationToken,
 ) -> anyhow::Result<()> {
-    let storage_config = &args.storage_config;
+    REQUEST_LOG.set_volume(&args.mount_dir);
+    let (fs, snapshot) = open_memfs(kv_engine, &args).await?;
+    HEALTH.report(CACHE_COMPONENT, None);
+    if let Some(ref socket) = args.virtiofs_socket {
+        HEALTH.report(MOUNT_COMPONENT, None);
+        #[cfg(target_os = "linux")]
+        virtiofs::run_virtiofs(socket, Arc::new(fs), token).await?;
+        #[cfg(not(target_os = "linux"))]
+        anyhow::bail!("virtio-fs is only supported on Linux, socket={socket:?}");
+    } else {
+        let mount_point = std::path::Path::new(&args.mount_dir);
+        let ss = session::new_session_of_memfs(
+            mount_point,
+            fs,
+            snapshot.is_some() || args.op_mask.denies(OpClass::Write),
+            args.selinux_context.as_deref(),
+            args.storage_config.writeback_cache,
+        )
+        .await?;
+        HEALTH.report(MOUNT_COMPONENT, None);
+        ss.run(token).await?;
+    }
+
+    if let Some(ref info) = snapshot {
+        close_snapshot(info).await;
+    }
+
+    Ok(())
+}
+
+/// Open the file system of the volume, or of its snapshot if
+/// `args.snapshot` is set, whose info is returned with it. The metadata of
+/// the snapshot is removed by `close_snapshot()`.
+#[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select`
+pub async fn open_memfs(
+    kv_engine: Arc<KVEngineType>,
+    args: &AsyncFuseArgs,
+) -> anyhow::Result<(memfs::MemFs<memfs::S3MetaData>, Option<SnapshotInfo>)> {
+    // The chunk size, compression, dedup, packing, checksum, encryption,
+    // tiering, replication and erasure coding of a volume are persisted in
+    // the metadata, they override the configured ones.
+    let erasure_config = args
+        .storage_config
+        .replication_config
+        .as_ref()
+        .filter(|config| config.policy != StoragePolicy::Replicate);
+    let volume_info = memfs::load_or_init_volume_info(
+        &kv_engine,
+        VolumeInfo {
+            chunk_size: args.storage_config.block_size.cast(),
+            compression: args.storage_config.compression,
+            dedup: args.storage_config.dedup_config.is_some(),
+            packing: args.storage_config.pack_config.is_some(),
+            checksum: args.storage_config.checksum_config.is_some(),
+            encryption: args.storage_config.encryption_config.is_some(),
+            snapshot: args.storage_config.snapshot,
+            tiering: args.storage_config.tiering_config.is_some(),
+            replication: args
+                .storage_config
+                .replication_config
+                .as_ref()
+                .map_or(0, |config| config.factor.cast()),
+            storage_policy: erasure_config.map_or(StoragePolicy::Replicate, |config| config.policy),
+            erasure_shards: erasure_config.map_or(ErasureShards::default(), |config| config.shards),
+            consistency: args.storage_config.consistency,
+        },
+        VolumeInfo::legacy(args.storage_config.legacy_block_size.cast()),
+    )
+    .await?;
+    let snapshot = match args.snapshot {
+        Some(ref name) => Some(open_snapshot(&kv_engine, name).await?),
+        None => None,
+    };
+    // A snapshot is read-only, and so is a mount with the writes turned off.
+    let read_only = snapshot.is_some() || args.op_mask.denies(OpClass::Write);
+    let mut storage_config = args.storage_config.clone();
+    storage_config.block_size = volume_info.chunk_size.cast();
+    // The disk cache holds the chunks of the volume, whose size may differ
+    // from the configured block size.
+    if let Some(ref disk_cache_config) = storage_config.disk_cache_config {
+        if disk_cache_config.capacity < storage_config.block_size {
+            return Err(anyhow!(
+                "the capacity of disk cache {} is less than the chunk size of the volume {}",
+                disk_cache_config.capacity,
+                storage_config.block_size
+            ));
+        }
+    }
+    storage_config.compression = volume_info.compression;
+    storage_config.consistency = volume_info.consistency;
+    let dedup_config = storage_config.dedup_config.unwrap_or_default();
+    storage_config.dedup_config = volume_info.dedup.then_some(dedup_config);
+    let pack_config = storage_config.pack_config.unwrap_or_default();
+    storage_config.pack_config = volume_info.packing.then_some(pack_config);
+    let checksum_config = storage_config.checksum_config.unwrap_or_default();
+    // The checksums are of the live blocks, they cannot verify the blocks of
+    // a snapshot.
+    storage_config.checksum_config =
+        (volume_info.checksum && snapshot.is_none()).then_some(checksum_config);
+    // Nothing is moved into the trash of a read-only mount, and the trash in
+    // it is kept as it is.
+    storage_config.trash_retention = storage_config.trash_retention.filter(|_| !read_only);
+    // The objects are collected by the nodes mounting the live file system
+    // read-write.
+    storage_config.gc_config = storage_config.gc_config.filter(|_| !read_only);
+    // The caches of a snapshot hold the blocks of the snapshot rather than the
+    // live ones, so they're not shared with the other nodes.
+    storage_config.peer_cache_config = storage_config
+        .peer_cache_config
+        .filter(|_| snapshot.is_none());
+    if volume_info.tiering && storage_config.tiering_config.is_none() {
+        return Err(anyhow!("the volume is tiered, but no hot tier is provided"));
+    }
+    storage_config.tiering_config = storage_config
+        .tiering_config
+        .filter(|_| volume_info.tiering);
+    if volume_info.replication > 0 {
+        // The replicas are stored as they are, without the compression and
+        // the cipher of the backend.
+        if volume_info.encryption {
+            return Err(anyhow!("replication cannot be enabled with encryption"));
+        }
+        let replication_config = storage_config.replication_config.as_mut().ok_or_else(|| {
+            anyhow!("the volume is replicated, but no replica nodes are provided")
+        })?;
+        replication_config.factor = volume_info.replication.cast();
+        if replication_config.nodes.len() < replication_config.factor {
+            return Err(anyhow!(
+                "the volume has {} replicas, but only {} replica nodes are provided",
+                replication_config.factor,
+                replication_config.nodes.len()
+            ));
+        }
+        replication_config.policy = volume_info.storage_policy;
+        replication_config.shards = volume_info.erasure_shards;
+        if replication_config.policy != StoragePolicy::Replicate
+            && replication_config.nodes.len() < replication_config.shards.total()
+        {
+            return Err(anyhow!(
+                "the volume has {} shards of blocks, but only {} replica nodes are provided",
+                replication_config.shards.total(),
+                replication_config.nodes.len()
+            ));
+        }
+    } else {
+        storage_config.replication_config = None;
+    }
+    let storage_config = &storage_config;
+
+    let cipher = if volume_info.encryption {
+        let encryption_config = storage_config
+            .encryption_config
+            .as_ref()
+            .ok_or_else(|| anyhow!("the volume is encrypted, but no master key is provided"))?;
+        let master_key = MasterKey::from_file(&encryption_config.master_key_file).await?;
+        let old_master_key = match encryption_config.old_master_key_file {
+            Some(ref path) => Some(MasterKey::from_file(path).await?),
+            None => None,
+        };
+        let data_keys = memfs::load_data_keys(
+            &kv_engine,
+            &master_key,
+            old_master_key.as_ref(),
+            encryption_config.rotate_data_key,
+        )
+        .await?;
+        Some(Arc::new(BlockCipher::new(&data_keys)?))
+    } else {
+        None
+    };
+
+    // The blocks served to the other nodes, which are the replicas of this
+    // node as well, and the caches of this node if they're shared.
+    let local_cache = Arc::new(LocalCache::new());
+    let mut transfer_store = None;
+    let mut transfer_tls = None;
+    if let Some(ref transfer_config) = storage_config.transfer_config {
+        let store = Arc::new(LocalBlockStore::new(&transfer_config.dir).await?);
+        let tls = match transfer_config.tls {
+            Some(ref tls) => Some(Tls::load(tls).await?),
+            None => None,
+        };
+        let mut service =
+            TransferService::new(Arc::clone(&store), tls.clone(), transfer_config.timeout);
+        if storage_config.peer_cache_config.is_some() {
+            service = service.with_cache(Arc::clone(&local_cache));
+        }
+        let listen = transfer_config.listen;
+        let server_tls = tls.clone();
+        TASK_MANAGER
+            .spawn(TaskName::Rpc, |token| {
+                run_transfer_server(listen, service, server_tls, token)
+            })
+            .await?;
+        transfer_store = Some(store);
+        transfer_tls = tls;
+    }
 
-    let mount_point = std::path::Path::new(&args.mount_dir);
-    let global_cache_capacity = args.storage_config.memory_cache_config.capacity;
+    let global_cache_capacity = storage_config.memory_cache_config.capacity;
+    let memory_budget = storage_config
+        .memory_budget
+        .map(|limit| Arc::new(MemoryBudget::new(limit)));
+    if let Some(ref budget) = memory_budget {
+        RELOAD.register_memory_budget(budget);
+    }
     let storage = {
         let storage_param = &storage_config.params;
         let memory_cache_config = &storage_config.memory_cache_config;
@@ -34,39 +272,373 @@ pub async fn start_async_fuse(
         let block_size = storage_config.block_size;
         let capacity_in_blocks = memory_cache_config.capacity.overflow_div(block_size);
 
-        let backend = BackendBuilder::new(storage_param.clone(), block_size).build()?;
-        let lru_policy = LruPolicy::<BlockCoordinate>::new(capacity_in_blocks);
-        let memory_cache = MemoryCacheBuilder::new(lru_policy, backend, block_size)
+        let backend = BackendBuilder::new(storage_param.clone(), block_size)
+            .compression(storage_config.compression)
+            .cipher(cipher)
+            .error_config(storage_config.backend_error_config.clone())
+            .build()?;
+        let probe = backend.clone();
+        TASK_MANAGER
+            .spawn(TaskName::BackendProbe, |token| {
+                condition::run_backend_probe(probe, BACKEND_PROBE_INTERVAL, token)
+            })
+            .await?;
+        if let Some(gc_config) = storage_config.gc_config {
+            let collector = Arc::new(
+                GarbageCollector::new(
+                    KvGcIndex::new(Arc::clone(&kv_engine), volume_info.snapshot),
+                    backend.clone(),
+                )
+                .with_rate_limit(gc_config.rate)
+                .with_dry_run(gc_config.dry_run),
+            );
+            TASK_MANAGER
+                .spawn(TaskName::BlockGc, |token| {
+                    collector.run_collector(gc_config.interval, token)
+                })
+                .await?;
+        }
+        // The replicas the scrubber repairs the corrupted blocks from.
+        let mut replica_repair: Option<Arc<dyn ReplicaRepair + Send + Sync>> = None;
+        let backend: BackendStorageType = if let Some((ref info, _)) = snapshot {
+            Arc::new(
+                SnapshotStorage::new(
+                    KvSnapshotIndex::new(Arc::clone(&kv_engine)),
+                    backend,
+                    block_size,
+                )
+                .with_snapshot(info.id),
+            )
+        } else if volume_info.snapshot {
+            Arc::new(SnapshotStorage::new(
+                KvSnapshotIndex::new(Arc::clone(&kv_engine)),
+                backend,
+                block_size,
+            ))
+        } else if let Some(dedup_config) = storage_config.dedup_config {
+            Arc::new(DedupStorage::new(
+                KvDedupIndex::new(Arc::clone(&kv_engine)),
+                backend,
+                block_size,
+                dedup_config.avg_chunk_size,
+            ))
+        } else if let Some(pack_config) = storage_config.pack_config {
+            Arc::new(PackStorage::new(
+                KvPackIndex::new(Arc::clone(&kv_engine)),
+                backend.clone(),
+                backend,
+                block_size,
+                pack_config.inline_threshold,
+                pack_config.pack_threshold,
+            ))
+        } else if let Some(ref tiering_config) = storage_config.tiering_config {
+            let store = Arc::new(TieredBlockStore::new(
+                KvTierIndex::new(Arc::clone(&kv_engine), tiering_config.rules.clone()),
+                LocalBlockStore::new(&tiering_config.hot_dir).await?,
+                backend,
+                TierPolicy {
+                    cold_after: tiering_config.cold_after,
+                    promote_accesses: tiering_config.promote_accesses,
+                },
+            ));
+            let migrator = Arc::clone(&store);
+            let interval = tiering_config.interval;
+            TASK_MANAGER
+                .spawn(TaskName::TierMigration, |token| {
+                    migrator.run_migrator(interval, token)
+                })
+                .await?;
+            Arc::new(BlockStoreBackend::new(store, block_size))
+        } else if let Some(ref replication_config) = storage_config.replication_config {
+            let transfer_config = storage_config
+                .transfer_config
+                .as_ref()
+                .ok_or_else(|| anyhow!("replication requires the transfer service"))?;
+            let mut replicas: BTreeMap<String, ReplicaStore> = BTreeMap::new();
+            for node in &replication_config.nodes {
+                let replica: ReplicaStore = match transfer_store {
+                    Some(ref store) if node.node_id == args.node_id => Arc::clone(store) as _,
+                    _ => {
+                        let remote = RemoteBlockStore::connect(
+                            &node.endpoint,
+                            transfer_tls.clone(),
+                            transfer_config.timeout,
+                        )?;
+                        match replication_config.breaker {
+                            Some(policy) => {
+                                Arc::new(BreakerBlockStore::new(remote, &node.node_id, policy))
+                            }
+                            None => Arc::new(remote),
+                        }
+                    }
+                };
+                replicas.insert(node.node_id.clone(), replica);
+            }
+            if replication_config.policy == StoragePolicy::Erasure {
+                let store = ErasureBlockStore::new(
+                    KvShardIndex::new(Arc::clone(&kv_engine)),
+                    replicas,
+                    replication_config.shards,
+                )?;
+                Arc::new(BlockStoreBackend::new(store, block_size))
+            } else {
+                let store = Arc::new(
+                    ReplicatedBlockStore::new(
+                        KvReplicaIndex::new(Arc::clone(&kv_engine)),
+                        &args.node_id,
+                        replicas.clone(),
+                        replication_config.factor,
+                        replication_config.quorum,
+                        replication_config.failure_timeout,
+                    )
+                    .with_rebalance_rate(replication_config.rebalance_rate)
+                    .with_hedge_percentile(replication_config.hedge_percentile),
+                );
+                let checker = Arc::clone(&store);
+                let membership = KvMembership::new(Arc::clone(&kv_engine));
+                TASK_MANAGER
+                    .spawn(TaskName::ReplicaHealth, |token| {
+                        checker.run_health_checker(membership, HEALTH_CHECK_INTERVAL, token)
+                    })
+                    .await?;
+                replica_repair = Some(Arc::clone(&store) as _);
+                if replication_config.policy == StoragePolicy::Tiered {
+                    // The replicas are the hot tier and the shards are the
+                    // cold tier, blocks are pinned to tiers by the extended
+                    // attribute only.
+                    let tiered = Arc::new(TieredBlockStore::new(
+                        KvTierIndex::new(Arc::clone(&kv_engine), vec![]),
+                        store,
+                        ErasureBlockStore::new(
+                            KvShardIndex::new(Arc::clone(&kv_engine)),
+                            replicas,
+                            replication_config.shards,
+                        )?,
+                        TierPolicy {
+                            cold_after: replication_config.ec_cold_after,
+                            promote_accesses: replication_config.ec_promote_accesses,
+                        },
+                    ));
+                    let migrator = Arc::clone(&tiered);
+                    let interval = replication_config.ec_interval;
+                    TASK_MANAGER
+                        .spawn(TaskName::TierMigration, |token| {
+                            migrator.run_migrator(interval, token)
+                        })
+                        .await?;
+                    Arc::new(BlockStoreBackend::new(tiered, block_size))
+                } else {
+                    Arc::new(BlockStoreBackend::new(store, block_size))
+                }
+            }
+        } else {
+            Arc::new(backend)
+        };
+        let backend: BackendStorageType =
+            if let Some(checksum_config) = storage_config.checksum_config {
+                let mut checksum_storage = ChecksumStorage::new(
+                    KvChecksumIndex::new(Arc::clone(&kv_engine)),
+                    backend,
+                    block_size,
+                );
+                if let Some(repair) = replica_repair {
+                    checksum_storage = checksum_storage.with_repair(repair);
+                }
+                let checksum_storage = Arc::new(checksum_storage);
+                if let Some(interval) = checksum_config.scrub_interval {
+                    let scrubber = Arc::clone(&checksum_storage);
+                    TASK_MANAGER
+                        .spawn(TaskName::Scrub, |token| {
+                            scrubber.run_scrubber(interval, token)
+                        })
+                        .await?;
+                }
+                checksum_storage
+            } else {
+                backend
+            };
+        let backend: BackendStorageType =
+            if let Some(ref peer_cache_config) = storage_config.peer_cache_config {
+                Arc::new(PeerCacheStorage::new(
+                    KvLocalityIndex::new(Arc::clone(&kv_engine)),
+                    backend,
+                    &args.node_id,
+                    peer_cache_config.zone.clone(),
+                    block_size,
+                    transfer_tls.clone(),
+                    peer_cache_config.timeout,
+                ))
+            } else {
+                backend
+            };
+        let backend = if let Some(ref disk_cache_config) = storage_config.disk_cache_config {
+            open_disk_cache(disk_cache_config, backend, block_size, &kv_engine).await?
+        } else {
+            backend
+        };
+        let policy = new_policy::<BlockCoordinate>(memory_cache_config.policy, capacity_in_blocks);
+        let mut memory_cache_builder = MemoryCacheBuilder::new(policy, backend, block_size)
             .command_queue_limit(memory_cache_config.command_queue_limit)
             .limit(memory_cache_config.soft_limit)
-            .write_through(!memory_cache_config.write_back)
-            .build()
-            .await;
+            .dirty_high_watermark(memory_cache_config.dirty_high_watermark)
+            .upload_concurrency(memory_cache_config.upload_concurrency)
+            .write_through(!memory_cache_config.write_back);
+        if let Some(ref budget) = memory_budget {
+            memory_cache_builder = memory_cache_builder.memory_budget(Arc::clone(budget));
+        }
+        let memory_cache = memory_cache_builder.build().await;
         StorageManager::new(memory_cache, block_size)
+            .with_read_ahead(storage_config.read_ahead_window)
+            .with_read_only(read_only)
     };
 
     let storage = Arc::new(storage);
+    local_cache.register(storage.storage());
 
+    // A snapshot is mounted with its metadata restored in a private kv engine.
+    let fs_kv_engine = match snapshot {
+        Some((_, ref snapshot_kv_engine)) => Arc::clone(snapshot_kv_engine),
+        None => kv_engine,
+    };
     let fs: memfs::MemFs<memfs::S3MetaData> = memfs::MemFs::new(
         &args.mount_dir,
         global_cache_capacity,
-        kv_engine,
+        fs_kv_engine,
         &args.node_id,
         storage_config,
         storage,
     )
     .await?;
+    let fs = match memory_budget {
+        Some(budget) => fs.with_memory_budget(budget).await?,
+        None => fs,
+    };
+    let fs = fs
+        .with_id_map(args.id_map.clone())
+        .with_squash(args.squash)
+        .with_op_mask(args.op_mask);
+    let auth = &args.auth;
+    let fs = if let (Some(key_file), Some(policy_file), Some(token)) = (
+        auth.key_file.as_deref(),
+        auth.policy_file.as_deref(),
+        auth.token.as_deref(),
+    ) {
+        let key = AuthKey::load(key_file).await?;
+        let tenant = key
+            .verify_token(token)
+            .ok_or_else(|| anyhow!("the tenant token is invalid"))?;
+        let policy = AuthPolicy::load(policy_file).await?;
+        let rules = policy
+            .tenants
+            .get(tenant)
+            .ok_or_else(|| anyhow!("tenant {tenant} is not in the auth policy"))?;
+        info!("The mount serves tenant {tenant}.");
+        fs.with_tenant(tenant, rules).await?
+    } else {
+        fs
+    };
+    Ok((fs, snapshot.map(|(info, _)| info)))
+}
+
+/// Remove the metadata of snapshot `info` opened by `open_memfs()`.
+pub async fn close_snapshot(info: &SnapshotInfo) {
+    let dir = snapshot_metadata_dir(info);
+    if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+        warn!("failed to remove the metadata of the snapshot in {dir:?}: {e}");
+    }
+}
 
-    let ss = session::new_session_of_memfs(mount_point, fs).await?;
-    ss.run(token).await?;
+/// The directory of the private kv engine, where the metadata of a mounted
+/// snapshot is restored.
+fn snapshot_metadata_dir(info: &SnapshotInfo) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "datenlord_snapshot_{}_{}",
+        info.id,
+        std::process::id()
+    ))
+}
 
-    Ok(())
+/// Open the disk cache upon `backend`. If its index is persisted, the blocks
+/// of the files unchanged are recovered, and the index is persisted again on
+/// shutdown.
+async fn open_disk_cache(
+    config: &DiskCacheConfig,
+    backend: BackendStorageType,
+    block_size: usize,
+    kv_engine: &Arc<KVEngineType>,
+) -> anyhow::Result<BackendStorageType> {
+    let capacity_in_blocks = config.capacity.overflow_div(block_size);
+    let policy = new_policy::<BlockCoordinate>(config.policy, capacity_in_blocks);
+    if !config.persist {
+        return Ok(Arc::new(
+            DiskCache::new(&config.dir, policy, backend, block_size).await?,
+        ));
+    }
+
+    let versions = KvFileVersions::new(Arc::clone(kv_engine));
+    let disk_cache =
+        Arc::new(DiskCache::recover(&config.dir, policy, backend, block_size, &versions).await?);
+    let persisted = Arc::clone(&disk_cache);
+    TASK_MANAGER
+        .spawn(TaskName::DiskCache, |token| async move {
+            token.cancelled().await;
+            if let Err(e) = persisted.persist(&versions).await {
+                warn!("Failed to persist the disk cache: {e}");
+            }
+        })
+        .await?;
+    Ok(disk_cache)
+}
+
+/// Open the snapshot named `name` to mount, its metadata is restored in a
+/// private kv engine.
+async fn open_snapshot(
+    kv_engine: &Arc<KVEngineType>,
+    name: &str,
+) -> anyhow::Result<(SnapshotInfo, Arc<KVEngineType>)> {
+    let info = snapshot::get_snapshot(kv_engine, name)
+        .await?
+        .ok_or_else(|| DatenLordError::SnapshotNotFound {
+            snapshot_id: name.to_owned(),
+            context: vec![],
+        })?;
+
+    let dir = snapshot_metadata_dir(&info);
+    // Remove the metadata left by a crashed process with the same pid.
+    if tokio::fs::try_exists(&dir).await? {
+        tokio::fs::remove_dir_all(&dir).await?;
+    }
+    let snapshot_kv_engine = Arc::new(KVEngineType::Local(LocalKVEngine::open(&dir)?));
+    snapshot::restore_snapshot(kv_engine, &info, &snapshot_kv_engine).await?;
+    Ok((info, snapshot_kv_engine))
+}
+
+/// Delete the versions of blocks seen by neither the live volume nor any
+/// snapshot, it's run after a snapshot is deleted.
+///
+/// Returns the number of deleted versions.
+pub async fn collect_snapshot_garbage(
+    kv_engine: Arc<KVEngineType>,
+    storage_config: &StorageConfig,
+) -> anyhow::Result<usize> {
+    let block_size = storage_config.block_size;
+    // No block is read by collecting, so the backend is built without the
+    // compression and the cipher of the volume.
+    let backend = BackendBuilder::new(storage_config.params.clone(), block_size)
+        .error_config(storage_config.backend_error_config.clone())
+        .build()?;
+    let storage = SnapshotStorage::new(KvSnapshotIndex::new(kv_engine), backend, block_size);
+    Ok(storage.collect_garbage().await?)
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
+    mod conformance_tests;
+    mod fusedump_tests;
     mod integration_tests;
-    mod test_util;
+    mod mock_kernel_tests;
+    pub(crate) mod test_util;
+    mod vfs_tests;
 
     use std::{fs, io};
 
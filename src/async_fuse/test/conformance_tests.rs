@@ -0,0 +1,586 @@
+//! A subset of the POSIX conformance cases of pjdfstest, expressed in Rust and
+//! run on a mounted `MemFs`.
+//!
+//! Every case runs in a directory of its own, and all the cases run however
+//! many fail, so one run reports every failure. A failure is reported with the
+//! FUSE opcode whose handler is responsible for it, and the file of pjdfstest
+//! the case comes from.
+
+use std::fmt::Debug;
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::{symlink, DirBuilderExt, FileExt, MetadataExt, OpenOptionsExt};
+use std::path::Path;
+
+use anyhow::{bail, ensure};
+use nix::sys::stat::{self, Mode};
+use nix::sys::time::TimeVal;
+use nix::unistd;
+use tracing::info;
+
+use super::test_util;
+use crate::async_fuse::fuse::protocol::FuseOpCode;
+
+/// Where the file system of the conformance cases is mounted.
+const CONFORMANCE_MOUNT_DIR: &str = "/tmp/datenlord_conformance_dir";
+
+/// The errno of unlinking a directory.
+#[cfg(target_os = "linux")]
+const UNLINK_DIR_ERRNO: i32 = libc::EISDIR;
+/// The errno of unlinking a directory.
+#[cfg(not(target_os = "linux"))]
+const UNLINK_DIR_ERRNO: i32 = libc::EPERM;
+
+/// A conformance case.
+struct Case {
+    /// The name of the case
+    name: &'static str,
+    /// The file of pjdfstest the case comes from
+    source: &'static str,
+    /// The opcode whose handler is responsible for the case
+    opcode: FuseOpCode,
+    /// Run the case in the directory given
+    run: fn(&Path) -> anyhow::Result<()>,
+}
+
+/// The conformance cases.
+const CASES: &[Case] = &[
+    Case {
+        name: "mkdir_mode_and_nlink",
+        source: "mkdir/00.t",
+        opcode: FuseOpCode::FUSE_MKDIR,
+        run: mkdir_mode_and_nlink,
+    },
+    Case {
+        name: "mkdir_exists",
+        source: "mkdir/10.t",
+        opcode: FuseOpCode::FUSE_MKDIR,
+        run: mkdir_exists,
+    },
+    Case {
+        name: "mkdir_name_too_long",
+        source: "mkdir/02.t",
+        opcode: FuseOpCode::FUSE_MKDIR,
+        run: mkdir_name_too_long,
+    },
+    Case {
+        name: "mkdir_no_parent",
+        source: "mkdir/04.t",
+        opcode: FuseOpCode::FUSE_LOOKUP,
+        run: mkdir_no_parent,
+    },
+    Case {
+        name: "rmdir_nlink",
+        source: "rmdir/00.t",
+        opcode: FuseOpCode::FUSE_RMDIR,
+        run: rmdir_nlink,
+    },
+    Case {
+        name: "rmdir_not_empty",
+        source: "rmdir/06.t",
+        opcode: FuseOpCode::FUSE_RMDIR,
+        run: rmdir_not_empty,
+    },
+    Case {
+        name: "rmdir_not_dir",
+        source: "rmdir/01.t",
+        opcode: FuseOpCode::FUSE_RMDIR,
+        run: rmdir_not_dir,
+    },
+    Case {
+        name: "unlink_nlink",
+        source: "unlink/00.t",
+        opcode: FuseOpCode::FUSE_UNLINK,
+        run: unlink_nlink,
+    },
+    Case {
+        name: "unlink_dir",
+        source: "unlink/11.t",
+        opcode: FuseOpCode::FUSE_UNLINK,
+        run: unlink_dir,
+    },
+    Case {
+        name: "create_mode_and_owner",
+        source: "open/00.t",
+        opcode: FuseOpCode::FUSE_CREATE,
+        run: create_mode_and_owner,
+    },
+    Case {
+        name: "create_exclusive",
+        source: "open/22.t",
+        opcode: FuseOpCode::FUSE_CREATE,
+        run: create_exclusive,
+    },
+    Case {
+        name: "open_missing",
+        source: "open/04.t",
+        opcode: FuseOpCode::FUSE_LOOKUP,
+        run: open_missing,
+    },
+    Case {
+        name: "truncate_size",
+        source: "truncate/00.t",
+        opcode: FuseOpCode::FUSE_SETATTR,
+        run: truncate_size,
+    },
+    Case {
+        name: "write_hole",
+        source: "ftruncate/00.t",
+        opcode: FuseOpCode::FUSE_WRITE,
+        run: write_hole,
+    },
+    Case {
+        name: "chmod_mode",
+        source: "chmod/00.t",
+        opcode: FuseOpCode::FUSE_SETATTR,
+        run: chmod_mode,
+    },
+    Case {
+        name: "utimes_times",
+        source: "utimensat/00.t",
+        opcode: FuseOpCode::FUSE_SETATTR,
+        run: utimes_times,
+    },
+    Case {
+        name: "link_nlink",
+        source: "link/00.t",
+        opcode: FuseOpCode::FUSE_LINK,
+        run: link_nlink,
+    },
+    Case {
+        name: "link_exists",
+        source: "link/10.t",
+        opcode: FuseOpCode::FUSE_LINK,
+        run: link_exists,
+    },
+    Case {
+        name: "symlink_type",
+        source: "symlink/00.t",
+        opcode: FuseOpCode::FUSE_SYMLINK,
+        run: symlink_type,
+    },
+    Case {
+        name: "readlink_target",
+        source: "symlink/00.t",
+        opcode: FuseOpCode::FUSE_READLINK,
+        run: readlink_target,
+    },
+    Case {
+        name: "rename_file",
+        source: "rename/00.t",
+        opcode: FuseOpCode::FUSE_RENAME,
+        run: rename_file,
+    },
+    Case {
+        name: "rename_dir_nlink",
+        source: "rename/00.t",
+        opcode: FuseOpCode::FUSE_RENAME,
+        run: rename_dir_nlink,
+    },
+    Case {
+        name: "rename_file_over_dir",
+        source: "rename/14.t",
+        opcode: FuseOpCode::FUSE_RENAME,
+        run: rename_file_over_dir,
+    },
+    Case {
+        name: "rename_dir_over_file",
+        source: "rename/13.t",
+        opcode: FuseOpCode::FUSE_RENAME,
+        run: rename_dir_over_file,
+    },
+    Case {
+        name: "rename_dir_over_non_empty",
+        source: "rename/20.t",
+        opcode: FuseOpCode::FUSE_RENAME,
+        run: rename_dir_over_non_empty,
+    },
+    Case {
+        name: "readdir_entries",
+        source: "readdir",
+        opcode: FuseOpCode::FUSE_READDIR,
+        run: readdir_entries,
+    },
+    Case {
+        name: "mkfifo_type",
+        source: "mkfifo/00.t",
+        opcode: FuseOpCode::FUSE_MKNOD,
+        run: mkfifo_type,
+    },
+];
+
+/// Get the umask of the process.
+fn umask() -> u32 {
+    let mask = stat::umask(Mode::empty());
+    stat::umask(mask);
+    mask.bits().into()
+}
+
+/// Check that `result` fails with one of `errnos`.
+fn expect_errno<T: Debug>(result: io::Result<T>, errnos: &[i32]) -> anyhow::Result<()> {
+    match result {
+        Ok(value) => bail!("succeeded with {value:?} instead of failing with {errnos:?}"),
+        Err(ref e)
+            if e.raw_os_error()
+                .is_some_and(|errno| errnos.contains(&errno)) =>
+        {
+            Ok(())
+        }
+        Err(e) => bail!("failed with {e} instead of {errnos:?}"),
+    }
+}
+
+/// Check that `path` has `nlink` links.
+fn expect_nlink(path: &Path, nlink: u64) -> anyhow::Result<()> {
+    let actual = fs::symlink_metadata(path)?.nlink();
+    ensure!(
+        actual == nlink,
+        "{path:?} has {actual} links instead of {nlink}"
+    );
+    Ok(())
+}
+
+fn mkdir_mode_and_nlink(dir: &Path) -> anyhow::Result<()> {
+    let path = dir.join("dir");
+    DirBuilder::new().mode(0o755).create(&path)?;
+    let metadata = fs::symlink_metadata(&path)?;
+    ensure!(metadata.is_dir(), "{path:?} is not a directory");
+    let mode = 0o755 & !umask();
+    ensure!(
+        metadata.mode() & 0o7777 == mode,
+        "the mode is {:o} instead of {mode:o}",
+        metadata.mode() & 0o7777
+    );
+    expect_nlink(&path, 2)?;
+    expect_nlink(dir, 3)
+}
+
+fn mkdir_exists(dir: &Path) -> anyhow::Result<()> {
+    File::create(dir.join("file"))?;
+    fs::create_dir(dir.join("dir"))?;
+    symlink("file", dir.join("link"))?;
+    for name in ["file", "dir", "link"] {
+        expect_errno(fs::create_dir(dir.join(name)), &[libc::EEXIST])?;
+    }
+    Ok(())
+}
+
+fn mkdir_name_too_long(dir: &Path) -> anyhow::Result<()> {
+    fs::create_dir(dir.join("a".repeat(255)))?;
+    expect_errno(
+        fs::create_dir(dir.join("a".repeat(256))),
+        &[libc::ENAMETOOLONG],
+    )
+}
+
+fn mkdir_no_parent(dir: &Path) -> anyhow::Result<()> {
+    expect_errno(fs::create_dir(dir.join("missing/dir")), &[libc::ENOENT])
+}
+
+fn rmdir_nlink(dir: &Path) -> anyhow::Result<()> {
+    let path = dir.join("dir");
+    fs::create_dir(&path)?;
+    expect_nlink(dir, 3)?;
+    fs::remove_dir(&path)?;
+    expect_nlink(dir, 2)?;
+    expect_errno(fs::symlink_metadata(&path), &[libc::ENOENT])
+}
+
+fn rmdir_not_empty(dir: &Path) -> anyhow::Result<()> {
+    let path = dir.join("dir");
+    fs::create_dir(&path)?;
+    File::create(path.join("file"))?;
+    expect_errno(fs::remove_dir(&path), &[libc::ENOTEMPTY, libc::EEXIST])
+}
+
+fn rmdir_not_dir(dir: &Path) -> anyhow::Result<()> {
+    let path = dir.join("file");
+    File::create(&path)?;
+    expect_errno(fs::remove_dir(&path), &[libc::ENOTDIR])
+}
+
+fn unlink_nlink(dir: &Path) -> anyhow::Result<()> {
+    let (path, link) = (dir.join("file"), dir.join("link"));
+    File::create(&path)?;
+    fs::hard_link(&path, &link)?;
+    expect_nlink(&link, 2)?;
+    fs::remove_file(&path)?;
+    expect_nlink(&link, 1)?;
+    expect_errno(fs::symlink_metadata(&path), &[libc::ENOENT])
+}
+
+fn unlink_dir(dir: &Path) -> anyhow::Result<()> {
+    let path = dir.join("dir");
+    fs::create_dir(&path)?;
+    expect_errno(fs::remove_file(&path), &[UNLINK_DIR_ERRNO])
+}
+
+fn create_mode_and_owner(dir: &Path) -> anyhow::Result<()> {
+    let path = dir.join("file");
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o644)
+        .open(&path)?;
+    let metadata = fs::symlink_metadata(&path)?;
+    ensure!(metadata.is_file(), "{path:?} is not a regular file");
+    let mode = 0o644 & !umask();
+    ensure!(
+        metadata.mode() & 0o7777 == mode,
+        "the mode is {:o} instead of {mode:o}",
+        metadata.mode() & 0o7777
+    );
+    ensure!(
+        metadata.uid() == unistd::getuid().as_raw(),
+        "the uid is {}",
+        metadata.uid()
+    );
+    ensure!(
+        metadata.gid() == unistd::getgid().as_raw(),
+        "the gid is {}",
+        metadata.gid()
+    );
+    ensure!(metadata.len() == 0, "the size is {}", metadata.len());
+    expect_nlink(&path, 1)
+}
+
+fn create_exclusive(dir: &Path) -> anyhow::Result<()> {
+    let path = dir.join("file");
+    File::create(&path)?;
+    expect_errno(
+        OpenOptions::new().write(true).create_new(true).open(&path),
+        &[libc::EEXIST],
+    )
+}
+
+fn open_missing(dir: &Path) -> anyhow::Result<()> {
+    expect_errno(File::open(dir.join("missing")), &[libc::ENOENT])
+}
+
+fn truncate_size(dir: &Path) -> anyhow::Result<()> {
+    let path = dir.join("file");
+    fs::write(&path, "0123456789")?;
+    File::options().write(true).open(&path)?.set_len(4)?;
+    ensure!(fs::read(&path)? == b"0123", "the file is not shrunk");
+    File::options().write(true).open(&path)?.set_len(8)?;
+    ensure!(
+        fs::read(&path)? == b"0123\0\0\0\0",
+        "the file is not extended by zeros"
+    );
+    Ok(())
+}
+
+fn write_hole(dir: &Path) -> anyhow::Result<()> {
+    let path = dir.join("file");
+    let file = File::create(&path)?;
+    file.write_all_at(b"x", 8192)?;
+    drop(file);
+    let data = fs::read(&path)?;
+    ensure!(data.len() == 8193, "the size is {}", data.len());
+    ensure!(
+        data.iter().take(8192).all(|&byte| byte == 0),
+        "the hole is not read as zeros"
+    );
+    Ok(())
+}
+
+fn chmod_mode(dir: &Path) -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = dir.join("file");
+    File::create(&path)?;
+    let ctime = fs::symlink_metadata(&path)?.ctime();
+    for mode in [0o600, 0o4755, 0o1777] {
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+        let metadata = fs::symlink_metadata(&path)?;
+        ensure!(
+            metadata.mode() & 0o7777 == mode,
+            "the mode is {:o} instead of {mode:o}",
+            metadata.mode() & 0o7777
+        );
+        ensure!(metadata.ctime() >= ctime, "the ctime goes back");
+    }
+    Ok(())
+}
+
+fn utimes_times(dir: &Path) -> anyhow::Result<()> {
+    let path = dir.join("file");
+    File::create(&path)?;
+    stat::utimes(
+        &path,
+        &TimeVal::new(1_000_000_000, 0),
+        &TimeVal::new(1_500_000_000, 0),
+    )?;
+    let metadata = fs::symlink_metadata(&path)?;
+    ensure!(
+        metadata.atime() == 1_000_000_000,
+        "the atime is {}",
+        metadata.atime()
+    );
+    ensure!(
+        metadata.mtime() == 1_500_000_000,
+        "the mtime is {}",
+        metadata.mtime()
+    );
+    Ok(())
+}
+
+fn link_nlink(dir: &Path) -> anyhow::Result<()> {
+    let (path, link) = (dir.join("file"), dir.join("link"));
+    File::create(&path)?;
+    fs::hard_link(&path, &link)?;
+    let (ino, link_ino) = (
+        fs::symlink_metadata(&path)?.ino(),
+        fs::symlink_metadata(&link)?.ino(),
+    );
+    ensure!(ino == link_ino, "the link is inode {link_ino}, not {ino}");
+    expect_nlink(&path, 2)?;
+    expect_nlink(&link, 2)
+}
+
+fn link_exists(dir: &Path) -> anyhow::Result<()> {
+    let (path, link) = (dir.join("file"), dir.join("link"));
+    File::create(&path)?;
+    File::create(&link)?;
+    expect_errno(fs::hard_link(&path, &link), &[libc::EEXIST])
+}
+
+fn symlink_type(dir: &Path) -> anyhow::Result<()> {
+    let link = dir.join("link");
+    symlink("target/of/link", &link)?;
+    let metadata = fs::symlink_metadata(&link)?;
+    ensure!(metadata.is_symlink(), "{link:?} is not a symbolic link");
+    ensure!(
+        metadata.len() == 14,
+        "the size is {} instead of the length of the target",
+        metadata.len()
+    );
+    Ok(())
+}
+
+fn readlink_target(dir: &Path) -> anyhow::Result<()> {
+    let link = dir.join("link");
+    symlink("target/of/link", &link)?;
+    let target = fs::read_link(&link)?;
+    ensure!(
+        target == Path::new("target/of/link"),
+        "the target is {target:?}"
+    );
+    Ok(())
+}
+
+fn rename_file(dir: &Path) -> anyhow::Result<()> {
+    let (from, to) = (dir.join("from"), dir.join("to"));
+    fs::write(&from, "data")?;
+    let ino = fs::symlink_metadata(&from)?.ino();
+    fs::rename(&from, &to)?;
+    ensure!(
+        fs::symlink_metadata(&to)?.ino() == ino,
+        "the inode is changed"
+    );
+    ensure!(fs::read(&to)? == b"data", "the content is changed");
+    expect_nlink(&to, 1)?;
+    expect_errno(fs::symlink_metadata(&from), &[libc::ENOENT])
+}
+
+fn rename_dir_nlink(dir: &Path) -> anyhow::Result<()> {
+    let (from, to) = (dir.join("from"), dir.join("to"));
+    fs::create_dir_all(from.join("sub"))?;
+    fs::create_dir(&to)?;
+    fs::rename(from.join("sub"), to.join("sub"))?;
+    expect_nlink(&from, 2)?;
+    expect_nlink(&to, 3)?;
+    expect_nlink(&to.join("sub"), 2)
+}
+
+fn rename_file_over_dir(dir: &Path) -> anyhow::Result<()> {
+    let (file, subdir) = (dir.join("file"), dir.join("dir"));
+    File::create(&file)?;
+    fs::create_dir(&subdir)?;
+    expect_errno(fs::rename(&file, &subdir), &[libc::EISDIR])
+}
+
+fn rename_dir_over_file(dir: &Path) -> anyhow::Result<()> {
+    let (file, subdir) = (dir.join("file"), dir.join("dir"));
+    File::create(&file)?;
+    fs::create_dir(&subdir)?;
+    expect_errno(fs::rename(&subdir, &file), &[libc::ENOTDIR])
+}
+
+fn rename_dir_over_non_empty(dir: &Path) -> anyhow::Result<()> {
+    let (from, to) = (dir.join("from"), dir.join("to"));
+    fs::create_dir(&from)?;
+    fs::create_dir(&to)?;
+    File::create(to.join("file"))?;
+    expect_errno(fs::rename(&from, &to), &[libc::ENOTEMPTY, libc::EEXIST])
+}
+
+fn readdir_entries(dir: &Path) -> anyhow::Result<()> {
+    for name in ["c", "a", "b"] {
+        File::create(dir.join(name))?;
+    }
+    fs::create_dir(dir.join("d"))?;
+    let mut names = fs::read_dir(dir)?
+        .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+        .collect::<io::Result<Vec<_>>>()?;
+    names.sort();
+    ensure!(names == ["a", "b", "c", "d"], "the entries are {names:?}");
+    Ok(())
+}
+
+fn mkfifo_type(dir: &Path) -> anyhow::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let path = dir.join("fifo");
+    unistd::mkfifo(&path, Mode::from_bits_truncate(0o644))?;
+    let file_type = fs::symlink_metadata(&path)?.file_type();
+    ensure!(file_type.is_fifo(), "{path:?} is not a FIFO");
+    Ok(())
+}
+
+/// The handler of `FileSystem` responsible for `opcode`.
+fn handler(opcode: &FuseOpCode) -> String {
+    format!("{opcode:?}")
+        .trim_start_matches("FUSE_")
+        .to_lowercase()
+}
+
+/// Run all the cases under `mount_dir`, returns the failures.
+fn run_cases(mount_dir: &Path) -> Vec<String> {
+    let mut failures = vec![];
+    for case in CASES {
+        info!("run conformance case {}", case.name);
+        let dir = mount_dir.join(format!("conformance-{}", case.name));
+        let result = fs::create_dir(&dir)
+            .map_err(anyhow::Error::from)
+            .and_then(|()| (case.run)(&dir))
+            .and_then(|()| fs::remove_dir_all(&dir).map_err(anyhow::Error::from));
+        if let Err(e) = result {
+            failures.push(format!(
+                "{} ({} of pjdfstest) failed in the {:?} handler `FileSystem::{}()`: {e:#}",
+                case.name,
+                case.source,
+                case.opcode,
+                handler(&case.opcode),
+            ));
+        }
+    }
+    failures
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_conformance() -> anyhow::Result<()> {
+    let mount_dir = Path::new(CONFORMANCE_MOUNT_DIR);
+    test_util::setup(mount_dir, false).await?;
+    let failures = run_cases(mount_dir);
+    test_util::teardown(mount_dir).await?;
+    if !failures.is_empty() {
+        bail!(
+            "{} of {} conformance cases failed:\n{}",
+            failures.len(),
+            CASES.len(),
+            failures.join("\n")
+        );
+    }
+    Ok(())
+}
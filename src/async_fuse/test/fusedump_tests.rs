@@ -0,0 +1,147 @@
+//! The replay of a trace of the FUSE requests and replies against `MemFs`.
+
+use std::fs;
+use std::mem;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::test_util;
+use crate::async_fuse::fuse::fusedump::{self, RecordKind, Recorder};
+use crate::async_fuse::fuse::mock_kernel::{arg_bytes, name_bytes};
+use crate::async_fuse::fuse::protocol::{
+    FuseEntryOut, FuseInHeader, FuseInitIn, FuseMkDirIn, FuseOpCode, FuseOutHeader,
+    FUSE_KERNEL_MINOR_VERSION, FUSE_KERNEL_VERSION, FUSE_ROOT_ID,
+};
+
+/// Where the file system of the test is registered, it's not mounted.
+const FUSEDUMP_TEST_DIR: &str = "/tmp/datenlord_fusedump_test";
+
+/// The i-number of the directory created in the recorded session, which
+/// differs from the one created by the replay.
+const RECORDED_DIR: u64 = 1000;
+
+/// The bytes of a request of `opcode` on `nodeid` with the arguments `args`.
+#[allow(clippy::as_conversions)] // allow this for enum
+fn request(opcode: FuseOpCode, unique: u64, nodeid: u64, args: &[&[u8]]) -> Vec<u8> {
+    let len = args
+        .iter()
+        .fold(mem::size_of::<FuseInHeader>(), |len, arg| len + arg.len());
+    let header = FuseInHeader {
+        len: len.try_into().unwrap(),
+        opcode: opcode as u32,
+        unique,
+        nodeid,
+        uid: 0,
+        gid: 0,
+        pid: 0,
+        padding: 0,
+    };
+    let mut bytes = arg_bytes(&header).to_vec();
+    for arg in args {
+        bytes.extend_from_slice(arg);
+    }
+    bytes
+}
+
+/// The bytes of a reply to `unique` failed with `error`, or succeeded with
+/// `body`.
+fn reply(unique: u64, error: i32, body: &[u8]) -> Vec<u8> {
+    let header = FuseOutHeader {
+        len: (mem::size_of::<FuseOutHeader>() + body.len())
+            .try_into()
+            .unwrap(),
+        error,
+        unique,
+    };
+    [arg_bytes(&header), body].concat()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_replay() -> anyhow::Result<()> {
+    let test_dir = Path::new(FUSEDUMP_TEST_DIR);
+    fs::create_dir_all(test_dir)?;
+    let kv_engine = test_util::open_kv_engine();
+    let memfs = test_util::new_memfs_with_kv_engine(test_dir, false, kv_engine).await?;
+
+    let trace = test_dir.join("trace");
+    let recorder = Recorder::default();
+    recorder.start(&trace)?;
+    let init = FuseInitIn {
+        major: FUSE_KERNEL_VERSION,
+        minor: FUSE_KERNEL_MINOR_VERSION,
+        max_readahead: 0,
+        flags: 0,
+    };
+    recorder.record(
+        RecordKind::Request,
+        &[request(FuseOpCode::FUSE_INIT, 1, 0, &[arg_bytes(&init)]).as_slice()],
+    );
+    recorder.record(RecordKind::Reply, &[reply(1, 0, &[]).as_slice()]);
+
+    let mkdir = FuseMkDirIn {
+        mode: 0o755,
+        #[cfg(not(feature = "abi-7-12"))]
+        padding: 0,
+        #[cfg(feature = "abi-7-12")]
+        umask: 0,
+    };
+    recorder.record(
+        RecordKind::Request,
+        &[request(
+            FuseOpCode::FUSE_MKDIR,
+            2,
+            FUSE_ROOT_ID,
+            &[arg_bytes(&mkdir), &name_bytes("dir")],
+        )
+        .as_slice()],
+    );
+    // `nodeid` leads the `fuse_entry_out`
+    let mut entry = RECORDED_DIR.to_ne_bytes().to_vec();
+    entry.resize(mem::size_of::<FuseEntryOut>(), 0);
+    recorder.record(RecordKind::Reply, &[reply(2, 0, &entry).as_slice()]);
+
+    // The directory is found by the i-number mapped to the replayed one
+    recorder.record(
+        RecordKind::Request,
+        &[request(FuseOpCode::FUSE_GETATTR, 3, RECORDED_DIR, &[]).as_slice()],
+    );
+    recorder.record(RecordKind::Reply, &[reply(3, 0, &[]).as_slice()]);
+
+    // The recorded lookup succeeded, while the replayed one fails
+    recorder.record(
+        RecordKind::Request,
+        &[request(
+            FuseOpCode::FUSE_LOOKUP,
+            4,
+            RECORDED_DIR,
+            &[&name_bytes("missing")],
+        )
+        .as_slice()],
+    );
+    recorder.record(RecordKind::Reply, &[reply(4, 0, &entry).as_slice()]);
+
+    let report = fusedump::replay(&trace, Arc::new(memfs)).await?;
+    assert_eq!(report.requests, 4);
+    assert_eq!(report.undecodable, 0);
+    assert_eq!(report.mismatches, 1, "{report}");
+    assert!(
+        report
+            .first_mismatches
+            .first()
+            .unwrap()
+            .contains("fuse=4 LOOKUP"),
+        "{report}"
+    );
+    assert_eq!(report.ops.get("MKDIR").map(|op| op.count), Some(1));
+    assert_eq!(report.ops.get("GETATTR").map(|op| op.count), Some(1));
+
+    let mut printed = Vec::new();
+    fusedump::print_trace(&trace, &mut printed)?;
+    let printed = String::from_utf8(printed)?;
+    assert_eq!(printed.lines().count(), 8);
+    assert!(
+        printed.contains("operation=MKDIR name=\"dir\""),
+        "{printed}"
+    );
+    Ok(())
+}
@@ -21,7 +21,7 @@ pub const FILE_CONTENT: &str = "0123456789ABCDEF";
 
 #[cfg(test)]
 fn test_create_file(mount_dir: &Path) -> anyhow::Result<()> {
-    use smol::fs::unix::MetadataExt;
+    use std::os::unix::fs::MetadataExt;
     info!("test create file");
     let file_path = Path::new(mount_dir).join("test_create_file_user.txt");
     let file_mode = Mode::from_bits_truncate(0o644);
@@ -0,0 +1,277 @@
+//! The end-to-end tests of the session, the dispatch and the replies of
+//! `MemFs`, driven by a mock kernel over a mock FUSE device, so they run
+//! without mounting and without root.
+
+use std::mem;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::test_util;
+use crate::async_fuse::fuse::mock_kernel::{arg_bytes, name_bytes, MockKernel};
+use crate::async_fuse::fuse::protocol::{
+    FuseAttrOut, FuseCreateIn, FuseEntryOut, FuseForgetIn, FuseMkDirIn, FuseOpCode, FuseOpenOut,
+    FuseOutHeader, FuseReadIn, FuseReleaseIn, FuseWriteIn, FuseWriteOut, FUSE_ROOT_ID,
+};
+#[cfg(feature = "abi-7-23")]
+use crate::async_fuse::fuse::protocol::{
+    FuseFSyncIn, FUSE_ASYNC_READ, FUSE_WRITEBACK_CACHE, FUSE_WRITE_CACHE,
+};
+#[cfg(feature = "abi-7-23")]
+use crate::storage::Storage;
+
+/// Where the file system of the test is registered, it's not mounted.
+const MOCK_KERNEL_TEST_DIR: &str = "/tmp/datenlord_mock_kernel_test";
+
+/// The header of a reply failed with `errno`, without the unique ID.
+fn error_header(errno: i32) -> Vec<u8> {
+    let len: u32 = mem::size_of::<FuseOutHeader>().try_into().unwrap();
+    [len.to_ne_bytes(), errno.wrapping_neg().to_ne_bytes()].concat()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_mock_kernel() -> anyhow::Result<()> {
+    let kv_engine = test_util::open_kv_engine();
+    let memfs =
+        test_util::new_memfs_with_kv_engine(Path::new(MOCK_KERNEL_TEST_DIR), false, kv_engine)
+            .await?;
+    let mut kernel = MockKernel::start(Arc::new(memfs)).await?;
+
+    let dir_name = format!("mock-{}", uuid::Uuid::new_v4());
+    let reply = kernel
+        .request(
+            FuseOpCode::FUSE_LOOKUP,
+            FUSE_ROOT_ID,
+            &[&name_bytes(&dir_name)],
+        )
+        .await?;
+    assert_eq!(
+        reply.bytes().get(..8),
+        Some(error_header(libc::ENOENT).as_slice())
+    );
+
+    let mkdir = FuseMkDirIn {
+        mode: 0o755,
+        #[cfg(not(feature = "abi-7-12"))]
+        padding: 0,
+        #[cfg(feature = "abi-7-12")]
+        umask: 0,
+    };
+    let reply = kernel
+        .request(
+            FuseOpCode::FUSE_MKDIR,
+            FUSE_ROOT_ID,
+            &[arg_bytes(&mkdir), &name_bytes(&dir_name)],
+        )
+        .await?;
+    let (entry, _) = reply.decode::<FuseEntryOut>(0)?;
+    let dir = entry.nodeid;
+    assert_eq!(entry.attr.mode & libc::S_IFMT, libc::S_IFDIR);
+
+    let create = FuseCreateIn {
+        flags: libc::O_RDWR.try_into().unwrap(),
+        mode: libc::S_IFREG | 0o644,
+        #[cfg(feature = "abi-7-12")]
+        umask: 0,
+        #[cfg(feature = "abi-7-12")]
+        padding: 0,
+    };
+    let reply = kernel
+        .request(
+            FuseOpCode::FUSE_CREATE,
+            dir,
+            &[arg_bytes(&create), &name_bytes("file")],
+        )
+        .await?;
+    let (entry, open) = reply.decode::<FuseEntryOut>(mem::size_of::<FuseOpenOut>())?;
+    let file = entry.nodeid;
+    let fh = u64::from_ne_bytes(open.get(..8).unwrap().try_into().unwrap());
+
+    let data = b"hello";
+    let write = FuseWriteIn {
+        fh,
+        offset: 0,
+        size: data.len().try_into().unwrap(),
+        write_flags: 0,
+        #[cfg(feature = "abi-7-9")]
+        lock_owner: 0,
+        #[cfg(feature = "abi-7-9")]
+        flags: 0,
+        #[cfg(feature = "abi-7-9")]
+        padding: 0,
+    };
+    let reply = kernel
+        .request(FuseOpCode::FUSE_WRITE, file, &[arg_bytes(&write), data])
+        .await?;
+    // `fuse_write_out` holds the size written and a padding
+    assert_eq!(reply.body(), [5_u32.to_ne_bytes(), [0; 4]].concat());
+
+    let read = FuseReadIn {
+        fh,
+        offset: 1,
+        size: 16,
+        #[cfg(feature = "abi-7-9")]
+        read_flags: 0,
+        #[cfg(feature = "abi-7-9")]
+        lock_owner: 0,
+        #[cfg(feature = "abi-7-9")]
+        flags: 0,
+        padding: 0,
+    };
+    let reply = kernel
+        .request(FuseOpCode::FUSE_READ, file, &[arg_bytes(&read)])
+        .await?;
+    assert_eq!(reply.error, 0);
+    assert_eq!(reply.body(), b"ello");
+
+    let reply = kernel.request(FuseOpCode::FUSE_GETATTR, file, &[]).await?;
+    let (attr, _) = reply.decode::<FuseAttrOut>(0)?;
+    assert_eq!(attr.attr.ino, file);
+    assert_eq!(attr.attr.size, 5);
+
+    let release = FuseReleaseIn {
+        fh,
+        flags: 0,
+        release_flags: 0,
+        lock_owner: 0,
+    };
+    let reply = kernel
+        .request(FuseOpCode::FUSE_RELEASE, file, &[arg_bytes(&release)])
+        .await?;
+    assert_eq!(reply.error, 0);
+    assert!(reply.body().is_empty());
+
+    // The lookups of `CREATE` and `MKDIR` are forgotten without replies, the
+    // next reply is checked to be the one of the next request
+    kernel.notify(
+        FuseOpCode::FUSE_FORGET,
+        file,
+        &[arg_bytes(&FuseForgetIn { nlookup: 1 })],
+    )?;
+    let reply = kernel
+        .request(
+            FuseOpCode::FUSE_LOOKUP,
+            FUSE_ROOT_ID,
+            &[&name_bytes(&dir_name)],
+        )
+        .await?;
+    let (entry, _) = reply.decode::<FuseEntryOut>(0)?;
+    assert_eq!(entry.nodeid, dir);
+    kernel.notify(
+        FuseOpCode::FUSE_FORGET,
+        dir,
+        &[arg_bytes(&FuseForgetIn { nlookup: 2 })],
+    )?;
+
+    let reply = kernel
+        .request(
+            FuseOpCode::FUSE_RMDIR,
+            FUSE_ROOT_ID,
+            &[&name_bytes(&dir_name)],
+        )
+        .await?;
+    assert_eq!(
+        reply.bytes().get(..8),
+        Some(error_header(libc::ENOTEMPTY).as_slice())
+    );
+    Ok(())
+}
+
+#[cfg(feature = "abi-7-23")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_init_writeback_cache() -> anyhow::Result<()> {
+    let kv_engine = test_util::open_kv_engine();
+    let memfs = Arc::new(
+        test_util::new_memfs_with_kv_engine(Path::new(MOCK_KERNEL_TEST_DIR), false, kv_engine)
+            .await?,
+    );
+
+    // The writeback cache is only claimed if the kernel offers it
+    let (_kernel, flags) =
+        MockKernel::start_with(Arc::clone(&memfs), FUSE_ASYNC_READ, true).await?;
+    assert_eq!(flags, FUSE_ASYNC_READ);
+    let offered = FUSE_ASYNC_READ | FUSE_WRITEBACK_CACHE;
+    let (_kernel, flags) = MockKernel::start_with(Arc::clone(&memfs), offered, false).await?;
+    assert_eq!(flags, FUSE_ASYNC_READ);
+    let (_kernel, flags) = MockKernel::start_with(memfs, offered, true).await?;
+    assert_eq!(flags, offered);
+    Ok(())
+}
+
+#[cfg(feature = "abi-7-23")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_fsync_writeback_cache() -> anyhow::Result<()> {
+    let kv_engine = test_util::open_kv_engine();
+    let memfs =
+        test_util::new_memfs_with_kv_engine(Path::new(MOCK_KERNEL_TEST_DIR), false, kv_engine)
+            .await?;
+    let (mut kernel, flags) =
+        MockKernel::start_with(Arc::new(memfs), FUSE_WRITEBACK_CACHE, true).await?;
+    assert_eq!(flags, FUSE_WRITEBACK_CACHE);
+
+    let create = FuseCreateIn {
+        flags: libc::O_RDWR.try_into().unwrap(),
+        mode: libc::S_IFREG | 0o644,
+        umask: 0,
+        padding: 0,
+    };
+    let reply = kernel
+        .request(
+            FuseOpCode::FUSE_CREATE,
+            FUSE_ROOT_ID,
+            &[arg_bytes(&create), &name_bytes("writeback")],
+        )
+        .await?;
+    let (entry, open) = reply.decode::<FuseEntryOut>(mem::size_of::<FuseOpenOut>())?;
+    let file = entry.nodeid;
+    let fh = u64::from_ne_bytes(open.get(..8).unwrap().try_into().unwrap());
+
+    // The dirty pages of the kernel are written back with `FUSE_WRITE_CACHE`
+    let data = uuid::Uuid::new_v4().to_string();
+    let write = FuseWriteIn {
+        fh,
+        offset: 0,
+        size: data.len().try_into().unwrap(),
+        write_flags: FUSE_WRITE_CACHE,
+        lock_owner: 0,
+        flags: 0,
+        padding: 0,
+    };
+    let reply = kernel
+        .request(
+            FuseOpCode::FUSE_WRITE,
+            file,
+            &[arg_bytes(&write), data.as_bytes()],
+        )
+        .await?;
+    let (written, _) = reply.decode::<FuseWriteOut>(0)?;
+    assert_eq!(written.size, write.size);
+
+    let fsync = FuseFSyncIn {
+        fh,
+        fsync_flags: 0,
+        padding: 0,
+    };
+    let reply = kernel
+        .request(FuseOpCode::FUSE_FSYNC, file, &[arg_bytes(&fsync)])
+        .await?;
+    assert_eq!(reply.error, 0);
+
+    // The data is persisted to the backend, not only kept in the cache
+    let block = test_util::new_backend(false)?
+        .load_from_self(file, 0)
+        .await?
+        .unwrap();
+    assert!(block.as_slice().starts_with(data.as_bytes()));
+
+    let release = FuseReleaseIn {
+        fh,
+        flags: 0,
+        release_flags: 0,
+        lock_owner: 0,
+    };
+    let reply = kernel
+        .request(FuseOpCode::FUSE_RELEASE, file, &[arg_bytes(&release)])
+        .await?;
+    assert_eq!(reply.error, 0);
+    Ok(())
+}
@@ -7,6 +7,7 @@ use std::time::Duration;
 use clippy_utilities::OverflowArithmetic;
 use datenlord::common::task_manager::{TaskName, TASK_MANAGER};
 use datenlord::config::{
+    BackendErrorConfig, CompressionType, ConsistencyModel, EvictPolicyType, LogFormat,
     MemoryCacheConfig, SoftLimit, StorageConfig, StorageParams, StorageS3Config,
 };
 use tokio_util::sync::CancellationToken;
@@ -14,11 +15,16 @@ use tracing::{debug, info}; // warn, error
 
 use crate::async_fuse::fuse::{mount, session};
 use crate::async_fuse::memfs;
+use crate::async_fuse::memfs::kv_engine::local_impl::LocalKVEngine;
 use crate::async_fuse::memfs::kv_engine::{KVEngine, KVEngineType};
+use crate::async_fuse::memfs::BackendStorageType;
 use crate::common::logger::{init_logger, LogRole};
-use crate::storage::policy::LruPolicy;
+use crate::storage::policy::new_policy;
+#[cfg(feature = "abi-7-23")]
+use crate::storage::Backend;
 use crate::storage::{
-    BackendBuilder, BlockCoordinate, MemoryCacheBuilder, StorageManager, BLOCK_SIZE_IN_BYTES,
+    BackendBuilder, BlockCoordinate, DiskCache, MemoryCacheBuilder, StorageManager,
+    BLOCK_SIZE_IN_BYTES,
 };
 
 pub const TEST_NODE_ID: &str = "test_node";
@@ -27,6 +33,15 @@ pub const TEST_ETCD_ENDPOINT: &str = "127.0.0.1:2379";
 /// The default capacity in bytes for test, 1GB
 const CACHE_DEFAULT_CAPACITY: usize = 1024 * 1024 * 1024;
 
+/// Open a local kv engine in a new directory of its own, so the tests running
+/// in parallel never share an engine.
+pub fn open_kv_engine() -> Arc<KVEngineType> {
+    let dir = std::env::temp_dir().join(format!("datenlord_kv_{}", uuid::Uuid::new_v4()));
+    let engine = LocalKVEngine::open(&dir)
+        .unwrap_or_else(|e| panic!("failed to open a kv engine in {}: {e}", dir.display()));
+    Arc::new(KVEngineType::Local(engine))
+}
+
 fn test_storage_config(is_s3: bool) -> StorageConfig {
     let params = if is_s3 {
         let s3_config = StorageS3Config {
@@ -34,6 +49,10 @@ fn test_storage_config(is_s3: bool) -> StorageConfig {
             access_key_id: "test".to_owned(),
             secret_access_key: "test1234".to_owned(),
             bucket_name: "fuse-test-bucket".to_owned(),
+            region: "auto".to_owned(),
+            prefix: String::new(),
+            part_size: 0x80_0000,
+            read_streams: 4,
         };
 
         StorageParams::S3(s3_config)
@@ -47,20 +66,63 @@ fn test_storage_config(is_s3: bool) -> StorageConfig {
     );
     StorageConfig {
         block_size: BLOCK_SIZE_IN_BYTES,
+        legacy_block_size: BLOCK_SIZE_IN_BYTES,
+        read_ahead_window: 8,
+        compression: CompressionType::None,
+        snapshot: false,
+        capacity: None,
+        trash_retention: None,
+        inline_data_threshold: None,
+        stat_ahead_limit: None,
+        negative_cache_ttl: None,
+        dentry_cache_capacity: None,
+        memory_budget: None,
+        writeback_cache: false,
+        consistency: ConsistencyModel::Strict,
+        pin_limit: Some(50),
         memory_cache_config: MemoryCacheConfig {
             capacity: CACHE_DEFAULT_CAPACITY,
             command_queue_limit: 1000,
             write_back: true,
             soft_limit,
+            dirty_high_watermark: 0x4000_0000,
+            upload_concurrency: 8,
+            policy: EvictPolicyType::Lru,
         },
+        disk_cache_config: None,
+        dedup_config: None,
+        pack_config: None,
+        checksum_config: None,
+        encryption_config: None,
+        gc_config: None,
+        tiering_config: None,
+        transfer_config: None,
+        replication_config: None,
+        peer_cache_config: None,
+        backend_error_config: BackendErrorConfig::default(),
         params,
     }
 }
 
-async fn run_fs(mount_point: &Path, is_s3: bool, token: CancellationToken) -> anyhow::Result<()> {
-    let storage_config = test_storage_config(is_s3);
-    let kv_engine: Arc<memfs::kv_engine::etcd_impl::EtcdKVEngine> =
+/// Open the file system of the tests, `mount_point` is where it's registered
+/// to be mounted.
+pub async fn new_memfs(
+    mount_point: &Path,
+    is_s3: bool,
+) -> anyhow::Result<memfs::MemFs<memfs::S3MetaData>> {
+    let kv_engine: Arc<KVEngineType> =
         Arc::new(KVEngineType::new(vec![TEST_ETCD_ENDPOINT.to_owned()]).await?);
+    new_memfs_with_kv_engine(mount_point, is_s3, kv_engine).await
+}
+
+/// Open the file system of the tests on `kv_engine`, such as a local one for
+/// the tests run without etcd.
+pub async fn new_memfs_with_kv_engine(
+    mount_point: &Path,
+    is_s3: bool,
+    kv_engine: Arc<KVEngineType>,
+) -> anyhow::Result<memfs::MemFs<memfs::S3MetaData>> {
+    let storage_config = test_storage_config(is_s3);
 
     let storage = {
         let storage_param = &storage_config.params;
@@ -69,15 +131,29 @@ async fn run_fs(mount_point: &Path, is_s3: bool, token: CancellationToken) -> an
         let block_size = storage_config.block_size;
         let capacity_in_blocks = memory_cache_config.capacity.overflow_div(block_size);
 
-        let backend = BackendBuilder::new(storage_param.clone(), block_size).build()?;
-        let lru_policy = LruPolicy::<BlockCoordinate>::new(capacity_in_blocks);
-        let memory_cache = MemoryCacheBuilder::new(lru_policy, backend, block_size)
+        let backend = BackendBuilder::new(storage_param.clone(), block_size)
+            .compression(storage_config.compression)
+            .build()?;
+        let backend: BackendStorageType =
+            if let Some(ref disk_cache_config) = storage_config.disk_cache_config {
+                let capacity_in_blocks = disk_cache_config.capacity.overflow_div(block_size);
+                let policy =
+                    new_policy::<BlockCoordinate>(disk_cache_config.policy, capacity_in_blocks);
+                Arc::new(DiskCache::new(&disk_cache_config.dir, policy, backend, block_size).await?)
+            } else {
+                Arc::new(backend)
+            };
+        let policy = new_policy::<BlockCoordinate>(memory_cache_config.policy, capacity_in_blocks);
+        let memory_cache = MemoryCacheBuilder::new(policy, backend, block_size)
             .command_queue_limit(memory_cache_config.command_queue_limit)
             .limit(memory_cache_config.soft_limit)
+            .dirty_high_watermark(memory_cache_config.dirty_high_watermark)
+            .upload_concurrency(memory_cache_config.upload_concurrency)
             .write_through(!memory_cache_config.write_back)
             .build()
             .await;
         StorageManager::new(memory_cache, block_size)
+            .with_read_ahead(storage_config.read_ahead_window)
     };
 
     let storage = Arc::new(storage);
@@ -93,7 +169,24 @@ async fn run_fs(mount_point: &Path, is_s3: bool, token: CancellationToken) -> an
         storage,
     )
     .await?;
-    let ss = session::new_session_of_memfs(mount_point, fs).await?;
+    Ok(fs)
+}
+
+/// Open the backend of the file system of the tests, to check the blocks
+/// persisted to it.
+#[cfg(feature = "abi-7-23")]
+pub fn new_backend(is_s3: bool) -> anyhow::Result<Backend> {
+    let storage_config = test_storage_config(is_s3);
+    Ok(
+        BackendBuilder::new(storage_config.params, storage_config.block_size)
+            .compression(storage_config.compression)
+            .build()?,
+    )
+}
+
+async fn run_fs(mount_point: &Path, is_s3: bool, token: CancellationToken) -> anyhow::Result<()> {
+    let fs = new_memfs(mount_point, is_s3).await?;
+    let ss = session::new_session_of_memfs(mount_point, fs, false, None, false).await?;
     ss.run(token).await?;
 
     Ok(())
@@ -101,7 +194,7 @@ async fn run_fs(mount_point: &Path, is_s3: bool, token: CancellationToken) -> an
 
 #[allow(clippy::let_underscore_must_use)]
 pub async fn setup(mount_dir: &Path, is_s3: bool) -> anyhow::Result<()> {
-    init_logger(LogRole::Test);
+    init_logger(LogRole::Test, LogFormat::Text);
     debug!("setup started with mount_dir: {:?}", mount_dir);
     if mount_dir.exists() {
         debug!("mount_dir {:?} exists ,try umount", mount_dir);
@@ -0,0 +1,79 @@
+use std::path::Path;
+
+use nix::sys::stat::SFlag;
+
+use super::test_util;
+use crate::async_fuse::fuse::protocol::{INum, FUSE_ROOT_ID};
+use crate::async_fuse::memfs::{CreateParam, RenameParam, ROOT_CONTEXT};
+
+/// Where the file system of the test is registered, it's not mounted.
+const VFS_TEST_DIR: &str = "/tmp/datenlord_vfs_test_dir";
+
+/// The parameters to create `name` of `node_type` under directory `parent`.
+fn create_param(parent: INum, name: &str, node_type: SFlag) -> CreateParam {
+    CreateParam {
+        parent,
+        name: name.to_owned(),
+        mode: 0o755,
+        rdev: 0,
+        uid: 0,
+        gid: 0,
+        umask: 0,
+        node_type,
+        link: None,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_vfs_without_fuse() -> anyhow::Result<()> {
+    let fs = test_util::new_memfs(Path::new(VFS_TEST_DIR), false).await?;
+
+    let dir_name = format!("vfs-{}", uuid::Uuid::new_v4());
+    let dir = fs
+        .create_stateless(create_param(FUSE_ROOT_ID, &dir_name, SFlag::S_IFDIR))
+        .await?;
+    let file = fs
+        .create_stateless(create_param(dir.ino, "file", SFlag::S_IFREG))
+        .await?;
+    let attr = fs
+        .write_file(ROOT_CONTEXT, file.ino, 0, b"0123456789")
+        .await?;
+    assert_eq!(attr.size, 10);
+    let (data, eof) = fs.read_file(ROOT_CONTEXT, file.ino, 2, 4).await?;
+    assert_eq!(data, b"2345");
+    assert!(!eof);
+    let (data, eof) = fs.read_file(ROOT_CONTEXT, file.ino, 8, 4).await?;
+    assert_eq!(data, b"89");
+    assert!(eof);
+
+    fs.rename_entry(
+        ROOT_CONTEXT,
+        RenameParam {
+            old_parent: dir.ino,
+            old_name: "file".to_owned(),
+            new_parent: dir.ino,
+            new_name: "renamed".to_owned(),
+            flags: 0,
+        },
+    )
+    .await?;
+    let (_, attr, _) = fs.lookup_entry(ROOT_CONTEXT, dir.ino, "renamed").await?;
+    assert_eq!(attr.ino, file.ino);
+    assert!(fs
+        .lookup_entry(ROOT_CONTEXT, dir.ino, "file")
+        .await
+        .is_err());
+    let entries = fs.list_dir(ROOT_CONTEXT, dir.ino).await?;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries.first().map(|entry| entry.name()), Some("renamed"));
+
+    // A directory is removed after its entries.
+    assert!(fs.remove_stateless(FUSE_ROOT_ID, &dir_name).await.is_err());
+    fs.remove_stateless(dir.ino, "renamed").await?;
+    fs.remove_stateless(FUSE_ROOT_ID, &dir_name).await?;
+    assert!(fs
+        .lookup_entry(ROOT_CONTEXT, FUSE_ROOT_ID, &dir_name)
+        .await
+        .is_err());
+    Ok(())
+}
@@ -58,6 +58,11 @@ pub fn mode_from_kind_and_perm(kind: SFlag, perm: u16) -> u32 {
     {
         file_type | file_perm
     }
+    // `mode_t` is `u16` on macOS and FreeBSD
+    #[cfg(any(target_os = "macos", target_os = "freebsd"))]
+    {
+        u32::from(file_type) | file_perm
+    }
 }
 
 /// Stores short bytes on stack, stores long bytes on heap and provides
@@ -125,6 +130,21 @@ pub fn clear_errno() {
     unsafe { *libc::__errno_location() = 0_i32 }
 }
 
+/// Returns the platform-specific value of errno
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+#[must_use]
+#[inline]
+pub fn errno() -> i32 {
+    unsafe { *libc::__error() }
+}
+
+/// Sets the platform-specific errno to no-error
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+#[inline]
+pub fn clear_errno() {
+    unsafe { *libc::__error() = 0_i32 }
+}
+
 /// Converts [`nix::Error`] to [`io::Error`]
 #[must_use]
 pub fn nix_to_io_error(err: nix::Error) -> io::Error {
@@ -0,0 +1,510 @@
+//! The built-in benchmark of the file system, like a small fio.
+//!
+//! The bench role measures the latency and the throughput of every operation,
+//! and of the writes and the reads by every block size, so a regression of the
+//! performance is measured without the external tools. The `core` target
+//! drives the file system of the volume in the process through the operations
+//! of the gateways, bypassing the kernel, and the `mount` target drives the
+//! volume mounted by FUSE through the system calls, so the overhead of the
+//! kernel and the FUSE session is the difference between them.
+//!
+//! A file of `--bench-file-size` bytes is written and read sequentially by
+//! every block size, then `--bench-files` files are created, stat and
+//! removed, all in a directory of the benchmark removed at the end. The
+//! operations are issued one by one, so the latency is the one of a single
+//! operation, and the data is random, so it's neither compressed nor deduped.
+//! A write of the core target is flushed before it returns, while one of the
+//! mount target may be cached by the kernel, and so may a read.
+
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File, OpenOptions};
+use std::future::Future;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use clippy_utilities::{Cast, OverflowArithmetic};
+use datenlord::config::BenchConfig;
+use nix::sys::stat::SFlag;
+use rand::Rng;
+
+use crate::async_fuse::fuse::protocol::{INum, FUSE_ROOT_ID};
+use crate::async_fuse::memfs::{CreateParam, MemFs, S3MetaData, ROOT_CONTEXT};
+use crate::common::error::{Context, DatenLordResult};
+use crate::control::format_bytes;
+
+/// The mode of the directory of the benchmark.
+const DIR_MODE: u32 = 0o755;
+/// The mode of the files of the benchmark.
+const FILE_MODE: u32 = 0o644;
+/// The nanoseconds of a second.
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// The operations measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchOp {
+    /// Write a block sequentially
+    Write,
+    /// Read a block sequentially
+    Read,
+    /// Create an empty file
+    Create,
+    /// Get the attributes of a file by its name
+    Stat,
+    /// Remove a file
+    Remove,
+}
+
+impl BenchOp {
+    /// The name of the operation in the report.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Write => "write",
+            Self::Read => "read",
+            Self::Create => "create",
+            Self::Stat => "stat",
+            Self::Remove => "remove",
+        }
+    }
+}
+
+/// The latency and the throughput of an operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpReport {
+    /// The operation
+    pub op: BenchOp,
+    /// The block size of the writes and the reads, `None` for the others
+    pub block_size: Option<u64>,
+    /// The operations issued
+    pub ops: u64,
+    /// The bytes written or read
+    pub bytes: u64,
+    /// The time spent in the operations
+    pub elapsed: Duration,
+    /// The mean latency
+    pub mean: Duration,
+    /// The median latency
+    pub p50: Duration,
+    /// The 99th percentile latency
+    pub p99: Duration,
+    /// The max latency
+    pub max: Duration,
+}
+
+impl OpReport {
+    /// Summarize the `latencies` of `op`, which transfer `bytes` in total.
+    fn new(op: BenchOp, block_size: Option<u64>, mut latencies: Vec<Duration>, bytes: u64) -> Self {
+        latencies.sort_unstable();
+        let elapsed: Duration = latencies.iter().sum();
+        let ops: u64 = latencies.len().cast();
+        let percentile = |p: usize| {
+            let index = latencies
+                .len()
+                .saturating_sub(1)
+                .overflow_mul(p)
+                .overflow_div(100);
+            latencies.get(index).copied().unwrap_or_default()
+        };
+        Self {
+            op,
+            block_size,
+            ops,
+            bytes,
+            elapsed,
+            mean: elapsed.checked_div(ops.cast()).unwrap_or_default(),
+            p50: percentile(50),
+            p99: percentile(99),
+            max: latencies.last().copied().unwrap_or_default(),
+        }
+    }
+
+    /// The operations per second.
+    #[must_use]
+    pub fn ops_per_sec(&self) -> u64 {
+        per_sec(self.ops, self.elapsed)
+    }
+
+    /// The bytes per second.
+    #[must_use]
+    pub fn bytes_per_sec(&self) -> u64 {
+        per_sec(self.bytes, self.elapsed)
+    }
+}
+
+/// The rate of `count` in `elapsed`.
+fn per_sec(count: u64, elapsed: Duration) -> u64 {
+    u128::from(count)
+        .saturating_mul(NANOS_PER_SEC)
+        .checked_div(elapsed.as_nanos())
+        .map_or(0, |rate| rate.try_into().unwrap_or(u64::MAX))
+}
+
+/// Format `latency` in microseconds, or in milliseconds if it's long.
+pub fn format_latency(latency: Duration) -> String {
+    let micros = latency.as_micros();
+    if micros < 10000 {
+        format!("{micros}us")
+    } else {
+        format!("{}ms", latency.as_millis())
+    }
+}
+
+/// The report of a benchmark.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchReport {
+    /// The operations measured, the writes and the reads of every block size
+    /// first
+    pub ops: Vec<OpReport>,
+}
+
+impl Display for BenchReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<8} {:>6} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}",
+            "OP", "BLOCK", "OPS", "OPS/S", "BW/S", "MEAN", "P50", "P99", "MAX",
+        )?;
+        for op in &self.ops {
+            write!(
+                f,
+                "\n{:<8} {:>6} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}",
+                op.op.as_str(),
+                op.block_size.map_or_else(|| "-".to_owned(), format_bytes),
+                op.ops,
+                op.ops_per_sec(),
+                format_bytes(op.bytes_per_sec()),
+                format_latency(op.mean),
+                format_latency(op.p50),
+                format_latency(op.p99),
+                format_latency(op.max),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The target driven by the benchmark, in the directory of the benchmark.
+#[async_trait]
+trait Driver {
+    /// A file opened for the writes and the reads
+    type File: Send + Sync;
+
+    /// Create the file `name` and open it.
+    async fn create(&self, name: &str) -> DatenLordResult<Self::File>;
+
+    /// Write `data` to `file` at `offset`.
+    async fn write(&self, file: &Self::File, offset: u64, data: &[u8]) -> DatenLordResult<()>;
+
+    /// Read `len` bytes of `file` at `offset`.
+    async fn read(&self, file: &Self::File, offset: u64, len: u64) -> DatenLordResult<()>;
+
+    /// Get the attributes of the file `name`.
+    async fn stat(&self, name: &str) -> DatenLordResult<()>;
+
+    /// Remove the file `name`, which is not open.
+    async fn remove(&self, name: &str) -> DatenLordResult<()>;
+
+    /// Remove the directory of the benchmark with the files left.
+    async fn remove_dir(&self) -> DatenLordResult<()>;
+}
+
+/// The name of the directory of the benchmark, which is unique among the
+/// benchmarks running together.
+fn bench_dir_name() -> String {
+    format!(".datenlord-bench-{}", std::process::id())
+}
+
+/// Time `f` into `latencies`.
+async fn timed<T, F>(latencies: &mut Vec<Duration>, f: F) -> DatenLordResult<T>
+where
+    F: Future<Output = DatenLordResult<T>>,
+{
+    let start = Instant::now();
+    let res = f.await?;
+    latencies.push(start.elapsed());
+    Ok(res)
+}
+
+/// Run the benchmark of `config` on `driver`.
+async fn run<D: Driver + Sync>(driver: &D, config: &BenchConfig) -> DatenLordResult<BenchReport> {
+    let mut ops = vec![];
+    let mut rng = rand::thread_rng();
+    for &block_size in &config.block_sizes {
+        let name = format!("data-{block_size}");
+        let file = driver.create(&name).await?;
+        let mut data = vec![0_u8; block_size.cast()];
+        let blocks = config.file_size.overflow_div(block_size);
+        let bytes = blocks.overflow_mul(block_size);
+
+        let mut latencies = vec![];
+        for block in 0..blocks {
+            rng.fill(data.as_mut_slice());
+            let offset = block.overflow_mul(block_size);
+            timed(&mut latencies, driver.write(&file, offset, &data)).await?;
+        }
+        ops.push(OpReport::new(
+            BenchOp::Write,
+            Some(block_size),
+            latencies,
+            bytes,
+        ));
+
+        let mut latencies = vec![];
+        for block in 0..blocks {
+            let offset = block.overflow_mul(block_size);
+            timed(&mut latencies, driver.read(&file, offset, block_size)).await?;
+        }
+        ops.push(OpReport::new(
+            BenchOp::Read,
+            Some(block_size),
+            latencies,
+            bytes,
+        ));
+
+        drop(file);
+        driver.remove(&name).await?;
+    }
+
+    let names: Vec<String> = (0..config.files).map(|i| format!("file-{i}")).collect();
+    let mut latencies = vec![];
+    for name in &names {
+        timed(&mut latencies, driver.create(name)).await?;
+    }
+    ops.push(OpReport::new(BenchOp::Create, None, latencies, 0));
+    let mut latencies = vec![];
+    for name in &names {
+        timed(&mut latencies, driver.stat(name)).await?;
+    }
+    ops.push(OpReport::new(BenchOp::Stat, None, latencies, 0));
+    let mut latencies = vec![];
+    for name in &names {
+        timed(&mut latencies, driver.remove(name)).await?;
+    }
+    ops.push(OpReport::new(BenchOp::Remove, None, latencies, 0));
+
+    Ok(BenchReport { ops })
+}
+
+/// Run the benchmark on `driver`, whose directory is removed even if the
+/// benchmark fails.
+async fn run_and_clean<D: Driver + Sync>(
+    driver: &D,
+    config: &BenchConfig,
+) -> DatenLordResult<BenchReport> {
+    let res = run(driver, config).await;
+    driver.remove_dir().await?;
+    res
+}
+
+/// The file system of the volume in the process.
+#[derive(Debug)]
+struct CoreDriver {
+    /// The file system
+    fs: MemFs<S3MetaData>,
+    /// The i-number of the directory of the benchmark
+    dir: INum,
+    /// The name of the directory of the benchmark
+    dir_name: String,
+}
+
+/// The parameters to create `name` of `node_type` in `parent` as root.
+fn create_param(parent: INum, name: &str, node_type: SFlag) -> CreateParam {
+    CreateParam {
+        parent,
+        name: name.to_owned(),
+        mode: if node_type == SFlag::S_IFDIR {
+            DIR_MODE
+        } else {
+            FILE_MODE
+        },
+        rdev: 0,
+        uid: 0,
+        gid: 0,
+        umask: 0,
+        node_type,
+        link: None,
+    }
+}
+
+#[async_trait]
+impl Driver for CoreDriver {
+    type File = INum;
+
+    async fn create(&self, name: &str) -> DatenLordResult<INum> {
+        let attr = self
+            .fs
+            .create_stateless(create_param(self.dir, name, SFlag::S_IFREG))
+            .await?;
+        Ok(attr.ino)
+    }
+
+    async fn write(&self, file: &INum, offset: u64, data: &[u8]) -> DatenLordResult<()> {
+        self.fs
+            .write_file(ROOT_CONTEXT, *file, offset, data)
+            .await?;
+        Ok(())
+    }
+
+    async fn read(&self, file: &INum, offset: u64, len: u64) -> DatenLordResult<()> {
+        self.fs.read_file(ROOT_CONTEXT, *file, offset, len).await?;
+        Ok(())
+    }
+
+    async fn stat(&self, name: &str) -> DatenLordResult<()> {
+        self.fs.lookup_entry(ROOT_CONTEXT, self.dir, name).await?;
+        Ok(())
+    }
+
+    async fn remove(&self, name: &str) -> DatenLordResult<()> {
+        self.fs.remove_stateless(self.dir, name).await
+    }
+
+    async fn remove_dir(&self) -> DatenLordResult<()> {
+        for entry in self.fs.list_dir(ROOT_CONTEXT, self.dir).await? {
+            self.fs.remove_stateless(self.dir, entry.name()).await?;
+        }
+        self.fs.remove_stateless(FUSE_ROOT_ID, &self.dir_name).await
+    }
+}
+
+/// Benchmark the file system `fs` of the volume in the process, bypassing
+/// the kernel.
+pub async fn bench_core(
+    fs: MemFs<S3MetaData>,
+    config: &BenchConfig,
+) -> DatenLordResult<BenchReport> {
+    let dir_name = bench_dir_name();
+    let attr = fs
+        .create_stateless(create_param(FUSE_ROOT_ID, &dir_name, SFlag::S_IFDIR))
+        .await
+        .add_context("failed to create the directory of the benchmark")?;
+    let driver = CoreDriver {
+        fs,
+        dir: attr.ino,
+        dir_name,
+    };
+    run_and_clean(&driver, config).await
+}
+
+/// The volume mounted by FUSE. Its system calls block the runtime on
+/// purpose, which runs nothing else, so the latency is not skewed by the
+/// threads of `tokio::fs`.
+#[derive(Debug)]
+struct MountDriver {
+    /// The directory of the benchmark
+    dir: PathBuf,
+}
+
+#[async_trait]
+impl Driver for MountDriver {
+    type File = File;
+
+    async fn create(&self, name: &str) -> DatenLordResult<File> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(self.dir.join(name))
+            .with_context(|| format!("failed to create {name}"))?;
+        Ok(file)
+    }
+
+    async fn write(&self, file: &File, offset: u64, data: &[u8]) -> DatenLordResult<()> {
+        file.write_all_at(data, offset)
+            .with_context(|| format!("failed to write at {offset}"))
+    }
+
+    async fn read(&self, file: &File, offset: u64, len: u64) -> DatenLordResult<()> {
+        let mut buf = vec![0_u8; len.cast()];
+        file.read_exact_at(&mut buf, offset)
+            .with_context(|| format!("failed to read at {offset}"))
+    }
+
+    async fn stat(&self, name: &str) -> DatenLordResult<()> {
+        fs::metadata(self.dir.join(name)).with_context(|| format!("failed to stat {name}"))?;
+        Ok(())
+    }
+
+    async fn remove(&self, name: &str) -> DatenLordResult<()> {
+        fs::remove_file(self.dir.join(name)).with_context(|| format!("failed to remove {name}"))
+    }
+
+    async fn remove_dir(&self) -> DatenLordResult<()> {
+        fs::remove_dir_all(&self.dir).add_context("failed to remove the directory of the benchmark")
+    }
+}
+
+/// Benchmark the volume mounted by FUSE on `mount_dir`.
+pub async fn bench_mount(mount_dir: &Path, config: &BenchConfig) -> DatenLordResult<BenchReport> {
+    let dir = mount_dir.join(bench_dir_name());
+    fs::create_dir(&dir).with_context(|| format!("failed to create {dir:?}"))?;
+    run_and_clean(&MountDriver { dir }, config).await
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::time::Duration;
+
+    use datenlord::config::{BenchConfig, BenchTarget};
+
+    use super::{bench_mount, BenchOp, OpReport};
+
+    #[test]
+    fn test_op_report() {
+        let latencies = (1..=100).rev().map(Duration::from_millis).collect();
+        let report = OpReport::new(BenchOp::Read, Some(4096), latencies, 409_600);
+        assert_eq!(report.ops, 100);
+        assert_eq!(report.elapsed, Duration::from_millis(5050));
+        assert_eq!(report.mean, Duration::from_micros(50500));
+        assert_eq!(report.p50, Duration::from_millis(50));
+        assert_eq!(report.p99, Duration::from_millis(99));
+        assert_eq!(report.max, Duration::from_millis(100));
+        assert_eq!(report.ops_per_sec(), 19);
+        assert_eq!(report.bytes_per_sec(), 81108);
+
+        let report = OpReport::new(BenchOp::Stat, None, vec![], 0);
+        assert_eq!(report.ops_per_sec(), 0);
+        assert_eq!(report.max, Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_bench_dir() {
+        let dir = Path::new("/tmp/datenlord_bench");
+        if dir.exists() {
+            fs::remove_dir_all(dir).unwrap();
+        }
+        fs::create_dir_all(dir).unwrap();
+        let config = BenchConfig {
+            target: BenchTarget::Mount,
+            block_sizes: vec![512, 4096],
+            file_size: 8192,
+            files: 10,
+        };
+        let report = bench_mount(dir, &config).await.unwrap();
+        let ops: Vec<_> = report
+            .ops
+            .iter()
+            .map(|op| (op.op, op.block_size, op.ops, op.bytes))
+            .collect();
+        assert_eq!(
+            ops,
+            vec![
+                (BenchOp::Write, Some(512), 16, 8192),
+                (BenchOp::Read, Some(512), 16, 8192),
+                (BenchOp::Write, Some(4096), 2, 8192),
+                (BenchOp::Read, Some(4096), 2, 8192),
+                (BenchOp::Create, None, 10, 0),
+                (BenchOp::Stat, None, 10, 0),
+                (BenchOp::Remove, None, 10, 0),
+            ]
+        );
+        // The directory of the benchmark is removed.
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 0);
+        assert!(report.to_string().starts_with("OP"));
+    }
+}
@@ -7,6 +7,7 @@ use clap::Parser;
 use datenlord::common::error::DatenLordError::ArgumentInvalid;
 use datenlord::common::error::{Context, DatenLordResult};
 use datenlord::common::logger::{init_logger, LogRole};
+use datenlord::config::LogFormat;
 use nix::mount::{self, MsFlags};
 use tracing::debug;
 
@@ -45,7 +46,7 @@ pub struct BindMounterConfig {
 }
 
 fn main() -> DatenLordResult<()> {
-    init_logger(LogRole::BindMounter);
+    init_logger(LogRole::BindMounter, LogFormat::Text);
     debug!(
         "bind_mounter started with args: {:?}",
         std::env::args().collect::<Vec<_>>()
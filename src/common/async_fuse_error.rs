@@ -7,4 +7,13 @@ pub enum KVEngineError {
     /// Error caused by std::io::Error
     #[error("Timeout arg in kv operation is <= 0")]
     WrongTimeoutArg,
+    /// The lease is expired before it's renewed
+    #[error("The lease {0} is expired")]
+    LeaseExpired(i64),
+    /// The raft group replicating the metadata has no leader
+    #[error("The raft group has no leader")]
+    NoLeader,
+    /// The raft group fails to handle a request
+    #[error("The raft group fails: {0}")]
+    Raft(String),
 }
@@ -0,0 +1,143 @@
+//! The schedule of faults injected into the block stores and the metadata
+//! engines in tests, with the `fault-injection` feature.
+//!
+//! Every operation on a wrapped store or engine draws from a `FaultSchedule`,
+//! which decides whether the operation is delayed, fails with `ENOSPC`, loses
+//! its connection, or is written only partially. The draws come from a seeded
+//! random generator, so a failing test is reproduced by running it again with
+//! the same seed.
+
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// The denominator of the rates of faults, a rate is in per mille.
+const RATE_DENOMINATOR: u32 = 1000;
+
+/// A fault injected into an operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The operation is delayed by the duration, then done as usual
+    Latency(Duration),
+    /// Only a prefix of the written data is persisted, then the write fails
+    PartialWrite,
+    /// The write fails with `ENOSPC`
+    NoSpace,
+    /// The connection is dropped, so the operation fails with `ECONNRESET`
+    Disconnect,
+}
+
+impl Fault {
+    /// The I/O error an operation fails with, `None` if the fault doesn't fail
+    /// the operation.
+    #[must_use]
+    pub fn io_error(self) -> Option<io::Error> {
+        match self {
+            Self::Latency(_) => None,
+            Self::PartialWrite => Some(io::Error::from_raw_os_error(libc::EIO)),
+            Self::NoSpace => Some(io::Error::from_raw_os_error(libc::ENOSPC)),
+            Self::Disconnect => Some(io::Error::from_raw_os_error(libc::ECONNRESET)),
+        }
+    }
+}
+
+/// The seedable schedule of faults, shared by the wrapped stores and engines.
+///
+/// Each kind of fault has a rate in per mille of operations, a write may fail
+/// with any kind, while a read only may be delayed or disconnected.
+#[derive(Debug)]
+pub struct FaultSchedule {
+    /// The random generator, seeded on creation
+    rng: Mutex<StdRng>,
+    /// The rate of delayed operations
+    latency_rate: u32,
+    /// The delay of delayed operations
+    latency: Duration,
+    /// The rate of partial writes
+    partial_write_rate: u32,
+    /// The rate of writes failing with `ENOSPC`
+    no_space_rate: u32,
+    /// The rate of dropped connections
+    disconnect_rate: u32,
+    /// The number of faults injected
+    injected: AtomicUsize,
+}
+
+impl FaultSchedule {
+    /// Create a schedule seeded with `seed`, which injects no fault until
+    /// the rates are set.
+    #[must_use]
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+            latency_rate: 0,
+            latency: Duration::ZERO,
+            partial_write_rate: 0,
+            no_space_rate: 0,
+            disconnect_rate: 0,
+            injected: AtomicUsize::new(0),
+        }
+    }
+
+    /// Delay `rate` per mille of operations by `latency`.
+    #[must_use]
+    pub fn with_latency(mut self, rate: u32, latency: Duration) -> Self {
+        self.latency_rate = rate;
+        self.latency = latency;
+        self
+    }
+
+    /// Persist only a prefix of `rate` per mille of writes.
+    #[must_use]
+    pub fn with_partial_write(mut self, rate: u32) -> Self {
+        self.partial_write_rate = rate;
+        self
+    }
+
+    /// Fail `rate` per mille of writes with `ENOSPC`.
+    #[must_use]
+    pub fn with_no_space(mut self, rate: u32) -> Self {
+        self.no_space_rate = rate;
+        self
+    }
+
+    /// Drop the connection of `rate` per mille of operations.
+    #[must_use]
+    pub fn with_disconnect(mut self, rate: u32) -> Self {
+        self.disconnect_rate = rate;
+        self
+    }
+
+    /// The number of faults injected so far.
+    #[must_use]
+    pub fn injected(&self) -> usize {
+        self.injected.load(Ordering::Relaxed)
+    }
+
+    /// Draw the fault of the next operation, `None` if it's not faulted.
+    #[must_use]
+    pub fn draw(&self, is_write: bool) -> Option<Fault> {
+        let mut candidates = vec![
+            (self.disconnect_rate, Fault::Disconnect),
+            (self.latency_rate, Fault::Latency(self.latency)),
+        ];
+        if is_write {
+            candidates.push((self.no_space_rate, Fault::NoSpace));
+            candidates.push((self.partial_write_rate, Fault::PartialWrite));
+        }
+
+        let mut draw = self.rng.lock().gen_range(0..RATE_DENOMINATOR);
+        for (rate, fault) in candidates {
+            if draw < rate {
+                self.injected.fetch_add(1, Ordering::Relaxed);
+                return Some(fault);
+            }
+            draw = draw.saturating_sub(rate);
+        }
+        None
+    }
+}
@@ -1,8 +1,12 @@
+use once_cell::sync::OnceCell;
+use opentelemetry_sdk::trace::Tracer;
 use tracing::level_filters::LevelFilter as Level;
 use tracing_subscriber::filter;
-use tracing_subscriber::fmt::format::FmtSpan;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::prelude::*;
+use tracing_subscriber::{reload, Registry};
+
+use crate::config::LogFormat;
 
 /// Represents the role of the logger.
 #[derive(Debug)]
@@ -15,6 +19,46 @@ pub enum LogRole {
     SchedulerExtender,
     /// Same as `NodeRole::AsyncFuse`.
     AsyncFuse,
+    /// Same as `NodeRole::Fsck`.
+    Fsck,
+    /// Same as `NodeRole::Snapshot`.
+    Snapshot,
+    /// Same as `NodeRole::Trash`.
+    Trash,
+    /// Same as `NodeRole::VolumeManager`.
+    VolumeManager,
+    /// Same as `NodeRole::Volume`.
+    Volume,
+    /// Same as `NodeRole::Operator`.
+    Operator,
+    /// Same as `NodeRole::Gateway`.
+    Gateway,
+    /// Same as `NodeRole::Tenant`.
+    Tenant,
+    /// Same as `NodeRole::Top`.
+    Top,
+    /// Same as `NodeRole::Health`.
+    Health,
+    /// Same as `NodeRole::Drain`.
+    Drain,
+    /// Same as `NodeRole::Usage`.
+    Usage,
+    /// Same as `NodeRole::Bench`.
+    Bench,
+    /// Same as `NodeRole::FuseDump`.
+    FuseDump,
+    /// Same as `NodeRole::Admin`.
+    Admin,
+    /// Same as `NodeRole::Stats`.
+    Stats,
+    /// Same as `NodeRole::Inspect`.
+    Inspect,
+    /// Same as `NodeRole::SlowLog`.
+    SlowLog,
+    /// Same as `NodeRole::WarmUp`.
+    WarmUp,
+    /// Same as `NodeRole::Pin`.
+    Pin,
     /// For testing purpose.
     #[cfg(test)]
     Test,
@@ -32,6 +76,26 @@ impl From<crate::config::NodeRole> for LogRole {
             crate::config::NodeRole::Controller => LogRole::Controller,
             crate::config::NodeRole::SchedulerExtender => LogRole::SchedulerExtender,
             crate::config::NodeRole::AsyncFuse => LogRole::AsyncFuse,
+            crate::config::NodeRole::Fsck => LogRole::Fsck,
+            crate::config::NodeRole::Snapshot => LogRole::Snapshot,
+            crate::config::NodeRole::Trash => LogRole::Trash,
+            crate::config::NodeRole::VolumeManager => LogRole::VolumeManager,
+            crate::config::NodeRole::Volume => LogRole::Volume,
+            crate::config::NodeRole::Operator => LogRole::Operator,
+            crate::config::NodeRole::Gateway => LogRole::Gateway,
+            crate::config::NodeRole::Tenant => LogRole::Tenant,
+            crate::config::NodeRole::Top => LogRole::Top,
+            crate::config::NodeRole::Health => LogRole::Health,
+            crate::config::NodeRole::Drain => LogRole::Drain,
+            crate::config::NodeRole::Usage => LogRole::Usage,
+            crate::config::NodeRole::Bench => LogRole::Bench,
+            crate::config::NodeRole::FuseDump => LogRole::FuseDump,
+            crate::config::NodeRole::Admin => LogRole::Admin,
+            crate::config::NodeRole::Stats => LogRole::Stats,
+            crate::config::NodeRole::Inspect => LogRole::Inspect,
+            crate::config::NodeRole::SlowLog => LogRole::SlowLog,
+            crate::config::NodeRole::WarmUp => LogRole::WarmUp,
+            crate::config::NodeRole::Pin => LogRole::Pin,
         }
     }
 }
@@ -46,25 +110,74 @@ impl LogRole {
             LogRole::Controller => "controller",
             LogRole::SchedulerExtender => "scheduler_extender",
             LogRole::AsyncFuse => "async_fuse",
+            LogRole::Fsck => "fsck",
+            LogRole::Snapshot => "snapshot",
+            LogRole::Trash => "trash",
+            LogRole::VolumeManager => "volume_manager",
+            LogRole::Volume => "volume",
+            LogRole::Operator => "operator",
+            LogRole::Gateway => "gateway",
+            LogRole::Tenant => "tenant",
+            LogRole::Top => "top",
+            LogRole::Health => "health",
+            LogRole::Drain => "drain",
+            LogRole::Usage => "usage",
+            LogRole::Bench => "bench",
+            LogRole::FuseDump => "fusedump",
+            LogRole::Admin => "admin",
+            LogRole::Stats => "stats",
+            LogRole::Inspect => "inspect",
+            LogRole::SlowLog => "slowlog",
+            LogRole::WarmUp => "warmup",
+            LogRole::Pin => "pin",
             #[cfg(test)]
             LogRole::Test => "test",
             LogRole::BindMounter => "bind_mounter",
         }
     }
 }
-/// Initialize the logger with the default settings.
+
+/// The handle reloading the filter of the log.
+type LogFilterHandle = reload::Handle<filter::Targets, Registry>;
+
+/// The handle reloading the filter of the log, set once the logger is
+/// initialized.
+static LOG_FILTER: OnceCell<LogFilterHandle> = OnceCell::new();
+
+/// The filter of the log at `level`, the logs of the dependencies are at
+/// `WARN` at most.
+fn log_filter(level: Level) -> filter::Targets {
+    let dependency_level = level.min(Level::WARN);
+    filter::Targets::new()
+        .with_target("hyper", dependency_level)
+        .with_target("h2", dependency_level)
+        .with_target("tower", dependency_level)
+        .with_target("datenlord::async_fuse::fuse", level)
+        .with_target("datenlord::metrics", level)
+        .with_target("", level)
+}
+
+/// Initialize the logger with the default settings, whose records are in
+/// `format`.
 /// The log file is located at `./datenlord.log`.
+#[inline]
+pub fn init_logger(role: LogRole, format: LogFormat) {
+    install_logger(role, format, None);
+}
+
+/// Initialize the logger as `init_logger`, and export the spans at `INFO` or
+/// above by `tracer` as well.
+#[inline]
+pub fn init_traced_logger(role: LogRole, format: LogFormat, tracer: Tracer) {
+    install_logger(role, format, Some(tracer));
+}
+
+/// Install the logger whose records are in `format`, exporting the spans by
+/// `tracer` if it's set.
 #[allow(clippy::let_underscore_must_use)]
 #[allow(clippy::needless_pass_by_value)] // Just pass a temporary value is fine.
-#[inline]
-pub fn init_logger(role: LogRole) {
-    let filter = filter::Targets::new()
-        .with_target("hyper", Level::WARN)
-        .with_target("h2", Level::WARN)
-        .with_target("tower", Level::WARN)
-        .with_target("datenlord::async_fuse::fuse", Level::INFO)
-        .with_target("datenlord::metrics", Level::INFO)
-        .with_target("", Level::INFO);
+fn install_logger(role: LogRole, format: LogFormat, tracer: Option<Tracer>) {
+    let (filter, handle) = reload::Layer::new(log_filter(Level::INFO));
 
     let log_path = format!("./datenlord_{}.log", role.as_str());
     let file = std::fs::OpenOptions::new()
@@ -73,23 +186,56 @@ pub fn init_logger(role: LogRole) {
         .truncate(true)
         .open(log_path)
         .unwrap_or_else(|err| panic!("Failed to open log file ,err {err}"));
+    let writer = std::sync::Mutex::new(file);
+
+    // The fields of the spans, such as the ones of the FUSE requests, are
+    // kept in the records logged in them, instead of the records of the
+    // spans entered and exited.
+    let layer = match format {
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .compact()
+            .with_file(false)
+            .with_target(false)
+            .with_ansi(false)
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_writer(writer)
+            .boxed(),
+    };
 
-    let layer = tracing_subscriber::fmt::layer()
-        .compact()
-        .with_file(false)
-        .with_target(false)
-        .with_ansi(false)
-        .with_writer(std::sync::Mutex::new(file))
-        .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
-        .with_filter(filter);
+    // The spans are exported regardless of the level of the log, so the
+    // traces are kept whole while the log is quiet.
+    let exporter = tracer.map(|tracer| {
+        tracing_opentelemetry::layer()
+            .with_tracer(tracer)
+            .with_filter(Level::INFO)
+    });
 
-    let subscriber = tracing_subscriber::Registry::default().with(layer);
+    let subscriber = Registry::default()
+        .with(layer.with_filter(filter))
+        .with(exporter);
 
     if cfg!(test) {
-        let _: Result<(), tracing::subscriber::SetGlobalDefaultError> =
-            tracing::subscriber::set_global_default(subscriber);
+        if tracing::subscriber::set_global_default(subscriber).is_ok() {
+            let _: Result<(), LogFilterHandle> = LOG_FILTER.set(handle);
+        }
     } else {
         tracing::subscriber::set_global_default(subscriber)
             .unwrap_or_else(|error| panic!("Could not set logger ,err {error}"));
+        let _: Result<(), LogFilterHandle> = LOG_FILTER.set(handle);
     }
 }
+
+/// Set the level of the log to `level`, such as when the config is reloaded.
+/// Returns `false` if the logger is not initialized.
+#[inline]
+pub fn set_log_level(level: Level) -> bool {
+    LOG_FILTER
+        .get()
+        .is_some_and(|handle| handle.reload(log_filter(level)).is_ok())
+}
@@ -0,0 +1,165 @@
+//! The memory budget shared by the caches and buffers of the daemon.
+//!
+//! The data cache, the metadata cache and the buffers of in-flight requests
+//! are bounded one by one, but their bounds don't add up to what the host
+//! affords, so a daemon under load may still be killed by OOM. They draw from
+//! one budget instead: the caches register themselves as consumers whose
+//! usage is sampled when it's checked, and the fixed buffers reserve their
+//! memory once. A write waits while the budget is exhausted, and the data
+//! cache evicts its blocks to the backend until the budget is met again.
+
+use std::fmt::{self, Debug};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+
+use clippy_utilities::OverflowArithmetic;
+use parking_lot::Mutex;
+
+/// A consumer of the memory accounted by a `MemoryBudget`, such as a cache.
+pub trait MemoryConsumer: Send + Sync {
+    /// The bytes of memory used by the consumer.
+    fn used_memory(&self) -> usize;
+}
+
+/// The memory budget shared by the consumers.
+pub struct MemoryBudget {
+    /// The max bytes of memory used by all the consumers, which is reloaded
+    /// with the config
+    limit: AtomicUsize,
+    /// The bytes reserved by `MemoryReservation`s
+    reserved: AtomicUsize,
+    /// The registered consumers, which are dropped from the budget once
+    /// they're dropped
+    consumers: Mutex<Vec<Weak<dyn MemoryConsumer>>>,
+}
+
+impl Debug for MemoryBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryBudget")
+            .field("limit", &self.limit.load(Ordering::Relaxed))
+            .field("reserved", &self.reserved.load(Ordering::Relaxed))
+            .field("consumers", &self.consumers.lock().len())
+            .finish()
+    }
+}
+
+impl MemoryBudget {
+    /// Create a budget of `limit` bytes.
+    #[must_use]
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit: AtomicUsize::new(limit),
+            reserved: AtomicUsize::new(0),
+            consumers: Mutex::new(vec![]),
+        }
+    }
+
+    /// The max bytes of memory used by all the consumers.
+    #[must_use]
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Set the max bytes of memory used by all the consumers to `limit`, the
+    /// writes waiting for the budget are resumed once they're checked again.
+    pub fn set_limit(&self, limit: usize) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    /// Register `consumer`, whose usage is accounted until it's dropped.
+    pub fn register<C: MemoryConsumer + 'static>(&self, consumer: &Arc<C>) {
+        let consumer: Weak<dyn MemoryConsumer> = Arc::downgrade(consumer);
+        self.consumers.lock().push(consumer);
+    }
+
+    /// Reserve `bytes` of memory, which are released once the reservation is
+    /// dropped.
+    ///
+    /// A reservation never waits, it's for the memory taken anyway, such as
+    /// the buffers allocated up front.
+    #[must_use]
+    pub fn reserve(self: &Arc<Self>, bytes: usize) -> MemoryReservation {
+        self.reserved.fetch_add(bytes, Ordering::Relaxed);
+        MemoryReservation {
+            budget: Arc::clone(self),
+            bytes,
+        }
+    }
+
+    /// The bytes of memory used by all the consumers.
+    #[must_use]
+    pub fn used(&self) -> usize {
+        let mut consumers = self.consumers.lock();
+        consumers.retain(|consumer| consumer.strong_count() > 0);
+        consumers
+            .iter()
+            .filter_map(Weak::upgrade)
+            .fold(self.reserved.load(Ordering::Relaxed), |used, consumer| {
+                used.overflow_add(consumer.used_memory())
+            })
+    }
+
+    /// Whether the consumers use more memory than the budget.
+    #[must_use]
+    pub fn is_exhausted(&self) -> bool {
+        self.used() > self.limit()
+    }
+}
+
+/// The memory reserved from a `MemoryBudget`, it's released once dropped.
+#[derive(Debug)]
+pub struct MemoryReservation {
+    /// The budget reserved from
+    budget: Arc<MemoryBudget>,
+    /// The bytes reserved
+    bytes: usize,
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        self.budget
+            .reserved
+            .fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::{MemoryBudget, MemoryConsumer};
+
+    struct Consumer(AtomicUsize);
+
+    impl MemoryConsumer for Consumer {
+        fn used_memory(&self) -> usize {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        let budget = Arc::new(MemoryBudget::new(100));
+        let consumer = Arc::new(Consumer(AtomicUsize::new(30)));
+        budget.register(&consumer);
+        let reservation = budget.reserve(50);
+        assert_eq!(budget.used(), 80);
+        assert!(!budget.is_exhausted());
+
+        // The usage of a consumer is sampled when it's checked.
+        consumer.0.store(60, Ordering::Relaxed);
+        assert_eq!(budget.used(), 110);
+        assert!(budget.is_exhausted());
+        // The limit is reloaded with the config.
+        budget.set_limit(200);
+        assert_eq!(budget.limit(), 200);
+        assert!(!budget.is_exhausted());
+
+        drop(reservation);
+        assert_eq!(budget.used(), 60);
+        // A dropped consumer is no longer accounted.
+        drop(consumer);
+        assert_eq!(budget.used(), 0);
+    }
+}
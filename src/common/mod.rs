@@ -4,6 +4,9 @@ pub mod async_fuse_error;
 pub mod error;
 #[allow(dead_code)] // For CSI, CSI has not been refactored to use KVEngine yet
 pub mod etcd_delegate;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
+pub mod memory_budget;
 /// Utility module
 pub mod util;
 
@@ -32,6 +32,44 @@ pub enum TaskName {
     WriteBack,
     /// The scheduler extender.
     SchedulerExtender,
+    /// The scrubber of block checksums.
+    Scrub,
+    /// The collector of the expired files in the trash.
+    TrashGc,
+    /// The collector of the orphaned objects in the backend.
+    BlockGc,
+    /// The migrator of blocks between tiers.
+    TierMigration,
+    /// The registration of the node, and the watcher of the changes made by
+    /// other nodes.
+    Cluster,
+    /// The handler of the leases of files recalled by other nodes.
+    Coherence,
+    /// The shrinker of the metadata caches under memory pressure.
+    CacheShrink,
+    /// The health checker of replica nodes, which repairs the blocks with
+    /// too few replicas alive.
+    ReplicaHealth,
+    /// The probe of the backend, which reports the condition of the storage.
+    BackendProbe,
+    /// The reconcilers of the Kubernetes operator.
+    Operator,
+    /// The gateway serving the file system over NFS and S3.
+    Gateway,
+    /// The control socket of the mount.
+    Control,
+    /// The server of the liveness and the readiness.
+    Health,
+    /// The persistence of the disk cache on shutdown.
+    DiskCache,
+    /// The drain of the node before it's shut down.
+    Drain,
+    /// The warm-up of the caches with datasets.
+    WarmUp,
+    /// The checkpointer of the usage of the volume for billing.
+    UsageCheckpoint,
+    /// The reload of the config file on `SIGHUP`.
+    ConfigReload,
 }
 
 /// The task handle(s) of the current task node.
@@ -181,16 +219,36 @@ impl Task {
 }
 
 /// Edges of the dependency graph of the tasks.
-pub(super) const EDGES: [(TaskName, TaskName); 9] = [
+pub(super) const EDGES: [(TaskName, TaskName); 28] = [
     (TaskName::Root, TaskName::Metrics),
+    (TaskName::Root, TaskName::Control),
+    (TaskName::Root, TaskName::ConfigReload),
+    (TaskName::Root, TaskName::Health),
     (TaskName::Root, TaskName::BlockFlush),
     (TaskName::Root, TaskName::SchedulerExtender),
+    (TaskName::Root, TaskName::Operator),
     (TaskName::BlockFlush, TaskName::AsyncFuse),
     (TaskName::BlockFlush, TaskName::FuseRequest),
     (TaskName::FuseRequest, TaskName::AsyncFuse),
     (TaskName::FuseRequest, TaskName::WriteBack),
+    (TaskName::BlockFlush, TaskName::Gateway),
+    (TaskName::Gateway, TaskName::AsyncFuse),
     (TaskName::AsyncFuse, TaskName::Rpc),
     (TaskName::AsyncFuse, TaskName::WriteBack),
+    (TaskName::AsyncFuse, TaskName::Scrub),
+    (TaskName::AsyncFuse, TaskName::TrashGc),
+    (TaskName::AsyncFuse, TaskName::BlockGc),
+    (TaskName::AsyncFuse, TaskName::TierMigration),
+    (TaskName::AsyncFuse, TaskName::Cluster),
+    (TaskName::AsyncFuse, TaskName::Coherence),
+    (TaskName::AsyncFuse, TaskName::CacheShrink),
+    (TaskName::AsyncFuse, TaskName::ReplicaHealth),
+    (TaskName::AsyncFuse, TaskName::BackendProbe),
+    (TaskName::AsyncFuse, TaskName::Drain),
+    (TaskName::AsyncFuse, TaskName::WarmUp),
+    (TaskName::AsyncFuse, TaskName::DiskCache),
+    (TaskName::WriteBack, TaskName::DiskCache),
+    (TaskName::AsyncFuse, TaskName::UsageCheckpoint),
 ];
 
 /// Nodes of GC tasks.
@@ -1,23 +1,42 @@
+use std::ffi::OsString;
+
 use clap::Parser;
 
+use super::file::with_config_file;
+use crate::common::error::{DatenLordError, DatenLordResult};
+
+/// The default root of the FS backend.
+pub const DEFAULT_FS_STORAGE_ROOT: &str = "/tmp/datenlord_backend";
+/// The default size of blocks.
+pub const DEFAULT_BLOCK_SIZE: usize = 0x40_0000;
+/// The default size of blocks before the chunk size of a volume is
+/// persisted.
+pub const LEGACY_BLOCK_SIZE: usize = 0x8_0000;
+
 #[derive(Debug, Parser)]
 #[clap(author, version, about, long_about = None)]
 /// A config
 pub struct Config {
-    #[clap(long, value_name = "VALUE")]
-    /// Role: Controller, Node, AsyncFuse, Scheduler
+    #[clap(long, value_name = "VALUE", default_value_t)]
+    /// Role: Controller, Node, AsyncFuse, Scheduler, Fsck, Snapshot, Trash,
+    /// VolumeManager, Volume, Operator, Gateway, Tenant, Top, Health, Drain,
+    /// Usage, Bench, FuseDump, Admin, Stats, Inspect, SlowLog, WarmUp, Pin,
+    /// required unless `--standalone`
     pub role: String,
-    #[clap(long = "node-name", value_name = "VALUE")]
-    /// Node name
+    #[clap(long = "node-name", value_name = "VALUE", default_value_t)]
+    /// Node name, required unless `--standalone`
     pub node_name: String,
-    #[clap(long = "node-ip", value_name = "VALUE")]
-    /// Node ip
+    #[clap(long = "node-ip", value_name = "VALUE", default_value_t)]
+    /// Node ip, required unless `--standalone`
     pub node_ip: String,
     #[clap(long = "mount-path", value_name = "VALUE")]
     /// Set the mount point of FUSE
     pub mount_path: String,
     #[clap(long = "kv-server-list", value_name = "VALUE", value_delimiter = ',')]
-    /// A list of kv servers, separated by commas
+    /// A list of kv servers, separated by commas, or `file://<dir>` to
+    /// persist the metadata in a local directory, or
+    /// `raft://<id>@<addr>/<dir>,<id>=<endpoint>,...` to replicate the
+    /// metadata among the nodes by raft
     pub kv_server_list: Vec<String>,
     #[clap(long = "server-port", value_name = "VALUE", default_value_t = 8800)]
     /// Set service port number
@@ -29,12 +48,276 @@ pub struct Config {
     )]
     /// Set the port of the scheduler extender
     pub scheduler_extender_port: u16,
+    #[clap(long = "fsck-repair")]
+    /// Repair the inconsistencies found by the fsck role, otherwise they are
+    /// only reported
+    pub fsck_repair: bool,
+    #[clap(long = "snapshot", value_name = "VALUE", default_value_t)]
+    /// The name of a snapshot. The snapshot role creates it, or lists the
+    /// snapshots if it's empty. The asyncFuse role mounts it read-only instead
+    /// of the live volume.
+    pub snapshot: String,
+    #[clap(long = "snapshot-delete")]
+    /// Delete the snapshot instead of creating it in the snapshot role
+    pub snapshot_delete: bool,
+    #[clap(long = "trash-restore", value_name = "VALUE", default_value_t)]
+    /// The i-number of a file to restore from the trash in the trash role,
+    /// which lists the files in the trash if it's 0
+    pub trash_restore: u64,
+    #[clap(long = "trash-restore-path", value_name = "VALUE", default_value_t)]
+    /// The absolute path in the volume to restore the file to, default is
+    /// where it's removed from
+    pub trash_restore_path: String,
+    #[clap(
+        long = "gateway-listen",
+        value_name = "VALUE",
+        default_value = "0.0.0.0:2049"
+    )]
+    /// The address the gateway role serves the volume on by NFSv3, for the
+    /// clients that cannot run FUSE
+    pub gateway_listen: String,
+    #[clap(long = "gateway-s3-listen", value_name = "VALUE", default_value_t)]
+    /// The address the gateway role serves the named volumes on by the S3
+    /// API, the volumes as the buckets, not served by default
+    pub gateway_s3_listen: String,
+    #[clap(long = "virtiofs-socket", value_name = "VALUE", default_value_t)]
+    /// The vhost-user socket the asyncFuse role serves the volume on as a
+    /// virtio-fs device to a VM, such as one of QEMU or Kata Containers,
+    /// instead of mounting it by FUSE
+    pub virtiofs_socket: String,
+    #[clap(long = "selinux-context", value_name = "VALUE", default_value_t)]
+    /// The SELinux context presented for all the nodes of the mount, like the
+    /// `context=` mount option, such as
+    /// `system_u:object_r:container_file_t:s0`, instead of the labels stored
+    /// in the volume. Not given by default
+    pub selinux_context: String,
+    #[clap(long = "standalone")]
+    /// Run all in one process for laptops and CI: the asyncFuse role with the
+    /// metadata embedded and the blocks in the local file system, both kept
+    /// in `--standalone-dir` unless `--kv-server-list` or `--storage-fs-root`
+    /// is given
+    pub standalone: bool,
+    #[clap(
+        long = "standalone-dir",
+        value_name = "VALUE",
+        default_value = "/var/tmp/datenlord"
+    )]
+    /// The directory of the metadata and the blocks in the standalone mode
+    pub standalone_dir: String,
+    #[clap(long = "uid-map", value_name = "VALUE", value_delimiter = ',')]
+    /// The uids seen on the mount mapped to the ones stored in the volume,
+    /// in the form of `MOUNT:VOLUME:COUNT`, separated by commas, like the uid
+    /// map of a user namespace. The uids out of the ranges are seen and
+    /// stored as the overflow uid 65534. Not mapped by default
+    pub uid_map: Vec<String>,
+    #[clap(long = "gid-map", value_name = "VALUE", value_delimiter = ',')]
+    /// The gids seen on the mount mapped to the ones stored in the volume, in
+    /// the same form as `--uid-map`. Not mapped by default
+    pub gid_map: Vec<String>,
+    #[clap(long = "root-squash")]
+    /// Run the requests of root on the mount as the anonymous user, so the
+    /// root of an untrusted node cannot act as root on the volume
+    pub root_squash: bool,
+    #[clap(long = "all-squash")]
+    /// Run the requests of all the users on the mount as the anonymous user
+    pub all_squash: bool,
+    #[clap(long = "anon-uid", value_name = "VALUE", default_value_t = 65534)]
+    /// The uid in the volume of the anonymous user, default 65534
+    pub anon_uid: u32,
+    #[clap(long = "anon-gid", value_name = "VALUE", default_value_t = 65534)]
+    /// The gid in the volume of the anonymous user, default 65534
+    pub anon_gid: u32,
+    #[clap(long = "read-only")]
+    /// Serve the mount read-only, enforced by the daemon besides the kernel:
+    /// the changes are refused with `EROFS`, and nothing is stored to the
+    /// caches, the same as `--deny-ops write`
+    pub read_only: bool,
+    #[clap(long = "deny-ops", value_name = "VALUE", value_delimiter = ',')]
+    /// The classes of the FUSE operations turned off on the mount, separated
+    /// by commas: `write` refused with `EROFS`, `xattr` with `EOPNOTSUPP`,
+    /// `device` (creating the device nodes) with `EPERM` and `lock` with
+    /// `ENOLCK`. None by default
+    pub deny_ops: Vec<String>,
+    #[clap(long = "allow-ops", value_name = "VALUE", value_delimiter = ',')]
+    /// The classes of the FUSE operations allowed on the mount, the others of
+    /// `--deny-ops` are turned off. It cannot be given with `--deny-ops`
+    pub allow_ops: Vec<String>,
+    #[clap(long = "tls-cert", value_name = "VALUE", default_value_t)]
+    /// The PEM certificate of the node, the raft members, the transfer
+    /// services and the volume manager are served and connected over TLS if
+    /// it's set, and the key and the CA certificate are required as well. The
+    /// files are reloaded once they're modified
+    pub tls_cert_file: String,
+    #[clap(long = "tls-key", value_name = "VALUE", default_value_t)]
+    /// The PEM private key of the certificate of the node
+    pub tls_key_file: String,
+    #[clap(long = "tls-ca", value_name = "VALUE", default_value_t)]
+    /// The PEM certificate of the CA verifying the certificates of the other
+    /// nodes
+    pub tls_ca_file: String,
+    #[clap(long = "mtls")]
+    /// Require the clients to present their certificates, which are verified
+    /// by the CA, that is mutual TLS
+    pub mtls: bool,
+    #[clap(long = "auth-key-file", value_name = "VALUE", default_value_t)]
+    /// The file the key of the tenant tokens is derived from, shared by the
+    /// mounts authenticating the tenants and the tenant role issuing their
+    /// tokens
+    pub auth_key_file: String,
+    #[clap(long = "auth-policy", value_name = "VALUE", default_value_t)]
+    /// The JSON file of the directories granted to each tenant, the mount
+    /// only serves the ones granted to its tenant if it's set
+    pub auth_policy_file: String,
+    #[clap(long = "tenant-token", value_name = "VALUE", default_value_t)]
+    /// The token of the tenant served by the mount, required with
+    /// `--auth-policy`
+    pub tenant_token: String,
+    #[clap(long = "tenant", value_name = "VALUE", default_value_t)]
+    /// The tenant to issue a token of, by the tenant role
+    pub tenant: String,
+    #[clap(long = "control-socket", value_name = "VALUE", default_value_t)]
+    /// The Unix socket the mount serves the IO of the processes and the
+    /// cgroups on, which the top role shows. Not served by default
+    pub control_socket: String,
+    #[clap(long = "top-interval", value_name = "VALUE", default_value_t)]
+    /// The seconds between the refreshes of the top role, which shows the IO
+    /// per second in them. It's shown once since the mount if it's 0
+    pub top_interval: u64,
+    #[clap(long = "output-format", value_name = "VALUE", default_value = "table")]
+    /// The format the stats role, the inspect role, the slowlog role and the
+    /// pin role show the mount in, `table` or `json`
+    pub output_format: String,
+    #[clap(long = "health-port", value_name = "VALUE", default_value_t)]
+    /// The port the mount serves the liveness and the readiness on by HTTP,
+    /// as `/livez` and `/readyz`, which the health role checks. Not served if
+    /// it's 0
+    pub health_port: u16,
+    #[clap(long = "health-probe", value_name = "VALUE", default_value = "ready")]
+    /// The probe the health role checks, `live` or `ready`
+    pub health_probe: String,
+    #[clap(
+        long = "usage-checkpoint-interval",
+        value_name = "VALUE",
+        default_value_t = 300
+    )]
+    /// The seconds between the checkpoints of the IO of the mount and the
+    /// storage of the volume for billing, which the usage role exports. Not
+    /// checkpointed if it's 0
+    pub usage_checkpoint_interval: u64,
+    #[clap(long = "fuse-trace", value_name = "VALUE", default_value_t)]
+    /// The file the FUSE requests and replies of the mount are recorded into,
+    /// which the fusedump role prints or replays. Not recorded by default
+    pub fuse_trace: String,
+    #[clap(long = "fusedump-replay")]
+    /// Replay the trace against the volume in the fusedump role, instead of
+    /// printing it
+    pub fusedump_replay: bool,
+    #[clap(long = "log-level", value_name = "VALUE", default_value = "info")]
+    /// The level of the log: off, error, warn, info, debug or trace. It's
+    /// reloaded with the config file
+    pub log_level: String,
+    #[clap(long = "log-format", value_name = "VALUE", default_value = "text")]
+    /// The format of the log, `text`, or `json` for the log pipelines, whose
+    /// records carry the volume, the opcode, the unique id and the i-number
+    /// of the FUSE request logged in, as their fields
+    pub log_format: String,
+    #[clap(long = "log-sample-rate", value_name = "VALUE", default_value_t = 1)]
+    /// One in how many requests of the frequent FUSE operations, such as
+    /// `READ`, `WRITE`, `GETATTR` and `LOOKUP`, is logged once it's replied,
+    /// 1 logs all of them. It's reloaded with the config file
+    pub log_sample_rate: u64,
+    #[clap(long = "otlp-endpoint", value_name = "VALUE", default_value_t)]
+    /// The OpenTelemetry collector the spans are exported to by OTLP over
+    /// gRPC, such as `http://localhost:4317`, so a FUSE request is traced
+    /// through the metadata transactions and the transfers between the nodes
+    /// it issues. Not exported by default
+    pub otlp_endpoint: String,
+    #[clap(
+        long = "otlp-sample-ratio",
+        value_name = "VALUE",
+        default_value_t = 1.0
+    )]
+    /// The ratio of the traces started by this node exported, from 0 to 1.
+    /// The traces continued from the other nodes follow their sampling
+    pub otlp_sample_ratio: f64,
+    #[clap(long = "slow-log-threshold", value_name = "VALUE", default_value_t)]
+    /// The milliseconds a FUSE request is served beyond to be kept in the
+    /// slow log, with its arguments, its queue wait and service time, and the
+    /// decisions of the caches and the backend, which the slowlog role dumps.
+    /// Nothing is kept if it's 0. It's reloaded with the config file
+    pub slow_log_threshold: u64,
+    #[clap(
+        long = "slow-log-capacity",
+        value_name = "VALUE",
+        default_value_t = 128
+    )]
+    /// The most slow requests kept, the earliest ones are dropped
+    pub slow_log_capacity: usize,
+    #[clap(long = "warmup-path", value_name = "VALUE", value_delimiter = ',')]
+    /// The files and the directories the warmup role loads into the caches of
+    /// the mount, or the pin role pins and loads, with all the files below
+    /// the directories, separated by commas. They're relative to the root of
+    /// the volume, or under the mount point
+    pub warmup_paths: Vec<String>,
+    #[clap(long = "warmup-file-list", value_name = "VALUE", default_value_t)]
+    /// The file listing the files and the directories the warmup role or the
+    /// pin role loads, one in a line, such as the manifest of a dataset,
+    /// besides `--warmup-path`
+    pub warmup_file_list: String,
+    #[clap(long = "warmup-bandwidth", value_name = "VALUE", default_value_t)]
+    /// The MiB per second the warmup role or the pin role loads the files at
+    /// most, so the jobs running on the node are not starved. Not limited if
+    /// it's 0
+    pub warmup_bandwidth: u64,
+    #[clap(long = "unpin")]
+    /// Unpin the paths given by `--warmup-path` in the pin role, instead of
+    /// pinning them. The pin role lists the paths pinned if none is given
+    pub unpin: bool,
+    #[clap(long = "config-file", value_name = "VALUE", default_value_t)]
+    /// The TOML or YAML file setting the flags by their long names, which
+    /// the flags given on the command line take precedence over. The log
+    /// level, the log sample rate, the slow log threshold and the memory
+    /// budget are reloaded from it on `SIGHUP` or the `reload` command of the
+    /// control socket. Not read by default
+    pub config_file: String,
     #[clap(flatten)]
     /// Storage related config
     pub storage: StorageConfig,
     #[clap(flatten)]
     /// CSI related config
     pub csi_config: CSIConfig,
+    #[clap(flatten)]
+    /// Volume manager related config
+    pub volume_config: VolumeConfig,
+    #[clap(flatten)]
+    /// Benchmark related config
+    pub bench_config: BenchConfig,
+}
+
+impl Config {
+    /// Parse the command line args with the flags set by the config file of
+    /// `--config-file`, see `file`. The process exits on the invalid args,
+    /// like `Config::parse`.
+    #[inline]
+    pub fn load() -> DatenLordResult<Self> {
+        let args = with_config_file(std::env::args_os().collect())?;
+        Ok(Self::parse_from(args))
+    }
+
+    /// Parse `args` with the flags set by the config file of `--config-file`,
+    /// whose errors are returned instead, such as when the config file is
+    /// reloaded.
+    #[inline]
+    pub fn try_load_from<I, T>(args: I) -> DatenLordResult<Self>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString>,
+    {
+        let args = with_config_file(args.into_iter().map(Into::into).collect())?;
+        Self::try_parse_from(args).map_err(|e| DatenLordError::ArgumentInvalid {
+            context: vec![e.to_string()],
+        })
+    }
 }
 
 #[derive(Debug, Parser)]
@@ -43,23 +326,159 @@ pub struct StorageConfig {
     #[clap(long = "storage-type", value_name = "VALUE", default_value = "fs")]
     /// Storage type: s3, fs
     pub storage_type: String,
-    /// The size of blocks, default is 512 KiB.
+    /// The size of blocks (chunks of files), default is 4 MiB. It only takes
+    /// effect when the volume is created, and is fixed afterwards. A volume
+    /// created before its chunk size is persisted takes it as the chunk size
+    /// if it's given, or 512 KiB, the default then, otherwise.
+    #[clap(long = "storage-block-size", value_name = "VALUE")]
+    pub block_size: Option<usize>,
+    /// The maximum window of sequential read-ahead in blocks, default is 8.
+    /// Read-ahead is disabled if it's set to 0.
+    #[clap(
+        long = "storage-read-ahead-window",
+        value_name = "VALUE",
+        default_value_t = 8
+    )]
+    pub read_ahead_window: usize,
+    /// The compression of blocks in the backend: none, lz4, zstd, default is
+    /// none. It only takes effect when the volume is created, and is fixed
+    /// afterwards.
+    #[clap(
+        long = "storage-compression",
+        value_name = "VALUE",
+        default_value = "none"
+    )]
+    pub compression: String,
+    /// Keep the versions of blocks seen by snapshots by copy-on-write, which
+    /// is required to take snapshots of the volume. It only takes effect when
+    /// the volume is created, and is fixed afterwards. It cannot be enabled
+    /// with dedup.
+    #[clap(long = "storage-snapshot")]
+    pub snapshot: bool,
+    /// The capacity of the volume in bytes reported by `statfs`, default is 0,
+    /// which means unlimited.
+    #[clap(long = "storage-capacity", value_name = "VALUE", default_value_t = 0)]
+    pub capacity: u64,
+    /// The seconds removed files are kept in the trash before they're
+    /// collected, default is 0, which disables the trash.
+    #[clap(
+        long = "storage-trash-retention",
+        value_name = "VALUE",
+        default_value_t = 0
+    )]
+    pub trash_retention: u64,
+    /// The max size in bytes of the files whose content is inlined in their
+    /// metadata as well, so they're read without the storage once opened.
+    /// Default is 0, which disables inlining.
+    #[clap(
+        long = "storage-inline-data-threshold",
+        value_name = "VALUE",
+        default_value_t = 0
+    )]
+    pub inline_data_threshold: u64,
+    /// The max number of the children whose metadata is prefetched when a
+    /// directory is listed, so the queries following are served locally.
+    /// Default is 0, which disables the stat-ahead.
+    #[clap(
+        long = "storage-stat-ahead-limit",
+        value_name = "VALUE",
+        default_value_t = 0
+    )]
+    pub stat_ahead_limit: usize,
+    /// The seconds the names missing in directories are cached, so the
+    /// lookups of them are answered without the metadata store. Default is
+    /// 0, which disables the cache.
+    #[clap(
+        long = "storage-negative-cache-ttl",
+        value_name = "VALUE",
+        default_value_t = 0
+    )]
+    pub negative_cache_ttl: u64,
+    /// The max bytes of the directory entries cached, so the names looked up
+    /// again are resolved without the metadata store. Default is 0, which
+    /// disables the cache.
+    #[clap(
+        long = "storage-dentry-cache-capacity",
+        value_name = "VALUE",
+        default_value_t = 0
+    )]
+    pub dentry_cache_capacity: usize,
+    /// The max bytes of memory taken by the data cache, the metadata cache
+    /// and the buffers of in-flight requests altogether. Writes wait while
+    /// the budget is exhausted, and the data cache is evicted to the backend
+    /// until it's met again. Default is 0, which leaves the memory bounded
+    /// by the caches one by one.
+    #[clap(
+        long = "storage-memory-budget",
+        value_name = "VALUE",
+        default_value_t = 0
+    )]
+    pub memory_budget: usize,
+    /// Let the kernel cache the writes in its pages and write them back
+    /// later, which are written back by close, fsync and the recalls of the
+    /// leases. Default is false, the writes are sent to the daemon at once.
+    #[clap(long = "storage-writeback-cache")]
+    pub writeback_cache: bool,
+    /// The consistency among the nodes: strict, close-to-open, default is
+    /// strict. The caches of files are kept coherent by their leases in the
+    /// strict mode, while files are only flushed on close and revalidated on
+    /// open in the close-to-open mode, like NFS, with far fewer round trips
+    /// to the metadata store. It only takes effect when the volume is
+    /// created, and is fixed afterwards.
     #[clap(
-        long = "storage-block-size",
+        long = "storage-consistency",
         value_name = "VALUE",
-        default_value_t = 0x8_0000
+        default_value = "strict"
     )]
-    pub block_size: usize,
+    pub consistency: String,
+    /// The max percentage of the lowest local cache, the disk cache if it's
+    /// enabled or the memory cache otherwise, taken by the pinned files,
+    /// which are never evicted. Default is 50, 0 disables pinning.
+    #[clap(long = "storage-pin-limit", value_name = "VALUE", default_value_t = 50)]
+    pub pin_limit: u32,
     #[clap(flatten)]
     /// The memory cache config
     pub memory_cache_config: MemoryCacheConfig,
     #[clap(flatten)]
+    /// The disk cache config
+    pub disk_cache_config: DiskCacheConfig,
+    #[clap(flatten)]
+    /// The dedup config
+    pub dedup_config: DedupConfig,
+    #[clap(flatten)]
+    /// The pack config
+    pub pack_config: PackConfig,
+    #[clap(flatten)]
+    /// The checksum config
+    pub checksum_config: ChecksumConfig,
+    #[clap(flatten)]
+    /// The encryption config
+    pub encryption_config: EncryptionConfig,
+    #[clap(flatten)]
+    /// The GC config
+    pub gc_config: GcConfig,
+    #[clap(flatten)]
+    /// The tiering config
+    pub tiering_config: TieringConfig,
+    #[clap(flatten)]
+    /// The transfer config
+    pub transfer_config: TransferConfig,
+    #[clap(flatten)]
+    /// The replication config
+    pub replication_config: ReplicationConfig,
+    #[clap(flatten)]
+    /// The peer cache config
+    pub peer_cache_config: PeerCacheConfig,
+    #[clap(flatten)]
+    /// The backend error config
+    pub backend_error_config: BackendErrorConfig,
+    #[clap(flatten)]
     /// S3 storage config
     pub s3_storage_config: S3StorageConfig,
     #[clap(
         long = "storage-fs-root",
         value_name = "VALUE",
-        default_value = "/tmp/datenlord_backend"
+        default_value = DEFAULT_FS_STORAGE_ROOT
     )]
     /// The root of FS backend
     pub fs_storage_root: String,
@@ -94,6 +513,461 @@ pub struct MemoryCacheConfig {
     /// **Note**: `b` cannot be set to 0.
     #[clap(long = "storage-mem-cache-soft-limit", default_value = "3,5")]
     pub soft_limit: String,
+    /// The high watermark of dirty bytes in write-back policy, default is 1
+    /// GiB. Writes will wait for dirty blocks being flushed to the backend
+    /// once it's hit.
+    #[clap(
+        long = "storage-mem-cache-dirty-high-watermark",
+        value_name = "VALUE",
+        default_value_t = 0x4000_0000
+    )]
+    pub dirty_high_watermark: usize,
+    /// The max number of blocks uploaded to the backend concurrently in
+    /// write-back policy, default is 8
+    #[clap(
+        long = "storage-mem-cache-upload-concurrency",
+        value_name = "VALUE",
+        default_value_t = 8
+    )]
+    pub upload_concurrency: usize,
+    /// The evict policy of memory cache: lru, lfu, arc, clock. Default is
+    /// lru.
+    #[clap(
+        long = "storage-mem-cache-policy",
+        value_name = "VALUE",
+        default_value = "lru"
+    )]
+    pub policy: String,
+}
+
+/// Dedup config
+#[derive(Debug, Parser)]
+pub struct DedupConfig {
+    /// Deduplicate blocks in the backend by content-defined chunking. It only
+    /// takes effect when the volume is created, and is fixed afterwards.
+    #[clap(long = "storage-dedup")]
+    pub enabled: bool,
+    /// The average size of chunks in bytes, it must be a power of two.
+    /// Default is 64 KiB.
+    #[clap(
+        long = "storage-dedup-avg-chunk-size",
+        value_name = "VALUE",
+        default_value_t = 0x1_0000
+    )]
+    pub avg_chunk_size: usize,
+}
+
+/// Pack config
+#[derive(Debug, Parser)]
+pub struct PackConfig {
+    /// Pack small blocks inline into the metadata, or into shared containers
+    /// in the backend. It only takes effect when the volume is created, and
+    /// is fixed afterwards. It cannot be enabled with snapshot, dedup,
+    /// tiering or replication.
+    #[clap(long = "storage-pack")]
+    pub enabled: bool,
+    /// The max length in bytes of the blocks inlined into the metadata,
+    /// default is 4 KiB.
+    #[clap(
+        long = "storage-pack-inline-threshold",
+        value_name = "VALUE",
+        default_value_t = 0x1000
+    )]
+    pub inline_threshold: usize,
+    /// The max length in bytes of the blocks packed into containers, it must
+    /// not be less than the inline threshold. Default is 64 KiB.
+    #[clap(
+        long = "storage-pack-threshold",
+        value_name = "VALUE",
+        default_value_t = 0x1_0000
+    )]
+    pub pack_threshold: usize,
+}
+
+/// Checksum config
+#[derive(Debug, Parser)]
+pub struct ChecksumConfig {
+    /// Record the checksum of every block, and verify blocks read from the
+    /// backend. It only takes effect when the volume is created, and is fixed
+    /// afterwards.
+    #[clap(long = "storage-checksum")]
+    pub enabled: bool,
+    /// The interval in seconds between scrub passes, which verify the blocks
+    /// not accessed recently. Default is 1 day, and the scrubber is disabled
+    /// if it's set to 0.
+    #[clap(
+        long = "storage-scrub-interval",
+        value_name = "VALUE",
+        default_value_t = 86400
+    )]
+    pub scrub_interval: u64,
+}
+
+/// GC config
+#[derive(Debug, Parser)]
+pub struct GcConfig {
+    /// The interval in seconds between GC passes, which delete the objects in
+    /// the backend referenced by neither any file nor any snapshot. Default is
+    /// 1 day, and GC is disabled if it's set to 0.
+    #[clap(
+        long = "storage-gc-interval",
+        value_name = "VALUE",
+        default_value_t = 86400
+    )]
+    pub interval: u64,
+    /// The maximum number of objects deleted by GC per second, default is
+    /// 100, and deletes are not rate limited if it's set to 0.
+    #[clap(long = "storage-gc-rate", value_name = "VALUE", default_value_t = 100)]
+    pub rate: u32,
+    /// Only report the orphaned objects and the space to reclaim, without
+    /// deleting them.
+    #[clap(long = "storage-gc-dry-run")]
+    pub dry_run: bool,
+}
+
+/// Tiering config
+#[derive(Debug, Parser)]
+pub struct TieringConfig {
+    /// The directory of the hot tier, usually on SSD, blocks are migrated
+    /// between it and the backend, which is the cold tier. Tiering is disabled
+    /// if it's not set. The directory must be reachable by all nodes mounting
+    /// the volume.
+    #[clap(long = "storage-tier-hot-dir", value_name = "VALUE", default_value_t)]
+    pub hot_dir: String,
+    /// The seconds after which a block not accessed is migrated to the cold
+    /// tier, default is 7 days.
+    #[clap(
+        long = "storage-tier-cold-after",
+        value_name = "VALUE",
+        default_value_t = 604_800
+    )]
+    pub cold_after: u64,
+    /// The number of accesses in a migration interval, after which a block in
+    /// the cold tier is migrated to the hot tier, default is 4.
+    #[clap(
+        long = "storage-tier-promote-accesses",
+        value_name = "VALUE",
+        default_value_t = 4
+    )]
+    pub promote_accesses: u32,
+    /// The interval in seconds between migration passes, default is 1 hour.
+    #[clap(
+        long = "storage-tier-interval",
+        value_name = "VALUE",
+        default_value_t = 3600
+    )]
+    pub interval: u64,
+    /// The rules pinning the files under paths to tiers, in the form of
+    /// `PATH=TIER`, where `TIER` is `hot` or `cold`, separated by commas. The
+    /// longest matching path wins, and the `user.datenlord.tier` extended
+    /// attribute of a file or its ancestors takes precedence over the rules.
+    #[clap(
+        long = "storage-tier-rule",
+        value_name = "VALUE",
+        value_delimiter = ','
+    )]
+    pub rules: Vec<String>,
+}
+
+/// Transfer config
+#[derive(Debug, Parser)]
+pub struct TransferConfig {
+    /// The address the transfer service listens on, such as `0.0.0.0:8900`,
+    /// which serves the blocks of the node to other nodes. The service is
+    /// disabled if it's not set.
+    #[clap(
+        long = "storage-transfer-listen",
+        value_name = "VALUE",
+        default_value_t
+    )]
+    pub listen: String,
+    /// The directory of the blocks served by the transfer service.
+    #[clap(long = "storage-transfer-dir", value_name = "VALUE", default_value_t)]
+    pub dir: String,
+    /// The timeout in seconds of a transfer not issued by a FUSE request,
+    /// default is 30. The transfers issued by a FUSE request share the
+    /// deadline of the request.
+    #[clap(
+        long = "storage-transfer-timeout",
+        value_name = "VALUE",
+        default_value_t = 30
+    )]
+    pub timeout: u64,
+    /// The PEM certificate of the node, the transfers are over TLS if it's
+    /// set, and the key and the CA certificate are required as well.
+    #[clap(
+        long = "storage-transfer-tls-cert",
+        value_name = "VALUE",
+        default_value_t
+    )]
+    pub tls_cert_file: String,
+    /// The PEM private key of the certificate of the node.
+    #[clap(
+        long = "storage-transfer-tls-key",
+        value_name = "VALUE",
+        default_value_t
+    )]
+    pub tls_key_file: String,
+    /// The PEM certificate of the CA verifying the certificates of the other
+    /// nodes.
+    #[clap(
+        long = "storage-transfer-tls-ca",
+        value_name = "VALUE",
+        default_value_t
+    )]
+    pub tls_ca_file: String,
+    /// Require the clients to present their certificates, which are verified
+    /// by the CA, that is mutual TLS.
+    #[clap(long = "storage-transfer-mtls")]
+    pub mtls: bool,
+}
+
+/// Replication config
+#[derive(Debug, Parser)]
+pub struct ReplicationConfig {
+    /// The number of replicas of every block, which are stored by the transfer
+    /// services of the replica nodes instead of the backend. Replication is
+    /// disabled if it's 0.
+    #[clap(
+        long = "storage-replication-factor",
+        value_name = "VALUE",
+        default_value_t = 0
+    )]
+    pub factor: u32,
+    /// The number of replicas persisting a block before a write is
+    /// acknowledged, which is `all`, `majority` or a number, default is `all`.
+    #[clap(
+        long = "storage-replication-quorum",
+        value_name = "VALUE",
+        default_value = "all"
+    )]
+    pub quorum: String,
+    /// The replica nodes in the form of `NODE_ID=ENDPOINT`, such as
+    /// `node1=http://10.0.0.1:8900`, separated by commas.
+    #[clap(
+        long = "storage-replication-node",
+        value_name = "VALUE",
+        value_delimiter = ','
+    )]
+    pub nodes: Vec<String>,
+    /// The time in seconds a replica node is not seen registered before it's
+    /// dead, default is 30. The blocks with replicas on a dead node are
+    /// replicated to other nodes.
+    #[clap(
+        long = "storage-replication-failure-timeout",
+        value_name = "VALUE",
+        default_value_t = 30
+    )]
+    pub failure_timeout: u64,
+    /// The maximal number of blocks moved per second, when the replicas are
+    /// rebalanced among the replica nodes changed, default is 100. The moves
+    /// are not rate limited if it's 0.
+    #[clap(
+        long = "storage-replication-rebalance-rate",
+        value_name = "VALUE",
+        default_value_t = 100
+    )]
+    pub rebalance_rate: u32,
+    /// The percentile of the latencies of the recent reads of replicas, a
+    /// read slower than which is hedged by a read of another replica, default
+    /// is 99. The reads are not hedged if it's 0.
+    #[clap(
+        long = "storage-replication-hedge-percentile",
+        value_name = "VALUE",
+        default_value_t = 99
+    )]
+    pub hedge_percentile: u32,
+    /// The number of consecutive failures of a replica node tripping its
+    /// circuit breaker, default is 5. The operations on the node fail fast
+    /// once it trips, so the reads go to the other replicas. The nodes are not
+    /// guarded by breakers if it's 0.
+    #[clap(
+        long = "storage-replication-breaker-failures",
+        value_name = "VALUE",
+        default_value_t = 5
+    )]
+    pub breaker_failures: u32,
+    /// The time in seconds the operations on a replica node fail fast after
+    /// its circuit breaker trips, default is 10. An operation is passed to
+    /// probe the node afterwards, which closes the breaker if it succeeds.
+    #[clap(
+        long = "storage-replication-breaker-cooldown",
+        value_name = "VALUE",
+        default_value_t = 10
+    )]
+    pub breaker_cooldown: u64,
+    /// The layout of blocks on the replica nodes, which is `replicate`,
+    /// `erasure` or `tiered`, default is `replicate`. Blocks are erasure coded
+    /// into data and parity shards on the replica nodes with `erasure`, and
+    /// they're replicated while hot and erasure coded once cold with `tiered`.
+    #[clap(
+        long = "storage-replication-policy",
+        value_name = "VALUE",
+        default_value = "replicate"
+    )]
+    pub policy: String,
+    /// The number of data shards of an erasure coded block, default is 4.
+    #[clap(
+        long = "storage-replication-ec-data-shards",
+        value_name = "VALUE",
+        default_value_t = 4
+    )]
+    pub ec_data_shards: u32,
+    /// The number of parity shards of an erasure coded block, default is 2.
+    /// A block is still readable with this many of its shards lost.
+    #[clap(
+        long = "storage-replication-ec-parity-shards",
+        value_name = "VALUE",
+        default_value_t = 2
+    )]
+    pub ec_parity_shards: u32,
+    /// The seconds after which a block not accessed is erasure coded with the
+    /// `tiered` policy, default is 7 days.
+    #[clap(
+        long = "storage-replication-ec-cold-after",
+        value_name = "VALUE",
+        default_value_t = 604_800
+    )]
+    pub ec_cold_after: u64,
+    /// The number of accesses in a conversion interval, after which an
+    /// erasure coded block is replicated again with the `tiered` policy,
+    /// default is 4.
+    #[clap(
+        long = "storage-replication-ec-promote-accesses",
+        value_name = "VALUE",
+        default_value_t = 4
+    )]
+    pub ec_promote_accesses: u32,
+    /// The interval in seconds between passes converting blocks between
+    /// replicas and shards with the `tiered` policy, default is 1 hour.
+    #[clap(
+        long = "storage-replication-ec-interval",
+        value_name = "VALUE",
+        default_value_t = 3600
+    )]
+    pub ec_interval: u64,
+}
+
+/// Peer cache config
+#[derive(Debug, Parser)]
+pub struct PeerCacheConfig {
+    /// The endpoint the other nodes reach the transfer service of the node
+    /// at, such as `http://10.0.0.1:8900`. The blocks in the caches of the
+    /// node are served to the other nodes by it, and the blocks missed in the
+    /// caches are fetched from theirs before the backend. The caches are not
+    /// shared if it's not set, and the transfer service is required.
+    #[clap(
+        long = "storage-peer-cache-endpoint",
+        value_name = "VALUE",
+        default_value_t
+    )]
+    pub endpoint: String,
+    /// The zone of the node, such as its rack, the blocks are only fetched
+    /// from the caches of the nodes in the same zone. All the nodes are in one
+    /// zone if it's not set.
+    #[clap(
+        long = "storage-peer-cache-zone",
+        value_name = "VALUE",
+        default_value_t
+    )]
+    pub zone: String,
+    /// The milliseconds a fetch from the cache of another node times out
+    /// after, default is 500. The block is loaded from the backend then.
+    #[clap(
+        long = "storage-peer-cache-timeout",
+        value_name = "VALUE",
+        default_value_t = 500
+    )]
+    pub timeout: u64,
+}
+
+/// Encryption config
+#[derive(Debug, Parser)]
+pub struct EncryptionConfig {
+    /// The file of the master key, with 32 raw bytes or 64 hex digits, which
+    /// is usually written by a KMS agent. Blocks are encrypted if it's set
+    /// when the volume is created, and it's required afterwards.
+    #[clap(
+        long = "storage-encryption-master-key-file",
+        value_name = "VALUE",
+        default_value_t
+    )]
+    pub master_key_file: String,
+    /// The file of the previous master key, for rotating the master key. Data
+    /// keys wrapped by it are re-wrapped by the current master key.
+    #[clap(
+        long = "storage-encryption-old-master-key-file",
+        value_name = "VALUE",
+        default_value_t
+    )]
+    pub old_master_key_file: String,
+    /// Generate a new data key for the following writes on start, blocks
+    /// written with older data keys are still readable.
+    #[clap(long = "storage-encryption-rotate-data-key")]
+    pub rotate_data_key: bool,
+}
+
+/// Disk cache config
+#[derive(Debug, Parser)]
+pub struct DiskCacheConfig {
+    /// The directory of disk cache, the disk cache is disabled if it's not
+    /// set.
+    ///
+    /// **Note**: everything in the directory will be removed on start, but the
+    /// blocks persisted with `--storage-disk-cache-persist`.
+    #[clap(long = "storage-disk-cache-dir", value_name = "VALUE", default_value_t)]
+    pub dir: String,
+    /// The capacity of disk cache in bytes, default is 64 GiB.
+    #[clap(
+        long = "storage-disk-cache-capacity",
+        value_name = "VALUE",
+        default_value_t = 0x10_0000_0000
+    )]
+    pub capacity: usize,
+    /// The evict policy of disk cache: lru, lfu, arc, clock. Default is lru.
+    #[clap(
+        long = "storage-disk-cache-policy",
+        value_name = "VALUE",
+        default_value = "lru"
+    )]
+    pub policy: String,
+    /// Persist the index of disk cache on shutdown, so the blocks of the files
+    /// unchanged are kept across restarts.
+    #[clap(long = "storage-disk-cache-persist")]
+    pub persist: bool,
+}
+
+/// Backend error config
+#[derive(Debug, Parser)]
+pub struct BackendErrorConfig {
+    /// The retries of the transient failures of the backend, such as `503
+    /// SlowDown` or a connection reset, by operation class, in the form of
+    /// `CLASS=N`, where `CLASS` is `read`, `write`, `delete` or `list`,
+    /// separated by commas. A class not given is retried 3 times.
+    #[clap(
+        long = "storage-backend-retries",
+        value_name = "VALUE",
+        value_delimiter = ','
+    )]
+    pub retries: Vec<String>,
+    /// The milliseconds before the first retry of a failure of the backend,
+    /// doubled on every retry after it, default is 100.
+    #[clap(
+        long = "storage-backend-retry-backoff",
+        value_name = "VALUE",
+        default_value_t = 100
+    )]
+    pub retry_backoff: u64,
+    /// The error codes of the backend mapped to the errors returned to users,
+    /// in the form of `CODE=ERRNO`, such as `QuotaExceeded=EDQUOT`, or
+    /// `CODE=retry` to retry them as the transient failures, separated by
+    /// commas. They take precedence over the built-in mappings.
+    #[clap(
+        long = "storage-backend-error-map",
+        value_name = "VALUE",
+        value_delimiter = ','
+    )]
+    pub error_map: Vec<String>,
 }
 
 /// S3 storage config
@@ -123,6 +997,36 @@ pub struct S3StorageConfig {
     #[clap(long = "storage-s3-bucket", value_name = "VALUE", default_value_t)]
     /// The bucket name of s3 storage
     pub bucket_name: String,
+    #[clap(
+        long = "storage-s3-region",
+        value_name = "VALUE",
+        default_value = "auto"
+    )]
+    /// The region of s3 storage, default is `auto`
+    pub region: String,
+    #[clap(long = "storage-s3-prefix", value_name = "VALUE", default_value_t)]
+    /// The key prefix of objects in the bucket, default is the bucket root
+    pub prefix: String,
+    /// The part size of multipart uploads, default is 8 MiB.
+    ///
+    /// Writes larger than this size are uploaded in parts of this size.
+    #[clap(
+        long = "storage-s3-part-size",
+        value_name = "VALUE",
+        default_value_t = 0x80_0000
+    )]
+    pub part_size: usize,
+    /// The max number of concurrent streams fetching a block, default is 4.
+    ///
+    /// A large block is split into ranges of at least 1 MiB, which are fetched
+    /// concurrently, so the reads of a single file are not capped by one
+    /// connection.
+    #[clap(
+        long = "storage-s3-read-streams",
+        value_name = "VALUE",
+        default_value_t = 4
+    )]
+    pub read_streams: usize,
 }
 
 /// CSI related config
@@ -137,20 +1041,110 @@ pub struct CSIConfig {
     #[clap(long = "csi-worker-port", value_name = "VALUE", default_value_t)]
     /// The worker port of csi server
     pub worker_port: u16,
+    #[clap(long = "csi-mount-namespace", value_name = "VALUE", default_value_t)]
+    /// The mount namespace the volumes are published in, such as
+    /// `/proc/1/ns/mnt` of the host for a node plugin with `hostPID`, instead
+    /// of the one of the plugin. Not given by default
+    pub mount_namespace: String,
 }
 
-#[cfg(test)]
-#[allow(clippy::unwrap_used)]
-mod tests {
-    use std::net::IpAddr;
-    use std::num::NonZeroUsize;
-    use std::str::FromStr;
-
-    use super::*;
-    use crate::config::inner::{InnerConfig, Role, StorageParams as InnerStorageParams};
-    use crate::config::SoftLimit;
+/// Volume manager related config
+#[derive(Debug, Clone, Parser)]
+pub struct VolumeConfig {
+    #[clap(
+        long = "volume-manager-listen",
+        value_name = "VALUE",
+        default_value = "0.0.0.0:7950"
+    )]
+    /// The address the volumeManager role serves the admin API on
+    pub listen: String,
+    #[clap(
+        long = "volume-manager-endpoint",
+        value_name = "VALUE",
+        default_value_t
+    )]
+    /// The endpoint of the volume manager, such as `http://10.0.0.1:7950`.
+    /// The volume role talks to it, and the CSI driver manages volumes with
+    /// it if it's set.
+    pub endpoint: String,
+    #[clap(long = "volume-name", value_name = "VALUE", default_value_t)]
+    /// The name of a volume. The volume role creates it, or lists the volumes
+    /// if it's empty.
+    pub name: String,
+    #[clap(long = "volume-capacity", value_name = "VALUE", default_value_t)]
+    /// The capacity in bytes of the volume to create or resize, 0 for
+    /// unlimited
+    pub capacity: u64,
+    #[clap(long = "volume-policy", value_name = "VALUE", default_value = "tiered")]
+    /// The storage policy of the volume to create: replicate, erasure or
+    /// tiered. It only takes effect when the storage policy of the file
+    /// system is tiered, and tiered follows the hotness of blocks.
+    pub policy: String,
+    #[clap(long = "volume-delete")]
+    /// Delete the volume instead of creating it in the volume role
+    pub delete: bool,
+    #[clap(long = "volume-resize")]
+    /// Resize the volume to the capacity instead of creating it in the volume
+    /// role
+    pub resize: bool,
+    #[clap(long = "admin-command", value_name = "VALUE", default_value = "list")]
+    /// The command of the admin role: list, create, delete, resize, quota,
+    /// snapshot, snapshots, delete-snapshot, nodes, scrub or gc
+    pub command: String,
+}
 
-    #[test]
+/// Benchmark config
+#[derive(Debug, Parser)]
+pub struct BenchConfig {
+    #[clap(long = "bench-target", value_name = "VALUE", default_value = "core")]
+    /// What the bench role drives: `core` for the file system of the volume
+    /// in the process, bypassing the kernel, or `mount` for the volume
+    /// mounted by FUSE on `--mount-path`
+    pub target: String,
+    #[clap(
+        long = "bench-block-sizes",
+        value_name = "VALUE",
+        value_delimiter = ',',
+        default_value = "4096,131072,1048576"
+    )]
+    /// The block sizes in bytes the bench role writes and reads by,
+    /// separated by commas
+    pub block_sizes: Vec<u64>,
+    #[clap(
+        long = "bench-file-size",
+        value_name = "VALUE",
+        default_value_t = 67_108_864
+    )]
+    /// The bytes the bench role writes and reads by every block size
+    pub file_size: u64,
+    #[clap(long = "bench-files", value_name = "VALUE", default_value_t = 1000)]
+    /// The files the bench role creates, stats and removes
+    pub files: usize,
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::net::IpAddr;
+    use std::num::{NonZeroU64, NonZeroUsize};
+    use std::path::PathBuf;
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    use tracing::level_filters::LevelFilter;
+
+    use super::*;
+    use crate::config::inner::{
+        InnerConfig, Role, StorageParams as InnerStorageParams, OVERFLOW_ID,
+    };
+    use crate::config::{
+        AdminCommand, AuthConfig, BackendErrorAction, BackendErrorConfig, BackendErrorMapping,
+        BackendOpClass, BenchTarget, BreakerPolicy, CompressionType, ConsistencyModel,
+        ErasureShards, EvictPolicyType, HealthProbe, LogFormat, OpClass, OpMask, OutputFormat,
+        RetryBudgets, SoftLimit, SquashMode, StoragePolicy, Tier, TierRule, WriteQuorum,
+    };
+
+    #[test]
     #[allow(clippy::indexing_slicing)]
     fn test_basic_config() {
         // Set the args
@@ -172,6 +1166,8 @@ mod tests {
             "io.datenlord.csi.plugin",
             "--csi-worker-port",
             "9001",
+            "--csi-mount-namespace",
+            "/proc/1/ns/mnt",
             "--storage-fs-root",
             "/tmp/datenlord_backend",
         ];
@@ -208,7 +1204,29 @@ mod tests {
             InnerStorageParams::Fs(root) => assert_eq!(root, "/tmp/datenlord_backend"),
             InnerStorageParams::S3(_) => panic!("storage params should be Fs"),
         }
-        assert_eq!(storage_config.block_size, 0x8_0000);
+        assert_eq!(storage_config.block_size, 0x40_0000);
+        assert_eq!(storage_config.legacy_block_size, 0x8_0000);
+        assert_eq!(storage_config.read_ahead_window, 8);
+        assert_eq!(storage_config.compression, CompressionType::None);
+        assert!(storage_config.dedup_config.is_none());
+        assert!(storage_config.pack_config.is_none());
+        assert!(storage_config.checksum_config.is_none());
+        assert!(storage_config.encryption_config.is_none());
+        assert!(storage_config.disk_cache_config.is_none());
+        assert!(storage_config.capacity.is_none());
+        assert!(storage_config.trash_retention.is_none());
+        assert!(storage_config.inline_data_threshold.is_none());
+        assert!(storage_config.stat_ahead_limit.is_none());
+        assert!(storage_config.negative_cache_ttl.is_none());
+        assert!(storage_config.dentry_cache_capacity.is_none());
+        assert!(storage_config.memory_budget.is_none());
+        assert!(!storage_config.writeback_cache);
+        assert_eq!(storage_config.consistency, ConsistencyModel::Strict);
+        assert_eq!(storage_config.pin_limit, Some(50));
+        assert!(storage_config.gc_config.is_some());
+        assert!(storage_config.tiering_config.is_none());
+        assert!(storage_config.transfer_config.is_none());
+        assert!(storage_config.replication_config.is_none());
 
         let memory_cache_config = storage_config.memory_cache_config;
         assert_eq!(memory_cache_config.capacity, 0x2_0000_0000);
@@ -218,11 +1236,18 @@ mod tests {
             memory_cache_config.soft_limit,
             SoftLimit(3, NonZeroUsize::new(5).unwrap())
         );
+        assert_eq!(memory_cache_config.dirty_high_watermark, 0x4000_0000);
+        assert_eq!(memory_cache_config.upload_concurrency, 8);
+        assert_eq!(memory_cache_config.policy, EvictPolicyType::Lru);
 
         let csi_config = inner_config.csi_config;
         assert_eq!(csi_config.endpoint, "unix:///tmp/node.sock ");
         assert_eq!(csi_config.driver_name, "io.datenlord.csi.plugin");
         assert_eq!(csi_config.worker_port, 9001);
+        assert_eq!(
+            csi_config.mount_namespace,
+            Some(PathBuf::from("/proc/1/ns/mnt"))
+        );
     }
 
     #[test]
@@ -252,6 +1277,12 @@ mod tests {
             "test_secret_key",
             "--storage-s3-bucket",
             "test_bucket",
+            "--storage-s3-prefix",
+            "datenlord/volume0",
+            "--storage-s3-part-size",
+            "5242880",
+            "--storage-s3-read-streams",
+            "8",
             "--kv-server-list",
             "127.0.0.1:7890,127.0.0.1:7891",
         ];
@@ -263,6 +1294,10 @@ mod tests {
                 assert_eq!(s3_config.access_key_id, "test_access_key");
                 assert_eq!(s3_config.secret_access_key, "test_secret_key");
                 assert_eq!(s3_config.bucket_name, "test_bucket");
+                assert_eq!(s3_config.region, "auto");
+                assert_eq!(s3_config.prefix, "datenlord/volume0");
+                assert_eq!(s3_config.part_size, 5_242_880);
+                assert_eq!(s3_config.read_streams, 8);
             }
             InnerStorageParams::Fs(_) => panic!("storage params should be S3"),
         }
@@ -295,6 +1330,10 @@ mod tests {
             "--storage-mem-cache-write-back",
             "--storage-mem-cache-soft-limit",
             "1,2",
+            "--storage-mem-cache-dirty-high-watermark",
+            "4096",
+            "--storage-mem-cache-upload-concurrency",
+            "16",
         ];
 
         let config = Config::parse_from(args);
@@ -305,6 +1344,8 @@ mod tests {
         assert_eq!(memory_cache_config.command_queue_limit, 2000);
         assert!(memory_cache_config.write_back);
         assert_eq!(memory_cache_config.soft_limit, "1,2");
+        assert_eq!(memory_cache_config.dirty_high_watermark, 4096);
+        assert_eq!(memory_cache_config.upload_concurrency, 16);
 
         let config: InnerConfig = config.try_into().unwrap();
         let memory_cache_config = &config.storage.memory_cache_config;
@@ -314,13 +1355,90 @@ mod tests {
         assert_eq!(memory_cache_config.command_queue_limit, 2000);
         assert!(memory_cache_config.write_back);
         assert_eq!(memory_cache_config.soft_limit, soft_limit);
+        assert_eq!(memory_cache_config.dirty_high_watermark, 4096);
+        assert_eq!(memory_cache_config.upload_concurrency, 16);
     }
 
     #[test]
-    #[allow(clippy::assertions_on_result_states)]
-    fn test_invalid_soft_limit() {
-        let build_args = |soft_limit: &'static str| {
-            vec![
+    fn test_disk_cache_config() {
+        let args = vec![
+            "datenlord",
+            "--role",
+            "node",
+            "--node-name",
+            "node1",
+            "--node-ip",
+            "127.0.0.1",
+            "--mount-path",
+            "/tmp/datenlord_data_dir",
+            "--kv-server-list",
+            "127.0.0.1:7890,127.0.0.1:7891",
+            "--csi-endpoint",
+            "unix:///tmp/node.sock ",
+            "--csi-driver-name",
+            "io.datenlord.csi.plugin",
+            "--csi-worker-port",
+            "9001",
+            "--storage-disk-cache-dir",
+            "/tmp/datenlord_disk_cache",
+            "--storage-disk-cache-capacity",
+            "1073741824",
+            "--storage-disk-cache-policy",
+            "arc",
+            "--storage-disk-cache-persist",
+        ];
+
+        let config = Config::parse_from(args);
+        assert_eq!(
+            config.storage.disk_cache_config.dir,
+            "/tmp/datenlord_disk_cache"
+        );
+
+        let config: InnerConfig = config.try_into().unwrap();
+        let disk_cache_config = config.storage.disk_cache_config.unwrap();
+        assert_eq!(disk_cache_config.dir, "/tmp/datenlord_disk_cache");
+        assert_eq!(disk_cache_config.capacity, 1_073_741_824);
+        assert_eq!(disk_cache_config.policy, EvictPolicyType::Arc);
+        assert!(disk_cache_config.persist);
+    }
+
+    #[test]
+    fn test_dedup_config() {
+        let args = vec![
+            "datenlord",
+            "--role",
+            "node",
+            "--node-name",
+            "node1",
+            "--node-ip",
+            "127.0.0.1",
+            "--mount-path",
+            "/tmp/datenlord_data_dir",
+            "--kv-server-list",
+            "127.0.0.1:7890,127.0.0.1:7891",
+            "--csi-endpoint",
+            "unix:///tmp/node.sock ",
+            "--csi-driver-name",
+            "io.datenlord.csi.plugin",
+            "--csi-worker-port",
+            "9001",
+            "--storage-dedup",
+            "--storage-dedup-avg-chunk-size",
+            "16384",
+        ];
+
+        let config = Config::parse_from(args);
+        assert!(config.storage.dedup_config.enabled);
+
+        let config: InnerConfig = config.try_into().unwrap();
+        let dedup_config = config.storage.dedup_config.unwrap();
+        assert_eq!(dedup_config.avg_chunk_size, 16384);
+    }
+
+    #[test]
+    fn test_pack_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
                 "datenlord",
                 "--role",
                 "node",
@@ -336,61 +1454,2169 @@ mod tests {
                 "unix:///tmp/node.sock ",
                 "--csi-driver-name",
                 "io.datenlord.csi.plugin",
-                "--storage-mem-cache-soft-limit",
-                soft_limit,
                 "--csi-worker-port",
                 "9001",
-            ]
+                "--storage-pack",
+            ];
+            args.extend_from_slice(extra_args);
+            args
         };
 
-        let config: Result<InnerConfig, _> = Config::parse_from(build_args("a,1")).try_into();
-        assert!(config.is_err());
+        let config: InnerConfig = Config::parse_from(build_args(&[])).try_into().unwrap();
+        let pack_config = config.storage.pack_config.unwrap();
+        assert_eq!(pack_config.inline_threshold, 4096);
+        assert_eq!(pack_config.pack_threshold, 65536);
 
-        let config: Result<InnerConfig, _> = Config::parse_from(build_args("1,a")).try_into();
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--storage-pack-inline-threshold",
+            "1024",
+            "--storage-pack-threshold",
+            "16384",
+        ]))
+        .try_into()
+        .unwrap();
+        let pack_config = config.storage.pack_config.unwrap();
+        assert_eq!(pack_config.inline_threshold, 1024);
+        assert_eq!(pack_config.pack_threshold, 16384);
+
+        // The inline threshold cannot exceed the pack threshold.
+        let config: Result<InnerConfig, _> = Config::parse_from(build_args(&[
+            "--storage-pack-inline-threshold",
+            "16384",
+            "--storage-pack-threshold",
+            "1024",
+        ]))
+        .try_into();
         assert!(config.is_err());
 
-        let config: Result<InnerConfig, _> = Config::parse_from(build_args("1,0")).try_into();
+        // Packing cannot be enabled with dedup.
+        let config: Result<InnerConfig, _> =
+            Config::parse_from(build_args(&["--storage-dedup"])).try_into();
         assert!(config.is_err());
     }
 
     #[test]
-    #[allow(clippy::indexing_slicing)]
-    fn test_csi_controller_config() {
-        // Set the args
+    fn test_inline_data_config() {
         let args = vec![
             "datenlord",
             "--role",
-            "controller",
+            "node",
+            "--node-name",
+            "node1",
+            "--node-ip",
+            "127.0.0.1",
+            "--mount-path",
+            "/tmp/datenlord_data_dir",
+            "--kv-server-list",
+            "127.0.0.1:7890,127.0.0.1:7891",
             "--csi-endpoint",
-            "unix:///tmp/controller.sock",
+            "unix:///tmp/node.sock ",
             "--csi-driver-name",
             "io.datenlord.csi.plugin",
             "--csi-worker-port",
             "9001",
+            "--storage-inline-data-threshold",
+            "2048",
+        ];
+
+        let config: InnerConfig = Config::parse_from(args).try_into().unwrap();
+        assert_eq!(config.storage.inline_data_threshold, Some(2048));
+    }
+
+    #[test]
+    fn test_metadata_cache_config() {
+        let args = vec![
+            "datenlord",
+            "--role",
+            "node",
             "--node-name",
-            "localhost",
+            "node1",
             "--node-ip",
             "127.0.0.1",
             "--mount-path",
             "/tmp/datenlord_data_dir",
             "--kv-server-list",
-            "127.0.0.1:7890",
+            "127.0.0.1:7890,127.0.0.1:7891",
+            "--csi-endpoint",
+            "unix:///tmp/node.sock ",
+            "--csi-driver-name",
+            "io.datenlord.csi.plugin",
+            "--csi-worker-port",
+            "9001",
+            "--storage-stat-ahead-limit",
+            "1024",
+            "--storage-negative-cache-ttl",
+            "5",
+            "--storage-dentry-cache-capacity",
+            "1048576",
+            "--storage-memory-budget",
+            "4294967296",
         ];
+
         let config: InnerConfig = Config::parse_from(args).try_into().unwrap();
-        assert_eq!(config.role, Role::Controller);
-        assert_eq!(config.node_name, "localhost");
-        assert_eq!(config.node_ip, IpAddr::from_str("127.0.0.1").unwrap());
-        assert_eq!(config.mount_path.as_str(), "/tmp/datenlord_data_dir");
-        assert_eq!(config.server_port, 8800);
+        assert_eq!(config.storage.stat_ahead_limit, Some(1024));
+        assert_eq!(
+            config.storage.negative_cache_ttl,
+            Some(std::time::Duration::from_secs(5))
+        );
+        assert_eq!(config.storage.dentry_cache_capacity, Some(1_048_576));
+        assert_eq!(config.storage.memory_budget, Some(0x1_0000_0000));
+    }
 
-        let kv_addrs = config.kv_addrs;
-        assert_eq!(kv_addrs.len(), 1);
-        assert_eq!(kv_addrs[0], "127.0.0.1:7890");
+    #[test]
+    fn test_consistency_config() {
+        let build_args = |consistency: &'static str| {
+            vec![
+                "datenlord",
+                "--role",
+                "node",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890,127.0.0.1:7891",
+                "--csi-endpoint",
+                "unix:///tmp/node.sock ",
+                "--csi-driver-name",
+                "io.datenlord.csi.plugin",
+                "--csi-worker-port",
+                "9001",
+                "--storage-consistency",
+                consistency,
+            ]
+        };
 
-        let csi_config = config.csi_config;
-        assert_eq!(csi_config.endpoint, "unix:///tmp/controller.sock");
-        assert_eq!(csi_config.driver_name, "io.datenlord.csi.plugin");
-        assert_eq!(csi_config.worker_port, 9001);
+        let config: InnerConfig = Config::parse_from(build_args("close-to-open"))
+            .try_into()
+            .unwrap();
+        assert_eq!(config.storage.consistency, ConsistencyModel::CloseToOpen);
+
+        let config: Result<InnerConfig, _> = Config::parse_from(build_args("eventual")).try_into();
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn test_pin_limit_config() {
+        let build_args = |pin_limit: &'static str| {
+            vec![
+                "datenlord",
+                "--role",
+                "node",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890,127.0.0.1:7891",
+                "--csi-endpoint",
+                "unix:///tmp/node.sock ",
+                "--csi-driver-name",
+                "io.datenlord.csi.plugin",
+                "--csi-worker-port",
+                "9001",
+                "--storage-pin-limit",
+                pin_limit,
+            ]
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args("80")).try_into().unwrap();
+        assert_eq!(config.storage.pin_limit, Some(80));
+        let config: InnerConfig = Config::parse_from(build_args("0")).try_into().unwrap();
+        assert!(config.storage.pin_limit.is_none());
+
+        let config: Result<InnerConfig, _> = Config::parse_from(build_args("101")).try_into();
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn test_checksum_config() {
+        let build_args = |scrub_interval: &'static str| {
+            vec![
+                "datenlord",
+                "--role",
+                "node",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890,127.0.0.1:7891",
+                "--csi-endpoint",
+                "unix:///tmp/node.sock ",
+                "--csi-driver-name",
+                "io.datenlord.csi.plugin",
+                "--csi-worker-port",
+                "9001",
+                "--storage-checksum",
+                "--storage-scrub-interval",
+                scrub_interval,
+            ]
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args("3600")).try_into().unwrap();
+        let checksum_config = config.storage.checksum_config.unwrap();
+        assert_eq!(
+            checksum_config.scrub_interval,
+            Some(std::time::Duration::from_secs(3600))
+        );
+
+        let config: InnerConfig = Config::parse_from(build_args("0")).try_into().unwrap();
+        let checksum_config = config.storage.checksum_config.unwrap();
+        assert!(checksum_config.scrub_interval.is_none());
+    }
+
+    #[test]
+    fn test_gc_config() {
+        let build_args = |interval: &'static str| {
+            vec![
+                "datenlord",
+                "--role",
+                "node",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890,127.0.0.1:7891",
+                "--csi-endpoint",
+                "unix:///tmp/node.sock ",
+                "--csi-driver-name",
+                "io.datenlord.csi.plugin",
+                "--csi-worker-port",
+                "9001",
+                "--storage-gc-interval",
+                interval,
+                "--storage-gc-rate",
+                "10",
+                "--storage-gc-dry-run",
+            ]
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args("3600")).try_into().unwrap();
+        let gc_config = config.storage.gc_config.unwrap();
+        assert_eq!(gc_config.interval, std::time::Duration::from_secs(3600));
+        assert_eq!(gc_config.rate, 10);
+        assert!(gc_config.dry_run);
+
+        let config: InnerConfig = Config::parse_from(build_args("0")).try_into().unwrap();
+        assert!(config.storage.gc_config.is_none());
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_tiering_config() {
+        let build_args = |rules: &'static str| {
+            vec![
+                "datenlord",
+                "--role",
+                "node",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890,127.0.0.1:7891",
+                "--csi-endpoint",
+                "unix:///tmp/node.sock ",
+                "--csi-driver-name",
+                "io.datenlord.csi.plugin",
+                "--csi-worker-port",
+                "9001",
+                "--storage-tier-hot-dir",
+                "/tmp/datenlord_hot_tier",
+                "--storage-tier-cold-after",
+                "86400",
+                "--storage-tier-rule",
+                rules,
+            ]
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args("/archive=cold,/db/=Hot"))
+            .try_into()
+            .unwrap();
+        let tiering_config = config.storage.tiering_config.unwrap();
+        assert_eq!(tiering_config.hot_dir, "/tmp/datenlord_hot_tier");
+        assert_eq!(
+            tiering_config.cold_after,
+            std::time::Duration::from_secs(86400)
+        );
+        assert_eq!(tiering_config.promote_accesses, 4);
+        assert_eq!(
+            tiering_config.rules,
+            vec![
+                TierRule {
+                    path: "/archive".to_owned(),
+                    tier: Tier::Cold,
+                },
+                TierRule {
+                    path: "/db".to_owned(),
+                    tier: Tier::Hot,
+                },
+            ]
+        );
+
+        for rules in ["archive=cold", "/archive=warm", "/archive"] {
+            let config: Result<InnerConfig, _> = Config::parse_from(build_args(rules)).try_into();
+            assert!(config.is_err());
+        }
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_backend_error_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--role",
+                "node",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890,127.0.0.1:7891",
+                "--csi-endpoint",
+                "unix:///tmp/node.sock ",
+                "--csi-driver-name",
+                "io.datenlord.csi.plugin",
+                "--csi-worker-port",
+                "9001",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&[])).try_into().unwrap();
+        assert_eq!(
+            config.storage.backend_error_config,
+            BackendErrorConfig::default()
+        );
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--storage-backend-retries",
+            "read=5,Write=0",
+            "--storage-backend-retry-backoff",
+            "50",
+            "--storage-backend-error-map",
+            "XMinioStorageFull=enospc,SlowDown=retry",
+        ]))
+        .try_into()
+        .unwrap();
+        let backend_error_config = config.storage.backend_error_config;
+        assert_eq!(
+            backend_error_config.retries,
+            RetryBudgets {
+                read: 5,
+                write: 0,
+                delete: 3,
+                list: 3,
+            }
+        );
+        assert_eq!(backend_error_config.retries.of(BackendOpClass::Read), 5);
+        assert_eq!(
+            backend_error_config.retry_backoff,
+            Duration::from_millis(50)
+        );
+        assert_eq!(
+            backend_error_config.error_map,
+            vec![
+                BackendErrorMapping {
+                    code: "XMinioStorageFull".to_owned(),
+                    action: BackendErrorAction::Errno(libc::ENOSPC),
+                },
+                BackendErrorMapping {
+                    code: "SlowDown".to_owned(),
+                    action: BackendErrorAction::Retry,
+                },
+            ]
+        );
+
+        for extra_args in [
+            ["--storage-backend-retries", "read"],
+            ["--storage-backend-retries", "stat=1"],
+            ["--storage-backend-retries", "read=-1"],
+            ["--storage-backend-error-map", "QuotaExceeded"],
+            ["--storage-backend-error-map", "QuotaExceeded=EQUOTA"],
+            ["--storage-backend-error-map", "=EIO"],
+        ] {
+            let config: Result<InnerConfig, _> =
+                Config::parse_from(build_args(&extra_args)).try_into();
+            assert!(config.is_err());
+        }
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_transfer_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--role",
+                "node",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890,127.0.0.1:7891",
+                "--csi-endpoint",
+                "unix:///tmp/node.sock ",
+                "--csi-driver-name",
+                "io.datenlord.csi.plugin",
+                "--csi-worker-port",
+                "9001",
+                "--storage-transfer-listen",
+                "0.0.0.0:8900",
+                "--storage-transfer-dir",
+                "/tmp/datenlord_transfer",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&[])).try_into().unwrap();
+        let transfer_config = config.storage.transfer_config.unwrap();
+        assert_eq!(transfer_config.listen.to_string(), "0.0.0.0:8900");
+        assert_eq!(transfer_config.dir, "/tmp/datenlord_transfer");
+        assert_eq!(transfer_config.timeout, std::time::Duration::from_secs(30));
+        assert!(transfer_config.tls.is_none());
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--storage-transfer-tls-cert",
+            "/etc/datenlord/node.pem",
+            "--storage-transfer-tls-key",
+            "/etc/datenlord/node.key",
+            "--storage-transfer-tls-ca",
+            "/etc/datenlord/ca.pem",
+            "--storage-transfer-mtls",
+        ]))
+        .try_into()
+        .unwrap();
+        let tls = config.storage.transfer_config.unwrap().tls.unwrap();
+        assert_eq!(tls.cert_file, "/etc/datenlord/node.pem");
+        assert_eq!(tls.key_file, "/etc/datenlord/node.key");
+        assert_eq!(tls.ca_file, "/etc/datenlord/ca.pem");
+        assert!(tls.mtls);
+
+        for extra_args in [
+            &["--storage-transfer-mtls"][..],
+            &["--storage-transfer-tls-cert", "/etc/datenlord/node.pem"][..],
+            &["--storage-transfer-timeout", "0"][..],
+        ] {
+            let config: Result<InnerConfig, _> =
+                Config::parse_from(build_args(extra_args)).try_into();
+            assert!(config.is_err());
+        }
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_peer_cache_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--role",
+                "node",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890,127.0.0.1:7891",
+                "--csi-endpoint",
+                "unix:///tmp/node.sock ",
+                "--csi-driver-name",
+                "io.datenlord.csi.plugin",
+                "--csi-worker-port",
+                "9001",
+                "--storage-peer-cache-endpoint",
+                "http://10.0.0.1:8900",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+        let transfer_args = [
+            "--storage-transfer-listen",
+            "0.0.0.0:8900",
+            "--storage-transfer-dir",
+            "/tmp/datenlord_transfer",
+        ];
+
+        // The peer cache requires the transfer service.
+        let config: Result<InnerConfig, _> = Config::parse_from(build_args(&[])).try_into();
+        assert!(config.is_err());
+
+        let config: InnerConfig = Config::parse_from(build_args(&transfer_args))
+            .try_into()
+            .unwrap();
+        let peer_cache_config = config.storage.peer_cache_config.unwrap();
+        assert_eq!(peer_cache_config.endpoint, "http://10.0.0.1:8900");
+        assert!(peer_cache_config.zone.is_none());
+        assert_eq!(
+            peer_cache_config.timeout,
+            std::time::Duration::from_millis(500)
+        );
+
+        let mut args = transfer_args.to_vec();
+        args.extend(["--storage-peer-cache-zone", "rack1"]);
+        let config: InnerConfig = Config::parse_from(build_args(&args)).try_into().unwrap();
+        let zone = config.storage.peer_cache_config.unwrap().zone;
+        assert_eq!(zone.as_deref(), Some("rack1"));
+
+        let mut args = transfer_args.to_vec();
+        args.extend(["--storage-peer-cache-timeout", "0"]);
+        let config: Result<InnerConfig, _> = Config::parse_from(build_args(&args)).try_into();
+        assert!(config.is_err());
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_tls_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--role",
+                "node",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890",
+                "--storage-transfer-listen",
+                "0.0.0.0:8900",
+                "--storage-transfer-dir",
+                "/tmp/datenlord_transfer",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&[])).try_into().unwrap();
+        assert!(config.tls.is_none());
+
+        let cluster_tls_args = [
+            "--tls-cert",
+            "/etc/datenlord/node.pem",
+            "--tls-key",
+            "/etc/datenlord/node.key",
+            "--tls-ca",
+            "/etc/datenlord/ca.pem",
+            "--mtls",
+        ];
+        let config: InnerConfig = Config::parse_from(build_args(&cluster_tls_args))
+            .try_into()
+            .unwrap();
+        let tls = config.tls.unwrap();
+        assert_eq!(tls.cert_file, "/etc/datenlord/node.pem");
+        assert_eq!(tls.key_file, "/etc/datenlord/node.key");
+        assert_eq!(tls.ca_file, "/etc/datenlord/ca.pem");
+        assert!(tls.mtls);
+        // The transfers fall back to the TLS of the cluster.
+        assert_eq!(config.storage.transfer_config.unwrap().tls, Some(tls));
+
+        let mut args = cluster_tls_args.to_vec();
+        args.extend_from_slice(&[
+            "--storage-transfer-tls-cert",
+            "/etc/datenlord/transfer.pem",
+            "--storage-transfer-tls-key",
+            "/etc/datenlord/transfer.key",
+            "--storage-transfer-tls-ca",
+            "/etc/datenlord/ca.pem",
+        ]);
+        let config: InnerConfig = Config::parse_from(build_args(&args)).try_into().unwrap();
+        let transfer_tls = config.storage.transfer_config.unwrap().tls.unwrap();
+        assert_eq!(transfer_tls.cert_file, "/etc/datenlord/transfer.pem");
+        assert!(!transfer_tls.mtls);
+
+        for extra_args in [
+            &["--mtls"][..],
+            &["--tls-cert", "/etc/datenlord/node.pem"][..],
+        ] {
+            let config: Result<InnerConfig, _> =
+                Config::parse_from(build_args(extra_args)).try_into();
+            assert!(config.is_err());
+        }
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_gateway_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--role",
+                "gateway",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&[])).try_into().unwrap();
+        assert_eq!(config.role, Role::Gateway);
+        assert_eq!(config.gateway_listen.to_string(), "0.0.0.0:2049");
+        assert!(config.gateway_s3_listen.is_none());
+
+        let config: InnerConfig =
+            Config::parse_from(build_args(&["--gateway-listen", "10.0.0.1:12049"]))
+                .try_into()
+                .unwrap();
+        assert_eq!(config.gateway_listen.to_string(), "10.0.0.1:12049");
+
+        let config: InnerConfig =
+            Config::parse_from(build_args(&["--gateway-s3-listen", "0.0.0.0:9000"]))
+                .try_into()
+                .unwrap();
+        assert_eq!(
+            config.gateway_s3_listen.map(|addr| addr.to_string()),
+            Some("0.0.0.0:9000".to_owned())
+        );
+
+        let config: Result<InnerConfig, _> =
+            Config::parse_from(build_args(&["--gateway-listen", "localhost"])).try_into();
+        assert!(config.is_err());
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_virtiofs_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--role",
+                "asyncFuse",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&[])).try_into().unwrap();
+        assert!(config.virtiofs_socket.is_none());
+
+        let config: InnerConfig =
+            Config::parse_from(build_args(&["--virtiofs-socket", "/run/datenlord-fs.sock"]))
+                .try_into()
+                .unwrap();
+        assert_eq!(
+            config.virtiofs_socket,
+            Some(PathBuf::from("/run/datenlord-fs.sock"))
+        );
+
+        let config: Result<InnerConfig, _> = Config::parse_from(build_args(&[
+            "--virtiofs-socket",
+            "/run/datenlord-fs.sock",
+            "--snapshot",
+            "daily",
+        ]))
+        .try_into();
+        assert!(config.is_err());
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_control_socket_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&["--role", "asyncFuse"]))
+            .try_into()
+            .unwrap();
+        assert!(config.control_socket.is_none());
+        assert!(config.top_interval.is_none());
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--role",
+            "asyncFuse",
+            "--control-socket",
+            "/run/datenlord-control.sock",
+        ]))
+        .try_into()
+        .unwrap();
+        assert_eq!(
+            config.control_socket,
+            Some(PathBuf::from("/run/datenlord-control.sock"))
+        );
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--role",
+            "top",
+            "--control-socket",
+            "/run/datenlord-control.sock",
+            "--top-interval",
+            "2",
+        ]))
+        .try_into()
+        .unwrap();
+        assert_eq!(config.role, Role::Top);
+        assert_eq!(config.top_interval, Some(Duration::from_secs(2)));
+
+        let config: Result<InnerConfig, _> =
+            Config::parse_from(build_args(&["--role", "top"])).try_into();
+        assert!(config.is_err());
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--role",
+            "drain",
+            "--control-socket",
+            "/run/datenlord-control.sock",
+        ]))
+        .try_into()
+        .unwrap();
+        assert_eq!(config.role, Role::Drain);
+
+        let config: Result<InnerConfig, _> =
+            Config::parse_from(build_args(&["--role", "drain"])).try_into();
+        assert!(config.is_err());
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--role",
+            "stats",
+            "--control-socket",
+            "/run/datenlord-control.sock",
+        ]))
+        .try_into()
+        .unwrap();
+        assert_eq!(config.role, Role::Stats);
+        assert_eq!(config.output_format, OutputFormat::Table);
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--role",
+            "inspect",
+            "--control-socket",
+            "/run/datenlord-control.sock",
+            "--output-format",
+            "json",
+        ]))
+        .try_into()
+        .unwrap();
+        assert_eq!(config.role, Role::Inspect);
+        assert_eq!(config.output_format, OutputFormat::Json);
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--role",
+            "slowlog",
+            "--control-socket",
+            "/run/datenlord-control.sock",
+        ]))
+        .try_into()
+        .unwrap();
+        assert_eq!(config.role, Role::SlowLog);
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--role",
+            "warmup",
+            "--control-socket",
+            "/run/datenlord-control.sock",
+            "--warmup-path",
+            "datasets/imagenet/train,/mnt/datenlord/labels.csv",
+            "--warmup-bandwidth",
+            "100",
+        ]))
+        .try_into()
+        .unwrap();
+        assert_eq!(config.role, Role::WarmUp);
+        assert_eq!(
+            config.warmup_paths,
+            ["datasets/imagenet/train", "/mnt/datenlord/labels.csv"]
+        );
+        assert!(config.warmup_file_list.is_none());
+        assert_eq!(config.warmup_bandwidth, NonZeroU64::new(100 << 20));
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--role",
+            "warmup",
+            "--control-socket",
+            "/run/datenlord-control.sock",
+            "--warmup-file-list",
+            "/etc/datenlord/manifest.txt",
+        ]))
+        .try_into()
+        .unwrap();
+        assert!(config.warmup_paths.is_empty());
+        assert_eq!(
+            config.warmup_file_list,
+            Some(PathBuf::from("/etc/datenlord/manifest.txt"))
+        );
+        assert!(config.warmup_bandwidth.is_none());
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--role",
+            "pin",
+            "--control-socket",
+            "/run/datenlord-control.sock",
+        ]))
+        .try_into()
+        .unwrap();
+        assert_eq!(config.role, Role::Pin);
+        assert!(config.warmup_paths.is_empty());
+        assert!(!config.unpin);
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--role",
+            "pin",
+            "--control-socket",
+            "/run/datenlord-control.sock",
+            "--warmup-path",
+            "models/llama",
+            "--unpin",
+        ]))
+        .try_into()
+        .unwrap();
+        assert_eq!(config.warmup_paths, ["models/llama"]);
+        assert!(config.unpin);
+
+        let config: Result<InnerConfig, _> =
+            Config::parse_from(build_args(&["--role", "inspect"])).try_into();
+        assert!(config.is_err());
+        // The warmup role requires the paths to load.
+        let config: Result<InnerConfig, _> = Config::parse_from(build_args(&[
+            "--role",
+            "warmup",
+            "--control-socket",
+            "/run/datenlord-control.sock",
+        ]))
+        .try_into();
+        assert!(config.is_err());
+        // The pin role requires the paths to unpin.
+        let config: Result<InnerConfig, _> = Config::parse_from(build_args(&[
+            "--role",
+            "pin",
+            "--control-socket",
+            "/run/datenlord-control.sock",
+            "--unpin",
+        ]))
+        .try_into();
+        assert!(config.is_err());
+        let config: Result<InnerConfig, _> = Config::parse_from(build_args(&[
+            "--role",
+            "stats",
+            "--control-socket",
+            "/run/datenlord-control.sock",
+            "--output-format",
+            "yaml",
+        ]))
+        .try_into();
+        assert!(config.is_err());
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_health_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&["--role", "asyncFuse"]))
+            .try_into()
+            .unwrap();
+        assert!(config.health_port.is_none());
+        assert_eq!(config.health_probe, HealthProbe::Readiness);
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--role",
+            "health",
+            "--health-port",
+            "9898",
+            "--health-probe",
+            "live",
+        ]))
+        .try_into()
+        .unwrap();
+        assert_eq!(config.role, Role::Health);
+        assert_eq!(config.health_port, Some(9898));
+        assert_eq!(config.health_probe, HealthProbe::Liveness);
+
+        let config: Result<InnerConfig, _> =
+            Config::parse_from(build_args(&["--role", "health"])).try_into();
+        assert!(config.is_err());
+
+        let config: Result<InnerConfig, _> = Config::parse_from(build_args(&[
+            "--role",
+            "asyncFuse",
+            "--health-probe",
+            "started",
+        ]))
+        .try_into();
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn test_usage_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&["--role", "asyncFuse"]))
+            .try_into()
+            .unwrap();
+        assert_eq!(
+            config.usage_checkpoint_interval,
+            Some(Duration::from_secs(300))
+        );
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--role",
+            "usage",
+            "--usage-checkpoint-interval",
+            "0",
+        ]))
+        .try_into()
+        .unwrap();
+        assert_eq!(config.role, Role::Usage);
+        assert!(config.usage_checkpoint_interval.is_none());
+    }
+
+    #[test]
+    fn test_bench_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--role",
+                "bench",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&[])).try_into().unwrap();
+        assert_eq!(config.role, Role::Bench);
+        let bench_config = config.bench_config;
+        assert_eq!(bench_config.target, BenchTarget::Core);
+        assert_eq!(bench_config.block_sizes, vec![4096, 131_072, 1_048_576]);
+        assert_eq!(bench_config.file_size, 67_108_864);
+        assert_eq!(bench_config.files, 1000);
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--bench-target",
+            "mount",
+            "--bench-block-sizes",
+            "512,8192",
+            "--bench-file-size",
+            "8192",
+        ]))
+        .try_into()
+        .unwrap();
+        let bench_config = config.bench_config;
+        assert_eq!(bench_config.target, BenchTarget::Mount);
+        assert_eq!(bench_config.block_sizes, vec![512, 8192]);
+
+        for extra_args in [
+            ["--bench-target", "kernel"],
+            ["--bench-block-sizes", "0"],
+            ["--bench-block-sizes", "134217728"],
+        ] {
+            let config: Result<InnerConfig, _> =
+                Config::parse_from(build_args(&extra_args)).try_into();
+            assert!(config.is_err());
+        }
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_zero_block_size() {
+        let args = vec![
+            "datenlord",
+            "--role",
+            "asyncFuse",
+            "--node-name",
+            "node1",
+            "--node-ip",
+            "127.0.0.1",
+            "--mount-path",
+            "/tmp/datenlord_data_dir",
+            "--kv-server-list",
+            "127.0.0.1:7890",
+            "--storage-block-size",
+            "0",
+        ];
+        let config: Result<InnerConfig, _> = Config::parse_from(args).try_into();
+        assert!(config.is_err());
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_fusedump_config() {
+        let build_args = |role: &'static str, extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--role",
+                role,
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args("asyncFuse", &[]))
+            .try_into()
+            .unwrap();
+        assert!(config.fuse_trace.is_none());
+
+        let config: InnerConfig = Config::parse_from(build_args(
+            "asyncFuse",
+            &["--fuse-trace", "/tmp/datenlord.trace"],
+        ))
+        .try_into()
+        .unwrap();
+        assert_eq!(
+            config.fuse_trace,
+            Some(PathBuf::from("/tmp/datenlord.trace"))
+        );
+
+        let config: InnerConfig = Config::parse_from(build_args(
+            "fusedump",
+            &["--fuse-trace", "/tmp/datenlord.trace", "--fusedump-replay"],
+        ))
+        .try_into()
+        .unwrap();
+        assert_eq!(config.role, Role::FuseDump);
+        assert!(config.fusedump_replay);
+
+        let config: Result<InnerConfig, _> =
+            Config::parse_from(build_args("fusedump", &[])).try_into();
+        assert!(config.is_err());
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_id_map_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--role",
+                "asyncFuse",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&[])).try_into().unwrap();
+        assert!(config.id_map.is_empty());
+        assert_eq!(config.id_map.uid_to_volume(1000), 1000);
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--uid-map",
+            "0:100000:1000,1000:1000:1",
+            "--gid-map",
+            "0:100000:65536",
+        ]))
+        .try_into()
+        .unwrap();
+        let id_map = config.id_map;
+        assert_eq!(id_map.uid_to_volume(0), 100_000);
+        assert_eq!(id_map.uid_to_volume(999), 100_999);
+        assert_eq!(id_map.uid_to_volume(1000), 1000);
+        assert_eq!(id_map.uid_to_mount(100_999), 999);
+        assert_eq!(id_map.gid_to_mount(100_100), 100);
+        // The ids out of the ranges are mapped to the overflow id.
+        assert_eq!(id_map.uid_to_volume(1001), OVERFLOW_ID);
+        assert_eq!(id_map.uid_to_mount(0), OVERFLOW_ID);
+        assert_eq!(id_map.gid_to_volume(65536), OVERFLOW_ID);
+
+        for invalid in ["0:100000", "0:100000:0", "1:4294967295:2", "a:0:1"] {
+            let config: Result<InnerConfig, _> =
+                Config::parse_from(build_args(&["--uid-map", invalid])).try_into();
+            assert!(config.is_err());
+        }
+        // The ranges overlap in the volume.
+        let config: Result<InnerConfig, _> =
+            Config::parse_from(build_args(&["--gid-map", "0:1000:10,100:1005:10"])).try_into();
+        assert!(config.is_err());
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_selinux_context_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--role",
+                "asyncFuse",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&[])).try_into().unwrap();
+        assert!(config.selinux_context.is_none());
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--selinux-context",
+            "system_u:object_r:container_file_t:s0:c1,c2",
+        ]))
+        .try_into()
+        .unwrap();
+        assert_eq!(
+            config.selinux_context.as_deref(),
+            Some("system_u:object_r:container_file_t:s0:c1,c2")
+        );
+
+        let config: Result<InnerConfig, _> =
+            Config::parse_from(build_args(&["--selinux-context", "\"system_u\""])).try_into();
+        assert!(config.is_err());
+        let config: Result<InnerConfig, _> = Config::parse_from(build_args(&[
+            "--selinux-context",
+            "system_u:object_r:container_file_t:s0",
+            "--virtiofs-socket",
+            "/run/datenlord-fs.sock",
+        ]))
+        .try_into();
+        assert!(config.is_err());
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_auth_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&["--role", "asyncFuse"]))
+            .try_into()
+            .unwrap();
+        assert_eq!(config.auth, AuthConfig::default());
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--role",
+            "asyncFuse",
+            "--auth-key-file",
+            "/etc/datenlord/auth.key",
+            "--auth-policy",
+            "/etc/datenlord/policy.json",
+            "--tenant-token",
+            "team-a.0123",
+        ]))
+        .try_into()
+        .unwrap();
+        assert_eq!(
+            config.auth.key_file.as_deref(),
+            Some("/etc/datenlord/auth.key")
+        );
+        assert_eq!(
+            config.auth.policy_file.as_deref(),
+            Some("/etc/datenlord/policy.json")
+        );
+        assert_eq!(config.auth.token.as_deref(), Some("team-a.0123"));
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--role",
+            "tenant",
+            "--auth-key-file",
+            "/etc/datenlord/auth.key",
+            "--tenant",
+            "team-a",
+        ]))
+        .try_into()
+        .unwrap();
+        assert_eq!(config.role, Role::Tenant);
+        assert_eq!(config.auth.tenant.as_deref(), Some("team-a"));
+
+        for extra_args in [
+            &[
+                "--role",
+                "asyncFuse",
+                "--auth-policy",
+                "/etc/datenlord/policy.json",
+            ][..],
+            &[
+                "--role",
+                "asyncFuse",
+                "--auth-policy",
+                "/etc/datenlord/policy.json",
+                "--tenant-token",
+                "team-a.0123",
+            ][..],
+            &["--role", "tenant", "--tenant", "team-a"][..],
+        ] {
+            let config: Result<InnerConfig, _> =
+                Config::parse_from(build_args(extra_args)).try_into();
+            assert!(config.is_err());
+        }
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_op_mask_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--role",
+                "asyncFuse",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&[])).try_into().unwrap();
+        assert!(OpClass::ALL
+            .iter()
+            .all(|&class| !config.op_mask.denies(class)));
+
+        let config: InnerConfig = Config::parse_from(build_args(&["--deny-ops", "xattr,lock"]))
+            .try_into()
+            .unwrap();
+        assert!(config.op_mask.denies(OpClass::Xattr));
+        assert!(config.op_mask.denies(OpClass::Lock));
+        assert!(!config.op_mask.denies(OpClass::Write));
+        assert!(!config.op_mask.denies(OpClass::Device));
+
+        // The classes not allowed are turned off.
+        let config: InnerConfig = Config::parse_from(build_args(&["--allow-ops", "write"]))
+            .try_into()
+            .unwrap();
+        assert_eq!(
+            config.op_mask,
+            OpMask::deny(&[OpClass::Xattr, OpClass::Device, OpClass::Lock])
+        );
+
+        // A read-only mount turns off the writes even if they're allowed.
+        let config: InnerConfig =
+            Config::parse_from(build_args(&["--read-only", "--allow-ops", "write,xattr"]))
+                .try_into()
+                .unwrap();
+        assert_eq!(
+            config.op_mask,
+            OpMask::deny(&[OpClass::Write, OpClass::Device, OpClass::Lock])
+        );
+
+        for extra_args in [
+            &["--deny-ops", "exec"][..],
+            &["--deny-ops", "write", "--allow-ops", "xattr"][..],
+        ] {
+            let config: Result<InnerConfig, _> =
+                Config::parse_from(build_args(extra_args)).try_into();
+            assert!(config.is_err());
+        }
+    }
+
+    #[test]
+    fn test_squash_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--role",
+                "asyncFuse",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&[])).try_into().unwrap();
+        assert_eq!(config.squash.mode, SquashMode::None);
+        assert_eq!(config.squash.squash(0), None);
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--root-squash",
+            "--anon-uid",
+            "1001",
+            "--anon-gid",
+            "1002",
+        ]))
+        .try_into()
+        .unwrap();
+        assert_eq!(config.squash.mode, SquashMode::Root);
+        assert_eq!(config.squash.squash(0), Some((1001, 1002)));
+        assert_eq!(config.squash.squash(1000), None);
+
+        // `--all-squash` squashes root as well.
+        let config: InnerConfig =
+            Config::parse_from(build_args(&["--root-squash", "--all-squash"]))
+                .try_into()
+                .unwrap();
+        assert_eq!(config.squash.mode, SquashMode::All);
+        assert_eq!(config.squash.squash(0), Some((OVERFLOW_ID, OVERFLOW_ID)));
+        assert_eq!(config.squash.squash(1000), Some((OVERFLOW_ID, OVERFLOW_ID)));
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_replication_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--role",
+                "node",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890,127.0.0.1:7891",
+                "--csi-endpoint",
+                "unix:///tmp/node.sock ",
+                "--csi-driver-name",
+                "io.datenlord.csi.plugin",
+                "--csi-worker-port",
+                "9001",
+                "--storage-transfer-listen",
+                "0.0.0.0:8900",
+                "--storage-transfer-dir",
+                "/tmp/datenlord_transfer",
+                "--storage-replication-node",
+                "node1=http://10.0.0.1:8900,node2=http://10.0.0.2:8900,node3=http://10.0.0.3:8900",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&[])).try_into().unwrap();
+        assert!(config.storage.replication_config.is_none());
+
+        let config: InnerConfig =
+            Config::parse_from(build_args(&["--storage-replication-factor", "3"]))
+                .try_into()
+                .unwrap();
+        let replication_config = config.storage.replication_config.unwrap();
+        assert_eq!(replication_config.factor, 3);
+        assert_eq!(replication_config.quorum, WriteQuorum::All);
+        assert_eq!(replication_config.nodes.len(), 3);
+        let node = replication_config.nodes.get(1).unwrap();
+        assert_eq!(node.node_id, "node2");
+        assert_eq!(node.endpoint, "http://10.0.0.2:8900");
+        assert_eq!(
+            replication_config.failure_timeout,
+            std::time::Duration::from_secs(30)
+        );
+        assert_eq!(replication_config.rebalance_rate, 100);
+        assert_eq!(replication_config.hedge_percentile, Some(99));
+        assert_eq!(
+            replication_config.breaker,
+            Some(BreakerPolicy {
+                failures: 5,
+                cooldown: std::time::Duration::from_secs(10),
+            })
+        );
+        assert_eq!(replication_config.policy, StoragePolicy::Replicate);
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--storage-replication-factor",
+            "3",
+            "--storage-replication-quorum",
+            "majority",
+        ]))
+        .try_into()
+        .unwrap();
+        let quorum = config.storage.replication_config.unwrap().quorum;
+        assert_eq!(quorum, WriteQuorum::Majority);
+        assert_eq!(quorum.required(3), 2);
+
+        for (factor, quorum) in [("4", "all"), ("2", "3"), ("2", "0"), ("2", "any")] {
+            let config: Result<InnerConfig, _> = Config::parse_from(build_args(&[
+                "--storage-replication-factor",
+                factor,
+                "--storage-replication-quorum",
+                quorum,
+            ]))
+            .try_into();
+            assert!(config.is_err());
+        }
+        let config: Result<InnerConfig, _> = Config::parse_from(build_args(&[
+            "--storage-replication-factor",
+            "2",
+            "--storage-snapshot",
+        ]))
+        .try_into();
+        assert!(config.is_err());
+        let config: Result<InnerConfig, _> = Config::parse_from(build_args(&[
+            "--storage-replication-factor",
+            "2",
+            "--storage-replication-failure-timeout",
+            "0",
+        ]))
+        .try_into();
+        assert!(config.is_err());
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--storage-replication-factor",
+            "2",
+            "--storage-replication-hedge-percentile",
+            "0",
+        ]))
+        .try_into()
+        .unwrap();
+        assert!(config
+            .storage
+            .replication_config
+            .unwrap()
+            .hedge_percentile
+            .is_none());
+        let config: Result<InnerConfig, _> = Config::parse_from(build_args(&[
+            "--storage-replication-factor",
+            "2",
+            "--storage-replication-hedge-percentile",
+            "100",
+        ]))
+        .try_into();
+        assert!(config.is_err());
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--storage-replication-factor",
+            "2",
+            "--storage-replication-breaker-failures",
+            "0",
+            "--storage-replication-breaker-cooldown",
+            "0",
+        ]))
+        .try_into()
+        .unwrap();
+        assert!(config.storage.replication_config.unwrap().breaker.is_none());
+        let config: Result<InnerConfig, _> = Config::parse_from(build_args(&[
+            "--storage-replication-factor",
+            "2",
+            "--storage-replication-breaker-cooldown",
+            "0",
+        ]))
+        .try_into();
+        assert!(config.is_err());
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--storage-replication-factor",
+            "2",
+            "--storage-replication-policy",
+            "tiered",
+            "--storage-replication-ec-data-shards",
+            "2",
+            "--storage-replication-ec-parity-shards",
+            "1",
+        ]))
+        .try_into()
+        .unwrap();
+        let replication_config = config.storage.replication_config.unwrap();
+        assert_eq!(replication_config.policy, StoragePolicy::Tiered);
+        assert_eq!(
+            replication_config.shards,
+            ErasureShards { data: 2, parity: 1 }
+        );
+        assert_eq!(
+            replication_config.ec_cold_after,
+            std::time::Duration::from_secs(604_800)
+        );
+        assert_eq!(replication_config.ec_promote_accesses, 4);
+        assert_eq!(
+            replication_config.ec_interval,
+            std::time::Duration::from_secs(3600)
+        );
+
+        // The default 6 shards exceed the 3 replica nodes.
+        for extra_args in [
+            &["--storage-replication-policy", "erasure"][..],
+            &["--storage-replication-policy", "mirror"],
+            &[
+                "--storage-replication-policy",
+                "erasure",
+                "--storage-replication-ec-data-shards",
+                "2",
+                "--storage-replication-ec-parity-shards",
+                "0",
+            ],
+            &[
+                "--storage-replication-policy",
+                "tiered",
+                "--storage-replication-ec-data-shards",
+                "2",
+                "--storage-replication-ec-parity-shards",
+                "1",
+                "--storage-replication-ec-interval",
+                "0",
+            ],
+        ] {
+            let mut args = vec!["--storage-replication-factor", "2"];
+            args.extend_from_slice(extra_args);
+            let config: Result<InnerConfig, _> = Config::parse_from(build_args(&args)).try_into();
+            assert!(config.is_err());
+        }
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_volume_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--role",
+                "volume",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&[])).try_into().unwrap();
+        assert_eq!(config.role, Role::Volume);
+        let volume_config = config.volume_config;
+        assert_eq!(volume_config.listen.to_string(), "0.0.0.0:7950");
+        assert!(volume_config.endpoint.is_none());
+        assert!(volume_config.name.is_none());
+        assert_eq!(volume_config.capacity, 0);
+        assert_eq!(volume_config.policy, StoragePolicy::Tiered);
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--volume-manager-endpoint",
+            "http://10.0.0.1:7950",
+            "--volume-name",
+            "data",
+            "--volume-capacity",
+            "1073741824",
+            "--volume-policy",
+            "erasure",
+        ]))
+        .try_into()
+        .unwrap();
+        let volume_config = config.volume_config;
+        assert_eq!(
+            volume_config.endpoint.as_deref(),
+            Some("http://10.0.0.1:7950")
+        );
+        assert_eq!(volume_config.name.as_deref(), Some("data"));
+        assert_eq!(volume_config.capacity, 1_073_741_824);
+        assert_eq!(volume_config.policy, StoragePolicy::Erasure);
+        assert!(!volume_config.delete && !volume_config.resize);
+
+        for extra_args in [
+            &["--volume-manager-listen", "localhost"][..],
+            &["--volume-policy", "mirror"],
+            &["--volume-delete"],
+            &[
+                "--volume-name",
+                "data",
+                "--volume-delete",
+                "--volume-resize",
+            ],
+        ] {
+            let config: Result<InnerConfig, _> =
+                Config::parse_from(build_args(extra_args)).try_into();
+            assert!(config.is_err());
+        }
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_admin_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--role",
+                "admin",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890",
+                "--volume-manager-endpoint",
+                "http://10.0.0.1:7950",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&[])).try_into().unwrap();
+        assert_eq!(config.role, Role::Admin);
+        assert_eq!(config.volume_config.command, AdminCommand::List);
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--admin-command",
+            "delete-snapshot",
+            "--snapshot",
+            "nightly",
+        ]))
+        .try_into()
+        .unwrap();
+        assert_eq!(config.volume_config.command, AdminCommand::DeleteSnapshot);
+        assert_eq!(config.snapshot.as_deref(), Some("nightly"));
+
+        let config: InnerConfig = Config::parse_from(build_args(&["--admin-command", "quota"]))
+            .try_into()
+            .unwrap();
+        assert_eq!(config.volume_config.command, AdminCommand::Quota);
+        assert!(config.volume_config.name.is_none());
+
+        for extra_args in [
+            &["--admin-command", "fsck"][..],
+            &["--admin-command", "resize"],
+            &["--admin-command", "snapshot"],
+        ] {
+            let config: Result<InnerConfig, _> =
+                Config::parse_from(build_args(extra_args)).try_into();
+            assert!(config.is_err());
+        }
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_encryption_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--role",
+                "node",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890,127.0.0.1:7891",
+                "--csi-endpoint",
+                "unix:///tmp/node.sock ",
+                "--csi-driver-name",
+                "io.datenlord.csi.plugin",
+                "--csi-worker-port",
+                "9001",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--storage-encryption-master-key-file",
+            "/etc/datenlord/master.key",
+            "--storage-encryption-rotate-data-key",
+        ]))
+        .try_into()
+        .unwrap();
+        let encryption_config = config.storage.encryption_config.unwrap();
+        assert_eq!(
+            encryption_config.master_key_file,
+            "/etc/datenlord/master.key"
+        );
+        assert!(encryption_config.old_master_key_file.is_none());
+        assert!(encryption_config.rotate_data_key);
+
+        // Rotation without the master key is invalid.
+        let config: Result<InnerConfig, _> = Config::parse_from(build_args(&[
+            "--storage-encryption-old-master-key-file",
+            "/etc/datenlord/old.key",
+        ]))
+        .try_into();
+        assert!(config.is_err());
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_snapshot_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--role",
+                "snapshot",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890,127.0.0.1:7891",
+                "--csi-endpoint",
+                "unix:///tmp/node.sock ",
+                "--csi-driver-name",
+                "io.datenlord.csi.plugin",
+                "--csi-worker-port",
+                "9001",
+                "--storage-snapshot",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&[])).try_into().unwrap();
+        assert_eq!(config.role, Role::Snapshot);
+        assert!(config.snapshot.is_none());
+        assert!(!config.snapshot_delete);
+        assert!(config.storage.snapshot);
+
+        let config: InnerConfig =
+            Config::parse_from(build_args(&["--snapshot", "daily", "--snapshot-delete"]))
+                .try_into()
+                .unwrap();
+        assert_eq!(config.snapshot.as_deref(), Some("daily"));
+        assert!(config.snapshot_delete);
+
+        // Snapshot cannot be enabled with dedup.
+        let config: Result<InnerConfig, _> =
+            Config::parse_from(build_args(&["--storage-dedup"])).try_into();
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn test_trash_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--role",
+                "trash",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890,127.0.0.1:7891",
+                "--csi-endpoint",
+                "unix:///tmp/node.sock ",
+                "--csi-driver-name",
+                "io.datenlord.csi.plugin",
+                "--csi-worker-port",
+                "9001",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&[])).try_into().unwrap();
+        assert_eq!(config.role, Role::Trash);
+        assert!(config.trash_restore.is_none());
+        assert!(config.trash_restore_path.is_none());
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--trash-restore",
+            "42",
+            "--trash-restore-path",
+            "/data/a",
+            "--storage-trash-retention",
+            "86400",
+        ]))
+        .try_into()
+        .unwrap();
+        assert_eq!(config.trash_restore, Some(42));
+        assert_eq!(config.trash_restore_path.as_deref(), Some("/data/a"));
+        assert_eq!(
+            config.storage.trash_retention,
+            Some(std::time::Duration::from_secs(86400))
+        );
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states, clippy::indexing_slicing)]
+    fn test_standalone_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--standalone",
+                "--standalone-dir",
+                "/tmp/datenlord_standalone",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&[])).try_into().unwrap();
+        assert!(config.standalone);
+        assert_eq!(config.role, Role::AsyncFuse);
+        assert_eq!(config.node_name, "localhost");
+        assert_eq!(config.node_ip, IpAddr::from_str("127.0.0.1").unwrap());
+        assert_eq!(config.kv_addrs, ["file:///tmp/datenlord_standalone/meta"]);
+        match config.storage.params {
+            InnerStorageParams::Fs(root) => assert_eq!(root, "/tmp/datenlord_standalone/blocks"),
+            InnerStorageParams::S3(_) => panic!("storage params should be Fs"),
+        }
+
+        // The metadata and the blocks given are kept.
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--kv-server-list",
+            "file:///data/meta",
+            "--storage-fs-root",
+            "/data/blocks",
+        ]))
+        .try_into()
+        .unwrap();
+        assert_eq!(config.kv_addrs[0], "file:///data/meta");
+        match config.storage.params {
+            InnerStorageParams::Fs(root) => assert_eq!(root, "/data/blocks"),
+            InnerStorageParams::S3(_) => panic!("storage params should be Fs"),
+        }
+
+        let config: Result<InnerConfig, _> =
+            Config::parse_from(build_args(&["--role", "node"])).try_into();
+        assert!(config.is_err());
+        let config: Result<InnerConfig, _> =
+            Config::parse_from(build_args(&["--storage-type", "s3"])).try_into();
+        assert!(config.is_err());
+
+        // The role and the node are required unless standalone.
+        let config: Result<InnerConfig, _> = Config::parse_from([
+            "datenlord",
+            "--mount-path",
+            "/tmp/datenlord_data_dir",
+            "--kv-server-list",
+            "127.0.0.1:7890",
+        ])
+        .try_into();
+        assert!(config.is_err());
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_invalid_soft_limit() {
+        let build_args = |soft_limit: &'static str| {
+            vec![
+                "datenlord",
+                "--role",
+                "node",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890,127.0.0.1:7891",
+                "--csi-endpoint",
+                "unix:///tmp/node.sock ",
+                "--csi-driver-name",
+                "io.datenlord.csi.plugin",
+                "--storage-mem-cache-soft-limit",
+                soft_limit,
+                "--csi-worker-port",
+                "9001",
+            ]
+        };
+
+        let config: Result<InnerConfig, _> = Config::parse_from(build_args("a,1")).try_into();
+        assert!(config.is_err());
+
+        let config: Result<InnerConfig, _> = Config::parse_from(build_args("1,a")).try_into();
+        assert!(config.is_err());
+
+        let config: Result<InnerConfig, _> = Config::parse_from(build_args("1,0")).try_into();
+        assert!(config.is_err());
+    }
+
+    #[test]
+    #[allow(clippy::indexing_slicing)]
+    fn test_csi_controller_config() {
+        // Set the args
+        let args = vec![
+            "datenlord",
+            "--role",
+            "controller",
+            "--csi-endpoint",
+            "unix:///tmp/controller.sock",
+            "--csi-driver-name",
+            "io.datenlord.csi.plugin",
+            "--csi-worker-port",
+            "9001",
+            "--node-name",
+            "localhost",
+            "--node-ip",
+            "127.0.0.1",
+            "--mount-path",
+            "/tmp/datenlord_data_dir",
+            "--kv-server-list",
+            "127.0.0.1:7890",
+        ];
+        let config: InnerConfig = Config::parse_from(args).try_into().unwrap();
+        assert_eq!(config.role, Role::Controller);
+        assert_eq!(config.node_name, "localhost");
+        assert_eq!(config.node_ip, IpAddr::from_str("127.0.0.1").unwrap());
+        assert_eq!(config.mount_path.as_str(), "/tmp/datenlord_data_dir");
+        assert_eq!(config.server_port, 8800);
+
+        let kv_addrs = config.kv_addrs;
+        assert_eq!(kv_addrs.len(), 1);
+        assert_eq!(kv_addrs[0], "127.0.0.1:7890");
+
+        let csi_config = config.csi_config;
+        assert_eq!(csi_config.endpoint, "unix:///tmp/controller.sock");
+        assert_eq!(csi_config.driver_name, "io.datenlord.csi.plugin");
+        assert_eq!(csi_config.worker_port, 9001);
+        assert!(csi_config.mount_namespace.is_none());
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_log_config() {
+        let build_args = |extra_args: &[&'static str]| {
+            let mut args = vec![
+                "datenlord",
+                "--role",
+                "asyncFuse",
+                "--node-name",
+                "node1",
+                "--node-ip",
+                "127.0.0.1",
+                "--mount-path",
+                "/tmp/datenlord_data_dir",
+                "--kv-server-list",
+                "127.0.0.1:7890",
+            ];
+            args.extend_from_slice(extra_args);
+            args
+        };
+
+        let config: InnerConfig = Config::parse_from(build_args(&[])).try_into().unwrap();
+        assert_eq!(config.log_level, LevelFilter::INFO);
+        assert_eq!(config.log_format, LogFormat::Text);
+        assert_eq!(config.log_sample_rate.get(), 1);
+        assert!(config.otlp_endpoint.is_none());
+        assert!(config.otlp_sample_ratio.total_cmp(&1.0).is_eq());
+        assert!(config.slow_log_threshold.is_none());
+        assert_eq!(config.slow_log_capacity, 128);
+
+        let config: InnerConfig = Config::parse_from(build_args(&[
+            "--log-level",
+            "trace",
+            "--log-format",
+            "json",
+            "--log-sample-rate",
+            "100",
+            "--otlp-endpoint",
+            "http://localhost:4317",
+            "--otlp-sample-ratio",
+            "0.5",
+            "--slow-log-threshold",
+            "500",
+            "--slow-log-capacity",
+            "16",
+        ]))
+        .try_into()
+        .unwrap();
+        assert_eq!(config.log_level, LevelFilter::TRACE);
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert_eq!(config.log_sample_rate.get(), 100);
+        assert_eq!(
+            config.otlp_endpoint.as_deref(),
+            Some("http://localhost:4317")
+        );
+        assert!(config.otlp_sample_ratio.total_cmp(&0.5).is_eq());
+        assert_eq!(config.slow_log_threshold, Some(Duration::from_millis(500)));
+        assert_eq!(config.slow_log_capacity, 16);
+
+        for invalid_args in [
+            ["--log-level", "verbose"],
+            ["--log-format", "xml"],
+            ["--log-sample-rate", "0"],
+            ["--otlp-sample-ratio", "1.5"],
+            ["--slow-log-capacity", "0"],
+        ] {
+            let config: Result<InnerConfig, _> =
+                Config::parse_from(build_args(&invalid_args)).try_into();
+            assert!(config.is_err());
+        }
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_result_states)]
+    fn test_config_file() {
+        let dir = std::env::temp_dir().join(format!("datenlord_config_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let toml_file = dir.join("datenlord.toml");
+        std::fs::write(
+            &toml_file,
+            r#"
+role = "asyncFuse"
+node-name = "node1"
+node-ip = "127.0.0.1"
+mount-path = "/tmp/datenlord_data_dir"
+kv-server-list = ["127.0.0.1:7890", "127.0.0.1:7891"]
+storage-memory-budget = 1073741824
+storage-writeback-cache = true
+log-level = "debug"
+"#,
+        )
+        .unwrap();
+        let toml_file = toml_file.to_str().unwrap();
+
+        let config: InnerConfig = Config::try_load_from(["datenlord", "--config-file", toml_file])
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(config.role, Role::AsyncFuse);
+        assert_eq!(config.node_name, "node1");
+        assert_eq!(config.kv_addrs, ["127.0.0.1:7890", "127.0.0.1:7891"]);
+        assert_eq!(config.storage.memory_budget, Some(0x4000_0000));
+        assert!(config.storage.writeback_cache);
+        assert_eq!(config.log_level, LevelFilter::DEBUG);
+        assert_eq!(config.config_file, Some(PathBuf::from(toml_file)));
+
+        // The command line takes precedence over the config file
+        let config: InnerConfig = Config::try_load_from([
+            "datenlord",
+            "--log-level=warn",
+            "--config-file",
+            toml_file,
+            "--node-name",
+            "node2",
+        ])
+        .unwrap()
+        .try_into()
+        .unwrap();
+        assert_eq!(config.node_name, "node2");
+        assert_eq!(config.log_level, LevelFilter::WARN);
+        assert_eq!(config.storage.memory_budget, Some(0x4000_0000));
+
+        let yaml_file = dir.join("datenlord.yaml");
+        std::fs::write(
+            &yaml_file,
+            "role: asyncFuse\nnode-name: node1\nnode-ip: 127.0.0.1\n\
+             mount-path: /tmp/datenlord_data_dir\nkv-server-list: ['127.0.0.1:7890']\n\
+             storage-writeback-cache: false\n",
+        )
+        .unwrap();
+        let config: InnerConfig =
+            Config::try_load_from(["datenlord", "--config-file", yaml_file.to_str().unwrap()])
+                .unwrap()
+                .try_into()
+                .unwrap();
+        assert_eq!(config.kv_addrs, ["127.0.0.1:7890"]);
+        assert!(!config.storage.writeback_cache);
+        assert_eq!(config.log_level, LevelFilter::INFO);
+
+        // A table is not a flag
+        let invalid_file = dir.join("invalid.toml");
+        std::fs::write(&invalid_file, "[storage]\ncapacity = 1\n").unwrap();
+        let config =
+            Config::try_load_from(["datenlord", "--config-file", invalid_file.to_str().unwrap()]);
+        assert!(config.is_err());
+        let config = Config::try_load_from(["datenlord", "--config-file", "/nonexistent.toml"]);
+        assert!(config.is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
@@ -0,0 +1,113 @@
+//! The config file given by `--config-file`.
+//!
+//! The file sets the flags of `Config` by their long names without the
+//! leading dashes, such as `storage-mem-cache-capacity = 1073741824`, in TOML,
+//! or in YAML if its extension is `.yaml` or `.yml`. A flag taking a list is
+//! set by an array, and a switch by a boolean. The flags given on the command
+//! line take precedence over the file, so a flag is overridden for a run
+//! without editing the file.
+
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::common::error::{DatenLordError, DatenLordResult};
+
+/// The flag giving the config file.
+const CONFIG_FILE_FLAG: &str = "--config-file";
+
+/// Merge the flags set by the config file given in `args` into `args`, which
+/// are returned as they are if there's no config file.
+pub fn with_config_file(args: Vec<OsString>) -> DatenLordResult<Vec<OsString>> {
+    let Some(path) = config_file(&args) else {
+        return Ok(args);
+    };
+    let flags = read_config_file(Path::new(path))?;
+
+    let mut merged: Vec<OsString> = args.first().cloned().into_iter().collect();
+    for (name, value) in &flags {
+        let flag = format!("--{name}");
+        if !is_given(&args, &flag) {
+            merged.extend(flag_args(&flag, value)?);
+        }
+    }
+    merged.extend(args.iter().skip(1).cloned());
+    Ok(merged)
+}
+
+/// The config file given in `args`, `None` if it's not given.
+fn config_file(args: &[OsString]) -> Option<&OsStr> {
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == CONFIG_FILE_FLAG {
+            return args.next().map(OsString::as_os_str);
+        }
+        let value = arg
+            .to_str()
+            .and_then(|arg| arg.strip_prefix(CONFIG_FILE_FLAG))
+            .and_then(|rest| rest.strip_prefix('='));
+        if let Some(path) = value {
+            return Some(OsStr::new(path));
+        }
+    }
+    None
+}
+
+/// Whether `flag` is given in `args`, as `--flag value` or `--flag=value`.
+fn is_given(args: &[OsString], flag: &str) -> bool {
+    args.iter().skip(1).any(|arg| {
+        arg == flag
+            || arg
+                .to_str()
+                .and_then(|arg| arg.strip_prefix(flag))
+                .is_some_and(|rest| rest.starts_with('='))
+    })
+}
+
+/// Read the flags set by the config file at `path`, the long name of a flag
+/// -> its value.
+fn read_config_file(path: &Path) -> DatenLordResult<BTreeMap<String, Value>> {
+    let invalid = |e: String| DatenLordError::ArgumentInvalid {
+        context: vec![format!("config file {} is invalid: {e}", path.display())],
+    };
+    let content = std::fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+    let is_yaml = path
+        .extension()
+        .is_some_and(|extension| extension == "yaml" || extension == "yml");
+    if is_yaml {
+        serde_yaml::from_str(&content).map_err(|e| invalid(e.to_string()))
+    } else {
+        toml::from_str(&content).map_err(|e| invalid(e.to_string()))
+    }
+}
+
+/// The args of `flag` set to `value` in the config file.
+fn flag_args(flag: &str, value: &Value) -> DatenLordResult<Vec<OsString>> {
+    let value = match *value {
+        Value::Bool(true) => return Ok(vec![flag.into()]),
+        Value::Bool(false) | Value::Null => return Ok(vec![]),
+        Value::Array(ref values) if values.is_empty() => return Ok(vec![]),
+        Value::Array(ref values) => values
+            .iter()
+            .map(|value| scalar(flag, value))
+            .collect::<DatenLordResult<Vec<_>>>()?
+            .join(","),
+        Value::Number(_) | Value::String(_) | Value::Object(_) => scalar(flag, value)?,
+    };
+    Ok(vec![flag.into(), value.into()])
+}
+
+/// The string of `value` of `flag`, which is a number or a string.
+fn scalar(flag: &str, value: &Value) -> DatenLordResult<String> {
+    match *value {
+        Value::Number(ref number) => Ok(number.to_string()),
+        Value::String(ref string) => Ok(string.clone()),
+        Value::Null | Value::Bool(_) | Value::Array(_) | Value::Object(_) => {
+            Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!("{flag} is set to {value} in the config file")],
+            })
+        }
+    }
+}
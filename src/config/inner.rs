@@ -1,16 +1,37 @@
-use std::net::IpAddr;
-use std::num::NonZeroUsize;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 
+use clippy_utilities::{Cast, OverflowArithmetic};
 use serde::{Deserialize, Serialize};
+use tracing::level_filters::LevelFilter;
 
 use crate::common::error::DatenLordError;
 use crate::config::config::{
-    CSIConfig as SupperCSIConfig, Config as SuperConfig,
-    MemoryCacheConfig as SuperMemoryCacheConfig, S3StorageConfig as SuperS3StorageConfig,
-    StorageConfig as SuperStorageConfig,
+    BackendErrorConfig as SuperBackendErrorConfig, BenchConfig as SuperBenchConfig,
+    CSIConfig as SupperCSIConfig, ChecksumConfig as SuperChecksumConfig, Config as SuperConfig,
+    DedupConfig as SuperDedupConfig, DiskCacheConfig as SuperDiskCacheConfig,
+    EncryptionConfig as SuperEncryptionConfig, GcConfig as SuperGcConfig,
+    MemoryCacheConfig as SuperMemoryCacheConfig, PackConfig as SuperPackConfig,
+    PeerCacheConfig as SuperPeerCacheConfig, ReplicationConfig as SuperReplicationConfig,
+    S3StorageConfig as SuperS3StorageConfig, StorageConfig as SuperStorageConfig,
+    TieringConfig as SuperTieringConfig, TransferConfig as SuperTransferConfig,
+    VolumeConfig as SuperVolumeConfig, DEFAULT_BLOCK_SIZE, DEFAULT_FS_STORAGE_ROOT,
+    LEGACY_BLOCK_SIZE,
 };
 
+/// The node name of the standalone mode.
+const STANDALONE_NODE_NAME: &str = "localhost";
+/// The node IP of the standalone mode.
+const STANDALONE_NODE_IP: &str = "127.0.0.1";
+/// The subdirectory of the embedded metadata in the standalone directory.
+const STANDALONE_META_DIR: &str = "meta";
+/// The subdirectory of the blocks in the standalone directory.
+const STANDALONE_BLOCK_DIR: &str = "blocks";
+
 /// The role of the node
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Role {
@@ -22,6 +43,50 @@ pub enum Role {
     SchedulerExtender,
     /// Run async fuse
     AsyncFuse,
+    /// Check the metadata of an unmounted volume
+    Fsck,
+    /// Create, delete or list snapshots of a volume
+    Snapshot,
+    /// List or restore the removed files in the trash of a volume
+    Trash,
+    /// Serve the admin API of the volume manager
+    VolumeManager,
+    /// Create, delete, resize or list the volumes of the volume manager
+    Volume,
+    /// Reconcile the `DatenlordCluster` and `DatenlordVolume` custom
+    /// resources of Kubernetes
+    Operator,
+    /// Serve the volume by NFSv3 for the clients without FUSE
+    Gateway,
+    /// Issue the token of a tenant to mount with
+    Tenant,
+    /// Show the IO of the processes and the cgroups of a mount
+    Top,
+    /// Check the liveness or the readiness of a mount
+    Health,
+    /// Drain a node before it's shut down
+    Drain,
+    /// Export the usage of a volume for billing
+    Usage,
+    /// Measure the performance of a volume
+    Bench,
+    /// Print or replay a trace of the FUSE requests and replies
+    FuseDump,
+    /// Administrate the volumes, the snapshots and the nodes of a cluster by
+    /// the volume manager
+    Admin,
+    /// Show the occupancy of the caches, the handles, the leases and the
+    /// latency of the operations of a mount
+    Stats,
+    /// Show the dirty bytes, the handles and the leases of the files held by
+    /// a mount
+    Inspect,
+    /// Dump the slow requests of a mount
+    SlowLog,
+    /// Warm up the caches of a mount with a dataset
+    WarmUp,
+    /// Pin files in the local cache of a mount, unpin them or list the pins
+    Pin,
 }
 
 impl FromStr for Role {
@@ -34,6 +99,26 @@ impl FromStr for Role {
             "node" => Ok(Role::Node),
             "scheduler" => Ok(Role::SchedulerExtender),
             "asyncFuse" => Ok(Role::AsyncFuse),
+            "fsck" => Ok(Role::Fsck),
+            "snapshot" => Ok(Role::Snapshot),
+            "trash" => Ok(Role::Trash),
+            "volumeManager" => Ok(Role::VolumeManager),
+            "volume" => Ok(Role::Volume),
+            "operator" => Ok(Role::Operator),
+            "gateway" => Ok(Role::Gateway),
+            "tenant" => Ok(Role::Tenant),
+            "top" => Ok(Role::Top),
+            "health" => Ok(Role::Health),
+            "drain" => Ok(Role::Drain),
+            "usage" => Ok(Role::Usage),
+            "bench" => Ok(Role::Bench),
+            "fusedump" => Ok(Role::FuseDump),
+            "admin" => Ok(Role::Admin),
+            "stats" => Ok(Role::Stats),
+            "inspect" => Ok(Role::Inspect),
+            "slowlog" => Ok(Role::SlowLog),
+            "warmup" => Ok(Role::WarmUp),
+            "pin" => Ok(Role::Pin),
             _ => Err(DatenLordError::ArgumentInvalid {
                 context: vec![format!("role {} is not supported", s)],
             }),
@@ -60,84 +145,2057 @@ pub struct InnerConfig {
     pub server_port: u16,
     /// Set the port of the scheduler extender
     pub scheduler_extender_port: u16,
+    /// Whether the fsck role repairs the inconsistencies
+    pub fsck_repair: bool,
+    /// The name of the snapshot to create, delete or mount, `None` if it's
+    /// not specified
+    pub snapshot: Option<String>,
+    /// Whether the snapshot role deletes the snapshot
+    pub snapshot_delete: bool,
+    /// The i-number of the file the trash role restores, `None` to list the
+    /// files in the trash
+    pub trash_restore: Option<u64>,
+    /// The path to restore the file to, `None` if it's where the file is
+    /// removed from
+    pub trash_restore_path: Option<String>,
     /// Storage related config
     pub storage: StorageConfig,
     /// CSI related config
     pub csi_config: CSIConfig,
+    /// Volume manager related config
+    pub volume_config: VolumeConfig,
+    /// Benchmark related config
+    pub bench_config: BenchConfig,
+    /// The address the gateway serves NFS on
+    pub gateway_listen: SocketAddr,
+    /// The address the gateway serves S3 on, `None` if S3 is not served
+    pub gateway_s3_listen: Option<SocketAddr>,
+    /// The vhost-user socket to serve the volume on by virtio-fs, `None` to
+    /// mount it by FUSE
+    pub virtiofs_socket: Option<PathBuf>,
+    /// Whether all runs in one process, see `Config::standalone`
+    pub standalone: bool,
+    /// The SELinux context of all the nodes of the mount, `None` to label
+    /// them by the labels stored in the volume
+    pub selinux_context: Option<String>,
+    /// The mapping of the uids and gids between the mount and the volume
+    pub id_map: IdMap,
+    /// The TLS of the gRPC services among the nodes, `None` if they're in
+    /// plaintext
+    pub tls: Option<TlsConfig>,
+    /// The authentication and authorization of the tenants
+    pub auth: AuthConfig,
+    /// The squashing of the callers of the mount
+    pub squash: Squash,
+    /// The classes of the FUSE operations turned off on the mount
+    pub op_mask: OpMask,
+    /// The control socket of the mount, `None` if it's not served
+    pub control_socket: Option<PathBuf>,
+    /// The interval between the refreshes of the top role, `None` to show it
+    /// once
+    pub top_interval: Option<Duration>,
+    /// The format the stats role, the inspect role, the slowlog role and the
+    /// pin role show the mount in
+    pub output_format: OutputFormat,
+    /// The port the health is served on, `None` if it's not served
+    pub health_port: Option<u16>,
+    /// The probe the health role checks
+    pub health_probe: HealthProbe,
+    /// The interval between the checkpoints of the usage of the volume,
+    /// `None` if the usage is not checkpointed
+    pub usage_checkpoint_interval: Option<Duration>,
+    /// The file the FUSE requests and replies are recorded into, or read by
+    /// the fusedump role, `None` if they're not recorded
+    pub fuse_trace: Option<PathBuf>,
+    /// Whether the fusedump role replays the trace
+    pub fusedump_replay: bool,
+    /// The level of the log
+    pub log_level: LevelFilter,
+    /// The format of the log
+    pub log_format: LogFormat,
+    /// One in how many requests of the frequent FUSE operations is logged
+    pub log_sample_rate: NonZeroU64,
+    /// The OpenTelemetry collector the spans are exported to, `None` if
+    /// they're not exported
+    pub otlp_endpoint: Option<String>,
+    /// The ratio of the traces started by this node exported
+    pub otlp_sample_ratio: f64,
+    /// The latency a FUSE request is kept in the slow log beyond, `None` if
+    /// none is kept
+    pub slow_log_threshold: Option<Duration>,
+    /// The most slow requests kept
+    pub slow_log_capacity: usize,
+    /// The files and the directories the warmup role or the pin role loads
+    pub warmup_paths: Vec<String>,
+    /// The file listing the files and the directories the warmup role or the
+    /// pin role loads, `None` if there's none
+    pub warmup_file_list: Option<PathBuf>,
+    /// The bytes per second the warmup role or the pin role loads the files
+    /// at most, `None` if it's not limited
+    pub warmup_bandwidth: Option<NonZeroU64>,
+    /// Whether the pin role unpins the paths, instead of pinning them
+    pub unpin: bool,
+    /// The config file the flags are set by, `None` if there's none
+    pub config_file: Option<PathBuf>,
+}
+
+/// Fill in the config of the standalone mode, which runs the asyncFuse role
+/// in one process with the local engine of the metadata and the FS backend,
+/// through the same code paths as a node of a cluster.
+fn fill_standalone(value: &mut SuperConfig) -> Result<(), DatenLordError> {
+    if !value.role.is_empty() && value.role != "asyncFuse" {
+        return Err(DatenLordError::ArgumentInvalid {
+            context: vec![format!(
+                "the standalone mode runs the asyncFuse role, not {}",
+                value.role
+            )],
+        });
+    }
+    if value.storage.storage_type.to_lowercase() != "fs" {
+        return Err(DatenLordError::ArgumentInvalid {
+            context: vec![
+                "the standalone mode stores the blocks in the local file system".to_owned(),
+            ],
+        });
+    }
+    value.role = "asyncFuse".to_owned();
+    if value.node_name.is_empty() {
+        value.node_name = STANDALONE_NODE_NAME.to_owned();
+    }
+    if value.node_ip.is_empty() {
+        value.node_ip = STANDALONE_NODE_IP.to_owned();
+    }
+    let dir = Path::new(&value.standalone_dir);
+    if value.kv_server_list.is_empty() {
+        let meta_dir = dir.join(STANDALONE_META_DIR);
+        value.kv_server_list = vec![format!("file://{}", meta_dir.display())];
+    }
+    if value.storage.fs_storage_root == DEFAULT_FS_STORAGE_ROOT {
+        let block_dir = dir.join(STANDALONE_BLOCK_DIR);
+        value.storage.fs_storage_root = block_dir.display().to_string();
+    }
+    Ok(())
+}
+
+impl TryFrom<SuperConfig> for InnerConfig {
+    type Error = DatenLordError;
+
+    #[inline]
+    fn try_from(mut value: SuperConfig) -> Result<Self, Self::Error> {
+        let standalone = value.standalone;
+        if standalone {
+            fill_standalone(&mut value)?;
+        }
+        if value.role.is_empty() || value.node_name.is_empty() || value.node_ip.is_empty() {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec![
+                    "role, node name and node ip are required unless standalone".to_owned()
+                ],
+            });
+        }
+        let role = Role::from_str(value.role.as_str())?;
+        let node_name = value.node_name;
+        let server_port = value.server_port;
+        let scheduler_extender_port = value.scheduler_extender_port;
+        let fsck_repair = value.fsck_repair;
+        let snapshot = (!value.snapshot.is_empty()).then_some(value.snapshot);
+        let snapshot_delete = value.snapshot_delete;
+        let trash_restore = (value.trash_restore != 0).then_some(value.trash_restore);
+        let trash_restore_path =
+            (!value.trash_restore_path.is_empty()).then_some(value.trash_restore_path);
+        let node_ip = IpAddr::from_str(value.node_ip.as_str()).map_err(|e| {
+            DatenLordError::ArgumentInvalid {
+                context: vec![format!("node ip {} is invalid: {}", value.node_ip, e)],
+            }
+        })?;
+        let mount_path = value.mount_path;
+        let mut storage: StorageConfig = value.storage.try_into()?;
+        let tls = TlsConfig::try_from_files(
+            value.tls_cert_file,
+            value.tls_key_file,
+            value.tls_ca_file,
+            value.mtls,
+        )?;
+        // The transfers are over the TLS of the cluster unless they're given
+        // their own.
+        if let Some(ref mut transfer_config) = storage.transfer_config {
+            if transfer_config.tls.is_none() {
+                transfer_config.tls = tls.clone();
+            }
+        }
+        let kv_addrs: Vec<String> = value.kv_server_list;
+        if kv_addrs.is_empty() {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["kv server addresses is empty".to_owned()],
+            });
+        }
+        let csi_config = value.csi_config.try_into()?;
+        let volume_config = VolumeConfig::try_from_super(value.volume_config)?;
+        let bench_config = BenchConfig::try_from_super(value.bench_config)?;
+        let gateway_listen = SocketAddr::from_str(value.gateway_listen.as_str()).map_err(|e| {
+            DatenLordError::ArgumentInvalid {
+                context: vec![format!(
+                    "gateway address {} is invalid: {}",
+                    value.gateway_listen, e
+                )],
+            }
+        })?;
+        let gateway_s3_listen = if value.gateway_s3_listen.is_empty() {
+            None
+        } else {
+            let addr = SocketAddr::from_str(value.gateway_s3_listen.as_str()).map_err(|e| {
+                DatenLordError::ArgumentInvalid {
+                    context: vec![format!(
+                        "S3 gateway address {} is invalid: {}",
+                        value.gateway_s3_listen, e
+                    )],
+                }
+            })?;
+            Some(addr)
+        };
+        let virtiofs_socket =
+            (!value.virtiofs_socket.is_empty()).then(|| PathBuf::from(value.virtiofs_socket));
+        // The guest mounts the volume by itself, so it cannot be forced to
+        // mount a snapshot read-only.
+        if virtiofs_socket.is_some() && snapshot.is_some() {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["a snapshot cannot be served by virtio-fs".to_owned()],
+            });
+        }
+        let selinux_context = (!value.selinux_context.is_empty()).then_some(value.selinux_context);
+        if let Some(ref selinux_context) = selinux_context {
+            // It's quoted in the mount options, for the commas of the
+            // categories.
+            if selinux_context.contains('"') {
+                return Err(DatenLordError::ArgumentInvalid {
+                    context: vec![format!("SELinux context {selinux_context} is invalid")],
+                });
+            }
+            if virtiofs_socket.is_some() {
+                return Err(DatenLordError::ArgumentInvalid {
+                    context: vec!["the guest of virtio-fs mounts with its own context".to_owned()],
+                });
+            }
+        }
+        let id_map = IdMap::new(
+            value
+                .uid_map
+                .iter()
+                .map(|range| range.parse())
+                .collect::<Result<_, _>>()?,
+            value
+                .gid_map
+                .iter()
+                .map(|range| range.parse())
+                .collect::<Result<_, _>>()?,
+        )?;
+        let squash = Squash {
+            mode: if value.all_squash {
+                SquashMode::All
+            } else if value.root_squash {
+                SquashMode::Root
+            } else {
+                SquashMode::None
+            },
+            anon_uid: value.anon_uid,
+            anon_gid: value.anon_gid,
+        };
+        let op_mask = OpMask::try_from_super(&value.deny_ops, &value.allow_ops, value.read_only)?;
+        let auth = AuthConfig::try_from_super(
+            role,
+            value.auth_key_file,
+            value.auth_policy_file,
+            value.tenant_token,
+            value.tenant,
+        )?;
+        let control_socket =
+            (!value.control_socket.is_empty()).then(|| PathBuf::from(value.control_socket));
+        if matches!(
+            role,
+            Role::Top
+                | Role::Drain
+                | Role::Stats
+                | Role::Inspect
+                | Role::SlowLog
+                | Role::WarmUp
+                | Role::Pin
+        ) && control_socket.is_none()
+        {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!("the {role:?} role requires the control socket")],
+            });
+        }
+        let top_interval =
+            (value.top_interval != 0).then(|| Duration::from_secs(value.top_interval));
+        let output_format = value.output_format.parse()?;
+        let health_port = (value.health_port != 0).then_some(value.health_port);
+        if role == Role::Health && health_port.is_none() {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["the health role requires the health port".to_owned()],
+            });
+        }
+        let health_probe = value.health_probe.parse()?;
+        let usage_checkpoint_interval = (value.usage_checkpoint_interval != 0)
+            .then(|| Duration::from_secs(value.usage_checkpoint_interval));
+        let fuse_trace = (!value.fuse_trace.is_empty()).then(|| PathBuf::from(value.fuse_trace));
+        if role == Role::FuseDump && fuse_trace.is_none() {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["the fusedump role requires the FUSE trace".to_owned()],
+            });
+        }
+        if role == Role::Admin
+            && matches!(
+                volume_config.command,
+                AdminCommand::Snapshot | AdminCommand::DeleteSnapshot
+            )
+            && snapshot.is_none()
+        {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!(
+                    "the admin command {} requires the snapshot",
+                    volume_config.command
+                )],
+            });
+        }
+        let log_level = value
+            .log_level
+            .parse()
+            .map_err(|_| DatenLordError::ArgumentInvalid {
+                context: vec![format!(
+                    "log level {} is not one of off, error, warn, info, debug and trace",
+                    value.log_level
+                )],
+            })?;
+        let log_format = value.log_format.parse()?;
+        let log_sample_rate = NonZeroU64::new(value.log_sample_rate).ok_or_else(|| {
+            DatenLordError::ArgumentInvalid {
+                context: vec!["the log sample rate must be positive".to_owned()],
+            }
+        })?;
+        let otlp_endpoint = (!value.otlp_endpoint.is_empty()).then_some(value.otlp_endpoint);
+        if !(0.0..=1.0).contains(&value.otlp_sample_ratio) {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!(
+                    "the OTLP sample ratio {} is not between 0 and 1",
+                    value.otlp_sample_ratio
+                )],
+            });
+        }
+        let slow_log_threshold = (value.slow_log_threshold != 0)
+            .then(|| Duration::from_millis(value.slow_log_threshold));
+        if value.slow_log_capacity == 0 {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["the slow log capacity must be positive".to_owned()],
+            });
+        }
+        let warmup_file_list =
+            (!value.warmup_file_list.is_empty()).then(|| PathBuf::from(value.warmup_file_list));
+        if role == Role::WarmUp && value.warmup_paths.is_empty() && warmup_file_list.is_none() {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["the warmup role requires the paths or the file list".to_owned()],
+            });
+        }
+        if value.unpin && value.warmup_paths.is_empty() && warmup_file_list.is_none() {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["unpinning requires the paths or the file list".to_owned()],
+            });
+        }
+        let warmup_bandwidth = match value.warmup_bandwidth.checked_mul(1 << 20) {
+            Some(bandwidth) => NonZeroU64::new(bandwidth),
+            None => {
+                return Err(DatenLordError::ArgumentInvalid {
+                    context: vec![format!(
+                        "the warm-up bandwidth {} MiB per second is too large",
+                        value.warmup_bandwidth
+                    )],
+                });
+            }
+        };
+        let config_file = (!value.config_file.is_empty()).then(|| PathBuf::from(value.config_file));
+        if standalone && storage.replication_config.is_some() {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["the standalone mode cannot replicate the blocks".to_owned()],
+            });
+        }
+        Ok(InnerConfig {
+            role,
+            node_name,
+            node_ip,
+            mount_path,
+            kv_addrs,
+            server_port,
+            scheduler_extender_port,
+            fsck_repair,
+            snapshot,
+            snapshot_delete,
+            trash_restore,
+            trash_restore_path,
+            storage,
+            csi_config,
+            volume_config,
+            bench_config,
+            gateway_listen,
+            gateway_s3_listen,
+            virtiofs_socket,
+            standalone,
+            selinux_context,
+            id_map,
+            squash,
+            op_mask,
+            tls,
+            auth,
+            control_socket,
+            top_interval,
+            output_format,
+            health_port,
+            health_probe,
+            usage_checkpoint_interval,
+            fuse_trace,
+            fusedump_replay: value.fusedump_replay,
+            log_level,
+            log_format,
+            log_sample_rate,
+            otlp_endpoint,
+            otlp_sample_ratio: value.otlp_sample_ratio,
+            slow_log_threshold,
+            slow_log_capacity: value.slow_log_capacity,
+            warmup_paths: value.warmup_paths,
+            warmup_file_list,
+            warmup_bandwidth,
+            unpin: value.unpin,
+            config_file,
+        })
+    }
+}
+
+/// A probe of the health of a mount
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum HealthProbe {
+    /// Whether the mount is alive, or should be restarted
+    Liveness,
+    /// Whether the mount is ready to serve
+    Readiness,
+}
+
+impl FromStr for HealthProbe {
+    type Err = DatenLordError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "live" => Ok(Self::Liveness),
+            "ready" => Ok(Self::Readiness),
+            _ => Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!("health probe {s} is not one of live and ready")],
+            }),
+        }
+    }
+}
+
+/// The format of the log
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum LogFormat {
+    /// The lines of text to read
+    Text,
+    /// The lines of JSON for the log pipelines
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = DatenLordError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!("log format {s} is not one of text and json")],
+            }),
+        }
+    }
+}
+
+/// The format the state of a mount is shown in
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// The tables to read
+    Table,
+    /// JSON for the scripts
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = DatenLordError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "table" => Ok(Self::Table),
+            "json" => Ok(Self::Json),
+            _ => Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!("output format {s} is not one of table and json")],
+            }),
+        }
+    }
+}
+
+/// What the bench role drives
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BenchTarget {
+    /// The file system of the volume in the process, bypassing the kernel
+    Core,
+    /// The volume mounted by FUSE
+    Mount,
+}
+
+impl FromStr for BenchTarget {
+    type Err = DatenLordError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "core" => Ok(Self::Core),
+            "mount" => Ok(Self::Mount),
+            _ => Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!("bench target {s} is not one of core and mount")],
+            }),
+        }
+    }
+}
+
+/// A command of the admin role
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum AdminCommand {
+    /// List the volumes
+    List,
+    /// Create the volume
+    Create,
+    /// Delete the volume
+    Delete,
+    /// Resize the volume to the capacity
+    Resize,
+    /// Show the quota and the usage of the volume, or of all the volumes
+    Quota,
+    /// Create the snapshot
+    Snapshot,
+    /// List the snapshots
+    Snapshots,
+    /// Delete the snapshot and collect the garbage
+    DeleteSnapshot,
+    /// List the nodes mounting the file system
+    Nodes,
+    /// Request a scrub pass of the nodes
+    Scrub,
+    /// Collect the block versions seen by neither the file system nor any
+    /// snapshot
+    Gc,
+}
+
+impl FromStr for AdminCommand {
+    type Err = DatenLordError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "list" => Ok(Self::List),
+            "create" => Ok(Self::Create),
+            "delete" => Ok(Self::Delete),
+            "resize" => Ok(Self::Resize),
+            "quota" => Ok(Self::Quota),
+            "snapshot" => Ok(Self::Snapshot),
+            "snapshots" => Ok(Self::Snapshots),
+            "delete-snapshot" => Ok(Self::DeleteSnapshot),
+            "nodes" => Ok(Self::Nodes),
+            "scrub" => Ok(Self::Scrub),
+            "gc" => Ok(Self::Gc),
+            _ => Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!("admin command {s} is not supported")],
+            }),
+        }
+    }
+}
+
+impl fmt::Display for AdminCommand {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            Self::List => "list",
+            Self::Create => "create",
+            Self::Delete => "delete",
+            Self::Resize => "resize",
+            Self::Quota => "quota",
+            Self::Snapshot => "snapshot",
+            Self::Snapshots => "snapshots",
+            Self::DeleteSnapshot => "delete-snapshot",
+            Self::Nodes => "nodes",
+            Self::Scrub => "scrub",
+            Self::Gc => "gc",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// The authentication and authorization config of the tenants
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AuthConfig {
+    /// The file the key of the tenant tokens is derived from, `None` if the
+    /// tenants are not authenticated
+    pub key_file: Option<String>,
+    /// The file of the authorization policy, `None` if the mount serves no
+    /// tenant
+    pub policy_file: Option<String>,
+    /// The token of the tenant served by the mount
+    pub token: Option<String>,
+    /// The tenant to issue a token of, by the tenant role
+    pub tenant: Option<String>,
+}
+
+impl AuthConfig {
+    /// Convert from the command line config of `role`.
+    fn try_from_super(
+        role: Role,
+        key_file: String,
+        policy_file: String,
+        token: String,
+        tenant: String,
+    ) -> Result<Self, DatenLordError> {
+        let non_empty = |value: String| (!value.is_empty()).then_some(value);
+        let config = Self {
+            key_file: non_empty(key_file),
+            policy_file: non_empty(policy_file),
+            token: non_empty(token),
+            tenant: non_empty(tenant),
+        };
+        let invalid = |message: &str| {
+            Err(DatenLordError::ArgumentInvalid {
+                context: vec![message.to_owned()],
+            })
+        };
+        if config.policy_file.is_some() != config.token.is_some() {
+            return invalid("The auth policy and the tenant token must be set together.");
+        }
+        if config.policy_file.is_some() && config.key_file.is_none() {
+            return invalid("The tenant token is verified by the auth key file.");
+        }
+        if role == Role::Tenant && (config.key_file.is_none() || config.tenant.is_none()) {
+            return invalid("The tenant role requires the auth key file and the tenant.");
+        }
+        Ok(config)
+    }
+}
+
+/// The id seen for the ids not mapped, the `overflowuid` and `overflowgid` of
+/// Linux.
+pub const OVERFLOW_ID: u32 = 65534;
+
+/// A range of ids mapped between a mount and the volume, like a line of the
+/// uid map of a user namespace
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IdRange {
+    /// The first id seen on the mount
+    pub mount: u32,
+    /// The first id stored in the volume
+    pub volume: u32,
+    /// The number of ids
+    pub count: u32,
+}
+
+impl IdRange {
+    /// Map `id` from the start `from` of the range to the start `to`, `None`
+    /// if it's out of the range.
+    fn map(self, id: u32, from: u32, to: u32) -> Option<u32> {
+        let offset = id.checked_sub(from)?;
+        (offset < self.count).then(|| to.overflow_add(offset))
+    }
+
+    /// Whether the range overlaps `other` on the mount or in the volume.
+    fn overlaps(self, other: Self) -> bool {
+        let overlaps = |start: u32, other_start: u32| {
+            u64::from(start) < u64::from(other_start).overflow_add(u64::from(other.count))
+                && u64::from(other_start) < u64::from(start).overflow_add(u64::from(self.count))
+        };
+        overlaps(self.mount, other.mount) || overlaps(self.volume, other.volume)
+    }
+}
+
+impl FromStr for IdRange {
+    type Err = DatenLordError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DatenLordError::ArgumentInvalid {
+            context: vec![format!(
+                "id range {s} is not in the form of `MOUNT:VOLUME:COUNT`"
+            )],
+        };
+        let mut ids = s.split(':').map(|id| id.trim().parse::<u32>());
+        let (Some(Ok(mount)), Some(Ok(volume)), Some(Ok(count)), None) =
+            (ids.next(), ids.next(), ids.next(), ids.next())
+        else {
+            return Err(invalid());
+        };
+        // The ranges must not wrap around.
+        let end = |start: u32| u64::from(start).overflow_add(u64::from(count));
+        if count == 0 || end(mount) > 1 << 32_i32 || end(volume) > 1 << 32_i32 {
+            return Err(invalid());
+        }
+        Ok(Self {
+            mount,
+            volume,
+            count,
+        })
+    }
+}
+
+/// The mapping of the uids and gids between a mount and the volume, for the
+/// mounts in containers whose user namespaces don't match the owners in the
+/// volume.
+///
+/// The ids of the requests and the owners set are mapped into the volume,
+/// and the owners replied are mapped back. Once a map of uids or gids is
+/// given, the ids out of its ranges are mapped to [`OVERFLOW_ID`], so the
+/// ids unknown to the other side don't alias the ones known. An empty map
+/// leaves the ids as they are.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IdMap {
+    /// The ranges of uids
+    uids: Vec<IdRange>,
+    /// The ranges of gids
+    gids: Vec<IdRange>,
+}
+
+impl IdMap {
+    /// Create a map of the ranges of uids and gids, which must not overlap.
+    #[inline]
+    pub fn new(uids: Vec<IdRange>, gids: Vec<IdRange>) -> Result<Self, DatenLordError> {
+        for ranges in [&uids, &gids] {
+            for (i, range) in ranges.iter().enumerate() {
+                if let Some(other) = ranges
+                    .iter()
+                    .skip(i.overflow_add(1))
+                    .find(|other| range.overlaps(**other))
+                {
+                    return Err(DatenLordError::ArgumentInvalid {
+                        context: vec![format!("id ranges {range:?} and {other:?} overlap")],
+                    });
+                }
+            }
+        }
+        Ok(Self { uids, gids })
+    }
+
+    /// Whether no id is mapped.
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.uids.is_empty() && self.gids.is_empty()
+    }
+
+    /// Map `id` by `ranges` into the volume, or to the mount if `to_mount`.
+    fn map(ranges: &[IdRange], id: u32, to_mount: bool) -> u32 {
+        if ranges.is_empty() {
+            return id;
+        }
+        ranges
+            .iter()
+            .find_map(|range| {
+                if to_mount {
+                    range.map(id, range.volume, range.mount)
+                } else {
+                    range.map(id, range.mount, range.volume)
+                }
+            })
+            .unwrap_or(OVERFLOW_ID)
+    }
+
+    /// Map a uid seen on the mount into the volume.
+    #[inline]
+    #[must_use]
+    pub fn uid_to_volume(&self, uid: u32) -> u32 {
+        Self::map(&self.uids, uid, false)
+    }
+
+    /// Map a gid seen on the mount into the volume.
+    #[inline]
+    #[must_use]
+    pub fn gid_to_volume(&self, gid: u32) -> u32 {
+        Self::map(&self.gids, gid, false)
+    }
+
+    /// Map a uid stored in the volume to the mount.
+    #[inline]
+    #[must_use]
+    pub fn uid_to_mount(&self, uid: u32) -> u32 {
+        Self::map(&self.uids, uid, true)
+    }
+
+    /// Map a gid stored in the volume to the mount.
+    #[inline]
+    #[must_use]
+    pub fn gid_to_mount(&self, gid: u32) -> u32 {
+        Self::map(&self.gids, gid, true)
+    }
+}
+
+/// The callers of a mount squashed to the anonymous user.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum SquashMode {
+    /// No caller is squashed
+    #[default]
+    None,
+    /// Root is squashed
+    Root,
+    /// All the callers are squashed
+    All,
+}
+
+/// The squashing of the callers of a mount, like the `root_squash` and
+/// `all_squash` of NFS exports, for the nodes not trusted with the root of
+/// the volume.
+///
+/// A caller squashed runs as the anonymous user, whose ids are the ones
+/// stored in the volume, they're not mapped by the [`IdMap`] of the mount.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Squash {
+    /// The callers squashed
+    pub mode: SquashMode,
+    /// The uid of the anonymous user in the volume
+    pub anon_uid: u32,
+    /// The gid of the anonymous user in the volume
+    pub anon_gid: u32,
+}
+
+impl Default for Squash {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            mode: SquashMode::None,
+            anon_uid: OVERFLOW_ID,
+            anon_gid: OVERFLOW_ID,
+        }
+    }
+}
+
+impl Squash {
+    /// The uid and the gid of the anonymous user if the caller of `uid` seen
+    /// on the mount is squashed, `None` if it's not.
+    #[inline]
+    #[must_use]
+    pub fn squash(&self, uid: u32) -> Option<(u32, u32)> {
+        let squashed = match self.mode {
+            SquashMode::None => false,
+            SquashMode::Root => uid == 0,
+            SquashMode::All => true,
+        };
+        squashed.then_some((self.anon_uid, self.anon_gid))
+    }
+}
+
+/// A class of the FUSE operations, which may be turned off on a mount
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OpClass {
+    /// The changes of the nodes, refused with `EROFS`
+    Write,
+    /// The extended attributes, refused with `EOPNOTSUPP`
+    Xattr,
+    /// Creating the device nodes, refused with `EPERM`
+    Device,
+    /// The POSIX and BSD locks, refused with `ENOLCK`
+    Lock,
+}
+
+impl OpClass {
+    /// All the classes
+    pub const ALL: [Self; 4] = [Self::Write, Self::Xattr, Self::Device, Self::Lock];
+
+    /// The bit of the class in an `OpMask`
+    fn bit(self) -> u8 {
+        match self {
+            Self::Write => 0b0001,
+            Self::Xattr => 0b0010,
+            Self::Device => 0b0100,
+            Self::Lock => 0b1000,
+        }
+    }
+}
+
+impl FromStr for OpClass {
+    type Err = DatenLordError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "write" => Ok(Self::Write),
+            "xattr" => Ok(Self::Xattr),
+            "device" => Ok(Self::Device),
+            "lock" => Ok(Self::Lock),
+            _ => Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!(
+                    "operation class {s} is not one of write, xattr, device and lock"
+                )],
+            }),
+        }
+    }
+}
+
+impl fmt::Display for OpClass {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            Self::Write => "write",
+            Self::Xattr => "xattr",
+            Self::Device => "device",
+            Self::Lock => "lock",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// The classes of the FUSE operations turned off on a mount, for the hardened
+/// or the read-only exports. Looking up and reading the nodes are never
+/// turned off.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OpMask {
+    /// The bits of the classes turned off
+    denied: u8,
+}
+
+impl OpMask {
+    /// Turn off `classes`.
+    #[inline]
+    #[must_use]
+    pub fn deny(classes: &[OpClass]) -> Self {
+        Self {
+            denied: classes.iter().fold(0, |bits, class| bits | class.bit()),
+        }
+    }
+
+    /// Turn off all the classes but `classes`.
+    #[inline]
+    #[must_use]
+    pub fn allow(classes: &[OpClass]) -> Self {
+        let allowed = Self::deny(classes);
+        Self {
+            denied: Self::deny(&OpClass::ALL).denied & !allowed.denied,
+        }
+    }
+
+    /// Whether `class` is turned off.
+    #[inline]
+    #[must_use]
+    pub fn denies(self, class: OpClass) -> bool {
+        self.denied & class.bit() != 0
+    }
+
+    /// Build the mask from the classes of `--deny-ops` or `--allow-ops`, at
+    /// most one of which is given, the writes are turned off as well if
+    /// `read_only`.
+    fn try_from_super(
+        deny_ops: &[String],
+        allow_ops: &[String],
+        read_only: bool,
+    ) -> Result<Self, DatenLordError> {
+        let parse = |ops: &[String]| -> Result<Vec<OpClass>, DatenLordError> {
+            ops.iter().map(|op| op.parse()).collect()
+        };
+        let mut mask = match (deny_ops.is_empty(), allow_ops.is_empty()) {
+            (true, true) => Self::default(),
+            (false, true) => Self::deny(&parse(deny_ops)?),
+            (true, false) => Self::allow(&parse(allow_ops)?),
+            (false, false) => {
+                return Err(DatenLordError::ArgumentInvalid {
+                    context: vec!["--deny-ops and --allow-ops cannot be given together".to_owned()],
+                });
+            }
+        };
+        if read_only {
+            mask.denied |= OpClass::Write.bit();
+        }
+        Ok(mask)
+    }
+}
+
+/// Storage related config
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StorageConfig {
+    /// The size of blocks, default is 4 MiB.
+    pub block_size: usize,
+    /// The size of blocks of a volume created before its chunk size is
+    /// persisted, the one given, or 512 KiB otherwise.
+    pub legacy_block_size: usize,
+    /// The maximum window of sequential read-ahead in blocks, default is 8.
+    /// Read-ahead is disabled if it's 0.
+    pub read_ahead_window: usize,
+    /// The compression of blocks in the backend, default is none.
+    pub compression: CompressionType,
+    /// Whether the versions of blocks are kept for snapshots
+    pub snapshot: bool,
+    /// The capacity of the volume in bytes, `None` if it's unlimited
+    pub capacity: Option<u64>,
+    /// How long removed files are kept in the trash, `None` if the trash is
+    /// disabled
+    pub trash_retention: Option<Duration>,
+    /// The max size of the files whose content is inlined in their metadata,
+    /// `None` if inlining is disabled
+    pub inline_data_threshold: Option<u64>,
+    /// The max number of the children prefetched when a directory is listed,
+    /// `None` if the stat-ahead is disabled
+    pub stat_ahead_limit: Option<usize>,
+    /// How long the names missing in directories are cached, `None` if
+    /// they're not cached
+    pub negative_cache_ttl: Option<Duration>,
+    /// The max bytes of the cached directory entries, `None` if they're not
+    /// cached
+    pub dentry_cache_capacity: Option<usize>,
+    /// The max bytes of memory taken by the caches and the buffers of
+    /// requests altogether, `None` if they're only bounded one by one
+    pub memory_budget: Option<usize>,
+    /// Whether the kernel caches the writes and writes them back later
+    pub writeback_cache: bool,
+    /// The consistency among the nodes, default is strict.
+    pub consistency: ConsistencyModel,
+    /// The max percentage of the lowest local cache taken by the pinned
+    /// files, `None` if pinning is disabled
+    pub pin_limit: Option<u32>,
+    /// Cache capacity
+    pub memory_cache_config: MemoryCacheConfig,
+    /// The disk cache config, `None` if the disk cache is disabled
+    pub disk_cache_config: Option<DiskCacheConfig>,
+    /// The dedup config, `None` if the dedup is disabled
+    pub dedup_config: Option<DedupConfig>,
+    /// The pack config, `None` if packing is disabled
+    pub pack_config: Option<PackConfig>,
+    /// The checksum config, `None` if checksums are disabled
+    pub checksum_config: Option<ChecksumConfig>,
+    /// The encryption config, `None` if no master key is provided
+    pub encryption_config: Option<EncryptionConfig>,
+    /// The GC config, `None` if GC is disabled
+    pub gc_config: Option<GcConfig>,
+    /// The tiering config, `None` if tiering is disabled
+    pub tiering_config: Option<TieringConfig>,
+    /// The transfer config, `None` if the transfer service is disabled
+    pub transfer_config: Option<TransferConfig>,
+    /// The replication config, `None` if replication is disabled
+    pub replication_config: Option<ReplicationConfig>,
+    /// The peer cache config, `None` if the caches are not shared among the
+    /// nodes
+    pub peer_cache_config: Option<PeerCacheConfig>,
+    /// How the failures of the backend are retried and returned
+    pub backend_error_config: BackendErrorConfig,
+    /// Storage params
+    pub params: StorageParams,
+}
+
+impl TryFrom<SuperStorageConfig> for StorageConfig {
+    type Error = DatenLordError;
+
+    #[inline]
+    fn try_from(value: SuperStorageConfig) -> Result<Self, Self::Error> {
+        let memory_cache_config = value.memory_cache_config.try_into()?;
+        let params = match value.storage_type.to_lowercase().as_str() {
+            "s3" => StorageParams::S3(value.s3_storage_config.try_into()?),
+            "fs" => StorageParams::Fs(value.fs_storage_root),
+            _ => {
+                return Err(DatenLordError::ArgumentInvalid {
+                    context: vec![format!(
+                        "storage type {} is not supported",
+                        value.storage_type
+                    )],
+                })
+            }
+        };
+        let block_size = value.block_size.unwrap_or(DEFAULT_BLOCK_SIZE);
+        let legacy_block_size = value.block_size.unwrap_or(LEGACY_BLOCK_SIZE);
+        if block_size == 0 {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["The block size of storage is 0.".to_owned()],
+            });
+        }
+        let disk_cache_config = DiskCacheConfig::try_from_super(value.disk_cache_config)?;
+        let dedup_config = DedupConfig::try_from_super(value.dedup_config)?;
+        let pack_config = PackConfig::try_from_super(value.pack_config)?;
+        let checksum_config = ChecksumConfig::from_super(value.checksum_config);
+        let encryption_config = EncryptionConfig::try_from_super(value.encryption_config)?;
+        let gc_config = GcConfig::from_super(value.gc_config);
+        let tiering_config = TieringConfig::try_from_super(value.tiering_config)?;
+        let transfer_config = TransferConfig::try_from_super(value.transfer_config)?;
+        let replication_config = ReplicationConfig::try_from_super(value.replication_config)?;
+        let peer_cache_config = PeerCacheConfig::try_from_super(value.peer_cache_config)?;
+        let backend_error_config = BackendErrorConfig::try_from_super(value.backend_error_config)?;
+        if value.snapshot && dedup_config.is_some() {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["snapshot cannot be enabled with dedup".to_owned()],
+            });
+        }
+        if tiering_config.is_some() && (value.snapshot || dedup_config.is_some()) {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["tiering cannot be enabled with snapshot or dedup".to_owned()],
+            });
+        }
+        if replication_config.is_some() {
+            if value.snapshot || dedup_config.is_some() || tiering_config.is_some() {
+                return Err(DatenLordError::ArgumentInvalid {
+                    context: vec![
+                        "replication cannot be enabled with snapshot, dedup or tiering".to_owned(),
+                    ],
+                });
+            }
+            if transfer_config.is_none() {
+                return Err(DatenLordError::ArgumentInvalid {
+                    context: vec!["replication requires the transfer service".to_owned()],
+                });
+            }
+        }
+        if pack_config.is_some()
+            && (value.snapshot
+                || dedup_config.is_some()
+                || tiering_config.is_some()
+                || replication_config.is_some())
+        {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec![
+                    "packing cannot be enabled with snapshot, dedup, tiering or replication"
+                        .to_owned(),
+                ],
+            });
+        }
+        if peer_cache_config.is_some() && transfer_config.is_none() {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["the peer cache requires the transfer service".to_owned()],
+            });
+        }
+        if value.pin_limit > 100 {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!(
+                    "storage pin limit {}% is more than 100%",
+                    value.pin_limit
+                )],
+            });
+        }
+        Ok(StorageConfig {
+            block_size,
+            legacy_block_size,
+            read_ahead_window: value.read_ahead_window,
+            compression: value.compression.parse()?,
+            snapshot: value.snapshot,
+            capacity: (value.capacity != 0).then_some(value.capacity),
+            trash_retention: (value.trash_retention != 0)
+                .then(|| Duration::from_secs(value.trash_retention)),
+            inline_data_threshold: (value.inline_data_threshold != 0)
+                .then_some(value.inline_data_threshold),
+            stat_ahead_limit: (value.stat_ahead_limit != 0).then_some(value.stat_ahead_limit),
+            negative_cache_ttl: (value.negative_cache_ttl != 0)
+                .then(|| Duration::from_secs(value.negative_cache_ttl)),
+            dentry_cache_capacity: (value.dentry_cache_capacity != 0)
+                .then_some(value.dentry_cache_capacity),
+            memory_budget: (value.memory_budget != 0).then_some(value.memory_budget),
+            writeback_cache: value.writeback_cache,
+            consistency: value.consistency.parse()?,
+            pin_limit: (value.pin_limit != 0).then_some(value.pin_limit),
+            memory_cache_config,
+            disk_cache_config,
+            dedup_config,
+            pack_config,
+            checksum_config,
+            encryption_config,
+            gc_config,
+            tiering_config,
+            transfer_config,
+            replication_config,
+            peer_cache_config,
+            backend_error_config,
+            params,
+        })
+    }
+}
+
+/// The type of evict policy of caches
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum EvictPolicyType {
+    /// Least recently used
+    Lru,
+    /// Least frequently used
+    Lfu,
+    /// Adaptive replacement cache
+    Arc,
+    /// CLOCK (second chance)
+    Clock,
+}
+
+impl FromStr for EvictPolicyType {
+    type Err = DatenLordError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "lru" => Ok(EvictPolicyType::Lru),
+            "lfu" => Ok(EvictPolicyType::Lfu),
+            "arc" => Ok(EvictPolicyType::Arc),
+            "clock" => Ok(EvictPolicyType::Clock),
+            _ => Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!("evict policy {s} is not supported")],
+            }),
+        }
+    }
+}
+
+/// The compression algorithm of blocks
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum CompressionType {
+    /// Blocks are stored as is
+    #[default]
+    None,
+    /// LZ4, fast with a moderate ratio
+    Lz4,
+    /// Zstandard, slower with a better ratio
+    Zstd,
+}
+
+impl FromStr for CompressionType {
+    type Err = DatenLordError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(CompressionType::None),
+            "lz4" => Ok(CompressionType::Lz4),
+            "zstd" => Ok(CompressionType::Zstd),
+            _ => Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!("compression {s} is not supported")],
+            }),
+        }
+    }
+}
+
+impl fmt::Display for CompressionType {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            CompressionType::None => "none",
+            CompressionType::Lz4 => "lz4",
+            CompressionType::Zstd => "zstd",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// The consistency among the nodes mounting a volume
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ConsistencyModel {
+    /// The caches are kept coherent by the leases of files
+    #[default]
+    Strict,
+    /// Files are flushed on close and revalidated on open, like NFS
+    CloseToOpen,
+}
+
+impl FromStr for ConsistencyModel {
+    type Err = DatenLordError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "strict" => Ok(ConsistencyModel::Strict),
+            "close-to-open" => Ok(ConsistencyModel::CloseToOpen),
+            _ => Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!("consistency {s} is not supported")],
+            }),
+        }
+    }
+}
+
+impl fmt::Display for ConsistencyModel {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            ConsistencyModel::Strict => "strict",
+            ConsistencyModel::CloseToOpen => "close-to-open",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// The storage tier of blocks
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum Tier {
+    /// The fast tier, usually on SSD
+    Hot,
+    /// The cheap tier, usually the object storage
+    Cold,
+}
+
+impl FromStr for Tier {
+    type Err = DatenLordError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hot" => Ok(Tier::Hot),
+            "cold" => Ok(Tier::Cold),
+            _ => Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!("tier {s} is not supported")],
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Tier {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Tier::Hot => write!(f, "hot"),
+            Tier::Cold => write!(f, "cold"),
+        }
+    }
+}
+
+/// A rule pinning the files under a path to a tier
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TierRule {
+    /// The absolute path, without the trailing slash
+    pub path: String,
+    /// The tier of the files under the path
+    pub tier: Tier,
+}
+
+impl FromStr for TierRule {
+    type Err = DatenLordError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DatenLordError::ArgumentInvalid {
+            context: vec![format!("tier rule {s} is not in the form of `PATH=TIER`")],
+        };
+        let (path, tier) = s.rsplit_once('=').ok_or_else(invalid)?;
+        if !path.starts_with('/') {
+            return Err(invalid());
+        }
+        Ok(Self {
+            path: path.trim_end_matches('/').to_owned(),
+            tier: tier.parse()?,
+        })
+    }
+}
+
+/// The number of replicas persisting a block before a write is acknowledged
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum WriteQuorum {
+    /// All replicas
+    All,
+    /// More than half of the replicas
+    Majority,
+    /// A fixed number of replicas
+    Count(usize),
+}
+
+impl WriteQuorum {
+    /// The number of replicas required with `factor` replicas, which is at
+    /// least 1 and at most `factor`.
+    #[inline]
+    #[must_use]
+    pub fn required(self, factor: usize) -> usize {
+        let required = match self {
+            WriteQuorum::All => factor,
+            WriteQuorum::Majority => factor.overflow_div(2).overflow_add(1),
+            WriteQuorum::Count(count) => count,
+        };
+        required.clamp(1, factor.max(1))
+    }
+}
+
+impl FromStr for WriteQuorum {
+    type Err = DatenLordError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "all" => Ok(WriteQuorum::All),
+            "majority" => Ok(WriteQuorum::Majority),
+            count => match count.parse() {
+                Ok(count) if count > 0 => Ok(WriteQuorum::Count(count)),
+                _ => Err(DatenLordError::ArgumentInvalid {
+                    context: vec![format!("write quorum {s} is not supported")],
+                }),
+            },
+        }
+    }
+}
+
+/// The layout of blocks on the replica nodes
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum StoragePolicy {
+    /// Every block is replicated
+    #[default]
+    Replicate,
+    /// Every block is erasure coded
+    Erasure,
+    /// The hot blocks are replicated, and the cold ones are erasure coded
+    Tiered,
+}
+
+impl FromStr for StoragePolicy {
+    type Err = DatenLordError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "replicate" => Ok(StoragePolicy::Replicate),
+            "erasure" => Ok(StoragePolicy::Erasure),
+            "tiered" => Ok(StoragePolicy::Tiered),
+            _ => Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!("storage policy {s} is not supported")],
+            }),
+        }
+    }
+}
+
+impl fmt::Display for StoragePolicy {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            StoragePolicy::Replicate => "replicate",
+            StoragePolicy::Erasure => "erasure",
+            StoragePolicy::Tiered => "tiered",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// The shards of an erasure coded block
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ErasureShards {
+    /// The number of data shards
+    pub data: usize,
+    /// The number of parity shards
+    pub parity: usize,
+}
+
+impl ErasureShards {
+    /// The maximal number of shards of a block, which is limited by the
+    /// Galois field of the Reed-Solomon code.
+    pub const MAX_TOTAL: usize = 256;
+
+    /// The number of all shards.
+    #[inline]
+    #[must_use]
+    pub fn total(self) -> usize {
+        self.data.overflow_add(self.parity)
+    }
+}
+
+/// The policy of the circuit breakers of the replica nodes
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BreakerPolicy {
+    /// The number of consecutive failures of a node tripping its breaker
+    pub failures: u32,
+    /// How long the operations on a node fail fast after its breaker trips,
+    /// before an operation is passed to probe it
+    pub cooldown: Duration,
+}
+
+/// A node storing the replicas of blocks
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ReplicaNode {
+    /// The id of the node
+    pub node_id: String,
+    /// The endpoint of the transfer service of the node
+    pub endpoint: String,
+}
+
+impl FromStr for ReplicaNode {
+    type Err = DatenLordError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((node_id, endpoint)) if !node_id.is_empty() && !endpoint.is_empty() => Ok(Self {
+                node_id: node_id.to_owned(),
+                endpoint: endpoint.to_owned(),
+            }),
+            _ => Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!(
+                    "replica node {s} is not in the form of `NODE_ID=ENDPOINT`"
+                )],
+            }),
+        }
+    }
+}
+
+/// Dedup config
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct DedupConfig {
+    /// The average size of chunks in bytes, default is 64 KiB.
+    pub avg_chunk_size: usize,
+}
+
+impl Default for DedupConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            avg_chunk_size: 0x1_0000,
+        }
+    }
+}
+
+impl DedupConfig {
+    /// Convert from the command line config, returns `None` if the dedup is
+    /// disabled. The average chunk size must be a power of two and at least
+    /// 64 bytes.
+    fn try_from_super(value: SuperDedupConfig) -> Result<Option<Self>, DatenLordError> {
+        let SuperDedupConfig {
+            enabled,
+            avg_chunk_size,
+        } = value;
+
+        if !enabled {
+            return Ok(None);
+        }
+
+        if !avg_chunk_size.is_power_of_two() || avg_chunk_size < 64 {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!(
+                    "The average chunk size {avg_chunk_size} is not a power of two, or is less \
+                     than 64."
+                )],
+            });
+        }
+
+        Ok(Some(Self { avg_chunk_size }))
+    }
+}
+
+/// Pack config
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct PackConfig {
+    /// The max length of the blocks inlined into the metadata, default is 4
+    /// KiB.
+    pub inline_threshold: usize,
+    /// The max length of the blocks packed into containers, default is 64
+    /// KiB.
+    pub pack_threshold: usize,
+}
+
+impl Default for PackConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            inline_threshold: 0x1000,
+            pack_threshold: 0x1_0000,
+        }
+    }
+}
+
+impl PackConfig {
+    /// Convert from the command line config, returns `None` if packing is
+    /// disabled. The inline threshold must not exceed the pack threshold.
+    fn try_from_super(value: SuperPackConfig) -> Result<Option<Self>, DatenLordError> {
+        let SuperPackConfig {
+            enabled,
+            inline_threshold,
+            pack_threshold,
+        } = value;
+
+        if !enabled {
+            return Ok(None);
+        }
+
+        if inline_threshold > pack_threshold {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!(
+                    "The inline threshold {inline_threshold} exceeds the pack threshold \
+                     {pack_threshold}."
+                )],
+            });
+        }
+
+        Ok(Some(Self {
+            inline_threshold,
+            pack_threshold,
+        }))
+    }
+}
+
+/// Checksum config
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct ChecksumConfig {
+    /// The interval between scrub passes, `None` if the scrubber is disabled.
+    /// Default is 1 day.
+    pub scrub_interval: Option<Duration>,
+}
+
+impl Default for ChecksumConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            scrub_interval: Some(Duration::from_secs(86400)),
+        }
+    }
+}
+
+impl ChecksumConfig {
+    /// Convert from the command line config, returns `None` if checksums are
+    /// disabled.
+    fn from_super(value: SuperChecksumConfig) -> Option<Self> {
+        let SuperChecksumConfig {
+            enabled,
+            scrub_interval,
+        } = value;
+
+        enabled.then(|| Self {
+            scrub_interval: (scrub_interval != 0).then_some(Duration::from_secs(scrub_interval)),
+        })
+    }
+}
+
+/// GC config
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct GcConfig {
+    /// The interval between GC passes
+    pub interval: Duration,
+    /// The maximum number of objects deleted per second, deletes are not rate
+    /// limited if it's 0
+    pub rate: u32,
+    /// Whether the orphaned objects are only reported instead of deleted
+    pub dry_run: bool,
+}
+
+impl GcConfig {
+    /// Convert from the command line config, returns `None` if GC is
+    /// disabled.
+    fn from_super(value: SuperGcConfig) -> Option<Self> {
+        let SuperGcConfig {
+            interval,
+            rate,
+            dry_run,
+        } = value;
+
+        (interval != 0).then(|| Self {
+            interval: Duration::from_secs(interval),
+            rate,
+            dry_run,
+        })
+    }
+}
+
+/// Tiering config
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TieringConfig {
+    /// The directory of the hot tier
+    pub hot_dir: String,
+    /// How long a block is not accessed before it's migrated to the cold tier
+    pub cold_after: Duration,
+    /// The number of accesses in a migration interval, after which a block is
+    /// migrated to the hot tier
+    pub promote_accesses: u32,
+    /// The interval between migration passes
+    pub interval: Duration,
+    /// The rules pinning the files under paths to tiers
+    pub rules: Vec<TierRule>,
+}
+
+impl TieringConfig {
+    /// Convert from the command line config, returns `None` if tiering is
+    /// disabled.
+    fn try_from_super(value: SuperTieringConfig) -> Result<Option<Self>, DatenLordError> {
+        let SuperTieringConfig {
+            hot_dir,
+            cold_after,
+            promote_accesses,
+            interval,
+            rules,
+        } = value;
+
+        if hot_dir.is_empty() {
+            return Ok(None);
+        }
+        if interval == 0 {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["The interval of tier migration must not be 0.".to_owned()],
+            });
+        }
+
+        Ok(Some(Self {
+            hot_dir,
+            cold_after: Duration::from_secs(cold_after),
+            promote_accesses,
+            interval: Duration::from_secs(interval),
+            rules: rules
+                .iter()
+                .map(|rule| rule.parse())
+                .collect::<Result<_, _>>()?,
+        }))
+    }
+}
+
+/// Transfer config
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TransferConfig {
+    /// The address the transfer service listens on
+    pub listen: SocketAddr,
+    /// The directory of the blocks served by the transfer service
+    pub dir: String,
+    /// The timeout of a transfer not issued by a FUSE request
+    pub timeout: Duration,
+    /// The TLS config, `None` if the transfers are in plaintext
+    pub tls: Option<TlsConfig>,
+}
+
+/// The TLS config of the gRPC services among the nodes
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// The PEM certificate of the node
+    pub cert_file: String,
+    /// The PEM private key of the certificate
+    pub key_file: String,
+    /// The PEM certificate of the CA
+    pub ca_file: String,
+    /// Whether the clients are required to present their certificates
+    pub mtls: bool,
+}
+
+impl TlsConfig {
+    /// Convert from the files of the command line, returns `None` if none is
+    /// given, the services are in plaintext then.
+    fn try_from_files(
+        cert_file: String,
+        key_file: String,
+        ca_file: String,
+        mtls: bool,
+    ) -> Result<Option<Self>, DatenLordError> {
+        let files = [&cert_file, &key_file, &ca_file];
+        if files.iter().all(|file| file.is_empty()) {
+            if mtls {
+                return Err(DatenLordError::ArgumentInvalid {
+                    context: vec!["Mutual TLS requires the TLS files to be set.".to_owned()],
+                });
+            }
+            return Ok(None);
+        }
+        if files.iter().any(|file| file.is_empty()) {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec![
+                    "The certificate, the key and the CA certificate of TLS must be set together."
+                        .to_owned(),
+                ],
+            });
+        }
+        Ok(Some(Self {
+            cert_file,
+            key_file,
+            ca_file,
+            mtls,
+        }))
+    }
+}
+
+impl TransferConfig {
+    /// Convert from the command line config, returns `None` if the transfer
+    /// service is disabled.
+    fn try_from_super(value: SuperTransferConfig) -> Result<Option<Self>, DatenLordError> {
+        let SuperTransferConfig {
+            listen,
+            dir,
+            timeout,
+            tls_cert_file,
+            tls_key_file,
+            tls_ca_file,
+            mtls,
+        } = value;
+
+        if listen.is_empty() {
+            return Ok(None);
+        }
+        let listen = listen
+            .parse()
+            .map_err(|e| DatenLordError::ArgumentInvalid {
+                context: vec![format!("The transfer address {listen} is invalid: {e}")],
+            })?;
+        if dir.is_empty() {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["The directory of the transfer service is required.".to_owned()],
+            });
+        }
+        if timeout == 0 {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["The timeout of transfers must not be 0.".to_owned()],
+            });
+        }
+
+        let tls = TlsConfig::try_from_files(tls_cert_file, tls_key_file, tls_ca_file, mtls)?;
+
+        Ok(Some(Self {
+            listen,
+            dir,
+            timeout: Duration::from_secs(timeout),
+            tls,
+        }))
+    }
 }
 
-impl TryFrom<SuperConfig> for InnerConfig {
-    type Error = DatenLordError;
+/// Peer cache config
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PeerCacheConfig {
+    /// The endpoint of the transfer service of the node for the other nodes
+    pub endpoint: String,
+    /// The zone of the node, `None` if all the nodes are in one zone
+    pub zone: Option<String>,
+    /// The timeout of a fetch from the cache of another node
+    pub timeout: Duration,
+}
 
-    #[inline]
-    fn try_from(value: SuperConfig) -> Result<Self, Self::Error> {
-        let role = Role::from_str(value.role.as_str())?;
-        let node_name = value.node_name;
-        let server_port = value.server_port;
-        let scheduler_extender_port = value.scheduler_extender_port;
-        let node_ip = IpAddr::from_str(value.node_ip.as_str()).map_err(|e| {
-            DatenLordError::ArgumentInvalid {
-                context: vec![format!("node ip {} is invalid: {}", value.node_ip, e)],
-            }
-        })?;
-        let mount_path = value.mount_path;
-        let storage = value.storage.try_into()?;
-        let kv_addrs: Vec<String> = value.kv_server_list;
-        if kv_addrs.is_empty() {
+impl PeerCacheConfig {
+    /// Convert from the command line config, returns `None` if the caches are
+    /// not shared.
+    fn try_from_super(value: SuperPeerCacheConfig) -> Result<Option<Self>, DatenLordError> {
+        let SuperPeerCacheConfig {
+            endpoint,
+            zone,
+            timeout,
+        } = value;
+
+        if endpoint.is_empty() {
+            return Ok(None);
+        }
+        if timeout == 0 {
             return Err(DatenLordError::ArgumentInvalid {
-                context: vec!["kv server addresses is empty".to_owned()],
+                context: vec!["The timeout of the peer cache must not be 0.".to_owned()],
             });
         }
-        let csi_config = value.csi_config.try_into()?;
-        Ok(InnerConfig {
-            role,
-            node_name,
-            node_ip,
-            mount_path,
-            kv_addrs,
-            server_port,
-            scheduler_extender_port,
-            storage,
-            csi_config,
-        })
+
+        Ok(Some(Self {
+            endpoint,
+            zone: (!zone.is_empty()).then_some(zone),
+            timeout: Duration::from_millis(timeout),
+        }))
     }
 }
 
-/// Storage related config
+/// Replication config
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct StorageConfig {
-    /// The size of blocks, default is 512 KiB.
-    pub block_size: usize,
-    /// Cache capacity
-    pub memory_cache_config: MemoryCacheConfig,
-    /// Storage params
-    pub params: StorageParams,
+pub struct ReplicationConfig {
+    /// The number of replicas of every block
+    pub factor: usize,
+    /// The number of replicas persisting a block before a write is
+    /// acknowledged
+    pub quorum: WriteQuorum,
+    /// The replica nodes
+    pub nodes: Vec<ReplicaNode>,
+    /// How long a replica node is not seen registered before it's dead
+    pub failure_timeout: Duration,
+    /// The maximal number of blocks moved per second by rebalancing, 0 if
+    /// it's not rate limited
+    pub rebalance_rate: u32,
+    /// The percentile of the latencies of the recent reads of replicas, a read
+    /// slower than which is hedged, `None` if the reads are not hedged
+    pub hedge_percentile: Option<u32>,
+    /// The policy of the circuit breakers of the replica nodes, `None` if
+    /// they're not guarded by breakers
+    pub breaker: Option<BreakerPolicy>,
+    /// The layout of blocks on the replica nodes
+    pub policy: StoragePolicy,
+    /// The shards of an erasure coded block
+    pub shards: ErasureShards,
+    /// How long a block is not accessed before it's erasure coded, with the
+    /// tiered policy
+    pub ec_cold_after: Duration,
+    /// The number of accesses in a conversion interval, after which an
+    /// erasure coded block is replicated again, with the tiered policy
+    pub ec_promote_accesses: u32,
+    /// The interval between conversion passes, with the tiered policy
+    pub ec_interval: Duration,
 }
 
-impl TryFrom<SuperStorageConfig> for StorageConfig {
-    type Error = DatenLordError;
+impl ReplicationConfig {
+    /// Convert from the command line config, returns `None` if replication is
+    /// disabled. There must be enough replica nodes for the replicas and the
+    /// shards, and the quorum must not exceed the replicas.
+    fn try_from_super(value: SuperReplicationConfig) -> Result<Option<Self>, DatenLordError> {
+        let SuperReplicationConfig {
+            factor,
+            quorum,
+            nodes,
+            failure_timeout,
+            rebalance_rate,
+            hedge_percentile,
+            breaker_failures,
+            breaker_cooldown,
+            policy,
+            ec_data_shards,
+            ec_parity_shards,
+            ec_cold_after,
+            ec_promote_accesses,
+            ec_interval,
+        } = value;
 
-    #[inline]
-    fn try_from(value: SuperStorageConfig) -> Result<Self, Self::Error> {
-        let memory_cache_config = value.memory_cache_config.try_into()?;
-        let params = match value.storage_type.to_lowercase().as_str() {
-            "s3" => StorageParams::S3(value.s3_storage_config.try_into()?),
-            "fs" => StorageParams::Fs(value.fs_storage_root),
-            _ => {
+        if factor == 0 {
+            return Ok(None);
+        }
+        if failure_timeout == 0 {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["The failure timeout of replica nodes must not be 0.".to_owned()],
+            });
+        }
+        let factor: usize = factor.cast();
+        let nodes: Vec<ReplicaNode> = nodes
+            .iter()
+            .map(|node| node.parse())
+            .collect::<Result<_, _>>()?;
+        if nodes.len() < factor {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!(
+                    "{factor} replicas require at least {factor} replica nodes, but {} are given",
+                    nodes.len()
+                )],
+            });
+        }
+        if hedge_percentile >= 100 {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!(
+                    "The hedge percentile {hedge_percentile} must be less than 100."
+                )],
+            });
+        }
+        if breaker_failures != 0 && breaker_cooldown == 0 {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["The cooldown of circuit breakers must not be 0.".to_owned()],
+            });
+        }
+        let quorum: WriteQuorum = quorum.parse()?;
+        if let WriteQuorum::Count(count) = quorum {
+            if count > factor {
                 return Err(DatenLordError::ArgumentInvalid {
                     context: vec![format!(
-                        "storage type {} is not supported",
-                        value.storage_type
+                        "The write quorum {count} exceeds the {factor} replicas."
                     )],
-                })
+                });
             }
+        }
+        let policy: StoragePolicy = policy.parse()?;
+        let shards = ErasureShards {
+            data: ec_data_shards.cast(),
+            parity: ec_parity_shards.cast(),
         };
-        let block_size = value.block_size;
-        Ok(StorageConfig {
-            block_size,
-            memory_cache_config,
-            params,
-        })
+        if policy != StoragePolicy::Replicate {
+            if shards.data == 0 || shards.parity == 0 {
+                return Err(DatenLordError::ArgumentInvalid {
+                    context: vec!["The data and parity shards must not be 0.".to_owned()],
+                });
+            }
+            if shards.total() > nodes.len().min(ErasureShards::MAX_TOTAL) {
+                return Err(DatenLordError::ArgumentInvalid {
+                    context: vec![format!(
+                        "{} shards require as many replica nodes, and at most {} are supported",
+                        shards.total(),
+                        ErasureShards::MAX_TOTAL
+                    )],
+                });
+            }
+        }
+        if policy == StoragePolicy::Tiered && ec_interval == 0 {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["The interval of erasure coding must not be 0.".to_owned()],
+            });
+        }
+
+        Ok(Some(Self {
+            factor,
+            quorum,
+            nodes,
+            failure_timeout: Duration::from_secs(failure_timeout),
+            rebalance_rate,
+            hedge_percentile: (hedge_percentile != 0).then_some(hedge_percentile),
+            breaker: (breaker_failures != 0).then(|| BreakerPolicy {
+                failures: breaker_failures,
+                cooldown: Duration::from_secs(breaker_cooldown),
+            }),
+            policy,
+            shards,
+            ec_cold_after: Duration::from_secs(ec_cold_after),
+            ec_promote_accesses,
+            ec_interval: Duration::from_secs(ec_interval),
+        }))
+    }
+}
+
+/// Encryption config
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EncryptionConfig {
+    /// The file of the master key
+    pub master_key_file: String,
+    /// The file of the previous master key, `None` if the master key is not
+    /// being rotated
+    pub old_master_key_file: Option<String>,
+    /// Whether to generate a new data key on start
+    pub rotate_data_key: bool,
+}
+
+impl EncryptionConfig {
+    /// Convert from the command line config, returns `None` if no master key
+    /// is provided. Rotations require the master key.
+    fn try_from_super(value: SuperEncryptionConfig) -> Result<Option<Self>, DatenLordError> {
+        let SuperEncryptionConfig {
+            master_key_file,
+            old_master_key_file,
+            rotate_data_key,
+        } = value;
+
+        if master_key_file.is_empty() {
+            if !old_master_key_file.is_empty() || rotate_data_key {
+                return Err(DatenLordError::ArgumentInvalid {
+                    context: vec![
+                        "Rotating keys requires the master key file to be set.".to_owned()
+                    ],
+                });
+            }
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            master_key_file,
+            old_master_key_file: (!old_master_key_file.is_empty()).then_some(old_master_key_file),
+            rotate_data_key,
+        }))
+    }
+}
+
+/// Disk cache config
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DiskCacheConfig {
+    /// The directory of disk cache
+    pub dir: String,
+    /// The capacity of disk cache in bytes, default is 64 GiB.
+    pub capacity: usize,
+    /// The evict policy of disk cache, default is LRU.
+    pub policy: EvictPolicyType,
+    /// Whether the index is persisted on shutdown, and the blocks are kept
+    /// across restarts
+    pub persist: bool,
+}
+
+impl DiskCacheConfig {
+    /// Convert from the command line config, returns `None` if the disk cache
+    /// is disabled. The capacity is checked against the chunk size of the
+    /// volume once it's loaded, which may differ from the configured block
+    /// size.
+    fn try_from_super(value: SuperDiskCacheConfig) -> Result<Option<Self>, DatenLordError> {
+        let SuperDiskCacheConfig {
+            dir,
+            capacity,
+            policy,
+            persist,
+        } = value;
+
+        if dir.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            dir,
+            capacity,
+            policy: policy.parse()?,
+            persist,
+        }))
     }
 }
 
@@ -156,6 +2214,14 @@ pub struct MemoryCacheConfig {
     /// It's a fraction with the form of `a,b`, which means that
     /// the soft limit is `a/b` of the capacity.
     pub soft_limit: SoftLimit,
+    /// The high watermark of dirty bytes in write-back policy, default is 1
+    /// GiB.
+    pub dirty_high_watermark: usize,
+    /// The max number of blocks uploaded to the backend concurrently in
+    /// write-back policy, default is 8.
+    pub upload_concurrency: usize,
+    /// The evict policy of memory cache, default is LRU.
+    pub policy: EvictPolicyType,
 }
 
 /// A type to represent the soft limit of cache.
@@ -210,20 +2276,43 @@ impl TryFrom<SuperMemoryCacheConfig> for MemoryCacheConfig {
             command_queue_limit,
             write_back,
             soft_limit,
+            dirty_high_watermark,
+            upload_concurrency,
+            policy,
         } = value;
 
+        if dirty_high_watermark == 0 {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec![
+                    "Argument `--storage-mem-cache-dirty-high-watermark` cannot be 0.".to_owned(),
+                ],
+            });
+        }
+
+        if upload_concurrency == 0 {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec![
+                    "Argument `--storage-mem-cache-upload-concurrency` cannot be 0.".to_owned(),
+                ],
+            });
+        }
+
         Ok(Self {
             capacity,
             command_queue_limit,
             write_back,
             soft_limit: soft_limit.parse()?,
+            dirty_high_watermark,
+            upload_concurrency,
+            policy: policy.parse()?,
         })
     }
 }
 
 /// Storage backend related config
 ///
-/// - `S3` : `endpoint_url`, `access_key_id`, `secret_access_key`, `bucket_name`
+/// - `S3` : `endpoint_url`, `access_key_id`, `secret_access_key`,
+///   `bucket_name`, `region`, `prefix`, `part_size`, `read_streams`
 /// - `Fs` : A local filesystem based backend, with argument `backend_root`.
 /// TODO(xiaguan) add more storage types
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -245,6 +2334,14 @@ pub struct StorageS3Config {
     pub secret_access_key: String,
     /// Bucket name
     pub bucket_name: String,
+    /// Region
+    pub region: String,
+    /// The key prefix of objects in the bucket
+    pub prefix: String,
+    /// The part size of multipart uploads
+    pub part_size: usize,
+    /// The max number of concurrent streams fetching a block
+    pub read_streams: usize,
 }
 
 impl TryFrom<SuperS3StorageConfig> for StorageS3Config {
@@ -252,11 +2349,253 @@ impl TryFrom<SuperS3StorageConfig> for StorageS3Config {
 
     #[inline]
     fn try_from(value: SuperS3StorageConfig) -> Result<Self, Self::Error> {
+        // S3 rejects multipart uploads with parts smaller than 5 MiB.
+        if value.part_size < 0x50_0000 {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!(
+                    "`--storage-s3-part-size` {} is less than 5 MiB",
+                    value.part_size
+                )],
+            });
+        }
+        if value.read_streams == 0 {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["`--storage-s3-read-streams` cannot be 0".to_owned()],
+            });
+        }
         Ok(StorageS3Config {
             endpoint_url: value.endpoint_url,
             access_key_id: value.access_key_id,
             secret_access_key: value.secret_access_key,
             bucket_name: value.bucket_name,
+            region: value.region,
+            prefix: value.prefix,
+            part_size: value.part_size,
+            read_streams: value.read_streams,
+        })
+    }
+}
+
+/// A class of the operations on the backend, each with its own budget of
+/// retries
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BackendOpClass {
+    /// Reading the objects
+    Read,
+    /// Writing the objects
+    Write,
+    /// Deleting the objects
+    Delete,
+    /// Listing the objects
+    List,
+}
+
+impl FromStr for BackendOpClass {
+    type Err = DatenLordError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "read" => Ok(Self::Read),
+            "write" => Ok(Self::Write),
+            "delete" => Ok(Self::Delete),
+            "list" => Ok(Self::List),
+            _ => Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!(
+                    "backend operation class {s} is not one of read, write, delete and list"
+                )],
+            }),
+        }
+    }
+}
+
+impl fmt::Display for BackendOpClass {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Delete => "delete",
+            Self::List => "list",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// The retries of the transient failures of the backend by operation class
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RetryBudgets {
+    /// The retries of the reads
+    pub read: u32,
+    /// The retries of the writes
+    pub write: u32,
+    /// The retries of the deletes
+    pub delete: u32,
+    /// The retries of the lists
+    pub list: u32,
+}
+
+impl Default for RetryBudgets {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            read: 3,
+            write: 3,
+            delete: 3,
+            list: 3,
+        }
+    }
+}
+
+impl RetryBudgets {
+    /// The retries of the operations of `class`.
+    #[inline]
+    #[must_use]
+    pub fn of(self, class: BackendOpClass) -> u32 {
+        match class {
+            BackendOpClass::Read => self.read,
+            BackendOpClass::Write => self.write,
+            BackendOpClass::Delete => self.delete,
+            BackendOpClass::List => self.list,
+        }
+    }
+
+    /// The retries of the operations of `class` to set.
+    fn of_mut(&mut self, class: BackendOpClass) -> &mut u32 {
+        match class {
+            BackendOpClass::Read => &mut self.read,
+            BackendOpClass::Write => &mut self.write,
+            BackendOpClass::Delete => &mut self.delete,
+            BackendOpClass::List => &mut self.list,
+        }
+    }
+}
+
+/// The errnos the error codes of the backend may be mapped to, by their names.
+const BACKEND_ERRNOS: [(&str, i32); 9] = [
+    ("EACCES", libc::EACCES),
+    ("EAGAIN", libc::EAGAIN),
+    ("EDQUOT", libc::EDQUOT),
+    ("EFBIG", libc::EFBIG),
+    ("EIO", libc::EIO),
+    ("ENOSPC", libc::ENOSPC),
+    ("EPERM", libc::EPERM),
+    ("EROFS", libc::EROFS),
+    ("ETIMEDOUT", libc::ETIMEDOUT),
+];
+
+/// How the errors of an error code of the backend are handled
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BackendErrorAction {
+    /// Retried as the transient failures, then returned as `EIO`
+    Retry,
+    /// Returned as the raw errno at once
+    Errno(i32),
+}
+
+impl FromStr for BackendErrorAction {
+    type Err = DatenLordError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("retry") {
+            return Ok(Self::Retry);
+        }
+        BACKEND_ERRNOS
+            .iter()
+            .find(|&&(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|&(_, errno)| Self::Errno(errno))
+            .ok_or_else(|| {
+                let names: Vec<&str> = BACKEND_ERRNOS.iter().map(|&(name, _)| name).collect();
+                DatenLordError::ArgumentInvalid {
+                    context: vec![format!(
+                        "{s} is not retry or one of the errnos {}",
+                        names.join(", ")
+                    )],
+                }
+            })
+    }
+}
+
+/// An error code of the backend mapped to how its errors are handled
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BackendErrorMapping {
+    /// The error code, such as `QuotaExceeded` of S3
+    pub code: String,
+    /// How the errors of the code are handled
+    pub action: BackendErrorAction,
+}
+
+impl FromStr for BackendErrorMapping {
+    type Err = DatenLordError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DatenLordError::ArgumentInvalid {
+            context: vec![format!(
+                "backend error mapping {s} is not in the form of `CODE=ERRNO`"
+            )],
+        };
+        let (code, action) = s.split_once('=').ok_or_else(invalid)?;
+        if code.is_empty() || !code.chars().all(char::is_alphanumeric) {
+            return Err(invalid());
+        }
+        Ok(Self {
+            code: code.to_owned(),
+            action: action.parse()?,
+        })
+    }
+}
+
+/// Backend error config
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BackendErrorConfig {
+    /// The retries of the transient failures by operation class
+    pub retries: RetryBudgets,
+    /// The delay before the first retry, doubled on every retry after it
+    pub retry_backoff: Duration,
+    /// The error codes mapped by users, over the built-in mappings
+    pub error_map: Vec<BackendErrorMapping>,
+}
+
+impl Default for BackendErrorConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            retries: RetryBudgets::default(),
+            retry_backoff: Duration::from_millis(100),
+            error_map: vec![],
+        }
+    }
+}
+
+impl BackendErrorConfig {
+    /// Convert from the command line config.
+    fn try_from_super(value: SuperBackendErrorConfig) -> Result<Self, DatenLordError> {
+        let SuperBackendErrorConfig {
+            retries: budgets,
+            retry_backoff,
+            error_map,
+        } = value;
+
+        let mut retries = RetryBudgets::default();
+        for budget in &budgets {
+            let invalid = || DatenLordError::ArgumentInvalid {
+                context: vec![format!(
+                    "backend retries {budget} is not in the form of `CLASS=N`"
+                )],
+            };
+            let (class, count) = budget.split_once('=').ok_or_else(invalid)?;
+            *retries.of_mut(class.parse()?) = count.parse().map_err(|_| invalid())?;
+        }
+
+        Ok(Self {
+            retries,
+            retry_backoff: Duration::from_millis(retry_backoff),
+            error_map: error_map
+                .iter()
+                .map(|mapping| mapping.parse())
+                .collect::<Result<_, _>>()?,
         })
     }
 }
@@ -270,6 +2609,9 @@ pub struct CSIConfig {
     pub driver_name: String,
     /// CSI worker port
     pub worker_port: u16,
+    /// The mount namespace the volumes are published in, `None` for the one
+    /// of this process
+    pub mount_namespace: Option<PathBuf>,
 }
 
 impl TryFrom<SupperCSIConfig> for CSIConfig {
@@ -280,10 +2622,141 @@ impl TryFrom<SupperCSIConfig> for CSIConfig {
         let endpoint = value.endpoint;
         let driver_name = value.driver_name;
         let worker_port = value.worker_port;
+        let mount_namespace =
+            (!value.mount_namespace.is_empty()).then(|| PathBuf::from(value.mount_namespace));
         Ok(CSIConfig {
             endpoint,
             driver_name,
             worker_port,
+            mount_namespace,
+        })
+    }
+}
+
+/// Volume manager config struct
+#[derive(Clone, Debug)]
+pub struct VolumeConfig {
+    /// The address the volume manager serves on
+    pub listen: SocketAddr,
+    /// The endpoint of the volume manager, `None` if it's not set
+    pub endpoint: Option<String>,
+    /// The name of the volume, `None` to list the volumes
+    pub name: Option<String>,
+    /// The capacity of the volume in bytes, `0` for unlimited
+    pub capacity: u64,
+    /// The storage policy of the volume to create
+    pub policy: StoragePolicy,
+    /// Whether the volume role deletes the volume
+    pub delete: bool,
+    /// Whether the volume role resizes the volume
+    pub resize: bool,
+    /// The command of the admin role
+    pub command: AdminCommand,
+}
+
+impl VolumeConfig {
+    /// Convert from the command line config.
+    fn try_from_super(value: SuperVolumeConfig) -> Result<Self, DatenLordError> {
+        let SuperVolumeConfig {
+            listen,
+            endpoint,
+            name,
+            capacity,
+            policy,
+            delete,
+            resize,
+            command,
+        } = value;
+
+        let listen = listen
+            .parse()
+            .map_err(|e| DatenLordError::ArgumentInvalid {
+                context: vec![format!(
+                    "The volume manager address {listen} is invalid: {e}"
+                )],
+            })?;
+        let policy = policy.parse()?;
+        if delete && resize {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["A volume cannot be deleted and resized together.".to_owned()],
+            });
+        }
+        if (delete || resize) && name.is_empty() {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["The name of the volume to delete or resize is required.".to_owned()],
+            });
+        }
+        let command: AdminCommand = command.parse()?;
+        if matches!(
+            command,
+            AdminCommand::Create | AdminCommand::Delete | AdminCommand::Resize
+        ) && name.is_empty()
+        {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!(
+                    "The admin command {command} requires the volume name."
+                )],
+            });
+        }
+
+        Ok(Self {
+            listen,
+            endpoint: (!endpoint.is_empty()).then_some(endpoint),
+            name: (!name.is_empty()).then_some(name),
+            capacity,
+            policy,
+            delete,
+            resize,
+            command,
+        })
+    }
+}
+
+/// The benchmark config
+#[derive(Clone, Debug)]
+pub struct BenchConfig {
+    /// What the bench role drives
+    pub target: BenchTarget,
+    /// The block sizes to write and read by
+    pub block_sizes: Vec<u64>,
+    /// The bytes to write and read by every block size
+    pub file_size: u64,
+    /// The files to create, stat and remove
+    pub files: usize,
+}
+
+impl BenchConfig {
+    /// Convert from the command line config.
+    fn try_from_super(value: SuperBenchConfig) -> Result<Self, DatenLordError> {
+        let SuperBenchConfig {
+            target,
+            block_sizes,
+            file_size,
+            files,
+        } = value;
+
+        let target = target.parse()?;
+        if block_sizes.is_empty() {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["The block sizes of the benchmark are empty.".to_owned()],
+            });
+        }
+        if let Some(block_size) = block_sizes
+            .iter()
+            .find(|&&block_size| block_size == 0 || block_size > file_size)
+        {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec![format!(
+                    "The block size {block_size} of the benchmark is 0 or beyond {file_size}."
+                )],
+            });
+        }
+
+        Ok(Self {
+            target,
+            block_sizes,
+            file_size,
+            files,
         })
     }
 }
@@ -1,12 +1,21 @@
 /// Configuration module. This module is used to parse configuration from
 /// command line arguments
 mod config;
+/// Config file module. This module is used to merge the flags set by a TOML
+/// or YAML file into the command line arguments
+mod file;
 /// Inner configuration module. This module is used to store the parsed
 /// configuration and will be used to initialize the server
 mod inner;
 
 pub use config::Config;
 pub use inner::{
-    InnerConfig, MemoryCacheConfig, Role as NodeRole, SoftLimit, StorageConfig, StorageParams,
-    StorageS3Config,
+    AdminCommand, AuthConfig, BackendErrorAction, BackendErrorConfig, BackendErrorMapping,
+    BackendOpClass, BenchConfig, BenchTarget, BreakerPolicy, ChecksumConfig, CompressionType,
+    ConsistencyModel, DedupConfig, DiskCacheConfig, EncryptionConfig, ErasureShards,
+    EvictPolicyType, GcConfig, HealthProbe, IdMap, IdRange, InnerConfig, LogFormat,
+    MemoryCacheConfig, OpClass, OpMask, OutputFormat, PackConfig, PeerCacheConfig, ReplicaNode,
+    ReplicationConfig, RetryBudgets, Role as NodeRole, SoftLimit, Squash, SquashMode,
+    StorageConfig, StorageParams, StoragePolicy, StorageS3Config, Tier, TierRule, TieringConfig,
+    TlsConfig, TransferConfig, VolumeConfig, WriteQuorum,
 };
@@ -0,0 +1,1071 @@
+//! The control socket of a mount.
+//!
+//! The daemon mounting a volume serves a Unix socket given by
+//! `--control-socket`, which the operators query the daemon on. A client sends
+//! a command in a line, and the daemon replies lines of JSON and closes the
+//! connection. The command `top` is replied by the IO of the FUSE requests by
+//! the processes and the cgroups issuing them, see `IO_STATS`, and shown by
+//! the top role like `top(1)`. The command `drain` starts the drain of the
+//! node, see `DRAIN`, and is replied by a line of each progress until the node
+//! is drained, which the drain role waits for. The command `scrub` is replied
+//! by the progress of the scrubber and the corrupted blocks it found, see
+//! `SCRUB`. The command `stats` is replied by the occupancy of the caches,
+//! the handles and the leases held, and the latency of the operations, and
+//! the command `inspect` by the state of every file holding any, see
+//! `INSPECTOR`, which the stats role and the inspect role show as tables or
+//! as JSON for the scripts. The command `reload` reloads the config file, see
+//! `RELOAD`, and is replied by the flags applied. The command `slowlog` is
+//! replied by the slow requests kept, see `SLOW_LOG`, which the slowlog role
+//! shows. The command `warmup` queues the warm-up of the caches with the
+//! files of the request sent in the next line, see `WARMUP`, and is replied
+//! by a line of each progress until the files are loaded, which the warmup
+//! role shows, and the pin role pins the files with. The command `unpin`
+//! unpins the paths sent in the next line, see `PINS`, and is replied by the
+//! pins removed, and the command `pins` by the paths pinned, which the pin
+//! role shows.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use clippy_utilities::{Cast, OverflowArithmetic};
+use datenlord::config::OutputFormat;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::select;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::async_fuse::fuse::io_stats::{IoCounters, IoSnapshot, OpLatency, IO_STATS};
+use crate::async_fuse::fuse::slow_log::{SlowRequest, SLOW_LOG};
+use crate::async_fuse::memfs::coherence::LeaseMode;
+use crate::async_fuse::memfs::drain::{DrainProgress, DrainStage, DRAIN};
+use crate::async_fuse::memfs::inspect::{CacheOccupancy, InodeState, Inspection, INSPECTOR};
+use crate::async_fuse::memfs::pin::{Pin, PinList, PINS};
+use crate::async_fuse::memfs::warmup::{WarmUpProgress, WarmUpRequest, WarmUpStage, WARMUP};
+use crate::bench::format_latency;
+use crate::common::error::{Context, DatenLordError, DatenLordResult};
+use crate::reload::RELOAD;
+use crate::storage::checksum::{ScrubStatus, SCRUB};
+
+/// The command replied by the IO of the processes and the cgroups.
+const TOP_COMMAND: &str = "top";
+
+/// The command draining the node, replied by its progress.
+const DRAIN_COMMAND: &str = "drain";
+
+/// The command replied by the status of the scrubber.
+const SCRUB_COMMAND: &str = "scrub";
+
+/// The command replied by the summary of the state of the mount.
+const STATS_COMMAND: &str = "stats";
+
+/// The command replied by the state of the files held by the mount.
+const INSPECT_COMMAND: &str = "inspect";
+
+/// The command reloading the config file, replied by the flags applied.
+const RELOAD_COMMAND: &str = "reload";
+
+/// The command replied by the slow requests kept.
+const SLOW_LOG_COMMAND: &str = "slowlog";
+
+/// The command warming up the caches with the files of the request sent in
+/// the next line, replied by its progress.
+const WARMUP_COMMAND: &str = "warmup";
+
+/// The command unpinning the paths sent in the next line, replied by the pins
+/// removed.
+const UNPIN_COMMAND: &str = "unpin";
+
+/// The command replied by the paths pinned.
+const PINS_COMMAND: &str = "pins";
+
+/// The error replied to `stats`, `inspect`, `warmup` and `pins` if no file
+/// system is mounted.
+const NOT_MOUNTED: &str = "no file system is mounted";
+
+/// The longest command read from a client.
+const MAX_COMMAND_LEN: u64 = 256;
+
+/// The longest request of a warm-up or an unpin read from a client, which
+/// lists the paths.
+const MAX_WARMUP_REQUEST_LEN: u64 = 1 << 20;
+
+/// The least interval between the progresses of a warm-up shown in the same
+/// stage.
+const WARMUP_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The reply to a command.
+#[derive(Debug, Serialize, Deserialize)]
+enum Reply {
+    /// The IO of the processes and the cgroups
+    Top(IoSnapshot),
+    /// The progress of the drain of the node
+    Drain(DrainProgress),
+    /// The status of the scrubber
+    Scrub(ScrubStatus),
+    /// The summary of the state of the mount
+    Stats(MountStats),
+    /// The files holding dirty bytes, handles or leases
+    Inspect(Vec<InodeState>),
+    /// The flags applied by the config file reloaded
+    Reload(Vec<String>),
+    /// The slow requests kept, the earliest first
+    SlowLog(Vec<SlowRequest>),
+    /// The progress of a warm-up of the caches
+    WarmUp(WarmUpProgress),
+    /// The pins removed
+    Unpinned(Vec<Pin>),
+    /// The paths pinned
+    Pins(PinList),
+    /// The command is not known, or no file system is mounted
+    Error(String),
+}
+
+/// The summary of the state of the mount.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MountStats {
+    /// The occupancy of the data cache
+    pub cache: CacheOccupancy,
+    /// The files with dirty bytes
+    pub dirty_files: usize,
+    /// The handles opened by the kernel
+    pub handles: usize,
+    /// The handles opened for writing
+    pub writable_handles: usize,
+    /// The read leases held
+    pub read_leases: usize,
+    /// The write leases held
+    pub write_leases: usize,
+    /// The name of an operation -> its latency since the control socket is
+    /// served
+    pub ops: BTreeMap<String, OpLatency>,
+}
+
+impl MountStats {
+    /// Summarize `inspection`, with the latency of the operations `ops`.
+    fn new(inspection: &Inspection, ops: BTreeMap<String, OpLatency>) -> Self {
+        let count = |matches: fn(&InodeState) -> bool| {
+            inspection
+                .inodes
+                .iter()
+                .filter(|&state| matches(state))
+                .count()
+        };
+        Self {
+            cache: inspection.cache,
+            dirty_files: count(|state| state.dirty_bytes > 0),
+            handles: inspection.inodes.iter().map(|state| state.handles).sum(),
+            writable_handles: inspection
+                .inodes
+                .iter()
+                .map(|state| state.writable_handles)
+                .sum(),
+            read_leases: count(|state| state.lease == Some(LeaseMode::Read)),
+            write_leases: count(|state| state.lease == Some(LeaseMode::Write)),
+            ops,
+        }
+    }
+}
+
+/// Serve the control socket at `path` until `token` is cancelled, a stale
+/// socket left at `path` is replaced.
+#[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+pub async fn serve_control_socket(path: PathBuf, token: CancellationToken) {
+    if let Err(e) = tokio::fs::remove_file(&path).await {
+        if e.kind() != ErrorKind::NotFound {
+            warn!("Failed to remove the stale control socket {path:?}: {e}");
+        }
+    }
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind the control socket {path:?}: {e}");
+            return;
+        }
+    };
+    IO_STATS.enable();
+    info!("Control socket is listening on {path:?}");
+
+    loop {
+        select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_command(stream).await {
+                            warn!("Failed to handle the command of the control socket: {e}");
+                        }
+                    });
+                }
+                Err(e) => warn!("Failed to accept a connection of the control socket: {e}"),
+            },
+            () = token.cancelled() => break,
+        }
+    }
+    if let Err(e) = tokio::fs::remove_file(&path).await {
+        warn!("Failed to remove the control socket {path:?}: {e}");
+    }
+}
+
+/// Reply the command of the client connected by `stream`.
+async fn handle_command(stream: UnixStream) -> DatenLordResult<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader.take(MAX_COMMAND_LEN));
+    let mut command = String::new();
+    reader
+        .read_line(&mut command)
+        .await
+        .add_context("failed to read the command")?;
+    match command.trim() {
+        TOP_COMMAND => write_reply(&mut writer, &Reply::Top(IO_STATS.snapshot())).await,
+        SCRUB_COMMAND => write_reply(&mut writer, &Reply::Scrub(SCRUB.status())).await,
+        SLOW_LOG_COMMAND => write_reply(&mut writer, &Reply::SlowLog(SLOW_LOG.dump())).await,
+        STATS_COMMAND => {
+            let reply = match INSPECTOR.inspect().await {
+                Some(inspection) => Reply::Stats(MountStats::new(&inspection, IO_STATS.latency())),
+                None => Reply::Error(NOT_MOUNTED.to_owned()),
+            };
+            write_reply(&mut writer, &reply).await
+        }
+        INSPECT_COMMAND => {
+            let reply = match INSPECTOR.inspect().await {
+                Some(inspection) => Reply::Inspect(inspection.inodes),
+                None => Reply::Error(NOT_MOUNTED.to_owned()),
+            };
+            write_reply(&mut writer, &reply).await
+        }
+        RELOAD_COMMAND => {
+            let reply = match RELOAD.reload() {
+                Ok(applied) => Reply::Reload(applied),
+                Err(e) => Reply::Error(e.to_string()),
+            };
+            write_reply(&mut writer, &reply).await
+        }
+        DRAIN_COMMAND => {
+            let mut progress = DRAIN.start();
+            loop {
+                let current = progress.borrow_and_update().clone();
+                let finished = current.is_finished();
+                write_reply(&mut writer, &Reply::Drain(current)).await?;
+                if finished || progress.changed().await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        WARMUP_COMMAND => {
+            reader.get_mut().set_limit(MAX_WARMUP_REQUEST_LEN);
+            let mut request = String::new();
+            reader
+                .read_line(&mut request)
+                .await
+                .add_context("failed to read the request of the warm-up")?;
+            let request: WarmUpRequest = match serde_json::from_str(&request) {
+                Ok(request) => request,
+                Err(e) => {
+                    let reply = Reply::Error(format!("invalid request of the warm-up: {e}"));
+                    return write_reply(&mut writer, &reply).await;
+                }
+            };
+            let Some(mut progress) = WARMUP.start(request) else {
+                return write_reply(&mut writer, &Reply::Error(NOT_MOUNTED.to_owned())).await;
+            };
+            loop {
+                let current = progress.borrow_and_update().clone();
+                let finished = current.is_finished();
+                write_reply(&mut writer, &Reply::WarmUp(current)).await?;
+                if finished || progress.changed().await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        UNPIN_COMMAND => {
+            reader.get_mut().set_limit(MAX_WARMUP_REQUEST_LEN);
+            let mut paths = String::new();
+            reader
+                .read_line(&mut paths)
+                .await
+                .add_context("failed to read the paths to unpin")?;
+            let reply = match serde_json::from_str::<Vec<String>>(&paths) {
+                Ok(paths) => match PINS.unpin(&paths) {
+                    Ok(pins) => Reply::Unpinned(pins),
+                    Err(e) => Reply::Error(e.to_string()),
+                },
+                Err(e) => Reply::Error(format!("invalid paths to unpin: {e}")),
+            };
+            write_reply(&mut writer, &reply).await
+        }
+        PINS_COMMAND => {
+            let reply = match PINS.list() {
+                Some(pins) => Reply::Pins(pins),
+                None => Reply::Error(NOT_MOUNTED.to_owned()),
+            };
+            write_reply(&mut writer, &reply).await
+        }
+        unknown => {
+            let reply = Reply::Error(format!("unknown command {unknown:?}"));
+            write_reply(&mut writer, &reply).await
+        }
+    }
+}
+
+/// Write `reply` in a line to `writer`.
+async fn write_reply(writer: &mut OwnedWriteHalf, reply: &Reply) -> DatenLordResult<()> {
+    let mut reply = serde_json::to_vec(reply)?;
+    reply.push(b'\n');
+    writer
+        .write_all(&reply)
+        .await
+        .add_context("failed to write the reply")
+}
+
+/// Send `command` to the control socket at `path`, and read the reply.
+async fn send_command(path: &Path, command: &str) -> DatenLordResult<Reply> {
+    let mut stream = UnixStream::connect(path)
+        .await
+        .with_context(|| format!("failed to connect the control socket {path:?}"))?;
+    stream
+        .write_all(format!("{command}\n").as_bytes())
+        .await
+        .add_context("failed to send the command")?;
+    let mut reply = String::new();
+    stream
+        .read_to_string(&mut reply)
+        .await
+        .add_context("failed to read the reply")?;
+    Ok(serde_json::from_str(&reply)?)
+}
+
+/// The error of `reply`, which is not the one of `command`.
+fn unexpected_reply(reply: Reply, command: &str) -> DatenLordError {
+    match reply {
+        Reply::Error(e) => DatenLordError::ArgumentInvalid { context: vec![e] },
+        Reply::Top(_)
+        | Reply::Drain(_)
+        | Reply::Scrub(_)
+        | Reply::Stats(_)
+        | Reply::Inspect(_)
+        | Reply::Reload(_)
+        | Reply::SlowLog(_)
+        | Reply::WarmUp(_)
+        | Reply::Unpinned(_)
+        | Reply::Pins(_) => DatenLordError::ArgumentInvalid {
+            context: vec![format!("unexpected reply to the {command}")],
+        },
+    }
+}
+
+/// Get the IO of the processes and the cgroups from the control socket at
+/// `path`.
+#[allow(clippy::wildcard_enum_match_arm)]
+async fn query_top(path: &Path) -> DatenLordResult<IoSnapshot> {
+    match send_command(path, TOP_COMMAND).await? {
+        Reply::Top(snapshot) => Ok(snapshot),
+        reply => Err(unexpected_reply(reply, TOP_COMMAND)),
+    }
+}
+
+/// Drain the node serving the control socket at `path`, its progress is shown
+/// until it's drained.
+#[allow(clippy::wildcard_enum_match_arm)]
+pub async fn drain(path: &Path) -> DatenLordResult<()> {
+    let mut stream = UnixStream::connect(path)
+        .await
+        .with_context(|| format!("failed to connect the control socket {path:?}"))?;
+    stream
+        .write_all(format!("{DRAIN_COMMAND}\n").as_bytes())
+        .await
+        .add_context("failed to send the command")?;
+    let mut lines = BufReader::new(stream).lines();
+    while let Some(line) = lines
+        .next_line()
+        .await
+        .add_context("failed to read the progress of the drain")?
+    {
+        let progress = match serde_json::from_str(&line)? {
+            Reply::Drain(progress) => progress,
+            reply => return Err(unexpected_reply(reply, DRAIN_COMMAND)),
+        };
+        println!(
+            "{:?}: {} files of locks written back, {} leases released",
+            progress.stage, progress.locks_written_back, progress.leases_released
+        );
+        if let DrainStage::Failed(e) = progress.stage {
+            return Err(DatenLordError::InternalErr {
+                source: anyhow::anyhow!(e),
+                context: vec!["failed to drain the node".to_owned()],
+            });
+        }
+        if progress.stage == DrainStage::Drained {
+            return Ok(());
+        }
+    }
+    Err(DatenLordError::InternalErr {
+        source: anyhow::anyhow!("the connection is closed"),
+        context: vec!["the node is not drained".to_owned()],
+    })
+}
+
+/// Warm up the caches of the mount serving the control socket at `path` by
+/// `request`, pinning the files if it's to pin them. Its progress is shown
+/// until the files are loaded.
+#[allow(clippy::wildcard_enum_match_arm)]
+pub async fn warm_up(path: &Path, request: &WarmUpRequest) -> DatenLordResult<()> {
+    let request = serde_json::to_string(request)?;
+    let mut stream = UnixStream::connect(path)
+        .await
+        .with_context(|| format!("failed to connect the control socket {path:?}"))?;
+    stream
+        .write_all(format!("{WARMUP_COMMAND}\n{request}\n").as_bytes())
+        .await
+        .add_context("failed to send the command")?;
+    let mut lines = BufReader::new(stream).lines();
+    // The stage of the progress shown last, and when it's shown.
+    let mut shown: Option<(WarmUpStage, Instant)> = None;
+    while let Some(line) = lines
+        .next_line()
+        .await
+        .add_context("failed to read the progress of the warm-up")?
+    {
+        let progress = match serde_json::from_str(&line)? {
+            Reply::WarmUp(progress) => progress,
+            reply => return Err(unexpected_reply(reply, WARMUP_COMMAND)),
+        };
+        let due = !shown.as_ref().is_some_and(|last| {
+            last.0 == progress.stage && last.1.elapsed() < WARMUP_PROGRESS_INTERVAL
+        });
+        if due || progress.is_finished() {
+            println!(
+                "{:?}: {}/{} files, {}/{} loaded, {} files skipped",
+                progress.stage,
+                progress.files_loaded,
+                progress.files,
+                format_bytes(progress.bytes_loaded),
+                format_bytes(progress.bytes),
+                progress.files_failed
+            );
+            shown = Some((progress.stage.clone(), Instant::now()));
+        }
+        if let WarmUpStage::Failed(e) = progress.stage {
+            return Err(DatenLordError::InternalErr {
+                source: anyhow::anyhow!(e),
+                context: vec!["failed to warm up the caches".to_owned()],
+            });
+        }
+        if progress.stage == WarmUpStage::Done {
+            return Ok(());
+        }
+    }
+    Err(DatenLordError::InternalErr {
+        source: anyhow::anyhow!("the connection is closed"),
+        context: vec!["the caches are not warmed up".to_owned()],
+    })
+}
+
+/// Show the IO of the processes and the cgroups of the mount serving the
+/// control socket at `path`. It's shown once since the mount if `interval` is
+/// `None`, or refreshed every `interval` by the IO per second in it.
+pub async fn top(path: &Path, interval: Option<Duration>) -> DatenLordResult<()> {
+    let mut snapshot = query_top(path).await?;
+    let Some(interval) = interval else {
+        print!("{}", render_top(&snapshot, None));
+        return Ok(());
+    };
+    loop {
+        tokio::time::sleep(interval).await;
+        let previous = snapshot;
+        snapshot = query_top(path).await?;
+        // Clear the screen first.
+        print!(
+            "\x1b[2J\x1b[H{}",
+            render_top(&snapshot, Some((&previous, interval)))
+        );
+    }
+}
+
+/// Render the processes and the cgroups of `snapshot`, by the IO per second
+/// since the previous snapshot taken the duration before if `previous` is
+/// given, or by the IO since the mount.
+fn render_top(snapshot: &IoSnapshot, previous: Option<(&IoSnapshot, Duration)>) -> String {
+    // The processes and the cgroups not in the previous snapshot are new.
+    let rate = |io: IoCounters, before: Option<&IoCounters>| -> IoCounters {
+        let Some((_, duration)) = previous else {
+            return io;
+        };
+        let before = before.copied().unwrap_or_default();
+        let secs = duration.as_secs().max(1);
+        IoCounters {
+            ops: io.ops.saturating_sub(before.ops).overflow_div(secs),
+            read_bytes: io
+                .read_bytes
+                .saturating_sub(before.read_bytes)
+                .overflow_div(secs),
+            write_bytes: io
+                .write_bytes
+                .saturating_sub(before.write_bytes)
+                .overflow_div(secs),
+        }
+    };
+    let (processes_before, cgroups_before) = previous.map_or_else(
+        || (HashMap::new(), HashMap::new()),
+        |(before, _)| {
+            (
+                before
+                    .processes
+                    .iter()
+                    .map(|process| (process.pid, &process.io))
+                    .collect(),
+                before
+                    .cgroups
+                    .iter()
+                    .map(|cgroup| (cgroup.cgroup.as_str(), &cgroup.io))
+                    .collect(),
+            )
+        },
+    );
+
+    let mut processes: Vec<_> = snapshot
+        .processes
+        .iter()
+        .map(|process| {
+            let io = rate(process.io, processes_before.get(&process.pid).copied());
+            (process, io)
+        })
+        .collect();
+    processes.sort_by_key(|&(_, io)| std::cmp::Reverse((io.bytes(), io.ops)));
+    let mut cgroups: Vec<_> = snapshot
+        .cgroups
+        .iter()
+        .map(|cgroup| {
+            let io = rate(
+                cgroup.io,
+                cgroups_before.get(cgroup.cgroup.as_str()).copied(),
+            );
+            (cgroup, io)
+        })
+        .collect();
+    cgroups.sort_by_key(|&(_, io)| std::cmp::Reverse((io.bytes(), io.ops)));
+
+    let unit = if previous.is_some() { "/s" } else { "" };
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{:>8} {:<16} {:>10} {:>10} {:>10} CGROUP",
+        "PID",
+        "COMMAND",
+        format!("OPS{unit}"),
+        format!("READ{unit}"),
+        format!("WRITE{unit}"),
+    );
+    for &(process, io) in &processes {
+        let _ = writeln!(
+            out,
+            "{:>8} {:<16} {:>10} {:>10} {:>10} {}",
+            process.pid,
+            process.comm,
+            io.ops,
+            format_bytes(io.read_bytes),
+            format_bytes(io.write_bytes),
+            display_cgroup(&process.cgroup),
+        );
+    }
+    let _ = writeln!(
+        out,
+        "\n{:>10} {:>10} {:>10} CGROUP",
+        format!("OPS{unit}"),
+        format!("READ{unit}"),
+        format!("WRITE{unit}"),
+    );
+    for &(cgroup, io) in &cgroups {
+        let _ = writeln!(
+            out,
+            "{:>10} {:>10} {:>10} {}",
+            io.ops,
+            format_bytes(io.read_bytes),
+            format_bytes(io.write_bytes),
+            display_cgroup(&cgroup.cgroup),
+        );
+    }
+    out
+}
+
+/// Show the summary of the state of the mount serving the control socket at
+/// `path`, in `format`.
+#[allow(clippy::wildcard_enum_match_arm)]
+pub async fn stats(path: &Path, format: OutputFormat) -> DatenLordResult<()> {
+    let stats = match send_command(path, STATS_COMMAND).await? {
+        Reply::Stats(stats) => stats,
+        reply => return Err(unexpected_reply(reply, STATS_COMMAND)),
+    };
+    match format {
+        OutputFormat::Table => print!("{}", render_stats(&stats)),
+        OutputFormat::Json => println!("{}", serde_json::to_string(&stats)?),
+    }
+    Ok(())
+}
+
+/// Show the state of the files held by the mount serving the control socket
+/// at `path`, in `format`.
+#[allow(clippy::wildcard_enum_match_arm)]
+pub async fn inspect(path: &Path, format: OutputFormat) -> DatenLordResult<()> {
+    let inodes = match send_command(path, INSPECT_COMMAND).await? {
+        Reply::Inspect(inodes) => inodes,
+        reply => return Err(unexpected_reply(reply, INSPECT_COMMAND)),
+    };
+    match format {
+        OutputFormat::Table => print!("{}", render_inspect(&inodes)),
+        OutputFormat::Json => println!("{}", serde_json::to_string(&inodes)?),
+    }
+    Ok(())
+}
+
+/// Show the slow requests kept by the mount serving the control socket at
+/// `path`, in `format`.
+#[allow(clippy::wildcard_enum_match_arm)]
+pub async fn slow_log(path: &Path, format: OutputFormat) -> DatenLordResult<()> {
+    let requests = match send_command(path, SLOW_LOG_COMMAND).await? {
+        Reply::SlowLog(requests) => requests,
+        reply => return Err(unexpected_reply(reply, SLOW_LOG_COMMAND)),
+    };
+    match format {
+        OutputFormat::Table => print!("{}", render_slow_log(&requests)),
+        OutputFormat::Json => println!("{}", serde_json::to_string(&requests)?),
+    }
+    Ok(())
+}
+
+/// Unpin `paths` of the mount serving the control socket at `path`, the pins
+/// removed are shown in `format`.
+#[allow(clippy::wildcard_enum_match_arm)]
+pub async fn unpin(path: &Path, paths: &[String], format: OutputFormat) -> DatenLordResult<()> {
+    let command = format!("{UNPIN_COMMAND}\n{}", serde_json::to_string(paths)?);
+    let pins = match send_command(path, &command).await? {
+        Reply::Unpinned(pins) => pins,
+        reply => return Err(unexpected_reply(reply, UNPIN_COMMAND)),
+    };
+    match format {
+        OutputFormat::Table => print!("{}", render_pins(&pins)),
+        OutputFormat::Json => println!("{}", serde_json::to_string(&pins)?),
+    }
+    Ok(())
+}
+
+/// Show the paths pinned in the mount serving the control socket at `path`,
+/// in `format`.
+#[allow(clippy::wildcard_enum_match_arm)]
+pub async fn list_pins(path: &Path, format: OutputFormat) -> DatenLordResult<()> {
+    let pins = match send_command(path, PINS_COMMAND).await? {
+        Reply::Pins(pins) => pins,
+        reply => return Err(unexpected_reply(reply, PINS_COMMAND)),
+    };
+    match format {
+        OutputFormat::Table => print!("{}", render_pin_list(&pins)),
+        OutputFormat::Json => println!("{}", serde_json::to_string(&pins)?),
+    }
+    Ok(())
+}
+
+/// Render the summary `stats`, with the operations in their names.
+fn render_stats(stats: &MountStats) -> String {
+    let cache = &stats.cache;
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{:<8} {}/{} blocks, {}/{}, {} dirty in {} files",
+        "CACHE",
+        cache.blocks,
+        cache.capacity,
+        format_bytes(cache.used_bytes()),
+        format_bytes(cache.capacity_bytes()),
+        format_bytes(cache.dirty_bytes),
+        stats.dirty_files,
+    );
+    let _ = writeln!(
+        out,
+        "{:<8} {} open, {} for writing",
+        "HANDLES", stats.handles, stats.writable_handles,
+    );
+    let _ = writeln!(
+        out,
+        "{:<8} {} read, {} write",
+        "LEASES", stats.read_leases, stats.write_leases,
+    );
+    let _ = writeln!(
+        out,
+        "\n{:<16} {:>10} {:>8} {:>8}",
+        "OP", "COUNT", "MEAN", "MAX"
+    );
+    for (op, latency) in &stats.ops {
+        let _ = writeln!(
+            out,
+            "{:<16} {:>10} {:>8} {:>8}",
+            op,
+            latency.count,
+            format_latency(latency.mean()),
+            format_latency(latency.max),
+        );
+    }
+    out
+}
+
+/// Render the state of the files `inodes`, in their order.
+fn render_inspect(inodes: &[InodeState]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{:>12} {:>10} {:>8} {:>8} LEASE",
+        "INODE", "DIRTY", "HANDLES", "WRITING"
+    );
+    for state in inodes {
+        let lease = match state.lease {
+            Some(LeaseMode::Read) => "read",
+            Some(LeaseMode::Write) => "write",
+            None => "-",
+        };
+        let _ = writeln!(
+            out,
+            "{:>12} {:>10} {:>8} {:>8} {lease}",
+            state.ino,
+            format_bytes(state.dirty_bytes),
+            state.handles,
+            state.writable_handles,
+        );
+    }
+    out
+}
+
+/// Render the slow requests `requests`, each followed by the decisions taken
+/// to serve it.
+fn render_slow_log(requests: &[SlowRequest]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{:<24} {:>10} {:>10} {:>10} {:>8} OPERATION",
+        "RECEIVED", "UNIQUE", "QUEUED", "SERVED", "PID"
+    );
+    for request in requests {
+        let received = DateTime::<Utc>::from(request.received);
+        let _ = writeln!(
+            out,
+            "{:<24} {:>10} {:>10} {:>10} {:>8} {}",
+            received.format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+            request.unique,
+            format_latency(request.queue_wait),
+            format_latency(request.service),
+            request.pid,
+            request.operation,
+        );
+        for decision in &request.decisions {
+            let _ = writeln!(out, "    {decision}");
+        }
+        if request.decisions_dropped > 0 {
+            let _ = writeln!(out, "    ... {} more", request.decisions_dropped);
+        }
+    }
+    out
+}
+
+/// Render the pins `pins`, in their order.
+fn render_pins(pins: &[Pin]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "{:>8} {:>8} {:>10} PATH", "FILES", "BLOCKS", "SIZE");
+    for pin in pins {
+        let _ = writeln!(
+            out,
+            "{:>8} {:>8} {:>10} {}",
+            pin.files,
+            pin.blocks,
+            format_bytes(pin.bytes),
+            pin.path,
+        );
+    }
+    out
+}
+
+/// Render the paths pinned `pins`, followed by the blocks pinned of the
+/// limit.
+fn render_pin_list(pins: &PinList) -> String {
+    let block_size: u64 = pins.block_size.cast();
+    let bytes = |blocks: usize| format_bytes(block_size.overflow_mul(blocks.cast()));
+    let mut out = render_pins(&pins.pins);
+    let _ = writeln!(
+        out,
+        "\n{:<8} {}/{} blocks, {}/{}",
+        "PINNED",
+        pins.blocks,
+        pins.limit,
+        bytes(pins.blocks),
+        bytes(pins.limit),
+    );
+    out
+}
+
+/// Show the unknown cgroup as `-`.
+fn display_cgroup(cgroup: &str) -> &str {
+    if cgroup.is_empty() {
+        "-"
+    } else {
+        cgroup
+    }
+}
+
+/// Format `bytes` in the binary units, such as `12M`.
+fn format_bytes(mut bytes: u64) -> String {
+    for unit in ["", "K", "M", "G", "T"] {
+        if bytes < 10240 {
+            return format!("{bytes}{unit}");
+        }
+        bytes >>= 10_i32;
+    }
+    format!("{bytes}P")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::time::{Duration, SystemTime};
+
+    use super::{
+        format_bytes, render_inspect, render_pin_list, render_slow_log, render_stats, render_top,
+        MountStats,
+    };
+    use crate::async_fuse::fuse::io_stats::{
+        CgroupIo, IoCounters, IoSnapshot, OpLatency, ProcessIo,
+    };
+    use crate::async_fuse::fuse::slow_log::{Decision, SlowRequest};
+    use crate::async_fuse::memfs::coherence::LeaseMode;
+    use crate::async_fuse::memfs::inspect::{CacheOccupancy, InodeState, Inspection};
+    use crate::async_fuse::memfs::pin::{Pin, PinList};
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0");
+        assert_eq!(format_bytes(10239), "10239");
+        assert_eq!(format_bytes(10240), "10K");
+        assert_eq!(format_bytes(5 << 30_i32), "5120M");
+        assert_eq!(format_bytes(50 << 30_i32), "50G");
+    }
+
+    #[test]
+    fn test_render_top() {
+        let snapshot = |ops: u64, read_bytes: u64| IoSnapshot {
+            processes: vec![ProcessIo {
+                pid: 42,
+                comm: "mysqld".to_owned(),
+                cgroup: "/kubepods/pod1234".to_owned(),
+                io: IoCounters {
+                    ops,
+                    read_bytes,
+                    write_bytes: 0,
+                },
+            }],
+            cgroups: vec![CgroupIo {
+                cgroup: "/kubepods/pod1234".to_owned(),
+                io: IoCounters {
+                    ops,
+                    read_bytes,
+                    write_bytes: 0,
+                },
+            }],
+        };
+        let before = snapshot(10, 4096);
+        let after = snapshot(30, 24576);
+
+        let total = render_top(&after, None);
+        let mut lines = total.lines();
+        assert!(lines.next().unwrap_or_default().contains("READ "));
+        let process: Vec<&str> = lines
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect();
+        assert_eq!(
+            process,
+            ["42", "mysqld", "30", "24K", "0", "/kubepods/pod1234"]
+        );
+
+        let rate = render_top(&after, Some((&before, Duration::from_secs(2))));
+        let mut lines = rate.lines();
+        assert!(lines.next().unwrap_or_default().contains("READ/s"));
+        let process: Vec<&str> = lines
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect();
+        assert_eq!(
+            process,
+            ["42", "mysqld", "10", "10K", "0", "/kubepods/pod1234"]
+        );
+        let cgroup: Vec<&str> = lines
+            .nth(2)
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect();
+        assert_eq!(cgroup, ["10", "10K", "0", "/kubepods/pod1234"]);
+    }
+
+    #[test]
+    fn test_render_stats() {
+        let inspection = Inspection {
+            cache: CacheOccupancy {
+                blocks: 48,
+                capacity: 1024,
+                block_size: 64 << 10_i32,
+                dirty_bytes: 128 << 10_i32,
+            },
+            inodes: vec![
+                InodeState {
+                    ino: 3,
+                    dirty_bytes: 128 << 10_i32,
+                    handles: 2,
+                    writable_handles: 1,
+                    lease: Some(LeaseMode::Write),
+                },
+                InodeState {
+                    ino: 2,
+                    dirty_bytes: 0,
+                    handles: 1,
+                    writable_handles: 0,
+                    lease: Some(LeaseMode::Read),
+                },
+            ],
+        };
+        let mut ops = BTreeMap::new();
+        ops.insert(
+            "READ".to_owned(),
+            OpLatency {
+                count: 4,
+                total: Duration::from_micros(400),
+                max: Duration::from_micros(250),
+            },
+        );
+        let stats = MountStats::new(&inspection, ops);
+        assert_eq!(stats.dirty_files, 1);
+        assert_eq!((stats.handles, stats.writable_handles), (3, 1));
+        assert_eq!((stats.read_leases, stats.write_leases), (1, 1));
+
+        let rendered = render_stats(&stats);
+        let mut lines = rendered.lines();
+        assert_eq!(
+            lines.next(),
+            Some("CACHE    48/1024 blocks, 3072K/64M, 128K dirty in 1 files")
+        );
+        assert_eq!(lines.next(), Some("HANDLES  3 open, 1 for writing"));
+        assert_eq!(lines.next(), Some("LEASES   1 read, 1 write"));
+        let op: Vec<&str> = lines
+            .nth(2)
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect();
+        assert_eq!(op, ["READ", "4", "100us", "250us"]);
+    }
+
+    #[test]
+    fn test_render_inspect() {
+        let inodes = [
+            InodeState {
+                ino: 3,
+                dirty_bytes: 20480,
+                handles: 2,
+                writable_handles: 1,
+                lease: Some(LeaseMode::Write),
+            },
+            InodeState {
+                ino: 2,
+                dirty_bytes: 0,
+                handles: 0,
+                writable_handles: 0,
+                lease: None,
+            },
+        ];
+        let rendered = render_inspect(&inodes);
+        let rows: Vec<Vec<&str>> = rendered
+            .lines()
+            .map(|line| line.split_whitespace().collect())
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                vec!["INODE", "DIRTY", "HANDLES", "WRITING", "LEASE"],
+                vec!["3", "20K", "2", "1", "write"],
+                vec!["2", "0", "0", "0", "-"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_slow_log() {
+        let requests = [SlowRequest {
+            received: SystemTime::UNIX_EPOCH,
+            unique: 12,
+            ino: 3,
+            pid: 42,
+            operation: "READ fh=1, offset=0, size=4096".to_owned(),
+            queue_wait: Duration::from_micros(300),
+            service: Duration::from_millis(1200),
+            decisions: vec![Decision::CacheMiss { ino: 3, block: 0 }],
+            decisions_dropped: 2,
+        }];
+        let rendered = render_slow_log(&requests);
+        let mut lines = rendered.lines();
+        assert!(lines.next().unwrap_or_default().starts_with("RECEIVED"));
+        let request: Vec<&str> = lines
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect();
+        assert_eq!(
+            request,
+            [
+                "1970-01-01T00:00:00.000Z",
+                "12",
+                "300us",
+                "1200ms",
+                "42",
+                "READ",
+                "fh=1,",
+                "offset=0,",
+                "size=4096",
+            ]
+        );
+        assert_eq!(
+            lines.next(),
+            Some("    cache miss of block 3:0, read from the backend")
+        );
+        assert_eq!(lines.next(), Some("    ... 2 more"));
+    }
+
+    #[test]
+    fn test_render_pin_list() {
+        let pins = PinList {
+            pins: vec![Pin {
+                path: "/models/llama".to_owned(),
+                files: 3,
+                blocks: 40,
+                bytes: 160 << 20_i32,
+            }],
+            blocks: 40,
+            limit: 1024,
+            block_size: 4 << 20_i32,
+        };
+        let rendered = render_pin_list(&pins);
+        let rows: Vec<Vec<&str>> = rendered
+            .lines()
+            .map(|line| line.split_whitespace().collect())
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                vec!["FILES", "BLOCKS", "SIZE", "PATH"],
+                vec!["3", "40", "160M", "/models/llama"],
+                vec![],
+                vec!["PINNED", "40/1024", "blocks,", "160M/4096M"],
+            ]
+        );
+    }
+}
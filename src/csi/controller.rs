@@ -3,11 +3,13 @@
 use std::cmp::Ordering;
 use std::sync::Arc;
 
+use clippy_utilities::Cast;
+use datenlord::config::StoragePolicy;
 use grpcio::{Error, RpcContext, UnarySink};
 use protobuf::RepeatedField;
 use tracing::{debug, error, info, warn};
 
-use super::meta_data::{DatenLordSnapshot, MetaData, VolumeSource};
+use super::meta_data::{DatenLordSnapshot, MetaData, VolumeAccessMode, VolumeSource};
 use super::proto::csi::{
     ControllerExpandVolumeRequest, ControllerExpandVolumeResponse,
     ControllerGetCapabilitiesRequest, ControllerGetCapabilitiesResponse,
@@ -23,11 +25,13 @@ use super::proto::csi::{
 };
 use super::proto::csi_grpc::Controller;
 use super::util;
+use crate::async_fuse::memfs::volume::Access;
 use crate::common::error::DatenLordError::{
     ArgumentInvalid, ArgumentOutOfRange, SnapshotAlreadyExist, SnapshotNotFound, Unimplemented,
     VolumeAlreadyExist, VolumeNotFound,
 };
 use crate::common::error::{Context, DatenLordResult};
+use crate::volume::VolumeManagerClient;
 
 /// for `ControllerService` implementation
 #[derive(Clone)]
@@ -42,20 +46,22 @@ struct ControllerImplInner {
     caps: Vec<ControllerServiceCapability>,
     /// Volume meta data for controller
     meta_data: Arc<MetaData>,
+    /// The volume manager CSI volumes are registered with, if configured
+    volume_manager: Option<VolumeManagerClient>,
 }
 
 impl ControllerImpl {
     /// Create `ControllerImpl`
-    pub fn new(meta_data: Arc<MetaData>) -> Self {
+    pub fn new(meta_data: Arc<MetaData>, volume_manager: Option<VolumeManagerClient>) -> Self {
         Self {
-            inner: Arc::new(ControllerImplInner::new(meta_data)),
+            inner: Arc::new(ControllerImplInner::new(meta_data, volume_manager)),
         }
     }
 }
 
 impl ControllerImplInner {
     /// Create `ControllerImplInner`
-    fn new(meta_data: Arc<MetaData>) -> Self {
+    fn new(meta_data: Arc<MetaData>, volume_manager: Option<VolumeManagerClient>) -> Self {
         let cap_vec = if meta_data.is_ephemeral() {
             Vec::new()
         } else {
@@ -77,7 +83,11 @@ impl ControllerImplInner {
             })
             .collect();
 
-        Self { caps, meta_data }
+        Self {
+            caps,
+            meta_data,
+            volume_manager,
+        }
     }
 
     /// Validate request with controller capabilities
@@ -264,6 +274,62 @@ impl ControllerImplInner {
         create_res.await.map_err(Error::into)
     }
 
+    /// Register the volume with the volume manager, if configured, then
+    /// create it on a worker under the ID the volume manager assigned
+    async fn create_managed_volume(
+        &self,
+        req: &CreateVolumeRequest,
+    ) -> DatenLordResult<CreateVolumeResponse> {
+        let params = util::parse_volume_params(req.get_parameters())?;
+        let Some(ref manager) = self.volume_manager else {
+            return self.worker_create_volume(req).await;
+        };
+        let vol_name = req.get_name();
+        let policy = match req.get_parameters().get(util::STORAGE_POLICY_KEY_PARAMETER) {
+            Some(policy) => policy.parse()?,
+            None => StoragePolicy::Tiered,
+        };
+        let required_bytes = req.get_capacity_range().get_required_bytes();
+        let capacity = u64::try_from(required_bytes).map_err(|_| ArgumentOutOfRange {
+            context: vec![format!("requested size {required_bytes} is negative")],
+        })?;
+        let secret = req.get_secrets().get(util::VOLUME_SECRET_KEY).cloned();
+        let (volume, _) = manager
+            .create(vol_name, capacity, policy, params, secret)
+            .await?;
+
+        // The nodes mount the volume with the token, which only grants read
+        // access if no capability writes the volume
+        let read_only = req
+            .get_volume_capabilities()
+            .iter()
+            .all(|cap| VolumeAccessMode::from(cap.get_access_mode().get_mode()).is_read_only());
+        let access = if read_only {
+            Access::ReadOnly
+        } else {
+            Access::ReadWrite
+        };
+        let create_res = match manager.issue_token(vol_name, access).await {
+            Ok(token) => {
+                let mut worker_req = req.clone();
+                let parameters = worker_req.mut_parameters();
+                parameters.insert(util::VOLUME_ID_KEY_PARAMETER.to_owned(), volume.id);
+                parameters.insert(util::ACCESS_TOKEN_KEY_CONTEXT.to_owned(), token);
+                self.worker_create_volume(&worker_req).await
+            }
+            Err(e) => Err(e),
+        };
+        if create_res.is_err() {
+            if let Err(e) = manager.delete(vol_name).await {
+                warn!(
+                    "failed to remove volume name={} from the volume manager, the error is: {}",
+                    vol_name, e,
+                );
+            }
+        }
+        create_res
+    }
+
     /// The pre-check helper function for `create_volume`
     fn create_volume_pre_check(&self, req: &CreateVolumeRequest) -> DatenLordResult<()> {
         let rpc_type = ControllerServiceCapability_RPC_Type::CREATE_DELETE_VOLUME;
@@ -288,20 +354,19 @@ impl ControllerImplInner {
         }
 
         let access_type_block = req_caps.iter().any(VolumeCapability::has_block);
-        let access_mode_multi_writer = req_caps.iter().any(|vc| {
-            let vol_access_mode = vc.get_access_mode().get_mode();
-            vol_access_mode == VolumeCapability_AccessMode_Mode::MULTI_NODE_READER_ONLY
-                || vol_access_mode == VolumeCapability_AccessMode_Mode::MULTI_NODE_MULTI_WRITER
-                || vol_access_mode == VolumeCapability_AccessMode_Mode::MULTI_NODE_SINGLE_WRITER
+        // Shared volumes are writable on every node publishing them
+        let access_mode_single_writer = req_caps.iter().any(|vc| {
+            vc.get_access_mode().get_mode()
+                == VolumeCapability_AccessMode_Mode::MULTI_NODE_SINGLE_WRITER
         });
         if access_type_block {
             return Err(ArgumentInvalid {
                 context: vec!["access type block not supported".to_owned()],
             });
         }
-        if access_mode_multi_writer {
+        if access_mode_single_writer {
             return Err(ArgumentInvalid {
-                context: vec!["access mode MULTI_NODE_SINGLE_WRITER and MULTI_NODE_MULTI_WRITER not supported".to_owned()],
+                context: vec!["access mode MULTI_NODE_SINGLE_WRITER not supported".to_owned()],
             });
         }
 
@@ -320,6 +385,18 @@ impl ControllerImplInner {
     }
 }
 
+/// Treat volumes the volume manager does not know as done, they were created
+/// before the volume manager was configured
+fn skip_unmanaged(res: DatenLordResult<()>) -> DatenLordResult<()> {
+    match res {
+        Err(VolumeNotFound { volume_id, .. }) => {
+            debug!("volume name={volume_id} is not managed by the volume manager");
+            Ok(())
+        }
+        Ok(()) | Err(_) => res,
+    }
+}
+
 impl Controller for ControllerImpl {
     fn create_volume(
         &mut self,
@@ -356,7 +433,7 @@ impl Controller for ControllerImpl {
                 }
             }
 
-            self_inner.worker_create_volume(&req).await.map_err(|e| {
+            self_inner.create_managed_volume(&req).await.map_err(|e| {
                 debug!("failed to create volume, the error is: {}", e);
                 e
             })
@@ -405,7 +482,12 @@ impl Controller for ControllerImpl {
                             )
                         });
                     match worker_delete_res {
-                        Ok(_) => info!("successfully deleted volume ID={}", vol_id),
+                        Ok(_) => {
+                            info!("successfully deleted volume ID={}", vol_id);
+                            if let Some(ref manager) = self_inner.volume_manager {
+                                skip_unmanaged(manager.delete(&vol.vol_name).await)?;
+                            }
+                        }
                         Err(e) => {
                             // Return error here?
                             // Should we return this error, old logic will ignore this
@@ -493,7 +575,7 @@ impl Controller for ControllerImpl {
                 });
             }
 
-            self_inner.meta_data.get_volume_by_id(vol_id).await?;
+            let volume = self_inner.meta_data.get_volume_by_id(vol_id).await?;
 
             for cap in vol_caps {
                 if !cap.has_mount() && !cap.has_block() {
@@ -508,9 +590,14 @@ impl Controller for ControllerImpl {
                         context: vec!["access type block is not supported".to_owned()],
                     });
                 }
-                // TODO: a real driver would check the capabilities of the given
-                // volume with the set of requested
-                // capabilities.
+                let mode = VolumeAccessMode::from(cap.get_access_mode().get_mode());
+                if mode.is_multi_node() && !volume.is_shared() {
+                    let mut r = ValidateVolumeCapabilitiesResponse::new();
+                    r.set_message(format!(
+                        "volume ID={vol_id} is not shared, access mode {mode:?} not supported"
+                    ));
+                    return Ok(r);
+                }
             }
 
             let mut r = ValidateVolumeCapabilitiesResponse::new();
@@ -881,6 +968,10 @@ impl Controller for ControllerImpl {
                     if let Err(e) = expand_res {
                         panic!("failed to expand volume ID={vol_id}, the error is: {e}",);
                     }
+                    if let Some(ref manager) = self_inner.volume_manager {
+                        let resize_res = manager.resize(&ex_vol.vol_name, capacity.cast()).await;
+                        skip_unmanaged(resize_res.map(|_| ()))?;
+                    }
                 }
                 Ordering::Greater => {
                     return Err(ArgumentInvalid {
@@ -10,6 +10,8 @@ use super::proto::csi::{
 };
 use super::proto::csi_grpc::Identity;
 use super::util;
+use crate::common::error::DatenLordError;
+use crate::health::HEALTH;
 
 /// for `IdentityService` implementation
 #[derive(Clone)]
@@ -70,8 +72,17 @@ impl Identity for IdentityImpl {
     fn probe(&mut self, _ctx: RpcContext, req: ProbeRequest, sink: UnarySink<ProbeResponse>) {
         debug!("probe request: {:?}", req);
 
-        let mut r = ProbeResponse::new();
-        r.mut_ready().set_value(true);
-        util::spawn_grpc_task(sink, async { Ok(r) });
+        util::spawn_grpc_task(sink, async {
+            // The liveness probe sidecar restarts the plugin once it's not alive.
+            if let Err(problems) = HEALTH.liveness() {
+                return Err(DatenLordError::InternalErr {
+                    source: anyhow::anyhow!("not alive"),
+                    context: vec![problems],
+                });
+            }
+            let mut r = ProbeResponse::new();
+            r.mut_ready().set_value(HEALTH.readiness().is_ok());
+            Ok(r)
+        });
     }
 }
@@ -2,9 +2,11 @@
 
 use std::collections::HashSet;
 use std::convert::From;
+use std::ffi::CString;
 use std::fmt::Debug;
 use std::fs::{self, File};
 use std::net::IpAddr;
+use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
@@ -15,6 +17,7 @@ use rand::Rng;
 use serde::{Deserialize, Serialize};
 use tracing::{debug, error, info, warn};
 
+use super::mount_ns;
 use super::proto::csi::{
     CreateVolumeRequest, ListSnapshotsResponse_Entry, ListVolumesResponse_Entry, Topology,
     TopologyRequirement, VolumeCapability_AccessMode_Mode, VolumeContentSource,
@@ -23,6 +26,8 @@ use super::proto::csi::{
 };
 use super::proto::datenlord_worker_grpc::WorkerClient;
 use super::util::{self, BindMountMode};
+use crate::async_fuse::memfs::quota::QuotaAttr;
+use crate::async_fuse::memfs::SCRATCH_XATTR;
 use crate::common::error::DatenLordError::{
     ArgumentInvalid, NodeNotFound, SnapshotNotFound, SnapshotNotReady, StartingTokenInvalid,
     VolumeNotFound,
@@ -77,6 +82,9 @@ pub struct MetaData {
     etcd_delegate: EtcdDelegate,
     /// The meta data about this node
     node: DatenLordNode,
+    /// The mount namespace the volumes are published in, `None` for the one
+    /// of this process
+    mount_namespace: Option<PathBuf>,
     // /// All volumes by ID
     // volume_meta_data: RwLock<HashMap<String, Arc<DatenLordVolume>>>,
     // /// All snapshots by ID
@@ -127,6 +135,7 @@ impl MetaData {
             run_as,
             etcd_delegate,
             node,
+            mount_namespace: None,
         };
         match md.run_as {
             NodeRole::Controller => md.register_to_etcd(CONTROLLER_PREFIX).await?,
@@ -134,12 +143,40 @@ impl MetaData {
             NodeRole::SchedulerExtender => {
                 md.register_to_etcd(SCHEDULER_EXTENDER_PREFIX).await?;
             }
-            NodeRole::AsyncFuse => (),
+            NodeRole::AsyncFuse
+            | NodeRole::Fsck
+            | NodeRole::Snapshot
+            | NodeRole::Trash
+            | NodeRole::VolumeManager
+            | NodeRole::Volume
+            | NodeRole::Operator
+            | NodeRole::Gateway
+            | NodeRole::Tenant
+            | NodeRole::Top
+            | NodeRole::Health
+            | NodeRole::Drain
+            | NodeRole::Usage
+            | NodeRole::Bench
+            | NodeRole::FuseDump
+            | NodeRole::Admin
+            | NodeRole::Stats
+            | NodeRole::Inspect
+            | NodeRole::SlowLog
+            | NodeRole::WarmUp
+            | NodeRole::Pin => (),
         }
 
         Ok(md)
     }
 
+    /// Publish the volumes in the mount namespace of the file `mount_namespace`
+    /// instead of the one of this process.
+    #[must_use]
+    pub fn with_mount_namespace(mut self, mount_namespace: Option<PathBuf>) -> Self {
+        self.mount_namespace = mount_namespace;
+        self
+    }
+
     /// Register this worker to etcd
     async fn register_to_etcd(&self, prefix: &str) -> DatenLordResult<()> {
         let key = format!("{}/{}", prefix, self.get_node_id());
@@ -336,6 +373,11 @@ impl MetaData {
         &self.node
     }
 
+    /// Get the mount namespace the volumes are published in
+    pub fn get_mount_namespace(&self) -> Option<&Path> {
+        self.mount_namespace.as_deref()
+    }
+
     /// Get all nodes in cluster
     pub async fn get_nodes(&self) -> DatenLordResult<Vec<String>> {
         let nodes: Vec<DatenLordNode> = self
@@ -872,11 +914,15 @@ impl MetaData {
             let pre_mount_path_vec_ref = Arc::new(pre_mount_path_vec);
             let pre_mount_path_vec_ref_clone =
                 Arc::<HashSet<String>>::clone(&pre_mount_path_vec_ref);
+            let mount_namespace = self.mount_namespace.clone();
             tokio::task::spawn_blocking(move || {
                 pre_mount_path_vec_ref_clone
                     .iter()
                     .for_each(|pre_mount_path| {
-                        let umount_res = util::umount_volume_bind_path(pre_mount_path);
+                        let umount_res =
+                            mount_ns::in_mount_namespace(mount_namespace.as_deref(), || {
+                                util::umount_volume_bind_path(pre_mount_path)
+                            });
                         if let Err(e) = umount_res {
                             panic!(
                                 "failed to un-mount volume ID={vol_id_owned} bind path={pre_mount_path}, \
@@ -1272,15 +1318,18 @@ impl MetaData {
         let target_path_owned = target_path.to_owned();
         let fs_type_owned = fs_type.to_owned();
         let mount_options_owned = mount_options.to_owned();
+        let mount_namespace = self.mount_namespace.clone();
         let mount_res = tokio::task::spawn_blocking(move || {
-            util::mount_volume_bind_path(
-                &vol_path_owned,
-                &target_path_owned,
-                bind_mount_mode,
-                &mount_options_owned,
-                &fs_type_owned,
-                read_only,
-            )
+            mount_ns::in_mount_namespace(mount_namespace.as_deref(), || {
+                util::mount_volume_bind_path(
+                    &vol_path_owned,
+                    &target_path_owned,
+                    bind_mount_mode,
+                    &mount_options_owned,
+                    &fs_type_owned,
+                    read_only,
+                )
+            })
         })
         .await?
         .with_context(|| format!("failed to bind mount from {vol_path:?} to {target_path:?}",));
@@ -1470,6 +1519,30 @@ impl From<VolumeCapability_AccessMode_Mode> for VolumeAccessMode {
     }
 }
 
+impl VolumeAccessMode {
+    /// Whether the volume can be published at multiple nodes simultaneously
+    pub const fn is_multi_node(&self) -> bool {
+        match *self {
+            Self::MultiNodeReadOnly | Self::MultiNodeSingleWriter | Self::MultiNodeMultiWriter => {
+                true
+            }
+            Self::Unknown | Self::SingleNodeWriter | Self::SingleNodeReadOnly => false,
+        }
+    }
+
+    /// Whether the volume is only published readonly
+    #[must_use]
+    pub const fn is_read_only(&self) -> bool {
+        match *self {
+            Self::SingleNodeReadOnly | Self::MultiNodeReadOnly => true,
+            Self::Unknown
+            | Self::SingleNodeWriter
+            | Self::MultiNodeSingleWriter
+            | Self::MultiNodeMultiWriter => false,
+        }
+    }
+}
+
 /// Volume source, copied from `VolumeContentSource_oneof_type`,
 /// because `VolumeContentSource_oneof_type` is not serializable,
 /// either source snapshot ID or source volume ID
@@ -1601,21 +1674,26 @@ impl DatenLordVolume {
         }
 
         vol.create_vol_dir()?;
+        vol.set_quota()?;
+        if vol.ephemeral {
+            vol.set_scratch()?;
+        }
         Ok(vol)
     }
 
-    /// Create ephemeral volume
+    /// Create ephemeral volume of `size_bytes`
     pub fn build_ephemeral_volume(
         vol_id: &str,
         vol_name: &str,
         node_id: &str,
         vol_path: &Path,
+        size_bytes: i64,
     ) -> DatenLordResult<Self> {
         Self::new(
             DatenLordVolumeBasicFields {
                 vol_id: vol_id.to_owned(),
                 vol_name: vol_name.to_owned(),
-                size_bytes: util::EPHEMERAL_VOLUME_STORAGE_CAPACITY,
+                size_bytes,
                 node_ids: vec![node_id.to_owned()],
                 accessible_nodes: vec![node_id.to_owned()],
                 vol_path: vol_path.to_owned(),
@@ -1675,6 +1753,73 @@ impl DatenLordVolume {
         Ok(())
     }
 
+    /// Limit the volume directory to the volume size with a quota, so writes
+    /// beyond it fail with `EDQUOT` and `statfs` of the mounted volume
+    /// reports the size, both taking effect without a remount
+    pub fn set_quota(&self) -> DatenLordResult<()> {
+        if !self.set_xattr(QuotaAttr::MaxBytes.name(), &self.size_bytes.to_string())? {
+            warn!(
+                "volume ID={} at {:?} doesn't support quotas, its size is not enforced",
+                self.vol_id, self.vol_path,
+            );
+        }
+        Ok(())
+    }
+
+    /// Mark the volume directory as a scratch directory, so the data of an
+    /// ephemeral volume is reclaimed once it's torn down instead of being
+    /// kept in the trash
+    pub fn set_scratch(&self) -> DatenLordResult<()> {
+        if !self.set_xattr(SCRATCH_XATTR, "1")? {
+            warn!(
+                "volume ID={} at {:?} doesn't support scratch directories",
+                self.vol_id, self.vol_path,
+            );
+        }
+        Ok(())
+    }
+
+    /// Set extended attribute `name` of the volume directory to `value`,
+    /// return `false` if the file system doesn't support it, which is not
+    /// `DatenLord`, such as in tests
+    fn set_xattr(&self, name: &str, value: &str) -> DatenLordResult<bool> {
+        let (Ok(c_path), Ok(c_name)) = (
+            CString::new(self.vol_path.as_os_str().as_bytes()),
+            CString::new(name),
+        ) else {
+            return Err(ArgumentInvalid {
+                context: vec![format!(
+                    "directory={:?} of volume ID={} contains a NUL byte",
+                    self.vol_path, self.vol_id,
+                )],
+            });
+        };
+        // SAFETY: the path and the name are NUL-terminated and the value
+        // outlives the call
+        let res = unsafe {
+            libc::setxattr(
+                c_path.as_ptr(),
+                c_name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            )
+        };
+        if res != 0 {
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ENOTSUP) {
+                return Ok(false);
+            }
+            return Err(err).with_context(|| {
+                format!(
+                    "failed to set {name}={value} of volume ID={} at {:?}",
+                    self.vol_id, self.vol_path,
+                )
+            });
+        }
+        Ok(true)
+    }
+
     /// Get volume size
     pub const fn get_size(&self) -> i64 {
         // TODO: use more relaxed ordering
@@ -1701,6 +1846,15 @@ impl DatenLordVolume {
         self.accessible_nodes.iter().any(|node| node == node_id)
     }
 
+    /// Check if volume is shared, which is published at multiple nodes
+    /// simultaneously, the file system keeps the nodes coherent and shares
+    /// the POSIX locks among them
+    pub fn is_shared(&self) -> bool {
+        self.vol_access_mode
+            .iter()
+            .any(VolumeAccessMode::is_multi_node)
+    }
+
     /// Get primary node id on which the volume is accessed first.
     pub fn get_primary_node_id(&self) -> &str {
         self.node_ids
@@ -1765,3 +1919,73 @@ impl DatenLordSnapshot {
         Ok(())
     }
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    use clippy_utilities::Cast;
+
+    use super::DatenLordVolume;
+    use crate::async_fuse::memfs::quota::QuotaAttr;
+
+    /// Get extended attribute `name` of `path`, `None` if the file system
+    /// doesn't support it.
+    fn get_xattr(path: &Path, name: &str) -> Option<String> {
+        let c_path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        let c_name = CString::new(name).unwrap();
+        let mut buf = [0_u8; 64];
+        // SAFETY: the path and the name are NUL-terminated and the buffer
+        // outlives the call
+        let res = unsafe {
+            libc::getxattr(
+                c_path.as_ptr(),
+                c_name.as_ptr(),
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+            )
+        };
+        if res < 0 {
+            let err = std::io::Error::last_os_error();
+            assert_eq!(
+                err.raw_os_error(),
+                Some(libc::ENOTSUP),
+                "failed to get {name} of {path:?}: {err}"
+            );
+            return None;
+        }
+        let value = buf.get(..res.cast::<usize>()).unwrap();
+        Some(String::from_utf8(value.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_volume_quota() {
+        let vol_path =
+            std::env::temp_dir().join(format!("datenlord_csi_volume_{}", uuid::Uuid::new_v4()));
+        let mut volume = DatenLordVolume::build_ephemeral_volume(
+            "quota-volume-id",
+            "quota-volume",
+            "node1",
+            &vol_path,
+            0x10_0000,
+        )
+        .unwrap();
+
+        // An extended attribute not supported by the file system is not an
+        // error, the volume is left unlimited with a warning.
+        assert!(!volume.set_xattr("datenlord.unsupported", "1").unwrap());
+
+        // The quota is raised like `NodeExpandVolume`, it's stored if the file
+        // system supports the extended attributes of users like DatenLord.
+        assert!(volume.expand_size(0x20_0000));
+        volume.set_quota().unwrap();
+        if let Some(max_bytes) = get_xattr(&vol_path, QuotaAttr::MaxBytes.name()) {
+            assert_eq!(max_bytes, "2097152");
+        }
+
+        volume.delete_directory().unwrap();
+    }
+}
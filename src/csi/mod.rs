@@ -4,6 +4,7 @@
 mod controller;
 mod identity;
 pub mod meta_data;
+pub mod mount_ns;
 mod node;
 /// Proto definition
 mod proto;
@@ -27,6 +28,7 @@ use worker::WorkerImpl;
 
 use crate::common::error::{Context, DatenLordResult};
 use crate::common::etcd_delegate::EtcdDelegate;
+use crate::volume::VolumeManagerClient;
 
 /// Build meta data
 pub async fn build_meta_data(
@@ -80,6 +82,7 @@ pub fn build_grpc_node_server(
     end_point: &str,
     driver_name: &str,
     meta_data: Arc<MetaData>,
+    volume_manager: Option<VolumeManagerClient>,
 ) -> DatenLordResult<Server> {
     remove_socket_file(end_point);
 
@@ -87,7 +90,7 @@ pub fn build_grpc_node_server(
         driver_name.to_owned(),
         util::CSI_PLUGIN_VERSION.to_owned(),
     ));
-    let node_service = proto::csi_grpc::create_node(NodeImpl::new(meta_data));
+    let node_service = proto::csi_grpc::create_node(NodeImpl::new(meta_data, volume_manager));
     // TODO: increase concurrent queue size
     let node_server = grpcio::ServerBuilder::new(Arc::new(Environment::new(1)))
         .register_service(identity_service)
@@ -105,6 +108,7 @@ pub fn build_grpc_controller_server(
     end_point: &str,
     driver_name: &str,
     meta_data: Arc<MetaData>,
+    volume_manager: Option<VolumeManagerClient>,
 ) -> DatenLordResult<Server> {
     remove_socket_file(end_point);
 
@@ -112,7 +116,8 @@ pub fn build_grpc_controller_server(
         driver_name.to_owned(),
         util::CSI_PLUGIN_VERSION.to_owned(),
     ));
-    let controller_service = proto::csi_grpc::create_controller(ControllerImpl::new(meta_data));
+    let controller_service =
+        proto::csi_grpc::create_controller(ControllerImpl::new(meta_data, volume_manager));
 
     // let (mem_size, overflow) = 1024_usize.overflowing_mul(1024);
     // debug_assert!(!overflow, "computing memory size overflowed");
@@ -182,6 +187,7 @@ mod test {
 
     use clippy_utilities::{Cast, OverflowArithmetic};
     use datenlord::common::task_manager::{TaskName, TASK_MANAGER};
+    use datenlord::config::LogFormat;
     use grpcio::{ChannelBuilder, EnvBuilder};
     use proto::csi::{
         ControllerExpandVolumeRequest, ControllerExpandVolumeResponse, CreateSnapshotRequest,
@@ -222,11 +228,71 @@ mod test {
     const DATA_DIR: &str = "/tmp/csi-data-dir";
     static GRPC_SERVER: Once = Once::new();
 
+    #[test]
+    fn test_parse_quantity() {
+        assert_eq!(util::parse_quantity("4096").ok(), Some(4096));
+        assert_eq!(util::parse_quantity("2k").ok(), Some(2000));
+        assert_eq!(util::parse_quantity("1Gi").ok(), Some(1_073_741_824));
+        assert!(util::parse_quantity("1.5Gi").is_err());
+        assert!(util::parse_quantity("Gi").is_err());
+        assert!(util::parse_quantity("1Xi").is_err());
+        assert!(util::parse_quantity("9999999999Ti").is_err());
+    }
+
+    #[test]
+    fn test_parse_volume_params() {
+        use std::collections::HashMap;
+
+        use datenlord::config::CompressionType;
+
+        use crate::async_fuse::memfs::volume::VolumeParams;
+
+        let parameters = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|&(key, value)| (key.to_owned(), value.to_owned()))
+                .collect::<HashMap<_, _>>()
+        };
+        let params = util::parse_volume_params(&parameters(&[
+            ("blockSize", "1Mi"),
+            ("cacheSizeMiB", "512"),
+            ("compression", "zstd"),
+            ("replicas", "3"),
+            ("storagePolicy", "tiered"),
+        ]))
+        .unwrap_or_else(|e| panic!("failed to parse the parameters: {e}"));
+        assert_eq!(params.block_size, Some(1 << 20));
+        assert_eq!(params.cache_size, Some(512 << 20));
+        assert_eq!(params.compression, Some(CompressionType::Zstd));
+        assert_eq!(params.replicas, Some(3));
+        let params = util::parse_volume_params(&parameters(&[])).ok();
+        assert_eq!(params, Some(VolumeParams::default()));
+        for invalid in [
+            ("blockSize", "1000"),
+            ("cacheSizeMiB", "-1"),
+            ("compression", "gzip"),
+            ("replicas", "0"),
+        ] {
+            assert!(util::parse_volume_params(&parameters(&[invalid])).is_err());
+        }
+    }
+
+    #[test]
+    fn test_volume_access_mode() {
+        use meta_data::VolumeAccessMode;
+
+        assert!(VolumeAccessMode::MultiNodeMultiWriter.is_multi_node());
+        assert!(VolumeAccessMode::MultiNodeReadOnly.is_multi_node());
+        assert!(!VolumeAccessMode::SingleNodeWriter.is_multi_node());
+        assert!(VolumeAccessMode::MultiNodeReadOnly.is_read_only());
+        assert!(!VolumeAccessMode::MultiNodeMultiWriter.is_read_only());
+    }
+
     #[ignore = "maybe conflict with other tests"]
     #[allow(clippy::let_underscore_must_use)]
     #[tokio::test(flavor = "multi_thread")]
     async fn test_all() -> DatenLordResult<()> {
-        init_logger(LogRole::Test);
+        init_logger(LogRole::Test, LogFormat::Text);
         // TODO: run test case in parallel
         // Because they all depend on etcd, so cannot run in parallel now
         // let mut etcd_server = MockEtcdServer::new();
@@ -318,6 +384,7 @@ mod test {
             "ephemeral-volume", // vol_name
             DEFAULT_NODE_NAME,
             meta_data.get_volume_path(NODE_PUBLISH_VOLUME_ID).as_path(), // vol_path
+            util::EPHEMERAL_VOLUME_STORAGE_CAPACITY,
         )?;
         let add_vol_res = meta_data.add_volume_meta_data(vol_id, &volume).await;
         assert!(
@@ -457,6 +524,7 @@ mod test {
                     &controller_end_point,
                     &driver_name,
                     Arc::<MetaData>::clone(&controller_md),
+                    None,
                 ) {
                     Ok(server) => server,
                     Err(e) => panic!("failed to build CSI server, the error is : {e}",),
@@ -479,6 +547,7 @@ mod test {
                     &node_end_point,
                     &driver_name,
                     Arc::<MetaData>::clone(&node_worker_md),
+                    None,
                 ) {
                     Ok(server) => server,
                     Err(e) => panic!("failed to build Node server, the error is : {e}",),
@@ -1240,7 +1309,11 @@ mod test {
             .collect::<Vec<_>>();
         assert_eq!(
             cap_vec,
-            vec![NodeServiceCapability_RPC_Type::EXPAND_VOLUME,],
+            vec![
+                NodeServiceCapability_RPC_Type::GET_VOLUME_STATS,
+                NodeServiceCapability_RPC_Type::EXPAND_VOLUME,
+                NodeServiceCapability_RPC_Type::VOLUME_CONDITION,
+            ],
             "node_get_capabilities returns unexpected capabilities",
         );
 
@@ -0,0 +1,69 @@
+//! Publishing the volumes in the mount namespace of the kubelet.
+//!
+//! The node plugin usually runs in a container, whose mount namespace is not
+//! the one of the kubelet, so the volumes it bind mounts are not seen by the
+//! pods unless the kubelet directory is mounted into the container with
+//! `Bidirectional` propagation. Given `--csi-mount-namespace`, such as
+//! `/proc/1/ns/mnt` of a plugin with `hostPID`, the volumes are bind mounted
+//! and un-mounted in that namespace instead, where the kubelet mounts the
+//! `subPath`s of the volumes and the runtime mounts them into the pods. The
+//! FUSE mount of the node must be seen in that namespace as well.
+//!
+//! A thread shares its root and working directories with the other threads of
+//! the process, which keeps it from joining another mount namespace, so each
+//! operation in the namespace runs on a thread of its own, which stops sharing
+//! them first.
+//!
+//! A volume bind mounted from a shared FUSE mount joins its peer group, so the
+//! mounts under a publish, such as the ones propagated back from a pod with
+//! `Bidirectional` propagation, would show up in the volume and in all its
+//! other publishes. Each publish is moved to a peer group of its own instead,
+//! as a slave of the one of the volume, which still receives the mounts of the
+//! volume.
+
+use std::fs::File;
+use std::path::Path;
+use std::thread;
+
+use nix::mount::{self, MsFlags};
+use nix::sched::{self, CloneFlags};
+
+use crate::common::error::{Context, DatenLordResult};
+
+/// Run `f` in the mount namespace of the file `namespace`, or in the one of
+/// this process if it's `None`.
+pub fn in_mount_namespace<T, F>(namespace: Option<&Path>, f: F) -> DatenLordResult<T>
+where
+    T: Send,
+    F: FnOnce() -> DatenLordResult<T> + Send,
+{
+    let Some(namespace) = namespace else {
+        return f();
+    };
+    let ns_file = File::open(namespace)
+        .with_context(|| format!("failed to open mount namespace {namespace:?}"))?;
+    thread::scope(|scope| {
+        scope
+            .spawn(move || {
+                sched::unshare(CloneFlags::CLONE_FS).with_context(|| {
+                    "failed to stop sharing the file system attributes".to_owned()
+                })?;
+                sched::setns(&ns_file, CloneFlags::CLONE_NEWNS)
+                    .with_context(|| format!("failed to enter mount namespace {namespace:?}"))?;
+                f()
+            })
+            .join()
+            .unwrap_or_else(|_| panic!("the thread in mount namespace {namespace:?} panics"))
+    })
+}
+
+/// Move the bind mount at `target` to a peer group of its own, as a slave of
+/// the peer group of its source if there's one.
+pub fn isolate_peer_group(target: &Path) -> DatenLordResult<()> {
+    for flag in [MsFlags::MS_SLAVE, MsFlags::MS_SHARED] {
+        mount::mount::<Path, Path, Path, Path>(None, target, None, flag, None).with_context(
+            || format!("failed to change the propagation of {target:?} to {flag:?}"),
+        )?;
+    }
+    Ok(())
+}
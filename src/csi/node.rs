@@ -1,9 +1,12 @@
 //! The implementation for CSI node service
 
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
 use std::sync::Arc;
 
 use grpcio::{RpcContext, UnarySink};
 use nix::sys::stat::{self, SFlag};
+use nix::sys::statvfs;
 use protobuf::RepeatedField;
 use tracing::{debug, error, info, warn};
 
@@ -15,12 +18,16 @@ use super::proto::csi::{
     NodePublishVolumeResponse, NodeServiceCapability, NodeServiceCapability_RPC_Type,
     NodeStageVolumeRequest, NodeStageVolumeResponse, NodeUnpublishVolumeRequest,
     NodeUnpublishVolumeResponse, NodeUnstageVolumeRequest, NodeUnstageVolumeResponse, Topology,
-    VolumeCapability_oneof_access_type,
+    VolumeCapability_oneof_access_type, VolumeCondition,
 };
 use super::proto::csi_grpc::Node;
-use super::util;
-use crate::common::error::DatenLordError::{ArgumentInvalid, Unimplemented};
-use crate::common::error::{Context, DatenLordResult};
+use super::{mount_ns, util};
+use crate::async_fuse::memfs::read_only::READ_ONLY_VOLUMES;
+use crate::async_fuse::memfs::volume::Access;
+use crate::common::error::DatenLordError::{ArgumentInvalid, ArgumentOutOfRange};
+use crate::common::error::{Context, DatenLordError, DatenLordResult};
+use crate::storage::condition::STORAGE_CONDITION;
+use crate::volume::VolumeManagerClient;
 
 /// for `NodeService` implementation
 #[derive(Clone)]
@@ -35,21 +42,27 @@ struct NodeImplInner {
     caps: Vec<NodeServiceCapability>,
     /// Volume meta data for this node
     meta_data: Arc<MetaData>,
+    /// The volume manager checking volume secrets, if configured
+    volume_manager: Option<VolumeManagerClient>,
 }
 
 impl NodeImpl {
     /// Create `NodeImpl`
-    pub fn new(meta_data: Arc<MetaData>) -> Self {
+    pub fn new(meta_data: Arc<MetaData>, volume_manager: Option<VolumeManagerClient>) -> Self {
         Self {
-            inner: Arc::new(NodeImplInner::new(meta_data)),
+            inner: Arc::new(NodeImplInner::new(meta_data, volume_manager)),
         }
     }
 }
 
 impl NodeImplInner {
     /// Create `NodeImpl`
-    fn new(meta_data: Arc<MetaData>) -> Self {
-        let cap_vec = vec![NodeServiceCapability_RPC_Type::EXPAND_VOLUME];
+    fn new(meta_data: Arc<MetaData>, volume_manager: Option<VolumeManagerClient>) -> Self {
+        let cap_vec = vec![
+            NodeServiceCapability_RPC_Type::GET_VOLUME_STATS,
+            NodeServiceCapability_RPC_Type::EXPAND_VOLUME,
+            NodeServiceCapability_RPC_Type::VOLUME_CONDITION,
+        ];
         let caps = cap_vec
             .into_iter()
             .map(|rpc_type| {
@@ -58,7 +71,11 @@ impl NodeImplInner {
                 csc
             })
             .collect();
-        Self { caps, meta_data }
+        Self {
+            caps,
+            meta_data,
+            volume_manager,
+        }
     }
 
     /// Validate request with controller capabilities
@@ -70,14 +87,16 @@ impl NodeImplInner {
                 .any(|cap| cap.get_rpc().get_field_type() == rpc_type)
     }
 
-    /// Create ephemeral volume
-    async fn create_ephemeral_volume(&self, vol_id: &str) -> DatenLordResult<()> {
+    /// Create ephemeral volume of `size_bytes`, its directory is a scratch
+    /// directory limited to the size
+    async fn create_ephemeral_volume(&self, vol_id: &str, size_bytes: i64) -> DatenLordResult<()> {
         let vol_name = format!("ephemeral-{vol_id}");
         let volume = DatenLordVolume::build_ephemeral_volume(
             vol_id,
             &vol_name,
             self.meta_data.get_node_id(),
             &self.meta_data.get_volume_path(vol_id),
+            size_bytes,
         )
         .with_context(|| {
             format!("failed to create ephemeral volume ID={vol_id} and name={vol_name}",)
@@ -94,6 +113,42 @@ impl NodeImplInner {
         Ok(())
     }
 
+    /// Check the credential to publish volume `vol_name` with the volume
+    /// manager, either the volume secret or the access token in the volume
+    /// context, returns whether the credential only grants read access.
+    /// The volumes unknown to the volume manager need no credential.
+    async fn authorize_publish(
+        &self,
+        vol_name: &str,
+        req: &NodePublishVolumeRequest,
+    ) -> DatenLordResult<bool> {
+        let Some(ref manager) = self.volume_manager else {
+            return Ok(false);
+        };
+        let vol_id = req.get_volume_id();
+        if let Some(secret) = req.get_secrets().get(util::VOLUME_SECRET_KEY) {
+            if !manager.authorize(vol_name, secret).await? {
+                return Err(ArgumentInvalid {
+                    context: vec![format!("volume secret of volume ID={vol_id} is rejected")],
+                });
+            }
+            return Ok(false);
+        }
+        let token = req
+            .get_volume_context()
+            .get(util::ACCESS_TOKEN_KEY_CONTEXT)
+            .map_or("", String::as_str);
+        match manager.verify_token(vol_name, token).await {
+            Ok(Some(Access::ReadOnly)) => Ok(true),
+            Ok(Some(Access::ReadWrite)) => Ok(false),
+            Ok(None) => Err(ArgumentInvalid {
+                context: vec![format!("access token of volume ID={vol_id} is rejected")],
+            }),
+            Err(DatenLordError::VolumeNotFound { .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
     /// Delete ephemeral volume
     /// `tolerant_error` means whether to ignore umount error or not
     async fn delete_ephemeral_volume(&self, volume: &DatenLordVolume, tolerant_error: bool) {
@@ -245,7 +300,7 @@ impl Node for NodeImpl {
 
         let task = async move {
             NodeImplInner::node_publish_volume_pre_check(&req)?;
-            let read_only = req.get_readonly();
+            let mut read_only = req.get_readonly();
             let volume_context = req.get_volume_context();
             let device_id = match volume_context.get("deviceID") {
                 Some(did) => did,
@@ -271,7 +326,20 @@ impl Node for NodeImpl {
             // If ephemeral is true, create volume here to avoid errors if not exists
             let volume_exist = self_inner.meta_data.find_volume_by_id(vol_id).await?;
             if ephemeral && !volume_exist {
-                if let Err(e) = self_inner.create_ephemeral_volume(vol_id).await {
+                let size_bytes = match volume_context.get(util::EPHEMERAL_SIZE_KEY_CONTEXT) {
+                    Some(size) => util::parse_quantity(size)?,
+                    None => util::EPHEMERAL_VOLUME_STORAGE_CAPACITY,
+                };
+                if size_bytes > util::MAX_VOLUME_STORAGE_CAPACITY {
+                    return Err(ArgumentOutOfRange {
+                        context: vec![format!(
+                            "requested size {} exceeds maximum allowed {}",
+                            size_bytes,
+                            util::MAX_VOLUME_STORAGE_CAPACITY,
+                        )],
+                    });
+                }
+                if let Err(e) = self_inner.create_ephemeral_volume(vol_id, size_bytes).await {
                     warn!(
                         "failed to create ephemeral volume ID={}, the error is:{}",
                         vol_id, e,
@@ -281,8 +349,15 @@ impl Node for NodeImpl {
             }
 
             let mut volume = self_inner.meta_data.get_volume_by_id(vol_id).await?;
+            if self_inner.authorize_publish(&volume.vol_name, &req).await? && !read_only {
+                info!(
+                    "volume ID={} is published read-only, its access token only grants read",
+                    vol_id,
+                );
+                read_only = true;
+            }
             let node_id = self_inner.meta_data.get_node_id();
-            if !volume.check_exist_in_accessible_nodes(node_id) {
+            if !volume.is_shared() && !volume.check_exist_in_accessible_nodes(node_id) {
                 return Err(ArgumentInvalid {
                     context: vec![format!(
                         "volume ID={vol_id} is not accessible on node ID={node_id}"
@@ -347,6 +422,10 @@ impl Node for NodeImpl {
                                 ephemeral,
                             )
                             .await?;
+                        // The file system refuses to change the volume only
+                        // published read-only on this node
+                        let dir = tokio::fs::metadata(&volume.vol_path).await?;
+                        READ_ONLY_VOLUMES.publish(target_dir, dir.ino(), read_only);
                     } else {
                         // VolumeCapability_oneof_access_type::block(..) not supported
                         return Err(ArgumentInvalid {
@@ -386,6 +465,7 @@ impl Node for NodeImpl {
             }
 
             let volume = self_inner.meta_data.get_volume_by_id(vol_id).await?;
+            READ_ONLY_VOLUMES.unpublish(target_path);
 
             let r = NodeUnpublishVolumeResponse::new();
             // Do not return error for non-existent path, repeated calls OK for idempotency
@@ -421,8 +501,14 @@ impl Node for NodeImpl {
                 true
             };
             let target_path_owned = target_path.to_owned();
+            let mount_namespace = self_inner
+                .meta_data
+                .get_mount_namespace()
+                .map(Path::to_owned);
             if let Err(e) = tokio::task::spawn_blocking(move || {
-                util::umount_volume_bind_path(&target_path_owned)
+                mount_ns::in_mount_namespace(mount_namespace.as_deref(), || {
+                    util::umount_volume_bind_path(&target_path_owned)
+                })
             })
             .await?
             {
@@ -468,15 +554,54 @@ impl Node for NodeImpl {
         sink: UnarySink<NodeGetVolumeStatsResponse>,
     ) {
         debug!("node_get_volume_stats request: {:?}", req);
-        util::spawn_grpc_task(sink, async {
-            Err(Unimplemented {
-                context: vec!["unimplemented".to_owned()],
-            })
-        });
+        let self_inner = Arc::<NodeImplInner>::clone(&self.inner);
+
+        let task = async move {
+            // Check arguments
+            let vol_id = req.get_volume_id();
+            if vol_id.is_empty() {
+                return Err(ArgumentInvalid {
+                    context: vec!["volume ID missing in request".to_owned()],
+                });
+            }
+            let vol_path = req.get_volume_path();
+            if vol_path.is_empty() {
+                return Err(ArgumentInvalid {
+                    context: vec!["volume path missing in request".to_owned()],
+                });
+            }
+            self_inner.meta_data.get_volume_by_id(vol_id).await?;
+
+            // The usage is reported by `statfs` of the volume, from the usage
+            // accounting limited by the quota of the volume directory
+            let mut r = NodeGetVolumeStatsResponse::new();
+            let mut condition = VolumeCondition::new();
+            let vol_path_owned = vol_path.to_owned();
+            match tokio::task::spawn_blocking(move || statvfs::statvfs(vol_path_owned.as_str()))
+                .await?
+            {
+                Ok(stat) => {
+                    r.set_usage(RepeatedField::from_vec(util::build_volume_usage(&stat)));
+                    if let Some(problem) = STORAGE_CONDITION.abnormal() {
+                        condition.set_abnormal(true);
+                        condition.set_message(problem);
+                    } else {
+                        condition.set_message("volume is healthy".to_owned());
+                    }
+                }
+                Err(e) => {
+                    condition.set_abnormal(true);
+                    condition.set_message(format!("failed to get the stats of {vol_path}: {e}"));
+                }
+            }
+            r.set_volume_condition(condition);
+            Ok(r)
+        };
+        util::spawn_grpc_task(sink, task);
     }
 
-    // node_expand_volume is only implemented so the driver can be used for e2e
-    // testing no actual volume expansion operation
+    // The controller has updated the volume size, raise the quota of the
+    // volume directory to it, which mounted instances see online
     fn node_expand_volume(
         &mut self,
         _ctx: RpcContext,
@@ -503,7 +628,7 @@ impl Node for NodeImpl {
                 });
             }
 
-            self_inner
+            let volume = self_inner
                 .meta_data
                 .get_volume_by_id(vol_id)
                 .await
@@ -521,8 +646,12 @@ impl Node for NodeImpl {
             let sflag = SFlag::from_bits_truncate(file_stat.st_mode);
             if let SFlag::S_IFDIR = sflag {
                 // SFlag::S_IFBLK and other type not supported
-                // TODO: implement volume expansion here
-                debug!("volume access type mount requires volume file type directory");
+                volume.set_quota()?;
+                info!(
+                    "expanded volume ID={} to size={}",
+                    vol_id,
+                    volume.get_size(),
+                );
             } else {
                 return Err(ArgumentInvalid {
                     context: vec![format!(
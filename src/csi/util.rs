@@ -1,15 +1,17 @@
 //! Utility functions and const variables
 
+use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::fmt::Debug;
 use std::fs;
 use std::path::Path;
 use std::process::Command;
 
-use clippy_utilities::Cast;
+use clippy_utilities::{Cast, OverflowArithmetic};
 use futures::prelude::*;
 use grpcio::{RpcStatus, UnarySink};
 use nix::mount::{self, MntFlags, MsFlags};
+use nix::sys::statvfs::Statvfs;
 use nix::unistd;
 use once_cell::sync::Lazy;
 use protobuf::RepeatedField;
@@ -17,11 +19,13 @@ use tokio::runtime::Runtime;
 use tracing::{error, info};
 use walkdir::WalkDir;
 
+use super::mount_ns;
 use super::proto::csi::{
     CreateSnapshotRequest, CreateSnapshotResponse, CreateVolumeRequest, CreateVolumeResponse,
-    Snapshot, Topology, Volume,
+    Snapshot, Topology, Volume, VolumeUsage, VolumeUsage_Unit,
 };
-use crate::common::error::DatenLordError::{IoErr, MountErr, NixErr, UmountErr};
+use crate::async_fuse::memfs::volume::VolumeParams;
+use crate::common::error::DatenLordError::{ArgumentInvalid, IoErr, MountErr, NixErr, UmountErr};
 use crate::common::error::{Context, DatenLordError, DatenLordResult};
 
 /// The CSI plugin name
@@ -42,6 +46,8 @@ pub const SNAPSHOT_EXT: &str = ".snap";
 pub const TOPOLOGY_KEY_NODE: &str = "topology.csi.datenlord.io/node";
 /// The key of ephemeral in volume context
 pub const EPHEMERAL_KEY_CONTEXT: &str = "csi.storage.k8s.io/ephemeral";
+/// The key of the size of an ephemeral inline volume in volume context
+pub const EPHEMERAL_SIZE_KEY_CONTEXT: &str = "size";
 /// Default max volume per node, should read from input argument
 pub const MAX_VOLUMES_PER_NODE: i32 = 256_i32;
 /// The socket file to be binded by worker service
@@ -50,6 +56,22 @@ pub const LOCAL_WORKER_SOCKET: &str = "unix:///tmp/worker.sock";
 pub const DEFAULT_BIND_MOUNT_HELPER_CMD_PATH: &str = "./target/debug/bind_mounter";
 /// The key of the bind mount helper command path environment variable
 pub const BIND_MOUNT_HELPER_CMD_ENV_KEY: &str = "BIND_MOUNTER";
+/// The key of the storage policy in `StorageClass` parameters
+pub const STORAGE_POLICY_KEY_PARAMETER: &str = "storagePolicy";
+/// The key of the block size in `StorageClass` parameters, a quantity
+pub const BLOCK_SIZE_KEY_PARAMETER: &str = "blockSize";
+/// The key of the cache size in MiB in `StorageClass` parameters
+pub const CACHE_SIZE_KEY_PARAMETER: &str = "cacheSizeMiB";
+/// The key of the compression in `StorageClass` parameters
+pub const COMPRESSION_KEY_PARAMETER: &str = "compression";
+/// The key of the number of replicas in `StorageClass` parameters
+pub const REPLICAS_KEY_PARAMETER: &str = "replicas";
+/// The key of the volume manager assigned volume ID in create volume parameters
+pub const VOLUME_ID_KEY_PARAMETER: &str = "datenlord.io/volumeID";
+/// The key of the volume secret in provisioner and node publish secrets
+pub const VOLUME_SECRET_KEY: &str = "volumeSecret";
+/// The key of the access token of a managed volume in volume context
+pub const ACCESS_TOKEN_KEY_CONTEXT: &str = "datenlord.io/accessToken";
 
 /// Static Tokio runtime for spawning `gRPC` tasks.
 static TOKIO_RUNTIME: Lazy<Runtime> = Lazy::new(|| {
@@ -69,6 +91,110 @@ pub enum BindMountMode {
     Remount,
 }
 
+/// Parse a size of the Kubernetes quantity format into bytes, like `1Gi` or
+/// `500M`
+pub fn parse_quantity(quantity: &str) -> DatenLordResult<i64> {
+    let invalid = || ArgumentInvalid {
+        context: vec![format!("size={quantity} is not a valid quantity")],
+    };
+    let digits = quantity
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(quantity.len());
+    let (number, suffix) = quantity.split_at(digits);
+    let multiplier: i64 = match suffix {
+        "" => 1,
+        "k" => 1_000,
+        "M" => 1_000_000,
+        "G" => 1_000_000_000,
+        "T" => 1_000_000_000_000,
+        "Ki" => 1_024,
+        "Mi" => 1_048_576,
+        "Gi" => 1_073_741_824,
+        "Ti" => 1_099_511_627_776,
+        _ => return Err(invalid()),
+    };
+    number
+        .parse::<i64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(invalid)
+}
+
+/// Parse the tuning parameters of a volume from `StorageClass` parameters,
+/// the absent ones are the defaults of the file system
+pub fn parse_volume_params(parameters: &HashMap<String, String>) -> DatenLordResult<VolumeParams> {
+    let invalid = |key: &str, value: &str| ArgumentInvalid {
+        context: vec![format!("parameter {key}={value} is invalid")],
+    };
+    let block_size = match parameters.get(BLOCK_SIZE_KEY_PARAMETER) {
+        Some(value) => Some(
+            u64::try_from(parse_quantity(value)?)
+                .map_err(|_| invalid(BLOCK_SIZE_KEY_PARAMETER, value))?,
+        ),
+        None => None,
+    };
+    let cache_size = match parameters.get(CACHE_SIZE_KEY_PARAMETER) {
+        Some(value) => Some(
+            value
+                .parse::<u64>()
+                .ok()
+                .and_then(|mib| mib.checked_mul(1_048_576))
+                .ok_or_else(|| invalid(CACHE_SIZE_KEY_PARAMETER, value))?,
+        ),
+        None => None,
+    };
+    let compression = match parameters.get(COMPRESSION_KEY_PARAMETER) {
+        Some(value) => Some(value.parse()?),
+        None => None,
+    };
+    let replicas = match parameters.get(REPLICAS_KEY_PARAMETER) {
+        Some(value) => Some(
+            value
+                .parse::<u32>()
+                .map_err(|_| invalid(REPLICAS_KEY_PARAMETER, value))?,
+        ),
+        None => None,
+    };
+    let params = VolumeParams {
+        block_size,
+        cache_size,
+        compression,
+        replicas,
+    };
+    params.validate()?;
+    Ok(params)
+}
+
+/// Build the usage of a volume in bytes and inodes from the `statvfs(3)` of
+/// its path
+pub fn build_volume_usage(stat: &Statvfs) -> Vec<VolumeUsage> {
+    let fragment_size: u64 = stat.fragment_size().cast();
+    let (blocks, blocks_free, blocks_available): (u64, u64, u64) = (
+        stat.blocks().cast(),
+        stat.blocks_free().cast(),
+        stat.blocks_available().cast(),
+    );
+    let mut bytes = VolumeUsage::new();
+    bytes.set_unit(VolumeUsage_Unit::BYTES);
+    bytes.set_total(blocks.overflow_mul(fragment_size).cast());
+    bytes.set_available(blocks_available.overflow_mul(fragment_size).cast());
+    bytes.set_used(
+        blocks
+            .saturating_sub(blocks_free)
+            .overflow_mul(fragment_size)
+            .cast(),
+    );
+
+    let (files, files_free): (u64, u64) = (stat.files().cast(), stat.files_free().cast());
+    let mut inodes = VolumeUsage::new();
+    inodes.set_unit(VolumeUsage_Unit::INODES);
+    inodes.set_total(files.cast());
+    inodes.set_available(files_free.cast());
+    inodes.set_used(files.saturating_sub(files_free).cast());
+
+    vec![bytes, inodes]
+}
+
 /// Convert `SystemTime` to proto timestamp
 pub fn generate_proto_timestamp(
     st: &std::time::SystemTime,
@@ -250,7 +376,13 @@ pub fn mount_volume_bind_path(
                 Some(OsStr::new(&mount_options))
             },
         )
-        .with_context(|| format!("failed to direct mount {from_path:?} to {target_path:?}"))
+        .with_context(|| format!("failed to direct mount {from_path:?} to {target_path:?}"))?;
+        match bind_mount_mode {
+            BindMountMode::Single | BindMountMode::Multiple => {
+                mount_ns::isolate_peer_group(target_path)
+            }
+            BindMountMode::Remount => Ok(()),
+        }
     } else {
         let mut mount_cmd = Command::new(get_bind_mount_helper_cmd());
         mount_cmd
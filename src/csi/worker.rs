@@ -114,8 +114,12 @@ impl Worker for WorkerImpl {
         let self_inner = Arc::<WorkerImplInner>::clone(&self.inner);
 
         let task = async move {
-            let vol_id = Uuid::new_v4();
-            let vol_id_str = vol_id.to_string();
+            // Volumes registered with the volume manager keep the ID it assigned
+            let vol_id = req
+                .get_parameters()
+                .get(util::VOLUME_ID_KEY_PARAMETER)
+                .cloned()
+                .unwrap_or_else(|| Uuid::new_v4().to_string());
             let vol_name = req.get_name();
             let vol_size = req.get_capacity_range().get_required_bytes();
             let nodes = if req.has_accessibility_requirements() {
@@ -134,10 +138,10 @@ impl Worker for WorkerImpl {
 
             let volume = DatenLordVolume::build_from_create_volume_req(
                 &req,
-                &vol_id_str,
+                &vol_id,
                 self_inner.meta_data.get_node_id(),
                 nodes.clone(),
-                &self_inner.meta_data.get_volume_path(&vol_id_str),
+                &self_inner.meta_data.get_volume_path(&vol_id),
             )
             .with_context(|| {
                 format!(
@@ -150,7 +154,7 @@ impl Worker for WorkerImpl {
 
             if let Some(ref content_source) = volume.content_source {
                 self_inner
-                    .build_volume_from_source(&vol_id_str, vol_name, vol_size, content_source)
+                    .build_volume_from_source(&vol_id, vol_name, vol_size, content_source)
                     .await?;
             }
 
@@ -171,7 +175,7 @@ impl Worker for WorkerImpl {
                 self_inner.meta_data.get_node_id(),
             );
 
-            let r = util::build_create_volume_response(&req, &vol_id.to_string(), nodes);
+            let r = util::build_create_volume_response(&req, &vol_id, nodes);
             Ok(r)
         };
         util::spawn_grpc_task(sink, task);
@@ -0,0 +1,85 @@
+//! The gateways re-exporting a volume over the network file protocols, for
+//! the clients that cannot run the FUSE daemon, such as Windows and
+//! appliances.
+//!
+//! A gateway opens the file system of the volume like a node mounting it, so
+//! the changes made through the gateway are coherent with the other nodes.
+//! NFSv3 serves a volume, and the S3 API serves the named volumes as the
+//! buckets.
+
+mod nfs;
+mod s3;
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests;
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::Server;
+use nfsserve::tcp::{NFSTcp, NFSTcpListener};
+use nix::errno::Errno;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+pub use self::nfs::NfsGateway;
+pub use self::s3::S3Gateway;
+use crate::common::error::DatenLordError;
+
+/// The errno of an error of the file system, if it's raised by one.
+pub(crate) fn errno_of(err: &DatenLordError) -> Option<Errno> {
+    if let DatenLordError::InternalErr { ref source, .. } = *err {
+        source.root_cause().downcast_ref::<nix::Error>().copied()
+    } else {
+        None
+    }
+}
+
+/// Serve `gateway` by NFSv3 on `addr` until `token` is cancelled.
+#[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+pub async fn run_gateway(addr: SocketAddr, gateway: NfsGateway, token: CancellationToken) {
+    let listener = match NFSTcpListener::bind(&addr.to_string(), gateway).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind the NFS gateway to {addr}: {e}");
+            return;
+        }
+    };
+    info!("NFS gateway listens on {addr}.");
+    tokio::select! {
+        () = token.cancelled() => {}
+        res = listener.handle_forever() => {
+            if let Err(e) = res {
+                error!("NFS gateway failed: {e}");
+            }
+        }
+    }
+    info!("NFS gateway exits.");
+}
+
+/// Serve `gateway` by the S3 API on `addr` until `token` is cancelled.
+pub async fn run_s3_gateway(addr: SocketAddr, gateway: S3Gateway, token: CancellationToken) {
+    let gateway = Arc::new(gateway);
+    let server = match Server::try_bind(&addr) {
+        Ok(builder) => builder.serve(make_service_fn(move |_| {
+            let gateway = Arc::clone(&gateway);
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let gateway = Arc::clone(&gateway);
+                    async move { Ok::<_, Infallible>(gateway.handle(req).await) }
+                }))
+            }
+        })),
+        Err(e) => {
+            error!("Failed to bind the S3 gateway to {addr}: {e}");
+            return;
+        }
+    };
+    info!("S3 gateway listens on {addr}.");
+    if let Err(e) = server.with_graceful_shutdown(token.cancelled_owned()).await {
+        error!("S3 gateway failed: {e}");
+    }
+    info!("S3 gateway exits.");
+}
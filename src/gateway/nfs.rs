@@ -0,0 +1,479 @@
+//! The NFSv3 gateway upon the file system of a volume.
+
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use clippy_utilities::{Cast, OverflowArithmetic};
+use nfsserve::nfs::{
+    fattr3, fileid3, filename3, ftype3, nfspath3, nfsstat3, nfstime3, sattr3, set_atime, set_gid3,
+    set_mode3, set_mtime, set_size3, set_uid3, specdata3,
+};
+use nfsserve::vfs::{DirEntry, NFSFileSystem, ReadDirResult, VFSCapabilities};
+use nix::errno::Errno;
+use nix::sys::stat::SFlag;
+use tracing::debug;
+
+use super::errno_of;
+use crate::async_fuse::fuse::protocol::setattr_flags::{
+    FATTR_ATIME, FATTR_GID, FATTR_MODE, FATTR_MTIME, FATTR_SIZE, FATTR_UID,
+};
+use crate::async_fuse::fuse::protocol::{FuseAttr, INum, FUSE_ROOT_ID};
+use crate::async_fuse::memfs::{
+    CreateParam, MemFs, RenameParam, S3MetaData, SetAttrParam, ROOT_CONTEXT,
+};
+use crate::common::error::DatenLordError;
+
+/// The mode of the files created without attributes.
+const DEFAULT_FILE_MODE: u32 = 0o644;
+/// The mode of the directories created.
+const DEFAULT_DIR_MODE: u32 = 0o755;
+/// The mode of the symbolic links created.
+const DEFAULT_LINK_MODE: u32 = 0o777;
+/// The bytes of a block counted by `FuseAttr::blocks`.
+const BLOCK_BYTES: u64 = 512;
+
+/// The NFSv3 file system serving a volume, a snapshot is served read-only.
+#[derive(Debug)]
+pub struct NfsGateway {
+    /// The file system of the volume
+    fs: Arc<MemFs<S3MetaData>>,
+    /// Whether the volume is served read-only
+    read_only: bool,
+}
+
+impl NfsGateway {
+    /// Serve `fs`, read-only if `read_only` is set.
+    #[must_use]
+    pub fn new(fs: Arc<MemFs<S3MetaData>>, read_only: bool) -> Self {
+        Self { fs, read_only }
+    }
+
+    /// Fail with `NFS3ERR_ROFS` if the volume is served read-only.
+    fn check_writable(&self) -> Result<(), nfsstat3> {
+        if self.read_only {
+            Err(nfsstat3::NFS3ERR_ROFS)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Create a node.
+    async fn create_node(&self, param: CreateParam) -> Result<(fileid3, fattr3), nfsstat3> {
+        self.check_writable()?;
+        let attr = self
+            .fs
+            .create_stateless(param)
+            .await
+            .map_err(to_nfs_error)?;
+        Ok((attr.ino, to_fattr(&attr)))
+    }
+}
+
+/// Convert a file name of NFS.
+pub(super) fn to_name(name: &[u8]) -> Result<&str, nfsstat3> {
+    std::str::from_utf8(name).map_err(|_| nfsstat3::NFS3ERR_INVAL)
+}
+
+/// Convert an error of the file system to the status of NFS by its errno.
+pub(super) fn to_nfs_error(err: DatenLordError) -> nfsstat3 {
+    match errno_of(&err) {
+        Some(Errno::EPERM) => nfsstat3::NFS3ERR_PERM,
+        Some(Errno::ENOENT) => nfsstat3::NFS3ERR_NOENT,
+        Some(Errno::EACCES) => nfsstat3::NFS3ERR_ACCES,
+        Some(Errno::EEXIST) => nfsstat3::NFS3ERR_EXIST,
+        Some(Errno::ENOTDIR) => nfsstat3::NFS3ERR_NOTDIR,
+        Some(Errno::EISDIR) => nfsstat3::NFS3ERR_ISDIR,
+        Some(Errno::EINVAL) => nfsstat3::NFS3ERR_INVAL,
+        Some(Errno::EFBIG) => nfsstat3::NFS3ERR_FBIG,
+        Some(Errno::ENOSPC) => nfsstat3::NFS3ERR_NOSPC,
+        Some(Errno::EROFS) => nfsstat3::NFS3ERR_ROFS,
+        Some(Errno::ENAMETOOLONG) => nfsstat3::NFS3ERR_NAMETOOLONG,
+        Some(Errno::ENOTEMPTY) => nfsstat3::NFS3ERR_NOTEMPTY,
+        Some(Errno::EDQUOT) => nfsstat3::NFS3ERR_DQUOT,
+        Some(Errno::ENOTSUP) => nfsstat3::NFS3ERR_NOTSUPP,
+        Some(_) | None => {
+            debug!("the error is returned as NFS3ERR_IO: {err}");
+            nfsstat3::NFS3ERR_IO
+        }
+    }
+}
+
+/// Convert a time of NFS.
+fn to_system_time(time: &nfstime3) -> SystemTime {
+    UNIX_EPOCH + Duration::new(time.seconds.into(), time.nseconds)
+}
+
+/// Convert the attributes of a node to the ones of NFS.
+pub(super) fn to_fattr(attr: &FuseAttr) -> fattr3 {
+    let ftype = match SFlag::from_bits_truncate(attr.mode & SFlag::S_IFMT.bits()) {
+        SFlag::S_IFDIR => ftype3::NF3DIR,
+        SFlag::S_IFLNK => ftype3::NF3LNK,
+        SFlag::S_IFBLK => ftype3::NF3BLK,
+        SFlag::S_IFCHR => ftype3::NF3CHR,
+        SFlag::S_IFIFO => ftype3::NF3FIFO,
+        SFlag::S_IFSOCK => ftype3::NF3SOCK,
+        _ => ftype3::NF3REG,
+    };
+    fattr3 {
+        ftype,
+        mode: attr.mode & 0o7777,
+        nlink: attr.nlink,
+        uid: attr.uid,
+        gid: attr.gid,
+        size: attr.size,
+        used: attr.blocks.overflow_mul(BLOCK_BYTES),
+        rdev: specdata3 {
+            specdata1: 0,
+            specdata2: 0,
+        },
+        fsid: 0,
+        fileid: attr.ino,
+        atime: nfstime3 {
+            seconds: attr.atime.cast(),
+            nseconds: attr.atimensec,
+        },
+        mtime: nfstime3 {
+            seconds: attr.mtime.cast(),
+            nseconds: attr.mtimensec,
+        },
+        ctime: nfstime3 {
+            seconds: attr.ctime.cast(),
+            nseconds: attr.ctimensec,
+        },
+    }
+}
+
+/// Convert the attributes to set of NFS.
+pub(super) fn to_setattr_param(attr: &sattr3) -> SetAttrParam {
+    let now = SystemTime::now();
+    let mut valid = 0;
+    let mode = match attr.mode {
+        set_mode3::mode(mode) => {
+            valid |= FATTR_MODE;
+            Some(mode)
+        }
+        set_mode3::Void => None,
+    };
+    let u_id = match attr.uid {
+        set_uid3::uid(uid) => {
+            valid |= FATTR_UID;
+            Some(uid)
+        }
+        set_uid3::Void => None,
+    };
+    let g_id = match attr.gid {
+        set_gid3::gid(gid) => {
+            valid |= FATTR_GID;
+            Some(gid)
+        }
+        set_gid3::Void => None,
+    };
+    let size = match attr.size {
+        set_size3::size(size) => {
+            valid |= FATTR_SIZE;
+            Some(size)
+        }
+        set_size3::Void => None,
+    };
+    let a_time = match attr.atime {
+        set_atime::SET_TO_CLIENT_TIME(ref time) => Some(to_system_time(time)),
+        set_atime::SET_TO_SERVER_TIME => Some(now),
+        set_atime::DONT_CHANGE => None,
+    };
+    if a_time.is_some() {
+        valid |= FATTR_ATIME;
+    }
+    let m_time = match attr.mtime {
+        set_mtime::SET_TO_CLIENT_TIME(ref time) => Some(to_system_time(time)),
+        set_mtime::SET_TO_SERVER_TIME => Some(now),
+        set_mtime::DONT_CHANGE => None,
+    };
+    if m_time.is_some() {
+        valid |= FATTR_MTIME;
+    }
+    SetAttrParam {
+        valid,
+        fh: None,
+        mode,
+        u_id,
+        g_id,
+        size,
+        #[cfg(feature = "abi-7-9")]
+        lock_owner: None,
+        a_time,
+        m_time,
+        #[cfg(feature = "abi-7-23")]
+        c_time: None,
+    }
+}
+
+/// The entries of a directory after the entry `start_after`, `0` for the
+/// first entry, and whether the last entry is included.
+pub(super) fn page_entries<T>(
+    entries: Vec<T>,
+    ino_of: impl Fn(&T) -> INum,
+    start_after: INum,
+    max_entries: usize,
+) -> Result<(Vec<T>, bool), nfsstat3> {
+    let start = if start_after == 0 {
+        0
+    } else {
+        entries
+            .iter()
+            .position(|entry| ino_of(entry) == start_after)
+            .ok_or(nfsstat3::NFS3ERR_BAD_COOKIE)?
+            .overflow_add(1)
+    };
+    let total = entries.len();
+    let page: Vec<T> = entries.into_iter().skip(start).take(max_entries).collect();
+    let end = start.overflow_add(page.len()) >= total;
+    Ok((page, end))
+}
+
+#[async_trait]
+impl NFSFileSystem for NfsGateway {
+    fn capabilities(&self) -> VFSCapabilities {
+        if self.read_only {
+            VFSCapabilities::ReadOnly
+        } else {
+            VFSCapabilities::ReadWrite
+        }
+    }
+
+    fn root_dir(&self) -> fileid3 {
+        FUSE_ROOT_ID
+    }
+
+    async fn lookup(&self, dirid: fileid3, filename: &filename3) -> Result<fileid3, nfsstat3> {
+        match to_name(filename)? {
+            "." => Ok(dirid),
+            ".." => self.fs.get_parent(dirid).await.map_err(to_nfs_error),
+            name => {
+                let (_, attr, _) = self
+                    .fs
+                    .lookup_entry(ROOT_CONTEXT, dirid, name)
+                    .await
+                    .map_err(to_nfs_error)?;
+                Ok(attr.ino)
+            }
+        }
+    }
+
+    async fn getattr(&self, id: fileid3) -> Result<fattr3, nfsstat3> {
+        let (_, attr) = self.fs.get_attr(id).await.map_err(to_nfs_error)?;
+        Ok(to_fattr(&attr))
+    }
+
+    async fn setattr(&self, id: fileid3, setattr: sattr3) -> Result<fattr3, nfsstat3> {
+        self.check_writable()?;
+        let (_, attr) = self
+            .fs
+            .set_attr(ROOT_CONTEXT, id, &to_setattr_param(&setattr))
+            .await
+            .map_err(to_nfs_error)?;
+        Ok(to_fattr(&attr))
+    }
+
+    async fn read(
+        &self,
+        id: fileid3,
+        offset: u64,
+        count: u32,
+    ) -> Result<(Vec<u8>, bool), nfsstat3> {
+        self.fs
+            .read_file(ROOT_CONTEXT, id, offset, count.into())
+            .await
+            .map_err(to_nfs_error)
+    }
+
+    async fn write(&self, id: fileid3, offset: u64, data: &[u8]) -> Result<fattr3, nfsstat3> {
+        self.check_writable()?;
+        let attr = self
+            .fs
+            .write_file(ROOT_CONTEXT, id, offset, data)
+            .await
+            .map_err(to_nfs_error)?;
+        Ok(to_fattr(&attr))
+    }
+
+    async fn create(
+        &self,
+        dirid: fileid3,
+        filename: &filename3,
+        attr: sattr3,
+    ) -> Result<(fileid3, fattr3), nfsstat3> {
+        let mode = match attr.mode {
+            set_mode3::mode(mode) => mode,
+            set_mode3::Void => DEFAULT_FILE_MODE,
+        };
+        self.check_writable()?;
+        // The mode is set on creation, and the others but the size are set in
+        // the same transaction, so the file is never seen with the default
+        // owner. The size is set afterwards, as it truncates the file.
+        let size = sattr3 {
+            mode: set_mode3::Void,
+            uid: set_uid3::Void,
+            gid: set_gid3::Void,
+            size: attr.size,
+            atime: set_atime::DONT_CHANGE,
+            mtime: set_mtime::DONT_CHANGE,
+        };
+        let attr = sattr3 {
+            mode: set_mode3::Void,
+            size: set_size3::Void,
+            ..attr
+        };
+        let created = self
+            .fs
+            .create_stateless_with_attr(
+                CreateParam {
+                    parent: dirid,
+                    name: to_name(filename)?.to_owned(),
+                    mode,
+                    rdev: 0,
+                    uid: 0,
+                    gid: 0,
+                    umask: 0,
+                    node_type: SFlag::S_IFREG,
+                    link: None,
+                },
+                to_setattr_param(&attr),
+            )
+            .await
+            .map_err(to_nfs_error)?;
+        let id = created.ino;
+        let attr = match size.size {
+            set_size3::size(_) => self.setattr(id, size).await?,
+            set_size3::Void => to_fattr(&created),
+        };
+        Ok((id, attr))
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: fileid3,
+        filename: &filename3,
+    ) -> Result<fileid3, nfsstat3> {
+        let (id, _) = self
+            .create_node(CreateParam {
+                parent: dirid,
+                name: to_name(filename)?.to_owned(),
+                mode: DEFAULT_FILE_MODE,
+                rdev: 0,
+                uid: 0,
+                gid: 0,
+                umask: 0,
+                node_type: SFlag::S_IFREG,
+                link: None,
+            })
+            .await?;
+        Ok(id)
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: fileid3,
+        dirname: &filename3,
+    ) -> Result<(fileid3, fattr3), nfsstat3> {
+        self.create_node(CreateParam {
+            parent: dirid,
+            name: to_name(dirname)?.to_owned(),
+            mode: DEFAULT_DIR_MODE,
+            rdev: 0,
+            uid: 0,
+            gid: 0,
+            umask: 0,
+            node_type: SFlag::S_IFDIR,
+            link: None,
+        })
+        .await
+    }
+
+    async fn remove(&self, dirid: fileid3, filename: &filename3) -> Result<(), nfsstat3> {
+        self.check_writable()?;
+        self.fs
+            .remove_stateless(dirid, to_name(filename)?)
+            .await
+            .map_err(to_nfs_error)
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: fileid3,
+        from_filename: &filename3,
+        to_dirid: fileid3,
+        to_filename: &filename3,
+    ) -> Result<(), nfsstat3> {
+        self.check_writable()?;
+        self.fs
+            .rename_entry(
+                ROOT_CONTEXT,
+                RenameParam {
+                    old_parent: from_dirid,
+                    old_name: to_name(from_filename)?.to_owned(),
+                    new_parent: to_dirid,
+                    new_name: to_name(to_filename)?.to_owned(),
+                    flags: 0,
+                },
+            )
+            .await
+            .map_err(to_nfs_error)
+    }
+
+    async fn readdir(
+        &self,
+        dirid: fileid3,
+        start_after: fileid3,
+        max_entries: usize,
+    ) -> Result<ReadDirResult, nfsstat3> {
+        let entries = self
+            .fs
+            .list_dir(ROOT_CONTEXT, dirid)
+            .await
+            .map_err(to_nfs_error)?;
+        let (page, end) = page_entries(entries, |entry| entry.ino(), start_after, max_entries)?;
+        let mut result = ReadDirResult {
+            entries: Vec::with_capacity(page.len()),
+            end,
+        };
+        for entry in page {
+            let (_, attr) = self.fs.get_attr(entry.ino()).await.map_err(to_nfs_error)?;
+            result.entries.push(DirEntry {
+                fileid: entry.ino(),
+                name: entry.name().as_bytes().to_vec().into(),
+                attr: to_fattr(&attr),
+            });
+        }
+        Ok(result)
+    }
+
+    async fn symlink(
+        &self,
+        dirid: fileid3,
+        linkname: &filename3,
+        symlink: &nfspath3,
+        _attr: &sattr3,
+    ) -> Result<(fileid3, fattr3), nfsstat3> {
+        self.create_node(CreateParam {
+            parent: dirid,
+            name: to_name(linkname)?.to_owned(),
+            mode: DEFAULT_LINK_MODE,
+            rdev: 0,
+            uid: 0,
+            gid: 0,
+            umask: 0,
+            node_type: SFlag::S_IFLNK,
+            link: Some(PathBuf::from(OsStr::from_bytes(symlink))),
+        })
+        .await
+    }
+
+    async fn readlink(&self, id: fileid3) -> Result<nfspath3, nfsstat3> {
+        let target = self.fs.read_link(id).await.map_err(to_nfs_error)?;
+        Ok(target.into())
+    }
+}
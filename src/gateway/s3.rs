@@ -0,0 +1,1074 @@
+//! The S3 gateway upon the file system of the named volumes.
+//!
+//! A bucket is a named volume, and the key of an object is its path in the
+//! directory of the volume, so the objects are shared with the POSIX clients
+//! of the volume. The directories on the path of a key are created when the
+//! object is put, and they're listed as the common prefixes. The parts of a
+//! multipart upload are kept in the hidden directory `.s3-multipart` of the
+//! volume until the upload is completed or aborted.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use clippy_utilities::{Cast, OverflowArithmetic};
+use futures::stream;
+use hyper::body::HttpBody;
+use hyper::header::{
+    HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, LAST_MODIFIED,
+    RANGE,
+};
+use hyper::{Body, HeaderMap, Method, Request, Response, StatusCode};
+use nix::errno::Errno;
+use nix::sys::stat::SFlag;
+use serde::Deserialize;
+use tracing::debug;
+use uuid::Uuid;
+
+use super::errno_of;
+use crate::async_fuse::fuse::protocol::setattr_flags::FATTR_SIZE;
+use crate::async_fuse::fuse::protocol::{FuseAttr, INum};
+use crate::async_fuse::memfs::direntry::FileType;
+use crate::async_fuse::memfs::volume::VolumeManager;
+use crate::async_fuse::memfs::{CreateParam, MemFs, S3MetaData, SetAttrParam, ROOT_CONTEXT};
+use crate::common::error::{DatenLordError, DatenLordResult};
+
+/// The hidden directory of the parts of the multipart uploads in a volume.
+const MULTIPART_DIR: &str = ".s3-multipart";
+/// The mode of the files created.
+const FILE_MODE: u32 = 0o644;
+/// The mode of the directories created.
+const DIR_MODE: u32 = 0o755;
+/// The bytes written or read at a time.
+const IO_CHUNK_SIZE: usize = 0x40_0000;
+/// The maximum number of keys listed at a time.
+const MAX_LIST_KEYS: usize = 1000;
+/// The prefix of `x-amz-content-sha256` of the bodies in `aws-chunked`.
+const STREAMING_PAYLOAD: &str = "STREAMING-";
+
+/// An error replied to the S3 clients.
+#[derive(Debug)]
+pub(super) struct S3Error {
+    /// The status of the response
+    status: StatusCode,
+    /// The S3 error code
+    code: &'static str,
+    /// The message of the error
+    message: String,
+}
+
+impl S3Error {
+    /// Create an error.
+    fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// The S3 error code.
+    pub(super) fn code(&self) -> &'static str {
+        self.code
+    }
+
+    /// An error of an invalid request.
+    fn invalid(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "InvalidArgument", message)
+    }
+
+    /// An error of an operation not supported by the gateway.
+    fn not_implemented() -> Self {
+        Self::new(
+            StatusCode::NOT_IMPLEMENTED,
+            "NotImplemented",
+            "the operation is not supported by the gateway",
+        )
+    }
+
+    /// An error of a key not found.
+    fn no_such_key() -> Self {
+        Self::new(
+            StatusCode::NOT_FOUND,
+            "NoSuchKey",
+            "the specified key does not exist",
+        )
+    }
+
+    /// The response of the error on `resource`.
+    fn into_response(self, resource: &str) -> Response<Body> {
+        let body = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <Error><Code>{}</Code><Message>{}</Message><Resource>{}</Resource></Error>",
+            self.code,
+            xml_escape(&self.message),
+            xml_escape(resource),
+        );
+        xml_response(self.status, body)
+    }
+}
+
+impl From<DatenLordError> for S3Error {
+    fn from(err: DatenLordError) -> Self {
+        let (status, code) = match errno_of(&err) {
+            Some(Errno::ENOENT) => (StatusCode::NOT_FOUND, "NoSuchKey"),
+            Some(Errno::EACCES | Errno::EPERM | Errno::EROFS) => {
+                (StatusCode::FORBIDDEN, "AccessDenied")
+            }
+            Some(Errno::EISDIR | Errno::ENOTDIR | Errno::EEXIST | Errno::ENOTEMPTY) => {
+                (StatusCode::CONFLICT, "OperationAborted")
+            }
+            Some(Errno::ENAMETOOLONG) => (StatusCode::BAD_REQUEST, "KeyTooLongError"),
+            Some(Errno::EDQUOT | Errno::ENOSPC | Errno::EFBIG) => {
+                (StatusCode::BAD_REQUEST, "EntityTooLarge")
+            }
+            Some(_) | None => {
+                if let DatenLordError::VolumeNotFound { .. } = err {
+                    (StatusCode::NOT_FOUND, "NoSuchBucket")
+                } else {
+                    (StatusCode::INTERNAL_SERVER_ERROR, "InternalError")
+                }
+            }
+        };
+        Self::new(status, code, err.to_string())
+    }
+}
+
+/// Escape `text` in XML.
+pub(super) fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Decode the percent-encoded `text` of a URI.
+pub(super) fn percent_decode(text: &str) -> Result<String, S3Error> {
+    let invalid = || S3Error::invalid(format!("{text} is not percent-encoded"));
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail.get(..2).ok_or_else(invalid)?;
+            let hex = std::str::from_utf8(hex).map_err(|_| invalid())?;
+            bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            rest = tail.get(2..).unwrap_or_default();
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+/// Parse the query of a URI.
+pub(super) fn parse_query(query: &str) -> Result<HashMap<String, String>, S3Error> {
+    let mut params = HashMap::new();
+    for param in query.split('&').filter(|param| !param.is_empty()) {
+        let (name, value) = param.split_once('=').unwrap_or((param, ""));
+        params.insert(percent_decode(name)?, percent_decode(value)?);
+    }
+    Ok(params)
+}
+
+/// Split `key` into the names on its path, and whether it's a directory,
+/// which ends with `/`.
+pub(super) fn split_key(key: &str) -> Result<(Vec<&str>, bool), S3Error> {
+    let (path, is_dir) = match key.strip_suffix('/') {
+        Some(path) => (path, true),
+        None => (key, false),
+    };
+    let names: Vec<&str> = path.split('/').collect();
+    if names
+        .iter()
+        .any(|&name| name.is_empty() || name == "." || name == "..")
+    {
+        return Err(S3Error::new(
+            StatusCode::BAD_REQUEST,
+            "InvalidObjectName",
+            format!("{key} is not a path in the volume"),
+        ));
+    }
+    if names.first() == Some(&MULTIPART_DIR) {
+        return Err(S3Error::new(
+            StatusCode::FORBIDDEN,
+            "AccessDenied",
+            format!("{MULTIPART_DIR} is reserved by the gateway"),
+        ));
+    }
+    Ok((names, is_dir))
+}
+
+/// Parse the header `Range` of a file of `size`, the range returned ends
+/// exclusively.
+pub(super) fn parse_range(range: &str, size: u64) -> Result<(u64, u64), S3Error> {
+    let invalid = || {
+        S3Error::new(
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            "InvalidRange",
+            format!("{range} is not satisfiable"),
+        )
+    };
+    let (first, last) = range
+        .strip_prefix("bytes=")
+        .and_then(|range| range.split_once('-'))
+        .ok_or_else(invalid)?;
+    let (start, end) = if first.is_empty() {
+        // The last bytes of the file
+        let len: u64 = last.parse().map_err(|_| invalid())?;
+        (size.saturating_sub(len), size)
+    } else {
+        let start: u64 = first.parse().map_err(|_| invalid())?;
+        let end = if last.is_empty() {
+            size
+        } else {
+            let last: u64 = last.parse().map_err(|_| invalid())?;
+            last.overflow_add(1).min(size)
+        };
+        (start, end)
+    };
+    if start >= end {
+        return Err(invalid());
+    }
+    Ok((start, end))
+}
+
+/// The state of decoding a body in `aws-chunked`.
+#[derive(Debug, Clone, Copy, Default)]
+enum ChunkState {
+    /// Reading the line of the size and the signature of a chunk
+    #[default]
+    Header,
+    /// Reading the data of a chunk
+    Data,
+    /// Skipping the line end after the data of a chunk
+    DataEnd,
+    /// The last chunk is read
+    Done,
+}
+
+/// The decoder of the bodies in `aws-chunked`, the chunk signatures are not
+/// verified.
+#[derive(Debug, Default)]
+pub(super) struct ChunkDecoder {
+    /// The partial line of the header of a chunk
+    line: Vec<u8>,
+    /// The bytes left in the current state
+    remaining: usize,
+    /// The current state
+    state: ChunkState,
+}
+
+impl ChunkDecoder {
+    /// Decode `input`, the data decoded is appended to `out`.
+    pub(super) fn feed(&mut self, mut input: &[u8], out: &mut Vec<u8>) -> Result<(), S3Error> {
+        while !input.is_empty() {
+            match self.state {
+                ChunkState::Header => {
+                    let Some(pos) = input.iter().position(|&byte| byte == b'\n') else {
+                        self.line.extend_from_slice(input);
+                        return Ok(());
+                    };
+                    let (line, rest) = input.split_at(pos);
+                    self.line.extend_from_slice(line);
+                    input = rest.get(1..).unwrap_or_default();
+                    let line = std::mem::take(&mut self.line);
+                    let line = String::from_utf8_lossy(&line);
+                    let size = line.split(';').next().unwrap_or_default().trim();
+                    self.remaining = usize::from_str_radix(size, 16).map_err(|_| {
+                        S3Error::new(
+                            StatusCode::BAD_REQUEST,
+                            "IncompleteBody",
+                            "the chunk size is invalid",
+                        )
+                    })?;
+                    self.state = if self.remaining == 0 {
+                        ChunkState::Done
+                    } else {
+                        ChunkState::Data
+                    };
+                }
+                ChunkState::Data | ChunkState::DataEnd => {
+                    let (data, rest) = input.split_at(self.remaining.min(input.len()));
+                    if let ChunkState::Data = self.state {
+                        out.extend_from_slice(data);
+                    }
+                    input = rest;
+                    self.remaining = self.remaining.overflow_sub(data.len());
+                    if self.remaining == 0 {
+                        (self.state, self.remaining) = match self.state {
+                            ChunkState::Data => (ChunkState::DataEnd, 2),
+                            ChunkState::Header | ChunkState::DataEnd | ChunkState::Done => {
+                                (ChunkState::Header, 0)
+                            }
+                        };
+                    }
+                }
+                ChunkState::Done => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the body ends with the last chunk.
+    pub(super) fn finish(&self) -> Result<(), S3Error> {
+        match self.state {
+            ChunkState::Done => Ok(()),
+            ChunkState::Header | ChunkState::Data | ChunkState::DataEnd => Err(S3Error::new(
+                StatusCode::BAD_REQUEST,
+                "IncompleteBody",
+                "the body ends before the last chunk",
+            )),
+        }
+    }
+}
+
+/// The request of completing a multipart upload.
+#[derive(Debug, Deserialize)]
+struct CompleteMultipartUpload {
+    /// The parts of the object
+    #[serde(rename = "Part", default)]
+    parts: Vec<CompletedPart>,
+}
+
+/// A part of the object of a multipart upload.
+#[derive(Debug, Deserialize)]
+struct CompletedPart {
+    /// The number of the part
+    #[serde(rename = "PartNumber")]
+    part_number: u32,
+}
+
+/// Parse the numbers of the parts to complete a multipart upload, which are
+/// in ascending order.
+pub(super) fn parse_completed_parts(body: &str) -> Result<Vec<u32>, S3Error> {
+    let request: CompleteMultipartUpload = serde_xml_rs::from_str(body)
+        .map_err(|e| S3Error::new(StatusCode::BAD_REQUEST, "MalformedXML", e.to_string()))?;
+    let parts: Vec<u32> = request.parts.iter().map(|part| part.part_number).collect();
+    if parts.is_empty() || parts.windows(2).any(|pair| pair.first() >= pair.get(1)) {
+        return Err(S3Error::new(
+            StatusCode::BAD_REQUEST,
+            "InvalidPartOrder",
+            "the parts are not in ascending order",
+        ));
+    }
+    Ok(parts)
+}
+
+/// The time of seconds and nanoseconds.
+fn system_time(secs: u64, nsecs: u32) -> chrono::DateTime<chrono::Utc> {
+    (UNIX_EPOCH + Duration::new(secs, nsecs)).into()
+}
+
+/// The entity tag of a file, which changes with its content.
+fn etag(attr: &FuseAttr) -> String {
+    format!(
+        "\"{:x}-{:x}{:08x}-{:x}\"",
+        attr.ino, attr.mtime, attr.mtimensec, attr.size
+    )
+}
+
+/// Whether `attr` is of a directory.
+fn is_dir(attr: &FuseAttr) -> bool {
+    attr.mode & SFlag::S_IFMT.bits() == SFlag::S_IFDIR.bits()
+}
+
+/// A response of `status` with `headers`.
+fn response(status: StatusCode, headers: Vec<(HeaderName, String)>, body: Body) -> Response<Body> {
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    for (name, value) in headers {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response.headers_mut().insert(name, value);
+        }
+    }
+    response
+}
+
+/// A response of the XML `body`.
+fn xml_response(status: StatusCode, body: String) -> Response<Body> {
+    response(
+        status,
+        vec![(CONTENT_TYPE, "application/xml".to_owned())],
+        Body::from(body),
+    )
+}
+
+/// An object or a common prefix listed.
+#[derive(Debug)]
+struct Listed {
+    /// The key of the object, or the common prefix
+    key: String,
+    /// The attributes of the object, `None` for a common prefix
+    attr: Option<FuseAttr>,
+}
+
+/// The S3 gateway serving the named volumes, the volumes of a snapshot are
+/// served read-only.
+#[derive(Debug)]
+pub struct S3Gateway {
+    /// The file system of the volumes
+    fs: Arc<MemFs<S3MetaData>>,
+    /// The manager of the named volumes, which are the buckets
+    volumes: VolumeManager,
+    /// Whether the volumes are served read-only
+    read_only: bool,
+}
+
+impl S3Gateway {
+    /// Serve the volumes of `volumes` in `fs`, read-only if `read_only` is
+    /// set.
+    #[must_use]
+    pub fn new(fs: Arc<MemFs<S3MetaData>>, volumes: VolumeManager, read_only: bool) -> Self {
+        Self {
+            fs,
+            volumes,
+            read_only,
+        }
+    }
+
+    /// Handle a request.
+    pub(super) async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        let resource = req.uri().path().to_owned();
+        match self.route(req).await {
+            Ok(response) => response,
+            Err(e) => {
+                debug!("S3 request on {resource} failed: {e:?}");
+                e.into_response(&resource)
+            }
+        }
+    }
+
+    /// Route a request to its operation.
+    async fn route(&self, req: Request<Body>) -> Result<Response<Body>, S3Error> {
+        let path = percent_decode(req.uri().path())?;
+        let query = parse_query(req.uri().query().unwrap_or_default())?;
+        let path = path.strip_prefix('/').unwrap_or(&path);
+        let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+        let method = req.method().clone();
+        if bucket.is_empty() {
+            return match method {
+                Method::GET => self.list_buckets().await,
+                _ => Err(S3Error::not_implemented()),
+            };
+        }
+        let root = self.volumes.get(bucket).await?.ino;
+        if key.is_empty() {
+            return match method {
+                Method::GET if query.contains_key("uploads") => Err(S3Error::not_implemented()),
+                Method::GET => self.list_objects(bucket, root, &query).await,
+                Method::HEAD => Ok(response(StatusCode::OK, vec![], Body::empty())),
+                _ => Err(S3Error::not_implemented()),
+            };
+        }
+
+        let (names, is_dir_key) = split_key(key)?;
+        let upload_id = query.get("uploadId").map(String::as_str);
+        if method != Method::GET && method != Method::HEAD && self.read_only {
+            return Err(S3Error::new(
+                StatusCode::FORBIDDEN,
+                "AccessDenied",
+                "the volume is served read-only",
+            ));
+        }
+        match (method, upload_id) {
+            (Method::GET, None) => self.get_object(root, &names, req.headers(), true).await,
+            (Method::HEAD, None) => self.get_object(root, &names, req.headers(), false).await,
+            (Method::PUT, None) if is_dir_key => {
+                let _: INum = self.make_dirs(root, &names).await?;
+                Ok(response(StatusCode::OK, vec![], Body::empty()))
+            }
+            (Method::PUT, None) => self.put_object(root, &names, req).await,
+            (Method::PUT, Some(upload_id)) => {
+                let part: u32 = query
+                    .get("partNumber")
+                    .and_then(|part| part.parse().ok())
+                    .ok_or_else(|| S3Error::invalid("the part number is invalid"))?;
+                self.upload_part(root, upload_id, part, req).await
+            }
+            (Method::DELETE, None) => {
+                self.delete_object(root, &names).await?;
+                Ok(response(StatusCode::NO_CONTENT, vec![], Body::empty()))
+            }
+            (Method::DELETE, Some(upload_id)) => {
+                self.abort_upload(root, upload_id).await?;
+                Ok(response(StatusCode::NO_CONTENT, vec![], Body::empty()))
+            }
+            (Method::POST, None) if query.contains_key("uploads") => {
+                self.initiate_upload(bucket, key, root).await
+            }
+            (Method::POST, Some(upload_id)) => {
+                let upload_id = upload_id.to_owned();
+                self.complete_upload(bucket, key, root, &names, &upload_id, req)
+                    .await
+            }
+            _ => Err(S3Error::not_implemented()),
+        }
+    }
+
+    /// List the volumes as the buckets.
+    async fn list_buckets(&self) -> Result<Response<Body>, S3Error> {
+        let mut body = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <ListAllMyBucketsResult><Owner><ID>datenlord</ID></Owner><Buckets>",
+        );
+        for spec in self.volumes.list().await? {
+            let created = chrono::DateTime::<chrono::Utc>::from(spec.created);
+            let _ = write!(
+                body,
+                "<Bucket><Name>{}</Name><CreationDate>{}</CreationDate></Bucket>",
+                xml_escape(&spec.name),
+                created.format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+            );
+        }
+        body.push_str("</Buckets></ListAllMyBucketsResult>");
+        Ok(xml_response(StatusCode::OK, body))
+    }
+
+    /// Look up the node of `names` under directory `dir`.
+    async fn walk(&self, dir: INum, names: &[&str]) -> DatenLordResult<FuseAttr> {
+        let (_, mut attr) = self.fs.get_attr(dir).await?;
+        for name in names {
+            (_, attr, _) = self.fs.lookup_entry(ROOT_CONTEXT, attr.ino, name).await?;
+        }
+        Ok(attr)
+    }
+
+    /// Create the directories of `names` under directory `dir` if they don't
+    /// exist, returns the i-number of the last one.
+    async fn make_dirs(&self, dir: INum, names: &[&str]) -> Result<INum, S3Error> {
+        let mut dir = dir;
+        for &name in names {
+            dir = match self.fs.lookup_entry(ROOT_CONTEXT, dir, name).await {
+                Ok((_, attr, _)) if is_dir(&attr) => attr.ino,
+                Ok(_) => {
+                    return Err(S3Error::new(
+                        StatusCode::CONFLICT,
+                        "OperationAborted",
+                        format!("{name} is an object, not a directory"),
+                    ))
+                }
+                Err(e) if errno_of(&e) == Some(Errno::ENOENT) => {
+                    match self
+                        .fs
+                        .create_stateless(create_param(dir, name, SFlag::S_IFDIR))
+                        .await
+                    {
+                        Ok(attr) => attr.ino,
+                        // Created by another client meanwhile
+                        Err(e) if errno_of(&e) == Some(Errno::EEXIST) => {
+                            let (_, attr, _) =
+                                self.fs.lookup_entry(ROOT_CONTEXT, dir, name).await?;
+                            attr.ino
+                        }
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            };
+        }
+        Ok(dir)
+    }
+
+    /// Open the file of `names` under directory `dir` to be overwritten,
+    /// which is created with the directories on its path if it doesn't exist.
+    async fn create_file(&self, dir: INum, names: &[&str]) -> Result<INum, S3Error> {
+        let (name, parents) = names.split_last().ok_or_else(S3Error::no_such_key)?;
+        let dir = self.make_dirs(dir, parents).await?;
+        match self.fs.lookup_entry(ROOT_CONTEXT, dir, name).await {
+            Ok((_, attr, _)) if is_dir(&attr) => Err(S3Error::new(
+                StatusCode::CONFLICT,
+                "OperationAborted",
+                format!("{name} is a directory"),
+            )),
+            Ok((_, attr, _)) => {
+                self.fs
+                    .set_attr(ROOT_CONTEXT, attr.ino, &truncate_param())
+                    .await?;
+                Ok(attr.ino)
+            }
+            Err(e) if errno_of(&e) == Some(Errno::ENOENT) => {
+                let attr = self
+                    .fs
+                    .create_stateless(create_param(dir, name, SFlag::S_IFREG))
+                    .await?;
+                Ok(attr.ino)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write the body of `req` to file `ino` from `offset`, returns the bytes
+    /// written.
+    async fn write_body(&self, ino: INum, offset: u64, req: Request<Body>) -> Result<u64, S3Error> {
+        let mut decoder = req
+            .headers()
+            .get("x-amz-content-sha256")
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| value.starts_with(STREAMING_PAYLOAD))
+            .map(|_| ChunkDecoder::default());
+        let mut body = req.into_body();
+        let mut buf = Vec::with_capacity(IO_CHUNK_SIZE);
+        let mut written = 0_u64;
+        while let Some(data) = body.data().await {
+            let data = data.map_err(|e| {
+                S3Error::new(StatusCode::BAD_REQUEST, "IncompleteBody", e.to_string())
+            })?;
+            match decoder {
+                Some(ref mut decoder) => decoder.feed(&data, &mut buf)?,
+                None => buf.extend_from_slice(&data),
+            }
+            if buf.len() >= IO_CHUNK_SIZE {
+                self.fs
+                    .write_file(ROOT_CONTEXT, ino, offset.overflow_add(written), &buf)
+                    .await?;
+                written = written.overflow_add(buf.len().cast());
+                buf.clear();
+            }
+        }
+        if let Some(ref decoder) = decoder {
+            decoder.finish()?;
+        }
+        if !buf.is_empty() {
+            self.fs
+                .write_file(ROOT_CONTEXT, ino, offset.overflow_add(written), &buf)
+                .await?;
+            written = written.overflow_add(buf.len().cast());
+        }
+        Ok(written)
+    }
+
+    /// Put the object of `names` with the body of `req`.
+    async fn put_object(
+        &self,
+        root: INum,
+        names: &[&str],
+        req: Request<Body>,
+    ) -> Result<Response<Body>, S3Error> {
+        let ino = self.create_file(root, names).await?;
+        let _: u64 = self.write_body(ino, 0, req).await?;
+        let (_, attr) = self.fs.get_attr(ino).await?;
+        Ok(response(
+            StatusCode::OK,
+            vec![(ETAG, etag(&attr))],
+            Body::empty(),
+        ))
+    }
+
+    /// Get the object of `names`, with its content if `with_body` is set.
+    async fn get_object(
+        &self,
+        root: INum,
+        names: &[&str],
+        headers: &HeaderMap,
+        with_body: bool,
+    ) -> Result<Response<Body>, S3Error> {
+        let attr = self.walk(root, names).await?;
+        if is_dir(&attr) {
+            return Err(S3Error::no_such_key());
+        }
+        let range = match headers.get(RANGE).and_then(|range| range.to_str().ok()) {
+            Some(range) => Some(parse_range(range, attr.size)?),
+            None => None,
+        };
+        let (start, end) = range.unwrap_or((0, attr.size));
+        let last_modified = system_time(attr.mtime, attr.mtimensec);
+        let mut headers = vec![
+            (CONTENT_LENGTH, end.overflow_sub(start).to_string()),
+            (CONTENT_TYPE, "application/octet-stream".to_owned()),
+            (ETAG, etag(&attr)),
+            (
+                LAST_MODIFIED,
+                last_modified
+                    .format("%a, %d %b %Y %H:%M:%S GMT")
+                    .to_string(),
+            ),
+        ];
+        let status = if range.is_some() {
+            headers.push((
+                CONTENT_RANGE,
+                format!("bytes {start}-{}/{}", end.overflow_sub(1), attr.size),
+            ));
+            StatusCode::PARTIAL_CONTENT
+        } else {
+            StatusCode::OK
+        };
+        if !with_body {
+            return Ok(response(status, headers, Body::empty()));
+        }
+        let fs = Arc::clone(&self.fs);
+        let ino = attr.ino;
+        let chunks = stream::try_unfold(start, move |offset| {
+            let fs = Arc::clone(&fs);
+            async move {
+                if offset >= end {
+                    return Ok::<_, DatenLordError>(None);
+                }
+                let len = end.overflow_sub(offset).min(IO_CHUNK_SIZE.cast());
+                let (data, _) = fs.read_file(ROOT_CONTEXT, ino, offset, len).await?;
+                if data.is_empty() {
+                    // The file is truncated meanwhile
+                    return Ok(None);
+                }
+                let next = offset.overflow_add(data.len().cast());
+                Ok(Some((data, next)))
+            }
+        });
+        Ok(response(status, headers, Body::wrap_stream(chunks)))
+    }
+
+    /// Delete the object of `names`, which succeeds if it doesn't exist.
+    async fn delete_object(&self, root: INum, names: &[&str]) -> Result<(), S3Error> {
+        let Some((name, parents)) = names.split_last() else {
+            return Ok(());
+        };
+        let dir = match self.walk(root, parents).await {
+            Ok(attr) => attr.ino,
+            Err(e) if errno_of(&e) == Some(Errno::ENOENT) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        match self.fs.remove_stateless(dir, name).await {
+            Err(e) if errno_of(&e) != Some(Errno::ENOENT) => Err(e.into()),
+            Ok(()) | Err(_) => Ok(()),
+        }
+    }
+
+    /// List the objects of a bucket by `ListObjects` or `ListObjectsV2`.
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        root: INum,
+        query: &HashMap<String, String>,
+    ) -> Result<Response<Body>, S3Error> {
+        let param = |name: &str| query.get(name).map(String::as_str).unwrap_or_default();
+        let prefix = param("prefix");
+        let delimiter = param("delimiter");
+        if !delimiter.is_empty() && delimiter != "/" {
+            return Err(S3Error::invalid("only the delimiter / is supported"));
+        }
+        let v2 = param("list-type") == "2";
+        let start_after = if v2 {
+            query
+                .get("continuation-token")
+                .or_else(|| query.get("start-after"))
+                .map(String::as_str)
+                .unwrap_or_default()
+        } else {
+            param("marker")
+        };
+        let max_keys = query
+            .get("max-keys")
+            .and_then(|max_keys| max_keys.parse().ok())
+            .unwrap_or(MAX_LIST_KEYS)
+            .min(MAX_LIST_KEYS);
+
+        let listed = self.collect(root, prefix, !delimiter.is_empty()).await?;
+        let mut listed = listed
+            .into_iter()
+            .filter(|listed| listed.key.as_str() > start_after)
+            .peekable();
+        let page: Vec<Listed> = listed.by_ref().take(max_keys).collect();
+        let truncated = listed.peek().is_some();
+
+        let mut body = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <ListBucketResult><Name>{}</Name><Prefix>{}</Prefix><MaxKeys>{max_keys}</MaxKeys>\
+             <IsTruncated>{truncated}</IsTruncated>",
+            xml_escape(bucket),
+            xml_escape(prefix),
+        );
+        if !delimiter.is_empty() {
+            let _ = write!(body, "<Delimiter>{delimiter}</Delimiter>");
+        }
+        if v2 {
+            let _ = write!(body, "<KeyCount>{}</KeyCount>", page.len());
+        }
+        if let (true, Some(last)) = (truncated, page.last()) {
+            let tag = if v2 {
+                "NextContinuationToken"
+            } else {
+                "NextMarker"
+            };
+            let _ = write!(body, "<{tag}>{}</{tag}>", xml_escape(&last.key));
+        }
+        for listed in &page {
+            match listed.attr {
+                Some(ref attr) => {
+                    let modified = system_time(attr.mtime, attr.mtimensec);
+                    let _ = write!(
+                        body,
+                        "<Contents><Key>{}</Key><LastModified>{}</LastModified>\
+                         <ETag>{}</ETag><Size>{}</Size><StorageClass>STANDARD</StorageClass>\
+                         </Contents>",
+                        xml_escape(&listed.key),
+                        modified.format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+                        xml_escape(&etag(attr)),
+                        attr.size,
+                    );
+                }
+                None => {
+                    let _ = write!(
+                        body,
+                        "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+                        xml_escape(&listed.key),
+                    );
+                }
+            }
+        }
+        body.push_str("</ListBucketResult>");
+        Ok(xml_response(StatusCode::OK, body))
+    }
+
+    /// Collect the objects of keys starting with `prefix` in the volume of
+    /// directory `root`, in the order of the keys. The directories are
+    /// collected as the common prefixes instead of being walked into if
+    /// `delimited` is set.
+    async fn collect(
+        &self,
+        root: INum,
+        prefix: &str,
+        delimited: bool,
+    ) -> Result<Vec<Listed>, S3Error> {
+        // Start from the directory of the prefix
+        let base = prefix.rsplit_once('/').map_or("", |(base, _)| base);
+        let names: Vec<&str> = base.split('/').filter(|name| !name.is_empty()).collect();
+        let dir = match self.walk(root, &names).await {
+            Ok(attr) if is_dir(&attr) => attr.ino,
+            Ok(_) => return Ok(vec![]),
+            Err(e) if errno_of(&e) == Some(Errno::ENOENT) => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let base = if names.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", names.join("/"))
+        };
+
+        let mut listed = vec![];
+        let mut dirs = vec![(dir, base)];
+        while let Some((dir, base)) = dirs.pop() {
+            for entry in self.fs.list_dir(ROOT_CONTEXT, dir).await? {
+                if dir == root && entry.name() == MULTIPART_DIR {
+                    continue;
+                }
+                let key = format!("{base}{}", entry.name());
+                match entry.file_type() {
+                    FileType::Dir => {
+                        let key = format!("{key}/");
+                        if delimited {
+                            if key.starts_with(prefix) {
+                                listed.push(Listed { key, attr: None });
+                            }
+                        } else if key.starts_with(prefix) || prefix.starts_with(&key) {
+                            dirs.push((entry.ino(), key));
+                        } else {
+                            // Not under the prefix
+                        }
+                    }
+                    FileType::File => {
+                        if key.starts_with(prefix) {
+                            let (_, attr) = self.fs.get_attr(entry.ino()).await?;
+                            listed.push(Listed {
+                                key,
+                                attr: Some(attr),
+                            });
+                        }
+                    }
+                    // The symbolic links are not objects
+                    FileType::Symlink => {}
+                }
+            }
+        }
+        listed.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(listed)
+    }
+
+    /// The directory of the parts of the multipart upload `upload_id`.
+    async fn upload_dir(&self, root: INum, upload_id: &str) -> Result<INum, S3Error> {
+        Uuid::parse_str(upload_id).map_err(|_| {
+            S3Error::new(
+                StatusCode::NOT_FOUND,
+                "NoSuchUpload",
+                format!("{upload_id} is not an upload"),
+            )
+        })?;
+        match self.walk(root, &[MULTIPART_DIR, upload_id]).await {
+            Ok(attr) => Ok(attr.ino),
+            Err(e) if errno_of(&e) == Some(Errno::ENOENT) => Err(S3Error::new(
+                StatusCode::NOT_FOUND,
+                "NoSuchUpload",
+                format!("upload {upload_id} does not exist"),
+            )),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Initiate a multipart upload.
+    async fn initiate_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        root: INum,
+    ) -> Result<Response<Body>, S3Error> {
+        let upload_id = Uuid::new_v4().to_string();
+        let _: INum = self
+            .make_dirs(root, &[MULTIPART_DIR, upload_id.as_str()])
+            .await?;
+        Ok(xml_response(
+            StatusCode::OK,
+            format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                 <InitiateMultipartUploadResult><Bucket>{}</Bucket><Key>{}</Key>\
+                 <UploadId>{upload_id}</UploadId></InitiateMultipartUploadResult>",
+                xml_escape(bucket),
+                xml_escape(key),
+            ),
+        ))
+    }
+
+    /// Upload part `part` of a multipart upload.
+    async fn upload_part(
+        &self,
+        root: INum,
+        upload_id: &str,
+        part: u32,
+        req: Request<Body>,
+    ) -> Result<Response<Body>, S3Error> {
+        let dir = self.upload_dir(root, upload_id).await?;
+        let ino = self.create_file(dir, &[part.to_string().as_str()]).await?;
+        let _: u64 = self.write_body(ino, 0, req).await?;
+        let (_, attr) = self.fs.get_attr(ino).await?;
+        Ok(response(
+            StatusCode::OK,
+            vec![(ETAG, etag(&attr))],
+            Body::empty(),
+        ))
+    }
+
+    /// Complete a multipart upload, the parts are concatenated into the
+    /// object of `names`.
+    async fn complete_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        root: INum,
+        names: &[&str],
+        upload_id: &str,
+        req: Request<Body>,
+    ) -> Result<Response<Body>, S3Error> {
+        let dir = self.upload_dir(root, upload_id).await?;
+        let body = hyper::body::to_bytes(req.into_body())
+            .await
+            .map_err(|e| S3Error::new(StatusCode::BAD_REQUEST, "IncompleteBody", e.to_string()))?;
+        let parts = parse_completed_parts(&String::from_utf8_lossy(&body))?;
+        let mut part_inos = Vec::with_capacity(parts.len());
+        for part in parts {
+            match self
+                .fs
+                .lookup_entry(ROOT_CONTEXT, dir, &part.to_string())
+                .await
+            {
+                Ok((_, attr, _)) => part_inos.push(attr.ino),
+                Err(e) if errno_of(&e) == Some(Errno::ENOENT) => {
+                    return Err(S3Error::new(
+                        StatusCode::BAD_REQUEST,
+                        "InvalidPart",
+                        format!("part {part} is not uploaded"),
+                    ))
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let ino = self.create_file(root, names).await?;
+        let mut offset = 0_u64;
+        for part_ino in part_inos {
+            let mut part_offset = 0_u64;
+            loop {
+                let (data, eof) = self
+                    .fs
+                    .read_file(ROOT_CONTEXT, part_ino, part_offset, IO_CHUNK_SIZE.cast())
+                    .await?;
+                if !data.is_empty() {
+                    self.fs.write_file(ROOT_CONTEXT, ino, offset, &data).await?;
+                    let len: u64 = data.len().cast();
+                    part_offset = part_offset.overflow_add(len);
+                    offset = offset.overflow_add(len);
+                }
+                if eof || data.is_empty() {
+                    break;
+                }
+            }
+        }
+        self.abort_upload(root, upload_id).await?;
+        let (_, attr) = self.fs.get_attr(ino).await?;
+        Ok(xml_response(
+            StatusCode::OK,
+            format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                 <CompleteMultipartUploadResult><Bucket>{}</Bucket><Key>{}</Key>\
+                 <ETag>{}</ETag></CompleteMultipartUploadResult>",
+                xml_escape(bucket),
+                xml_escape(key),
+                xml_escape(&etag(&attr)),
+            ),
+        ))
+    }
+
+    /// Abort a multipart upload, its parts are removed.
+    async fn abort_upload(&self, root: INum, upload_id: &str) -> Result<(), S3Error> {
+        let dir = self.upload_dir(root, upload_id).await?;
+        for entry in self.fs.list_dir(ROOT_CONTEXT, dir).await? {
+            self.fs.remove_stateless(dir, entry.name()).await?;
+        }
+        let multipart_dir = self.walk(root, &[MULTIPART_DIR]).await?.ino;
+        self.fs.remove_stateless(multipart_dir, upload_id).await?;
+        Ok(())
+    }
+}
+
+/// The parameters to create `name` of `node_type` under directory `parent`.
+fn create_param(parent: INum, name: &str, node_type: SFlag) -> CreateParam {
+    CreateParam {
+        parent,
+        name: name.to_owned(),
+        mode: if node_type == SFlag::S_IFDIR {
+            DIR_MODE
+        } else {
+            FILE_MODE
+        },
+        rdev: 0,
+        uid: 0,
+        gid: 0,
+        umask: 0,
+        node_type,
+        link: None,
+    }
+}
+
+/// The parameters to truncate a file to be empty.
+fn truncate_param() -> SetAttrParam {
+    SetAttrParam {
+        valid: FATTR_SIZE,
+        fh: None,
+        mode: None,
+        u_id: None,
+        g_id: None,
+        size: Some(0),
+        #[cfg(feature = "abi-7-9")]
+        lock_owner: None,
+        a_time: None,
+        m_time: None,
+        #[cfg(feature = "abi-7-23")]
+        c_time: None,
+    }
+}
@@ -0,0 +1,226 @@
+use anyhow::anyhow;
+use nfsserve::nfs::{
+    ftype3, nfsstat3, nfstime3, sattr3, set_atime, set_gid3, set_mode3, set_mtime, set_size3,
+    set_uid3,
+};
+use nix::errno::Errno;
+
+use super::nfs::{page_entries, to_fattr, to_name, to_nfs_error, to_setattr_param};
+use super::s3::{
+    parse_completed_parts, parse_query, parse_range, percent_decode, split_key, xml_escape,
+    ChunkDecoder, S3Error,
+};
+use crate::async_fuse::fuse::protocol::setattr_flags::{FATTR_MODE, FATTR_MTIME, FATTR_SIZE};
+use crate::async_fuse::fuse::protocol::FuseAttr;
+use crate::common::error::DatenLordError;
+
+/// The attributes of a node of `mode`.
+fn fuse_attr(mode: u32) -> FuseAttr {
+    FuseAttr {
+        ino: 2,
+        size: 4096,
+        blocks: 8,
+        atime: 1,
+        mtime: 2,
+        ctime: 3,
+        #[cfg(target_os = "macos")]
+        crtime: 3,
+        atimensec: 4,
+        mtimensec: 5,
+        ctimensec: 6,
+        #[cfg(target_os = "macos")]
+        crtimensec: 6,
+        mode,
+        nlink: 1,
+        uid: 1000,
+        gid: 1000,
+        rdev: 0,
+        #[cfg(target_os = "macos")]
+        flags: 0,
+        #[cfg(feature = "abi-7-9")]
+        blksize: 4096,
+        #[cfg(feature = "abi-7-9")]
+        padding: 0,
+    }
+}
+
+#[test]
+fn test_to_fattr() {
+    let attr = to_fattr(&fuse_attr(libc::S_IFDIR | 0o755));
+    assert!(matches!(attr.ftype, ftype3::NF3DIR));
+    assert_eq!(attr.mode, 0o755);
+    assert_eq!(attr.fileid, 2);
+    assert_eq!(attr.used, 4096);
+    assert_eq!(attr.mtime.seconds, 2);
+    assert_eq!(attr.mtime.nseconds, 5);
+
+    let attr = to_fattr(&fuse_attr(libc::S_IFLNK | 0o777));
+    assert!(matches!(attr.ftype, ftype3::NF3LNK));
+    let attr = to_fattr(&fuse_attr(libc::S_IFREG | 0o644));
+    assert!(matches!(attr.ftype, ftype3::NF3REG));
+}
+
+#[test]
+fn test_to_setattr_param() {
+    let param = to_setattr_param(&sattr3 {
+        mode: set_mode3::mode(0o600),
+        uid: set_uid3::Void,
+        gid: set_gid3::Void,
+        size: set_size3::size(10),
+        atime: set_atime::DONT_CHANGE,
+        mtime: set_mtime::SET_TO_CLIENT_TIME(nfstime3 {
+            seconds: 100,
+            nseconds: 0,
+        }),
+    });
+    assert_eq!(param.valid, FATTR_MODE | FATTR_SIZE | FATTR_MTIME);
+    assert_eq!(param.mode, Some(0o600));
+    assert_eq!(param.size, Some(10));
+    assert!(param.u_id.is_none() && param.a_time.is_none());
+    assert_eq!(
+        param.m_time,
+        Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(100))
+    );
+}
+
+#[test]
+fn test_to_nfs_error() {
+    let err = DatenLordError::InternalErr {
+        source: anyhow::Error::new(Errno::ENOENT),
+        context: vec![],
+    };
+    assert!(matches!(to_nfs_error(err), nfsstat3::NFS3ERR_NOENT));
+    let err = DatenLordError::InternalErr {
+        source: anyhow::Error::new(Errno::EROFS),
+        context: vec![],
+    };
+    assert!(matches!(to_nfs_error(err), nfsstat3::NFS3ERR_ROFS));
+    let err = DatenLordError::InternalErr {
+        source: anyhow!("broken"),
+        context: vec![],
+    };
+    assert!(matches!(to_nfs_error(err), nfsstat3::NFS3ERR_IO));
+}
+
+#[test]
+fn test_to_name() {
+    assert_eq!(to_name(b"data").unwrap(), "data");
+    assert!(matches!(to_name(&[0xff]), Err(nfsstat3::NFS3ERR_INVAL)));
+}
+
+#[test]
+fn test_page_entries() {
+    let entries = vec![5_u64, 3, 8, 2];
+    let (page, end) = page_entries(entries.clone(), |&ino| ino, 0, 2).unwrap();
+    assert_eq!(page, vec![5, 3]);
+    assert!(!end);
+    let (page, end) = page_entries(entries.clone(), |&ino| ino, 3, 2).unwrap();
+    assert_eq!(page, vec![8, 2]);
+    assert!(end);
+    assert!(matches!(
+        page_entries(entries, |&ino| ino, 7, 2),
+        Err(nfsstat3::NFS3ERR_BAD_COOKIE)
+    ));
+}
+
+#[test]
+fn test_percent_decode() {
+    assert_eq!(percent_decode("a%20b%2Fc").unwrap(), "a b/c");
+    assert_eq!(xml_escape("<a & 'b'>"), "&lt;a &amp; &apos;b&apos;&gt;");
+    assert!(percent_decode("a%2").is_err());
+    assert!(percent_decode("a%zz").is_err());
+
+    let query = parse_query("list-type=2&prefix=dir%2F&uploads").unwrap();
+    assert_eq!(query.get("list-type").unwrap(), "2");
+    assert_eq!(query.get("prefix").unwrap(), "dir/");
+    assert_eq!(query.get("uploads").unwrap(), "");
+}
+
+#[test]
+fn test_split_key() {
+    assert_eq!(split_key("a/b/c").unwrap(), (vec!["a", "b", "c"], false));
+    assert_eq!(split_key("a/b/").unwrap(), (vec!["a", "b"], true));
+    assert_eq!(split_key("a//b").unwrap_err().code(), "InvalidObjectName");
+    assert_eq!(split_key("a/../b").unwrap_err().code(), "InvalidObjectName");
+    assert_eq!(
+        split_key(".s3-multipart/id/1").unwrap_err().code(),
+        "AccessDenied"
+    );
+}
+
+#[test]
+fn test_parse_range() {
+    assert_eq!(parse_range("bytes=0-9", 100).unwrap(), (0, 10));
+    assert_eq!(parse_range("bytes=90-", 100).unwrap(), (90, 100));
+    assert_eq!(parse_range("bytes=-10", 100).unwrap(), (90, 100));
+    assert_eq!(parse_range("bytes=50-200", 100).unwrap(), (50, 100));
+    assert_eq!(
+        parse_range("bytes=100-", 100).unwrap_err().code(),
+        "InvalidRange"
+    );
+    assert!(parse_range("items=0-1", 100).is_err());
+}
+
+#[test]
+fn test_chunk_decoder() {
+    let body = b"5;chunk-signature=abc\r\nhello\r\n6;chunk-signature=def\r\n world\r\n\
+                 0;chunk-signature=ghi\r\n\r\n";
+    // Fed in pieces split in the middle of the headers and the data
+    let mut decoder = ChunkDecoder::default();
+    let mut out = vec![];
+    for piece in body.chunks(7) {
+        decoder.feed(piece, &mut out).unwrap();
+    }
+    decoder.finish().unwrap();
+    assert_eq!(out, b"hello world");
+
+    let mut decoder = ChunkDecoder::default();
+    let mut out = vec![];
+    decoder
+        .feed(b"5;chunk-signature=abc\r\nhel", &mut out)
+        .unwrap();
+    assert_eq!(decoder.finish().unwrap_err().code(), "IncompleteBody");
+    assert!(ChunkDecoder::default()
+        .feed(b"xyz;chunk-signature=abc\r\n", &mut out)
+        .is_err());
+}
+
+#[test]
+fn test_parse_completed_parts() {
+    let body = "<CompleteMultipartUpload>\
+                <Part><PartNumber>1</PartNumber><ETag>\"a\"</ETag></Part>\
+                <Part><PartNumber>3</PartNumber><ETag>\"b\"</ETag></Part>\
+                </CompleteMultipartUpload>";
+    assert_eq!(parse_completed_parts(body).unwrap(), vec![1, 3]);
+    let body = "<CompleteMultipartUpload>\
+                <Part><PartNumber>2</PartNumber></Part>\
+                <Part><PartNumber>1</PartNumber></Part>\
+                </CompleteMultipartUpload>";
+    assert_eq!(
+        parse_completed_parts(body).unwrap_err().code(),
+        "InvalidPartOrder"
+    );
+    assert_eq!(
+        parse_completed_parts("<Broken").unwrap_err().code(),
+        "MalformedXML"
+    );
+}
+
+#[test]
+fn test_s3_error() {
+    let err = DatenLordError::InternalErr {
+        source: anyhow::Error::new(Errno::ENOENT),
+        context: vec![],
+    };
+    assert_eq!(S3Error::from(err).code(), "NoSuchKey");
+    let err = DatenLordError::InternalErr {
+        source: anyhow::Error::new(Errno::EROFS),
+        context: vec![],
+    };
+    assert_eq!(S3Error::from(err).code(), "AccessDenied");
+    let err = DatenLordError::VolumeNotFound {
+        volume_id: "bucket".to_owned(),
+        context: vec![],
+    };
+    assert_eq!(S3Error::from(err).code(), "NoSuchBucket");
+}
@@ -0,0 +1,341 @@
+//! The health of the daemon, served by HTTP to the probes of Kubernetes.
+//!
+//! The daemon is ready once all the components registered are, such as the
+//! volume mounted, the metadata reached and the cache built, and it's alive
+//! while its event loop keeps beating and no write to the backend is stuck.
+//! They're served on `--health-port` as `/readyz` and `/livez`, answered by
+//! `200 OK`, or by `503 Service Unavailable` with the problems found. The
+//! health role checks them for the exec probes, and exits with 1 on failure.
+//! The CSI `Probe` reports them as well, so the liveness probe sidecar of CSI
+//! fails once the node plugin is not alive, and it's not ready until the
+//! volume is mounted.
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use datenlord::config::HealthProbe;
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use crate::async_fuse::memfs::kv_engine::{KVEngine, KVEngineType, KeyType};
+use crate::common::error::{Context, DatenLordResult};
+
+/// The health of this process.
+pub static HEALTH: Lazy<Health> = Lazy::new(Health::default);
+
+/// The component of the volume mounted, or served by virtio-fs.
+pub const MOUNT_COMPONENT: &str = "mount";
+/// The component of the cache and the storage under it.
+pub const CACHE_COMPONENT: &str = "cache";
+/// The component of the metadata engine.
+const METADATA_COMPONENT: &str = "metadata";
+
+/// The interval between the beats of the event loop.
+const BEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// The event loop is stuck if it hasn't beaten for it.
+const MAX_BEAT_DELAY: Duration = Duration::from_secs(10);
+/// A write to the backend is stuck if it hasn't finished in it.
+const MAX_WRITE_DURATION: Duration = Duration::from_secs(300);
+/// The interval between the probes of the metadata engine.
+const METADATA_PROBE_INTERVAL: Duration = Duration::from_secs(10);
+/// The metadata engine can't be reached if a probe takes longer.
+const METADATA_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The readiness and the liveness of the daemon.
+#[derive(Debug, Default)]
+pub struct Health {
+    /// Component name -> why the component is not ready, `None` if it's ready
+    components: Mutex<BTreeMap<&'static str, Option<String>>>,
+    /// When the event loop beats the last time, `None` if it's not watched
+    last_beat: Mutex<Option<Instant>>,
+    /// The id of the next write to the backend
+    next_write: AtomicU64,
+    /// The id of a write to the backend in flight -> when it's started
+    writes: Mutex<HashMap<u64, Instant>>,
+}
+
+impl Health {
+    /// Register `component`, the daemon is not ready for `reason` until the
+    /// component is reported ready.
+    pub fn register(&self, component: &'static str, reason: &str) {
+        self.components
+            .lock()
+            .insert(component, Some(reason.to_owned()));
+    }
+
+    /// Report `component` ready, or not ready for `problem`. Only the changes
+    /// are logged.
+    pub fn report(&self, component: &'static str, problem: Option<String>) {
+        let mut components = self.components.lock();
+        let Some(current) = components.get_mut(component) else {
+            return;
+        };
+        if *current != problem {
+            match problem {
+                Some(ref problem) => info!("The {component} is not ready: {problem}"),
+                None => info!("The {component} is ready."),
+            }
+            *current = problem;
+        }
+    }
+
+    /// Track a write to the backend until the guard returned is dropped.
+    #[must_use]
+    pub fn track_write(&self) -> WriteGuard<'_> {
+        let id = self.next_write.fetch_add(1, Ordering::Relaxed);
+        self.writes.lock().insert(id, Instant::now());
+        WriteGuard { health: self, id }
+    }
+
+    /// Record a beat of the event loop.
+    fn beat(&self) {
+        *self.last_beat.lock() = Some(Instant::now());
+    }
+
+    /// Check whether all the components are ready, the ones not ready are
+    /// returned otherwise.
+    pub fn readiness(&self) -> Result<(), String> {
+        let components = self.components.lock();
+        let problems: Vec<String> = components
+            .iter()
+            .filter_map(|(component, problem)| {
+                problem
+                    .as_ref()
+                    .map(|problem| format!("{component}: {problem}"))
+            })
+            .collect();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.join("; "))
+        }
+    }
+
+    /// Check whether the event loop keeps beating and no write to the backend
+    /// is stuck, the problems are returned otherwise.
+    pub fn liveness(&self) -> Result<(), String> {
+        let mut problems = vec![];
+        if let Some(last_beat) = *self.last_beat.lock() {
+            let delay = last_beat.elapsed();
+            if delay > MAX_BEAT_DELAY {
+                problems.push(format!("the event loop hasn't beaten for {delay:?}"));
+            }
+        }
+        if let Some(started) = self.writes.lock().values().min() {
+            let duration = started.elapsed();
+            if duration > MAX_WRITE_DURATION {
+                problems.push(format!("a write to the backend is stuck for {duration:?}"));
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.join("; "))
+        }
+    }
+}
+
+/// A write to the backend in flight, which is finished once it's dropped.
+#[derive(Debug)]
+pub struct WriteGuard<'a> {
+    /// The health tracking the write
+    health: &'a Health,
+    /// The id of the write
+    id: u64,
+}
+
+impl Drop for WriteGuard<'_> {
+    fn drop(&mut self) {
+        self.health.writes.lock().remove(&self.id);
+    }
+}
+
+/// Build a plain text response of `status`.
+fn text_response(status: StatusCode, body: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Body::from(body))
+        .unwrap_or_else(|_| panic!("Fail to build health response"))
+}
+
+/// Answer a probe of the liveness or the readiness.
+#[allow(clippy::unused_async)] // Hyper requires an async function
+async fn serve_req(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let checked = match req.uri().path() {
+        "/livez" | "/healthz" => HEALTH.liveness(),
+        "/readyz" => HEALTH.readiness(),
+        _ => {
+            return Ok(text_response(
+                StatusCode::NOT_FOUND,
+                "not found\n".to_owned(),
+            ))
+        }
+    };
+    Ok(match checked {
+        Ok(()) => text_response(StatusCode::OK, "ok\n".to_owned()),
+        Err(problems) => text_response(StatusCode::SERVICE_UNAVAILABLE, format!("{problems}\n")),
+    })
+}
+
+/// Beat for the event loop until `token` is cancelled.
+#[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+async fn run_beats(token: CancellationToken) {
+    let mut ticker = tokio::time::interval(BEAT_INTERVAL);
+    loop {
+        select! {
+            _ = ticker.tick() => HEALTH.beat(),
+            () = token.cancelled() => return,
+        }
+    }
+}
+
+/// Probe `kv_engine` until `token` is cancelled, the metadata is not ready
+/// while it can't be reached.
+#[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+async fn run_metadata_probe(kv_engine: Arc<KVEngineType>, token: CancellationToken) {
+    let mut ticker = tokio::time::interval(METADATA_PROBE_INTERVAL);
+    loop {
+        select! {
+            _ = ticker.tick() => {}
+            () = token.cancelled() => return,
+        }
+        let problem =
+            match tokio::time::timeout(METADATA_PROBE_TIMEOUT, kv_engine.get(&KeyType::VolumeInfo))
+                .await
+            {
+                Ok(Ok(_)) => None,
+                Ok(Err(e)) => Some(format!("failed to reach the metadata: {e}")),
+                Err(_) => Some(format!(
+                    "the metadata is not reached in {METADATA_PROBE_TIMEOUT:?}"
+                )),
+            };
+        HEALTH.report(METADATA_COMPONENT, problem);
+    }
+}
+
+/// Serve the probes of the health of the mount on `port` until `token` is
+/// cancelled, the metadata is probed by `kv_engine`.
+pub async fn serve_health(port: u16, kv_engine: Arc<KVEngineType>, token: CancellationToken) {
+    HEALTH.register(MOUNT_COMPONENT, "not mounted yet");
+    HEALTH.register(CACHE_COMPONENT, "not built yet");
+    HEALTH.register(METADATA_COMPONENT, "not probed yet");
+    let addr = ([0, 0, 0, 0], port).into();
+    let server = match Server::try_bind(&addr) {
+        Ok(builder) => builder.serve(make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(serve_req))
+        })),
+        Err(e) => {
+            error!("Failed to bind the health server to {addr}: {e}");
+            return;
+        }
+    };
+    HEALTH.beat();
+    info!("Health server is listening on {addr}");
+
+    let server = async {
+        if let Err(e) = server
+            .with_graceful_shutdown(token.clone().cancelled_owned())
+            .await
+        {
+            error!("Health server failed: {e}");
+        }
+    };
+    tokio::join!(
+        server,
+        run_beats(token.clone()),
+        run_metadata_probe(kv_engine, token.clone())
+    );
+}
+
+/// Check `probe` of the daemon serving the health on `port` of this host,
+/// the problems are returned if it fails.
+pub async fn check_health(port: u16, probe: HealthProbe) -> DatenLordResult<Result<(), String>> {
+    let path = match probe {
+        HealthProbe::Liveness => "/livez",
+        HealthProbe::Readiness => "/readyz",
+    };
+    let mut stream = TcpStream::connect(("127.0.0.1", port))
+        .await
+        .with_context(|| format!("failed to connect the health server on port {port}"))?;
+    stream
+        .write_all(format!("GET {path} HTTP/1.0\r\n\r\n").as_bytes())
+        .await
+        .add_context("failed to send the probe")?;
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .await
+        .add_context("failed to read the response of the probe")?;
+    let status = response.split_whitespace().nth(1).unwrap_or_default();
+    let body = response
+        .split_once("\r\n\r\n")
+        .map_or("", |(_, body)| body)
+        .trim_end();
+    if status == StatusCode::OK.as_str() {
+        Ok(Ok(()))
+    } else {
+        Ok(Err(format!("{status} {body}")))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::Health;
+
+    #[test]
+    fn test_readiness() {
+        let health = Health::default();
+        assert!(health.readiness().is_ok());
+        health.register("mount", "not mounted yet");
+        health.register("cache", "not built yet");
+        assert_eq!(
+            health.readiness(),
+            Err("cache: not built yet; mount: not mounted yet".to_owned())
+        );
+        health.report("cache", None);
+        health.report("mount", None);
+        assert!(health.readiness().is_ok());
+        // The components not registered are ignored.
+        health.report("metadata", Some("unreachable".to_owned()));
+        assert!(health.readiness().is_ok());
+    }
+
+    #[test]
+    fn test_liveness() {
+        let health = Health::default();
+        assert!(health.liveness().is_ok());
+        let write = health.track_write();
+        health.beat();
+        assert!(health.liveness().is_ok());
+
+        let long_ago = Instant::now()
+            .checked_sub(Duration::from_secs(600))
+            .unwrap();
+        *health.last_beat.lock() = Some(long_ago);
+        for started in health.writes.lock().values_mut() {
+            *started = long_ago;
+        }
+        let problems = health.liveness().unwrap_err();
+        assert!(problems.contains("event loop"));
+        assert!(problems.contains("stuck"));
+
+        health.beat();
+        drop(write);
+        assert!(health.liveness().is_ok());
+    }
+}
@@ -67,3 +67,32 @@ pub mod common;
 /// Configurations
 pub mod config;
 pub mod metrics;
+
+/// The FUSE request decoder and reply encoders, compiled into the library for
+/// the fuzz targets under `fuzz/`, with the lints allowed by the binary
+#[cfg(fuzzing)]
+#[allow(
+    dead_code,
+    clippy::arithmetic_side_effects,
+    clippy::missing_safety_doc,
+    clippy::missing_trait_methods,
+    clippy::same_name_method,
+    clippy::semicolon_outside_block,
+    clippy::similar_names,
+    clippy::single_char_lifetime_names,
+    clippy::undocumented_unsafe_blocks,
+    clippy::use_debug
+)]
+pub mod async_fuse {
+    /// Implementation of FUSE library
+    pub mod fuse {
+        #[allow(clippy::tests_outside_test_module)]
+        mod abi_marker;
+        pub mod context;
+        pub mod de;
+        pub mod fuse_reply;
+        pub mod fuse_request;
+        pub mod protocol;
+    }
+    pub mod util;
+}
@@ -73,24 +73,51 @@
 )]
 
 pub mod async_fuse;
+mod bench;
 mod common;
+mod control;
 mod csi;
+mod gateway;
+mod health;
+mod operator;
+mod reload;
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod simulation;
 pub mod storage;
+mod telemetry;
+mod tls;
+mod volume;
+// The adapter is only mounted on Windows, its conversions are tested on the
+// others.
+#[cfg_attr(not(windows), allow(dead_code))]
+mod winfsp;
 
 use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::SystemTime;
 
+use async_fuse::fuse::fusedump::{self, FUSE_TRACE};
+use async_fuse::fuse::request_log::REQUEST_LOG;
+use async_fuse::fuse::slow_log::SLOW_LOG;
+use async_fuse::memfs::auth::AuthKey;
 use async_fuse::memfs::kv_engine::{KVEngine, KVEngineType};
-use clap::Parser;
+use async_fuse::memfs::volume::{VolumeManager, VolumeParams};
+use async_fuse::memfs::warmup::WarmUpRequest;
+use async_fuse::memfs::{billing, snapshot, trash};
 use csi::meta_data::MetaData;
 use csi::scheduler_extender::SchedulerExtender;
 use datenlord::common::task_manager::{self, TaskName, TASK_MANAGER};
-use datenlord::config::{InnerConfig, NodeRole, StorageConfig};
+use datenlord::config::{
+    AuthConfig, BenchTarget, IdMap, InnerConfig, NodeRole, OpMask, Squash, StorageConfig,
+};
 use datenlord::{config, metrics};
 
 use crate::common::error::DatenLordResult;
 use crate::common::etcd_delegate::EtcdDelegate;
-use crate::common::logger::init_logger;
+use crate::common::logger::{init_logger, init_traced_logger, set_log_level, LogRole};
+use crate::volume::VolumeManagerClient;
 
 /// Async fuse args type
 #[derive(Debug)]
@@ -105,6 +132,23 @@ pub struct AsyncFuseArgs {
     pub mount_dir: String,
     /// Storage config
     pub storage_config: StorageConfig,
+    /// The name of the snapshot to mount read-only, `None` to mount the live
+    /// volume
+    pub snapshot: Option<String>,
+    /// The vhost-user socket to serve the volume on by virtio-fs, `None` to
+    /// mount it by FUSE
+    pub virtiofs_socket: Option<PathBuf>,
+    /// The mapping of the uids and gids between the mount and the volume
+    pub id_map: IdMap,
+    /// The squashing of the callers of the mount
+    pub squash: Squash,
+    /// The classes of the FUSE operations turned off on the mount
+    pub op_mask: OpMask,
+    /// The SELinux context of all the nodes of the mount, `None` to label
+    /// them by the labels stored in the volume
+    pub selinux_context: Option<String>,
+    /// The tenant served by the mount and the policy authorizing it
+    pub auth: AuthConfig,
 }
 /// Parse config from command line arguments, and return the created `MetaData`
 async fn parse_metadata(config: &InnerConfig) -> DatenLordResult<MetaData> {
@@ -123,13 +167,120 @@ async fn parse_metadata(config: &InnerConfig) -> DatenLordResult<MetaData> {
         etcd_delegate,
     )
     .await
+    .map(|md| md.with_mount_namespace(config.csi_config.mount_namespace.clone()))
+}
+
+/// Serve the control socket of the mount if it's configured
+async fn serve_control_socket(config: &InnerConfig) -> anyhow::Result<()> {
+    if let Some(ref path) = config.control_socket {
+        let path = path.clone();
+        TASK_MANAGER
+            .spawn(TaskName::Control, |token| {
+                control::serve_control_socket(path, token)
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+/// Reload the config file on `SIGHUP` if it's given
+async fn serve_config_reload(config: &InnerConfig) -> anyhow::Result<()> {
+    if config.config_file.is_some() {
+        TASK_MANAGER
+            .spawn(TaskName::ConfigReload, reload::reload_on_hangup)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Record the FUSE requests and replies of the mount if a trace is configured
+fn start_fuse_trace(config: &InnerConfig) -> anyhow::Result<()> {
+    if let Some(ref path) = config.fuse_trace {
+        FUSE_TRACE.start(path)?;
+    }
+    Ok(())
+}
+
+/// Serve the health of the mount if its port is configured, the metadata is
+/// probed by `kv_engine`
+async fn serve_health(config: &InnerConfig, kv_engine: &Arc<KVEngineType>) -> anyhow::Result<()> {
+    if let Some(port) = config.health_port {
+        let kv_engine = Arc::clone(kv_engine);
+        TASK_MANAGER
+            .spawn(TaskName::Health, move |token| {
+                health::serve_health(port, kv_engine, token)
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+/// Checkpoint the usage of the volume by `kv_engine` for billing, if its
+/// interval is configured
+async fn checkpoint_usage(
+    config: &InnerConfig,
+    kv_engine: &Arc<KVEngineType>,
+) -> anyhow::Result<()> {
+    if let Some(interval) = config.usage_checkpoint_interval {
+        let kv_engine = Arc::clone(kv_engine);
+        let node_id = config.node_name.clone();
+        TASK_MANAGER
+            .spawn(TaskName::UsageCheckpoint, move |token| {
+                billing::run_checkpointer(kv_engine, node_id, interval, token)
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+/// Connect to the volume manager if an endpoint is configured, CSI volumes
+/// are then registered as named volumes
+fn volume_manager_client(config: &InnerConfig) -> DatenLordResult<Option<VolumeManagerClient>> {
+    config
+        .volume_config
+        .endpoint
+        .as_deref()
+        .map(VolumeManagerClient::connect)
+        .transpose()
+}
+
+/// The paths the warmup role or the pin role loads, given by the flags and
+/// listed in the file list.
+async fn warmup_paths(config: &InnerConfig) -> anyhow::Result<Vec<String>> {
+    let mut paths = config.warmup_paths.clone();
+    if let Some(ref file_list) = config.warmup_file_list {
+        let list = tokio::fs::read_to_string(file_list)
+            .await
+            .map_err(|e| anyhow::anyhow!("failed to read the file list {file_list:?}: {e}"))?;
+        paths.extend(
+            list.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_owned),
+        );
+    }
+    Ok(paths)
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let config = InnerConfig::try_from(config::Config::parse())?;
+    let config = InnerConfig::try_from(config::Config::load()?)?;
 
-    init_logger(config.role.into());
+    if let Some(ref endpoint) = config.otlp_endpoint {
+        let role = LogRole::from(config.role);
+        let tracer = telemetry::init_tracer(endpoint, config.otlp_sample_ratio, role.as_str())?;
+        init_traced_logger(role, config.log_format, tracer);
+    } else {
+        init_logger(config.role.into(), config.log_format);
+    }
+    set_log_level(config.log_level);
+    REQUEST_LOG.set_sample_rate(config.log_sample_rate);
+    SLOW_LOG.set_threshold(config.slow_log_threshold);
+    SLOW_LOG.set_capacity(config.slow_log_capacity);
+    serve_config_reload(&config).await?;
+    if let Some(ref tls_config) = config.tls {
+        tls::init_cluster_tls(tls_config).await?;
+    }
 
     match config.role {
         NodeRole::Node => {
@@ -145,7 +296,9 @@ async fn main() -> anyhow::Result<()> {
             let driver_name = config.csi_config.driver_name.clone();
 
             let worker_server = csi::build_grpc_worker_server(Arc::<MetaData>::clone(&md))?;
-            let node_server = csi::build_grpc_node_server(&csi_endpoint, &driver_name, md)?;
+            let volume_manager = volume_manager_client(&config)?;
+            let node_server =
+                csi::build_grpc_node_server(&csi_endpoint, &driver_name, md, volume_manager)?;
             TASK_MANAGER
                 .spawn(TaskName::Rpc, |token| {
                     csi::run_grpc_servers(token, vec![worker_server, node_server])
@@ -155,6 +308,10 @@ async fn main() -> anyhow::Result<()> {
             TASK_MANAGER
                 .spawn(TaskName::Metrics, metrics::start_metrics_server)
                 .await?;
+            serve_control_socket(&config).await?;
+            serve_health(&config, &kv_engine).await?;
+            checkpoint_usage(&config, &kv_engine).await?;
+            start_fuse_trace(&config)?;
 
             let async_args = AsyncFuseArgs {
                 node_id,
@@ -162,8 +319,25 @@ async fn main() -> anyhow::Result<()> {
                 server_port: config.server_port,
                 mount_dir: mount_dir.clone(),
                 storage_config: config.storage,
+                snapshot: None,
+                virtiofs_socket: None,
+                id_map: config.id_map,
+                squash: config.squash,
+                op_mask: config.op_mask,
+                selinux_context: config.selinux_context,
+                auth: config.auth,
             };
 
+            // Windows mounts the volume by WinFsp instead of FUSE
+            #[cfg(windows)]
+            TASK_MANAGER
+                .spawn(TaskName::AsyncFuse, |token| async {
+                    if let Err(e) = winfsp::start_winfsp(kv_engine, async_args, token).await {
+                        panic!("failed to start WinFsp, error is {e:?}");
+                    }
+                })
+                .await?;
+            #[cfg(not(windows))]
             TASK_MANAGER
                 .spawn(TaskName::AsyncFuse, |token| async {
                     if let Err(e) = async_fuse::start_async_fuse(kv_engine, async_args, token).await
@@ -179,10 +353,12 @@ async fn main() -> anyhow::Result<()> {
 
             let end_point = config.csi_config.endpoint.clone();
             let driver_name = config.csi_config.driver_name.clone();
+            let volume_manager = volume_manager_client(&config)?;
             let controller_server = csi::build_grpc_controller_server(
                 &end_point,
                 &driver_name,
                 Arc::<MetaData>::clone(&md),
+                volume_manager,
             )?;
             TASK_MANAGER
                 .spawn(TaskName::Rpc, |token| {
@@ -207,7 +383,11 @@ async fn main() -> anyhow::Result<()> {
                 .await?;
         }
         NodeRole::AsyncFuse => {
+            serve_control_socket(&config).await?;
             let kv_engine = Arc::new(KVEngineType::new(config.kv_addrs.clone()).await?);
+            serve_health(&config, &kv_engine).await?;
+            checkpoint_usage(&config, &kv_engine).await?;
+            start_fuse_trace(&config)?;
             let node_id = config.node_name.clone();
             let ip_address = config.node_ip;
             let mount_dir = config.mount_path.clone();
@@ -217,12 +397,29 @@ async fn main() -> anyhow::Result<()> {
                 server_port: config.server_port,
                 mount_dir: mount_dir.clone(),
                 storage_config: config.storage,
+                snapshot: config.snapshot,
+                virtiofs_socket: config.virtiofs_socket,
+                id_map: config.id_map,
+                squash: config.squash,
+                op_mask: config.op_mask,
+                selinux_context: config.selinux_context,
+                auth: config.auth,
             };
 
             TASK_MANAGER
                 .spawn(TaskName::Metrics, metrics::start_metrics_server)
                 .await?;
 
+            // Windows mounts the volume by WinFsp instead of FUSE
+            #[cfg(windows)]
+            TASK_MANAGER
+                .spawn(TaskName::AsyncFuse, |token| async {
+                    if let Err(e) = winfsp::start_winfsp(kv_engine, async_args, token).await {
+                        panic!("failed to start WinFsp, error is {e:?}");
+                    }
+                })
+                .await?;
+            #[cfg(not(windows))]
             TASK_MANAGER
                 .spawn(TaskName::AsyncFuse, |token| async {
                     if let Err(e) = async_fuse::start_async_fuse(kv_engine, async_args, token).await
@@ -232,8 +429,333 @@ async fn main() -> anyhow::Result<()> {
                 })
                 .await?;
         }
+        NodeRole::Fsck => {
+            let kv_engine = Arc::new(KVEngineType::new(config.kv_addrs.clone()).await?);
+            let report = async_fuse::memfs::fsck::check(kv_engine, config.fsck_repair).await?;
+            println!("{report}");
+            if !report.is_clean() && !report.repaired {
+                anyhow::bail!("the metadata is inconsistent, run with --fsck-repair to repair it");
+            }
+            return Ok(());
+        }
+        NodeRole::Snapshot => {
+            let kv_engine = Arc::new(KVEngineType::new(config.kv_addrs.clone()).await?);
+            let Some(ref name) = config.snapshot else {
+                for info in snapshot::list_snapshots(&kv_engine).await? {
+                    let state = if info.ready { "ready" } else { "not ready" };
+                    println!("{}\t{}\t{state}", info.id, info.name);
+                }
+                return Ok(());
+            };
+            if config.snapshot_delete {
+                let info = snapshot::delete_snapshot(&kv_engine, name).await?;
+                let versions =
+                    async_fuse::collect_snapshot_garbage(kv_engine, &config.storage).await?;
+                println!(
+                    "deleted snapshot {} with id {}, {versions} block versions are collected",
+                    info.name, info.id
+                );
+            } else {
+                let info = snapshot::create_snapshot(&kv_engine, name).await?;
+                println!("created snapshot {} with id {}", info.name, info.id);
+            }
+            return Ok(());
+        }
+        NodeRole::Trash => {
+            let kv_engine = Arc::new(KVEngineType::new(config.kv_addrs.clone()).await?);
+            let Some(ino) = config.trash_restore else {
+                for entry in trash::list_trash(&kv_engine).await? {
+                    let deleted = chrono::DateTime::<chrono::Utc>::from(entry.deleted);
+                    println!("{}\t{}\t{}", entry.ino, deleted.to_rfc3339(), entry.path);
+                }
+                return Ok(());
+            };
+            let path = trash::restore(
+                kv_engine,
+                &config.node_name,
+                ino,
+                config.trash_restore_path.as_deref(),
+            )
+            .await?;
+            println!("restored ino {ino} to {path}");
+            return Ok(());
+        }
+        NodeRole::VolumeManager => {
+            let kv_engine = Arc::new(KVEngineType::new(config.kv_addrs.clone()).await?);
+            let manager = Arc::new(VolumeManager::new(kv_engine, &config.node_name).await?);
+            let listen = config.volume_config.listen;
+            let storage_config = config.storage;
+            TASK_MANAGER
+                .spawn(TaskName::Rpc, move |token| {
+                    volume::run_volume_manager(listen, manager, storage_config, token)
+                })
+                .await?;
+        }
+        NodeRole::Gateway => {
+            let kv_engine = Arc::new(KVEngineType::new(config.kv_addrs.clone()).await?);
+            let async_args = AsyncFuseArgs {
+                node_id: config.node_name.clone(),
+                ip_address: config.node_ip,
+                server_port: config.server_port,
+                mount_dir: config.mount_path.clone(),
+                storage_config: config.storage,
+                snapshot: config.snapshot,
+                virtiofs_socket: None,
+                // The clients of the gateway are not mapped.
+                id_map: IdMap::default(),
+                squash: Squash::default(),
+                // The gateway dispatches no FUSE request.
+                op_mask: OpMask::default(),
+                selinux_context: None,
+                // The clients of the gateway are not tenants.
+                auth: AuthConfig::default(),
+            };
+            let (fs, snapshot) =
+                async_fuse::open_memfs(Arc::clone(&kv_engine), &async_args).await?;
+            let fs = Arc::new(fs);
+            let read_only = snapshot.is_some();
+            let gateway = gateway::NfsGateway::new(Arc::clone(&fs), read_only);
+            let listen = config.gateway_listen;
+            let s3_gateway = match config.gateway_s3_listen {
+                Some(addr) => {
+                    let volumes = VolumeManager::new(kv_engine, &config.node_name).await?;
+                    Some((addr, gateway::S3Gateway::new(fs, volumes, read_only)))
+                }
+                None => None,
+            };
+
+            TASK_MANAGER
+                .spawn(TaskName::Metrics, metrics::start_metrics_server)
+                .await?;
+
+            TASK_MANAGER
+                .spawn(TaskName::Gateway, move |token| async move {
+                    let nfs = gateway::run_gateway(listen, gateway, token.clone());
+                    match s3_gateway {
+                        Some((addr, s3_gateway)) => {
+                            let s3 = gateway::run_s3_gateway(addr, s3_gateway, token);
+                            tokio::join!(nfs, s3);
+                        }
+                        None => nfs.await,
+                    }
+                    if let Some(ref info) = snapshot {
+                        async_fuse::close_snapshot(info).await;
+                    }
+                })
+                .await?;
+        }
+        NodeRole::Operator => {
+            TASK_MANAGER
+                .spawn(TaskName::Operator, operator::run_operator)
+                .await?;
+        }
+        NodeRole::Volume => {
+            let volume_config = config.volume_config;
+            let Some(ref endpoint) = volume_config.endpoint else {
+                anyhow::bail!("the volume role requires --volume-manager-endpoint");
+            };
+            let client = VolumeManagerClient::connect(endpoint)?;
+            let Some(ref name) = volume_config.name else {
+                for volume in client.list().await? {
+                    println!(
+                        "{}\t{}\t{}\t{}",
+                        volume.name, volume.id, volume.capacity, volume.policy
+                    );
+                }
+                return Ok(());
+            };
+            if volume_config.delete {
+                client.delete(name).await?;
+                println!("deleted volume {name}");
+            } else if volume_config.resize {
+                let volume = client.resize(name, volume_config.capacity).await?;
+                println!("resized volume {name} to capacity {}", volume.capacity);
+            } else {
+                let (volume, secret) = client
+                    .create(
+                        name,
+                        volume_config.capacity,
+                        volume_config.policy,
+                        VolumeParams::default(),
+                        None,
+                    )
+                    .await?;
+                println!(
+                    "created volume {name} with id {}, the secret to mount it is {secret}",
+                    volume.id
+                );
+            }
+            return Ok(());
+        }
+        NodeRole::Admin => {
+            let Some(ref endpoint) = config.volume_config.endpoint else {
+                anyhow::bail!("the admin role requires --volume-manager-endpoint");
+            };
+            let client = VolumeManagerClient::connect(endpoint)?;
+            volume::run_admin(
+                &client,
+                &config.volume_config,
+                config.snapshot.as_deref(),
+                &mut std::io::stdout(),
+            )
+            .await?;
+            return Ok(());
+        }
+        NodeRole::Tenant => {
+            let (Some(ref key_file), Some(ref tenant)) = (config.auth.key_file, config.auth.tenant)
+            else {
+                anyhow::bail!("the tenant role requires --auth-key-file and --tenant");
+            };
+            let key = AuthKey::load(key_file).await?;
+            println!("{}", key.issue_token(tenant));
+            return Ok(());
+        }
+        NodeRole::Top => {
+            let Some(ref path) = config.control_socket else {
+                anyhow::bail!("the top role requires --control-socket");
+            };
+            control::top(path, config.top_interval).await?;
+            return Ok(());
+        }
+        NodeRole::Drain => {
+            let Some(ref path) = config.control_socket else {
+                anyhow::bail!("the drain role requires --control-socket");
+            };
+            control::drain(path).await?;
+            return Ok(());
+        }
+        NodeRole::Stats => {
+            let Some(ref path) = config.control_socket else {
+                anyhow::bail!("the stats role requires --control-socket");
+            };
+            control::stats(path, config.output_format).await?;
+            return Ok(());
+        }
+        NodeRole::Inspect => {
+            let Some(ref path) = config.control_socket else {
+                anyhow::bail!("the inspect role requires --control-socket");
+            };
+            control::inspect(path, config.output_format).await?;
+            return Ok(());
+        }
+        NodeRole::SlowLog => {
+            let Some(ref path) = config.control_socket else {
+                anyhow::bail!("the slowlog role requires --control-socket");
+            };
+            control::slow_log(path, config.output_format).await?;
+            return Ok(());
+        }
+        NodeRole::WarmUp => {
+            let Some(ref path) = config.control_socket else {
+                anyhow::bail!("the warmup role requires --control-socket");
+            };
+            let request = WarmUpRequest {
+                paths: warmup_paths(&config).await?,
+                bandwidth: config.warmup_bandwidth,
+                pin: false,
+            };
+            control::warm_up(path, &request).await?;
+            return Ok(());
+        }
+        NodeRole::Pin => {
+            let Some(ref path) = config.control_socket else {
+                anyhow::bail!("the pin role requires --control-socket");
+            };
+            let paths = warmup_paths(&config).await?;
+            if paths.is_empty() {
+                control::list_pins(path, config.output_format).await?;
+            } else if config.unpin {
+                control::unpin(path, &paths, config.output_format).await?;
+            } else {
+                let request = WarmUpRequest {
+                    paths,
+                    bandwidth: config.warmup_bandwidth,
+                    pin: true,
+                };
+                control::warm_up(path, &request).await?;
+            }
+            return Ok(());
+        }
+        NodeRole::Health => {
+            let Some(port) = config.health_port else {
+                anyhow::bail!("the health role requires --health-port");
+            };
+            if let Err(problems) = health::check_health(port, config.health_probe).await? {
+                anyhow::bail!("the {:?} probe fails: {problems}", config.health_probe);
+            }
+            println!("ok");
+            return Ok(());
+        }
+        NodeRole::Usage => {
+            let kv_engine = KVEngineType::new(config.kv_addrs.clone()).await?;
+            let report = billing::report(&kv_engine, SystemTime::now()).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            return Ok(());
+        }
+        NodeRole::Bench => {
+            let bench_config = &config.bench_config;
+            let report = match bench_config.target {
+                BenchTarget::Core => {
+                    let kv_engine = Arc::new(KVEngineType::new(config.kv_addrs.clone()).await?);
+                    let async_args = AsyncFuseArgs {
+                        node_id: config.node_name.clone(),
+                        ip_address: config.node_ip,
+                        server_port: config.server_port,
+                        mount_dir: config.mount_path.clone(),
+                        storage_config: config.storage,
+                        snapshot: None,
+                        virtiofs_socket: None,
+                        // The benchmark runs as root.
+                        id_map: IdMap::default(),
+                        squash: Squash::default(),
+                        op_mask: OpMask::default(),
+                        selinux_context: None,
+                        auth: AuthConfig::default(),
+                    };
+                    let (fs, _) = async_fuse::open_memfs(kv_engine, &async_args).await?;
+                    bench::bench_core(fs, bench_config).await?
+                }
+                BenchTarget::Mount => {
+                    bench::bench_mount(Path::new(&config.mount_path), bench_config).await?
+                }
+            };
+            println!("{report}");
+            return Ok(());
+        }
+        NodeRole::FuseDump => {
+            let Some(ref path) = config.fuse_trace else {
+                anyhow::bail!("the fusedump role requires --fuse-trace");
+            };
+            if !config.fusedump_replay {
+                fusedump::print_trace(path, &mut std::io::stdout().lock())?;
+                return Ok(());
+            }
+            let kv_engine = Arc::new(KVEngineType::new(config.kv_addrs.clone()).await?);
+            let async_args = AsyncFuseArgs {
+                node_id: config.node_name.clone(),
+                ip_address: config.node_ip,
+                server_port: config.server_port,
+                mount_dir: config.mount_path.clone(),
+                storage_config: config.storage,
+                snapshot: None,
+                virtiofs_socket: None,
+                // The requests are replayed as they're recorded.
+                id_map: IdMap::default(),
+                squash: Squash::default(),
+                op_mask: OpMask::default(),
+                selinux_context: None,
+                auth: AuthConfig::default(),
+            };
+            let (fs, _) = async_fuse::open_memfs(kv_engine, &async_args).await?;
+            let report = fusedump::replay(path, Arc::new(fs)).await?;
+            println!("{report}");
+            return Ok(());
+        }
     }
 
     task_manager::wait_for_shutdown(&TASK_MANAGER)?.await;
+    if config.otlp_endpoint.is_some() {
+        telemetry::shutdown_tracer().await;
+    }
     Ok(())
 }
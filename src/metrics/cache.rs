@@ -3,7 +3,7 @@
 use once_cell::sync::Lazy;
 use prometheus::{register_counter_vec_with_registry, CounterVec, Registry};
 
-use super::DATENLORD_REGISTRY;
+use super::{LossyCast, DATENLORD_REGISTRY};
 
 /// The file caches related metrics.
 pub static CACHE_METRICS: Lazy<CacheMetrics> = Lazy::new(|| CacheMetrics::new(&DATENLORD_REGISTRY));
@@ -11,14 +11,16 @@ pub static CACHE_METRICS: Lazy<CacheMetrics> = Lazy::new(|| CacheMetrics::new(&D
 /// The file caches related metrics.
 #[derive(Debug)]
 pub struct CacheMetrics {
-    /// The counters of total of cache hits. With label: `[name]`.
+    /// The counters of total of cache hits. With label: `[name, policy]`.
     cache_hit_count: CounterVec,
-    /// The counters of total of cache misses. With label: `[name]`
+    /// The counters of total of cache misses. With label: `[name, policy]`
     cache_miss_count: CounterVec,
+    /// The counters of total of cache evictions. With label: `[name, policy]`
+    cache_eviction_count: CounterVec,
 }
 
 impl CacheMetrics {
-    /// Creates an instance of `CacheMetrics`, which will create two
+    /// Creates an instance of `CacheMetrics`, which will create three
     /// `CounterVec`s and register them into the specified registry.
     ///
     /// # Panics
@@ -29,7 +31,7 @@ impl CacheMetrics {
         let cache_hit_count = register_counter_vec_with_registry!(
             "cache_hit_count",
             "The total of cache hits",
-            &["name"],
+            &["name", "policy"],
             registry,
         )
         .expect("Metrics name must be unique.");
@@ -37,7 +39,15 @@ impl CacheMetrics {
         let cache_miss_count = register_counter_vec_with_registry!(
             "cache_miss_count",
             "The total of cache misses",
-            &["name"],
+            &["name", "policy"],
+            registry,
+        )
+        .expect("Metrics name must be unique.");
+
+        let cache_eviction_count = register_counter_vec_with_registry!(
+            "cache_eviction_count",
+            "The total of cache evictions",
+            &["name", "policy"],
             registry,
         )
         .expect("Metrics name must be unique.");
@@ -45,16 +55,36 @@ impl CacheMetrics {
         Self {
             cache_hit_count,
             cache_miss_count,
+            cache_eviction_count,
         }
     }
 
-    /// Increase the hit count with `name`.
-    pub fn cache_hit_count_inc(&self, name: &str) {
-        self.cache_hit_count.with_label_values(&[name]).inc();
+    /// Increase the hit count with `name` of the cache, and the `policy` it
+    /// uses. Hit rates of policies can be compared with the `policy` label.
+    pub fn cache_hit_count_inc(&self, name: &str, policy: &str) {
+        self.cache_hit_count
+            .with_label_values(&[name, policy])
+            .inc();
+    }
+
+    /// Increase the miss count with `name` of the cache, and the `policy` it
+    /// uses.
+    pub fn cache_miss_count_inc(&self, name: &str, policy: &str) {
+        self.cache_miss_count
+            .with_label_values(&[name, policy])
+            .inc();
     }
 
-    /// Increase the miss count with `name`.
-    pub fn cache_miss_count_inc(&self, name: &str) {
-        self.cache_miss_count.with_label_values(&[name]).inc();
+    /// Increase the eviction count with `name` of the cache, and the `policy`
+    /// it uses, by `count`.
+    pub fn cache_eviction_count_inc_by<T: LossyCast<f64>>(
+        &self,
+        name: &str,
+        policy: &str,
+        count: T,
+    ) {
+        self.cache_eviction_count
+            .with_label_values(&[name, policy])
+            .inc_by(count.lossy_cast());
     }
 }
@@ -17,6 +17,7 @@ pub use self::cache::CACHE_METRICS;
 pub use self::file_system::FILESYSTEM_METRICS;
 pub use self::kv::KV_METRICS;
 pub use self::server::start_metrics_server;
+pub use self::storage::STORAGE_METRICS;
 pub use self::utils::LossyCast;
 
 /// The global metrics registry used by `DatenLord`.
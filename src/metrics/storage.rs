@@ -1,4 +1,198 @@
 //! Metrics for storage (besides of cache).
 //!
-//! This module is unused for now, and the metrics of storage
-//! are delegated to `opendal::layers::PrometheusLayer`.
+//! The metrics of backend operations are delegated to
+//! `opendal::layers::PrometheusLayer`.
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_counter_vec_with_registry, register_int_counter_vec_with_registry,
+    register_int_counter_with_registry, register_int_gauge_with_registry, CounterVec, IntCounter,
+    IntCounterVec, IntGauge, Registry,
+};
+
+use super::DATENLORD_REGISTRY;
+
+/// The storage related metrics.
+pub static STORAGE_METRICS: Lazy<StorageMetrics> =
+    Lazy::new(|| StorageMetrics::new(&DATENLORD_REGISTRY));
+
+/// The storage related metrics.
+#[derive(Debug)]
+pub struct StorageMetrics {
+    /// The counters of total of checksum mismatches. With label: `[source]`.
+    checksum_mismatch_count: CounterVec,
+    /// The counter of total bytes of orphaned objects deleted by GC.
+    gc_reclaimed_bytes: IntCounter,
+    /// The counter of total blocks verified by the scrubber.
+    scrub_scanned_blocks: IntCounter,
+    /// The gauge of blocks left to check by the running scrub pass.
+    scrub_pending_blocks: IntGauge,
+    /// The counters of total of corrupted blocks found by the scrubber. With
+    /// label: `[outcome]`.
+    scrub_repair_count: IntCounterVec,
+    /// The counter of total scrub passes failed.
+    scrub_failed_passes: IntCounter,
+    /// The counter of total reads of blocks from the replicas.
+    replica_read_count: IntCounter,
+    /// The counters of total of reads of replicas hedged by another replica.
+    /// With label: `[winner]`.
+    replica_hedged_read_count: IntCounterVec,
+    /// The counters of total trips of circuit breakers. With label:
+    /// `[target]`.
+    breaker_trip_count: IntCounterVec,
+    /// The counters of total reads of blocks from the caches of other nodes.
+    /// With label: `[result]`.
+    peer_cache_read_count: IntCounterVec,
+}
+
+impl StorageMetrics {
+    /// Creates an instance of `StorageMetrics`, which will create a
+    /// `CounterVec` and register it into the specified registry.
+    ///
+    /// # Panics
+    /// This method panics if it called multiple times on the same registry.
+    #[allow(clippy::expect_used)] // We can ensure that this method won't panic if we followed the hints above
+    #[allow(clippy::ignored_unit_patterns)] // Raised by `register_counter_vec_with_registry`
+    fn new(registry: &Registry) -> Self {
+        let checksum_mismatch_count = register_counter_vec_with_registry!(
+            "checksum_mismatch_count",
+            "The total of blocks mismatching their checksums",
+            &["source"],
+            registry,
+        )
+        .expect("Metrics name must be unique.");
+        let gc_reclaimed_bytes = register_int_counter_with_registry!(
+            "gc_reclaimed_bytes",
+            "The total bytes of orphaned objects deleted by GC",
+            registry,
+        )
+        .expect("Metrics name must be unique.");
+        let scrub_scanned_blocks = register_int_counter_with_registry!(
+            "scrub_scanned_blocks",
+            "The total of blocks verified by the scrubber",
+            registry,
+        )
+        .expect("Metrics name must be unique.");
+        let scrub_pending_blocks = register_int_gauge_with_registry!(
+            "scrub_pending_blocks",
+            "The blocks left to check by the running scrub pass",
+            registry,
+        )
+        .expect("Metrics name must be unique.");
+        let scrub_repair_count = register_int_counter_vec_with_registry!(
+            "scrub_repair_count",
+            "The total of corrupted blocks found by the scrubber by the outcomes of repairs",
+            &["outcome"],
+            registry,
+        )
+        .expect("Metrics name must be unique.");
+        let scrub_failed_passes = register_int_counter_with_registry!(
+            "scrub_failed_passes",
+            "The total of scrub passes failed",
+            registry,
+        )
+        .expect("Metrics name must be unique.");
+        let replica_read_count = register_int_counter_with_registry!(
+            "replica_read_count",
+            "The total of reads of blocks from the replicas",
+            registry,
+        )
+        .expect("Metrics name must be unique.");
+        let replica_hedged_read_count = register_int_counter_vec_with_registry!(
+            "replica_hedged_read_count",
+            "The total of reads of replicas hedged by another replica by the winners",
+            &["winner"],
+            registry,
+        )
+        .expect("Metrics name must be unique.");
+        let breaker_trip_count = register_int_counter_vec_with_registry!(
+            "breaker_trip_count",
+            "The total trips of circuit breakers by the stores they guard",
+            &["target"],
+            registry,
+        )
+        .expect("Metrics name must be unique.");
+        let peer_cache_read_count = register_int_counter_vec_with_registry!(
+            "peer_cache_read_count",
+            "The total reads of blocks from the caches of other nodes by the results",
+            &["result"],
+            registry,
+        )
+        .expect("Metrics name must be unique.");
+
+        Self {
+            checksum_mismatch_count,
+            gc_reclaimed_bytes,
+            scrub_scanned_blocks,
+            scrub_pending_blocks,
+            scrub_repair_count,
+            scrub_failed_passes,
+            replica_read_count,
+            replica_hedged_read_count,
+            breaker_trip_count,
+            peer_cache_read_count,
+        }
+    }
+
+    /// Increase the checksum mismatch count, `source` is the operation found
+    /// the mismatch, such as `read` and `scrub`.
+    pub fn checksum_mismatch_count_inc(&self, source: &str) {
+        self.checksum_mismatch_count
+            .with_label_values(&[source])
+            .inc();
+    }
+
+    /// Increase the bytes reclaimed by GC.
+    pub fn gc_reclaimed_bytes_inc_by(&self, bytes: u64) {
+        self.gc_reclaimed_bytes.inc_by(bytes);
+    }
+
+    /// Increase the blocks verified by the scrubber.
+    pub fn scrub_scanned_blocks_inc(&self) {
+        self.scrub_scanned_blocks.inc();
+    }
+
+    /// Set the blocks left to check by the running scrub pass.
+    pub fn scrub_pending_blocks_set(&self, blocks: usize) {
+        self.scrub_pending_blocks
+            .set(i64::try_from(blocks).unwrap_or(i64::MAX));
+    }
+
+    /// Increase the corrupted blocks found by the scrubber, `outcome` is the
+    /// outcome of the repair, such as `repaired` and `unrepairable`.
+    pub fn scrub_repair_count_inc(&self, outcome: &str) {
+        self.scrub_repair_count.with_label_values(&[outcome]).inc();
+    }
+
+    /// Increase the scrub passes failed.
+    pub fn scrub_failed_passes_inc(&self) {
+        self.scrub_failed_passes.inc();
+    }
+
+    /// Increase the reads of blocks from the replicas.
+    pub fn replica_read_count_inc(&self) {
+        self.replica_read_count.inc();
+    }
+
+    /// Increase the reads of replicas hedged by another replica, `winner` is
+    /// the read returning the block, such as `primary`, `hedge` and `none`.
+    pub fn replica_hedged_read_count_inc(&self, winner: &str) {
+        self.replica_hedged_read_count
+            .with_label_values(&[winner])
+            .inc();
+    }
+
+    /// Increase the trips of the circuit breaker guarding `target`, such as a
+    /// replica node.
+    pub fn breaker_trip_count_inc(&self, target: &str) {
+        self.breaker_trip_count.with_label_values(&[target]).inc();
+    }
+
+    /// Increase the reads of blocks from the caches of other nodes, `result`
+    /// is such as `hit`, `miss` and `error`.
+    pub fn peer_cache_read_count_inc(&self, result: &str) {
+        self.peer_cache_read_count
+            .with_label_values(&[result])
+            .inc();
+    }
+}
@@ -0,0 +1,807 @@
+//! The reconciler of `DatenlordCluster`.
+//!
+//! A cluster is deployed as the etcd `StatefulSet` of the metadata service,
+//! the `Deployment`s of the volume manager and the CSI controller, and the
+//! `DaemonSet` of the storage nodes, all owned by the cluster. A new image is
+//! rolled out component by component in that order, a component is only
+//! updated once the ones before it have finished rolling out and are ready, so
+//! the nodes serving the volumes are the last to change.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+use k8s_openapi::api::apps::v1::{DaemonSet, Deployment, StatefulSet};
+use k8s_openapi::api::core::v1::{PodSpec, Service};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use kube::api::{Patch, PatchParams};
+use kube::runtime::controller::Action;
+use kube::{Api, Resource};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use super::crd::{set_condition, DatenlordCluster, DatenlordClusterSpec, DatenlordClusterStatus};
+use super::{apply, kube_error, name_and_namespace, Context, FIELD_MANAGER};
+use crate::common::error::{DatenLordError, DatenLordResult};
+
+/// The client port of etcd.
+const ETCD_CLIENT_PORT: u16 = 2379;
+
+/// The peer port of etcd.
+const ETCD_PEER_PORT: u16 = 2380;
+
+/// The port the volume manager serves on.
+const VOLUME_MANAGER_PORT: u16 = 7950;
+
+/// The mount path of the file system on the storage nodes, which is the same
+/// inside and outside the container, as the volumes are bind mounted from it.
+const FUSE_MOUNT_DIR: &str = "/var/opt/datenlord-data";
+
+/// The interval to check a cluster during a rollout.
+const ROLLOUT_REQUEUE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The interval to check a cluster which has rolled out.
+const REQUEUE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// The rollout of a workload.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct Rollout {
+    /// The number of pods desired
+    pub desired: i32,
+    /// The number of pods of the latest template
+    pub updated: i32,
+    /// The number of pods ready
+    pub ready: i32,
+    /// Whether the controller of the workload has seen the latest spec
+    pub observed: bool,
+}
+
+impl Rollout {
+    /// Whether all the pods are of the latest template and ready.
+    pub const fn is_complete(self) -> bool {
+        self.observed && self.updated >= self.desired && self.ready >= self.desired
+    }
+
+    /// Whether all the pods are ready, some may be of a former template.
+    pub const fn is_ready(self) -> bool {
+        self.ready >= self.desired
+    }
+}
+
+/// Whether the controller of a workload has seen the spec of `generation`.
+fn is_observed(generation: Option<i64>, observed: Option<i64>) -> bool {
+    matches!((generation, observed), (Some(generation), Some(observed)) if observed >= generation)
+}
+
+/// A workload deploying pods of a template.
+pub(super) trait Workload:
+    Resource<DynamicType = ()> + Clone + Debug + DeserializeOwned + Serialize
+{
+    /// The rollout of the workload.
+    fn rollout(&self) -> Rollout;
+
+    /// The spec of the pods.
+    fn pod_spec(&self) -> Option<&PodSpec>;
+
+    /// The image of the main container, which is the first one.
+    fn image(&self) -> Option<&str> {
+        self.pod_spec()?.containers.first()?.image.as_deref()
+    }
+}
+
+impl Workload for StatefulSet {
+    fn rollout(&self) -> Rollout {
+        let desired = self.spec.as_ref().and_then(|spec| spec.replicas);
+        let status = self.status.as_ref();
+        Rollout {
+            desired: desired.unwrap_or(1),
+            updated: status.and_then(|s| s.updated_replicas).unwrap_or(0),
+            ready: status.and_then(|s| s.ready_replicas).unwrap_or(0),
+            observed: is_observed(
+                self.metadata.generation,
+                status.and_then(|s| s.observed_generation),
+            ),
+        }
+    }
+
+    fn pod_spec(&self) -> Option<&PodSpec> {
+        self.spec.as_ref()?.template.spec.as_ref()
+    }
+}
+
+impl Workload for Deployment {
+    fn rollout(&self) -> Rollout {
+        let desired = self.spec.as_ref().and_then(|spec| spec.replicas);
+        let status = self.status.as_ref();
+        Rollout {
+            desired: desired.unwrap_or(1),
+            updated: status.and_then(|s| s.updated_replicas).unwrap_or(0),
+            ready: status.and_then(|s| s.ready_replicas).unwrap_or(0),
+            observed: is_observed(
+                self.metadata.generation,
+                status.and_then(|s| s.observed_generation),
+            ),
+        }
+    }
+
+    fn pod_spec(&self) -> Option<&PodSpec> {
+        self.spec.as_ref()?.template.spec.as_ref()
+    }
+}
+
+impl Workload for DaemonSet {
+    fn rollout(&self) -> Rollout {
+        let status = self.status.as_ref();
+        Rollout {
+            desired: status.map_or(0, |s| s.desired_number_scheduled),
+            updated: status.and_then(|s| s.updated_number_scheduled).unwrap_or(0),
+            ready: status.map_or(0, |s| s.number_ready),
+            observed: is_observed(
+                self.metadata.generation,
+                status.and_then(|s| s.observed_generation),
+            ),
+        }
+    }
+
+    fn pod_spec(&self) -> Option<&PodSpec> {
+        self.spec.as_ref()?.template.spec.as_ref()
+    }
+}
+
+/// A component of a cluster at the end of a reconciliation.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Component {
+    /// The type of the condition of the component
+    pub condition: &'static str,
+    /// The rollout of the component
+    pub rollout: Rollout,
+    /// Whether the component is updated to the image of the cluster
+    pub image_updated: bool,
+}
+
+impl Component {
+    /// Whether the component has rolled out the spec of the cluster.
+    const fn is_rolled_out(self) -> bool {
+        self.image_updated && self.rollout.is_complete()
+    }
+}
+
+/// The image a component is updated to, which is `desired` once the former
+/// components have rolled out, otherwise the `current` one is kept, if the
+/// component is deployed.
+pub(super) fn staged_image(
+    desired: &str,
+    current: Option<String>,
+    former_rolled_out: bool,
+) -> String {
+    match current {
+        Some(current) if !former_rolled_out => current,
+        Some(_) | None => desired.to_owned(),
+    }
+}
+
+/// Build the status of a cluster of `image` from its components.
+pub(super) fn build_status(
+    old: Option<&DatenlordClusterStatus>,
+    generation: Option<i64>,
+    image: &str,
+    components: &[Component],
+) -> DatenlordClusterStatus {
+    let mut conditions = old
+        .map(|status| status.conditions.clone())
+        .unwrap_or_default();
+    for component in components {
+        let rollout = component.rollout;
+        let (reason, message) = if rollout.is_ready() {
+            (
+                "Ready",
+                format!("{}/{} pods are ready", rollout.ready, rollout.desired),
+            )
+        } else {
+            (
+                "NotReady",
+                format!("{}/{} pods are ready", rollout.ready, rollout.desired),
+            )
+        };
+        set_condition(
+            &mut conditions,
+            component.condition,
+            rollout.is_ready(),
+            reason,
+            message,
+        );
+    }
+
+    let rolled_out = components.iter().all(|component| component.is_rolled_out());
+    if rolled_out {
+        set_condition(
+            &mut conditions,
+            "Progressing",
+            false,
+            "RolledOut",
+            format!("all components run {image}"),
+        );
+    } else {
+        set_condition(
+            &mut conditions,
+            "Progressing",
+            true,
+            "RollingOut",
+            format!("rolling out {image}"),
+        );
+    }
+    let ready = components
+        .iter()
+        .all(|component| component.rollout.is_ready());
+    let (reason, message) = if ready {
+        ("Ready", "all components are ready".to_owned())
+    } else {
+        ("NotReady", "some components are not ready".to_owned())
+    };
+    set_condition(&mut conditions, "Ready", ready, reason, message);
+
+    DatenlordClusterStatus {
+        observed_generation: generation,
+        image: if rolled_out {
+            Some(image.to_owned())
+        } else {
+            old.and_then(|status| status.image.clone())
+        },
+        conditions,
+    }
+}
+
+/// The name of the metadata service of cluster `name`.
+fn etcd_name(name: &str) -> String {
+    format!("{name}-etcd")
+}
+
+/// The name of the volume manager of cluster `name`.
+fn volume_manager_name(name: &str) -> String {
+    format!("{name}-volume-manager")
+}
+
+/// The name of the CSI controller of cluster `name`.
+fn controller_name(name: &str) -> String {
+    format!("{name}-controller")
+}
+
+/// The name of the storage nodes of cluster `name`.
+fn node_name(name: &str) -> String {
+    format!("{name}-node")
+}
+
+/// The endpoint of the volume manager of cluster `name` in `namespace`.
+pub(super) fn volume_manager_endpoint(name: &str, namespace: &str) -> String {
+    format!(
+        "http://{}.{namespace}.svc:{VOLUME_MANAGER_PORT}",
+        volume_manager_name(name)
+    )
+}
+
+/// The addresses of the etcd members of cluster `name`.
+pub(super) fn kv_server_list(name: &str, replicas: i32) -> String {
+    let etcd = etcd_name(name);
+    (0..replicas.max(1))
+        .map(|i| format!("{etcd}-{i}.{etcd}:{ETCD_CLIENT_PORT}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The labels of `component` of cluster `name`.
+fn labels(name: &str, component: &str) -> Value {
+    json!({
+        "app.kubernetes.io/name": "datenlord",
+        "app.kubernetes.io/instance": name,
+        "app.kubernetes.io/component": component,
+        "app.kubernetes.io/managed-by": FIELD_MANAGER,
+    })
+}
+
+/// The metadata of object `object_name` of `component` of cluster `name`.
+fn object_meta(name: &str, object_name: &str, component: &str, owner: &OwnerReference) -> Value {
+    json!({
+        "name": object_name,
+        "labels": labels(name, component),
+        "ownerReferences": [owner],
+    })
+}
+
+/// The environment variables of the pod name, the pod IP and the node name.
+fn pod_env() -> Value {
+    json!([
+        { "name": "POD_NAME", "valueFrom": { "fieldRef": { "fieldPath": "metadata.name" } } },
+        { "name": "POD_IP", "valueFrom": { "fieldRef": { "fieldPath": "status.podIP" } } },
+        { "name": "NODE_ID", "valueFrom": { "fieldRef": { "fieldPath": "spec.nodeName" } } },
+        { "name": "RUST_LOG", "value": "info" },
+        { "name": "RUST_BACKTRACE", "value": "full" },
+    ])
+}
+
+/// The mount of volume `name` at `path`, which propagates the mounts both ways
+/// if `bidirectional`.
+fn mount(name: &str, path: &str, bidirectional: bool) -> Value {
+    if bidirectional {
+        json!({ "name": name, "mountPath": path, "mountPropagation": "Bidirectional" })
+    } else {
+        json!({ "name": name, "mountPath": path })
+    }
+}
+
+/// The volume `name` of `path` on the host of `type_`.
+fn host_path(name: &str, path: &str, type_: &str) -> Value {
+    json!({ "name": name, "hostPath": { "path": path, "type": type_ } })
+}
+
+/// A sidecar of the CSI controller talking to it on the socket.
+fn controller_sidecar(name: &str, image: &str, args: &[&str]) -> Value {
+    json!({
+        "name": name,
+        "image": image,
+        "imagePullPolicy": "IfNotPresent",
+        "args": args,
+        "env": [{ "name": "ADDRESS", "value": "/plugin/controller.sock" }],
+        // This is necessary only for systems with SELinux, where
+        // non-privileged sidecar containers cannot access unix domain socket
+        // created by privileged CSI driver container.
+        "securityContext": { "privileged": true },
+        "volumeMounts": [{ "name": "socket-dir", "mountPath": "/plugin" }],
+    })
+}
+
+/// The headless service of the metadata service of cluster `name`.
+pub(super) fn etcd_service(name: &str, owner: &OwnerReference) -> DatenLordResult<Service> {
+    let etcd = etcd_name(name);
+    Ok(serde_json::from_value(json!({
+        "apiVersion": "v1",
+        "kind": "Service",
+        "metadata": object_meta(name, &etcd, "metadata", owner),
+        "spec": {
+            "clusterIP": "None",
+            "publishNotReadyAddresses": true,
+            "selector": labels(name, "metadata"),
+            "ports": [
+                { "name": "client", "port": ETCD_CLIENT_PORT },
+                { "name": "peer", "port": ETCD_PEER_PORT },
+            ],
+        },
+    }))?)
+}
+
+/// The `StatefulSet` of the metadata service of cluster `name`.
+pub(super) fn etcd_statefulset(
+    name: &str,
+    spec: &DatenlordClusterSpec,
+    owner: &OwnerReference,
+) -> DatenLordResult<StatefulSet> {
+    let etcd = etcd_name(name);
+    let replicas = spec.metadata.replicas.max(1);
+    let peer_url = format!("http://$(POD_NAME).{etcd}:{ETCD_PEER_PORT}");
+    let client_url = format!("http://$(POD_NAME).{etcd}:{ETCD_CLIENT_PORT}");
+    let initial_cluster = (0..replicas)
+        .map(|i| format!("{etcd}-{i}=http://{etcd}-{i}.{etcd}:{ETCD_PEER_PORT}"))
+        .collect::<Vec<_>>()
+        .join(",");
+    Ok(serde_json::from_value(json!({
+        "apiVersion": "apps/v1",
+        "kind": "StatefulSet",
+        "metadata": object_meta(name, &etcd, "metadata", owner),
+        "spec": {
+            "serviceName": etcd,
+            "replicas": replicas,
+            // The members find each other to form the cluster.
+            "podManagementPolicy": "Parallel",
+            "updateStrategy": { "type": "RollingUpdate" },
+            "selector": { "matchLabels": labels(name, "metadata") },
+            "template": {
+                "metadata": { "labels": labels(name, "metadata") },
+                "spec": {
+                    "containers": [{
+                        "name": "etcd",
+                        "image": spec.metadata.image,
+                        "imagePullPolicy": "IfNotPresent",
+                        "command": ["/usr/local/bin/etcd"],
+                        "args": [
+                            "--name=$(POD_NAME)",
+                            format!("--initial-advertise-peer-urls={peer_url}"),
+                            format!("--listen-peer-urls=http://0.0.0.0:{ETCD_PEER_PORT}"),
+                            format!("--advertise-client-urls={client_url}"),
+                            format!("--listen-client-urls=http://0.0.0.0:{ETCD_CLIENT_PORT}"),
+                            format!("--initial-cluster={initial_cluster}"),
+                            format!("--initial-cluster-token={etcd}"),
+                            "--initial-cluster-state=new",
+                            "--listen-metrics-urls=http://0.0.0.0:2381",
+                            "--data-dir=/var/lib/etcd/default.etcd",
+                        ],
+                        "env": pod_env(),
+                        "ports": [
+                            { "containerPort": ETCD_CLIENT_PORT, "name": "client" },
+                            { "containerPort": ETCD_PEER_PORT, "name": "peer" },
+                        ],
+                        "readinessProbe": {
+                            "httpGet": { "path": "/health", "port": 2381 },
+                            "periodSeconds": 10,
+                        },
+                        "volumeMounts": [{ "name": "data", "mountPath": "/var/lib/etcd" }],
+                    }],
+                },
+            },
+            "volumeClaimTemplates": [{
+                "metadata": { "name": "data" },
+                "spec": {
+                    "accessModes": ["ReadWriteOnce"],
+                    "resources": { "requests": { "storage": spec.metadata.storage_size } },
+                },
+            }],
+        },
+    }))?)
+}
+
+/// The service of the volume manager of cluster `name`.
+pub(super) fn volume_manager_service(
+    name: &str,
+    owner: &OwnerReference,
+) -> DatenLordResult<Service> {
+    Ok(serde_json::from_value(json!({
+        "apiVersion": "v1",
+        "kind": "Service",
+        "metadata": object_meta(name, &volume_manager_name(name), "volume-manager", owner),
+        "spec": {
+            "selector": labels(name, "volume-manager"),
+            "ports": [{ "name": "grpc", "port": VOLUME_MANAGER_PORT }],
+        },
+    }))?)
+}
+
+/// The `Deployment` of the volume manager of cluster `name` running `image`.
+pub(super) fn volume_manager_deployment(
+    name: &str,
+    spec: &DatenlordClusterSpec,
+    image: &str,
+    owner: &OwnerReference,
+) -> DatenLordResult<Deployment> {
+    let kv_server_list = kv_server_list(name, spec.metadata.replicas);
+    Ok(serde_json::from_value(json!({
+        "apiVersion": "apps/v1",
+        "kind": "Deployment",
+        "metadata": object_meta(name, &volume_manager_name(name), "volume-manager", owner),
+        "spec": {
+            "replicas": 1,
+            "selector": { "matchLabels": labels(name, "volume-manager") },
+            "template": {
+                "metadata": { "labels": labels(name, "volume-manager") },
+                "spec": {
+                    "containers": [{
+                        "name": "volume-manager",
+                        "image": image,
+                        "imagePullPolicy": "IfNotPresent",
+                        "args": [
+                            "--role=volumeManager",
+                            "--node-name=$(POD_NAME)",
+                            "--node-ip=$(POD_IP)",
+                            "--mount-path=/tmp/datenlord-data",
+                            format!("--kv-server-list={kv_server_list}"),
+                            format!("--volume-manager-listen=0.0.0.0:{VOLUME_MANAGER_PORT}"),
+                        ],
+                        "env": pod_env(),
+                        "ports": [{ "containerPort": VOLUME_MANAGER_PORT, "name": "grpc" }],
+                    }],
+                },
+            },
+        },
+    }))?)
+}
+
+/// The `Deployment` of the CSI controller of cluster `name` in `namespace`
+/// running `image`.
+pub(super) fn controller_deployment(
+    name: &str,
+    namespace: &str,
+    spec: &DatenlordClusterSpec,
+    image: &str,
+    owner: &OwnerReference,
+) -> DatenLordResult<Deployment> {
+    let driver_name = &spec.csi.driver_name;
+    let socket_dir = format!("/var/lib/kubelet/plugins/{driver_name}");
+    let kv_server_list = kv_server_list(name, spec.metadata.replicas);
+    let endpoint = volume_manager_endpoint(name, namespace);
+    Ok(serde_json::from_value(json!({
+        "apiVersion": "apps/v1",
+        "kind": "Deployment",
+        "metadata": object_meta(name, &controller_name(name), "controller", owner),
+        "spec": {
+            "replicas": 1,
+            "selector": { "matchLabels": labels(name, "controller") },
+            "template": {
+                "metadata": { "labels": labels(name, "controller") },
+                "spec": {
+                    "serviceAccountName": spec.csi.controller_service_account,
+                    "containers": [
+                        {
+                            "name": "datenlord-controller-plugin",
+                            "image": image,
+                            "imagePullPolicy": "IfNotPresent",
+                            "securityContext": { "privileged": true },
+                            "args": [
+                                "--role=controller",
+                                "--csi-endpoint=unix:///plugin/controller.sock",
+                                format!("--csi-driver-name={driver_name}"),
+                                "--node-name=$(NODE_ID)",
+                                "--node-ip=$(POD_IP)",
+                                "--csi-worker-port=50051",
+                                "--mount-path=/tmp/datenlord-data",
+                                format!("--kv-server-list={kv_server_list}"),
+                                format!("--volume-manager-endpoint={endpoint}"),
+                            ],
+                            "env": pod_env(),
+                            "volumeMounts": [{ "name": "socket-dir", "mountPath": "/plugin" }],
+                        },
+                        controller_sidecar(
+                            "csi-attacher",
+                            "quay.io/k8scsi/csi-attacher:v2.2.0",
+                            &["--v=5", "--csi-address=$(ADDRESS)"],
+                        ),
+                        controller_sidecar(
+                            "csi-provisioner",
+                            "quay.io/k8scsi/csi-provisioner:v1.6.0",
+                            &[
+                                "--csi-address=$(ADDRESS)",
+                                "--volume-name-prefix=datenlord-pv",
+                                "--feature-gates=Topology=true",
+                                "--strict-topology",
+                                "--v=5",
+                            ],
+                        ),
+                        controller_sidecar(
+                            "csi-snapshotter",
+                            "quay.io/k8scsi/csi-snapshotter:v2.1.1",
+                            &[
+                                "--csi-address=$(ADDRESS)",
+                                "--snapshot-name-prefix=datenlord-snapshot",
+                                "--v=5",
+                            ],
+                        ),
+                        controller_sidecar(
+                            "csi-resizer",
+                            "quay.io/k8scsi/csi-resizer:v0.5.0",
+                            &["--v=5", "--csi-address=$(ADDRESS)"],
+                        ),
+                    ],
+                    "volumes": [host_path("socket-dir", &socket_dir, "DirectoryOrCreate")],
+                },
+            },
+        },
+    }))?)
+}
+
+/// The `DaemonSet` of the storage nodes of cluster `name` in `namespace`
+/// running `image`.
+pub(super) fn node_daemonset(
+    name: &str,
+    namespace: &str,
+    spec: &DatenlordClusterSpec,
+    image: &str,
+    owner: &OwnerReference,
+) -> DatenLordResult<DaemonSet> {
+    let driver_name = &spec.csi.driver_name;
+    let mut args = vec![
+        "--role=node".to_owned(),
+        format!("--mount-path={FUSE_MOUNT_DIR}"),
+        "--csi-endpoint=unix:///plugin/node.sock".to_owned(),
+        format!("--csi-driver-name={driver_name}"),
+        format!(
+            "--kv-server-list={}",
+            kv_server_list(name, spec.metadata.replicas)
+        ),
+        "--node-name=$(NODE_ID)".to_owned(),
+        "--node-ip=$(POD_IP)".to_owned(),
+        "--server-port=8800".to_owned(),
+        format!("--storage-type={}", spec.storage.storage_type),
+        "--csi-worker-port=50051".to_owned(),
+        format!(
+            "--volume-manager-endpoint={}",
+            volume_manager_endpoint(name, namespace)
+        ),
+    ];
+    if spec.storage.storage_type == "fs" {
+        args.push("--storage-fs-root=/tmp/datenlord_backend".to_owned());
+    }
+    args.extend(spec.storage.args.iter().cloned());
+    let socket_dir = format!("/var/lib/kubelet/plugins/{driver_name}");
+    let registration_path = format!("{socket_dir}/node.sock");
+    Ok(serde_json::from_value(json!({
+        "apiVersion": "apps/v1",
+        "kind": "DaemonSet",
+        "metadata": object_meta(name, &node_name(name), "node", owner),
+        "spec": {
+            "selector": { "matchLabels": labels(name, "node") },
+            // The volumes on a node are unavailable while its pod is replaced.
+            "updateStrategy": { "type": "RollingUpdate", "rollingUpdate": { "maxUnavailable": 1 } },
+            "template": {
+                "metadata": { "labels": labels(name, "node") },
+                "spec": {
+                    "serviceAccountName": spec.csi.node_service_account,
+                    "nodeSelector": spec.storage.node_selector,
+                    "containers": [
+                        {
+                            "name": "datenlord",
+                            "image": image,
+                            "imagePullPolicy": "IfNotPresent",
+                            "securityContext": {
+                                "privileged": true,
+                                "allowPrivilegeEscalation": true,
+                            },
+                            "args": args,
+                            "env": pod_env(),
+                            "lifecycle": {
+                                "preStop": {
+                                    "exec": {
+                                        "command": [
+                                            "/bin/sh",
+                                            "/usr/local/bin/umount-in-container.sh",
+                                            FUSE_MOUNT_DIR,
+                                        ],
+                                    },
+                                },
+                            },
+                            "ports": [
+                                { "containerPort": 9897 },
+                                { "containerPort": 50051, "hostPort": 50051, "name": "workerport" },
+                            ],
+                            "volumeMounts": [
+                                mount("datenlord-data-dir", FUSE_MOUNT_DIR, true),
+                                mount("fuse-device", "/dev/fuse", true),
+                                { "name": "socket-dir", "mountPath": "/plugin" },
+                                mount("mountpoint-dir", "/var/lib/kubelet/pods", true),
+                                mount("datenlord-fs-backend", "/tmp/datenlord_backend", true),
+                            ],
+                        },
+                        {
+                            "name": "csi-node-driver-registrar",
+                            "image": "quay.io/k8scsi/csi-node-driver-registrar:v1.3.0",
+                            "imagePullPolicy": "IfNotPresent",
+                            "securityContext": { "privileged": true },
+                            "args": [
+                                "--v=5",
+                                "--csi-address=$(ADDRESS)",
+                                format!("--kubelet-registration-path={registration_path}"),
+                            ],
+                            "env": [
+                                { "name": "ADDRESS", "value": "/plugin/node.sock" },
+                                {
+                                    "name": "KUBE_NODE_NAME",
+                                    "valueFrom": { "fieldRef": { "fieldPath": "spec.nodeName" } },
+                                },
+                            ],
+                            "volumeMounts": [
+                                { "name": "socket-dir", "mountPath": "/plugin" },
+                                { "name": "registration-dir", "mountPath": "/registration" },
+                            ],
+                        },
+                    ],
+                    "volumes": [
+                        host_path("socket-dir", &socket_dir, "DirectoryOrCreate"),
+                        host_path("datenlord-data-dir", FUSE_MOUNT_DIR, "DirectoryOrCreate"),
+                        host_path("fuse-device", "/dev/fuse", "CharDevice"),
+                        host_path(
+                            "registration-dir",
+                            "/var/lib/kubelet/plugins_registry",
+                            "Directory",
+                        ),
+                        host_path("mountpoint-dir", "/var/lib/kubelet/pods", "Directory"),
+                        host_path(
+                            "datenlord-fs-backend",
+                            &spec.storage.backend_path,
+                            "DirectoryOrCreate",
+                        ),
+                    ],
+                },
+            },
+        },
+    }))?)
+}
+
+/// The image of workload `name` in `namespace`, `None` if it's not deployed.
+async fn current_image<K: Workload>(
+    ctx: &Context,
+    namespace: &str,
+    name: &str,
+) -> DatenLordResult<Option<String>> {
+    let workload = Api::<K>::namespaced(ctx.client.clone(), namespace)
+        .get_opt(name)
+        .await
+        .map_err(kube_error)?;
+    Ok(workload.and_then(|workload| workload.image().map(str::to_owned)))
+}
+
+/// Reconcile `cluster`, deploy or update its components and report its status.
+pub(super) async fn reconcile(
+    cluster: Arc<DatenlordCluster>,
+    ctx: Arc<Context>,
+) -> DatenLordResult<Action> {
+    let (name, namespace) = name_and_namespace(cluster.meta())?;
+    let owner =
+        cluster
+            .controller_owner_ref(&())
+            .ok_or_else(|| DatenLordError::ArgumentInvalid {
+                context: vec![format!("cluster {namespace}/{name} has no uid")],
+            })?;
+    let spec = &cluster.spec;
+    let desired = spec.image.as_str();
+
+    apply(&ctx, &namespace, &etcd_service(&name, &owner)?).await?;
+    let etcd = apply(&ctx, &namespace, &etcd_statefulset(&name, spec, &owner)?).await?;
+    let mut components = vec![Component {
+        condition: "MetadataReady",
+        rollout: etcd.rollout(),
+        image_updated: true,
+    }];
+    let rolled_out = |components: &[Component]| components.iter().all(|c| c.is_rolled_out());
+
+    apply(&ctx, &namespace, &volume_manager_service(&name, &owner)?).await?;
+    let current =
+        current_image::<Deployment>(&ctx, &namespace, &volume_manager_name(&name)).await?;
+    let image = staged_image(desired, current, rolled_out(&components));
+    let volume_manager = volume_manager_deployment(&name, spec, &image, &owner)?;
+    let volume_manager = apply(&ctx, &namespace, &volume_manager).await?;
+    components.push(Component {
+        condition: "VolumeManagerReady",
+        rollout: volume_manager.rollout(),
+        image_updated: image == desired,
+    });
+
+    let current = current_image::<Deployment>(&ctx, &namespace, &controller_name(&name)).await?;
+    let image = staged_image(desired, current, rolled_out(&components));
+    let controller = controller_deployment(&name, &namespace, spec, &image, &owner)?;
+    let controller = apply(&ctx, &namespace, &controller).await?;
+    components.push(Component {
+        condition: "ControllerReady",
+        rollout: controller.rollout(),
+        image_updated: image == desired,
+    });
+
+    let current = current_image::<DaemonSet>(&ctx, &namespace, &node_name(&name)).await?;
+    let image = staged_image(desired, current, rolled_out(&components));
+    let nodes = node_daemonset(&name, &namespace, spec, &image, &owner)?;
+    let nodes = apply(&ctx, &namespace, &nodes).await?;
+    components.push(Component {
+        condition: "NodesReady",
+        rollout: nodes.rollout(),
+        image_updated: image == desired,
+    });
+
+    let status = build_status(
+        cluster.status.as_ref(),
+        cluster.metadata.generation,
+        desired,
+        &components,
+    );
+    if cluster.status.as_ref().and_then(|s| s.image.as_deref()) != status.image.as_deref() {
+        if let Some(ref image) = status.image {
+            info!("cluster {namespace}/{name} has rolled out {image}");
+        }
+    }
+    let requeue = if rolled_out(&components) {
+        REQUEUE_INTERVAL
+    } else {
+        ROLLOUT_REQUEUE_INTERVAL
+    };
+    Api::<DatenlordCluster>::namespaced(ctx.client.clone(), &namespace)
+        .patch_status(
+            &name,
+            &PatchParams::default(),
+            &Patch::Merge(json!({ "status": status })),
+        )
+        .await
+        .map_err(kube_error)?;
+    Ok(Action::requeue(requeue))
+}
+
+/// Retry a cluster failed to reconcile later.
+#[allow(clippy::needless_pass_by_value)] // Required by `Controller::run`
+pub(super) fn error_policy(error: &DatenLordError, _ctx: Arc<Context>) -> Action {
+    warn!("failed to reconcile a cluster, the error is: {error}");
+    Action::requeue(ROLLOUT_REQUEUE_INTERVAL)
+}
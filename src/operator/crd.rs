@@ -0,0 +1,262 @@
+//! The custom resources reconciled by the operator.
+
+use std::collections::BTreeMap;
+
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// The image of the metadata service, an etcd cluster.
+const DEFAULT_ETCD_IMAGE: &str = "gcr.io/etcd-development/etcd:v3.4.13";
+
+/// The default name of the CSI driver.
+const DEFAULT_DRIVER_NAME: &str = "csi.datenlord.io";
+
+/// A datenlord cluster, which is the metadata service, the volume manager, the
+/// CSI controller and the storage nodes serving the CSI node service.
+#[derive(CustomResource, Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+#[kube(
+    group = "datenlord.io",
+    version = "v1alpha1",
+    kind = "DatenlordCluster",
+    namespaced,
+    status = "DatenlordClusterStatus",
+    shortname = "dlc",
+    printcolumn = r#"{"name":"Image", "type":"string", "jsonPath":".spec.image"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct DatenlordClusterSpec {
+    /// The image of datenlord, changing it rolls the cluster out to the image
+    pub image: String,
+    /// The metadata service
+    #[serde(default)]
+    pub metadata: MetadataSpec,
+    /// The storage nodes
+    #[serde(default)]
+    pub storage: StorageSpec,
+    /// The CSI driver
+    #[serde(default)]
+    pub csi: CsiSpec,
+}
+
+/// The metadata service of a cluster, an etcd cluster.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct MetadataSpec {
+    /// The image of etcd
+    #[serde(default = "default_etcd_image")]
+    pub image: String,
+    /// The number of etcd members, which is fixed once the cluster is created
+    #[serde(default = "default_etcd_replicas")]
+    pub replicas: i32,
+    /// The size of the persistent volume of each member
+    #[serde(default = "default_etcd_storage_size")]
+    pub storage_size: String,
+}
+
+impl Default for MetadataSpec {
+    fn default() -> Self {
+        Self {
+            image: default_etcd_image(),
+            replicas: default_etcd_replicas(),
+            storage_size: default_etcd_storage_size(),
+        }
+    }
+}
+
+/// The storage nodes of a cluster, which run on every node selected.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageSpec {
+    /// The labels selecting the nodes to run on, all nodes if it's empty
+    #[serde(default)]
+    pub node_selector: BTreeMap<String, String>,
+    /// The backend of blocks: fs or s3
+    #[serde(default = "default_storage_type")]
+    pub storage_type: String,
+    /// The directory on the host of the fs backend
+    #[serde(default = "default_backend_path")]
+    pub backend_path: String,
+    /// The extra arguments of the storage nodes, such as the settings of the
+    /// S3 backend
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+impl Default for StorageSpec {
+    fn default() -> Self {
+        Self {
+            node_selector: BTreeMap::new(),
+            storage_type: default_storage_type(),
+            backend_path: default_backend_path(),
+            args: vec![],
+        }
+    }
+}
+
+/// The CSI driver of a cluster.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CsiSpec {
+    /// The name of the CSI driver
+    #[serde(default = "default_driver_name")]
+    pub driver_name: String,
+    /// The service account of the CSI controller
+    #[serde(default = "default_controller_service_account")]
+    pub controller_service_account: String,
+    /// The service account of the storage nodes
+    #[serde(default = "default_node_service_account")]
+    pub node_service_account: String,
+}
+
+impl Default for CsiSpec {
+    fn default() -> Self {
+        Self {
+            driver_name: default_driver_name(),
+            controller_service_account: default_controller_service_account(),
+            node_service_account: default_node_service_account(),
+        }
+    }
+}
+
+/// The status of a cluster.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DatenlordClusterStatus {
+    /// The generation of the spec the status is of
+    #[serde(default)]
+    pub observed_generation: Option<i64>,
+    /// The image all the components run, `None` during a rollout
+    #[serde(default)]
+    pub image: Option<String>,
+    /// The conditions of the components and the cluster
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+}
+
+/// A named volume of the volume manager of a cluster.
+#[derive(CustomResource, Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+#[kube(
+    group = "datenlord.io",
+    version = "v1alpha1",
+    kind = "DatenlordVolume",
+    namespaced,
+    status = "DatenlordVolumeStatus",
+    shortname = "dlv",
+    printcolumn = r#"{"name":"Cluster", "type":"string", "jsonPath":".spec.cluster"}"#,
+    printcolumn = r#"{"name":"Capacity", "type":"integer", "jsonPath":".spec.capacity"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct DatenlordVolumeSpec {
+    /// The name of the cluster in the same namespace
+    pub cluster: String,
+    /// The capacity in bytes, 0 for unlimited
+    #[serde(default)]
+    pub capacity: u64,
+    /// The parameters of the volume, the same as the ones of a `StorageClass`,
+    /// such as `storagePolicy` and `compression`
+    #[serde(default)]
+    pub parameters: BTreeMap<String, String>,
+}
+
+/// The status of a volume.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DatenlordVolumeStatus {
+    /// The id of the volume, which names its directory
+    #[serde(default)]
+    pub id: Option<String>,
+    /// The capacity of the volume in bytes
+    #[serde(default)]
+    pub capacity: Option<u64>,
+    /// The name of the secret holding the secret to mount the volume
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// The conditions of the volume
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+}
+
+/// A condition of a custom resource.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Condition {
+    /// The type of the condition, such as `Ready`
+    #[serde(rename = "type")]
+    pub type_: String,
+    /// `True` or `False`
+    pub status: String,
+    /// The reason of the last transition in `CamelCase`
+    pub reason: String,
+    /// The message of the last transition
+    pub message: String,
+    /// The time of the last transition in RFC 3339
+    pub last_transition_time: String,
+}
+
+/// Set the condition of `type_` in `conditions`, the transition time is kept
+/// if the status is not changed.
+pub fn set_condition(
+    conditions: &mut Vec<Condition>,
+    type_: &str,
+    status: bool,
+    reason: &str,
+    message: String,
+) {
+    let status = if status { "True" } else { "False" };
+    let condition = Condition {
+        type_: type_.to_owned(),
+        status: status.to_owned(),
+        reason: reason.to_owned(),
+        message,
+        last_transition_time: chrono::Utc::now().to_rfc3339(),
+    };
+    match conditions.iter_mut().find(|c| c.type_ == type_) {
+        Some(old) if old.status == status => {
+            old.reason = condition.reason;
+            old.message = condition.message;
+        }
+        Some(old) => *old = condition,
+        None => conditions.push(condition),
+    }
+}
+
+/// The default image of etcd.
+fn default_etcd_image() -> String {
+    DEFAULT_ETCD_IMAGE.to_owned()
+}
+
+/// The default number of etcd members.
+const fn default_etcd_replicas() -> i32 {
+    3
+}
+
+/// The default size of the persistent volume of an etcd member.
+fn default_etcd_storage_size() -> String {
+    "1Gi".to_owned()
+}
+
+/// The default backend of blocks.
+fn default_storage_type() -> String {
+    "fs".to_owned()
+}
+
+/// The default directory on the host of the fs backend.
+fn default_backend_path() -> String {
+    "/var/opt/datenlord-backend".to_owned()
+}
+
+/// The default name of the CSI driver.
+fn default_driver_name() -> String {
+    DEFAULT_DRIVER_NAME.to_owned()
+}
+
+/// The default service account of the CSI controller.
+fn default_controller_service_account() -> String {
+    "csi-controller-sa".to_owned()
+}
+
+/// The default service account of the storage nodes.
+fn default_node_service_account() -> String {
+    "csi-nodeplugin-sa".to_owned()
+}
@@ -0,0 +1,169 @@
+//! The Kubernetes operator of datenlord.
+//!
+//! The operator installs the `DatenlordCluster` and `DatenlordVolume` custom
+//! resource definitions, deploys the clusters declared and rolls them out to
+//! new images, and manages the volumes declared in the volume managers of
+//! their clusters.
+
+mod cluster;
+#[allow(missing_docs)] // The structs generated by `CustomResource` have no docs
+mod crd;
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests;
+mod volume;
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::StreamExt;
+use k8s_openapi::api::apps::v1::{DaemonSet, Deployment, StatefulSet};
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::api::{ListParams, Patch, PatchParams};
+use kube::runtime::controller::Controller;
+use kube::runtime::wait::{await_condition, conditions};
+use kube::{Api, Client, CustomResourceExt, Resource};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info};
+
+use self::crd::{DatenlordCluster, DatenlordVolume};
+use crate::common::error::{DatenLordError, DatenLordResult};
+
+/// The field manager of the objects applied by the operator.
+const FIELD_MANAGER: &str = "datenlord-operator";
+
+/// The timeout to wait for the custom resource definitions to be established.
+const CRD_ESTABLISH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The context shared by the reconcilers.
+struct Context {
+    /// The client of Kubernetes
+    client: Client,
+}
+
+impl Debug for Context {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Context").finish_non_exhaustive()
+    }
+}
+
+/// Convert an error of Kubernetes.
+fn kube_error(e: kube::Error) -> DatenLordError {
+    DatenLordError::InternalErr {
+        source: anyhow::Error::new(e),
+        context: vec!["failed to access Kubernetes".to_owned()],
+    }
+}
+
+/// The name and the namespace of a namespaced object.
+fn name_and_namespace(meta: &ObjectMeta) -> DatenLordResult<(String, String)> {
+    match (meta.name.clone(), meta.namespace.clone()) {
+        (Some(name), Some(namespace)) => Ok((name, namespace)),
+        (None, _) | (_, None) => Err(DatenLordError::ArgumentInvalid {
+            context: vec!["the object has no name or namespace".to_owned()],
+        }),
+    }
+}
+
+/// Apply `object` to `namespace` by server-side apply.
+async fn apply<K>(ctx: &Context, namespace: &str, object: &K) -> DatenLordResult<K>
+where
+    K: Resource<DynamicType = ()> + Clone + Debug + DeserializeOwned + Serialize,
+{
+    let name = object.meta().name.clone().unwrap_or_default();
+    Api::<K>::namespaced(ctx.client.clone(), namespace)
+        .patch(
+            &name,
+            &PatchParams::apply(FIELD_MANAGER).force(),
+            &Patch::Apply(object),
+        )
+        .await
+        .map_err(kube_error)
+}
+
+/// Install the custom resource definitions and wait for them to be served.
+async fn install_crds(client: &Client) -> DatenLordResult<()> {
+    let api = Api::<CustomResourceDefinition>::all(client.clone());
+    for crd in [DatenlordCluster::crd(), DatenlordVolume::crd()] {
+        let name = crd.metadata.name.clone().unwrap_or_default();
+        api.patch(
+            &name,
+            &PatchParams::apply(FIELD_MANAGER).force(),
+            &Patch::Apply(&crd),
+        )
+        .await
+        .map_err(kube_error)?;
+        let established = await_condition(api.clone(), &name, conditions::is_crd_established());
+        tokio::time::timeout(CRD_ESTABLISH_TIMEOUT, established)
+            .await
+            .map_err(|_| DatenLordError::InternalErr {
+                source: anyhow::anyhow!("timeout"),
+                context: vec![format!(
+                    "custom resource definition {name} is not established"
+                )],
+            })?
+            .map_err(|e| DatenLordError::InternalErr {
+                source: anyhow::Error::new(e),
+                context: vec![format!("failed to wait for {name}")],
+            })?;
+        info!("Installed custom resource definition {name}.");
+    }
+    Ok(())
+}
+
+/// Run the reconcilers until `token` is cancelled.
+#[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+async fn serve(token: CancellationToken) -> DatenLordResult<()> {
+    let client = Client::try_default().await.map_err(kube_error)?;
+    install_crds(&client).await?;
+    let ctx = Arc::new(Context {
+        client: client.clone(),
+    });
+
+    let clusters = Controller::new(
+        Api::<DatenlordCluster>::all(client.clone()),
+        ListParams::default(),
+    )
+    .owns(
+        Api::<StatefulSet>::all(client.clone()),
+        ListParams::default(),
+    )
+    .owns(
+        Api::<Deployment>::all(client.clone()),
+        ListParams::default(),
+    )
+    .owns(Api::<DaemonSet>::all(client.clone()), ListParams::default())
+    .run(cluster::reconcile, cluster::error_policy, Arc::clone(&ctx))
+    .for_each(|res| async move {
+        if let Ok((object, _)) = res {
+            debug!("reconciled cluster {}", object.name);
+        }
+    });
+    let volumes = Controller::new(Api::<DatenlordVolume>::all(client), ListParams::default())
+        .run(volume::reconcile, volume::error_policy, ctx)
+        .for_each(|res| async move {
+            if let Ok((object, _)) = res {
+                debug!("reconciled volume {}", object.name);
+            }
+        });
+    tokio::select! {
+        () = token.cancelled() => {}
+        ((), ()) = futures::future::join(clusters, volumes) => {
+            error!("The reconcilers of the operator exit unexpectedly.");
+        }
+    }
+    Ok(())
+}
+
+/// Run the operator until `token` is cancelled.
+pub async fn run_operator(token: CancellationToken) {
+    info!("Operator starts.");
+    if let Err(e) = serve(token).await {
+        error!("Operator failed: {e}");
+    }
+    info!("Operator exits.");
+}
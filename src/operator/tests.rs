@@ -0,0 +1,192 @@
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+
+use super::cluster::{
+    build_status, controller_deployment, etcd_service, etcd_statefulset, kv_server_list,
+    node_daemonset, staged_image, volume_manager_deployment, volume_manager_endpoint,
+    volume_manager_service, Component, Rollout, Workload,
+};
+use super::crd::{set_condition, Condition, DatenlordClusterSpec, DatenlordClusterStatus};
+use super::volume::{manager_volume_name, secret_name};
+
+/// The spec of a cluster of `image` with the defaults.
+fn cluster_spec(image: &str) -> DatenlordClusterSpec {
+    serde_json::from_value(serde_json::json!({ "image": image })).unwrap()
+}
+
+/// The owner reference of cluster `name`.
+fn owner(name: &str) -> OwnerReference {
+    OwnerReference {
+        api_version: "datenlord.io/v1alpha1".to_owned(),
+        kind: "DatenlordCluster".to_owned(),
+        name: name.to_owned(),
+        uid: "0000".to_owned(),
+        controller: Some(true),
+        ..OwnerReference::default()
+    }
+}
+
+/// The status of the condition of `type_`.
+fn condition_status<'a>(conditions: &'a [Condition], type_: &str) -> Option<&'a str> {
+    conditions
+        .iter()
+        .find(|c| c.type_ == type_)
+        .map(|c| c.status.as_str())
+}
+
+#[test]
+fn test_cluster_spec_defaults() {
+    let spec = cluster_spec("datenlord/datenlord:e2e_test");
+    assert_eq!(spec.metadata.replicas, 3);
+    assert_eq!(spec.storage.storage_type, "fs");
+    assert_eq!(spec.csi.driver_name, "csi.datenlord.io");
+}
+
+#[test]
+fn test_cluster_objects() {
+    let spec = cluster_spec("datenlord/datenlord:v2");
+    let owner = owner("dl");
+    assert_eq!(
+        kv_server_list("dl", 2),
+        "dl-etcd-0.dl-etcd:2379,dl-etcd-1.dl-etcd:2379"
+    );
+    assert_eq!(
+        volume_manager_endpoint("dl", "storage"),
+        "http://dl-volume-manager.storage.svc:7950"
+    );
+
+    let service = etcd_service("dl", &owner).unwrap();
+    assert_eq!(service.metadata.name.as_deref(), Some("dl-etcd"));
+    assert_eq!(service.spec.unwrap().cluster_ip.as_deref(), Some("None"));
+    let etcd = etcd_statefulset("dl", &spec, &owner).unwrap();
+    assert_eq!(etcd.spec.as_ref().unwrap().replicas, Some(3));
+    assert_eq!(etcd.image(), Some(spec.metadata.image.as_str()));
+    assert_eq!(
+        etcd.metadata.owner_references.as_ref().unwrap().first(),
+        Some(&owner)
+    );
+    volume_manager_service("dl", &owner).unwrap();
+
+    let volume_manager = volume_manager_deployment("dl", &spec, "old", &owner).unwrap();
+    assert_eq!(volume_manager.image(), Some("old"));
+    let controller = controller_deployment("dl", "storage", &spec, "new", &owner).unwrap();
+    assert_eq!(controller.image(), Some("new"));
+    let containers = &controller.spec.unwrap().template.spec.unwrap().containers;
+    assert_eq!(containers.len(), 5);
+    assert!(containers.first().unwrap().args.as_ref().unwrap().contains(
+        &"--volume-manager-endpoint=http://dl-volume-manager.storage.svc:7950".to_owned()
+    ));
+
+    let nodes = node_daemonset("dl", "storage", &spec, "new", &owner).unwrap();
+    assert_eq!(nodes.image(), Some("new"));
+    let args = nodes
+        .pod_spec()
+        .unwrap()
+        .containers
+        .first()
+        .unwrap()
+        .args
+        .clone()
+        .unwrap();
+    assert!(args.contains(&"--storage-fs-root=/tmp/datenlord_backend".to_owned()));
+    assert!(args.contains(&format!("--kv-server-list={}", kv_server_list("dl", 3))));
+}
+
+#[test]
+fn test_staged_image() {
+    // A component not deployed yet starts with the desired image.
+    assert_eq!(staged_image("v2", None, false), "v2");
+    // A deployed one waits for the former components.
+    assert_eq!(staged_image("v2", Some("v1".to_owned()), false), "v1");
+    assert_eq!(staged_image("v2", Some("v1".to_owned()), true), "v2");
+}
+
+#[test]
+fn test_rollout() {
+    let rollout = Rollout {
+        desired: 3,
+        updated: 1,
+        ready: 3,
+        observed: true,
+    };
+    assert!(rollout.is_ready());
+    assert!(!rollout.is_complete());
+    let rollout = Rollout {
+        updated: 3,
+        ..rollout
+    };
+    assert!(rollout.is_complete());
+    assert!(!Rollout {
+        observed: false,
+        ..rollout
+    }
+    .is_complete());
+}
+
+#[test]
+fn test_build_status() {
+    let complete = Rollout {
+        desired: 1,
+        updated: 1,
+        ready: 1,
+        observed: true,
+    };
+    let rolling = [
+        Component {
+            condition: "MetadataReady",
+            rollout: complete,
+            image_updated: true,
+        },
+        Component {
+            condition: "NodesReady",
+            rollout: complete,
+            image_updated: false,
+        },
+    ];
+    let old = DatenlordClusterStatus {
+        image: Some("v1".to_owned()),
+        ..DatenlordClusterStatus::default()
+    };
+    let status = build_status(Some(&old), Some(2), "v2", &rolling);
+    assert_eq!(status.observed_generation, Some(2));
+    assert_eq!(status.image.as_deref(), Some("v1"));
+    assert_eq!(
+        condition_status(&status.conditions, "Progressing"),
+        Some("True")
+    );
+    assert_eq!(condition_status(&status.conditions, "Ready"), Some("True"));
+
+    let rolled_out = [Component {
+        image_updated: true,
+        ..*rolling.get(1).unwrap()
+    }];
+    let status = build_status(Some(&status), Some(2), "v2", &rolled_out);
+    assert_eq!(status.image.as_deref(), Some("v2"));
+    assert_eq!(
+        condition_status(&status.conditions, "Progressing"),
+        Some("False")
+    );
+    assert_eq!(
+        condition_status(&status.conditions, "NodesReady"),
+        Some("True")
+    );
+}
+
+#[test]
+fn test_set_condition() {
+    let mut conditions = vec![];
+    set_condition(&mut conditions, "Ready", false, "NotReady", "a".to_owned());
+    let time = conditions.first().unwrap().last_transition_time.clone();
+    set_condition(&mut conditions, "Ready", false, "NotReady", "b".to_owned());
+    assert_eq!(conditions.len(), 1);
+    let condition = conditions.first().unwrap();
+    assert_eq!(condition.message, "b");
+    assert_eq!(condition.last_transition_time, time);
+    set_condition(&mut conditions, "Ready", true, "Ready", "c".to_owned());
+    assert_eq!(condition_status(&conditions, "Ready"), Some("True"));
+}
+
+#[test]
+fn test_volume_names() {
+    assert_eq!(manager_volume_name("data", "default"), "default/data");
+    assert_eq!(secret_name("data"), "data-volume");
+}
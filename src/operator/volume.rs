@@ -0,0 +1,221 @@
+//! The reconciler of `DatenlordVolume`.
+//!
+//! A volume is created in the volume manager of its cluster under the name
+//! `<namespace>/<name>`, with the secret to mount it kept in the `Secret`
+//! `<name>-volume` owned by the volume. The volume is deleted from the volume
+//! manager before the resource is removed, by the finalizer of the operator.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use datenlord::config::StoragePolicy;
+use k8s_openapi::api::core::v1::Secret;
+use kube::api::{Patch, PatchParams, PostParams};
+use kube::runtime::controller::Action;
+use kube::runtime::finalizer::{finalizer, Event};
+use kube::{Api, Resource};
+use serde_json::json;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::cluster::volume_manager_endpoint;
+use super::crd::{set_condition, DatenlordVolume, DatenlordVolumeStatus};
+use super::{kube_error, name_and_namespace, Context};
+use crate::common::error::{DatenLordError, DatenLordResult};
+use crate::csi::util::{parse_volume_params, STORAGE_POLICY_KEY_PARAMETER, VOLUME_SECRET_KEY};
+use crate::volume::VolumeManagerClient;
+
+/// The finalizer deleting a volume from the volume manager.
+const FINALIZER: &str = "datenlord.io/volume";
+
+/// The interval to check a volume.
+const REQUEUE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// The interval to retry a volume failed to reconcile.
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The name of a volume in the volume manager.
+pub(super) fn manager_volume_name(name: &str, namespace: &str) -> String {
+    format!("{namespace}/{name}")
+}
+
+/// The name of the `Secret` holding the secret of volume `name`.
+pub(super) fn secret_name(name: &str) -> String {
+    format!("{name}-volume")
+}
+
+/// The client of the volume manager of the cluster of `volume`.
+fn manager_client(
+    volume: &DatenlordVolume,
+    namespace: &str,
+) -> DatenLordResult<VolumeManagerClient> {
+    VolumeManagerClient::connect(&volume_manager_endpoint(&volume.spec.cluster, namespace))
+}
+
+/// Reconcile `volume` with the finalizer deleting it from the volume manager.
+pub(super) async fn reconcile(
+    volume: Arc<DatenlordVolume>,
+    ctx: Arc<Context>,
+) -> DatenLordResult<Action> {
+    let (_, namespace) = name_and_namespace(volume.meta())?;
+    let api = Api::<DatenlordVolume>::namespaced(ctx.client.clone(), &namespace);
+    finalizer(&api, FINALIZER, volume, |event| async {
+        match event {
+            Event::Apply(volume) => apply_volume(&volume, &ctx).await,
+            Event::Cleanup(volume) => cleanup_volume(&volume).await,
+        }
+    })
+    .await
+    .map_err(|e| DatenLordError::InternalErr {
+        source: anyhow::Error::new(e),
+        context: vec!["failed to reconcile a volume".to_owned()],
+    })
+}
+
+/// Create or resize `volume` and report its status.
+async fn apply_volume(volume: &DatenlordVolume, ctx: &Context) -> DatenLordResult<Action> {
+    let (name, namespace) = name_and_namespace(volume.meta())?;
+    let mut status = volume.status.clone().unwrap_or_default();
+    let result = create_volume(volume, &name, &namespace, ctx, &mut status).await;
+    match result {
+        Ok(()) => set_condition(
+            &mut status.conditions,
+            "Ready",
+            true,
+            "Created",
+            "the volume is created".to_owned(),
+        ),
+        Err(ref e) => set_condition(
+            &mut status.conditions,
+            "Ready",
+            false,
+            "CreateFailed",
+            e.to_string(),
+        ),
+    }
+    Api::<DatenlordVolume>::namespaced(ctx.client.clone(), &namespace)
+        .patch_status(
+            &name,
+            &PatchParams::default(),
+            &Patch::Merge(json!({ "status": status })),
+        )
+        .await
+        .map_err(kube_error)?;
+    result.map(|()| Action::requeue(REQUEUE_INTERVAL))
+}
+
+/// The secret of `volume`, which is generated and saved in its `Secret` if it
+/// doesn't exist.
+async fn volume_secret(
+    volume: &DatenlordVolume,
+    name: &str,
+    namespace: &str,
+    ctx: &Context,
+) -> DatenLordResult<String> {
+    let api = Api::<Secret>::namespaced(ctx.client.clone(), namespace);
+    let secret_name = secret_name(name);
+    if let Some(secret) = api.get_opt(&secret_name).await.map_err(kube_error)? {
+        let value = secret
+            .data
+            .as_ref()
+            .and_then(|data| data.get(VOLUME_SECRET_KEY))
+            .ok_or_else(|| DatenLordError::ArgumentInvalid {
+                context: vec![format!(
+                    "secret {namespace}/{secret_name} has no key {VOLUME_SECRET_KEY}"
+                )],
+            })?;
+        return String::from_utf8(value.0.clone()).map_err(|e| DatenLordError::ArgumentInvalid {
+            context: vec![format!("secret {namespace}/{secret_name} is invalid: {e}")],
+        });
+    }
+
+    let value = Uuid::new_v4().simple().to_string();
+    let secret: Secret = serde_json::from_value(json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": {
+            "name": secret_name,
+            "ownerReferences": [volume.controller_owner_ref(&())],
+        },
+        "stringData": { VOLUME_SECRET_KEY: value },
+    }))?;
+    api.create(&PostParams::default(), &secret)
+        .await
+        .map_err(kube_error)?;
+    Ok(value)
+}
+
+/// Create `volume` in the volume manager, or resize it if it exists, and
+/// record it in `status`.
+async fn create_volume(
+    volume: &DatenlordVolume,
+    name: &str,
+    namespace: &str,
+    ctx: &Context,
+    status: &mut DatenlordVolumeStatus,
+) -> DatenLordResult<()> {
+    let parameters: HashMap<String, String> = volume
+        .spec
+        .parameters
+        .iter()
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    let params = parse_volume_params(&parameters)?;
+    let policy = match parameters.get(STORAGE_POLICY_KEY_PARAMETER) {
+        Some(policy) => policy.parse()?,
+        None => StoragePolicy::Tiered,
+    };
+    let secret = volume_secret(volume, name, namespace, ctx).await?;
+    let client = manager_client(volume, namespace)?;
+    let manager_name = manager_volume_name(name, namespace);
+    let capacity = volume.spec.capacity;
+
+    let created = match client
+        .create(&manager_name, capacity, policy, params, Some(secret))
+        .await
+    {
+        Ok((created, _)) => {
+            info!("created volume {manager_name} with id {}", created.id);
+            created
+        }
+        Err(DatenLordError::VolumeAlreadyExist { .. }) => {
+            let existing = client.get(&manager_name).await?;
+            if existing.capacity == capacity {
+                existing
+            } else {
+                info!("resize volume {manager_name} to capacity {capacity}");
+                client.resize(&manager_name, capacity).await?
+            }
+        }
+        Err(e) => return Err(e),
+    };
+    status.id = Some(created.id);
+    status.capacity = Some(created.capacity);
+    status.secret = Some(secret_name(name));
+    Ok(())
+}
+
+/// Delete `volume` from the volume manager.
+async fn cleanup_volume(volume: &DatenlordVolume) -> DatenLordResult<Action> {
+    let (name, namespace) = name_and_namespace(volume.meta())?;
+    let manager_name = manager_volume_name(&name, &namespace);
+    match manager_client(volume, &namespace)?
+        .delete(&manager_name)
+        .await
+    {
+        Ok(()) => info!("deleted volume {manager_name}"),
+        Err(DatenLordError::VolumeNotFound { .. }) => {
+            info!("volume {manager_name} is already deleted");
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(Action::await_change())
+}
+
+/// Retry a volume failed to reconcile later.
+#[allow(clippy::needless_pass_by_value)] // Required by `Controller::run`
+pub(super) fn error_policy(error: &DatenLordError, _ctx: Arc<Context>) -> Action {
+    warn!("failed to reconcile a volume, the error is: {error}");
+    Action::requeue(RETRY_INTERVAL)
+}
@@ -0,0 +1,115 @@
+//! The reload of the config file.
+//!
+//! A daemon started with `--config-file` reads it again on `SIGHUP` or the
+//! `reload` command of the control socket, see `control`, and applies the
+//! flags safe to change while it's serving: the level and the sample rate of
+//! the log, the threshold of the slow log, and the memory budget of the mount
+//! if one is set at the start. The file is validated as it's at the start, so
+//! an invalid file is refused and the config in effect is kept. The other
+//! flags take effect on the next start, and the TLS certificates are reloaded
+//! once their files are modified anyway, see `tls`.
+
+use std::sync::{Arc, Weak};
+
+use datenlord::common::error::{DatenLordError, DatenLordResult};
+use datenlord::common::memory_budget::MemoryBudget;
+use datenlord::config::{Config, InnerConfig};
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use signal_hook::consts::SIGHUP;
+use signal_hook_tokio::Signals;
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::async_fuse::fuse::request_log::REQUEST_LOG;
+use crate::async_fuse::fuse::slow_log::SLOW_LOG;
+use crate::common::logger::set_log_level;
+
+/// The flags reloaded from the config file of this process.
+pub static RELOAD: Lazy<ConfigReload> = Lazy::new(ConfigReload::default);
+
+/// The state the reloaded flags are applied to.
+#[derive(Debug, Default)]
+pub struct ConfigReload {
+    /// The memory budget of the mount, which is not reloaded if it's not set
+    /// at the start
+    memory_budget: Mutex<Weak<MemoryBudget>>,
+}
+
+impl ConfigReload {
+    /// Register the memory budget of the mount, whose limit is reloaded.
+    pub fn register_memory_budget(&self, budget: &Arc<MemoryBudget>) {
+        *self.memory_budget.lock() = Arc::downgrade(budget);
+    }
+
+    /// Read the config file again and apply the flags safe to change, which
+    /// are returned as they're applied, such as `log level: debug`.
+    pub fn reload(&self) -> DatenLordResult<Vec<String>> {
+        let config = InnerConfig::try_from(Config::try_load_from(std::env::args_os())?)?;
+        let Some(config_file) = config.config_file else {
+            return Err(DatenLordError::ArgumentInvalid {
+                context: vec!["no config file is given to reload".to_owned()],
+            });
+        };
+
+        let mut applied = vec![];
+        if set_log_level(config.log_level) {
+            applied.push(format!("log level: {}", config.log_level));
+        }
+        REQUEST_LOG.set_sample_rate(config.log_sample_rate);
+        applied.push(format!("log sample rate: {}", config.log_sample_rate));
+        SLOW_LOG.set_threshold(config.slow_log_threshold);
+        match config.slow_log_threshold {
+            Some(threshold) => applied.push(format!("slow log threshold: {threshold:?}")),
+            None => applied.push("slow log threshold: off".to_owned()),
+        }
+        if let Some(budget) = self.memory_budget.lock().upgrade() {
+            match config.storage.memory_budget {
+                Some(limit) if limit != budget.limit() => {
+                    budget.set_limit(limit);
+                    applied.push(format!("memory budget: {limit}"));
+                }
+                Some(_) => {}
+                None => warn!(
+                    "The memory budget cannot be turned off while the mount is served, it's kept \
+                     at {}.",
+                    budget.limit()
+                ),
+            }
+        }
+        info!(
+            "Config file {} is reloaded, applied {applied:?}.",
+            config_file.display()
+        );
+        Ok(applied)
+    }
+}
+
+/// Reload the config file on every `SIGHUP` until `token` is cancelled.
+#[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+pub async fn reload_on_hangup(token: CancellationToken) {
+    let mut signals = match Signals::new([SIGHUP]) {
+        Ok(signals) => signals,
+        Err(e) => {
+            error!("Failed to handle SIGHUP, the config file is not reloaded on it: {e}");
+            return;
+        }
+    };
+    let handle = signals.handle();
+    loop {
+        select! {
+            () = token.cancelled() => break,
+            signal = signals.next() => {
+                if signal.is_none() {
+                    break;
+                }
+                if let Err(e) = RELOAD.reload() {
+                    warn!("Failed to reload the config file, the config in effect is kept: {e}");
+                }
+            }
+        }
+    }
+    handle.close();
+}
@@ -0,0 +1,267 @@
+//! The simulation of the nodes caching files under the leases of `Coherence`.
+//!
+//! Every node caches the content of the files it holds the leases of, a
+//! write is cached until the lease is recalled, and is flushed to the shared
+//! backend before the lease is released. A node may crash and restart, its
+//! lease is evicted by the others, and the writes it has not flushed are lost
+//! as the writes not synced, so they're discarded from the history.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use datenlord::config::ConsistencyModel;
+use parking_lot::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use super::{History, OpKind, Schedule, INITIAL_VALUE, SEEDS};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::async_fuse::memfs::coherence::{Coherence, LeaseMode};
+use crate::async_fuse::memfs::kv_engine::local_impl::LocalKVEngine;
+use crate::async_fuse::memfs::kv_engine::KVEngineType;
+
+/// The root of the kv engines of the simulations.
+const KV_ENGINE_ROOT: &str = "/tmp/datenlord_simulation_coherence";
+/// The number of nodes.
+const NODES: usize = 3;
+/// The number of files.
+const FILES: usize = 3;
+/// The number of steps of a schedule.
+const STEPS: usize = 200;
+/// The interval between handling the recalls of the nodes.
+const RECALL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The content of a file cached by a node.
+#[derive(Debug, Clone, Copy)]
+struct CachedFile {
+    /// The value of the file
+    value: u64,
+    /// Whether the value is not flushed to the backend
+    dirty: bool,
+}
+
+/// A virtual node.
+#[derive(Debug)]
+struct SimNode {
+    /// The index of the node
+    index: usize,
+    /// The leases of the node, replaced on restart
+    coherence: Mutex<Arc<Coherence>>,
+    /// The files cached
+    cache: Mutex<HashMap<INum, CachedFile>>,
+    /// The operations seeing the writes not flushed, by the file
+    unflushed: Mutex<HashMap<INum, Vec<usize>>>,
+    /// Whether the node is up
+    up: AtomicBool,
+    /// Whether an operation of the node is in progress
+    busy: AtomicBool,
+}
+
+/// The virtual nodes sharing a kv engine and a backend.
+#[derive(Debug)]
+struct Cluster {
+    /// The kv engine keeping the leases
+    kv_engine: Arc<KVEngineType>,
+    /// The nodes
+    nodes: Vec<Arc<SimNode>>,
+    /// The backend persisting the flushed files
+    backend: Mutex<HashMap<INum, u64>>,
+    /// The schedule
+    schedule: Schedule,
+    /// The history of the operations
+    history: History,
+}
+
+impl Cluster {
+    /// Create the cluster of the schedule of `seed`.
+    fn new(seed: u64) -> Self {
+        let dir = Path::new(KV_ENGINE_ROOT).join(seed.to_string());
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        let kv_engine = Arc::new(KVEngineType::Local(LocalKVEngine::open(dir).unwrap()));
+        let nodes = (0..NODES)
+            .map(|index| {
+                Arc::new(SimNode {
+                    index,
+                    coherence: Mutex::new(Arc::new(new_coherence(&kv_engine, index))),
+                    cache: Mutex::new(HashMap::new()),
+                    unflushed: Mutex::new(HashMap::new()),
+                    up: AtomicBool::new(true),
+                    busy: AtomicBool::new(false),
+                })
+            })
+            .collect();
+        Self {
+            kv_engine,
+            nodes,
+            backend: Mutex::new(HashMap::new()),
+            schedule: Schedule::new(seed),
+            history: History::default(),
+        }
+    }
+
+    /// Flush the file `ino` cached by `node` to the backend, and drop it from
+    /// the cache, before the lease is released.
+    fn release_caches(&self, node: &SimNode, ino: INum) {
+        if let Some(cached) = node.cache.lock().remove(&ino) {
+            if cached.dirty {
+                self.backend.lock().insert(ino, cached.value);
+            }
+        }
+        node.unflushed.lock().remove(&ino);
+    }
+
+    /// Release the leases recalled from the nodes up.
+    async fn handle_recalls(self: &Arc<Self>) {
+        for node in &self.nodes {
+            if !node.up.load(Ordering::Acquire) {
+                continue;
+            }
+            let coherence = Arc::clone(&node.coherence.lock());
+            coherence
+                .handle_recalls(|ino| {
+                    self.release_caches(node, ino);
+                    async { Ok(()) }
+                })
+                .await
+                .unwrap();
+        }
+    }
+
+    /// Write `value` to the file `ino` on `node`.
+    async fn write(&self, node: &SimNode, ino: INum, value: u64) {
+        let op = self.history.invoke(node.index, ino, OpKind::Write, value);
+        let coherence = Arc::clone(&node.coherence.lock());
+        let _guard = coherence.guard(ino, LeaseMode::Write).await.unwrap();
+        tokio::time::sleep(self.schedule.delay()).await;
+        node.cache
+            .lock()
+            .insert(ino, CachedFile { value, dirty: true });
+        node.unflushed.lock().entry(ino).or_default().push(op);
+        self.history.respond(op, value);
+    }
+
+    /// Read the file `ino` on `node`.
+    async fn read(&self, node: &SimNode, ino: INum) {
+        let op = self.history.invoke(node.index, ino, OpKind::Read, 0);
+        let coherence = Arc::clone(&node.coherence.lock());
+        let _guard = coherence.guard(ino, LeaseMode::Read).await.unwrap();
+        tokio::time::sleep(self.schedule.delay()).await;
+        let cached = *node.cache.lock().entry(ino).or_insert_with(|| CachedFile {
+            value: self
+                .backend
+                .lock()
+                .get(&ino)
+                .copied()
+                .unwrap_or(INITIAL_VALUE),
+            dirty: false,
+        });
+        if cached.dirty {
+            node.unflushed.lock().entry(ino).or_default().push(op);
+        }
+        self.history.respond(op, cached.value);
+    }
+
+    /// Crash `node`, its cache is lost along with the operations seeing the
+    /// writes not flushed, and its leases are left to be evicted.
+    fn crash(&self, node: &SimNode) {
+        node.up.store(false, Ordering::Release);
+        node.cache.lock().clear();
+        for op in node.unflushed.lock().drain().flat_map(|(_, ops)| ops) {
+            self.history.discard(op);
+        }
+    }
+
+    /// Restart `node` holding no lease.
+    fn restart(&self, node: &SimNode) {
+        *node.coherence.lock() = Arc::new(new_coherence(&self.kv_engine, node.index));
+        node.up.store(true, Ordering::Release);
+    }
+}
+
+/// Create the `Coherence` of the node `index`.
+fn new_coherence(kv_engine: &Arc<KVEngineType>, index: usize) -> Coherence {
+    Coherence::new(
+        Arc::clone(kv_engine),
+        &format!("node{index}"),
+        ConsistencyModel::Strict,
+    )
+}
+
+/// Run the schedule of `seed`, returns the violations of the history.
+async fn simulate(seed: u64) -> Result<(), String> {
+    let cluster = Arc::new(Cluster::new(seed));
+    let token = CancellationToken::new();
+    let recall_handler = {
+        let cluster = Arc::clone(&cluster);
+        let token = token.clone();
+        tokio::spawn(async move {
+            while !token.is_cancelled() {
+                tokio::time::sleep(RECALL_INTERVAL).await;
+                cluster.handle_recalls().await;
+            }
+        })
+    };
+
+    let mut tasks = vec![];
+    let mut next_value = INITIAL_VALUE;
+    for _ in 0..STEPS {
+        tokio::time::sleep(cluster.schedule.delay()).await;
+        let node = Arc::clone(&cluster.nodes[cluster.schedule.pick(NODES)]);
+        if node.busy.load(Ordering::Acquire) {
+            continue;
+        }
+        let up_nodes = cluster
+            .nodes
+            .iter()
+            .filter(|node| node.up.load(Ordering::Acquire))
+            .count();
+        if !node.up.load(Ordering::Acquire) {
+            if cluster.schedule.happens(1, 4) {
+                cluster.restart(&node);
+            }
+            continue;
+        }
+        if up_nodes > 1 && cluster.schedule.happens(1, 30) {
+            cluster.crash(&node);
+            continue;
+        }
+
+        let ino: INum = (cluster.schedule.pick(FILES) + 2).try_into().unwrap();
+        let value = if cluster.schedule.happens(1, 2) {
+            next_value += 1;
+            Some(next_value)
+        } else {
+            None
+        };
+        node.busy.store(true, Ordering::Release);
+        let cluster = Arc::clone(&cluster);
+        tasks.push(tokio::spawn(async move {
+            match value {
+                Some(value) => cluster.write(&node, ino, value).await,
+                None => cluster.read(&node, ino).await,
+            }
+            node.busy.store(false, Ordering::Release);
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+    token.cancel();
+    recall_handler.await.unwrap();
+
+    cluster.history.check()
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_simulate_coherence() {
+    for seed in 0..SEEDS {
+        if let Err(violations) = simulate(seed).await {
+            panic!("the schedule of seed {seed} violates the coherence:\n{violations}");
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//! The deterministic simulation tests of the coherence and the replication
+//! protocols.
+//!
+//! A simulation runs several virtual nodes in one thread on the virtual clock
+//! of tokio, the nodes talk to each other by the in-memory kv engine and
+//! block stores. A seeded `Schedule` decides which node issues which
+//! operation, how long every step takes and when a node fails, so a failing
+//! schedule is reproduced by the seed reported with it.
+//!
+//! The operations are recorded into a `History` on a logical clock, and the
+//! history of every key is checked to be linearizable as a register, and to
+//! let every node read its own writes.
+
+mod coherence;
+mod replication;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// The number of seeded schedules run by a simulation test.
+const SEEDS: u64 = 20;
+
+/// The longest delay of a step of the schedule.
+const MAX_DELAY: Duration = Duration::from_millis(200);
+
+/// The value of every key before it's written.
+const INITIAL_VALUE: u64 = 0;
+
+/// The seeded random schedule of a simulation.
+#[derive(Debug)]
+struct Schedule {
+    /// The random generator
+    rng: Mutex<StdRng>,
+}
+
+impl Schedule {
+    /// Create the schedule of `seed`.
+    fn new(seed: u64) -> Self {
+        Self {
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+
+    /// A random index below `len`.
+    fn pick(&self, len: usize) -> usize {
+        self.rng.lock().gen_range(0..len)
+    }
+
+    /// Whether an event of the probability `numerator / denominator` happens.
+    fn happens(&self, numerator: u32, denominator: u32) -> bool {
+        self.rng.lock().gen_ratio(numerator, denominator)
+    }
+
+    /// The random delay of a step.
+    fn delay(&self) -> Duration {
+        let millis = self.rng.lock().gen_range(0..=MAX_DELAY.as_millis());
+        Duration::from_millis(millis.try_into().unwrap_or(u64::MAX))
+    }
+}
+
+/// An operation on a register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OpKind {
+    /// Write the value
+    Write,
+    /// Read the value
+    Read,
+}
+
+/// An operation recorded in a `History`.
+#[derive(Debug, Clone)]
+struct Op {
+    /// The node issuing the operation
+    node: usize,
+    /// The key of the register
+    key: u64,
+    /// The kind of the operation
+    kind: OpKind,
+    /// The value written, or read once the read responds
+    value: u64,
+    /// The logical time of the invocation
+    invoke: u64,
+    /// The logical time of the response, `None` if the operation failed, a
+    /// failed write may take effect or not
+    response: Option<u64>,
+}
+
+impl Op {
+    /// The time of the response, a failed write never responds.
+    fn responded(&self) -> u64 {
+        self.response.unwrap_or(u64::MAX)
+    }
+
+    /// Whether the operation responds before `other` is invoked.
+    fn precedes(&self, other: &Op) -> bool {
+        self.responded() < other.invoke
+    }
+}
+
+/// The history of the operations of a simulation, on a logical clock.
+#[derive(Debug, Default)]
+struct History {
+    /// The logical clock and the operations, `None` if discarded
+    inner: Mutex<(u64, Vec<Option<Op>>)>,
+}
+
+impl History {
+    /// Record the invocation of an operation, returns its index.
+    fn invoke(&self, node: usize, key: u64, kind: OpKind, value: u64) -> usize {
+        let mut inner = self.inner.lock();
+        inner.0 += 1;
+        let invoke = inner.0;
+        inner.1.push(Some(Op {
+            node,
+            key,
+            kind,
+            value,
+            invoke,
+            response: None,
+        }));
+        inner.1.len() - 1
+    }
+
+    /// Record the response of the operation `index`, with the value read.
+    fn respond(&self, index: usize, value: u64) {
+        let mut inner = self.inner.lock();
+        inner.0 += 1;
+        let response = inner.0;
+        if let Some(&mut Some(ref mut op)) = inner.1.get_mut(index) {
+            op.value = value;
+            op.response = Some(response);
+        }
+    }
+
+    /// Discard the operation `index`, such as a failed read or a write lost
+    /// by a crash, which is never seen.
+    fn discard(&self, index: usize) {
+        if let Some(op) = self.inner.lock().1.get_mut(index) {
+            *op = None;
+        }
+    }
+
+    /// Check the operations of every key, returns the violations found.
+    fn check(&self) -> Result<(), String> {
+        let mut keys: HashMap<u64, Vec<Op>> = HashMap::new();
+        for op in self.inner.lock().1.iter().flatten() {
+            // A failed read is discarded, a failed write may take effect.
+            if op.kind == OpKind::Read && op.response.is_none() {
+                continue;
+            }
+            keys.entry(op.key).or_default().push(op.clone());
+        }
+        let mut violations = String::new();
+        for (key, ops) in keys {
+            if let Err(e) = check_register(&ops) {
+                let _ignore = writeln!(violations, "key {key}: {e}");
+            }
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+/// Check the operations on a register whose every write is of a unique value.
+///
+/// The history is linearizable if no read returns a value written after it,
+/// or a value overwritten before it, and no read returns a value older than
+/// the one of a preceding read. Every node reads its own writes, which is
+/// checked apart to point out the violations of it.
+fn check_register(ops: &[Op]) -> Result<(), String> {
+    let initial = Op {
+        node: usize::MAX,
+        key: 0,
+        kind: OpKind::Write,
+        value: INITIAL_VALUE,
+        invoke: 0,
+        response: Some(0),
+    };
+    let writes: HashMap<u64, &Op> = ops
+        .iter()
+        .filter(|op| op.kind == OpKind::Write)
+        .chain([&initial])
+        .map(|op| (op.value, op))
+        .collect();
+    let reads: Vec<&Op> = ops.iter().filter(|op| op.kind == OpKind::Read).collect();
+
+    for &read in &reads {
+        let Some(&written) = writes.get(&read.value) else {
+            return Err(format!("{read:?} reads a value never written"));
+        };
+        if read.precedes(written) {
+            return Err(format!("{read:?} reads the value of a later {written:?}"));
+        }
+        if let Some(own) = writes
+            .values()
+            .filter(|write| write.node == read.node && write.precedes(read))
+            .max_by_key(|write| write.invoke)
+        {
+            if written.precedes(own) {
+                return Err(format!("{read:?} doesn't read its own {own:?}"));
+            }
+        }
+        if let Some(overwrite) = writes
+            .values()
+            .find(|write| written.precedes(write) && write.precedes(read))
+        {
+            return Err(format!(
+                "{read:?} reads the value of {written:?} overwritten by {overwrite:?}"
+            ));
+        }
+        if let Some(earlier) = reads.iter().find(|earlier| {
+            earlier.precedes(read)
+                && writes
+                    .get(&earlier.value)
+                    .map_or(false, |newer| written.precedes(newer))
+        }) {
+            return Err(format!("{read:?} reads a value older than {earlier:?}"));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_check_register() {
+    let history = History::default();
+    let write = history.invoke(0, 1, OpKind::Write, 1);
+    let read = history.invoke(1, 1, OpKind::Read, 0);
+    // A read concurrent with a write may see either value.
+    history.respond(read, INITIAL_VALUE);
+    history.respond(write, 1);
+    let read = history.invoke(1, 1, OpKind::Read, 0);
+    history.respond(read, 1);
+    assert!(history.check().is_ok());
+
+    // A read after the write completes sees it.
+    let stale = history.invoke(0, 1, OpKind::Read, 0);
+    history.respond(stale, INITIAL_VALUE);
+    let violations = history.check().unwrap_err();
+    assert!(violations.contains("its own"), "{violations}");
+    history.discard(stale);
+    assert!(history.check().is_ok());
+
+    // A failed write may take effect.
+    let failed = history.invoke(0, 1, OpKind::Write, 2);
+    let read = history.invoke(1, 1, OpKind::Read, 0);
+    history.respond(read, 2);
+    assert!(history.check().is_ok());
+    history.discard(failed);
+    assert!(history.check().is_err());
+}
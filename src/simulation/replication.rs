@@ -0,0 +1,229 @@
+//! The simulation of the nodes writing and reading blocks replicated by
+//! `ReplicatedBlockStore`.
+//!
+//! Every node coordinates its reads and writes with its own
+//! `ReplicatedBlockStore`, over the replica nodes reached by the in-memory
+//! transports, which are delayed by the schedule and fail while the replica
+//! node is down. The accesses of a block are serialized as they are under
+//! the leases of the file, a write excludes the other accesses, while the
+//! reads are concurrent.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use datenlord::config::WriteQuorum;
+use tokio::sync::RwLock;
+
+use super::{History, OpKind, Schedule, INITIAL_VALUE, SEEDS};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::block_store::MemoryBlockStore;
+use crate::storage::error::{StorageError, StorageResult};
+use crate::storage::replication::{MemoryReplicaIndex, ReplicaStore, ReplicatedBlockStore};
+use crate::storage::{BlockKey, BlockStore};
+
+/// The number of replica nodes.
+const REPLICA_NODES: usize = 5;
+/// The number of nodes coordinating the reads and writes.
+const NODES: usize = 3;
+/// The number of replicas of a block.
+const FACTOR: usize = 3;
+/// The number of blocks.
+const BLOCKS: usize = 3;
+/// The number of steps of a schedule.
+const STEPS: usize = 300;
+/// The failure timeout of the replica nodes, which are never detected dead,
+/// as no heartbeat is checked.
+const FAILURE_TIMEOUT: Duration = Duration::from_secs(3600);
+/// The i-number of the file of the blocks.
+const INO: INum = 2;
+
+/// A replica node reached by an in-memory transport.
+#[derive(Debug)]
+struct SimReplica {
+    /// The blocks of the node
+    store: MemoryBlockStore,
+    /// Whether the node is down
+    down: AtomicBool,
+    /// The schedule delaying the transport
+    schedule: Arc<Schedule>,
+}
+
+impl SimReplica {
+    /// Deliver a request to the node, fails if the node is down on arrival.
+    async fn deliver(&self) -> StorageResult<()> {
+        tokio::time::sleep(self.schedule.delay()).await;
+        if self.down.load(Ordering::Acquire) {
+            Err(StorageError::Internal(anyhow::anyhow!("the node is down")))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[async_trait]
+impl BlockStore for SimReplica {
+    async fn get(&self, key: BlockKey) -> StorageResult<Option<Vec<u8>>> {
+        self.deliver().await?;
+        self.store.get(key).await
+    }
+
+    async fn put(&self, key: BlockKey, data: Vec<u8>) -> StorageResult<()> {
+        self.deliver().await?;
+        self.store.put(key, data).await
+    }
+
+    async fn delete(&self, key: BlockKey) -> StorageResult<()> {
+        self.deliver().await?;
+        self.store.delete(key).await
+    }
+
+    async fn delete_file(&self, ino: INum) -> StorageResult<()> {
+        self.deliver().await?;
+        self.store.delete_file(ino).await
+    }
+}
+
+/// The coordinating nodes and the replica nodes.
+#[derive(Debug)]
+struct Cluster {
+    /// The stores of the coordinating nodes
+    nodes: Vec<ReplicatedBlockStore<Arc<MemoryReplicaIndex>>>,
+    /// The replica nodes
+    replicas: Vec<Arc<SimReplica>>,
+    /// The leases of the blocks
+    leases: Vec<RwLock<()>>,
+    /// The schedule
+    schedule: Arc<Schedule>,
+    /// The history of the operations
+    history: History,
+}
+
+impl Cluster {
+    /// Create the cluster of the schedule of `seed`.
+    fn new(seed: u64) -> Self {
+        let schedule = Arc::new(Schedule::new(seed));
+        let index = Arc::new(MemoryReplicaIndex::new());
+        let replicas: Vec<_> = (0..REPLICA_NODES)
+            .map(|_| {
+                Arc::new(SimReplica {
+                    store: MemoryBlockStore::new(),
+                    down: AtomicBool::new(false),
+                    schedule: Arc::clone(&schedule),
+                })
+            })
+            .collect();
+        let nodes = (0..NODES)
+            .map(|node| {
+                let stores: BTreeMap<String, ReplicaStore> = replicas
+                    .iter()
+                    .enumerate()
+                    .map(|(i, replica)| {
+                        let replica: ReplicaStore = Arc::<SimReplica>::clone(replica);
+                        (format!("node{i}"), replica)
+                    })
+                    .collect();
+                ReplicatedBlockStore::new(
+                    Arc::clone(&index),
+                    &format!("node{node}"),
+                    stores,
+                    FACTOR,
+                    WriteQuorum::Majority,
+                    FAILURE_TIMEOUT,
+                )
+            })
+            .collect();
+        Self {
+            nodes,
+            replicas,
+            leases: (0..BLOCKS).map(|_| RwLock::new(())).collect(),
+            schedule,
+            history: History::default(),
+        }
+    }
+
+    /// Write `value` to `block_id` on the node `node`.
+    async fn write(&self, node: usize, block_id: usize, value: u64) {
+        let key = block_id.try_into().unwrap();
+        let op = self.history.invoke(node, key, OpKind::Write, value);
+        let _lease = self.leases[block_id].write().await;
+        let result = self.nodes[node]
+            .put(BlockKey::new(INO, block_id), value.to_le_bytes().to_vec())
+            .await;
+        // A failed write may take effect or not, it never responds.
+        if result.is_ok() {
+            self.history.respond(op, value);
+        }
+    }
+
+    /// Read `block_id` on the node `node`.
+    async fn read(&self, node: usize, block_id: usize) {
+        let key = block_id.try_into().unwrap();
+        let op = self.history.invoke(node, key, OpKind::Read, 0);
+        let _lease = self.leases[block_id].read().await;
+        match self.nodes[node].get(BlockKey::new(INO, block_id)).await {
+            Ok(data) => {
+                let value = data.map_or(INITIAL_VALUE, |data| {
+                    u64::from_le_bytes(data.try_into().unwrap())
+                });
+                self.history.respond(op, value);
+            }
+            // The replicas of the block are down.
+            Err(_) => self.history.discard(op),
+        }
+    }
+}
+
+/// Run the schedule of `seed`, returns the violations of the history.
+async fn simulate(seed: u64) -> Result<(), String> {
+    let cluster = Arc::new(Cluster::new(seed));
+    let mut tasks = vec![];
+    let mut next_value = INITIAL_VALUE;
+    for _ in 0..STEPS {
+        tokio::time::sleep(cluster.schedule.delay()).await;
+        if cluster.schedule.happens(1, 15) {
+            let replica = &cluster.replicas[cluster.schedule.pick(REPLICA_NODES)];
+            let down = cluster
+                .replicas
+                .iter()
+                .filter(|replica| replica.down.load(Ordering::Acquire))
+                .count();
+            if replica.down.load(Ordering::Acquire) || down < 2 {
+                replica.down.fetch_xor(true, Ordering::AcqRel);
+            }
+            continue;
+        }
+
+        let node = cluster.schedule.pick(NODES);
+        let block_id = cluster.schedule.pick(BLOCKS);
+        let value = if cluster.schedule.happens(1, 2) {
+            next_value += 1;
+            Some(next_value)
+        } else {
+            None
+        };
+        let cluster = Arc::clone(&cluster);
+        tasks.push(tokio::spawn(async move {
+            match value {
+                Some(value) => cluster.write(node, block_id, value).await,
+                None => cluster.read(node, block_id).await,
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    cluster.history.check()
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_simulate_replication() {
+    for seed in 0..SEEDS {
+        if let Err(violations) = simulate(seed).await {
+            panic!("the schedule of seed {seed} violates the replication:\n{violations}");
+        }
+    }
+}
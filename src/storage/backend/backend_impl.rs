@@ -1,18 +1,33 @@
 //! The backend implementation.
 
+use std::ops::Range;
+use std::sync::Arc;
+
 use async_trait::async_trait;
-use clippy_utilities::OverflowArithmetic;
-use datenlord::config::{StorageParams, StorageS3Config};
+use clippy_utilities::{Cast, OverflowArithmetic};
+use datenlord::config::{
+    BackendErrorConfig, BackendOpClass, CompressionType, StorageParams, StorageS3Config,
+};
 use datenlord::metrics::DATENLORD_REGISTRY;
-use futures::{stream, AsyncReadExt, AsyncWriteExt, StreamExt};
+use futures::{future, stream, AsyncReadExt, AsyncWriteExt, StreamExt, TryStreamExt};
 use opendal::layers::PrometheusLayer;
 use opendal::services::{Fs, S3};
-use opendal::{ErrorKind, Operator};
+use opendal::{ErrorKind, Metakey, Operator};
 use prometheus::{exponential_buckets, linear_buckets};
 
+use super::codec::{self, HEADER_LEN};
+use super::error_policy::ErrorPolicy;
 use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::block_store::INITIAL_BLOCK_VERSION;
+use crate::storage::dedup::{ChunkHash, ChunkStore};
+use crate::storage::encryption::{self, BlockCipher};
 use crate::storage::error::StorageResult;
-use crate::storage::{Block, Storage};
+use crate::storage::gc::{ObjectKey, ObjectStore, StoredObject};
+use crate::storage::{Block, BlockKey, BlockStore, Storage};
+
+/// The minimum length of the range of a block fetched by a stream, a block
+/// is not split into ranges shorter than it.
+const MIN_READ_STREAM_LEN: usize = 0x10_0000;
 
 /// Get file path by `ino`
 fn get_file_path(ino: INum) -> String {
@@ -24,6 +39,57 @@ fn get_block_path(ino: INum, block_id: usize) -> String {
     format!("{ino}/{block_id}.block")
 }
 
+/// Get block path by `BlockKey`.
+///
+/// Blocks of the initial version share the path with `get_block_path`.
+fn get_versioned_block_path(key: BlockKey) -> String {
+    if key.version == INITIAL_BLOCK_VERSION {
+        get_block_path(key.ino, key.block_id)
+    } else {
+        format!("{}/{}.{}.block", key.ino, key.block_id, key.version)
+    }
+}
+
+/// Get chunk path by its hash, chunks are spread into directories by the
+/// first byte of their hashes.
+fn get_chunk_path(hash: ChunkHash) -> String {
+    let hash = hash.to_string();
+    let dir = hash.get(..2).unwrap_or_default();
+    format!("chunks/{dir}/{hash}")
+}
+
+/// Parse the path of an object written by the backend, returns `None` if it's
+/// not a block or a chunk.
+fn parse_object_path(path: &str) -> Option<ObjectKey> {
+    let path = path.trim_start_matches('/');
+    let mut components = path.split('/');
+    let (first, second, third) = (components.next()?, components.next()?, components.next());
+    if components.next().is_some() {
+        return None;
+    }
+    if first == "chunks" {
+        let hash = ChunkHash::from_hex(third?)?;
+        return (hash.to_string().get(..2) == Some(second)).then_some(ObjectKey::Chunk(hash));
+    }
+    if third.is_some() {
+        return None;
+    }
+
+    let ino = first.parse().ok()?;
+    let name = second.strip_suffix(".block")?;
+    let (block_id, version) = match name.split_once('.') {
+        Some((block_id, version)) => (block_id.parse().ok()?, version.parse().ok()?),
+        None => (name.parse().ok()?, INITIAL_BLOCK_VERSION),
+    };
+    let key = BlockKey {
+        ino,
+        block_id,
+        version,
+    };
+    // Reject the paths not generated by `get_versioned_block_path`.
+    (get_versioned_block_path(key) == path).then_some(ObjectKey::Block(key))
+}
+
 /// A builder to build `BackendWrapper`.
 #[derive(Debug)]
 pub struct BackendBuilder {
@@ -31,19 +97,60 @@ pub struct BackendBuilder {
     config: StorageParams,
     /// The size of a block
     block_size: usize,
+    /// The compression of blocks
+    compression: CompressionType,
+    /// The cipher of blocks
+    cipher: Option<Arc<BlockCipher>>,
+    /// How the failures are retried and returned
+    error_config: BackendErrorConfig,
 }
 
 impl BackendBuilder {
     /// Create a backend builder.
     #[must_use]
     pub fn new(config: StorageParams, block_size: usize) -> Self {
-        Self { config, block_size }
+        Self {
+            config,
+            block_size,
+            compression: CompressionType::None,
+            cipher: None,
+            error_config: BackendErrorConfig::default(),
+        }
+    }
+
+    /// Set the compression of blocks written to the backend, default is no
+    /// compression.
+    #[must_use]
+    pub fn compression(mut self, compression: CompressionType) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Set the cipher of objects in the backend, default is no encryption.
+    #[must_use]
+    pub fn cipher(mut self, cipher: Option<Arc<BlockCipher>>) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// Set how the failures of the backend are retried and returned, default
+    /// is 3 retries of every operation class and the built-in error mappings.
+    #[must_use]
+    pub fn error_config(mut self, error_config: BackendErrorConfig) -> Self {
+        self.error_config = error_config;
+        self
     }
 
     /// Build the backend.
     #[allow(clippy::expect_used, clippy::unwrap_in_result)] // `.expect()` here are ensured not to panic.
     pub fn build(self) -> opendal::Result<Backend> {
-        let BackendBuilder { config, block_size } = self;
+        let BackendBuilder {
+            config,
+            block_size,
+            compression,
+            cipher,
+            error_config,
+        } = self;
 
         let layer = PrometheusLayer::with_registry(DATENLORD_REGISTRY.clone())
             .bytes_total_buckets(
@@ -53,12 +160,16 @@ impl BackendBuilder {
                 linear_buckets(0.005, 0.005, 20).expect("Arguments are legal."),
             );
 
-        let operator = match config {
+        let (operator, part_size, read_streams) = match config {
             StorageParams::S3(StorageS3Config {
                 ref endpoint_url,
                 ref access_key_id,
                 ref secret_access_key,
                 ref bucket_name,
+                ref region,
+                ref prefix,
+                part_size,
+                read_streams,
             }) => {
                 let mut builder = S3::default();
 
@@ -66,32 +177,55 @@ impl BackendBuilder {
                     .endpoint(endpoint_url)
                     .access_key_id(access_key_id)
                     .secret_access_key(secret_access_key)
-                    .region("auto")
-                    .bucket(bucket_name);
-
-                Operator::new(builder)?.layer(layer).finish()
+                    .region(region)
+                    .bucket(bucket_name)
+                    .root(prefix);
+
+                (
+                    Operator::new(builder)?.layer(layer).finish(),
+                    Some(part_size),
+                    read_streams,
+                )
             }
             StorageParams::Fs(ref root) => {
                 let mut builder = Fs::default();
                 builder.root(root);
-                Operator::new(builder)?.layer(layer).finish()
+                (Operator::new(builder)?.layer(layer).finish(), None, 1)
             }
         };
 
         Ok(Backend {
             operator,
             block_size,
+            part_size,
+            read_streams,
+            compression,
+            cipher,
+            policy: Arc::new(ErrorPolicy::new(&error_config)),
         })
     }
 }
 
 /// The backend wrapper of `openDAL` operator.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Backend {
     /// The inner `Operator`
     operator: Operator,
     /// Block size
     block_size: usize,
+    /// The part size of multipart uploads, `None` if the underlying service
+    /// does not need multipart uploads, such as `Fs`.
+    part_size: Option<usize>,
+    /// The max number of concurrent streams fetching the ranges of a block.
+    read_streams: usize,
+    /// The compression of blocks written to the backend. Blocks are always
+    /// decoded by the codec in their headers, regardless of this setting.
+    compression: CompressionType,
+    /// The cipher of objects, `None` if the volume is not encrypted. Objects
+    /// are encrypted after they are compressed.
+    cipher: Option<Arc<BlockCipher>>,
+    /// How the failures of the operations are retried and returned
+    policy: Arc<ErrorPolicy>,
 }
 
 impl Backend {
@@ -101,21 +235,140 @@ impl Backend {
         Self {
             operator,
             block_size,
+            part_size: None,
+            read_streams: 1,
+            compression: CompressionType::None,
+            cipher: None,
+            policy: Arc::new(ErrorPolicy::default()),
         }
     }
-}
 
-#[async_trait]
-impl Storage for Backend {
-    async fn load_from_self(&self, ino: INum, block_id: usize) -> StorageResult<Option<Block>> {
-        let mut block = Block::new_zeroed(self.block_size);
+    /// Set the compression of blocks written to the backend.
+    #[must_use]
+    pub fn with_compression(mut self, compression: CompressionType) -> Self {
+        self.compression = compression;
+        self
+    }
 
-        let mut reader = self.operator.reader(&get_block_path(ino, block_id)).await?;
+    /// Set the cipher of objects in the backend.
+    #[must_use]
+    pub fn with_cipher(mut self, cipher: Arc<BlockCipher>) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    /// Set the part size of multipart uploads.
+    ///
+    /// Blocks larger than `part_size` will be uploaded in parts.
+    #[must_use]
+    pub fn with_part_size(mut self, part_size: usize) -> Self {
+        self.part_size = Some(part_size);
+        self
+    }
+
+    /// Set the max number of concurrent streams fetching the ranges of a
+    /// block.
+    ///
+    /// Blocks are read by one stream if it's `1`, which is the default.
+    #[must_use]
+    pub fn with_read_streams(mut self, read_streams: usize) -> Self {
+        self.read_streams = read_streams;
+        self
+    }
+
+    /// Check whether the backend can be reached.
+    pub async fn check(&self) -> StorageResult<()> {
+        self.operator.check().await?;
+        Ok(())
+    }
+
+    /// Write the whole content of an object, in multipart if the content is
+    /// larger than the part size. The write is retried by the error policy.
+    async fn write_object(&self, path: &str, content: &[u8]) -> StorageResult<()> {
+        self.policy
+            .run(BackendOpClass::Write, || {
+                self.try_write_object(path, content)
+            })
+            .await
+    }
+
+    /// Write the whole content of an object once.
+    async fn try_write_object(&self, path: &str, content: &[u8]) -> StorageResult<()> {
+        let mut writer = match self.part_size {
+            Some(part_size) if content.len() > part_size => {
+                self.operator.writer_with(path).buffer(part_size).await?
+            }
+            Some(_) | None => self.operator.writer(path).await?,
+        };
+        writer.write_all(content).await?;
+        writer.close().await?;
+        Ok(())
+    }
+
+    /// The maximum length of a block stored in the backend, with the header
+    /// and the overhead of encryption.
+    fn max_stored_len(&self) -> usize {
+        let max_len = self.block_size.overflow_add(HEADER_LEN);
+        if self.cipher.is_some() {
+            max_len.overflow_add(encryption::OVERHEAD)
+        } else {
+            max_len
+        }
+    }
+
+    /// Encode (and encrypt) the content of a block and write it.
+    async fn write_block(&self, path: &str, content: &[u8]) -> StorageResult<()> {
+        let encoded = codec::encode(self.compression, content)?;
+        if let Some(ref cipher) = self.cipher {
+            let encrypted = cipher.encrypt(path, &encoded)?;
+            self.write_object(path, &encrypted).await
+        } else {
+            self.write_object(path, &encoded).await
+        }
+    }
+
+    /// Decrypt (if encrypted) and decode the content of a block read from
+    /// `path`.
+    fn decode_block(&self, path: &str, data: Vec<u8>) -> StorageResult<Vec<u8>> {
+        if let Some(ref cipher) = self.cipher {
+            codec::decode(cipher.decrypt(path, &data)?)
+        } else {
+            codec::decode(data)
+        }
+    }
+
+    /// Read the range `range` of an object, returns `None` if the object does
+    /// not exist. The content read is shorter than the range, if the object
+    /// ends within it. The read is retried by the error policy.
+    async fn read_range(&self, path: &str, range: Range<usize>) -> StorageResult<Option<Vec<u8>>> {
+        self.policy
+            .run(BackendOpClass::Read, || {
+                self.try_read_range(path, range.clone())
+            })
+            .await
+    }
+
+    /// Read the range `range` of an object once.
+    async fn try_read_range(
+        &self,
+        path: &str,
+        range: Range<usize>,
+    ) -> StorageResult<Option<Vec<u8>>> {
+        let len = range.end.overflow_sub(range.start);
+        let mut buf = vec![0; len];
+
+        // Only the range is requested, which is a range GET for object
+        // storage.
+        let mut reader = self
+            .operator
+            .reader_with(path)
+            .range(range.start.cast::<u64>()..range.end.cast::<u64>())
+            .await?;
         let mut offset = 0;
         // Check if the reader point is at the end of the file.
         loop {
             match reader
-                .read(block.make_mut_slice().get_mut(offset..).ok_or_else(|| {
+                .read(buf.get_mut(offset..).ok_or_else(|| {
                     std::io::Error::new(std::io::ErrorKind::Other, "slice bounds out of range")
                 })?)
                 .await
@@ -125,10 +378,10 @@ impl Storage for Backend {
                     break;
                 }
                 Ok(bytes_read) => {
-                    // The block is not full, continue to read.
+                    // The range is not full, continue to read.
                     offset += bytes_read;
-                    if offset == self.block_size {
-                        // The block is full, process is done.
+                    if offset == len {
+                        // The range is full, process is done.
                         break;
                     }
                 }
@@ -142,7 +395,190 @@ impl Storage for Backend {
             }
         }
 
-        Ok(Some(block))
+        buf.truncate(offset);
+        Ok(Some(buf))
+    }
+
+    /// Read the stored content of a block, returns `None` if the block does
+    /// not exist.
+    ///
+    /// A large block is split into ranges fetched by concurrent streams, so
+    /// the reads of a single file are not capped by the throughput of one
+    /// connection to the object storage. The ranges are reassembled in order,
+    /// and those beyond the end of the object are dropped.
+    async fn read_stored_block(&self, path: &str) -> StorageResult<Option<Vec<u8>>> {
+        // A block with header is at most `HEADER_LEN` bytes longer than the
+        // block size, plus the overhead of encryption.
+        let max_len = self.max_stored_len();
+        let streams = self
+            .read_streams
+            .min(max_len.overflow_div(MIN_READ_STREAM_LEN))
+            .max(1);
+        if streams == 1 {
+            return self.read_range(path, 0..max_len).await;
+        }
+
+        let stream_len = max_len
+            .overflow_add(streams.overflow_sub(1))
+            .overflow_div(streams);
+        let ranges: Vec<_> = (0..max_len)
+            .step_by(stream_len)
+            .map(|start| start..start.overflow_add(stream_len).min(max_len))
+            .collect();
+        let parts = future::join_all(
+            ranges
+                .iter()
+                .map(|range| self.read_range(path, range.clone())),
+        )
+        .await;
+
+        let mut stored = Vec::with_capacity(max_len);
+        for (range, part) in ranges.into_iter().zip(parts) {
+            let part = match part {
+                Ok(Some(part)) => part,
+                Ok(None) => return Ok(None),
+                Err(e) => {
+                    // A range beyond the end of the object may be rejected,
+                    // rather than read as empty.
+                    let meta = self
+                        .policy
+                        .run(BackendOpClass::Read, || self.operator.stat(path))
+                        .await?;
+                    let len: usize = meta.content_length().cast();
+                    if range.start < len {
+                        return Err(e);
+                    }
+                    break;
+                }
+            };
+            let is_full = part.len() == range.len();
+            stored.extend(part);
+            if !is_full {
+                break;
+            }
+        }
+
+        Ok(Some(stored))
+    }
+
+    /// Read and decode the content of a block, returns `None` if the block
+    /// does not exist.
+    async fn read_block(&self, path: &str) -> StorageResult<Option<Vec<u8>>> {
+        let data = self
+            .policy
+            .run(BackendOpClass::Read, || async {
+                match self.operator.read(path).await {
+                    Ok(data) => Ok(Some(data)),
+                    Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+                    Err(e) => Err(e),
+                }
+            })
+            .await?;
+        data.map(|data| self.decode_block(path, data)).transpose()
+    }
+
+    /// Delete the object of `path`, which is retried by the error policy.
+    async fn delete_path(&self, path: &str) -> StorageResult<()> {
+        // Deleting a non-existing object is not an error in `openDAL`.
+        self.policy
+            .run(BackendOpClass::Delete, || self.operator.delete(path))
+            .await
+    }
+
+    /// Delete the objects under the directory of `path`, which is retried by
+    /// the error policy.
+    async fn delete_dir(&self, path: &str) -> StorageResult<()> {
+        self.policy
+            .run(BackendOpClass::Delete, || self.operator.remove_all(path))
+            .await
+    }
+
+    /// List the objects of blocks and chunks once.
+    async fn try_list_objects(&self) -> StorageResult<Vec<StoredObject>> {
+        let mut lister = self
+            .operator
+            .lister_with("/")
+            .recursive(true)
+            .metakey(Metakey::Mode | Metakey::ContentLength)
+            .await?;
+        let mut objects = vec![];
+        while let Some(entry) = lister.try_next().await? {
+            if entry.metadata().is_dir() {
+                continue;
+            }
+            if let Some(key) = parse_object_path(entry.path()) {
+                objects.push(StoredObject {
+                    key,
+                    size: entry.metadata().content_length(),
+                });
+            }
+        }
+        Ok(objects)
+    }
+}
+
+#[async_trait]
+impl BlockStore for Backend {
+    async fn get(&self, key: BlockKey) -> StorageResult<Option<Vec<u8>>> {
+        self.read_block(&get_versioned_block_path(key)).await
+    }
+
+    async fn put(&self, key: BlockKey, data: Vec<u8>) -> StorageResult<()> {
+        self.write_block(&get_versioned_block_path(key), &data)
+            .await
+    }
+
+    async fn delete(&self, key: BlockKey) -> StorageResult<()> {
+        self.delete_path(&get_versioned_block_path(key)).await
+    }
+
+    async fn delete_file(&self, ino: INum) -> StorageResult<()> {
+        self.delete_dir(&get_file_path(ino)).await
+    }
+}
+
+#[async_trait]
+impl ChunkStore for Backend {
+    async fn get_chunk(&self, hash: ChunkHash) -> StorageResult<Option<Vec<u8>>> {
+        self.read_block(&get_chunk_path(hash)).await
+    }
+
+    async fn put_chunk(&self, hash: ChunkHash, data: &[u8]) -> StorageResult<()> {
+        self.write_block(&get_chunk_path(hash), data).await
+    }
+
+    async fn delete_chunk(&self, hash: ChunkHash) -> StorageResult<()> {
+        self.delete_path(&get_chunk_path(hash)).await
+    }
+}
+
+#[async_trait]
+impl ObjectStore for Backend {
+    async fn list_objects(&self) -> StorageResult<Vec<StoredObject>> {
+        self.policy
+            .run(BackendOpClass::List, || self.try_list_objects())
+            .await
+    }
+
+    async fn delete_object(&self, key: ObjectKey) -> StorageResult<()> {
+        let path = match key {
+            ObjectKey::Block(key) => get_versioned_block_path(key),
+            ObjectKey::Chunk(hash) => get_chunk_path(hash),
+        };
+        self.delete_path(&path).await
+    }
+}
+
+#[async_trait]
+impl Storage for Backend {
+    async fn load_from_self(&self, ino: INum, block_id: usize) -> StorageResult<Option<Block>> {
+        let path = get_block_path(ino, block_id);
+        let Some(stored) = self.read_stored_block(&path).await? else {
+            return Ok(None);
+        };
+        let decoded = self.decode_block(&path, stored)?;
+
+        Ok(Some(Block::from_slice(self.block_size, &decoded)))
     }
 
     async fn load_from_backend(&self, _: INum, _: usize) -> StorageResult<Option<Block>> {
@@ -162,23 +598,12 @@ impl Storage for Backend {
 
         if block_start == 0 && block_end == self.block_size {
             // To store a whole block
-            let mut writer = self.operator.writer(&path).await?;
-            writer.write_all(block.as_slice()).await?;
-            writer.close().await?;
+            self.write_block(&path, block.as_slice()).await?;
             return Ok(());
         }
 
-        let mut dest = match self.operator.read(&path).await {
-            Ok(dest) => dest,
-            Err(e) => {
-                if e.kind() == ErrorKind::NotFound {
-                    // Create an empty block for overwriting is ok.
-                    vec![]
-                } else {
-                    return Err(e.into());
-                }
-            }
-        };
+        // Create an empty block for overwriting is ok.
+        let mut dest = self.read_block(&path).await?.unwrap_or_default();
 
         // Ensure that the vector is long enough to be overwritten
         if dest.len() < block_end {
@@ -189,15 +614,13 @@ impl Storage for Backend {
         dest.get_mut(block_start..block_end)
             .unwrap_or_else(|| unreachable!("The vector is ensured to be long enough."))
             .copy_from_slice(block.as_slice());
-        self.operator.write(&path, dest).await?;
+        self.write_block(&path, &dest).await?;
 
         Ok(())
     }
 
     async fn remove(&self, ino: INum) -> StorageResult<()> {
-        self.operator.remove_all(&get_file_path(ino)).await?;
-
-        Ok(())
+        self.delete_dir(&get_file_path(ino)).await
     }
 
     async fn invalidate(&self, _: INum) -> StorageResult<()> {
@@ -225,40 +648,47 @@ impl Storage for Backend {
         to_block: usize,
         fill_start: usize,
     ) -> StorageResult<()> {
-        let paths =
-            stream::iter(to_block..from_block).map(|block_id| get_block_path(ino, block_id));
-
         let file_path = get_file_path(ino);
 
         let file_exists = self.operator.is_exist(&file_path).await.unwrap_or(false);
 
         if file_exists {
             if to_block == 0 {
-                self.operator.remove_all(&file_path).await?;
+                self.delete_dir(&file_path).await?;
                 return Ok(());
             }
 
-            self.operator.remove_via(paths).await?;
+            self.policy
+                .run(BackendOpClass::Delete, || {
+                    let paths = stream::iter(to_block..from_block)
+                        .map(move |block_id| get_block_path(ino, block_id));
+                    self.operator.remove_via(paths)
+                })
+                .await?;
 
             // truncate the last block
             if to_block > 0 && fill_start < self.block_size {
                 let truncate_block_id = to_block.overflow_sub(1);
                 let path = get_block_path(ino, truncate_block_id);
-                match self.operator.read(&path).await {
-                    Ok(mut dest) => {
-                        dest.truncate(fill_start);
-                        self.operator.write(&path, dest).await?;
-                    }
-                    Err(e) => {
-                        // It's OK that the block is not found for truncate.
-                        if e.kind() != ErrorKind::NotFound {
-                            return Err(e.into());
-                        }
-                    }
+                // It's OK that the block is not found for truncate.
+                if let Some(mut dest) = self.read_block(&path).await? {
+                    dest.truncate(fill_start);
+                    self.write_block(&path, &dest).await?;
                 }
             }
         }
 
         Ok(())
     }
+
+    async fn punch_blocks(&self, ino: INum, blocks: Range<usize>) -> StorageResult<bool> {
+        self.policy
+            .run(BackendOpClass::Delete, || {
+                let paths =
+                    stream::iter(blocks.clone()).map(move |block_id| get_block_path(ino, block_id));
+                self.operator.remove_via(paths)
+            })
+            .await?;
+        Ok(true)
+    }
 }
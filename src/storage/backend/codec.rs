@@ -0,0 +1,197 @@
+//! The codec of blocks stored in the backend.
+//!
+//! A block is stored with a header recording its codec, if it's written with
+//! compression enabled:
+//!
+//! ```text
+//! | magic (4 bytes) | codec (1 byte) | original length (4 bytes, LE) | payload |
+//! ```
+//!
+//! Blocks without the header are stored as is, which are written with
+//! compression disabled, or before compression was supported.
+
+use std::borrow::Cow;
+
+use anyhow::anyhow;
+use clippy_utilities::{Cast, OverflowArithmetic};
+use datenlord::config::CompressionType;
+
+use crate::storage::error::StorageResult;
+use crate::storage::KB_SIZE;
+
+/// The magic number at the beginning of a block with header.
+const MAGIC: &[u8; 4] = b"DLCB";
+
+/// The length of the block header.
+pub const HEADER_LEN: usize = 9;
+
+/// The codec of a block stored as is.
+const CODEC_NONE: u8 = 0;
+
+/// The codec of a block compressed by LZ4.
+const CODEC_LZ4: u8 = 1;
+
+/// The codec of a block compressed by Zstandard.
+const CODEC_ZSTD: u8 = 2;
+
+/// The compression level of Zstandard.
+const ZSTD_LEVEL: i32 = 3;
+
+/// The size of the sample to check whether a block is compressible.
+const SAMPLE_SIZE: usize = 4 * KB_SIZE;
+
+/// Returns whether `compressed_len` saves enough space compared to
+/// `original_len`, at least 1/8 of it is saved.
+fn saves_enough(original_len: usize, compressed_len: usize) -> bool {
+    compressed_len <= original_len.overflow_sub(original_len.overflow_div(8))
+}
+
+/// Check whether `data` is worth compressing, by compressing a sample at the
+/// beginning of it with LZ4, which is cheap.
+///
+/// Data such as media files or archives are already compressed, compressing
+/// them again costs CPU for nothing.
+fn is_compressible(data: &[u8]) -> bool {
+    let sample = data.get(..SAMPLE_SIZE).unwrap_or(data);
+    !sample.is_empty() && saves_enough(sample.len(), lz4_flex::block::compress(sample).len())
+}
+
+/// Build a block of `codec` with a header.
+fn with_header(codec: u8, original_len: usize, payload: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(HEADER_LEN.overflow_add(payload.len()));
+    encoded.extend_from_slice(MAGIC);
+    encoded.push(codec);
+    encoded.extend_from_slice(&original_len.cast::<u32>().to_le_bytes());
+    encoded.extend_from_slice(payload);
+    encoded
+}
+
+/// Encode the content of a block with `compression`.
+///
+/// The block is stored without compression if it's not compressible, but
+/// with a header, so that it's never confused with a compressed one.
+pub fn encode(compression: CompressionType, data: &[u8]) -> StorageResult<Cow<'_, [u8]>> {
+    if compression == CompressionType::None {
+        // A raw block looks like a block with header, add a header to it.
+        if data.starts_with(MAGIC) {
+            return Ok(Cow::Owned(with_header(CODEC_NONE, data.len(), data)));
+        }
+        return Ok(Cow::Borrowed(data));
+    }
+
+    if !is_compressible(data) {
+        return Ok(Cow::Owned(with_header(CODEC_NONE, data.len(), data)));
+    }
+
+    let (codec, compressed) = match compression {
+        CompressionType::Lz4 => (CODEC_LZ4, lz4_flex::block::compress(data)),
+        CompressionType::Zstd => (CODEC_ZSTD, zstd::bulk::compress(data, ZSTD_LEVEL)?),
+        CompressionType::None => unreachable!("Uncompressed blocks are returned above."),
+    };
+
+    let encoded = if saves_enough(data.len(), compressed.len()) {
+        with_header(codec, data.len(), &compressed)
+    } else {
+        with_header(CODEC_NONE, data.len(), data)
+    };
+
+    Ok(Cow::Owned(encoded))
+}
+
+/// Decode the content of a block, with any codec.
+pub fn decode(data: Vec<u8>) -> StorageResult<Vec<u8>> {
+    if data.len() < HEADER_LEN || !data.starts_with(MAGIC) {
+        // A block stored as is.
+        return Ok(data);
+    }
+
+    let (header, payload) = data.split_at(HEADER_LEN);
+    let (codec, original_len) = match *header {
+        [_, _, _, _, codec, l0, l1, l2, l3] => {
+            (codec, u32::from_le_bytes([l0, l1, l2, l3]).cast::<usize>())
+        }
+        _ => unreachable!("The header is ensured to be {HEADER_LEN} bytes."),
+    };
+
+    let decoded = match codec {
+        CODEC_NONE => payload.to_vec(),
+        CODEC_LZ4 => lz4_flex::block::decompress(payload, original_len)
+            .map_err(|e| anyhow!("failed to decompress a LZ4 block: {e}"))?,
+        CODEC_ZSTD => zstd::bulk::decompress(payload, original_len)?,
+        _ => return Err(anyhow!("unknown codec {codec} of block").into()),
+    };
+
+    if decoded.len() != original_len {
+        return Err(anyhow!(
+            "the length of decoded block {} mismatches the header {original_len}",
+            decoded.len()
+        )
+        .into());
+    }
+
+    Ok(decoded)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use clippy_utilities::OverflowArithmetic;
+    use datenlord::config::CompressionType;
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+
+    use super::{decode, encode, HEADER_LEN, MAGIC};
+
+    /// A compressible block content.
+    fn compressible() -> Vec<u8> {
+        b"foo bar ".repeat(1024)
+    }
+
+    /// An incompressible block content.
+    fn incompressible() -> Vec<u8> {
+        let mut data = vec![0; 8192];
+        StdRng::seed_from_u64(0).fill_bytes(&mut data);
+        data
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = compressible();
+        for compression in [CompressionType::Lz4, CompressionType::Zstd] {
+            let encoded = encode(compression, &data).unwrap().into_owned();
+            assert!(encoded.len() < data.len());
+            assert_eq!(decode(encoded).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_no_compression() {
+        let data = compressible();
+        let encoded = encode(CompressionType::None, &data).unwrap().into_owned();
+        assert_eq!(encoded, data);
+        assert_eq!(decode(encoded).unwrap(), data);
+
+        // A raw block which looks like a block with header.
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(b"foo bar ");
+        let encoded = encode(CompressionType::None, &data).unwrap().into_owned();
+        assert_eq!(encoded.len(), data.len() + HEADER_LEN);
+        assert_eq!(decode(encoded).unwrap(), data);
+    }
+
+    #[test]
+    fn test_incompressible() {
+        let data = incompressible();
+        let encoded = encode(CompressionType::Zstd, &data).unwrap().into_owned();
+        assert_eq!(encoded.len(), data.len() + HEADER_LEN);
+        assert_eq!(decode(encoded).unwrap(), data);
+    }
+
+    #[test]
+    fn test_corrupted() {
+        let data = compressible();
+        let mut encoded = encode(CompressionType::Lz4, &data).unwrap().into_owned();
+        encoded.truncate(encoded.len().overflow_div(2));
+        assert!(decode(encoded).is_err());
+    }
+}
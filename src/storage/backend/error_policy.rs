@@ -0,0 +1,417 @@
+//! The policy of the failures of the backend.
+//!
+//! A failure of the backend is classified before it's returned to users. The
+//! transient ones, such as `503 SlowDown` of S3, a timeout or a connection
+//! reset, are retried with exponential backoff up to the budget of the class
+//! of the operation, `--storage-backend-retries`, and are `EIO` once the budget
+//! is exhausted or the next retry would miss the deadline of the request. The
+//! permanent ones are returned at once as the errno they mean, an exceeded
+//! quota of the bucket as `EDQUOT`, a full storage as `ENOSPC`, and refused
+//! credentials as `EACCES`, while the others are `EIO`.
+//!
+//! The error codes of S3 are found in the messages of the failures, which are
+//! mapped by `--storage-backend-error-map` over the built-in mappings, so the
+//! codes of other S3 compatible stores can be mapped as well.
+
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::future::Future;
+use std::time::Duration;
+use std::{io, iter};
+
+use clippy_utilities::OverflowArithmetic;
+use datenlord::config::{BackendErrorAction, BackendErrorConfig, BackendOpClass, RetryBudgets};
+use nix::errno::Errno;
+use opendal::ErrorKind;
+use tracing::debug;
+
+use crate::storage::error::{StorageError, StorageResult};
+use crate::storage::transfer::deadline;
+
+/// The built-in mappings of the error codes of S3 and the S3 compatible
+/// stores.
+const BUILTIN_ERROR_MAP: [(&str, BackendErrorAction); 10] = [
+    ("QuotaExceeded", BackendErrorAction::Errno(libc::EDQUOT)),
+    (
+        "XMinioAdminBucketQuotaExceeded",
+        BackendErrorAction::Errno(libc::EDQUOT),
+    ),
+    ("XMinioStorageFull", BackendErrorAction::Errno(libc::ENOSPC)),
+    ("AccessDenied", BackendErrorAction::Errno(libc::EACCES)),
+    ("AllAccessDisabled", BackendErrorAction::Errno(libc::EACCES)),
+    (
+        "InvalidAccessKeyId",
+        BackendErrorAction::Errno(libc::EACCES),
+    ),
+    (
+        "SignatureDoesNotMatch",
+        BackendErrorAction::Errno(libc::EACCES),
+    ),
+    ("ExpiredToken", BackendErrorAction::Errno(libc::EACCES)),
+    ("InvalidToken", BackendErrorAction::Errno(libc::EACCES)),
+    ("SlowDown", BackendErrorAction::Retry),
+];
+
+/// How a failure of the backend is handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    /// Retried within the budget, then returned as `EIO`
+    Transient,
+    /// Returned as the errno at once
+    Permanent(Errno),
+}
+
+/// How the failures of the backend are retried and returned.
+#[derive(Debug)]
+pub struct ErrorPolicy {
+    /// The retries of the transient failures by operation class
+    retries: RetryBudgets,
+    /// The delay before the first retry, doubled on every retry after it
+    backoff: Duration,
+    /// Error code -> how its failures are handled
+    codes: HashMap<String, BackendErrorAction>,
+}
+
+impl Default for ErrorPolicy {
+    fn default() -> Self {
+        Self::new(&BackendErrorConfig::default())
+    }
+}
+
+impl ErrorPolicy {
+    /// Create the policy of `config`.
+    #[must_use]
+    pub fn new(config: &BackendErrorConfig) -> Self {
+        let codes = BUILTIN_ERROR_MAP
+            .iter()
+            .map(|&(code, action)| (code.to_owned(), action))
+            .chain(
+                config
+                    .error_map
+                    .iter()
+                    .map(|mapping| (mapping.code.clone(), mapping.action)),
+            )
+            .collect();
+        Self {
+            retries: config.retries,
+            backoff: config.retry_backoff,
+            codes,
+        }
+    }
+
+    /// Run `op` of `class` on the backend, it's retried on the transient
+    /// failures, and the failure returned is mapped to an errno.
+    pub async fn run<T, E, F, Fut>(&self, class: BackendOpClass, mut op: F) -> StorageResult<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: Into<StorageError>,
+    {
+        let budget = self.retries.of(class);
+        let mut backoff = self.backoff;
+        let mut retries = 0;
+        loop {
+            let err: StorageError = match op().await {
+                Ok(output) => return Ok(output),
+                Err(e) => e.into(),
+            };
+            let errno = match self.classify(&err) {
+                // Not a failure of the backend, such as a corrupted block.
+                None => return Err(err),
+                Some(Failure::Permanent(errno)) => errno,
+                Some(Failure::Transient) => {
+                    // The retries must not outlive the request.
+                    if retries < budget && deadline::remaining(backoff) == Some(backoff) {
+                        debug!("Backend {class} failed, retry in {backoff:?}: {err}");
+                        tokio::time::sleep(backoff).await;
+                        retries = retries.overflow_add(1);
+                        backoff = backoff.saturating_mul(2);
+                        continue;
+                    }
+                    Errno::EIO
+                }
+            };
+            return Err(StorageError::Backend {
+                class,
+                retries,
+                message: err.to_string(),
+                source: errno,
+            });
+        }
+    }
+
+    /// How `err` is handled, `None` if it's not a failure of the backend.
+    #[allow(clippy::wildcard_enum_match_arm)]
+    fn classify(&self, err: &StorageError) -> Option<Failure> {
+        match *err {
+            StorageError::OpenDalError(ref e) => Some(self.classify_opendal(e)),
+            StorageError::StdIoError(ref e) => Some(self.classify_io(e)),
+            _ => None,
+        }
+    }
+
+    /// How a failure of `openDAL` is handled.
+    #[allow(clippy::wildcard_enum_match_arm)]
+    fn classify_opendal(&self, err: &opendal::Error) -> Failure {
+        if let Some(failure) = self.classify_code(&err.to_string()) {
+            return failure;
+        }
+        // The failures of the local file system carry their errnos.
+        let os_failure = iter::successors(err.source(), |e| e.source())
+            .filter_map(|e| e.downcast_ref::<io::Error>())
+            .find_map(|e| e.raw_os_error().and_then(classify_errno));
+        if let Some(failure) = os_failure {
+            return failure;
+        }
+        match err.kind() {
+            ErrorKind::PermissionDenied => Failure::Permanent(Errno::EACCES),
+            ErrorKind::NotFound => Failure::Permanent(Errno::ENOENT),
+            ErrorKind::RateLimited => Failure::Transient,
+            _ if err.is_temporary() => Failure::Transient,
+            _ => Failure::Permanent(Errno::EIO),
+        }
+    }
+
+    /// How an I/O failure is handled, which may wrap a failure of `openDAL`.
+    #[allow(clippy::wildcard_enum_match_arm)]
+    fn classify_io(&self, err: &io::Error) -> Failure {
+        if let Some(inner) = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<opendal::Error>())
+        {
+            return self.classify_opendal(inner);
+        }
+        if let Some(failure) = err.raw_os_error().and_then(classify_errno) {
+            return failure;
+        }
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => Failure::Permanent(Errno::EACCES),
+            io::ErrorKind::NotFound => Failure::Permanent(Errno::ENOENT),
+            io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::UnexpectedEof => Failure::Transient,
+            _ => Failure::Permanent(Errno::EIO),
+        }
+    }
+
+    /// How the failure with an error code mapped in `message` is handled,
+    /// `None` if no code mapped is in it.
+    fn classify_code(&self, message: &str) -> Option<Failure> {
+        message
+            .split(|c: char| !c.is_alphanumeric())
+            .find_map(|word| self.codes.get(word))
+            .map(|&action| match action {
+                BackendErrorAction::Retry => Failure::Transient,
+                BackendErrorAction::Errno(errno) => Failure::Permanent(Errno::from_raw(errno)),
+            })
+    }
+}
+
+/// How a failure with the OS errno `raw` is handled, `None` if it's handled
+/// by its kind.
+#[allow(clippy::wildcard_enum_match_arm)]
+fn classify_errno(raw: i32) -> Option<Failure> {
+    let errno = Errno::from_raw(raw);
+    match errno {
+        Errno::EDQUOT
+        | Errno::ENOSPC
+        | Errno::EFBIG
+        | Errno::EACCES
+        | Errno::EPERM
+        | Errno::EROFS => Some(Failure::Permanent(errno)),
+        Errno::ETIMEDOUT
+        | Errno::ECONNRESET
+        | Errno::ECONNREFUSED
+        | Errno::ECONNABORTED
+        | Errno::EAGAIN
+        | Errno::EINTR => Some(Failure::Transient),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::io;
+    use std::time::Duration;
+
+    use clippy_utilities::OverflowArithmetic;
+    use datenlord::config::{BackendErrorConfig, BackendOpClass};
+    use nix::errno::Errno;
+    use opendal::ErrorKind;
+    use tokio::time::Instant;
+
+    use super::{ErrorPolicy, Failure};
+    use crate::storage::error::StorageError;
+    use crate::storage::transfer::deadline;
+
+    /// A failure of S3 with `code`, as it's formatted by `openDAL`.
+    fn s3_error(code: &str) -> opendal::Error {
+        opendal::Error::new(
+            ErrorKind::Unexpected,
+            &format!("S3Error {{ code: \"{code}\", message: \"\", resource: \"/0/0.block\" }}"),
+        )
+    }
+
+    #[test]
+    fn test_classify() {
+        let policy = ErrorPolicy::default();
+        let classify = |err: StorageError| policy.classify(&err);
+
+        assert_eq!(
+            classify(s3_error("QuotaExceeded").into()),
+            Some(Failure::Permanent(Errno::EDQUOT))
+        );
+        assert_eq!(
+            classify(s3_error("InvalidAccessKeyId").into()),
+            Some(Failure::Permanent(Errno::EACCES))
+        );
+        assert_eq!(
+            classify(s3_error("SlowDown").into()),
+            Some(Failure::Transient)
+        );
+        assert_eq!(
+            classify(s3_error("NoSuchUpload").into()),
+            Some(Failure::Permanent(Errno::EIO))
+        );
+        // A `503` is temporary in `openDAL`
+        let unavailable = opendal::Error::new(ErrorKind::Unexpected, "unavailable");
+        assert_eq!(
+            classify(unavailable.set_temporary().into()),
+            Some(Failure::Transient)
+        );
+        let denied = opendal::Error::new(ErrorKind::PermissionDenied, "denied");
+        assert_eq!(
+            classify(denied.into()),
+            Some(Failure::Permanent(Errno::EACCES))
+        );
+
+        // The I/O failures, which may wrap a failure of `openDAL`
+        assert_eq!(
+            classify(io::Error::from_raw_os_error(libc::ENOSPC).into()),
+            Some(Failure::Permanent(Errno::ENOSPC))
+        );
+        assert_eq!(
+            classify(io::Error::from_raw_os_error(libc::ECONNRESET).into()),
+            Some(Failure::Transient)
+        );
+        let wrapped = io::Error::new(io::ErrorKind::Other, s3_error("QuotaExceeded"));
+        assert_eq!(
+            classify(wrapped.into()),
+            Some(Failure::Permanent(Errno::EDQUOT))
+        );
+
+        // Not a failure of the backend
+        assert_eq!(
+            classify(StorageError::OutOfRange {
+                maximum: 8,
+                found: 16,
+            }),
+            None
+        );
+    }
+
+    #[test]
+    fn test_error_map() {
+        let config = BackendErrorConfig {
+            error_map: vec![
+                "QuotaExceeded=ENOSPC".parse().unwrap(),
+                "InternalError=retry".parse().unwrap(),
+            ],
+            ..BackendErrorConfig::default()
+        };
+        let policy = ErrorPolicy::new(&config);
+        assert_eq!(
+            policy.classify(&s3_error("QuotaExceeded").into()),
+            Some(Failure::Permanent(Errno::ENOSPC))
+        );
+        assert_eq!(
+            policy.classify(&s3_error("InternalError").into()),
+            Some(Failure::Transient)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries() {
+        let policy = ErrorPolicy::default();
+        let budget = policy.retries.of(BackendOpClass::Read);
+
+        // Exhausts the budget
+        let mut attempts = 0_u32;
+        let err = policy
+            .run(BackendOpClass::Read, || {
+                attempts = attempts.overflow_add(1);
+                async { Err::<(), _>(s3_error("SlowDown")) }
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(attempts, budget.overflow_add(1));
+        assert!(matches!(
+            err,
+            StorageError::Backend {
+                retries,
+                source: Errno::EIO,
+                ..
+            } if retries == budget
+        ));
+
+        // Succeeds on a retry
+        let mut attempts = 0_u32;
+        policy
+            .run(BackendOpClass::Write, || {
+                attempts = attempts.overflow_add(1);
+                let res = if attempts < 2 {
+                    Err(io::Error::from_raw_os_error(libc::ETIMEDOUT))
+                } else {
+                    Ok(())
+                };
+                async move { res }
+            })
+            .await
+            .unwrap();
+        assert_eq!(attempts, 2);
+
+        // Not retried
+        let mut attempts = 0_u32;
+        let err = policy
+            .run(BackendOpClass::Delete, || {
+                attempts = attempts.overflow_add(1);
+                async { Err::<(), _>(s3_error("AccessDenied")) }
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(attempts, 1);
+        assert!(matches!(
+            err,
+            StorageError::Backend {
+                retries: 0,
+                source: Errno::EACCES,
+                ..
+            }
+        ));
+
+        // Not retried beyond the deadline of the request
+        let mut attempts = 0_u32;
+        let deadline = Instant::now() + Duration::from_millis(10);
+        let err = deadline::scope(
+            deadline,
+            policy.run(BackendOpClass::Read, || {
+                attempts = attempts.overflow_add(1);
+                async { Err::<(), _>(s3_error("SlowDown")) }
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(attempts, 1);
+        assert!(matches!(
+            err,
+            StorageError::Backend {
+                source: Errno::EIO,
+                ..
+            }
+        ));
+    }
+}
@@ -1,6 +1,8 @@
 //! The backend storage.
 
 mod backend_impl;
+mod codec;
+mod error_policy;
 
 pub use backend_impl::{Backend, BackendBuilder};
 
@@ -1,9 +1,16 @@
 use std::path::Path;
+use std::sync::Arc;
 
+use datenlord::config::CompressionType;
+use opendal::services::Fs;
+use opendal::Operator;
 use tokio::fs;
 
-use super::{prepare_backend, BACKEND_ROOT, BLOCK_CONTENT, BLOCK_SIZE_IN_BYTES};
-use crate::storage::{Block, Storage};
+use super::{prepare_backend, Backend, BACKEND_ROOT, BLOCK_CONTENT, BLOCK_SIZE_IN_BYTES};
+use crate::storage::dedup::{ChunkHash, ChunkStore};
+use crate::storage::encryption::{BlockCipher, DataKey};
+use crate::storage::gc::{ObjectKey, ObjectStore};
+use crate::storage::{Block, BlockKey, BlockStore, Storage, StorageError};
 
 #[tokio::test]
 async fn test_backend_read() {
@@ -240,3 +247,170 @@ async fn test_unused() {
 
     fs::remove_dir_all(backend_root).await.unwrap();
 }
+
+#[tokio::test]
+async fn test_compression() {
+    let backend_root = format!("{BACKEND_ROOT}/compression");
+    fs::create_dir_all(&backend_root).await.unwrap();
+    let (backend, _) = prepare_backend(&backend_root);
+    let backend = backend.with_compression(CompressionType::Lz4);
+
+    let backend_root = Path::new(&backend_root);
+
+    let block = Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT);
+    backend.store(0, 0, block).await.unwrap();
+
+    // The block is too small to be compressed, but it's stored with a header.
+    let stored = fs::read(backend_root.join("0").join("0.block"))
+        .await
+        .unwrap();
+    assert!(stored.starts_with(b"DLCB"));
+    assert!(stored.ends_with(BLOCK_CONTENT));
+
+    let loaded = backend.load(0, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), BLOCK_CONTENT);
+
+    // Partial writes and truncation merge with the decoded block.
+    let mut block = Block::from_slice(BLOCK_SIZE_IN_BYTES, b"baz ");
+    block.set_end(4);
+    backend.store(0, 0, block).await.unwrap();
+    backend.truncate(0, 1, 1, 6).await.unwrap();
+    let loaded = backend.load(0, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), b"baz ba\0\0");
+
+    fs::remove_dir_all(backend_root).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_encryption() {
+    let backend_root = format!("{BACKEND_ROOT}/encryption");
+    fs::create_dir_all(&backend_root).await.unwrap();
+    let (backend, _) = prepare_backend(&backend_root);
+    let cipher = Arc::new(BlockCipher::new(&[DataKey::generate(0)]).unwrap());
+    let backend = backend
+        .with_compression(CompressionType::Lz4)
+        .with_cipher(cipher);
+
+    let backend_root = Path::new(&backend_root);
+
+    let block = Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT);
+    backend.store(0, 0, block).await.unwrap();
+
+    // The raw object never exposes the content.
+    let stored = fs::read(backend_root.join("0").join("0.block"))
+        .await
+        .unwrap();
+    assert!(stored.starts_with(b"DLEB"));
+    assert!(!stored
+        .windows(BLOCK_CONTENT.len())
+        .any(|window| window == BLOCK_CONTENT));
+
+    let loaded = backend.load(0, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), BLOCK_CONTENT);
+
+    // Partial writes and truncation merge with the decrypted block.
+    let mut block = Block::from_slice(BLOCK_SIZE_IN_BYTES, b"baz ");
+    block.set_end(4);
+    backend.store(0, 0, block).await.unwrap();
+    backend.truncate(0, 1, 1, 6).await.unwrap();
+    let loaded = backend.load(0, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), b"baz ba\0\0");
+
+    // A block moved from another path is refused.
+    fs::copy(
+        backend_root.join("0").join("0.block"),
+        backend_root.join("0").join("1.block"),
+    )
+    .await
+    .unwrap();
+    assert!(matches!(
+        backend.load(0, 1).await,
+        Err(StorageError::Decryption { .. })
+    ));
+
+    fs::remove_dir_all(backend_root).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_list_and_delete_objects() {
+    let backend_root = format!("{BACKEND_ROOT}/list_objects");
+    fs::create_dir_all(&backend_root).await.unwrap();
+    let (backend, _) = prepare_backend(&backend_root);
+    let backend_root = Path::new(&backend_root);
+
+    let block = BlockKey::new(2, 0);
+    let versioned = BlockKey {
+        version: 3,
+        ..BlockKey::new(2, 1)
+    };
+    let hash = ChunkHash::of(BLOCK_CONTENT);
+    backend.put(block, BLOCK_CONTENT.to_vec()).await.unwrap();
+    backend
+        .put(versioned, BLOCK_CONTENT.to_vec())
+        .await
+        .unwrap();
+    backend.put_chunk(hash, BLOCK_CONTENT).await.unwrap();
+    // Objects not written by the backend are skipped.
+    fs::write(backend_root.join("2").join("foo.block"), BLOCK_CONTENT)
+        .await
+        .unwrap();
+    fs::write(backend_root.join("bar"), BLOCK_CONTENT)
+        .await
+        .unwrap();
+
+    let mut objects = backend.list_objects().await.unwrap();
+    objects.sort_by_key(|object| object.key);
+    let keys: Vec<ObjectKey> = objects.iter().map(|object| object.key).collect();
+    assert_eq!(
+        keys,
+        vec![
+            ObjectKey::Block(block),
+            ObjectKey::Block(versioned),
+            ObjectKey::Chunk(hash),
+        ]
+    );
+    assert!(objects.iter().all(|object| object.size > 0));
+
+    backend
+        .delete_object(ObjectKey::Block(versioned))
+        .await
+        .unwrap();
+    backend.delete_object(ObjectKey::Chunk(hash)).await.unwrap();
+    assert_eq!(backend.list_objects().await.unwrap().len(), 1);
+    assert!(backend.get_chunk(hash).await.unwrap().is_none());
+
+    fs::remove_dir_all(backend_root).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_read_streams() {
+    const LARGE_BLOCK_SIZE: usize = 0x40_0000;
+
+    let backend_root = format!("{BACKEND_ROOT}/read_streams");
+    fs::create_dir_all(&backend_root).await.unwrap();
+    let mut builder = Fs::default();
+    builder.root(&backend_root);
+    let operator = Operator::new(builder).unwrap().finish();
+    let backend = Backend::new(operator, LARGE_BLOCK_SIZE).with_read_streams(4);
+
+    // A whole block is fetched in ranges, which are reassembled in order.
+    let content: Vec<u8> = (0..=u8::MAX).cycle().take(LARGE_BLOCK_SIZE).collect();
+    let block = Block::from_slice(LARGE_BLOCK_SIZE, &content);
+    backend.store(0, 0, block).await.unwrap();
+    let loaded = backend.load(0, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), content.as_slice());
+
+    // The ranges beyond the end of a short block are dropped.
+    let mut block = Block::from_slice(LARGE_BLOCK_SIZE, BLOCK_CONTENT);
+    block.set_end(BLOCK_SIZE_IN_BYTES);
+    backend.store(0, 1, block).await.unwrap();
+    let loaded = backend.load(0, 1).await.unwrap().unwrap();
+    assert_eq!(&loaded.as_slice()[..BLOCK_SIZE_IN_BYTES], BLOCK_CONTENT);
+    assert!(loaded.as_slice()[BLOCK_SIZE_IN_BYTES..]
+        .iter()
+        .all(|&b| b == 0));
+
+    assert!(backend.load(0, 2).await.unwrap().is_none());
+
+    fs::remove_dir_all(backend_root).await.unwrap();
+}
@@ -1,7 +1,6 @@
-use std::io::ErrorKind as StdErrorKind;
 use std::path::Path;
 
-use opendal::ErrorKind as OpenDalErrorKind;
+use nix::errno::Errno;
 use tokio::fs;
 
 use super::{prepare_backend, BACKEND_ROOT, BLOCK_CONTENT, BLOCK_SIZE_IN_BYTES};
@@ -39,8 +38,10 @@ async fn test_failed_load() {
     assert!(
         matches!(
             err,
-            StorageError::StdIoError(ref e)
-            if e.kind() == StdErrorKind::PermissionDenied
+            StorageError::Backend {
+                source: Errno::EACCES,
+                ..
+            }
         ),
         "Mismatched: error={err:?}"
     );
@@ -52,8 +53,10 @@ async fn test_failed_load() {
     assert!(
         matches!(
             err,
-            StorageError::OpenDalError(ref e)
-            if e.kind() == OpenDalErrorKind::PermissionDenied
+            StorageError::Backend {
+                source: Errno::EACCES,
+                ..
+            }
         ),
         "Mismatched: error={err:?}"
     );
@@ -79,8 +82,10 @@ async fn test_failed_store() {
     assert!(
         matches!(
             err,
-            StorageError::StdIoError(ref e)
-            if e.kind() == StdErrorKind::Other  // openDAL mapped all write error to `StdErrorKind::Other`
+            StorageError::Backend {
+                source: Errno::EACCES,
+                ..
+            }
         ),
         "Mismatched: error={err:?}"
     );
@@ -95,8 +100,10 @@ async fn test_failed_store() {
     assert!(
         matches!(
             err,
-            StorageError::OpenDalError(ref e)
-            if e.kind() == OpenDalErrorKind::PermissionDenied
+            StorageError::Backend {
+                source: Errno::EACCES,
+                ..
+            }
         ),
         "Mismatched: error={err:?}"
     );
@@ -111,8 +118,10 @@ async fn test_failed_store() {
     assert!(
         matches!(
             err,
-            StorageError::OpenDalError(ref e)
-            if e.kind() == OpenDalErrorKind::PermissionDenied
+            StorageError::Backend {
+                source: Errno::EACCES,
+                ..
+            }
         ),
         "Mismatched: error={err:?}"
     );
@@ -146,8 +155,10 @@ async fn test_failed_partial_store() {
     assert!(
         matches!(
             err,
-            StorageError::OpenDalError(ref e)
-            if e.kind() == OpenDalErrorKind::PermissionDenied
+            StorageError::Backend {
+                source: Errno::EACCES,
+                ..
+            }
         ),
         "Mismatched: error={err:?}"
     );
@@ -167,8 +178,10 @@ async fn test_failed_partial_store() {
     assert!(
         matches!(
             err,
-            StorageError::OpenDalError(ref e)
-            if e.kind() == OpenDalErrorKind::PermissionDenied
+            StorageError::Backend {
+                source: Errno::EACCES,
+                ..
+            }
         ),
         "Mismatched: error={err:?}"
     );
@@ -195,8 +208,10 @@ async fn test_failed_remove() {
     assert!(
         matches!(
             err,
-            StorageError::OpenDalError(ref e)
-            if e.kind() == OpenDalErrorKind::PermissionDenied
+            StorageError::Backend {
+                source: Errno::EACCES,
+                ..
+            }
         ),
         "Mismatched: error={err:?}"
     );
@@ -227,8 +242,10 @@ async fn test_failed_truncate() {
     assert!(
         matches!(
             err,
-            StorageError::OpenDalError(ref e)
-            if e.kind() == OpenDalErrorKind::PermissionDenied
+            StorageError::Backend {
+                source: Errno::EACCES,
+                ..
+            }
         ),
         "Mismatched: error={err:?}"
     );
@@ -241,8 +258,10 @@ async fn test_failed_truncate() {
     assert!(
         matches!(
             err,
-            StorageError::OpenDalError(ref e)
-            if e.kind() == OpenDalErrorKind::PermissionDenied
+            StorageError::Backend {
+                source: Errno::EACCES,
+                ..
+            }
         ),
         "Mismatched: error={err:?}"
     );
@@ -255,8 +274,10 @@ async fn test_failed_truncate() {
     assert!(
         matches!(
             err,
-            StorageError::OpenDalError(ref e)
-            if e.kind() == OpenDalErrorKind::PermissionDenied
+            StorageError::Backend {
+                source: Errno::EACCES,
+                ..
+            }
         ),
         "Mismatched: error={err:?}"
     );
@@ -269,8 +290,10 @@ async fn test_failed_truncate() {
     assert!(
         matches!(
             err,
-            StorageError::OpenDalError(ref e)
-            if e.kind() == OpenDalErrorKind::PermissionDenied
+            StorageError::Backend {
+                source: Errno::EACCES,
+                ..
+            }
         ),
         "Mismatched: error={err:?}"
     );
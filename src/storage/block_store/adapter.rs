@@ -0,0 +1,135 @@
+//! The adapter to use a `BlockStore` as the backend of the storage layers.
+
+use std::ops::Range;
+
+use async_trait::async_trait;
+use clippy_utilities::OverflowArithmetic;
+
+use super::{BlockKey, BlockStore};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::error::StorageResult;
+use crate::storage::{Block, Storage};
+
+/// A `Storage` backed by a `BlockStore`.
+///
+/// This is the bottom of the storage layers, it has no cache and no backend.
+#[derive(Debug)]
+pub struct BlockStoreBackend<B> {
+    /// The inner block store
+    store: B,
+    /// The size of blocks
+    block_size: usize,
+}
+
+impl<B> BlockStoreBackend<B> {
+    /// Create a `BlockStoreBackend` with a `BlockStore`.
+    pub fn new(store: B, block_size: usize) -> Self {
+        Self { store, block_size }
+    }
+
+    /// Get the inner `BlockStore`.
+    pub fn block_store(&self) -> &B {
+        &self.store
+    }
+}
+
+#[async_trait]
+impl<B> Storage for BlockStoreBackend<B>
+where
+    B: BlockStore + Send + Sync,
+{
+    async fn load_from_self(&self, ino: INum, block_id: usize) -> StorageResult<Option<Block>> {
+        let data = self.store.get(BlockKey::new(ino, block_id)).await?;
+        Ok(data.map(|data| Block::from_slice(self.block_size, &data)))
+    }
+
+    async fn load_from_backend(&self, _: INum, _: usize) -> StorageResult<Option<Block>> {
+        // This storage has no backend.
+        Ok(None)
+    }
+
+    async fn cache_block_from_backend(&self, _: INum, _: usize, _: Block) -> StorageResult<()> {
+        unreachable!("This storage has no backend, and has no cache.");
+    }
+
+    async fn store(&self, ino: INum, block_id: usize, block: Block) -> StorageResult<()> {
+        let key = BlockKey::new(ino, block_id);
+
+        let block_start = block.start();
+        let block_end = block.end();
+
+        if block_start == 0 && block_end == self.block_size {
+            // To store a whole block
+            return self.store.put(key, block.as_slice().to_vec()).await;
+        }
+
+        let mut dest = self.store.get(key).await?.unwrap_or_default();
+
+        // Ensure that the vector is long enough to be overwritten
+        if dest.len() < block_end {
+            dest.resize(block_end, 0);
+        }
+
+        // merge two blocks
+        dest.get_mut(block_start..block_end)
+            .unwrap_or_else(|| unreachable!("The vector is ensured to be long enough."))
+            .copy_from_slice(block.as_slice());
+        self.store.put(key, dest).await
+    }
+
+    async fn remove(&self, ino: INum) -> StorageResult<()> {
+        self.store.delete_file(ino).await
+    }
+
+    async fn invalidate(&self, _: INum) -> StorageResult<()> {
+        // This storage has no cache, therefore, its contents cannot be
+        // invalidated.
+        Ok(())
+    }
+
+    async fn flush(&self, _: INum) -> StorageResult<()> {
+        // This storage has no cache and backend, therefore, there is no need to
+        // flush its data.
+        Ok(())
+    }
+
+    async fn flush_all(&self) -> StorageResult<()> {
+        // This storage has no cache and backend, therefore, there is no need to
+        // flush its data.
+        Ok(())
+    }
+
+    async fn truncate(
+        &self,
+        ino: INum,
+        from_block: usize,
+        to_block: usize,
+        fill_start: usize,
+    ) -> StorageResult<()> {
+        if to_block == 0 {
+            return self.store.delete_file(ino).await;
+        }
+
+        for block_id in to_block..from_block {
+            self.store.delete(BlockKey::new(ino, block_id)).await?;
+        }
+
+        // truncate the last block
+        if fill_start < self.block_size {
+            let key = BlockKey::new(ino, to_block.overflow_sub(1));
+            if let Some(mut dest) = self.store.get(key).await? {
+                dest.truncate(fill_start);
+                self.store.put(key, dest).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn punch_blocks(&self, ino: INum, blocks: Range<usize>) -> StorageResult<bool> {
+        for block_id in blocks {
+            self.store.delete(BlockKey::new(ino, block_id)).await?;
+        }
+        Ok(true)
+    }
+}
@@ -0,0 +1,148 @@
+//! The `BlockStore` guarded by a circuit breaker.
+//!
+//! After `failures` consecutive failures of the inner store, such as a replica
+//! node flapping, the breaker trips and the operations fail fast for the
+//! cooldown instead of waiting for the store to time out, so the replicated
+//! store reads the other replicas at once. Once the cooldown elapses, an
+//! operation is passed to probe the store, which closes the breaker if it
+//! succeeds, or keeps it open for another cooldown. A probe cancelled, such as
+//! a read hedged by another replica, is retried after the cooldown as well.
+
+use std::future::Future;
+
+use async_trait::async_trait;
+use clippy_utilities::OverflowArithmetic;
+use datenlord::config::BreakerPolicy;
+use datenlord::metrics::STORAGE_METRICS;
+use nix::errno::Errno;
+use parking_lot::Mutex;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use super::{BlockKey, BlockStore};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::error::{StorageError, StorageResult};
+
+/// The state of a circuit breaker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// The operations are passed, with the number of consecutive failures
+    Closed(u32),
+    /// The operations fail fast until the instant, when one is passed to
+    /// probe the store
+    Open(Instant),
+}
+
+/// A `BlockStore` failing fast while its inner store fails consecutively.
+#[derive(Debug)]
+pub struct BreakerBlockStore<S> {
+    /// The inner store
+    inner: S,
+    /// The name of the inner store, such as the id of a replica node
+    target: String,
+    /// The policy of the breaker
+    policy: BreakerPolicy,
+    /// The state of the breaker
+    state: Mutex<State>,
+}
+
+impl<S> BreakerBlockStore<S> {
+    /// Guard `inner` named `target` by a circuit breaker of `policy`.
+    #[must_use]
+    pub fn new(inner: S, target: &str, policy: BreakerPolicy) -> Self {
+        Self {
+            inner,
+            target: target.to_owned(),
+            policy,
+            state: Mutex::new(State::Closed(0)),
+        }
+    }
+
+    /// The state of the breaker opened now.
+    fn open_now(&self) -> State {
+        let now = Instant::now();
+        State::Open(now.checked_add(self.policy.cooldown).unwrap_or(now))
+    }
+
+    /// Whether an operation is passed to the inner store. Once the cooldown
+    /// elapses, the breaker is rearmed for the probe passed.
+    fn admit(&self) -> bool {
+        let mut state = self.state.lock();
+        match *state {
+            State::Closed(_) => true,
+            State::Open(until) => {
+                if Instant::now() < until {
+                    return false;
+                }
+                *state = self.open_now();
+                true
+            }
+        }
+    }
+
+    /// Record the outcome of an operation passed.
+    fn record(&self, succeeded: bool) {
+        let mut state = self.state.lock();
+        match (*state, succeeded) {
+            (State::Closed(_), true) => *state = State::Closed(0),
+            (State::Open(_), true) => {
+                *state = State::Closed(0);
+                info!("Circuit breaker of {} is closed, it recovers.", self.target);
+            }
+            (State::Closed(failures), false) => {
+                let failures = failures.overflow_add(1);
+                if failures < self.policy.failures {
+                    *state = State::Closed(failures);
+                    return;
+                }
+                *state = self.open_now();
+                STORAGE_METRICS.breaker_trip_count_inc(&self.target);
+                warn!(
+                    "Circuit breaker of {} is open after {failures} consecutive failures, the \
+                     operations on it fail fast for {:?}.",
+                    self.target, self.policy.cooldown
+                );
+            }
+            // The probe fails, the store is probed again after the cooldown.
+            (State::Open(_), false) => *state = self.open_now(),
+        }
+    }
+
+    /// Run `op` on the inner store if it's passed by the breaker.
+    async fn guard<T, Fut>(&self, op: Fut) -> StorageResult<T>
+    where
+        Fut: Future<Output = StorageResult<T>>,
+    {
+        if !self.admit() {
+            return Err(StorageError::CircuitOpen {
+                target: self.target.clone(),
+                source: Errno::EIO,
+            });
+        }
+        let result = op.await;
+        self.record(result.is_ok());
+        result
+    }
+}
+
+#[async_trait]
+impl<S> BlockStore for BreakerBlockStore<S>
+where
+    S: BlockStore + Send + Sync,
+{
+    async fn get(&self, key: BlockKey) -> StorageResult<Option<Vec<u8>>> {
+        self.guard(self.inner.get(key)).await
+    }
+
+    async fn put(&self, key: BlockKey, data: Vec<u8>) -> StorageResult<()> {
+        self.guard(self.inner.put(key, data)).await
+    }
+
+    async fn delete(&self, key: BlockKey) -> StorageResult<()> {
+        self.guard(self.inner.delete(key)).await
+    }
+
+    async fn delete_file(&self, ino: INum) -> StorageResult<()> {
+        self.guard(self.inner.delete_file(ino)).await
+    }
+}
@@ -0,0 +1,165 @@
+//! The direct IO of the local block store.
+//!
+//! With the `direct-io` feature, the blocks of a `LocalBlockStore` are read
+//! and written with `O_DIRECT`, so they're not cached again in the page cache
+//! of the host besides the caches of the storage. The buffers of direct IO
+//! must be aligned to the logical blocks of the device, so they're allocated
+//! in pages and pooled by their sizes, as the blocks are mostly of the same
+//! size. A filesystem rejecting `O_DIRECT` falls back to buffered IO.
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use aligned_utils::bytes::AlignedBytes;
+use clippy_utilities::{Cast, OverflowArithmetic};
+use parking_lot::Mutex;
+
+/// The alignment of the buffers and the lengths of direct IO.
+pub(super) const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+/// The max number of the free buffers of each size kept by the pool.
+const MAX_POOLED_BUFFERS: usize = 16;
+
+/// Round `len` up to the alignment of direct IO.
+fn align_up(len: usize) -> usize {
+    len.overflow_add(DIRECT_IO_ALIGNMENT.overflow_sub(1))
+        .overflow_div(DIRECT_IO_ALIGNMENT)
+        .overflow_mul(DIRECT_IO_ALIGNMENT)
+}
+
+/// A pool of page-aligned buffers for direct IO.
+#[derive(Default)]
+pub(super) struct AlignedBufferPool {
+    /// The free buffers by their sizes
+    buffers: Mutex<HashMap<usize, Vec<AlignedBytes>>>,
+}
+
+impl Debug for AlignedBufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let buffers = self.buffers.lock();
+        f.debug_struct("AlignedBufferPool")
+            .field("pooled", &buffers.values().map(Vec::len).sum::<usize>())
+            .finish()
+    }
+}
+
+impl AlignedBufferPool {
+    /// Create an empty pool.
+    #[must_use]
+    pub(super) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take a buffer of at least `len` bytes, whose length is aligned.
+    ///
+    /// The content of a reused buffer is not cleared.
+    #[must_use]
+    pub(super) fn take(&self, len: usize) -> AlignedBytes {
+        let size = align_up(len).max(DIRECT_IO_ALIGNMENT);
+        self.buffers
+            .lock()
+            .get_mut(&size)
+            .and_then(Vec::pop)
+            .unwrap_or_else(|| AlignedBytes::new_zeroed(size, DIRECT_IO_ALIGNMENT))
+    }
+
+    /// Give a buffer back to the pool, it's dropped if there are enough free
+    /// buffers of its size.
+    pub(super) fn give_back(&self, buffer: AlignedBytes) {
+        let mut buffers = self.buffers.lock();
+        let pooled = buffers.entry(buffer.len()).or_default();
+        if pooled.len() < MAX_POOLED_BUFFERS {
+            pooled.push(buffer);
+        }
+    }
+}
+
+/// Whether an error is the rejection of `O_DIRECT` by the filesystem.
+pub(super) fn is_unsupported(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(libc::EINVAL)
+}
+
+/// Read from `file` until `buffer` is full or the end of the file, returns
+/// the number of bytes read.
+fn read_full(file: &mut File, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut offset = 0;
+    while let Some(rest) = buffer.get_mut(offset..).filter(|rest| !rest.is_empty()) {
+        match file.read(rest) {
+            Ok(0) => break,
+            Ok(read) => offset = offset.overflow_add(read),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(offset)
+}
+
+/// Read the whole file at `path` with `O_DIRECT`.
+pub(super) fn read(pool: &AlignedBufferPool, path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(path)?;
+    let len: usize = file.metadata()?.len().cast();
+    let mut buffer = pool.take(len);
+    let res = read_full(&mut file, &mut buffer).map(|read| {
+        buffer
+            .get(..read.min(len))
+            .unwrap_or_else(|| unreachable!("The read bytes are in the buffer."))
+            .to_vec()
+    });
+    pool.give_back(buffer);
+    res
+}
+
+/// Write `data` as the whole content of the file at `path` with `O_DIRECT`.
+///
+/// The data is padded with zeros to the alignment when it's written, and the
+/// padding is truncated afterwards.
+pub(super) fn write(pool: &AlignedBufferPool, path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(path)?;
+    let mut buffer = pool.take(data.len());
+    let aligned = buffer
+        .get_mut(..align_up(data.len()))
+        .unwrap_or_else(|| unreachable!("The buffer is at least of the aligned length."));
+    let (content, padding) = aligned.split_at_mut(data.len());
+    content.copy_from_slice(data);
+    padding.fill(0);
+    let res = file
+        .write_all(aligned)
+        .and_then(|()| file.set_len(data.len().cast()));
+    pool.give_back(buffer);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{align_up, AlignedBufferPool, DIRECT_IO_ALIGNMENT};
+
+    #[test]
+    fn test_aligned_buffer_pool() {
+        assert_eq!(align_up(0), 0);
+        assert_eq!(align_up(1), DIRECT_IO_ALIGNMENT);
+        assert_eq!(align_up(DIRECT_IO_ALIGNMENT), DIRECT_IO_ALIGNMENT);
+
+        let pool = AlignedBufferPool::new();
+        let buffer = pool.take(DIRECT_IO_ALIGNMENT + 1);
+        assert_eq!(buffer.len(), DIRECT_IO_ALIGNMENT * 2);
+        assert_eq!(buffer.as_ptr().align_offset(DIRECT_IO_ALIGNMENT), 0);
+        let ptr = buffer.as_ptr();
+        pool.give_back(buffer);
+
+        // A free buffer of the same size is reused.
+        assert_eq!(pool.take(DIRECT_IO_ALIGNMENT * 2).as_ptr(), ptr);
+        assert_eq!(pool.take(0).len(), DIRECT_IO_ALIGNMENT);
+    }
+}
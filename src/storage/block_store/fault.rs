@@ -0,0 +1,100 @@
+//! The `BlockStore` injecting faults into an inner store, with the
+//! `fault-injection` feature.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use clippy_utilities::OverflowArithmetic;
+
+use super::{BlockKey, BlockStore};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::common::fault::{Fault, FaultSchedule};
+use crate::storage::error::{StorageError, StorageResult};
+
+/// A `BlockStore` which injects the faults drawn from a `FaultSchedule` into
+/// the operations on the inner store.
+///
+/// A partial write persists the first half of the block before it fails, as a
+/// torn write of a backend does, so the layers above must not trust a block
+/// whose write is not acknowledged.
+#[derive(Debug)]
+pub struct FaultyBlockStore<S> {
+    /// The inner store
+    inner: S,
+    /// The schedule of faults
+    schedule: Arc<FaultSchedule>,
+}
+
+impl<S> FaultyBlockStore<S> {
+    /// Wrap `inner` to inject the faults of `schedule`.
+    #[must_use]
+    pub fn new(inner: S, schedule: Arc<FaultSchedule>) -> Self {
+        Self { inner, schedule }
+    }
+
+    /// The inner store.
+    #[must_use]
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Draw the fault of the next operation, sleeps for a delay, or returns
+    /// the fault failing the operation.
+    async fn inject(&self, is_write: bool) -> Option<Fault> {
+        match self.schedule.draw(is_write) {
+            Some(Fault::Latency(latency)) => {
+                tokio::time::sleep(latency).await;
+                None
+            }
+            fault => fault,
+        }
+    }
+}
+
+/// The error of an operation failed by `fault`.
+fn fault_error(fault: Fault) -> StorageError {
+    StorageError::StdIoError(
+        fault
+            .io_error()
+            .unwrap_or_else(|| unreachable!("a delay doesn't fail the operation")),
+    )
+}
+
+#[async_trait]
+impl<S> BlockStore for FaultyBlockStore<S>
+where
+    S: BlockStore + Send + Sync,
+{
+    async fn get(&self, key: BlockKey) -> StorageResult<Option<Vec<u8>>> {
+        if let Some(fault) = self.inject(false).await {
+            return Err(fault_error(fault));
+        }
+        self.inner.get(key).await
+    }
+
+    async fn put(&self, key: BlockKey, mut data: Vec<u8>) -> StorageResult<()> {
+        match self.inject(true).await {
+            None => self.inner.put(key, data).await,
+            Some(Fault::PartialWrite) => {
+                data.truncate(data.len().overflow_div(2));
+                self.inner.put(key, data).await?;
+                Err(fault_error(Fault::PartialWrite))
+            }
+            Some(fault) => Err(fault_error(fault)),
+        }
+    }
+
+    async fn delete(&self, key: BlockKey) -> StorageResult<()> {
+        if let Some(fault) = self.inject(false).await {
+            return Err(fault_error(fault));
+        }
+        self.inner.delete(key).await
+    }
+
+    async fn delete_file(&self, ino: INum) -> StorageResult<()> {
+        if let Some(fault) = self.inject(false).await {
+            return Err(fault_error(fault));
+        }
+        self.inner.delete_file(ino).await
+    }
+}
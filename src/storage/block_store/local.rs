@@ -0,0 +1,207 @@
+//! The `BlockStore` implementation based on a directory of the local disk.
+
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+#[cfg(all(feature = "direct-io", target_os = "linux"))]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(all(feature = "direct-io", target_os = "linux"))]
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio::fs;
+#[cfg(all(feature = "direct-io", target_os = "linux"))]
+use tracing::warn;
+use uuid::Uuid;
+
+#[cfg(all(feature = "direct-io", target_os = "linux"))]
+use super::direct_io::{self, AlignedBufferPool};
+use super::{BlockKey, BlockStore};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::error::StorageResult;
+
+/// A `BlockStore` that stores each block as a file in a local directory.
+///
+/// The blocks of a file are stored in `<root>/<ino>/<block_id>.<version>.block`.
+///
+/// With the `direct-io` feature, they're read and written with `O_DIRECT` if
+/// the filesystem of the directory supports it.
+#[derive(Debug)]
+pub struct LocalBlockStore {
+    /// The root directory
+    root: PathBuf,
+    /// Whether the blocks are read and written with `O_DIRECT`, it's turned
+    /// off once the filesystem rejects it
+    #[cfg(all(feature = "direct-io", target_os = "linux"))]
+    direct_io: AtomicBool,
+    /// The aligned buffers of direct IO
+    #[cfg(all(feature = "direct-io", target_os = "linux"))]
+    buffers: Arc<AlignedBufferPool>,
+}
+
+impl LocalBlockStore {
+    /// Create a `LocalBlockStore` with `root` directory, the directory will be
+    /// created if it does not exist.
+    pub async fn new(root: impl AsRef<Path>) -> StorageResult<Self> {
+        let root = root.as_ref().to_owned();
+        fs::create_dir_all(&root).await?;
+        Ok(Self {
+            root,
+            #[cfg(all(feature = "direct-io", target_os = "linux"))]
+            direct_io: AtomicBool::new(true),
+            #[cfg(all(feature = "direct-io", target_os = "linux"))]
+            buffers: Arc::new(AlignedBufferPool::new()),
+        })
+    }
+
+    /// Get the directory of a file.
+    fn file_dir(&self, ino: INum) -> PathBuf {
+        self.root.join(ino.to_string())
+    }
+
+    /// Get the path of a block.
+    fn block_path(&self, key: BlockKey) -> PathBuf {
+        self.file_dir(key.ino)
+            .join(format!("{}.{}.block", key.block_id, key.version))
+    }
+
+    /// Remove everything in the root directory but the blocks `keep` returns
+    /// `true` for, which are returned.
+    pub async fn retain(&self, keep: impl Fn(BlockKey) -> bool) -> StorageResult<Vec<BlockKey>> {
+        let mut kept = vec![];
+        let mut dirs = fs::read_dir(&self.root).await?;
+        while let Some(dir) = dirs.next_entry().await? {
+            let is_dir = dir.file_type().await?.is_dir();
+            let ino = dir
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse::<INum>().ok());
+            let (true, Some(ino)) = (is_dir, ino) else {
+                remove_entry(&dir.path(), is_dir).await?;
+                continue;
+            };
+
+            let mut files = fs::read_dir(dir.path()).await?;
+            while let Some(file) = files.next_entry().await? {
+                let key = file
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| parse_block_name(ino, name));
+                match key {
+                    Some(key) if keep(key) => kept.push(key),
+                    _ => remove_entry(&file.path(), file.file_type().await?.is_dir()).await?,
+                }
+            }
+        }
+        Ok(kept)
+    }
+
+    /// Fall back to buffered IO if `e` is the rejection of `O_DIRECT`,
+    /// returns whether it falls back.
+    #[cfg(all(feature = "direct-io", target_os = "linux"))]
+    fn fall_back_on(&self, e: &io::Error) -> bool {
+        if !direct_io::is_unsupported(e) {
+            return false;
+        }
+        if self.direct_io.swap(false, Ordering::Relaxed) {
+            warn!(
+                "The filesystem of {} rejects direct IO, fall back to buffered IO.",
+                self.root.display()
+            );
+        }
+        true
+    }
+
+    /// Read the whole content of a file.
+    async fn read_file(&self, path: PathBuf) -> io::Result<Vec<u8>> {
+        #[cfg(all(feature = "direct-io", target_os = "linux"))]
+        if self.direct_io.load(Ordering::Relaxed) {
+            let buffers = Arc::clone(&self.buffers);
+            let direct_path = path.clone();
+            match tokio::task::spawn_blocking(move || direct_io::read(&buffers, &direct_path))
+                .await?
+            {
+                Err(ref e) if self.fall_back_on(e) => {}
+                res => return res,
+            }
+        }
+        fs::read(path).await
+    }
+
+    /// Write `data` as the whole content of a file.
+    async fn write_file(&self, path: PathBuf, data: Vec<u8>) -> io::Result<()> {
+        #[cfg(all(feature = "direct-io", target_os = "linux"))]
+        if self.direct_io.load(Ordering::Relaxed) {
+            let buffers = Arc::clone(&self.buffers);
+            let direct_path = path.clone();
+            let (res, data) = tokio::task::spawn_blocking(move || {
+                (direct_io::write(&buffers, &direct_path, &data), data)
+            })
+            .await?;
+            match res {
+                Err(ref e) if self.fall_back_on(e) => return fs::write(path, data).await,
+                res => return res,
+            }
+        }
+        fs::write(path, data).await
+    }
+}
+
+/// Parse the key of a block of file `ino` from the name of its file, `None` if
+/// it's not a block.
+fn parse_block_name(ino: INum, name: &str) -> Option<BlockKey> {
+    let (block_id, version) = name.strip_suffix(".block")?.split_once('.')?;
+    Some(BlockKey::with_version(
+        ino,
+        block_id.parse().ok()?,
+        version.parse().ok()?,
+    ))
+}
+
+/// Remove a file, or a directory with everything in it.
+async fn remove_entry(path: &Path, is_dir: bool) -> io::Result<()> {
+    if is_dir {
+        fs::remove_dir_all(path).await
+    } else {
+        fs::remove_file(path).await
+    }
+}
+
+#[async_trait]
+impl BlockStore for LocalBlockStore {
+    async fn get(&self, key: BlockKey) -> StorageResult<Option<Vec<u8>>> {
+        match self.read_file(self.block_path(key)).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn put(&self, key: BlockKey, data: Vec<u8>) -> StorageResult<()> {
+        let dir = self.file_dir(key.ino);
+        fs::create_dir_all(&dir).await?;
+
+        // Write to a temporary file then rename it, so that a crash during
+        // writing never leaves a partially written block.
+        let tmp_path = dir.join(format!(".{}.tmp", Uuid::new_v4()));
+        self.write_file(tmp_path.clone(), data).await?;
+        fs::rename(&tmp_path, self.block_path(key)).await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, key: BlockKey) -> StorageResult<()> {
+        match fs::remove_file(self.block_path(key)).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete_file(&self, ino: INum) -> StorageResult<()> {
+        match fs::remove_dir_all(self.file_dir(ino)).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
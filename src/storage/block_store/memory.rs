@@ -0,0 +1,60 @@
+//! The in-memory `BlockStore` implementation.
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use parking_lot::RwLock;
+
+use super::{BlockKey, BlockStore};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::error::StorageResult;
+
+/// A `BlockStore` keeps all blocks in memory, which is useful for testing and
+/// for volumes that don't need persistence.
+#[derive(Debug, Default)]
+pub struct MemoryBlockStore {
+    /// The blocks
+    blocks: RwLock<HashMap<BlockKey, Vec<u8>>>,
+}
+
+impl MemoryBlockStore {
+    /// Create an empty `MemoryBlockStore`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of blocks in the store.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.blocks.read().len()
+    }
+
+    /// Returns if the store is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.blocks.read().is_empty()
+    }
+}
+
+#[async_trait]
+impl BlockStore for MemoryBlockStore {
+    async fn get(&self, key: BlockKey) -> StorageResult<Option<Vec<u8>>> {
+        Ok(self.blocks.read().get(&key).cloned())
+    }
+
+    async fn put(&self, key: BlockKey, data: Vec<u8>) -> StorageResult<()> {
+        self.blocks.write().insert(key, data);
+        Ok(())
+    }
+
+    async fn delete(&self, key: BlockKey) -> StorageResult<()> {
+        self.blocks.write().remove(&key);
+        Ok(())
+    }
+
+    async fn delete_file(&self, ino: INum) -> StorageResult<()> {
+        self.blocks.write().retain(|key, _| key.ino != ino);
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+//! The block store, a key-value abstraction of the persistent layer.
+//!
+//! A `BlockStore` only knows how to get, put and delete a whole block by its
+//! key, the storage layers above it (see [`BlockStoreBackend`]) handle the
+//! partial writes and truncating of files. Therefore, a new kind of persistent
+//! layer only needs to implement this trait.
+
+mod adapter;
+mod breaker;
+#[cfg(all(feature = "direct-io", target_os = "linux"))]
+mod direct_io;
+#[cfg(feature = "fault-injection")]
+mod fault;
+mod local;
+mod memory;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+pub use adapter::BlockStoreBackend;
+pub use breaker::BreakerBlockStore;
+#[cfg(feature = "fault-injection")]
+pub use fault::FaultyBlockStore;
+pub use local::LocalBlockStore;
+pub use memory::MemoryBlockStore;
+
+use super::error::StorageResult;
+use crate::async_fuse::fuse::protocol::INum;
+
+/// The initial version of blocks.
+pub const INITIAL_BLOCK_VERSION: u64 = 0;
+
+/// The key to locate a block in a `BlockStore`.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BlockKey {
+    /// The i-number of the file
+    pub ino: INum,
+    /// The index of the block in the file
+    pub block_id: usize,
+    /// The version of the block
+    pub version: u64,
+}
+
+impl BlockKey {
+    /// Create a key of the block with initial version.
+    #[must_use]
+    pub const fn new(ino: INum, block_id: usize) -> Self {
+        Self {
+            ino,
+            block_id,
+            version: INITIAL_BLOCK_VERSION,
+        }
+    }
+
+    /// Create a key of the block with the `version`.
+    #[must_use]
+    pub const fn with_version(ino: INum, block_id: usize, version: u64) -> Self {
+        Self {
+            ino,
+            block_id,
+            version,
+        }
+    }
+}
+
+/// The `BlockStore` trait, which stores whole blocks by `BlockKey`.
+#[async_trait]
+pub trait BlockStore {
+    /// Get the content of a block.
+    ///
+    /// Returns `None` if the block does not exist.
+    async fn get(&self, key: BlockKey) -> StorageResult<Option<Vec<u8>>>;
+
+    /// Put the content of a block, the previous content will be overwritten.
+    async fn put(&self, key: BlockKey, data: Vec<u8>) -> StorageResult<()>;
+
+    /// Delete a block. It's not an error to delete a non-existing block.
+    async fn delete(&self, key: BlockKey) -> StorageResult<()>;
+
+    /// Delete all blocks of a file, of all versions.
+    async fn delete_file(&self, ino: INum) -> StorageResult<()>;
+}
+
+#[async_trait]
+impl<T> BlockStore for Arc<T>
+where
+    T: BlockStore + Send + Sync,
+{
+    async fn get(&self, key: BlockKey) -> StorageResult<Option<Vec<u8>>> {
+        self.as_ref().get(key).await
+    }
+
+    async fn put(&self, key: BlockKey, data: Vec<u8>) -> StorageResult<()> {
+        self.as_ref().put(key, data).await
+    }
+
+    async fn delete(&self, key: BlockKey) -> StorageResult<()> {
+        self.as_ref().delete(key).await
+    }
+
+    async fn delete_file(&self, ino: INum) -> StorageResult<()> {
+        self.as_ref().delete_file(ino).await
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests;
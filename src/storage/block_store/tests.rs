@@ -0,0 +1,273 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use datenlord::config::BreakerPolicy;
+use nix::errno::Errno;
+
+use super::{
+    BlockKey, BlockStore, BlockStoreBackend, BreakerBlockStore, LocalBlockStore, MemoryBlockStore,
+};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::error::{StorageError, StorageResult};
+use crate::storage::{Block, Storage};
+
+const BLOCK_SIZE_IN_BYTES: usize = 8;
+const BLOCK_CONTENT: &[u8; BLOCK_SIZE_IN_BYTES] = b"foo bar ";
+const LOCAL_STORE_ROOT: &str = "/tmp/datenlord_block_store";
+
+/// Run the basic get/put/delete operations against a `BlockStore`.
+async fn check_basic_operations(store: &impl BlockStore) {
+    let key = BlockKey::new(0, 0);
+    assert!(store.get(key).await.unwrap().is_none());
+
+    store.put(key, BLOCK_CONTENT.to_vec()).await.unwrap();
+    assert_eq!(store.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+
+    // Another version of the same block is a different block.
+    let new_version = BlockKey::with_version(0, 0, 1);
+    assert!(store.get(new_version).await.unwrap().is_none());
+    store.put(new_version, b"bar".to_vec()).await.unwrap();
+    assert_eq!(store.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+
+    store.delete(key).await.unwrap();
+    assert!(store.get(key).await.unwrap().is_none());
+    // Deleting a non-existing block is not an error.
+    store.delete(key).await.unwrap();
+
+    store
+        .put(BlockKey::new(0, 1), b"foo".to_vec())
+        .await
+        .unwrap();
+    store
+        .put(BlockKey::new(1, 0), b"foo".to_vec())
+        .await
+        .unwrap();
+    store.delete_file(0).await.unwrap();
+    assert!(store.get(new_version).await.unwrap().is_none());
+    assert!(store.get(BlockKey::new(0, 1)).await.unwrap().is_none());
+    assert!(store.get(BlockKey::new(1, 0)).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn test_memory_block_store() {
+    let store = MemoryBlockStore::new();
+    check_basic_operations(&store).await;
+    assert_eq!(store.len(), 1);
+}
+
+#[tokio::test]
+async fn test_local_block_store() {
+    let root = format!("{LOCAL_STORE_ROOT}/basic");
+    let store = LocalBlockStore::new(&root).await.unwrap();
+    check_basic_operations(&store).await;
+    tokio::fs::remove_dir_all(root).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_local_block_store_retain() {
+    let root = format!("{LOCAL_STORE_ROOT}/retain");
+    let store = LocalBlockStore::new(&root).await.unwrap();
+    for key in [
+        BlockKey::new(0, 0),
+        BlockKey::new(0, 1),
+        BlockKey::new(1, 0),
+    ] {
+        store.put(key, BLOCK_CONTENT.to_vec()).await.unwrap();
+    }
+    tokio::fs::write(format!("{root}/stale"), b"stale")
+        .await
+        .unwrap();
+    tokio::fs::write(format!("{root}/0/.stale.tmp"), b"stale")
+        .await
+        .unwrap();
+
+    let kept = store.retain(|key| key.block_id == 0).await.unwrap();
+    let mut kept: Vec<_> = kept.into_iter().map(|key| key.ino).collect();
+    kept.sort_unstable();
+    assert_eq!(kept, [0, 1]);
+    assert!(store.get(BlockKey::new(0, 0)).await.unwrap().is_some());
+    assert!(store.get(BlockKey::new(0, 1)).await.unwrap().is_none());
+    assert!(!tokio::fs::try_exists(format!("{root}/stale"))
+        .await
+        .unwrap());
+    assert!(!tokio::fs::try_exists(format!("{root}/0/.stale.tmp"))
+        .await
+        .unwrap());
+    tokio::fs::remove_dir_all(root).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_backend_partial_write() {
+    let backend = BlockStoreBackend::new(MemoryBlockStore::new(), BLOCK_SIZE_IN_BYTES);
+
+    let block = Block::from_slice_with_range(BLOCK_SIZE_IN_BYTES, 4, 7, &BLOCK_CONTENT[4..]);
+    backend.store(0, 0, block).await.unwrap();
+    let loaded = backend.load(0, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), b"\0\0\0\0bar\0");
+
+    let block = Block::from_slice_with_range(BLOCK_SIZE_IN_BYTES, 0, 4, BLOCK_CONTENT);
+    backend.store(0, 0, block).await.unwrap();
+    let loaded = backend.load(0, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), b"foo bar\0");
+}
+
+#[tokio::test]
+async fn test_backend_truncate() {
+    let backend = BlockStoreBackend::new(MemoryBlockStore::new(), BLOCK_SIZE_IN_BYTES);
+
+    for block_id in 0..4 {
+        let block = Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT);
+        backend.store(0, block_id, block).await.unwrap();
+    }
+
+    backend.truncate(0, 4, 2, 4).await.unwrap();
+    assert_eq!(backend.block_store().len(), 2);
+    let loaded = backend.load(0, 1).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), b"foo \0\0\0\0");
+
+    backend.truncate(0, 2, 0, 0).await.unwrap();
+    assert!(backend.block_store().is_empty());
+}
+
+/// A store failing its operations while it's down.
+#[derive(Debug, Default)]
+struct FlakyStore {
+    /// The blocks of the store
+    inner: MemoryBlockStore,
+    /// Whether the store is down
+    down: AtomicBool,
+    /// The number of operations reaching the store
+    calls: AtomicUsize,
+}
+
+impl FlakyStore {
+    /// Count an operation, which fails if the store is down.
+    fn call(&self) -> StorageResult<()> {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        if self.down.load(Ordering::Relaxed) {
+            return Err(StorageError::Internal(anyhow::anyhow!("the store is down")));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BlockStore for FlakyStore {
+    async fn get(&self, key: BlockKey) -> StorageResult<Option<Vec<u8>>> {
+        self.call()?;
+        self.inner.get(key).await
+    }
+
+    async fn put(&self, key: BlockKey, data: Vec<u8>) -> StorageResult<()> {
+        self.call()?;
+        self.inner.put(key, data).await
+    }
+
+    async fn delete(&self, key: BlockKey) -> StorageResult<()> {
+        self.call()?;
+        self.inner.delete(key).await
+    }
+
+    async fn delete_file(&self, ino: INum) -> StorageResult<()> {
+        self.call()?;
+        self.inner.delete_file(ino).await
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_breaker_block_store() {
+    let flaky = Arc::new(FlakyStore::default());
+    let policy = BreakerPolicy {
+        failures: 3,
+        cooldown: Duration::from_secs(10),
+    };
+    let store = BreakerBlockStore::new(Arc::clone(&flaky), "node1", policy);
+    check_basic_operations(&store).await;
+    let key = BlockKey::new(0, 0);
+    store.put(key, BLOCK_CONTENT.to_vec()).await.unwrap();
+
+    // The breaker trips after 3 consecutive failures, and fails fast.
+    flaky.down.store(true, Ordering::Relaxed);
+    let calls = flaky.calls.load(Ordering::Relaxed);
+    for _ in 0..3 {
+        let err = store.get(key).await.unwrap_err();
+        assert!(matches!(err, StorageError::Internal(_)));
+    }
+    let err = store.get(key).await.unwrap_err();
+    assert!(matches!(
+        err,
+        StorageError::CircuitOpen {
+            source: Errno::EIO,
+            ..
+        }
+    ));
+    assert_eq!(flaky.calls.load(Ordering::Relaxed), calls + 3);
+
+    // The probe after the cooldown fails, and the breaker stays open.
+    tokio::time::advance(Duration::from_secs(10)).await;
+    assert!(matches!(
+        store.get(key).await.unwrap_err(),
+        StorageError::Internal(_)
+    ));
+    assert!(matches!(
+        store.get(key).await.unwrap_err(),
+        StorageError::CircuitOpen { .. }
+    ));
+
+    // The store recovers, the breaker is closed by the next probe.
+    flaky.down.store(false, Ordering::Relaxed);
+    assert!(store.get(key).await.is_err());
+    tokio::time::advance(Duration::from_secs(10)).await;
+    assert_eq!(store.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+    for _ in 0..3 {
+        assert_eq!(store.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+    }
+}
+
+#[cfg(feature = "fault-injection")]
+#[tokio::test]
+async fn test_faulty_block_store() {
+    use std::sync::Arc;
+
+    use super::FaultyBlockStore;
+    use crate::common::fault::FaultSchedule;
+
+    let schedule = Arc::new(FaultSchedule::new(0).with_partial_write(1000));
+    let store = FaultyBlockStore::new(MemoryBlockStore::new(), Arc::clone(&schedule));
+    let key = BlockKey::new(0, 0);
+    let error = store.put(key, BLOCK_CONTENT.to_vec()).await.unwrap_err();
+    assert!(error.to_string().contains("Input/output error"));
+    // The torn write leaves the first half of the block.
+    assert_eq!(store.inner().get(key).await.unwrap().unwrap(), b"foo ");
+    // A read is never written partially.
+    assert!(store.get(key).await.is_ok());
+    assert_eq!(schedule.injected(), 1);
+
+    let schedule = Arc::new(FaultSchedule::new(0).with_no_space(1000));
+    let store = FaultyBlockStore::new(MemoryBlockStore::new(), schedule);
+    let error = store.put(key, BLOCK_CONTENT.to_vec()).await.unwrap_err();
+    assert!(error.to_string().contains("No space left on device"));
+    assert!(store.inner().is_empty());
+}
+
+#[cfg(feature = "fault-injection")]
+#[test]
+fn test_fault_schedule_seeded() {
+    use crate::common::fault::FaultSchedule;
+
+    let new_schedule = || {
+        FaultSchedule::new(42)
+            .with_disconnect(100)
+            .with_no_space(100)
+            .with_partial_write(100)
+    };
+    let (first, second) = (new_schedule(), new_schedule());
+    let draws: Vec<_> = (0..100).map(|_| first.draw(true)).collect();
+    let replayed: Vec<_> = (0..100).map(|_| second.draw(true)).collect();
+    assert_eq!(draws, replayed);
+    assert!(draws.iter().any(Option::is_some));
+    assert!(draws.iter().any(Option::is_none));
+    assert_eq!(first.injected(), draws.iter().flatten().count());
+}
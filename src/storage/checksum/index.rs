@@ -0,0 +1,132 @@
+//! The index of block checksums.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+
+use super::BlockChecksum;
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::error::StorageResult;
+
+/// The `ChecksumIndex` trait, which records the checksums of blocks.
+#[async_trait]
+pub trait ChecksumIndex {
+    /// Get the checksum record of a block.
+    ///
+    /// Returns `None` if the block has no checksum recorded.
+    async fn get_checksum(
+        &self,
+        ino: INum,
+        block_id: usize,
+    ) -> StorageResult<Option<BlockChecksum>>;
+
+    /// Set the checksum record of a block, the previous record will be
+    /// overwritten.
+    async fn set_checksum(&self, checksum: BlockChecksum) -> StorageResult<()>;
+
+    /// Remove the checksum records of blocks of a file in `block_ids`.
+    async fn remove_checksums(&self, ino: INum, block_ids: Range<usize>) -> StorageResult<()>;
+
+    /// List all checksum records, for scrubbing.
+    async fn list_checksums(&self) -> StorageResult<Vec<BlockChecksum>>;
+
+    /// Get the time a scrub pass is last requested out of the schedule.
+    ///
+    /// Returns `None` if no pass is requested.
+    async fn scrub_requested(&self) -> StorageResult<Option<SystemTime>> {
+        Ok(None)
+    }
+}
+
+#[async_trait]
+impl<T> ChecksumIndex for Arc<T>
+where
+    T: ChecksumIndex + Send + Sync,
+{
+    async fn get_checksum(
+        &self,
+        ino: INum,
+        block_id: usize,
+    ) -> StorageResult<Option<BlockChecksum>> {
+        self.as_ref().get_checksum(ino, block_id).await
+    }
+
+    async fn set_checksum(&self, checksum: BlockChecksum) -> StorageResult<()> {
+        self.as_ref().set_checksum(checksum).await
+    }
+
+    async fn remove_checksums(&self, ino: INum, block_ids: Range<usize>) -> StorageResult<()> {
+        self.as_ref().remove_checksums(ino, block_ids).await
+    }
+
+    async fn list_checksums(&self) -> StorageResult<Vec<BlockChecksum>> {
+        self.as_ref().list_checksums().await
+    }
+
+    async fn scrub_requested(&self) -> StorageResult<Option<SystemTime>> {
+        self.as_ref().scrub_requested().await
+    }
+}
+
+/// A `ChecksumIndex` in memory, which is not persisted.
+#[derive(Debug, Default)]
+pub struct MemoryChecksumIndex {
+    /// The checksum records
+    checksums: Mutex<BTreeMap<(INum, usize), BlockChecksum>>,
+}
+
+impl MemoryChecksumIndex {
+    /// Create an empty `MemoryChecksumIndex`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Overwrite the checksum of a block without keeping the previous one,
+    /// which simulates a corruption in tests.
+    #[cfg(test)]
+    pub fn corrupt(&self, ino: INum, block_id: usize) {
+        if let Some(record) = self.checksums.lock().get_mut(&(ino, block_id)) {
+            record.checksum = !record.checksum;
+            record.previous = None;
+        }
+    }
+}
+
+#[async_trait]
+impl ChecksumIndex for MemoryChecksumIndex {
+    async fn get_checksum(
+        &self,
+        ino: INum,
+        block_id: usize,
+    ) -> StorageResult<Option<BlockChecksum>> {
+        Ok(self.checksums.lock().get(&(ino, block_id)).copied())
+    }
+
+    async fn set_checksum(&self, checksum: BlockChecksum) -> StorageResult<()> {
+        self.checksums
+            .lock()
+            .insert((checksum.ino, checksum.block_id), checksum);
+        Ok(())
+    }
+
+    async fn remove_checksums(&self, ino: INum, block_ids: Range<usize>) -> StorageResult<()> {
+        let mut checksums = self.checksums.lock();
+        let keys: Vec<_> = checksums
+            .range((ino, block_ids.start)..(ino, block_ids.end))
+            .map(|(&key, _)| key)
+            .collect();
+        for key in keys {
+            checksums.remove(&key);
+        }
+        Ok(())
+    }
+
+    async fn list_checksums(&self) -> StorageResult<Vec<BlockChecksum>> {
+        Ok(self.checksums.lock().values().copied().collect())
+    }
+}
@@ -0,0 +1,63 @@
+//! The end-to-end checksums of blocks.
+//!
+//! The checksum of every block written to the backend is recorded in a
+//! [`ChecksumIndex`], which is usually the metadata store, and blocks read
+//! from the backend are verified against it. Therefore, silent corruptions of
+//! the backend are reported as `EIO` instead of being returned to users. A
+//! scrubber walks the cold blocks periodically, to find corruptions before
+//! they are read. A corrupted block of a replicated volume is repaired from a
+//! healthy replica by the scrubber, see [`ReplicaRepair`], and the blocks it
+//! finds corrupted are kept in [`SCRUB`] for the control socket.
+
+mod index;
+mod scrub;
+mod storage;
+
+pub use index::{ChecksumIndex, MemoryChecksumIndex};
+pub use scrub::{ReplicaRepair, Scrub, ScrubEvent, ScrubOutcome, ScrubStatus, SCRUB};
+use serde::{Deserialize, Serialize};
+pub use storage::{ChecksumStorage, ScrubReport};
+
+use crate::async_fuse::fuse::protocol::INum;
+
+/// The checksum record of a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockChecksum {
+    /// The inode number of the file
+    pub ino: INum,
+    /// The index of the block in the file
+    pub block_id: usize,
+    /// The CRC32C of the block
+    pub checksum: u32,
+    /// The CRC32C of the block before the last write.
+    ///
+    /// The checksum is recorded before the block is written, the block may
+    /// still hold the previous content if the write fails or the node crashes.
+    pub previous: Option<u32>,
+}
+
+impl BlockChecksum {
+    /// Returns whether `checksum` is the one of the block, or the one before
+    /// the last write.
+    #[must_use]
+    pub fn matches(&self, checksum: u32) -> bool {
+        self.checksum == checksum || self.previous == Some(checksum)
+    }
+}
+
+/// Calculate the CRC32C of a block of `block_size`, `data` is padded with
+/// zeros if it's shorter than the block.
+#[must_use]
+pub fn checksum_of(data: &[u8], block_size: usize) -> u32 {
+    let checksum = crc32c::crc32c(data);
+    if data.len() < block_size {
+        let padding = vec![0; block_size.saturating_sub(data.len())];
+        crc32c::crc32c_append(checksum, &padding)
+    } else {
+        checksum
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests;
@@ -0,0 +1,164 @@
+//! The status of the scrubber, and the repair of the blocks it finds
+//! corrupted.
+
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use clippy_utilities::OverflowArithmetic;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use super::ScrubReport;
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::error::StorageResult;
+use crate::storage::BlockKey;
+
+/// The status of the scrubber of this process.
+pub static SCRUB: Lazy<Scrub> = Lazy::new(Scrub::default);
+
+/// The most repairs kept in the status.
+const MAX_SCRUB_EVENTS: usize = 100;
+
+/// The `ReplicaRepair` trait, implemented by the storage keeping several
+/// replicas of every block, which repairs a corrupted block from a healthy
+/// replica.
+#[async_trait]
+pub trait ReplicaRepair {
+    /// Read all the replicas of a block, the first one accepted by `healthy`
+    /// is written to the others.
+    ///
+    /// Returns the number of replicas rewritten, or `None` if no replica is
+    /// healthy.
+    async fn repair_replicas(
+        &self,
+        key: BlockKey,
+        healthy: &(dyn Fn(&[u8]) -> bool + Send + Sync),
+    ) -> StorageResult<Option<usize>>;
+}
+
+/// The outcome of the repair of a corrupted block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScrubOutcome {
+    /// The block is rewritten on the replicas of it corrupted
+    Repaired(usize),
+    /// No replica of the block is healthy
+    Unrepairable,
+    /// The block has no replica to repair from
+    NotReplicated,
+    /// The repair failed for the error
+    Failed(String),
+}
+
+impl ScrubOutcome {
+    /// The label of the outcome in metrics.
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Self::Repaired(_) => "repaired",
+            Self::Unrepairable => "unrepairable",
+            Self::NotReplicated => "not_replicated",
+            Self::Failed(_) => "failed",
+        }
+    }
+}
+
+/// A corrupted block found by the scrubber.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScrubEvent {
+    /// When the block is found
+    pub time: SystemTime,
+    /// The inode number of the file
+    pub ino: INum,
+    /// The index of the block in the file
+    pub block_id: usize,
+    /// The outcome of the repair
+    pub outcome: ScrubOutcome,
+}
+
+/// The status of the scrubber.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScrubStatus {
+    /// Whether a pass is running
+    pub running: bool,
+    /// The passes finished
+    pub passes: u64,
+    /// The passes failed
+    pub failed_passes: u64,
+    /// The error of the last pass failed
+    pub last_error: Option<String>,
+    /// The blocks recorded when the running or the last pass starts
+    pub total: usize,
+    /// The blocks checked by the running or the last pass
+    pub checked: usize,
+    /// The report of the running or the last pass
+    pub report: ScrubReport,
+    /// The corrupted blocks found recently, the latest last
+    pub events: VecDeque<ScrubEvent>,
+}
+
+/// The scrubber of a node, which reports its passes and the blocks it finds
+/// corrupted.
+#[derive(Debug, Default)]
+pub struct Scrub {
+    /// The status of the scrubber
+    status: Mutex<ScrubStatus>,
+}
+
+impl Scrub {
+    /// Get the status of the scrubber.
+    #[must_use]
+    pub fn status(&self) -> ScrubStatus {
+        self.status.lock().clone()
+    }
+
+    /// Start a pass.
+    pub(super) fn start_pass(&self) {
+        let mut status = self.status.lock();
+        status.running = true;
+        status.total = 0;
+        status.checked = 0;
+        status.report = ScrubReport::default();
+    }
+
+    /// Update the progress of the running pass.
+    pub(super) fn progress(&self, total: usize, checked: usize, report: ScrubReport) {
+        let mut status = self.status.lock();
+        status.total = total;
+        status.checked = checked;
+        status.report = report;
+    }
+
+    /// Record a corrupted block, the oldest one is dropped if too many are
+    /// recorded.
+    pub(super) fn record(&self, ino: INum, block_id: usize, outcome: ScrubOutcome) {
+        let mut status = self.status.lock();
+        if status.events.len() >= MAX_SCRUB_EVENTS {
+            status.events.pop_front();
+        }
+        status.events.push_back(ScrubEvent {
+            time: SystemTime::now(),
+            ino,
+            block_id,
+            outcome,
+        });
+    }
+
+    /// Finish the running pass with `result`.
+    pub(super) fn finish_pass(&self, result: &StorageResult<ScrubReport>) {
+        let mut status = self.status.lock();
+        status.running = false;
+        match *result {
+            Ok(report) => {
+                status.passes = status.passes.overflow_add(1);
+                status.checked = status.total;
+                status.report = report;
+            }
+            Err(ref e) => {
+                status.failed_passes = status.failed_passes.overflow_add(1);
+                status.last_error = Some(e.to_string());
+            }
+        }
+    }
+}
@@ -0,0 +1,455 @@
+//! The storage layer verifying checksums of blocks.
+
+use std::collections::HashSet;
+use std::fmt::{self, Debug};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use clippy_utilities::OverflowArithmetic;
+use datenlord::metrics::STORAGE_METRICS;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use super::{checksum_of, BlockChecksum, ChecksumIndex, ReplicaRepair, ScrubOutcome, SCRUB};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::error::{StorageError, StorageResult};
+use crate::storage::{Block, BlockKey, Storage};
+
+/// The interval between the polls of the scrub passes requested.
+const SCRUB_REQUEST_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The result of a scrub pass.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScrubReport {
+    /// The number of blocks verified
+    pub scanned: usize,
+    /// The number of blocks mismatching their checksums
+    pub corrupted: usize,
+    /// The number of corrupted blocks repaired from their replicas
+    pub repaired: usize,
+}
+
+/// A `Storage` which records the checksum of every block stored into the
+/// inner storage, and verifies every block loaded from it.
+///
+/// It's placed right upon the backend (or the dedup storage), so blocks are
+/// verified when they leave the backend, and the caches above only hold
+/// verified blocks. The checksum is recorded before the block is written, and
+/// the previous checksum is kept in the record, therefore, a failed or
+/// interrupted write never makes a block mismatch.
+pub struct ChecksumStorage<I, S> {
+    /// The index of checksums
+    index: I,
+    /// The inner storage
+    inner: S,
+    /// The size of blocks
+    block_size: usize,
+    /// The blocks accessed since the last scrub pass, they are hot and skipped
+    /// by the next one.
+    accessed: Mutex<HashSet<(INum, usize)>>,
+    /// The replicas the corrupted blocks are repaired from, `None` if the
+    /// blocks are not replicated
+    repair: Option<Arc<dyn ReplicaRepair + Send + Sync>>,
+}
+
+impl<I: Debug, S: Debug> Debug for ChecksumStorage<I, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChecksumStorage")
+            .field("index", &self.index)
+            .field("inner", &self.inner)
+            .field("block_size", &self.block_size)
+            .field("repair", &self.repair.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I, S> ChecksumStorage<I, S>
+where
+    I: ChecksumIndex + Send + Sync,
+    S: Storage + Send + Sync,
+{
+    /// Create a `ChecksumStorage` upon `inner`.
+    pub fn new(index: I, inner: S, block_size: usize) -> Self {
+        Self {
+            index,
+            inner,
+            block_size,
+            accessed: Mutex::new(HashSet::new()),
+            repair: None,
+        }
+    }
+
+    /// Repair the corrupted blocks found by the scrubber from the healthy
+    /// replicas of `repair`.
+    #[must_use]
+    pub fn with_repair(mut self, repair: Arc<dyn ReplicaRepair + Send + Sync>) -> Self {
+        self.repair = Some(repair);
+        self
+    }
+
+    /// Load a block from the inner storage, and verify it with the record.
+    async fn load_verified(&self, ino: INum, block_id: usize) -> StorageResult<Option<Block>> {
+        let Some(block) = self.inner.load(ino, block_id).await? else {
+            return Ok(None);
+        };
+
+        if let Some(record) = self.index.get_checksum(ino, block_id).await? {
+            if !record.matches(checksum_of(block.as_slice(), self.block_size)) {
+                STORAGE_METRICS.checksum_mismatch_count_inc("read");
+                error!("Checksum of block {block_id} of file {ino} mismatches, it's corrupted.");
+                return Err(StorageError::ChecksumMismatch {
+                    ino,
+                    block_id,
+                    source: nix::errno::Errno::EIO,
+                });
+            }
+        }
+
+        Ok(Some(block))
+    }
+
+    /// Record the checksum of the new content of a block, the current one is
+    /// kept as the previous one.
+    async fn record_checksum(&self, ino: INum, block_id: usize, data: &[u8]) -> StorageResult<()> {
+        let previous = self
+            .index
+            .get_checksum(ino, block_id)
+            .await?
+            .map(|record| record.checksum);
+        self.index
+            .set_checksum(BlockChecksum {
+                ino,
+                block_id,
+                checksum: checksum_of(data, self.block_size),
+                previous,
+            })
+            .await
+    }
+
+    /// Mark a block as accessed.
+    fn touch(&self, ino: INum, block_id: usize) {
+        self.accessed.lock().insert((ino, block_id));
+    }
+
+    /// Repair the corrupted block of `record` from a healthy replica, which
+    /// matches the record.
+    async fn repair(&self, record: BlockChecksum) -> ScrubOutcome {
+        let Some(ref repair) = self.repair else {
+            return ScrubOutcome::NotReplicated;
+        };
+        let block_size = self.block_size;
+        let healthy = move |data: &[u8]| record.matches(checksum_of(data, block_size));
+        let key = BlockKey::new(record.ino, record.block_id);
+        match repair.repair_replicas(key, &healthy).await {
+            Ok(Some(rewritten)) => ScrubOutcome::Repaired(rewritten),
+            Ok(None) => ScrubOutcome::Unrepairable,
+            Err(e) => ScrubOutcome::Failed(e.to_string()),
+        }
+    }
+
+    /// Verify all the cold blocks, i.e. the blocks not accessed since the last
+    /// pass. Corrupted blocks are logged, counted in metrics, and repaired
+    /// from their replicas if they're replicated. The progress of the pass
+    /// and the corrupted blocks are reported to `SCRUB`.
+    pub async fn scrub(&self) -> StorageResult<ScrubReport> {
+        SCRUB.start_pass();
+        let result = self.scrub_pass().await;
+        SCRUB.finish_pass(&result);
+        STORAGE_METRICS.scrub_pending_blocks_set(0);
+        result
+    }
+
+    /// Run a scrub pass.
+    async fn scrub_pass(&self) -> StorageResult<ScrubReport> {
+        let hot = std::mem::take(&mut *self.accessed.lock());
+        let mut report = ScrubReport::default();
+
+        let records = self.index.list_checksums().await?;
+        let total = records.len();
+        for (checked, record) in records.into_iter().enumerate() {
+            SCRUB.progress(total, checked, report);
+            STORAGE_METRICS.scrub_pending_blocks_set(total.overflow_sub(checked));
+
+            let (ino, block_id) = (record.ino, record.block_id);
+            if hot.contains(&(ino, block_id)) {
+                continue;
+            }
+
+            // The block is not written yet, or is removed.
+            let Some(block) = self.inner.load(ino, block_id).await? else {
+                continue;
+            };
+            report.scanned = report.scanned.overflow_add(1);
+            STORAGE_METRICS.scrub_scanned_blocks_inc();
+
+            if record.matches(checksum_of(block.as_slice(), self.block_size)) {
+                continue;
+            }
+
+            // The block may be written during the pass, check with the latest
+            // record.
+            if self.accessed.lock().contains(&(ino, block_id)) {
+                continue;
+            }
+            if self.index.get_checksum(ino, block_id).await? != Some(record) {
+                continue;
+            }
+
+            STORAGE_METRICS.checksum_mismatch_count_inc("scrub");
+            error!("Scrubber found block {block_id} of file {ino} corrupted.");
+            report.corrupted = report.corrupted.overflow_add(1);
+
+            let outcome = self.repair(record).await;
+            STORAGE_METRICS.scrub_repair_count_inc(outcome.as_str());
+            match outcome {
+                ScrubOutcome::Repaired(rewritten) => {
+                    info!(
+                        "Block {block_id} of file {ino} is repaired, {rewritten} replicas rewritten."
+                    );
+                    report.repaired = report.repaired.overflow_add(1);
+                }
+                ScrubOutcome::Unrepairable => {
+                    error!("Block {block_id} of file {ino} has no healthy replica to repair from.");
+                }
+                ScrubOutcome::NotReplicated => {}
+                ScrubOutcome::Failed(ref e) => {
+                    warn!("Failed to repair block {block_id} of file {ino}: {e}");
+                }
+            }
+            SCRUB.record(ino, block_id, outcome);
+        }
+
+        Ok(report)
+    }
+
+    /// Run a scrub pass and log its result.
+    async fn run_pass(&self) {
+        match self.scrub().await {
+            Ok(report) => info!(
+                "Scrub pass finished, {} blocks scanned, {} corrupted, {} repaired.",
+                report.scanned, report.corrupted, report.repaired
+            ),
+            Err(e) => {
+                STORAGE_METRICS.scrub_failed_passes_inc();
+                error!("Scrub pass failed: {e}");
+            }
+        }
+    }
+
+    /// Run scrub passes every `interval`, and the ones requested in the index
+    /// out of the schedule, until `token` is cancelled.
+    #[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+    pub async fn run_scrubber(self: Arc<Self>, interval: Duration, token: CancellationToken) {
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick completes immediately, skip it to scrub after an
+        // interval.
+        ticker.tick().await;
+        let mut poller = tokio::time::interval(SCRUB_REQUEST_POLL_INTERVAL);
+        // The requests made before the scrubber starts are not served.
+        let mut last_pass = SystemTime::now();
+
+        loop {
+            select! {
+                _ = ticker.tick() => {
+                    last_pass = SystemTime::now();
+                    self.run_pass().await;
+                }
+                _ = poller.tick() => {
+                    match self.index.scrub_requested().await {
+                        Ok(Some(requested)) if requested > last_pass => {
+                            info!("Scrub pass is requested.");
+                            last_pass = SystemTime::now();
+                            self.run_pass().await;
+                        }
+                        Ok(_) => {}
+                        Err(e) => warn!("Failed to poll the scrub requests: {e}"),
+                    }
+                }
+                () = token.cancelled() => {
+                    info!("Scrubber exits.");
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<I, S> Storage for ChecksumStorage<I, S>
+where
+    I: ChecksumIndex + Send + Sync,
+    S: Storage + Send + Sync,
+{
+    async fn load_from_self(&self, ino: INum, block_id: usize) -> StorageResult<Option<Block>> {
+        self.touch(ino, block_id);
+        self.load_verified(ino, block_id).await
+    }
+
+    async fn load_from_backend(&self, _: INum, _: usize) -> StorageResult<Option<Block>> {
+        // The inner storage is loaded in `load_from_self`.
+        Ok(None)
+    }
+
+    async fn cache_block_from_backend(&self, _: INum, _: usize, _: Block) -> StorageResult<()> {
+        unreachable!("This storage has no backend, and has no cache.");
+    }
+
+    async fn store(&self, ino: INum, block_id: usize, block: Block) -> StorageResult<()> {
+        self.touch(ino, block_id);
+
+        let block_start = block.start();
+        let block_end = block.end();
+
+        let block = if block_start == 0 && block_end == self.block_size {
+            block
+        } else {
+            // The checksum covers the whole block, so merge it here.
+            let mut dest = self
+                .load_verified(ino, block_id)
+                .await?
+                .unwrap_or_else(|| Block::new_zeroed(self.block_size));
+            dest.update(&block)?;
+            dest
+        };
+
+        self.record_checksum(ino, block_id, block.as_slice())
+            .await?;
+        self.inner.store(ino, block_id, block).await
+    }
+
+    async fn remove(&self, ino: INum) -> StorageResult<()> {
+        self.inner.remove(ino).await?;
+        self.index.remove_checksums(ino, 0..usize::MAX).await
+    }
+
+    async fn invalidate(&self, ino: INum) -> StorageResult<()> {
+        self.inner.invalidate(ino).await
+    }
+
+    async fn flush(&self, ino: INum) -> StorageResult<()> {
+        self.inner.flush(ino).await
+    }
+
+    async fn flush_all(&self) -> StorageResult<()> {
+        self.inner.flush_all().await
+    }
+
+    async fn truncate(
+        &self,
+        ino: INum,
+        from_block: usize,
+        to_block: usize,
+        fill_start: usize,
+    ) -> StorageResult<()> {
+        if to_block == 0 {
+            return self.remove(ino).await;
+        }
+
+        // Record the checksum of the truncated last block
+        if fill_start < self.block_size {
+            let block_id = to_block.overflow_sub(1);
+            self.touch(ino, block_id);
+            if let Some(mut block) = self.load_verified(ino, block_id).await? {
+                if let Some(tail) = block.make_mut_slice().get_mut(fill_start..) {
+                    tail.fill(0);
+                }
+                self.record_checksum(ino, block_id, block.as_slice())
+                    .await?;
+            }
+        }
+
+        self.inner
+            .truncate(ino, from_block, to_block, fill_start)
+            .await?;
+
+        if to_block < from_block {
+            self.index
+                .remove_checksums(ino, to_block..from_block)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn clone_blocks(
+        &self,
+        src_ino: INum,
+        src_block: usize,
+        dst_ino: INum,
+        dst_block: usize,
+        count: usize,
+    ) -> StorageResult<bool> {
+        // The clones have the same checksums as the sources. Like storing,
+        // they are recorded before the blocks are cloned, and the current
+        // ones are kept as the previous ones.
+        let mut recorded = vec![];
+        for offset in 0..count {
+            let block_id = dst_block.overflow_add(offset);
+            let Some(source) = self
+                .index
+                .get_checksum(src_ino, src_block.overflow_add(offset))
+                .await?
+            else {
+                continue;
+            };
+            let current = self.index.get_checksum(dst_ino, block_id).await?;
+            self.index
+                .set_checksum(BlockChecksum {
+                    ino: dst_ino,
+                    block_id,
+                    checksum: source.checksum,
+                    previous: current.map(|record| record.checksum),
+                })
+                .await?;
+            recorded.push((block_id, current));
+        }
+
+        if !self
+            .inner
+            .clone_blocks(src_ino, src_block, dst_ino, dst_block, count)
+            .await?
+        {
+            // Nothing is cloned, restore the records.
+            for (block_id, current) in recorded {
+                match current {
+                    Some(record) => self.index.set_checksum(record).await?,
+                    None => {
+                        self.index
+                            .remove_checksums(dst_ino, block_id..block_id.overflow_add(1))
+                            .await?;
+                    }
+                }
+            }
+            return Ok(false);
+        }
+
+        // The clones of holes have no checksum.
+        let mut recorded = recorded
+            .into_iter()
+            .map(|(block_id, _)| block_id)
+            .peekable();
+        for block_id in dst_block..dst_block.overflow_add(count) {
+            self.touch(dst_ino, block_id);
+            if recorded.next_if_eq(&block_id).is_none() {
+                self.index
+                    .remove_checksums(dst_ino, block_id..block_id.overflow_add(1))
+                    .await?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    async fn punch_blocks(&self, ino: INum, blocks: Range<usize>) -> StorageResult<bool> {
+        if !self.inner.punch_blocks(ino, blocks.clone()).await? {
+            return Ok(false);
+        }
+        self.index.remove_checksums(ino, blocks).await?;
+        Ok(true)
+    }
+}
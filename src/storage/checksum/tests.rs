@@ -0,0 +1,214 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use super::{
+    ChecksumIndex, ChecksumStorage, MemoryChecksumIndex, ReplicaRepair, ScrubOutcome, SCRUB,
+};
+use crate::storage::error::{StorageError, StorageResult};
+use crate::storage::{Block, BlockKey, MemoryStorage, Storage};
+
+const BLOCK_SIZE_IN_BYTES: usize = 8;
+const BLOCK_CONTENT: &[u8; BLOCK_SIZE_IN_BYTES] = b"foo bar ";
+
+type ChecksumStorageType = ChecksumStorage<Arc<MemoryChecksumIndex>, Arc<MemoryStorage>>;
+
+fn prepare_storage() -> (
+    Arc<MemoryChecksumIndex>,
+    Arc<MemoryStorage>,
+    ChecksumStorageType,
+) {
+    let index = Arc::new(MemoryChecksumIndex::new());
+    let inner = Arc::new(MemoryStorage::new(
+        BLOCK_SIZE_IN_BYTES,
+        Duration::from_millis(0),
+    ));
+    let storage = ChecksumStorage::new(Arc::clone(&index), Arc::clone(&inner), BLOCK_SIZE_IN_BYTES);
+    (index, inner, storage)
+}
+
+#[tokio::test]
+async fn test_verify_on_load() {
+    let (index, inner, storage) = prepare_storage();
+
+    storage
+        .store(0, 0, Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT))
+        .await
+        .unwrap();
+    let loaded = storage.load(0, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), BLOCK_CONTENT);
+
+    // Corrupt the block behind the checksum storage.
+    inner
+        .store(0, 0, Block::from_slice(BLOCK_SIZE_IN_BYTES, b"foo baz "))
+        .await
+        .unwrap();
+    let err = storage.load(0, 0).await.unwrap_err();
+    assert!(matches!(
+        err,
+        StorageError::ChecksumMismatch {
+            ino: 0,
+            block_id: 0,
+            source: nix::errno::Errno::EIO,
+        }
+    ));
+
+    // A block without checksum is not verified.
+    inner
+        .store(0, 1, Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT))
+        .await
+        .unwrap();
+    assert!(index.get_checksum(0, 1).await.unwrap().is_none());
+    assert!(storage.load(0, 1).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn test_interrupted_write() {
+    let (index, inner, storage) = prepare_storage();
+
+    storage
+        .store(0, 0, Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT))
+        .await
+        .unwrap();
+    storage
+        .store(0, 0, Block::from_slice(BLOCK_SIZE_IN_BYTES, b"foo baz "))
+        .await
+        .unwrap();
+    let record = index.get_checksum(0, 0).await.unwrap().unwrap();
+    assert!(record.previous.is_some());
+
+    // The block still holds the previous content, as if the last write was
+    // interrupted.
+    inner
+        .store(0, 0, Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT))
+        .await
+        .unwrap();
+    let loaded = storage.load(0, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), BLOCK_CONTENT);
+}
+
+#[tokio::test]
+async fn test_partial_write_and_truncate() {
+    let (_, _, storage) = prepare_storage();
+
+    storage
+        .store(0, 0, Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT))
+        .await
+        .unwrap();
+    storage
+        .store(0, 1, Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT))
+        .await
+        .unwrap();
+    storage
+        .store(
+            0,
+            0,
+            Block::from_slice_with_range(BLOCK_SIZE_IN_BYTES, 4, 7, b"baz"),
+        )
+        .await
+        .unwrap();
+    let loaded = storage.load(0, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), b"foo baz ");
+
+    storage.truncate(0, 2, 1, 4).await.unwrap();
+    let loaded = storage.load(0, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), b"foo \0\0\0\0");
+    assert!(storage.load(0, 1).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_scrub() {
+    let (index, _, storage) = prepare_storage();
+
+    for block_id in 0..3 {
+        storage
+            .store(
+                0,
+                block_id,
+                Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT),
+            )
+            .await
+            .unwrap();
+    }
+
+    // All blocks are hot in the first pass.
+    let report = storage.scrub().await.unwrap();
+    assert_eq!(report.scanned, 0);
+
+    index.corrupt(0, 1);
+    let report = storage.scrub().await.unwrap();
+    assert_eq!(report.scanned, 3);
+    assert_eq!(report.corrupted, 1);
+
+    // Removed blocks are no longer scrubbed.
+    storage.remove(0).await.unwrap();
+    let report = storage.scrub().await.unwrap();
+    assert_eq!(report.scanned, 0);
+    assert!(index.list_checksums().await.unwrap().is_empty());
+}
+
+/// The replicas of the blocks, which are repaired into a `MemoryStorage`.
+#[derive(Debug)]
+struct TestReplicas {
+    /// The storage repaired
+    inner: Arc<MemoryStorage>,
+    /// The content of the replicas
+    replicas: Vec<&'static [u8]>,
+}
+
+#[async_trait]
+impl ReplicaRepair for TestReplicas {
+    async fn repair_replicas(
+        &self,
+        key: BlockKey,
+        healthy: &(dyn Fn(&[u8]) -> bool + Send + Sync),
+    ) -> StorageResult<Option<usize>> {
+        let Some(data) = self.replicas.iter().find(|&&data| healthy(data)) else {
+            return Ok(None);
+        };
+        self.inner
+            .store(
+                key.ino,
+                key.block_id,
+                Block::from_slice(BLOCK_SIZE_IN_BYTES, data),
+            )
+            .await?;
+        Ok(Some(1))
+    }
+}
+
+#[tokio::test]
+async fn test_scrub_repair() {
+    let (_, inner, storage) = prepare_storage();
+    let storage = storage.with_repair(Arc::new(TestReplicas {
+        inner: Arc::clone(&inner),
+        replicas: vec![b"foo baz ", BLOCK_CONTENT],
+    }));
+    let ino = 42;
+    storage
+        .store(
+            ino,
+            0,
+            Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT),
+        )
+        .await
+        .unwrap();
+    storage.scrub().await.unwrap();
+
+    // Corrupt the block behind the checksum storage.
+    inner
+        .store(ino, 0, Block::from_slice(BLOCK_SIZE_IN_BYTES, b"bar foo "))
+        .await
+        .unwrap();
+    let report = storage.scrub().await.unwrap();
+    assert_eq!(report.corrupted, 1);
+    assert_eq!(report.repaired, 1);
+    let loaded = storage.load(ino, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), BLOCK_CONTENT);
+    assert!(SCRUB
+        .status()
+        .events
+        .iter()
+        .any(|event| event.ino == ino && event.outcome == ScrubOutcome::Repaired(1)));
+}
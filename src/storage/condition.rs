@@ -0,0 +1,121 @@
+//! The condition of the storage of the volume.
+//!
+//! The components of the storage report their problems here, such as a
+//! backend which can't be reached, or too few replica nodes alive for the
+//! write quorum. The CSI node service reports them to kubelet as the condition
+//! of the volumes published on the node, which share the storage.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use super::Backend;
+
+/// The interval between the probes of the backend.
+pub const BACKEND_PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The component name of the backend.
+const BACKEND_COMPONENT: &str = "backend";
+
+/// The condition of the storage of this node.
+pub static STORAGE_CONDITION: Lazy<StorageCondition> = Lazy::new(StorageCondition::default);
+
+/// The problems of the components of the storage.
+#[derive(Debug, Default)]
+pub struct StorageCondition {
+    /// Component name -> the problem of the component, the healthy ones are
+    /// absent
+    problems: Mutex<BTreeMap<&'static str, String>>,
+}
+
+impl StorageCondition {
+    /// Report the problem of `component`, or that it's healthy if `problem`
+    /// is `None`. Only the changes are logged.
+    pub fn report(&self, component: &'static str, problem: Option<String>) {
+        let mut problems = self.problems.lock();
+        match problem {
+            Some(problem) => {
+                if problems.get(component) != Some(&problem) {
+                    warn!("The {component} is unhealthy: {problem}");
+                    problems.insert(component, problem);
+                }
+            }
+            None => {
+                if problems.remove(component).is_some() {
+                    info!("The {component} is healthy again.");
+                }
+            }
+        }
+    }
+
+    /// Get the problems of the unhealthy components, `None` if all the
+    /// components are healthy.
+    pub fn abnormal(&self) -> Option<String> {
+        let problems = self.problems.lock();
+        if problems.is_empty() {
+            return None;
+        }
+        Some(
+            problems
+                .iter()
+                .map(|(component, problem)| format!("{component}: {problem}"))
+                .collect::<Vec<_>>()
+                .join("; "),
+        )
+    }
+}
+
+/// Probe `backend` every `interval` until `token` is cancelled, the backend
+/// is reported unhealthy while it can't be reached.
+#[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+pub async fn run_backend_probe(backend: Backend, interval: Duration, token: CancellationToken) {
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        select! {
+            _ = ticker.tick() => {
+                let problem = backend
+                    .check()
+                    .await
+                    .err()
+                    .map(|e| format!("failed to reach the backend: {e}"));
+                STORAGE_CONDITION.report(BACKEND_COMPONENT, problem);
+            }
+            () = token.cancelled() => {
+                info!("Backend probe exits.");
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StorageCondition;
+
+    #[test]
+    fn test_report() {
+        let condition = StorageCondition::default();
+        assert_eq!(condition.abnormal(), None);
+
+        condition.report("replication", Some("1 of 3 nodes alive".to_owned()));
+        condition.report("backend", Some("timeout".to_owned()));
+        assert_eq!(
+            condition.abnormal().as_deref(),
+            Some("backend: timeout; replication: 1 of 3 nodes alive")
+        );
+
+        condition.report("backend", None);
+        assert_eq!(
+            condition.abnormal().as_deref(),
+            Some("replication: 1 of 3 nodes alive")
+        );
+        condition.report("replication", None);
+        assert_eq!(condition.abnormal(), None);
+    }
+}
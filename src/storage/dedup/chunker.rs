@@ -0,0 +1,177 @@
+//! The content-defined chunker, based on FastCDC.
+//!
+//! The boundaries of chunks are decided by the content instead of the offset,
+//! so that an insertion only changes the chunks around it, and the same data
+//! is split into the same chunks wherever it locates.
+
+use std::ops::Range;
+
+use clippy_utilities::OverflowArithmetic;
+
+/// Generate the gear table with `SplitMix64`, so that the table is the same
+/// across builds and nodes.
+#[allow(clippy::indexing_slicing)] // `get_mut` is not usable in const fn, `i` is less than 256.
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// The gear table to roll the hash.
+const GEAR: [u64; 256] = gear_table();
+
+/// A content-defined chunker.
+///
+/// With normalized chunking, a stricter mask is used before the average size
+/// and a looser one after it, so that the sizes of chunks are concentrated
+/// around the average size.
+#[derive(Debug, Clone, Copy)]
+pub struct Chunker {
+    /// The minimum size of chunks
+    min_size: usize,
+    /// The average size of chunks
+    avg_size: usize,
+    /// The maximum size of chunks
+    max_size: usize,
+    /// The mask used before the average size
+    mask_small: u64,
+    /// The mask used after the average size
+    mask_large: u64,
+}
+
+impl Chunker {
+    /// Create a chunker with the average size of chunks, the minimum size is a
+    /// quarter of it, and the maximum size is four times of it.
+    ///
+    /// # Panics
+    /// Panics if `avg_size` is not a power of two, or is less than 64.
+    #[must_use]
+    pub fn new(avg_size: usize) -> Self {
+        assert!(
+            avg_size.is_power_of_two() && avg_size >= 64,
+            "The average chunk size {avg_size} must be a power of two and at least 64."
+        );
+
+        let bits = avg_size.trailing_zeros();
+        Self {
+            min_size: avg_size.overflow_div(4),
+            avg_size,
+            max_size: avg_size.overflow_mul(4),
+            mask_small: !0_u64 << 64_u32.overflow_sub(bits.overflow_add(1)),
+            mask_large: !0_u64 << 64_u32.overflow_sub(bits.overflow_sub(1)),
+        }
+    }
+
+    /// Returns the length of the first chunk in `data`.
+    fn cut(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min_size {
+            return data.len();
+        }
+
+        let end = data.len().min(self.max_size);
+        let normal = self.avg_size.min(end);
+        let mut hash = 0_u64;
+
+        for (offset, &byte) in data.iter().enumerate().take(end).skip(self.min_size) {
+            #[allow(clippy::indexing_slicing)] // The table covers all values of `u8`.
+            let gear = GEAR[usize::from(byte)];
+            hash = (hash << 1_u32).wrapping_add(gear);
+            let mask = if offset < normal {
+                self.mask_small
+            } else {
+                self.mask_large
+            };
+            if hash & mask == 0 {
+                return offset.overflow_add(1);
+            }
+        }
+
+        end
+    }
+
+    /// Split `data` into chunks, returns the ranges of chunks.
+    #[must_use]
+    pub fn split(&self, data: &[u8]) -> Vec<Range<usize>> {
+        let mut chunks = vec![];
+        let mut start = 0;
+        while let Some(rest) = data.get(start..).filter(|rest| !rest.is_empty()) {
+            let end = start.overflow_add(self.cut(rest));
+            chunks.push(start..end);
+            start = end;
+        }
+        chunks
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::indexing_slicing)]
+mod tests {
+    use clippy_utilities::OverflowArithmetic;
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+
+    use super::Chunker;
+
+    const AVG_SIZE: usize = 1024;
+
+    fn random_data(len: usize, seed: u64) -> Vec<u8> {
+        let mut data = vec![0; len];
+        StdRng::seed_from_u64(seed).fill_bytes(&mut data);
+        data
+    }
+
+    #[test]
+    fn test_chunk_sizes() {
+        let chunker = Chunker::new(AVG_SIZE);
+        let data = random_data(AVG_SIZE * 64, 0);
+        let chunks = chunker.split(&data);
+
+        assert_eq!(chunks.first().map(|chunk| chunk.start), Some(0));
+        assert_eq!(chunks.last().map(|chunk| chunk.end), Some(data.len()));
+        for pair in chunks.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.len() >= AVG_SIZE.overflow_div(4));
+            assert!(chunk.len() <= AVG_SIZE * 4);
+        }
+    }
+
+    #[test]
+    fn test_shifted_content() {
+        let chunker = Chunker::new(AVG_SIZE);
+        let data = random_data(AVG_SIZE * 64, 1);
+        let mut shifted = random_data(100, 2);
+        shifted.extend_from_slice(&data);
+
+        let chunks = chunker.split(&data);
+        let shifted_chunks = chunker.split(&shifted);
+
+        // Most chunks are the same after an insertion at the beginning.
+        let same = chunks
+            .iter()
+            .filter(|chunk| {
+                shifted_chunks
+                    .iter()
+                    .any(|shifted_chunk| shifted[shifted_chunk.clone()] == data[(*chunk).clone()])
+            })
+            .count();
+        assert!(same * 10 >= chunks.len() * 8);
+    }
+
+    #[test]
+    fn test_small_data() {
+        let chunker = Chunker::new(AVG_SIZE);
+        assert!(chunker.split(&[]).is_empty());
+        assert_eq!(chunker.split(&[0; 10]), vec![0..10]);
+    }
+}
@@ -0,0 +1,197 @@
+//! The index of deduplicated blocks.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use clippy_utilities::OverflowArithmetic;
+use parking_lot::Mutex;
+
+use super::{BlockRecipe, ChunkHash};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::error::StorageResult;
+
+/// The `DedupIndex` trait, which records the recipes of blocks and the
+/// refcounts of chunks.
+///
+/// The refcount of a chunk is the number of its occurrences in all recipes,
+/// and the updates of recipes and refcounts must be atomic.
+#[async_trait]
+pub trait DedupIndex {
+    /// Get the recipe of a block.
+    ///
+    /// Returns `None` if the block does not exist.
+    async fn get_recipe(&self, ino: INum, block_id: usize) -> StorageResult<Option<BlockRecipe>>;
+
+    /// Returns whether a chunk is referenced by any recipe.
+    async fn contains_chunk(&self, hash: ChunkHash) -> StorageResult<bool>;
+
+    /// Set the recipe of a block, the previous recipe will be overwritten.
+    ///
+    /// Returns the chunks no longer referenced by any recipe.
+    async fn set_recipe(&self, ino: INum, recipe: BlockRecipe) -> StorageResult<Vec<ChunkHash>>;
+
+    /// Remove the recipes of blocks of a file in `block_ids`.
+    ///
+    /// Returns the chunks no longer referenced by any recipe.
+    async fn remove_recipes(
+        &self,
+        ino: INum,
+        block_ids: Range<usize>,
+    ) -> StorageResult<Vec<ChunkHash>>;
+}
+
+#[async_trait]
+impl<T> DedupIndex for Arc<T>
+where
+    T: DedupIndex + Send + Sync,
+{
+    async fn get_recipe(&self, ino: INum, block_id: usize) -> StorageResult<Option<BlockRecipe>> {
+        self.as_ref().get_recipe(ino, block_id).await
+    }
+
+    async fn contains_chunk(&self, hash: ChunkHash) -> StorageResult<bool> {
+        self.as_ref().contains_chunk(hash).await
+    }
+
+    async fn set_recipe(&self, ino: INum, recipe: BlockRecipe) -> StorageResult<Vec<ChunkHash>> {
+        self.as_ref().set_recipe(ino, recipe).await
+    }
+
+    async fn remove_recipes(
+        &self,
+        ino: INum,
+        block_ids: Range<usize>,
+    ) -> StorageResult<Vec<ChunkHash>> {
+        self.as_ref().remove_recipes(ino, block_ids).await
+    }
+}
+
+/// The changes of refcounts of chunks, caused by adding and removing recipes.
+#[derive(Debug, Default)]
+pub struct RefDeltas(HashMap<ChunkHash, i64>);
+
+impl RefDeltas {
+    /// Record the chunks referenced by a new recipe.
+    pub fn add(&mut self, recipe: &BlockRecipe) {
+        for chunk in &recipe.chunks {
+            self.add_chunk(chunk.hash);
+        }
+    }
+
+    /// Record the chunks referenced by a removed recipe.
+    pub fn sub(&mut self, recipe: &BlockRecipe) {
+        for chunk in &recipe.chunks {
+            self.sub_chunk(chunk.hash);
+        }
+    }
+
+    /// Record a new reference to a chunk.
+    pub fn add_chunk(&mut self, hash: ChunkHash) {
+        let delta = self.0.entry(hash).or_default();
+        *delta = delta.overflow_add(1);
+    }
+
+    /// Record a removed reference to a chunk.
+    pub fn sub_chunk(&mut self, hash: ChunkHash) {
+        let delta = self.0.entry(hash).or_default();
+        *delta = delta.overflow_sub(1);
+    }
+
+    /// Iterate the changed refcounts.
+    pub fn iter(&self) -> impl Iterator<Item = (ChunkHash, i64)> + '_ {
+        self.0
+            .iter()
+            .filter(|&(_, &delta)| delta != 0)
+            .map(|(&hash, &delta)| (hash, delta))
+    }
+}
+
+/// The inner state of `MemoryDedupIndex`.
+#[derive(Debug, Default)]
+struct MemoryDedupIndexInner {
+    /// The recipes of blocks
+    recipes: BTreeMap<(INum, usize), BlockRecipe>,
+    /// The refcounts of chunks
+    refcounts: HashMap<ChunkHash, u64>,
+}
+
+impl MemoryDedupIndexInner {
+    /// Apply `deltas` to the refcounts, returns the chunks no longer
+    /// referenced.
+    fn apply(&mut self, deltas: &RefDeltas) -> Vec<ChunkHash> {
+        let mut orphans = vec![];
+        for (hash, delta) in deltas.iter() {
+            let refcount = self.refcounts.entry(hash).or_default();
+            *refcount = refcount.saturating_add_signed(delta);
+            if *refcount == 0 {
+                self.refcounts.remove(&hash);
+                orphans.push(hash);
+            }
+        }
+        orphans
+    }
+}
+
+/// A `DedupIndex` in memory, which is not persisted.
+#[derive(Debug, Default)]
+pub struct MemoryDedupIndex {
+    /// The inner state
+    inner: Mutex<MemoryDedupIndexInner>,
+}
+
+impl MemoryDedupIndex {
+    /// Create an empty `MemoryDedupIndex`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of chunks referenced by any recipe.
+    #[must_use]
+    pub fn chunk_count(&self) -> usize {
+        self.inner.lock().refcounts.len()
+    }
+}
+
+#[async_trait]
+impl DedupIndex for MemoryDedupIndex {
+    async fn get_recipe(&self, ino: INum, block_id: usize) -> StorageResult<Option<BlockRecipe>> {
+        Ok(self.inner.lock().recipes.get(&(ino, block_id)).cloned())
+    }
+
+    async fn contains_chunk(&self, hash: ChunkHash) -> StorageResult<bool> {
+        Ok(self.inner.lock().refcounts.contains_key(&hash))
+    }
+
+    async fn set_recipe(&self, ino: INum, recipe: BlockRecipe) -> StorageResult<Vec<ChunkHash>> {
+        let mut inner = self.inner.lock();
+        let mut deltas = RefDeltas::default();
+        deltas.add(&recipe);
+        if let Some(old) = inner.recipes.insert((ino, recipe.block_id), recipe) {
+            deltas.sub(&old);
+        }
+        Ok(inner.apply(&deltas))
+    }
+
+    async fn remove_recipes(
+        &self,
+        ino: INum,
+        block_ids: Range<usize>,
+    ) -> StorageResult<Vec<ChunkHash>> {
+        let mut inner = self.inner.lock();
+        let keys: Vec<_> = inner
+            .recipes
+            .range((ino, block_ids.start)..(ino, block_ids.end))
+            .map(|(&key, _)| key)
+            .collect();
+        let mut deltas = RefDeltas::default();
+        for key in keys {
+            if let Some(old) = inner.recipes.remove(&key) {
+                deltas.sub(&old);
+            }
+        }
+        Ok(inner.apply(&deltas))
+    }
+}
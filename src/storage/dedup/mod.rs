@@ -0,0 +1,115 @@
+//! The content-defined deduplication of blocks.
+//!
+//! Blocks are split into chunks with content-defined chunking, and the chunks
+//! are stored by their hashes in a [`ChunkStore`]. A block is stored as a
+//! recipe, the list of its chunks, and the chunks referenced by recipes are
+//! reference counted in a [`DedupIndex`], which is usually the metadata store.
+//! Therefore, the same data written by different files, or at different
+//! offsets, only takes backend space once.
+
+mod chunker;
+mod index;
+mod storage;
+
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+pub use chunker::Chunker;
+use clippy_utilities::OverflowArithmetic;
+pub use index::{DedupIndex, MemoryDedupIndex, RefDeltas};
+use serde::{Deserialize, Serialize};
+pub use storage::DedupStorage;
+
+use super::error::StorageResult;
+
+/// The hash of a chunk, it's the address of the chunk in a `ChunkStore`.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ChunkHash([u8; 32]);
+
+impl ChunkHash {
+    /// Calculate the hash of a chunk.
+    #[must_use]
+    pub fn of(data: &[u8]) -> Self {
+        Self(*blake3::hash(data).as_bytes())
+    }
+
+    /// Parse a hash from its hex digits, returns `None` if it's malformed.
+    #[must_use]
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let mut hash = [0; 32];
+        if hex.len() != hash.len().overflow_mul(2) || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        for (byte, digits) in hash.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let digits = std::str::from_utf8(digits).ok()?;
+            *byte = u8::from_str_radix(digits, 16).ok()?;
+        }
+        Some(Self(hash))
+    }
+}
+
+impl Display for ChunkHash {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A reference to a chunk in a recipe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkRef {
+    /// The hash of the chunk
+    pub hash: ChunkHash,
+    /// The length of the chunk
+    pub len: usize,
+}
+
+/// The recipe of a block, the block is the concatenation of its chunks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockRecipe {
+    /// The index of the block in the file
+    pub block_id: usize,
+    /// The chunks of the block
+    pub chunks: Vec<ChunkRef>,
+}
+
+/// The `ChunkStore` trait, which stores chunks by their hashes.
+#[async_trait]
+pub trait ChunkStore {
+    /// Get the content of a chunk.
+    ///
+    /// Returns `None` if the chunk does not exist.
+    async fn get_chunk(&self, hash: ChunkHash) -> StorageResult<Option<Vec<u8>>>;
+
+    /// Put the content of a chunk. As chunks are addressed by their contents,
+    /// putting an existing chunk has no effect.
+    async fn put_chunk(&self, hash: ChunkHash, data: &[u8]) -> StorageResult<()>;
+
+    /// Delete a chunk. It's not an error to delete a non-existing chunk.
+    async fn delete_chunk(&self, hash: ChunkHash) -> StorageResult<()>;
+}
+
+#[async_trait]
+impl<T> ChunkStore for Arc<T>
+where
+    T: ChunkStore + Send + Sync,
+{
+    async fn get_chunk(&self, hash: ChunkHash) -> StorageResult<Option<Vec<u8>>> {
+        self.as_ref().get_chunk(hash).await
+    }
+
+    async fn put_chunk(&self, hash: ChunkHash, data: &[u8]) -> StorageResult<()> {
+        self.as_ref().put_chunk(hash, data).await
+    }
+
+    async fn delete_chunk(&self, hash: ChunkHash) -> StorageResult<()> {
+        self.as_ref().delete_chunk(hash).await
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests;
@@ -0,0 +1,258 @@
+//! The storage layer of deduplicated blocks.
+
+use std::collections::HashSet;
+use std::ops::Range;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use clippy_utilities::OverflowArithmetic;
+use futures::future::try_join_all;
+use tracing::warn;
+
+use super::{BlockRecipe, ChunkHash, ChunkRef, ChunkStore, Chunker, DedupIndex};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::error::{StorageError, StorageResult};
+use crate::storage::{Block, Storage};
+
+/// A `Storage` which deduplicates blocks by content-defined chunking.
+///
+/// This is the bottom of the storage layers, it has no cache and no backend.
+/// A chunk is put into the `ChunkStore` before the first recipe referencing it
+/// is committed to the index, and deleted after the last one is removed.
+///
+/// Chunking restarts at the beginning of each block, so data is deduplicated
+/// among blocks, and among shifted contents inside a block.
+#[derive(Debug)]
+pub struct DedupStorage<I, C> {
+    /// The index of recipes and refcounts
+    index: I,
+    /// The store of chunks
+    chunks: C,
+    /// The chunker
+    chunker: Chunker,
+    /// The size of blocks
+    block_size: usize,
+}
+
+impl<I, C> DedupStorage<I, C>
+where
+    I: DedupIndex + Send + Sync,
+    C: ChunkStore + Send + Sync,
+{
+    /// Create a `DedupStorage`, blocks are split into chunks of `avg_chunk_size`
+    /// on average.
+    pub fn new(index: I, chunks: C, block_size: usize, avg_chunk_size: usize) -> Self {
+        Self {
+            index,
+            chunks,
+            chunker: Chunker::new(avg_chunk_size),
+            block_size,
+        }
+    }
+
+    /// Read the content of a block by its recipe.
+    async fn read_content(&self, ino: INum, block_id: usize) -> StorageResult<Option<Vec<u8>>> {
+        let Some(recipe) = self.index.get_recipe(ino, block_id).await? else {
+            return Ok(None);
+        };
+
+        let chunks = try_join_all(recipe.chunks.iter().map(|chunk| async move {
+            self.chunks.get_chunk(chunk.hash).await?.ok_or_else(|| {
+                StorageError::Internal(anyhow!(
+                    "chunk {} of block {block_id} of file {ino} is missing",
+                    chunk.hash
+                ))
+            })
+        }))
+        .await?;
+
+        Ok(Some(chunks.concat()))
+    }
+
+    /// Write the content of a block, by putting its new chunks and committing
+    /// its recipe.
+    async fn write_content(&self, ino: INum, block_id: usize, data: &[u8]) -> StorageResult<()> {
+        let mut chunks = vec![];
+        let mut new_chunks = vec![];
+        let mut seen = HashSet::new();
+        for range in self.chunker.split(data) {
+            let content = data
+                .get(range)
+                .unwrap_or_else(|| unreachable!("Chunks are ensured to be in the data."));
+            let hash = ChunkHash::of(content);
+            chunks.push(ChunkRef {
+                hash,
+                len: content.len(),
+            });
+            if seen.insert(hash) && !self.index.contains_chunk(hash).await? {
+                new_chunks.push((hash, content));
+            }
+        }
+
+        try_join_all(
+            new_chunks
+                .into_iter()
+                .map(|(hash, content)| self.chunks.put_chunk(hash, content)),
+        )
+        .await?;
+
+        let orphans = self
+            .index
+            .set_recipe(ino, BlockRecipe { block_id, chunks })
+            .await?;
+        self.delete_chunks(orphans).await;
+
+        Ok(())
+    }
+
+    /// Delete chunks no longer referenced.
+    ///
+    /// Failures are only logged, a leaked chunk costs space but never breaks
+    /// data.
+    async fn delete_chunks(&self, orphans: Vec<ChunkHash>) {
+        for hash in orphans {
+            if let Err(e) = self.chunks.delete_chunk(hash).await {
+                warn!("failed to delete chunk {hash}: {e}");
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<I, C> Storage for DedupStorage<I, C>
+where
+    I: DedupIndex + Send + Sync,
+    C: ChunkStore + Send + Sync,
+{
+    async fn load_from_self(&self, ino: INum, block_id: usize) -> StorageResult<Option<Block>> {
+        let data = self.read_content(ino, block_id).await?;
+        Ok(data.map(|data| Block::from_slice(self.block_size, &data)))
+    }
+
+    async fn load_from_backend(&self, _: INum, _: usize) -> StorageResult<Option<Block>> {
+        // This storage has no backend.
+        Ok(None)
+    }
+
+    async fn cache_block_from_backend(&self, _: INum, _: usize, _: Block) -> StorageResult<()> {
+        unreachable!("This storage has no backend, and has no cache.");
+    }
+
+    async fn store(&self, ino: INum, block_id: usize, block: Block) -> StorageResult<()> {
+        let block_start = block.start();
+        let block_end = block.end();
+
+        if block_start == 0 && block_end == self.block_size {
+            // To store a whole block
+            return self.write_content(ino, block_id, block.as_slice()).await;
+        }
+
+        let mut dest = self.read_content(ino, block_id).await?.unwrap_or_default();
+
+        // Ensure that the vector is long enough to be overwritten
+        if dest.len() < block_end {
+            dest.resize(block_end, 0);
+        }
+
+        // merge two blocks
+        dest.get_mut(block_start..block_end)
+            .unwrap_or_else(|| unreachable!("The vector is ensured to be long enough."))
+            .copy_from_slice(block.as_slice());
+        self.write_content(ino, block_id, &dest).await
+    }
+
+    async fn remove(&self, ino: INum) -> StorageResult<()> {
+        let orphans = self.index.remove_recipes(ino, 0..usize::MAX).await?;
+        self.delete_chunks(orphans).await;
+        Ok(())
+    }
+
+    async fn invalidate(&self, _: INum) -> StorageResult<()> {
+        // This storage has no cache, therefore, its contents cannot be
+        // invalidated.
+        Ok(())
+    }
+
+    async fn flush(&self, _: INum) -> StorageResult<()> {
+        // This storage has no cache and backend, therefore, there is no need to
+        // flush its data.
+        Ok(())
+    }
+
+    async fn flush_all(&self) -> StorageResult<()> {
+        // This storage has no cache and backend, therefore, there is no need to
+        // flush its data.
+        Ok(())
+    }
+
+    async fn truncate(
+        &self,
+        ino: INum,
+        from_block: usize,
+        to_block: usize,
+        fill_start: usize,
+    ) -> StorageResult<()> {
+        if to_block == 0 {
+            return self.remove(ino).await;
+        }
+
+        if to_block < from_block {
+            let orphans = self.index.remove_recipes(ino, to_block..from_block).await?;
+            self.delete_chunks(orphans).await;
+        }
+
+        // truncate the last block
+        if fill_start < self.block_size {
+            let block_id = to_block.overflow_sub(1);
+            if let Some(mut dest) = self.read_content(ino, block_id).await? {
+                dest.truncate(fill_start);
+                self.write_content(ino, block_id, &dest).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn clone_blocks(
+        &self,
+        src_ino: INum,
+        src_block: usize,
+        dst_ino: INum,
+        dst_block: usize,
+        count: usize,
+    ) -> StorageResult<bool> {
+        // A clone is a copy of the recipe, its chunks are shared by
+        // referencing them once more.
+        for offset in 0..count {
+            let block_id = dst_block.overflow_add(offset);
+            let orphans = match self
+                .index
+                .get_recipe(src_ino, src_block.overflow_add(offset))
+                .await?
+            {
+                Some(recipe) => {
+                    let recipe = BlockRecipe {
+                        block_id,
+                        chunks: recipe.chunks,
+                    };
+                    self.index.set_recipe(dst_ino, recipe).await?
+                }
+                // The source block is in a hole, so is the clone.
+                None => {
+                    self.index
+                        .remove_recipes(dst_ino, block_id..block_id.overflow_add(1))
+                        .await?
+                }
+            };
+            self.delete_chunks(orphans).await;
+        }
+
+        Ok(true)
+    }
+
+    async fn punch_blocks(&self, ino: INum, blocks: Range<usize>) -> StorageResult<bool> {
+        let orphans = self.index.remove_recipes(ino, blocks).await?;
+        self.delete_chunks(orphans).await;
+        Ok(true)
+    }
+}
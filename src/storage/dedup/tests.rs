@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use clippy_utilities::{Cast, OverflowArithmetic};
+use parking_lot::Mutex;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+
+use super::{ChunkHash, ChunkStore, DedupStorage, MemoryDedupIndex};
+use crate::storage::error::StorageResult;
+use crate::storage::{Block, Storage};
+
+const BLOCK_SIZE_IN_BYTES: usize = 4096;
+const AVG_CHUNK_SIZE: usize = 256;
+
+/// A `ChunkStore` in memory.
+#[derive(Debug, Default)]
+struct MemoryChunkStore {
+    /// The chunks
+    chunks: Mutex<HashMap<ChunkHash, Vec<u8>>>,
+}
+
+impl MemoryChunkStore {
+    fn len(&self) -> usize {
+        self.chunks.lock().len()
+    }
+}
+
+#[async_trait]
+impl ChunkStore for MemoryChunkStore {
+    async fn get_chunk(&self, hash: ChunkHash) -> StorageResult<Option<Vec<u8>>> {
+        Ok(self.chunks.lock().get(&hash).cloned())
+    }
+
+    async fn put_chunk(&self, hash: ChunkHash, data: &[u8]) -> StorageResult<()> {
+        self.chunks.lock().insert(hash, data.to_vec());
+        Ok(())
+    }
+
+    async fn delete_chunk(&self, hash: ChunkHash) -> StorageResult<()> {
+        self.chunks.lock().remove(&hash);
+        Ok(())
+    }
+}
+
+type DedupStorageType = DedupStorage<Arc<MemoryDedupIndex>, Arc<MemoryChunkStore>>;
+
+fn prepare_storage() -> (
+    Arc<MemoryDedupIndex>,
+    Arc<MemoryChunkStore>,
+    DedupStorageType,
+) {
+    let index = Arc::new(MemoryDedupIndex::new());
+    let chunks = Arc::new(MemoryChunkStore::default());
+    let storage = DedupStorage::new(
+        Arc::clone(&index),
+        Arc::clone(&chunks),
+        BLOCK_SIZE_IN_BYTES,
+        AVG_CHUNK_SIZE,
+    );
+    (index, chunks, storage)
+}
+
+fn random_block(seed: u64) -> Vec<u8> {
+    let mut data = vec![0; BLOCK_SIZE_IN_BYTES];
+    StdRng::seed_from_u64(seed).fill_bytes(&mut data);
+    data
+}
+
+#[tokio::test]
+async fn test_dedup_same_content() {
+    let (index, chunks, storage) = prepare_storage();
+    let content = random_block(0);
+
+    storage
+        .store(0, 0, Block::from_slice(BLOCK_SIZE_IN_BYTES, &content))
+        .await
+        .unwrap();
+    let chunk_count = chunks.len();
+    assert!(chunk_count > 1);
+    assert_eq!(index.chunk_count(), chunk_count);
+
+    // The same content in another file takes no more chunks.
+    storage
+        .store(1, 3, Block::from_slice(BLOCK_SIZE_IN_BYTES, &content))
+        .await
+        .unwrap();
+    assert_eq!(chunks.len(), chunk_count);
+
+    let loaded = storage.load(1, 3).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), content.as_slice());
+    assert!(storage.load(1, 0).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_partial_write() {
+    let (_, _, storage) = prepare_storage();
+    let mut content = random_block(1);
+
+    storage
+        .store(0, 0, Block::from_slice(BLOCK_SIZE_IN_BYTES, &content))
+        .await
+        .unwrap();
+    storage
+        .store(
+            0,
+            0,
+            Block::from_slice_with_range(BLOCK_SIZE_IN_BYTES, 100, 104, b"foo "),
+        )
+        .await
+        .unwrap();
+
+    content.get_mut(100..104).unwrap().copy_from_slice(b"foo ");
+    let loaded = storage.load(0, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), content.as_slice());
+}
+
+#[tokio::test]
+async fn test_remove_and_truncate() {
+    let (index, chunks, storage) = prepare_storage();
+
+    for block_id in 0..3_usize {
+        let block = Block::from_slice(BLOCK_SIZE_IN_BYTES, &random_block(block_id.cast()));
+        storage.store(0, block_id, block).await.unwrap();
+    }
+    let block = Block::from_slice(BLOCK_SIZE_IN_BYTES, &random_block(0));
+    storage.store(1, 0, block).await.unwrap();
+
+    // Chunks of block 1 and 2 of file 0 are deleted.
+    let chunk_count = chunks.len();
+    storage.truncate(0, 3, 1, 100).await.unwrap();
+    assert!(chunks.len() < chunk_count);
+    assert!(storage.load(0, 1).await.unwrap().is_none());
+    assert!(storage.load(0, 2).await.unwrap().is_none());
+
+    let loaded = storage.load(0, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice().get(..100), random_block(0).get(..100));
+    assert!(loaded.as_slice().iter().skip(100).all(|&byte| byte == 0));
+
+    // Removing file 1 does not affect file 0.
+    storage.remove(1).await.unwrap();
+    assert!(storage.load(0, 0).await.unwrap().is_some());
+
+    storage.remove(0).await.unwrap();
+    assert_eq!(chunks.len(), 0);
+    assert_eq!(index.chunk_count(), 0);
+}
+
+#[tokio::test]
+async fn test_clone_blocks() {
+    let (index, chunks, storage) = prepare_storage();
+
+    for block_id in 0..2_usize {
+        let block = Block::from_slice(BLOCK_SIZE_IN_BYTES, &random_block(block_id.cast()));
+        storage.store(0, block_id, block).await.unwrap();
+    }
+    let block = Block::from_slice(BLOCK_SIZE_IN_BYTES, &random_block(2));
+    storage.store(1, 6, block).await.unwrap();
+    let chunk_count = chunks.len();
+
+    // Block 2 of file 0 is a hole, the clone of it punches block 6 of file 1.
+    assert!(storage.clone_blocks(0, 0, 1, 4, 3).await.unwrap());
+    assert!(chunks.len() < chunk_count);
+    for block_id in 0..2_usize {
+        let loaded = storage
+            .load(1, block_id.overflow_add(4))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.as_slice(), random_block(block_id.cast()).as_slice());
+    }
+    assert!(storage.load(1, 6).await.unwrap().is_none());
+
+    // Overwriting the clone does not affect the source.
+    let block = Block::from_slice_with_range(BLOCK_SIZE_IN_BYTES, 0, 4, b"foo ");
+    storage.store(1, 4, block).await.unwrap();
+    let loaded = storage.load(0, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), random_block(0).as_slice());
+
+    // The shared chunks are kept until both files are removed.
+    storage.remove(0).await.unwrap();
+    let loaded = storage.load(1, 5).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), random_block(1).as_slice());
+    storage.remove(1).await.unwrap();
+    assert_eq!(chunks.len(), 0);
+    assert_eq!(index.chunk_count(), 0);
+}
+
+#[test]
+fn test_chunk_hash_hex() {
+    let hash = ChunkHash::of(b"foo bar");
+    assert_eq!(ChunkHash::from_hex(&hash.to_string()), Some(hash));
+    assert_eq!(ChunkHash::from_hex("00"), None);
+    assert_eq!(ChunkHash::from_hex(&"+0".repeat(32)), None);
+}
@@ -0,0 +1,514 @@
+//! The `DiskCache` implementation.
+
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use clippy_utilities::OverflowArithmetic;
+use datenlord::metrics::CACHE_METRICS;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+use super::versions::{FileVersion, FileVersions};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::block_store::{BlockKey, BlockStore, LocalBlockStore, INITIAL_BLOCK_VERSION};
+use crate::storage::error::StorageResult;
+use crate::storage::policy::EvictPolicy;
+use crate::storage::{Block, BlockCoordinate, BlockId, Storage};
+
+/// The name of the index persisted in the cache directory.
+const INDEX_FILE: &str = "index";
+
+/// The index of the cache persisted on shutdown.
+#[derive(Debug, Serialize, Deserialize)]
+struct PersistedIndex {
+    /// The block size of the blocks cached
+    block_size: usize,
+    /// The files cached
+    files: Vec<PersistedFile>,
+}
+
+/// The blocks of a file in the index persisted.
+#[derive(Debug, Serialize, Deserialize)]
+struct PersistedFile {
+    /// The i-number of the file
+    ino: INum,
+    /// The version of the file when the index is persisted
+    version: FileVersion,
+    /// The blocks cached
+    blocks: Vec<BlockId>,
+}
+
+/// The on-disk cache.
+///
+/// Blocks are spilled to a local directory when they are evicted from the
+/// upper cache, or when they are loaded from the backend. The cache only holds
+/// clean blocks: a dirty block is written to the backend before it's cached,
+/// so the cache can be dropped at any time without losing data.
+///
+/// The index of cached blocks is kept in memory. The cache directory is
+/// cleared when the cache is created by `new`, because the blocks left by a
+/// previous (may be crashed) run cannot be trusted. To keep the cache warm
+/// across restarts, the index is persisted with the versions of the files by
+/// `persist` on a clean shutdown, and the cache is created by `recover`, which
+/// keeps the blocks of the files still of those versions.
+#[derive(Debug)]
+pub struct DiskCache<P, S> {
+    /// The cache directory
+    root: PathBuf,
+    /// The blocks on the local disk
+    store: LocalBlockStore,
+    /// The index of cached blocks
+    index: Mutex<HashMap<INum, HashSet<BlockId>>>,
+    /// The evict policy
+    policy: P,
+    /// The backend storage
+    backend: S,
+    /// The block size
+    block_size: usize,
+}
+
+impl<P, S> DiskCache<P, S> {
+    /// The limit of retrying to insert a block into the cache.
+    const INSERT_RETRY_LIMMIT: usize = 10;
+
+    /// Create a disk cache in the directory of `root`, the capacity of the
+    /// cache is limited by `policy`.
+    ///
+    /// Everything in `root` will be removed.
+    pub async fn new(
+        root: impl AsRef<Path>,
+        policy: P,
+        backend: S,
+        block_size: usize,
+    ) -> StorageResult<Self> {
+        let root = root.as_ref();
+        match fs::remove_dir_all(root).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        Ok(DiskCache {
+            root: root.to_owned(),
+            store: LocalBlockStore::new(root).await?,
+            index: Mutex::default(),
+            policy,
+            backend,
+            block_size,
+        })
+    }
+
+    /// Read and remove the index persisted in `root`, returns `None` if
+    /// there's none.
+    ///
+    /// The removal is synced before the cache is used, so the index never
+    /// outlives a crash of the run using it.
+    async fn take_index(root: &Path) -> StorageResult<Option<PersistedIndex>> {
+        let path = root.join(INDEX_FILE);
+        let data = match fs::read(&path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        fs::remove_file(&path).await?;
+        File::open(root).await?.sync_all().await?;
+        let index = bincode::deserialize(&data)
+            .map_err(|e| anyhow!("The index of disk cache is corrupted: {e}"))?;
+        Ok(Some(index))
+    }
+
+    /// Persist the index of the cache with the versions of the files in
+    /// `versions`, so the blocks are kept by `recover` on the next start.
+    ///
+    /// It must be called once no more block is cached, usually on shutdown.
+    pub async fn persist(&self, versions: &impl FileVersions) -> StorageResult<()> {
+        let cached: Vec<(INum, Vec<BlockId>)> = self
+            .index
+            .lock()
+            .iter()
+            .map(|(&ino, blocks)| (ino, blocks.iter().copied().collect()))
+            .collect();
+        let mut files = Vec::with_capacity(cached.len());
+        for (ino, blocks) in cached {
+            // The blocks of the files removed are not kept.
+            if let Some(version) = versions.file_version(ino).await? {
+                files.push(PersistedFile {
+                    ino,
+                    version,
+                    blocks,
+                });
+            }
+        }
+        let blocks: usize = files.iter().map(|file| file.blocks.len()).sum();
+        let index = PersistedIndex {
+            block_size: self.block_size,
+            files,
+        };
+        let data = bincode::serialize(&index)
+            .map_err(|e| anyhow!("Failed to serialize the index of disk cache: {e}"))?;
+
+        // Write to a temporary file then rename it, so that a crash during
+        // writing never leaves a partially written index.
+        let tmp_path = self.root.join(format!(".{INDEX_FILE}.tmp"));
+        let mut file = File::create(&tmp_path).await?;
+        file.write_all(&data).await?;
+        file.sync_all().await?;
+        fs::rename(&tmp_path, self.root.join(INDEX_FILE)).await?;
+        File::open(&self.root).await?.sync_all().await?;
+        info!(
+            "Persisted {blocks} blocks of disk cache {}.",
+            self.root.display()
+        );
+        Ok(())
+    }
+
+    /// Check if a block is in the cache.
+    fn contains(&self, ino: INum, block_id: BlockId) -> bool {
+        self.index
+            .lock()
+            .get(&ino)
+            .map_or(false, |blocks| blocks.contains(&block_id))
+    }
+
+    /// Remove a block from the index, returns if the block was in the index.
+    fn remove_from_index(&self, ino: INum, block_id: BlockId) -> bool {
+        let mut index = self.index.lock();
+        let Some(blocks) = index.get_mut(&ino) else {
+            return false;
+        };
+        let removed = blocks.remove(&block_id);
+        if blocks.is_empty() {
+            index.remove(&ino);
+        }
+        removed
+    }
+
+    /// Discard a block from the cache.
+    async fn discard(&self, ino: INum, block_id: BlockId) -> StorageResult<()> {
+        if self.remove_from_index(ino, block_id) {
+            self.store.delete(BlockKey::new(ino, block_id)).await?;
+        }
+        Ok(())
+    }
+
+    /// Discard all blocks of a file from the cache.
+    async fn discard_file(&self, ino: INum) -> StorageResult<()> {
+        self.index.lock().remove(&ino);
+        self.store.delete_file(ino).await
+    }
+}
+
+impl<P, S> DiskCache<P, S>
+where
+    P: EvictPolicy<BlockCoordinate> + Send + Sync,
+{
+    /// Create a disk cache in the directory of `root` like `new`, but the
+    /// blocks in the index persisted by `persist` are kept, if their files are
+    /// still of the versions in `versions`. Everything else in `root` is
+    /// removed, and so is the index, which is only trusted once.
+    ///
+    /// The cache starts cold if there's no index, such as after a crash.
+    pub async fn recover(
+        root: impl AsRef<Path>,
+        policy: P,
+        backend: S,
+        block_size: usize,
+        versions: &impl FileVersions,
+    ) -> StorageResult<Self> {
+        let root = root.as_ref();
+        let persisted = match Self::take_index(root).await {
+            Ok(Some(persisted)) if persisted.block_size == block_size => persisted,
+            Ok(Some(_)) => {
+                warn!("The block size of disk cache is changed, the blocks are dropped.");
+                return Self::new(root, policy, backend, block_size).await;
+            }
+            Ok(None) => return Self::new(root, policy, backend, block_size).await,
+            Err(e) => {
+                warn!("Failed to read the index of disk cache, the blocks are dropped, err={e}");
+                return Self::new(root, policy, backend, block_size).await;
+            }
+        };
+
+        let mut valid: HashMap<INum, HashSet<BlockId>> = HashMap::new();
+        let mut stale = 0_usize;
+        for file in persisted.files {
+            match versions.file_version(file.ino).await {
+                Ok(Some(version)) if version == file.version => {
+                    valid.insert(file.ino, file.blocks.into_iter().collect());
+                }
+                Ok(_) => stale = stale.overflow_add(1),
+                Err(e) => {
+                    warn!(
+                        "Failed to get the version of file, its blocks in disk cache are dropped, \
+                         ino={}, err={e}",
+                        file.ino
+                    );
+                    stale = stale.overflow_add(1);
+                }
+            }
+        }
+
+        let store = LocalBlockStore::new(root).await?;
+        let kept = store
+            .retain(|key| {
+                key.version == INITIAL_BLOCK_VERSION
+                    && valid
+                        .get(&key.ino)
+                        .map_or(false, |blocks| blocks.contains(&key.block_id))
+            })
+            .await?;
+        let cache = DiskCache {
+            root: root.to_owned(),
+            store,
+            index: Mutex::default(),
+            policy,
+            backend,
+            block_size,
+        };
+        let mut recovered = 0_usize;
+        for key in kept {
+            // The capacity may be shrunk since the last run.
+            if cache.policy.try_put(BlockCoordinate(key.ino, key.block_id)) {
+                cache
+                    .index
+                    .lock()
+                    .entry(key.ino)
+                    .or_default()
+                    .insert(key.block_id);
+                recovered = recovered.overflow_add(1);
+            } else {
+                cache.store.delete(key).await?;
+            }
+        }
+        info!(
+            "Recovered {recovered} blocks of disk cache {}, {stale} stale files are dropped.",
+            root.display()
+        );
+        Ok(cache)
+    }
+
+    /// Evict a block from the cache.
+    async fn evict(&self) -> StorageResult<()> {
+        if let Some(BlockCoordinate(ino, block_id)) = self.policy.evict() {
+            self.discard(ino, block_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Write a clean block into the cache.
+    async fn try_spill(&self, ino: INum, block_id: BlockId, block: &Block) -> StorageResult<()> {
+        // Only whole blocks are cached, as a partial one cannot be served
+        // without the backend.
+        if block.start() != 0 || block.end() != self.block_size {
+            return self.discard(ino, block_id).await;
+        }
+
+        let mut retry_times = 0;
+        while !self.policy.try_put(BlockCoordinate(ino, block_id)) {
+            if retry_times >= Self::INSERT_RETRY_LIMMIT {
+                return Err(
+                    anyhow!("Gave up retrying to insert a block into the disk cache.").into(),
+                );
+            }
+            self.evict().await?;
+            retry_times = retry_times.overflow_add(1);
+        }
+
+        self.store
+            .put(BlockKey::new(ino, block_id), block.as_slice().to_vec())
+            .await?;
+        self.index.lock().entry(ino).or_default().insert(block_id);
+
+        Ok(())
+    }
+
+    /// Write a clean block into the cache. A failure of the cache is not
+    /// fatal, as the block is always available in the backend, so the error
+    /// is only logged.
+    async fn spill(&self, ino: INum, block_id: BlockId, block: &Block) {
+        if let Err(e) = self.try_spill(ino, block_id, block).await {
+            warn!("Failed to spill block to disk cache, ino={ino}, block_id={block_id}, err={e}");
+            if let Err(e) = self.discard(ino, block_id).await {
+                warn!(
+                    "Failed to discard block from disk cache, ino={ino}, block_id={block_id}, err={e}"
+                );
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<P, S> Storage for DiskCache<P, S>
+where
+    P: EvictPolicy<BlockCoordinate> + Send + Sync,
+    S: Storage + Send + Sync,
+{
+    async fn load_from_self(&self, ino: INum, block_id: usize) -> StorageResult<Option<Block>> {
+        if !self.contains(ino, block_id) {
+            return Ok(None);
+        }
+
+        match self.store.get(BlockKey::new(ino, block_id)).await {
+            Ok(Some(data)) => {
+                CACHE_METRICS.cache_hit_count_inc("disk", self.policy.name());
+                self.policy.touch(&BlockCoordinate(ino, block_id));
+                Ok(Some(Block::from_slice(self.block_size, &data)))
+            }
+            Ok(None) => {
+                self.remove_from_index(ino, block_id);
+                Ok(None)
+            }
+            Err(e) => {
+                // Fallback to the backend.
+                warn!(
+                    "Failed to read block from disk cache, ino={ino}, block_id={block_id}, err={e}"
+                );
+                self.discard(ino, block_id).await?;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn load_from_backend(&self, ino: INum, block_id: usize) -> StorageResult<Option<Block>> {
+        let res = self.backend.load(ino, block_id).await;
+
+        if let Ok(Some(_)) = res {
+            // The cache is considered missed, only if the block exists in the backend.
+            CACHE_METRICS.cache_miss_count_inc("disk", self.policy.name());
+        }
+
+        res
+    }
+
+    async fn cache_block_from_backend(
+        &self,
+        ino: INum,
+        block_id: usize,
+        block: Block,
+    ) -> StorageResult<()> {
+        self.spill(ino, block_id, &block).await;
+        Ok(())
+    }
+
+    async fn store(&self, ino: INum, block_id: usize, block: Block) -> StorageResult<()> {
+        // A clean block is evicted from the upper cache, and is consistent with
+        // the backend, so it's only spilled to the disk.
+        if block.dirty() {
+            self.backend.store(ino, block_id, block.clone()).await?;
+        }
+        self.spill(ino, block_id, &block).await;
+
+        Ok(())
+    }
+
+    async fn remove(&self, ino: INum) -> StorageResult<()> {
+        self.discard_file(ino).await?;
+        self.backend.remove(ino).await
+    }
+
+    async fn invalidate(&self, ino: INum) -> StorageResult<()> {
+        self.discard_file(ino).await?;
+        self.backend.invalidate(ino).await
+    }
+
+    async fn flush(&self, ino: INum) -> StorageResult<()> {
+        self.backend.flush(ino).await
+    }
+
+    async fn flush_all(&self) -> StorageResult<()> {
+        self.backend.flush_all().await
+    }
+
+    async fn truncate(
+        &self,
+        ino: INum,
+        from_block: usize,
+        to_block: usize,
+        fill_start: usize,
+    ) -> StorageResult<()> {
+        // The blocks out of range, and the last block to be filled with zeros.
+        let discard_from = if to_block > 0 && fill_start < self.block_size {
+            to_block.overflow_sub(1)
+        } else {
+            to_block
+        };
+
+        let discarded: Vec<BlockId> = self
+            .index
+            .lock()
+            .get(&ino)
+            .map(|blocks| {
+                blocks
+                    .iter()
+                    .copied()
+                    .filter(|&block_id| block_id >= discard_from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        for block_id in discarded {
+            self.discard(ino, block_id).await?;
+        }
+
+        self.backend
+            .truncate(ino, from_block, to_block, fill_start)
+            .await
+    }
+
+    async fn clone_blocks(
+        &self,
+        src_ino: INum,
+        src_block: usize,
+        dst_ino: INum,
+        dst_block: usize,
+        count: usize,
+    ) -> StorageResult<bool> {
+        let cloned = self
+            .backend
+            .clone_blocks(src_ino, src_block, dst_ino, dst_block, count)
+            .await?;
+        if cloned {
+            for block_id in dst_block..dst_block.overflow_add(count) {
+                self.discard(dst_ino, block_id).await?;
+            }
+        }
+        Ok(cloned)
+    }
+
+    async fn punch_blocks(&self, ino: INum, blocks: Range<BlockId>) -> StorageResult<bool> {
+        let punched = self.backend.punch_blocks(ino, blocks.clone()).await?;
+        if punched {
+            for block_id in blocks {
+                self.discard(ino, block_id).await?;
+            }
+        }
+        Ok(punched)
+    }
+
+    fn pin(&self, ino: INum, blocks: Range<BlockId>) {
+        for block_id in blocks {
+            self.policy.pin(&BlockCoordinate(ino, block_id));
+        }
+    }
+
+    fn unpin(&self, ino: INum, blocks: Range<BlockId>) {
+        for block_id in blocks {
+            self.policy.unpin(&BlockCoordinate(ino, block_id));
+        }
+    }
+
+    fn pin_capacity(&self) -> usize {
+        self.policy.capacity()
+    }
+
+    async fn load_cached(&self, ino: INum, block_id: usize) -> StorageResult<Option<Block>> {
+        // The blocks are written through to the backend, so they're all clean.
+        self.load_from_self(ino, block_id).await
+    }
+}
@@ -0,0 +1,10 @@
+//! The on-disk cache, as the second-tier cache below the in-memory cache.
+
+mod cache;
+mod versions;
+
+pub use cache::DiskCache;
+pub use versions::{FileVersion, FileVersions, MemoryFileVersions};
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,160 @@
+#![allow(clippy::unwrap_used)]
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use super::{DiskCache, FileVersion, MemoryFileVersions};
+use crate::storage::mock::MemoryStorage;
+use crate::storage::policy::LruPolicy;
+use crate::storage::{Block, BlockCoordinate, Storage};
+
+const BLOCK_SIZE_IN_BYTES: usize = 8;
+const BLOCK_CONTENT: &[u8; BLOCK_SIZE_IN_BYTES] = b"foo bar ";
+const CACHE_CAPACITY_IN_BLOCKS: usize = 2;
+
+type DiskCacheType = DiskCache<LruPolicy<BlockCoordinate>, Arc<MemoryStorage>>;
+
+async fn prepare_empty_storage(name: &str) -> (Arc<MemoryStorage>, DiskCacheType) {
+    let policy = LruPolicy::<BlockCoordinate>::new(CACHE_CAPACITY_IN_BLOCKS);
+    let backend = Arc::new(MemoryStorage::new(
+        BLOCK_SIZE_IN_BYTES,
+        Duration::from_millis(0),
+    ));
+    let cache = DiskCache::new(
+        format!("/tmp/datenlord_disk_cache/{name}"),
+        policy,
+        Arc::clone(&backend),
+        BLOCK_SIZE_IN_BYTES,
+    )
+    .await
+    .unwrap();
+
+    (backend, cache)
+}
+
+#[tokio::test]
+async fn test_spill_clean_block() {
+    let (backend, cache) = prepare_empty_storage("spill_clean_block").await;
+
+    // A clean block is only spilled to the disk.
+    let block = Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT);
+    cache.store(0, 0, block).await.unwrap();
+    assert!(!backend.contains(0, 0));
+
+    let loaded = cache.load_from_self(0, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), BLOCK_CONTENT);
+}
+
+#[tokio::test]
+async fn test_store_dirty_block() {
+    let (backend, cache) = prepare_empty_storage("store_dirty_block").await;
+
+    let mut block = Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT);
+    block.set_dirty(true);
+    cache.store(0, 0, block).await.unwrap();
+    assert!(backend.contains(0, 0));
+    assert!(cache.load_from_self(0, 0).await.unwrap().is_some());
+
+    // A partial block is written to the backend, but not cached.
+    let mut block = Block::from_slice_with_range(BLOCK_SIZE_IN_BYTES, 0, 4, b"bar ");
+    block.set_dirty(true);
+    cache.store(0, 0, block).await.unwrap();
+    assert!(cache.load_from_self(0, 0).await.unwrap().is_none());
+
+    let loaded = cache.load(0, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), b"bar bar ");
+}
+
+#[tokio::test]
+async fn test_cache_block_from_backend() {
+    let (backend, cache) = prepare_empty_storage("cache_block_from_backend").await;
+
+    let block = Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT);
+    backend.store(0, 0, block).await.unwrap();
+
+    assert!(cache.load_from_self(0, 0).await.unwrap().is_none());
+    let loaded = cache.load(0, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), BLOCK_CONTENT);
+    assert!(cache.load_from_self(0, 0).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn test_evict() {
+    let (_, cache) = prepare_empty_storage("evict").await;
+
+    for block_id in 0..3 {
+        let block = Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT);
+        cache.store(0, block_id, block).await.unwrap();
+    }
+
+    assert!(cache.load_from_self(0, 0).await.unwrap().is_none());
+    assert!(cache.load_from_self(0, 1).await.unwrap().is_some());
+    assert!(cache.load_from_self(0, 2).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn test_invalidate_and_truncate() {
+    let (_, cache) = prepare_empty_storage("invalidate_and_truncate").await;
+
+    for block_id in 0..2 {
+        let block = Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT);
+        cache.store(0, block_id, block).await.unwrap();
+    }
+
+    // The last block to be filled with zeros is discarded.
+    cache.truncate(0, 2, 1, 4).await.unwrap();
+    assert!(cache.load_from_self(0, 0).await.unwrap().is_none());
+    assert!(cache.load_from_self(0, 1).await.unwrap().is_none());
+
+    let block = Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT);
+    cache.store(1, 0, block).await.unwrap();
+    cache.invalidate(1).await.unwrap();
+    assert!(cache.load_from_self(1, 0).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_persist_and_recover() {
+    let root = "/tmp/datenlord_disk_cache/persist_and_recover";
+    let (backend, cache) = prepare_empty_storage("persist_and_recover").await;
+    let versions = MemoryFileVersions::new();
+    let version = FileVersion {
+        mtime: SystemTime::UNIX_EPOCH,
+        size: 16,
+    };
+    for ino in 0..3 {
+        let block = Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT);
+        cache.store(ino, 0, block).await.unwrap();
+        versions.set_version(ino, version);
+    }
+    cache.persist(&versions).await.unwrap();
+    drop(cache);
+
+    // File 0 is modified, and file 1 is removed while the cache is down.
+    versions.set_version(
+        0,
+        FileVersion {
+            mtime: SystemTime::now(),
+            size: 16,
+        },
+    );
+    versions.remove(1);
+    let recover = || {
+        DiskCache::recover(
+            root,
+            LruPolicy::<BlockCoordinate>::new(CACHE_CAPACITY_IN_BLOCKS),
+            Arc::clone(&backend),
+            BLOCK_SIZE_IN_BYTES,
+            &versions,
+        )
+    };
+    let cache: DiskCacheType = recover().await.unwrap();
+    assert!(cache.load_from_self(0, 0).await.unwrap().is_none());
+    assert!(cache.load_from_self(1, 0).await.unwrap().is_none());
+    let loaded = cache.load_from_self(2, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), BLOCK_CONTENT);
+    drop(cache);
+
+    // The index is trusted only once, so a crash leaves the cache cold.
+    let cache: DiskCacheType = recover().await.unwrap();
+    assert!(cache.load_from_self(2, 0).await.unwrap().is_none());
+}
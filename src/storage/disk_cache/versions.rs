@@ -0,0 +1,74 @@
+//! The versions of files, which the blocks cached are validated against.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::error::StorageResult;
+
+/// The version of a file in the metadata, which changes once the content of
+/// the file changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileVersion {
+    /// The time of the last modification
+    pub mtime: SystemTime,
+    /// The size in bytes
+    pub size: u64,
+}
+
+/// The `FileVersions` trait, which looks up the versions of files, usually in
+/// the metadata store.
+#[async_trait]
+pub trait FileVersions {
+    /// Get the version of a file.
+    ///
+    /// Returns `None` if the file doesn't exist.
+    async fn file_version(&self, ino: INum) -> StorageResult<Option<FileVersion>>;
+}
+
+#[async_trait]
+impl<T> FileVersions for Arc<T>
+where
+    T: FileVersions + Send + Sync,
+{
+    async fn file_version(&self, ino: INum) -> StorageResult<Option<FileVersion>> {
+        self.as_ref().file_version(ino).await
+    }
+}
+
+/// A `FileVersions` in memory, which is not persisted.
+#[derive(Debug, Default)]
+pub struct MemoryFileVersions {
+    /// The versions of files
+    versions: Mutex<HashMap<INum, FileVersion>>,
+}
+
+impl MemoryFileVersions {
+    /// Create an empty `MemoryFileVersions`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the version of a file.
+    pub fn set_version(&self, ino: INum, version: FileVersion) {
+        self.versions.lock().insert(ino, version);
+    }
+
+    /// Remove a file.
+    pub fn remove(&self, ino: INum) {
+        self.versions.lock().remove(&ino);
+    }
+}
+
+#[async_trait]
+impl FileVersions for MemoryFileVersions {
+    async fn file_version(&self, ino: INum) -> StorageResult<Option<FileVersion>> {
+        Ok(self.versions.lock().get(&ino).copied())
+    }
+}
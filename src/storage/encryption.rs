@@ -0,0 +1,357 @@
+//! The encryption of blocks at rest.
+//!
+//! Every volume has its own data keys, which are generated randomly, and
+//! persisted in the metadata wrapped (encrypted) by a master key. The master
+//! key is provided by the operator, usually written into a file by a KMS agent,
+//! and is never persisted by `DatenLord`.
+//!
+//! Blocks are encrypted by the newest data key with AES-256-GCM before they
+//! are written to the backend, so raw objects in the backend never expose data
+//! of tenants:
+//!
+//! ```text
+//! | magic (4 bytes) | key id (4 bytes, LE) | nonce (12 bytes) | ciphertext | tag (16 bytes) |
+//! ```
+//!
+//! The path of the object is authenticated as the associated data, so an
+//! encrypted block cannot be moved to another path. Nonces are random, which
+//! is safe for about 2^32 writes with one data key. Rotating the data key adds
+//! a new key for writes, and blocks written with the older keys are still
+//! readable.
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::path::Path;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::anyhow;
+use clippy_utilities::OverflowArithmetic;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use super::error::{StorageError, StorageResult};
+
+/// The magic number at the beginning of an encrypted object.
+const MAGIC: &[u8; 4] = b"DLEB";
+
+/// The length of keys.
+const KEY_LEN: usize = 32;
+
+/// The length of nonces.
+const NONCE_LEN: usize = 12;
+
+/// The length of the header of an encrypted object.
+const HEADER_LEN: usize = 20;
+
+/// The length of the authentication tag.
+const TAG_LEN: usize = 16;
+
+/// The extra bytes of an encrypted object compared to the plain one.
+pub const OVERHEAD: usize = HEADER_LEN + TAG_LEN;
+
+/// Generate a random nonce.
+fn random_nonce() -> [u8; NONCE_LEN] {
+    let mut nonce = [0; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    nonce
+}
+
+/// Parse a key, either 32 raw bytes, or 64 hex digits.
+fn parse_key(content: &[u8]) -> StorageResult<[u8; KEY_LEN]> {
+    let mut key = [0; KEY_LEN];
+    if content.len() == KEY_LEN {
+        key.copy_from_slice(content);
+        return Ok(key);
+    }
+
+    let invalid = || {
+        anyhow!(
+            "a key must be {KEY_LEN} raw bytes or {} hex digits",
+            KEY_LEN * 2
+        )
+    };
+    let hex = std::str::from_utf8(content).map_err(|_| invalid())?.trim();
+    if hex.len() != KEY_LEN * 2 {
+        return Err(invalid().into());
+    }
+    for (i, byte) in key.iter_mut().enumerate() {
+        let digits = hex
+            .get(i.overflow_mul(2)..i.overflow_mul(2).overflow_add(2))
+            .ok_or_else(invalid)?;
+        *byte = u8::from_str_radix(digits, 16).map_err(|_| invalid())?;
+    }
+    Ok(key)
+}
+
+/// A data key wrapped by the master key, which is persisted in the metadata.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WrappedDataKey {
+    /// The id of the data key
+    pub id: u32,
+    /// The nonce used to wrap the data key
+    pub nonce: [u8; NONCE_LEN],
+    /// The wrapped data key
+    pub ciphertext: Vec<u8>,
+}
+
+/// A plain data key.
+#[derive(Clone)]
+pub struct DataKey {
+    /// The id of the key, which is recorded in encrypted objects
+    id: u32,
+    /// The key
+    key: [u8; KEY_LEN],
+}
+
+impl Debug for DataKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DataKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DataKey {
+    /// Generate a random data key with `id`.
+    #[must_use]
+    pub fn generate(id: u32) -> Self {
+        let mut key = [0; KEY_LEN];
+        OsRng.fill_bytes(&mut key);
+        Self { id, key }
+    }
+
+    /// The id of the key.
+    #[must_use]
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+/// The master key, which wraps data keys.
+pub struct MasterKey {
+    /// The cipher of the key
+    cipher: Aes256Gcm,
+}
+
+impl Debug for MasterKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MasterKey").finish_non_exhaustive()
+    }
+}
+
+impl MasterKey {
+    /// Create a master key from 32 raw bytes, or 64 hex digits.
+    pub fn from_bytes(content: &[u8]) -> StorageResult<Self> {
+        let key = parse_key(content)?;
+        Ok(Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+        })
+    }
+
+    /// Read a master key from a file, with 32 raw bytes, or 64 hex digits.
+    pub async fn from_file(path: impl AsRef<Path>) -> StorageResult<Self> {
+        let content = tokio::fs::read(path).await?;
+        Self::from_bytes(&content)
+    }
+
+    /// Wrap a data key, the id of the key is authenticated.
+    pub fn wrap_key(&self, key: &DataKey) -> StorageResult<WrappedDataKey> {
+        let nonce = random_nonce();
+        let payload = Payload {
+            msg: &key.key,
+            aad: &key.id.to_le_bytes(),
+        };
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| anyhow!("failed to wrap data key {}", key.id))?;
+        Ok(WrappedDataKey {
+            id: key.id,
+            nonce,
+            ciphertext,
+        })
+    }
+
+    /// Unwrap a data key, it fails if the key is not wrapped by this master
+    /// key.
+    pub fn unwrap_key(&self, wrapped: &WrappedDataKey) -> StorageResult<DataKey> {
+        let payload = Payload {
+            msg: &wrapped.ciphertext,
+            aad: &wrapped.id.to_le_bytes(),
+        };
+        let plain = self
+            .cipher
+            .decrypt(Nonce::from_slice(&wrapped.nonce), payload)
+            .map_err(|_| anyhow!("failed to unwrap data key {}", wrapped.id))?;
+        if plain.len() != KEY_LEN {
+            return Err(anyhow!("the data key {} is malformed", wrapped.id).into());
+        }
+        let mut key = [0; KEY_LEN];
+        key.copy_from_slice(&plain);
+        Ok(DataKey {
+            id: wrapped.id,
+            key,
+        })
+    }
+}
+
+/// The cipher of objects in the backend, with all data keys of a volume.
+pub struct BlockCipher {
+    /// The ciphers of data keys, by their ids
+    ciphers: HashMap<u32, Aes256Gcm>,
+    /// The id of the newest data key, which encrypts new objects
+    current: u32,
+}
+
+impl Debug for BlockCipher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockCipher")
+            .field("current", &self.current)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BlockCipher {
+    /// Create a cipher with data keys, the one with the largest id is used to
+    /// encrypt.
+    pub fn new(keys: &[DataKey]) -> StorageResult<Self> {
+        let current = keys
+            .iter()
+            .map(DataKey::id)
+            .max()
+            .ok_or_else(|| anyhow!("no data key is provided"))?;
+        let ciphers = keys
+            .iter()
+            .map(|key| {
+                (
+                    key.id,
+                    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key.key)),
+                )
+            })
+            .collect();
+        Ok(Self { ciphers, current })
+    }
+
+    /// Encrypt the content of the object at `path`.
+    pub fn encrypt(&self, path: &str, data: &[u8]) -> StorageResult<Vec<u8>> {
+        let cipher = self
+            .ciphers
+            .get(&self.current)
+            .unwrap_or_else(|| unreachable!("The current key is ensured to exist."));
+        let nonce = random_nonce();
+        let payload = Payload {
+            msg: data,
+            aad: path.as_bytes(),
+        };
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| anyhow!("failed to encrypt {path}"))?;
+
+        let mut encrypted = Vec::with_capacity(HEADER_LEN.overflow_add(ciphertext.len()));
+        encrypted.extend_from_slice(MAGIC);
+        encrypted.extend_from_slice(&self.current.to_le_bytes());
+        encrypted.extend_from_slice(&nonce);
+        encrypted.extend_from_slice(&ciphertext);
+        Ok(encrypted)
+    }
+
+    /// Decrypt the content of the object at `path`.
+    ///
+    /// Objects not encrypted, or modified, or moved from another path, are
+    /// refused with `EIO`.
+    pub fn decrypt(&self, path: &str, data: &[u8]) -> StorageResult<Vec<u8>> {
+        let refused = || StorageError::Decryption {
+            path: path.to_owned(),
+            source: nix::errno::Errno::EIO,
+        };
+
+        if data.len() < OVERHEAD || !data.starts_with(MAGIC) {
+            return Err(refused());
+        }
+        let (header, ciphertext) = data.split_at(HEADER_LEN);
+        let (key_id, nonce) = match *header {
+            [_, _, _, _, k0, k1, k2, k3, ref nonce @ ..] => {
+                (u32::from_le_bytes([k0, k1, k2, k3]), nonce)
+            }
+            _ => unreachable!("The header is ensured to be {HEADER_LEN} bytes."),
+        };
+
+        let cipher = self.ciphers.get(&key_id).ok_or_else(refused)?;
+        let payload = Payload {
+            msg: ciphertext,
+            aad: path.as_bytes(),
+        };
+        cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| refused())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::{BlockCipher, DataKey, MasterKey, OVERHEAD};
+
+    const MASTER_KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    #[test]
+    fn test_wrap_key() {
+        let master_key = MasterKey::from_bytes(MASTER_KEY.as_bytes()).unwrap();
+        let data_key = DataKey::generate(3);
+        let wrapped = master_key.wrap_key(&data_key).unwrap();
+        assert_ne!(wrapped.ciphertext.as_slice(), data_key.key.as_slice());
+
+        let unwrapped = master_key.unwrap_key(&wrapped).unwrap();
+        assert_eq!(unwrapped.id(), 3);
+        assert_eq!(unwrapped.key, data_key.key);
+
+        // Another master key cannot unwrap it.
+        let other = MasterKey::from_bytes(&[7; 32]).unwrap();
+        assert!(other.unwrap_key(&wrapped).is_err());
+
+        assert!(MasterKey::from_bytes(b"too short").is_err());
+    }
+
+    #[test]
+    fn test_encrypt() {
+        let cipher = BlockCipher::new(&[DataKey::generate(0)]).unwrap();
+        let data = b"foo bar ".repeat(16);
+
+        let encrypted = cipher.encrypt("0/0.block", &data).unwrap();
+        assert_eq!(encrypted.len(), data.len() + OVERHEAD);
+        assert!(!encrypted.windows(8).any(|window| window == b"foo bar "));
+        assert_eq!(cipher.decrypt("0/0.block", &encrypted).unwrap(), data);
+
+        // Moved to another path
+        assert!(cipher.decrypt("0/1.block", &encrypted).is_err());
+
+        // Modified
+        let mut modified = encrypted;
+        if let Some(byte) = modified.last_mut() {
+            *byte ^= 1;
+        }
+        assert!(cipher.decrypt("0/0.block", &modified).is_err());
+
+        // Not encrypted
+        assert!(cipher.decrypt("0/0.block", &data).is_err());
+    }
+
+    #[test]
+    fn test_rotation() {
+        let old_key = DataKey::generate(0);
+        let old_cipher = BlockCipher::new(&[old_key.clone()]).unwrap();
+        let encrypted = old_cipher.encrypt("0/0.block", b"foo bar ").unwrap();
+
+        let cipher = BlockCipher::new(&[old_key, DataKey::generate(1)]).unwrap();
+        assert_eq!(
+            cipher.decrypt("0/0.block", &encrypted).unwrap(),
+            b"foo bar "
+        );
+
+        let encrypted = cipher.encrypt("0/0.block", b"foo bar ").unwrap();
+        assert_eq!(encrypted.get(4..8), Some(1_u32.to_le_bytes().as_slice()));
+        assert!(old_cipher.decrypt("0/0.block", &encrypted).is_err());
+    }
+}
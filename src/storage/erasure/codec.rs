@@ -0,0 +1,88 @@
+//! The Reed-Solomon codec of blocks.
+
+use std::fmt::{self, Debug};
+
+use reed_solomon_erasure::galois_8::ReedSolomon;
+
+use super::ErasureShards;
+use crate::storage::error::{StorageError, StorageResult};
+
+/// The codec splitting a block into data shards and computing the parity
+/// shards, and reconstructing the block from any data shards in number.
+pub struct ErasureCodec {
+    /// The shards of a block
+    shards: ErasureShards,
+    /// The Reed-Solomon code
+    code: ReedSolomon,
+}
+
+impl Debug for ErasureCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ErasureCodec")
+            .field("shards", &self.shards)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Convert the error of the Reed-Solomon code into the error of storage.
+fn code_error(e: reed_solomon_erasure::Error) -> StorageError {
+    StorageError::Internal(anyhow::anyhow!("erasure coding failed: {e}"))
+}
+
+impl ErasureCodec {
+    /// Create an `ErasureCodec` of `shards`.
+    pub fn new(shards: ErasureShards) -> StorageResult<Self> {
+        let code = ReedSolomon::new(shards.data, shards.parity).map_err(code_error)?;
+        Ok(Self { shards, code })
+    }
+
+    /// The shards of a block.
+    #[must_use]
+    pub fn shards(&self) -> ErasureShards {
+        self.shards
+    }
+
+    /// Encode `data` into the data shards followed by the parity shards, the
+    /// last data shard is padded with zeros, and every shard has one byte at
+    /// least.
+    pub fn encode(&self, data: &[u8]) -> StorageResult<Vec<Vec<u8>>> {
+        let shard_len = data.len().div_ceil(self.shards.data).max(1);
+        let mut shards: Vec<Vec<u8>> = data
+            .chunks(shard_len)
+            .map(|chunk| {
+                let mut shard = chunk.to_vec();
+                shard.resize(shard_len, 0);
+                shard
+            })
+            .collect();
+        shards.resize(self.shards.total(), vec![0; shard_len]);
+        self.code.encode(&mut shards).map_err(code_error)?;
+        Ok(shards)
+    }
+
+    /// Decode a block of `len` bytes from its `shards` in order, `None` if a
+    /// shard is missing. The missing data shards are reconstructed from the
+    /// other shards, and it fails if fewer than the data shards are given.
+    pub fn decode(&self, mut shards: Vec<Option<Vec<u8>>>, len: usize) -> StorageResult<Vec<u8>> {
+        if shards.iter().take(self.shards.data).any(Option::is_none) {
+            self.code
+                .reconstruct_data(&mut shards)
+                .map_err(code_error)?;
+        }
+        let mut data = Vec::with_capacity(len);
+        for shard in shards.into_iter().take(self.shards.data) {
+            // The data shards are all reconstructed.
+            let shard = shard
+                .ok_or_else(|| code_error(reed_solomon_erasure::Error::TooFewShardsPresent))?;
+            data.extend_from_slice(&shard);
+        }
+        if data.len() < len {
+            return Err(StorageError::Internal(anyhow::anyhow!(
+                "the shards of {} bytes are shorter than the block of {len} bytes",
+                data.len()
+            )));
+        }
+        data.truncate(len);
+        Ok(data)
+    }
+}
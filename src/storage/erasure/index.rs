@@ -0,0 +1,83 @@
+//! The index of block shards.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+
+use super::BlockShards;
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::error::StorageResult;
+
+/// The `ShardIndex` trait, which records the nodes holding the shards of
+/// blocks.
+#[async_trait]
+pub trait ShardIndex {
+    /// Get the shard record of a block.
+    ///
+    /// Returns `None` if the block has no shard recorded.
+    async fn get_shards(&self, ino: INum, block_id: usize) -> StorageResult<Option<BlockShards>>;
+
+    /// Set the shard record of a block, the previous record will be
+    /// overwritten.
+    async fn set_shards(&self, record: BlockShards) -> StorageResult<()>;
+
+    /// Remove the shard records of blocks of a file in `block_ids`.
+    async fn remove_shards(&self, ino: INum, block_ids: Range<usize>) -> StorageResult<()>;
+}
+
+#[async_trait]
+impl<T> ShardIndex for Arc<T>
+where
+    T: ShardIndex + Send + Sync,
+{
+    async fn get_shards(&self, ino: INum, block_id: usize) -> StorageResult<Option<BlockShards>> {
+        self.as_ref().get_shards(ino, block_id).await
+    }
+
+    async fn set_shards(&self, record: BlockShards) -> StorageResult<()> {
+        self.as_ref().set_shards(record).await
+    }
+
+    async fn remove_shards(&self, ino: INum, block_ids: Range<usize>) -> StorageResult<()> {
+        self.as_ref().remove_shards(ino, block_ids).await
+    }
+}
+
+/// A `ShardIndex` in memory, which is not persisted.
+#[derive(Debug, Default)]
+pub struct MemoryShardIndex {
+    /// The shard records
+    shards: Mutex<BTreeMap<(INum, usize), BlockShards>>,
+}
+
+impl MemoryShardIndex {
+    /// Create an empty `MemoryShardIndex`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl ShardIndex for MemoryShardIndex {
+    async fn get_shards(&self, ino: INum, block_id: usize) -> StorageResult<Option<BlockShards>> {
+        Ok(self.shards.lock().get(&(ino, block_id)).cloned())
+    }
+
+    async fn set_shards(&self, record: BlockShards) -> StorageResult<()> {
+        self.shards
+            .lock()
+            .insert((record.ino, record.block_id), record);
+        Ok(())
+    }
+
+    async fn remove_shards(&self, ino: INum, block_ids: Range<usize>) -> StorageResult<()> {
+        self.shards
+            .lock()
+            .retain(|&(key_ino, block_id), _| key_ino != ino || !block_ids.contains(&block_id));
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+//! The erasure coding of blocks among storage nodes.
+//!
+//! Every block of an erasure coded volume is split into `data` shards, and
+//! `parity` shards are computed from them by a Reed-Solomon code, which are
+//! stored by as many replica nodes, placed by the same rendezvous hashing as
+//! the replicas. The block is readable as long as any `data` of its shards
+//! are, so it tolerates `parity` node failures like `parity + 1` replicas,
+//! but takes `(data + parity) / data` times its size instead of `parity + 1`
+//! times.
+//!
+//! The nodes holding the shards of a block are recorded in a [`ShardIndex`],
+//! which is usually the metadata store. A read fetches the data shards and
+//! concatenates them, and if some of them are missing, it's a degraded read,
+//! which fetches the parity shards as well and reconstructs the block.
+//!
+//! Erasure coded blocks are slow to rewrite, so the hot blocks are usually
+//! replicated, and the cold ones are converted to shards by the tiering of
+//! blocks, whose hot tier is the replicas and cold tier is the shards.
+
+mod codec;
+mod index;
+mod store;
+
+pub use codec::ErasureCodec;
+pub use datenlord::config::ErasureShards;
+pub use index::{MemoryShardIndex, ShardIndex};
+use serde::{Deserialize, Serialize};
+pub use store::ErasureBlockStore;
+
+use crate::async_fuse::fuse::protocol::INum;
+
+/// The shard record of a block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockShards {
+    /// The inode number of the file
+    pub ino: INum,
+    /// The index of the block in the file
+    pub block_id: usize,
+    /// The size of the block before it's encoded
+    pub len: usize,
+    /// The nodes holding the shards in order, the data shards go first and
+    /// the parity shards follow, `None` if a shard is failed to write
+    pub nodes: Vec<Option<String>>,
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests;
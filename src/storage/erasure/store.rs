@@ -0,0 +1,232 @@
+//! The block store erasure coding blocks into shards on replica nodes.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug};
+use std::ops::Range;
+
+use async_trait::async_trait;
+use clippy_utilities::{Cast, OverflowArithmetic};
+use futures::future;
+use nix::errno::Errno;
+use tracing::warn;
+
+use super::{BlockShards, ErasureCodec, ErasureShards, ShardIndex};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::error::{StorageError, StorageResult};
+use crate::storage::replication::{place_replicas, ReplicaStore};
+use crate::storage::{BlockKey, BlockStore};
+
+/// A `BlockStore` storing every block as shards on several replica nodes.
+///
+/// Blocks are addressed by their files and indexes only, so it's not used
+/// with the storage layers keeping versions of blocks.
+pub struct ErasureBlockStore<I> {
+    /// The index of shards
+    index: I,
+    /// The replica nodes
+    nodes: BTreeMap<String, ReplicaStore>,
+    /// The codec of blocks
+    codec: ErasureCodec,
+    /// The number of shards persisting a block before a write is
+    /// acknowledged, which is one more than the data shards, so the block
+    /// still survives a node failure.
+    quorum: usize,
+}
+
+impl<I: Debug> Debug for ErasureBlockStore<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ErasureBlockStore")
+            .field("index", &self.index)
+            .field("codec", &self.codec)
+            .field("quorum", &self.quorum)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The key of the shard `index` of a block on its node, whose version is
+/// `index + 1`, so it never overwrites a replica of the block, whose version
+/// is the initial one, during the conversion between replicas and shards.
+fn shard_key(key: BlockKey, index: usize) -> BlockKey {
+    BlockKey::with_version(key.ino, key.block_id, index.overflow_add(1).cast())
+}
+
+impl<I> ErasureBlockStore<I>
+where
+    I: ShardIndex + Send + Sync,
+{
+    /// Create an `ErasureBlockStore` storing the `shards` of every block on
+    /// the `nodes`, there must be a node for every shard.
+    pub fn new(
+        index: I,
+        nodes: BTreeMap<String, ReplicaStore>,
+        shards: ErasureShards,
+    ) -> StorageResult<Self> {
+        if nodes.len() < shards.total() {
+            return Err(StorageError::Internal(anyhow::anyhow!(
+                "{} shards require as many replica nodes, but {} are given",
+                shards.total(),
+                nodes.len()
+            )));
+        }
+        Ok(Self {
+            index,
+            nodes,
+            codec: ErasureCodec::new(shards)?,
+            quorum: shards.data.overflow_add(1).min(shards.total()),
+        })
+    }
+
+    /// Get the store of a replica node.
+    fn node(&self, node_id: &str) -> StorageResult<&ReplicaStore> {
+        self.nodes.get(node_id).ok_or_else(|| {
+            StorageError::Internal(anyhow::anyhow!("replica node {node_id} is unknown"))
+        })
+    }
+
+    /// Read the shards in `range` of a block concurrently from the `nodes`
+    /// holding them, a shard is `None` if it's missing or failed to read.
+    async fn read_shards(
+        &self,
+        key: BlockKey,
+        nodes: &[Option<String>],
+        range: Range<usize>,
+    ) -> Vec<Option<Vec<u8>>> {
+        let reads = range.map(|index| async move {
+            let node = nodes.get(index).and_then(Option::as_ref)?;
+            let result = match self.node(node) {
+                Ok(store) => store.get(shard_key(key, index)).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(Some(shard)) => Some(shard),
+                Ok(None) => {
+                    warn!("Shard {index} of block {key:?} is missing on replica node {node}.");
+                    None
+                }
+                Err(e) => {
+                    warn!("Failed to read shard {index} of block {key:?} from node {node}: {e}");
+                    None
+                }
+            }
+        });
+        future::join_all(reads).await
+    }
+}
+
+#[async_trait]
+impl<I> BlockStore for ErasureBlockStore<I>
+where
+    I: ShardIndex + Send + Sync,
+{
+    async fn get(&self, key: BlockKey) -> StorageResult<Option<Vec<u8>>> {
+        let Some(record) = self.index.get_shards(key.ino, key.block_id).await? else {
+            return Ok(None);
+        };
+        let data_shards = self.codec.shards().data;
+        let mut shards = self.read_shards(key, &record.nodes, 0..data_shards).await;
+        if shards.iter().all(Option::is_some) {
+            shards.resize(record.nodes.len(), None);
+        } else {
+            // A degraded read, the missing data shards are reconstructed from
+            // the parity shards.
+            let parity = self
+                .read_shards(key, &record.nodes, data_shards..record.nodes.len())
+                .await;
+            shards.extend(parity);
+            let available = shards.iter().filter(|shard| shard.is_some()).count();
+            if available < data_shards {
+                return Err(StorageError::ShardsUnavailable {
+                    ino: key.ino,
+                    block_id: key.block_id,
+                    available,
+                    required: data_shards,
+                    source: Errno::EIO,
+                });
+            }
+            warn!("Block {key:?} is reconstructed from {available} shards.");
+        }
+        self.codec.decode(shards, record.len).map(Some)
+    }
+
+    async fn put(&self, key: BlockKey, data: Vec<u8>) -> StorageResult<()> {
+        let shards = self.codec.encode(&data)?;
+        let node_ids: Vec<String> = self.nodes.keys().cloned().collect();
+        let placed = place_replicas(&node_ids, key.ino, key.block_id, shards.len());
+        let writes =
+            placed
+                .iter()
+                .zip(shards)
+                .enumerate()
+                .map(|(index, (node, shard))| async move {
+                    self.node(node)?.put(shard_key(key, index), shard).await
+                });
+        let results = future::join_all(writes).await;
+
+        let mut nodes = Vec::with_capacity(placed.len());
+        for (node, result) in placed.into_iter().zip(results) {
+            match result {
+                Ok(()) => nodes.push(Some(node)),
+                Err(e) => {
+                    warn!("Failed to write a shard of block {key:?} to replica node {node}: {e}");
+                    nodes.push(None);
+                }
+            }
+        }
+        let acked = nodes.iter().filter(|node| node.is_some()).count();
+        // The shards persisting the write hold the latest content, and the
+        // others are never read, even if they're fewer than the quorum.
+        self.index
+            .set_shards(BlockShards {
+                ino: key.ino,
+                block_id: key.block_id,
+                len: data.len(),
+                nodes,
+            })
+            .await?;
+        if acked < self.quorum {
+            return Err(StorageError::WriteQuorum {
+                ino: key.ino,
+                block_id: key.block_id,
+                acked,
+                required: self.quorum,
+                source: Errno::EIO,
+            });
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, key: BlockKey) -> StorageResult<()> {
+        let Some(record) = self.index.get_shards(key.ino, key.block_id).await? else {
+            return Ok(());
+        };
+        self.index
+            .remove_shards(key.ino, key.block_id..key.block_id.saturating_add(1))
+            .await?;
+        // The shards are not reachable once the record is removed, so the
+        // ones failed to delete are left behind.
+        for (index, node) in record.nodes.iter().enumerate() {
+            let Some(node) = node.as_ref() else {
+                continue;
+            };
+            let result = async { self.node(node)?.delete(shard_key(key, index)).await }.await;
+            if let Err(e) = result {
+                warn!("Failed to delete shard {index} of block {key:?} from node {node}: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    async fn delete_file(&self, ino: INum) -> StorageResult<()> {
+        self.index.remove_shards(ino, 0..usize::MAX).await?;
+        let deletes = self
+            .nodes
+            .iter()
+            .map(|(node, store)| async move { (node, store.delete_file(ino).await) });
+        for (node, result) in future::join_all(deletes).await {
+            if let Err(e) = result {
+                warn!("Failed to delete file {ino} from replica node {node}: {e}");
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use clippy_utilities::{Cast, OverflowArithmetic};
+use nix::errno::Errno;
+
+use super::{ErasureBlockStore, ErasureCodec, ErasureShards, MemoryShardIndex, ShardIndex};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::block_store::MemoryBlockStore;
+use crate::storage::error::{StorageError, StorageResult};
+use crate::storage::replication::{
+    MemoryReplicaIndex, ReplicaStore, ReplicatedBlockStore, WriteQuorum,
+};
+use crate::storage::tiering::{MemoryTierIndex, TierPolicy, TieredBlockStore};
+use crate::storage::{BlockKey, BlockStore};
+
+const BLOCK_CONTENT: &[u8] = b"foo bar baz qux quux";
+
+/// 3 data shards and 2 parity shards.
+const SHARDS: ErasureShards = ErasureShards { data: 3, parity: 2 };
+
+/// A replica node which is down.
+#[derive(Debug)]
+struct DownStore;
+
+#[async_trait]
+impl BlockStore for DownStore {
+    async fn get(&self, _: BlockKey) -> StorageResult<Option<Vec<u8>>> {
+        Err(StorageError::Internal(anyhow::anyhow!("the node is down")))
+    }
+
+    async fn put(&self, _: BlockKey, _: Vec<u8>) -> StorageResult<()> {
+        Err(StorageError::Internal(anyhow::anyhow!("the node is down")))
+    }
+
+    async fn delete(&self, _: BlockKey) -> StorageResult<()> {
+        Err(StorageError::Internal(anyhow::anyhow!("the node is down")))
+    }
+
+    async fn delete_file(&self, _: INum) -> StorageResult<()> {
+        Err(StorageError::Internal(anyhow::anyhow!("the node is down")))
+    }
+}
+
+/// Create the stores of 5 nodes in memory.
+fn new_nodes() -> BTreeMap<String, Arc<MemoryBlockStore>> {
+    (0..5)
+        .map(|i| (format!("node{i}"), Arc::new(MemoryBlockStore::new())))
+        .collect()
+}
+
+/// The replica nodes upon the stores of `nodes`, the nodes in `down` are
+/// down.
+fn replicas(
+    nodes: &BTreeMap<String, Arc<MemoryBlockStore>>,
+    down: &[String],
+) -> BTreeMap<String, ReplicaStore> {
+    nodes
+        .iter()
+        .map(|(node, store)| {
+            let replica: ReplicaStore = if down.contains(node) {
+                Arc::new(DownStore)
+            } else {
+                Arc::clone(store) as _
+            };
+            (node.clone(), replica)
+        })
+        .collect()
+}
+
+#[test]
+fn test_codec() {
+    let codec = ErasureCodec::new(SHARDS).unwrap();
+    for len in [0, 1, 5, 1000] {
+        let data: Vec<u8> = (0..len)
+            .map(|i: usize| i.overflow_rem(251).cast())
+            .collect();
+        let shards = codec.encode(&data).unwrap();
+        assert_eq!(shards.len(), 5);
+        let all: Vec<Option<Vec<u8>>> = shards.iter().cloned().map(Some).collect();
+        assert_eq!(codec.decode(all, len).unwrap(), data);
+
+        // Any 2 shards can be lost.
+        for lost in [[0, 1], [1, 4], [2, 3], [3, 4]] {
+            let mut degraded: Vec<Option<Vec<u8>>> = shards.iter().cloned().map(Some).collect();
+            for index in lost {
+                *degraded.get_mut(index).unwrap() = None;
+            }
+            assert_eq!(codec.decode(degraded, len).unwrap(), data);
+        }
+
+        let mut degraded: Vec<Option<Vec<u8>>> = shards.iter().cloned().map(Some).collect();
+        for index in [0, 2, 4] {
+            *degraded.get_mut(index).unwrap() = None;
+        }
+        assert!(codec.decode(degraded, len).is_err());
+    }
+}
+
+#[tokio::test]
+async fn test_erasure_block_store() {
+    let index = Arc::new(MemoryShardIndex::new());
+    let nodes = new_nodes();
+    let store = ErasureBlockStore::new(Arc::clone(&index), replicas(&nodes, &[]), SHARDS).unwrap();
+    let key = BlockKey::new(1, 0);
+
+    store.put(key, BLOCK_CONTENT.to_vec()).await.unwrap();
+    let record = index.get_shards(1, 0).await.unwrap().unwrap();
+    assert_eq!(record.len, BLOCK_CONTENT.len());
+    assert_eq!(record.nodes.len(), 5);
+    // Every node holds a shard, whose version is its index plus 1.
+    for (i, node) in record.nodes.iter().enumerate() {
+        let node_store = nodes.get(node.as_ref().unwrap()).unwrap();
+        assert_eq!(node_store.len(), 1);
+        let shard_key = BlockKey::with_version(1, 0, i.overflow_add(1).cast());
+        assert!(node_store.get(shard_key).await.unwrap().is_some());
+    }
+    assert_eq!(store.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+    assert!(store.get(BlockKey::new(1, 1)).await.unwrap().is_none());
+
+    store.delete(key).await.unwrap();
+    assert!(store.get(key).await.unwrap().is_none());
+    assert!(nodes.values().all(|node_store| node_store.is_empty()));
+
+    store.put(key, BLOCK_CONTENT.to_vec()).await.unwrap();
+    store.delete_file(1).await.unwrap();
+    assert!(store.get(key).await.unwrap().is_none());
+    assert!(nodes.values().all(|node_store| node_store.is_empty()));
+}
+
+#[tokio::test]
+async fn test_degraded_read() {
+    let index = Arc::new(MemoryShardIndex::new());
+    let nodes = new_nodes();
+    let store = ErasureBlockStore::new(Arc::clone(&index), replicas(&nodes, &[]), SHARDS).unwrap();
+    let key = BlockKey::new(1, 0);
+    store.put(key, BLOCK_CONTENT.to_vec()).await.unwrap();
+    let record = index.get_shards(1, 0).await.unwrap().unwrap();
+    let data_nodes: Vec<String> = record.nodes.iter().flatten().take(3).cloned().collect();
+
+    // 2 nodes of data shards are down, the block is reconstructed from the
+    // parity shards.
+    let (down, _) = data_nodes.split_at(2);
+    let degraded =
+        ErasureBlockStore::new(Arc::clone(&index), replicas(&nodes, down), SHARDS).unwrap();
+    assert_eq!(degraded.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+
+    // Only 2 shards are left.
+    let unavailable =
+        ErasureBlockStore::new(Arc::clone(&index), replicas(&nodes, &data_nodes), SHARDS).unwrap();
+    assert!(matches!(
+        unavailable.get(key).await.unwrap_err(),
+        StorageError::ShardsUnavailable {
+            available: 2,
+            required: 3,
+            source: Errno::EIO,
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn test_erasure_write_quorum() {
+    let index = Arc::new(MemoryShardIndex::new());
+    let nodes = new_nodes();
+    let key = BlockKey::new(1, 0);
+
+    // A write is acknowledged with one shard lost.
+    let down = vec!["node0".to_owned()];
+    let store =
+        ErasureBlockStore::new(Arc::clone(&index), replicas(&nodes, &down), SHARDS).unwrap();
+    store.put(key, BLOCK_CONTENT.to_vec()).await.unwrap();
+    let record = index.get_shards(1, 0).await.unwrap().unwrap();
+    assert_eq!(record.nodes.iter().flatten().count(), 4);
+    assert_eq!(store.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+
+    // Fewer shards than the data shards plus one are persisted.
+    let down = vec!["node0".to_owned(), "node1".to_owned()];
+    let store =
+        ErasureBlockStore::new(Arc::clone(&index), replicas(&nodes, &down), SHARDS).unwrap();
+    assert!(matches!(
+        store.put(key, BLOCK_CONTENT.to_vec()).await.unwrap_err(),
+        StorageError::WriteQuorum {
+            acked: 3,
+            required: 4,
+            ..
+        }
+    ));
+    // The shards persisted are still readable.
+    assert_eq!(store.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+
+    // There must be a node for every shard.
+    let mut too_few = replicas(&nodes, &[]);
+    too_few.remove("node0");
+    assert!(ErasureBlockStore::new(Arc::clone(&index), too_few, SHARDS).is_err());
+}
+
+#[tokio::test]
+async fn test_convert_between_replicas_and_shards() {
+    let nodes = new_nodes();
+    let replicated = Arc::new(ReplicatedBlockStore::new(
+        Arc::new(MemoryReplicaIndex::new()),
+        "node0",
+        replicas(&nodes, &[]),
+        3,
+        WriteQuorum::All,
+        Duration::from_secs(60),
+    ));
+    let erasure = Arc::new(
+        ErasureBlockStore::new(
+            Arc::new(MemoryShardIndex::new()),
+            replicas(&nodes, &[]),
+            SHARDS,
+        )
+        .unwrap(),
+    );
+    let store = TieredBlockStore::new(
+        Arc::new(MemoryTierIndex::new()),
+        Arc::clone(&replicated),
+        Arc::clone(&erasure),
+        TierPolicy {
+            cold_after: Duration::ZERO,
+            promote_accesses: 2,
+        },
+    );
+    let key = BlockKey::new(1, 0);
+    let count_blocks = || -> usize { nodes.values().map(|node_store| node_store.len()).sum() };
+
+    // A new block is replicated.
+    store.put(key, BLOCK_CONTENT.to_vec()).await.unwrap();
+    assert_eq!(count_blocks(), 3);
+    assert!(replicated.get(key).await.unwrap().is_some());
+
+    // The cold block is converted to shards, and the replicas are removed.
+    let report = store.migrate().await.unwrap();
+    assert_eq!(report.demoted, 1);
+    assert_eq!(count_blocks(), 5);
+    assert!(replicated.get(key).await.unwrap().is_none());
+    assert_eq!(erasure.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+    assert_eq!(store.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+
+    // The block read frequently is replicated again.
+    store.get(key).await.unwrap();
+    let report = store.migrate().await.unwrap();
+    assert_eq!(report.promoted, 1);
+    assert_eq!(count_blocks(), 3);
+    assert!(erasure.get(key).await.unwrap().is_none());
+    assert_eq!(store.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+}
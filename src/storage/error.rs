@@ -1,7 +1,11 @@
 //! Error types for storage.
 
+use datenlord::config::BackendOpClass;
 use thiserror::Error;
 
+use crate::async_fuse::fuse::protocol::INum;
+use crate::common::error::DatenLordError;
+
 /// The result of storage operation.
 pub type StorageResult<T> = Result<T, StorageError>;
 
@@ -22,7 +26,129 @@ pub enum StorageError {
     /// An error caused by [`opendal::Error`]
     #[error("{0}")]
     OpenDalError(#[from] opendal::Error),
+    /// The checksum of a block read from the backend mismatches the recorded
+    /// one. Its source is `EIO`, which is returned to users.
+    #[error("checksum of block {block_id} of file {ino} mismatches")]
+    ChecksumMismatch {
+        /// The inode number of the file
+        ino: INum,
+        /// The index of the block
+        block_id: usize,
+        /// Always `EIO`
+        source: nix::errno::Errno,
+    },
+    /// An object read from the backend cannot be decrypted, it's not
+    /// encrypted, or is modified. Its source is `EIO`, which is returned to
+    /// users.
+    #[error("failed to decrypt {path}")]
+    Decryption {
+        /// The path of the object
+        path: String,
+        /// Always `EIO`
+        source: nix::errno::Errno,
+    },
+    /// A block is written to a read-only storage, such as a mounted snapshot.
+    /// Its source is `EROFS`, which is returned to users.
+    #[error("block {block_id} of file {ino} is written to a read-only storage")]
+    ReadOnly {
+        /// The inode number of the file
+        ino: INum,
+        /// The index of the block
+        block_id: usize,
+        /// Always `EROFS`
+        source: nix::errno::Errno,
+    },
+    /// A transfer of blocks between nodes fails. Its source is `ETIMEDOUT` if
+    /// the deadline is exceeded, or `EIO` otherwise, which is returned to
+    /// users.
+    #[error("transfer of blocks failed: {message}")]
+    Transfer {
+        /// The message of the failure
+        message: String,
+        /// `ETIMEDOUT` or `EIO`
+        source: nix::errno::Errno,
+    },
+    /// A block is persisted by fewer replicas, or erasure coded shards, than
+    /// the write quorum. Its source is `EIO`, which is returned to users.
+    #[error(
+        "block {block_id} of file {ino} is persisted by {acked} nodes, {required} are required"
+    )]
+    WriteQuorum {
+        /// The inode number of the file
+        ino: INum,
+        /// The index of the block
+        block_id: usize,
+        /// The number of replicas persisting the block
+        acked: usize,
+        /// The write quorum
+        required: usize,
+        /// Always `EIO`
+        source: nix::errno::Errno,
+    },
+    /// No replica of a block is on a node alive. Its source is `EIO`, which
+    /// is returned to users.
+    #[error("no replica of block {block_id} of file {ino} is on a node alive")]
+    ReplicasUnavailable {
+        /// The inode number of the file
+        ino: INum,
+        /// The index of the block
+        block_id: usize,
+        /// Always `EIO`
+        source: nix::errno::Errno,
+    },
+    /// Fewer shards of an erasure coded block are available than its data
+    /// shards, so it cannot be reconstructed. Its source is `EIO`, which is
+    /// returned to users.
+    #[error(
+        "{available} shards of block {block_id} of file {ino} are available, {required} needed"
+    )]
+    ShardsUnavailable {
+        /// The inode number of the file
+        ino: INum,
+        /// The index of the block
+        block_id: usize,
+        /// The number of shards available
+        available: usize,
+        /// The number of data shards
+        required: usize,
+        /// Always `EIO`
+        source: nix::errno::Errno,
+    },
+    /// An operation on a store is refused as its circuit breaker is open,
+    /// after the store fails consecutively. Its source is `EIO`, which is
+    /// returned to users.
+    #[error("circuit breaker of {target} is open")]
+    CircuitOpen {
+        /// The store refusing the operation, such as a replica node
+        target: String,
+        /// Always `EIO`
+        source: nix::errno::Errno,
+    },
+    /// An operation on the backend fails, after it's retried if the failure
+    /// is transient. Its source is the errno the failure is mapped to, such
+    /// as `EDQUOT` if the quota of the bucket is exceeded, `EACCES` if the
+    /// credentials are refused, or `EIO` once the retries are exhausted,
+    /// which is returned to users.
+    #[error("backend {class} failed after {retries} retries: {message}")]
+    Backend {
+        /// The class of the operation
+        class: BackendOpClass,
+        /// The times the operation is retried
+        retries: u32,
+        /// The message of the last failure
+        message: String,
+        /// The errno the failure is mapped to
+        source: nix::errno::Errno,
+    },
     /// A internal storage error.
     #[error("{0}")]
     Internal(#[from] anyhow::Error),
 }
+
+/// The errors of the metadata, such as the failures of the kv engine, are
+/// internal errors of storage.
+impl From<DatenLordError> for StorageError {
+    fn from(e: DatenLordError) -> Self {
+        Self::Internal(e.into())
+    }
+}
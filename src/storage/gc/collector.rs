@@ -0,0 +1,163 @@
+//! The garbage collector of orphaned objects.
+
+use std::collections::HashSet;
+use std::mem;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clippy_utilities::OverflowArithmetic;
+use datenlord::metrics::STORAGE_METRICS;
+use parking_lot::Mutex;
+use tokio::select;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use super::{GcIndex, ObjectKey, ObjectStore};
+use crate::storage::error::StorageResult;
+
+/// The result of a GC pass.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GcReport {
+    /// The number of objects listed
+    pub scanned: usize,
+    /// The number of objects found orphaned by two consecutive passes
+    pub orphaned: usize,
+    /// The number of objects deleted, it's always 0 in a dry run
+    pub deleted: usize,
+    /// The total size of the orphaned objects in bytes, they are reclaimed
+    /// unless it's a dry run
+    pub reclaimed: u64,
+}
+
+/// A garbage collector deleting the objects in the backend which are
+/// referenced by neither any file nor any snapshot.
+#[derive(Debug)]
+pub struct GarbageCollector<I, S> {
+    /// The index of references
+    index: I,
+    /// The objects in the backend
+    store: S,
+    /// The minimal interval between deletes, `None` if deletes are not rate
+    /// limited
+    delete_interval: Option<Duration>,
+    /// Whether the orphaned objects are only reported instead of deleted
+    dry_run: bool,
+    /// The objects found orphaned by the last pass
+    candidates: Mutex<HashSet<ObjectKey>>,
+}
+
+impl<I, S> GarbageCollector<I, S>
+where
+    I: GcIndex + Send + Sync,
+    S: ObjectStore + Send + Sync,
+{
+    /// Create a `GarbageCollector` of the objects in `store`.
+    pub fn new(index: I, store: S) -> Self {
+        Self {
+            index,
+            store,
+            delete_interval: None,
+            dry_run: false,
+            candidates: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Limit the deletes to `rate` objects per second, deletes are not rate
+    /// limited if it's 0.
+    #[must_use]
+    pub fn with_rate_limit(mut self, rate: u32) -> Self {
+        self.delete_interval = Duration::from_secs(1)
+            .checked_div(rate)
+            .filter(|interval| !interval.is_zero());
+        self
+    }
+
+    /// Only report the orphaned objects, without deleting them.
+    #[must_use]
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Run a GC pass, the objects found orphaned by this pass and the last one
+    /// are deleted.
+    pub async fn collect(&self) -> StorageResult<GcReport> {
+        let objects = self.store.list_objects().await?;
+        // The candidates are reset if the pass fails, so the objects are
+        // checked by two more passes.
+        let previous = mem::take(&mut *self.candidates.lock());
+        let mut candidates = HashSet::new();
+        let mut report = GcReport::default();
+        let mut ticker = self.delete_interval.map(|interval| {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            ticker
+        });
+
+        for object in objects {
+            report.scanned = report.scanned.overflow_add(1);
+            if self.index.is_referenced(object.key).await? {
+                continue;
+            }
+            if !previous.contains(&object.key) {
+                candidates.insert(object.key);
+                continue;
+            }
+            if self.dry_run {
+                // Keep it as a candidate, to report it again by the next pass.
+                candidates.insert(object.key);
+                info!("GC found {:?} orphaned, {} bytes.", object.key, object.size);
+                report.orphaned = report.orphaned.overflow_add(1);
+                report.reclaimed = report.reclaimed.overflow_add(object.size);
+                continue;
+            }
+
+            if let Some(ref mut ticker) = ticker {
+                ticker.tick().await;
+            }
+            // The object may be referenced during the pass, check with the
+            // latest references.
+            if self.index.is_referenced(object.key).await? {
+                continue;
+            }
+            self.store.delete_object(object.key).await?;
+            STORAGE_METRICS.gc_reclaimed_bytes_inc_by(object.size);
+            report.orphaned = report.orphaned.overflow_add(1);
+            report.deleted = report.deleted.overflow_add(1);
+            report.reclaimed = report.reclaimed.overflow_add(object.size);
+        }
+
+        *self.candidates.lock() = candidates;
+        Ok(report)
+    }
+
+    /// Run GC passes every `interval`, until `token` is cancelled.
+    #[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+    pub async fn run_collector(self: Arc<Self>, interval: Duration, token: CancellationToken) {
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick completes immediately, skip it to collect after an
+        // interval.
+        ticker.tick().await;
+
+        loop {
+            select! {
+                _ = ticker.tick() => {
+                    match self.collect().await {
+                        Ok(report) => info!(
+                            "GC pass finished{}, {} objects scanned, {} orphaned, {} deleted, \
+                             {} bytes reclaimed.",
+                            if self.dry_run { " (dry run)" } else { "" },
+                            report.scanned, report.orphaned, report.deleted, report.reclaimed
+                        ),
+                        Err(e) => error!("GC pass failed: {e}"),
+                    }
+                }
+                () = token.cancelled() => {
+                    info!("Garbage collector exits.");
+                    return;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! The garbage collection of orphaned objects in the backend.
+//!
+//! Objects may be left in the backend without any reference, when a write
+//! fails after the block is stored, a node crashes before the blocks of a
+//! removed file are deleted, or a truncate races with a write-back. They are
+//! never read again, but take backend space forever.
+//!
+//! The [`GarbageCollector`] lists the objects in an [`ObjectStore`]
+//! periodically, and checks each of them against a [`GcIndex`], which is
+//! usually the metadata store:
+//!
+//! - A block is referenced if it's in the chunk index of an existing file, or
+//!   if it's a version of the block recorded for the live file system or a
+//!   snapshot.
+//! - A chunk is referenced if it's reference counted by any recipe.
+//!
+//! An object is only deleted when it's found orphaned by two consecutive
+//! passes, and still orphaned right before it's deleted, so the objects being
+//! written while the references are recorded are never deleted.
+
+mod collector;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+pub use collector::{GarbageCollector, GcReport};
+
+use super::dedup::ChunkHash;
+use super::error::StorageResult;
+use super::BlockKey;
+
+/// The key of an object in the backend.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ObjectKey {
+    /// A (versioned) block of a file
+    Block(BlockKey),
+    /// A chunk of the deduplicated blocks
+    Chunk(ChunkHash),
+}
+
+/// An object listed from the backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoredObject {
+    /// The key of the object
+    pub key: ObjectKey,
+    /// The size of the object in bytes
+    pub size: u64,
+}
+
+/// The `ObjectStore` trait, which lists and deletes the objects in the
+/// backend.
+#[async_trait]
+pub trait ObjectStore {
+    /// List all objects, the objects not written by the storage are skipped.
+    async fn list_objects(&self) -> StorageResult<Vec<StoredObject>>;
+
+    /// Delete an object. It's not an error to delete a non-existing object.
+    async fn delete_object(&self, key: ObjectKey) -> StorageResult<()>;
+}
+
+#[async_trait]
+impl<T> ObjectStore for Arc<T>
+where
+    T: ObjectStore + Send + Sync,
+{
+    async fn list_objects(&self) -> StorageResult<Vec<StoredObject>> {
+        self.as_ref().list_objects().await
+    }
+
+    async fn delete_object(&self, key: ObjectKey) -> StorageResult<()> {
+        self.as_ref().delete_object(key).await
+    }
+}
+
+/// The `GcIndex` trait, which tells whether an object is referenced.
+#[async_trait]
+pub trait GcIndex {
+    /// Returns whether the object is referenced by any file or snapshot.
+    async fn is_referenced(&self, key: ObjectKey) -> StorageResult<bool>;
+}
+
+#[async_trait]
+impl<T> GcIndex for Arc<T>
+where
+    T: GcIndex + Send + Sync,
+{
+    async fn is_referenced(&self, key: ObjectKey) -> StorageResult<bool> {
+        self.as_ref().is_referenced(key).await
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests;
@@ -0,0 +1,154 @@
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+
+use super::{GarbageCollector, GcIndex, GcReport, ObjectKey, ObjectStore, StoredObject};
+use crate::storage::dedup::ChunkHash;
+use crate::storage::error::StorageResult;
+use crate::storage::BlockKey;
+
+/// An `ObjectStore` in memory.
+#[derive(Debug, Default)]
+struct MemoryObjectStore {
+    /// The sizes of objects
+    objects: Mutex<BTreeMap<ObjectKey, u64>>,
+}
+
+impl MemoryObjectStore {
+    fn put(&self, key: ObjectKey, size: u64) {
+        self.objects.lock().insert(key, size);
+    }
+
+    fn contains(&self, key: ObjectKey) -> bool {
+        self.objects.lock().contains_key(&key)
+    }
+}
+
+#[async_trait]
+impl ObjectStore for MemoryObjectStore {
+    async fn list_objects(&self) -> StorageResult<Vec<StoredObject>> {
+        Ok(self
+            .objects
+            .lock()
+            .iter()
+            .map(|(&key, &size)| StoredObject { key, size })
+            .collect())
+    }
+
+    async fn delete_object(&self, key: ObjectKey) -> StorageResult<()> {
+        self.objects.lock().remove(&key);
+        Ok(())
+    }
+}
+
+/// A `GcIndex` in memory.
+#[derive(Debug, Default)]
+struct MemoryGcIndex {
+    /// The referenced objects
+    referenced: Mutex<HashSet<ObjectKey>>,
+}
+
+impl MemoryGcIndex {
+    fn reference(&self, key: ObjectKey) {
+        self.referenced.lock().insert(key);
+    }
+
+    fn unreference(&self, key: ObjectKey) {
+        self.referenced.lock().remove(&key);
+    }
+}
+
+#[async_trait]
+impl GcIndex for MemoryGcIndex {
+    async fn is_referenced(&self, key: ObjectKey) -> StorageResult<bool> {
+        Ok(self.referenced.lock().contains(&key))
+    }
+}
+
+type GarbageCollectorType = GarbageCollector<Arc<MemoryGcIndex>, Arc<MemoryObjectStore>>;
+
+fn prepare_collector(
+    dry_run: bool,
+) -> (
+    Arc<MemoryGcIndex>,
+    Arc<MemoryObjectStore>,
+    GarbageCollectorType,
+) {
+    let index = Arc::new(MemoryGcIndex::default());
+    let store = Arc::new(MemoryObjectStore::default());
+    let collector = GarbageCollector::new(Arc::clone(&index), Arc::clone(&store))
+        .with_rate_limit(1000)
+        .with_dry_run(dry_run);
+    (index, store, collector)
+}
+
+#[tokio::test]
+async fn test_collect_orphans() {
+    let (index, store, collector) = prepare_collector(false);
+    let live = ObjectKey::Block(BlockKey::new(2, 0));
+    let orphan = ObjectKey::Block(BlockKey::new(2, 1));
+    let chunk = ObjectKey::Chunk(ChunkHash::of(b"foo"));
+    store.put(live, 4096);
+    store.put(orphan, 1024);
+    store.put(chunk, 256);
+    index.reference(live);
+    index.reference(chunk);
+
+    // An orphan is only a candidate in the first pass it's found.
+    let report = collector.collect().await.unwrap();
+    assert_eq!(
+        report,
+        GcReport {
+            scanned: 3,
+            ..GcReport::default()
+        }
+    );
+    assert!(store.contains(orphan));
+
+    // The chunk is unreferenced after the first pass, it's kept by the second
+    // pass.
+    index.unreference(chunk);
+    let report = collector.collect().await.unwrap();
+    assert_eq!(
+        report,
+        GcReport {
+            scanned: 3,
+            orphaned: 1,
+            deleted: 1,
+            reclaimed: 1024,
+        }
+    );
+    assert!(!store.contains(orphan));
+    assert!(store.contains(live));
+    assert!(store.contains(chunk));
+
+    // A candidate referenced again is not deleted.
+    index.reference(chunk);
+    let report = collector.collect().await.unwrap();
+    assert_eq!(report.deleted, 0);
+    assert!(store.contains(chunk));
+}
+
+#[tokio::test]
+async fn test_dry_run() {
+    let (_, store, collector) = prepare_collector(true);
+    let orphan = ObjectKey::Block(BlockKey::new(2, 0));
+    store.put(orphan, 1024);
+
+    collector.collect().await.unwrap();
+    for _ in 0..2 {
+        let report = collector.collect().await.unwrap();
+        assert_eq!(
+            report,
+            GcReport {
+                scanned: 1,
+                orphaned: 1,
+                deleted: 0,
+                reclaimed: 1024,
+            }
+        );
+        assert!(store.contains(orphan));
+    }
+}
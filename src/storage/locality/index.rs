@@ -0,0 +1,109 @@
+//! The index of block holders.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+
+use super::{BlockHolders, Peer};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::error::StorageResult;
+
+/// The `LocalityIndex` trait, which records the nodes holding blocks in their
+/// caches, and the nodes serving their caches.
+#[async_trait]
+pub trait LocalityIndex {
+    /// Get the ids of the nodes holding a block, the latest one first.
+    async fn get_holders(&self, ino: INum, block_id: usize) -> StorageResult<Vec<String>>;
+
+    /// Record `node_id` as the latest holder of a block.
+    async fn add_holder(&self, ino: INum, block_id: usize, node_id: &str) -> StorageResult<()>;
+
+    /// Forget the holders of blocks of a file in `block_ids`.
+    async fn remove_holders(&self, ino: INum, block_ids: Range<usize>) -> StorageResult<()>;
+
+    /// List the nodes serving their caches.
+    async fn list_peers(&self) -> StorageResult<Vec<Peer>>;
+}
+
+#[async_trait]
+impl<T> LocalityIndex for Arc<T>
+where
+    T: LocalityIndex + Send + Sync,
+{
+    async fn get_holders(&self, ino: INum, block_id: usize) -> StorageResult<Vec<String>> {
+        self.as_ref().get_holders(ino, block_id).await
+    }
+
+    async fn add_holder(&self, ino: INum, block_id: usize, node_id: &str) -> StorageResult<()> {
+        self.as_ref().add_holder(ino, block_id, node_id).await
+    }
+
+    async fn remove_holders(&self, ino: INum, block_ids: Range<usize>) -> StorageResult<()> {
+        self.as_ref().remove_holders(ino, block_ids).await
+    }
+
+    async fn list_peers(&self) -> StorageResult<Vec<Peer>> {
+        self.as_ref().list_peers().await
+    }
+}
+
+/// A `LocalityIndex` in memory, which is not persisted.
+#[derive(Debug, Default)]
+pub struct MemoryLocalityIndex {
+    /// The holder records
+    holders: Mutex<BTreeMap<(INum, usize), BlockHolders>>,
+    /// The nodes serving their caches
+    peers: Mutex<Vec<Peer>>,
+}
+
+impl MemoryLocalityIndex {
+    /// Create an empty `MemoryLocalityIndex`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a node serving its caches.
+    pub fn add_peer(&self, peer: Peer) {
+        self.peers.lock().push(peer);
+    }
+}
+
+#[async_trait]
+impl LocalityIndex for MemoryLocalityIndex {
+    async fn get_holders(&self, ino: INum, block_id: usize) -> StorageResult<Vec<String>> {
+        Ok(self
+            .holders
+            .lock()
+            .get(&(ino, block_id))
+            .map(|record| record.nodes.clone())
+            .unwrap_or_default())
+    }
+
+    async fn add_holder(&self, ino: INum, block_id: usize, node_id: &str) -> StorageResult<()> {
+        self.holders
+            .lock()
+            .entry((ino, block_id))
+            .or_insert_with(|| BlockHolders {
+                ino,
+                block_id,
+                nodes: vec![],
+            })
+            .add(node_id);
+        Ok(())
+    }
+
+    async fn remove_holders(&self, ino: INum, block_ids: Range<usize>) -> StorageResult<()> {
+        self.holders
+            .lock()
+            .retain(|&(key_ino, block_id), _| key_ino != ino || !block_ids.contains(&block_id));
+        Ok(())
+    }
+
+    async fn list_peers(&self) -> StorageResult<Vec<Peer>> {
+        Ok(self.peers.lock().clone())
+    }
+}
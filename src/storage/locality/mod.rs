@@ -0,0 +1,119 @@
+//! The reads of blocks from the caches of the nearby nodes.
+//!
+//! Every node records itself in a [`LocalityIndex`], which is usually the
+//! metadata store, as a holder of every block it loads into its caches, so a
+//! hot block is known to be cached by a few nodes. A node missing a block in
+//! its own caches fetches it from the cache of a holder in its zone, such as
+//! its rack, by the transfer service (see [`LocalCache`]), which is much
+//! faster than the object store, and only falls back to the object store if
+//! none of them has it.
+//!
+//! The holders are hints, a block evicted from a cache stays recorded, and is
+//! simply missed on the node. The holders of a block are forgotten once it's
+//! written, truncated or removed, so the stale content of the other caches is
+//! not read. Only clean blocks are served, which are the ones written to the
+//! backend.
+
+mod index;
+mod storage;
+
+use std::fmt::{self, Debug};
+use std::sync::{Arc, Weak};
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+pub use index::{LocalityIndex, MemoryLocalityIndex};
+pub use storage::PeerCacheStorage;
+
+use super::error::StorageResult;
+use super::{Block, Storage};
+use crate::async_fuse::fuse::protocol::INum;
+
+/// The max number of holders recorded of a block.
+pub const MAX_HOLDERS: usize = 3;
+
+/// The holder record of a block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHolders {
+    /// The inode number of the file
+    pub ino: INum,
+    /// The index of the block in the file
+    pub block_id: usize,
+    /// The ids of the nodes holding the block in their caches, the latest one
+    /// first
+    pub nodes: Vec<String>,
+}
+
+impl BlockHolders {
+    /// Record `node_id` as the latest holder, only the latest `MAX_HOLDERS`
+    /// are kept. Returns `false` if it's the latest one already.
+    pub fn add(&mut self, node_id: &str) -> bool {
+        if self.nodes.first().is_some_and(|first| first == node_id) {
+            return false;
+        }
+        self.nodes.retain(|node| node != node_id);
+        self.nodes.insert(0, node_id.to_owned());
+        self.nodes.truncate(MAX_HOLDERS);
+        true
+    }
+}
+
+/// A node serving the blocks in its caches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    /// The id of the node
+    pub node_id: String,
+    /// The endpoint of the transfer service of the node, such as
+    /// `http://10.0.0.2:8900`
+    pub endpoint: String,
+    /// The zone of the node, such as its rack, `None` if it's not set
+    pub zone: Option<String>,
+}
+
+/// The caches of this node served by the transfer service.
+///
+/// The transfer service starts before the storage is built, so the caches
+/// are registered later, and none is served until then.
+#[derive(Default)]
+pub struct LocalCache {
+    /// The storage containing the caches
+    storage: RwLock<Option<Weak<dyn Storage + Send + Sync>>>,
+}
+
+impl Debug for LocalCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalCache")
+            .field("registered", &self.storage.read().is_some())
+            .finish()
+    }
+}
+
+impl LocalCache {
+    /// Create a `LocalCache` without any cache registered.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve the caches of `storage`, which replaces the one registered
+    /// before.
+    pub fn register<S: Storage + Send + Sync + 'static>(&self, storage: &Arc<S>) {
+        let storage: Arc<dyn Storage + Send + Sync> = Arc::clone(storage) as _;
+        *self.storage.write() = Some(Arc::downgrade(&storage));
+    }
+
+    /// Load a clean block from the caches registered. Returns `None` if it's
+    /// not cached, or no cache is registered.
+    pub async fn load(&self, ino: INum, block_id: usize) -> StorageResult<Option<Block>> {
+        let storage = self.storage.read().as_ref().and_then(Weak::upgrade);
+        match storage {
+            Some(storage) => storage.load_cached(ino, block_id).await,
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests;
@@ -0,0 +1,303 @@
+//! The storage layer reading blocks from the caches of the nearby nodes.
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::ops::Range;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use clippy_utilities::OverflowArithmetic;
+use datenlord::metrics::STORAGE_METRICS;
+use parking_lot::Mutex;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+use super::LocalityIndex;
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::error::StorageResult;
+use crate::storage::transfer::RemoteBlockStore;
+use crate::storage::{Block, Storage};
+use crate::tls::Tls;
+
+/// The interval between the refreshes of the nodes nearby.
+const PEER_REFRESH_INTERVAL: Duration = Duration::from_secs(3);
+
+/// The nodes nearby, listed at `refreshed`.
+#[derive(Debug, Default)]
+struct Peers {
+    /// The time the nodes are listed, `None` if they're never listed
+    refreshed: Option<Instant>,
+    /// The node id -> the endpoint of the node and the store of it
+    stores: HashMap<String, (String, RemoteBlockStore)>,
+}
+
+impl Peers {
+    /// The node id -> the store of the node.
+    fn stores(&self) -> HashMap<String, RemoteBlockStore> {
+        self.stores
+            .iter()
+            .map(|(node_id, peer)| (node_id.clone(), peer.1.clone()))
+            .collect()
+    }
+}
+
+/// A `Storage` which fetches the blocks missed in the caches above from the
+/// caches of the nodes nearby, before loading them from the inner storage.
+///
+/// It's placed right below the caches, the blocks fetched from the other nodes
+/// were verified when they were loaded into their caches. The node is
+/// recorded as a holder of every block it loads, and the holders of a block
+/// are forgotten after it's written to the inner storage.
+pub struct PeerCacheStorage<I, S> {
+    /// The index of holders
+    index: I,
+    /// The inner storage
+    inner: S,
+    /// The id of this node
+    node_id: String,
+    /// The zone of this node, only the nodes in the same zone are nearby
+    zone: Option<String>,
+    /// The size of blocks
+    block_size: usize,
+    /// The TLS to connect the other nodes, `None` if the transfers are in
+    /// plaintext
+    tls: Option<Tls>,
+    /// The timeout of a fetch from another node
+    timeout: Duration,
+    /// The nodes nearby
+    peers: Mutex<Peers>,
+}
+
+impl<I: Debug, S: Debug> Debug for PeerCacheStorage<I, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PeerCacheStorage")
+            .field("index", &self.index)
+            .field("inner", &self.inner)
+            .field("node_id", &self.node_id)
+            .field("zone", &self.zone)
+            .field("block_size", &self.block_size)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I, S> PeerCacheStorage<I, S>
+where
+    I: LocalityIndex + Send + Sync,
+    S: Storage + Send + Sync,
+{
+    /// Create a `PeerCacheStorage` of node `node_id` in `zone` upon `inner`,
+    /// a fetch from another node over `tls` times out after `timeout`.
+    pub fn new(
+        index: I,
+        inner: S,
+        node_id: &str,
+        zone: Option<String>,
+        block_size: usize,
+        tls: Option<Tls>,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            index,
+            inner,
+            node_id: node_id.to_owned(),
+            zone,
+            block_size,
+            tls,
+            timeout,
+            peers: Mutex::new(Peers::default()),
+        }
+    }
+
+    /// The stores of the nodes nearby, which are listed again once they're
+    /// listed for `PEER_REFRESH_INTERVAL`. The ones listed before are kept if
+    /// they fail to be listed.
+    async fn nearby_peers(&self) -> HashMap<String, RemoteBlockStore> {
+        {
+            let peers = self.peers.lock();
+            if peers
+                .refreshed
+                .is_some_and(|refreshed| refreshed.elapsed() < PEER_REFRESH_INTERVAL)
+            {
+                return peers.stores();
+            }
+        }
+
+        let listed = self.index.list_peers().await;
+        let mut peers = self.peers.lock();
+        peers.refreshed = Some(Instant::now());
+        let listed = match listed {
+            Ok(listed) => listed,
+            Err(e) => {
+                warn!("Failed to list the nodes serving their caches: {e}");
+                return peers.stores();
+            }
+        };
+        let mut stores = HashMap::new();
+        for peer in listed {
+            if peer.node_id == self.node_id || peer.zone != self.zone {
+                continue;
+            }
+            // The connections to the nodes kept are reused.
+            let store = match peers.stores.remove(&peer.node_id) {
+                Some((endpoint, store)) if endpoint == peer.endpoint => store,
+                Some(_) | None => {
+                    match RemoteBlockStore::connect(&peer.endpoint, self.tls.clone(), self.timeout)
+                    {
+                        Ok(store) => store,
+                        Err(e) => {
+                            warn!("Node {} is not reachable: {e}", peer.node_id);
+                            continue;
+                        }
+                    }
+                }
+            };
+            stores.insert(peer.node_id, (peer.endpoint, store));
+        }
+        peers.stores = stores;
+        peers.stores()
+    }
+
+    /// Fetch a block from the cache of a holder nearby, the latest holders
+    /// first. Returns `None` if none of them has it.
+    async fn load_from_peers(&self, ino: INum, block_id: usize) -> Option<Block> {
+        let holders = match self.index.get_holders(ino, block_id).await {
+            Ok(holders) => holders,
+            Err(e) => {
+                warn!("Failed to get the holders of block {block_id} of file {ino}: {e}");
+                return None;
+            }
+        };
+        if holders.is_empty() {
+            return None;
+        }
+
+        let peers = self.nearby_peers().await;
+        for holder in holders {
+            let Some(store) = peers.get(&holder) else {
+                continue;
+            };
+            match store.get_cached(ino, block_id).await {
+                Ok(Some(data)) => {
+                    STORAGE_METRICS.peer_cache_read_count_inc("hit");
+                    debug!("Block {block_id} of file {ino} is fetched from node {holder}.");
+                    return Some(Block::from_slice(self.block_size, &data));
+                }
+                Ok(None) => STORAGE_METRICS.peer_cache_read_count_inc("miss"),
+                Err(e) => {
+                    STORAGE_METRICS.peer_cache_read_count_inc("error");
+                    warn!("Failed to fetch block {block_id} of file {ino} from node {holder}: {e}");
+                }
+            }
+        }
+        None
+    }
+
+    /// Record this node as the latest holder of a block.
+    async fn record_holder(&self, ino: INum, block_id: usize) {
+        if let Err(e) = self.index.add_holder(ino, block_id, &self.node_id).await {
+            warn!("Failed to record the holder of block {block_id} of file {ino}: {e}");
+        }
+    }
+}
+
+#[async_trait]
+impl<I, S> Storage for PeerCacheStorage<I, S>
+where
+    I: LocalityIndex + Send + Sync,
+    S: Storage + Send + Sync,
+{
+    async fn load_from_self(&self, ino: INum, block_id: usize) -> StorageResult<Option<Block>> {
+        let block = match self.load_from_peers(ino, block_id).await {
+            Some(block) => Some(block),
+            None => self.inner.load(ino, block_id).await?,
+        };
+        // The block is cached by the caches above once it's returned.
+        if block.is_some() {
+            self.record_holder(ino, block_id).await;
+        }
+        Ok(block)
+    }
+
+    async fn load_from_backend(&self, _: INum, _: usize) -> StorageResult<Option<Block>> {
+        // The inner storage is loaded in `load_from_self`.
+        Ok(None)
+    }
+
+    async fn cache_block_from_backend(&self, _: INum, _: usize, _: Block) -> StorageResult<()> {
+        unreachable!("This storage has no backend, and has no cache.");
+    }
+
+    async fn store(&self, ino: INum, block_id: usize, block: Block) -> StorageResult<()> {
+        self.inner.store(ino, block_id, block).await?;
+        // The holders are forgotten after the block is written, so a holder
+        // recorded meanwhile holds the new content.
+        self.index
+            .remove_holders(ino, block_id..block_id.overflow_add(1))
+            .await
+    }
+
+    async fn remove(&self, ino: INum) -> StorageResult<()> {
+        self.inner.remove(ino).await?;
+        self.index.remove_holders(ino, 0..usize::MAX).await
+    }
+
+    async fn invalidate(&self, ino: INum) -> StorageResult<()> {
+        self.inner.invalidate(ino).await
+    }
+
+    async fn flush(&self, ino: INum) -> StorageResult<()> {
+        self.inner.flush(ino).await
+    }
+
+    async fn flush_all(&self) -> StorageResult<()> {
+        self.inner.flush_all().await
+    }
+
+    async fn truncate(
+        &self,
+        ino: INum,
+        from_block: usize,
+        to_block: usize,
+        fill_start: usize,
+    ) -> StorageResult<()> {
+        self.inner
+            .truncate(ino, from_block, to_block, fill_start)
+            .await?;
+        // The last block left is filled with zeros in its end.
+        let block_ids = to_block.saturating_sub(1)..from_block;
+        if block_ids.is_empty() {
+            return Ok(());
+        }
+        self.index.remove_holders(ino, block_ids).await
+    }
+
+    async fn clone_blocks(
+        &self,
+        src_ino: INum,
+        src_block: usize,
+        dst_ino: INum,
+        dst_block: usize,
+        count: usize,
+    ) -> StorageResult<bool> {
+        if !self
+            .inner
+            .clone_blocks(src_ino, src_block, dst_ino, dst_block, count)
+            .await?
+        {
+            return Ok(false);
+        }
+        self.index
+            .remove_holders(dst_ino, dst_block..dst_block.overflow_add(count))
+            .await?;
+        Ok(true)
+    }
+
+    async fn punch_blocks(&self, ino: INum, blocks: Range<usize>) -> StorageResult<bool> {
+        if !self.inner.punch_blocks(ino, blocks.clone()).await? {
+            return Ok(false);
+        }
+        self.index.remove_holders(ino, blocks).await?;
+        Ok(true)
+    }
+}
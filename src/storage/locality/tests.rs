@@ -0,0 +1,131 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    BlockHolders, LocalCache, LocalityIndex, MemoryLocalityIndex, Peer, PeerCacheStorage,
+    MAX_HOLDERS,
+};
+use crate::storage::block_store::MemoryBlockStore;
+use crate::storage::policy::LruPolicy;
+use crate::storage::transfer::{serve, TransferService};
+use crate::storage::{Block, BlockCoordinate, MemoryCacheBuilder, MemoryStorage, Storage};
+
+const BLOCK_SIZE_IN_BYTES: usize = 8;
+const BLOCK_CONTENT: &[u8; BLOCK_SIZE_IN_BYTES] = b"foo bar ";
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+type PeerCacheStorageType = PeerCacheStorage<Arc<MemoryLocalityIndex>, Arc<MemoryStorage>>;
+
+/// Serve the caches of `cache` on a random local port, returns the endpoint
+/// of it.
+async fn start_server(cache: Arc<LocalCache>, token: CancellationToken) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let service =
+        TransferService::new(Arc::new(MemoryBlockStore::new()), None, TIMEOUT).with_cache(cache);
+    tokio::spawn(async move { serve(listener, service, None, token).await.unwrap() });
+    format!("http://{addr}")
+}
+
+/// Create the storage of node `node_id` in `zone` upon an empty backend.
+fn prepare_storage(
+    index: &Arc<MemoryLocalityIndex>,
+    node_id: &str,
+    zone: &str,
+) -> (Arc<MemoryStorage>, PeerCacheStorageType) {
+    let backend = Arc::new(MemoryStorage::new(BLOCK_SIZE_IN_BYTES, Duration::ZERO));
+    let storage = PeerCacheStorage::new(
+        Arc::clone(index),
+        Arc::clone(&backend),
+        node_id,
+        Some(zone.to_owned()),
+        BLOCK_SIZE_IN_BYTES,
+        None,
+        TIMEOUT,
+    );
+    (backend, storage)
+}
+
+#[test]
+fn test_block_holders() {
+    let mut holders = BlockHolders {
+        ino: 1,
+        block_id: 0,
+        nodes: vec![],
+    };
+    assert!(holders.add("node1"));
+    assert!(!holders.add("node1"));
+    assert!(holders.add("node2"));
+    assert!(holders.add("node1"));
+    assert_eq!(holders.nodes, ["node1", "node2"]);
+
+    // Only the latest holders are kept.
+    for node in ["node3", "node4", "node5"] {
+        holders.add(node);
+    }
+    assert_eq!(holders.nodes.len(), MAX_HOLDERS);
+    assert_eq!(holders.nodes, ["node5", "node4", "node3"]);
+}
+
+#[tokio::test]
+async fn test_peer_cache_read() {
+    let token = CancellationToken::new();
+
+    // Node 1 caches block 0 loaded from the backend, and block 1 written.
+    let backend = Arc::new(MemoryStorage::new(BLOCK_SIZE_IN_BYTES, Duration::ZERO));
+    backend
+        .store(1, 0, Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT))
+        .await
+        .unwrap();
+    let policy = LruPolicy::<BlockCoordinate>::new(4);
+    let cache = MemoryCacheBuilder::new(policy, backend, BLOCK_SIZE_IN_BYTES)
+        .build()
+        .await;
+    cache.load(1, 0).await.unwrap().unwrap();
+    let mut written = Block::from_slice(BLOCK_SIZE_IN_BYTES, b"written ");
+    written.set_dirty(true);
+    cache.store(1, 1, written).await.unwrap();
+    let local_cache = Arc::new(LocalCache::new());
+    local_cache.register(&cache);
+    let endpoint = start_server(local_cache, token.clone()).await;
+
+    let index = Arc::new(MemoryLocalityIndex::new());
+    index.add_peer(Peer {
+        node_id: "node1".to_owned(),
+        endpoint,
+        zone: Some("rack1".to_owned()),
+    });
+    index.add_holder(1, 0, "node1").await.unwrap();
+    index.add_holder(1, 1, "node1").await.unwrap();
+
+    // A node in another zone doesn't fetch from node 1.
+    let (_, far) = prepare_storage(&index, "node3", "rack2");
+    assert!(far.load(1, 0).await.unwrap().is_none());
+
+    // A node in the same zone fetches from node 1, and holds the block then.
+    let (backend, near) = prepare_storage(&index, "node2", "rack1");
+    let loaded = near.load(1, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), BLOCK_CONTENT);
+    assert_eq!(index.get_holders(1, 0).await.unwrap(), ["node2", "node1"]);
+
+    // The dirty blocks are not served, they're loaded from the backend.
+    backend
+        .store(1, 1, Block::from_slice(BLOCK_SIZE_IN_BYTES, b"backend "))
+        .await
+        .unwrap();
+    let loaded = near.load(1, 1).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), b"backend ");
+
+    // The holders of a block are forgotten once it's written.
+    near.store(1, 0, Block::from_slice(BLOCK_SIZE_IN_BYTES, b"new one "))
+        .await
+        .unwrap();
+    assert!(index.get_holders(1, 0).await.unwrap().is_empty());
+    near.remove(1).await.unwrap();
+    assert!(index.get_holders(1, 1).await.unwrap().is_empty());
+
+    token.cancel();
+}
@@ -4,6 +4,7 @@ use std::num::NonZeroUsize;
 use std::sync::Arc;
 use std::time::Duration;
 
+use datenlord::common::memory_budget::MemoryBudget;
 use datenlord::common::task_manager::{TaskName, TASK_MANAGER};
 use datenlord::config::SoftLimit;
 use tokio::sync::mpsc;
@@ -17,6 +18,11 @@ use crate::storage::{BlockCoordinate, Storage};
 const DEFAULT_COMMAND_QUEUE_LIMIT: usize = 1000;
 /// The default basic period of the write back task.
 const DEFAULT_INTERVAL_IN_MILLISEC: u64 = 100;
+/// The default high watermark of dirty bytes of the write back task.
+const DEFAULT_DIRTY_HIGH_WATERMARK: usize = 0x4000_0000;
+/// The default max number of blocks uploaded concurrently by the write back
+/// task.
+const DEFAULT_UPLOAD_CONCURRENCY: usize = 8;
 
 /// A builder to configure and build a `MemoryCache`.
 #[derive(Debug)]
@@ -35,6 +41,13 @@ pub struct MemoryCacheBuilder<P, S> {
     interval: Duration,
     /// The limitation of the message queue of the write back task
     command_queue_limit: usize,
+    /// The high watermark of dirty bytes of the write back task
+    dirty_high_watermark: usize,
+    /// The max number of blocks uploaded concurrently by the write back task
+    upload_concurrency: usize,
+    /// The memory budget the built `MemoryCache` draws from, `None` if it's
+    /// only bounded by its capacity
+    memory_budget: Option<Arc<MemoryBudget>>,
 }
 
 impl<P, S> MemoryCacheBuilder<P, S>
@@ -57,6 +70,9 @@ where
             limit,
             interval: Duration::from_millis(DEFAULT_INTERVAL_IN_MILLISEC),
             command_queue_limit: DEFAULT_COMMAND_QUEUE_LIMIT,
+            dirty_high_watermark: DEFAULT_DIRTY_HIGH_WATERMARK,
+            upload_concurrency: DEFAULT_UPLOAD_CONCURRENCY,
+            memory_budget: None,
         }
     }
 
@@ -90,6 +106,38 @@ where
         self
     }
 
+    /// Set the high watermark of dirty bytes for the write back task.
+    ///
+    /// Once the dirty bytes pending in the write back task exceeds the
+    /// watermark, the task writes blocks back in place until it's below the
+    /// watermark, and writers are blocked meanwhile.
+    #[must_use]
+    pub fn dirty_high_watermark(mut self, watermark: usize) -> Self {
+        self.dirty_high_watermark = watermark;
+        self
+    }
+
+    /// Set the max number of blocks uploaded to the backend concurrently by
+    /// the write back task.
+    ///
+    /// The uploads of different blocks are overlapped, while the stores to the
+    /// same block are coalesced before they're uploaded.
+    #[must_use]
+    pub fn upload_concurrency(mut self, concurrency: usize) -> Self {
+        self.upload_concurrency = concurrency;
+        self
+    }
+
+    /// Set the memory budget shared with the other consumers.
+    ///
+    /// While the budget is exhausted, blocks are evicted to the backend
+    /// before a write is cached, and by the write back task as well.
+    #[must_use]
+    pub fn memory_budget(mut self, budget: Arc<MemoryBudget>) -> Self {
+        self.memory_budget = Some(budget);
+        self
+    }
+
     /// Builds a `MemoryCache`.
     pub async fn build(self) -> Arc<MemoryCache<P, S>> {
         let MemoryCacheBuilder {
@@ -100,6 +148,9 @@ where
             limit,
             interval,
             command_queue_limit,
+            dirty_high_watermark,
+            upload_concurrency,
+            memory_budget,
         } = self;
 
         let (tx, rx) = mpsc::channel(command_queue_limit);
@@ -110,10 +161,22 @@ where
             block_size,
             write_through,
             tx,
+            memory_budget.clone(),
         ));
+        if let Some(ref budget) = memory_budget {
+            budget.register(&cache);
+        }
 
-        let write_back_task =
-            WriteBackTask::new(Arc::clone(&cache), rx, limit, interval, command_queue_limit).await;
+        let write_back_task = WriteBackTask::new(
+            Arc::clone(&cache),
+            rx,
+            limit,
+            interval,
+            command_queue_limit,
+            dirty_high_watermark,
+            upload_concurrency,
+        )
+        .await;
         TASK_MANAGER
             .spawn(TaskName::WriteBack, |token| write_back_task.run(token))
             .await
@@ -1,11 +1,13 @@
 //! The `MemoryCache` implementation.
 
 use std::collections::{BTreeSet, HashMap as StdHashMap};
+use std::ops::Range;
 use std::sync::Arc;
 
 use anyhow::anyhow;
 use async_trait::async_trait;
 use clippy_utilities::OverflowArithmetic;
+use datenlord::common::memory_budget::{MemoryBudget, MemoryConsumer};
 use datenlord::metrics::CACHE_METRICS;
 use lockfree_cuckoohash::{pin, LockFreeCuckooHash as HashMap};
 use tokio::sync::{mpsc, oneshot, RwLock};
@@ -13,6 +15,7 @@ use tracing::warn;
 
 use super::write_back_task::Command;
 use crate::async_fuse::fuse::protocol::INum;
+use crate::async_fuse::fuse::slow_log::{self, Decision};
 use crate::storage::error::StorageResult;
 use crate::storage::policy::EvictPolicy;
 use crate::storage::{Block, BlockCoordinate, BlockId, Storage, StorageError};
@@ -51,6 +54,9 @@ pub struct MemoryCache<P, S> {
     command_sender: mpsc::Sender<Command>,
     /// A set of blocks to be removed, when a file is truncated.
     truncate_records: HashMap<INum, TruncateRecord>,
+    /// The memory budget the cache draws from, `None` if it's only bounded
+    /// by the capacity of the policy
+    memory_budget: Option<Arc<MemoryBudget>>,
 }
 
 impl<P, S> MemoryCache<P, S> {
@@ -64,6 +70,7 @@ impl<P, S> MemoryCache<P, S> {
         block_size: usize,
         write_through: bool,
         command_sender: mpsc::Sender<Command>,
+        memory_budget: Option<Arc<MemoryBudget>>,
     ) -> Self {
         MemoryCache {
             map: HashMap::new(),
@@ -74,6 +81,7 @@ impl<P, S> MemoryCache<P, S> {
             pending_write_back: RwLock::default(),
             command_sender,
             truncate_records: HashMap::new(),
+            memory_budget,
         }
     }
 
@@ -107,9 +115,76 @@ impl<P, S> MemoryCache<P, S> {
         block
     }
 
+    /// Whether a block only holds a part of its content, whose rest remains
+    /// in the backend.
+    ///
+    /// A partial block is only cached in write-back policy, when a part of a
+    /// block missed in the cache is written.
+    fn is_partial(&self, block: &Block) -> bool {
+        block.start() != 0 || block.end() != self.block_size
+    }
+
+    /// Complete a partial block with the content in the backend, which is the
+    /// read-modify-write deferred until the whole block is needed.
+    async fn complete_block(
+        &self,
+        ino: INum,
+        block_id: BlockId,
+        block: &Block,
+    ) -> StorageResult<Block>
+    where
+        S: Storage + Send + Sync,
+    {
+        slow_log::note(Decision::BlockCompleted {
+            ino,
+            block: block_id,
+        });
+        let mut completed = self
+            .backend
+            .load(ino, block_id)
+            .await?
+            .unwrap_or_else(|| Block::new_zeroed(self.block_size));
+        merge_two_blocks(block, &mut completed)?;
+        Ok(completed)
+    }
+
+    /// Get a block from the in-memory cache, a partial block is completed with
+    /// the content in the backend.
+    async fn get_whole_block_from_cache(
+        &self,
+        ino: INum,
+        block_id: BlockId,
+    ) -> StorageResult<Option<Block>>
+    where
+        S: Storage + Send + Sync,
+    {
+        let Some(block) = self.get_block_from_cache(ino, block_id).await else {
+            return Ok(None);
+        };
+        if !self.is_partial(&block) {
+            return Ok(Some(block));
+        }
+
+        let Some(file_cache) = self.get_file_cache(ino) else {
+            return Ok(None);
+        };
+        let mut file_cache = file_cache.write().await;
+        let Some(block) = file_cache.get_mut(&block_id) else {
+            return Ok(None);
+        };
+        if self.is_partial(block) {
+            *block = self.complete_block(ino, block_id, block).await?;
+        }
+        Ok(Some(block.clone()))
+    }
+
     /// Send a block to the write back task, and it will be stored to the
     /// backend later.
     async fn send_block_to_write_back_task(&self, ino: INum, block_id: BlockId, block: Block) {
+        slow_log::note(Decision::WriteBack {
+            ino,
+            block: block_id,
+        });
         let (tx, rx) = oneshot::channel();
 
         self.command_sender
@@ -133,15 +208,26 @@ impl<P, S> MemoryCache<P, S> {
     /// Update a block into cache in place.
     /// Return the block if success (the destination existing in cache),
     /// otherwise returns `None`.
+    ///
+    /// A write adjacent to or overlapping a partial block extends it, while a
+    /// write leaving a gap in it completes the block from the backend first.
     async fn update_block(
         &self,
         ino: INum,
         block_id: usize,
         src: &Block,
-    ) -> StorageResult<Option<Block>> {
+    ) -> StorageResult<Option<Block>>
+    where
+        S: Storage + Send + Sync,
+    {
         let res = if let Some(file_cache) = self.get_file_cache(ino) {
             let mut file_cache = file_cache.write().await;
             if let Some(block) = file_cache.get_mut(&block_id) {
+                if self.is_partial(block)
+                    && (src.end() < block.start() || src.start() > block.end())
+                {
+                    *block = self.complete_block(ino, block_id, block).await?;
+                }
                 merge_two_blocks(src, block)?;
                 if !self.write_through {
                     if let Some(truncate_record) = {
@@ -162,6 +248,41 @@ impl<P, S> MemoryCache<P, S> {
         Ok(res)
     }
 
+    /// The blocks cached, and the capacity of the cache in blocks.
+    pub fn occupancy(&self) -> (usize, usize)
+    where
+        P: EvictPolicy<BlockCoordinate>,
+    {
+        (self.policy.size(), self.policy.capacity())
+    }
+
+    /// The block size.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// The dirty bytes of each file pending to be written back, there's none
+    /// in the writing-through policy.
+    pub async fn dirty_bytes(&self) -> StdHashMap<INum, usize> {
+        if self.write_through {
+            return StdHashMap::new();
+        }
+        let (tx, rx) = oneshot::channel();
+        if self
+            .command_sender
+            .send(Command::DirtyBytes(tx))
+            .await
+            .is_err()
+        {
+            warn!("Write back task is closed unexpectedly.");
+            return StdHashMap::new();
+        }
+        rx.await.unwrap_or_else(|_| {
+            warn!("Receiver is closed unexpectedly.");
+            StdHashMap::new()
+        })
+    }
+
     /// Try to evict a block from the cache to backend, if needed.
     pub(super) async fn evict(&self) -> StorageResult<()>
     where
@@ -175,6 +296,10 @@ impl<P, S> MemoryCache<P, S> {
         // the backend incorrectly, but will not cause incorrect data fetched by
         // the user.
         if let Some(BlockCoordinate(ino, block_id)) = evicted {
+            slow_log::note(Decision::Eviction {
+                ino,
+                block: block_id,
+            });
             if let Some(file_cache) = self.get_file_cache(ino) {
                 let mut file_cache = file_cache.write().await;
 
@@ -201,6 +326,36 @@ impl<P, S> MemoryCache<P, S> {
         Ok(())
     }
 
+    /// Whether the memory budget the cache draws from is exhausted.
+    pub(super) fn is_over_budget(&self) -> bool {
+        self.memory_budget
+            .as_ref()
+            .is_some_and(|budget| budget.is_exhausted())
+    }
+
+    /// Evict blocks to the backend while the memory budget is exhausted, so a
+    /// write waits for the memory it takes.
+    ///
+    /// The other consumers may exhaust the budget on their own, so it stops
+    /// once no more block is evicted, e.g. when the rest are all pinned.
+    async fn evict_to_budget(&self) -> StorageResult<()>
+    where
+        P: EvictPolicy<BlockCoordinate> + Send + Sync,
+        S: Storage + Send + Sync,
+    {
+        while self.is_over_budget() {
+            let size = self.policy.size();
+            if size == 0 {
+                break;
+            }
+            self.evict().await?;
+            if self.policy.size() >= size {
+                break;
+            }
+        }
+        Ok(())
+    }
+
     /// Write a block into the cache without writing through.
     ///
     /// A block may be evicted to the backend.
@@ -260,20 +415,28 @@ where
     S: Storage + Send + Sync,
 {
     async fn load_from_self(&self, ino: INum, block_id: usize) -> StorageResult<Option<Block>> {
-        let res = self.get_block_from_cache(ino, block_id).await;
+        let res = self.get_whole_block_from_cache(ino, block_id).await?;
         if res.is_some() {
-            CACHE_METRICS.cache_hit_count_inc("memory");
+            CACHE_METRICS.cache_hit_count_inc("memory", self.policy.name());
             self.policy.touch(&BlockCoordinate(ino, block_id));
+            slow_log::note(Decision::CacheHit {
+                ino,
+                block: block_id,
+            });
         }
         Ok(res)
     }
 
     async fn load_from_backend(&self, ino: INum, block_id: usize) -> StorageResult<Option<Block>> {
+        slow_log::note(Decision::CacheMiss {
+            ino,
+            block: block_id,
+        });
         let res = self.backend.load(ino, block_id).await;
 
         if let Ok(Some(_)) = res {
             // The cache is considered missed, only if the block exists in the backend.
-            CACHE_METRICS.cache_miss_count_inc("memory");
+            CACHE_METRICS.cache_miss_count_inc("memory", self.policy.name());
         }
 
         res
@@ -300,6 +463,8 @@ where
             });
         }
 
+        self.evict_to_budget().await?;
+
         // If the writing block is the whole block, then there is no need to fetch a
         // block from cache or backend, as the block in storage will be
         // overwritten directly.
@@ -308,6 +473,10 @@ where
                 .await?;
 
             if self.write_through {
+                slow_log::note(Decision::WriteThrough {
+                    ino,
+                    block: block_id,
+                });
                 self.backend.store(ino, block_id, input).await?;
             } else {
                 self.send_block_to_write_back_task(ino, block_id, input)
@@ -318,16 +487,28 @@ where
         }
 
         let dirty_block = if let Some(inserted) = self.update_block(ino, block_id, &input).await? {
-            CACHE_METRICS.cache_hit_count_inc("memory");
+            CACHE_METRICS.cache_hit_count_inc("memory", self.policy.name());
             self.policy.touch(&BlockCoordinate(ino, block_id));
             inserted
         } else {
-            CACHE_METRICS.cache_miss_count_inc("memory");
-            let mut to_be_inserted = self.backend.load(ino, block_id).await?.unwrap_or_else(|| {
-                // Create a new block for write, despite the offset is larger than file size.
-                Block::new_zeroed(self.block_size)
-            });
-            merge_two_blocks(&input, &mut to_be_inserted)?;
+            CACHE_METRICS.cache_miss_count_inc("memory", self.policy.name());
+            let to_be_inserted = if self.write_through {
+                let mut to_be_inserted =
+                    self.backend.load(ino, block_id).await?.unwrap_or_else(|| {
+                        // Create a new block for write, despite the offset is larger than file
+                        // size.
+                        Block::new_zeroed(self.block_size)
+                    });
+                merge_two_blocks(&input, &mut to_be_inserted)?;
+                to_be_inserted
+            } else {
+                // The written part is cached alone, so small writes are coalesced into the
+                // block without reading it from the backend. It's only read once the block
+                // is read, or a write leaves a gap in it.
+                let mut to_be_inserted = input;
+                to_be_inserted.set_dirty(true);
+                to_be_inserted
+            };
             self.write_block_into_cache(ino, block_id, to_be_inserted.clone())
                 .await?;
 
@@ -335,6 +516,10 @@ where
         };
 
         if self.write_through {
+            slow_log::note(Decision::WriteThrough {
+                ino,
+                block: block_id,
+            });
             self.backend.store(ino, block_id, dirty_block).await?;
         } else {
             self.send_block_to_write_back_task(ino, block_id, dirty_block)
@@ -495,11 +680,14 @@ where
                 if to_block > 0 && fill_start < self.block_size {
                     let fill_block_id = to_block.overflow_sub(1);
                     if let Some(block) = file_cache.get_mut(&fill_block_id) {
-                        CACHE_METRICS.cache_hit_count_inc("memory");
+                        CACHE_METRICS.cache_hit_count_inc("memory", self.policy.name());
+                        if self.is_partial(block) {
+                            *block = self.complete_block(ino, fill_block_id, block).await?;
+                        }
                         fill_block_with_zeros(block);
                     } else if !self.write_through {
                         drop(file_cache);
-                        CACHE_METRICS.cache_miss_count_inc("memory");
+                        CACHE_METRICS.cache_miss_count_inc("memory", self.policy.name());
                         let mut block = self
                             .load_from_backend(ino, fill_block_id)
                             .await?
@@ -538,4 +726,100 @@ where
 
         Ok(())
     }
+
+    async fn clone_blocks(
+        &self,
+        src_ino: INum,
+        src_block: usize,
+        dst_ino: INum,
+        dst_block: usize,
+        count: usize,
+    ) -> StorageResult<bool> {
+        // The backend must hold the latest content of the source, and no
+        // pending write back of the destination may overwrite the clones.
+        self.flush(src_ino).await?;
+        if dst_ino != src_ino {
+            self.flush(dst_ino).await?;
+        }
+
+        let cloned = self
+            .backend
+            .clone_blocks(src_ino, src_block, dst_ino, dst_block, count)
+            .await?;
+        if cloned {
+            if let Some(file_cache) = self.get_file_cache(dst_ino) {
+                let mut file_cache = file_cache.write().await;
+                for block_id in dst_block..dst_block.overflow_add(count) {
+                    file_cache.remove(&block_id);
+                }
+            }
+        }
+        Ok(cloned)
+    }
+
+    async fn punch_blocks(&self, ino: INum, blocks: Range<BlockId>) -> StorageResult<bool> {
+        // No pending write back may store the blocks again once they're
+        // removed from the backend.
+        self.flush(ino).await?;
+
+        let punched = self.backend.punch_blocks(ino, blocks.clone()).await?;
+        if punched {
+            if let Some(file_cache) = self.get_file_cache(ino) {
+                let mut file_cache = file_cache.write().await;
+                for block_id in blocks {
+                    file_cache.remove(&block_id);
+                }
+            }
+        }
+        Ok(punched)
+    }
+
+    /// The blocks are pinned in the disk cache below if there's one, where
+    /// they're spilled to once they're evicted from the memory, or in the
+    /// memory otherwise.
+    fn pin(&self, ino: INum, blocks: Range<BlockId>) {
+        if self.backend.pin_capacity() > 0 {
+            self.backend.pin(ino, blocks);
+            return;
+        }
+        for block_id in blocks {
+            self.policy.pin(&BlockCoordinate(ino, block_id));
+        }
+    }
+
+    fn unpin(&self, ino: INum, blocks: Range<BlockId>) {
+        if self.backend.pin_capacity() > 0 {
+            self.backend.unpin(ino, blocks);
+            return;
+        }
+        for block_id in blocks {
+            self.policy.unpin(&BlockCoordinate(ino, block_id));
+        }
+    }
+
+    fn pin_capacity(&self) -> usize {
+        match self.backend.pin_capacity() {
+            0 => self.policy.capacity(),
+            capacity => capacity,
+        }
+    }
+
+    /// The dirty blocks may not be written to the backend yet, and the partial
+    /// ones miss their rest, so neither is served to the other nodes.
+    async fn load_cached(&self, ino: INum, block_id: usize) -> StorageResult<Option<Block>> {
+        match self.get_block_from_cache(ino, block_id).await {
+            Some(block) if !block.dirty() && !self.is_partial(&block) => Ok(Some(block)),
+            Some(_) | None => self.backend.load_cached(ino, block_id).await,
+        }
+    }
+}
+
+impl<P, S> MemoryConsumer for MemoryCache<P, S>
+where
+    P: EvictPolicy<BlockCoordinate> + Send + Sync,
+    S: Send + Sync,
+{
+    fn used_memory(&self) -> usize {
+        self.policy.size().overflow_mul(self.block_size)
+    }
 }
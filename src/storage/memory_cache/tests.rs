@@ -4,6 +4,7 @@ use std::num::NonZeroUsize;
 use std::sync::Arc;
 use std::time::Duration;
 
+use datenlord::common::memory_budget::MemoryBudget;
 use datenlord::config::SoftLimit;
 
 use super::{MemoryCache, MemoryCacheBuilder};
@@ -385,6 +386,39 @@ async fn test_store_write_back() {
     assert!(backend.contains(0, 1));
 }
 
+#[tokio::test]
+async fn test_dirty_bytes() {
+    let policy = LruPolicy::<BlockCoordinate>::new(CACHE_CAPACITY_IN_BLOCKS);
+    let backend = Arc::new(MemoryStorage::new(
+        BLOCK_SIZE_IN_BYTES,
+        Duration::from_millis(0),
+    ));
+    let cache = MemoryCacheBuilder::new(policy, Arc::clone(&backend), BLOCK_SIZE_IN_BYTES)
+        .write_through(false)
+        .interval(Duration::from_secs(60))
+        .build()
+        .await;
+
+    for (ino, block_id) in [(0, 0), (0, 1), (1, 0)] {
+        let mut block = Block::new_zeroed(BLOCK_SIZE_IN_BYTES);
+        block.set_dirty(true);
+        cache.store(ino, block_id, block).await.unwrap();
+    }
+    assert_eq!(cache.occupancy(), (3, CACHE_CAPACITY_IN_BLOCKS));
+    let dirty_bytes = cache.dirty_bytes().await;
+    assert_eq!(dirty_bytes.len(), 2);
+    assert_eq!(
+        dirty_bytes.get(&0),
+        Some(&BLOCK_SIZE_IN_BYTES.wrapping_mul(2))
+    );
+    assert_eq!(dirty_bytes.get(&1), Some(&BLOCK_SIZE_IN_BYTES));
+
+    cache.flush(0).await.unwrap();
+    let dirty_bytes = cache.dirty_bytes().await;
+    assert_eq!(dirty_bytes.get(&0), None);
+    assert_eq!(dirty_bytes.get(&1), Some(&BLOCK_SIZE_IN_BYTES));
+}
+
 #[tokio::test]
 async fn test_evict_dirty_block_with_write_back() {
     let (backend, cache) = prepare_empty_storage_with_write_back().await;
@@ -508,3 +542,176 @@ async fn test_write_back_task() {
         assert!(!file_cache.contains_key(&1));
     }
 }
+
+#[tokio::test]
+async fn test_write_back_coalesce() {
+    let (backend, cache) = prepare_empty_storage_with_write_back().await;
+
+    for block_id in 0..3 {
+        let mut block = Block::new_zeroed(BLOCK_SIZE_IN_BYTES);
+        block.set_dirty(true);
+        cache.store(0, block_id, block).await.unwrap();
+    }
+
+    // The contiguous blocks are flushed together in one tick.
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    for block_id in 0..3 {
+        assert!(backend.contains(0, block_id));
+    }
+}
+
+#[tokio::test]
+async fn test_dirty_high_watermark() {
+    let policy = LruPolicy::<BlockCoordinate>::new(CACHE_CAPACITY_IN_BLOCKS);
+    let backend = Arc::new(MemoryStorage::new(
+        BLOCK_SIZE_IN_BYTES,
+        Duration::from_millis(0),
+    ));
+    let cache = MemoryCacheBuilder::new(policy, Arc::clone(&backend), BLOCK_SIZE_IN_BYTES)
+        .write_through(false)
+        .interval(Duration::from_secs(60))
+        .dirty_high_watermark(BLOCK_SIZE_IN_BYTES.wrapping_mul(2))
+        .build()
+        .await;
+
+    for block_id in [0, 2] {
+        let mut block = Block::new_zeroed(BLOCK_SIZE_IN_BYTES);
+        block.set_dirty(true);
+        cache.store(0, block_id, block).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!backend.contains(0, 0));
+
+    // The watermark is hit, the oldest block is written back.
+    let mut block = Block::new_zeroed(BLOCK_SIZE_IN_BYTES);
+    block.set_dirty(true);
+    cache.store(0, 4, block).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(backend.contains(0, 0));
+    assert!(!backend.contains(0, 2));
+    assert!(!backend.contains(0, 4));
+
+    cache.flush(0).await.unwrap();
+    assert!(backend.contains(0, 2));
+    assert!(backend.contains(0, 4));
+}
+
+#[tokio::test]
+async fn test_memory_budget() {
+    let budget = Arc::new(MemoryBudget::new(BLOCK_SIZE_IN_BYTES.wrapping_mul(3)));
+    // The memory taken by the other consumers
+    let reservation = budget.reserve(BLOCK_SIZE_IN_BYTES);
+
+    let policy = LruPolicy::<BlockCoordinate>::new(CACHE_CAPACITY_IN_BLOCKS);
+    let backend = Arc::new(MemoryStorage::new(
+        BLOCK_SIZE_IN_BYTES,
+        Duration::from_millis(0),
+    ));
+    let cache = MemoryCacheBuilder::new(policy, Arc::clone(&backend), BLOCK_SIZE_IN_BYTES)
+        .interval(Duration::from_secs(60))
+        .memory_budget(Arc::clone(&budget))
+        .build()
+        .await;
+
+    for block_id in 0..3 {
+        let block = Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT);
+        cache.store(0, block_id, block).await.unwrap();
+    }
+    assert_eq!(budget.used(), BLOCK_SIZE_IN_BYTES.wrapping_mul(4));
+
+    // The budget is exhausted, the oldest block is evicted before the write,
+    // though the cache is not full.
+    let block = Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT);
+    cache.store(0, 3, block).await.unwrap();
+    assert!(cache.get_block_from_cache(0, 0).await.is_none());
+    assert!(cache.get_block_from_cache(0, 1).await.is_some());
+    assert_eq!(budget.used(), BLOCK_SIZE_IN_BYTES.wrapping_mul(4));
+
+    // The memory released by the other consumers is taken by the cache.
+    drop(reservation);
+    let block = Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT);
+    cache.store(0, 4, block).await.unwrap();
+    assert!(cache.get_block_from_cache(0, 1).await.is_some());
+    assert_eq!(budget.used(), BLOCK_SIZE_IN_BYTES.wrapping_mul(4));
+}
+
+#[tokio::test]
+async fn test_partial_write_back_coalesce() {
+    let (backend, cache) = prepare_empty_storage_with_write_back().await;
+
+    let block = Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT);
+    backend.store(0, 0, block).await.unwrap();
+
+    // The adjacent writes are coalesced into the block without reading it from
+    // the backend.
+    let block = Block::from_slice_with_range(BLOCK_SIZE_IN_BYTES, 0, 2, b"xx");
+    cache.store(0, 0, block).await.unwrap();
+    let block = Block::from_slice_with_range(BLOCK_SIZE_IN_BYTES, 2, 4, b"yy");
+    cache.store(0, 0, block).await.unwrap();
+    let cached = cache.get_block_from_cache(0, 0).await.unwrap();
+    assert_eq!((cached.start(), cached.end()), (0, 4));
+    assert_eq!(cached.as_slice(), b"xxyy");
+
+    // The rest of the block is read from the backend once the block is read.
+    let loaded = cache.load(0, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), b"xxyybar ");
+
+    cache.flush(0).await.unwrap();
+    let loaded = backend.load(0, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), b"xxyybar ");
+}
+
+#[tokio::test]
+async fn test_partial_write_back_with_gap() {
+    let (backend, cache) = prepare_empty_storage_with_write_back().await;
+
+    let block = Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT);
+    backend.store(0, 0, block).await.unwrap();
+
+    let block = Block::from_slice_with_range(BLOCK_SIZE_IN_BYTES, 0, 2, b"xx");
+    cache.store(0, 0, block).await.unwrap();
+    // The write leaves a gap in the block, which is completed from the backend
+    // first.
+    let block = Block::from_slice_with_range(BLOCK_SIZE_IN_BYTES, 6, 8, b"yy");
+    cache.store(0, 0, block).await.unwrap();
+    let cached = cache.get_block_from_cache(0, 0).await.unwrap();
+    assert_eq!(cached.as_slice(), b"xxo bayy");
+
+    // A partial block written back is merged with the backend.
+    let block = Block::from_slice_with_range(BLOCK_SIZE_IN_BYTES, 4, 6, b"zz");
+    cache.store(0, 1, block).await.unwrap();
+    cache.flush(0).await.unwrap();
+    let loaded = backend.load(0, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), b"xxo bayy");
+    let loaded = backend.load(0, 1).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), b"\0\0\0\0zz\0\0");
+}
+
+#[tokio::test]
+async fn test_write_back_pipeline() {
+    let policy = LruPolicy::<BlockCoordinate>::new(CACHE_CAPACITY_IN_BLOCKS);
+    let backend = Arc::new(MemoryStorage::new(
+        BLOCK_SIZE_IN_BYTES,
+        Duration::from_millis(100),
+    ));
+    let cache = MemoryCacheBuilder::new(policy, Arc::clone(&backend), BLOCK_SIZE_IN_BYTES)
+        .write_through(false)
+        .interval(Duration::from_secs(60))
+        .upload_concurrency(CACHE_CAPACITY_IN_BLOCKS)
+        .build()
+        .await;
+
+    for block_id in 0..CACHE_CAPACITY_IN_BLOCKS {
+        let mut block = Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT);
+        block.set_dirty(true);
+        cache.store(0, block_id, block).await.unwrap();
+    }
+
+    // The uploads of the blocks are overlapped.
+    let start = tokio::time::Instant::now();
+    cache.flush(0).await.unwrap();
+    assert!(start.elapsed() < Duration::from_millis(300));
+    for block_id in 0..CACHE_CAPACITY_IN_BLOCKS {
+        assert!(backend.contains(0, block_id));
+    }
+}
@@ -5,9 +5,11 @@ use std::mem;
 use std::sync::Arc;
 use std::time::Duration;
 
+use anyhow::anyhow;
 use clippy_utilities::OverflowArithmetic;
 use datenlord::common::task_manager::{GcHandle, TaskName, TASK_MANAGER};
 use datenlord::config::SoftLimit;
+use futures::stream::{self, StreamExt};
 use hashlink::LinkedHashSet;
 use tokio::select;
 use tokio::sync::{mpsc, oneshot};
@@ -15,6 +17,7 @@ use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
 
 use crate::async_fuse::fuse::protocol::INum;
+use crate::health::HEALTH;
 use crate::storage::error::StorageResult;
 use crate::storage::policy::EvictPolicy;
 use crate::storage::{Block, BlockCoordinate, BlockId, MemoryCache, Storage};
@@ -40,26 +43,91 @@ pub enum Command {
     FlushAll(oneshot::Sender<()>),
     /// Cancel the writing back of a file
     Cancel(INum),
+    /// Get the dirty bytes of each file pending to be written back
+    DirtyBytes(oneshot::Sender<HashMap<INum, usize>>),
 }
 
-/// Write a block back to the backend.
-async fn write_back_one_block<P, S>(
-    cache: Arc<MemoryCache<P, S>>,
+/// The maximum number of contiguous blocks of a file coalesced into one
+/// flush.
+const MAX_COALESCED_BLOCKS: usize = 16;
+
+/// A block pending to be written back.
+///
+/// Stores to the same block are coalesced, the block keeps the senders of all
+/// the coalesced `Store` commands, and all of them are notified when the
+/// block is written back.
+struct PendingBlock {
+    /// The latest content of the block
+    block: Block,
+    /// Senders to notify the `MemoryCache` that the block has been flushed
+    senders: Vec<StorageResultSender>,
+}
+
+impl PendingBlock {
+    /// The number of dirty bytes of the block.
+    fn dirty_bytes(&self) -> usize {
+        self.block.end().overflow_sub(self.block.start())
+    }
+}
+
+/// Notify all the senders of a coalesced block with the storage result.
+fn notify_senders(
     ino: INum,
     block_id: BlockId,
-    block: Block,
-    tx: oneshot::Sender<StorageResult<()>>,
+    senders: Vec<StorageResultSender>,
+    result: &StorageResult<()>,
+) {
+    if let Err(ref e) = *result {
+        error!("Failed to write the block back, where ino={ino}, block_id={block_id}, err={e}");
+    }
+
+    for tx in senders {
+        let res = match *result {
+            Ok(()) => Ok(()),
+            Err(ref e) => Err(anyhow!("{e}").into()),
+        };
+        tx.send(res).unwrap_or_else(|_| {
+            warn!("The receiver of pending block is closed unexpectedly.");
+        });
+    }
+}
+
+/// Write blocks back to the backend, the uploads of at most `concurrency`
+/// blocks are overlapped.
+///
+/// The blocks are started in the order of `blocks`, and each block is written
+/// back only once, so the uploads of different blocks never race.
+async fn write_back_blocks<P, S>(
+    cache: Arc<MemoryCache<P, S>>,
+    blocks: Vec<(BlockCoordinate, PendingBlock)>,
+    concurrency: usize,
 ) where
     P: EvictPolicy<BlockCoordinate> + Send + Sync + 'static,
     S: Storage + Send + Sync + 'static,
 {
-    let result = cache.backend().store(ino, block_id, block).await;
-    tx.send(result).unwrap_or_else(|res| {
-        warn!("The receiver of pending block is closed unexpectedly.");
-        if let Err(e) = res {
-            error!("Failed to write the block back, where ino={ino}, block_id={block_id}, err={e}");
-        }
-    });
+    stream::iter(blocks)
+        .for_each_concurrent(
+            concurrency,
+            |(BlockCoordinate(ino, block_id), PendingBlock { block, senders })| {
+                let cache = Arc::clone(&cache);
+                async move {
+                    let _write = HEALTH.track_write();
+                    let result = cache.backend().store(ino, block_id, block).await;
+                    notify_senders(ino, block_id, senders, &result);
+                }
+            },
+        )
+        .await;
+}
+
+/// Attach `ino` to the pending blocks of it.
+fn with_ino(
+    ino: INum,
+    blocks: Vec<(BlockId, PendingBlock)>,
+) -> impl Iterator<Item = (BlockCoordinate, PendingBlock)> {
+    blocks
+        .into_iter()
+        .map(move |(block_id, pending)| (BlockCoordinate(ino, block_id), pending))
 }
 
 /// The write back task running in the background.
@@ -71,7 +139,7 @@ pub(super) struct WriteBackTask<P, S> {
     /// The queue of block to be flushed.
     lru_queue: LinkedHashSet<BlockCoordinate>,
     /// The blocks to be flushed.
-    pending_blocks: HashMap<INum, HashMap<BlockId, (Block, StorageResultSender)>>,
+    pending_blocks: HashMap<INum, HashMap<BlockId, PendingBlock>>,
     /// Receiver to accept commands from `MemoryCache`.
     command_receiver: mpsc::Receiver<Command>,
     /// The soft limit of cache.
@@ -80,6 +148,12 @@ pub(super) struct WriteBackTask<P, S> {
     interval: Duration,
     /// The limit of command limit.
     command_queue_limit: usize,
+    /// The number of dirty bytes in `pending_blocks`.
+    dirty_bytes: usize,
+    /// The high watermark of dirty bytes.
+    dirty_high_watermark: usize,
+    /// The max number of blocks uploaded to the backend concurrently.
+    upload_concurrency: usize,
 }
 
 impl<P, S> WriteBackTask<P, S> {
@@ -97,6 +171,8 @@ impl<P, S> WriteBackTask<P, S> {
         limit: SoftLimit,
         interval: Duration,
         command_queue_limit: usize,
+        dirty_high_watermark: usize,
+        upload_concurrency: usize,
     ) -> Self {
         let block_flush_spawn_handle = TASK_MANAGER
             .get_gc_handle(TaskName::BlockFlush)
@@ -111,6 +187,9 @@ impl<P, S> WriteBackTask<P, S> {
             limit,
             interval,
             command_queue_limit,
+            dirty_bytes: 0,
+            dirty_high_watermark,
+            upload_concurrency,
         }
     }
 
@@ -131,6 +210,87 @@ impl<P, S> WriteBackTask<P, S> {
             self.interval
         }
     }
+
+    /// Add a block to the pending blocks, coalescing it with the pending one
+    /// of the same coordinate.
+    fn add_pending_block(
+        &mut self,
+        ino: INum,
+        block_id: BlockId,
+        block: Block,
+        tx: StorageResultSender,
+    ) {
+        let file_level_pending_blocks = self.pending_blocks.entry(ino).or_default();
+        let senders = if let Some(coalesced) = file_level_pending_blocks.remove(&block_id) {
+            self.dirty_bytes = self.dirty_bytes.overflow_sub(coalesced.dirty_bytes());
+            let mut senders = coalesced.senders;
+            senders.push(tx);
+            senders
+        } else {
+            vec![tx]
+        };
+
+        let pending = PendingBlock { block, senders };
+        self.dirty_bytes = self.dirty_bytes.overflow_add(pending.dirty_bytes());
+        file_level_pending_blocks.insert(block_id, pending);
+
+        self.lru_queue.insert(BlockCoordinate(ino, block_id));
+    }
+
+    /// Take a pending block out.
+    fn take_pending_block(&mut self, ino: INum, block_id: BlockId) -> Option<PendingBlock> {
+        let file_level_pending_blocks = self.pending_blocks.get_mut(&ino)?;
+        let pending = file_level_pending_blocks.remove(&block_id)?;
+        if file_level_pending_blocks.is_empty() {
+            self.pending_blocks.remove(&ino);
+        }
+        self.dirty_bytes = self.dirty_bytes.overflow_sub(pending.dirty_bytes());
+        Some(pending)
+    }
+
+    /// Take all pending blocks of a file out, ordered by block id.
+    fn take_file_pending_blocks(&mut self, ino: INum) -> Vec<(BlockId, PendingBlock)> {
+        self.lru_queue
+            .retain_with_order(|&BlockCoordinate(i, _)| i != ino);
+
+        let mut blocks: Vec<_> = self
+            .pending_blocks
+            .remove(&ino)
+            .map(|file_level_pending_blocks| file_level_pending_blocks.into_iter().collect())
+            .unwrap_or_default();
+        blocks.sort_unstable_by_key(|&(block_id, _)| block_id);
+
+        for &(_, ref pending) in &blocks {
+            self.dirty_bytes = self.dirty_bytes.overflow_sub(pending.dirty_bytes());
+        }
+
+        blocks
+    }
+
+    /// Take the oldest pending block out, coalesced with the pending blocks
+    /// next to it in the same file.
+    fn take_coalesced_blocks(&mut self) -> Option<(INum, Vec<(BlockId, PendingBlock)>)> {
+        while let Some(BlockCoordinate(ino, block_id)) = self.lru_queue.pop_front() {
+            let Some(pending) = self.take_pending_block(ino, block_id) else {
+                continue;
+            };
+
+            let mut blocks = vec![(block_id, pending)];
+            let mut next_block_id = block_id.overflow_add(1);
+            while blocks.len() < MAX_COALESCED_BLOCKS {
+                let Some(pending) = self.take_pending_block(ino, next_block_id) else {
+                    break;
+                };
+                self.lru_queue.remove(&BlockCoordinate(ino, next_block_id));
+                blocks.push((next_block_id, pending));
+                next_block_id = next_block_id.overflow_add(1);
+            }
+
+            return Some((ino, blocks));
+        }
+
+        None
+    }
 }
 
 impl<P, S> WriteBackTask<P, S>
@@ -138,95 +298,90 @@ where
     P: EvictPolicy<BlockCoordinate> + Send + Sync + 'static,
     S: Storage + Send + Sync + 'static,
 {
-    /// Flush a pending block to the backend
+    /// Write blocks of a file back in a `BlockFlush` task, or in place if the
+    /// task manager is shutdown.
+    async fn spawn_write_back(&self, ino: INum, blocks: Vec<(BlockId, PendingBlock)>) {
+        if blocks.is_empty() {
+            return;
+        }
+
+        let blocks = with_ino(ino, blocks).collect();
+        if self.block_flush_spawn_handle.is_shutdown() {
+            error!("Trying to flush blocks of ino={ino} after shutdown, write them in place.");
+            write_back_blocks(Arc::clone(&self.storage), blocks, self.upload_concurrency).await;
+            return;
+        }
+
+        if self
+            .block_flush_spawn_handle
+            .spawn(|_| {
+                write_back_blocks(Arc::clone(&self.storage), blocks, self.upload_concurrency)
+            })
+            .await
+            .is_err()
+        {
+            error!("Try to spawn a `BlockFlush` task after shutdown.");
+        }
+    }
+
+    /// Flush the oldest pending block to the backend, with the contiguous
+    /// pending blocks next to it.
     async fn flush_a_block(&mut self) {
-        if let Some(BlockCoordinate(ino, block_id)) = self.lru_queue.pop_front() {
-            if let Some((block, tx)) = self
-                .pending_blocks
-                .get_mut(&ino)
-                .and_then(|file_level_pending_blocks| file_level_pending_blocks.remove(&block_id))
-            {
-                if self
-                    .block_flush_spawn_handle
-                    .spawn(|_| {
-                        write_back_one_block(
-                            Arc::clone(&self.storage),
-                            ino,
-                            block_id,
-                            block.clone(),
-                            tx,
-                        )
-                    })
-                    .await
-                    .is_err()
-                {
-                    // Ensure that the block is flushed to the backend, and notify the loop to
-                    // shutdown.
-                    error!("Try to spawn a `BlockFlush` task after shutdown.");
-                }
-            }
+        if let Some((ino, blocks)) = self.take_coalesced_blocks() {
+            self.spawn_write_back(ino, blocks).await;
+        }
+    }
+
+    /// Write blocks back in place, until the dirty bytes are below the high
+    /// watermark.
+    ///
+    /// The command queue is not consumed meanwhile, so that writers are
+    /// throttled.
+    async fn flush_to_watermark(&mut self) {
+        while self.dirty_bytes > self.dirty_high_watermark {
+            let Some((ino, blocks)) = self.take_coalesced_blocks() else {
+                break;
+            };
+            write_back_blocks(
+                Arc::clone(&self.storage),
+                with_ino(ino, blocks).collect(),
+                self.upload_concurrency,
+            )
+            .await;
         }
     }
 
     /// Flush a file, write blocks of it to backend immediately.
     async fn flush_file(&mut self, ino: INum) {
-        let file_level_pending_blocks = self.pending_blocks.remove(&ino);
-        self.lru_queue
-            .retain_with_order(|&BlockCoordinate(i, _)| i != ino);
-
-        let mut shutdown = self.block_flush_spawn_handle.is_shutdown();
-
-        if let Some(file_level_pending_blocks) = file_level_pending_blocks {
-            for (block_id, (block, tx)) in file_level_pending_blocks {
-                if shutdown {
-                    error!("Trying to flush a file after shutdown.");
-                } else {
-                    let res = self
-                        .block_flush_spawn_handle
-                        .spawn(|_| {
-                            write_back_one_block(
-                                Arc::clone(&self.storage),
-                                ino,
-                                block_id,
-                                block.clone(),
-                                tx,
-                            )
-                        })
-                        .await;
-                    if res.is_err() {
-                        shutdown = true;
-                        if let Err(e) = self.storage.backend().store(ino, block_id, block).await {
-                            error!("Failed to store block where ino={ino}, block_id={block_id}, err={e}.");
-                        }
-                    }
-                }
-            }
-        }
+        let blocks = self.take_file_pending_blocks(ino);
+        self.spawn_write_back(ino, blocks).await;
     }
 
     /// Flush all files, write blocks of them to backend immediately.
     ///
     /// The flush tasks here are resolved in place, because the caller needs to
-    /// know when the operations finish.
+    /// know when the operations finish. The uploads of all the files are
+    /// overlapped.
     async fn flush_all(&mut self, tx: oneshot::Sender<()>) {
         self.lru_queue.clear();
+        self.dirty_bytes = 0;
 
-        let blocks = mem::take(&mut self.pending_blocks);
+        let files = mem::take(&mut self.pending_blocks);
 
-        for (ino, file_level_pending_blocks) in blocks {
-            for (block_id, (block, block_sender)) in file_level_pending_blocks {
-                let res = self.storage.backend().store(ino, block_id, block).await;
-                block_sender.send(res).unwrap_or_else(|res| {
-                    error!("The receiver is closed unexpectedly, with storage result: {res:?}");
-                });
-            }
+        let mut blocks = vec![];
+        for (ino, file_level_pending_blocks) in files {
+            let mut file_blocks: Vec<_> = file_level_pending_blocks.into_iter().collect();
+            file_blocks.sort_unstable_by_key(|&(block_id, _)| block_id);
+            blocks.extend(with_ino(ino, file_blocks));
         }
+        write_back_blocks(Arc::clone(&self.storage), blocks, self.upload_concurrency).await;
 
         tx.send(())
             .unwrap_or_else(|()| warn!("The receiver of pending block is closed unexpectedly."));
     }
 
-    /// Evict blocks from the cache, if it hits the soft limit.
+    /// Evict blocks from the cache, if it hits the soft limit or the memory
+    /// budget is exhausted.
     async fn do_evict(&self) {
         // `a` is a numerator while `b` is a denominator, which are to represent the
         // soft limit. If `size > capacity * a / b`, the soft limit is considered to
@@ -242,10 +397,16 @@ where
             let size = policy.size();
 
             // Soft limitation is hit, do evict.
-            if size.overflow_mul(b).overflow_div(a) > capacity {
+            if size.overflow_mul(b).overflow_div(a) > capacity
+                || (size > 0 && self.storage.is_over_budget())
+            {
                 if let Err(e) = self.storage.evict().await {
                     error!("Failed to evict a block: {e}");
                 }
+                // All the blocks left are pinned.
+                if policy.size() >= size {
+                    break;
+                }
             } else {
                 break;
             }
@@ -260,12 +421,10 @@ where
                 block,
                 tx,
             } => {
-                // Add the block to `lru_queue` and `pending_blocks`
-                self.lru_queue.insert(BlockCoordinate(ino, block_id));
-                self.pending_blocks
-                    .entry(ino)
-                    .or_default()
-                    .insert(block_id, (block, tx));
+                self.add_pending_block(ino, block_id, block, tx);
+                if self.dirty_bytes > self.dirty_high_watermark {
+                    self.flush_to_watermark().await;
+                }
             }
             Command::Flush(ino) => {
                 self.flush_file(ino).await;
@@ -274,9 +433,23 @@ where
                 self.flush_all(tx).await;
             }
             Command::Cancel(ino) => {
-                self.pending_blocks.remove(&ino);
-                self.lru_queue
-                    .retain_with_order(|&BlockCoordinate(i, _)| i != ino);
+                self.take_file_pending_blocks(ino);
+            }
+            Command::DirtyBytes(tx) => {
+                let dirty_bytes = self
+                    .pending_blocks
+                    .iter()
+                    .map(|(&ino, file_level_pending_blocks)| {
+                        let bytes = file_level_pending_blocks
+                            .values()
+                            .map(PendingBlock::dirty_bytes)
+                            .sum();
+                        (ino, bytes)
+                    })
+                    .collect();
+                tx.send(dirty_bytes).unwrap_or_else(|_| {
+                    warn!("The receiver of dirty bytes is closed unexpectedly.");
+                });
             }
         }
     }
@@ -308,11 +481,15 @@ where
                 },
                 _ = interval.tick() => {
                     // If the queue is empty, do evict;
-                    // Otherwise, flush a block to the backend
+                    // Otherwise, flush a block to the backend, and evict at once as well if the
+                    // memory budget is exhausted.
                     if self.lru_queue.is_empty() {
                         self.do_evict().await;
                     } else {
                         self.flush_a_block().await;
+                        if self.storage.is_over_budget() {
+                            self.do_evict().await;
+                        }
                     }
                 },
                 () = token.cancelled() => {
@@ -1,6 +1,7 @@
 //! Mock storages for test and local nodes.
 
 use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 use std::time::Duration;
 
 use async_trait::async_trait;
@@ -152,6 +153,16 @@ impl Storage for MemoryStorage {
 
         Ok(())
     }
+
+    async fn punch_blocks(&self, ino: INum, blocks: Range<usize>) -> StorageResult<bool> {
+        self.sleep().await;
+        if let Some(file_cache) = self.inner.lock().get_mut(&ino) {
+            for block_id in blocks {
+                file_cache.remove(&block_id);
+            }
+        }
+        Ok(true)
+    }
 }
 
 #[cfg(test)]
@@ -2,18 +2,38 @@
 
 mod backend;
 mod block;
+pub mod block_store;
+pub mod checksum;
+pub mod condition;
+pub mod dedup;
+pub mod disk_cache;
+pub mod encryption;
 mod memory_cache;
+pub mod pack;
 mod storage_manager;
 mod storage_trait;
 
+pub mod erasure;
 pub mod error;
+pub mod gc;
+pub mod locality;
 pub mod policy;
+pub mod replication;
+pub mod snapshot;
+pub mod tiering;
+pub mod transfer;
 
 pub use backend::{Backend, BackendBuilder};
 pub use block::{Block, BlockCoordinate};
+pub use block_store::{BlockKey, BlockStore, BlockStoreBackend};
+pub use checksum::ChecksumStorage;
+pub use dedup::DedupStorage;
+pub use disk_cache::DiskCache;
 pub use error::StorageError;
 pub use memory_cache::{MemoryCache, MemoryCacheBuilder};
-pub use storage_manager::StorageManager;
+pub use pack::PackStorage;
+pub use snapshot::SnapshotStorage;
+pub use storage_manager::{ReadAheadState, StorageManager};
 pub use storage_trait::Storage;
 
 /// The number of bytes in one KiB.
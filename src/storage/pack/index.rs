@@ -0,0 +1,155 @@
+//! The index of packed blocks.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+
+use super::PackedBlock;
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::dedup::{ChunkHash, RefDeltas};
+use crate::storage::error::StorageResult;
+
+/// The `PackIndex` trait, which records the packed blocks and the refcounts
+/// of containers.
+///
+/// The refcount of a container is the number of the records of the blocks in
+/// it, and the updates of records and refcounts must be atomic.
+#[async_trait]
+pub trait PackIndex {
+    /// Get the record of a packed block.
+    ///
+    /// Returns `None` if the block is not packed.
+    async fn get_packed(&self, ino: INum, block_id: usize) -> StorageResult<Option<PackedBlock>>;
+
+    /// Set the records of packed blocks of files at once, the previous
+    /// records will be overwritten.
+    ///
+    /// Returns the containers no longer referenced by any record.
+    async fn set_packed(&self, blocks: Vec<(INum, PackedBlock)>) -> StorageResult<Vec<ChunkHash>>;
+
+    /// Remove the records of packed blocks of a file in `block_ids`.
+    ///
+    /// Returns the containers no longer referenced by any record.
+    async fn remove_packed(
+        &self,
+        ino: INum,
+        block_ids: Range<usize>,
+    ) -> StorageResult<Vec<ChunkHash>>;
+}
+
+#[async_trait]
+impl<T> PackIndex for Arc<T>
+where
+    T: PackIndex + Send + Sync,
+{
+    async fn get_packed(&self, ino: INum, block_id: usize) -> StorageResult<Option<PackedBlock>> {
+        self.as_ref().get_packed(ino, block_id).await
+    }
+
+    async fn set_packed(&self, blocks: Vec<(INum, PackedBlock)>) -> StorageResult<Vec<ChunkHash>> {
+        self.as_ref().set_packed(blocks).await
+    }
+
+    async fn remove_packed(
+        &self,
+        ino: INum,
+        block_ids: Range<usize>,
+    ) -> StorageResult<Vec<ChunkHash>> {
+        self.as_ref().remove_packed(ino, block_ids).await
+    }
+}
+
+/// The inner state of `MemoryPackIndex`.
+#[derive(Debug, Default)]
+struct MemoryPackIndexInner {
+    /// The records of packed blocks
+    blocks: BTreeMap<(INum, usize), PackedBlock>,
+    /// The refcounts of containers
+    refcounts: HashMap<ChunkHash, u64>,
+}
+
+impl MemoryPackIndexInner {
+    /// Apply `deltas` to the refcounts, returns the containers no longer
+    /// referenced.
+    fn apply(&mut self, deltas: &RefDeltas) -> Vec<ChunkHash> {
+        let mut orphans = vec![];
+        for (hash, delta) in deltas.iter() {
+            let refcount = self.refcounts.entry(hash).or_default();
+            *refcount = refcount.saturating_add_signed(delta);
+            if *refcount == 0 {
+                self.refcounts.remove(&hash);
+                orphans.push(hash);
+            }
+        }
+        orphans
+    }
+}
+
+/// A `PackIndex` in memory, which is not persisted.
+#[derive(Debug, Default)]
+pub struct MemoryPackIndex {
+    /// The inner state
+    inner: Mutex<MemoryPackIndexInner>,
+}
+
+impl MemoryPackIndex {
+    /// Create an empty `MemoryPackIndex`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of containers referenced by any record.
+    #[must_use]
+    pub fn container_count(&self) -> usize {
+        self.inner.lock().refcounts.len()
+    }
+}
+
+#[async_trait]
+impl PackIndex for MemoryPackIndex {
+    async fn get_packed(&self, ino: INum, block_id: usize) -> StorageResult<Option<PackedBlock>> {
+        Ok(self.inner.lock().blocks.get(&(ino, block_id)).cloned())
+    }
+
+    async fn set_packed(&self, blocks: Vec<(INum, PackedBlock)>) -> StorageResult<Vec<ChunkHash>> {
+        let mut inner = self.inner.lock();
+        let mut deltas = RefDeltas::default();
+        for (ino, block) in blocks {
+            if let Some(hash) = block.container() {
+                deltas.add_chunk(hash);
+            }
+            if let Some(hash) = inner
+                .blocks
+                .insert((ino, block.block_id), block)
+                .and_then(|old| old.container())
+            {
+                deltas.sub_chunk(hash);
+            }
+        }
+        Ok(inner.apply(&deltas))
+    }
+
+    async fn remove_packed(
+        &self,
+        ino: INum,
+        block_ids: Range<usize>,
+    ) -> StorageResult<Vec<ChunkHash>> {
+        let mut inner = self.inner.lock();
+        let keys: Vec<_> = inner
+            .blocks
+            .range((ino, block_ids.start)..(ino, block_ids.end))
+            .map(|(&key, _)| key)
+            .collect();
+        let mut deltas = RefDeltas::default();
+        for key in keys {
+            if let Some(hash) = inner.blocks.remove(&key).and_then(|old| old.container()) {
+                deltas.sub_chunk(hash);
+            }
+        }
+        Ok(inner.apply(&deltas))
+    }
+}
@@ -0,0 +1,71 @@
+//! The packing of small blocks.
+//!
+//! A small file takes a whole object in the backend otherwise, so a workload
+//! of many small files, such as a git repository or `node_modules`, is bound
+//! by the requests to the backend. Instead, the blocks of small contents are
+//! packed, which are recorded in a [`PackIndex`], usually the metadata store:
+//!
+//! - A block not longer than the inline threshold is inlined into its record.
+//! - A block not longer than the pack threshold is packed with others into a
+//!   container, which is stored in a [`ChunkStore`] by its hash, and its record
+//!   locates the block in the container.
+//! - The other blocks are stored in the inner storage as usual.
+//!
+//! The trailing zeros of a block are not stored, as a block of a small file
+//! is padded with zeros to the block size by the caches.
+//!
+//! Containers are immutable, the blocks to pack are buffered in the open
+//! container of the node until it's full, or their files are flushed, as the
+//! dirty blocks of a write-back cache. A container is reference counted by
+//! the records of the blocks in it, and is deleted with its last block.
+//!
+//! [`ChunkStore`]: super::dedup::ChunkStore
+
+mod index;
+mod storage;
+
+pub use index::{MemoryPackIndex, PackIndex};
+use serde::{Deserialize, Serialize};
+pub use storage::PackStorage;
+
+use super::dedup::ChunkHash;
+
+/// Where the content of a packed block is.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PackedData {
+    /// The content inlined in the record
+    Inline(Vec<u8>),
+    /// A range of a container
+    Container {
+        /// The hash of the container
+        hash: ChunkHash,
+        /// The offset of the block in the container
+        offset: usize,
+        /// The length of the block
+        len: usize,
+    },
+}
+
+/// The record of a packed block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackedBlock {
+    /// The index of the block in the file
+    pub block_id: usize,
+    /// The content of the block
+    pub data: PackedData,
+}
+
+impl PackedBlock {
+    /// The container holding the block, `None` if it's inlined.
+    #[must_use]
+    pub fn container(&self) -> Option<ChunkHash> {
+        match self.data {
+            PackedData::Inline(_) => None,
+            PackedData::Container { hash, .. } => Some(hash),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests;
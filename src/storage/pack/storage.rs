@@ -0,0 +1,349 @@
+//! The storage layer packing small blocks.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use clippy_utilities::OverflowArithmetic;
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use super::{PackIndex, PackedBlock, PackedData};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::dedup::{ChunkHash, ChunkStore};
+use crate::storage::error::{StorageError, StorageResult};
+use crate::storage::{Block, Storage};
+
+/// The container being filled with the blocks to pack.
+#[derive(Debug, Default)]
+struct OpenContainer {
+    /// The content of the container
+    data: Vec<u8>,
+    /// The ranges of the latest contents of the blocks in the container
+    blocks: BTreeMap<(INum, usize), Range<usize>>,
+}
+
+impl OpenContainer {
+    /// The latest content of a block in the container.
+    fn get(&self, ino: INum, block_id: usize) -> Option<&[u8]> {
+        let range = self.blocks.get(&(ino, block_id))?;
+        self.data.get(range.clone())
+    }
+
+    /// Append the content of a block, the previous content of the block in
+    /// the container becomes garbage.
+    fn append(&mut self, ino: INum, block_id: usize, data: &[u8]) {
+        let offset = self.data.len();
+        self.data.extend_from_slice(data);
+        self.blocks
+            .insert((ino, block_id), offset..offset.overflow_add(data.len()));
+    }
+
+    /// Forget the blocks of a file in `block_ids`, as they're no longer
+    /// packed in this container.
+    fn forget(&mut self, ino: INum, block_ids: Range<usize>) {
+        self.blocks
+            .retain(|&(i, block_id), _| i != ino || !block_ids.contains(&block_id));
+    }
+
+    /// Whether the container holds any block of a file.
+    fn contains_file(&self, ino: INum) -> bool {
+        self.blocks
+            .range((ino, 0)..=(ino, usize::MAX))
+            .next()
+            .is_some()
+    }
+}
+
+/// A `Storage` which packs small blocks, they're inlined into their records
+/// in the index, or packed into the containers in a `ChunkStore`, and the
+/// others are stored in the inner storage.
+///
+/// A packed block takes precedence over the one in the inner storage, which
+/// is a stale content written before the block shrank.
+#[derive(Debug)]
+pub struct PackStorage<I, C, S> {
+    /// The index of packed blocks and refcounts of containers
+    index: I,
+    /// The store of containers
+    containers: C,
+    /// The inner storage of the blocks not packed
+    inner: S,
+    /// The size of blocks, it's the capacity of a container as well
+    block_size: usize,
+    /// The max length of the blocks inlined into their records
+    inline_threshold: usize,
+    /// The max length of the blocks packed into containers
+    pack_threshold: usize,
+    /// The container being filled
+    open: Mutex<OpenContainer>,
+}
+
+impl<I, C, S> PackStorage<I, C, S>
+where
+    I: PackIndex + Send + Sync,
+    C: ChunkStore + Send + Sync,
+    S: Storage + Send + Sync,
+{
+    /// Create a `PackStorage` upon `inner`, the blocks up to
+    /// `inline_threshold` are inlined, and the ones up to `pack_threshold` are
+    /// packed into containers.
+    pub fn new(
+        index: I,
+        containers: C,
+        inner: S,
+        block_size: usize,
+        inline_threshold: usize,
+        pack_threshold: usize,
+    ) -> Self {
+        Self {
+            index,
+            containers,
+            inner,
+            block_size,
+            inline_threshold,
+            pack_threshold: pack_threshold.min(block_size),
+            open: Mutex::new(OpenContainer::default()),
+        }
+    }
+
+    /// Read the content of a packed block, `None` if it's not packed.
+    async fn read_packed(&self, ino: INum, block_id: usize) -> StorageResult<Option<Vec<u8>>> {
+        if let Some(data) = self.open.lock().await.get(ino, block_id) {
+            return Ok(Some(data.to_vec()));
+        }
+
+        let Some(record) = self.index.get_packed(ino, block_id).await? else {
+            return Ok(None);
+        };
+        match record.data {
+            PackedData::Inline(data) => Ok(Some(data)),
+            PackedData::Container { hash, offset, len } => {
+                let container = self.containers.get_chunk(hash).await?.ok_or_else(|| {
+                    StorageError::Internal(anyhow!(
+                        "container {hash} of block {block_id} of file {ino} is missing"
+                    ))
+                })?;
+                let data = container
+                    .get(offset..offset.overflow_add(len))
+                    .ok_or_else(|| {
+                        StorageError::Internal(anyhow!(
+                            "block {block_id} of file {ino} is out of range of container {hash}"
+                        ))
+                    })?;
+                Ok(Some(data.to_vec()))
+            }
+        }
+    }
+
+    /// Load a block, from the packed ones first.
+    async fn load_block(&self, ino: INum, block_id: usize) -> StorageResult<Option<Block>> {
+        match self.read_packed(ino, block_id).await? {
+            Some(data) => Ok(Some(Block::from_slice(self.block_size, &data))),
+            None => self.inner.load(ino, block_id).await,
+        }
+    }
+
+    /// Store the content of a whole block, where it fits by its length.
+    async fn store_block(&self, ino: INum, block_id: usize, block: Block) -> StorageResult<()> {
+        let data = block.as_slice();
+        let len = data
+            .iter()
+            .rposition(|&byte| byte != 0)
+            .map_or(0, |pos| pos.overflow_add(1));
+        let data = data
+            .get(..len)
+            .unwrap_or_else(|| unreachable!("The length is ensured to be in the block."));
+
+        if len > self.pack_threshold {
+            self.inner.store(ino, block_id, block).await?;
+            // The block is no longer packed.
+            let mut open = self.open.lock().await;
+            open.forget(ino, block_id..block_id.overflow_add(1));
+            let orphans = self
+                .index
+                .remove_packed(ino, block_id..block_id.overflow_add(1))
+                .await?;
+            drop(open);
+            self.delete_containers(orphans).await;
+            return Ok(());
+        }
+
+        let mut open = self.open.lock().await;
+        if len > self.inline_threshold {
+            if open.data.len().overflow_add(len) > self.block_size {
+                self.seal(&mut open).await?;
+            }
+            open.append(ino, block_id, data);
+            return Ok(());
+        }
+
+        open.forget(ino, block_id..block_id.overflow_add(1));
+        let record = PackedBlock {
+            block_id,
+            data: PackedData::Inline(data.to_vec()),
+        };
+        let orphans = self.index.set_packed(vec![(ino, record)]).await?;
+        drop(open);
+        self.delete_containers(orphans).await;
+        Ok(())
+    }
+
+    /// Store the open container, and record the blocks in it.
+    async fn seal(&self, open: &mut OpenContainer) -> StorageResult<()> {
+        if open.blocks.is_empty() {
+            open.data.clear();
+            return Ok(());
+        }
+
+        let hash = ChunkHash::of(&open.data);
+        self.containers.put_chunk(hash, &open.data).await?;
+        let records = open
+            .blocks
+            .iter()
+            .map(|(&(ino, block_id), range)| {
+                let record = PackedBlock {
+                    block_id,
+                    data: PackedData::Container {
+                        hash,
+                        offset: range.start,
+                        len: range.len(),
+                    },
+                };
+                (ino, record)
+            })
+            .collect();
+        let orphans = self.index.set_packed(records).await?;
+        debug!(
+            "Container {hash} of {} bytes is sealed with {} blocks.",
+            open.data.len(),
+            open.blocks.len()
+        );
+        *open = OpenContainer::default();
+        self.delete_containers(orphans).await;
+        Ok(())
+    }
+
+    /// Delete containers no longer referenced.
+    ///
+    /// Failures are only logged, a leaked container costs space but never
+    /// breaks data.
+    async fn delete_containers(&self, orphans: Vec<ChunkHash>) {
+        for hash in orphans {
+            if let Err(e) = self.containers.delete_chunk(hash).await {
+                warn!("failed to delete container {hash}: {e}");
+            }
+        }
+    }
+
+    /// Remove the packed blocks of a file in `block_ids`.
+    async fn remove_packed(&self, ino: INum, block_ids: Range<usize>) -> StorageResult<()> {
+        let mut open = self.open.lock().await;
+        open.forget(ino, block_ids.clone());
+        let orphans = self.index.remove_packed(ino, block_ids).await?;
+        drop(open);
+        self.delete_containers(orphans).await;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<I, C, S> Storage for PackStorage<I, C, S>
+where
+    I: PackIndex + Send + Sync,
+    C: ChunkStore + Send + Sync,
+    S: Storage + Send + Sync,
+{
+    async fn load_from_self(&self, ino: INum, block_id: usize) -> StorageResult<Option<Block>> {
+        self.load_block(ino, block_id).await
+    }
+
+    async fn load_from_backend(&self, _: INum, _: usize) -> StorageResult<Option<Block>> {
+        // The inner storage is loaded in `load_from_self`.
+        Ok(None)
+    }
+
+    async fn cache_block_from_backend(&self, _: INum, _: usize, _: Block) -> StorageResult<()> {
+        unreachable!("This storage has no backend, and has no cache.");
+    }
+
+    async fn store(&self, ino: INum, block_id: usize, block: Block) -> StorageResult<()> {
+        let block = if block.start() == 0 && block.end() == self.block_size {
+            block
+        } else {
+            // The length of the content is of the whole block, so merge it
+            // here.
+            let mut dest = self
+                .load_block(ino, block_id)
+                .await?
+                .unwrap_or_else(|| Block::new_zeroed(self.block_size));
+            dest.update(&block)?;
+            dest
+        };
+        self.store_block(ino, block_id, block).await
+    }
+
+    async fn remove(&self, ino: INum) -> StorageResult<()> {
+        self.remove_packed(ino, 0..usize::MAX).await?;
+        self.inner.remove(ino).await
+    }
+
+    async fn invalidate(&self, ino: INum) -> StorageResult<()> {
+        self.inner.invalidate(ino).await
+    }
+
+    async fn flush(&self, ino: INum) -> StorageResult<()> {
+        {
+            let mut open = self.open.lock().await;
+            if open.contains_file(ino) {
+                self.seal(&mut open).await?;
+            }
+        }
+        self.inner.flush(ino).await
+    }
+
+    async fn flush_all(&self) -> StorageResult<()> {
+        self.seal(&mut *self.open.lock().await).await?;
+        self.inner.flush_all().await
+    }
+
+    async fn truncate(
+        &self,
+        ino: INum,
+        from_block: usize,
+        to_block: usize,
+        fill_start: usize,
+    ) -> StorageResult<()> {
+        if to_block == 0 {
+            return self.remove(ino).await;
+        }
+
+        if to_block < from_block {
+            self.remove_packed(ino, to_block..from_block).await?;
+        }
+
+        // Truncate the last block if it's packed, it's shorter than the block
+        // in the inner storage if there's any.
+        if fill_start < self.block_size {
+            let block_id = to_block.overflow_sub(1);
+            if let Some(mut data) = self.read_packed(ino, block_id).await? {
+                data.truncate(fill_start);
+                let block = Block::from_slice(self.block_size, &data);
+                self.store_block(ino, block_id, block).await?;
+            }
+        }
+
+        self.inner
+            .truncate(ino, from_block, to_block, fill_start)
+            .await
+    }
+
+    async fn punch_blocks(&self, ino: INum, blocks: Range<usize>) -> StorageResult<bool> {
+        if !self.inner.punch_blocks(ino, blocks.clone()).await? {
+            return Ok(false);
+        }
+        self.remove_packed(ino, blocks).await?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+
+use super::{MemoryPackIndex, PackIndex, PackStorage, PackedData};
+use crate::storage::dedup::{ChunkHash, ChunkStore};
+use crate::storage::error::StorageResult;
+use crate::storage::{Block, MemoryStorage, Storage};
+
+const BLOCK_SIZE_IN_BYTES: usize = 64;
+const INLINE_THRESHOLD: usize = 8;
+const PACK_THRESHOLD: usize = 24;
+
+/// A `ChunkStore` in memory.
+#[derive(Debug, Default)]
+struct MemoryChunkStore {
+    /// The chunks
+    chunks: Mutex<HashMap<ChunkHash, Vec<u8>>>,
+}
+
+impl MemoryChunkStore {
+    fn len(&self) -> usize {
+        self.chunks.lock().len()
+    }
+}
+
+#[async_trait]
+impl ChunkStore for MemoryChunkStore {
+    async fn get_chunk(&self, hash: ChunkHash) -> StorageResult<Option<Vec<u8>>> {
+        Ok(self.chunks.lock().get(&hash).cloned())
+    }
+
+    async fn put_chunk(&self, hash: ChunkHash, data: &[u8]) -> StorageResult<()> {
+        self.chunks.lock().insert(hash, data.to_vec());
+        Ok(())
+    }
+
+    async fn delete_chunk(&self, hash: ChunkHash) -> StorageResult<()> {
+        self.chunks.lock().remove(&hash);
+        Ok(())
+    }
+}
+
+type PackStorageType = PackStorage<Arc<MemoryPackIndex>, Arc<MemoryChunkStore>, Arc<MemoryStorage>>;
+
+fn prepare_storage() -> (
+    Arc<MemoryPackIndex>,
+    Arc<MemoryChunkStore>,
+    Arc<MemoryStorage>,
+    PackStorageType,
+) {
+    let index = Arc::new(MemoryPackIndex::new());
+    let containers = Arc::new(MemoryChunkStore::default());
+    let inner = Arc::new(MemoryStorage::new(
+        BLOCK_SIZE_IN_BYTES,
+        Duration::from_millis(0),
+    ));
+    let storage = PackStorage::new(
+        Arc::clone(&index),
+        Arc::clone(&containers),
+        Arc::clone(&inner),
+        BLOCK_SIZE_IN_BYTES,
+        INLINE_THRESHOLD,
+        PACK_THRESHOLD,
+    );
+    (index, containers, inner, storage)
+}
+
+/// A block of `len` bytes of `byte`, padded with zeros.
+fn block_of(byte: u8, len: usize) -> Block {
+    Block::from_slice(BLOCK_SIZE_IN_BYTES, &vec![byte; len])
+}
+
+#[tokio::test]
+async fn test_inline() {
+    let (index, containers, inner, storage) = prepare_storage();
+
+    storage.store(2, 0, block_of(b'a', 5)).await.unwrap();
+    let record = index.get_packed(2, 0).await.unwrap().unwrap();
+    assert_eq!(record.data, PackedData::Inline(b"aaaaa".to_vec()));
+    assert!(!inner.contains(2, 0));
+    assert_eq!(containers.len(), 0);
+
+    let loaded = storage.load(2, 0).await.unwrap().unwrap();
+    assert_eq!(loaded.as_slice(), block_of(b'a', 5).as_slice());
+}
+
+#[tokio::test]
+async fn test_pack_into_containers() {
+    let (index, containers, inner, storage) = prepare_storage();
+
+    // Blocks are buffered in the open container until it's full.
+    storage.store(2, 0, block_of(b'a', 20)).await.unwrap();
+    storage.store(3, 0, block_of(b'b', 20)).await.unwrap();
+    storage.store(4, 0, block_of(b'c', 20)).await.unwrap();
+    assert_eq!(containers.len(), 0);
+    assert_eq!(
+        storage.load(3, 0).await.unwrap().unwrap().as_slice(),
+        block_of(b'b', 20).as_slice()
+    );
+
+    // The next block doesn't fit, the container is sealed.
+    storage.store(5, 0, block_of(b'd', 20)).await.unwrap();
+    assert_eq!(containers.len(), 1);
+    assert_eq!(index.container_count(), 1);
+    let record = index.get_packed(3, 0).await.unwrap().unwrap();
+    assert!(matches!(
+        record.data,
+        PackedData::Container {
+            offset: 20,
+            len: 20,
+            ..
+        }
+    ));
+    assert!(!inner.contains(3, 0));
+
+    // Flushing a file seals the container holding its blocks.
+    storage.flush(5).await.unwrap();
+    assert_eq!(containers.len(), 2);
+    for (ino, byte) in [(2, b'a'), (3, b'b'), (4, b'c'), (5, b'd')] {
+        let loaded = storage.load(ino, 0).await.unwrap().unwrap();
+        assert_eq!(loaded.as_slice(), block_of(byte, 20).as_slice());
+    }
+
+    // A container is deleted with its last block.
+    storage.remove(5).await.unwrap();
+    assert_eq!(containers.len(), 1);
+    storage.remove(2).await.unwrap();
+    storage.remove(3).await.unwrap();
+    assert_eq!(containers.len(), 1);
+    storage.remove(4).await.unwrap();
+    assert_eq!(containers.len(), 0);
+    assert_eq!(index.container_count(), 0);
+}
+
+#[tokio::test]
+async fn test_grow_and_shrink() {
+    let (index, containers, inner, storage) = prepare_storage();
+
+    storage.store(2, 0, block_of(b'a', 20)).await.unwrap();
+    storage.flush(2).await.unwrap();
+    assert_eq!(containers.len(), 1);
+
+    // A large block is stored in the inner storage, and is no longer packed.
+    storage.store(2, 0, block_of(b'b', 40)).await.unwrap();
+    assert!(inner.contains(2, 0));
+    assert!(index.get_packed(2, 0).await.unwrap().is_none());
+    assert_eq!(containers.len(), 0);
+    assert_eq!(
+        storage.load(2, 0).await.unwrap().unwrap().as_slice(),
+        block_of(b'b', 40).as_slice()
+    );
+
+    // Rewriting it small packs it again, over the stale one in the inner
+    // storage.
+    storage.store(2, 0, block_of(b'c', 6)).await.unwrap();
+    assert_eq!(
+        storage.load(2, 0).await.unwrap().unwrap().as_slice(),
+        block_of(b'c', 6).as_slice()
+    );
+
+    // Truncating a packed block truncates its record.
+    storage.truncate(2, 1, 1, 4).await.unwrap();
+    assert_eq!(
+        index.get_packed(2, 0).await.unwrap().unwrap().data,
+        PackedData::Inline(b"cccc".to_vec())
+    );
+
+    // A partial write is merged with the packed block.
+    let partial = Block::from_slice_with_range(BLOCK_SIZE_IN_BYTES, 4, 6, b"dd");
+    storage.store(2, 0, partial).await.unwrap();
+    let mut expected = vec![b'c'; 4];
+    expected.extend_from_slice(b"dd");
+    assert_eq!(
+        index.get_packed(2, 0).await.unwrap().unwrap().data,
+        PackedData::Inline(expected)
+    );
+}
@@ -0,0 +1,256 @@
+//! The ARC (Adaptive Replacement Cache) policy implementation.
+
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use clippy_utilities::OverflowArithmetic;
+use hashlink::LinkedHashSet;
+use parking_lot::Mutex;
+
+use super::{pop_unpinned, EvictPolicy};
+
+/// The inner state of `ArcPolicy`.
+#[derive(Debug)]
+struct ArcInner<K> {
+    /// Keys accessed once recently, in LRU order
+    recent: LinkedHashSet<K>,
+    /// Keys accessed at least twice recently, in LRU order
+    frequent: LinkedHashSet<K>,
+    /// Ghost keys evicted from `recent`
+    recent_ghost: LinkedHashSet<K>,
+    /// Ghost keys evicted from `frequent`
+    frequent_ghost: LinkedHashSet<K>,
+    /// The target size of `recent`
+    target: usize,
+    /// The pinned keys
+    pinned: HashSet<K>,
+}
+
+impl<K: Clone + Hash + Eq> ArcInner<K> {
+    /// Move a key accessed again to the MRU end of `frequent`, returns if the
+    /// key exists.
+    fn access(&mut self, key: &K) -> bool {
+        if self.recent.remove(key) {
+            self.frequent.insert(key.clone());
+            true
+        } else {
+            self.frequent.to_back(key)
+        }
+    }
+
+    /// Keep the total size of ghost lists within `capacity`.
+    fn trim_ghosts(&mut self, capacity: usize) {
+        while self
+            .recent_ghost
+            .len()
+            .overflow_add(self.frequent_ghost.len())
+            > capacity
+        {
+            if self.recent_ghost.len() >= self.frequent_ghost.len() {
+                self.recent_ghost.pop_front();
+            } else {
+                self.frequent_ghost.pop_front();
+            }
+        }
+    }
+}
+
+/// The evict policy based on ARC.
+///
+/// ARC balances between recency and frequency: keys accessed only once are
+/// kept in a recent list, and keys accessed more are kept in a frequent list.
+/// The ghost keys evicted from each list adapt the target size of the recent
+/// list, so that a scan does not flush the frequently accessed keys.
+#[derive(Debug)]
+pub struct ArcPolicy<K> {
+    /// The inner state
+    inner: Mutex<ArcInner<K>>,
+    /// The capacity of this policy
+    capacity: usize,
+}
+
+impl<K: Hash + Eq> ArcPolicy<K> {
+    /// Create a new `ArcPolicy` with the given `capacity`.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        ArcPolicy {
+            inner: Mutex::new(ArcInner {
+                recent: LinkedHashSet::new(),
+                frequent: LinkedHashSet::new(),
+                recent_ghost: LinkedHashSet::new(),
+                frequent_ghost: LinkedHashSet::new(),
+                target: 0,
+                pinned: HashSet::new(),
+            }),
+            capacity,
+        }
+    }
+}
+
+impl<K: Clone + Hash + Eq> EvictPolicy<K> for ArcPolicy<K> {
+    fn touch(&self, key: &K) {
+        self.inner.lock().access(key);
+    }
+
+    /// Evict from the recent list if it exceeds the target size, otherwise
+    /// evict from the frequent list. The evicted key is remembered as a ghost.
+    fn evict(&self) -> Option<K> {
+        let mut arc = self.inner.lock();
+        let ArcInner {
+            ref mut recent,
+            ref mut frequent,
+            ref mut recent_ghost,
+            ref mut frequent_ghost,
+            target,
+            ref pinned,
+        } = *arc;
+
+        let prefer_recent = !recent.is_empty() && (recent.len() > target || frequent.is_empty());
+
+        let evicted = if prefer_recent {
+            pop_unpinned(recent, pinned)
+                .map(|key| (key, true))
+                .or_else(|| pop_unpinned(frequent, pinned).map(|key| (key, false)))
+        } else {
+            pop_unpinned(frequent, pinned)
+                .map(|key| (key, false))
+                .or_else(|| pop_unpinned(recent, pinned).map(|key| (key, true)))
+        };
+
+        let (key, from_recent) = evicted?;
+        if from_recent {
+            recent_ghost.insert(key.clone());
+        } else {
+            frequent_ghost.insert(key.clone());
+        }
+        arc.trim_ghosts(self.capacity);
+
+        Some(key)
+    }
+
+    /// Attempt to insert `key` into the policy.
+    /// If the policy is full and does not contain the `key`, return false.
+    /// If the `key` is inserted successfully or already exists, return true.
+    ///
+    /// The existed key will considered to be touched.
+    fn try_put(&self, key: K) -> bool {
+        let mut arc = self.inner.lock();
+
+        if arc.access(&key) {
+            return true;
+        }
+
+        if arc.recent.len().overflow_add(arc.frequent.len()) == self.capacity {
+            return false;
+        }
+
+        let recent_ghost_len = arc.recent_ghost.len().max(1);
+        let frequent_ghost_len = arc.frequent_ghost.len().max(1);
+        if arc.recent_ghost.remove(&key) {
+            // The recent list is too small.
+            let delta = frequent_ghost_len.overflow_div(recent_ghost_len).max(1);
+            arc.target = arc.target.overflow_add(delta).min(self.capacity);
+            arc.frequent.insert(key);
+        } else if arc.frequent_ghost.remove(&key) {
+            // The frequent list is too small.
+            let delta = recent_ghost_len.overflow_div(frequent_ghost_len).max(1);
+            arc.target = arc.target.saturating_sub(delta);
+            arc.frequent.insert(key);
+        } else {
+            arc.recent.insert(key);
+        }
+
+        true
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn size(&self) -> usize {
+        let arc = self.inner.lock();
+        arc.recent.len().overflow_add(arc.frequent.len())
+    }
+
+    fn pin(&self, key: &K) {
+        self.inner.lock().pinned.insert(key.clone());
+    }
+
+    fn unpin(&self, key: &K) {
+        self.inner.lock().pinned.remove(key);
+    }
+
+    fn name(&self) -> &'static str {
+        "arc"
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::default_numeric_fallback)]
+mod tests {
+    use super::{ArcPolicy, EvictPolicy};
+
+    /// Create a `ArcPolicy` of `i32`, with keys `1, 2, 3`.
+    fn create_arc() -> ArcPolicy<i32> {
+        let cache = ArcPolicy::<i32>::new(3);
+
+        assert!(cache.try_put(1));
+        assert!(cache.try_put(2));
+        assert!(cache.try_put(3));
+
+        cache
+    }
+
+    #[test]
+    fn test_evict() {
+        let cache = create_arc();
+
+        assert!(!cache.try_put(4));
+        assert_eq!(cache.evict(), Some(1));
+        assert!(cache.try_put(4));
+        assert_eq!(cache.size(), 3);
+    }
+
+    #[test]
+    fn test_scan_resistance() {
+        let cache = create_arc();
+
+        // 1 is accessed twice, and moved to the frequent list.
+        cache.touch(&1);
+
+        // A scan only evicts keys in the recent list.
+        for key in 4..10 {
+            if !cache.try_put(key) {
+                assert_ne!(cache.evict(), Some(1));
+                assert!(cache.try_put(key));
+            }
+        }
+        assert!(cache.try_put(1));
+        assert_eq!(cache.size(), 3);
+    }
+
+    #[test]
+    fn test_ghost_hit() {
+        let cache = create_arc();
+
+        assert_eq!(cache.evict(), Some(1));
+        // 1 is a ghost, putting it again moves it to the frequent list.
+        assert!(cache.try_put(1));
+        cache.touch(&2);
+
+        // The target of the recent list grows on the ghost hit, so the
+        // frequent list is evicted first.
+        assert_eq!(cache.evict(), Some(1));
+    }
+
+    #[test]
+    fn test_pin() {
+        let cache = create_arc();
+
+        cache.pin(&1);
+        assert_eq!(cache.evict(), Some(2));
+
+        cache.unpin(&1);
+        assert_eq!(cache.evict(), Some(1));
+    }
+}
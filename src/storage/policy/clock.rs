@@ -0,0 +1,177 @@
+//! The CLOCK policy implementation.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::mem;
+
+use parking_lot::Mutex;
+
+use super::EvictPolicy;
+
+/// The inner state of `ClockPolicy`.
+#[derive(Debug)]
+struct ClockInner<K> {
+    /// The ring of keys, the front is where the clock hand points to
+    ring: VecDeque<K>,
+    /// Maps keys to their reference bits
+    referenced: HashMap<K, bool>,
+    /// The pinned keys
+    pinned: HashSet<K>,
+}
+
+/// The evict policy based on CLOCK (second chance), an approximation of LRU
+/// with lower overhead on accessing.
+#[derive(Debug)]
+pub struct ClockPolicy<K> {
+    /// The inner state
+    inner: Mutex<ClockInner<K>>,
+    /// The capacity of this policy
+    capacity: usize,
+}
+
+impl<K: Hash + Eq> ClockPolicy<K> {
+    /// Create a new `ClockPolicy` with the given `capacity`.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        ClockPolicy {
+            inner: Mutex::new(ClockInner {
+                ring: VecDeque::with_capacity(capacity),
+                referenced: HashMap::with_capacity(capacity),
+                pinned: HashSet::new(),
+            }),
+            capacity,
+        }
+    }
+}
+
+impl<K: Clone + Hash + Eq> EvictPolicy<K> for ClockPolicy<K> {
+    /// Set the reference bit of the key.
+    fn touch(&self, key: &K) {
+        if let Some(referenced) = self.inner.lock().referenced.get_mut(key) {
+            *referenced = true;
+        }
+    }
+
+    /// Sweep the clock hand, a referenced key gets a second chance with its
+    /// reference bit cleared, and the first unreferenced key is evicted.
+    fn evict(&self) -> Option<K> {
+        let mut clock = self.inner.lock();
+        let ClockInner {
+            ref mut ring,
+            ref mut referenced,
+            ref pinned,
+        } = *clock;
+
+        // Each key is visited at most twice, the first visit clears its
+        // reference bit.
+        for _ in 0..ring.len().saturating_mul(2) {
+            let key = ring.pop_front()?;
+            let second_chance = pinned.contains(&key)
+                || referenced
+                    .get_mut(&key)
+                    .map_or(false, |bit| mem::replace(bit, false));
+            if second_chance {
+                ring.push_back(key);
+            } else {
+                referenced.remove(&key);
+                return Some(key);
+            }
+        }
+
+        None
+    }
+
+    /// Attempt to insert `key` into the policy.
+    /// If the policy is full and does not contain the `key`, return false.
+    /// If the `key` is inserted successfully or already exists, return true.
+    ///
+    /// The existed key will considered to be touched.
+    fn try_put(&self, key: K) -> bool {
+        let mut clock = self.inner.lock();
+
+        if let Some(referenced) = clock.referenced.get_mut(&key) {
+            *referenced = true;
+            return true;
+        }
+
+        if clock.ring.len() == self.capacity {
+            return false;
+        }
+
+        clock.ring.push_back(key.clone());
+        clock.referenced.insert(key, false);
+        true
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn size(&self) -> usize {
+        self.inner.lock().ring.len()
+    }
+
+    fn pin(&self, key: &K) {
+        self.inner.lock().pinned.insert(key.clone());
+    }
+
+    fn unpin(&self, key: &K) {
+        self.inner.lock().pinned.remove(key);
+    }
+
+    fn name(&self) -> &'static str {
+        "clock"
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::default_numeric_fallback)]
+mod tests {
+    use super::{ClockPolicy, EvictPolicy};
+
+    /// Create a `ClockPolicy` of `i32`, with keys `1, 2, 3`.
+    fn create_clock() -> ClockPolicy<i32> {
+        let cache = ClockPolicy::<i32>::new(3);
+
+        assert!(cache.try_put(1));
+        assert!(cache.try_put(2));
+        assert!(cache.try_put(3));
+
+        cache
+    }
+
+    #[test]
+    fn test_evict() {
+        let cache = create_clock();
+
+        assert!(!cache.try_put(4));
+        assert_eq!(cache.evict(), Some(1));
+        assert!(cache.try_put(4));
+        assert_eq!(cache.size(), 3);
+    }
+
+    #[test]
+    fn test_second_chance() {
+        let cache = create_clock();
+
+        cache.touch(&1);
+        cache.touch(&2);
+
+        // 1 and 2 get the second chance.
+        assert_eq!(cache.evict(), Some(3));
+        assert_eq!(cache.evict(), Some(1));
+    }
+
+    #[test]
+    fn test_pin() {
+        let cache = create_clock();
+
+        cache.pin(&1);
+        cache.pin(&2);
+        cache.pin(&3);
+        assert_eq!(cache.evict(), None);
+
+        cache.unpin(&2);
+        assert_eq!(cache.evict(), Some(2));
+    }
+}
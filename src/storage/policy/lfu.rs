@@ -0,0 +1,193 @@
+//! The LFU policy implementation.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+
+use clippy_utilities::OverflowArithmetic;
+use parking_lot::Mutex;
+
+use super::EvictPolicy;
+
+/// The inner state of `LfuPolicy`.
+#[derive(Debug)]
+struct LfuInner<K> {
+    /// Maps keys to their `(frequency, sequence)`
+    entries: HashMap<K, (u64, u64)>,
+    /// Keys ordered by `(frequency, sequence)`, the first one is the least
+    /// frequently used, and the oldest one among the same frequency.
+    order: BTreeMap<(u64, u64), K>,
+    /// The sequence for the next access
+    sequence: u64,
+    /// The pinned keys
+    pinned: HashSet<K>,
+}
+
+impl<K: Clone + Hash + Eq> LfuInner<K> {
+    /// Get the next sequence.
+    fn next_sequence(&mut self) -> u64 {
+        let sequence = self.sequence;
+        self.sequence = self.sequence.overflow_add(1);
+        sequence
+    }
+
+    /// Increase the frequency of a key, if it exists.
+    fn access(&mut self, key: &K) -> bool {
+        let sequence = self.next_sequence();
+        let Some(&(frequency, old_sequence)) = self.entries.get(key) else {
+            return false;
+        };
+
+        self.order.remove(&(frequency, old_sequence));
+        let new_order = (frequency.overflow_add(1), sequence);
+        self.order.insert(new_order, key.clone());
+        self.entries.insert(key.clone(), new_order);
+        true
+    }
+}
+
+/// The evict policy based on LFU.
+///
+/// The least frequently used key is evicted, and the least recently used one
+/// is evicted among keys with the same frequency.
+#[derive(Debug)]
+pub struct LfuPolicy<K> {
+    /// The inner state
+    inner: Mutex<LfuInner<K>>,
+    /// The capacity of this policy
+    capacity: usize,
+}
+
+impl<K: Hash + Eq> LfuPolicy<K> {
+    /// Create a new `LfuPolicy` with the given `capacity`.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        LfuPolicy {
+            inner: Mutex::new(LfuInner {
+                entries: HashMap::with_capacity(capacity),
+                order: BTreeMap::new(),
+                sequence: 0,
+                pinned: HashSet::new(),
+            }),
+            capacity,
+        }
+    }
+}
+
+impl<K: Clone + Hash + Eq> EvictPolicy<K> for LfuPolicy<K> {
+    fn touch(&self, key: &K) {
+        self.inner.lock().access(key);
+    }
+
+    fn evict(&self) -> Option<K> {
+        let mut lfu = self.inner.lock();
+
+        let order = lfu
+            .order
+            .iter()
+            .find(|&(_, key)| !lfu.pinned.contains(key))
+            .map(|(&order, _)| order)?;
+        let key = lfu.order.remove(&order)?;
+        lfu.entries.remove(&key);
+
+        Some(key)
+    }
+
+    /// Attempt to insert `key` into the policy.
+    /// If the policy is full and does not contain the `key`, return false.
+    /// If the `key` is inserted successfully or already exists, return true.
+    ///
+    /// The existed key will considered to be touched.
+    fn try_put(&self, key: K) -> bool {
+        let mut lfu = self.inner.lock();
+
+        if lfu.access(&key) {
+            return true;
+        }
+
+        if lfu.entries.len() == self.capacity {
+            return false;
+        }
+
+        let order = (1, lfu.next_sequence());
+        lfu.order.insert(order, key.clone());
+        lfu.entries.insert(key, order);
+        true
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn size(&self) -> usize {
+        self.inner.lock().entries.len()
+    }
+
+    fn pin(&self, key: &K) {
+        self.inner.lock().pinned.insert(key.clone());
+    }
+
+    fn unpin(&self, key: &K) {
+        self.inner.lock().pinned.remove(key);
+    }
+
+    fn name(&self) -> &'static str {
+        "lfu"
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::default_numeric_fallback)]
+mod tests {
+    use super::{EvictPolicy, LfuPolicy};
+
+    /// Create a `LfuPolicy` of `i32`, with keys `1, 2, 3`.
+    fn create_lfu() -> LfuPolicy<i32> {
+        let cache = LfuPolicy::<i32>::new(3);
+
+        assert!(cache.try_put(1));
+        assert!(cache.try_put(2));
+        assert!(cache.try_put(3));
+
+        cache
+    }
+
+    #[test]
+    fn test_evict() {
+        let cache = create_lfu();
+
+        assert!(!cache.try_put(4));
+
+        let evicted = cache.evict();
+        assert_eq!(evicted, Some(1));
+        assert!(cache.try_put(4));
+        assert_eq!(cache.size(), 3);
+    }
+
+    #[test]
+    fn test_frequency() {
+        let cache = create_lfu();
+
+        cache.touch(&1);
+        cache.touch(&1);
+        cache.touch(&2);
+
+        // 3 (1) -> 2 (2) -> 1 (3)
+        assert_eq!(cache.evict(), Some(3));
+        assert_eq!(cache.evict(), Some(2));
+        assert_eq!(cache.evict(), Some(1));
+        assert_eq!(cache.evict(), None);
+    }
+
+    #[test]
+    fn test_pin() {
+        let cache = create_lfu();
+
+        cache.pin(&1);
+        assert_eq!(cache.evict(), Some(2));
+        assert_eq!(cache.evict(), Some(3));
+        assert_eq!(cache.evict(), None);
+
+        cache.unpin(&1);
+        assert_eq!(cache.evict(), Some(1));
+    }
+}
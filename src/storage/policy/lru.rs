@@ -1,17 +1,27 @@
 //! The LRU policy implementation.
 
+use std::collections::HashSet;
 use std::hash::Hash;
 
 use hashlink::LinkedHashSet;
 use parking_lot::Mutex;
 
-use super::EvictPolicy;
+use super::{pop_unpinned, EvictPolicy};
+
+/// The inner state of `LruPolicy`.
+#[derive(Debug)]
+struct LruInner<K> {
+    /// The inner hashlink
+    queue: LinkedHashSet<K>,
+    /// The pinned keys
+    pinned: HashSet<K>,
+}
 
 /// The evict policy based on LRU.
 #[derive(Debug)]
 pub struct LruPolicy<K> {
-    /// The inner hashlink
-    inner: Mutex<LinkedHashSet<K>>,
+    /// The inner state
+    inner: Mutex<LruInner<K>>,
     /// The capacity of this policy
     capacity: usize,
 }
@@ -21,7 +31,10 @@ impl<K: Hash + Eq> LruPolicy<K> {
     #[must_use]
     pub fn new(capacity: usize) -> Self {
         LruPolicy {
-            inner: Mutex::new(LinkedHashSet::with_capacity(capacity)),
+            inner: Mutex::new(LruInner {
+                queue: LinkedHashSet::with_capacity(capacity),
+                pinned: HashSet::new(),
+            }),
             capacity,
         }
     }
@@ -30,14 +43,18 @@ impl<K: Hash + Eq> LruPolicy<K> {
 impl<K: Clone + Hash + Eq> EvictPolicy<K> for LruPolicy<K> {
     /// Update the position of the given key to mark it as recently used.
     fn touch(&self, key: &K) {
-        self.inner.lock().to_back(key);
+        self.inner.lock().queue.to_back(key);
     }
 
-    /// Remove and return the least recently used item.
+    /// Remove and return the least recently used item, which is not pinned.
     fn evict(&self) -> Option<K> {
         let mut lru = self.inner.lock();
+        let LruInner {
+            ref mut queue,
+            ref pinned,
+        } = *lru;
 
-        lru.pop_front()
+        pop_unpinned(queue, pinned)
     }
 
     /// Attempt to insert `key` into the policy.
@@ -47,12 +64,12 @@ impl<K: Clone + Hash + Eq> EvictPolicy<K> for LruPolicy<K> {
     /// The existed key will considered to be touched.
     fn try_put(&self, key: K) -> bool {
         let mut lru = self.inner.lock();
-        let len = lru.len();
+        let len = lru.queue.len();
 
-        if !lru.contains(&key) && len == self.capacity {
+        if !lru.queue.contains(&key) && len == self.capacity {
             false
         } else {
-            lru.insert(key);
+            lru.queue.insert(key);
             true
         }
     }
@@ -64,7 +81,19 @@ impl<K: Clone + Hash + Eq> EvictPolicy<K> for LruPolicy<K> {
 
     /// Return the current number of items in the LRU policy.
     fn size(&self) -> usize {
-        self.inner.lock().len()
+        self.inner.lock().queue.len()
+    }
+
+    fn pin(&self, key: &K) {
+        self.inner.lock().pinned.insert(key.clone());
+    }
+
+    fn unpin(&self, key: &K) {
+        self.inner.lock().pinned.remove(key);
+    }
+
+    fn name(&self) -> &'static str {
+        "lru"
     }
 }
 
@@ -140,4 +169,19 @@ mod tests {
         assert_eq!(cache.capacity(), 3);
         assert_eq!(cache.size(), 2);
     }
+
+    #[test]
+    fn test_pin() {
+        let cache = create_lru();
+
+        cache.pin(&1);
+
+        // 1 (pinned) -> 2 -> 3
+        let evicted = cache.evict();
+        assert_eq!(evicted, Some(2));
+
+        cache.unpin(&1);
+        let evicted = cache.evict();
+        assert_eq!(evicted, Some(1));
+    }
 }
@@ -1,7 +1,18 @@
 //! Evict policies for cache.
 
+mod arc;
+mod clock;
+mod lfu;
 mod lru;
 
+use std::collections::HashSet;
+use std::hash::Hash;
+
+pub use arc::ArcPolicy;
+pub use clock::ClockPolicy;
+use datenlord::config::EvictPolicyType;
+use hashlink::LinkedHashSet;
+pub use lfu::LfuPolicy;
 pub use lru::LruPolicy;
 
 /// The evict policy trait.
@@ -12,9 +23,9 @@ pub trait EvictPolicy<K> {
     /// This may make no differences in some policy, such as FIFO.
     fn touch(&self, key: &K);
 
-    /// Evict a key from the policy manually.
+    /// Evict a key from the policy manually. Pinned keys are never evicted.
     ///
-    /// If the policy is empty, returns None.
+    /// If the policy is empty, or all keys are pinned, returns None.
     fn evict(&self) -> Option<K>;
 
     /// Try to put a key into the policy.
@@ -27,4 +38,81 @@ pub trait EvictPolicy<K> {
 
     /// Returns the current size of this policy.
     fn size(&self) -> usize;
+
+    /// Pin a key, so that it will not be evicted until it's unpinned.
+    ///
+    /// Pinning does not put the key into the policy, and a pinned key still
+    /// takes a place of the capacity once it's put.
+    fn pin(&self, key: &K);
+
+    /// Unpin a key.
+    fn unpin(&self, key: &K);
+
+    /// Returns the name of this policy, it's used as a label of metrics.
+    fn name(&self) -> &'static str;
+}
+
+impl<K, T> EvictPolicy<K> for Box<T>
+where
+    T: EvictPolicy<K> + ?Sized,
+{
+    fn touch(&self, key: &K) {
+        self.as_ref().touch(key);
+    }
+
+    fn evict(&self) -> Option<K> {
+        self.as_ref().evict()
+    }
+
+    fn try_put(&self, key: K) -> bool {
+        self.as_ref().try_put(key)
+    }
+
+    fn capacity(&self) -> usize {
+        self.as_ref().capacity()
+    }
+
+    fn size(&self) -> usize {
+        self.as_ref().size()
+    }
+
+    fn pin(&self, key: &K) {
+        self.as_ref().pin(key);
+    }
+
+    fn unpin(&self, key: &K) {
+        self.as_ref().unpin(key);
+    }
+
+    fn name(&self) -> &'static str {
+        self.as_ref().name()
+    }
+}
+
+/// An evict policy chosen at runtime.
+pub type BoxedPolicy<K> = Box<dyn EvictPolicy<K> + Send + Sync>;
+
+/// Create an evict policy of `policy_type` with the given `capacity`.
+#[must_use]
+pub fn new_policy<K>(policy_type: EvictPolicyType, capacity: usize) -> BoxedPolicy<K>
+where
+    K: Clone + Hash + Eq + Send + 'static,
+{
+    match policy_type {
+        EvictPolicyType::Lru => Box::new(LruPolicy::new(capacity)),
+        EvictPolicyType::Lfu => Box::new(LfuPolicy::new(capacity)),
+        EvictPolicyType::Arc => Box::new(ArcPolicy::new(capacity)),
+        EvictPolicyType::Clock => Box::new(ClockPolicy::new(capacity)),
+    }
+}
+
+/// Remove and return the least recently used key in `queue` which is not
+/// pinned.
+fn pop_unpinned<K: Clone + Hash + Eq>(
+    queue: &mut LinkedHashSet<K>,
+    pinned: &HashSet<K>,
+) -> Option<K> {
+    let key = queue.iter().find(|key| !pinned.contains(*key)).cloned()?;
+    queue.remove(&key);
+    Some(key)
 }
@@ -0,0 +1,133 @@
+//! The failure detection of replica nodes.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+
+use crate::storage::error::StorageResult;
+
+/// The `Membership` trait, which lists the nodes alive, usually by their
+/// registrations renewed with leases in the metadata store.
+#[async_trait]
+pub trait Membership {
+    /// List the ids of the nodes registered.
+    async fn list_members(&self) -> StorageResult<Vec<String>>;
+}
+
+/// The state of a replica node.
+#[derive(Debug, Clone, Copy)]
+struct NodeState {
+    /// The last time the node is seen registered
+    last_heartbeat: Instant,
+    /// Whether the node is marked dead
+    dead: bool,
+}
+
+/// The nodes whose states are changed by a check.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HealthChange {
+    /// The nodes marked dead
+    pub dead: Vec<String>,
+    /// The dead nodes seen again
+    pub revived: Vec<String>,
+}
+
+impl HealthChange {
+    /// Whether no node is changed.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.dead.is_empty() && self.revived.is_empty()
+    }
+}
+
+/// A failure detector of replica nodes, a node is marked dead if it's not
+/// seen registered for `timeout`, and alive again once it's seen.
+///
+/// All nodes are alive at first, so they have `timeout` to register after
+/// this node starts.
+#[derive(Debug)]
+pub struct FailureDetector {
+    /// How long a node is not seen before it's marked dead
+    timeout: Duration,
+    /// When the detector is created
+    started: Instant,
+    /// The states of the replica nodes
+    nodes: Mutex<BTreeMap<String, NodeState>>,
+}
+
+impl FailureDetector {
+    /// Create a `FailureDetector` of the replica nodes `node_ids`.
+    #[must_use]
+    pub fn new<'a>(node_ids: impl IntoIterator<Item = &'a String>, timeout: Duration) -> Self {
+        let now = Instant::now();
+        let nodes = node_ids
+            .into_iter()
+            .map(|node| {
+                let state = NodeState {
+                    last_heartbeat: now,
+                    dead: false,
+                };
+                (node.clone(), state)
+            })
+            .collect();
+        Self {
+            timeout,
+            started: now,
+            nodes: Mutex::new(nodes),
+        }
+    }
+
+    /// Record that node `node_id` is seen at `now`, the nodes other than the
+    /// replica nodes are ignored.
+    pub fn heartbeat(&self, node_id: &str, now: Instant) {
+        if let Some(state) = self.nodes.lock().get_mut(node_id) {
+            state.last_heartbeat = state.last_heartbeat.max(now);
+        }
+    }
+
+    /// Update the states of the nodes at `now`, returns the nodes changed.
+    pub fn check(&self, now: Instant) -> HealthChange {
+        let mut change = HealthChange::default();
+        for (node, state) in self.nodes.lock().iter_mut() {
+            let dead = now.saturating_duration_since(state.last_heartbeat) >= self.timeout;
+            if dead != state.dead {
+                state.dead = dead;
+                if dead {
+                    change.dead.push(node.clone());
+                } else {
+                    change.revived.push(node.clone());
+                }
+            }
+        }
+        change
+    }
+
+    /// Whether every node has had the timeout to register at `now`, so the
+    /// nodes alive are known.
+    #[must_use]
+    pub fn settled(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.started) >= self.timeout
+    }
+
+    /// Whether node `node_id` is alive, the unknown nodes are dead.
+    #[must_use]
+    pub fn is_alive(&self, node_id: &str) -> bool {
+        self.nodes
+            .lock()
+            .get(node_id)
+            .map_or(false, |state| !state.dead)
+    }
+
+    /// The ids of the nodes alive, sorted.
+    #[must_use]
+    pub fn alive_nodes(&self) -> Vec<String> {
+        self.nodes
+            .lock()
+            .iter()
+            .filter(|&(_, state)| !state.dead)
+            .map(|(node, _)| node.clone())
+            .collect()
+    }
+}
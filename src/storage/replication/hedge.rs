@@ -0,0 +1,169 @@
+//! The hedging of the reads of replicas.
+//!
+//! A read of a replica slower than a percentile of the recent reads, the p99
+//! by default, is hedged by a read of the next replica, the first one to
+//! return the block wins and the other is cancelled. So a replica stalled by
+//! its disk or network doesn't hold a read beyond the tail latency of the
+//! others. The reads are not hedged until enough of them are seen to tell the
+//! tail latency, and a read is hedged once at most.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::time::Duration;
+
+use clippy_utilities::{Cast, OverflowArithmetic};
+use datenlord::metrics::STORAGE_METRICS;
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use parking_lot::Mutex;
+use tokio::select;
+use tokio::time::{self, Instant};
+
+/// The number of the recent reads the budget is computed from.
+const WINDOW: usize = 1024;
+/// The number of reads seen before the reads are hedged.
+const MIN_SAMPLES: usize = 100;
+/// The number of reads between the updates of the budget.
+const UPDATE_INTERVAL: usize = 32;
+/// The least budget, so the reads served in memory are not doubled.
+const MIN_BUDGET: Duration = Duration::from_millis(1);
+
+/// The latencies of the recent reads.
+#[derive(Debug, Default)]
+struct Latencies {
+    /// The latencies, the latest last
+    samples: VecDeque<Duration>,
+    /// The latency a read is hedged beyond, `None` if too few reads are seen
+    budget: Option<Duration>,
+    /// The number of reads since the last update of the budget
+    unaccounted: usize,
+}
+
+/// The hedging of the reads of replicas.
+#[derive(Debug)]
+pub struct Hedge {
+    /// The percentile of the latencies a read is hedged beyond, `None` if the
+    /// reads are not hedged
+    percentile: Option<u32>,
+    /// The latencies of the recent reads
+    latencies: Mutex<Latencies>,
+}
+
+impl Hedge {
+    /// Create a `Hedge` hedging the reads slower than `percentile` of the
+    /// recent reads, the reads are not hedged if it's `None`.
+    pub fn new(percentile: Option<u32>) -> Self {
+        Self {
+            percentile,
+            latencies: Mutex::default(),
+        }
+    }
+
+    /// The latency a read is hedged beyond, `None` if it's not hedged.
+    pub fn budget(&self) -> Option<Duration> {
+        self.percentile?;
+        self.latencies.lock().budget
+    }
+
+    /// Record the latency of a read.
+    fn record(&self, latency: Duration) {
+        let Some(percentile) = self.percentile else {
+            return;
+        };
+        let mut latencies = self.latencies.lock();
+        latencies.samples.push_back(latency);
+        if latencies.samples.len() > WINDOW {
+            latencies.samples.pop_front();
+        }
+        latencies.unaccounted = latencies.unaccounted.overflow_add(1);
+        if latencies.unaccounted < UPDATE_INTERVAL || latencies.samples.len() < MIN_SAMPLES {
+            return;
+        }
+
+        let mut sorted: Vec<Duration> = latencies.samples.iter().copied().collect();
+        let index = sorted
+            .len()
+            .overflow_mul(percentile.cast())
+            .overflow_div(100)
+            .min(sorted.len().overflow_sub(1));
+        let (_, &mut budget, _) = sorted.select_nth_unstable(index);
+        latencies.budget = Some(budget.max(MIN_BUDGET));
+        latencies.unaccounted = 0;
+    }
+
+    /// Read from `sources` in order by `read`, returns the first value read,
+    /// or the errors of all the sources in the order they fail.
+    ///
+    /// A read slower than the budget is hedged by a read of the next source,
+    /// and a failed read falls back to the next source if no other read is
+    /// pending.
+    #[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+    pub async fn read<S, I, T, E, F, Fut>(&self, sources: I, mut read: F) -> Result<T, Vec<E>>
+    where
+        I: IntoIterator<Item = S>,
+        F: FnMut(S) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        STORAGE_METRICS.replica_read_count_inc();
+        let budget = self.budget();
+        let mut sources = sources.into_iter().peekable();
+        // The start of the read of every source, `None` once it fails.
+        let mut starts: Vec<Option<Instant>> = vec![];
+        let mut pending = FuturesUnordered::new();
+        // The nth read hedging a slow read, `None` if it's not hedged
+        let mut hedged = None;
+        let mut errors = vec![];
+
+        loop {
+            if pending.is_empty() {
+                let Some(source) = sources.next() else {
+                    if hedged.is_some() {
+                        STORAGE_METRICS.replica_hedged_read_count_inc("none");
+                    }
+                    return Err(errors);
+                };
+                pending.push(tagged(starts.len(), read(source)));
+                starts.push(Some(Instant::now()));
+            }
+
+            let hedgeable = hedged.is_none() && sources.peek().is_some();
+            let hedge_at = budget
+                .filter(|_| hedgeable)
+                .zip(starts.last().copied().flatten())
+                .and_then(|(budget, start)| start.checked_add(budget));
+            let hedge_timer = time::sleep_until(hedge_at.unwrap_or_else(Instant::now));
+            select! {
+                Some((nth, result)) = pending.next() => match result {
+                    Ok(value) => {
+                        for start in starts.iter().flatten() {
+                            self.record(start.elapsed());
+                        }
+                        if let Some(hedge) = hedged {
+                            let winner = if nth == hedge { "hedge" } else { "primary" };
+                            STORAGE_METRICS.replica_hedged_read_count_inc(winner);
+                        }
+                        return Ok(value);
+                    }
+                    Err(e) => {
+                        errors.push(e);
+                        if let Some(start) = starts.get_mut(nth) {
+                            *start = None;
+                        }
+                    }
+                },
+                () = hedge_timer, if hedge_at.is_some() => {
+                    if let Some(source) = sources.next() {
+                        hedged = Some(starts.len());
+                        pending.push(tagged(starts.len(), read(source)));
+                        starts.push(Some(Instant::now()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Tag the output of the `nth` read.
+async fn tagged<Fut: Future>(nth: usize, fut: Fut) -> (usize, Fut::Output) {
+    (nth, fut.await)
+}
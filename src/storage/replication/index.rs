@@ -0,0 +1,138 @@
+//! The index of block replicas.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+
+use super::BlockReplicas;
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::error::StorageResult;
+
+/// The `ReplicaIndex` trait, which records the nodes holding the replicas of
+/// blocks.
+#[async_trait]
+pub trait ReplicaIndex {
+    /// Get the replica record of a block.
+    ///
+    /// Returns `None` if the block has no replica recorded.
+    async fn get_replicas(
+        &self,
+        ino: INum,
+        block_id: usize,
+    ) -> StorageResult<Option<BlockReplicas>>;
+
+    /// Set the replica record of a block, the previous record will be
+    /// overwritten.
+    async fn set_replicas(&self, record: BlockReplicas) -> StorageResult<()>;
+
+    /// Set the replica record of a block atomically, if the current record is
+    /// `expected`. Returns whether it's set.
+    async fn compare_and_set_replicas(
+        &self,
+        expected: &BlockReplicas,
+        record: BlockReplicas,
+    ) -> StorageResult<bool>;
+
+    /// Remove the replica records of blocks of a file in `block_ids`.
+    async fn remove_replicas(&self, ino: INum, block_ids: Range<usize>) -> StorageResult<()>;
+
+    /// List all replica records.
+    async fn list_replicas(&self) -> StorageResult<Vec<BlockReplicas>>;
+}
+
+#[async_trait]
+impl<T> ReplicaIndex for Arc<T>
+where
+    T: ReplicaIndex + Send + Sync,
+{
+    async fn get_replicas(
+        &self,
+        ino: INum,
+        block_id: usize,
+    ) -> StorageResult<Option<BlockReplicas>> {
+        self.as_ref().get_replicas(ino, block_id).await
+    }
+
+    async fn set_replicas(&self, record: BlockReplicas) -> StorageResult<()> {
+        self.as_ref().set_replicas(record).await
+    }
+
+    async fn compare_and_set_replicas(
+        &self,
+        expected: &BlockReplicas,
+        record: BlockReplicas,
+    ) -> StorageResult<bool> {
+        self.as_ref()
+            .compare_and_set_replicas(expected, record)
+            .await
+    }
+
+    async fn remove_replicas(&self, ino: INum, block_ids: Range<usize>) -> StorageResult<()> {
+        self.as_ref().remove_replicas(ino, block_ids).await
+    }
+
+    async fn list_replicas(&self) -> StorageResult<Vec<BlockReplicas>> {
+        self.as_ref().list_replicas().await
+    }
+}
+
+/// A `ReplicaIndex` in memory, which is not persisted.
+#[derive(Debug, Default)]
+pub struct MemoryReplicaIndex {
+    /// The replica records
+    replicas: Mutex<BTreeMap<(INum, usize), BlockReplicas>>,
+}
+
+impl MemoryReplicaIndex {
+    /// Create an empty `MemoryReplicaIndex`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl ReplicaIndex for MemoryReplicaIndex {
+    async fn get_replicas(
+        &self,
+        ino: INum,
+        block_id: usize,
+    ) -> StorageResult<Option<BlockReplicas>> {
+        Ok(self.replicas.lock().get(&(ino, block_id)).cloned())
+    }
+
+    async fn set_replicas(&self, record: BlockReplicas) -> StorageResult<()> {
+        self.replicas
+            .lock()
+            .insert((record.ino, record.block_id), record);
+        Ok(())
+    }
+
+    async fn compare_and_set_replicas(
+        &self,
+        expected: &BlockReplicas,
+        record: BlockReplicas,
+    ) -> StorageResult<bool> {
+        let mut replicas = self.replicas.lock();
+        let key = (record.ino, record.block_id);
+        if replicas.get(&key) != Some(expected) {
+            return Ok(false);
+        }
+        replicas.insert(key, record);
+        Ok(true)
+    }
+
+    async fn remove_replicas(&self, ino: INum, block_ids: Range<usize>) -> StorageResult<()> {
+        self.replicas
+            .lock()
+            .retain(|&(key_ino, block_id), _| key_ino != ino || !block_ids.contains(&block_id));
+        Ok(())
+    }
+
+    async fn list_replicas(&self) -> StorageResult<Vec<BlockReplicas>> {
+        Ok(self.replicas.lock().values().cloned().collect())
+    }
+}
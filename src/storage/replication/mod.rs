@@ -0,0 +1,84 @@
+//! The synchronous replication of blocks among storage nodes.
+//!
+//! Every block of a replicated volume is stored by `factor` replica nodes,
+//! which are placed by rendezvous hashing over the replica nodes, so the
+//! blocks are spread evenly and a new node only takes over its share of them.
+//! The nodes holding a block are recorded in a [`ReplicaIndex`], which is
+//! usually the metadata store, so a block is found after the replica nodes
+//! change.
+//!
+//! A block is written to all its replicas concurrently, and the write is
+//! acknowledged only if the write quorum of them persist it, otherwise it
+//! fails with `EIO`. The record only keeps the replicas persisting the latest
+//! write, so a replica missing a write is never read. Reads try the local
+//! replica first and fall back to any other replica.
+//!
+//! The replica nodes are watched by a [`FailureDetector`], a node not seen
+//! registered in the [`Membership`] for a while is dead. The blocks are not
+//! placed on or read from the dead nodes, and a repair pass replicates the
+//! blocks with too few replicas alive to other nodes, once a node is dead or
+//! alive again.
+//!
+//! Once the replica nodes change, a rebalance pass moves the replicas of every
+//! block to the nodes alive they're placed on, so a new node takes over its
+//! share of the blocks, and the extra replicas left by the repairs are
+//! removed once the dead nodes are back. A block is copied to
+//! its new replicas before its record is updated, and is removed from its old
+//! replicas afterwards, the moves are rate limited.
+//!
+//! A read of a replica slower than the tail latency of the recent reads is
+//! hedged by a read of the next replica, see `hedge`, so a stalled replica
+//! doesn't leak into the latency of the reads.
+
+mod health;
+mod hedge;
+mod index;
+mod rebalance;
+mod store;
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+pub use datenlord::config::WriteQuorum;
+pub use health::{FailureDetector, HealthChange, Membership};
+pub use index::{MemoryReplicaIndex, ReplicaIndex};
+pub use rebalance::{plan_moves, BlockMove};
+use serde::{Deserialize, Serialize};
+pub use store::{RebalanceReport, RepairReport, ReplicaStore, ReplicatedBlockStore};
+
+use crate::async_fuse::fuse::protocol::INum;
+
+/// The replica record of a block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockReplicas {
+    /// The inode number of the file
+    pub ino: INum,
+    /// The index of the block in the file
+    pub block_id: usize,
+    /// The ids of the nodes holding the latest content of the block
+    pub nodes: Vec<String>,
+}
+
+/// Place the replicas of a block on `factor` nodes of `nodes`, by the highest
+/// random weights of the nodes with the block.
+#[must_use]
+pub fn place_replicas(nodes: &[String], ino: INum, block_id: usize, factor: usize) -> Vec<String> {
+    let mut weighted: Vec<(u64, &String)> = nodes
+        .iter()
+        .map(|node| {
+            let mut hasher = DefaultHasher::new();
+            (node, ino, block_id).hash(&mut hasher);
+            (hasher.finish(), node)
+        })
+        .collect();
+    weighted.sort_unstable_by(|a, b| b.cmp(a));
+    weighted
+        .into_iter()
+        .take(factor)
+        .map(|(_, node)| node.clone())
+        .collect()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests;
@@ -0,0 +1,44 @@
+//! The plans of moving replicas, when the replica nodes change.
+
+use super::{place_replicas, BlockReplicas};
+
+/// A move of the replicas of a block to the nodes they're placed on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockMove {
+    /// The replica record when the move is planned, the move is skipped if
+    /// the record is changed before it's done
+    pub record: BlockReplicas,
+    /// The nodes to copy the block to
+    pub to: Vec<String>,
+    /// The nodes to remove the block from, once it's copied to all of `to`
+    pub from: Vec<String>,
+}
+
+/// Plan the moves of the blocks of `records`, so the replicas of every block
+/// are on the `factor` nodes placed among the nodes `alive`.
+///
+/// The replicas on the dead nodes are neither moved nor removed, they're
+/// removed by a later plan once the nodes are alive again. The blocks without
+/// any replica alive are not moved.
+#[must_use]
+pub fn plan_moves(records: Vec<BlockReplicas>, alive: &[String], factor: usize) -> Vec<BlockMove> {
+    records
+        .into_iter()
+        .filter(|record| record.nodes.iter().any(|node| alive.contains(node)))
+        .filter_map(|record| {
+            let placed = place_replicas(alive, record.ino, record.block_id, factor);
+            let to: Vec<String> = placed
+                .iter()
+                .filter(|&node| !record.nodes.contains(node))
+                .cloned()
+                .collect();
+            let from: Vec<String> = record
+                .nodes
+                .iter()
+                .filter(|&node| alive.contains(node) && !placed.contains(node))
+                .cloned()
+                .collect();
+            (!to.is_empty() || !from.is_empty()).then_some(BlockMove { record, to, from })
+        })
+        .collect()
+}
@@ -0,0 +1,671 @@
+//! The block store replicating blocks to replica nodes.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use clippy_utilities::OverflowArithmetic;
+use futures::future;
+use nix::errno::Errno;
+use tokio::select;
+use tokio::sync::RwLock;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use super::health::{FailureDetector, Membership};
+use super::hedge::Hedge;
+use super::rebalance::{plan_moves, BlockMove};
+use super::{place_replicas, BlockReplicas, ReplicaIndex, WriteQuorum};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::checksum::ReplicaRepair;
+use crate::storage::condition::STORAGE_CONDITION;
+use crate::storage::error::{StorageError, StorageResult};
+use crate::storage::{BlockKey, BlockStore};
+
+/// The component name of the replication in the condition of the storage.
+const REPLICATION_COMPONENT: &str = "replication";
+
+/// The blocks of a replica node.
+pub type ReplicaStore = Arc<dyn BlockStore + Send + Sync>;
+
+/// The result of a repair pass.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RepairReport {
+    /// The number of blocks replicated to enough nodes alive
+    pub repaired: usize,
+    /// The number of blocks still under-replicated, which are retried by the
+    /// next pass
+    pub pending: usize,
+}
+
+/// The result of a rebalance pass.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RebalanceReport {
+    /// The number of blocks planned to move
+    pub planned: usize,
+    /// The number of blocks moved to the nodes they're placed on
+    pub moved: usize,
+    /// The number of blocks failed to move, which are retried by the next
+    /// pass
+    pub pending: usize,
+}
+
+/// A `BlockStore` storing every block on several replica nodes.
+///
+/// Blocks are addressed by their files and indexes only, so it's not used
+/// with the storage layers keeping versions of blocks.
+pub struct ReplicatedBlockStore<I> {
+    /// The index of replicas
+    index: I,
+    /// The id of this node, whose replica is read first
+    node_id: String,
+    /// The replica nodes
+    replicas: BTreeMap<String, ReplicaStore>,
+    /// The failure detector of the replica nodes, the replicas are placed on
+    /// and read from the nodes alive only
+    detector: FailureDetector,
+    /// The number of replicas of every block
+    factor: usize,
+    /// The number of replicas persisting a block before a write is
+    /// acknowledged
+    quorum: usize,
+    /// The minimal interval between moves of blocks by a rebalance pass,
+    /// `None` if the moves are not rate limited
+    move_interval: Option<Duration>,
+    /// The hedging of the reads of replicas
+    hedge: Hedge,
+    /// Writes and deletes hold it shared, and repairing or moving a block
+    /// holds it exclusively, so the record of a block is not overwritten with
+    /// the replicas missing a write.
+    moving: RwLock<()>,
+}
+
+impl<I: Debug> Debug for ReplicatedBlockStore<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplicatedBlockStore")
+            .field("index", &self.index)
+            .field("node_id", &self.node_id)
+            .field("detector", &self.detector)
+            .field("factor", &self.factor)
+            .field("quorum", &self.quorum)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I> ReplicatedBlockStore<I>
+where
+    I: ReplicaIndex + Send + Sync,
+{
+    /// Create a `ReplicatedBlockStore` storing `factor` replicas of every
+    /// block on the `replicas` nodes, a node is dead if it's not seen
+    /// registered for `failure_timeout`.
+    pub fn new(
+        index: I,
+        node_id: &str,
+        replicas: BTreeMap<String, ReplicaStore>,
+        factor: usize,
+        quorum: WriteQuorum,
+        failure_timeout: Duration,
+    ) -> Self {
+        let detector = FailureDetector::new(replicas.keys(), failure_timeout);
+        Self {
+            index,
+            node_id: node_id.to_owned(),
+            replicas,
+            detector,
+            factor,
+            quorum: quorum.required(factor),
+            move_interval: None,
+            hedge: Hedge::new(None),
+            moving: RwLock::new(()),
+        }
+    }
+
+    /// Limit the moves of blocks by a rebalance pass to `rate` blocks per
+    /// second, the moves are not rate limited if it's 0.
+    #[must_use]
+    pub fn with_rebalance_rate(mut self, rate: u32) -> Self {
+        self.move_interval = Duration::from_secs(1)
+            .checked_div(rate)
+            .filter(|interval| !interval.is_zero());
+        self
+    }
+
+    /// Hedge the reads of replicas slower than `percentile` of the recent
+    /// reads by a read of another replica, the reads are not hedged if it's
+    /// `None`.
+    #[must_use]
+    pub fn with_hedge_percentile(mut self, percentile: Option<u32>) -> Self {
+        self.hedge = Hedge::new(percentile);
+        self
+    }
+
+    /// Get the store of a replica node.
+    fn replica(&self, node_id: &str) -> StorageResult<&ReplicaStore> {
+        self.replicas.get(node_id).ok_or_else(|| {
+            StorageError::Internal(anyhow::anyhow!("replica node {node_id} is unknown"))
+        })
+    }
+
+    /// Order the replicas of a block to read, the local replica goes first,
+    /// and the ones on the dead nodes are skipped.
+    fn read_order<'a>(&self, nodes: &'a [String]) -> Vec<&'a String> {
+        let (mut ordered, remote): (Vec<_>, Vec<_>) = nodes
+            .iter()
+            .filter(|node| self.detector.is_alive(node))
+            .partition(|&node| *node == self.node_id);
+        ordered.extend(remote);
+        ordered
+    }
+
+    /// Read a block from the replicas alive in `nodes`, a slow read is hedged
+    /// by a read of the next replica.
+    async fn read_replicas(
+        &self,
+        key: BlockKey,
+        nodes: &[String],
+    ) -> StorageResult<Option<Vec<u8>>> {
+        let ordered = self.read_order(nodes);
+        if ordered.is_empty() {
+            return Err(StorageError::ReplicasUnavailable {
+                ino: key.ino,
+                block_id: key.block_id,
+                source: Errno::EIO,
+            });
+        }
+
+        let result = self
+            .hedge
+            .read(ordered, |node| async move {
+                let result = match self.replica(node) {
+                    Ok(replica) => replica.get(key).await,
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(Some(data)) => Ok(data),
+                    Ok(None) => {
+                        warn!("Block {key:?} is missing on replica node {node}.");
+                        Err(None)
+                    }
+                    Err(e) => {
+                        warn!("Failed to read block {key:?} from replica node {node}: {e}");
+                        Err(Some(e))
+                    }
+                }
+            })
+            .await;
+        match result {
+            Ok(data) => Ok(Some(data)),
+            // The block may be on the replicas failing to respond.
+            Err(errors) => errors
+                .into_iter()
+                .flatten()
+                .next_back()
+                .map_or(Ok(None), Err),
+        }
+    }
+
+    /// Replicate a block to enough nodes alive, returns whether it's
+    /// replicated to `factor` nodes, or all the nodes alive if they're fewer.
+    async fn repair_block(&self, ino: INum, block_id: usize) -> StorageResult<bool> {
+        let _guard = self.moving.write().await;
+        // The block may be removed or rewritten during the pass, repair with
+        // the latest record.
+        let Some(record) = self.index.get_replicas(ino, block_id).await? else {
+            return Ok(true);
+        };
+        let alive = self.detector.alive_nodes();
+        let target = self.factor.min(alive.len());
+        let alive_replicas = count_alive(&record.nodes, &alive);
+        if alive_replicas >= target {
+            return Ok(true);
+        }
+
+        let key = BlockKey { ino, block_id };
+        let data = match self.read_replicas(key, &record.nodes).await {
+            Ok(Some(data)) => data,
+            Ok(None) => {
+                warn!("Block {key:?} is missing on all replica nodes alive, it's not repaired.");
+                return Ok(false);
+            }
+            Err(e) => {
+                warn!("Failed to read block {key:?} to repair: {e}");
+                return Ok(false);
+            }
+        };
+        // The new replicas are placed in the order of the placement, so they
+        // stay where the writes place them.
+        let missing = target.overflow_sub(alive_replicas);
+        let mut nodes = record.nodes.clone();
+        let candidates: Vec<String> = place_replicas(&alive, ino, block_id, alive.len())
+            .into_iter()
+            .filter(|node| !nodes.contains(node))
+            .collect();
+        let writes = candidates.iter().take(missing).map(|node| {
+            let data = data.clone();
+            async move { self.replica(node)?.put(key, data).await }
+        });
+        let results = future::join_all(writes).await;
+        let mut repaired = alive_replicas;
+        for (node, result) in candidates.into_iter().zip(results) {
+            match result {
+                Ok(()) => {
+                    nodes.push(node);
+                    repaired = repaired.overflow_add(1);
+                }
+                Err(e) => warn!("Failed to repair block {key:?} on replica node {node}: {e}"),
+            }
+        }
+
+        // The replicas on the dead nodes are kept in the record, as they hold
+        // the latest content, a write while they're dead removes them. The
+        // block rewritten by another node meanwhile is placed by the write.
+        let updated = BlockReplicas {
+            ino,
+            block_id,
+            nodes,
+        };
+        if !self
+            .index
+            .compare_and_set_replicas(&record, updated)
+            .await?
+        {
+            return Ok(true);
+        }
+        Ok(repaired >= target)
+    }
+
+    /// Run a repair pass, every block with fewer replicas alive than `factor`
+    /// is replicated to other nodes alive.
+    pub async fn repair(&self) -> StorageResult<RepairReport> {
+        let mut report = RepairReport::default();
+        let alive = self.detector.alive_nodes();
+        let target = self.factor.min(alive.len());
+        for record in self.index.list_replicas().await? {
+            if count_alive(&record.nodes, &alive) >= target {
+                continue;
+            }
+            if self.repair_block(record.ino, record.block_id).await? {
+                report.repaired = report.repaired.overflow_add(1);
+            } else {
+                report.pending = report.pending.overflow_add(1);
+            }
+        }
+        Ok(report)
+    }
+
+    /// Move a block by `plan`, returns whether it's moved or not planned any
+    /// more.
+    ///
+    /// The block is copied to the new replicas before the record is updated,
+    /// and is removed from the old replicas afterwards, so it's always
+    /// readable during the move.
+    async fn move_block(&self, plan: BlockMove) -> StorageResult<bool> {
+        let _guard = self.moving.write().await;
+        let BlockMove { record, to, from } = plan;
+        let (ino, block_id) = (record.ino, record.block_id);
+        // The block is rewritten or removed after the plan, and placed by the
+        // writes already.
+        if self.index.get_replicas(ino, block_id).await?.as_ref() != Some(&record) {
+            return Ok(true);
+        }
+
+        let key = BlockKey { ino, block_id };
+        let mut nodes = record.nodes.clone();
+        if !to.is_empty() {
+            let data = match self.read_replicas(key, &nodes).await {
+                Ok(Some(data)) => data,
+                Ok(None) => {
+                    warn!("Block {key:?} is missing on all replica nodes alive, it's not moved.");
+                    return Ok(false);
+                }
+                Err(e) => {
+                    warn!("Failed to read block {key:?} to move: {e}");
+                    return Ok(false);
+                }
+            };
+            let writes = to.iter().map(|node| {
+                let data = data.clone();
+                async move { self.replica(node)?.put(key, data).await }
+            });
+            let results = future::join_all(writes).await;
+            let mut copied = vec![];
+            for (node, result) in to.into_iter().zip(results) {
+                match result {
+                    Ok(()) => copied.push(node),
+                    Err(e) => warn!("Failed to move block {key:?} to replica node {node}: {e}"),
+                }
+            }
+            if copied.is_empty() {
+                return Ok(false);
+            }
+            let all_copied = copied.len() == to.len();
+            nodes.extend(copied);
+            if !all_copied {
+                // The old replicas are kept until the block is on all the new
+                // ones.
+                let updated = BlockReplicas {
+                    ino,
+                    block_id,
+                    nodes,
+                };
+                let set = self
+                    .index
+                    .compare_and_set_replicas(&record, updated)
+                    .await?;
+                return Ok(!set);
+            }
+        }
+
+        nodes.retain(|node| !from.contains(node));
+        let updated = BlockReplicas {
+            ino,
+            block_id,
+            nodes,
+        };
+        // The block rewritten by another node meanwhile is placed by the
+        // write, and the old replicas may be in its record.
+        if !self
+            .index
+            .compare_and_set_replicas(&record, updated)
+            .await?
+        {
+            return Ok(true);
+        }
+        // The old replicas are not reachable once the record is updated, so
+        // the ones failed to remove are only logged.
+        for node in &from {
+            let result = async { self.replica(node)?.delete(key).await }.await;
+            if let Err(e) = result {
+                warn!("Failed to remove block {key:?} from replica node {node}: {e}");
+            }
+        }
+        Ok(true)
+    }
+
+    /// Run a rebalance pass, the replicas of every block are moved to the
+    /// nodes alive they're placed on, so the new nodes take over their share
+    /// of the blocks, and the nodes removed are drained.
+    pub async fn rebalance(&self) -> StorageResult<RebalanceReport> {
+        let alive = self.detector.alive_nodes();
+        let plans = plan_moves(self.index.list_replicas().await?, &alive, self.factor);
+        let mut report = RebalanceReport {
+            planned: plans.len(),
+            ..RebalanceReport::default()
+        };
+        let mut ticker = self.move_interval.map(|interval| {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            ticker
+        });
+
+        for plan in plans {
+            if let Some(ref mut ticker) = ticker {
+                ticker.tick().await;
+            }
+            if self.move_block(plan).await? {
+                report.moved = report.moved.overflow_add(1);
+            } else {
+                report.pending = report.pending.overflow_add(1);
+            }
+        }
+        Ok(report)
+    }
+
+    /// Check the health of the replica nodes by `membership` every
+    /// `interval`, until `token` is cancelled. Once a node is dead or alive
+    /// again, a repair pass and a rebalance pass are run, and they're retried
+    /// every `interval` until no block is pending.
+    ///
+    /// A rebalance pass is run at first as well, once every node has had the
+    /// failure timeout to register, since the replica nodes may be changed
+    /// while this node is down.
+    ///
+    /// The passes are run by the first replica node alive only, so the nodes
+    /// don't move the same blocks concurrently. Another node takes over once
+    /// it's dead.
+    #[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+    pub async fn run_health_checker<M: Membership + Send + Sync>(
+        self: Arc<Self>,
+        membership: M,
+        interval: Duration,
+        token: CancellationToken,
+    ) {
+        let mut ticker = tokio::time::interval(interval);
+        let mut repair_pending = false;
+        let mut rebalance_pending = true;
+
+        loop {
+            select! {
+                _ = ticker.tick() => {
+                    // The nodes are not marked dead if the members are unknown.
+                    let members = match membership.list_members().await {
+                        Ok(members) => members,
+                        Err(e) => {
+                            error!("Failed to list the members: {e}");
+                            continue;
+                        }
+                    };
+                    let now = Instant::now();
+                    for member in &members {
+                        self.detector.heartbeat(member, now);
+                    }
+                    let change = self.detector.check(now);
+                    for node in &change.dead {
+                        warn!("Replica node {node} is dead.");
+                    }
+                    for node in &change.revived {
+                        info!("Replica node {node} is alive again.");
+                    }
+                    if !change.is_empty() {
+                        repair_pending = true;
+                        rebalance_pending = true;
+                    }
+                    let alive = self.detector.alive_nodes().len();
+                    let problem = (alive < self.quorum).then(|| {
+                        format!(
+                            "{alive} of {} replica nodes alive, fewer than the write quorum {}",
+                            self.replicas.len(),
+                            self.quorum
+                        )
+                    });
+                    STORAGE_CONDITION.report(REPLICATION_COMPONENT, problem);
+                    if self.detector.alive_nodes().first() != Some(&self.node_id) {
+                        continue;
+                    }
+
+                    if repair_pending {
+                        repair_pending = match self.repair().await {
+                            Ok(report) => {
+                                info!(
+                                    "Replica repair pass finished, {} blocks repaired, {} pending.",
+                                    report.repaired, report.pending
+                                );
+                                report.pending > 0
+                            }
+                            Err(e) => {
+                                error!("Replica repair pass failed: {e}");
+                                true
+                            }
+                        };
+                    }
+                    if rebalance_pending && self.detector.settled(now) {
+                        rebalance_pending = match self.rebalance().await {
+                            Ok(report) => {
+                                info!(
+                                    "Rebalance pass finished, {} of {} blocks moved, {} pending.",
+                                    report.moved, report.planned, report.pending
+                                );
+                                report.pending > 0
+                            }
+                            Err(e) => {
+                                error!("Rebalance pass failed: {e}");
+                                true
+                            }
+                        };
+                    }
+                }
+                () = token.cancelled() => {
+                    info!("Replica health checker exits.");
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Count the nodes in `nodes` which are `alive`.
+fn count_alive(nodes: &[String], alive: &[String]) -> usize {
+    nodes.iter().filter(|&node| alive.contains(node)).count()
+}
+
+#[async_trait]
+impl<I> BlockStore for ReplicatedBlockStore<I>
+where
+    I: ReplicaIndex + Send + Sync,
+{
+    async fn get(&self, key: BlockKey) -> StorageResult<Option<Vec<u8>>> {
+        let Some(record) = self.index.get_replicas(key.ino, key.block_id).await? else {
+            return Ok(None);
+        };
+        self.read_replicas(key, &record.nodes).await
+    }
+
+    async fn put(&self, key: BlockKey, data: Vec<u8>) -> StorageResult<()> {
+        let _guard = self.moving.read().await;
+        let nodes = place_replicas(
+            &self.detector.alive_nodes(),
+            key.ino,
+            key.block_id,
+            self.factor,
+        );
+        let writes = nodes.iter().map(|node| {
+            let data = data.clone();
+            async move { self.replica(node)?.put(key, data).await }
+        });
+        let results = future::join_all(writes).await;
+
+        let mut acked = vec![];
+        for (node, result) in nodes.into_iter().zip(results) {
+            match result {
+                Ok(()) => acked.push(node),
+                Err(e) => warn!("Failed to write block {key:?} to replica node {node}: {e}"),
+            }
+        }
+        let acked_count = acked.len();
+        // The replicas persisting the write hold the latest content, even if
+        // they're fewer than the quorum.
+        if !acked.is_empty() {
+            self.index
+                .set_replicas(BlockReplicas {
+                    ino: key.ino,
+                    block_id: key.block_id,
+                    nodes: acked,
+                })
+                .await?;
+        }
+        if acked_count < self.quorum {
+            return Err(StorageError::WriteQuorum {
+                ino: key.ino,
+                block_id: key.block_id,
+                acked: acked_count,
+                required: self.quorum,
+                source: Errno::EIO,
+            });
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, key: BlockKey) -> StorageResult<()> {
+        let _guard = self.moving.read().await;
+        let Some(record) = self.index.get_replicas(key.ino, key.block_id).await? else {
+            return Ok(());
+        };
+        self.index
+            .remove_replicas(key.ino, key.block_id..key.block_id.saturating_add(1))
+            .await?;
+        // The replicas are not reachable once the record is removed, so the
+        // ones failed to delete, or on the dead nodes, are left behind.
+        for node in self.read_order(&record.nodes) {
+            let result = async { self.replica(node)?.delete(key).await }.await;
+            if let Err(e) = result {
+                warn!("Failed to delete block {key:?} from replica node {node}: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    async fn delete_file(&self, ino: INum) -> StorageResult<()> {
+        let _guard = self.moving.read().await;
+        self.index.remove_replicas(ino, 0..usize::MAX).await?;
+        let deletes = self
+            .replicas
+            .iter()
+            .filter(|&(node, _)| self.detector.is_alive(node))
+            .map(|(node, replica)| async move { (node, replica.delete_file(ino).await) });
+        for (node, result) in future::join_all(deletes).await {
+            if let Err(e) = result {
+                warn!("Failed to delete file {ino} from replica node {node}: {e}");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<I> ReplicaRepair for ReplicatedBlockStore<I>
+where
+    I: ReplicaIndex + Send + Sync,
+{
+    async fn repair_replicas(
+        &self,
+        key: BlockKey,
+        healthy: &(dyn Fn(&[u8]) -> bool + Send + Sync),
+    ) -> StorageResult<Option<usize>> {
+        // The replicas are not rewritten or moved during the repair.
+        let _guard = self.moving.write().await;
+        let Some(record) = self.index.get_replicas(key.ino, key.block_id).await? else {
+            return Ok(None);
+        };
+
+        let mut healthy_data = None;
+        let mut corrupted = vec![];
+        for node in self.read_order(&record.nodes) {
+            let result = match self.replica(node) {
+                Ok(replica) => replica.get(key).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(Some(data)) if healthy(&data) => {
+                    healthy_data.get_or_insert(data);
+                }
+                Ok(Some(_)) => {
+                    warn!("Replica of block {key:?} on node {node} is corrupted.");
+                    corrupted.push(node);
+                }
+                Ok(None) => {
+                    warn!("Block {key:?} is missing on replica node {node}.");
+                    corrupted.push(node);
+                }
+                // The replica may be healthy, it's left to the next pass.
+                Err(e) => warn!("Failed to read block {key:?} from replica node {node}: {e}"),
+            }
+        }
+        let Some(data) = healthy_data else {
+            return Ok(None);
+        };
+
+        let mut rewritten = 0_usize;
+        for node in corrupted {
+            let result = async { self.replica(node)?.put(key, data.clone()).await }.await;
+            match result {
+                Ok(()) => rewritten = rewritten.overflow_add(1),
+                Err(e) => warn!("Failed to rewrite block {key:?} on replica node {node}: {e}"),
+            }
+        }
+        Ok(Some(rewritten))
+    }
+}
@@ -0,0 +1,543 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use nix::errno::Errno;
+use parking_lot::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    place_replicas, plan_moves, BlockReplicas, FailureDetector, HealthChange, Membership,
+    MemoryReplicaIndex, ReplicaIndex, ReplicatedBlockStore, WriteQuorum,
+};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::block_store::MemoryBlockStore;
+use crate::storage::checksum::ReplicaRepair;
+use crate::storage::error::{StorageError, StorageResult};
+use crate::storage::{BlockKey, BlockStore};
+
+const BLOCK_CONTENT: &[u8] = b"foo bar ";
+/// The failure timeout of the tests not checking the health of nodes.
+const FAILURE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A replica node which is down.
+#[derive(Debug)]
+struct DownStore;
+
+#[async_trait]
+impl BlockStore for DownStore {
+    async fn get(&self, _: BlockKey) -> StorageResult<Option<Vec<u8>>> {
+        Err(StorageError::Internal(anyhow::anyhow!("the node is down")))
+    }
+
+    async fn put(&self, _: BlockKey, _: Vec<u8>) -> StorageResult<()> {
+        Err(StorageError::Internal(anyhow::anyhow!("the node is down")))
+    }
+
+    async fn delete(&self, _: BlockKey) -> StorageResult<()> {
+        Err(StorageError::Internal(anyhow::anyhow!("the node is down")))
+    }
+
+    async fn delete_file(&self, _: INum) -> StorageResult<()> {
+        Err(StorageError::Internal(anyhow::anyhow!("the node is down")))
+    }
+}
+
+/// A replica node serving the reads after a delay.
+#[derive(Debug)]
+struct SlowStore {
+    /// The blocks of the node
+    inner: MemoryBlockStore,
+    /// The delay of the reads
+    delay: Mutex<Duration>,
+}
+
+#[async_trait]
+impl BlockStore for SlowStore {
+    async fn get(&self, key: BlockKey) -> StorageResult<Option<Vec<u8>>> {
+        let delay = *self.delay.lock();
+        tokio::time::sleep(delay).await;
+        self.inner.get(key).await
+    }
+
+    async fn put(&self, key: BlockKey, data: Vec<u8>) -> StorageResult<()> {
+        self.inner.put(key, data).await
+    }
+
+    async fn delete(&self, key: BlockKey) -> StorageResult<()> {
+        self.inner.delete(key).await
+    }
+
+    async fn delete_file(&self, ino: INum) -> StorageResult<()> {
+        self.inner.delete_file(ino).await
+    }
+}
+
+/// The node ids of `count` nodes.
+fn node_ids(count: usize) -> Vec<String> {
+    (0..count).map(|i| format!("node{i}")).collect()
+}
+
+/// Create a `ReplicatedBlockStore` of 3 replicas on 3 nodes in memory, the
+/// node `down` is down. The index and the stores of the nodes are returned to
+/// be inspected.
+fn new_store(
+    quorum: WriteQuorum,
+    down: Option<&str>,
+) -> (
+    ReplicatedBlockStore<Arc<MemoryReplicaIndex>>,
+    Arc<MemoryReplicaIndex>,
+    BTreeMap<String, Arc<MemoryBlockStore>>,
+) {
+    let index = Arc::new(MemoryReplicaIndex::new());
+    let stores: BTreeMap<String, Arc<MemoryBlockStore>> = node_ids(3)
+        .into_iter()
+        .map(|node| (node, Arc::new(MemoryBlockStore::new())))
+        .collect();
+    let replicas = stores
+        .iter()
+        .map(|(node, store)| {
+            let replica: Arc<dyn BlockStore + Send + Sync> = if Some(node.as_str()) == down {
+                Arc::new(DownStore)
+            } else {
+                Arc::clone(store) as _
+            };
+            (node.clone(), replica)
+        })
+        .collect();
+    let store = ReplicatedBlockStore::new(
+        Arc::clone(&index),
+        "node0",
+        replicas,
+        3,
+        quorum,
+        FAILURE_TIMEOUT,
+    );
+    (store, index, stores)
+}
+
+/// The members listed by the tests.
+#[derive(Debug, Default)]
+struct TestMembership {
+    /// The ids of the members
+    members: Mutex<Vec<String>>,
+}
+
+#[async_trait]
+impl Membership for Arc<TestMembership> {
+    async fn list_members(&self) -> StorageResult<Vec<String>> {
+        Ok(self.members.lock().clone())
+    }
+}
+
+#[test]
+fn test_place_replicas() {
+    let nodes = node_ids(5);
+    let placed = place_replicas(&nodes, 1, 2, 3);
+    assert_eq!(placed.len(), 3);
+    assert!(placed.iter().all(|node| nodes.contains(node)));
+    // The placement is stable.
+    assert_eq!(place_replicas(&nodes, 1, 2, 3), placed);
+    assert!(place_replicas(&nodes, 1, 2, 2)
+        .iter()
+        .all(|node| placed.contains(node)));
+
+    // A new node takes over some replicas, the others stay.
+    let mut more_nodes = nodes.clone();
+    more_nodes.push("node5".to_owned());
+    let moved = (0..100)
+        .filter(|&block_id| {
+            let before = place_replicas(&nodes, 1, block_id, 1);
+            place_replicas(&more_nodes, 1, block_id, 1) != before
+        })
+        .count();
+    assert!(moved < 50);
+
+    assert_eq!(place_replicas(&nodes, 1, 2, 10).len(), 5);
+}
+
+#[test]
+fn test_write_quorum() {
+    assert_eq!(WriteQuorum::All.required(3), 3);
+    assert_eq!(WriteQuorum::Majority.required(3), 2);
+    assert_eq!(WriteQuorum::Majority.required(4), 3);
+    assert_eq!(WriteQuorum::Count(1).required(3), 1);
+    assert_eq!(WriteQuorum::Count(5).required(3), 3);
+}
+
+#[tokio::test]
+async fn test_replicated_block_store() {
+    let (store, index, stores) = new_store(WriteQuorum::All, None);
+    let key = BlockKey::new(0, 0);
+    assert!(store.get(key).await.unwrap().is_none());
+
+    store.put(key, BLOCK_CONTENT.to_vec()).await.unwrap();
+    let record = index.get_replicas(0, 0).await.unwrap().unwrap();
+    assert_eq!(record.nodes.len(), 3);
+    for replica in stores.values() {
+        assert_eq!(replica.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+    }
+    assert_eq!(store.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+
+    // Reads fall back to the other replicas.
+    stores.get("node0").unwrap().delete(key).await.unwrap();
+    stores.get("node1").unwrap().delete(key).await.unwrap();
+    assert_eq!(store.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+
+    store.delete(key).await.unwrap();
+    assert!(store.get(key).await.unwrap().is_none());
+    assert!(index.get_replicas(0, 0).await.unwrap().is_none());
+    assert!(stores.values().all(|replica| replica.is_empty()));
+
+    store
+        .put(BlockKey::new(1, 0), b"foo".to_vec())
+        .await
+        .unwrap();
+    store
+        .put(BlockKey::new(1, 1), b"bar".to_vec())
+        .await
+        .unwrap();
+    store.delete_file(1).await.unwrap();
+    assert!(index.list_replicas().await.unwrap().is_empty());
+    assert!(stores.values().all(|replica| replica.is_empty()));
+}
+
+#[tokio::test]
+async fn test_repair_replicas() {
+    let (store, _, stores) = new_store(WriteQuorum::All, None);
+    let key = BlockKey::new(0, 0);
+    let healthy = |data: &[u8]| data == BLOCK_CONTENT;
+    assert!(store
+        .repair_replicas(key, &healthy)
+        .await
+        .unwrap()
+        .is_none());
+
+    // The local replica is corrupted, and another one is lost.
+    store.put(key, BLOCK_CONTENT.to_vec()).await.unwrap();
+    stores
+        .get("node0")
+        .unwrap()
+        .put(key, b"foo baz ".to_vec())
+        .await
+        .unwrap();
+    stores.get("node2").unwrap().delete(key).await.unwrap();
+    assert_eq!(store.repair_replicas(key, &healthy).await.unwrap(), Some(2));
+    for replica in stores.values() {
+        assert_eq!(replica.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+    }
+
+    // No replica is healthy.
+    for replica in stores.values() {
+        replica.put(key, b"foo baz ".to_vec()).await.unwrap();
+    }
+    assert!(store
+        .repair_replicas(key, &healthy)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_write_quorum_with_node_down() {
+    let key = BlockKey::new(0, 0);
+
+    // The write is acknowledged by the majority, and the replica missing it
+    // is not recorded.
+    let (store, index, _) = new_store(WriteQuorum::Majority, Some("node1"));
+    store.put(key, BLOCK_CONTENT.to_vec()).await.unwrap();
+    let record = index.get_replicas(0, 0).await.unwrap().unwrap();
+    assert_eq!(record.nodes.len(), 2);
+    assert!(!record.nodes.contains(&"node1".to_owned()));
+    assert_eq!(store.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+
+    // The write fails if all replicas are required.
+    let (store, _, _) = new_store(WriteQuorum::All, Some("node1"));
+    let err = store.put(key, BLOCK_CONTENT.to_vec()).await.unwrap_err();
+    assert!(matches!(
+        err,
+        StorageError::WriteQuorum {
+            acked: 2,
+            required: 3,
+            source: Errno::EIO,
+            ..
+        }
+    ));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_hedged_reads() {
+    let slow = Arc::new(SlowStore {
+        inner: MemoryBlockStore::new(),
+        delay: Mutex::new(Duration::from_millis(10)),
+    });
+    let replicas = node_ids(3)
+        .into_iter()
+        .map(|node| {
+            let replica: Arc<dyn BlockStore + Send + Sync> = if node == "node0" {
+                Arc::clone(&slow) as _
+            } else {
+                Arc::new(MemoryBlockStore::new())
+            };
+            (node, replica)
+        })
+        .collect();
+    let store = ReplicatedBlockStore::new(
+        Arc::new(MemoryReplicaIndex::new()),
+        "node0",
+        replicas,
+        3,
+        WriteQuorum::All,
+        FAILURE_TIMEOUT,
+    )
+    .with_hedge_percentile(Some(99));
+    let key = BlockKey::new(0, 0);
+    store.put(key, BLOCK_CONTENT.to_vec()).await.unwrap();
+
+    // The reads are not hedged until enough of them are seen.
+    for _ in 0..200 {
+        assert_eq!(store.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+    }
+
+    // The local replica stalls, and the read is hedged by the next replica
+    // after the p99 latency.
+    *slow.delay.lock() = Duration::from_secs(60);
+    let start = tokio::time::Instant::now();
+    assert_eq!(store.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn test_failure_detector() {
+    let nodes = node_ids(3);
+    let timeout = Duration::from_secs(10);
+    let detector = FailureDetector::new(&nodes, timeout);
+    let start = Instant::now();
+    assert!(detector.check(start).is_empty());
+    assert_eq!(detector.alive_nodes(), nodes);
+
+    let later = start.checked_add(timeout).unwrap();
+    detector.heartbeat("node0", later);
+    detector.heartbeat("unknown", later);
+    assert_eq!(
+        detector.check(later),
+        HealthChange {
+            dead: vec!["node1".to_owned(), "node2".to_owned()],
+            revived: vec![],
+        }
+    );
+    assert!(detector.is_alive("node0"));
+    assert!(!detector.is_alive("node1"));
+    assert!(!detector.is_alive("unknown"));
+    assert_eq!(detector.alive_nodes(), vec!["node0".to_owned()]);
+
+    detector.heartbeat("node1", later);
+    assert_eq!(
+        detector.check(later),
+        HealthChange {
+            dead: vec![],
+            revived: vec!["node1".to_owned()],
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_repair_after_node_dead() {
+    let index = Arc::new(MemoryReplicaIndex::new());
+    let stores: BTreeMap<String, Arc<MemoryBlockStore>> = node_ids(4)
+        .into_iter()
+        .map(|node| (node, Arc::new(MemoryBlockStore::new())))
+        .collect();
+    let replicas = stores
+        .iter()
+        .map(|(node, store)| {
+            let replica: Arc<dyn BlockStore + Send + Sync> = Arc::clone(store) as _;
+            (node.clone(), replica)
+        })
+        .collect();
+    let store = Arc::new(ReplicatedBlockStore::new(
+        Arc::clone(&index),
+        "node0",
+        replicas,
+        3,
+        WriteQuorum::All,
+        Duration::from_millis(100),
+    ));
+    let membership = Arc::new(TestMembership::default());
+    *membership.members.lock() = node_ids(4);
+    let token = CancellationToken::new();
+    let handle = tokio::spawn(Arc::clone(&store).run_health_checker(
+        Arc::clone(&membership),
+        Duration::from_millis(20),
+        token.clone(),
+    ));
+
+    for block_id in 0..8 {
+        let key = BlockKey::new(1, block_id);
+        store.put(key, BLOCK_CONTENT.to_vec()).await.unwrap();
+    }
+    // Node 3 is gone, its registration expires.
+    let dead = "node3".to_owned();
+    membership.members.lock().retain(|node| *node != dead);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    for record in index.list_replicas().await.unwrap() {
+        let alive: Vec<&String> = record.nodes.iter().filter(|&node| *node != dead).collect();
+        assert_eq!(alive.len(), 3, "{record:?}");
+        for node in alive {
+            let key = BlockKey::new(record.ino, record.block_id);
+            let replica = stores.get(node).unwrap().get(key).await.unwrap();
+            assert_eq!(replica.unwrap(), BLOCK_CONTENT);
+        }
+    }
+    for block_id in 0..8 {
+        let key = BlockKey::new(1, block_id);
+        assert_eq!(store.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+    }
+
+    // The new blocks are not placed on the dead node.
+    let key = BlockKey::new(2, 0);
+    store.put(key, BLOCK_CONTENT.to_vec()).await.unwrap();
+    let record = index.get_replicas(2, 0).await.unwrap().unwrap();
+    assert_eq!(record.nodes.len(), 3);
+    assert!(!record.nodes.contains(&dead));
+
+    token.cancel();
+    handle.await.unwrap();
+}
+
+#[test]
+fn test_plan_moves() {
+    let nodes = node_ids(4);
+    let (old_nodes, _) = nodes.split_at(3);
+    let records: Vec<BlockReplicas> = (0..16)
+        .map(|block_id| BlockReplicas {
+            ino: 1,
+            block_id,
+            nodes: place_replicas(old_nodes, 1, block_id, 2),
+        })
+        .collect();
+
+    // Nothing is moved if the nodes are unchanged.
+    assert!(plan_moves(records.clone(), old_nodes, 2).is_empty());
+
+    // Some blocks are moved to the new node, and only to it.
+    let plans = plan_moves(records.clone(), &nodes, 2);
+    assert!(!plans.is_empty());
+    for plan in plans {
+        assert_eq!(plan.to, vec!["node3".to_owned()]);
+        assert_eq!(plan.from.len(), 1);
+        let mut moved: Vec<String> = plan
+            .record
+            .nodes
+            .iter()
+            .filter(|&node| !plan.from.contains(node))
+            .chain(plan.to.iter())
+            .cloned()
+            .collect();
+        moved.sort();
+        let mut placed = place_replicas(&nodes, 1, plan.record.block_id, 2);
+        placed.sort();
+        assert_eq!(moved, placed);
+    }
+
+    // The blocks without any replica alive are not moved.
+    let lost = BlockReplicas {
+        ino: 2,
+        block_id: 0,
+        nodes: vec!["node4".to_owned()],
+    };
+    assert!(plan_moves(vec![lost], &nodes, 2).is_empty());
+}
+
+#[tokio::test]
+async fn test_compare_and_set_replicas() {
+    let index = MemoryReplicaIndex::new();
+    let record = BlockReplicas {
+        ino: 1,
+        block_id: 0,
+        nodes: node_ids(2),
+    };
+    index.set_replicas(record.clone()).await.unwrap();
+
+    let updated = BlockReplicas {
+        nodes: node_ids(3),
+        ..record.clone()
+    };
+    assert!(index
+        .compare_and_set_replicas(&record, updated.clone())
+        .await
+        .unwrap());
+    // The record is changed, so it's not set again.
+    assert!(!index
+        .compare_and_set_replicas(&record, record.clone())
+        .await
+        .unwrap());
+    assert_eq!(index.get_replicas(1, 0).await.unwrap().unwrap(), updated);
+}
+
+#[tokio::test]
+async fn test_rebalance_after_node_added() {
+    let index = Arc::new(MemoryReplicaIndex::new());
+    let nodes = node_ids(4);
+    let (old_nodes, _) = nodes.split_at(3);
+    let stores: BTreeMap<String, Arc<MemoryBlockStore>> = nodes
+        .iter()
+        .map(|node| (node.clone(), Arc::new(MemoryBlockStore::new())))
+        .collect();
+    let replicas = stores
+        .iter()
+        .map(|(node, store)| {
+            let replica: Arc<dyn BlockStore + Send + Sync> = Arc::clone(store) as _;
+            (node.clone(), replica)
+        })
+        .collect();
+    let store = ReplicatedBlockStore::new(
+        Arc::clone(&index),
+        "node0",
+        replicas,
+        2,
+        WriteQuorum::All,
+        FAILURE_TIMEOUT,
+    );
+
+    // The blocks are written before node 3 is added.
+    for block_id in 0..16 {
+        let key = BlockKey::new(1, block_id);
+        let placed = place_replicas(old_nodes, 1, block_id, 2);
+        for node in &placed {
+            let replica = stores.get(node).unwrap();
+            replica.put(key, BLOCK_CONTENT.to_vec()).await.unwrap();
+        }
+        let record = BlockReplicas {
+            ino: 1,
+            block_id,
+            nodes: placed,
+        };
+        index.set_replicas(record).await.unwrap();
+    }
+
+    let report = store.rebalance().await.unwrap();
+    assert!(report.planned > 0);
+    assert_eq!(report.moved, report.planned);
+    assert_eq!(report.pending, 0);
+
+    for block_id in 0..16 {
+        let key = BlockKey::new(1, block_id);
+        let mut placed = place_replicas(&nodes, 1, block_id, 2);
+        placed.sort();
+        let mut record = index.get_replicas(1, block_id).await.unwrap().unwrap();
+        record.nodes.sort();
+        assert_eq!(record.nodes, placed);
+        for (node, replica) in &stores {
+            let data = replica.get(key).await.unwrap();
+            assert_eq!(data.is_some(), placed.contains(node), "{key:?} on {node}");
+        }
+        assert_eq!(store.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+    }
+
+    // The replicas are placed already, nothing is moved again.
+    let report = store.rebalance().await.unwrap();
+    assert_eq!(report.planned, 0);
+}
@@ -0,0 +1,149 @@
+//! The index of block versions and snapshots.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use clippy_utilities::OverflowArithmetic;
+use parking_lot::Mutex;
+
+use super::BlockVersions;
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::error::StorageResult;
+
+/// The snapshot state of a volume.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotState {
+    /// The current epoch, which writes belong to, it's `INITIAL_EPOCH` before
+    /// any snapshot is taken
+    pub epoch: u64,
+    /// The epochs of existing snapshots
+    pub snapshots: BTreeSet<u64>,
+}
+
+/// The `SnapshotIndex` trait, which records the versions of blocks and the
+/// existing snapshots.
+#[async_trait]
+pub trait SnapshotIndex {
+    /// Get the current epoch and the existing snapshots.
+    async fn state(&self) -> StorageResult<SnapshotState>;
+
+    /// Get the versions of a block.
+    ///
+    /// Returns `None` if the block has never been written.
+    async fn get_versions(
+        &self,
+        ino: INum,
+        block_id: usize,
+    ) -> StorageResult<Option<BlockVersions>>;
+
+    /// Set the versions of a block, the record is removed if there is no
+    /// version.
+    async fn set_versions(&self, versions: BlockVersions) -> StorageResult<()>;
+
+    /// List the versions of all blocks of a file.
+    async fn list_file_versions(&self, ino: INum) -> StorageResult<Vec<BlockVersions>>;
+
+    /// List the versions of all blocks.
+    async fn list_versions(&self) -> StorageResult<Vec<BlockVersions>>;
+}
+
+#[async_trait]
+impl<T> SnapshotIndex for Arc<T>
+where
+    T: SnapshotIndex + Send + Sync,
+{
+    async fn state(&self) -> StorageResult<SnapshotState> {
+        self.as_ref().state().await
+    }
+
+    async fn get_versions(
+        &self,
+        ino: INum,
+        block_id: usize,
+    ) -> StorageResult<Option<BlockVersions>> {
+        self.as_ref().get_versions(ino, block_id).await
+    }
+
+    async fn set_versions(&self, versions: BlockVersions) -> StorageResult<()> {
+        self.as_ref().set_versions(versions).await
+    }
+
+    async fn list_file_versions(&self, ino: INum) -> StorageResult<Vec<BlockVersions>> {
+        self.as_ref().list_file_versions(ino).await
+    }
+
+    async fn list_versions(&self) -> StorageResult<Vec<BlockVersions>> {
+        self.as_ref().list_versions().await
+    }
+}
+
+/// A `SnapshotIndex` in memory, which is not persisted.
+#[derive(Debug, Default)]
+pub struct MemorySnapshotIndex {
+    /// The snapshot state
+    state: Mutex<SnapshotState>,
+    /// The versions of blocks
+    versions: Mutex<BTreeMap<(INum, usize), BlockVersions>>,
+}
+
+impl MemorySnapshotIndex {
+    /// Create an empty `MemorySnapshotIndex`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take a snapshot, returns its epoch.
+    pub fn take_snapshot(&self) -> u64 {
+        let mut state = self.state.lock();
+        let snapshot = state.epoch;
+        state.snapshots.insert(snapshot);
+        state.epoch = snapshot.overflow_add(1);
+        snapshot
+    }
+
+    /// Delete a snapshot.
+    pub fn delete_snapshot(&self, snapshot: u64) {
+        self.state.lock().snapshots.remove(&snapshot);
+    }
+}
+
+#[async_trait]
+impl SnapshotIndex for MemorySnapshotIndex {
+    async fn state(&self) -> StorageResult<SnapshotState> {
+        Ok(self.state.lock().clone())
+    }
+
+    async fn get_versions(
+        &self,
+        ino: INum,
+        block_id: usize,
+    ) -> StorageResult<Option<BlockVersions>> {
+        Ok(self.versions.lock().get(&(ino, block_id)).cloned())
+    }
+
+    async fn set_versions(&self, versions: BlockVersions) -> StorageResult<()> {
+        let mut all_versions = self.versions.lock();
+        let key = (versions.ino, versions.block_id);
+        if versions.is_empty() {
+            all_versions.remove(&key);
+        } else {
+            all_versions.insert(key, versions);
+        }
+        Ok(())
+    }
+
+    async fn list_file_versions(&self, ino: INum) -> StorageResult<Vec<BlockVersions>> {
+        Ok(self
+            .versions
+            .lock()
+            .range((ino, 0)..=(ino, usize::MAX))
+            .map(|(_, versions)| versions.clone())
+            .collect())
+    }
+
+    async fn list_versions(&self) -> StorageResult<Vec<BlockVersions>> {
+        Ok(self.versions.lock().values().cloned().collect())
+    }
+}
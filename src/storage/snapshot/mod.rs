@@ -0,0 +1,175 @@
+//! Copy-on-write snapshots of blocks.
+//!
+//! Snapshots are numbered by epochs. Taking a snapshot freezes the current
+//! epoch as the id of the snapshot, and the following writes belong to the
+//! next epoch. Every block records its versions, each of which is written in
+//! an epoch, and is stored in the [`BlockStore`](crate::storage::BlockStore)
+//! with the epoch as its version:
+//!
+//! - A block is overwritten in place if its live version is written in the
+//!   current epoch, as no snapshot can see it.
+//! - Otherwise, the new content is written as a new version of the current
+//!   epoch, and the old version is kept for the snapshots.
+//! - Removing or truncating a file records tombstones instead of deleting the
+//!   versions seen by snapshots.
+//!
+//! A snapshot sees the latest version written no later than its epoch. The
+//! versions seen by neither the live file system nor any snapshot are deleted
+//! on the next write of the block, or when a snapshot is deleted.
+
+mod index;
+mod storage;
+
+use std::collections::BTreeSet;
+
+pub use index::{MemorySnapshotIndex, SnapshotIndex, SnapshotState};
+use serde::{Deserialize, Serialize};
+pub use storage::SnapshotStorage;
+
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::block_store::INITIAL_BLOCK_VERSION;
+
+/// The epoch of a volume before any snapshot is taken.
+pub const INITIAL_EPOCH: u64 = INITIAL_BLOCK_VERSION;
+
+/// A version of a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockVersion {
+    /// The epoch in which the version is written, it's also the version of
+    /// the block in the block store
+    pub epoch: u64,
+    /// Whether the block exists, it's `false` for a tombstone of a removed
+    /// block
+    pub present: bool,
+}
+
+/// The versions of a block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockVersions {
+    /// The inode number of the file
+    pub ino: INum,
+    /// The index of the block in the file
+    pub block_id: usize,
+    /// The versions in ascending order of epochs, the last one is the live
+    /// version
+    pub versions: Vec<BlockVersion>,
+}
+
+impl BlockVersions {
+    /// Create the versions of a block which has never been written.
+    #[must_use]
+    pub fn new(ino: INum, block_id: usize) -> Self {
+        Self {
+            ino,
+            block_id,
+            versions: vec![],
+        }
+    }
+
+    /// Returns whether there is no version of the block.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.versions.is_empty()
+    }
+
+    /// Get the live version of the block, `None` if the block does not exist
+    /// in the live file system.
+    #[must_use]
+    pub fn live(&self) -> Option<u64> {
+        self.versions
+            .last()
+            .filter(|version| version.present)
+            .map(|version| version.epoch)
+    }
+
+    /// Get the version of the block seen by the snapshot of `snapshot`,
+    /// `None` if the block does not exist in the snapshot.
+    #[must_use]
+    pub fn at(&self, snapshot: u64) -> Option<u64> {
+        self.versions
+            .iter()
+            .rev()
+            .find(|version| version.epoch <= snapshot)
+            .filter(|version| version.present)
+            .map(|version| version.epoch)
+    }
+
+    /// Record a write of the block in `epoch`, the version of `epoch` is
+    /// written.
+    ///
+    /// Returns the live version before the write, which a partial write is
+    /// merged with.
+    pub fn write(&mut self, epoch: u64) -> Option<u64> {
+        let base = self.live();
+        if let Some(last) = self.versions.last_mut().filter(|last| last.epoch == epoch) {
+            last.present = true;
+        } else {
+            self.versions.push(BlockVersion {
+                epoch,
+                present: true,
+            });
+        }
+        base
+    }
+
+    /// Record a removal of the block in `epoch`.
+    ///
+    /// Returns the live version if it's written in `epoch`, which is seen by
+    /// no snapshot and can be deleted.
+    pub fn remove(&mut self, epoch: u64) -> Option<u64> {
+        let live = self.live()?;
+        if live == epoch {
+            self.versions.pop();
+        }
+        if !self.versions.is_empty() {
+            self.versions.push(BlockVersion {
+                epoch,
+                present: false,
+            });
+        }
+        (live == epoch).then_some(live)
+    }
+
+    /// Drop the versions seen by neither the live file system nor any of
+    /// `snapshots`.
+    ///
+    /// Returns the dropped versions which hold content.
+    pub fn collect(&mut self, snapshots: &BTreeSet<u64>) -> Vec<u64> {
+        let nexts = self
+            .versions
+            .iter()
+            .skip(1)
+            .map(|next| Some(next.epoch))
+            .chain(std::iter::once(None));
+        let mut kept = vec![];
+        let mut garbage = vec![];
+        for (version, next) in self.versions.iter().zip(nexts) {
+            let seen = match next {
+                // The live version
+                None => version.present,
+                Some(next) => snapshots.range(version.epoch..next).next().is_some(),
+            };
+            if seen {
+                kept.push(*version);
+            } else if version.present {
+                garbage.push(version.epoch);
+            } else {
+                // A tombstone holds no content.
+            }
+        }
+
+        // A trailing tombstone hides the older versions from the live file
+        // system.
+        if let Some(&last) = self.versions.last() {
+            if !last.present && !kept.is_empty() {
+                kept.push(last);
+            }
+        }
+        self.versions = kept;
+        garbage
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests;
@@ -0,0 +1,280 @@
+//! The storage layer of copy-on-write blocks.
+
+use async_trait::async_trait;
+use clippy_utilities::OverflowArithmetic;
+use nix::errno::Errno;
+use tracing::warn;
+
+use super::{BlockVersions, SnapshotIndex};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::error::{StorageError, StorageResult};
+use crate::storage::{Block, BlockKey, BlockStore, Storage};
+
+/// A `Storage` which keeps the versions of blocks seen by snapshots.
+///
+/// This is the bottom of the storage layers, it has no cache and no backend.
+/// The content of a new version is put into the `BlockStore` before the
+/// version is committed to the index, and the dropped versions are deleted
+/// after the index is updated. Therefore, a crash leaks a version at most.
+///
+/// A `SnapshotStorage` reading a snapshot is read-only.
+#[derive(Debug)]
+pub struct SnapshotStorage<I, B> {
+    /// The index of versions and snapshots
+    index: I,
+    /// The store of block versions
+    store: B,
+    /// The size of blocks
+    block_size: usize,
+    /// The snapshot to read, `None` for the live file system
+    snapshot: Option<u64>,
+}
+
+impl<I, B> SnapshotStorage<I, B>
+where
+    I: SnapshotIndex + Send + Sync,
+    B: BlockStore + Send + Sync,
+{
+    /// Create a `SnapshotStorage` of the live file system.
+    pub fn new(index: I, store: B, block_size: usize) -> Self {
+        Self {
+            index,
+            store,
+            block_size,
+            snapshot: None,
+        }
+    }
+
+    /// Read the blocks seen by the snapshot of `snapshot` instead of the live
+    /// ones, the storage becomes read-only.
+    #[must_use]
+    pub fn with_snapshot(mut self, snapshot: u64) -> Self {
+        self.snapshot = Some(snapshot);
+        self
+    }
+
+    /// Returns an error if the storage is read-only.
+    fn ensure_writable(&self, ino: INum, block_id: usize) -> StorageResult<()> {
+        if self.snapshot.is_some() {
+            Err(StorageError::ReadOnly {
+                ino,
+                block_id,
+                source: Errno::EROFS,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Get the content of a version of a block.
+    async fn get_version(
+        &self,
+        ino: INum,
+        block_id: usize,
+        version: Option<u64>,
+    ) -> StorageResult<Option<Vec<u8>>> {
+        match version {
+            Some(version) => {
+                self.store
+                    .get(BlockKey::with_version(ino, block_id, version))
+                    .await
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Delete versions of a block no longer seen.
+    ///
+    /// Failures are only logged, a leaked version costs space but never
+    /// breaks data.
+    async fn delete_versions(&self, ino: INum, block_id: usize, versions: Vec<u64>) {
+        for version in versions {
+            let key = BlockKey::with_version(ino, block_id, version);
+            if let Err(e) = self.store.delete(key).await {
+                warn!("failed to delete version {version} of block {block_id} of file {ino}: {e}");
+            }
+        }
+    }
+
+    /// Write a block in the current epoch, the content is `update` applied to
+    /// the live content, which is empty if `merge` is not set or the block
+    /// does not exist.
+    async fn write_block<F>(
+        &self,
+        ino: INum,
+        block_id: usize,
+        merge: bool,
+        update: F,
+    ) -> StorageResult<()>
+    where
+        F: FnOnce(Vec<u8>) -> Vec<u8> + Send,
+    {
+        let state = self.index.state().await?;
+        let mut versions = self
+            .index
+            .get_versions(ino, block_id)
+            .await?
+            .unwrap_or_else(|| BlockVersions::new(ino, block_id));
+        let base = versions.write(state.epoch);
+
+        let content = if merge {
+            self.get_version(ino, block_id, base)
+                .await?
+                .unwrap_or_default()
+        } else {
+            vec![]
+        };
+        self.store
+            .put(
+                BlockKey::with_version(ino, block_id, state.epoch),
+                update(content),
+            )
+            .await?;
+
+        let garbage = versions.collect(&state.snapshots);
+        self.index.set_versions(versions).await?;
+        self.delete_versions(ino, block_id, garbage).await;
+        Ok(())
+    }
+
+    /// Remove the blocks of a file from `from_block`.
+    async fn remove_blocks(&self, ino: INum, from_block: usize) -> StorageResult<()> {
+        let state = self.index.state().await?;
+        for mut versions in self.index.list_file_versions(ino).await? {
+            if versions.block_id < from_block {
+                continue;
+            }
+            let block_id = versions.block_id;
+            let mut garbage: Vec<u64> = versions.remove(state.epoch).into_iter().collect();
+            garbage.extend(versions.collect(&state.snapshots));
+            self.index.set_versions(versions).await?;
+            self.delete_versions(ino, block_id, garbage).await;
+        }
+        Ok(())
+    }
+
+    /// Delete the versions of blocks seen by neither the live file system nor
+    /// any snapshot, it should be called after a snapshot is deleted.
+    ///
+    /// Returns the number of deleted versions.
+    pub async fn collect_garbage(&self) -> StorageResult<usize> {
+        let state = self.index.state().await?;
+        let mut count: usize = 0;
+        for mut versions in self.index.list_versions().await? {
+            let garbage = versions.collect(&state.snapshots);
+            if garbage.is_empty() {
+                continue;
+            }
+            let (ino, block_id) = (versions.ino, versions.block_id);
+            count = count.overflow_add(garbage.len());
+            self.index.set_versions(versions).await?;
+            self.delete_versions(ino, block_id, garbage).await;
+        }
+        Ok(count)
+    }
+}
+
+#[async_trait]
+impl<I, B> Storage for SnapshotStorage<I, B>
+where
+    I: SnapshotIndex + Send + Sync,
+    B: BlockStore + Send + Sync,
+{
+    async fn load_from_self(&self, ino: INum, block_id: usize) -> StorageResult<Option<Block>> {
+        let Some(versions) = self.index.get_versions(ino, block_id).await? else {
+            return Ok(None);
+        };
+        let version = match self.snapshot {
+            Some(snapshot) => versions.at(snapshot),
+            None => versions.live(),
+        };
+        let data = self.get_version(ino, block_id, version).await?;
+        Ok(data.map(|data| Block::from_slice(self.block_size, &data)))
+    }
+
+    async fn load_from_backend(&self, _: INum, _: usize) -> StorageResult<Option<Block>> {
+        // This storage has no backend.
+        Ok(None)
+    }
+
+    async fn cache_block_from_backend(&self, _: INum, _: usize, _: Block) -> StorageResult<()> {
+        unreachable!("This storage has no backend, and has no cache.");
+    }
+
+    async fn store(&self, ino: INum, block_id: usize, block: Block) -> StorageResult<()> {
+        self.ensure_writable(ino, block_id)?;
+
+        let block_start = block.start();
+        let block_end = block.end();
+        let whole = block_start == 0 && block_end == self.block_size;
+        self.write_block(ino, block_id, !whole, |mut dest| {
+            if whole {
+                return block.as_slice().to_vec();
+            }
+            // Ensure that the vector is long enough to be overwritten
+            if dest.len() < block_end {
+                dest.resize(block_end, 0);
+            }
+            dest.get_mut(block_start..block_end)
+                .unwrap_or_else(|| unreachable!("The vector is ensured to be long enough."))
+                .copy_from_slice(block.as_slice());
+            dest
+        })
+        .await
+    }
+
+    async fn remove(&self, ino: INum) -> StorageResult<()> {
+        self.ensure_writable(ino, 0)?;
+        self.remove_blocks(ino, 0).await
+    }
+
+    async fn invalidate(&self, _: INum) -> StorageResult<()> {
+        // This storage has no cache, therefore, its contents cannot be
+        // invalidated.
+        Ok(())
+    }
+
+    async fn flush(&self, _: INum) -> StorageResult<()> {
+        // This storage has no cache and backend, therefore, there is no need to
+        // flush its data.
+        Ok(())
+    }
+
+    async fn flush_all(&self) -> StorageResult<()> {
+        // This storage has no cache and backend, therefore, there is no need to
+        // flush its data.
+        Ok(())
+    }
+
+    async fn truncate(
+        &self,
+        ino: INum,
+        from_block: usize,
+        to_block: usize,
+        fill_start: usize,
+    ) -> StorageResult<()> {
+        self.ensure_writable(ino, to_block)?;
+        debug_assert!(from_block >= to_block);
+        self.remove_blocks(ino, to_block).await?;
+
+        // truncate the last block
+        if to_block > 0 && fill_start < self.block_size {
+            let block_id = to_block.overflow_sub(1);
+            let exists = self
+                .index
+                .get_versions(ino, block_id)
+                .await?
+                .and_then(|versions| versions.live())
+                .is_some();
+            if exists {
+                self.write_block(ino, block_id, true, |mut dest| {
+                    dest.truncate(fill_start);
+                    dest
+                })
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+}
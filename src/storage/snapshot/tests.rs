@@ -0,0 +1,199 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use super::{BlockVersion, BlockVersions, MemorySnapshotIndex, SnapshotStorage};
+use crate::storage::block_store::MemoryBlockStore;
+use crate::storage::error::StorageError;
+use crate::storage::{Block, Storage};
+
+const BLOCK_SIZE_IN_BYTES: usize = 8;
+
+type SnapshotStorageType = SnapshotStorage<Arc<MemorySnapshotIndex>, Arc<MemoryBlockStore>>;
+
+fn prepare_storage() -> (
+    Arc<MemorySnapshotIndex>,
+    Arc<MemoryBlockStore>,
+    SnapshotStorageType,
+) {
+    let index = Arc::new(MemorySnapshotIndex::new());
+    let store = Arc::new(MemoryBlockStore::new());
+    let storage = SnapshotStorage::new(Arc::clone(&index), Arc::clone(&store), BLOCK_SIZE_IN_BYTES);
+    (index, store, storage)
+}
+
+fn snapshot_of(
+    index: &Arc<MemorySnapshotIndex>,
+    store: &Arc<MemoryBlockStore>,
+    snapshot: u64,
+) -> SnapshotStorageType {
+    SnapshotStorage::new(Arc::clone(index), Arc::clone(store), BLOCK_SIZE_IN_BYTES)
+        .with_snapshot(snapshot)
+}
+
+async fn load(storage: &SnapshotStorageType, ino: u64, block_id: usize) -> Option<Vec<u8>> {
+    storage
+        .load_from_self(ino, block_id)
+        .await
+        .unwrap()
+        .map(|block| block.as_slice().to_vec())
+}
+
+#[test]
+fn test_block_versions() {
+    let mut versions = BlockVersions::new(0, 0);
+    assert_eq!(versions.write(0), None);
+    assert_eq!(versions.write(0), Some(0));
+    assert_eq!(versions.versions.len(), 1);
+
+    // Snapshot 0 is taken, the write of epoch 1 is a new version.
+    assert_eq!(versions.write(1), Some(0));
+    assert_eq!(versions.live(), Some(1));
+    assert_eq!(versions.at(0), Some(0));
+
+    let snapshots = BTreeSet::from([0]);
+    assert!(versions.collect(&snapshots).is_empty());
+    assert_eq!(versions.remove(1), Some(1));
+    assert_eq!(
+        versions.versions,
+        vec![
+            BlockVersion {
+                epoch: 0,
+                present: true
+            },
+            BlockVersion {
+                epoch: 1,
+                present: false
+            },
+        ]
+    );
+    assert_eq!(versions.live(), None);
+    assert_eq!(versions.at(0), Some(0));
+
+    // Snapshot 0 is deleted, nothing is seen any more.
+    assert_eq!(versions.collect(&BTreeSet::new()), vec![0]);
+    assert!(versions.is_empty());
+}
+
+#[tokio::test]
+async fn test_snapshot_copy_on_write() {
+    let (index, store, storage) = prepare_storage();
+
+    storage
+        .store(0, 0, Block::from_slice(BLOCK_SIZE_IN_BYTES, b"aaaaaaaa"))
+        .await
+        .unwrap();
+    // Overwrite in place, as no snapshot is taken.
+    storage
+        .store(0, 0, Block::from_slice(BLOCK_SIZE_IN_BYTES, b"bbbbbbbb"))
+        .await
+        .unwrap();
+    assert_eq!(store.len(), 1);
+
+    let snapshot = index.take_snapshot();
+    storage
+        .store(
+            0,
+            0,
+            Block::from_slice_with_range(BLOCK_SIZE_IN_BYTES, 2, 4, b"cc"),
+        )
+        .await
+        .unwrap();
+    storage
+        .store(0, 1, Block::from_slice(BLOCK_SIZE_IN_BYTES, b"dddddddd"))
+        .await
+        .unwrap();
+    assert_eq!(store.len(), 3);
+
+    assert_eq!(load(&storage, 0, 0).await.unwrap(), b"bbccbbbb");
+    assert_eq!(load(&storage, 0, 1).await.unwrap(), b"dddddddd");
+
+    let snapshot_storage = snapshot_of(&index, &store, snapshot);
+    assert_eq!(load(&snapshot_storage, 0, 0).await.unwrap(), b"bbbbbbbb");
+    assert_eq!(load(&snapshot_storage, 0, 1).await, None);
+
+    // A snapshot is read-only.
+    let err = snapshot_storage
+        .store(0, 0, Block::from_slice(BLOCK_SIZE_IN_BYTES, b"eeeeeeee"))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, StorageError::ReadOnly { .. }));
+}
+
+#[tokio::test]
+async fn test_snapshot_remove_and_truncate() {
+    let (index, store, storage) = prepare_storage();
+
+    for block_id in 0..3 {
+        storage
+            .store(
+                0,
+                block_id,
+                Block::from_slice(BLOCK_SIZE_IN_BYTES, b"aaaaaaaa"),
+            )
+            .await
+            .unwrap();
+        storage
+            .store(
+                1,
+                block_id,
+                Block::from_slice(BLOCK_SIZE_IN_BYTES, b"bbbbbbbb"),
+            )
+            .await
+            .unwrap();
+    }
+    let snapshot = index.take_snapshot();
+
+    storage.truncate(0, 3, 1, 4).await.unwrap();
+    storage.remove(1).await.unwrap();
+
+    assert_eq!(load(&storage, 0, 0).await.unwrap(), b"aaaa\0\0\0\0");
+    assert_eq!(load(&storage, 0, 1).await, None);
+    assert_eq!(load(&storage, 1, 0).await, None);
+
+    let snapshot_storage = snapshot_of(&index, &store, snapshot);
+    for block_id in 0..3 {
+        assert_eq!(
+            load(&snapshot_storage, 0, block_id).await.unwrap(),
+            b"aaaaaaaa"
+        );
+        assert_eq!(
+            load(&snapshot_storage, 1, block_id).await.unwrap(),
+            b"bbbbbbbb"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_snapshot_garbage_collection() {
+    let (index, store, storage) = prepare_storage();
+
+    storage
+        .store(0, 0, Block::from_slice(BLOCK_SIZE_IN_BYTES, b"aaaaaaaa"))
+        .await
+        .unwrap();
+    storage
+        .store(0, 1, Block::from_slice(BLOCK_SIZE_IN_BYTES, b"aaaaaaaa"))
+        .await
+        .unwrap();
+    let snapshot = index.take_snapshot();
+    storage
+        .store(0, 0, Block::from_slice(BLOCK_SIZE_IN_BYTES, b"bbbbbbbb"))
+        .await
+        .unwrap();
+    storage
+        .truncate(0, 2, 1, BLOCK_SIZE_IN_BYTES)
+        .await
+        .unwrap();
+    assert_eq!(store.len(), 3);
+
+    index.delete_snapshot(snapshot);
+    assert_eq!(storage.collect_garbage().await.unwrap(), 2);
+    assert_eq!(store.len(), 1);
+    assert_eq!(storage.collect_garbage().await.unwrap(), 0);
+    assert_eq!(load(&storage, 0, 0).await.unwrap(), b"bbbbbbbb");
+    assert_eq!(load(&storage, 0, 1).await, None);
+
+    // Without any snapshot, the old versions are deleted on writes.
+    storage.remove(0).await.unwrap();
+    assert!(store.is_empty());
+}
@@ -1,17 +1,36 @@
 //! The storage manager implementation.
 
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::SystemTime;
 
 use anyhow::Context;
 use clippy_utilities::OverflowArithmetic;
-use lockfree_cuckoohash::{pin, LockFreeCuckooHash as HashMap};
+use nix::errno::Errno;
+use parking_lot::Mutex;
 use tokio::task;
+use tracing::debug;
 
 use super::super::{Block, Storage};
+use super::read_ahead::{ReadAhead, ReadAheadState};
 use crate::async_fuse::fuse::protocol::INum;
+use crate::async_fuse::util::build_error_result_from_errno;
 use crate::common::error::DatenLordResult;
 
+/// The `mtime`s a file is cached with.
+///
+/// The writes of disjoint ranges of a file run concurrently, so a write may
+/// pass the `mtime` from before another write that has finished meanwhile.
+/// The cache is valid for any `mtime` from the one it's validated with to the
+/// one of the latest store, rather than the latest one only.
+#[derive(Debug, Clone, Copy)]
+struct CacheMtime {
+    /// The `mtime` the cache is validated with
+    validated: SystemTime,
+    /// The `mtime` of the latest store to the cache
+    latest: SystemTime,
+}
+
 /// The storage manager, which exposes the interfaces to `FileSystem` for
 /// interacting with the storage layers.
 #[derive(Debug)]
@@ -21,7 +40,11 @@ pub struct StorageManager<S> {
     /// Block size in bytes
     block_size: usize,
     /// Last modified times of the cache (on file level)
-    mtimes: HashMap<INum, SystemTime>,
+    mtimes: Mutex<HashMap<INum, CacheMtime>>,
+    /// The sequential read-ahead
+    read_ahead: ReadAhead,
+    /// Whether the stores are refused
+    read_only: bool,
 }
 
 impl<S> StorageManager<S>
@@ -33,10 +56,44 @@ where
         StorageManager {
             storage: Arc::new(storage),
             block_size,
-            mtimes: HashMap::new(),
+            mtimes: Mutex::new(HashMap::new()),
+            read_ahead: ReadAhead::new(0),
+            read_only: false,
         }
     }
 
+    /// Enable sequential read-ahead, with the maximum window in blocks.
+    /// Read-ahead is disabled if `max_window` is `0`, which is the default.
+    #[must_use]
+    pub fn with_read_ahead(mut self, max_window: usize) -> Self {
+        self.read_ahead = ReadAhead::new(max_window);
+        self
+    }
+
+    /// Refuse the stores, truncations and copies with `EROFS` if `read_only`
+    /// is true, so no block is ever dirtied in the caches.
+    #[must_use]
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Get the top-level storage.
+    pub fn storage(&self) -> &Arc<S> {
+        &self.storage
+    }
+
+    /// Fail with `EROFS` if the storage is read-only.
+    fn check_writable(&self, ino: INum) -> DatenLordResult<()> {
+        if self.read_only {
+            return build_error_result_from_errno(
+                Errno::EROFS,
+                format!("ino={ino} is written to a read-only storage"),
+            );
+        }
+        Ok(())
+    }
+
     /// Convert offset in byte to block id via the equation:
     ///
     /// `block_id = offset / block_size`
@@ -44,6 +101,40 @@ where
         offset.overflow_div(self.block_size)
     }
 
+    /// Whether the cache of a file is valid for `mtime`.
+    fn is_cache_valid(&self, ino: INum, mtime: SystemTime) -> bool {
+        self.mtimes.lock().get(&ino).is_some_and(|cache_mtime| {
+            cache_mtime.validated <= mtime && mtime <= cache_mtime.latest
+        })
+    }
+
+    /// Record that the cache of a file is validated with `mtime`.
+    fn set_cache_validated(&self, ino: INum, mtime: SystemTime) {
+        self.mtimes.lock().insert(
+            ino,
+            CacheMtime {
+                validated: mtime,
+                latest: mtime,
+            },
+        );
+    }
+
+    /// Record a store to the cache of a file with `mtime`, returns the new
+    /// `mtime`. A cache invalidated before the store is validated with
+    /// `mtime`.
+    fn set_cache_stored(&self, ino: INum, mtime: SystemTime) -> SystemTime {
+        let new_mtime = SystemTime::now();
+        self.mtimes
+            .lock()
+            .entry(ino)
+            .and_modify(|cache_mtime| cache_mtime.latest = cache_mtime.latest.max(new_mtime))
+            .or_insert(CacheMtime {
+                validated: mtime.min(new_mtime),
+                latest: new_mtime,
+            });
+        new_mtime
+    }
+
     /// Load blocks from the storage concurrently.
     async fn load_blocks(
         &self,
@@ -61,21 +152,13 @@ where
 
         let mut blocks = vec![];
 
-        for (handle, block_id) in handles.into_iter().zip(start_block..end_block) {
+        for handle in handles {
             let block = handle
                 .await?
                 .context("Storage manager failed to load blocks.")?;
-            if let Some(block) = block {
-                blocks.push(block);
-            } else {
-                let mut zero_filled = Block::new_zeroed(self.block_size);
-                zero_filled.set_dirty(true);
-                self.storage
-                    .store(ino, block_id, zero_filled.clone())
-                    .await
-                    .context("Storage Manager failed to store blocks")?;
-                blocks.push(zero_filled);
-            }
+            // A block never written is in a hole, it reads as zeros and is not
+            // stored.
+            blocks.push(block.unwrap_or_else(|| Block::new_zeroed(self.block_size)));
         }
 
         Ok(blocks)
@@ -164,13 +247,10 @@ where
         mtime: SystemTime,
     ) -> DatenLordResult<Vec<Block>> {
         // Check if the cache is valid.
-        let invalid = {
-            let guard = pin();
-            let cache_mtime = self.mtimes.get(&ino, &guard);
-            cache_mtime != Some(&mtime)
-        };
+        let invalid = !self.is_cache_valid(ino, mtime);
 
         if invalid {
+            self.mtimes.lock().remove(&ino);
             self.storage
                 .invalidate(ino)
                 .await
@@ -192,7 +272,7 @@ where
         // If the cache is invalidated, it must be re-fetched from backend.
         // So the mtime of the cache should be updated to the passed-in one.
         if invalid {
-            self.mtimes.insert(ino, mtime);
+            self.set_cache_validated(ino, mtime);
         }
 
         if let Some(first_block) = blocks.first_mut() {
@@ -212,6 +292,52 @@ where
         Ok(blocks)
     }
 
+    /// Load data from storage via an open file handle, with the read-ahead
+    /// state kept by the handle.
+    ///
+    /// Sequential reads on the handle are detected, and the blocks next to
+    /// the read range are prefetched into the storage asynchronously.
+    /// `file_size` limits the prefetched blocks.
+    pub async fn read(
+        &self,
+        ino: INum,
+        read_ahead: &Mutex<ReadAheadState>,
+        offset: usize,
+        len: usize,
+        file_size: usize,
+        mtime: SystemTime,
+    ) -> DatenLordResult<Vec<Block>> {
+        let blocks = self.load(ino, offset, len, mtime).await?;
+
+        if len != 0 {
+            let end_block = self
+                .offset_to_block_id(offset.overflow_add(len).overflow_sub(1))
+                .overflow_add(1);
+            let file_blocks = file_size
+                .overflow_add(self.block_size.overflow_sub(1))
+                .overflow_div(self.block_size);
+            let prefetch = self.read_ahead.on_read(
+                &mut read_ahead.lock(),
+                offset,
+                len,
+                end_block,
+                file_blocks,
+            );
+            for block_id in prefetch {
+                let storage = Arc::clone(&self.storage);
+                task::spawn(async move {
+                    if let Err(e) = storage.load(ino, block_id).await {
+                        debug!(
+                            "Failed to prefetch block where ino={ino}, block_id={block_id}, err={e}"
+                        );
+                    }
+                });
+            }
+        }
+
+        Ok(blocks)
+    }
+
     /// Store data into storage.
     pub async fn store(
         &self,
@@ -220,14 +346,12 @@ where
         data: &[u8],
         mtime: SystemTime,
     ) -> DatenLordResult<SystemTime> {
+        self.check_writable(ino)?;
         // Check if the cache is valid.
-        let invalid = {
-            let guard = pin();
-            let cache_mtime = self.mtimes.get(&ino, &guard);
-            cache_mtime != Some(&mtime)
-        };
+        let invalid = !self.is_cache_valid(ino, mtime);
 
         if invalid {
+            self.mtimes.lock().remove(&ino);
             self.storage
                 .invalidate(ino)
                 .await
@@ -236,10 +360,7 @@ where
 
         if data.is_empty() {
             // The cache is invalid, but no new blocks will be loaded into the cache
-            // thus the mtime of this file is removed.
-            if invalid {
-                self.mtimes.remove(&ino);
-            }
+            // thus the mtime of this file has been removed.
             // No data will be written, so the passed-in mtime will be passed-out
             // changelessly.
             return Ok(mtime);
@@ -253,15 +374,209 @@ where
             .await?;
 
         // As the cache is overwritten, the cache mtime should be set to now.
-        let new_mtime = SystemTime::now();
-        self.mtimes.insert(ino, new_mtime);
+        Ok(self.set_cache_stored(ino, mtime))
+    }
+
+    /// Fill zeros in the byte range `[offset, offset + len)` of a file, as
+    /// `fallocate(2)` punches a hole.
+    ///
+    /// The blocks fully covered by the range are removed from the caches and
+    /// the backend, and the partially covered ones at both ends are
+    /// overwritten with zeros, so that the stale data never shows up again.
+    /// If the storage cannot remove blocks, all of them are overwritten in
+    /// batches instead. This method performs a store operation, therefore it
+    /// also takes a `mtime` and returns a new `mtime` like `store` method.
+    pub async fn punch_hole(
+        &self,
+        ino: INum,
+        offset: usize,
+        len: usize,
+        mtime: SystemTime,
+    ) -> DatenLordResult<SystemTime> {
+        /// The number of blocks stored concurrently.
+        const PUNCH_BATCH_SIZE: usize = 16;
+
+        self.check_writable(ino)?;
+        let invalid = !self.is_cache_valid(ino, mtime);
+
+        if invalid {
+            self.mtimes.lock().remove(&ino);
+            self.storage
+                .invalidate(ino)
+                .await
+                .context("Storage manager failed to invalidate the cache of a file")?;
+        }
+
+        if len == 0 {
+            return Ok(mtime);
+        }
+
+        let end = offset.overflow_add(len);
+        let start_block = self.offset_to_block_id(offset);
+        let end_block = self.offset_to_block_id(end.overflow_sub(1)).overflow_add(1);
+
+        let full_blocks = offset.div_ceil(self.block_size)..self.offset_to_block_id(end);
+        let punched = !full_blocks.is_empty()
+            && self
+                .storage
+                .punch_blocks(ino, full_blocks.clone())
+                .await
+                .context("Storage manager failed to remove the blocks in a hole")?;
+        let zeroed = if punched {
+            vec![start_block..full_blocks.start, full_blocks.end..end_block]
+        } else {
+            vec![start_block..end_block]
+        };
+
+        for blocks in zeroed {
+            let mut batch_start = blocks.start;
+            while batch_start < blocks.end {
+                let batch_end = batch_start.overflow_add(PUNCH_BATCH_SIZE).min(blocks.end);
+                let zeros = (batch_start..batch_end).map(|block_id| {
+                    let block_offset = block_id.overflow_mul(self.block_size);
+                    let start = offset.saturating_sub(block_offset).min(self.block_size);
+                    let end = end.overflow_sub(block_offset).min(self.block_size);
+                    Block::new_zeroed_with_range(self.block_size, start, end)
+                });
+                self.store_blocks(ino, batch_start, zeros).await?;
+                batch_start = batch_end;
+            }
+        }
 
-        Ok(new_mtime)
+        Ok(self.set_cache_stored(ino, mtime))
+    }
+
+    /// Copy the byte range `[src_offset, src_offset + len)` of file `src_ino`
+    /// to the offset of `dst_offset` of file `dst_ino`, as
+    /// `copy_file_range(2)` does.
+    ///
+    /// If both offsets are at the same position in their blocks, the whole
+    /// blocks in the range are cloned by the storage, and share the content
+    /// with the sources if it's supported. The rest is loaded and stored in
+    /// batches. This method performs a store operation on the destination,
+    /// therefore it takes the `mtime` of both files and returns a new `mtime`
+    /// of the destination like `store` method.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn copy_range(
+        &self,
+        src_ino: INum,
+        src_offset: usize,
+        src_mtime: SystemTime,
+        dst_ino: INum,
+        dst_offset: usize,
+        len: usize,
+        dst_mtime: SystemTime,
+    ) -> DatenLordResult<SystemTime> {
+        self.check_writable(dst_ino)?;
+        let in_block = src_offset.overflow_rem(self.block_size);
+        let (head_len, clone_count) = if in_block == dst_offset.overflow_rem(self.block_size) {
+            let head_len = self
+                .block_size
+                .overflow_sub(in_block)
+                .overflow_rem(self.block_size)
+                .min(len);
+            let clone_count = len.overflow_sub(head_len).overflow_div(self.block_size);
+            (head_len, clone_count)
+        } else {
+            (len, 0)
+        };
+
+        let mut dst_mtime = self
+            .copy_data(
+                src_ino, src_offset, src_mtime, dst_ino, dst_offset, head_len, dst_mtime,
+            )
+            .await?;
+        let mut copied = head_len;
+
+        if clone_count > 0 {
+            let src_block = self.offset_to_block_id(src_offset.overflow_add(copied));
+            let dst_block = self.offset_to_block_id(dst_offset.overflow_add(copied));
+            let src_mtime = if src_ino == dst_ino {
+                dst_mtime
+            } else {
+                src_mtime
+            };
+            let src_invalid = !self.is_cache_valid(src_ino, src_mtime);
+            let dst_invalid = !self.is_cache_valid(dst_ino, dst_mtime);
+            if src_invalid {
+                self.storage
+                    .invalidate(src_ino)
+                    .await
+                    .context("Storage manager failed to invalidate the cache of a file")?;
+                self.set_cache_validated(src_ino, src_mtime);
+            }
+            if dst_invalid {
+                self.mtimes.lock().remove(&dst_ino);
+                self.storage
+                    .invalidate(dst_ino)
+                    .await
+                    .context("Storage manager failed to invalidate the cache of a file")?;
+            }
+
+            let cloned = self
+                .storage
+                .clone_blocks(src_ino, src_block, dst_ino, dst_block, clone_count)
+                .await
+                .context("Storage manager failed to clone blocks")?;
+            if cloned {
+                copied = copied.overflow_add(clone_count.overflow_mul(self.block_size));
+                dst_mtime = self.set_cache_stored(dst_ino, dst_mtime);
+            }
+        }
+
+        self.copy_data(
+            src_ino,
+            src_offset.overflow_add(copied),
+            src_mtime,
+            dst_ino,
+            dst_offset.overflow_add(copied),
+            len.overflow_sub(copied),
+            dst_mtime,
+        )
+        .await
+    }
+
+    /// Copy a byte range by loading and storing the data in batches.
+    #[allow(clippy::too_many_arguments)]
+    async fn copy_data(
+        &self,
+        src_ino: INum,
+        src_offset: usize,
+        mut src_mtime: SystemTime,
+        dst_ino: INum,
+        dst_offset: usize,
+        len: usize,
+        mut dst_mtime: SystemTime,
+    ) -> DatenLordResult<SystemTime> {
+        /// The number of blocks copied in a batch.
+        const COPY_BATCH_SIZE: usize = 16;
+
+        let batch_len = self.block_size.overflow_mul(COPY_BATCH_SIZE);
+        let mut copied = 0;
+        while copied < len {
+            // Copying inside a file changes the `mtime` of the source.
+            if src_ino == dst_ino {
+                src_mtime = dst_mtime;
+            }
+            let size = len.overflow_sub(copied).min(batch_len);
+            let blocks = self
+                .load(src_ino, src_offset.overflow_add(copied), size, src_mtime)
+                .await?;
+            let data: Vec<u8> = blocks
+                .iter()
+                .flat_map(|block| block.as_slice().iter().copied())
+                .collect();
+            dst_mtime = self
+                .store(dst_ino, dst_offset.overflow_add(copied), &data, dst_mtime)
+                .await?;
+            copied = copied.overflow_add(size);
+        }
+        Ok(dst_mtime)
     }
 
     /// Remove a file from the storage.
     pub async fn remove(&self, ino: INum) -> DatenLordResult<()> {
-        self.mtimes.remove(&ino);
+        self.mtimes.lock().remove(&ino);
         self.storage
             .remove(ino)
             .await
@@ -269,6 +584,17 @@ where
         Ok(())
     }
 
+    /// Invalidate the cache of a file, it's re-fetched from the backend on the
+    /// next load.
+    pub async fn invalidate(&self, ino: INum) -> DatenLordResult<()> {
+        self.mtimes.lock().remove(&ino);
+        self.storage
+            .invalidate(ino)
+            .await
+            .context("Storage manager failed to invalidate the cache of a file")?;
+        Ok(())
+    }
+
     /// Flush the cache to the persistent layer.
     pub async fn flush(&self, ino: INum) -> DatenLordResult<()> {
         self.storage
@@ -290,10 +616,12 @@ where
     /// Truncate a file, from size of `from` to size of `to`.
     /// Both `from` and `to` are in bytes, and are excluded from the range.
     /// After the truncating, the valid range of a file in the storage
-    /// is `[0, to)`.
+    /// is `[0, to)`. Truncating up stores nothing, the extended range is a
+    /// hole.
     ///
     /// This method performs a store operation, therefore it also takes a
-    /// `mtime` and returns a new `mtime` like `store` method.
+    /// `mtime` and returns a new `mtime` like `store` method, unless the size
+    /// is not changed.
     pub async fn truncate(
         &self,
         ino: INum,
@@ -301,27 +629,26 @@ where
         to: usize,
         mtime: SystemTime,
     ) -> DatenLordResult<SystemTime> {
-        let invalid = {
-            let guard = pin();
-            let cache_mtime = self.mtimes.get(&ino, &guard);
-            cache_mtime != Some(&mtime)
-        };
+        self.check_writable(ino)?;
+        let invalid = !self.is_cache_valid(ino, mtime);
 
         if invalid {
+            self.mtimes.lock().remove(&ino);
             self.storage
                 .invalidate(ino)
                 .await
                 .context("Storage manager failed to invalidate the cache of a file")?;
         }
 
-        if from <= to {
-            if invalid {
-                self.mtimes.remove(&ino);
-            }
-
+        if from == to {
             return Ok(mtime);
         }
 
+        if from < to {
+            // The cached blocks are still valid, as nothing is stored.
+            return Ok(self.set_cache_stored(ino, mtime));
+        }
+
         let from_block = self
             .offset_to_block_id(from.overflow_sub(1))
             .overflow_add(1);
@@ -345,9 +672,6 @@ where
             .await
             .context("Storage manager failed to truncate a file")?;
 
-        let new_mtime = SystemTime::now();
-        self.mtimes.insert(ino, new_mtime);
-
-        Ok(new_mtime)
+        Ok(self.set_cache_stored(ino, mtime))
     }
 }
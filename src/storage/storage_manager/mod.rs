@@ -1,8 +1,10 @@
 //! The storage manager.
 
 mod manager;
+mod read_ahead;
 
 pub use manager::StorageManager;
+pub use read_ahead::ReadAheadState;
 
 #[cfg(test)]
 mod test;
@@ -0,0 +1,141 @@
+//! The sequential read-ahead of `StorageManager`.
+
+use std::ops::Range;
+
+use clippy_utilities::OverflowArithmetic;
+
+/// The initial window of read-ahead in blocks, once a sequential read is
+/// detected.
+const INITIAL_WINDOW_IN_BLOCKS: usize = 1;
+
+/// The read-ahead state of an open file handle, which is kept by the handle
+/// and dropped with it.
+#[derive(Debug, Default)]
+pub struct ReadAheadState {
+    /// The offset where the next sequential read is expected to start
+    next_offset: usize,
+    /// The current window in blocks, `0` means no read-ahead
+    window: usize,
+    /// The blocks before this one have been prefetched
+    prefetched_until: usize,
+}
+
+/// Tracks the access patterns of open file handles, and decides which blocks
+/// to prefetch.
+///
+/// The window starts with one block when a sequential read is detected, and
+/// doubles on each sequential read until it reaches the maximum window. A
+/// random read resets the window.
+#[derive(Debug)]
+pub(super) struct ReadAhead {
+    /// The maximum window in blocks, `0` disables read-ahead
+    max_window: usize,
+}
+
+impl ReadAhead {
+    /// Create a `ReadAhead` with the maximum window in blocks.
+    pub(super) fn new(max_window: usize) -> Self {
+        Self { max_window }
+    }
+
+    /// Record a read of `[offset, offset + len)` on the file handle of
+    /// `state`, and returns the range of blocks to prefetch.
+    ///
+    /// `end_block` is the block next to the last block read, and `file_blocks`
+    /// is the number of blocks of the file, no block beyond it will be
+    /// prefetched.
+    pub(super) fn on_read(
+        &self,
+        state: &mut ReadAheadState,
+        offset: usize,
+        len: usize,
+        end_block: usize,
+        file_blocks: usize,
+    ) -> Range<usize> {
+        if self.max_window == 0 {
+            return 0..0;
+        }
+
+        if offset == state.next_offset {
+            state.window = if state.window == 0 {
+                INITIAL_WINDOW_IN_BLOCKS
+            } else {
+                state.window.overflow_mul(2)
+            }
+            .min(self.max_window);
+        } else {
+            state.window = 0;
+            state.prefetched_until = 0;
+        }
+        state.next_offset = offset.overflow_add(len);
+
+        let start = end_block.max(state.prefetched_until);
+        let end = end_block.overflow_add(state.window).min(file_blocks);
+        if start >= end {
+            return 0..0;
+        }
+
+        state.prefetched_until = end;
+        start..end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReadAhead, ReadAheadState};
+
+    #[test]
+    fn test_window_grows_on_sequential_reads() {
+        let read_ahead = ReadAhead::new(4);
+        let mut state = ReadAheadState::default();
+
+        assert_eq!(read_ahead.on_read(&mut state, 0, 8, 1, 100), 1..2);
+        assert_eq!(read_ahead.on_read(&mut state, 8, 8, 2, 100), 2..4);
+        assert_eq!(read_ahead.on_read(&mut state, 16, 8, 3, 100), 4..7);
+        // The window is limited by the maximum window.
+        assert_eq!(read_ahead.on_read(&mut state, 24, 8, 4, 100), 7..8);
+    }
+
+    #[test]
+    fn test_random_read_resets_window() {
+        let read_ahead = ReadAhead::new(4);
+        let mut state = ReadAheadState::default();
+
+        assert_eq!(read_ahead.on_read(&mut state, 0, 8, 1, 100), 1..2);
+        assert_eq!(read_ahead.on_read(&mut state, 80, 8, 11, 100), 0..0);
+        assert_eq!(read_ahead.on_read(&mut state, 88, 8, 12, 100), 12..13);
+    }
+
+    #[test]
+    fn test_read_ahead_within_file() {
+        let read_ahead = ReadAhead::new(4);
+        let mut state = ReadAheadState::default();
+
+        assert_eq!(read_ahead.on_read(&mut state, 0, 8, 1, 2), 1..2);
+        assert_eq!(read_ahead.on_read(&mut state, 8, 8, 2, 2), 0..0);
+    }
+
+    #[test]
+    fn test_handles_are_independent() {
+        let read_ahead = ReadAhead::new(4);
+        let mut state = ReadAheadState::default();
+        let mut other = ReadAheadState::default();
+
+        assert_eq!(read_ahead.on_read(&mut state, 0, 8, 1, 100), 1..2);
+        assert_eq!(read_ahead.on_read(&mut other, 40, 8, 6, 100), 0..0);
+        assert_eq!(read_ahead.on_read(&mut state, 8, 8, 2, 100), 2..4);
+
+        // A new handle starts without read-ahead.
+        let mut state = ReadAheadState::default();
+        assert_eq!(read_ahead.on_read(&mut state, 16, 8, 3, 100), 0..0);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let read_ahead = ReadAhead::new(0);
+        let mut state = ReadAheadState::default();
+
+        assert_eq!(read_ahead.on_read(&mut state, 0, 8, 1, 100), 0..0);
+        assert_eq!(read_ahead.on_read(&mut state, 8, 8, 2, 100), 0..0);
+    }
+}
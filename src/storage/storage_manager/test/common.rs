@@ -4,12 +4,14 @@ use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
 use clippy_utilities::OverflowArithmetic;
+use parking_lot::Mutex;
 
 use super::{BLOCK_CONTENT, BLOCK_SIZE_IN_BYTES, CACHE_CAPACITY_IN_BLOCKS};
 use crate::storage::mock::MemoryStorage;
 use crate::storage::policy::LruPolicy;
 use crate::storage::{
-    Block, BlockCoordinate, MemoryCache, MemoryCacheBuilder, Storage, StorageManager,
+    Block, BlockCoordinate, MemoryCache, MemoryCacheBuilder, ReadAheadState, Storage,
+    StorageManager,
 };
 
 type MemoryCacheType = MemoryCache<LruPolicy<BlockCoordinate>, Arc<MemoryStorage>>;
@@ -172,6 +174,91 @@ async fn test_read_write_inexist_block() {
     assert_eq!(loaded[0].as_slice(), ZEROED_BLOCK);
 }
 
+#[tokio::test]
+async fn test_read_hole_not_stored() {
+    let (backend, storage) = create_storage().await;
+
+    let loaded = storage
+        .load(0, 0, BLOCK_SIZE_IN_BYTES, SystemTime::now())
+        .await
+        .unwrap();
+    assert_eq!(loaded.len(), 1);
+    storage.flush_all().await.unwrap();
+    assert!(!backend.contains(0, 0));
+}
+
+#[tokio::test]
+async fn test_punch_hole() {
+    let ino = 0;
+    let content = BLOCK_CONTENT.repeat(3);
+
+    let (backend, storage) = create_storage().await;
+
+    let mtime = storage
+        .store(ino, 0, &content, SystemTime::now())
+        .await
+        .unwrap();
+    let mtime = storage
+        .punch_hole(ino, 4, BLOCK_SIZE_IN_BYTES.overflow_add(6), mtime)
+        .await
+        .unwrap();
+
+    let loaded = storage.load(ino, 0, content.len(), mtime).await.unwrap();
+    assert_eq!(loaded.len(), 3);
+    assert_eq!(loaded[0].as_slice(), b"foo \0\0\0\0");
+    assert_eq!(loaded[1].as_slice(), b"\0\0\0\0\0\0\0\0");
+    assert_eq!(loaded[2].as_slice(), b"\0\0o bar ");
+
+    // The block fully covered is removed rather than filled with zeros, and
+    // the ones at both ends are kept.
+    storage.flush_all().await.unwrap();
+    assert!(backend.contains(ino, 0));
+    assert!(!backend.contains(ino, 1));
+    assert!(backend.contains(ino, 2));
+}
+
+#[tokio::test]
+async fn test_copy_range() {
+    let content = b"0123456789abcdefghijklmn";
+
+    let (_, storage) = create_storage().await;
+
+    let src_mtime = storage
+        .store(0, 0, content, SystemTime::now())
+        .await
+        .unwrap();
+
+    // Aligned, the whole block is cloned, or copied if cloning is not
+    // supported.
+    let dst_mtime = storage
+        .copy_range(0, 4, src_mtime, 1, 4, 16, SystemTime::now())
+        .await
+        .unwrap();
+    let loaded = storage.load(1, 0, 20, dst_mtime).await.unwrap();
+    assert_eq!(loaded.len(), 3);
+    assert_eq!(loaded[0].as_slice(), b"\0\0\0\04567");
+    assert_eq!(loaded[1].as_slice(), b"89abcdef");
+    assert_eq!(loaded[2].as_slice(), b"ghij");
+
+    // Unaligned
+    let dst_mtime = storage
+        .copy_range(0, 1, src_mtime, 1, 2, 10, dst_mtime)
+        .await
+        .unwrap();
+    let loaded = storage.load(1, 0, 12, dst_mtime).await.unwrap();
+    assert_eq!(loaded[0].as_slice(), b"\0\0123456");
+    assert_eq!(loaded[1].as_slice(), b"789a");
+
+    // Inside a file
+    let src_mtime = storage
+        .copy_range(0, 0, src_mtime, 0, 12, 8, src_mtime)
+        .await
+        .unwrap();
+    let loaded = storage.load(0, 8, 16, src_mtime).await.unwrap();
+    assert_eq!(loaded[0].as_slice(), b"89ab0123");
+    assert_eq!(loaded[1].as_slice(), b"4567klmn");
+}
+
 #[tokio::test]
 async fn test_zero_size_read_write() {
     let ino = 0;
@@ -283,6 +370,32 @@ async fn test_invalid_cache_on_write() {
     assert_eq!(loaded[0].as_slice(), b"foo foo ");
 }
 
+#[tokio::test]
+async fn test_concurrent_stores_keep_cache() {
+    let ino = 0;
+
+    let (backend, storage) = create_storage().await;
+
+    // Two writes read the same `mtime` before either of them is stored.
+    let mtime = SystemTime::now();
+    let first_mtime = storage.store(ino, 0, b"foo ", mtime).await.unwrap();
+    // A block stored by another node is not loaded, as the cache is valid.
+    backend
+        .store(ino, 0, Block::from_slice(BLOCK_SIZE_IN_BYTES, b"xxxxxxxx"))
+        .await
+        .unwrap();
+    let second_mtime = storage.store(ino, 4, b"bar ", mtime).await.unwrap();
+    assert!(second_mtime >= first_mtime);
+
+    for mtime in [first_mtime, second_mtime] {
+        let loaded = storage
+            .load(ino, 0, BLOCK_SIZE_IN_BYTES, mtime)
+            .await
+            .unwrap();
+        assert_eq!(loaded[0].as_slice(), b"foo bar ");
+    }
+}
+
 #[tokio::test]
 async fn test_remove() {
     let ino = 0;
@@ -342,12 +455,43 @@ async fn test_truncate() {
     assert_eq!(loaded[0].as_slice(), b"\0\0");
 
     let epoch = storage
-        .truncate(ino, 0, 4, SystemTime::UNIX_EPOCH)
+        .truncate(ino, 4, 4, SystemTime::UNIX_EPOCH)
         .await
         .unwrap();
     assert_eq!(epoch, SystemTime::UNIX_EPOCH);
 }
 
+#[tokio::test]
+async fn test_truncate_up() {
+    let ino = 0;
+
+    let (_, storage) = create_storage().await;
+
+    let mtime = storage
+        .store(ino, 0, BLOCK_CONTENT, SystemTime::now())
+        .await
+        .unwrap();
+    let new_mtime = storage
+        .truncate(
+            ino,
+            BLOCK_SIZE_IN_BYTES,
+            BLOCK_SIZE_IN_BYTES.overflow_mul(3),
+            mtime,
+        )
+        .await
+        .unwrap();
+    assert_ne!(new_mtime, mtime);
+
+    // The cache is kept, and the extended range reads as zeros.
+    let loaded = storage
+        .load(ino, 0, BLOCK_SIZE_IN_BYTES.overflow_mul(2), new_mtime)
+        .await
+        .unwrap();
+    assert_eq!(loaded.len(), 2);
+    assert_eq!(loaded[0].as_slice(), BLOCK_CONTENT);
+    assert_eq!(loaded[1].as_slice(), &[0_u8; BLOCK_SIZE_IN_BYTES]);
+}
+
 #[tokio::test]
 async fn test_truncate_remove() {
     let (backend, storage) = create_storage().await;
@@ -364,3 +508,91 @@ async fn test_truncate_remove() {
 
     assert!(!backend.contains(0, 0));
 }
+
+#[tokio::test]
+async fn test_sequential_read_ahead() {
+    let ino = 0;
+    let read_ahead = Mutex::new(ReadAheadState::default());
+    let mtime = SystemTime::now();
+    let file_size = BLOCK_SIZE_IN_BYTES.overflow_mul(3);
+
+    let backend = Arc::new(MemoryStorage::new(
+        BLOCK_SIZE_IN_BYTES,
+        Duration::from_millis(0),
+    ));
+    let lru = LruPolicy::<BlockCoordinate>::new(CACHE_CAPACITY_IN_BLOCKS);
+    let cache = MemoryCacheBuilder::new(lru, Arc::clone(&backend), BLOCK_SIZE_IN_BYTES)
+        .build()
+        .await;
+    let storage = StorageManager::new(Arc::clone(&cache), BLOCK_SIZE_IN_BYTES).with_read_ahead(2);
+
+    for block_id in 0..3 {
+        backend
+            .store(
+                ino,
+                block_id,
+                Block::from_slice(BLOCK_SIZE_IN_BYTES, BLOCK_CONTENT),
+            )
+            .await
+            .unwrap();
+    }
+
+    let loaded = storage
+        .read(ino, &read_ahead, 0, BLOCK_SIZE_IN_BYTES, file_size, mtime)
+        .await
+        .unwrap();
+    assert_eq!(loaded[0].as_slice(), BLOCK_CONTENT);
+
+    // The next block is prefetched into the cache.
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(cache.load_from_self(ino, 1).await.unwrap().is_some());
+    assert!(cache.load_from_self(ino, 2).await.unwrap().is_none());
+
+    let _: Vec<Block> = storage
+        .read(
+            ino,
+            &read_ahead,
+            BLOCK_SIZE_IN_BYTES,
+            BLOCK_SIZE_IN_BYTES,
+            file_size,
+            mtime,
+        )
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(cache.load_from_self(ino, 2).await.unwrap().is_some());
+    // No block beyond the file is prefetched.
+    assert!(!backend.contains(ino, 3));
+}
+
+#[tokio::test]
+async fn test_read_only() {
+    let ino = 0;
+
+    let (backend, storage) = create_storage().await;
+
+    let mtime = storage
+        .store(ino, 0, BLOCK_CONTENT, SystemTime::now())
+        .await
+        .unwrap();
+    let storage = storage.with_read_only(true);
+
+    // Nothing is stored, while the blocks are still loaded.
+    assert!(storage.store(ino, 0, b"foo", mtime).await.is_err());
+    assert!(storage
+        .store(ino, BLOCK_SIZE_IN_BYTES, b"foo", mtime)
+        .await
+        .is_err());
+    assert!(storage.punch_hole(ino, 0, 4, mtime).await.is_err());
+    assert!(storage
+        .truncate(ino, BLOCK_SIZE_IN_BYTES, 0, mtime)
+        .await
+        .is_err());
+    assert!(!backend.contains(ino, 1));
+
+    let loaded = storage
+        .load(ino, 0, BLOCK_SIZE_IN_BYTES, mtime)
+        .await
+        .unwrap();
+    assert_eq!(loaded[0].as_slice(), BLOCK_CONTENT);
+}
@@ -1,11 +1,12 @@
 //! The storage trait, as a abstraction of the storage layers.
 
+use std::ops::Range;
 use std::sync::Arc;
 
 use async_trait::async_trait;
 
 use super::error::StorageResult;
-use super::Block;
+use super::{Block, BlockId};
 use crate::async_fuse::fuse::protocol::INum;
 
 /// The `Storage` trait. It handles blocks with storage such as in-memory cache,
@@ -60,6 +61,56 @@ pub trait Storage {
 
     // Provided methods
 
+    /// Clone `count` blocks of file `src_ino` from the block id of `src_block`
+    /// to file `dst_ino` from the block id of `dst_block`, the clones share
+    /// their content with the sources, and are copied on write.
+    ///
+    /// Returns `false` if the storage cannot share blocks, then nothing is
+    /// done, and the caller should copy the content itself.
+    async fn clone_blocks(
+        &self,
+        _src_ino: INum,
+        _src_block: usize,
+        _dst_ino: INum,
+        _dst_block: usize,
+        _count: usize,
+    ) -> StorageResult<bool> {
+        Ok(false)
+    }
+
+    /// Remove `blocks` of file `ino` from the caches and the backend, as
+    /// they're deallocated by a hole punched in the file, and read as zeros
+    /// afterwards.
+    ///
+    /// Returns `false` if the storage cannot remove blocks in the middle of a
+    /// file, then nothing is done, and the caller should fill them with zeros
+    /// itself.
+    async fn punch_blocks(&self, _ino: INum, _blocks: Range<BlockId>) -> StorageResult<bool> {
+        Ok(false)
+    }
+
+    /// Pin `blocks` of file `ino` in the lowest cache of the storage, so
+    /// they're never evicted from it until they're unpinned. It does nothing
+    /// if the storage contains no cache.
+    fn pin(&self, _ino: INum, _blocks: Range<BlockId>) {}
+
+    /// Unpin `blocks` of file `ino` pinned by `pin`.
+    fn unpin(&self, _ino: INum, _blocks: Range<BlockId>) {}
+
+    /// The capacity in blocks of the cache the blocks are pinned in, 0 if
+    /// the storage contains no cache.
+    fn pin_capacity(&self) -> usize {
+        0
+    }
+
+    /// Load a clean block from the caches of the storage, but neither from
+    /// its backend nor from the other nodes, which serves the reads of the
+    /// other nodes. Returns `None` if the block is not cached, or the storage
+    /// contains no cache.
+    async fn load_cached(&self, _ino: INum, _block_id: usize) -> StorageResult<Option<Block>> {
+        Ok(None)
+    }
+
     /// Load a block from the storage.
     async fn load(&self, ino: INum, block_id: usize) -> StorageResult<Option<Block>> {
         if let Some(block_in_cache) = self.load_from_self(ino, block_id).await? {
@@ -80,7 +131,7 @@ pub trait Storage {
 #[async_trait]
 impl<T> Storage for Arc<T>
 where
-    T: Storage + Send + Sync,
+    T: Storage + Send + Sync + ?Sized,
 {
     async fn load_from_self(&self, ino: INum, block_id: usize) -> StorageResult<Option<Block>> {
         self.as_ref().load_from_self(ino, block_id).await
@@ -136,4 +187,37 @@ where
             .truncate(ino, from_block, to_block, fill_start)
             .await
     }
+
+    async fn clone_blocks(
+        &self,
+        src_ino: INum,
+        src_block: usize,
+        dst_ino: INum,
+        dst_block: usize,
+        count: usize,
+    ) -> StorageResult<bool> {
+        self.as_ref()
+            .clone_blocks(src_ino, src_block, dst_ino, dst_block, count)
+            .await
+    }
+
+    async fn punch_blocks(&self, ino: INum, blocks: Range<BlockId>) -> StorageResult<bool> {
+        self.as_ref().punch_blocks(ino, blocks).await
+    }
+
+    fn pin(&self, ino: INum, blocks: Range<BlockId>) {
+        self.as_ref().pin(ino, blocks);
+    }
+
+    fn unpin(&self, ino: INum, blocks: Range<BlockId>) {
+        self.as_ref().unpin(ino, blocks);
+    }
+
+    fn pin_capacity(&self) -> usize {
+        self.as_ref().pin_capacity()
+    }
+
+    async fn load_cached(&self, ino: INum, block_id: usize) -> StorageResult<Option<Block>> {
+        self.as_ref().load_cached(ino, block_id).await
+    }
 }
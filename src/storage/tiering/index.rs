@@ -0,0 +1,116 @@
+//! The index of block tiers.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+
+use super::{BlockTier, Tier};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::error::StorageResult;
+
+/// The `TierIndex` trait, which records the tiers of blocks and the tiers
+/// files are pinned to.
+#[async_trait]
+pub trait TierIndex {
+    /// Get the tier record of a block.
+    ///
+    /// Returns `None` if the block has no tier recorded.
+    async fn get_tier(&self, ino: INum, block_id: usize) -> StorageResult<Option<BlockTier>>;
+
+    /// Set the tier record of a block, the previous record will be
+    /// overwritten.
+    async fn set_tier(&self, record: BlockTier) -> StorageResult<()>;
+
+    /// Remove the tier records of blocks of a file in `block_ids`.
+    async fn remove_tiers(&self, ino: INum, block_ids: Range<usize>) -> StorageResult<()>;
+
+    /// List all tier records, for migrating.
+    async fn list_tiers(&self) -> StorageResult<Vec<BlockTier>>;
+
+    /// Get the tier a file is pinned to, `None` if it's decided by the policy.
+    async fn pinned_tier(&self, ino: INum) -> StorageResult<Option<Tier>>;
+}
+
+#[async_trait]
+impl<T> TierIndex for Arc<T>
+where
+    T: TierIndex + Send + Sync,
+{
+    async fn get_tier(&self, ino: INum, block_id: usize) -> StorageResult<Option<BlockTier>> {
+        self.as_ref().get_tier(ino, block_id).await
+    }
+
+    async fn set_tier(&self, record: BlockTier) -> StorageResult<()> {
+        self.as_ref().set_tier(record).await
+    }
+
+    async fn remove_tiers(&self, ino: INum, block_ids: Range<usize>) -> StorageResult<()> {
+        self.as_ref().remove_tiers(ino, block_ids).await
+    }
+
+    async fn list_tiers(&self) -> StorageResult<Vec<BlockTier>> {
+        self.as_ref().list_tiers().await
+    }
+
+    async fn pinned_tier(&self, ino: INum) -> StorageResult<Option<Tier>> {
+        self.as_ref().pinned_tier(ino).await
+    }
+}
+
+/// A `TierIndex` in memory, which is not persisted.
+#[derive(Debug, Default)]
+pub struct MemoryTierIndex {
+    /// The tier records
+    tiers: Mutex<BTreeMap<(INum, usize), BlockTier>>,
+    /// The tiers files are pinned to
+    pins: Mutex<HashMap<INum, Tier>>,
+}
+
+impl MemoryTierIndex {
+    /// Create an empty `MemoryTierIndex`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pin a file to `tier`, or unpin it if it's `None`.
+    pub fn pin(&self, ino: INum, tier: Option<Tier>) {
+        let mut pins = self.pins.lock();
+        match tier {
+            Some(tier) => pins.insert(ino, tier),
+            None => pins.remove(&ino),
+        };
+    }
+}
+
+#[async_trait]
+impl TierIndex for MemoryTierIndex {
+    async fn get_tier(&self, ino: INum, block_id: usize) -> StorageResult<Option<BlockTier>> {
+        Ok(self.tiers.lock().get(&(ino, block_id)).copied())
+    }
+
+    async fn set_tier(&self, record: BlockTier) -> StorageResult<()> {
+        self.tiers
+            .lock()
+            .insert((record.ino, record.block_id), record);
+        Ok(())
+    }
+
+    async fn remove_tiers(&self, ino: INum, block_ids: Range<usize>) -> StorageResult<()> {
+        self.tiers
+            .lock()
+            .retain(|&(key_ino, block_id), _| key_ino != ino || !block_ids.contains(&block_id));
+        Ok(())
+    }
+
+    async fn list_tiers(&self) -> StorageResult<Vec<BlockTier>> {
+        Ok(self.tiers.lock().values().copied().collect())
+    }
+
+    async fn pinned_tier(&self, ino: INum) -> StorageResult<Option<Tier>> {
+        Ok(self.pins.lock().get(&ino).copied())
+    }
+}
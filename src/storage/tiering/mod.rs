@@ -0,0 +1,83 @@
+//! The tiering of blocks between a hot tier and a cold tier.
+//!
+//! The hot tier is a fast [`BlockStore`](crate::storage::BlockStore), usually
+//! a directory on SSD, and the cold tier is the object storage. The tier of
+//! every block is recorded in a [`TierIndex`], which is usually the metadata
+//! store, and reads are served by whichever tier holds the block.
+//!
+//! New blocks are written to the hot tier, and a migration pass moves blocks
+//! by the [`TierPolicy`] periodically:
+//!
+//! - A block pinned to a tier, by the extended attribute of its file or an
+//!   ancestor, or by a path rule, is moved to that tier.
+//! - A block in the hot tier not accessed for a while is moved to the cold
+//!   tier.
+//! - A block in the cold tier accessed frequently is moved to the hot tier.
+//!
+//! A block is written to its new tier before its record is updated, and is
+//! deleted from its old tier afterwards, so it's always readable during the
+//! migration.
+
+mod index;
+mod store;
+
+use std::time::{Duration, SystemTime};
+
+pub use datenlord::config::Tier;
+pub use index::{MemoryTierIndex, TierIndex};
+use serde::{Deserialize, Serialize};
+pub use store::{TierReport, TieredBlockStore};
+
+use crate::async_fuse::fuse::protocol::INum;
+
+/// The tier record of a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockTier {
+    /// The inode number of the file
+    pub ino: INum,
+    /// The index of the block in the file
+    pub block_id: usize,
+    /// The tier holding the block
+    pub tier: Tier,
+    /// The last time the block is accessed, it's updated by migration passes
+    pub accessed: SystemTime,
+}
+
+/// The policy deciding the tiers of blocks not pinned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TierPolicy {
+    /// How long a block is not accessed before it's moved to the cold tier
+    pub cold_after: Duration,
+    /// The number of accesses in a migration interval, after which a block is
+    /// moved to the hot tier
+    pub promote_accesses: u32,
+}
+
+impl TierPolicy {
+    /// Decide the tier of a block, which is accessed `accesses` times since
+    /// the last pass.
+    #[must_use]
+    pub fn decide(&self, record: &BlockTier, accesses: u32, now: SystemTime) -> Tier {
+        match record.tier {
+            Tier::Hot => {
+                let idle = now.duration_since(record.accessed).unwrap_or_default();
+                if accesses == 0 && idle >= self.cold_after {
+                    Tier::Cold
+                } else {
+                    Tier::Hot
+                }
+            }
+            Tier::Cold => {
+                if accesses > 0 && accesses >= self.promote_accesses {
+                    Tier::Hot
+                } else {
+                    Tier::Cold
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests;
@@ -0,0 +1,241 @@
+//! The block store migrating blocks between tiers.
+
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use clippy_utilities::OverflowArithmetic;
+use parking_lot::Mutex;
+use tokio::select;
+use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use super::{BlockTier, Tier, TierIndex, TierPolicy};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::error::StorageResult;
+use crate::storage::{BlockKey, BlockStore};
+
+/// The result of a migration pass.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TierReport {
+    /// The number of blocks moved to the hot tier
+    pub promoted: usize,
+    /// The number of blocks moved to the cold tier
+    pub demoted: usize,
+}
+
+/// A `BlockStore` storing blocks in a hot tier and a cold tier.
+///
+/// Blocks are addressed by their files and indexes only, so it's not used
+/// with the storage layers keeping versions of blocks.
+#[derive(Debug)]
+pub struct TieredBlockStore<I, H, C> {
+    /// The index of tiers
+    index: I,
+    /// The hot tier
+    hot: H,
+    /// The cold tier
+    cold: C,
+    /// The policy of the tiers of blocks not pinned
+    policy: TierPolicy,
+    /// The numbers of accesses of blocks since the last migration pass
+    accessed: Mutex<HashMap<(INum, usize), u32>>,
+    /// Writes and deletes hold it shared, and moving a block holds it
+    /// exclusively, so a block is never written to its old tier while moving.
+    moving: RwLock<()>,
+}
+
+impl<I, H, C> TieredBlockStore<I, H, C>
+where
+    I: TierIndex + Send + Sync,
+    H: BlockStore + Send + Sync,
+    C: BlockStore + Send + Sync,
+{
+    /// Create a `TieredBlockStore` upon the `hot` and `cold` tiers.
+    pub fn new(index: I, hot: H, cold: C, policy: TierPolicy) -> Self {
+        Self {
+            index,
+            hot,
+            cold,
+            policy,
+            accessed: Mutex::new(HashMap::new()),
+            moving: RwLock::new(()),
+        }
+    }
+
+    /// Get the store of a tier.
+    fn tier(&self, tier: Tier) -> &(dyn BlockStore + Send + Sync) {
+        match tier {
+            Tier::Hot => &self.hot,
+            Tier::Cold => &self.cold,
+        }
+    }
+
+    /// Move a block to the tier `to`, returns whether it's moved.
+    async fn move_block(&self, ino: INum, block_id: usize, to: Tier) -> StorageResult<bool> {
+        let _guard = self.moving.write().await;
+        // The block may be removed or moved during the pass, check with the
+        // latest record.
+        let Some(mut record) = self.index.get_tier(ino, block_id).await? else {
+            return Ok(false);
+        };
+        if record.tier == to {
+            return Ok(false);
+        }
+
+        let key = BlockKey::new(ino, block_id);
+        let Some(data) = self.tier(record.tier).get(key).await? else {
+            // The block is gone, only the record is left.
+            self.index
+                .remove_tiers(ino, block_id..block_id.overflow_add(1))
+                .await?;
+            return Ok(false);
+        };
+        self.tier(to).put(key, data).await?;
+        let from = mem::replace(&mut record.tier, to);
+        self.index.set_tier(record).await?;
+        self.tier(from).delete(key).await?;
+        Ok(true)
+    }
+
+    /// Run a migration pass, every block is moved to the tier it's pinned to,
+    /// or decided by the policy.
+    pub async fn migrate(&self) -> StorageResult<TierReport> {
+        let accessed = mem::take(&mut *self.accessed.lock());
+        let now = SystemTime::now();
+        let mut pins: HashMap<INum, Option<Tier>> = HashMap::new();
+        let mut report = TierReport::default();
+
+        for mut record in self.index.list_tiers().await? {
+            let accesses = accessed
+                .get(&(record.ino, record.block_id))
+                .copied()
+                .unwrap_or(0);
+            let pinned = match pins.get(&record.ino) {
+                Some(&pinned) => pinned,
+                None => {
+                    let pinned = self.index.pinned_tier(record.ino).await?;
+                    pins.insert(record.ino, pinned);
+                    pinned
+                }
+            };
+            let target = pinned.unwrap_or_else(|| self.policy.decide(&record, accesses, now));
+
+            if target != record.tier {
+                if self.move_block(record.ino, record.block_id, target).await? {
+                    match target {
+                        Tier::Hot => report.promoted = report.promoted.overflow_add(1),
+                        Tier::Cold => report.demoted = report.demoted.overflow_add(1),
+                    }
+                }
+            } else if accesses > 0 {
+                let _guard = self.moving.read().await;
+                // Only the access time is updated, if the block still exists.
+                if self
+                    .index
+                    .get_tier(record.ino, record.block_id)
+                    .await?
+                    .is_some()
+                {
+                    record.accessed = now;
+                    self.index.set_tier(record).await?;
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Run migration passes every `interval`, until `token` is cancelled.
+    #[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+    pub async fn run_migrator(self: Arc<Self>, interval: Duration, token: CancellationToken) {
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick completes immediately, skip it to migrate after an
+        // interval.
+        ticker.tick().await;
+
+        loop {
+            select! {
+                _ = ticker.tick() => {
+                    match self.migrate().await {
+                        Ok(report) => info!(
+                            "Tier migration pass finished, {} blocks promoted, {} demoted.",
+                            report.promoted, report.demoted
+                        ),
+                        Err(e) => error!("Tier migration pass failed: {e}"),
+                    }
+                }
+                () = token.cancelled() => {
+                    info!("Tier migrator exits.");
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<I, H, C> BlockStore for TieredBlockStore<I, H, C>
+where
+    I: TierIndex + Send + Sync,
+    H: BlockStore + Send + Sync,
+    C: BlockStore + Send + Sync,
+{
+    async fn get(&self, key: BlockKey) -> StorageResult<Option<Vec<u8>>> {
+        let Some(record) = self.index.get_tier(key.ino, key.block_id).await? else {
+            return Ok(None);
+        };
+        {
+            let mut accessed = self.accessed.lock();
+            let count = accessed.entry((key.ino, key.block_id)).or_insert(0);
+            *count = count.saturating_add(1);
+        }
+
+        if let Some(data) = self.tier(record.tier).get(key).await? {
+            return Ok(Some(data));
+        }
+        // The block may be moved after the record is read.
+        let other = match record.tier {
+            Tier::Hot => Tier::Cold,
+            Tier::Cold => Tier::Hot,
+        };
+        self.tier(other).get(key).await
+    }
+
+    async fn put(&self, key: BlockKey, data: Vec<u8>) -> StorageResult<()> {
+        let _guard = self.moving.read().await;
+        if let Some(record) = self.index.get_tier(key.ino, key.block_id).await? {
+            return self.tier(record.tier).put(key, data).await;
+        }
+        // The record is set first, so a failed write never leaves a block
+        // without a record.
+        self.index
+            .set_tier(BlockTier {
+                ino: key.ino,
+                block_id: key.block_id,
+                tier: Tier::Hot,
+                accessed: SystemTime::now(),
+            })
+            .await?;
+        self.hot.put(key, data).await
+    }
+
+    async fn delete(&self, key: BlockKey) -> StorageResult<()> {
+        let _guard = self.moving.read().await;
+        self.hot.delete(key).await?;
+        self.cold.delete(key).await?;
+        self.index
+            .remove_tiers(key.ino, key.block_id..key.block_id.overflow_add(1))
+            .await
+    }
+
+    async fn delete_file(&self, ino: INum) -> StorageResult<()> {
+        let _guard = self.moving.read().await;
+        self.hot.delete_file(ino).await?;
+        self.cold.delete_file(ino).await?;
+        self.index.remove_tiers(ino, 0..usize::MAX).await
+    }
+}
@@ -0,0 +1,183 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use super::{
+    BlockTier, MemoryTierIndex, Tier, TierIndex, TierPolicy, TierReport, TieredBlockStore,
+};
+use crate::storage::block_store::MemoryBlockStore;
+use crate::storage::{BlockKey, BlockStore};
+
+const BLOCK_CONTENT: &[u8] = b"foo bar ";
+
+type TestStore =
+    TieredBlockStore<Arc<MemoryTierIndex>, Arc<MemoryBlockStore>, Arc<MemoryBlockStore>>;
+
+/// Create a `TieredBlockStore` in memory, the tiers and the index are
+/// returned to be inspected.
+fn new_store(
+    policy: TierPolicy,
+) -> (
+    TestStore,
+    Arc<MemoryTierIndex>,
+    Arc<MemoryBlockStore>,
+    Arc<MemoryBlockStore>,
+) {
+    let index = Arc::new(MemoryTierIndex::new());
+    let hot = Arc::new(MemoryBlockStore::new());
+    let cold = Arc::new(MemoryBlockStore::new());
+    let store = TieredBlockStore::new(
+        Arc::clone(&index),
+        Arc::clone(&hot),
+        Arc::clone(&cold),
+        policy,
+    );
+    (store, index, hot, cold)
+}
+
+/// A policy demoting blocks not accessed in the last pass, and promoting
+/// blocks accessed twice.
+const EAGER_POLICY: TierPolicy = TierPolicy {
+    cold_after: Duration::ZERO,
+    promote_accesses: 2,
+};
+
+#[test]
+fn test_policy_decide() {
+    let policy = TierPolicy {
+        cold_after: Duration::from_secs(60),
+        promote_accesses: 2,
+    };
+    let now = SystemTime::now();
+    let mut record = BlockTier {
+        ino: 0,
+        block_id: 0,
+        tier: Tier::Hot,
+        accessed: now - Duration::from_secs(30),
+    };
+    assert_eq!(policy.decide(&record, 0, now), Tier::Hot);
+    record.accessed = now - Duration::from_secs(60);
+    assert_eq!(policy.decide(&record, 0, now), Tier::Cold);
+    assert_eq!(policy.decide(&record, 1, now), Tier::Hot);
+
+    record.tier = Tier::Cold;
+    assert_eq!(policy.decide(&record, 0, now), Tier::Cold);
+    assert_eq!(policy.decide(&record, 1, now), Tier::Cold);
+    assert_eq!(policy.decide(&record, 2, now), Tier::Hot);
+}
+
+#[tokio::test]
+async fn test_demote_and_promote() {
+    let (store, index, hot, cold) = new_store(EAGER_POLICY);
+    let key = BlockKey::new(0, 0);
+
+    store.put(key, BLOCK_CONTENT.to_vec()).await.unwrap();
+    assert_eq!(hot.len(), 1);
+    assert_eq!(cold.len(), 0);
+    let record = index.get_tier(0, 0).await.unwrap().unwrap();
+    assert_eq!(record.tier, Tier::Hot);
+
+    // Not accessed, it's demoted.
+    let report = store.migrate().await.unwrap();
+    assert_eq!(
+        report,
+        TierReport {
+            promoted: 0,
+            demoted: 1
+        }
+    );
+    assert_eq!(hot.len(), 0);
+    assert_eq!(cold.len(), 1);
+    assert_eq!(store.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+
+    // Accessed twice, it's promoted.
+    assert_eq!(store.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+    let report = store.migrate().await.unwrap();
+    assert_eq!(
+        report,
+        TierReport {
+            promoted: 1,
+            demoted: 0
+        }
+    );
+    assert_eq!(hot.len(), 1);
+    assert_eq!(cold.len(), 0);
+    assert_eq!(store.get(key).await.unwrap().unwrap(), BLOCK_CONTENT);
+
+    // Writes go to the tier holding the block.
+    store.migrate().await.unwrap();
+    store.migrate().await.unwrap();
+    assert_eq!(cold.len(), 1);
+    store.put(key, b"bar".to_vec()).await.unwrap();
+    assert_eq!(hot.len(), 0);
+    assert_eq!(store.get(key).await.unwrap().unwrap(), b"bar");
+}
+
+#[tokio::test]
+async fn test_pinned_tier() {
+    let (store, index, hot, cold) = new_store(EAGER_POLICY);
+    store
+        .put(BlockKey::new(0, 0), BLOCK_CONTENT.to_vec())
+        .await
+        .unwrap();
+    store
+        .put(BlockKey::new(1, 0), BLOCK_CONTENT.to_vec())
+        .await
+        .unwrap();
+
+    // The pinned file stays hot even if it's not accessed.
+    index.pin(0, Some(Tier::Hot));
+    store.migrate().await.unwrap();
+    assert_eq!(index.get_tier(0, 0).await.unwrap().unwrap().tier, Tier::Hot);
+    assert_eq!(
+        index.get_tier(1, 0).await.unwrap().unwrap().tier,
+        Tier::Cold
+    );
+
+    // The pinned file stays cold even if it's accessed frequently.
+    index.pin(1, Some(Tier::Cold));
+    for _ in 0..4_i32 {
+        store.get(BlockKey::new(1, 0)).await.unwrap().unwrap();
+    }
+    store.migrate().await.unwrap();
+    assert_eq!(
+        index.get_tier(1, 0).await.unwrap().unwrap().tier,
+        Tier::Cold
+    );
+    assert_eq!(hot.len(), 1);
+    assert_eq!(cold.len(), 1);
+
+    // Unpinned, it's up to the policy.
+    index.pin(0, None);
+    store.migrate().await.unwrap();
+    assert_eq!(hot.len(), 0);
+    assert_eq!(cold.len(), 2);
+}
+
+#[tokio::test]
+async fn test_delete() {
+    let (store, index, hot, cold) = new_store(EAGER_POLICY);
+    for block_id in 0..3 {
+        store
+            .put(BlockKey::new(0, block_id), BLOCK_CONTENT.to_vec())
+            .await
+            .unwrap();
+    }
+    index.pin(0, Some(Tier::Cold));
+    store.migrate().await.unwrap();
+    index.pin(0, None);
+    store
+        .put(BlockKey::new(1, 0), BLOCK_CONTENT.to_vec())
+        .await
+        .unwrap();
+
+    store.delete(BlockKey::new(0, 0)).await.unwrap();
+    assert!(store.get(BlockKey::new(0, 0)).await.unwrap().is_none());
+    assert!(index.get_tier(0, 0).await.unwrap().is_none());
+    assert_eq!(cold.len(), 2);
+
+    store.delete_file(0).await.unwrap();
+    assert!(store.get(BlockKey::new(0, 1)).await.unwrap().is_none());
+    assert_eq!(index.list_tiers().await.unwrap().len(), 1);
+    assert_eq!(cold.len(), 0);
+    assert_eq!(hot.len(), 1);
+}
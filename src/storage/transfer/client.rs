@@ -0,0 +1,158 @@
+//! The client of the transfer service.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use clippy_utilities::Cast;
+use futures::stream;
+use tonic::transport::Channel;
+use tonic::{Request, Streaming};
+
+use super::proto::block_transfer_client::BlockTransferClient;
+use super::proto::{
+    DeleteBlockRequest, DeleteFileRequest, ReadBlockRequest, ReadBlockResponse,
+    ReadCachedBlockRequest, ReplicateBlockRequest, WriteBlockRequest,
+};
+use super::{deadline, deadline_exceeded, split_chunks, status_to_error};
+use crate::async_fuse::fuse::protocol::INum;
+use crate::storage::block_store::{BlockKey, BlockStore};
+use crate::storage::error::{StorageError, StorageResult};
+use crate::telemetry;
+use crate::tls::{Tls, TlsChannel};
+
+/// The blocks of another node, accessed by its transfer service.
+#[derive(Debug, Clone)]
+pub struct RemoteBlockStore {
+    /// The channel to the transfer service
+    channel: Arc<TlsChannel>,
+    /// The timeout of a transfer issued without a deadline
+    timeout: Duration,
+}
+
+impl RemoteBlockStore {
+    /// Create a `RemoteBlockStore` of the node serving at `endpoint`, such as
+    /// `http://10.0.0.2:8900`, over `tls` if it's set. The node is connected
+    /// on the first transfer.
+    pub fn connect(endpoint: &str, tls: Option<Tls>, timeout: Duration) -> StorageResult<Self> {
+        let channel = TlsChannel::new(endpoint, tls).map_err(|e| {
+            StorageError::Internal(anyhow::anyhow!("invalid endpoint {endpoint}: {e}"))
+        })?;
+        Ok(Self {
+            channel: Arc::new(channel),
+            timeout,
+        })
+    }
+
+    /// The client of the transfer service.
+    fn client(&self) -> BlockTransferClient<Channel> {
+        BlockTransferClient::new(self.channel.get())
+    }
+
+    /// Create a request of `message`, with the time left before the deadline
+    /// and the trace of the current span.
+    fn request<T>(&self, message: T) -> StorageResult<Request<T>> {
+        let timeout = deadline::remaining(self.timeout).ok_or_else(deadline_exceeded)?;
+        let mut request = Request::new(message);
+        request.set_timeout(timeout);
+        telemetry::inject(&mut request);
+        Ok(request)
+    }
+
+    /// Ask the node to copy a block to the nodes serving at `targets`.
+    /// Returns `false` if the block doesn't exist on the node.
+    pub async fn replicate(&self, key: BlockKey, targets: Vec<String>) -> StorageResult<bool> {
+        let request = self.request(ReplicateBlockRequest {
+            key: Some(key.into()),
+            targets,
+        })?;
+        let response = self
+            .client()
+            .replicate_block(request)
+            .await
+            .map_err(|status| status_to_error(&status))?;
+        Ok(response.into_inner().found)
+    }
+
+    /// Read a block of a file from the caches of the node. Returns `None` if
+    /// it's not cached there.
+    pub async fn get_cached(&self, ino: INum, block_id: usize) -> StorageResult<Option<Vec<u8>>> {
+        let request = self.request(ReadCachedBlockRequest {
+            ino,
+            block_id: block_id.cast(),
+        })?;
+        let chunks = self
+            .client()
+            .read_cached_block(request)
+            .await
+            .map_err(|status| status_to_error(&status))?
+            .into_inner();
+        read_chunks(chunks).await
+    }
+}
+
+/// Collect the chunks of a block streamed, `None` if the stream is empty.
+async fn read_chunks(mut chunks: Streaming<ReadBlockResponse>) -> StorageResult<Option<Vec<u8>>> {
+    let mut data: Option<Vec<u8>> = None;
+    while let Some(chunk) = chunks
+        .message()
+        .await
+        .map_err(|status| status_to_error(&status))?
+    {
+        data.get_or_insert_with(Vec::new).extend(chunk.data);
+    }
+    Ok(data)
+}
+
+#[async_trait]
+impl BlockStore for RemoteBlockStore {
+    async fn get(&self, key: BlockKey) -> StorageResult<Option<Vec<u8>>> {
+        let request = self.request(ReadBlockRequest {
+            key: Some(key.into()),
+        })?;
+        let chunks = self
+            .client()
+            .read_block(request)
+            .await
+            .map_err(|status| status_to_error(&status))?
+            .into_inner();
+        read_chunks(chunks).await
+    }
+
+    async fn put(&self, key: BlockKey, data: Vec<u8>) -> StorageResult<()> {
+        let mut key = Some(key.into());
+        let chunks: Vec<WriteBlockRequest> = split_chunks(&data)
+            .into_iter()
+            .map(|data| WriteBlockRequest {
+                key: key.take(),
+                data,
+            })
+            .collect();
+        let request = self.request(stream::iter(chunks))?;
+        self.client()
+            .write_block(request)
+            .await
+            .map_err(|status| status_to_error(&status))?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: BlockKey) -> StorageResult<()> {
+        let request = self.request(DeleteBlockRequest {
+            key: Some(key.into()),
+        })?;
+        self.client()
+            .delete_block(request)
+            .await
+            .map_err(|status| status_to_error(&status))?;
+        Ok(())
+    }
+
+    async fn delete_file(&self, ino: INum) -> StorageResult<()> {
+        let request = self.request(DeleteFileRequest { ino })?;
+        self.client()
+            .delete_file(request)
+            .await
+            .map_err(|status| status_to_error(&status))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+//! The deadline of the transfers issued by a request.
+//!
+//! The deadline is scoped to the task handling a request, such as a FUSE
+//! request or a transfer from another node, every transfer issued in the
+//! scope shares the remaining time, and carries it to the next node in the
+//! `grpc-timeout` header.
+
+use std::future::Future;
+use std::time::Duration;
+
+use clippy_utilities::OverflowArithmetic;
+use tokio::time::Instant;
+
+tokio::task_local! {
+    /// The deadline of the current task
+    static DEADLINE: Instant;
+}
+
+/// Run `fut` with `deadline`, the transfers issued by it must finish before
+/// the deadline.
+pub async fn scope<F: Future>(deadline: Instant, fut: F) -> F::Output {
+    DEADLINE.scope(deadline, fut).await
+}
+
+/// The deadline of the current task, `None` if it's not set.
+#[must_use]
+pub fn current() -> Option<Instant> {
+    DEADLINE.try_with(|&deadline| deadline).ok()
+}
+
+/// The time left for a transfer, which is `timeout` capped by the deadline of
+/// the current task. Returns `None` if the deadline is exceeded.
+#[must_use]
+pub fn remaining(timeout: Duration) -> Option<Duration> {
+    match current() {
+        Some(deadline) => deadline
+            .checked_duration_since(Instant::now())
+            .filter(|left| !left.is_zero())
+            .map(|left| left.min(timeout)),
+        None => Some(timeout),
+    }
+}
+
+/// Parse the value of a `grpc-timeout` header, which is at most 8 digits
+/// followed by a unit.
+#[must_use]
+pub fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    let unit = value.chars().last()?;
+    let digits = value.strip_suffix(unit)?;
+    if digits.is_empty() || digits.len() > 8 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let value: u64 = digits.parse().ok()?;
+
+    match unit {
+        'H' => Some(Duration::from_secs(value.overflow_mul(3600))),
+        'M' => Some(Duration::from_secs(value.overflow_mul(60))),
+        'S' => Some(Duration::from_secs(value)),
+        'm' => Some(Duration::from_millis(value)),
+        'u' => Some(Duration::from_micros(value)),
+        'n' => Some(Duration::from_nanos(value)),
+        _ => None,
+    }
+}
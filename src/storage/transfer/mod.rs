@@ -0,0 +1,113 @@
+//! The transfer of blocks between storage nodes.
+//!
+//! A node serves the blocks of a `BlockStore` to the other nodes by a gRPC
+//! service, where a block is streamed in chunks of `TRANSFER_CHUNK_SIZE`, so a
+//! large block is not sent in one message. A `RemoteBlockStore` accesses the
+//! blocks of another node as a `BlockStore`.
+//!
+//! The transfers share the deadline of the request issuing them (see
+//! [`deadline`]), and are optionally over TLS (see [`crate::tls`]), with the
+//! clients authenticated by their certificates as well in mutual TLS.
+
+mod client;
+pub mod deadline;
+mod server;
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests;
+
+use clippy_utilities::Cast;
+use nix::errno::Errno;
+use tonic::{Code, Status};
+
+pub use client::RemoteBlockStore;
+#[cfg(test)]
+pub(crate) use server::serve;
+pub use server::{run_transfer_server, TransferService};
+
+use super::block_store::BlockKey;
+use super::error::{StorageError, StorageResult};
+use super::KB_SIZE;
+
+/// The generated code of the transfer service
+#[allow(
+    missing_docs,
+    unreachable_pub,
+    unused_qualifications,
+    trivial_casts,
+    clippy::all,
+    clippy::restriction,
+    clippy::pedantic,
+    clippy::nursery,
+    clippy::cargo
+)]
+mod proto {
+    tonic::include_proto!("datenlord.transfer.v1");
+}
+
+/// The size of the chunks a block is streamed in.
+pub const TRANSFER_CHUNK_SIZE: usize = 64 * KB_SIZE;
+
+impl From<BlockKey> for proto::BlockKey {
+    fn from(key: BlockKey) -> Self {
+        Self {
+            ino: key.ino,
+            block_id: key.block_id.cast(),
+            version: key.version,
+        }
+    }
+}
+
+/// Convert the key in a request, which is required.
+fn key_from_proto(key: Option<proto::BlockKey>) -> Result<BlockKey, Status> {
+    let key = key.ok_or_else(|| Status::invalid_argument("the key of the block is missing"))?;
+    Ok(BlockKey::with_version(
+        key.ino,
+        key.block_id.cast(),
+        key.version,
+    ))
+}
+
+/// Split the content of a block into chunks, an empty block is one empty
+/// chunk.
+fn split_chunks(data: &[u8]) -> Vec<Vec<u8>> {
+    if data.is_empty() {
+        return vec![vec![]];
+    }
+    data.chunks(TRANSFER_CHUNK_SIZE)
+        .map(<[u8]>::to_vec)
+        .collect()
+}
+
+/// Convert a failed transfer into a `StorageError`.
+fn status_to_error(status: &Status) -> StorageError {
+    let source = if status.code() == Code::DeadlineExceeded {
+        Errno::ETIMEDOUT
+    } else {
+        Errno::EIO
+    };
+    StorageError::Transfer {
+        message: format!("{:?}: {}", status.code(), status.message()),
+        source,
+    }
+}
+
+/// Convert a `StorageError` into the status of a failed transfer.
+fn error_to_status(error: &StorageError) -> Status {
+    match *error {
+        StorageError::Transfer {
+            source: Errno::ETIMEDOUT,
+            ..
+        } => Status::deadline_exceeded(error.to_string()),
+        _ => Status::internal(error.to_string()),
+    }
+}
+
+/// The error of a transfer exceeding the deadline before it's issued.
+fn deadline_exceeded() -> StorageError {
+    StorageError::Transfer {
+        message: "the deadline is exceeded".to_owned(),
+        source: Errno::ETIMEDOUT,
+    }
+}
@@ -0,0 +1,235 @@
+//! The transfer service serving the blocks of a node.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clippy_utilities::Cast;
+use futures::stream::{self, BoxStream};
+use futures::{future, StreamExt};
+use tokio::net::TcpListener;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{debug, error, info};
+
+use super::client::RemoteBlockStore;
+use super::proto::block_transfer_server::{BlockTransfer, BlockTransferServer};
+use super::proto::{
+    DeleteBlockRequest, DeleteBlockResponse, DeleteFileRequest, DeleteFileResponse,
+    ReadBlockRequest, ReadBlockResponse, ReadCachedBlockRequest, ReplicateBlockRequest,
+    ReplicateBlockResponse, WriteBlockRequest, WriteBlockResponse,
+};
+use super::{deadline, error_to_status, key_from_proto, split_chunks};
+use crate::storage::block_store::{BlockKey, BlockStore};
+use crate::storage::locality::LocalCache;
+use crate::tls::{self, Tls};
+
+/// The deadline of a request, carried by its `grpc-timeout` header.
+fn deadline_of<T>(request: &Request<T>) -> Option<Instant> {
+    let timeout = request.metadata().get("grpc-timeout")?.to_str().ok()?;
+    deadline::parse_grpc_timeout(timeout).map(|timeout| Instant::now() + timeout)
+}
+
+/// The transfer service serving the blocks of a `BlockStore`.
+#[derive(Debug)]
+pub struct TransferService<S> {
+    /// The blocks of the node
+    store: S,
+    /// The TLS to connect the other nodes, `None` if the transfers are in
+    /// plaintext
+    tls: Option<Tls>,
+    /// The timeout of a transfer to the other nodes without a deadline
+    timeout: Duration,
+    /// The caches of the node served to the other nodes, `None` if they're
+    /// not served
+    cache: Option<Arc<LocalCache>>,
+}
+
+impl<S> TransferService<S>
+where
+    S: BlockStore + Send + Sync + 'static,
+{
+    /// Create a `TransferService` serving the blocks of `store`.
+    pub fn new(store: S, tls: Option<Tls>, timeout: Duration) -> Self {
+        Self {
+            store,
+            tls,
+            timeout,
+            cache: None,
+        }
+    }
+
+    /// Serve the blocks in the caches of the node registered in `cache` as
+    /// well, which are read by `ReadCachedBlock`.
+    #[must_use]
+    pub fn with_cache(mut self, cache: Arc<LocalCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Copy a block to the nodes serving at `targets` concurrently. Returns
+    /// `false` if the block doesn't exist.
+    async fn replicate(&self, key: BlockKey, targets: &[String]) -> Result<bool, Status> {
+        let Some(data) = self.store.get(key).await.map_err(|e| error_to_status(&e))? else {
+            return Ok(false);
+        };
+        let puts = targets.iter().map(|target| {
+            let data = data.clone();
+            async move {
+                let store = RemoteBlockStore::connect(target, self.tls.clone(), self.timeout)?;
+                store.put(key, data).await
+            }
+        });
+        future::try_join_all(puts)
+            .await
+            .map_err(|e| error_to_status(&e))?;
+        Ok(true)
+    }
+}
+
+#[tonic::async_trait]
+impl<S> BlockTransfer for TransferService<S>
+where
+    S: BlockStore + Send + Sync + 'static,
+{
+    type ReadBlockStream = BoxStream<'static, Result<ReadBlockResponse, Status>>;
+    type ReadCachedBlockStream = BoxStream<'static, Result<ReadBlockResponse, Status>>;
+
+    async fn read_block(
+        &self,
+        request: Request<ReadBlockRequest>,
+    ) -> Result<Response<Self::ReadBlockStream>, Status> {
+        let key = key_from_proto(request.into_inner().key)?;
+        let chunks = match self.store.get(key).await {
+            Ok(Some(data)) => split_chunks(&data),
+            Ok(None) => vec![],
+            Err(e) => return Err(error_to_status(&e)),
+        };
+        let chunks = chunks
+            .into_iter()
+            .map(|data| Ok(ReadBlockResponse { data }));
+        Ok(Response::new(stream::iter(chunks).boxed()))
+    }
+
+    async fn write_block(
+        &self,
+        request: Request<Streaming<WriteBlockRequest>>,
+    ) -> Result<Response<WriteBlockResponse>, Status> {
+        let mut chunks = request.into_inner();
+        let Some(first) = chunks.message().await? else {
+            return Err(Status::invalid_argument("the block is not sent"));
+        };
+        let key = key_from_proto(first.key)?;
+        let mut data = first.data;
+        while let Some(chunk) = chunks.message().await? {
+            data.extend(chunk.data);
+        }
+
+        self.store
+            .put(key, data)
+            .await
+            .map_err(|e| error_to_status(&e))?;
+        Ok(Response::new(WriteBlockResponse {}))
+    }
+
+    async fn delete_block(
+        &self,
+        request: Request<DeleteBlockRequest>,
+    ) -> Result<Response<DeleteBlockResponse>, Status> {
+        let key = key_from_proto(request.into_inner().key)?;
+        self.store
+            .delete(key)
+            .await
+            .map_err(|e| error_to_status(&e))?;
+        Ok(Response::new(DeleteBlockResponse {}))
+    }
+
+    async fn delete_file(
+        &self,
+        request: Request<DeleteFileRequest>,
+    ) -> Result<Response<DeleteFileResponse>, Status> {
+        let ino = request.into_inner().ino;
+        self.store
+            .delete_file(ino)
+            .await
+            .map_err(|e| error_to_status(&e))?;
+        Ok(Response::new(DeleteFileResponse {}))
+    }
+
+    async fn replicate_block(
+        &self,
+        request: Request<ReplicateBlockRequest>,
+    ) -> Result<Response<ReplicateBlockResponse>, Status> {
+        // The transfers to the targets share the deadline of the request.
+        let deadline = deadline_of(&request).unwrap_or_else(|| Instant::now() + self.timeout);
+        let ReplicateBlockRequest { key, targets } = request.into_inner();
+        let key = key_from_proto(key)?;
+
+        let found = deadline::scope(deadline, self.replicate(key, &targets)).await?;
+        if found {
+            debug!("Block {key:?} is replicated to {targets:?}.");
+        }
+        Ok(Response::new(ReplicateBlockResponse { found }))
+    }
+
+    async fn read_cached_block(
+        &self,
+        request: Request<ReadCachedBlockRequest>,
+    ) -> Result<Response<Self::ReadCachedBlockStream>, Status> {
+        let ReadCachedBlockRequest { ino, block_id } = request.into_inner();
+        let block = match self.cache {
+            Some(ref cache) => cache
+                .load(ino, block_id.cast())
+                .await
+                .map_err(|e| error_to_status(&e))?,
+            None => None,
+        };
+        let chunks = block.map_or_else(Vec::new, |block| split_chunks(block.as_slice()));
+        let chunks = chunks
+            .into_iter()
+            .map(|data| Ok(ReadBlockResponse { data }));
+        Ok(Response::new(stream::iter(chunks).boxed()))
+    }
+}
+
+/// Serve `service` on `listener` until `token` is cancelled, over `tls` if
+/// it's set.
+pub(crate) async fn serve<S>(
+    listener: TcpListener,
+    service: TransferService<S>,
+    tls: Option<Tls>,
+    token: CancellationToken,
+) -> Result<(), tonic::transport::Error>
+where
+    S: BlockStore + Send + Sync + 'static,
+{
+    let service = Arc::new(service);
+    tls::serve(listener, tls, token, |server| {
+        server.add_service(BlockTransferServer::from_arc(Arc::clone(&service)))
+    })
+    .await
+}
+
+/// Serve `service` on `addr` until `token` is cancelled.
+pub async fn run_transfer_server<S>(
+    addr: SocketAddr,
+    service: TransferService<S>,
+    tls: Option<Tls>,
+    token: CancellationToken,
+) where
+    S: BlockStore + Send + Sync + 'static,
+{
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind the transfer service to {addr}: {e}");
+            return;
+        }
+    };
+    info!("Transfer service listens on {addr}.");
+    if let Err(e) = serve(listener, service, tls, token).await {
+        error!("Transfer service failed: {e}");
+    }
+    info!("Transfer service exits.");
+}
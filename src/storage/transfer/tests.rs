@@ -0,0 +1,158 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use nix::errno::Errno;
+use tokio::net::TcpListener;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use super::server::serve;
+use super::{deadline, split_chunks, RemoteBlockStore, TransferService, TRANSFER_CHUNK_SIZE};
+use crate::storage::block_store::{BlockKey, BlockStore, MemoryBlockStore};
+use crate::storage::error::StorageError;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serve `store` on a random local port, returns the endpoint of it.
+async fn start_server(store: Arc<MemoryBlockStore>, token: CancellationToken) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let service = TransferService::new(store, None, TIMEOUT);
+    tokio::spawn(async move { serve(listener, service, None, token).await.unwrap() });
+    format!("http://{addr}")
+}
+
+#[test]
+fn test_split_chunks() {
+    assert_eq!(split_chunks(&[]), vec![Vec::<u8>::new()]);
+    assert_eq!(split_chunks(b"foo"), vec![b"foo".to_vec()]);
+
+    let data = vec![1_u8; TRANSFER_CHUNK_SIZE * 2 + 1];
+    let chunks = split_chunks(&data);
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(chunks.last().unwrap().len(), 1);
+    assert_eq!(chunks.concat(), data);
+}
+
+#[test]
+fn test_parse_grpc_timeout() {
+    let parse = deadline::parse_grpc_timeout;
+    assert_eq!(parse("2H"), Some(Duration::from_secs(7200)));
+    assert_eq!(parse("3M"), Some(Duration::from_secs(180)));
+    assert_eq!(parse("10S"), Some(Duration::from_secs(10)));
+    assert_eq!(parse("250m"), Some(Duration::from_millis(250)));
+    assert_eq!(parse("7u"), Some(Duration::from_micros(7)));
+    assert_eq!(parse("99999999n"), Some(Duration::from_nanos(99_999_999)));
+
+    assert_eq!(parse(""), None);
+    assert_eq!(parse("S"), None);
+    assert_eq!(parse("10"), None);
+    assert_eq!(parse("10s"), None);
+    assert_eq!(parse("-1S"), None);
+    assert_eq!(parse("123456789S"), None);
+}
+
+#[tokio::test]
+async fn test_remote_block_store() {
+    let token = CancellationToken::new();
+    let store = Arc::new(MemoryBlockStore::new());
+    let endpoint = start_server(Arc::clone(&store), token.clone()).await;
+    let remote = RemoteBlockStore::connect(&endpoint, None, TIMEOUT).unwrap();
+
+    let key = BlockKey::new(0, 0);
+    assert!(remote.get(key).await.unwrap().is_none());
+
+    // A block larger than a chunk is streamed in chunks.
+    let data: Vec<u8> = (0..TRANSFER_CHUNK_SIZE * 3 + 7)
+        .map(|i| u8::try_from(i % 251).unwrap())
+        .collect();
+    remote.put(key, data.clone()).await.unwrap();
+    assert_eq!(store.get(key).await.unwrap().unwrap(), data);
+    assert_eq!(remote.get(key).await.unwrap().unwrap(), data);
+
+    // An empty block exists.
+    let empty = BlockKey::new(0, 1);
+    remote.put(empty, vec![]).await.unwrap();
+    assert_eq!(remote.get(empty).await.unwrap().unwrap(), Vec::<u8>::new());
+
+    remote.delete(key).await.unwrap();
+    assert!(remote.get(key).await.unwrap().is_none());
+
+    remote
+        .put(BlockKey::new(1, 0), b"foo".to_vec())
+        .await
+        .unwrap();
+    remote.delete_file(0).await.unwrap();
+    assert!(remote.get(empty).await.unwrap().is_none());
+    assert_eq!(store.len(), 1);
+
+    token.cancel();
+}
+
+#[tokio::test]
+async fn test_replicate() {
+    let token = CancellationToken::new();
+    let source = Arc::new(MemoryBlockStore::new());
+    let target = Arc::new(MemoryBlockStore::new());
+    let source_endpoint = start_server(Arc::clone(&source), token.clone()).await;
+    let target_endpoint = start_server(Arc::clone(&target), token.clone()).await;
+    let remote = RemoteBlockStore::connect(&source_endpoint, None, TIMEOUT).unwrap();
+
+    let key = BlockKey::new(0, 0);
+    assert!(!remote
+        .replicate(key, vec![target_endpoint.clone()])
+        .await
+        .unwrap());
+
+    source.put(key, b"foo bar".to_vec()).await.unwrap();
+    assert!(remote.replicate(key, vec![target_endpoint]).await.unwrap());
+    assert_eq!(target.get(key).await.unwrap().unwrap(), b"foo bar");
+
+    token.cancel();
+}
+
+#[tokio::test]
+async fn test_deadline_exceeded() {
+    let token = CancellationToken::new();
+    let store = Arc::new(MemoryBlockStore::new());
+    let endpoint = start_server(store, token.clone()).await;
+    let remote = RemoteBlockStore::connect(&endpoint, None, TIMEOUT).unwrap();
+
+    let key = BlockKey::new(0, 0);
+    let err = deadline::scope(Instant::now(), remote.get(key))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        StorageError::Transfer {
+            source: Errno::ETIMEDOUT,
+            ..
+        }
+    ));
+
+    // The transfers within the deadline succeed.
+    let deadline = Instant::now() + TIMEOUT;
+    assert!(deadline::scope(deadline, remote.get(key))
+        .await
+        .unwrap()
+        .is_none());
+
+    token.cancel();
+}
+
+#[tokio::test]
+async fn test_read_cached_block_without_cache() {
+    let token = CancellationToken::new();
+    let store = Arc::new(MemoryBlockStore::new());
+    let endpoint = start_server(Arc::clone(&store), token.clone()).await;
+    let remote = RemoteBlockStore::connect(&endpoint, None, TIMEOUT).unwrap();
+
+    // The blocks of the store are not the caches, and no cache is served.
+    store
+        .put(BlockKey::new(0, 0), b"foo".to_vec())
+        .await
+        .unwrap();
+    assert!(remote.get_cached(0, 0).await.unwrap().is_none());
+
+    token.cancel();
+}
@@ -0,0 +1,153 @@
+//! The distributed tracing of the requests across the nodes.
+//!
+//! Given `--otlp-endpoint`, the spans are exported to an OpenTelemetry
+//! collector by OTLP, see `common::logger`, so a slow FUSE request is followed
+//! through the metadata transactions and the transfers it issues. A gRPC
+//! request carries the context of the span issuing it in the W3C
+//! `traceparent` header, and the span serving it on the other node, such as a
+//! proposal to the raft leader or a replication of a block, continues the
+//! trace. The requests carrying no trace, such as the heartbeats of raft, are
+//! not traced.
+
+use datenlord::common::error::{DatenLordError, DatenLordResult};
+use opentelemetry::propagation::{Extractor, Injector};
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{self, Sampler, Tracer};
+use opentelemetry_sdk::{runtime, Resource};
+use tonic::codegen::http::header::HeaderName;
+use tonic::codegen::http::{HeaderMap, Request as HttpRequest};
+use tonic::metadata::{AsciiMetadataKey, AsciiMetadataValue, MetadataMap};
+use tonic::Request;
+use tracing::{info_span, warn, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Create the tracer exporting the spans of `role` to the collector at
+/// `endpoint`, which samples `ratio` of the traces started by this node.
+pub fn init_tracer(endpoint: &str, ratio: f64, role: &str) -> DatenLordResult<Tracer> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let exporter = opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(endpoint);
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(ratio)));
+    let config = trace::config()
+        .with_sampler(sampler)
+        .with_resource(Resource::new([
+            KeyValue::new("service.name", "datenlord"),
+            KeyValue::new("datenlord.role", role.to_owned()),
+        ]));
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .with_trace_config(config)
+        .install_batch(runtime::Tokio)
+        .map_err(|e| DatenLordError::ArgumentInvalid {
+            context: vec![format!("failed to export the spans to {endpoint}: {e}")],
+        })
+}
+
+/// Export the spans pending and stop the tracer.
+pub async fn shutdown_tracer() {
+    // Blocks until the spans pending are exported by the runtime.
+    if let Err(e) = tokio::task::spawn_blocking(global::shutdown_tracer_provider).await {
+        warn!("Failed to export the spans pending: {e}");
+    }
+}
+
+/// Carry the context of the current span in the metadata of `request`.
+pub fn inject<T>(request: &mut Request<T>) {
+    let context = Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut MetadataInjector(request.metadata_mut()));
+    });
+}
+
+/// The span serving the gRPC request `request`, which continues the trace it
+/// carries, or is disabled if it carries none.
+pub fn request_span(request: &HttpRequest<()>) -> Span {
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    if !parent.span().span_context().is_valid() {
+        return Span::none();
+    }
+    let span = info_span!("grpc", path = request.uri().path());
+    span.set_parent(parent);
+    span
+}
+
+/// Sets the fields of the trace context in the metadata of a gRPC request.
+struct MetadataInjector<'a>(&'a mut MetadataMap);
+
+impl Injector for MetadataInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(key), Ok(value)) = (
+            AsciiMetadataKey::from_bytes(key.as_bytes()),
+            AsciiMetadataValue::try_from(value),
+        ) {
+            self.0.insert(key, value);
+        }
+    }
+}
+
+/// Gets the fields of the trace context from the headers of a gRPC request.
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use opentelemetry::propagation::TextMapPropagator;
+    use opentelemetry::trace::{
+        SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+    };
+    use opentelemetry::Context;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use tonic::Request;
+
+    use super::{HeaderExtractor, MetadataInjector};
+
+    #[test]
+    fn test_propagation() {
+        let span_context = SpanContext::new(
+            TraceId::from_u128(0x0af7_6519_16cd_43dd_8448_eb21_1c80_319c),
+            SpanId::from_u64(0xb7ad_6b71_6920_3331),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let context = Context::new().with_remote_span_context(span_context.clone());
+        let propagator = TraceContextPropagator::new();
+
+        let mut request = Request::new(());
+        propagator.inject_context(&context, &mut MetadataInjector(request.metadata_mut()));
+        assert_eq!(
+            request.metadata().get("traceparent").unwrap(),
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"
+        );
+
+        // The metadata is sent as the headers of the HTTP request
+        let headers = request.metadata().clone().into_headers();
+        let extracted = propagator.extract(&HeaderExtractor(&headers));
+        assert_eq!(
+            extracted.span().span_context().trace_id(),
+            span_context.trace_id()
+        );
+        assert_eq!(
+            extracted.span().span_context().span_id(),
+            span_context.span_id()
+        );
+        assert!(extracted.span().span_context().is_sampled());
+    }
+}
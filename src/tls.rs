@@ -0,0 +1,314 @@
+//! The TLS of the gRPC services among the nodes.
+//!
+//! The raft members, the transfer services and the volume manager are served
+//! and connected over TLS once a certificate is configured, and the clients
+//! present their certificates as well in mutual TLS. The PEM files are checked
+//! every `TLS_RELOAD_INTERVAL`, so a certificate renewed in place is taken
+//! without a restart, including a Kubernetes secret mounted as a volume, whose
+//! files are swapped by the kubelet. The services are then restarted on their
+//! listeners by the new certificate, while the connections accepted before are
+//! drained by the old ones, and the clients reconnect by the new certificate
+//! on their next requests.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use datenlord::config::TlsConfig;
+use futures::stream;
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use tokio::net::TcpListener;
+use tokio::select;
+use tokio::sync::watch;
+use tokio_stream::wrappers::TcpListenerStream;
+use tokio_util::sync::CancellationToken;
+use tonic::transport::server::Router;
+use tonic::transport::{
+    Certificate, Channel, ClientTlsConfig, Endpoint, Identity, Server, ServerTlsConfig,
+};
+use tracing::{info, warn};
+
+use crate::common::error::{Context, DatenLordError, DatenLordResult};
+use crate::telemetry;
+
+/// The interval between checking the PEM files for a renewed certificate.
+const TLS_RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The TLS of the cluster, it's not set if the services are in plaintext.
+static CLUSTER_TLS: OnceCell<Tls> = OnceCell::new();
+
+/// The contents of the PEM files.
+#[derive(Debug, PartialEq, Eq)]
+struct PemFiles {
+    /// The certificate of the node
+    cert: Vec<u8>,
+    /// The private key of the certificate
+    key: Vec<u8>,
+    /// The certificate of the CA
+    ca: Vec<u8>,
+}
+
+impl PemFiles {
+    /// Read the PEM files of `config`.
+    async fn read(config: &TlsConfig) -> DatenLordResult<Self> {
+        Ok(Self {
+            cert: read_pem(&config.cert_file).await?,
+            key: read_pem(&config.key_file).await?,
+            ca: read_pem(&config.ca_file).await?,
+        })
+    }
+}
+
+/// Read a PEM file.
+async fn read_pem(path: &str) -> DatenLordResult<Vec<u8>> {
+    tokio::fs::read(path)
+        .await
+        .with_context(|| format!("failed to read TLS file {path}"))
+}
+
+/// The TLS configs built from the PEM files.
+#[derive(Debug)]
+struct LoadedTls {
+    /// The times the certificate is reloaded
+    generation: u64,
+    /// The TLS config of the services, the clients are verified by the CA in
+    /// mutual TLS
+    server: ServerTlsConfig,
+    /// The TLS config of the clients, the services are verified by the CA,
+    /// and the certificate of the node is presented in mutual TLS
+    client: ClientTlsConfig,
+}
+
+impl LoadedTls {
+    /// Build the TLS configs from `files`, the certificates are parsed and
+    /// verified here, so an invalid one is not taken.
+    fn build(generation: u64, files: &PemFiles, mtls: bool) -> DatenLordResult<Self> {
+        let identity = Identity::from_pem(&files.cert, &files.key);
+        let ca = Certificate::from_pem(&files.ca);
+        let server = ServerTlsConfig::new().identity(identity.clone());
+        let client = ClientTlsConfig::new().ca_certificate(ca.clone());
+        let (server, client) = if mtls {
+            (server.client_ca_root(ca), client.identity(identity))
+        } else {
+            (server, client)
+        };
+
+        let invalid = |e: tonic::transport::Error| DatenLordError::ArgumentInvalid {
+            context: vec![format!("TLS certificate is invalid: {e}")],
+        };
+        Server::builder()
+            .tls_config(server.clone())
+            .map_err(invalid)?;
+        Endpoint::from_static("https://localhost")
+            .tls_config(client.clone())
+            .map_err(invalid)?;
+        Ok(Self {
+            generation,
+            server,
+            client,
+        })
+    }
+}
+
+/// The TLS of the gRPC services, which is reloaded once the PEM files are
+/// modified.
+#[derive(Debug, Clone)]
+pub struct Tls {
+    /// The TLS configs built from the latest valid PEM files
+    loaded: watch::Receiver<Arc<LoadedTls>>,
+}
+
+impl Tls {
+    /// Load the PEM files of `config`, which are checked for modifications
+    /// until the `Tls` and all its clones are dropped.
+    pub async fn load(config: &TlsConfig) -> DatenLordResult<Self> {
+        let files = PemFiles::read(config).await?;
+        let loaded = LoadedTls::build(0, &files, config.mtls)?;
+        let (sender, receiver) = watch::channel(Arc::new(loaded));
+        tokio::spawn(reload(config.clone(), files, sender));
+        Ok(Self { loaded: receiver })
+    }
+
+    /// The TLS configs built from the latest valid PEM files.
+    fn current(&self) -> Arc<LoadedTls> {
+        Arc::clone(&self.loaded.borrow())
+    }
+}
+
+/// Rebuild the TLS configs and send them by `sender` whenever the PEM files of
+/// `config` differ from `files`, until no one receives them.
+#[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+async fn reload(config: TlsConfig, mut files: PemFiles, sender: watch::Sender<Arc<LoadedTls>>) {
+    let mut ticker = tokio::time::interval(TLS_RELOAD_INTERVAL);
+    // The first tick completes immediately.
+    ticker.tick().await;
+
+    loop {
+        select! {
+            _ = ticker.tick() => {}
+            () = sender.closed() => return,
+        }
+
+        let reloaded = match PemFiles::read(&config).await {
+            Ok(reloaded) => reloaded,
+            Err(e) => {
+                warn!("Failed to reload the TLS certificate, the old one is kept: {e}");
+                continue;
+            }
+        };
+        if reloaded == files {
+            continue;
+        }
+        let generation = sender.borrow().generation.wrapping_add(1);
+        // The files may be caught in the middle of an update, it's retried
+        // once they're modified again.
+        match LoadedTls::build(generation, &reloaded, config.mtls) {
+            Ok(loaded) => {
+                sender.send_replace(Arc::new(loaded));
+                info!("TLS certificate {} is reloaded.", config.cert_file);
+            }
+            Err(e) => {
+                warn!("Failed to reload the TLS certificate, the old one is kept: {e}");
+            }
+        }
+        files = reloaded;
+    }
+}
+
+/// Load the TLS of the cluster by `config`, which is then used by the gRPC
+/// services among the nodes.
+pub async fn init_cluster_tls(config: &TlsConfig) -> DatenLordResult<()> {
+    let tls = Tls::load(config).await?;
+    CLUSTER_TLS
+        .set(tls)
+        .map_err(|_tls| DatenLordError::ArgumentInvalid {
+            context: vec!["the TLS of the cluster is loaded twice".to_owned()],
+        })
+}
+
+/// The TLS of the cluster, `None` if the services are in plaintext.
+pub fn cluster_tls() -> Option<Tls> {
+    CLUSTER_TLS.get().cloned()
+}
+
+/// Serve the services added by `add_services` on `listener` until `token` is
+/// cancelled, over `tls` if it's set. A request carrying a trace is served in
+/// a span continuing it, see `telemetry`.
+///
+/// The services are restarted on the listener once the certificate is
+/// reloaded, and the connections accepted before are served by the old ones
+/// until they're closed.
+#[allow(clippy::pattern_type_mismatch)] // Raised by `tokio::select!`
+pub async fn serve<F>(
+    listener: TcpListener,
+    tls: Option<Tls>,
+    token: CancellationToken,
+    add_services: F,
+) -> Result<(), tonic::transport::Error>
+where
+    F: Fn(&mut Server) -> Router,
+{
+    let Some(mut tls) = tls else {
+        return add_services(&mut Server::builder().trace_fn(telemetry::request_span))
+            .serve_with_incoming_shutdown(TcpListenerStream::new(listener), token.cancelled())
+            .await;
+    };
+
+    let listener = Arc::new(listener);
+    loop {
+        let config = tls.loaded.borrow_and_update().server.clone();
+        let builder = Server::builder().trace_fn(telemetry::request_span);
+        let router = add_services(&mut builder.tls_config(config)?);
+        let incoming = stream::unfold(Arc::clone(&listener), |listener| async move {
+            let accepted = listener.accept().await.map(|(stream, _)| stream);
+            Some((accepted, listener))
+        });
+        let generation = token.child_token();
+        let shutdown = generation.clone();
+        let mut server = Box::pin(
+            router
+                .serve_with_incoming_shutdown(incoming, async move { shutdown.cancelled().await }),
+        );
+
+        select! {
+            result = &mut server => return result,
+            changed = tls.loaded.changed() => {
+                if changed.is_err() {
+                    return server.await;
+                }
+            }
+        }
+        generation.cancel();
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                warn!("Failed to drain the connections of the old TLS certificate: {e}");
+            }
+        });
+        info!(
+            "Services on {:?} are restarted by the reloaded TLS certificate.",
+            listener.local_addr().ok()
+        );
+    }
+}
+
+/// A channel to a gRPC service, which is over TLS if it's set, and is
+/// reconnected by the certificate reloaded.
+#[derive(Debug)]
+pub struct TlsChannel {
+    /// The endpoint of the service
+    endpoint: Endpoint,
+    /// The TLS to connect the service, `None` if it's in plaintext
+    tls: Option<Tls>,
+    /// The channel, and the generation of the certificate it's connected by
+    channel: Mutex<(u64, Channel)>,
+}
+
+impl TlsChannel {
+    /// Create a channel to the service serving at `endpoint`, such as
+    /// `http://10.0.0.2:7900`, whose scheme is changed to `https` over TLS.
+    /// The service is connected on the first request.
+    pub fn new(endpoint: &str, tls: Option<Tls>) -> Result<Self, tonic::transport::Error> {
+        let endpoint = match endpoint.strip_prefix("http://") {
+            Some(authority) if tls.is_some() => format!("https://{authority}"),
+            Some(_) | None => endpoint.to_owned(),
+        };
+        let endpoint = Endpoint::from_shared(endpoint)?;
+        let (generation, channel) = match tls {
+            Some(ref tls) => {
+                let loaded = tls.current();
+                let channel = endpoint
+                    .clone()
+                    .tls_config(loaded.client.clone())?
+                    .connect_lazy();
+                (loaded.generation, channel)
+            }
+            None => (0, endpoint.connect_lazy()),
+        };
+        Ok(Self {
+            endpoint,
+            tls,
+            channel: Mutex::new((generation, channel)),
+        })
+    }
+
+    /// Get the channel, which is reconnected if the certificate is reloaded.
+    pub fn get(&self) -> Channel {
+        let mut channel = self.channel.lock();
+        if let Some(ref tls) = self.tls {
+            let loaded = tls.current();
+            if loaded.generation != channel.0 {
+                match self.endpoint.clone().tls_config(loaded.client.clone()) {
+                    Ok(endpoint) => *channel = (loaded.generation, endpoint.connect_lazy()),
+                    Err(e) => {
+                        warn!(
+                            "Failed to reconnect {} by the reloaded TLS certificate: {e}",
+                            self.endpoint.uri()
+                        );
+                        channel.0 = loaded.generation;
+                    }
+                }
+            }
+        }
+        channel.1.clone()
+    }
+}
@@ -0,0 +1,143 @@
+//! The commands of the admin role, run by the volume manager.
+
+use std::io::Write;
+use std::time::{Duration, UNIX_EPOCH};
+
+use datenlord::config::{AdminCommand, VolumeConfig};
+
+use super::VolumeManagerClient;
+use crate::async_fuse::memfs::volume::VolumeParams;
+
+/// The time of `secs` since the UNIX epoch in RFC 3339.
+fn rfc3339(secs: u64) -> String {
+    let time = UNIX_EPOCH + Duration::from_secs(secs);
+    chrono::DateTime::<chrono::Utc>::from(time).to_rfc3339()
+}
+
+/// Run the admin command of `volume_config` by `client`, on the volume and
+/// the snapshot named in the config, and print its result into `out`.
+pub async fn run_admin(
+    client: &VolumeManagerClient,
+    volume_config: &VolumeConfig,
+    snapshot: Option<&str>,
+    out: &mut impl Write,
+) -> anyhow::Result<()> {
+    let name = volume_config.name.as_deref();
+    let command = volume_config.command;
+    let required = |value: Option<&str>, what: &str| {
+        value
+            .map(str::to_owned)
+            .ok_or_else(|| anyhow::anyhow!("the admin command {command} requires the {what}"))
+    };
+    match command {
+        AdminCommand::List => {
+            for volume in client.list().await? {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}",
+                    volume.name, volume.id, volume.capacity, volume.policy
+                )?;
+            }
+        }
+        AdminCommand::Create => {
+            let name = required(name, "--volume-name")?;
+            let (volume, secret) = client
+                .create(
+                    &name,
+                    volume_config.capacity,
+                    volume_config.policy,
+                    VolumeParams::default(),
+                    None,
+                )
+                .await?;
+            writeln!(
+                out,
+                "created volume {name} with id {}, the secret to mount it is {secret}",
+                volume.id
+            )?;
+        }
+        AdminCommand::Delete => {
+            let name = required(name, "--volume-name")?;
+            client.delete(&name).await?;
+            writeln!(out, "deleted volume {name}")?;
+        }
+        AdminCommand::Resize => {
+            let name = required(name, "--volume-name")?;
+            let volume = client.resize(&name, volume_config.capacity).await?;
+            writeln!(out, "resized volume {name} to capacity {}", volume.capacity)?;
+        }
+        AdminCommand::Quota => {
+            let names = match name {
+                Some(name) => vec![name.to_owned()],
+                None => client
+                    .list()
+                    .await?
+                    .into_iter()
+                    .map(|volume| volume.name)
+                    .collect(),
+            };
+            for name in names {
+                let (volume, quota) = client.quota(&name).await?;
+                match quota {
+                    Some(quota) => writeln!(
+                        out,
+                        "{name}\t{}\t{}\t{}\t{}",
+                        volume.capacity, quota.used_bytes, quota.max_inodes, quota.used_inodes
+                    )?,
+                    // An unlimited volume is not tracked
+                    None => writeln!(out, "{name}\t{}\t-\t-\t-", volume.capacity)?,
+                }
+            }
+        }
+        AdminCommand::Snapshot => {
+            let name = required(snapshot, "--snapshot")?;
+            let snapshot = client.create_snapshot(&name).await?;
+            writeln!(out, "created snapshot {name} with id {}", snapshot.id)?;
+        }
+        AdminCommand::Snapshots => {
+            for snapshot in client.list_snapshots().await? {
+                let state = if snapshot.ready { "ready" } else { "not ready" };
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{state}",
+                    snapshot.id,
+                    snapshot.name,
+                    rfc3339(snapshot.created)
+                )?;
+            }
+        }
+        AdminCommand::DeleteSnapshot => {
+            let name = required(snapshot, "--snapshot")?;
+            let (snapshot, collected) = client.delete_snapshot(&name).await?;
+            writeln!(
+                out,
+                "deleted snapshot {name} with id {}, {collected} block versions are collected",
+                snapshot.id
+            )?;
+        }
+        AdminCommand::Nodes => {
+            for node in client.list_nodes().await? {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}",
+                    node.node_id,
+                    node.mount_point,
+                    rfc3339(node.registered)
+                )?;
+            }
+        }
+        AdminCommand::Scrub => {
+            let requested = client.scrub().await?;
+            writeln!(
+                out,
+                "requested a scrub pass at {}, the nodes scrubbing the blocks start it in seconds",
+                rfc3339(requested)
+            )?;
+        }
+        AdminCommand::Gc => {
+            let collected = client.collect_garbage().await?;
+            writeln!(out, "{collected} block versions are collected")?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,295 @@
+//! The client of the volume manager.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use datenlord::config::StoragePolicy;
+use tonic::transport::Channel;
+use tonic::Request;
+
+use super::proto::volume_manager_client::VolumeManagerClient as GrpcClient;
+use super::proto::{
+    AuthorizeRequest, CollectGarbageRequest, CreateRequest, CreateSnapshotRequest, DeleteRequest,
+    DeleteSnapshotRequest, GetQuotaRequest, GetRequest, IssueTokenRequest, ListNodesRequest,
+    ListRequest, ListSnapshotsRequest, Node, Quota, ResizeRequest, ScrubRequest, Snapshot,
+    VerifyTokenRequest, Volume,
+};
+use super::{snapshot_status_to_error, status_to_error};
+use crate::async_fuse::memfs::volume::{Access, VolumeParams};
+use crate::common::error::{DatenLordError, DatenLordResult};
+use crate::tls::{self, TlsChannel};
+
+/// The timeout of a request, deleting a volume removes all its files.
+const RPC_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The timeout of a request walking all the metadata or all the blocks, such
+/// as creating a snapshot or collecting the garbage.
+const LONG_RPC_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// The error of a response without the volume.
+fn volume_missing(name: &str) -> DatenLordError {
+    DatenLordError::InternalErr {
+        source: anyhow::anyhow!("the response of volume {name} carries no volume"),
+        context: vec![],
+    }
+}
+
+/// The error of a response without the snapshot.
+fn snapshot_missing(name: &str) -> DatenLordError {
+    DatenLordError::InternalErr {
+        source: anyhow::anyhow!("the response of snapshot {name} carries no snapshot"),
+        context: vec![],
+    }
+}
+
+/// The client of the volume manager.
+#[derive(Debug, Clone)]
+pub struct VolumeManagerClient {
+    /// The channel to the service
+    channel: Arc<TlsChannel>,
+}
+
+impl VolumeManagerClient {
+    /// Create a client of the volume manager serving at `endpoint`, such as
+    /// `http://10.0.0.1:7950`, over the TLS of the cluster if it's set. The
+    /// manager is connected on the first request.
+    pub fn connect(endpoint: &str) -> DatenLordResult<Self> {
+        let channel = TlsChannel::new(endpoint, tls::cluster_tls()).map_err(|e| {
+            DatenLordError::ArgumentInvalid {
+                context: vec![format!(
+                    "volume manager endpoint {endpoint} is invalid: {e}"
+                )],
+            }
+        })?;
+        Ok(Self {
+            channel: Arc::new(channel),
+        })
+    }
+
+    /// The client of the service.
+    fn client(&self) -> GrpcClient<Channel> {
+        GrpcClient::new(self.channel.get())
+    }
+
+    /// Create a request of `message` with the timeout.
+    fn request<T>(message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        request.set_timeout(RPC_TIMEOUT);
+        request
+    }
+
+    /// Create a request of `message` walking all the metadata or all the
+    /// blocks, with the long timeout.
+    fn long_request<T>(message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        request.set_timeout(LONG_RPC_TIMEOUT);
+        request
+    }
+
+    /// Create a volume named `name` with the tuning parameters `params`,
+    /// which is mounted with `secret`, or a generated one if it's `None`.
+    ///
+    /// Returns the volume and its secret.
+    pub async fn create(
+        &self,
+        name: &str,
+        capacity: u64,
+        policy: StoragePolicy,
+        params: VolumeParams,
+        secret: Option<String>,
+    ) -> DatenLordResult<(Volume, String)> {
+        let response = self
+            .client()
+            .create(Self::request(CreateRequest {
+                name: name.to_owned(),
+                capacity,
+                policy: policy.to_string(),
+                secret: secret.unwrap_or_default(),
+                params: Some(params.into()),
+            }))
+            .await
+            .map_err(|status| status_to_error(&status, name))?
+            .into_inner();
+        let volume = response.volume.ok_or_else(|| volume_missing(name))?;
+        Ok((volume, response.secret))
+    }
+
+    /// Delete volume `name` with its files.
+    pub async fn delete(&self, name: &str) -> DatenLordResult<()> {
+        self.client()
+            .delete(Self::request(DeleteRequest {
+                name: name.to_owned(),
+            }))
+            .await
+            .map_err(|status| status_to_error(&status, name))?;
+        Ok(())
+    }
+
+    /// Change the capacity of volume `name`, `0` for unlimited.
+    pub async fn resize(&self, name: &str, capacity: u64) -> DatenLordResult<Volume> {
+        self.client()
+            .resize(Self::request(ResizeRequest {
+                name: name.to_owned(),
+                capacity,
+            }))
+            .await
+            .map_err(|status| status_to_error(&status, name))?
+            .into_inner()
+            .volume
+            .ok_or_else(|| volume_missing(name))
+    }
+
+    /// Get volume `name`.
+    pub async fn get(&self, name: &str) -> DatenLordResult<Volume> {
+        self.client()
+            .get(Self::request(GetRequest {
+                name: name.to_owned(),
+            }))
+            .await
+            .map_err(|status| status_to_error(&status, name))?
+            .into_inner()
+            .volume
+            .ok_or_else(|| volume_missing(name))
+    }
+
+    /// List all the volumes, in the order of their names.
+    pub async fn list(&self) -> DatenLordResult<Vec<Volume>> {
+        let response = self
+            .client()
+            .list(Self::request(ListRequest {}))
+            .await
+            .map_err(|status| status_to_error(&status, ""))?;
+        Ok(response.into_inner().volumes)
+    }
+
+    /// Returns whether `secret` is the one to mount volume `name`.
+    pub async fn authorize(&self, name: &str, secret: &str) -> DatenLordResult<bool> {
+        let response = self
+            .client()
+            .authorize(Self::request(AuthorizeRequest {
+                name: name.to_owned(),
+                secret: secret.to_owned(),
+            }))
+            .await
+            .map_err(|status| status_to_error(&status, name))?;
+        Ok(response.into_inner().authorized)
+    }
+
+    /// Issue a token granting `access` to volume `name`.
+    pub async fn issue_token(&self, name: &str, access: Access) -> DatenLordResult<String> {
+        let response = self
+            .client()
+            .issue_token(Self::request(IssueTokenRequest {
+                name: name.to_owned(),
+                read_only: access == Access::ReadOnly,
+            }))
+            .await
+            .map_err(|status| status_to_error(&status, name))?;
+        Ok(response.into_inner().token)
+    }
+
+    /// Returns the access to volume `name` granted by `token`, `None` if it's
+    /// not a token of the volume.
+    pub async fn verify_token(&self, name: &str, token: &str) -> DatenLordResult<Option<Access>> {
+        let response = self
+            .client()
+            .verify_token(Self::request(VerifyTokenRequest {
+                name: name.to_owned(),
+                token: token.to_owned(),
+            }))
+            .await
+            .map_err(|status| status_to_error(&status, name))?
+            .into_inner();
+        Ok(response.authorized.then_some(if response.read_only {
+            Access::ReadOnly
+        } else {
+            Access::ReadWrite
+        }))
+    }
+
+    /// Get volume `name` with the quota of its directory, the quota is `None`
+    /// if the volume is unlimited, whose usage is not tracked.
+    pub async fn quota(&self, name: &str) -> DatenLordResult<(Volume, Option<Quota>)> {
+        let response = self
+            .client()
+            .get_quota(Self::request(GetQuotaRequest {
+                name: name.to_owned(),
+            }))
+            .await
+            .map_err(|status| status_to_error(&status, name))?
+            .into_inner();
+        let volume = response.volume.ok_or_else(|| volume_missing(name))?;
+        Ok((volume, response.quota))
+    }
+
+    /// Create a snapshot named `name` of the file system holding the volumes.
+    pub async fn create_snapshot(&self, name: &str) -> DatenLordResult<Snapshot> {
+        self.client()
+            .create_snapshot(Self::long_request(CreateSnapshotRequest {
+                name: name.to_owned(),
+            }))
+            .await
+            .map_err(|status| snapshot_status_to_error(&status, name))?
+            .into_inner()
+            .snapshot
+            .ok_or_else(|| snapshot_missing(name))
+    }
+
+    /// Delete snapshot `name`, and collect the block versions only it sees.
+    ///
+    /// Returns the deleted snapshot and the number of the versions collected.
+    pub async fn delete_snapshot(&self, name: &str) -> DatenLordResult<(Snapshot, u64)> {
+        let response = self
+            .client()
+            .delete_snapshot(Self::long_request(DeleteSnapshotRequest {
+                name: name.to_owned(),
+            }))
+            .await
+            .map_err(|status| snapshot_status_to_error(&status, name))?
+            .into_inner();
+        let snapshot = response.snapshot.ok_or_else(|| snapshot_missing(name))?;
+        Ok((snapshot, response.collected))
+    }
+
+    /// List all the snapshots, in the order of creation.
+    pub async fn list_snapshots(&self) -> DatenLordResult<Vec<Snapshot>> {
+        let response = self
+            .client()
+            .list_snapshots(Self::request(ListSnapshotsRequest {}))
+            .await
+            .map_err(|status| snapshot_status_to_error(&status, ""))?;
+        Ok(response.into_inner().snapshots)
+    }
+
+    /// List the nodes mounting the file system, in the order of their ids.
+    pub async fn list_nodes(&self) -> DatenLordResult<Vec<Node>> {
+        let response = self
+            .client()
+            .list_nodes(Self::request(ListNodesRequest {}))
+            .await
+            .map_err(|status| status_to_error(&status, ""))?;
+        Ok(response.into_inner().nodes)
+    }
+
+    /// Request a scrub pass of every node scrubbing the blocks, returns the
+    /// seconds since the UNIX epoch it's requested at.
+    pub async fn scrub(&self) -> DatenLordResult<u64> {
+        let response = self
+            .client()
+            .scrub(Self::request(ScrubRequest {}))
+            .await
+            .map_err(|status| status_to_error(&status, ""))?;
+        Ok(response.into_inner().requested)
+    }
+
+    /// Collect the block versions seen by neither the file system nor any
+    /// snapshot, returns the number of them.
+    pub async fn collect_garbage(&self) -> DatenLordResult<u64> {
+        let response = self
+            .client()
+            .collect_garbage(Self::long_request(CollectGarbageRequest {}))
+            .await
+            .map_err(|status| status_to_error(&status, ""))?;
+        Ok(response.into_inner().collected)
+    }
+}
@@ -0,0 +1,216 @@
+//! The admin API of the volume manager.
+//!
+//! The volume manager role serves the
+//! [`VolumeManager`](crate::async_fuse::memfs::volume::VolumeManager) of the
+//! named volumes with gRPC, see `proto/volume.proto`. The volume role and the CSI driver
+//! talk to it with the [`VolumeManagerClient`], so the named volumes are
+//! created, deleted and resized in one place.
+//!
+//! The admin role drives the rest of the administration of the cluster with
+//! it as well: the snapshots, the quotas of the volumes, the nodes mounting
+//! them, the scrub passes and the garbage collection of the block versions.
+
+mod admin;
+mod client;
+mod server;
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub use admin::run_admin;
+pub use client::VolumeManagerClient;
+use proto::VolumeParams as VolumeParamsMessage;
+pub use proto::{Node, Quota, Snapshot, Volume};
+pub use server::run_volume_manager;
+use tonic::{Code, Status};
+
+use crate::async_fuse::memfs::quota::Quota as QuotaRecord;
+use crate::async_fuse::memfs::snapshot::SnapshotInfo;
+use crate::async_fuse::memfs::volume::{VolumeParams, VolumeSpec};
+use crate::async_fuse::memfs::NodeRegistration;
+use crate::common::error::{DatenLordError, DatenLordResult};
+
+/// The generated code of the volume manager service
+#[allow(
+    missing_docs,
+    unreachable_pub,
+    unused_qualifications,
+    trivial_casts,
+    clippy::all,
+    clippy::restriction,
+    clippy::pedantic,
+    clippy::nursery,
+    clippy::cargo
+)]
+mod proto {
+    tonic::include_proto!("datenlord.volume.v1");
+}
+
+impl From<&VolumeSpec> for Volume {
+    fn from(spec: &VolumeSpec) -> Self {
+        Self {
+            name: spec.name.clone(),
+            id: spec.id.clone(),
+            capacity: spec.capacity,
+            policy: spec.policy.to_string(),
+            created: unix_secs(spec.created),
+            params: Some(spec.params.into()),
+        }
+    }
+}
+
+impl From<&QuotaRecord> for Quota {
+    fn from(quota: &QuotaRecord) -> Self {
+        Self {
+            max_inodes: quota.max_inodes,
+            used_bytes: quota.used_bytes,
+            used_inodes: quota.used_inodes,
+        }
+    }
+}
+
+impl From<&SnapshotInfo> for Snapshot {
+    fn from(info: &SnapshotInfo) -> Self {
+        Self {
+            id: info.id,
+            name: info.name.clone(),
+            created: unix_secs(info.created),
+            ready: info.ready,
+        }
+    }
+}
+
+impl From<&NodeRegistration> for Node {
+    fn from(registration: &NodeRegistration) -> Self {
+        Self {
+            node_id: registration.node_id.clone(),
+            mount_point: registration.mount_point.clone(),
+            registered: unix_secs(registration.registered),
+        }
+    }
+}
+
+/// The seconds since the UNIX epoch of `time`, `0` if it's before the epoch.
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+impl From<VolumeParams> for VolumeParamsMessage {
+    fn from(params: VolumeParams) -> Self {
+        Self {
+            block_size: params.block_size.unwrap_or_default(),
+            cache_size: params.cache_size.unwrap_or_default(),
+            compression: params
+                .compression
+                .map(|compression| compression.to_string())
+                .unwrap_or_default(),
+            replicas: params.replicas.unwrap_or_default(),
+        }
+    }
+}
+
+impl TryFrom<VolumeParamsMessage> for VolumeParams {
+    type Error = DatenLordError;
+
+    fn try_from(params: VolumeParamsMessage) -> DatenLordResult<Self> {
+        let compression = if params.compression.is_empty() {
+            None
+        } else {
+            Some(params.compression.parse()?)
+        };
+        Ok(Self {
+            block_size: (params.block_size != 0).then_some(params.block_size),
+            cache_size: (params.cache_size != 0).then_some(params.cache_size),
+            compression,
+            replicas: (params.replicas != 0).then_some(params.replicas),
+        })
+    }
+}
+
+/// Convert an error of the manager into the status of the request.
+fn error_to_status(error: &DatenLordError) -> Status {
+    let message = error.to_string();
+    if matches!(
+        *error,
+        DatenLordError::VolumeNotFound { .. } | DatenLordError::SnapshotNotFound { .. }
+    ) {
+        Status::not_found(message)
+    } else if matches!(
+        *error,
+        DatenLordError::VolumeAlreadyExist { .. } | DatenLordError::SnapshotAlreadyExist { .. }
+    ) {
+        Status::already_exists(message)
+    } else if matches!(
+        *error,
+        DatenLordError::ArgumentInvalid { .. } | DatenLordError::ArgumentOutOfRange { .. }
+    ) {
+        Status::invalid_argument(message)
+    } else {
+        Status::internal(message)
+    }
+}
+
+/// Convert a failed request on volume `name` into an error.
+fn status_to_error(status: &Status, name: &str) -> DatenLordError {
+    let context = vec![status.message().to_owned()];
+    match status.code() {
+        Code::NotFound => DatenLordError::VolumeNotFound {
+            volume_id: name.to_owned(),
+            context,
+        },
+        Code::AlreadyExists => DatenLordError::VolumeAlreadyExist {
+            volume_id: name.to_owned(),
+            context,
+        },
+        Code::InvalidArgument | Code::OutOfRange => DatenLordError::ArgumentInvalid { context },
+        Code::Ok
+        | Code::Cancelled
+        | Code::Unknown
+        | Code::DeadlineExceeded
+        | Code::PermissionDenied
+        | Code::ResourceExhausted
+        | Code::FailedPrecondition
+        | Code::Aborted
+        | Code::Unimplemented
+        | Code::Internal
+        | Code::Unavailable
+        | Code::DataLoss
+        | Code::Unauthenticated => DatenLordError::InternalErr {
+            source: anyhow::anyhow!("the volume manager failed with {:?}", status.code()),
+            context,
+        },
+    }
+}
+
+/// Convert a failed request on snapshot `name` into an error.
+fn snapshot_status_to_error(status: &Status, name: &str) -> DatenLordError {
+    let context = vec![status.message().to_owned()];
+    match status.code() {
+        Code::NotFound => DatenLordError::SnapshotNotFound {
+            snapshot_id: name.to_owned(),
+            context,
+        },
+        Code::AlreadyExists => DatenLordError::SnapshotAlreadyExist {
+            snapshot_id: name.to_owned(),
+            context,
+        },
+        Code::Ok
+        | Code::Cancelled
+        | Code::Unknown
+        | Code::InvalidArgument
+        | Code::DeadlineExceeded
+        | Code::PermissionDenied
+        | Code::ResourceExhausted
+        | Code::FailedPrecondition
+        | Code::Aborted
+        | Code::OutOfRange
+        | Code::Unimplemented
+        | Code::Internal
+        | Code::Unavailable
+        | Code::DataLoss
+        | Code::Unauthenticated => status_to_error(status, name),
+    }
+}
@@ -0,0 +1,307 @@
+//! The service of the volume manager.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use clippy_utilities::Cast;
+use datenlord::config::{StorageConfig, StoragePolicy};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tonic::{Request, Response, Status};
+use tracing::{error, info};
+
+use super::proto::volume_manager_server::{VolumeManager as VolumeManagerApi, VolumeManagerServer};
+use super::proto::{
+    AuthorizeRequest, AuthorizeResponse, CollectGarbageRequest, CollectGarbageResponse,
+    CreateRequest, CreateResponse, CreateSnapshotRequest, CreateSnapshotResponse, DeleteRequest,
+    DeleteResponse, DeleteSnapshotRequest, DeleteSnapshotResponse, GetQuotaRequest,
+    GetQuotaResponse, GetRequest, GetResponse, IssueTokenRequest, IssueTokenResponse,
+    ListNodesRequest, ListNodesResponse, ListRequest, ListResponse, ListSnapshotsRequest,
+    ListSnapshotsResponse, ResizeRequest, ResizeResponse, ScrubRequest, ScrubResponse,
+    VerifyTokenRequest, VerifyTokenResponse,
+};
+use super::{error_to_status, unix_secs};
+use crate::async_fuse;
+use crate::async_fuse::memfs::snapshot;
+use crate::async_fuse::memfs::volume::{Access, VolumeManager, VolumeParams, VolumeSpec};
+use crate::async_fuse::memfs::{list_nodes, request_scrub};
+use crate::tls;
+
+/// The service of the volume manager.
+#[derive(Debug)]
+struct VolumeService {
+    /// The manager of the volumes
+    manager: Arc<VolumeManager>,
+    /// The storage of the file system, where the garbage is collected from
+    storage_config: StorageConfig,
+}
+
+impl VolumeService {
+    /// Collect the block versions seen by neither the file system nor any
+    /// snapshot, returns the number of them.
+    async fn collect_versions(&self) -> Result<u64, Status> {
+        let kv_engine = Arc::clone(self.manager.kv_engine());
+        let collected = async_fuse::collect_snapshot_garbage(kv_engine, &self.storage_config)
+            .await
+            .map_err(|e| Status::internal(format!("failed to collect the garbage: {e}")))?;
+        info!("Collected {collected} block versions.");
+        Ok(collected.cast())
+    }
+}
+
+#[tonic::async_trait]
+impl VolumeManagerApi for VolumeService {
+    async fn create(
+        &self,
+        request: Request<CreateRequest>,
+    ) -> Result<Response<CreateResponse>, Status> {
+        let CreateRequest {
+            name,
+            capacity,
+            policy,
+            secret,
+            params,
+        } = request.into_inner();
+        let policy = if policy.is_empty() {
+            StoragePolicy::Tiered
+        } else {
+            policy.parse().map_err(|e| error_to_status(&e))?
+        };
+        let params = match params {
+            Some(params) => VolumeParams::try_from(params).map_err(|e| error_to_status(&e))?,
+            None => VolumeParams::default(),
+        };
+        let secret = (!secret.is_empty()).then_some(secret);
+        let (spec, secret) = self
+            .manager
+            .create(&name, capacity, policy, params, secret)
+            .await
+            .map_err(|e| error_to_status(&e))?;
+        Ok(Response::new(CreateResponse {
+            volume: Some((&spec).into()),
+            secret,
+        }))
+    }
+
+    async fn delete(
+        &self,
+        request: Request<DeleteRequest>,
+    ) -> Result<Response<DeleteResponse>, Status> {
+        let _: VolumeSpec = self
+            .manager
+            .delete(&request.into_inner().name)
+            .await
+            .map_err(|e| error_to_status(&e))?;
+        Ok(Response::new(DeleteResponse {}))
+    }
+
+    async fn resize(
+        &self,
+        request: Request<ResizeRequest>,
+    ) -> Result<Response<ResizeResponse>, Status> {
+        let ResizeRequest { name, capacity } = request.into_inner();
+        let spec = self
+            .manager
+            .resize(&name, capacity)
+            .await
+            .map_err(|e| error_to_status(&e))?;
+        Ok(Response::new(ResizeResponse {
+            volume: Some((&spec).into()),
+        }))
+    }
+
+    async fn get(&self, request: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
+        let spec = self
+            .manager
+            .get(&request.into_inner().name)
+            .await
+            .map_err(|e| error_to_status(&e))?;
+        Ok(Response::new(GetResponse {
+            volume: Some((&spec).into()),
+        }))
+    }
+
+    async fn list(&self, _request: Request<ListRequest>) -> Result<Response<ListResponse>, Status> {
+        let specs = self.manager.list().await.map_err(|e| error_to_status(&e))?;
+        Ok(Response::new(ListResponse {
+            volumes: specs.iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn authorize(
+        &self,
+        request: Request<AuthorizeRequest>,
+    ) -> Result<Response<AuthorizeResponse>, Status> {
+        let AuthorizeRequest { name, secret } = request.into_inner();
+        let spec = self
+            .manager
+            .get(&name)
+            .await
+            .map_err(|e| error_to_status(&e))?;
+        Ok(Response::new(AuthorizeResponse {
+            authorized: spec.authorize(&secret),
+        }))
+    }
+
+    async fn issue_token(
+        &self,
+        request: Request<IssueTokenRequest>,
+    ) -> Result<Response<IssueTokenResponse>, Status> {
+        let IssueTokenRequest { name, read_only } = request.into_inner();
+        let spec = self
+            .manager
+            .get(&name)
+            .await
+            .map_err(|e| error_to_status(&e))?;
+        let access = if read_only {
+            Access::ReadOnly
+        } else {
+            Access::ReadWrite
+        };
+        let token = spec.issue_token(access).map_err(|e| error_to_status(&e))?;
+        Ok(Response::new(IssueTokenResponse { token }))
+    }
+
+    async fn verify_token(
+        &self,
+        request: Request<VerifyTokenRequest>,
+    ) -> Result<Response<VerifyTokenResponse>, Status> {
+        let VerifyTokenRequest { name, token } = request.into_inner();
+        let spec = self
+            .manager
+            .get(&name)
+            .await
+            .map_err(|e| error_to_status(&e))?;
+        let access = spec.verify_token(&token);
+        Ok(Response::new(VerifyTokenResponse {
+            authorized: access.is_some(),
+            read_only: access == Some(Access::ReadOnly),
+        }))
+    }
+
+    async fn get_quota(
+        &self,
+        request: Request<GetQuotaRequest>,
+    ) -> Result<Response<GetQuotaResponse>, Status> {
+        let (spec, quota) = self
+            .manager
+            .quota(&request.into_inner().name)
+            .await
+            .map_err(|e| error_to_status(&e))?;
+        Ok(Response::new(GetQuotaResponse {
+            volume: Some((&spec).into()),
+            quota: quota.as_ref().map(Into::into),
+        }))
+    }
+
+    async fn create_snapshot(
+        &self,
+        request: Request<CreateSnapshotRequest>,
+    ) -> Result<Response<CreateSnapshotResponse>, Status> {
+        let info = snapshot::create_snapshot(self.manager.kv_engine(), &request.into_inner().name)
+            .await
+            .map_err(|e| error_to_status(&e))?;
+        Ok(Response::new(CreateSnapshotResponse {
+            snapshot: Some((&info).into()),
+        }))
+    }
+
+    async fn delete_snapshot(
+        &self,
+        request: Request<DeleteSnapshotRequest>,
+    ) -> Result<Response<DeleteSnapshotResponse>, Status> {
+        let info = snapshot::delete_snapshot(self.manager.kv_engine(), &request.into_inner().name)
+            .await
+            .map_err(|e| error_to_status(&e))?;
+        let collected = self.collect_versions().await?;
+        Ok(Response::new(DeleteSnapshotResponse {
+            snapshot: Some((&info).into()),
+            collected,
+        }))
+    }
+
+    async fn list_snapshots(
+        &self,
+        _request: Request<ListSnapshotsRequest>,
+    ) -> Result<Response<ListSnapshotsResponse>, Status> {
+        let snapshots = snapshot::list_snapshots(self.manager.kv_engine())
+            .await
+            .map_err(|e| error_to_status(&e))?;
+        Ok(Response::new(ListSnapshotsResponse {
+            snapshots: snapshots.iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn list_nodes(
+        &self,
+        _request: Request<ListNodesRequest>,
+    ) -> Result<Response<ListNodesResponse>, Status> {
+        let nodes = list_nodes(self.manager.kv_engine())
+            .await
+            .map_err(|e| error_to_status(&e))?;
+        Ok(Response::new(ListNodesResponse {
+            nodes: nodes.iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn scrub(
+        &self,
+        _request: Request<ScrubRequest>,
+    ) -> Result<Response<ScrubResponse>, Status> {
+        let requested = request_scrub(self.manager.kv_engine())
+            .await
+            .map_err(|e| error_to_status(&e))?;
+        info!("Requested a scrub pass.");
+        Ok(Response::new(ScrubResponse {
+            requested: unix_secs(requested),
+        }))
+    }
+
+    async fn collect_garbage(
+        &self,
+        _request: Request<CollectGarbageRequest>,
+    ) -> Result<Response<CollectGarbageResponse>, Status> {
+        let collected = self.collect_versions().await?;
+        Ok(Response::new(CollectGarbageResponse { collected }))
+    }
+}
+
+/// Serve `manager` of the file system stored in `storage_config` on
+/// `listener` until `token` is cancelled, over the TLS of the cluster if it's
+/// set.
+pub(super) async fn serve(
+    listener: TcpListener,
+    manager: Arc<VolumeManager>,
+    storage_config: StorageConfig,
+    token: CancellationToken,
+) -> Result<(), tonic::transport::Error> {
+    tls::serve(listener, tls::cluster_tls(), token, |server| {
+        server.add_service(VolumeManagerServer::new(VolumeService {
+            manager: Arc::clone(&manager),
+            storage_config: storage_config.clone(),
+        }))
+    })
+    .await
+}
+
+/// Serve `manager` of the file system stored in `storage_config` on `addr`
+/// until `token` is cancelled.
+pub async fn run_volume_manager(
+    addr: SocketAddr,
+    manager: Arc<VolumeManager>,
+    storage_config: StorageConfig,
+    token: CancellationToken,
+) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind the volume manager to {addr}: {e}");
+            return;
+        }
+    };
+    info!("Volume manager listens on {addr}.");
+    if let Err(e) = serve(listener, manager, storage_config, token).await {
+        error!("Volume manager failed: {e}");
+    }
+    info!("Volume manager exits.");
+}
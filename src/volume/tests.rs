@@ -0,0 +1,244 @@
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use clap::Parser;
+use datenlord::config::{
+    AdminCommand, CompressionType, Config, ConsistencyModel, ErasureShards, InnerConfig,
+    StorageConfig, StoragePolicy, VolumeConfig,
+};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+use super::server::serve;
+use super::{run_admin, VolumeManagerClient};
+use crate::async_fuse::memfs::chunk_index::VolumeInfo;
+use crate::async_fuse::memfs::kv_engine::{KVEngine, KVEngineType, KeyType, ValueType};
+use crate::async_fuse::memfs::volume::{Access, VolumeManager, VolumeParams};
+use crate::async_fuse::memfs::NodeRegistration;
+use crate::async_fuse::test::test_util::open_kv_engine;
+use crate::common::error::DatenLordError;
+
+/// The storage config of the file system with the blocks in `dir`.
+fn storage_config(dir: &str) -> StorageConfig {
+    let config: InnerConfig = Config::parse_from([
+        "datenlord",
+        "--role",
+        "volumeManager",
+        "--node-name",
+        "node1",
+        "--node-ip",
+        "127.0.0.1",
+        "--mount-path",
+        "/tmp/datenlord_data_dir",
+        "--kv-server-list",
+        "127.0.0.1:2379",
+        "--storage-fs-root",
+        dir,
+    ])
+    .try_into()
+    .unwrap();
+    config.storage
+}
+
+/// Serve a volume manager of `kv_engine` on a random local port, returns the
+/// endpoint of it.
+async fn start_server(kv_engine: Arc<KVEngineType>, token: CancellationToken) -> String {
+    let manager = Arc::new(VolumeManager::new(kv_engine, "node1").await.unwrap());
+    let storage_config = storage_config("/tmp/datenlord_volume_backend");
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        serve(listener, manager, storage_config, token)
+            .await
+            .unwrap();
+    });
+    format!("http://{addr}")
+}
+
+/// Run the admin `command` on volume `name` and snapshot `snapshot`, returns
+/// what it prints.
+async fn admin(
+    client: &VolumeManagerClient,
+    command: AdminCommand,
+    name: Option<&str>,
+    snapshot: Option<&str>,
+) -> anyhow::Result<String> {
+    let volume_config = VolumeConfig {
+        listen: "127.0.0.1:7950".parse().unwrap(),
+        endpoint: None,
+        name: name.map(str::to_owned),
+        capacity: 1 << 30,
+        policy: StoragePolicy::Tiered,
+        delete: false,
+        resize: false,
+        command,
+    };
+    let mut out = Vec::new();
+    run_admin(client, &volume_config, snapshot, &mut out).await?;
+    Ok(String::from_utf8(out)?)
+}
+
+#[tokio::test]
+async fn test_volume_manager_client() {
+    let token = CancellationToken::new();
+    let kv_engine = open_kv_engine();
+    let endpoint = start_server(kv_engine, token.clone()).await;
+    let client = VolumeManagerClient::connect(&endpoint).unwrap();
+
+    let params = VolumeParams {
+        compression: Some(CompressionType::Lz4),
+        replicas: Some(2),
+        ..VolumeParams::default()
+    };
+    let (volume, secret) = client
+        .create("data", 1 << 30, StoragePolicy::Replicate, params, None)
+        .await
+        .unwrap();
+    assert_eq!(volume.name, "data");
+    assert_eq!(volume.capacity, 1 << 30);
+    assert_eq!(volume.policy, "replicate");
+    let returned = VolumeParams::try_from(volume.params.clone().unwrap()).unwrap();
+    assert_eq!(returned, params);
+    assert!(!volume.id.is_empty());
+    assert!(client.authorize("data", &secret).await.unwrap());
+    assert!(!client.authorize("data", "guess").await.unwrap());
+    assert_eq!(client.get("data").await.unwrap(), volume);
+    let token = client.issue_token("data", Access::ReadOnly).await.unwrap();
+    let access = client.verify_token("data", &token).await.unwrap();
+    assert_eq!(access, Some(Access::ReadOnly));
+    assert_eq!(client.verify_token("data", "rw.guess").await.unwrap(), None);
+    let err = client
+        .create("data", 0, StoragePolicy::Tiered, params, None)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, DatenLordError::VolumeAlreadyExist { .. }));
+    let err = client
+        .create("", 0, StoragePolicy::Tiered, params, None)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, DatenLordError::ArgumentInvalid { .. }));
+    let invalid = VolumeParams {
+        block_size: Some(3000),
+        ..params
+    };
+    let err = client
+        .create("zero", 0, StoragePolicy::Tiered, invalid, None)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, DatenLordError::ArgumentInvalid { .. }));
+
+    let resized = client.resize("data", 2 << 30).await.unwrap();
+    assert_eq!(resized.capacity, 2 << 30);
+    assert_eq!(client.list().await.unwrap(), vec![resized]);
+
+    client.delete("data").await.unwrap();
+    assert!(client.list().await.unwrap().is_empty());
+    let err = client.delete("data").await.unwrap_err();
+    assert!(matches!(err, DatenLordError::VolumeNotFound { .. }));
+    let err = client.authorize("data", &secret).await.unwrap_err();
+    assert!(matches!(err, DatenLordError::VolumeNotFound { .. }));
+    token.cancel();
+}
+
+#[tokio::test]
+async fn test_admin() {
+    let token = CancellationToken::new();
+    let kv_engine = open_kv_engine();
+    let volume_info = VolumeInfo {
+        chunk_size: 4096,
+        compression: CompressionType::None,
+        dedup: false,
+        packing: false,
+        checksum: false,
+        encryption: false,
+        snapshot: true,
+        tiering: false,
+        replication: 0,
+        storage_policy: StoragePolicy::Replicate,
+        erasure_shards: ErasureShards::default(),
+        consistency: ConsistencyModel::Strict,
+    };
+    kv_engine
+        .set(
+            &KeyType::VolumeInfo,
+            &ValueType::VolumeInfo(volume_info),
+            None,
+        )
+        .await
+        .unwrap();
+    let registration = NodeRegistration {
+        node_id: "node2".to_owned(),
+        mount_point: "/var/datenlord".to_owned(),
+        registered: SystemTime::now(),
+        cache_endpoint: None,
+        zone: None,
+    };
+    kv_engine
+        .set(
+            &KeyType::NodeRegistration("node2".to_owned()),
+            &ValueType::NodeRegistration(registration),
+            None,
+        )
+        .await
+        .unwrap();
+    let endpoint = start_server(Arc::clone(&kv_engine), token.clone()).await;
+    let client = VolumeManagerClient::connect(&endpoint).unwrap();
+
+    let out = admin(&client, AdminCommand::Create, Some("data"), None)
+        .await
+        .unwrap();
+    assert!(out.starts_with("created volume data with id"), "{out}");
+    admin(&client, AdminCommand::Create, None, None)
+        .await
+        .unwrap_err();
+    client
+        .create(
+            "scratch",
+            0,
+            StoragePolicy::Tiered,
+            VolumeParams::default(),
+            None,
+        )
+        .await
+        .unwrap();
+    // The usage of the unlimited volume is not tracked
+    let out = admin(&client, AdminCommand::Quota, None, None)
+        .await
+        .unwrap();
+    assert_eq!(out, "data\t1073741824\t0\t0\t0\nscratch\t0\t-\t-\t-\n");
+
+    let out = admin(&client, AdminCommand::Snapshot, None, Some("snap1"))
+        .await
+        .unwrap();
+    assert!(out.starts_with("created snapshot snap1 with id"), "{out}");
+    let err = client.create_snapshot("snap1").await.unwrap_err();
+    assert!(matches!(err, DatenLordError::SnapshotAlreadyExist { .. }));
+    let out = admin(&client, AdminCommand::Snapshots, None, None)
+        .await
+        .unwrap();
+    assert_eq!(out.lines().count(), 1, "{out}");
+    assert!(
+        out.contains("\tsnap1\t") && out.ends_with("\tready\n"),
+        "{out}"
+    );
+    let out = admin(&client, AdminCommand::DeleteSnapshot, None, Some("snap1"))
+        .await
+        .unwrap();
+    assert!(out.ends_with(", 0 block versions are collected\n"), "{out}");
+    let err = client.delete_snapshot("snap1").await.unwrap_err();
+    assert!(matches!(err, DatenLordError::SnapshotNotFound { .. }));
+
+    let out = admin(&client, AdminCommand::Nodes, None, None)
+        .await
+        .unwrap();
+    assert!(out.starts_with("node2\t/var/datenlord\t"), "{out}");
+
+    let requested = client.scrub().await.unwrap();
+    assert!(requested > 0);
+    let request = kv_engine.get(&KeyType::ScrubRequest).await.unwrap();
+    assert!(request.is_some());
+
+    let out = admin(&client, AdminCommand::Gc, None, None).await.unwrap();
+    assert_eq!(out, "0 block versions are collected\n");
+    token.cancel();
+}
@@ -0,0 +1,489 @@
+//! The WinFsp host mounting the adapter, WinFsp calls it from its dispatcher
+//! threads, which block on the runtime until the operations are done.
+
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use clippy_utilities::{Cast, OverflowArithmetic};
+use nix::errno::Errno;
+use parking_lot::Mutex;
+use tokio::runtime::Handle;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{
+    LocalFree, HLOCAL, NTSTATUS, STATUS_ACCESS_DENIED, STATUS_DIRECTORY_NOT_EMPTY,
+    STATUS_DISK_FULL, STATUS_END_OF_FILE, STATUS_FILE_IS_A_DIRECTORY, STATUS_INVALID_PARAMETER,
+    STATUS_IO_DEVICE_ERROR, STATUS_MEDIA_WRITE_PROTECTED, STATUS_NOT_A_DIRECTORY,
+    STATUS_NOT_SUPPORTED, STATUS_OBJECT_NAME_COLLISION, STATUS_OBJECT_NAME_INVALID,
+    STATUS_OBJECT_NAME_NOT_FOUND, STATUS_QUOTA_EXCEEDED,
+};
+use windows::Win32::Security::Authorization::{
+    ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+};
+use windows::Win32::Security::PSECURITY_DESCRIPTOR;
+use windows::Win32::Storage::FileSystem::{FILE_ACCESS_RIGHTS, FILE_FLAGS_AND_ATTRIBUTES};
+use winfsp::filesystem::{
+    DirBuffer, DirInfo, DirMarker, FileInfo, FileSecurity, FileSystemContext, OpenFileInfo,
+    VolumeInfo, WideNameInfo,
+};
+use winfsp::host::{FileSystemHost, VolumeParams};
+use winfsp::{FspError, U16CStr, U16CString};
+
+use super::{file_attributes, is_dir, security, split_path, to_filetime, WinFspAdapter};
+use crate::async_fuse::fuse::protocol::{FuseAttr, INum};
+use crate::async_fuse::memfs::kv_engine::KVEngineType;
+use crate::common::error::DatenLordError;
+use crate::gateway::errno_of;
+use crate::{async_fuse, AsyncFuseArgs};
+
+/// `FILE_DIRECTORY_FILE` of the create options, to create a directory.
+const FILE_DIRECTORY_FILE: u32 = 0x1;
+/// `FspCleanupDelete` of the cleanup flags, to delete the node on cleanup.
+const CLEANUP_DELETE: u32 = 0x1;
+/// The bytes of a block counted by `FuseAttr::blocks`.
+const BLOCK_BYTES: u64 = 512;
+/// The max length of the names of the entries listed.
+const MAX_NAME_LEN: usize = 255;
+/// The label of the volumes mounted.
+const VOLUME_LABEL: &str = "DatenLord";
+
+/// A node opened by WinFsp.
+struct WinFspFile {
+    /// The i-number of the node
+    ino: INum,
+    /// The path of the node, which changes by renames
+    path: Mutex<String>,
+    /// Whether the node is a directory
+    is_dir: bool,
+    /// Whether the node is to be deleted on cleanup
+    delete_on_close: AtomicBool,
+    /// The entries of the directory listed
+    dir_buffer: DirBuffer,
+}
+
+impl WinFspFile {
+    /// Open the node of `attr` at `path`.
+    fn new(path: String, attr: &FuseAttr) -> Self {
+        Self {
+            ino: attr.ino,
+            path: Mutex::new(path),
+            is_dir: is_dir(attr),
+            delete_on_close: AtomicBool::new(false),
+            dir_buffer: DirBuffer::new(),
+        }
+    }
+}
+
+/// The context of the file system called by WinFsp.
+struct WinFspContext {
+    /// The adapter upon the file system of the volume
+    adapter: WinFspAdapter,
+    /// The runtime the operations are run in
+    runtime: Handle,
+}
+
+/// Convert an error of the file system to the `NTSTATUS` by its errno.
+fn to_fsp_error(err: &DatenLordError) -> FspError {
+    let status = match errno_of(err) {
+        Some(Errno::ENOENT) => STATUS_OBJECT_NAME_NOT_FOUND,
+        Some(Errno::EEXIST) => STATUS_OBJECT_NAME_COLLISION,
+        Some(Errno::EPERM | Errno::EACCES) => STATUS_ACCESS_DENIED,
+        Some(Errno::ENOTDIR) => STATUS_NOT_A_DIRECTORY,
+        Some(Errno::EISDIR) => STATUS_FILE_IS_A_DIRECTORY,
+        Some(Errno::ENOTEMPTY) => STATUS_DIRECTORY_NOT_EMPTY,
+        Some(Errno::EINVAL) => STATUS_INVALID_PARAMETER,
+        Some(Errno::ENAMETOOLONG) => STATUS_OBJECT_NAME_INVALID,
+        Some(Errno::ENOSPC) => STATUS_DISK_FULL,
+        Some(Errno::EDQUOT) => STATUS_QUOTA_EXCEEDED,
+        Some(Errno::EROFS) => STATUS_MEDIA_WRITE_PROTECTED,
+        Some(Errno::ENOTSUP) => STATUS_NOT_SUPPORTED,
+        Some(_) | None => {
+            debug!("the error is returned as STATUS_IO_DEVICE_ERROR: {err}");
+            STATUS_IO_DEVICE_ERROR
+        }
+    };
+    ntstatus(status)
+}
+
+/// The error of `status`.
+fn ntstatus(status: NTSTATUS) -> FspError {
+    FspError::NTSTATUS(status.0)
+}
+
+/// The name of the node of `path`, which is empty for the root.
+fn name_of(path: &str) -> &str {
+    split_path(path).last().copied().unwrap_or_default()
+}
+
+/// Fill `info` by the attributes of the node `name`.
+fn fill_file_info(name: &str, attr: &FuseAttr, info: &mut FileInfo) {
+    info.file_attributes = file_attributes(name, attr);
+    info.reparse_tag = 0;
+    info.file_size = attr.size;
+    info.allocation_size = attr.blocks.overflow_mul(BLOCK_BYTES);
+    // There's no creation time, the change time is used instead.
+    info.creation_time = to_filetime(attr.ctime, attr.ctimensec);
+    info.last_access_time = to_filetime(attr.atime, attr.atimensec);
+    info.last_write_time = to_filetime(attr.mtime, attr.mtimensec);
+    info.change_time = to_filetime(attr.ctime, attr.ctimensec);
+    info.index_number = attr.ino;
+    info.hard_links = 0;
+    info.ea_size = 0;
+}
+
+/// The security descriptor of the node of `attr` in its self-relative
+/// binary form, copied to `buffer` if it's large enough.
+fn copy_security_descriptor(attr: &FuseAttr, buffer: Option<&mut [c_void]>) -> winfsp::Result<u64> {
+    let sddl = U16CString::from_str(security::security_descriptor(attr, is_dir(attr)))
+        .map_err(|_| ntstatus(STATUS_INVALID_PARAMETER))?;
+    let mut descriptor = PSECURITY_DESCRIPTOR::default();
+    let mut len = 0_u32;
+    // SAFETY: `sddl` is nul-terminated, and `descriptor` is freed below
+    unsafe {
+        ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            PCWSTR(sddl.as_ptr()),
+            SDDL_REVISION_1,
+            &mut descriptor,
+            Some(&mut len),
+        )?;
+    }
+    if let Some(buffer) = buffer {
+        if buffer.len() >= len.cast::<usize>() {
+            // SAFETY: `descriptor` is of `len` bytes, and `buffer` is larger
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    descriptor.0.cast::<u8>(),
+                    buffer.as_mut_ptr().cast::<u8>(),
+                    len.cast(),
+                );
+            }
+        }
+    }
+    // SAFETY: `descriptor` is allocated by
+    // `ConvertStringSecurityDescriptorToSecurityDescriptorW`
+    unsafe {
+        let _ = LocalFree(HLOCAL(descriptor.0));
+    }
+    Ok(len.into())
+}
+
+impl WinFspContext {
+    /// Run `future` of the adapter to completion.
+    fn block_on<T>(
+        &self,
+        future: impl std::future::Future<Output = Result<T, DatenLordError>>,
+    ) -> winfsp::Result<T> {
+        self.runtime.block_on(future).map_err(|e| to_fsp_error(&e))
+    }
+
+    /// Fill `info` by the current attributes of `file`.
+    fn refresh(&self, file: &WinFspFile, info: &mut FileInfo) -> winfsp::Result<()> {
+        let attr = self.block_on(self.adapter.attr(file.ino))?;
+        fill_file_info(name_of(&file.path.lock()), &attr, info);
+        Ok(())
+    }
+}
+
+impl FileSystemContext for WinFspContext {
+    type FileContext = WinFspFile;
+
+    fn get_security_by_name(
+        &self,
+        file_name: &U16CStr,
+        security_descriptor: Option<&mut [c_void]>,
+        _reparse_point_resolver: impl FnOnce(&U16CStr) -> Option<FileSecurity>,
+    ) -> winfsp::Result<FileSecurity> {
+        let path = file_name.to_string_lossy();
+        let attr = self.block_on(self.adapter.resolve(&path))?;
+        let len = copy_security_descriptor(&attr, security_descriptor)?;
+        Ok(FileSecurity {
+            reparse: false,
+            sz_security_descriptor: len,
+            attributes: file_attributes(name_of(&path), &attr),
+        })
+    }
+
+    fn open(
+        &self,
+        file_name: &U16CStr,
+        _create_options: u32,
+        _granted_access: FILE_ACCESS_RIGHTS,
+        file_info: &mut OpenFileInfo,
+    ) -> winfsp::Result<Self::FileContext> {
+        let path = file_name.to_string_lossy();
+        let attr = self.block_on(self.adapter.resolve(&path))?;
+        fill_file_info(name_of(&path), &attr, file_info.as_mut());
+        Ok(WinFspFile::new(path, &attr))
+    }
+
+    fn close(&self, _context: Self::FileContext) {}
+
+    fn create(
+        &self,
+        file_name: &U16CStr,
+        create_options: u32,
+        _granted_access: FILE_ACCESS_RIGHTS,
+        _file_attributes: FILE_FLAGS_AND_ATTRIBUTES,
+        _security_descriptor: Option<&[c_void]>,
+        _allocation_size: u64,
+        _extra_buffer: Option<&[u8]>,
+        _extra_buffer_is_reparse_point: bool,
+        file_info: &mut OpenFileInfo,
+    ) -> winfsp::Result<Self::FileContext> {
+        let path = file_name.to_string_lossy();
+        let is_dir = create_options & FILE_DIRECTORY_FILE != 0;
+        let attr = self.block_on(self.adapter.create(&path, is_dir))?;
+        fill_file_info(name_of(&path), &attr, file_info.as_mut());
+        Ok(WinFspFile::new(path, &attr))
+    }
+
+    fn cleanup(&self, context: &Self::FileContext, _file_name: Option<&U16CStr>, flags: u32) {
+        if flags & CLEANUP_DELETE == 0 || !context.delete_on_close.load(Ordering::Acquire) {
+            return;
+        }
+        let path = context.path.lock().clone();
+        if let Err(e) = self.block_on(self.adapter.remove(&path)) {
+            debug!("failed to delete {path} on cleanup: {e:?}");
+        }
+    }
+
+    fn flush(
+        &self,
+        context: Option<&Self::FileContext>,
+        file_info: &mut FileInfo,
+    ) -> winfsp::Result<()> {
+        // The data is flushed by every write.
+        match context {
+            Some(file) => self.refresh(file, file_info),
+            None => Ok(()),
+        }
+    }
+
+    fn get_file_info(
+        &self,
+        context: &Self::FileContext,
+        file_info: &mut FileInfo,
+    ) -> winfsp::Result<()> {
+        self.refresh(context, file_info)
+    }
+
+    fn get_security(
+        &self,
+        context: &Self::FileContext,
+        security_descriptor: Option<&mut [c_void]>,
+    ) -> winfsp::Result<u64> {
+        let attr = self.block_on(self.adapter.attr(context.ino))?;
+        copy_security_descriptor(&attr, security_descriptor)
+    }
+
+    fn overwrite(
+        &self,
+        context: &Self::FileContext,
+        _file_attributes: FILE_FLAGS_AND_ATTRIBUTES,
+        _replace_file_attributes: bool,
+        _allocation_size: u64,
+        _extra_buffer: Option<&[u8]>,
+        file_info: &mut FileInfo,
+    ) -> winfsp::Result<()> {
+        let attr = self.block_on(self.adapter.set_size(context.ino, 0))?;
+        fill_file_info(name_of(&context.path.lock()), &attr, file_info);
+        Ok(())
+    }
+
+    fn read_directory(
+        &self,
+        context: &Self::FileContext,
+        _pattern: Option<&U16CStr>,
+        marker: DirMarker,
+        buffer: &mut [u8],
+    ) -> winfsp::Result<u32> {
+        if let Ok(lock) = context.dir_buffer.acquire(marker.is_none(), None) {
+            let entries = self.block_on(self.adapter.list(context.ino))?;
+            for (name, attr) in entries {
+                let mut info: DirInfo<MAX_NAME_LEN> = DirInfo::new();
+                info.set_name(name.as_str())?;
+                fill_file_info(&name, &attr, info.file_info_mut());
+                lock.write(&mut info)?;
+            }
+        }
+        Ok(context.dir_buffer.read(marker, buffer))
+    }
+
+    fn rename(
+        &self,
+        context: &Self::FileContext,
+        file_name: &U16CStr,
+        new_file_name: &U16CStr,
+        replace_if_exists: bool,
+    ) -> winfsp::Result<()> {
+        let new_path = new_file_name.to_string_lossy();
+        self.block_on(self.adapter.rename(
+            &file_name.to_string_lossy(),
+            &new_path,
+            replace_if_exists,
+        ))?;
+        *context.path.lock() = new_path;
+        Ok(())
+    }
+
+    fn set_basic_info(
+        &self,
+        context: &Self::FileContext,
+        file_attributes: u32,
+        _creation_time: u64,
+        last_access_time: u64,
+        last_write_time: u64,
+        _last_change_time: u64,
+        file_info: &mut FileInfo,
+    ) -> winfsp::Result<()> {
+        let attr = self.block_on(self.adapter.set_basic_info(
+            context.ino,
+            file_attributes,
+            last_access_time,
+            last_write_time,
+        ))?;
+        fill_file_info(name_of(&context.path.lock()), &attr, file_info);
+        Ok(())
+    }
+
+    fn set_delete(
+        &self,
+        context: &Self::FileContext,
+        _file_name: &U16CStr,
+        delete_file: bool,
+    ) -> winfsp::Result<()> {
+        if delete_file {
+            if self.adapter.is_read_only() {
+                return Err(ntstatus(STATUS_MEDIA_WRITE_PROTECTED));
+            }
+            if context.is_dir && !self.block_on(self.adapter.list(context.ino))?.is_empty() {
+                return Err(ntstatus(STATUS_DIRECTORY_NOT_EMPTY));
+            }
+        }
+        context
+            .delete_on_close
+            .store(delete_file, Ordering::Release);
+        Ok(())
+    }
+
+    fn set_file_size(
+        &self,
+        context: &Self::FileContext,
+        new_size: u64,
+        set_allocation_size: bool,
+        file_info: &mut FileInfo,
+    ) -> winfsp::Result<()> {
+        // The allocation is not reserved, only the size of the file is set.
+        if set_allocation_size {
+            return self.refresh(context, file_info);
+        }
+        let attr = self.block_on(self.adapter.set_size(context.ino, new_size))?;
+        fill_file_info(name_of(&context.path.lock()), &attr, file_info);
+        Ok(())
+    }
+
+    fn read(
+        &self,
+        context: &Self::FileContext,
+        buffer: &mut [u8],
+        offset: u64,
+    ) -> winfsp::Result<u32> {
+        let data = self.block_on(self.adapter.read(context.ino, offset, buffer.len().cast()))?;
+        if data.is_empty() && !buffer.is_empty() {
+            return Err(ntstatus(STATUS_END_OF_FILE));
+        }
+        let len = data.len().min(buffer.len());
+        if let (Some(dst), Some(src)) = (buffer.get_mut(..len), data.get(..len)) {
+            dst.copy_from_slice(src);
+        }
+        Ok(len.cast())
+    }
+
+    fn write(
+        &self,
+        context: &Self::FileContext,
+        buffer: &[u8],
+        offset: u64,
+        write_to_eof: bool,
+        constrained_io: bool,
+        file_info: &mut FileInfo,
+    ) -> winfsp::Result<u32> {
+        let size = self.block_on(self.adapter.attr(context.ino))?.size;
+        let offset = if write_to_eof { size } else { offset };
+        // A constrained write, such as one of the paging I/O, never extends
+        // the file.
+        let len = if constrained_io {
+            buffer.len().min(size.saturating_sub(offset).cast())
+        } else {
+            buffer.len()
+        };
+        let data = buffer.get(..len).unwrap_or_default();
+        if data.is_empty() {
+            self.refresh(context, file_info)?;
+            return Ok(0);
+        }
+        let attr = self.block_on(self.adapter.write(context.ino, offset, data))?;
+        fill_file_info(name_of(&context.path.lock()), &attr, file_info);
+        Ok(len.cast())
+    }
+
+    fn get_volume_info(&self, out_volume_info: &mut VolumeInfo) -> winfsp::Result<()> {
+        let (total, free) = self.block_on(self.adapter.statfs())?;
+        out_volume_info.total_size = total;
+        out_volume_info.free_size = free;
+        out_volume_info.set_volume_label(VOLUME_LABEL);
+        Ok(())
+    }
+}
+
+/// Mount the volume of `args` on the drive or the directory
+/// `args.mount_dir` by WinFsp, until `token` is cancelled.
+pub async fn start_winfsp(
+    kv_engine: Arc<KVEngineType>,
+    args: AsyncFuseArgs,
+    token: CancellationToken,
+) -> anyhow::Result<()> {
+    let (fs, snapshot) = async_fuse::open_memfs(kv_engine, &args).await?;
+    let adapter = WinFspAdapter::new(Arc::new(fs), snapshot.is_some());
+    let _init = winfsp::winfsp_init().map_err(|e| anyhow!("failed to load WinFsp: {e:?}"))?;
+
+    let mut params = VolumeParams::new();
+    params
+        .filesystem_name("datenlord")
+        .sector_size(BLOCK_BYTES.cast())
+        .sectors_per_allocation_unit(1)
+        .case_sensitive_search(true)
+        .case_preserved_names(true)
+        .unicode_on_disk(true)
+        .persistent_acls(true)
+        .post_cleanup_when_modified_only(true)
+        .read_only_volume(adapter.is_read_only());
+    let context = WinFspContext {
+        adapter,
+        runtime: Handle::current(),
+    };
+    let mut host = FileSystemHost::new(params, context)
+        .map_err(|e| anyhow!("failed to create the WinFsp host: {e:?}"))?;
+    host.mount(&args.mount_dir)
+        .map_err(|e| anyhow!("failed to mount {}: {e:?}", args.mount_dir))?;
+    host.start()
+        .map_err(|e| anyhow!("failed to start the WinFsp dispatcher: {e:?}"))?;
+    info!("WinFsp mounted the volume on {}.", args.mount_dir);
+
+    token.cancelled().await;
+    // The dispatcher threads block on the runtime, they're stopped outside it.
+    tokio::task::spawn_blocking(move || {
+        host.stop();
+        host.unmount();
+    })
+    .await?;
+    info!("WinFsp session exits.");
+
+    if let Some(ref info) = snapshot {
+        async_fuse::close_snapshot(info).await;
+    }
+    Ok(())
+}
@@ -0,0 +1,363 @@
+//! The WinFsp adapter mounting a volume on Windows workstations natively.
+//!
+//! WinFsp calls the file system by the paths of the nodes, such as
+//! `\dir\file`, which are resolved from the root of the volume by the
+//! transport-agnostic operations of the file system, like the gateways. The
+//! nodes created through WinFsp are never forgotten by it, so they're created
+//! and removed statelessly. Windows checks the access by the security
+//! descriptors synthesized from the modes of the nodes, see [`security`], and
+//! the operations run as root afterwards.
+//!
+//! Symbolic links have no counterpart without the reparse points, they're
+//! neither listed nor opened. The names beginning with `.` are hidden.
+
+#[cfg(windows)]
+mod host;
+pub mod security;
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests;
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clippy_utilities::{Cast, OverflowArithmetic};
+use nix::errno::Errno;
+use nix::sys::stat::SFlag;
+
+#[cfg(windows)]
+pub use self::host::start_winfsp;
+use crate::async_fuse::fuse::protocol::setattr_flags::{
+    FATTR_ATIME, FATTR_MODE, FATTR_MTIME, FATTR_SIZE,
+};
+use crate::async_fuse::fuse::protocol::{FuseAttr, INum, FUSE_ROOT_ID};
+use crate::async_fuse::memfs::{
+    CreateParam, MemFs, RenameParam, S3MetaData, SetAttrParam, ROOT_CONTEXT,
+};
+use crate::async_fuse::util::build_error_result_from_errno;
+use crate::common::error::DatenLordResult;
+use crate::gateway::errno_of;
+
+/// The mode of the files created.
+const FILE_MODE: u32 = 0o644;
+/// The mode of the directories created.
+const DIR_MODE: u32 = 0o755;
+/// The write bits cleared by `FILE_ATTRIBUTE_READONLY`.
+const WRITE_BITS: u32 = 0o222;
+/// The separator of the components of a path.
+const SEPARATOR: char = '\\';
+/// The 100-nanoseconds from 1601-01-01, the epoch of `FILETIME`, to the Unix
+/// epoch.
+const FILETIME_UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+/// The 100-nanoseconds of a second.
+const FILETIME_PER_SEC: u64 = 10_000_000;
+
+/// The file is read-only.
+pub const FILE_ATTRIBUTE_READONLY: u32 = 0x1;
+/// The file is hidden from the ordinary listings.
+pub const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+/// The node is a directory.
+pub const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x10;
+/// The file has no other attributes.
+pub const FILE_ATTRIBUTE_NORMAL: u32 = 0x80;
+
+/// The components of `path` of WinFsp.
+pub fn split_path(path: &str) -> Vec<&str> {
+    path.split(SEPARATOR)
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Whether `attr` is of a directory.
+pub fn is_dir(attr: &FuseAttr) -> bool {
+    attr.mode & SFlag::S_IFMT.bits() == SFlag::S_IFDIR.bits()
+}
+
+/// Whether `attr` is of a symbolic link.
+fn is_symlink(attr: &FuseAttr) -> bool {
+    attr.mode & SFlag::S_IFMT.bits() == SFlag::S_IFLNK.bits()
+}
+
+/// The attributes of Windows of the node `name` of `attr`.
+pub fn file_attributes(name: &str, attr: &FuseAttr) -> u32 {
+    let mut attributes = 0;
+    if is_dir(attr) {
+        attributes |= FILE_ATTRIBUTE_DIRECTORY;
+    } else if attr.mode & WRITE_BITS == 0 {
+        attributes |= FILE_ATTRIBUTE_READONLY;
+    }
+    if name.starts_with('.') {
+        attributes |= FILE_ATTRIBUTE_HIDDEN;
+    }
+    if attributes == 0 {
+        FILE_ATTRIBUTE_NORMAL
+    } else {
+        attributes
+    }
+}
+
+/// The `FILETIME` of the time of seconds and nanoseconds.
+pub fn to_filetime(secs: u64, nsecs: u32) -> u64 {
+    secs.overflow_mul(FILETIME_PER_SEC)
+        .overflow_add(u64::from(nsecs).overflow_div(100))
+        .overflow_add(FILETIME_UNIX_EPOCH)
+}
+
+/// The time of `FILETIME`, `None` if it's not set or before the Unix epoch.
+pub fn from_filetime(filetime: u64) -> Option<SystemTime> {
+    let since_epoch = filetime.checked_sub(FILETIME_UNIX_EPOCH)?;
+    let secs = since_epoch.overflow_div(FILETIME_PER_SEC);
+    let nsecs = since_epoch.overflow_rem(FILETIME_PER_SEC).overflow_mul(100);
+    Some(UNIX_EPOCH + Duration::new(secs, nsecs.cast()))
+}
+
+/// The parameters to set the attributes of `valid`.
+fn setattr_param(valid: u32) -> SetAttrParam {
+    SetAttrParam {
+        valid,
+        fh: None,
+        mode: None,
+        u_id: None,
+        g_id: None,
+        size: None,
+        #[cfg(feature = "abi-7-9")]
+        lock_owner: None,
+        a_time: None,
+        m_time: None,
+        #[cfg(feature = "abi-7-23")]
+        c_time: None,
+    }
+}
+
+/// The file system of a volume called by paths, a snapshot is served
+/// read-only.
+#[derive(Debug)]
+pub struct WinFspAdapter {
+    /// The file system of the volume
+    fs: Arc<MemFs<S3MetaData>>,
+    /// Whether the volume is served read-only
+    read_only: bool,
+}
+
+impl WinFspAdapter {
+    /// Serve `fs`, read-only if `read_only` is set.
+    #[must_use]
+    pub fn new(fs: Arc<MemFs<S3MetaData>>, read_only: bool) -> Self {
+        Self { fs, read_only }
+    }
+
+    /// Whether the volume is served read-only.
+    #[must_use]
+    pub const fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Fail with `EROFS` if the volume is served read-only.
+    fn check_writable(&self) -> DatenLordResult<()> {
+        if self.read_only {
+            build_error_result_from_errno(Errno::EROFS, "the volume is read-only".to_owned())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The attributes of `ino`.
+    pub async fn attr(&self, ino: INum) -> DatenLordResult<FuseAttr> {
+        let (_, attr) = self.fs.get_attr(ino).await?;
+        Ok(attr)
+    }
+
+    /// Look up the node of `path`, the symbolic links are not found.
+    pub async fn resolve(&self, path: &str) -> DatenLordResult<FuseAttr> {
+        let mut attr = self.attr(FUSE_ROOT_ID).await?;
+        for name in split_path(path) {
+            (_, attr, _) = self.fs.lookup_entry(ROOT_CONTEXT, attr.ino, name).await?;
+            if is_symlink(&attr) {
+                return build_error_result_from_errno(
+                    Errno::ENOENT,
+                    format!("{path} is a symbolic link"),
+                );
+            }
+        }
+        Ok(attr)
+    }
+
+    /// Look up the parent directory of `path`, returns its i-number and the
+    /// name of the node in it.
+    async fn resolve_parent<'a>(&self, path: &'a str) -> DatenLordResult<(INum, &'a str)> {
+        let mut names = split_path(path);
+        let Some(name) = names.pop() else {
+            return build_error_result_from_errno(
+                Errno::EINVAL,
+                "the root directory has no parent".to_owned(),
+            );
+        };
+        let mut dir = FUSE_ROOT_ID;
+        for parent in names {
+            let (_, attr, _) = self.fs.lookup_entry(ROOT_CONTEXT, dir, parent).await?;
+            if !is_dir(&attr) {
+                return build_error_result_from_errno(
+                    Errno::ENOTDIR,
+                    format!("{parent} of {path} is not a directory"),
+                );
+            }
+            dir = attr.ino;
+        }
+        Ok((dir, name))
+    }
+
+    /// Create a file or a directory of `path`.
+    pub async fn create(&self, path: &str, is_dir: bool) -> DatenLordResult<FuseAttr> {
+        self.check_writable()?;
+        let (parent, name) = self.resolve_parent(path).await?;
+        let (mode, node_type) = if is_dir {
+            (DIR_MODE, SFlag::S_IFDIR)
+        } else {
+            (FILE_MODE, SFlag::S_IFREG)
+        };
+        self.fs
+            .create_stateless(CreateParam {
+                parent,
+                name: name.to_owned(),
+                mode,
+                rdev: 0,
+                uid: 0,
+                gid: 0,
+                umask: 0,
+                node_type,
+                link: None,
+            })
+            .await
+    }
+
+    /// Remove the node of `path`, a directory is removed if it's empty.
+    pub async fn remove(&self, path: &str) -> DatenLordResult<()> {
+        self.check_writable()?;
+        let (parent, name) = self.resolve_parent(path).await?;
+        self.fs.remove_stateless(parent, name).await
+    }
+
+    /// Rename the node of `path` to `new_path`, which is replaced if it
+    /// exists and `replace` is set.
+    pub async fn rename(&self, path: &str, new_path: &str, replace: bool) -> DatenLordResult<()> {
+        self.check_writable()?;
+        let (old_parent, old_name) = self.resolve_parent(path).await?;
+        let (new_parent, new_name) = self.resolve_parent(new_path).await?;
+        if !replace {
+            match self
+                .fs
+                .lookup_entry(ROOT_CONTEXT, new_parent, new_name)
+                .await
+            {
+                Ok(_) => {
+                    return build_error_result_from_errno(
+                        Errno::EEXIST,
+                        format!("{new_path} exists"),
+                    )
+                }
+                Err(e) if errno_of(&e) == Some(Errno::ENOENT) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.fs
+            .rename_entry(
+                ROOT_CONTEXT,
+                RenameParam {
+                    old_parent,
+                    old_name: old_name.to_owned(),
+                    new_parent,
+                    new_name: new_name.to_owned(),
+                    flags: 0,
+                },
+            )
+            .await
+    }
+
+    /// Read at most `len` bytes of `ino` from `offset`.
+    pub async fn read(&self, ino: INum, offset: u64, len: u64) -> DatenLordResult<Vec<u8>> {
+        let (data, _) = self.fs.read_file(ROOT_CONTEXT, ino, offset, len).await?;
+        Ok(data)
+    }
+
+    /// Write `data` to `ino` at `offset`, returns the attributes of the file.
+    pub async fn write(&self, ino: INum, offset: u64, data: &[u8]) -> DatenLordResult<FuseAttr> {
+        self.check_writable()?;
+        self.fs.write_file(ROOT_CONTEXT, ino, offset, data).await
+    }
+
+    /// Truncate or extend `ino` to `size`.
+    pub async fn set_size(&self, ino: INum, size: u64) -> DatenLordResult<FuseAttr> {
+        self.check_writable()?;
+        let param = SetAttrParam {
+            size: Some(size),
+            ..setattr_param(FATTR_SIZE)
+        };
+        let (_, attr) = self.fs.set_attr(ROOT_CONTEXT, ino, &param).await?;
+        Ok(attr)
+    }
+
+    /// Set the basic information of `ino`, `FILE_ATTRIBUTE_READONLY` of
+    /// `attributes` clears the write bits of a file, and the times of
+    /// `FILETIME` are not changed if they're `0`. The attributes are not
+    /// changed if `attributes` is `u32::MAX`.
+    pub async fn set_basic_info(
+        &self,
+        ino: INum,
+        attributes: u32,
+        access_time: u64,
+        write_time: u64,
+    ) -> DatenLordResult<FuseAttr> {
+        let attr = self.attr(ino).await?;
+        let mut param = setattr_param(0);
+        if attributes != u32::MAX && !is_dir(&attr) {
+            let mode = if attributes & FILE_ATTRIBUTE_READONLY == 0 {
+                // The owner writes the file again
+                attr.mode | 0o200
+            } else {
+                attr.mode & !WRITE_BITS
+            };
+            if mode != attr.mode {
+                param.valid |= FATTR_MODE;
+                param.mode = Some(mode);
+            }
+        }
+        param.a_time = from_filetime(access_time);
+        if param.a_time.is_some() {
+            param.valid |= FATTR_ATIME;
+        }
+        param.m_time = from_filetime(write_time);
+        if param.m_time.is_some() {
+            param.valid |= FATTR_MTIME;
+        }
+        if param.valid == 0 {
+            return Ok(attr);
+        }
+        self.check_writable()?;
+        let (_, attr) = self.fs.set_attr(ROOT_CONTEXT, ino, &param).await?;
+        Ok(attr)
+    }
+
+    /// List the entries of directory `ino` with their attributes, except the
+    /// symbolic links.
+    pub async fn list(&self, ino: INum) -> DatenLordResult<Vec<(String, FuseAttr)>> {
+        let entries = self.fs.list_dir(ROOT_CONTEXT, ino).await?;
+        let mut listed = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let attr = self.attr(entry.ino()).await?;
+            if !is_symlink(&attr) {
+                listed.push((entry.name().to_owned(), attr));
+            }
+        }
+        Ok(listed)
+    }
+
+    /// The total and the free bytes of the volume.
+    pub async fn statfs(&self) -> DatenLordResult<(u64, u64)> {
+        let stat = self.fs.stat_fs(ROOT_CONTEXT, FUSE_ROOT_ID).await?;
+        let bsize: u64 = stat.bsize.cast();
+        Ok((
+            stat.blocks.overflow_mul(bsize),
+            stat.bavail.overflow_mul(bsize),
+        ))
+    }
+}
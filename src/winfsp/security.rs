@@ -0,0 +1,54 @@
+//! The security descriptors of the nodes synthesized from their modes.
+//!
+//! Windows checks the access to a node by its security descriptor, which is
+//! built from the owner, the group and the permission bits of the node. The
+//! owner and the group are the Unix SIDs `S-1-22-1-<uid>` and
+//! `S-1-22-2-<gid>`, as the ones of WinFsp-FUSE and Samba for the IDs not
+//! mapped to Windows accounts, and the others are `Everyone`.
+
+use crate::async_fuse::fuse::protocol::FuseAttr;
+
+/// The right to read the data, the attributes and the descriptor.
+const FILE_GENERIC_READ: u32 = 0x0012_0089;
+/// The right to write the data and the attributes.
+const FILE_GENERIC_WRITE: u32 = 0x0012_0116;
+/// The right to execute a file or to traverse a directory.
+const FILE_GENERIC_EXECUTE: u32 = 0x0012_00A0;
+/// The right to remove the entries of a directory.
+const FILE_DELETE_CHILD: u32 = 0x0000_0040;
+/// The rights everyone has, to read the attributes and the descriptor.
+const BASE_RIGHTS: u32 = 0x0012_0080;
+/// The rights the owner has besides its permission bits, to delete the node
+/// and to change its attributes, descriptor and owner.
+const OWNER_RIGHTS: u32 = 0x000D_0100;
+
+/// The access mask of the permission bits `rwx` of a node.
+fn access_mask(rwx: u32, is_dir: bool) -> u32 {
+    let mut mask = BASE_RIGHTS;
+    if rwx & 0o4 != 0 {
+        mask |= FILE_GENERIC_READ;
+    }
+    if rwx & 0o2 != 0 {
+        mask |= FILE_GENERIC_WRITE;
+        if is_dir {
+            mask |= FILE_DELETE_CHILD;
+        }
+    }
+    if rwx & 0o1 != 0 {
+        mask |= FILE_GENERIC_EXECUTE;
+    }
+    mask
+}
+
+/// The security descriptor of the node of `attr` in SDDL.
+pub fn security_descriptor(attr: &FuseAttr, is_dir: bool) -> String {
+    let owner = format!("S-1-22-1-{}", attr.uid);
+    let group = format!("S-1-22-2-{}", attr.gid);
+    let owner_mask = access_mask(attr.mode >> 6, is_dir) | OWNER_RIGHTS;
+    let group_mask = access_mask(attr.mode >> 3, is_dir);
+    let other_mask = access_mask(attr.mode, is_dir);
+    format!(
+        "O:{owner}G:{group}D:P(A;;0x{owner_mask:x};;;{owner})(A;;0x{group_mask:x};;;{group})\
+        (A;;0x{other_mask:x};;;WD)"
+    )
+}
@@ -0,0 +1,85 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use super::security::security_descriptor;
+use super::{
+    file_attributes, from_filetime, split_path, to_filetime, FILE_ATTRIBUTE_DIRECTORY,
+    FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_NORMAL, FILE_ATTRIBUTE_READONLY,
+};
+use crate::async_fuse::fuse::protocol::FuseAttr;
+
+/// The attributes of a node of `mode` owned by 1000:100.
+fn fuse_attr(mode: u32) -> FuseAttr {
+    FuseAttr {
+        ino: 2,
+        size: 4096,
+        blocks: 8,
+        atime: 1,
+        mtime: 2,
+        ctime: 3,
+        #[cfg(target_os = "macos")]
+        crtime: 3,
+        atimensec: 4,
+        mtimensec: 5,
+        ctimensec: 6,
+        #[cfg(target_os = "macos")]
+        crtimensec: 6,
+        mode,
+        nlink: 1,
+        uid: 1000,
+        gid: 100,
+        rdev: 0,
+        #[cfg(target_os = "macos")]
+        flags: 0,
+        #[cfg(feature = "abi-7-9")]
+        blksize: 4096,
+        #[cfg(feature = "abi-7-9")]
+        padding: 0,
+    }
+}
+
+#[test]
+fn test_split_path() {
+    assert!(split_path("\\").is_empty());
+    assert_eq!(split_path("\\dir\\file"), vec!["dir", "file"]);
+    assert_eq!(split_path("\\dir\\\\file\\"), vec!["dir", "file"]);
+}
+
+#[test]
+fn test_file_attributes() {
+    let dir = fuse_attr(0o040_755);
+    assert_eq!(file_attributes("dir", &dir), FILE_ATTRIBUTE_DIRECTORY);
+    assert_eq!(
+        file_attributes(".git", &dir),
+        FILE_ATTRIBUTE_DIRECTORY | FILE_ATTRIBUTE_HIDDEN
+    );
+    let file = fuse_attr(0o100_644);
+    assert_eq!(file_attributes("file", &file), FILE_ATTRIBUTE_NORMAL);
+    let read_only = fuse_attr(0o100_444);
+    assert_eq!(file_attributes("file", &read_only), FILE_ATTRIBUTE_READONLY);
+}
+
+#[test]
+fn test_filetime() {
+    // 1970-01-01 is 11644473600 seconds after 1601-01-01.
+    assert_eq!(to_filetime(0, 0), 116_444_736_000_000_000);
+    let filetime = to_filetime(100, 1_500);
+    assert_eq!(filetime, 116_444_737_000_000_015);
+    assert_eq!(
+        from_filetime(filetime),
+        Some(UNIX_EPOCH + Duration::new(100, 1_500))
+    );
+    assert_eq!(from_filetime(0), None);
+}
+
+#[test]
+fn test_security_descriptor() {
+    let sddl = security_descriptor(&fuse_attr(0o100_640), false);
+    assert_eq!(
+        sddl,
+        "O:S-1-22-1-1000G:S-1-22-2-100D:P(A;;0x1f019f;;;S-1-22-1-1000)\
+        (A;;0x120089;;;S-1-22-2-100)(A;;0x120080;;;WD)"
+    );
+    // Writing a directory removes its entries.
+    let sddl = security_descriptor(&fuse_attr(0o040_777), true);
+    assert!(sddl.ends_with("(A;;0x1201ff;;;WD)"));
+}